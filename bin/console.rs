@@ -115,6 +115,10 @@ commands! {
         "addblskey <permkeyhash> <keyhash> <expire-at>\t add validator bls key"
     Bundle, "bundle", 
         "bundle <block_id>\tprepare bundle"
+    CheckDbConsistency, "checkdbconsistency", 
+        "checkdbconsistency <Option<fix>>\tcheck block handles against stored data, fix mismatches if 'fix' is given"
+    EncryptKeystore, "encryptkeystore", 
+        "encryptkeystore\tmove validator keys from node config into encrypted keystore"
     ExportPub, "exportpub", 
         "exportpub <keyhash>\texports public key by key hash"
     FutureBundle, "future_bundle", 
@@ -237,6 +241,43 @@ impl <Q: ToString> SendReceive<Q> for GetSelectedStats {
     }
 }
 
+impl <Q: ToString> SendReceive<Q> for CheckDbConsistency {
+    fn send(params: &mut impl Iterator<Item = Q>) -> Result<TLObject> {
+        let filter = match params.next().map(|param| param.to_string()) {
+            None => "db_consistency_check",
+            Some(param) if param == "fix" => "db_consistency_fix",
+            Some(param) => fail!("unknown parameter {}", param)
+        };
+        let req = ton::rpc::engine::validator::GetSelectedStats {
+            filter: filter.to_string()
+        };
+        Ok(TLObject::new(req))
+    }
+    fn receive(answer: TLObject, _params: &mut impl Iterator) -> Result<(String, Vec<u8>)> {
+        let data = serialize_boxed(&answer)?;
+        let stats = downcast::<ton_api::ton::engine::validator::Stats>(answer)?;
+        let description = stats_to_json(stats.stats().iter());
+        let description = format!("{:#}", description);
+        Ok((description, data))
+    }
+}
+
+impl <Q: ToString> SendReceive<Q> for EncryptKeystore {
+    fn send(_params: &mut impl Iterator) -> Result<TLObject> {
+        let req = ton::rpc::engine::validator::GetSelectedStats {
+            filter: "keystore_encrypt".to_string()
+        };
+        Ok(TLObject::new(req))
+    }
+    fn receive(answer: TLObject, _params: &mut impl Iterator) -> Result<(String, Vec<u8>)> {
+        let data = serialize_boxed(&answer)?;
+        let stats = downcast::<ton_api::ton::engine::validator::Stats>(answer)?;
+        let description = stats_to_json(stats.stats().iter());
+        let description = format!("{:#}", description);
+        Ok((description, data))
+    }
+}
+
 impl <Q: ToString> SendReceive<Q> for GetSessionStats {
     fn send(_params: &mut impl Iterator) -> Result<TLObject> {
         Ok(TLObject::new(ton::rpc::engine::validator::GetSessionStats))
@@ -1156,7 +1197,11 @@ mod test {
     };
     use ever_node::{
         block::BlockKind, collator_test_bundle::{create_engine_telemetry, create_engine_allocated},
-        config::TonNodeConfig, engine_traits::{EngineAlloc, EngineOperations},
+        config::TonNodeConfig,
+        engine_traits::{
+            BlockAccess, BroadcastSupport, EngineAlloc, EngineOperations, RempSupport,
+            StateAccess, ValidatorSupport
+        },
         internal_db::{InternalDbConfig, InternalDb, state_gc_resolver::AllowStateGcSmartResolver}, 
         network::{control::{ControlServer, DataSource}, node_network::NodeNetwork},
         shard_state::ShardStateStuff, shard_states_keeper::PinnedShardStateGuard,
@@ -1304,39 +1349,15 @@ mod test {
         fn calc_tps(&self, _period: u64) -> Result<u32> {
             Ok(0)
         }
-        fn validation_status(&self) -> ValidationStatus {
-            ValidationStatus::Active
-        }
-        fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
-            &self.last_validation_time
-        }
-        fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
-            &self.last_validation_time
-        }
         fn get_sync_status(&self) -> u32 {
             0
         }
-        fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
-            self.db.load_block_handle(id)
-        }
         fn load_last_applied_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
             Ok(Some(Arc::new(self.master_state_id.clone())))
         }
-        async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
-            Ok(self.master_state.clone())   
-        }
         fn load_shard_client_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
             Ok(Some(Arc::new(self.master_state_id.clone())))
         }
-        async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
-            if block_id == &self.master_state_id {
-                Ok(self.master_state.clone())   
-            } else if block_id == &self.shard_state_id {
-                Ok(self.shard_state.clone())   
-            } else {
-                fail!("Wrong block ID {}", block_id)
-            }
-        }
         async fn load_and_pin_state(&self, block_id: &BlockIdExt) -> Result<PinnedShardStateGuard> {
             if *block_id == self.master_state_id {
                 PinnedShardStateGuard::new(
@@ -1361,6 +1382,34 @@ mod test {
                 None
             })
         }
+    }
+
+    impl BlockAccess for TestEngine {
+        fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+            self.db.load_block_handle(id)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl StateAccess for TestEngine {
+        async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+            Ok(self.master_state.clone())   
+        }
+        async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+            if block_id == &self.master_state_id {
+                Ok(self.master_state.clone())   
+            } else if block_id == &self.shard_state_id {
+                Ok(self.shard_state.clone())   
+            } else {
+                fail!("Wrong block ID {}", block_id)
+            }
+        }
+    }
+
+    impl RempSupport for TestEngine {}
+
+    #[async_trait::async_trait]
+    impl BroadcastSupport for TestEngine {
         async fn redirect_external_message(&self, _message: &[u8], _id: UInt256) -> Result<()> {
             if let Some(counter) = &self.counter {
                 counter.fetch_add(1, Ordering::Relaxed);
@@ -1368,6 +1417,18 @@ mod test {
             Ok(())
         }
     }
+
+    impl ValidatorSupport for TestEngine {
+        fn validation_status(&self) -> ValidationStatus {
+            ValidationStatus::Active
+        }
+        fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+            &self.last_validation_time
+        }
+        fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+            &self.last_validation_time
+        }
+    }
     
     const ADNL_SERVER_CONFIG: &str = r#"{
         "ton_global_config_name": "light_global.json",
@@ -0,0 +1,160 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::block::BlockStuff;
+use ever_block::{fail, BlockIdExt, Result};
+use std::{
+    pin::Pin, sync::{Arc, atomic::{AtomicU64, Ordering}}, task::{Context, Poll}
+};
+use storage::block_handle_db::BlockHandle;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+#[cfg(test)]
+#[path = "tests/test_applied_blocks.rs"]
+mod tests;
+
+pub const DEFAULT_APPLIED_BLOCKS_CHANNEL_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShardFilter {
+    Masterchain,
+    Workchain(i32),
+    All, // Including blocks of mesh networks
+}
+
+impl ShardFilter {
+    pub fn matches(&self, handle: &BlockHandle) -> bool {
+        match self {
+            ShardFilter::Masterchain =>
+                !handle.is_mesh() && handle.id().shard().is_masterchain(),
+            ShardFilter::Workchain(workchain_id) =>
+                !handle.is_mesh() && handle.id().shard().workchain_id() == *workchain_id,
+            ShardFilter::All => true
+        }
+    }
+}
+
+pub struct AppliedBlock {
+    pub handle: Arc<BlockHandle>,
+    // Block is set if it was in memory while applying
+    pub block: Option<BlockStuff>,
+}
+
+impl AppliedBlock {
+    pub fn id(&self) -> &BlockIdExt {
+        self.handle.id()
+    }
+}
+
+pub enum AppliedBlockEvent {
+    Applied(AppliedBlock),
+    // Count of notifications skipped because the subscriber was too slow
+    Lagged(u64),
+}
+
+pub struct AppliedBlockStream {
+    receiver: mpsc::Receiver<AppliedBlockEvent>,
+}
+
+impl AppliedBlockStream {
+    pub async fn recv(&mut self) -> Option<AppliedBlockEvent> {
+        self.receiver.recv().await
+    }
+}
+
+impl futures::Stream for AppliedBlockStream {
+    type Item = AppliedBlockEvent;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+struct AppliedBlockSubscriber {
+    filter: ShardFilter,
+    sender: mpsc::Sender<AppliedBlockEvent>,
+    lagged: u64,
+}
+
+// Delivers applied block notifications to subscribers. Block applying never waits for
+// subscribers: if a subscriber's channel is full, the notification is skipped and
+// the subscriber gets Lagged event with count of skipped ones as soon as there is a room.
+pub struct AppliedBlocksNotifier {
+    subscribers: parking_lot::Mutex<Vec<AppliedBlockSubscriber>>,
+    channel_size: usize,
+    overflowed: AtomicU64,
+}
+
+impl AppliedBlocksNotifier {
+
+    pub fn new(channel_size: usize) -> Self {
+        Self {
+            subscribers: parking_lot::Mutex::new(Vec::new()),
+            channel_size,
+            overflowed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn subscribe(&self, filter: ShardFilter) -> Result<AppliedBlockStream> {
+        if self.channel_size == 0 {
+            fail!("Applied blocks channel size must be positive")
+        }
+        let (sender, receiver) = mpsc::channel(self.channel_size);
+        self.subscribers.lock().push(AppliedBlockSubscriber { filter, sender, lagged: 0 });
+        Ok(AppliedBlockStream { receiver })
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.lock().len()
+    }
+
+    // Total count of notifications skipped for all subscribers
+    pub fn overflowed(&self) -> u64 {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+
+    pub fn notify(&self, handle: &Arc<BlockHandle>, block: Option<&BlockStuff>) {
+        let mut subscribers = self.subscribers.lock();
+        if subscribers.is_empty() {
+            return
+        }
+        // Closed subscribers are removed
+        subscribers.retain_mut(|subscriber| {
+            if !subscriber.filter.matches(handle) {
+                return !subscriber.sender.is_closed()
+            }
+            if subscriber.lagged > 0 {
+                match subscriber.sender.try_send(AppliedBlockEvent::Lagged(subscriber.lagged)) {
+                    Ok(()) => subscriber.lagged = 0,
+                    Err(TrySendError::Full(_)) => {
+                        subscriber.lagged += 1;
+                        self.overflowed.fetch_add(1, Ordering::Relaxed);
+                        return true
+                    }
+                    Err(TrySendError::Closed(_)) => return false
+                }
+            }
+            let event = AppliedBlockEvent::Applied(
+                AppliedBlock { handle: handle.clone(), block: block.cloned() }
+            );
+            match subscriber.sender.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.lagged += 1;
+                    self.overflowed.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false
+            }
+        });
+    }
+}
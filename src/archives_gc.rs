@@ -0,0 +1,193 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    config::{ArchivesWatermarksConfig, DiskWatermark},
+    internal_db::storage_usage::collect_files
+};
+use ever_block::{fail, Result};
+use std::{collections::BTreeSet, path::{Path, PathBuf}, sync::Arc};
+use storage::archives::archive_manager::ArchivePackageInfo;
+
+#[cfg(test)]
+#[path = "tests/test_archives_gc.rs"]
+mod tests;
+
+pub trait DiskUsageProvider: Send + Sync {
+    // Bytes taken by archives
+    fn used_bytes(&self) -> Result<u64>;
+    // Size of the file system archives are stored on
+    fn total_bytes(&self) -> Result<u64>;
+}
+
+// Usage of archive directory: packages and unapplied block files
+pub struct ArchiveDirUsage {
+    dir: PathBuf,
+}
+
+impl ArchiveDirUsage {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl DiskUsageProvider for ArchiveDirUsage {
+    fn used_bytes(&self) -> Result<u64> {
+        let mut files = Vec::new();
+        collect_files(&self.dir, &mut files)?;
+        Ok(files.iter().map(|(_, metadata)| metadata.len()).sum())
+    }
+
+    fn total_bytes(&self) -> Result<u64> {
+        filesystem_size(&self.dir)
+    }
+}
+
+#[cfg(unix)]
+fn filesystem_size(path: &Path) -> Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        fail!("Can't get file system stats of {}: {}", path.display(), std::io::Error::last_os_error())
+    }
+    Ok(stat.f_blocks as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn filesystem_size(path: &Path) -> Result<u64> {
+    fail!("Can't get file system size of {}: not supported on the platform", path.display())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub used: u64,
+    pub high_watermark: u64,
+    pub low_watermark: u64,
+}
+
+impl DiskUsage {
+    pub fn above_high_watermark(&self) -> bool {
+        self.used > self.high_watermark
+    }
+}
+
+// Stats of the last run, exported by control query and metrics
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchivesGcStats {
+    pub started_at: u32,
+    pub duration_ms: u64,
+    pub used_bytes_before: u64,
+    pub used_bytes_after: u64,
+    pub high_watermark_bytes: u64,
+    pub low_watermark_bytes: u64,
+    // Packages ending after this masterchain seqno are kept by min retention
+    pub retention_floor_seq_no: u32,
+    pub removed_packages: u32,
+    pub removed_bytes: u64,
+    pub error: Option<String>,
+}
+
+// GC of archive packages driven by disk usage. Once usage is over the high watermark,
+// the oldest packages are removed until usage is below the low watermark.
+pub struct ArchivesWatermarkGc {
+    config: ArchivesWatermarksConfig,
+    usage_provider: Arc<dyn DiskUsageProvider>,
+    last_run: parking_lot::Mutex<Option<ArchivesGcStats>>,
+}
+
+impl ArchivesWatermarkGc {
+
+    pub fn new(config: ArchivesWatermarksConfig, usage_provider: Arc<dyn DiskUsageProvider>) -> Result<Self> {
+        for watermark in [&config.high_watermark, &config.low_watermark] {
+            if let DiskWatermark::Percent(percent) = watermark {
+                if *percent > 100 {
+                    fail!("Archives GC watermark {}% is more than 100%", percent)
+                }
+            }
+        }
+        Ok(Self { config, usage_provider, last_run: parking_lot::Mutex::new(None) })
+    }
+
+    pub fn config(&self) -> &ArchivesWatermarksConfig {
+        &self.config
+    }
+
+    // Current usage with watermarks converted to bytes
+    pub fn usage(&self) -> Result<DiskUsage> {
+        let mut total = None;
+        let mut to_bytes = |watermark: &DiskWatermark| -> Result<u64> {
+            match watermark {
+                DiskWatermark::Bytes(bytes) => Ok(*bytes),
+                DiskWatermark::Percent(percent) => {
+                    let total = match total {
+                        Some(total) => total,
+                        None => *total.insert(self.usage_provider.total_bytes()?)
+                    };
+                    Ok((total as u128 * *percent as u128 / 100) as u64)
+                }
+            }
+        };
+        let high_watermark = to_bytes(&self.config.high_watermark)?;
+        let low_watermark = to_bytes(&self.config.low_watermark)?;
+        if low_watermark > high_watermark {
+            fail!("Archives GC low watermark {} is above high watermark {}", low_watermark, high_watermark)
+        }
+        let used = self.usage_provider.used_bytes()?;
+        Ok(DiskUsage { used, high_watermark, low_watermark })
+    }
+
+    // Chooses packages to get usage below the low watermark. Only packages ending not later
+    // than the retention floor are removed, the oldest ones first. If packages with key
+    // blocks are preserved, they are removed only after all other ones.
+    pub fn select_packages(
+        &self,
+        packages: &[ArchivePackageInfo],
+        floor_seq_no: u32,
+        key_blocks: &BTreeSet<u32>,
+        usage: &DiskUsage
+    ) -> Vec<u32> {
+        let mut candidates = packages.iter()
+            .filter(|package| package.end_seq_no <= floor_seq_no)
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|package| package.archive_id);
+        if self.config.preserve_key_block_packages {
+            // Stable sort keeps the age order within both groups
+            candidates.sort_by_key(|package| {
+                key_blocks.range(package.archive_id..package.end_seq_no).next().is_some()
+            });
+        }
+        let mut used = usage.used;
+        let mut selected = Vec::new();
+        for package in candidates {
+            if used < usage.low_watermark {
+                break
+            }
+            used = used.saturating_sub(package.size);
+            selected.push(package.archive_id);
+        }
+        selected
+    }
+
+    pub fn set_last_run(&self, stats: ArchivesGcStats) {
+        metrics::gauge!("archives_gc_used_bytes", stats.used_bytes_after as f64);
+        metrics::gauge!("archives_gc_removed_packages", stats.removed_packages as f64);
+        metrics::gauge!("archives_gc_removed_bytes", stats.removed_bytes as f64);
+        metrics::gauge!("archives_gc_duration_ms", stats.duration_ms as f64);
+        *self.last_run.lock() = Some(stats);
+    }
+
+    pub fn last_run(&self) -> Option<ArchivesGcStats> {
+        self.last_run.lock().clone()
+    }
+}
@@ -12,25 +12,69 @@
 */
 
 use crate::{
-    block::{BlockIdExtExtention, BlockStuff},
+    block::{BlockIdExtExtention, BlockKind, BlockStuff},
     error::NodeError,
     shard_state::ShardStateStuff,
     engine_traits::EngineOperations,
     validator::validator_utils::{
-        check_crypto_signatures, calc_subset_for_masterchain, ValidatorSubsetInfo
+        check_crypto_signatures, calc_subset_for_masterchain, collect_crypto_signatures,
+        SignatureBatch, ValidatorSubsetInfo
     },
 };
 
 use ever_block::{
     error, fail, Block, BlockIdExt, BlockInfo, BlockProof, BocReader, Cell, ConfigParams, 
     Deserializable, HashmapType, MerkleProof, Result, Serializable, write_boc, BlockSignatures,
+    ByteOrderRead, CryptoSignaturePair, UInt256,
 };
-use std::sync::Arc;
+use std::{io::Cursor, sync::Arc};
 
 #[cfg(test)]
 #[path = "tests/test_block_proof.rs"]
 mod tests;
 
+// Who assembled the block: the collator and validators whose signatures are in the proof.
+// Proof links of shard blocks have no signatures, so signers of shard blocks are empty
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct BlockProvenance {
+    // Public key of the collator
+    pub created_by: UInt256,
+    pub catchain_seqno: u32,
+    pub validator_list_hash_short: u32,
+    // Short node ids of validators which signed the block
+    pub signers: Vec<UInt256>,
+}
+
+impl BlockProvenance {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(44 + self.signers.len() * 32);
+        data.extend_from_slice(self.created_by.as_slice());
+        data.extend_from_slice(&self.catchain_seqno.to_le_bytes());
+        data.extend_from_slice(&self.validator_list_hash_short.to_le_bytes());
+        data.extend_from_slice(&(self.signers.len() as u32).to_le_bytes());
+        for signer in &self.signers {
+            data.extend_from_slice(signer.as_slice());
+        }
+        data
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let created_by = UInt256::from(cursor.read_u256()?);
+        let catchain_seqno = cursor.read_le_u32()?;
+        let validator_list_hash_short = cursor.read_le_u32()?;
+        let count = cursor.read_le_u32()?;
+        let mut signers = Vec::new();
+        for _ in 0..count {
+            signers.push(UInt256::from(cursor.read_u256()?));
+        }
+        if cursor.position() as usize != data.len() {
+            fail!("Block provenance has {} extra bytes", data.len() - cursor.position() as usize)
+        }
+        Ok(Self { created_by, catchain_seqno, validator_list_hash_short, signers })
+    }
+}
+
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct BlockProofStuff {
     proof: BlockProof,
@@ -159,23 +203,76 @@ impl BlockProofStuff {
         Arc::try_unwrap(self.data).unwrap_or_else(|s| (*s).clone())
     }
 
+    // Collator is taken from the block extra which is always kept in proofs
+    pub fn provenance(&self) -> Result<BlockProvenance> {
+        let (virt_block, _) = self.virtualize_block()?;
+        let mut provenance = BlockProvenance {
+            created_by: virt_block.read_extra()?.created_by().clone(),
+            ..Default::default()
+        };
+        if let Some(signatures) = &self.proof.signatures {
+            provenance.catchain_seqno = signatures.validator_info.catchain_seqno;
+            provenance.validator_list_hash_short = signatures.validator_info.validator_list_hash_short;
+            signatures.pure_signatures.signatures().iterate_slices(|_key, ref mut slice| {
+                let sign = CryptoSignaturePair::construct_from(slice)?;
+                provenance.signers.push(sign.node_id_short);
+                Ok(true)
+            })?;
+        }
+        Ok(provenance)
+    }
+
     pub fn drain_signatures(self) -> Result<BlockSignatures> {
         self.proof.signatures.ok_or_else(|| error!("Proof doesn't contain signatures"))
     }
 
     pub fn check_with_prev_key_block_proof(&self, prev_key_block_proof: &BlockProofStuff) -> Result<()> {
+        self.check_with_prev_key_block_proof_batched(prev_key_block_proof, None)
+    }
+
+    // Signatures are not verified but added to the batch, which must be verified then
+    pub fn check_with_prev_key_block_proof_deferred(
+        &self,
+        prev_key_block_proof: &BlockProofStuff,
+        batch: &mut SignatureBatch
+    ) -> Result<()> {
+        self.check_with_prev_key_block_proof_batched(prev_key_block_proof, Some(batch))
+    }
+
+    fn check_with_prev_key_block_proof_batched(
+        &self,
+        prev_key_block_proof: &BlockProofStuff,
+        batch: Option<&mut SignatureBatch>
+    ) -> Result<()> {
         let now = std::time::Instant::now();
         log::trace!("Checking proof for block: {}", self.id());
 
         let (virt_block, virt_block_info) = self.pre_check_block_proof()?;
 
-        self.check_with_prev_key_block_proof_(prev_key_block_proof, &virt_block, &virt_block_info)?;
+        self.check_with_prev_key_block_proof_(prev_key_block_proof, &virt_block, &virt_block_info, batch)?;
 
         log::trace!("Checked proof for block: {}   TIME {}ms", self.id(), now.elapsed().as_millis());
         Ok(())
     }
 
     pub fn check_with_master_state(&self, master_state: &ShardStateStuff) -> Result<()> {
+        self.check_with_master_state_batched(master_state, None)
+    }
+
+    // Signatures are not verified but added to the batch, which must be verified then
+    pub fn check_with_master_state_deferred(
+        &self,
+        master_state: &ShardStateStuff,
+        batch: &mut SignatureBatch
+    ) -> Result<()> {
+        self.check_with_master_state_batched(master_state, Some(batch))
+    }
+
+    fn check_with_master_state_batched(
+        &self,
+        master_state: &ShardStateStuff,
+        batch: Option<&mut SignatureBatch>
+    ) -> Result<()> {
         let now = std::time::Instant::now();
         log::trace!("Checking proof for block: {}", self.id());
 
@@ -187,7 +284,7 @@ impl BlockProofStuff {
 
         let (virt_block, virt_block_info) = self.pre_check_block_proof()?;
 
-        self.check_with_master_state_(master_state, &virt_block, &virt_block_info)?;
+        self.check_with_master_state_(master_state, &virt_block, &virt_block_info, batch)?;
 
         log::trace!("Checked proof for block: {}   TIME {}ms", self.id(), now.elapsed().as_millis());
         Ok(())
@@ -233,7 +330,7 @@ impl BlockProofStuff {
 
             if prev_key_block_seqno == 0 {
                 let zerostate = engine.load_mc_zero_state().await?;
-                self.check_with_master_state_(&zerostate, &virt_block, &virt_block_info)?;
+                self.check_with_master_state_(&zerostate, &virt_block, &virt_block_info, None)?;
             } else {
                 let handle = engine.find_mc_block_by_seq_no(prev_key_block_seqno).await
                     .map_err(|err|
@@ -247,7 +344,8 @@ impl BlockProofStuff {
                 self.check_with_prev_key_block_proof_(
                     &prev_key_block_proof, 
                     &virt_block, 
-                    &virt_block_info
+                    &virt_block_info,
+                    None
                 )?;
             }
 
@@ -285,6 +383,37 @@ impl BlockProofStuff {
         Ok(())
     }
 
+    // Recomputes hashes of out queue parts carried by queue update and compares them with 
+    // the ones declared by the update, which are committed by the block root hash. 
+    // Empty queue updates are not verified, false is returned for them.
+    pub fn verify_queue_update(queue_update: &BlockStuff) -> Result<bool> {
+        let id = queue_update.id();
+        let (wc, empty) = match queue_update.kind() {
+            BlockKind::QueueUpdate { queue_update_for, empty } => (queue_update_for, empty),
+            _ => fail!("Block {} is not a queue update", id)
+        };
+        if empty {
+            return Ok(false)
+        }
+        let update = queue_update.get_queue_update_for(wc)?.update;
+        for (side, root, hash, depth) in [
+            ("old", &update.old, &update.old_hash, update.old_depth),
+            ("new", &update.new, &update.new_hash, update.new_depth),
+        ] {
+            // Hashes are calculated again while the tree is read back
+            let data = Arc::new(write_boc(root)?);
+            let root = BocReader::new().read_inmem(data)?.withdraw_single_root()?;
+            if root.hash(0) != *hash || root.depth(0) != depth {
+                fail!(NodeError::QueueUpdateMismatch(format!(
+                    "Queue update {} for wc {}: {} out queue hash {:x} depth {} doesn't match \
+                    declared hash {:x} depth {}",
+                    id, wc, side, root.hash(0), root.depth(0), hash, depth
+                )))
+            }
+        }
+        Ok(true)
+    }
+
 // Unused
 //    pub fn get_cur_validators_set(&self) -> Result<(ValidatorSet, CatchainConfig)> {
 //        let (virt_key_block, prev_key_block_info) = self.pre_check_block_proof()?;
@@ -308,7 +437,8 @@ impl BlockProofStuff {
         &self,
         prev_key_block_proof: &BlockProofStuff,
         virt_block: &Block,
-        virt_block_info: &BlockInfo
+        virt_block_info: &BlockInfo,
+        batch: Option<&mut SignatureBatch>
     ) -> Result<()> {
 
         if !self.id().is_masterchain() {
@@ -348,15 +478,21 @@ impl BlockProofStuff {
             self.pre_check_key_block_proof(virt_block)?;
         }
 
-        self.check_signatures(&subset)
+        self.check_signatures(&subset, batch)
     }
 
-    fn check_with_master_state_(&self, master_state: &ShardStateStuff, virt_block: &Block, virt_block_info: &BlockInfo) -> Result<()> {
+    fn check_with_master_state_(
+        &self,
+        master_state: &ShardStateStuff,
+        virt_block: &Block,
+        virt_block_info: &BlockInfo,
+        batch: Option<&mut SignatureBatch>
+    ) -> Result<()> {
         if virt_block_info.key_block() {
             self.pre_check_key_block_proof(&virt_block)?;
         }
         let subset = self.process_given_state(master_state, virt_block_info)?;
-        self.check_signatures(&subset)
+        self.check_signatures(&subset, batch)
     }
 
     fn pre_check_block_proof(&self) -> Result<(Block, BlockInfo)> {
@@ -541,7 +677,7 @@ impl BlockProofStuff {
         Ok((subset, virt_key_block))
     }
 
-    fn check_signatures(&self, subset: &ValidatorSubsetInfo) -> Result<()> {
+    fn check_signatures(&self, subset: &ValidatorSubsetInfo, batch: Option<&mut SignatureBatch>) -> Result<()> {
 
         // Pre checks
         if self.proof.signatures.is_none() {
@@ -576,12 +712,17 @@ impl BlockProofStuff {
             &self.id.file_hash
         );
         let total_weight: u64 = subset.validators.iter().map(|v| v.weight).sum();
-        let weight = check_crypto_signatures(&signatures.pure_signatures, &subset.validators, &checked_data)
-            .map_err(|err| { 
-                NodeError::InvalidData(
-                    format!("Proof for {}: error while check signatures: {}", self.id(), err)
-                )
-            })?;
+        let weight = match batch {
+            Some(batch) => {
+                batch.set_owner(format!("Proof for {}", self.id()));
+                collect_crypto_signatures(&signatures.pure_signatures, &subset.validators, &checked_data, batch)
+            }
+            None => check_crypto_signatures(&signatures.pure_signatures, &subset.validators, &checked_data)
+        }.map_err(|err| { 
+            NodeError::InvalidData(
+                format!("Proof for {}: error while check signatures: {}", self.id(), err)
+            )
+        })?;
 
         // Check weight
         if weight != signatures.pure_signatures.weight() {
@@ -646,3 +787,55 @@ impl BlockProofStuff {
     }
 }
 
+
+/// Builds chain of serialized proofs which allows to check the block starting from the trusted 
+/// key block. Proofs go in order of verification: key blocks proofs from the oldest one, proof 
+/// of the masterchain block and proof link of the shard block. Each key block and masterchain 
+/// block proof is signed by validators of the previous key block.
+pub async fn build_proof_chain(
+    engine: &dyn EngineOperations,
+    id: &BlockIdExt,
+    trusted_key_block_seqno: u32,
+    max_length: usize,
+) -> Result<Vec<Vec<u8>>> {
+    let handle = engine.load_block_handle(id)?.ok_or_else(
+        || error!(NodeError::NotFound(format!("handle of block {}", id)))
+    )?;
+    let mut chain = Vec::new();
+    let mut handle = if id.is_masterchain() {
+        handle
+    } else {
+        // Proofs moved to archives are read by archive manager as well
+        chain.push(engine.load_block_proof_raw(&handle, true).await?);
+        let mc_seqno = handle.masterchain_ref_seq_no();
+        if mc_seqno == 0 {
+            fail!(NodeError::NotFound(format!("masterchain block which refers block {}", id)))
+        }
+        engine.find_mc_block_by_seq_no(mc_seqno).await?
+    };
+    if handle.id().seq_no() < trusted_key_block_seqno {
+        fail!(
+            "Can't build proof chain for block {}: it is older than trusted key block {}",
+            id, trusted_key_block_seqno
+        )
+    }
+    loop {
+        if chain.len() >= max_length {
+            fail!("Proof chain for block {} is longer than {}", id, max_length)
+        }
+        let proof = BlockProofStuff::deserialize(
+            handle.id(),
+            engine.load_block_proof_raw(&handle, false).await?,
+            false
+        )?;
+        let prev_key_block_seqno = proof.virtualize_block()?.0.read_info()?.prev_key_block_seqno();
+        chain.push(proof.drain_data());
+        // Trusted key block ends the chain, it may be the requested block itself
+        if handle.id().seq_no() <= trusted_key_block_seqno || prev_key_block_seqno <= trusted_key_block_seqno {
+            break
+        }
+        handle = engine.find_mc_block_by_seq_no(prev_key_block_seqno).await?;
+    }
+    chain.reverse();
+    Ok(chain)
+}
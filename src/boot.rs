@@ -12,8 +12,8 @@
 */
 
 use crate::{
-    CHECK, block_proof::BlockProofStuff, engine_traits::EngineOperations, 
-    shard_state::ShardStateStuff, engine::Engine
+    CHECK, block_proof::BlockProofStuff, engine_traits::{BlockAccess, EngineOperations, StateAccess},
+    error::{is_retryable, NodeError}, shard_state::ShardStateStuff, engine::Engine
 };
 
 use std::collections::HashSet;
@@ -21,7 +21,7 @@ use std::{ops::Deref, sync::Arc, time::Duration};
 use std::path::Path;
 use storage::block_handle_db::BlockHandle;
 use ever_block::{BlockIdExt, ShardIdent, SHARD_FULL};
-use ever_block::{error, fail, KeyId, Result};
+use ever_block::{error, fail, Error, KeyId, Result};
 use crate::block::BlockStuff;
 use crate::validator::accept_block::create_new_proof_link;
 
@@ -45,6 +45,9 @@ async fn run_cold(
         return Ok((handle, Some(zero_state), None));
     }
 
+    // trusted key block from node config must exactly match the proof
+    let trusted_id = engine.trusted_key_block().filter(|id| *id == block_id);
+
     // id should be key block if not it will never sync
     log::info!(target: "boot", "check if block proof is in database {}", block_id);
     let handle = if let Some(handle) = engine.load_block_handle(&block_id)? {
@@ -60,6 +63,10 @@ async fn run_cold(
                     engine.load_block_proof(&handle, true).await?
                 }
             };
+            if let Some(trusted_id) = trusted_id {
+                check_trusted_key_block(trusted_id, &proof)?;
+                log::info!(target: "boot", "resuming boot from trusted key block {}", trusted_id);
+            }
             CHECK!(handle.is_key_block()?);
             return Ok((handle, None, Some(proof)))
         }
@@ -71,7 +78,7 @@ async fn run_cold(
     let (handle, proof) = loop {
 
         if engine.check_stop() {
-            fail!("Boot was stopped");
+            fail!(NodeError::Cancelled("Boot was stopped".to_string()));
         }
 
         log::info!(target: "boot", "download init block proof {}", block_id);
@@ -79,6 +86,9 @@ async fn run_cold(
             Ok(proof) => match proof.check_proof_as_link() {
                 Ok(_) => {
                     log::info!(target: "boot", "block proof downloaded {}", block_id);
+                    if let Some(trusted_id) = trusted_id {
+                        check_trusted_key_block(trusted_id, &proof)?;
+                    }
                     let handle = engine.store_block_proof(0, &block_id, handle, &proof).await? 
                         .to_non_created()
                         .ok_or_else( 
@@ -96,11 +106,16 @@ async fn run_cold(
                     block_id, err
                 )
             },
-            Err(err) => log::warn!(
-                target: "boot", 
-                "download block proof for init_block {} error: {}", 
-                block_id, err
-            )
+            Err(err) => {
+                log::warn!(
+                    target: "boot", 
+                    "download block proof for init_block {} error: {}", 
+                    block_id, err
+                );
+                if !is_retryable(&err) {
+                    return Err(err)
+                }
+            }
         }
         futures_timer::Delay::new(Duration::from_secs(1)).await;
 
@@ -108,6 +123,9 @@ async fn run_cold(
         match engine.download_block_proof(0, &block_id, true, true).await {
             Ok(proof) => match proof.check_proof_link() {
                 Ok(_) => {
+                    if let Some(trusted_id) = trusted_id {
+                        check_trusted_key_block(trusted_id, &proof)?;
+                    }
                     let handle = engine.store_block_proof(0, &block_id, handle, &proof).await? 
                         .to_non_created()
                         .ok_or_else( 
@@ -125,11 +143,16 @@ async fn run_cold(
                     block_id, err
                 )
             },
-            Err(err) => log::warn!(
-                target: "boot", 
-                "download block proof link for init_block {} error: {}", 
-                block_id, err
-            )
+            Err(err) => {
+                log::warn!(
+                    target: "boot", 
+                    "download block proof link for init_block {} error: {}", 
+                    block_id, err
+                );
+                if !is_retryable(&err) {
+                    return Err(err)
+                }
+            }
         }
         futures_timer::Delay::new(Duration::from_secs(1)).await;
 
@@ -140,6 +163,58 @@ async fn run_cold(
 
 }
 
+/// choose the block to start cold boot from:
+/// trusted key block from node config is preferred over init block from global config,
+/// init block saved by interrupted boot is used if it is newer
+pub(crate) fn choose_init_block(
+    zero_state_id: &BlockIdExt,
+    init_block: Option<BlockIdExt>,
+    trusted_key_block: Option<&BlockIdExt>,
+    last_hardfork: Option<&BlockIdExt>,
+    saved_init_block: Option<&BlockIdExt>,
+    boot_from_zerostate: bool,
+) -> Result<BlockIdExt> {
+    if boot_from_zerostate {
+        return Ok(zero_state_id.clone())
+    }
+    let mut init_block_id = if let Some(trusted_id) = trusted_key_block {
+        log::info!("zero state substitued by trusted key block {}", trusted_id);
+        trusted_id.clone()
+    } else if let Some(init_block_id) = init_block {
+        log::info!("zero state substitued by init block {}", init_block_id);
+        init_block_id
+    } else {
+        zero_state_id.clone()
+    };
+    if let Some(block_id) = last_hardfork {
+        if block_id.seq_no > init_block_id.seq_no {
+            fail!("config contains init block and hardforks sections so init block {} \
+                must be after last hard_fork {}", init_block_id.seq_no, block_id.seq_no)
+        }
+    }
+    if let Some(block_id) = saved_init_block {
+        if block_id.seq_no > init_block_id.seq_no {
+            init_block_id = block_id.clone()
+        }
+    }
+    Ok(init_block_id)
+}
+
+/// check that the proof is for trusted key block supplied in config
+pub(crate) fn check_trusted_key_block(trusted_id: &BlockIdExt, proof: &BlockProofStuff) -> Result<()> {
+    if proof.id() != trusted_id {
+        fail!(NodeError::InvalidProof(format!(
+            "trusted key block {} doesn't match the proof for {}, check hashes in config",
+            trusted_id, proof.id()
+        )))
+    }
+    let (virt_block, _) = proof.virtualize_block()?;
+    if !virt_block.read_info()?.key_block() {
+        fail!(NodeError::InvalidProof(format!("trusted block {} is not a key block", trusted_id)))
+    }
+    Ok(())
+}
+
 /// download key blocks
 /// 1. define time period
 /// 2. get next key blocks ids infinitely
@@ -158,7 +233,7 @@ async fn get_key_blocks(
     let mut stuck_count = 0;
     'main_loop: loop {
         if engine.check_stop() {
-            fail!("Boot was stopped");
+            fail!(NodeError::Cancelled("Boot was stopped".to_string()));
         }
         log::info!(target: "boot", "download_next_key_blocks_ids {}", handle.id());
         // this information is not trusted
@@ -230,6 +305,9 @@ async fn get_key_blocks(
                     }
                     Err(err) => {
                         log::warn!(target: "boot", "cannot get block proof link for {}: {}", block_id, err);
+                        if !is_retryable(&err) {
+                            return Err(err)
+                        }
                         futures_timer::Delay::new(Duration::from_secs(1)).await;
                         continue 'main_loop;
                     }
@@ -389,7 +467,7 @@ pub(crate) async fn download_zerostate(
     log::info!(target: "boot", "download zero state {}", block_id);
     loop {
         if engine.check_stop() {
-            fail!("Boot was stopped");
+            fail!(NodeError::Cancelled("Boot was stopped".to_string()));
         }
         match engine.download_zerostate(0, block_id).await {
             Ok((state, state_bytes)) => {
@@ -400,7 +478,12 @@ pub(crate) async fn download_zerostate(
                 engine.process_initial_state(&state).await?;
                 return Ok(handle)
             }
-            Err(err) => log::warn!(target: "boot", "download_zerostate error: {}", err)
+            Err(err) => {
+                log::warn!(target: "boot", "download_zerostate error: {}", err);
+                if !is_retryable(&err) {
+                    return Err(err)
+                }
+            }
         }
         futures_timer::Delay::new(Duration::from_secs(1)).await;
     }
@@ -420,7 +503,7 @@ async fn download_and_check_key_block_proof(
     }
     loop {
         if engine.check_stop() {
-            fail!("Boot was stopped");
+            fail!(NodeError::Cancelled("Boot was stopped".to_string()));
         }
         let proof = engine.download_block_proof(0, block_id, false, true).await?;
         let result = if let Some(prev_block_proof) = prev_block_proof {
@@ -538,14 +621,17 @@ pub async fn cold_boot(engine: Arc<dyn EngineOperations>) -> Result<Arc<BlockHan
             };
             download_wc_zerostates(engine.deref(), zero_state).await?;
             break Ok(handle);
-        } else if let Err(err) = download_start_blocks_and_states(
+        }
+        // interrupted boot will be resumed from the chosen key block
+        engine.save_init_mc_block_id(handle.id())?;
+        if let Err(err) = download_start_blocks_and_states(
             engine.deref(), 
             &handle, 
             &active_peers, 
             &mut bad_peers
         ).await {
             i += 1;
-            if i >= MAX_RETRIES {
+            if i >= MAX_RETRIES || !is_retryable(&err) {
                 break Err(err)
             }
             log::warn!(target: "boot", "{}", err)
@@ -556,12 +642,30 @@ pub async fn cold_boot(engine: Arc<dyn EngineOperations>) -> Result<Arc<BlockHan
 
 }
 
+#[cfg(test)]
+#[path = "tests/test_boot.rs"]
+mod tests;
+
 pub async fn warm_boot(
     engine: Arc<Engine>,
     block_id: Arc<BlockIdExt>,
     hardfork_path: impl AsRef<Path>,
+    state_rollback: bool,
 ) -> Result<BlockIdExt> {
     log::info!(target: "boot", "Warm boot");
+    let block_id = match engine.load_state(&block_id).await {
+        Ok(_) => block_id,
+        Err(err) => {
+            let rollback_id = recover_pruned_state(engine.deref(), &block_id, err, state_rollback).await?;
+            // Shard states keeper must not be ahead of the chain
+            if let Some(pss_keeper_id) = engine.load_pss_keeper_mc_block_id()? {
+                if pss_keeper_id.seq_no() > rollback_id.seq_no() {
+                    engine.save_pss_keeper_mc_block_id(&rollback_id)?;
+                }
+            }
+            Arc::new(rollback_id)
+        }
+    };
     if let Some(block_id) = check_hardforks(&engine, &block_id, hardfork_path).await? {
         return Ok(block_id)
     }
@@ -585,6 +689,132 @@ pub async fn warm_boot(
     Ok(block_id)
 }
 
+fn is_state_not_found(err: &Error) -> bool {
+    matches!(
+        err.downcast_ref::<NodeError>(),
+        Some(NodeError::NotFound(_)) | Some(NodeError::StateNotAvailable(_))
+    )
+}
+
+/// Rolls full node state back if the state of the last applied masterchain block is pruned.
+/// Other errors, as well as any error if rollback is disabled, are returned as is
+pub(crate) async fn recover_pruned_state(
+    engine: &dyn EngineOperations,
+    last_applied_id: &BlockIdExt,
+    err: Error,
+    state_rollback: bool,
+) -> Result<BlockIdExt> {
+    if !is_state_not_found(&err) {
+        return Err(err)
+    }
+    if !state_rollback {
+        log::error!(
+            target: "boot",
+            "State of last applied block {} is not available and state rollback is disabled: {}",
+            last_applied_id, err
+        );
+        return Err(err)
+    }
+    let handle = find_rollback_block(engine, last_applied_id).await?;
+    if !handle.has_state() {
+        restore_state_from_persistent(engine, &handle).await?;
+    }
+    log::warn!(
+        target: "boot",
+        "STATE ROLLBACK: state of last applied block {} is not available ({}), \
+        node state is rolled back to block {}, {} masterchain blocks are to be applied again",
+        last_applied_id, err, handle.id(), last_applied_id.seq_no() - handle.id().seq_no()
+    );
+    engine.save_last_applied_mc_block_id(handle.id())?;
+    if let Some(shard_client_id) = engine.load_shard_client_mc_block_id()? {
+        if shard_client_id.seq_no() > handle.id().seq_no() {
+            engine.save_shard_client_mc_block_id(handle.id())?;
+        }
+    }
+    Ok(handle.id().clone())
+}
+
+/// Walks back from the last applied masterchain block to the most recent applied one
+/// whose state is stored or can be restored from persistent state file.
+/// Rollback never goes past the init or trusted key block
+pub(crate) async fn find_rollback_block(
+    engine: &dyn EngineOperations,
+    last_applied_id: &BlockIdExt,
+) -> Result<Arc<BlockHandle>> {
+    let mut min_seq_no = engine.init_mc_block_id().seq_no();
+    if let Some(trusted_id) = engine.trusted_key_block() {
+        min_seq_no = min_seq_no.max(trusted_id.seq_no());
+    }
+    let mut block_id = last_applied_id.clone();
+    while block_id.seq_no() >= min_seq_no {
+        let handle = engine.load_block_handle(&block_id)?.ok_or_else(
+            || error!("Cannot load handle for block {}", block_id)
+        )?;
+        if handle.is_applied() && is_state_available(engine, &handle).await? {
+            return Ok(handle)
+        }
+        if (block_id.seq_no() == min_seq_no) || !handle.has_prev1() {
+            break
+        }
+        block_id = engine.load_block_prev1(&block_id)?;
+    }
+    fail!(NodeError::NotFound(format!(
+        "No block with available state between init block seqno {} and last applied block {}",
+        min_seq_no, last_applied_id
+    )))
+}
+
+async fn is_state_available(engine: &dyn EngineOperations, handle: &Arc<BlockHandle>) -> Result<bool> {
+    if handle.has_state() {
+        match engine.load_state(handle.id()).await {
+            Ok(_) => return Ok(true),
+            Err(err) if is_state_not_found(&err) => (),
+            Err(err) => return Err(err)
+        }
+    }
+    if handle.has_persistent_state() {
+        match engine.load_persistent_state_size(handle.id()).await {
+            Ok(_) => return Ok(true),
+            Err(err) => log::warn!(
+                target: "boot", "Persistent state of block {} is not available: {}", handle.id(), err
+            )
+        }
+    }
+    Ok(false)
+}
+
+/// Stores masterchain state and absent states of its top shard blocks from persistent state files
+async fn restore_state_from_persistent(engine: &dyn EngineOperations, handle: &Arc<BlockHandle>) -> Result<()> {
+    let state = load_persistent_state(engine, handle).await?;
+    for id in state.top_blocks_all()? {
+        let shard_handle = engine.load_block_handle(&id)?.ok_or_else(
+            || error!("Cannot load handle for shard block {}", id)
+        )?;
+        if !shard_handle.has_state() && shard_handle.has_persistent_state() {
+            load_persistent_state(engine, &shard_handle).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn load_persistent_state(
+    engine: &dyn EngineOperations,
+    handle: &Arc<BlockHandle>
+) -> Result<Arc<ShardStateStuff>> {
+    log::info!(target: "boot", "Restoring state of block {} from persistent state", handle.id());
+    let size = engine.load_persistent_state_size(handle.id()).await?;
+    let data = engine.load_persistent_state_slice(handle, 0, size).await?;
+    let state = ShardStateStuff::deserialize_state_inmem(
+        handle.id().clone(),
+        Arc::new(data),
+        #[cfg(feature = "telemetry")]
+        engine.engine_telemetry(),
+        engine.engine_allocated(),
+        &|| engine.check_stop()
+    )?;
+    engine.store_state(handle, state).await
+}
+
 async fn check_hardforks(
     engine: &Arc<Engine>, 
     last_applied_mc_block: &Arc<BlockIdExt>,
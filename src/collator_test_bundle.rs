@@ -12,21 +12,31 @@
 */
 
 use crate::{
-    block::BlockStuff, engine_traits::{EngineAlloc, EngineOperations}, 
+    block::BlockStuff,
+    engine_traits::{
+        BlockAccess, BroadcastSupport, EngineAlloc, EngineOperations, RempQueueCollatorInterface,
+        RempSupport, StateAccess, ValidatorSupport
+    },
     shard_state::ShardStateStuff, types::top_block_descr::TopBlockDescrStuff,
     validator::{
-        accept_block::create_top_shard_block_description, BlockCandidate,
-        out_msg_queue::{OutMsgQueueInfoStuff, CachedStates},
-    }, config::CollatorConfig
+        accept_block::create_top_shard_block_description, BlockCandidate, CollatorSettings,
+        collator::Collator, out_msg_queue::{OutMsgQueueInfoStuff, CachedStates},
+        message_cache::{MessageCache, MessageCacheSnapshot, MessageCacheSnapshotMessage},
+        reliable_message_queue::RempQueueCollatorInterfaceImpl,
+        validate_query::ValidateQuery, validator_utils::{compute_validator_set_cc, is_remp_enabled},
+    }, config::{CollatorConfig, DuplicatePolicy}
 };
 #[cfg(feature = "telemetry")]
-use crate::engine_traits::EngineTelemetry;
+use crate::{
+    engine_traits::EngineTelemetry,
+    validator::telemetry::{CollationTimingTelemetry, CollatorValidatorTelemetry}
+};
 
 #[cfg(feature = "telemetry")]
-use adnl::telemetry::Metric;
+use adnl::telemetry::{Metric, MetricBuilder};
 use std::{
     collections::{HashMap, HashSet}, convert::{TryFrom, TryInto}, fs::{File, read, write}, 
-    ops::Deref, sync::{Arc, atomic::AtomicU64} 
+    ops::Deref, sync::{Arc, atomic::AtomicU64}, time::{Duration, Instant}
 };
 use storage::{
     StorageAlloc, TimeChecker,
@@ -37,14 +47,90 @@ use storage::{
 #[cfg(feature = "telemetry")]
 use storage::StorageTelemetry;
 use ever_block::{
-    BlockIdExt, Message, ShardIdent, Serializable, MerkleUpdate, Deserializable, 
-    ValidatorBaseInfo, BlockSignaturesPure, BlockSignatures, HashmapAugType, 
-    TopBlockDescrSet, OutMsgQueue,
+    AccountBlock, Block, BlockIdExt, ConfigParamEnum, Message, ShardIdent, Serializable, 
+    MerkleUpdate, Deserializable, ValidatorBaseInfo, BlockSignaturesPure, BlockSignatures, 
+    HashmapAugType, TopBlockDescrSet, OutMsgQueue, Transaction, ValidatorSet,
 };
 use ever_block::{ShardStateUnsplit, TopBlockDescr};
 use ever_block::{UInt256, fail, error, Error, Result, CellType, read_boc, read_single_root_boc};
+use ton_api::ton::ton_node::RempMessageStatus;
 use crate::engine_traits::RempDuplicateStatus;
 
+#[cfg(test)]
+#[path = "tests/test_collator_test_bundle.rs"]
+mod tests;
+
+// Version 2 adds REMP section, bundles without version are of version 1
+const COLLATOR_TEST_BUNDLE_VERSION: u32 = 2;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct RempSessionJson {
+    master_cc: u32,
+    start_time: u32,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct RempMessageJson {
+    id: String,
+    uid: String,
+    status: String,
+    origin: String,
+    // Message cache record in hex, other fields are not used to replay
+    record: String,
+}
+
+// Message cache contents at the collation start
+#[derive(serde::Deserialize, serde::Serialize)]
+struct RempSectionJson {
+    master_cc_seqno: u32,
+    master_cc_lwb: u32,
+    duplicate_policy: DuplicatePolicy,
+    sessions: Vec<RempSessionJson>,
+    messages: Vec<RempMessageJson>,
+}
+
+impl TryFrom<RempSectionJson> for MessageCacheSnapshot {
+    type Error = Error;
+    fn try_from(value: RempSectionJson) -> Result<Self> {
+        let mut messages = vec!();
+        for m in value.messages {
+            messages.push(MessageCacheSnapshotMessage {
+                message_id: m.id.parse()?,
+                message_uid: m.uid.parse()?,
+                status: m.status,
+                origin: m.origin,
+                record: hex::decode(&m.record)?,
+            });
+        }
+        Ok(MessageCacheSnapshot {
+            master_cc_range: value.master_cc_lwb..=value.master_cc_seqno,
+            duplicate_policy: value.duplicate_policy,
+            sessions: value.sessions.iter().map(|s| (s.master_cc, s.start_time)).collect(),
+            messages,
+        })
+    }
+}
+
+impl From<&MessageCacheSnapshot> for RempSectionJson {
+    fn from(value: &MessageCacheSnapshot) -> Self {
+        RempSectionJson {
+            master_cc_seqno: *value.master_cc_range.end(),
+            master_cc_lwb: *value.master_cc_range.start(),
+            duplicate_policy: value.duplicate_policy,
+            sessions: value.sessions.iter().map(
+                |(master_cc, start_time)| RempSessionJson { master_cc: *master_cc, start_time: *start_time }
+            ).collect(),
+            messages: value.messages.iter().map(|m| RempMessageJson {
+                id: m.message_id.to_hex_string(),
+                uid: m.message_uid.to_hex_string(),
+                status: m.status.clone(),
+                origin: m.origin.clone(),
+                record: hex::encode(&m.record),
+            }).collect(),
+        }
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 struct CollatorTestBundleIndexJson {
     id: String,
@@ -66,6 +152,10 @@ struct CollatorTestBundleIndexJson {
     contains_candidate: bool,
     #[serde(default)]
     notes: String,
+    #[serde(default)]
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    remp: Option<RempSectionJson>,
 }
 
 impl TryFrom<CollatorTestBundleIndexJson> for CollatorTestBundleIndex {
@@ -91,6 +181,9 @@ impl TryFrom<CollatorTestBundleIndexJson> for CollatorTestBundleIndex {
         for s in value.prev_blocks {
             prev_blocks.push(s.parse()?);
         }
+        if value.version > COLLATOR_TEST_BUNDLE_VERSION {
+            fail!("Bundle version {} is not supported, the latest is {}", value.version, COLLATOR_TEST_BUNDLE_VERSION)
+        }
         Ok(CollatorTestBundleIndex {
             id: value.id.parse()?,
             top_shard_blocks: shard_blocks,
@@ -107,6 +200,7 @@ impl TryFrom<CollatorTestBundleIndexJson> for CollatorTestBundleIndex {
             contains_ethalon: value.contains_ethalon,
             contains_candidate: value.contains_candidate,
             notes: value.notes,
+            remp: value.remp.map(MessageCacheSnapshot::try_from).transpose()?,
         })
     }
 }
@@ -133,6 +227,8 @@ impl From<&CollatorTestBundleIndex> for CollatorTestBundleIndexJson {
             contains_ethalon: value.contains_ethalon,
             contains_candidate: value.contains_candidate,
             notes: String::new(),
+            version: COLLATOR_TEST_BUNDLE_VERSION,
+            remp: value.remp.as_ref().map(RempSectionJson::from),
         }
     }
 }
@@ -153,6 +249,7 @@ struct CollatorTestBundleIndex {
     contains_ethalon: bool,
     contains_candidate: bool,
     notes: String,
+    remp: Option<MessageCacheSnapshot>,
 }
 
 impl CollatorTestBundleIndex {
@@ -180,6 +277,7 @@ pub fn create_block_handle_storage() -> BlockHandleStorage {
         Arc::new(BlockHandleDb::in_memory()),
         Arc::new(NodeStateDb::in_memory()),
         Arc::new(NodeStateDb::in_memory()),
+        None,
         #[cfg(feature = "telemetry")]
         Arc::new(StorageTelemetry::default()),
         Arc::new(StorageAlloc::default()),
@@ -201,6 +299,18 @@ pub fn create_engine_telemetry() -> Arc<EngineTelemetry> {
             validator_peers: Metric::without_totals("", 1),
             validator_sets: Metric::without_totals("", 1),
             old_state_cell_load_time: Metric::with_total_average("", 10),
+            pinned_states: Metric::without_totals("", 1),
+            sync_queue_depth: Metric::without_totals("", 1),
+            sync_downloads: Metric::without_totals("", 1),
+            sync_checked_proofs: MetricBuilder::with_metric_and_period(
+                Metric::with_total_amount("", 1), 1000000000
+            ),
+            broken_block_files: Metric::without_totals("", 1),
+            apply_throttle_blocks_per_sec: Metric::without_totals("", 1),
+            apply_throttle_state_tasks: Metric::without_totals("", 1),
+            applied_blocks: MetricBuilder::with_metric_and_period(
+                Metric::with_total_amount("", 1), 1000000000
+            ),
         }
     )
 }
@@ -221,6 +331,104 @@ pub fn create_engine_allocated() -> Arc<EngineAlloc> {
     )
 }
 
+// Config parameters which replace the ones of the last masterchain state in a dry run
+#[derive(Clone, Default)]
+pub struct ConfigPatch {
+    pub params: Vec<ConfigParamEnum>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DryRunReport {
+    pub block_id: Option<BlockIdExt>,
+    pub in_messages: usize,
+    pub out_messages: usize,
+    pub transactions: usize,
+    pub gas_used: u64,
+    pub candidate_size: usize,
+    pub collated_data_size: usize,
+    pub errors: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MissingArtifact {
+    BlockHandle(BlockIdExt),
+    BlockData(BlockIdExt),
+    BlockProof(BlockIdExt),
+    State(BlockIdExt),
+}
+
+impl std::fmt::Display for MissingArtifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::BlockHandle(id) => write!(f, "block handle {}", id),
+            Self::BlockData(id) => write!(f, "block data {}", id),
+            Self::BlockProof(id) => write!(f, "block proof {}", id),
+            Self::State(id) => write!(f, "state {}", id),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ReplayOutcome {
+    Validated { duration: Duration },
+    Failed { error: String, duration: Duration },
+    MissingArtifacts(Vec<MissingArtifact>),
+}
+
+// Collation queue of REMP messages over the message cache restored from the bundle.
+// The queue itself is not saved, so messages are selected by the rules of the queue
+struct BundleRempQueue {
+    cache: MessageCache,
+    messages: lockfree::queue::Queue<(UInt256, Arc<Message>)>,
+}
+
+impl BundleRempQueue {
+    fn new(snapshot: &MessageCacheSnapshot) -> Result<Self> {
+        let cache = MessageCache::with_metrics(
+            None,
+            snapshot.duplicate_policy,
+            None,
+            #[cfg(feature = "telemetry")]
+            Metric::without_totals("bundle message cache size", 0)
+        );
+        let restored = cache.restore_snapshot(snapshot)?;
+        log::info!("Bundle message cache: {} REMP messages restored", restored);
+        Ok(Self { cache, messages: lockfree::queue::Queue::new() })
+    }
+
+    fn into_interface(self) -> Arc<dyn RempQueueCollatorInterface> {
+        Arc::new(self)
+    }
+}
+
+#[async_trait::async_trait]
+impl RempQueueCollatorInterface for BundleRempQueue {
+    async fn init_queue(
+        &self,
+        _master_block_id: &BlockIdExt,
+        prev_blocks_ids: &[&BlockIdExt]
+    ) -> Result<()> {
+        let mut ordered = self.cache.collation_candidates()?.into_iter().map(|(id, message, _origin)| {
+            (RempQueueCollatorInterfaceImpl::compute_ordering_hash(&id, prev_blocks_ids), id, message)
+        }).collect::<Vec<_>>();
+        ordered.sort_by(|(hash1, _, _), (hash2, _, _)| hash1.cmp(hash2));
+        log::info!("Bundle message cache: {} REMP messages for collation", ordered.len());
+        for (_hash, id, message) in ordered {
+            self.messages.push((id, message));
+        }
+        Ok(())
+    }
+
+    async fn get_next_message_for_collation(&self) -> Result<Option<(Arc<Message>, UInt256)>> {
+        Ok(self.messages.pop().map(|(id, message)| (message, id)))
+    }
+
+    async fn update_message_collation_result(&self, id: &UInt256, result: RempMessageStatus) -> Result<()> {
+        log::info!("Bundle REMP message {:x}: {}", id, result);
+        Ok(())
+    }
+}
+
 pub struct CollatorTestBundle {
     index: CollatorTestBundleIndex,
     top_shard_blocks: Vec<Arc<TopBlockDescrStuff>>,
@@ -235,6 +443,12 @@ pub struct CollatorTestBundle {
     allocated: Arc<EngineAlloc>,
     collator_config: CollatorConfig,
     split_queues_cache: lockfree::map::Map<BlockIdExt, Option<(OutMsgQueue, OutMsgQueue, HashSet<UInt256>)>>,
+    #[cfg(feature = "telemetry")]
+    collator_telemetry: CollatorValidatorTelemetry,
+    #[cfg(feature = "telemetry")]
+    validator_telemetry: CollatorValidatorTelemetry,
+    #[cfg(feature = "telemetry")]
+    collation_timing_telemetry: CollationTimingTelemetry,
 }
 
 #[allow(dead_code)]
@@ -294,6 +508,7 @@ impl CollatorTestBundle {
             contains_ethalon: false,
             contains_candidate: false,
             notes: String::new(),
+            remp: None,
         };
 
         Ok(Self {
@@ -310,6 +525,12 @@ impl CollatorTestBundle {
             allocated,
             collator_config: CollatorConfig::default(),
             split_queues_cache: lockfree::map::Map::new(),
+            #[cfg(feature = "telemetry")]
+            collator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            validator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            collation_timing_telemetry: Default::default(),
         })
     }
 
@@ -517,6 +738,12 @@ impl CollatorTestBundle {
                 ..Default::default()
             },
             split_queues_cache: lockfree::map::Map::new(),
+            #[cfg(feature = "telemetry")]
+            collator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            validator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            collation_timing_telemetry: Default::default(),
         })
     }
 
@@ -614,6 +841,18 @@ impl CollatorTestBundle {
             0
         ).collect::<Vec<_>>();
 
+        //
+        // REMP messages
+        //
+        let remp = if is_remp_enabled(engine.clone(), mc_state.config_params()?) {
+            engine.remp_message_cache_snapshot().unwrap_or_else(|e| {
+                log::warn!("Cannot take REMP messages for the bundle: {}", e);
+                None
+            })
+        } else {
+            None
+        };
+
         // 
         // neighbors
         //
@@ -717,6 +956,7 @@ impl CollatorTestBundle {
             contains_ethalon: false,
             contains_candidate: false,
             notes: String::new(),
+            remp,
         };
 
         Ok(Self {
@@ -733,6 +973,12 @@ impl CollatorTestBundle {
             allocated: create_engine_allocated(),
             collator_config: CollatorConfig::default(),
             split_queues_cache: lockfree::map::Map::new(),
+            #[cfg(feature = "telemetry")]
+            collator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            validator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            collation_timing_telemetry: Default::default(),
         })
     }
 
@@ -876,6 +1122,7 @@ impl CollatorTestBundle {
             contains_ethalon: false,
             contains_candidate: true,
             notes: String::new(),
+            remp: None,
         };
 
         Ok(Self {
@@ -892,6 +1139,12 @@ impl CollatorTestBundle {
             allocated: create_engine_allocated(),
             collator_config: CollatorConfig::default(),
             split_queues_cache: lockfree::map::Map::new(),
+            #[cfg(feature = "telemetry")]
+            collator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            validator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            collation_timing_telemetry: Default::default(),
         })
     }
 
@@ -1085,6 +1338,7 @@ impl CollatorTestBundle {
             contains_ethalon: true,
             contains_candidate: false,
             notes: String::new(),
+            remp: None,
         };
 
         Ok(Self {
@@ -1101,6 +1355,12 @@ impl CollatorTestBundle {
             allocated: create_engine_allocated(),
             collator_config: CollatorConfig::default(),
             split_queues_cache: lockfree::map::Map::new(),
+            #[cfg(feature = "telemetry")]
+            collator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            validator_telemetry: Default::default(),
+            #[cfg(feature = "telemetry")]
+            collation_timing_telemetry: Default::default(),
         })
     }
 
@@ -1208,71 +1468,278 @@ impl CollatorTestBundle {
 
     pub fn candidate(&self) -> Option<&BlockCandidate> { self.candidate.as_ref() }
     pub fn set_notes(&mut self, notes: String) { self.index.notes = notes }
+    // Replaces REMP messages with the ones taken at the collation start
+    pub fn set_remp_context(&mut self, snapshot: MessageCacheSnapshot) { self.index.remp = Some(snapshot) }
+    pub fn set_candidate(&mut self, candidate: BlockCandidate) {
+        self.index.id = candidate.block_id.clone();
+        self.index.contains_candidate = true;
+        self.candidate = Some(candidate);
+    }
 }
 
-// Is used instead full node's engine for run tests
-#[async_trait::async_trait]
-impl EngineOperations for CollatorTestBundle {
-
-    fn now(&self) -> u32 {
-        (self.index.now_ms / 1000) as u32
+impl CollatorTestBundle {
+    // Collates next block of the shard over the last applied states with patched config.
+    // Collator works with the bundle instead of the engine, so the candidate is neither stored
+    // nor broadcast, and message queues and REMP are not touched: REMP messages are collated
+    // from a copy of the message cache.
+    pub async fn collate_dry_run(
+        engine: &Arc<dyn EngineOperations>,
+        shard: ShardIdent,
+        config_patch: ConfigPatch,
+    ) -> Result<DryRunReport> {
+        let mc_state = engine.load_last_applied_mc_state().await?;
+        let prev_blocks_ids = if shard.is_masterchain() {
+            vec![mc_state.block_id().clone()]
+        } else {
+            let descr = mc_state.shard_state_extra()?.shards().find_shard(&shard)?.ok_or_else(
+                || error!("Can't find description for shard {} in state {}", shard, mc_state.block_id())
+            )?;
+            vec![descr.block_id().clone()]
+        };
+        let mut bundle = Self::build_for_collating_block(prev_blocks_ids, engine).await?;
+        bundle.patch_config(&config_patch)?;
+        Arc::new(bundle).dry_run().await
     }
 
-    fn now_ms(&self) -> u64 {
-        self.index.now_ms
+    pub fn patch_config(&mut self, config_patch: &ConfigPatch) -> Result<()> {
+        let id = self.index.last_mc_state.clone();
+        let mc_state = self.states.get(&id)
+            .ok_or_else(|| error!("bundle doesn't contain state for block {}", id))?;
+        let mut extra = mc_state.shard_state_extra()?.clone();
+        for param in config_patch.params.iter() {
+            extra.config.set_config(param.clone())?;
+        }
+        let mut state = mc_state.state()?.clone();
+        state.write_custom(Some(&extra))?;
+        let mc_state = ShardStateStuff::from_state(
+            id.clone(),
+            state,
+            #[cfg(feature = "telemetry")]
+            &self.telemetry,
+            &self.allocated
+        )?;
+        self.states.insert(id, mc_state);
+        Ok(())
     }
 
-    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
-        let handle = self.block_handle_storage.create_handle(
-            id.clone(), 
-            BlockMeta::default(), 
-            None
+    // Collation failure is reported in the errors
+    pub async fn dry_run(self: Arc<Self>) -> Result<DryRunReport> {
+        let shard = self.block_id().shard().clone();
+        let prev_blocks_ids = self.prev_blocks_ids().clone();
+        let engine = self.clone() as Arc<dyn EngineOperations>;
+        let validator_set = self.compute_validator_set().await?;
+        let min_mc_seqno = if prev_blocks_ids[0].seq_no() == 0 {
+            0
+        } else {
+            engine.load_state(&prev_blocks_ids[0]).await?.state()?.min_ref_mc_seqno()
+        };
+
+        let remp_collator_interface = match &self.index.remp {
+            None => None,
+            Some(snapshot) => Some(BundleRempQueue::new(snapshot)?.into_interface())
+        };
+
+        let collator = Collator::new(
+            shard,
+            min_mc_seqno,
+            prev_blocks_ids,
+            validator_set,
+            self.created_by().clone(),
+            engine,
+            self.rand_seed().cloned(),
+            remp_collator_interface,
+            CollatorSettings::default(),
         )?;
-        if let Some(handle) = handle {
-            if self.blocks.contains_key(id) && (id != &self.index.id) {
-                handle.set_data();
-                handle.set_state();
-                handle.set_block_applied();
+        let mut report = DryRunReport::default();
+        let candidate = match collator.collate().await {
+            Ok((candidate, _state)) => candidate,
+            Err(e) => {
+                report.errors.push(e.to_string());
+                return Ok(report)
             }
-            Ok(Some(handle))
+        };
+        report.candidate_size = candidate.data.len();
+        report.collated_data_size = candidate.collated_data.len();
+        let extra = Block::construct_from_bytes(&candidate.data)?.read_extra()?;
+        extra.read_in_msg_descr()?.iterate_objects(|_| {
+            report.in_messages += 1;
+            Ok(true)
+        })?;
+        extra.read_out_msg_descr()?.iterate_objects(|_| {
+            report.out_messages += 1;
+            Ok(true)
+        })?;
+        extra.read_account_blocks()?.iterate_objects(|account_block: AccountBlock| {
+            account_block.transactions().iterate_slices(|_, transaction_slice| {
+                let transaction = Transaction::construct_from_cell(transaction_slice.reference(0)?)?;
+                report.transactions += 1;
+                report.gas_used += transaction.gas_used().unwrap_or(0);
+                Ok(true)
+            })?;
+            Ok(true)
+        })?;
+        report.block_id = Some(candidate.block_id);
+        Ok(report)
+    }
+
+    async fn compute_validator_set(&self) -> Result<ValidatorSet> {
+        let shard = self.block_id().shard();
+        let mc_state = self.load_last_applied_mc_state().await?;
+        let mc_state_extra = mc_state.shard_state_extra()?;
+        let cc_seqno = if shard.is_masterchain() {
+            mc_state_extra.validator_info.catchain_seqno
         } else {
-            Ok(None)
+            mc_state_extra.shards.calc_shard_cc_seqno(shard)?
+        };
+        let mut cc_seqno_with_delta = 0;
+        let nodes = compute_validator_set_cc(
+            &mc_state,
+            shard,
+            self.block_id().seq_no(),
+            cc_seqno,
+            &mut cc_seqno_with_delta
+        )?;
+        ValidatorSet::with_cc_seqno(0, 0, 0, cc_seqno_with_delta, nodes)
+    }
+
+    // Validates stored block over its inputs taken from the engine. Inputs are saved 
+    // as a bundle to share the case if path is given.
+    pub async fn replay_block_validation(
+        engine: &Arc<dyn EngineOperations>,
+        block_id: &BlockIdExt,
+        bundle_path: Option<&str>,
+    ) -> Result<ReplayOutcome> {
+        let missing = Self::find_missing_artifacts(engine, block_id).await?;
+        if !missing.is_empty() {
+            return Ok(ReplayOutcome::MissingArtifacts(missing))
         }
+        let bundle = Self::build_with_ethalon(block_id, engine).await?;
+        if let Some(path) = bundle_path {
+            bundle.save(path)?;
+        }
+        Arc::new(bundle).replay_validation().await
     }
 
-    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
-        if *block_id != self.index.id {
-            if let Some(s) = self.states.get(block_id) {
-                return Ok(s.clone());
+    // Lists artifacts which are needed to build the bundle for the block but absent
+    pub async fn find_missing_artifacts(
+        engine: &Arc<dyn EngineOperations>,
+        block_id: &BlockIdExt,
+    ) -> Result<Vec<MissingArtifact>> {
+        let mut missing = Vec::new();
+        let handle = match engine.load_block_handle(block_id)? {
+            Some(handle) => handle,
+            None => return Ok(vec![MissingArtifact::BlockHandle(block_id.clone())])
+        };
+        if !handle.has_proof_or_link(&mut false) && block_id.seq_no() != 0 {
+            missing.push(MissingArtifact::BlockProof(block_id.clone()));
+        }
+        if !handle.has_data() {
+            // Other inputs are known from the block only
+            missing.push(MissingArtifact::BlockData(block_id.clone()));
+            return Ok(missing)
+        }
+        let block = engine.load_block(&handle).await?;
+        let check_state = |id: &BlockIdExt, missing: &mut Vec<MissingArtifact>| -> Result<bool> {
+            match engine.load_block_handle(id)? {
+                Some(handle) if handle.has_state() => return Ok(true),
+                Some(_) => missing.push(MissingArtifact::State(id.clone())),
+                None => missing.push(MissingArtifact::BlockHandle(id.clone()))
+            }
+            Ok(false)
+        };
+        let (prev1, prev2) = block.construct_prev_id()?;
+        check_state(&prev1, &mut missing)?;
+        if let Some(prev2) = prev2 {
+            if check_state(&prev2, &mut missing)? {
+                let handle = engine.load_block_handle(&prev2)?
+                    .ok_or_else(|| error!("Cannot load handle for prev2 block {}", prev2))?;
+                if !handle.has_data() {
+                    missing.push(MissingArtifact::BlockData(prev2));
+                }
             }
         }
-        fail!("bundle doesn't contain state for block {}", block_id)
+        if !block_id.shard().is_masterchain() {
+            check_state(block_id, &mut missing)?;
+        }
+        let last_mc_id = match block.block()?.read_info()?.read_master_ref()? {
+            Some(master_ref) => BlockIdExt::from_ext_blk(master_ref.master),
+            None => prev1
+        };
+        if !check_state(&last_mc_id, &mut missing)? {
+            return Ok(missing)
+        }
+        let mc_state = engine.load_state(&last_mc_id).await?;
+        if let Ok(shards) = block.shards() {
+            shards.iterate_shards(|shard_id, descr| {
+                if descr.seq_no != 0 {
+                    let id = BlockIdExt::with_params(shard_id, descr.seq_no, descr.root_hash, descr.file_hash);
+                    match engine.load_block_handle(&id)? {
+                        Some(handle) if handle.has_data() => (),
+                        Some(_) => missing.push(MissingArtifact::BlockData(id)),
+                        None => missing.push(MissingArtifact::BlockHandle(id))
+                    }
+                }
+                Ok(true)
+            })?;
+        }
+        let shards = match block.shard_hashes() {
+            Ok(shards) => shards,
+            Err(_) => mc_state.shard_hashes()?
+        };
+        for neighbor in shards.neighbours_for(block_id.shard())? {
+            check_state(neighbor.block_id(), &mut missing)?;
+        }
+        Ok(missing)
     }
 
-    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
-        if *handle.id() != self.index.id {
-            if let Some(s) = self.blocks.get(handle.id()) {
-                return Ok(s.clone());
+    // Runs the validator over the candidate or ethalon block of the bundle
+    pub async fn replay_validation(self: Arc<Self>) -> Result<ReplayOutcome> {
+        let candidate = match self.candidate() {
+            Some(candidate) => candidate.clone(),
+            None => {
+                let block = self.ethalon_block()?.ok_or_else(
+                    || error!("bundle {} contains neither candidate nor block", self.block_id())
+                )?;
+                BlockCandidate {
+                    block_id: block.id().clone(),
+                    data: block.data().to_vec(),
+                    created_by: self.created_by().clone(),
+                    ..Default::default()
+                }
             }
-        }
-        fail!("bundle doesn't contain block {}", handle.id())
+        };
+        let validator_set = self.compute_validator_set().await?;
+        let query = ValidateQuery::new(
+            self.block_id().shard().clone(),
+            self.min_ref_mc_seqno(),
+            self.prev_blocks_ids().clone(),
+            candidate,
+            validator_set,
+            self.clone(),
+            self.index.fake,
+            true,
+            None,
+        );
+        let started = Instant::now();
+        let result = query.try_validate().await;
+        let duration = started.elapsed();
+        Ok(match result {
+            Ok(()) => ReplayOutcome::Validated { duration },
+            Err(error) => ReplayOutcome::Failed { error: error.to_string(), duration }
+        })
     }
+}
 
-    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
-        if let Some(s) = self.states.get(&self.index.last_mc_state) {
-            Ok(s.clone())
-        } else {
-            fail!("bundle doesn't contain state for block {}", &self.index.last_mc_state)
-        }
+// Is used instead full node's engine for run tests
+#[async_trait::async_trait]
+impl EngineOperations for CollatorTestBundle {
+
+    fn now(&self) -> u32 {
+        (self.index.now_ms / 1000) as u32
     }
 
-    async fn wait_state(
-        self: Arc<Self>,
-        id: &BlockIdExt,
-        _timeout_ms: Option<u64>,
-        _allow_block_downloading: bool
-    ) -> Result<Arc<ShardStateStuff>> {
-        self.load_state(id).await
+    fn now_ms(&self) -> u64 {
+        self.index.now_ms
     }
 
     async fn find_mc_block_by_seq_no(&self, seq_no: u32) -> Result<Arc<BlockHandle>> {
@@ -1333,14 +1800,25 @@ impl EngineOperations for CollatorTestBundle {
         &self.allocated
     }
 
-    async fn check_remp_duplicate(&self, _message_id: &UInt256) -> Result<RempDuplicateStatus> {
-        Ok(RempDuplicateStatus::Fresh(UInt256::default()))
-    }
-
     fn collator_config(&self) -> &CollatorConfig {
         &self.collator_config
     }
 
+    #[cfg(feature = "telemetry")]
+    fn collator_telemetry(&self) -> &CollatorValidatorTelemetry {
+        &self.collator_telemetry
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn validator_telemetry(&self) -> &CollatorValidatorTelemetry {
+        &self.validator_telemetry
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn collation_timing_telemetry(&self) -> &CollationTimingTelemetry {
+        &self.collation_timing_telemetry
+    }
+
     fn set_split_queues_calculating(&self, _before_split_block: &BlockIdExt) -> bool {
         true
     }
@@ -1370,3 +1848,76 @@ impl EngineOperations for CollatorTestBundle {
         None
     }
 }
+
+#[async_trait::async_trait]
+impl BlockAccess for CollatorTestBundle {
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        let handle = self.block_handle_storage.create_handle(
+            id.clone(), 
+            BlockMeta::default(), 
+            None
+        )?;
+        if let Some(handle) = handle {
+            if self.blocks.contains_key(id) && (id != &self.index.id) {
+                handle.set_data();
+                handle.set_state();
+                handle.set_block_applied();
+            }
+            Ok(Some(handle))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
+        if *handle.id() != self.index.id {
+            if let Some(s) = self.blocks.get(handle.id()) {
+                return Ok(s.clone());
+            }
+        }
+        fail!("bundle doesn't contain block {}", handle.id())
+    }
+}
+
+#[async_trait::async_trait]
+impl StateAccess for CollatorTestBundle {
+    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        if *block_id != self.index.id {
+            if let Some(s) = self.states.get(block_id) {
+                return Ok(s.clone());
+            }
+        }
+        fail!("bundle doesn't contain state for block {}", block_id)
+    }
+
+    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+        if let Some(s) = self.states.get(&self.index.last_mc_state) {
+            Ok(s.clone())
+        } else {
+            fail!("bundle doesn't contain state for block {}", &self.index.last_mc_state)
+        }
+    }
+
+    async fn wait_state(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        _timeout_ms: Option<u64>,
+        _allow_block_downloading: bool
+    ) -> Result<Arc<ShardStateStuff>> {
+        self.load_state(id).await
+    }
+}
+
+#[async_trait::async_trait]
+impl RempSupport for CollatorTestBundle {
+    async fn check_remp_duplicate(&self, _message_id: &UInt256) -> Result<RempDuplicateStatus> {
+        Ok(RempDuplicateStatus::Fresh(UInt256::default()))
+    }
+
+    async fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>> {
+        Ok(vec![true; ids.len()])
+    }
+}
+
+impl BroadcastSupport for CollatorTestBundle {}
+impl ValidatorSupport for CollatorTestBundle {}
@@ -11,13 +11,25 @@
 * limitations under the License.
 */
 
-use crate::network::node_network::NodeNetwork;
+use crate::{
+    internal_db::{consistency::RepairMode, persistent_state_reader::DEFAULT_PERSISTENT_STATE_CHUNK_SIZE},
+    keystore::{load_key_ring, read_passphrase, save_key_ring, KeystoreConfig, SecretBytes},
+    network::{
+        compression::DEFAULT_BLOCK_COMPRESSION_LEVEL, control_limits::ControlLimitsConfig,
+        control_permissions::ControlPermissionsConfig,
+        node_network::NodeNetwork
+    },
+    sync::{DEFAULT_SYNC_DOWNLOAD_CONCURRENCY, DEFAULT_SYNC_MAX_DOWNLOADED_ARCHIVES}
+};
 use adnl::{
     client::AdnlClientConfigJson,
     common::{add_unbound_object_to_map_with_update, Wait},
     node::{AdnlNodeConfig, AdnlNodeConfigJson}, server::{AdnlServerConfig, AdnlServerConfigJson}
 };
-use storage::shardstate_db_async::CellsDbConfig;
+use storage::{
+    fsync::FsyncConfig, instrumented_channel::{ChannelLimits, OverflowPolicy}, 
+    shardstate_db_async::CellsDbConfig
+};
 use std::{
     collections::{HashMap, HashSet}, convert::TryInto, fs::{File, read_dir}, fmt::{Display, Formatter},
     io::BufReader, path::{Path, PathBuf}, sync::{Arc, atomic::{self, AtomicI32}}, 
@@ -89,6 +101,10 @@ pub struct CollatorConfig {
     pub external_messages_timeout_percentage_points: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub external_messages_maximum_queue_length: Option<u32>, // None - unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_messages_maximum_queue_bytes: Option<u64>, // None - unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_messages_maximum_account_messages: Option<u32>, // None - unlimited
 }
 impl Default for CollatorConfig {
     fn default() -> Self {
@@ -104,6 +120,8 @@ impl Default for CollatorConfig {
             empty_collation_sleep_ms: 100,
             external_messages_timeout_percentage_points: 100, // 0.1 = 10% = 100ms
             external_messages_maximum_queue_length: Some(25600),
+            external_messages_maximum_queue_bytes: None,
+            external_messages_maximum_account_messages: None,
         }
     }
 }
@@ -127,6 +145,37 @@ impl ShardStatesCacheMode {
     }
 }
 
+// Custom rule of persistent states saving. If it is not set, a key block's state is saved 
+// when the block starts a new persistent states period. Only key blocks are considered 
+// by any rule, because other nodes boot from key blocks' persistent states.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Copy, PartialEq)]
+pub enum PersistentStatePolicy {
+    EveryKeyBlock,
+    TimeInterval(u32), // Min interval in seconds since the last saved state
+    SeqnoGap(u32), // Min masterchain seqno gap since the last saved state
+    Composite { interval_sec: u32, seqno_gap: u32 }, // Whichever condition is met first
+}
+
+// Masterchain key block to start cold boot from instead of init block from global config.
+// Hashes have the same format as in global config.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct TrustedKeyBlock {
+    seqno: u32,
+    root_hash: String,
+    file_hash: String,
+}
+
+impl TrustedKeyBlock {
+    pub fn block_id(&self) -> Result<BlockIdExt> {
+        Ok(BlockIdExt {
+            shard_id: ShardIdent::masterchain(),
+            seq_no: self.seqno,
+            root_hash: self.root_hash.parse()?,
+            file_hash: self.file_hash.parse()?,
+        })
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct TonNodeConfig {
     log_config_name: Option<String>,
@@ -150,6 +199,10 @@ pub struct TonNodeConfig {
     #[serde(skip_serializing)]
     control_server_port: Option<u16>,
     control_server: Option<AdnlServerConfigJson>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    control_permissions: Option<ControlPermissionsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    control_limits: Option<ControlLimitsConfig>,
     kafka_consumer_config: Option<KafkaConsumerConfig>,
     external_db_config: Option<ExternalDbConfig>,
     default_rldp_roundtrip_ms: Option<u32>,
@@ -159,6 +212,10 @@ pub struct TonNodeConfig {
     connectivity_check_config: ConnectivityCheckBroadcastConfig,
     gc: Option<GC>,
     validator_key_ring: Option<HashMap<String, KeyOptionJson>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validator_keystore: Option<KeystoreConfig>,
+    #[serde(skip)]
+    keystore_passphrase: Option<Arc<SecretBytes>>,
     #[serde(skip)]
     configs_dir: String,
     #[serde(skip)]
@@ -180,6 +237,42 @@ pub struct TonNodeConfig {
     sync_by_archives: bool,
     #[serde(default)]
     smft_disabled: bool,
+    #[serde(default)]
+    validator_session_checkpoints: bool,
+    #[serde(default)]
+    strict_config_check: bool,
+    #[serde(default)]
+    state_rollback_disabled: bool,
+    check_db_consistency: Option<RepairMode>,
+    persistent_state_chunk_size: Option<usize>,
+    persistent_state_policy: Option<PersistentStatePolicy>,
+    persistent_state_delta_chain_limit: Option<u32>,
+    sync_download_concurrency: Option<usize>,
+    sync_max_downloaded_archives: Option<usize>,
+    sync_proof_check_threads: Option<usize>,
+    #[serde(default)]
+    conservative_signature_check: bool,
+    block_compression_level: Option<i32>,
+    catch_up_throttle: Option<CatchUpThrottleConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trusted_key_block: Option<TrustedKeyBlock>,
+    proof_chain_max_length: Option<usize>,
+    account_state_proof_max_size: Option<usize>,
+    top_block_mc_ref_horizon: Option<u32>,
+    queue_lag_warning_threshold: Option<u32>,
+    node_status_log_interval_sec: Option<u32>,
+    states_memory_ceiling_mb: Option<u64>,
+    max_unsaved_states_per_shard: Option<u32>,
+    states_prefetch_distance: Option<u32>,
+    #[serde(default)]
+    storage_fsync: FsyncConfig,
+    block_broadcast_dedup: Option<BroadcastDedupConfig>,
+    neighbours_rebalance: Option<NeighboursRebalanceConfig>,
+    block_broadcast_fanout: Option<BroadcastFanoutConfig>,
+    time_service: Option<TimeServiceConfig>,
+    #[serde(default)]
+    account_transactions_index: bool,
+    key_rotation: Option<KeyRotationConfig>,
 }
 
 pub struct TonNodeGlobalConfig(TonNodeGlobalConfigJson);
@@ -229,11 +322,44 @@ pub struct KafkaConsumerConfig {
 pub struct GC {
     enable_for_archives: bool,
     archives_life_time_hours: Option<u32>, // Hours
+    // Archives are collected by disk usage instead of life time if set
+    archives_watermarks: Option<ArchivesWatermarksConfig>,
     enable_for_shard_state_persistent: bool,
     #[serde(default)]
     cells_gc_config: CellsGcConfig,
 }
 
+// Disk usage of archives in bytes or in percents of the file system size
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiskWatermark {
+    Bytes(u64),
+    Percent(u8),
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ArchivesWatermarksConfig {
+    pub high_watermark: DiskWatermark,
+    pub low_watermark: DiskWatermark,
+    // Packages with blocks generated later than this are never removed
+    pub min_retention_hours: u32,
+    // Packages containing key blocks are removed after all other ones
+    pub preserve_key_block_packages: bool,
+    pub check_interval_sec: u32,
+}
+
+impl Default for ArchivesWatermarksConfig {
+    fn default() -> Self {
+        Self {
+            high_watermark: DiskWatermark::Percent(85),
+            low_watermark: DiskWatermark::Percent(75),
+            min_retention_hours: 24,
+            preserve_key_block_packages: false,
+            check_interval_sec: 60,
+        }
+    }
+}
+
 #[derive(Debug, Default, serde::Deserialize, serde::Serialize, Clone)]
 pub struct TopicMask {
     pub mask: String,
@@ -269,7 +395,66 @@ pub struct ExternalDbConfig {
     pub chain_range_producer: KafkaProducerConfig,
     pub remp_statuses_producer: KafkaProducerConfig,
     pub shard_hashes_producer: KafkaProducerConfig,
+    pub remp_history_producer: KafkaProducerConfig,
     pub bad_blocks_storage: String,
+    pub file_sink: Option<FileSinkConfig>,
+    pub remp_history: RempHistoryConfig,
+}
+
+/// Export of REMP message status transitions from message cache
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct RempHistoryConfig {
+    // Only final statuses are exported by default
+    pub intermediate_statuses: bool,
+    // Transitions above this count are dropped, REMP never waits for the sink
+    pub buffer_size: usize,
+}
+
+impl Default for RempHistoryConfig {
+    fn default() -> Self {
+        Self {
+            intermediate_statuses: false,
+            buffer_size: 10_000,
+        }
+    }
+}
+
+/// Writes external db documents into local files instead of kafka
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct FileSinkConfig {
+    pub directory: String,
+    pub max_file_size: u64,
+    pub rotation_interval_sec: u64,
+    pub retry_timeout_ms: u32,
+    // Kinds of documents to write, all by default
+    pub documents: Option<Vec<String>>,
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> Self {
+        Self {
+            directory: "external_db".to_string(),
+            max_file_size: 256 * 1024 * 1024,
+            rotation_interval_sec: 3600,
+            retry_timeout_ms: 1000,
+            documents: None,
+        }
+    }
+}
+
+/// Resolution of REMP messages with equal uids
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    LowestId, // Message with the lowest id wins
+    FirstAccepted, // Message which is accepted first wins
+    PreferLocal, // Lowest id wins among messages with the same destination
+}
+impl Default for DuplicatePolicy {
+    fn default() -> Self {
+        DuplicatePolicy::LowestId
+    }
 }
 
 #[derive(Debug, Default, serde::Deserialize, serde::Serialize, Clone)]
@@ -279,6 +464,17 @@ pub struct RempConfig {
     service_enabled: Option<bool>,
     message_queue_max_len: Option<usize>,
     max_incoming_broadcast_delay_millis: Option<u32>,
+    max_cached_messages: Option<usize>,
+    max_ext_message_age_secs: Option<u32>,
+    max_ext_message_future_skew_secs: Option<u32>,
+    duplicate_policy: Option<DuplicatePolicy>,
+    persistent_message_cache: Option<bool>,
+    ext_messages_rate_limit: Option<ExtMessagesRateLimitConfig>,
+    ext_messages_prevalidation: Option<ExtMessagesPrevalidationConfig>,
+    ext_message_limits: Option<HashMap<i32, ExtMessageLimitsConfig>>,
+    traced_messages: Option<Vec<String>>,
+    incoming_queue: Option<ChannelLimits>,
+    response_queue: Option<ChannelLimits>,
 }
 
 impl RempConfig {
@@ -290,9 +486,45 @@ impl RempConfig {
             service_enabled: None,
             message_queue_max_len: None,
             max_incoming_broadcast_delay_millis: None,
+            max_cached_messages: None,
+            max_ext_message_age_secs: None,
+            max_ext_message_future_skew_secs: None,
+            duplicate_policy: None,
+            persistent_message_cache: None,
+            ext_messages_rate_limit: None,
+            ext_messages_prevalidation: None,
+            ext_message_limits: None,
+            traced_messages: None,
+            incoming_queue: None,
+            response_queue: None,
         }
     }
 
+    #[cfg(test)]
+    pub fn set_traced_messages(&mut self, traced_messages: Vec<String>) {
+        self.traced_messages = Some(traced_messages);
+    }
+
+    #[cfg(test)]
+    pub fn set_ext_messages_rate_limit(&mut self, rate_limit: Option<ExtMessagesRateLimitConfig>) {
+        self.ext_messages_rate_limit = rate_limit;
+    }
+
+    #[cfg(test)]
+    pub fn set_persistent_message_cache(&mut self, enabled: bool) {
+        self.persistent_message_cache = Some(enabled);
+    }
+
+    #[cfg(test)]
+    pub fn set_duplicate_policy(&mut self, duplicate_policy: DuplicatePolicy) {
+        self.duplicate_policy = Some(duplicate_policy);
+    }
+
+    #[cfg(test)]
+    pub fn set_max_cached_messages(&mut self, max_cached_messages: Option<usize>) {
+        self.max_cached_messages = max_cached_messages;
+    }
+
     pub fn is_client_enabled(&self) -> bool {
         self.client_enabled.unwrap_or(true)
     }
@@ -307,6 +539,59 @@ impl RempConfig {
 
     pub fn get_max_incoming_broadcast_delay_millis(&self) -> u32 { self.max_incoming_broadcast_delay_millis.unwrap_or(1000) }
 
+    pub fn get_max_cached_messages(&self) -> Option<usize> {
+        self.max_cached_messages
+    }
+
+    pub fn get_duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy.unwrap_or_default()
+    }
+
+    pub fn is_persistent_message_cache(&self) -> bool {
+        self.persistent_message_cache.unwrap_or(false)
+    }
+
+    /// Returns external messages rate limits, None if messages are not limited
+    pub fn get_ext_messages_rate_limit(&self) -> Option<&ExtMessagesRateLimitConfig> {
+        self.ext_messages_rate_limit.as_ref()
+    }
+
+    /// Returns workers count and lane capacities of external messages prevalidation
+    pub fn get_ext_messages_prevalidation(&self) -> ExtMessagesPrevalidationConfig {
+        self.ext_messages_prevalidation.clone().unwrap_or_default()
+    }
+
+    /// Returns acceptance limits of external messages by destination workchain
+    pub fn get_ext_message_limits(&self) -> HashMap<i32, ExtMessageLimitsConfig> {
+        self.ext_message_limits.clone().unwrap_or_default()
+    }
+
+    /// Returns hex ids of messages which are traced from the start
+    pub fn get_traced_messages(&self) -> &[String] {
+        self.traced_messages.as_deref().unwrap_or_default()
+    }
+
+    /// Returns limits of the queue of messages from fullnode. 
+    /// New messages are refused when it is full, so clients may resend them
+    pub fn get_incoming_queue_limits(&self) -> ChannelLimits {
+        self.incoming_queue.unwrap_or(ChannelLimits::new(10000, 100000, OverflowPolicy::DropNew))
+    }
+
+    /// Returns limits of the queue of message statuses sent to fullnode.
+    /// The oldest statuses are dropped when it is full, since newer ones supersede them
+    pub fn get_response_queue_limits(&self) -> ChannelLimits {
+        self.response_queue.unwrap_or(ChannelLimits::new(10000, 100000, OverflowPolicy::DropOldest))
+    }
+
+    /// Returns (max age, max future skew) for external messages creation time check,
+    /// None if the check is disabled
+    pub fn get_ext_message_time_window(&self) -> Option<(u32, u32)> {
+        match (self.max_ext_message_age_secs, self.max_ext_message_future_skew_secs) {
+            (None, None) => None,
+            (age, skew) => Some((age.unwrap_or(u32::MAX), skew.unwrap_or(u32::MAX)))
+        }
+    }
+
     pub fn get_catchain_options(&self) -> Option<catchain::Options> {
         if self.is_service_enabled() {
             let mut opts = catchain::Options::default();
@@ -325,6 +610,192 @@ impl RempConfig {
 
 }
 
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct ExtMessagesRateLimitConfig {
+    pub source_messages_per_sec: u32,
+    pub source_burst: u32,
+    // Zero value disables global limit
+    pub global_messages_per_sec: u32,
+    pub global_burst: u32,
+    pub source_idle_timeout_sec: u32,
+}
+
+impl Default for ExtMessagesRateLimitConfig {
+    fn default() -> Self {
+        ExtMessagesRateLimitConfig {
+            source_messages_per_sec: 50,
+            source_burst: 100,
+            global_messages_per_sec: 0,
+            global_burst: 0,
+            source_idle_timeout_sec: 300,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct ExtMessagesPrevalidationConfig {
+    pub workers: u32,
+    // REMP messages from validators
+    pub high_lane_capacity: u32,
+    // Other messages, the oldest one is dropped when the lane is full
+    pub low_lane_capacity: u32,
+}
+
+impl Default for ExtMessagesPrevalidationConfig {
+    fn default() -> Self {
+        ExtMessagesPrevalidationConfig {
+            workers: 4,
+            high_lane_capacity: 10_000,
+            low_lane_capacity: 10_000,
+        }
+    }
+}
+
+// Limits of external messages to a workchain, checked before a message gets into any cache.
+// Unspecified size and depth are taken from the network config (param 43) or defaults.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ExtMessageLimitsConfig {
+    pub max_size: Option<u32>,
+    pub max_body_depth: Option<u16>,
+    // Minimum import fee declared in the message header, in nanotokens
+    pub min_import_fee: Option<u64>,
+}
+
+// Limits of block applying, zero value disables a limit
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct CatchUpThrottleConfig {
+    // Used while node is in the current validator set
+    pub validator_blocks_per_sec: u32,
+    pub validator_state_tasks: u32,
+    // Used otherwise
+    pub blocks_per_sec: u32,
+    pub state_tasks: u32,
+}
+
+impl Default for CatchUpThrottleConfig {
+    fn default() -> Self {
+        CatchUpThrottleConfig {
+            validator_blocks_per_sec: 50,
+            validator_state_tasks: 2,
+            blocks_per_sec: 0,
+            state_tasks: 0,
+        }
+    }
+}
+
+// Window of recently received block broadcasts, copies of which are dropped
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct BroadcastDedupConfig {
+    // Max count of broadcasts in the window, the oldest ones are evicted
+    pub window: usize,
+    pub ttl_sec: u32,
+}
+
+impl Default for BroadcastDedupConfig {
+    fn default() -> Self {
+        BroadcastDedupConfig {
+            window: 4096,
+            ttl_sec: 60,
+        }
+    }
+}
+
+// Periodic rotation of the worst overlay neighbour by quality score
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct NeighboursRebalanceConfig {
+    // Zero disables rotation
+    pub interval_sec: u32,
+    // Smaller set of neighbours is not rotated
+    pub min_neighbours: u32,
+    // Neighbours with fewer observations are not rotated out
+    pub min_samples: u32,
+}
+
+impl Default for NeighboursRebalanceConfig {
+    fn default() -> Self {
+        NeighboursRebalanceConfig {
+            interval_sec: 300,
+            min_neighbours: 8,
+            min_samples: 20,
+        }
+    }
+}
+
+// Block broadcasts are sent directly to a limited count of neighbours not known to have the block
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct BroadcastFanoutConfig {
+    pub max_fanout: usize,
+    // Max count of blocks whose known neighbours are kept, the oldest ones are evicted
+    pub window: usize,
+    pub ttl_sec: u32,
+}
+
+impl Default for BroadcastFanoutConfig {
+    fn default() -> Self {
+        BroadcastFanoutConfig {
+            max_fanout: 8,
+            window: 4096,
+            ttl_sec: 300,
+        }
+    }
+}
+
+// Local clock offset is estimated by gen_utime of applied masterchain blocks and optionally by NTP
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct TimeServiceConfig {
+    pub interval_sec: u32,
+    // Count of recent observations kept for every source
+    pub window: usize,
+    pub min_observations: usize,
+    // Usual time between collation of a masterchain block and its applying
+    pub expected_block_delay_ms: u64,
+    pub warn_drift_ms: u64,
+    pub max_collation_drift_ms: u64,
+    pub ntp_server: Option<String>,
+    pub ntp_timeout_ms: u64,
+}
+
+impl Default for TimeServiceConfig {
+    fn default() -> Self {
+        TimeServiceConfig {
+            interval_sec: 60,
+            window: 32,
+            min_observations: 3,
+            expected_block_delay_ms: 3000,
+            warn_drift_ms: 1000,
+            max_collation_drift_ms: 10000,
+            ntp_server: None,
+            ntp_timeout_ms: 1000,
+        }
+    }
+}
+
+// Validator keys are replaced with new ones every elections
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct KeyRotationConfig {
+    pub interval_sec: u32,
+    // Keys are not rotated later than this time before elections end
+    pub margin_sec: u32,
+}
+
+impl Default for KeyRotationConfig {
+    fn default() -> Self {
+        KeyRotationConfig {
+            interval_sec: 60,
+            margin_sec: 600,
+        }
+    }
+}
+
 #[derive(Debug, Default, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(default)]
 pub struct CollatorTestBundlesConfig {
@@ -405,6 +876,8 @@ pub struct CollatorTestBundlesGeneralConfig {
 }
 
 const LOCAL_HOST: &str = "127.0.0.1";
+const DEFAULT_PROOF_CHAIN_MAX_LENGTH: usize = 16;
+const DEFAULT_ACCOUNT_STATE_PROOF_MAX_SIZE: usize = 1 << 20;
 
 impl TonNodeConfig {
 
@@ -429,6 +902,18 @@ impl TonNodeConfig {
         self.smft_disabled
     }
 
+    pub fn validator_session_checkpoints(&self) -> bool {
+        self.validator_session_checkpoints
+    }
+
+    pub fn strict_config_check(&self) -> bool {
+        self.strict_config_check
+    }
+
+    pub fn is_state_rollback_disabled(&self) -> bool {
+        self.state_rollback_disabled
+    }
+
     pub fn from_file(
         configs_dir: &str,
         json_file_name: &str,
@@ -487,12 +972,56 @@ impl TonNodeConfig {
         // }
 
         config_json.connectivity_check_config.check()?;
+        config_json.open_keystore(configs_dir)?;
 
         config_json.configs_dir = configs_dir.to_string();
         config_json.file_name = json_file_name.to_string();
         Ok(config_json)
     }
 
+    // Keys of the keystore are decrypted into validator key ring, they are encrypted back
+    // on each save. Plain keys of the config are moved into the keystore by the next save
+    fn open_keystore(&mut self, configs_dir: &str) -> Result<()> {
+        let Some(keystore) = &self.validator_keystore else {
+            return Ok(())
+        };
+        let passphrase = read_passphrase(&keystore.passphrase)?;
+        let key_ring = load_key_ring(configs_dir, keystore, &passphrase)?;
+        let plain_keys = self.validator_key_ring.as_ref().map_or(0, |key_ring| key_ring.len());
+        if plain_keys > 0 {
+            println!(
+                "{} validator keys are not encrypted in config, use `encryptkeystore` console command \
+                to move them into keystore {}",
+                plain_keys, keystore.file
+            );
+        }
+        if let Some(key_ring) = key_ring {
+            self.validator_key_ring.get_or_insert_with(HashMap::new).extend(key_ring);
+        }
+        self.keystore_passphrase = Some(Arc::new(passphrase));
+        Ok(())
+    }
+
+    // Reads config of running node again, nothing is generated
+    pub fn reread_file(configs_dir: &str, json_file_name: &str) -> Result<Self> {
+        let config_file_path = TonNodeConfig::build_path(configs_dir, json_file_name);
+        let config_file = File::open(&config_file_path)
+            .map_err(|err| error!("Can`t open {:?}: {}", config_file_path, err))?;
+        let mut config: TonNodeConfig = serde_json::from_reader(BufReader::new(config_file))?;
+        config.connectivity_check_config.check()?;
+        config.configs_dir = configs_dir.to_string();
+        config.file_name = json_file_name.to_string();
+        Ok(config)
+    }
+
+    pub fn configs_dir(&self) -> &str {
+        &self.configs_dir
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
     pub fn adnl_node(&self) -> Result<AdnlNodeConfig> {
         let adnl_node = self.adnl_node.as_ref().ok_or_else(|| error!("ADNL node is not configured!"))?;
 
@@ -510,6 +1039,14 @@ impl TonNodeConfig {
         }
     }
 
+    pub fn control_permissions(&self) -> Option<&ControlPermissionsConfig> {
+        self.control_permissions.as_ref()
+    }
+
+    pub fn control_limits(&self) -> Option<&ControlLimitsConfig> {
+        self.control_limits.as_ref()
+    }
+
     pub fn log_config_path(&self) -> Option<PathBuf> {
         if let Some(log_config_name) = &self.log_config_name {
             return Some(self.build_config_path(&log_config_name))
@@ -555,6 +1092,14 @@ impl TonNodeConfig {
         }
     }
 
+    // Watermarks are used only if GC of archives is enabled
+    pub fn gc_archives_watermarks(&self) -> Option<ArchivesWatermarksConfig> {
+        match &self.gc {
+            Some(gc) if gc.enable_for_archives => gc.archives_watermarks.clone(),
+            _ => None
+        }
+    }
+
     #[cfg(feature = "external_db")]
     pub fn kafka_consumer_config(&self) -> Option<KafkaConsumerConfig> {
         self.kafka_consumer_config.clone()
@@ -564,6 +1109,31 @@ impl TonNodeConfig {
         self.internal_db_path.as_ref().map(|path| path.as_str()).unwrap_or(Self::DEFAULT_DB_ROOT)
     }
 
+    // Checks that every validator key is in the key ring and decodes, returns count of keys
+    pub fn check_validator_keys(&self) -> Result<usize> {
+        let Some(validator_keys) = &self.validator_keys else {
+            return Ok(0)
+        };
+        let key_ring = self.validator_key_ring.as_ref();
+        let get_key = |key_id: &str| {
+            key_ring.and_then(|key_ring| key_ring.get(key_id))
+                .ok_or_else(|| error!("validator key {} is not found in key ring", key_id))
+        };
+        for key in validator_keys {
+            Ed25519KeyOption::from_private_key_json(get_key(&key.validator_key_id)?)
+                .map_err(|e| error!("bad validator key {}: {}", key.validator_key_id, e))?;
+            if let Some(key_id) = &key.validator_adnl_key_id {
+                Ed25519KeyOption::from_private_key_json(get_key(key_id)?)
+                    .map_err(|e| error!("bad validator ADNL key {}: {}", key_id, e))?;
+            }
+            if let Some(key_id) = &key.validator_bls_key {
+                BlsKeyOption::from_private_key_json(get_key(key_id)?)
+                    .map_err(|e| error!("bad validator BLS key {}: {}", key_id, e))?;
+            }
+        }
+        Ok(validator_keys.len())
+    }
+
     pub fn cells_gc_config(&self) -> CellsGcConfig {
         match &self.gc {
             Some(conf) => conf.cells_gc_config.clone(),
@@ -616,9 +1186,92 @@ impl TonNodeConfig {
     pub fn sync_by_archives(&self) -> bool {
         self.sync_by_archives
     }
+    pub fn check_db_consistency(&self) -> Option<RepairMode> {
+        self.check_db_consistency
+    }
+    pub fn persistent_state_chunk_size(&self) -> usize {
+        self.persistent_state_chunk_size.unwrap_or(DEFAULT_PERSISTENT_STATE_CHUNK_SIZE)
+    }
+    pub fn proof_chain_max_length(&self) -> usize {
+        self.proof_chain_max_length.unwrap_or(DEFAULT_PROOF_CHAIN_MAX_LENGTH)
+    }
+    pub fn account_state_proof_max_size(&self) -> usize {
+        self.account_state_proof_max_size.unwrap_or(DEFAULT_ACCOUNT_STATE_PROOF_MAX_SIZE)
+    }
+    pub fn top_block_mc_ref_horizon(&self) -> Option<u32> {
+        self.top_block_mc_ref_horizon
+    }
+    pub fn queue_lag_warning_threshold(&self) -> Option<u32> {
+        self.queue_lag_warning_threshold
+    }
+    pub fn node_status_log_interval_sec(&self) -> Option<u32> {
+        self.node_status_log_interval_sec
+    }
+    pub fn states_memory_ceiling_mb(&self) -> Option<u64> {
+        self.states_memory_ceiling_mb
+    }
+    pub fn max_unsaved_states_per_shard(&self) -> Option<u32> {
+        self.max_unsaved_states_per_shard
+    }
+    pub fn states_prefetch_distance(&self) -> Option<u32> {
+        self.states_prefetch_distance
+    }
+    pub fn persistent_state_policy(&self) -> Option<PersistentStatePolicy> {
+        self.persistent_state_policy
+    }
+    pub fn persistent_state_delta_chain_limit(&self) -> u32 {
+        self.persistent_state_delta_chain_limit.unwrap_or_default()
+    }
+    pub fn sync_download_concurrency(&self) -> usize {
+        self.sync_download_concurrency.unwrap_or(DEFAULT_SYNC_DOWNLOAD_CONCURRENCY)
+    }
+    pub fn sync_max_downloaded_archives(&self) -> usize {
+        self.sync_max_downloaded_archives.unwrap_or(DEFAULT_SYNC_MAX_DOWNLOADED_ARCHIVES)
+    }
+    pub fn sync_proof_check_threads(&self) -> usize {
+        self.sync_proof_check_threads.unwrap_or_else(num_cpus::get)
+    }
+    pub fn conservative_signature_check(&self) -> bool {
+        self.conservative_signature_check
+    }
+    pub fn block_compression_level(&self) -> i32 {
+        self.block_compression_level.unwrap_or(DEFAULT_BLOCK_COMPRESSION_LEVEL)
+    }
+    pub fn catch_up_throttle(&self) -> Option<&CatchUpThrottleConfig> {
+        self.catch_up_throttle.as_ref()
+    }
+    pub fn block_broadcast_dedup(&self) -> BroadcastDedupConfig {
+        self.block_broadcast_dedup.clone().unwrap_or_default()
+    }
+    pub fn neighbours_rebalance(&self) -> NeighboursRebalanceConfig {
+        self.neighbours_rebalance.clone().unwrap_or_default()
+    }
+    pub fn block_broadcast_fanout(&self) -> Option<&BroadcastFanoutConfig> {
+        self.block_broadcast_fanout.as_ref()
+    }
+    pub fn time_service(&self) -> Option<&TimeServiceConfig> {
+        self.time_service.as_ref()
+    }
+    pub fn account_transactions_index(&self) -> bool {
+        self.account_transactions_index
+    }
+    pub fn key_rotation(&self) -> Option<&KeyRotationConfig> {
+        self.key_rotation.as_ref()
+    }
+    pub fn trusted_key_block(&self) -> Result<Option<BlockIdExt>> {
+        match &self.trusted_key_block {
+            Some(block) => block.block_id()
+                .map(Some)
+                .map_err(|err| error!("trusted key block parse error: {}", err)),
+            None => Ok(None)
+        }
+    }
     pub fn cells_db_config(&self) -> &CellsDbConfig {
         &self.cells_db_config
     }
+    pub fn storage_fsync(&self) -> &FsyncConfig {
+        &self.storage_fsync
+    }
 
     #[cfg(test)]
     pub fn set_port(&mut self, port: u16) {
@@ -774,12 +1427,32 @@ impl TonNodeConfig {
         path.join(file_name)
     }
 
-    fn save_to_file(&self, file_name: &str) -> Result<()> {
+    fn save_to_file(&mut self, file_name: &str) -> Result<()> {
         let config_file_path = self.build_config_path(file_name);
-        std::fs::write(config_file_path, serde_json::to_string_pretty(&self)?)?;
+        let (Some(keystore), Some(passphrase)) = (&self.validator_keystore, &self.keystore_passphrase) else {
+            std::fs::write(config_file_path, serde_json::to_string_pretty(&self)?)?;
+            return Ok(())
+        };
+        // Keys are written into the keystore only, config is written without them
+        if let Some(key_ring) = &self.validator_key_ring {
+            save_key_ring(&self.configs_dir, keystore, passphrase, key_ring)?;
+        }
+        let key_ring = self.validator_key_ring.take();
+        let json = serde_json::to_string_pretty(&self);
+        self.validator_key_ring = key_ring;
+        std::fs::write(config_file_path, json?)?;
         Ok(())
     }
 
+    // Writes validator keys into the keystore and removes them from the config file
+    fn encrypt_keystore(&mut self, file_name: &str) -> Result<usize> {
+        if self.keystore_passphrase.is_none() {
+            fail!("Validator keystore is not configured")
+        }
+        self.save_to_file(file_name)?;
+        Ok(self.validator_key_ring.as_ref().map_or(0, |key_ring| key_ring.len()))
+    }
+
     fn generate_and_save_keys(&mut self, _key_type: i32) -> Result<([u8; 32], Arc<dyn KeyOption>)> {
         let (private, public) = Ed25519KeyOption::generate_with_json()?;
         let key_id = public.id().data();
@@ -901,9 +1574,11 @@ enum Task {
     AddValidatorKey([u8; 32], i32),
     AddValidatorAdnlKey([u8; 32], [u8; 32]),
     AddValidatorBlsKey([u8; 32], [u8; 32]),
+    RemoveValidatorKey([u8; 32], i32),
     GetKey([u8; 32]),
     GetBlsKey([u8; 32]),
     StoreStatesGcInterval(u32),
+    EncryptKeystore,
 }
 
 #[derive(Debug)]
@@ -911,6 +1586,7 @@ enum Answer {
     Generate(Result<[u8; 32]>),
     GetKey(Option<Arc<dyn KeyOption>>),
     Result(Result<()>),
+    Count(Result<usize>),
 }
 
 pub struct NodeConfigHandlerContext {
@@ -1008,6 +1684,48 @@ impl NodeConfigHandler {
         }
     }
 
+    // Removes validator key of the election with its ADNL and BLS keys, false if there is no such key
+    pub async fn remove_validator_key(&self, key_hash: &[u8; 32], election_id: ton::int) -> Result<bool> {
+        let (wait, mut queue_reader) = Wait::new();
+        let pushed_task = Arc::new((wait.clone(), Task::RemoveValidatorKey(key_hash.clone(), election_id)));
+        wait.request();
+        if let Err(e) = self.sender.send(pushed_task) {
+            fail!("Error remove_validator_key: {}", e);
+        }
+        match wait.wait(&mut queue_reader, true).await {
+            Some(None) => fail!("Answer was not set!"),
+            Some(Some(Answer::Count(result))) => Ok(result? > 0),
+            Some(Some(_)) => fail!("Bad answer (RemoveValidatorKey)!"),
+            None => fail!("Waiting returned an internal error!")
+        }
+    }
+
+    // Validator key hashes with their election ids, oldest election first
+    pub fn get_validator_keys(&self) -> Result<Vec<([u8; 32], i32)>> {
+        let mut result = Vec::new();
+        for key in self.validator_keys.list() {
+            let key_id = base64_decode(&key.validator_key_id)?;
+            result.push((key_id[..].try_into()?, key.election_id));
+        }
+        Ok(result)
+    }
+
+    // Returns count of keys in the keystore
+    pub async fn encrypt_keystore(&self) -> Result<usize> {
+        let (wait, mut queue_reader) = Wait::new();
+        let pushed_task = Arc::new((wait.clone(), Task::EncryptKeystore));
+        wait.request();
+        if let Err(e) = self.sender.send(pushed_task) {
+            fail!("Error encrypt_keystore: {}", e);
+        }
+        match wait.wait(&mut queue_reader, true).await {
+            Some(None) => fail!("Answer was not set!"),
+            Some(Some(Answer::Count(result))) => result,
+            Some(Some(_)) => fail!("Bad answer (EncryptKeystore)!"),
+            None => fail!("Waiting returned an internal error!")
+        }
+    }
+
     pub fn store_states_gc_interval(&self, interval: u32) {
         let (wait, _) = Wait::new();
         let pushed_task = Arc::new((wait.clone(), Task::StoreStatesGcInterval(interval)));
@@ -1147,18 +1865,7 @@ impl NodeConfigHandler {
                     if config_validator_keys.len() > 2 {
                         let oldest_validator_key = NodeConfigHandler::get_oldest_validator_key(&config);
                         if let Some(oldest_key) = oldest_validator_key {
-                                config.remove_validator_key(
-                                    oldest_key.validator_key_id.clone(),
-                                    oldest_key.election_id
-                                )?;
-                                validator_keys.remove(&oldest_key)?;
-                                config.remove_key_from_key_ring(&oldest_key.validator_key_id.clone());
-                                if let Some(adnl_key_id) = oldest_key.validator_adnl_key_id {
-                                    config.remove_key_from_key_ring(&adnl_key_id);
-                                }
-                                if let Some(bls_key_id) = oldest_key.validator_bls_key {
-                                    config.remove_key_from_key_ring(&bls_key_id);
-                                }
+                            Self::forget_validator_key(validator_keys, config, &oldest_key)?;
                         }
                     } else {
                         break;
@@ -1170,6 +1877,43 @@ impl NodeConfigHandler {
         Ok(())
     }
 
+    fn forget_validator_key(
+        validator_keys: &Arc<ValidatorKeys>,
+        config: &mut TonNodeConfig,
+        key: &ValidatorKeysJson
+    ) -> Result<()> {
+        config.remove_validator_key(key.validator_key_id.clone(), key.election_id)?;
+        validator_keys.remove(key)?;
+        config.remove_key_from_key_ring(&key.validator_key_id);
+        if let Some(adnl_key_id) = &key.validator_adnl_key_id {
+            config.remove_key_from_key_ring(adnl_key_id);
+        }
+        if let Some(bls_key_id) = &key.validator_bls_key {
+            config.remove_key_from_key_ring(bls_key_id);
+        }
+        Ok(())
+    }
+
+    // Returns count of removed keys
+    fn remove_validator_key_and_save(
+        validator_keys: &Arc<ValidatorKeys>,
+        config: &mut TonNodeConfig,
+        key_id: &[u8; 32],
+        election_id: i32
+    ) -> Result<usize> {
+        let key_id = base64_encode(key_id);
+        let key = config.validator_keys.iter().flatten()
+            .find(|key| key.validator_key_id == key_id && key.election_id == election_id)
+            .cloned();
+        let Some(key) = key else {
+            return Ok(0)
+        };
+        Self::forget_validator_key(validator_keys, config, &key)?;
+        let file_name = config.file_name.clone();
+        config.save_to_file(&file_name)?;
+        Ok(1)
+    }
+
     fn add_validator_bls_key_and_save(
         self: Arc<Self>,
         validator_keys: &Arc<ValidatorKeys>,
@@ -1184,7 +1928,8 @@ impl NodeConfigHandler {
 
         // check validator keys
         Self::revision_validator_keys(validator_keys, config)?;
-        config.save_to_file(&config.file_name)?;
+        let file_name = config.file_name.clone();
+        config.save_to_file(&file_name)?;
         Ok(())
     }
 
@@ -1218,7 +1963,8 @@ impl NodeConfigHandler {
 
         // check validator keys
         Self::revision_validator_keys(validator_keys, config)?;
-        config.save_to_file(&config.file_name)?;
+        let file_name = config.file_name.clone();
+        config.save_to_file(&file_name)?;
         Ok(())
     }
 
@@ -1230,7 +1976,8 @@ impl NodeConfigHandler {
     )-> Result<()> {
         let key = config.add_validator_key(&key_id, election_id)?;
         validator_keys.add(key)?;
-        config.save_to_file(&config.file_name)?;
+        let file_name = config.file_name.clone();
+        config.save_to_file(&file_name)?;
         Ok(())
     }
 
@@ -1376,6 +2123,12 @@ impl NodeConfigHandler {
                         );
                         Answer::Result(result)
                     },
+                    Task::RemoveValidatorKey(key, election_id) => {
+                        let result = NodeConfigHandler::remove_validator_key_and_save(
+                            &validator_keys, &mut actual_config, &key, election_id
+                        );
+                        Answer::Count(result)
+                    },
                     Task::GetKey(key_data) => {
                         let result = NodeConfigHandler::get_key(&actual_config, key_data);
                         Answer::GetKey(result)
@@ -1399,6 +2152,10 @@ impl NodeConfigHandler {
                         }
                         let result = actual_config.save_to_file(&name);
                         Answer::Result(result)
+                    },
+                    Task::EncryptKeystore => {
+                        let result = actual_config.encrypt_keystore(&name);
+                        Answer::Count(result)
                     }
                 };
                 task.0.respond(Some(answer));
@@ -1758,6 +2515,9 @@ pub struct ValidatorManagerConfig {
     pub unsafe_catchain_rotates: HashMap<u32, (u32, u32)>,
     pub no_countdown_for_zerostate: bool,
     pub smft_disabled: bool,
+    pub session_checkpoints: bool,
+    // Validation is not started under the config which fails the config check
+    pub strict_config_check: bool,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -1775,9 +2535,12 @@ struct ValidatorManagerConfigImpl {
 
 impl Display for ValidatorManagerConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "validation countdown mode: {}; update interval: {} ms; resync: [{}]; rotates: [{}]",
+        write!(f, "validation countdown mode: {}; update interval: {} ms; session checkpoints: {}; \
+            strict config check: {}; resync: [{}]; rotates: [{}]",
             if self.no_countdown_for_zerostate { "except-zerostate" } else { "always" },
             self.update_interval.as_millis(),
+            self.session_checkpoints,
+            self.strict_config_check,
             self.unsafe_resync_catchains.iter().map(|n| format!("{} ", n)).collect::<String>(),
             self.unsafe_catchain_rotates.iter().map(
                 |(cc, (blk, uid))| format!("({},{})=>{} ",cc,blk,uid)
@@ -1787,7 +2550,13 @@ impl Display for ValidatorManagerConfig {
 }
 
 impl ValidatorManagerConfig {
-    pub fn read_configs(config_files: Vec<String>, validation_countdown_mode: Option<String>, smft_disabled: bool) -> ValidatorManagerConfig {
+    pub fn read_configs(
+        config_files: Vec<String>, 
+        validation_countdown_mode: Option<String>, 
+        smft_disabled: bool,
+        session_checkpoints: bool,
+        strict_config_check: bool
+    ) -> ValidatorManagerConfig {
         log::debug!(target: "validator", "Reading validator manager config files: {}",
             config_files.iter().map(|x| format!("{}; ",x)).collect::<String>());
 
@@ -1803,6 +2572,8 @@ impl ValidatorManagerConfig {
         }
 
         validator_config.smft_disabled = smft_disabled;
+        validator_config.session_checkpoints = session_checkpoints;
+        validator_config.strict_config_check = strict_config_check;
 
         'iterate_configs: for one_config in config_files.into_iter() {
             if let Ok(config_file) = std::fs::File::open(one_config.clone()) {
@@ -1856,6 +2627,8 @@ impl Default for ValidatorManagerConfig {
             unsafe_catchain_rotates: HashMap::new(),
             no_countdown_for_zerostate: false,
             smft_disabled: false,
+            session_checkpoints: false,
+            strict_config_check: false,
         }
     }
 }
@@ -1958,6 +2731,23 @@ impl ValidatorKeys {
         Ok(())
     }
 
+    // Keys in order of election ids
+    fn list(&self) -> Vec<ValidatorKeysJson> {
+        let mut keys = Vec::new();
+        let mut current = self.first.load(atomic::Ordering::Relaxed);
+        while current != 0 {
+            match self.values.get(&current) {
+                Some(key) => keys.push(key.val().clone()),
+                None => break
+            }
+            current = match self.index.get(&current) {
+                Some(next) => *next.val(),
+                None => break
+            };
+        }
+        keys
+    }
+
     fn remove(&self, key: &ValidatorKeysJson) -> Result<bool> {
         let mut current = self.first.load(atomic::Ordering::Relaxed);
 
@@ -0,0 +1,249 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::validator::validator_utils::try_calc_vset_for_workchain;
+
+use std::fmt;
+use ever_block::{
+    BlockIdExt, ConfigParamEnum, ConfigParams, GlobalCapabilities, Result, UInt256, ValidatorSet
+};
+
+#[cfg(test)]
+#[path = "tests/test_config_check.rs"]
+mod tests;
+
+const MAX_SPLIT_DEPTH: u8 = 60;
+const MAX_ROUND_CANDIDATES: u32 = 32;
+const MAX_CONSENSUS_TIMEOUT_MS: u32 = 10 * 60 * 1000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigViolation {
+    pub rule: &'static str,
+    pub details: String,
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.rule, self.details)
+    }
+}
+
+// Result of the config check of the key block
+#[derive(Clone, Debug)]
+pub struct ConfigCheckResult {
+    pub block_id: BlockIdExt,
+    pub violations: Vec<ConfigViolation>,
+}
+
+impl ConfigCheckResult {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+// Rule returns descriptions of all violations it has found.
+// Error means the checked params can't be read, it is a violation too
+type ConfigRule = fn(&ConfigParams) -> Result<Vec<String>>;
+
+const CONFIG_RULES: &[(&str, ConfigRule)] = &[
+    ("validator sets", check_validator_sets),
+    ("catchain config", check_catchain_config),
+    ("consensus config", check_consensus_config),
+    ("workchains", check_workchains),
+    ("gas limits", check_gas_limits),
+    ("block limits", check_block_limits),
+];
+
+// Runs all rules over the config of the key block
+pub fn check_config(block_id: &BlockIdExt, config: &ConfigParams) -> ConfigCheckResult {
+    let mut violations = Vec::new();
+    for &(rule, check) in CONFIG_RULES {
+        match check(config) {
+            Ok(found) => violations.extend(
+                found.into_iter().map(|details| ConfigViolation { rule, details })
+            ),
+            Err(e) => violations.push(ConfigViolation { rule, details: format!("can't check: {}", e) })
+        }
+    }
+    ConfigCheckResult { block_id: block_id.clone(), violations }
+}
+
+fn check_validator_set(name: &str, vset: &ValidatorSet, violations: &mut Vec<String>) {
+    if vset.list().is_empty() {
+        violations.push(format!("{} is empty", name));
+        return
+    }
+    if vset.main() == 0 || vset.main() as usize > vset.list().len() {
+        violations.push(format!(
+            "{} has {} main validators of {}", name, vset.main(), vset.list().len()
+        ));
+    }
+    let zero_weights = vset.list().iter().filter(|descr| descr.weight == 0).count();
+    if zero_weights > 0 {
+        violations.push(format!("{} has {} validators with zero weight", name, zero_weights));
+    }
+    let total_weight = vset.list().iter().fold(0u64, |total, descr| total.saturating_add(descr.weight));
+    if total_weight == 0 {
+        violations.push(format!("{} has zero total weight", name));
+    }
+}
+
+fn check_validator_sets(config: &ConfigParams) -> Result<Vec<String>> {
+    let mut violations = Vec::new();
+    let mut vsets = vec![("current validator set (param 34)", config.validator_set()?)];
+    if let Some(ConfigParamEnum::ConfigParam36(param)) = config.config(36)? {
+        vsets.push(("next validator set (param 36)", param.next_validators));
+    }
+    for (name, vset) in &vsets {
+        check_validator_set(name, vset, &mut violations);
+    }
+    // Each workchain must get own validators, otherwise its shards are never validated
+    if config.has_capability(GlobalCapabilities::CapWorkchains) && config.workchains()?.len()? > 1 {
+        let cc_config = config.catchain_config()?;
+        let mut workchains = Vec::new();
+        config.workchains()?.iterate_keys(|workchain_id: i32| {
+            workchains.push(workchain_id);
+            Ok(true)
+        })?;
+        for (name, vset) in &vsets {
+            for workchain_id in &workchains {
+                if try_calc_vset_for_workchain(vset, config, &cc_config, *workchain_id)?.is_empty() {
+                    violations.push(format!("{} has no validators for workchain {}", name, workchain_id));
+                }
+            }
+        }
+    }
+    Ok(violations)
+}
+
+fn check_catchain_config(config: &ConfigParams) -> Result<Vec<String>> {
+    let cc_config = config.catchain_config()?;
+    let mut violations = Vec::new();
+    for (name, value) in [
+        ("mc_catchain_lifetime", cc_config.mc_catchain_lifetime),
+        ("shard_catchain_lifetime", cc_config.shard_catchain_lifetime),
+        ("shard_validators_lifetime", cc_config.shard_validators_lifetime),
+        ("shard_validators_num", cc_config.shard_validators_num),
+    ] {
+        if value == 0 {
+            violations.push(format!("{} is zero", name));
+        }
+    }
+    Ok(violations)
+}
+
+fn check_consensus_config(config: &ConfigParams) -> Result<Vec<String>> {
+    let consensus_config = match config.config(29)? {
+        Some(ConfigParamEnum::ConfigParam29(param)) => param.consensus_config,
+        _ => return Ok(vec!["consensus config (param 29) is absent".to_string()])
+    };
+    let mut violations = Vec::new();
+    for (name, value) in [
+        ("attempt_duration", consensus_config.attempt_duration),
+        ("catchain_max_deps", consensus_config.catchain_max_deps),
+        ("max_block_bytes", consensus_config.max_block_bytes),
+        ("max_collated_bytes", consensus_config.max_collated_bytes),
+    ] {
+        if value == 0 {
+            violations.push(format!("{} is zero", name));
+        }
+    }
+    if !(1..=MAX_ROUND_CANDIDATES).contains(&consensus_config.round_candidates) {
+        violations.push(format!(
+            "round_candidates {} is out of range 1..={}",
+            consensus_config.round_candidates, MAX_ROUND_CANDIDATES
+        ));
+    }
+    if !(1..=MAX_CONSENSUS_TIMEOUT_MS).contains(&consensus_config.consensus_timeout_ms) {
+        violations.push(format!(
+            "consensus_timeout_ms {} is out of range 1..={}",
+            consensus_config.consensus_timeout_ms, MAX_CONSENSUS_TIMEOUT_MS
+        ));
+    }
+    if consensus_config.next_candidate_delay_ms >= consensus_config.consensus_timeout_ms {
+        violations.push(format!(
+            "next_candidate_delay_ms {} is not less than consensus_timeout_ms {}",
+            consensus_config.next_candidate_delay_ms, consensus_config.consensus_timeout_ms
+        ));
+    }
+    Ok(violations)
+}
+
+fn check_workchains(config: &ConfigParams) -> Result<Vec<String>> {
+    let workchains = config.workchains()?;
+    let mut violations = Vec::new();
+    if workchains.len()? == 0 {
+        violations.push("workchains description (param 12) is empty".to_string());
+    }
+    workchains.iterate_with_keys(|workchain_id: i32, descr| {
+        let no_hash = UInt256::default();
+        if descr.active() && (descr.zerostate_root_hash == no_hash || descr.zerostate_file_hash == no_hash) {
+            violations.push(format!("active workchain {} has no zerostate hashes", workchain_id));
+        }
+        if descr.min_split() > descr.max_split() {
+            violations.push(format!(
+                "workchain {} min_split {} is greater than max_split {}",
+                workchain_id, descr.min_split(), descr.max_split()
+            ));
+        }
+        if descr.max_split() > MAX_SPLIT_DEPTH {
+            violations.push(format!(
+                "workchain {} max_split {} is greater than {}", workchain_id, descr.max_split(), MAX_SPLIT_DEPTH
+            ));
+        }
+        Ok(true)
+    })?;
+    Ok(violations)
+}
+
+fn check_gas_limits(config: &ConfigParams) -> Result<Vec<String>> {
+    let mut violations = Vec::new();
+    for index in [20, 21] {
+        let prices = match config.config(index)? {
+            Some(ConfigParamEnum::ConfigParam20(prices)) | Some(ConfigParamEnum::ConfigParam21(prices)) => prices,
+            _ => {
+                violations.push(format!("gas prices (param {}) are absent", index));
+                continue
+            }
+        };
+        if prices.gas_limit == 0 {
+            violations.push(format!("param {}: gas_limit is zero", index));
+        }
+        if prices.block_gas_limit < prices.gas_limit {
+            violations.push(format!(
+                "param {}: block_gas_limit {} is less than gas_limit {}",
+                index, prices.block_gas_limit, prices.gas_limit
+            ));
+        }
+    }
+    Ok(violations)
+}
+
+fn check_block_limits(config: &ConfigParams) -> Result<Vec<String>> {
+    let mut violations = Vec::new();
+    for (index, is_masterchain) in [(22, true), (23, false)] {
+        let limits = config.block_limits(is_masterchain)?;
+        for (name, limits) in [("bytes", limits.bytes()), ("gas", limits.gas()), ("lt_delta", limits.lt_delta())] {
+            if limits.hard_limit() == 0 {
+                violations.push(format!("param {}: {} hard limit is zero", index, name));
+            }
+            if limits.underload() > limits.soft_limit() || limits.soft_limit() > limits.hard_limit() {
+                violations.push(format!(
+                    "param {}: {} limits {}/{}/{} are not ordered",
+                    index, name, limits.underload(), limits.soft_limit(), limits.hard_limit()
+                ));
+            }
+        }
+    }
+    Ok(violations)
+}
@@ -0,0 +1,239 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    config::TonNodeConfig, ext_messages::MessagesPoolLimits,
+    network::control_permissions::ControlPermissionsConfig
+};
+use ever_block::{error, fail, Result};
+use serde_json::Value;
+use std::{collections::BTreeSet, path::{Path, PathBuf}, sync::OnceLock};
+
+#[cfg(test)]
+#[path = "tests/test_config_reload.rs"]
+mod tests;
+
+// Pseudo option with content of log config file, so changed log levels are caught too
+const LOG_CONFIG: &str = "log_config";
+
+// Options which can be changed in running node
+const RELOADABLE: &[&str] = &[
+    "log_config_name",
+    LOG_CONFIG,
+    "gc/cells_gc_config/gc_interval_sec",
+    "gc/cells_gc_config/cells_lifetime_sec",
+    "gc/enable_for_archives",
+    "gc/archives_life_time_hours",
+    "collator_config/external_messages_maximum_queue_length",
+    "collator_config/external_messages_maximum_queue_bytes",
+    "collator_config/external_messages_maximum_account_messages",
+    "control_permissions",
+];
+
+// Options which are written by the node itself
+const IGNORED: &[&str] = &["validator_keys", "validator_key_ring"];
+
+static LOG_HANDLE: OnceLock<log4rs::Handle> = OnceLock::new();
+
+pub fn set_log_handle(handle: log4rs::Handle) {
+    LOG_HANDLE.set(handle).ok();
+}
+
+pub fn reload_log_config(path: Option<&Path>) -> Result<()> {
+    let Some(path) = path else {
+        fail!("log config can't be removed without restart")
+    };
+    let handle = LOG_HANDLE.get().ok_or_else(|| error!("logger is not reconfigurable"))?;
+    let config = log4rs::config::load_config_file(path, Default::default())
+        .map_err(|e| error!("can't load log config {}: {}", path.display(), e))?;
+    handle.set_config(config);
+    Ok(())
+}
+
+/// Options of reloaded config by outcome, options are named by their path like `gc/enable_for_archives`
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub rejected: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigChange {
+    LogConfig(Option<PathBuf>),
+    StatesGcInterval(u32),
+    CellsLifetime(u64),
+    ArchivesLifeTime(Option<u32>),
+    ExtMessagesLimits(MessagesPoolLimits),
+    ControlPermissions(Option<ControlPermissionsConfig>),
+}
+
+impl ConfigChange {
+    // Option is one of reloadable ones
+    fn new(option: &str, config: &TonNodeConfig) -> Self {
+        let collator_config = config.collator_config();
+        match option {
+            "log_config_name" | LOG_CONFIG => Self::LogConfig(config.log_config_path()),
+            "gc/cells_gc_config/gc_interval_sec" =>
+                Self::StatesGcInterval(config.cells_gc_config().gc_interval_sec),
+            "gc/cells_gc_config/cells_lifetime_sec" =>
+                Self::CellsLifetime(config.cells_gc_config().cells_lifetime_sec),
+            "gc/enable_for_archives" | "gc/archives_life_time_hours" =>
+                Self::ArchivesLifeTime(config.gc_archives_life_time_hours()),
+            "control_permissions" => Self::ControlPermissions(config.control_permissions().cloned()),
+            _ => Self::ExtMessagesLimits(MessagesPoolLimits {
+                max_messages: collator_config.external_messages_maximum_queue_length,
+                max_bytes: collator_config.external_messages_maximum_queue_bytes,
+                max_account_messages: collator_config.external_messages_maximum_account_messages,
+            })
+        }
+    }
+}
+
+// Keeps options of running node to find out what is changed by new config
+pub struct ConfigReloader {
+    configs_dir: String,
+    file_name: String,
+    running: parking_lot::Mutex<Value>,
+}
+
+impl ConfigReloader {
+    pub fn new(config: &TonNodeConfig) -> Result<Self> {
+        Ok(Self {
+            configs_dir: config.configs_dir().to_string(),
+            file_name: config.file_name().to_string(),
+            running: parking_lot::Mutex::new(Self::snapshot(config)?),
+        })
+    }
+
+    // Config file the node was started with
+    pub fn read_config(&self) -> Result<TonNodeConfig> {
+        TonNodeConfig::reread_file(&self.configs_dir, &self.file_name)
+    }
+
+    // Changes of reloadable options are applied one by one. Options which are not applied
+    // are reported again by next reload.
+    pub fn reload(
+        &self,
+        new: &TonNodeConfig,
+        mut apply: impl FnMut(&ConfigChange) -> Result<()>
+    ) -> Result<ReloadReport> {
+        let snapshot = Self::snapshot(new)?;
+        let mut running = self.running.lock();
+        let mut options = Vec::new();
+        diff("", &running, &snapshot, &mut options);
+
+        let mut report = ReloadReport::default();
+        // Several options may give the same change
+        let mut changes: Vec<(ConfigChange, Vec<String>)> = Vec::new();
+        for option in options {
+            if IGNORED.contains(&option.as_str()) {
+                continue
+            }
+            if !RELOADABLE.contains(&option.as_str()) {
+                log::warn!("Config option {} can't be changed without restart", option);
+                report.rejected.push(option);
+                continue
+            }
+            let change = ConfigChange::new(&option, new);
+            match changes.iter_mut().find(|(found, _)| found == &change) {
+                Some((_, options)) => options.push(option),
+                None => changes.push((change, vec![option]))
+            }
+        }
+
+        for (change, options) in changes {
+            match apply(&change) {
+                Ok(()) => {
+                    log::info!("Config reloaded: {:?}", change);
+                    for option in options {
+                        set_option(&mut running, &option, snapshot.pointer(&format!("/{}", option)));
+                        report.applied.push(option);
+                    }
+                }
+                Err(e) => {
+                    log::error!("Can't apply reloaded config {:?}: {}", change, e);
+                    report.errors.extend(options.into_iter().map(|option| format!("{}: {}", option, e)));
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn snapshot(config: &TonNodeConfig) -> Result<Value> {
+        let mut snapshot = serde_json::to_value(config)?;
+        let log_config = config.log_config_path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map_or(Value::Null, Value::String);
+        if let Value::Object(map) = &mut snapshot {
+            map.insert(LOG_CONFIG.to_string(), log_config);
+        }
+        Ok(snapshot)
+    }
+}
+
+// Collects changed options. Sections are compared field by field only if they have
+// reloadable options, missing section is the same as the empty one.
+fn diff(path: &str, old: &Value, new: &Value, changed: &mut Vec<String>) {
+    if old == new {
+        return
+    }
+    let is_section = path.is_empty() || RELOADABLE.iter().any(
+        |option| option.strip_prefix(path).map_or(false, |rest| rest.starts_with('/'))
+    );
+    match (old, new) {
+        (Value::Object(_) | Value::Null, Value::Object(_) | Value::Null) if is_section => {
+            let mut keys = BTreeSet::new();
+            for value in [old, new] {
+                if let Value::Object(map) = value {
+                    keys.extend(map.keys());
+                }
+            }
+            for key in keys {
+                let child = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}/{}", path, key)
+                };
+                diff(
+                    &child,
+                    old.get(key).unwrap_or(&Value::Null),
+                    new.get(key).unwrap_or(&Value::Null),
+                    changed
+                );
+            }
+        }
+        _ => changed.push(path.to_string())
+    }
+}
+
+fn set_option(root: &mut Value, option: &str, value: Option<&Value>) {
+    let mut fields = option.split('/').peekable();
+    let mut section = root;
+    while let Some(field) = fields.next() {
+        if !section.is_object() {
+            *section = Value::Object(Default::default());
+        }
+        let Value::Object(map) = section else {
+            unreachable!()
+        };
+        if fields.peek().is_none() {
+            match value {
+                Some(value) => map.insert(field.to_string(), value.clone()),
+                None => map.remove(field)
+            };
+            return
+        }
+        section = map.entry(field).or_insert(Value::Null);
+    }
+}
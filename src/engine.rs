@@ -12,17 +12,28 @@
 */
 
 use crate::{
+    applied_blocks::{AppliedBlocksNotifier, DEFAULT_APPLIED_BLOCKS_CHANNEL_SIZE},
+    archives_gc::{ArchiveDirUsage, ArchivesGcStats, ArchivesWatermarkGc},
     block::{BlockStuff, BlockIdExtExtention, BlockKind},
     block_proof::BlockProofStuff, boot,
     config::{
-        CollatorConfig, CollatorTestBundlesGeneralConfig, TonNodeConfig, ValidatorManagerConfig
+        CollatorConfig, CollatorTestBundlesGeneralConfig, KeyRing, TonNodeConfig, ValidatorManagerConfig
     },
+    config_check::{check_config, ConfigCheckResult},
+    config_reload::{reload_log_config, ConfigChange, ConfigReloader, ReloadReport},
     engine_traits::{
-        EngineAlloc, EngineOperations, OverlayOperations, PrivateOverlayOperations, Server
+        BlockAccess, EngineAlloc, EngineOperations, OverlayOperations, PrivateOverlayOperations,
+        RempSupport, Server, StateAccess
+    },
+    error::NodeError,
+    ext_messages::{
+        create_ext_message, create_ext_message_with_time_check, limits::ExtMessageLimits,
+        prevalidation::{PrevalidationQueue, PrevalidationTask}, rate_limiter::ExtMessagesRateLimiter, 
+        MessagesPool, MessagesPoolLimits, EXT_MESSAGES_TRACE_TARGET
     },
-    ext_messages::{MessagesPool, EXT_MESSAGES_TRACE_TARGET},
     full_node::{
-        apply_block::{self, apply_block},
+        apply_block::{self, apply_block}, apply_throttle::ApplyThrottle,
+        broadcast_dedup::BroadcastDedup,
         shard_client::{
             process_block_broadcast, start_masterchain_client, start_shards_client,
             SHARD_BROADCAST_WINDOW, apply_proof_chain,
@@ -31,30 +42,46 @@ use crate::{
     },
     internal_db::{
         InternalDb, InternalDbConfig, 
-        INITIAL_MC_BLOCK, LAST_APPLIED_MC_BLOCK, PSS_KEEPER_MC_BLOCK, ARCHIVES_GC_BLOCK
+        INITIAL_MC_BLOCK, LAST_APPLIED_MC_BLOCK, PSS_KEEPER_MC_BLOCK, ARCHIVES_GC_BLOCK,
+        LAST_PERSISTENT_STATE_BLOCK
     },
+    manual_gc::{GcKind, GcTicket, ManualGc},
     network::{
         control::{ControlServer, DataSource, StatusReporter},
+        control_limits::ControlLimits, control_permissions::ControlPermissions,
         full_node_client::FullNodeOverlayClient, full_node_service::FullNodeOverlayService,
         node_network::NodeNetwork
     },
+    network_snapshot::NetworkSnapshotCache,
+    node_status::{BlockStatus, BroadcastFanoutStatus, NodeStatus, RempStatus, ValidatorStatus},
     shard_blocks::{
         ShardBlocksPool, resend_top_shard_blocks_worker, save_top_shard_blocks_worker, 
         ShardBlockProcessingResult
     },
     shard_state::ShardStateStuff,
     shard_states_keeper::ShardStatesKeeper,
+    time_service::TimeService,
     types::awaiters_pool::AwaitersPool,
     validator::{
         candidate_db::{CandidateDb, CandidateDbPool},
+        key_rotation::{
+            ElectionTiming, KeyRotation, KeyRotationOps, RotationKeys, RotationPlan, KEY_ROTATION_STATE
+        },
         remp_service::RempService,
         validator_manager::{start_validator_manager, ValidationStatus},
+        validator_schedule::{
+            compute_validator_schedule, ValidatorSchedule, ValidatorSessionsHistory, SESSIONS_HISTORY_LEN
+        },
+        validator_utils::set_batch_signature_check,
     }
 };
 #[cfg(feature = "external_db")]
 use crate::{
     config::KafkaConsumerConfig, engine_traits::ExternalDb, 
-    external_db::{kafka_consumer::KafkaConsumer, start_external_db_worker}
+    external_db::{
+        kafka_consumer::KafkaConsumer, start_external_db_worker,
+        remp_history::{start_remp_history_worker, RempHistoryExporter}
+    }
 };
 #[cfg(not(feature = "external_db"))]
 use crate::{full_node::mesh_client::MeshClient, internal_db::EXTERNAL_DB_BLOCK};
@@ -67,7 +94,7 @@ use crate::validator::{
 use crate::{
     engine_traits::EngineTelemetry, full_node::telemetry::{FullNodeTelemetry, RempClientTelemetry},
     network::telemetry::{FullNodeNetworkTelemetry, FullNodeNetworkTelemetryKind},
-    validator::telemetry::{CollatorValidatorTelemetry, RempCoreTelemetry},
+    validator::telemetry::{CollationTimingTelemetry, CollatorValidatorTelemetry, RempCoreTelemetry},
 };
 
 use adnl::common::Subscriber;
@@ -75,24 +102,24 @@ use adnl::common::Subscriber;
 use adnl::telemetry::{Metric, MetricBuilder, TelemetryItem, TelemetryPrinter};
 use catchain::SessionId;
 use ever_block::{
-    error, fail, BASE_WORKCHAIN_ID, BlockIdExt, GlobalCapabilities, KeyId, MASTERCHAIN_ID, 
-    OutMsgQueue, Result, SHARD_FULL, ShardIdent, UInt256
+    error, fail, BASE_WORKCHAIN_ID, BlockIdExt, ConfigParams, Deserializable, Ed25519KeyOption,
+    GlobalCapabilities, HashmapType, KeyId, MASTERCHAIN_ID, OutMsgQueue, ProcessedInfoKey, Result, SHARD_FULL, ShardIdent, UInt256
 };
 #[cfg(feature = "slashing")]
-use ever_block::{CryptoSignaturePair, Deserializable, HashmapType};
+use ever_block::CryptoSignaturePair;
 #[cfg(feature = "telemetry")]
 use ever_block::Cell;
 use std::{
     ops::Deref, sync::{Arc, atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering, AtomicU64, AtomicI32}},
-    time::{Duration, SystemTime}, collections::{HashMap, HashSet}, path::Path
+    time::{Duration, SystemTime}, collections::{BTreeSet, HashMap, HashSet}, path::Path
 };
 #[cfg(feature = "telemetry")]
 use std::fmt::Write;
-use storage::{StorageAlloc, block_handle_db::BlockHandle};
+use storage::{GcCounters, StorageAlloc, block_handle_db::{BlockHandle, BlockOrigin}};
 #[cfg(feature = "telemetry")]
-use storage::{StorageTelemetry, types::StorageCell};
+use storage::{StorageTelemetry, instrumented_channel::ChannelTelemetry, types::StorageCell};
 use ton_api::ton::ton_node::{
-    Broadcast, 
+    Broadcast, RempMessage,
     broadcast::{
         BlockBroadcast, QueueUpdateBroadcast, ExternalMessageBroadcast, NewShardBlockBroadcast
     }
@@ -108,6 +135,12 @@ const MAX_VALIDATED_BLOCK_STATS_ENTRIES_COUNT: usize = 10000;
 #[path = "tests/test_engine.rs"]
 mod tests;
 
+// External message waiting for prevalidation
+pub enum ExtMessageTask {
+    Broadcast(ExternalMessageBroadcast),
+    Remp(RempMessage),
+}
+
 pub struct Engine {
     db: Arc<InternalDb>,
     #[cfg(feature = "external_db")]
@@ -130,10 +163,12 @@ pub struct Engine {
 
     zero_state_id: BlockIdExt,
     init_mc_block_id: BlockIdExt,
+    trusted_key_block: Option<BlockIdExt>,
     hardforks: Vec<BlockIdExt>,
     flags: EngineFlags,
     pub network: Arc<NodeNetwork>,
-    archives_life_time: Option<u32>,
+    archives_life_time: parking_lot::RwLock<Option<u32>>,
+    archives_watermark_gc: Option<Arc<ArchivesWatermarkGc>>,
     // enable_shard_state_persistent_gc: bool,
     shard_blocks: ShardBlocksPool,
     last_known_mc_block_seqno: AtomicU32,
@@ -150,6 +185,31 @@ pub struct Engine {
  
     shard_states_keeper: Arc<ShardStatesKeeper>,
     processed_workchain: Option<i32>,
+    ext_message_time_window: Option<(u32, u32)>,
+    ext_messages_rate_limiter: Option<Arc<ExtMessagesRateLimiter>>,
+    ext_message_limits: Arc<ExtMessageLimits>,
+    #[cfg(feature = "external_db")]
+    remp_history_exporter: Option<Arc<RempHistoryExporter>>,
+    ext_messages_prevalidation: Arc<PrevalidationQueue<ExtMessageTask>>,
+    validator_sessions_history: Arc<ValidatorSessionsHistory>,
+    last_config_check: parking_lot::RwLock<Option<Arc<ConfigCheckResult>>>,
+    apply_throttle: Option<Arc<ApplyThrottle>>,
+    block_broadcast_dedup: Arc<BroadcastDedup>,
+    time_service: Option<Arc<TimeService>>,
+    key_rotation: Option<Arc<KeyRotation>>,
+    network_snapshot_cache: NetworkSnapshotCache,
+    persistent_state_chunk_size: usize,
+    proof_chain_max_length: usize,
+    account_state_proof_max_size: usize,
+    sync_download_concurrency: usize,
+    sync_max_downloaded_archives: usize,
+    sync_proof_check_threads: usize,
+    block_compression_level: i32,
+    applied_blocks_notifier: AppliedBlocksNotifier,
+    manual_gc: ManualGc,
+    config_reloader: ConfigReloader,
+    control_permissions: Arc<ControlPermissions>,
+    control_limits: Arc<ControlLimits>,
 
     // None - queue calculating is in progress
     split_queues_cache: lockfree::map::Map<BlockIdExt, Option<(OutMsgQueue, OutMsgQueue, HashSet<UInt256>)>>,
@@ -170,6 +230,8 @@ pub struct Engine {
     #[cfg(feature = "telemetry")]
     validator_telemetry: CollatorValidatorTelemetry,
     #[cfg(feature = "telemetry")]
+    collation_timing_telemetry: CollationTimingTelemetry,
+    #[cfg(feature = "telemetry")]
     full_node_service_telemetry: FullNodeNetworkTelemetry,
     #[cfg(feature = "telemetry")]
     engine_telemetry: Arc<EngineTelemetry>,
@@ -200,23 +262,28 @@ impl <T> DownloadContext<'_, T> {
 
     async fn download(&mut self) -> Result<T> {
         let mut attempt = 1;
+        // Download attempt and pause after it are interrupted by node stop
+        let stopper = self.engine.stopper();
+        let what = format!("{} id: {}", self.name, self.id);
         loop {
-            if self.engine.check_stop() {
-                fail!("{} id: {}, stop flag was set", self.name, self.id);
-            }
-            match self.downloader.try_download(self).await {
+            match stopper.cancellable(&what, self.downloader.try_download(self)).await? {
+                Err(e) if NodeError::is_cancelled(&e) => break Err(e),
+                Err(e) if !crate::error::is_retryable(&e) => {
+                    log::error!("{} (attempt {}): id: {}, giving up: {}", self.name, attempt, self.id, e);
+                    break Err(e)
+                },
                 Err(e) => self.log(format!("{}", e).as_str(), attempt),
                 Ok(ret) => break Ok(ret)
             }
             attempt += 1;
             if let Some(limit) = &self.limit {
                 if &attempt > limit {
-                    fail!("Downloader: out of attempts");
+                    fail!(NodeError::NetworkTimeout("Downloader: out of attempts".to_string()));
                 }
             }
             if let Some((current, mult, max)) = &mut self.timeout {
                 *current = (*max).min(*current * *mult / 10);
-                futures_timer::Delay::new(Duration::from_millis(*current)).await;
+                stopper.delay(&what, Duration::from_millis(*current)).await?;
             } else {
                 tokio::task::yield_now().await;
             }
@@ -262,7 +329,7 @@ impl Downloader for BlockDownloader {
                     Err(e) => if !handle.has_data() {
                         None
                     } else {
-                        return Err(e)
+                        return Err(error!(NodeError::classify(e, NodeError::DbCorruption)))
                     },
                     Ok(block) => Some(block)
                 };
@@ -275,7 +342,7 @@ impl Downloader for BlockDownloader {
                         } else if !is_link && !handle.has_proof() {
                             None
                         } else {
-                            return Err(e)
+                            return Err(error!(NodeError::classify(e, NodeError::DbCorruption)))
                         },
                         Ok(proof) => Some(proof)
                     }
@@ -313,7 +380,9 @@ impl Downloader for QueueUpdateDownloader {
             if handle.has_data() && handle.is_queue_update() {
                 match context.engine.db.load_block_data(&handle).await {
                     Ok(block) => return Ok(block),
-                    Err(e) if handle.has_data() => return Err(e),
+                    Err(e) if handle.has_data() => {
+                        return Err(error!(NodeError::classify(e, NodeError::DbCorruption)))
+                    },
                     _ => ()
                 }
             }
@@ -537,6 +606,9 @@ impl Stopper {
         if bitmap & Engine::MASK_SERVICE_ARCHIVES_GC != 0 {
             ss.push_str("archives gc, ");
         }
+        if bitmap & Engine::MASK_SERVICE_EXT_MESSAGES_PREVALIDATION != 0 {
+            ss.push_str("ext messages prevalidation, ");
+        }
         #[cfg(feature = "external_db")]
         if bitmap & Engine::MASK_SERVICE_EXTERNAL_DB != 0 {
             ss.push_str("external db, ");
@@ -556,6 +628,32 @@ impl Stopper {
         self.stop.fetch_and(!mask, Ordering::Relaxed);
     }
 
+    // Resolves when the node is stopping
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    // Runs the future unless the node is stopping, then the future is dropped at once
+    // and Cancelled error is returned
+    pub async fn cancellable<T>(
+        &self,
+        what: impl std::fmt::Display,
+        future: impl std::future::Future<Output = T>
+    ) -> Result<T> {
+        tokio::select! {
+            biased;
+            _ = self.token.cancelled() => {
+                fail!(NodeError::Cancelled(format!("{}: node is stopping", what)))
+            }
+            result = future => Ok(result)
+        }
+    }
+
+    // Pause between download attempts, interrupted by node stop
+    pub async fn delay(&self, what: impl std::fmt::Display, timeout: Duration) -> Result<()> {
+        self.cancellable(what, tokio::time::sleep(timeout)).await
+    }
+
     #[cfg(not(feature = "external_db"))]
     pub fn token(&self) -> tokio_util::sync::CancellationToken {
         self.token.clone()
@@ -582,6 +680,7 @@ impl Engine {
     pub const MASK_SERVICE_SS_CACHE_KEEPER: u32                = 0x1000;
     #[cfg(feature = "external_db")]
     pub const MASK_SERVICE_EXTERNAL_DB: u32                    = 0x2000;
+    pub const MASK_SERVICE_EXT_MESSAGES_PREVALIDATION: u32     = 0x4000;
 
     // Sync status
     pub const SYNC_STATUS_START_BOOT: u32           = 0x0001;
@@ -670,12 +769,47 @@ impl Engine {
         );
 
         let archives_life_time = general_config.gc_archives_life_time_hours();
+        let archives_watermarks = general_config.gc_archives_watermarks();
+        let config_reloader = ConfigReloader::new(&general_config)?;
+        let control_permissions = Arc::new(
+            ControlPermissions::new(general_config.control_permissions())?
+                .with_read_only(flags.read_only)
+        );
+        let control_limits = Arc::new(ControlLimits::new(general_config.control_limits())?);
         let remp_config = general_config.remp_config().clone();
+        #[cfg(feature = "external_db")]
+        let remp_history_config = general_config.external_db_config()
+            .map(|config| config.remp_history)
+            .unwrap_or_default();
         let cells_lifetime_sec = general_config.cells_gc_config().cells_lifetime_sec;
         let enable_shard_state_persistent_gc = general_config.enable_shard_state_persistent_gc();
         let skip_saving_persistent_states = general_config.skip_saving_persistent_states();
         let states_cache_mode = general_config.states_cache_mode();
         let restore_db = general_config.restore_db();
+        let check_db_consistency = general_config.check_db_consistency();
+        let persistent_state_chunk_size = general_config.persistent_state_chunk_size();
+        let proof_chain_max_length = general_config.proof_chain_max_length();
+        let account_state_proof_max_size = general_config.account_state_proof_max_size();
+        let sync_download_concurrency = general_config.sync_download_concurrency();
+        let sync_max_downloaded_archives = general_config.sync_max_downloaded_archives();
+        let sync_proof_check_threads = general_config.sync_proof_check_threads();
+        set_batch_signature_check(!general_config.conservative_signature_check());
+        let block_compression_level = general_config.block_compression_level();
+        let apply_throttle = general_config.catch_up_throttle()
+            .map(|config| Arc::new(ApplyThrottle::new(config.clone())));
+        let block_broadcast_dedup = Arc::new(BroadcastDedup::new(&general_config.block_broadcast_dedup()));
+        let time_service = general_config.time_service()
+            .map(|config| Arc::new(TimeService::new(config.clone())));
+        let key_rotation = general_config.key_rotation()
+            .map(|config| Arc::new(KeyRotation::new(config.clone())));
+        let persistent_state_policy = general_config.persistent_state_policy();
+        let persistent_state_delta_chain_limit = general_config.persistent_state_delta_chain_limit();
+        let trusted_key_block = general_config.trusted_key_block()?;
+        let top_block_mc_ref_horizon = general_config.top_block_mc_ref_horizon();
+        let queue_lag_warning_threshold = general_config.queue_lag_warning_threshold();
+        let states_memory_ceiling_mb = general_config.states_memory_ceiling_mb();
+        let max_unsaved_states_per_shard = general_config.max_unsaved_states_per_shard();
+        let states_prefetch_distance = general_config.states_prefetch_distance();
         let processed_workchain = general_config.workchain();
 
         let cells_db_config = general_config.cells_db_config().clone();
@@ -683,13 +817,20 @@ impl Engine {
             db_directory: general_config.internal_db_path().to_string(), 
             cells_gc_interval_sec: general_config.cells_gc_config().gc_interval_sec,
             cells_db_config: cells_db_config.clone(),
+            fsync: general_config.storage_fsync().clone(),
+            read_only: flags.read_only,
+            account_tx_index: general_config.account_transactions_index(),
         };
         let control_config = general_config.control_server()?;
         let collator_config = general_config.collator_config().clone();
         let boot_from_zerostate = general_config.boot_from_zerostate();
         let global_config = general_config.load_global_config()?;
         let test_bundles_config = general_config.test_bundles_config().clone();
-        let external_messages_maximum_queue_length = collator_config.external_messages_maximum_queue_length;
+        let external_messages_limits = MessagesPoolLimits {
+            max_messages: collator_config.external_messages_maximum_queue_length,
+            max_bytes: collator_config.external_messages_maximum_queue_bytes,
+            max_account_messages: collator_config.external_messages_maximum_account_messages,
+        };
 
         let network = NodeNetwork::new(
             general_config,
@@ -712,6 +853,8 @@ impl Engine {
                 DataSource::Status(status_reporter.clone()),
                 network.config_handler(),
                 network.config_handler(),
+                control_permissions.clone(),
+                control_limits.clone(),
                 Some(&network)
             ).await?;
             (Some(status_reporter), Some(status_server))
@@ -741,31 +884,35 @@ impl Engine {
         }
         stopper.release_stop(Self::MASK_SERVICE_DB_RESTORE);
         let db = db?;
-
-        let zero_state_id = global_config.zero_state().expect("check zero state settings");
-        let mut init_mc_block_id = match global_config.init_block()? {
-            Some(init_mc_block_id) if !boot_from_zerostate => {
-                log::info!("zero state substitued by init block {}", init_mc_block_id);
-                init_mc_block_id
+        let archives_watermark_gc = match archives_watermarks {
+            Some(config) => {
+                let usage = Arc::new(ArchiveDirUsage::new(db.archive_dir()));
+                Some(Arc::new(ArchivesWatermarkGc::new(config, usage)?))
             }
-            _ => zero_state_id.clone()
+            None => None
         };
-        let mut hardforks = global_config.hardforks()?;
-        hardforks.sort_by(|a, b| a.seq_no.cmp(&b.seq_no));
-        if !boot_from_zerostate {
-            if let Some(block_id) = hardforks.last() {
-                if block_id.seq_no > init_mc_block_id.seq_no {
-                    fail!("global config contains init_block and hardforks sections so int_block {} \
-                        must be after last hard_fork {}", init_mc_block_id.seq_no, block_id.seq_no)
-                }
-            }
-            if let Ok(Some(block_id)) = db.load_full_node_state(INITIAL_MC_BLOCK) {
-                if block_id.seq_no > init_mc_block_id.seq_no {
-                    init_mc_block_id = block_id.deref().clone()
-                }
+
+        if let Some(mode) = check_db_consistency.filter(|_| !flags.read_only) {
+            log::info!("Checking DB consistency ({:?})...", mode);
+            match db.check_and_repair_consistency(mode).await {
+                Ok(report) => log::info!("DB consistency check finished: {}", report),
+                Err(e) => log::error!("DB consistency check failed: {}", e)
             }
         }
 
+        let zero_state_id = global_config.zero_state().expect("check zero state settings");
+        let mut hardforks = global_config.hardforks()?;
+        hardforks.sort_by(|a, b| a.seq_no.cmp(&b.seq_no));
+        let saved_init_block = db.load_full_node_state(INITIAL_MC_BLOCK).ok().flatten();
+        let init_mc_block_id = boot::choose_init_block(
+            &zero_state_id,
+            global_config.init_block()?,
+            trusted_key_block.as_ref(),
+            hardforks.last(),
+            saved_init_block.as_deref(),
+            boot_from_zerostate
+        )?;
+
         log::info!("load_all_top_shard_blocks");
         let shard_blocks = match db.load_all_top_shard_blocks() {
             Ok(tsbs) => tsbs,
@@ -787,11 +934,16 @@ impl Engine {
             &engine_telemetry,
             &engine_allocated
         )?;
+        if let Some(horizon) = top_block_mc_ref_horizon {
+            shard_blocks_pool.set_mc_ref_horizon(horizon);
+        }
 
         let shard_states_keeper = ShardStatesKeeper::new(
             db.clone(),
             enable_shard_state_persistent_gc,
             skip_saving_persistent_states,
+            persistent_state_policy,
+            persistent_state_delta_chain_limit,
             states_cache_mode,
             cells_lifetime_sec,
             stopper.clone(),
@@ -800,6 +952,18 @@ impl Engine {
             engine_telemetry.clone(),
             engine_allocated.clone()
         )?;
+        if let Some(threshold) = queue_lag_warning_threshold {
+            shard_states_keeper.mesh_queues_keeper().queue_lags().set_warning_threshold(threshold);
+        }
+        if let Some(ceiling_mb) = states_memory_ceiling_mb {
+            shard_states_keeper.set_states_memory_ceiling(ceiling_mb * 1024 * 1024);
+        }
+        if let Some(limit) = max_unsaved_states_per_shard {
+            shard_states_keeper.set_max_unsaved_states(limit);
+        }
+        if let Some(distance) = states_prefetch_distance {
+            shard_states_keeper.prefetcher().set_distance(distance);
+        }
 
         let remp_client = if remp_config.is_client_enabled() {
             let remp_client = Arc::new(RempClient::new(network.public_overlay_key()?.id().data().into()));
@@ -828,6 +992,15 @@ impl Engine {
             crossbeam_channel::bounded(MAX_VALIDATED_BLOCK_STATS_ENTRIES_COUNT);
         let now = now_duration().as_secs() as u32;
         let candidate_db = CandidateDbPool::with_path(db.db_root_dir()?);
+        #[cfg(feature = "external_db")]
+        let remp_history_exporter = if ext_db.iter().any(|db| db.process_remp_history_enabled()) {
+            let (exporter, receiver) = RempHistoryExporter::new(&remp_history_config);
+            let exporter = Arc::new(exporter);
+            start_remp_history_worker(exporter.clone(), receiver, ext_db.clone());
+            Some(exporter)
+        } else {
+            None
+        };
         let engine = Arc::new(Engine {
             db,
             #[cfg(feature = "external_db")]
@@ -865,7 +1038,7 @@ impl Engine {
                 engine_allocated.clone()
             ),
             external_messages: Arc::new(
-                MessagesPool::new(now, external_messages_maximum_queue_length)
+                MessagesPool::with_limits(now, external_messages_limits)
             ),
             download_mesh_kit_awaiters: AwaitersPool::new(
                 "download_mesh_kit_awaiters",
@@ -885,9 +1058,11 @@ impl Engine {
             stopper,
             zero_state_id,
             init_mc_block_id,
+            trusted_key_block,
             hardforks,
             flags,
-            archives_life_time,
+            archives_life_time: parking_lot::RwLock::new(archives_life_time),
+            archives_watermark_gc,
             network,
             shard_blocks: shard_blocks_pool,
             last_known_mc_block_seqno: AtomicU32::new(0),
@@ -901,6 +1076,36 @@ impl Engine {
             collator_config,
             shard_states_keeper: shard_states_keeper.clone(),
             processed_workchain,
+            ext_message_time_window: remp_config.get_ext_message_time_window(),
+            ext_messages_rate_limiter: remp_config.get_ext_messages_rate_limit()
+                .map(|limit| Arc::new(ExtMessagesRateLimiter::new(limit.clone()))),
+            ext_message_limits: Arc::new(
+                ExtMessageLimits::new(remp_config.get_ext_message_limits(), processed_workchain)
+            ),
+            #[cfg(feature = "external_db")]
+            remp_history_exporter,
+            ext_messages_prevalidation: Arc::new(
+                PrevalidationQueue::new(remp_config.get_ext_messages_prevalidation())
+            ),
+            validator_sessions_history: Arc::new(ValidatorSessionsHistory::new(SESSIONS_HISTORY_LEN)),
+            last_config_check: parking_lot::RwLock::new(None),
+            apply_throttle,
+            block_broadcast_dedup,
+            time_service,
+            key_rotation,
+            network_snapshot_cache: NetworkSnapshotCache::default(),
+            persistent_state_chunk_size,
+            proof_chain_max_length,
+            account_state_proof_max_size,
+            sync_download_concurrency,
+            sync_max_downloaded_archives,
+            sync_proof_check_threads,
+            block_compression_level,
+            applied_blocks_notifier: AppliedBlocksNotifier::new(DEFAULT_APPLIED_BLOCKS_CHANNEL_SIZE),
+            manual_gc: ManualGc::new(),
+            config_reloader,
+            control_permissions,
+            control_limits,
             split_queues_cache: lockfree::map::Map::new(),
             validation_status: Arc::new(AtomicU8::new(0)),
             last_validation_time: lockfree::map::Map::new(),
@@ -918,6 +1123,8 @@ impl Engine {
             #[cfg(feature = "telemetry")]
             validator_telemetry: CollatorValidatorTelemetry::default(),
             #[cfg(feature = "telemetry")]
+            collation_timing_telemetry: CollationTimingTelemetry::default(),
+            #[cfg(feature = "telemetry")]
             full_node_service_telemetry: 
                 FullNodeNetworkTelemetry::new(FullNodeNetworkTelemetryKind::Service),
             #[cfg(feature = "telemetry")]
@@ -940,11 +1147,31 @@ impl Engine {
             rs.set_engine(engine.clone())?;
         }
 
+        let weak = Arc::downgrade(&engine);
+        engine.db.set_broken_block_handler(Arc::new(move |id: &BlockIdExt| {
+            if let Some(engine) = weak.upgrade() {
+                let id = id.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = engine.redownload_block(&id).await {
+                        log::error!("Can't download again broken block {}: {}", id, e)
+                    }
+                });
+            }
+        }));
+
         engine.acquire_stop(Self::MASK_SERVICE_SHARDSTATE_GC);
         save_top_shard_blocks_worker(engine.clone(), shard_blocks_receiver);
         Ok(engine)
     }
 
+    // Restores block whose stored file was found broken
+    async fn redownload_block(&self, id: &BlockIdExt) -> Result<()> {
+        let (block, _) = self.download_block(id, None).await?;
+        self.store_block(&block).await?;
+        log::info!("Broken block {} is downloaded again", id);
+        Ok(())
+    }
+
     pub fn set_sync_status(&self, status: u32) {
         log::info!("sync status now is: {}", status);
         self.sync_status.store(status, Ordering::Relaxed);
@@ -988,6 +1215,12 @@ impl Engine {
 
         // wait while all node's services will stop
         self.stopper.clone().wait_stop().await;
+        if let Err(e) = self.db.stop_block_handles().await {
+            log::warn!("Error while stopping block handles storer: {}", e);
+        }
+        if let Err(e) = self.db.stop_fsync() {
+            log::warn!("Error while final fsync of storage: {}", e);
+        }
         self.network.stop_adnl().await;
 
     }
@@ -1014,6 +1247,7 @@ impl Engine {
     pub fn zero_state_id(&self) -> &BlockIdExt { &self.zero_state_id }
 
     pub fn init_mc_block_id(&self) -> &BlockIdExt {&self.init_mc_block_id}
+    pub fn trusted_key_block(&self) -> Option<&BlockIdExt> {self.trusted_key_block.as_ref()}
 
     pub fn flags(&self) -> &EngineFlags { &self.flags }
 
@@ -1112,6 +1346,10 @@ impl Engine {
         self.will_validate.load(Ordering::SeqCst)
     }
 
+    pub fn last_known_mc_block_seqno(&self) -> u32 {
+        self.last_known_mc_block_seqno.load(Ordering::Relaxed)
+    }
+
     pub fn update_last_known_mc_block_seqno(&self, seqno: u32) -> bool {
         self.last_known_mc_block_seqno.fetch_max(seqno, Ordering::SeqCst) < seqno
     }
@@ -1148,6 +1386,11 @@ impl Engine {
         &self.validator_telemetry
     }
 
+    #[cfg(feature = "telemetry")]
+    pub fn collation_timing_telemetry(&self) -> &CollationTimingTelemetry {
+        &self.collation_timing_telemetry
+    }
+
     #[cfg(feature = "telemetry")]
     pub fn full_node_service_telemetry(&self) -> &FullNodeNetworkTelemetry {
         &self.full_node_service_telemetry
@@ -1249,6 +1492,284 @@ impl Engine {
         self.processed_workchain
     }
 
+    pub fn ext_message_time_window(&self) -> Option<(u32, u32)> {
+        self.ext_message_time_window
+    }
+
+    pub fn ext_messages_rate_limiter(&self) -> Option<&Arc<ExtMessagesRateLimiter>> {
+        self.ext_messages_rate_limiter.as_ref()
+    }
+
+    #[cfg(feature = "external_db")]
+    pub fn remp_history_exporter(&self) -> Option<&Arc<RempHistoryExporter>> {
+        self.remp_history_exporter.as_ref()
+    }
+
+    pub fn ext_message_limits(&self) -> &Arc<ExtMessageLimits> {
+        &self.ext_message_limits
+    }
+
+    pub fn ext_messages_prevalidation(&self) -> &Arc<PrevalidationQueue<ExtMessageTask>> {
+        &self.ext_messages_prevalidation
+    }
+
+    pub fn validator_sessions_history(&self) -> &Arc<ValidatorSessionsHistory> {
+        &self.validator_sessions_history
+    }
+
+    pub fn last_config_check(&self) -> Option<Arc<ConfigCheckResult>> {
+        self.last_config_check.read().clone()
+    }
+
+    // Checks config of the applied key block, bad config is only reported here
+    fn check_key_block_config(&self, block_id: &BlockIdExt, config: &ConfigParams) {
+        let result = check_config(block_id, config);
+        if !result.is_ok() {
+            log::warn!(
+                "CONFIG CHECK FAILED: config of key block {} violates {} rules:\n{}",
+                block_id,
+                result.violations.len(),
+                result.violations.iter().map(|v| format!("  {}", v)).collect::<Vec<_>>().join("\n")
+            );
+        } else {
+            log::info!("Config of key block {} is checked", block_id);
+        }
+        *self.last_config_check.write() = Some(Arc::new(result));
+    }
+
+    pub async fn validator_schedule(&self, lookahead_cc: u32) -> Result<ValidatorSchedule> {
+        let mc_state = self.load_last_applied_mc_state().await?;
+        let local_adnl_ids = self.network().config_handler().get_actual_validator_adnl_ids()?;
+        compute_validator_schedule(&mc_state, &local_adnl_ids, lookahead_cc, self.now())
+    }
+
+    pub fn archives_gc_stats(&self) -> Result<Option<ArchivesGcStats>> {
+        match &self.archives_watermark_gc {
+            Some(gc) => Ok(gc.last_run()),
+            None => fail!("Archives GC by disk watermarks is not configured")
+        }
+    }
+
+    pub fn apply_throttle(&self) -> Option<&Arc<ApplyThrottle>> {
+        self.apply_throttle.as_ref()
+    }
+
+    pub fn time_service(&self) -> Option<&Arc<TimeService>> {
+        self.time_service.as_ref()
+    }
+
+    pub fn network_snapshot_cache(&self) -> &NetworkSnapshotCache {
+        &self.network_snapshot_cache
+    }
+
+    pub fn key_rotation(&self) -> Option<&Arc<KeyRotation>> {
+        self.key_rotation.as_ref()
+    }
+
+    pub async fn election_timing(&self) -> Result<ElectionTiming> {
+        ElectionTiming::from_config(self.load_last_applied_mc_state().await?.config_params()?)
+    }
+
+    pub async fn key_rotation_plan(&self) -> Result<RotationPlan> {
+        match &self.key_rotation {
+            Some(key_rotation) => key_rotation.plan(self, &self.election_timing().await?).await,
+            None => fail!("Validator key rotation is not configured")
+        }
+    }
+
+    pub async fn provide_rotation_keys(&self, keys: RotationKeys) -> Result<()> {
+        match &self.key_rotation {
+            Some(key_rotation) => key_rotation.provide_keys(self, keys).await,
+            None => fail!("Validator key rotation is not configured")
+        }
+    }
+
+    pub fn persistent_state_chunk_size(&self) -> usize {
+        self.persistent_state_chunk_size
+    }
+
+    pub fn proof_chain_max_length(&self) -> usize {
+        self.proof_chain_max_length
+    }
+
+    pub fn account_state_proof_max_size(&self) -> usize {
+        self.account_state_proof_max_size
+    }
+
+    pub fn control_permissions(&self) -> &Arc<ControlPermissions> {
+        &self.control_permissions
+    }
+
+    pub fn control_limits(&self) -> &Arc<ControlLimits> {
+        &self.control_limits
+    }
+
+    pub fn reload_config(&self, new: &TonNodeConfig) -> Result<ReloadReport> {
+        self.config_reloader.reload(new, |change| self.apply_config_change(change))
+    }
+
+    pub fn reload_config_from_file(&self) -> Result<ReloadReport> {
+        self.reload_config(&self.config_reloader.read_config()?)
+    }
+
+    // Moves validator keys of the config into the encrypted keystore, returns count of keys
+    pub async fn encrypt_validator_keystore(&self) -> Result<usize> {
+        let count = self.network().config_handler().encrypt_keystore().await?;
+        log::info!("Validator keystore is written with {} keys", count);
+        Ok(count)
+    }
+
+    fn apply_config_change(&self, change: &ConfigChange) -> Result<()> {
+        match change {
+            ConfigChange::LogConfig(path) => reload_log_config(path.as_deref())?,
+            ConfigChange::StatesGcInterval(interval_sec) =>
+                self.db.adjust_states_gc_interval(*interval_sec),
+            ConfigChange::CellsLifetime(lifetime_sec) =>
+                self.shard_states_keeper.set_cells_lifetime_sec(*lifetime_sec),
+            ConfigChange::ArchivesLifeTime(life_time) =>
+                *self.archives_life_time.write() = *life_time,
+            ConfigChange::ExtMessagesLimits(limits) =>
+                self.external_messages.set_limits(limits.clone()),
+            ConfigChange::ControlPermissions(config) =>
+                self.control_permissions.update(config.as_ref())?
+        }
+        Ok(())
+    }
+
+    pub fn sync_download_concurrency(&self) -> usize {
+        self.sync_download_concurrency
+    }
+
+    pub fn sync_max_downloaded_archives(&self) -> usize {
+        self.sync_max_downloaded_archives
+    }
+
+    pub fn sync_proof_check_threads(&self) -> usize {
+        self.sync_proof_check_threads
+    }
+
+    pub fn block_compression_level(&self) -> i32 {
+        self.block_compression_level
+    }
+
+    pub fn applied_blocks_notifier(&self) -> &AppliedBlocksNotifier {
+        &self.applied_blocks_notifier
+    }
+
+    pub fn manual_gc(&self) -> &ManualGc {
+        &self.manual_gc
+    }
+
+    pub fn start_manual_gc(self: &Arc<Self>, kind: GcKind) -> GcTicket {
+        let engine = self.clone();
+        self.manual_gc.trigger(kind, move |counters| async move {
+            match kind {
+                GcKind::ShardStates => {
+                    engine.shard_states_keeper().persistent_states_gc(&engine, &counters).await
+                }
+                GcKind::Archives => Self::manual_archives_gc(&engine, &counters).await,
+                GcKind::BlockHandles => {
+                    let count = engine.db().gc_expired_validator_states(engine.now(), &counters)?;
+                    log::info!("validator states gc: {} expired states removed", count);
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    async fn manual_archives_gc(engine: &Arc<Engine>, counters: &GcCounters) -> Result<()> {
+        let block_id = engine.load_archives_gc_mc_block_id()?
+            .ok_or_else(|| error!("No archives GC block id"))?;
+        let handle = engine.load_block_handle(&block_id)?.ok_or_else(
+            || error!("Cannot load handle for archives_gc_block {}", block_id)
+        )?;
+        if let Some(watermark_gc) = &engine.archives_watermark_gc {
+            return Self::check_watermarks_for_archives(engine, watermark_gc, &handle, counters).await
+        }
+        let mc_state = engine.load_state(handle.id()).await?;
+        match Self::last_key_block_handle(engine, handle, &mc_state)? {
+            Some(last_keyblock) =>
+                Self::check_gc_for_archives(engine, &last_keyblock, &mc_state, counters).await,
+            None => Ok(())
+        }
+    }
+
+    fn last_key_block_handle(
+        engine: &Arc<Engine>,
+        handle: Arc<BlockHandle>,
+        mc_state: &ShardStateStuff
+    ) -> Result<Option<Arc<BlockHandle>>> {
+        if handle.is_key_block()? {
+            return Ok(Some(handle))
+        }
+        let prev_blocks = &mc_state.shard_state_extra()?.prev_blocks;
+        match prev_blocks.get_prev_key_block(handle.id().seq_no())? {
+            None => Ok(None),
+            Some(keyblock) => {
+                let keyblock = BlockIdExt::from_ext_blk(keyblock);
+                let handle = engine.load_block_handle(&keyblock)?.ok_or_else(
+                    || error!("Cannot load handle for archives GC key block {}", keyblock)
+                )?;
+                Ok(Some(handle))
+            }
+        }
+    }
+
+    // Sets applied flag and notifies subscribers if the block is applied for the first time
+    pub async fn set_applied_with_block(
+        &self, 
+        handle: &Arc<BlockHandle>, 
+        mc_seq_no: u32,
+        block: Option<&BlockStuff>
+    ) -> Result<bool> {
+        if handle.is_applied() {
+            return Ok(false);
+        }
+        if !handle.is_mesh() {
+            self.db().assign_mc_ref_seq_no(handle, mc_seq_no, None)?;
+            if handle.id().seq_no() != 0 {
+                self.db().archive_block(handle.id(), None).await?;
+                if self.db().is_account_tx_indexed() && !handle.is_queue_update() {
+                    if let Err(e) = self.index_account_transactions(handle, mc_seq_no, block).await {
+                        log::warn!("Can't index account transactions of block {}: {}", handle.id(), e);
+                    }
+                }
+            }
+        }
+        if self.db().store_block_applied(handle, None)? {
+            #[cfg(feature = "telemetry")]
+            self.full_node_telemetry().new_applied_block();
+            // Blocks applied while syncing are old, they don't tell anything about the clock
+            if let Some(time_service) = self.time_service() {
+                let synced = self.get_sync_status() == Engine::SYNC_STATUS_FINISH_SYNC;
+                if synced && handle.id().shard().is_masterchain() {
+                    if let Ok(gen_utime) = handle.gen_utime() {
+                        time_service.observe_block(gen_utime);
+                    }
+                }
+            }
+            self.applied_blocks_notifier.notify(handle, block);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn index_account_transactions(
+        &self,
+        handle: &Arc<BlockHandle>,
+        mc_seq_no: u32,
+        block: Option<&BlockStuff>
+    ) -> Result<()> {
+        match block {
+            Some(block) => self.db().index_account_transactions(block, mc_seq_no),
+            None => {
+                let block = self.db().load_block_data(handle).await?;
+                self.db().index_account_transactions(&block, mc_seq_no)
+            }
+        }
+    }
+
     pub async fn download_and_apply_block_worker(
         self: Arc<Self>, 
         id: &BlockIdExt, 
@@ -1334,6 +1855,9 @@ impl Engine {
                     } else {
                         continue
                     };
+                    handle.set_origin(BlockOrigin::Download);
+                    // Downloaded update is verified by the client
+                    self.db.store_queue_update_verified(&handle)?;
                     log::trace!(
                         "Downloaded queue update for {}apply {} TIME download: {}ms, check & save: {}", 
                         if pre_apply { "pre-" } else { "" }, 
@@ -1361,6 +1885,7 @@ impl Engine {
                     } else {
                         continue
                     };
+                    handle.set_origin(BlockOrigin::Download);
                     let handle = self.store_block_proof(0, id, Some(handle), &proof).await?;
                     let handle = handle.to_non_created().ok_or_else(
                         || error!("INTERNAL ERROR: bad result for store block {} proof", id)
@@ -1427,7 +1952,9 @@ impl Engine {
         metrics::gauge!("timediff", ago as f64);
         self.shard_blocks().update_shard_blocks(&self.load_state(block.id()).await?).await?;
 
-        let first_time_applied = self.set_applied(handle, block.id().seq_no).await?;
+        let first_time_applied = self.set_applied_with_block(
+            handle, block.id().seq_no, Some(block)
+        ).await?;
 
         if let Err(e) = self.mc_block_post_apply(block, gen_utime, first_time_applied).await {
             log::error!("Error after apply block {}: {}", block.id(), e);
@@ -1497,8 +2024,21 @@ impl Engine {
             _ => ()
         }
 
+        if let Some(throttle) = self.apply_throttle() {
+            throttle.refresh(&*self);
+            #[cfg(feature = "telemetry")] {
+                let limits = throttle.limits();
+                let telemetry = self.engine_telemetry();
+                telemetry.apply_throttle_blocks_per_sec.update(limits.blocks_per_sec as u64);
+                telemetry.apply_throttle_state_tasks.update(limits.state_tasks as u64);
+            }
+            throttle.acquire_block().await;
+        }
+
         apply_block(handle, block, mc_seq_no, &(self.clone() as Arc<dyn EngineOperations>),
             pre_apply, recursion_depth).await?;
+        #[cfg(feature = "telemetry")]
+        self.engine_telemetry().applied_blocks.update(1);
 
         let gen_utime = block.gen_utime()?;
         let ago = std::time::SystemTime::now()
@@ -1509,7 +2049,9 @@ impl Engine {
                     .update_shard_blocks(&self.load_state(block.id()).await?)
                     .await?;
 
-                let first_time_applied = self.set_applied(handle, block.id().seq_no()).await?;
+                let first_time_applied = self.set_applied_with_block(
+                    handle, block.id().seq_no(), Some(block)
+                ).await?;
 
                 if first_time_applied {
                     if let Err(e) = self.save_last_applied_mc_block_id(block.id()) {
@@ -1528,7 +2070,9 @@ impl Engine {
             log::info!("{op_name} {block_name} {id}, {ago} seconds old");
         } else {
             if !pre_apply {
-                let first_time_applied = self.set_applied(handle, mc_seq_no).await?;
+                let first_time_applied = self.set_applied_with_block(
+                    handle, mc_seq_no, Some(block)
+                ).await?;
                 if first_time_applied {
                     if block.is_usual_block() {
                         self.tps_counter.submit_transactions(gen_utime as u64, block.calculate_tr_count()?);
@@ -1536,6 +2080,9 @@ impl Engine {
                     if let BlockKind::MeshUpdate { network_id } = block.kind() {
                         self.save_last_mesh_mc_block_id(network_id, id)?;
                     }
+                    if let Err(e) = self.track_queue_lag(handle, block, mc_seq_no).await {
+                        log::warn!("Can't track queue lag after {block_name} {id}: {e}");
+                    }
                 }
             }
 
@@ -1545,6 +2092,33 @@ impl Engine {
         Ok(())
     }
 
+    // Queue updates from other workchains are created, own blocks consume them
+    async fn track_queue_lag(
+        &self,
+        handle: &Arc<BlockHandle>,
+        block: &BlockStuff,
+        mc_seq_no: u32
+    ) -> Result<()> {
+        let lags = self.shard_states_keeper().mesh_queues_keeper().queue_lags();
+        let wc = block.id().shard().workchain_id();
+        if let BlockKind::QueueUpdate { queue_update_for, .. } = block.kind() {
+            lags.queue_update_applied(wc, queue_update_for, mc_seq_no, handle.is_empty_queue_update());
+        } else if block.is_usual_block() && lags.is_tracked(wc) {
+            // Messages are processed up to the least of shards' processed up to mc blocks
+            let mut processed = HashMap::new();
+            self.load_state(block.id()).await?.proc_info()?.iterate_slices_with_keys(|ref mut key, _| {
+                let key = ProcessedInfoKey::construct_from(key)?;
+                let seq_no = processed.entry(key.shard).or_insert(key.mc_seqno);
+                *seq_no = key.mc_seqno.max(*seq_no);
+                Ok(true)
+            })?;
+            if let Some(mc_seq_no) = processed.into_values().min() {
+                lags.processed_upto(wc, mc_seq_no);
+            }
+        }
+        Ok(())
+    }
+
     async fn mc_block_post_apply(
         &self,
         block: &BlockStuff,
@@ -1565,6 +2139,13 @@ impl Engine {
                 block.get_config_params()?.has_capability(GlobalCapabilities::CapSmft),
                 Ordering::Relaxed
             );
+            if let Err(e) = self.ext_message_limits.refresh(&block.get_config_params()?) {
+                log::warn!("Can't refresh external message limits from key block {}: {}", block.id(), e);
+            }
+            if let Err(e) = self.ext_messages_prevalidation.refresh(&block.get_config_params()?) {
+                log::warn!("Can't refresh prevalidation priority sources from key block {}: {}", block.id(), e);
+            }
+            self.check_key_block_config(block.id(), &block.get_config_params()?);
             // While the node boots start key block is not processed by this function.
             // So see process_initial_state for the same code
         }
@@ -1652,6 +2233,70 @@ impl Engine {
         Ok(())
     }
 
+    // Status is assembled from workers state and telemetry, unavailable parts are left empty
+    pub async fn node_status(&self) -> NodeStatus {
+        let now = self.now();
+        let mut status = NodeStatus::new(self.get_sync_status(), now);
+        let block_status = |result: Result<Option<Arc<BlockIdExt>>>| {
+            let id = result.ok().flatten()?;
+            let gen_utime = self.load_block_handle(&id).ok().flatten()
+                .and_then(|handle| handle.gen_utime().ok());
+            Some(BlockStatus::new(&id, gen_utime, now))
+        };
+        status.last_applied_mc_block = block_status(self.load_last_applied_mc_block_id());
+        status.last_known_mc_seq_no = self.last_known_mc_block_seqno();
+        status.mc_seq_no_lag = status.last_applied_mc_block.as_ref()
+            .map(|block| status.last_known_mc_seq_no.saturating_sub(block.seq_no));
+        status.shard_client_mc_block = block_status(self.load_shard_client_mc_block_id());
+        status.pss_keeper_mc_block = block_status(self.load_pss_keeper_mc_block_id());
+        status.archives_gc_mc_block = block_status(self.load_archives_gc_mc_block_id());
+        #[cfg(feature = "external_db")] {
+            status.external_db_mc_block = block_status(self.load_external_db_mc_block_id());
+        }
+        if let Ok(Some(id)) = self.load_shard_client_mc_block_id() {
+            if let Ok(top_blocks) = self.load_state(&id).await.and_then(|state| state.top_blocks_all()) {
+                status.shards = Some(
+                    top_blocks.into_iter()
+                        .filter_map(|id| block_status(Ok(Some(Arc::new(id)))))
+                        .collect()
+                );
+            }
+        }
+        status.validator = ValidatorStatus {
+            validation_status: format!("{:?}", self.validation_status()),
+            last_validation_ago_sec: ValidatorStatus::ago_sec(self.last_validation_time(), now),
+            last_collation_ago_sec: ValidatorStatus::ago_sec(self.last_collation_time(), now),
+        };
+        if self.remp_service().is_some() {
+            #[cfg(feature = "telemetry")]
+            let remp = RempStatus {
+                incoming_queue: Some(self.remp_core_telemetry().incoming_queue_telemetry().depth.current()),
+                response_queue: Some(self.remp_core_telemetry().response_queue_telemetry().depth.current()),
+            };
+            #[cfg(not(feature = "telemetry"))]
+            let remp = RempStatus::default();
+            status.remp = Some(remp);
+        }
+        let (pinned_states, pins) = self.shard_states_keeper().pinned_states_stats();
+        status.storage.pinned_states = Some(pinned_states);
+        status.storage.pinned_states_pins = Some(pins);
+        if let Ok(Some(gc_stats)) = self.archives_gc_stats() {
+            status.storage.used_bytes = Some(gc_stats.used_bytes_after);
+            status.storage.high_watermark_bytes = Some(gc_stats.high_watermark_bytes);
+        }
+        status.storage.degraded_columns = storage::db::degradation::degraded_columns();
+        for (overlay, _, _) in self.network().neighbours_quality_table() {
+            *status.peers.entry(overlay.to_string()).or_default() += 1;
+        }
+        status.block_broadcast_fanout = self.network().broadcast_fanout().map(|fanout| BroadcastFanoutStatus {
+            suppressed_known: fanout.suppressed_known(),
+            suppressed_by_cap: fanout.suppressed_by_cap(),
+            full_fanouts: fanout.full_fanouts(),
+        });
+        status.clock_drift_ms = self.time_service().map(|time_service| time_service.drift_ms());
+        status
+    }
+
     async fn listen_broadcasts(self: Arc<Self>, shard_ident: ShardIdent, mask: u32) -> Result<()> {
         log::debug!("Started listening overlay for shard {}", shard_ident);
         let client = self.get_full_node_overlay(
@@ -1674,13 +2319,15 @@ impl Engine {
                     Ok(Some((broadcast, src))) => {
                         match broadcast {
                             Broadcast::TonNode_BlockBroadcast(broadcast) => {
-                                self.clone().process_block_broadcast(broadcast, src);
+                                self.clone().process_block_broadcast(broadcast, src, false);
                             }
                             Broadcast::TonNode_QueueUpdateBroadcast(broadcast) => {
                                 self.clone().process_queue_update_broadcast(broadcast, src);
                             }
                             Broadcast::TonNode_ExternalMessageBroadcast(broadcast) => {
-                                self.process_ext_msg_broadcast(broadcast, src).await;
+                                self.ext_messages_prevalidation.push(
+                                    ExtMessageTask::Broadcast(broadcast), src, false
+                                );
                             }
                             Broadcast::TonNode_IhrMessageBroadcast(broadcast) => {
                                 log::trace!("TonNode_IhrMessageBroadcast from {}: {:?}", src, broadcast);
@@ -1749,6 +2396,15 @@ impl Engine {
                 updated_cells: create_metric_ex("NODE update cell_counters/sec"),
                 new_cells: create_metric_ex("NODE create new cells/sec"),
                 deleted_cells: create_metric_ex("NODE delete cells/sec"),
+                cells_cache_capacity: create_metric("NODE cells cache capacity"),
+                cells_cache_bytes: create_metric("NODE cells cache bytes"),
+                cells_cache_hit_rate: create_metric("NODE cells cache hit rate %"),
+                cells_cache_evictions: create_metric("NODE cells cache evictions"),
+                storer_queue: Arc::new(ChannelTelemetry {
+                    depth: create_metric("NODE storer queue"),
+                    enqueued: create_metric_ex("NODE storer enqueued jobs/sec"),
+                    overflows: create_metric_ex("NODE storer blocked jobs/sec"),
+                }),
             }
         );
         let engine_telemetry = Arc::new(
@@ -1764,6 +2420,14 @@ impl Engine {
                 validator_peers: create_metric("Alloc NODE validator peers"),
                 validator_sets: create_metric("Alloc NODE validator sets"),
                 old_state_cell_load_time: Metric::with_total_average("Old state cell load time (nanos)", Engine::TIMEOUT_TELEMETRY_SEC),
+                pinned_states: create_metric("NODE pinned states"),
+                sync_queue_depth: create_metric("NODE sync downloaded archives"),
+                sync_downloads: create_metric("NODE sync archive downloads"),
+                sync_checked_proofs: create_metric_ex("NODE sync checked proofs/sec"),
+                broken_block_files: create_metric("NODE broken block files"),
+                apply_throttle_blocks_per_sec: create_metric("NODE apply throttle blocks/sec"),
+                apply_throttle_state_tasks: create_metric("NODE apply throttle state tasks"),
+                applied_blocks: create_metric_ex("NODE applied blocks/sec"),
             }
         );
         let metrics = vec![
@@ -1780,6 +2444,13 @@ impl Engine {
             TelemetryItem::MetricBuilder(engine_telemetry.storage.updated_cells.clone()),
             TelemetryItem::MetricBuilder(engine_telemetry.storage.new_cells.clone()),
             TelemetryItem::MetricBuilder(engine_telemetry.storage.deleted_cells.clone()),
+            TelemetryItem::Metric(engine_telemetry.storage.cells_cache_capacity.clone()),
+            TelemetryItem::Metric(engine_telemetry.storage.cells_cache_bytes.clone()),
+            TelemetryItem::Metric(engine_telemetry.storage.cells_cache_hit_rate.clone()),
+            TelemetryItem::Metric(engine_telemetry.storage.cells_cache_evictions.clone()),
+            TelemetryItem::Metric(engine_telemetry.storage.storer_queue.depth.clone()),
+            TelemetryItem::MetricBuilder(engine_telemetry.storage.storer_queue.enqueued.clone()),
+            TelemetryItem::MetricBuilder(engine_telemetry.storage.storer_queue.overflows.clone()),
             TelemetryItem::Metric(engine_telemetry.awaiters.clone()),
             TelemetryItem::Metric(engine_telemetry.catchain_clients.clone()),
             TelemetryItem::Metric(engine_telemetry.cells.clone()),
@@ -1789,7 +2460,15 @@ impl Engine {
             TelemetryItem::Metric(engine_telemetry.top_blocks.clone()),
             TelemetryItem::Metric(engine_telemetry.validator_peers.clone()),
             TelemetryItem::Metric(engine_telemetry.validator_sets.clone()),
-            TelemetryItem::Metric(engine_telemetry.old_state_cell_load_time.clone())
+            TelemetryItem::Metric(engine_telemetry.old_state_cell_load_time.clone()),
+            TelemetryItem::Metric(engine_telemetry.pinned_states.clone()),
+            TelemetryItem::Metric(engine_telemetry.sync_queue_depth.clone()),
+            TelemetryItem::Metric(engine_telemetry.sync_downloads.clone()),
+            TelemetryItem::MetricBuilder(engine_telemetry.sync_checked_proofs.clone()),
+            TelemetryItem::Metric(engine_telemetry.broken_block_files.clone()),
+            TelemetryItem::Metric(engine_telemetry.apply_throttle_blocks_per_sec.clone()),
+            TelemetryItem::Metric(engine_telemetry.apply_throttle_state_tasks.clone()),
+            TelemetryItem::MetricBuilder(engine_telemetry.applied_blocks.clone())
         ];
         (metrics, engine_telemetry)
 
@@ -1872,7 +2551,19 @@ impl Engine {
         }
     }
 
-    fn process_block_broadcast(self: Arc<Self>, broadcast: BlockBroadcast, src: Arc<KeyId>) {
+    // Direct broadcast is sent by a neighbour's fanout, it is not retransmitted by overlay,
+    // so a new block is sent on by our fanout
+    pub fn process_block_broadcast(self: Arc<Self>, broadcast: BlockBroadcast, src: Arc<KeyId>, direct: bool) {
+        if let Some(fanout) = self.network.broadcast_fanout() {
+            fanout.record_known(&broadcast.id, &src);
+        }
+        // Copies from other neighbours are dropped by id before any deserialization
+        let dedup_guard = self.block_broadcast_dedup.try_start(&broadcast.id);
+        self.network.neighbours_quality().record_broadcast(&src, dedup_guard.is_some());
+        let Some(dedup_guard) = dedup_guard else {
+            log::trace!("Skipped block broadcast {} from {}: already in processing", broadcast.id, src);
+            return
+        };
         // because of ALL blocks-broadcasts received in one task - spawn for each block
         log::trace!("Processing block broadcast {}", broadcast.id);
         let engine = self.clone() as Arc<dyn EngineOperations>;
@@ -1889,6 +2580,15 @@ impl Engine {
                 }
                 Ok(_block_opt) => {
                     log::trace!("Processed block broadcast {} from {}", broadcast.id, src);
+                    // Skipped broadcast leaves the window, so a later copy is checked again
+                    if _block_opt.is_some() {
+                        dedup_guard.complete();
+                        if direct {
+                            if let Err(e) = engine.send_block_broadcast(broadcast.clone()).await {
+                                log::warn!("Error while sending on block broadcast {}: {}", broadcast.id, e);
+                            }
+                        }
+                    }
 
                     #[cfg(feature = "slashing")]
                     if broadcast.id.shard().is_masterchain() {
@@ -1933,6 +2633,10 @@ impl Engine {
             if let Err(e) = process_block_broadcast(&engine, &broadcast).await {
                 log::error!("Error while processing queue update broadcast {} for wc {} from {}: {:?}",
                     broadcast.id, broadcast.target_wc, src, e);
+                if NodeError::is_queue_update_mismatch(&e) {
+                    self.network.peer_scoring().record_rejected_broadcast(&src);
+                    self.network.peer_scoring().quarantine(&src);
+                }
             } else {
                 log::trace!("Processed queue update broadcast {} for wc {} from {}", 
                     broadcast.id, broadcast.target_wc, src);
@@ -1940,6 +2644,51 @@ impl Engine {
         });
     }
 
+    // Workers parse and check external messages out of the network receive path. On stop they
+    // finish high lane messages, the service is released when all workers are finished
+    fn start_ext_messages_prevalidation(engine: Arc<Engine>) {
+        let workers = engine.ext_messages_prevalidation.workers();
+        log::info!("start_ext_messages_prevalidation: {} workers", workers);
+        tokio::spawn(async move {
+            engine.acquire_stop(Engine::MASK_SERVICE_EXT_MESSAGES_PREVALIDATION);
+            let handles = (0..workers).map(|_| {
+                let engine = engine.clone();
+                tokio::spawn(async move {
+                    while let Some(task) = engine.ext_messages_prevalidation.next_task().await {
+                        engine.process_prevalidation_task(task).await;
+                    }
+                })
+            }).collect::<Vec<_>>();
+            engine.stopper().cancelled().await;
+            engine.ext_messages_prevalidation.stop();
+            futures::future::join_all(handles).await;
+            let stats = engine.ext_messages_prevalidation.stats();
+            log::info!(
+                "ext messages prevalidation stopped, dropped {} high lane and {} low lane messages",
+                stats.high_dropped, stats.low_dropped
+            );
+            engine.release_stop(Engine::MASK_SERVICE_EXT_MESSAGES_PREVALIDATION);
+        });
+    }
+
+    async fn process_prevalidation_task(&self, task: PrevalidationTask<ExtMessageTask>) {
+        log::trace!(
+            target: EXT_MESSAGES_TRACE_TARGET, 
+            "Prevalidating message from {} ({} lane)", task.source, task.lane
+        );
+        match task.task {
+            ExtMessageTask::Broadcast(broadcast) => 
+                self.process_ext_msg_broadcast(broadcast, task.source).await,
+            ExtMessageTask::Remp(message) => match self.remp_service() {
+                Some(remp_service) => remp_service.process_message(message, &task.source).await,
+                None => log::warn!(
+                    target: EXT_MESSAGES_TRACE_TARGET,
+                    "Skipped REMP message from {}: REMP service is not enabled", task.source
+                )
+            }
+        }
+    }
+
     async fn process_ext_msg_broadcast(&self, broadcast: ExternalMessageBroadcast, src: Arc<KeyId>) {
         let remp = self.remp_capability();
         // just add to list
@@ -1949,15 +2698,30 @@ impl Engine {
                 "Skipped ext message broadcast {}bytes from {}: NOT A VALIDATOR",
                 broadcast.message.data.len(), src
             );
+        } else if !self.ext_messages_rate_limiter().map_or(true, |limiter| limiter.allow(&src)) {
+            log::warn!(
+                target: EXT_MESSAGES_TRACE_TARGET,
+                "Dropped ext message broadcast {}bytes from {}: source is over quota",
+                broadcast.message.data.len(), src
+            );
         } else {
             let bytes_len = broadcast.message.data.len();
             let result = if remp {
                 self.push_message_to_remp(broadcast.message.data).await
             } else {
-                self.external_messages().new_message_raw(
-                    &broadcast.message.data, 
-                    self.now()
-                ).map(|_| ())
+                let parsed = match self.ext_message_time_window() {
+                    Some((max_age, max_skew)) => create_ext_message_with_time_check(
+                        &broadcast.message.data, 
+                        self.now(), 
+                        max_age, 
+                        max_skew
+                    ),
+                    None => create_ext_message(&broadcast.message.data)
+                };
+                parsed.and_then(|(id, message)| {
+                    self.ext_message_limits().check(bytes_len, &message)?;
+                    self.external_messages().new_message(&id, Arc::new(message), self.now())
+                })
             };
             match result {
                 Err(e) => {
@@ -1992,6 +2756,9 @@ impl Engine {
                     match self.clone().process_new_shard_block(broadcast).await {
                         Err(e) => {
                             log::error!("Error while processing new shard block broadcast {} from {}: {}", id, src, e);
+                            if NodeError::is_top_block_rejection(&e) {
+                                self.network.peer_scoring().record_rejected_broadcast(&src);
+                            }
                             #[cfg(feature = "telemetry")]
                             self.full_node_telemetry().bad_top_block_broadcast();
                         }
@@ -2083,8 +2850,12 @@ impl Engine {
                 }
 
                 // just passively waiting for 10s...
-                if let Err(e) = self.clone().wait_state(&id, Some(10_000), false).await {
+                let res = self.wait_stored_state(&id, Some(Duration::from_secs(10)), self.stopper()).await;
+                if let Err(e) = res {
                     log::error!("Error in wait_state after top-block-broadcast false {}: {}", id, e);
+                    if self.check_stop() {
+                        return;
+                    }
                     // ...and then allow to download needed blocks forced
                     if let Err(e) = self.clone().wait_state(&id, Some(10_000), true).await {
                         log::error!("Error in wait_state after top-block-broadcast true {}: {}", id, e);
@@ -2322,6 +3093,14 @@ impl Engine {
         self.db().save_full_node_state(PSS_KEEPER_MC_BLOCK, id)
     }
 
+    pub fn load_last_persistent_state_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
+        self.db().load_full_node_state(LAST_PERSISTENT_STATE_BLOCK)
+    }
+
+    pub fn save_last_persistent_state_block_id(&self, id: &BlockIdExt) -> Result<()> {
+        self.db().save_full_node_state(LAST_PERSISTENT_STATE_BLOCK, id)
+    }
+
     pub fn load_archives_gc_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
         self.db().load_full_node_state(ARCHIVES_GC_BLOCK)
     }
@@ -2376,14 +3155,29 @@ impl Engine {
             || error!("Cannot load handle for archives_gc_block {}", archives_gc_block)
         )?;
         let mut last_clean_unapplied_time = std::time::Instant::now();
+        let mut last_validator_states_gc_time = std::time::Instant::now();
+        let mut last_watermarks_check_time: Option<std::time::Instant> = None;
         'm: loop {
             let mc_state = engine.load_state(handle.id()).await?;
             if engine.check_stop() {
                 break 'm;
             }
-            if handle.is_key_block()? {
+            if let Some(watermark_gc) = &engine.archives_watermark_gc {
+                // archives are removed by disk usage instead of age
+                let interval = watermark_gc.config().check_interval_sec as u64;
+                if last_watermarks_check_time.map_or(true, |time| time.elapsed().as_secs() >= interval) {
+                    let counters = GcCounters::default();
+                    if let Err(e) = Self::check_watermarks_for_archives(
+                        &engine, watermark_gc, &handle, &counters
+                    ).await {
+                        log::error!("Archives GC: {}", e);
+                    }
+                    last_watermarks_check_time = Some(std::time::Instant::now());
+                }
+            } else if handle.is_key_block()? {
                 let mc_state = engine.load_state(handle.id()).await?;
-                if let Err(e) = Self::check_gc_for_archives(&engine, &handle, &mc_state).await {
+                let counters = GcCounters::default();
+                if let Err(e) = Self::check_gc_for_archives(&engine, &handle, &mc_state, &counters).await {
                     log::error!("Archives GC: {}", e);
                 }
             }
@@ -2400,6 +3194,14 @@ impl Engine {
                 engine.db().clean_unapplied_files(&ids).await;
                 last_clean_unapplied_time = std::time::Instant::now();
             }
+            // clean expired validator states every hour
+            if last_validator_states_gc_time.elapsed().as_secs() > 3600 {
+                match engine.db().gc_expired_validator_states(engine.now(), &GcCounters::default()) {
+                    Ok(count) => log::info!("validator states gc: {} expired states removed", count),
+                    Err(e) => log::warn!("validator states gc: {}", e)
+                }
+                last_validator_states_gc_time = std::time::Instant::now();
+            }
             handle = loop {
                 match engine.wait_next_applied_mc_block(&handle, Some(500)).await {
                     Ok(r) => break r.0,
@@ -2419,10 +3221,12 @@ impl Engine {
     async fn check_gc_for_archives(
         engine: &Arc<Engine>,
         last_keyblock: &Arc<BlockHandle>,
-        mc_state: &ShardStateStuff
+        mc_state: &ShardStateStuff,
+        counters: &GcCounters
     ) -> Result<()> {
         let mut gc_max_date = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
-        match &engine.archives_life_time {
+        let archives_life_time = *engine.archives_life_time.read();
+        match archives_life_time {
             None => return Ok(()),
             Some(life_time) => {
                 match gc_max_date.checked_sub(Duration::from_secs((life_time * 3600) as u64)) {
@@ -2439,12 +3243,30 @@ impl Engine {
             }
         }
 
+        let keyblock = Self::find_archives_gc_bound(
+            engine, last_keyblock, mc_state, gc_max_date.as_secs()
+        ).await?;
+        if let Some(keyblock) = keyblock {
+            log::info!("start gc for archives..");
+            engine.db.archive_gc(keyblock.id(), counters).await?;
+            log::info!("finish gc for archives.");
+        }
+        Ok(())
+    }
+
+    // Finds the newest key block which archives may be removed before
+    async fn find_archives_gc_bound(
+        engine: &Arc<Engine>,
+        last_keyblock: &Arc<BlockHandle>,
+        mc_state: &ShardStateStuff,
+        gc_max_date: u64
+    ) -> Result<Option<Arc<BlockHandle>>> {
         let mut visited_pss_blocks = 0;
         let mut keyblock = last_keyblock.clone();
         let prev_blocks = &mc_state.shard_state_extra()?.prev_blocks;
         loop {
             match prev_blocks.get_prev_key_block(keyblock.id().seq_no() - 1)? {
-                None => return Ok(()),
+                None => return Ok(None),
                 Some(prev_keyblock) => {
                     let prev_keyblock = BlockIdExt::from_ext_blk(prev_keyblock);
                     let prev_keyblock = engine.load_block_handle(&prev_keyblock)?.ok_or_else(
@@ -2466,22 +3288,18 @@ impl Engine {
                         if keyblock.id().seq_no() < pss_block.seq_no() {
                             if visited_pss_blocks >= 4 {
                                 let gen_time = keyblock.gen_utime()? as u64;
-                                let gc_max_date = gc_max_date.as_secs();
                                 if gen_time < gc_max_date {
                                     log::info!(
                                         "gc for archives: found block (gen time: {}, seq_no: {}), gc max date: {}",
                                         &gen_time, keyblock.id().seq_no(), &gc_max_date
                                     );
-                                    log::info!("start gc for archives..");
-                                    engine.db.archive_gc(keyblock.id()).await?;
-                                    log::info!("finish gc for archives.");
-                                    return Ok(());
+                                    return Ok(Some(keyblock));
                                 }
                             }
                         }
                     }
                     if prev_keyblock.id().seq_no() == 0 {
-                        return Ok(());
+                        return Ok(None);
                     }
                     keyblock = prev_keyblock;
                 }
@@ -2489,6 +3307,92 @@ impl Engine {
         }
     }
 
+    async fn check_watermarks_for_archives(
+        engine: &Arc<Engine>,
+        watermark_gc: &ArchivesWatermarkGc,
+        handle: &Arc<BlockHandle>,
+        counters: &GcCounters
+    ) -> Result<()> {
+        let started = std::time::Instant::now();
+        let mut stats = ArchivesGcStats { started_at: engine.now(), ..Default::default() };
+        let result = Self::watermark_gc_for_archives(
+            engine, watermark_gc, handle, counters, &mut stats
+        ).await;
+        stats.duration_ms = started.elapsed().as_millis() as u64;
+        if let Err(e) = &result {
+            stats.error = Some(e.to_string());
+        }
+        watermark_gc.set_last_run(stats);
+        result
+    }
+
+    async fn watermark_gc_for_archives(
+        engine: &Arc<Engine>,
+        watermark_gc: &ArchivesWatermarkGc,
+        handle: &Arc<BlockHandle>,
+        counters: &GcCounters,
+        stats: &mut ArchivesGcStats
+    ) -> Result<()> {
+        let usage = watermark_gc.usage()?;
+        stats.used_bytes_before = usage.used;
+        stats.used_bytes_after = usage.used;
+        stats.high_watermark_bytes = usage.high_watermark;
+        stats.low_watermark_bytes = usage.low_watermark;
+        if !usage.above_high_watermark() {
+            return Ok(())
+        }
+
+        let mc_state = engine.load_state(handle.id()).await?;
+        let Some(last_keyblock) = Self::last_key_block_handle(engine, handle.clone(), &mc_state)? else {
+            return Ok(())
+        };
+        let retention_sec = watermark_gc.config().min_retention_hours as u64 * 3600;
+        let gc_max_date = (engine.now() as u64).saturating_sub(retention_sec);
+        let floor = Self::find_archives_gc_bound(engine, &last_keyblock, &mc_state, gc_max_date).await?;
+        let Some(floor) = floor else {
+            log::warn!(
+                "archives gc: usage {} is above high watermark {}, but all archives are retained",
+                usage.used, usage.high_watermark
+            );
+            return Ok(())
+        };
+        let floor_seq_no = floor.id().seq_no();
+        stats.retention_floor_seq_no = floor_seq_no;
+
+        let mut key_blocks = BTreeSet::new();
+        if watermark_gc.config().preserve_key_block_packages {
+            let prev_blocks = &mc_state.shard_state_extra()?.prev_blocks;
+            let mut seq_no = floor_seq_no;
+            while let Some(keyblock) = prev_blocks.get_prev_key_block(seq_no)? {
+                key_blocks.insert(keyblock.seq_no);
+                if keyblock.seq_no == 0 {
+                    break
+                }
+                seq_no = keyblock.seq_no - 1;
+            }
+        }
+
+        let packages = engine.db().archive_packages().await;
+        for archive_id in watermark_gc.select_packages(&packages, floor_seq_no, &key_blocks, &usage) {
+            if engine.check_stop() {
+                break
+            }
+            match engine.db().remove_archive_package(archive_id, counters).await {
+                Ok(size) => {
+                    stats.removed_packages += 1;
+                    stats.removed_bytes += size;
+                }
+                Err(e) => log::warn!("archives gc: can't remove package {}: {}", archive_id, e)
+            }
+        }
+        stats.used_bytes_after = watermark_gc.usage()?.used;
+        log::info!(
+            "archives gc: {} packages ({} bytes) removed, usage {} -> {}",
+            stats.removed_packages, stats.removed_bytes, stats.used_bytes_before, stats.used_bytes_after
+        );
+        Ok(())
+    }
+
     fn check_finish_sync(self: Arc<Self>) {
         tokio::spawn(async move {
             const SLEEP_TIME: u64 = 30;
@@ -2623,7 +3527,8 @@ struct BootInfo {
 async fn boot(
     engine: &Arc<Engine>, 
     zerostate_path: Option<&str>, 
-    hardfork_path: impl AsRef<Path>
+    hardfork_path: impl AsRef<Path>,
+    state_rollback: bool
 ) -> Result<BootInfo> {
 
     log::info!("Booting...");
@@ -2634,7 +3539,7 @@ async fn boot(
     }
 
     let result = match engine.load_last_applied_mc_block_id() {
-        Ok(Some(id)) => crate::boot::warm_boot(engine.clone(), id, hardfork_path).await,
+        Ok(Some(id)) => crate::boot::warm_boot(engine.clone(), id, hardfork_path, state_rollback).await,
         Ok(None) => Err(error!("No last applied MC block, warm boot is not possible")),
         Err(x) => Err(x)
     };
@@ -2651,6 +3556,13 @@ async fn boot(
                 state.config_params()?.has_capability(GlobalCapabilities::CapSmft),
                 Ordering::Relaxed
             );
+            if let Err(e) = engine.ext_message_limits.refresh(state.config_params()?) {
+                log::warn!("Can't refresh external message limits: {}", e);
+            }
+            if let Err(e) = engine.ext_messages_prevalidation.refresh(state.config_params()?) {
+                log::warn!("Can't refresh prevalidation priority sources: {}", e);
+            }
+            engine.check_key_block_config(state.block_id(), state.config_params()?);
             (block_id.clone(), false)
         }
         Err(err) => {
@@ -2666,6 +3578,13 @@ async fn boot(
                 Ok(s) => s
             };
             engine.network_global_id.store(state.state()?.global_id(), Ordering::Relaxed);
+            if let Err(e) = engine.ext_message_limits.refresh(state.config_params()?) {
+                log::warn!("Can't refresh external message limits: {}", e);
+            }
+            if let Err(e) = engine.ext_messages_prevalidation.refresh(state.config_params()?) {
+                log::warn!("Can't refresh prevalidation priority sources: {}", e);
+            }
+            engine.check_key_block_config(state.block_id(), state.config_params()?);
 
             (id, true)
         }
@@ -2743,6 +3662,8 @@ pub struct EngineFlags {
     pub initial_sync_disabled: bool,
     pub starting_block_disabled: bool,
     pub force_check_db: bool,
+    // Database is opened for inspection only, see `run`
+    pub read_only: bool,
 }
 
 pub async fn run(
@@ -2764,11 +3685,15 @@ pub async fn run(
         node_config.unsafe_catchain_patches_files(),
         node_config.validation_countdown_mode(),
         node_config.is_smft_disabled(),
+        node_config.validator_session_checkpoints(),
+        node_config.strict_config_check(),
     );
     let wc_from_config = node_config.workchain();
     let remp_client_pool = node_config.remp_config().remp_client_pool();
     let configs_dir = node_config.build_config_path("");
     let sync_by_archives = node_config.sync_by_archives();
+    let state_rollback = !node_config.is_state_rollback_disabled();
+    let node_status_log_interval = node_config.node_status_log_interval_sec();
 
     // Create engine
     let engine = Engine::new(
@@ -2783,6 +3708,15 @@ pub async fn run(
 
         #[cfg(feature = "telemetry")]
         telemetry_logger(engine.clone());
+        if let Some(interval) = node_status_log_interval.filter(|interval| *interval > 0) {
+            node_status_logger(engine.clone(), interval);
+        }
+        if let Some(time_service) = engine.time_service() {
+            time_service_worker(engine.clone(), time_service.clone());
+        }
+        if let Some(key_rotation) = engine.key_rotation() {
+            key_rotation_worker(engine.clone(), key_rotation.clone());
+        }
 
         // Console service - run first to allow console to connect to generate new keys
         // while node is looking for net
@@ -2793,12 +3727,23 @@ pub async fn run(
                     DataSource::Engine(engine.clone()),
                     engine.network().config_handler(),
                     engine.network().config_handler(),
+                    engine.control_permissions().clone(),
+                    engine.control_limits().clone(),
                     Some(engine.network())
                 ).await?
             );
             engine.register_server(server)
         };
 
+        // Inspection of read-only DB: only control queries are served, no overlays, 
+        // boot, sync, GC, archiving or validation
+        if engine.flags().read_only {
+            log::info!("Database is opened read-only, node serves read-only control queries only");
+            let stopper = engine.stopper.clone();
+            let join = tokio::spawn(async move { stopper.token.cancelled().await });
+            return Ok((join, tokio::spawn(async {})))
+        }
+
         #[cfg(feature = "external_db")]
         // Messages from external DB (usually kafka)
         start_external_broadcast_process(engine.clone(), &consumer_config)?;
@@ -2840,9 +3785,10 @@ pub async fn run(
         }
 
         // Boot
-        let mut boot_info = boot(&engine, zerostate_path, configs_dir).await?;
+        let mut boot_info = boot(&engine, zerostate_path, configs_dir, state_rollback).await?;
 
         // Broadcasts (blocks, external messages etc.)
+        Engine::start_ext_messages_prevalidation(engine.clone());
         if let Some(wc) = &wc_from_config {
             Arc::clone(&engine).listen_broadcasts(
                 ShardIdent::with_tagged_prefix(*wc, SHARD_FULL)?,
@@ -2954,6 +3900,84 @@ pub async fn run(
     }
 }
 
+// Dumps node status as single line json, to be parsed by log collectors
+fn node_status_logger(engine: Arc<Engine>, interval_sec: u32) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval_sec as u64)).await;
+            if engine.check_stop() {
+                break
+            }
+            match engine.node_status().await.to_json_line() {
+                Ok(line) => log::info!(target: "node_status", "{}", line),
+                Err(e) => log::warn!("Can't serialize node status: {}", e)
+            }
+        }
+    });
+}
+
+fn time_service_worker(engine: Arc<Engine>, time_service: Arc<TimeService>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(time_service.interval()).await;
+            if engine.check_stop() {
+                break
+            }
+            time_service.update().await;
+        }
+    });
+}
+
+#[async_trait::async_trait]
+impl KeyRotationOps for Engine {
+    async fn generate_key(&self) -> Result<[u8; 32]> {
+        self.network().config_handler().generate(Ed25519KeyOption::KEY_TYPE).await
+    }
+    async fn register_keys(&self, keys: &RotationKeys, election_id: i32) -> Result<()> {
+        let config_handler = self.network().config_handler();
+        config_handler.add_validator_key(&keys.key_id, election_id).await?;
+        config_handler.add_validator_adnl_key(&keys.key_id, &keys.adnl_key_id).await
+    }
+    async fn retire_key(&self, key_id: &[u8; 32], election_id: i32) -> Result<()> {
+        self.network().config_handler().remove_validator_key(key_id, election_id).await?;
+        Ok(())
+    }
+    fn validator_keys(&self) -> Result<Vec<([u8; 32], i32)>> {
+        self.network().config_handler().get_validator_keys()
+    }
+    fn key_in_use(&self, key_id: &[u8; 32]) -> bool {
+        self.validator_sessions_history().key_in_use(key_id)
+    }
+    fn load_rotation_state(&self) -> Result<Option<Vec<u8>>> {
+        self.db().load_validator_record(KEY_ROTATION_STATE)
+    }
+    fn save_rotation_state(&self, data: &[u8]) -> Result<()> {
+        self.db().save_validator_record(KEY_ROTATION_STATE, data.to_vec())
+    }
+}
+
+fn key_rotation_worker(engine: Arc<Engine>, key_rotation: Arc<KeyRotation>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(key_rotation.interval()).await;
+            if engine.check_stop() {
+                break
+            }
+            // Election timing of a node far behind the chain is outdated
+            if engine.get_sync_status() != Engine::SYNC_STATUS_FINISH_SYNC {
+                continue
+            }
+            let result = async {
+                let timing = engine.election_timing().await?;
+                key_rotation.update(engine.deref(), &timing, engine.now()).await
+            }.await;
+            if let Err(e) = result {
+                log::warn!("Key rotation: {}", e);
+            }
+        }
+    });
+}
+
 #[cfg(feature = "telemetry")]
 fn telemetry_logger(engine: Arc<Engine>) {
     tokio::spawn(async move {
@@ -2999,6 +4023,9 @@ fn telemetry_logger(engine: Arc<Engine>) {
             engine.engine_telemetry.validator_sets.update(
                 engine.engine_allocated.validator_sets.load(Ordering::Relaxed)
             );        
+            engine.engine_telemetry.pinned_states.update(
+                engine.shard_states_keeper().pinned_states_stats().0 as u64
+            );
             engine.telemetry_printer.try_print();
             elapsed += millis;
             if elapsed < Engine::TIMEOUT_TELEMETRY_SEC * 1000 {
@@ -3033,6 +4060,11 @@ fn telemetry_logger(engine: Arc<Engine>) {
                 "Validator's telemetry:\n{}",
                 engine.validator_telemetry().report()
             );
+            log::debug!(
+                target: "telemetry",
+                "Collation timings:\n{}",
+                engine.collation_timing_telemetry().report()
+            );
             log::debug!(
                 target: "telemetry",
                 "Full node service's telemetry:\n{}",
@@ -3043,6 +4075,11 @@ fn telemetry_logger(engine: Arc<Engine>) {
                 "Full node client's telemetry:\n{}",
                 engine.network.telemetry().report(Engine::TIMEOUT_TELEMETRY_SEC)
             );
+            log::debug!(
+                target: "telemetry",
+                "Download peers scoring:\n{}",
+                engine.network.peer_scoring().report()
+            );
             log::debug!(
                 target: "telemetry",
                 "Full node neighbours's telemetry:",
@@ -12,37 +12,55 @@
 */
 
 use crate::{
+    applied_blocks::{AppliedBlockStream, ShardFilter}, archives_gc::ArchivesGcStats,
     block::{BlockKind, BlockStuff}, 
-    block_proof::BlockProofStuff, config::{CollatorConfig, CollatorTestBundlesGeneralConfig},
-    engine::{Engine, EngineFlags}, 
+    block_proof::{build_proof_chain, BlockProofStuff, BlockProvenance}, 
+    collator_test_bundle::{CollatorTestBundle, ConfigPatch, DryRunReport, ReplayOutcome},
+    config::{CollatorConfig, CollatorTestBundlesGeneralConfig, TonNodeConfig},
+    config_check::ConfigCheckResult, config_reload::ReloadReport, engine::{now_duration, Engine, EngineFlags, ExtMessageTask, Stopper},
     engine_traits::{
-        EngineAlloc, EngineOperations, PrivateOverlayOperations, RempCoreInterface, 
-        RempDuplicateStatus, Server
+        BlockAccess, BroadcastSupport, EngineAlloc, EngineOperations, PrivateOverlayOperations, 
+        RempCoreInterface, RempDuplicateStatus, RempSupport, Server, StateAccess, ValidatorSupport
     }, 
     error::NodeError, 
-    ext_messages::{create_ext_message, EXT_MESSAGES_TRACE_TARGET}, 
+    ext_messages::{
+        create_ext_message, create_ext_message_with_time_check,
+        limits::ExtMessageLimits, prevalidation::{PrevalidationLane, PrevalidationStats},
+        rate_limiter::ExtMessagesRateLimiter, EXT_MESSAGES_TRACE_TARGET
+    }, 
+    full_node::{apply_throttle::ApplyThrottle, state_helper::finish_state_download},
+    network::{
+        block_range::{BlockRangeQuery, DownloadedBlockRange}, neighbours_quality::NeighbourQuality
+    },
+    network_snapshot::{NetworkSnapshot, SignedSnapshot},
     internal_db::{
+        consistency::{ConsistencyReport, RepairMode}, persistent_state_reader::PersistentStateReader,
+        storage_usage::StorageUsageReport,
         BlockResult, INITIAL_MC_BLOCK, LAST_APPLIED_MC_BLOCK, LAST_MESH_HARDFORK_BLOCK, 
         LAST_MESH_KEYBLOCK, LAST_MESH_MC_BLOCK, LAST_ROTATION_MC_BLOCK, SHARD_CLIENT_MC_BLOCK
     }, 
-    jaeger,
-    shard_state::ShardStateStuff,
-    shard_states_keeper::PinnedShardStateGuard,
+    jaeger, manual_gc::{GcKind, GcProgress, GcTicket},
+    mesh_queues_keeper::{MeshQueueUpdate, MeshQueueUpdateResult, PruneStats, QueueLag},
+    node_status::NodeStatus,
+    shard_state::{AccountStateProof, ShardStateStuff},
+    shard_states_keeper::{PinGuard, PinnedShardStateGuard}, time_service::TimeService,
     types::top_block_descr::{TopBlockDescrId, TopBlockDescrStuff},
     validator::{
-        validator_manager::ValidationStatus,
+        key_rotation::{RotationKeys, RotationPlan},
+        message_cache::{MessageCacheSnapshot, MessageTraceEvent}, validator_manager::ValidationStatus,
+        validator_schedule::{ValidatorSchedule, ValidatorSessionsHistory},
         validator_utils::validatordescr_to_catchain_node,
     }
 };
 #[cfg(feature = "external_db")]
-use crate::engine_traits::ChainRange;
+use crate::{engine_traits::ChainRange, external_db::remp_history::RempHistoryExporter};
 #[cfg(feature = "slashing")]
 use crate::validator::slashing::ValidatedBlockStat;
 #[cfg(feature = "telemetry")]
 use crate::{
     engine_traits::EngineTelemetry, full_node::telemetry::{FullNodeTelemetry, RempClientTelemetry}, 
     network::telemetry::FullNodeNetworkTelemetry, 
-    validator::telemetry::{CollatorValidatorTelemetry, RempCoreTelemetry},
+    validator::telemetry::{CollationTimingTelemetry, CollatorValidatorTelemetry, RempCoreTelemetry},
 };
 #[cfg(feature = "external_db")]
 use crate::internal_db::EXTERNAL_DB_BLOCK;
@@ -51,8 +69,14 @@ use adnl::{BroadcastSendInfo, PrivateOverlayShortId};
 use catchain::{
     CatchainNode, CatchainOverlay, CatchainOverlayListenerPtr, CatchainOverlayLogReplayListenerPtr
 };
-use std::{collections::HashSet, ops::Deref, sync::Arc};
-use storage::block_handle_db::BlockHandle;
+use std::{collections::HashSet, ops::Deref, sync::Arc, time::Duration};
+use storage::{
+    account_tx_index::{TxContinuation, TxLocation}, archives::mapped_packages::ArchiveSliceData,
+    block_handle_db::{BlockHandle, NodeStateEntry}, error::StorageError,
+    remp_messages_db::RempMessagesDb
+};
+#[cfg(feature = "telemetry")]
+use storage::block_handle_db::BlockOrigin;
 use ton_api::{
     serialize_boxed, 
     ton::ton_node::{
@@ -61,8 +85,8 @@ use ton_api::{
     }, IntoBoxed
 };
 use ever_block::{
-    AccountIdPrefixFull, BlockIdExt, CellsFactory, GlobalCapabilities, Message, OutMsgQueue,
-    OutMsgQueueInfo, ShardIdent, MASTERCHAIN_ID, SHARD_FULL
+    AccountId, AccountIdPrefixFull, BlockIdExt, CellsFactory, Deserializable, GlobalCapabilities,
+    Message, OutMsgQueue, OutMsgQueueInfo, ShardIdent, MASTERCHAIN_ID, SHARD_FULL
 };
 use ever_block::{error, fail, KeyId, KeyOption, Result, UInt256};
 use validator_session::{BlockHash, SessionId, ValidatorBlockCandidate};
@@ -92,30 +116,6 @@ impl EngineOperations for Engine {
         }
     }
 
-    fn get_validator_status(&self) -> bool {
-        self.network.config_handler().get_validator_status()
-    }
-
-    fn validator_network(&self) -> Arc<dyn PrivateOverlayOperations> {
-        Engine::validator_network(self)
-    }
-
-    async fn set_validator_list(
-        &self, 
-        validator_list_id: UInt256,
-        validators: &Vec<CatchainNode>
-    ) -> Result<Option<Arc<dyn KeyOption>>> {
-        self.validator_network().set_validator_list(validator_list_id, validators).await
-    }
-
-    fn activate_validator_list(&self, validator_list_id: UInt256) -> Result<()> {
-        self.network().activate_validator_list(validator_list_id)
-    }
-
-    async fn get_validator_bls_key(&self, key_id: &Arc<KeyId>) -> Option<Arc<dyn KeyOption>> {
-        self.network().get_validator_bls_key(key_id).await
-    }
-
     fn set_sync_status(&self, status: u32) {
         self.set_sync_status(status);
     }
@@ -128,65 +128,8 @@ impl EngineOperations for Engine {
         self.calc_overlay_id(workchain, shard)
     }
 
-    fn validation_status(&self) -> ValidationStatus {
-        self.validation_status()
-    }
-
-    fn set_validation_status(&self, status: ValidationStatus) {
-        self.set_validation_status(status)
-    }
-
-    fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
-        self.last_validation_time()
-    }
-
-    fn set_last_validation_time(&self, shard: ShardIdent, time: u64) {
-        self.set_last_validation_time(shard, time)
-    }
-
-    fn remove_last_validation_time(&self, shard: &ShardIdent) {
-        self.remove_last_validation_time(shard)
-    }
-
-    fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
-        self.last_collation_time()
-    }
-
-    fn set_last_collation_time(&self, shard: ShardIdent, time: u64) {
-        self.set_last_collation_time(shard, time)
-    }
-    
-    fn remove_last_collation_time(&self, shard: &ShardIdent) {
-        self.remove_last_collation_time(shard)
-    }
-
-    async fn remove_validator_list(&self, validator_list_id: UInt256) -> Result<bool> {
-        self.validator_network().remove_validator_list(validator_list_id).await
-    }
-
-    fn create_catchain_client(
-        &self,
-        validator_list_id: UInt256,
-        overlay_short_id : &Arc<PrivateOverlayShortId>,
-        nodes_public_keys : &Vec<CatchainNode>,
-        listener : CatchainOverlayListenerPtr,
-        _log_replay_listener: CatchainOverlayLogReplayListenerPtr
-    ) -> Result<Arc<dyn CatchainOverlay + Send>> {
-        self.validator_network().create_catchain_client(
-            validator_list_id,
-            overlay_short_id,
-            nodes_public_keys,
-            listener,
-            _log_replay_listener
-        )
-    }
-
-    fn stop_catchain_client(&self, overlay_short_id: &Arc<PrivateOverlayShortId>) {
-        self.validator_network().stop_catchain_client(overlay_short_id)
-    }
-
-    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
-        self.db().load_block_handle(id)
+    fn load_mesh_block_handle(&self, nw_id: i32, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        self.db().load_mesh_block_handle(nw_id, id)
     }
 
     async fn load_applied_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
@@ -194,16 +137,12 @@ impl EngineOperations for Engine {
         if handle.is_applied() {
             self.load_block(handle).await
         } else if handle.has_data() {
-            fail!("Block is not applied yet")
+            fail!(NodeError::NotYetApplied("Block is not applied yet".to_string()))
         } else {
-            fail!("No block")
+            fail!(NodeError::NotFound("No block".to_string()))
         }
     }
 
-    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
-        self.db().load_block_data(handle).await
-    }
-
     async fn load_block_raw(&self, handle: &BlockHandle) -> Result<Vec<u8>> {
         self.db().load_block_data_raw(handle).await
     }
@@ -224,7 +163,12 @@ impl EngineOperations for Engine {
                 }
             }
 
-            self.block_applying_awaiters().wait(id, timeout_ms, &is_applied).await?;
+            self.block_applying_awaiters().wait(id, timeout_ms, &is_applied).await
+                .map_err(|e| match e.downcast_ref::<NodeError>() {
+                    // Block is still expected, it just hasn't been applied in time
+                    Some(NodeError::NetworkTimeout(msg)) => error!(NodeError::NotYetApplied(msg.clone())),
+                    _ => e
+                })?;
         }
     }
 
@@ -261,6 +205,30 @@ impl EngineOperations for Engine {
         )
     }
 
+    async fn find_mc_block_by_utime(&self, utime: u32) -> Result<Option<Arc<BlockHandle>>> {
+        let Some(seqno) = self.db().find_mc_seq_no_by_utime(utime)? else {
+            return Ok(None)
+        };
+        // Index may already have the block which is being applied now
+        let last_state = self.load_last_applied_mc_state().await?;
+        let id = last_state.find_block_id(seqno.min(last_state.seq_no()))?;
+        Ok(Some(self.load_block_handle(&id)?.ok_or_else(
+            || error!("Cannot load handle for master block {}", id)
+        )?))
+    }
+
+    async fn mc_blocks_in_utime_range(&self, from: u32, to: u32) -> Result<Vec<BlockIdExt>> {
+        let last_state = self.load_last_applied_mc_state().await?;
+        let mut ids = Vec::new();
+        for seqno in self.db().mc_seq_nos_in_utime_range(from, to)? {
+            if seqno > last_state.seq_no() {
+                break
+            }
+            ids.push(last_state.find_block_id(seqno)?);
+        }
+        Ok(ids)
+    }
+
     fn find_full_block_id(&self, root_hash: &UInt256) -> Result<Option<BlockIdExt>> {
         self.db().find_full_block_id(root_hash)
     }
@@ -277,15 +245,6 @@ impl EngineOperations for Engine {
         }
     }
 
-    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
-        match self.load_last_applied_mc_block_id()? {
-            Some(block_id) => {
-                self.load_state(&block_id).await
-            }
-            None => fail!("INTERNAL ERROR: No last applied MC block set")
-        }
-    }
-
     fn load_last_applied_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
         self.db().load_full_node_state(LAST_APPLIED_MC_BLOCK)
     }
@@ -325,6 +284,18 @@ impl EngineOperations for Engine {
         self.db().drop_validator_state(LAST_ROTATION_MC_BLOCK)
     }
 
+    fn save_session_checkpoint(&self, session_id: &SessionId, data: Vec<u8>) -> Result<()> {
+        self.db().save_session_checkpoint(session_id, data)
+    }
+
+    fn load_session_checkpoint(&self, session_id: &SessionId) -> Result<Option<Vec<u8>>> {
+        self.db().load_session_checkpoint(session_id)
+    }
+
+    fn drop_stale_session_checkpoints(&self, active: &HashSet<SessionId>) -> Result<usize> {
+        self.db().drop_stale_session_checkpoints(active)
+    }
+
     fn save_block_candidate(
         &self, 
         session_id: &SessionId, 
@@ -444,7 +415,6 @@ impl EngineOperations for Engine {
         attempts: Option<usize>
     ) -> Result<Arc<ShardStateStuff>> {
 
-
         let (is_foreign_block, own_wc) = self.is_foreign_wc(handle.id().shard().workchain_id()).await?;
         let (queue_for_wc, overlay_wc) = if is_foreign_block {
             (Some(own_wc), own_wc)
@@ -453,6 +423,7 @@ impl EngineOperations for Engine {
         };
 
         let overlay = self.get_full_node_overlay(0, overlay_wc, SHARD_FULL).await?;
+        let download_dir = self.db().state_downloads_dir();
 
         let data = crate::full_node::state_helper::download_persistent_state(
             handle.id(),
@@ -462,18 +433,16 @@ impl EngineOperations for Engine {
             active_peers,
             bad_peers,
             attempts,
-            &|| {
-                if self.check_stop() {
-                    fail!("Persistent state downloading was stopped")
-                }
-                Ok(())
-            }
+            self.db().deref(),
+            &download_dir,
+            self.stopper()
         ).await?;
 
-        let state = self.shard_states_keeper().check_and_store_state(
-            handle, root_hash, data).await?;
-
-        Ok(state)
+        let result = self.shard_states_keeper().check_and_store_state(
+            handle, root_hash, data).await;
+        // Broken state is downloaded again from zero
+        finish_state_download(self.db().deref(), &download_dir, handle.id(), queue_for_wc)?;
+        result
     }
 
     async fn download_zerostate(
@@ -558,26 +527,130 @@ impl EngineOperations for Engine {
     ) -> Result<BlockProofStuff> {
         // TODO make cache?
         self.db().load_block_proof(handle, is_link).await
+            .map_err(|e| error!(NodeError::classify(e, NodeError::DbCorruption)))
     }
 
     async fn load_block_proof_raw(&self, handle: &BlockHandle, is_link: bool) -> Result<Vec<u8>> {
         self.db().load_block_proof_raw(handle, is_link).await
     }
 
+    async fn build_proof_chain(&self, id: &BlockIdExt) -> Result<Vec<Vec<u8>>> {
+        build_proof_chain(
+            self, 
+            id, 
+            self.init_mc_block_id().seq_no(), 
+            self.proof_chain_max_length()
+        ).await
+    }
+
     async fn load_mc_zero_state(&self) -> Result<Arc<ShardStateStuff>> {
         let block_id = self.zero_state_id();
         self.load_state(block_id).await
     }
 
-    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
-        self.shard_states_keeper().load_state(block_id).await
-    }
-
     // It is prohibited to use any cell from the state after the guard's disposal.
     async fn load_and_pin_state(&self, block_id: &BlockIdExt) -> Result<PinnedShardStateGuard> {
         self.shard_states_keeper().load_and_pin_state(block_id).await
     }
 
+    fn pin_state(&self, block_id: &BlockIdExt) -> Result<PinGuard> {
+        self.shard_states_keeper().pin_state(block_id)
+    }
+
+    fn pinned_states_stats(&self) -> Result<(usize, u32)> {
+        Ok(self.shard_states_keeper().pinned_states_stats())
+    }
+
+    async fn generate_account_proof(
+        &self,
+        mc_block_id: &BlockIdExt,
+        account: &AccountIdPrefixFull
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        if !mc_block_id.shard().is_masterchain() {
+            fail!(NodeError::InvalidArg(format!("Block {} is not a masterchain one", mc_block_id)))
+        }
+        let mc_state = load_and_pin_available_state(self, mc_block_id).await?;
+        let block_id = if account.workchain_id == MASTERCHAIN_ID {
+            mc_block_id.clone()
+        } else {
+            mc_state.state().shards()?.find_shard_by_prefix(account)?
+                .ok_or_else(|| error!("Can't find shard for {} in state {}", account, mc_block_id))?
+                .block_id
+        };
+        let (account_id, proof) = if block_id == *mc_block_id {
+            mc_state.state().account_proof(account)?
+        } else {
+            load_and_pin_available_state(self, &block_id).await?.state().account_proof(account)?
+        };
+        log::trace!("generate_account_proof: built proof of {:x} in {}", account_id, block_id);
+        let handle = self.load_block_handle(&block_id)?
+            .ok_or_else(|| error!("Cannot load handle for block {}", block_id))?;
+        let mut is_link = false;
+        if !handle.has_proof_or_link(&mut is_link) {
+            fail!("Block {} has no proof or proof link", block_id)
+        }
+        let block_proof = self.load_block_proof_raw(&handle, is_link).await?;
+        Ok((proof, block_proof))
+    }
+
+    async fn generate_account_state_proof(
+        &self,
+        workchain_id: i32,
+        account_id: &AccountId
+    ) -> Result<AccountStateProof> {
+        let mc_block_id = if workchain_id == MASTERCHAIN_ID {
+            self.load_last_applied_mc_block_id()?
+                .ok_or_else(|| error!("Cannot load last applied mc block id"))?
+        } else {
+            self.load_shard_client_mc_block_id()?
+                .ok_or_else(|| error!("Cannot load shard client mc block id"))?
+        };
+        let mc_state = load_and_pin_available_state(self, &mc_block_id).await?;
+        let state = if workchain_id == MASTERCHAIN_ID {
+            mc_state
+        } else {
+            let mut block_id = None;
+            for id in mc_state.state().top_blocks(workchain_id)? {
+                if id.shard().contains_account(account_id.clone())? {
+                    block_id = Some(id);
+                    break
+                }
+            }
+            let block_id = block_id.ok_or_else(
+                || error!("Cannot find shard of account {}:{:x}", workchain_id, account_id)
+            )?;
+            load_and_pin_available_state(self, &block_id).await?
+        };
+        let (exists, state_proof) = state.state().account_state_proof(
+            account_id, self.account_state_proof_max_size()
+        )?;
+        let block_id = state.state().block_id().clone();
+        Ok(AccountStateProof {
+            proof_chain: self.build_proof_chain(&block_id).await?,
+            block_id,
+            state_root_hash: state.state().root_cell().repr_hash(),
+            exists,
+            state_proof,
+        })
+    }
+
+    fn account_transactions(
+        &self,
+        workchain_id: i32,
+        account_id: &AccountId,
+        from_seq_no: u32,
+        to_seq_no: u32,
+        limit: usize,
+        continuation: Option<TxContinuation>
+    ) -> Result<(Vec<TxLocation>, Option<TxContinuation>)> {
+        let account = UInt256::construct_from(&mut account_id.clone())?;
+        self.db().account_transactions(workchain_id, &account, from_seq_no, to_seq_no, limit, continuation)
+    }
+
+    async fn load_account_transaction(&self, location: &TxLocation) -> Result<Vec<u8>> {
+        self.db().load_account_transaction(location).await
+    }
+
     async fn load_persistent_state_size(&self, block_id: &BlockIdExt) -> Result<u64> {
         self.db().load_shard_state_persistent_size(block_id).await
     }
@@ -591,33 +664,12 @@ impl EngineOperations for Engine {
         self.db().load_shard_state_persistent_slice(handle.id(), offset, length).await
     }
 
-    async fn wait_state(
-        self: Arc<Self>,
-        id: &BlockIdExt,
-        timeout_ms: Option<u64>,
-        allow_block_downloading: bool
-    ) -> Result<Arc<ShardStateStuff>> {
-        loop {
-            let has_state = || {
-                Ok(self.load_block_handle(id)?.map(|h| h.has_state()).unwrap_or(false))
-            };
-
-            if has_state()? {
-                break self.load_state(id).await
-            }
-            let id1 = id.clone();
-            let engine = self.clone();
-            if allow_block_downloading {
-                tokio::spawn(async move {
-                    if let Err(e) = engine.download_and_apply_block(&id1, 0, true).await {
-                        log::error!("Error while pre-apply block (while wait_state) {}: {:?}", id1, e);
-                    }
-                });
-            }
-            if let Some(ss) = self.shard_states_awaiters().wait(id, timeout_ms, &has_state).await? {
-                break Ok(ss)
-            }
-        }
+    async fn open_persistent_state_reader(
+        &self,
+        handle: &Arc<BlockHandle>
+    ) -> Result<PersistentStateReader> {
+        let reader = self.db().open_persistent_state_reader(handle).await?;
+        Ok(reader.with_chunk_size(self.persistent_state_chunk_size()))
     }
 
     async fn store_state(
@@ -629,7 +681,9 @@ impl EngineOperations for Engine {
             self.shard_states_keeper().store_state(handle, state, None, false).await?;
         if saved {
             #[cfg(feature = "telemetry")]
-            self.full_node_telemetry().new_pre_applied_block(handle.got_by_broadcast());
+            self.full_node_telemetry().new_pre_applied_block(
+                matches!(handle.origin(), BlockOrigin::Broadcast | BlockOrigin::Synthesized)
+            );
         }
         self.shard_states_awaiters().do_or_wait(
             state.block_id(),
@@ -667,32 +721,20 @@ impl EngineOperations for Engine {
         self.db().store_block_prev1(handle, prev, None)
     }
 
-    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
-        self.db().load_block_prev1(id)
-    }
-
     fn store_block_prev2(&self, handle: &Arc<BlockHandle>, prev2: &BlockIdExt) -> Result<()> {
         self.db().store_block_prev2(handle, prev2, None)
     }
 
-    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
-        self.db().load_block_prev2(id)
-    }
-
     fn store_block_next1(&self, handle: &Arc<BlockHandle>, next: &BlockIdExt) -> Result<()> {
         self.db().store_block_next1(handle, next, None)
     }
 
-    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
-        self.db().load_block_next1(id)
-    }
-
     fn store_block_next2(&self, handle: &Arc<BlockHandle>, next2: &BlockIdExt) -> Result<()> {
         self.db().store_block_next2(handle, next2, None)
     }
 
-    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
-        self.db().load_block_next2(id)
+    fn store_queue_update_verified(&self, handle: &Arc<BlockHandle>) -> Result<bool> {
+        self.db().store_queue_update_verified(handle)
     }
 
     #[cfg(feature = "external_db")]
@@ -802,22 +844,19 @@ impl EngineOperations for Engine {
         handle: &Arc<BlockHandle>, 
         mc_seq_no: u32
     ) -> Result<bool> {
-        if handle.is_applied() {
-            return Ok(false);
-        }
-        if !handle.is_mesh() {
-            self.db().assign_mc_ref_seq_no(handle, mc_seq_no, None)?;
-            if handle.id().seq_no() != 0 {
-                self.db().archive_block(handle.id(), None).await?;
-            }
-        }
-        if self.db().store_block_applied(handle, None)? {
-            #[cfg(feature = "telemetry")]
-            self.full_node_telemetry().new_applied_block();
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+        self.set_applied_with_block(handle, mc_seq_no, None).await
+    }
+
+    fn subscribe_applied_blocks(&self, filter: ShardFilter) -> Result<AppliedBlockStream> {
+        self.applied_blocks_notifier().subscribe(filter)
+    }
+
+    fn trigger_gc(self: Arc<Self>, kind: GcKind) -> Result<GcTicket> {
+        Ok(self.start_manual_gc(kind))
+    }
+
+    fn gc_status(&self, ticket: &GcTicket) -> Result<GcProgress> {
+        self.manual_gc().status(ticket)
     }
 
     fn hardforks(&self) -> &[BlockIdExt] {
@@ -836,174 +875,54 @@ impl EngineOperations for Engine {
         self.db().save_full_node_state(INITIAL_MC_BLOCK, id)
     }
 
-    async fn broadcast_to_public_overlay(
+    fn trusted_key_block(&self) -> Option<&BlockIdExt> {
+        (self as &Engine).trusted_key_block()
+    }
+
+    async fn get_archive_id(&self, mc_seq_no: u32) -> Option<u64> {
+        self.db().get_archive_id(mc_seq_no).await
+    }
+
+    async fn get_archive_slice(&self, archive_id: u64, offset: u64, limit: u32) -> Result<ArchiveSliceData> {
+        self.db().get_archive_slice(archive_id, offset, limit).await
+    }
+
+    async fn download_archive(
         &self, 
-        to: &AccountIdPrefixFull, 
-        data: &[u8]
-    ) -> Result<BroadcastSendInfo> {
-        let overlay = self.get_full_node_overlay(0, to.workchain_id, to.prefix).await?;
-        overlay.broadcast_external_message(data).await
+        masterchain_seqno: u32,
+        active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>
+    ) -> Result<Option<Vec<u8>>> {
+        let client = self.get_masterchain_overlay().await?;
+        client.download_archive(masterchain_seqno, active_peers).await
     }
 
-    async fn redirect_external_message(&self, message_data: &[u8], id: UInt256) -> Result<()> {
-        if !self.check_sync().await? {
-            fail!("Can't process external message because node is out of sync");
-        }
+    async fn download_block_range(&self, query: &BlockRangeQuery) -> Result<DownloadedBlockRange> {
+        let client = self.get_masterchain_overlay().await?;
+        client.download_block_range(query).await
+    }
 
-        let remp_way = self.remp_capability() && self.remp_client().is_some();
-        if remp_way {
-            self.remp_client()
-                .ok_or_else(|| error!("redirect_external_message: remp client is not set"))?
-                .clone()
-                .process_remp_message(message_data.into(), id.clone());
-            log::debug!(
-                target: EXT_MESSAGES_TRACE_TARGET,
-                "Redirected external message {:x} to REMP",
-                id,
-            );
-            Ok(())
-        } else {
-            match create_ext_message(message_data) {
-                Err(e) => {
-                    let err = format!(
-                        "Can't deserialize external message with len {}: {}",
-                        message_data.len(), e,
-                    );
-                    log::warn!(target: EXT_MESSAGES_TRACE_TARGET, "{}", &err);
-                    fail!("{}", err);
-                }
-                Ok((id, message)) => {
-                    match redirect_external_message(self, message, id.clone(), message_data).await {
-                        Err(e) => {
-                            let err = format!(
-                                "Can't redirect external message {:x}: {}",
-                                id, e,
-                            );
-                            log::error!(target: EXT_MESSAGES_TRACE_TARGET, "{}", &err);
-                            fail!("{}", err);
-                        }
-                        Ok(info) => {
-                            log::debug!(
-                                target: EXT_MESSAGES_TRACE_TARGET,
-                                "Redirected external message {:x} to {} nodes by {} packages",
-                                id, info.send_to, info.packets,
-                            );
-                            return Ok(());
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    async fn get_archive_id(&self, mc_seq_no: u32) -> Option<u64> {
-        self.db().get_archive_id(mc_seq_no).await
-    }
-
-    async fn get_archive_slice(&self, archive_id: u64, offset: u64, limit: u32) -> Result<Vec<u8>> {
-        self.db().get_archive_slice(archive_id, offset, limit).await
+    fn last_known_mc_block_seqno(&self) -> u32 {
+        self.last_known_mc_block_seqno()
     }
 
-    async fn download_archive(
-        &self, 
-        masterchain_seqno: u32,
-        active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>
-    ) -> Result<Option<Vec<u8>>> {
-        let client = self.get_masterchain_overlay().await?;
-        client.download_archive(masterchain_seqno, active_peers).await
+    fn sync_download_concurrency(&self) -> usize {
+        self.sync_download_concurrency()
     }
 
-    async fn send_block_broadcast(&self, broadcast: BlockBroadcast) -> Result<()> {
-        let mut target_wcs = vec!();
-
-        // If Wc2WcQueueUpdates enabled - send master block to all WCs
-        if broadcast.id.shard().is_masterchain() {
-            let mc_state = self.load_last_applied_mc_state().await?;
-            if mc_state.config_params()?.has_capability(GlobalCapabilities::CapWorkchains) {
-                mc_state.shard_state_extra()?.shards().iterate_with_keys(|workchain_id: i32, _| {
-                    target_wcs.push(workchain_id);
-                    Ok(true)
-                })?;
-                target_wcs.push(MASTERCHAIN_ID);
-            } else {
-                target_wcs.push(broadcast.id.shard().workchain_id());
-            }
-        } else {
-            target_wcs.push(broadcast.id.shard().workchain_id());
-        }
-
-        for wc in target_wcs {
-            log::trace!("send_block_broadcast {} to {}", broadcast.id, wc);
-            let overlay = self.get_full_node_overlay(0, wc, SHARD_FULL).await?;
-            overlay.send_block_broadcast(broadcast.clone()).await?;
-        }
-
-        #[cfg(feature = "telemetry")]
-        self.full_node_telemetry().sent_block_broadcast();
-
-        Ok(())
+    fn sync_max_downloaded_archives(&self) -> usize {
+        self.sync_max_downloaded_archives()
     }
 
-    async fn send_queue_update_broadcast(&self, broadcast: QueueUpdateBroadcast) -> Result<()> {
-        // TODO select right overlay
-        let overlay = self.get_full_node_overlay(
-            0,
-            broadcast.target_wc,
-            SHARD_FULL, //broadcast.id.shard as u64
-        ).await?;
-        
-        log::trace!("send_queue_update_broadcast {} to {}", broadcast.id, broadcast.target_wc);
-        overlay.send_queue_update_broadcast(broadcast).await?;
-        #[cfg(feature = "telemetry")]
-        self.full_node_telemetry().sent_block_broadcast(); // TODO
-        Ok(())
+    fn sync_proof_check_threads(&self) -> usize {
+        self.sync_proof_check_threads()
     }
 
-    async fn send_mesh_update_broadcast(&self, broadcast: MeshUpdateBroadcast) -> Result<()> {
-        let overlay = self.get_full_node_overlay(
-            broadcast.target_nw,
-            MASTERCHAIN_ID,
-            SHARD_FULL,
-        ).await?;
-        
-        log::trace!("send_mesh_update_broadcast {} to {}", broadcast.id, broadcast.target_nw);
-        overlay.send_mesh_update_broadcast(broadcast).await?;
-        #[cfg(feature = "telemetry")]
-        self.full_node_telemetry().sent_block_broadcast(); // TODO
-        Ok(())
+    fn block_compression_level(&self) -> i32 {
+        self.block_compression_level()
     }
 
-    async fn send_top_shard_block_description(
-        &self,
-        tbd: Arc<TopBlockDescrStuff>,
-        cc_seqno: u32,
-        is_resend: bool,
-    ) -> Result<()> {
-
-        if !is_resend {
-            let id = tbd.proof_for();
-            if let Err(e) = self.shard_blocks().process_shard_block(
-                id, cc_seqno, || Ok(tbd.clone()), false, self).await {
-                log::error!("Can't add own shard top block {}: {}", id, e);
-            }
-        }
-
-        let mut target_wcs = vec!();
-        if self.load_actual_config_params().await?.has_capability(GlobalCapabilities::CapWorkchains) {
-            target_wcs.push(tbd.proof_for().shard().workchain_id());
-            target_wcs.push(MASTERCHAIN_ID);
-        } else {
-            target_wcs.push(MASTERCHAIN_ID);
-        }
-
-        for wc in target_wcs {
-            let overlay = self.get_full_node_overlay(0, wc, SHARD_FULL).await?;
-            overlay.send_top_shard_block_description(&tbd).await?;
-        }
-
-        #[cfg(feature = "telemetry")]
-        self.full_node_telemetry().sent_top_block_broadcast();
-        Ok(())
+    fn peer_has_capability(&self, peer: &Arc<KeyId>, capability: i64) -> bool {
+        self.network().peer_has_capability(peer, capability)
     }
 
     async fn check_sync(&self) -> Result<bool> {
@@ -1047,29 +966,19 @@ impl EngineOperations for Engine {
         self.external_messages().complete_messages(to_delay, to_delete, self.now())
     }
 
-    // Remp messages
-    async fn check_remp_duplicate(&self, message_id: &UInt256) -> Result<RempDuplicateStatus> {
-        self.remp_service()
-            .ok_or_else(|| error!("Can't get message status because remp service was not set"))?
-            .check_remp_duplicate(message_id)
-    }
-
-    async fn push_message_to_remp(&self, data: ton_api::ton::bytes) -> Result<()> {
-        let (id, _message) = create_ext_message(&data)?;
-        let remp_message = ton_api::ton::ton_node::rempmessage::RempMessage {
-            message: data,
-            id: id.clone(),
-            timestamp: 0,
-            signature: Vec::new().into()
-        }.into_boxed();
-        let zero_source = Arc::new(KeyId::from_data([0; 32]));
-        self.network().remp().messages_subscriber()?.new_remp_message(
-            remp_message, &zero_source).await?;
-        Ok(())
+    // Adjusted by estimated drift of the local clock if time service is configured
+    fn now(&self) -> u32 {
+        match self.time_service() {
+            Some(time_service) => time_service.adjusted_now(),
+            None => now_duration().as_secs() as u32
+        }
     }
 
-    fn remp_capability(&self) -> bool {
-        Engine::remp_capability(self)
+    fn now_ms(&self) -> u64 {
+        match self.time_service() {
+            Some(time_service) => time_service.adjusted_now_ms(),
+            None => now_duration().as_millis() as u64
+        }
     }
 
     fn smft_capability(&self) -> bool {
@@ -1111,6 +1020,24 @@ impl EngineOperations for Engine {
         Engine::collator_config(self)
     }
 
+    async fn collate_dry_run(
+        self: Arc<Self>,
+        shard: ShardIdent,
+        config_patch: ConfigPatch
+    ) -> Result<DryRunReport> {
+        let engine = self as Arc<dyn EngineOperations>;
+        CollatorTestBundle::collate_dry_run(&engine, shard, config_patch).await
+    }
+
+    async fn replay_block_validation(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        bundle_path: Option<&str>
+    ) -> Result<ReplayOutcome> {
+        let engine = self as Arc<dyn EngineOperations>;
+        CollatorTestBundle::replay_block_validation(&engine, id, bundle_path).await
+    }
+
     fn db_root_dir(&self) -> Result<&str> {
         self.db().db_root_dir()
     }
@@ -1130,11 +1057,6 @@ impl EngineOperations for Engine {
         Engine::full_node_telemetry(self)
     }
 
-    #[cfg(feature = "telemetry")]
-    fn remp_core_telemetry(&self) -> &RempCoreTelemetry {
-        Engine::remp_core_telemetry(self)
-    }
-
     #[cfg(feature = "telemetry")]
     fn collator_telemetry(&self) -> &CollatorValidatorTelemetry {
         Engine::collator_telemetry(self)
@@ -1145,6 +1067,11 @@ impl EngineOperations for Engine {
         Engine::validator_telemetry(self)
     }
 
+    #[cfg(feature = "telemetry")]
+    fn collation_timing_telemetry(&self) -> &CollationTimingTelemetry {
+        Engine::collation_timing_telemetry(self)
+    }
+
     #[cfg(feature = "telemetry")]
     fn full_node_service_telemetry(&self) -> &FullNodeNetworkTelemetry {
         Engine::full_node_service_telemetry(self)
@@ -1182,6 +1109,85 @@ impl EngineOperations for Engine {
         self.db().adjust_states_gc_interval(interval_ms)
     }
 
+    async fn check_db_consistency(&self, mode: RepairMode) -> Result<ConsistencyReport> {
+        self.db().check_and_repair_consistency(mode).await
+    }
+
+    async fn storage_usage_report(&self) -> Result<StorageUsageReport> {
+        self.db().storage_usage_report().await
+    }
+
+    async fn node_status(&self) -> NodeStatus {
+        Engine::node_status(self).await
+    }
+
+    async fn network_snapshot(&self) -> Result<SignedSnapshot> {
+        let mc_state = self.load_last_applied_mc_state().await?;
+        let mc_seq_no = mc_state.block_id().seq_no();
+        if let Some(snapshot) = self.network_snapshot_cache().get(mc_seq_no) {
+            return Ok(snapshot)
+        }
+        // Shard blocks pool may be behind the applied masterchain, pending blocks are optional
+        let pending_blocks = match self.get_shard_blocks(&mc_state, None).await {
+            Ok(blocks) => blocks.iter().map(|tbd| tbd.proof_for().clone()).collect(),
+            Err(e) => {
+                log::debug!("Network snapshot is built without pending shard blocks: {}", e);
+                Vec::new()
+            }
+        };
+        let snapshot = NetworkSnapshot::from_mc_state(&mc_state, &pending_blocks, self.now())?;
+        let snapshot = SignedSnapshot::sign(&snapshot, &self.network().public_overlay_key()?)?;
+        self.network_snapshot_cache().put(mc_seq_no, snapshot.clone());
+        Ok(snapshot)
+    }
+
+    fn archives_gc_stats(&self) -> Result<Option<ArchivesGcStats>> {
+        Engine::archives_gc_stats(self)
+    }
+
+    fn neighbours_quality(&self) -> Vec<(Arc<adnl::OverlayShortId>, Arc<KeyId>, NeighbourQuality)> {
+        self.network().neighbours_quality_table()
+    }
+
+    async fn block_provenance(&self, id: &BlockIdExt) -> Result<Option<BlockProvenance>> {
+        self.db().load_block_provenance(id).await
+    }
+
+    fn ext_messages_prevalidation_stats(
+        &self
+    ) -> (PrevalidationStats, Vec<(PrevalidationLane, Arc<KeyId>, u64)>) {
+        let queue = self.ext_messages_prevalidation();
+        (queue.stats(), queue.source_drops())
+    }
+
+    async fn export_node_state(&self) -> Result<Vec<(String, BlockIdExt)>> {
+        self.db().export_node_state().await
+    }
+
+    fn import_node_state(&self, entries: &[(String, BlockIdExt)], overwrite: bool) -> Result<usize> {
+        self.db().import_node_state(entries, overwrite)
+    }
+
+    fn list_full_node_state_keys(&self) -> Result<Vec<NodeStateEntry>> {
+        self.db().list_full_node_state_keys()
+    }
+
+    fn list_validator_state_keys(&self) -> Result<Vec<NodeStateEntry>> {
+        self.db().list_validator_state_keys()
+    }
+
+    fn reload_config(&self, new: TonNodeConfig) -> Result<ReloadReport> {
+        Engine::reload_config(self, &new)
+    }
+
+    fn reload_config_from_file(&self) -> Result<ReloadReport> {
+        Engine::reload_config_from_file(self)
+    }
+
+    async fn encrypt_validator_keystore(&self) -> Result<usize> {
+        Engine::encrypt_validator_keystore(self).await
+    }
+
     fn acquire_stop(&self, mask: u32) {
         self.stopper().acquire_stop(mask);
     }
@@ -1190,6 +1196,10 @@ impl EngineOperations for Engine {
         self.stopper().check_stop()
     }
 
+    fn stopper(&self) -> &Stopper {
+        Engine::stopper(self)
+    }
+
     fn release_stop(&self, mask: u32) {
         self.stopper().release_stop(mask);
     }
@@ -1198,10 +1208,6 @@ impl EngineOperations for Engine {
         Engine::register_server(self, server)
     }
 
-    fn send_remp_message(&self, to: Arc<KeyId>, message: &RempMessage) -> Result<()> {
-        self.network().remp().send_message(to, message)
-    }
-
     /*async fn sign_and_send_remp_receipt(&self, to: Arc<KeyId>, receipt: RempReceipt) -> Result<()> {
         let validators: Vec<CatchainNode> = self.load_actual_config_params().await?
             .validator_set()?.list()
@@ -1226,29 +1232,6 @@ impl EngineOperations for Engine {
 
     }*/
 
-    async fn send_remp_receipt(&self, to: Arc<KeyId>, receipt: RempReceipt) -> Result<()> {
-        let validators: Vec<CatchainNode> = self.load_actual_config_params().await?
-            .validator_set()?.list()
-            .iter().map(|vd| validatordescr_to_catchain_node(vd)).collect();
-        let (key, adnl_id) = self.network
-            .get_validator_key(&validators).await?
-            .ok_or_else(|| error!("Can't get validator's key"))?;
-        if key.id().data() != receipt.source_id().as_slice() {
-            fail!("given source_id {} is not correspond to key {}", hex::encode(receipt.source_id().as_slice()), hex::encode(key.id().data()))
-        }
-        self.network().remp().combine_and_send_receipt(to, receipt, adnl_id).await
-    }
-
-    fn sign_remp_receipt(&self, receipt: &RempReceipt) -> Result<Vec<u8>> {
-        let receipt_bytes = serialize_boxed(receipt)?;
-        let key = self.network.public_overlay_key()?;
-        if key.id().data() != receipt.source_id().as_slice() {
-            fail!("given source_id {} is not correspond to key {}", hex::encode(receipt.source_id().as_slice()), hex::encode(key.id().data()))
-        }
-        let signature = key.sign(&receipt_bytes)?;
-        Ok(signature)
-    }
-
     async fn update_validators(
         &self,
         to_resolve: Vec<CatchainNode>,
@@ -1269,13 +1252,8 @@ impl EngineOperations for Engine {
         Ok(())
     }
 
-    fn set_remp_core_interface(&self, rci: Arc<dyn RempCoreInterface>) -> Result<()> {
-        if let Some(rs) = self.remp_service() {
-            rs.set_remp_core_interface(rci)?;
-        } else {
-            log::warn!("Attempt to set remp_core_interface while remp service is disabled");
-        }
-        Ok(())
+    fn apply_throttle(&self) -> Option<Arc<ApplyThrottle>> {
+        Engine::apply_throttle(self).cloned()
     }
 
     // returns true if there were no either calculating or done queues before
@@ -1396,26 +1374,566 @@ impl EngineOperations for Engine {
     ) -> Result<Arc<OutMsgQueueInfo>> {
         self.shard_states_keeper().mesh_queues_keeper().load_mesh_queue(nw_id, mc_block_id, shard)
     }
-}
 
-async fn redirect_external_message(
-    engine: &dyn EngineOperations, 
-    message: Message, 
-    id: UInt256,
-    message_data: &[u8]
-) -> Result<BroadcastSendInfo> {
-    let message = Arc::new(message);
-    engine.new_external_message(&id, message.clone())?;
-    if let Some(header) = message.ext_in_header() {
-        let res = engine.broadcast_to_public_overlay(
-            &AccountIdPrefixFull::checked_prefix(&header.dst)?,
-            message_data
-        ).await;
-        #[cfg(feature = "telemetry")]
-        engine.full_node_telemetry().sent_ext_msg_broadcast();
-        jaeger::broadcast_sended(id.to_hex_string());
-        res
-    } else {
-        fail!("External message is not properly formatted: {}", message)
+    fn apply_mesh_queue_update(
+        &self,
+        nw_id: i32,
+        shard: &ShardIdent,
+        update: MeshQueueUpdate
+    ) -> Result<MeshQueueUpdateResult> {
+        self.shard_states_keeper()
+            .mesh_queues_keeper().apply_mesh_queue_update(nw_id, shard, update, self.db())
+    }
+
+    fn drop_mesh_network(&self, nw_id: i32) -> Result<()> {
+        self.shard_states_keeper().mesh_queues_keeper().drop_network(nw_id, self.db())?;
+        Ok(())
     }
+
+    async fn prune_mesh_queues(&self, nw_id: i32) -> Result<PruneStats> {
+        self.shard_states_keeper().mesh_queues_keeper().prune_queues_for(nw_id, self.db()).await
+    }
+
+    fn queue_lag_table(&self) -> Vec<QueueLag> {
+        self.shard_states_keeper().mesh_queues_keeper().queue_lags().lag_table()
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockAccess for Engine {
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        self.db().load_block_handle(id)
+    }
+
+    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
+        // Block is flagged as stored, so failure to read it means broken db
+        self.db().load_block_data(handle).await
+            .map_err(|e| error!(NodeError::classify(e, NodeError::DbCorruption)))
+    }
+
+    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        self.db().load_block_prev1(id)
+    }
+
+    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        self.db().load_block_prev2(id)
+    }
+
+    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        self.db().load_block_next1(id)
+    }
+
+    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        self.db().load_block_next2(id)
+    }
+}
+
+#[async_trait::async_trait]
+impl StateAccess for Engine {
+    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+        match self.load_last_applied_mc_block_id()? {
+            Some(block_id) => {
+                self.load_state(&block_id).await
+            }
+            None => fail!("INTERNAL ERROR: No last applied MC block set")
+        }
+    }
+
+    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        self.shard_states_keeper().load_state(block_id).await.map_err(|e| {
+            let has_state = self.load_block_handle(block_id).ok().flatten()
+                .map(|handle| handle.has_state())
+                .unwrap_or(false);
+            let kind: fn(String) -> NodeError = if has_state {
+                NodeError::DbCorruption
+            } else {
+                NodeError::NotFound
+            };
+            error!(NodeError::classify(e, kind))
+        })
+    }
+
+    async fn wait_state(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        timeout_ms: Option<u64>,
+        allow_block_downloading: bool
+    ) -> Result<Arc<ShardStateStuff>> {
+        loop {
+            let has_state = || {
+                Ok(self.load_block_handle(id)?.map(|h| h.has_state()).unwrap_or(false))
+            };
+
+            if has_state()? {
+                break self.load_state(id).await
+            }
+            let id1 = id.clone();
+            let engine = self.clone();
+            if allow_block_downloading {
+                tokio::spawn(async move {
+                    if let Err(e) = engine.download_and_apply_block(&id1, 0, true).await {
+                        log::error!("Error while pre-apply block (while wait_state) {}: {:?}", id1, e);
+                    }
+                });
+            }
+            if let Some(ss) = self.shard_states_awaiters().wait(id, timeout_ms, &has_state).await? {
+                break Ok(ss)
+            }
+        }
+    }
+
+    async fn wait_stored_state(
+        &self,
+        id: &BlockIdExt,
+        timeout: Option<Duration>,
+        stopper: &Stopper
+    ) -> Result<Arc<ShardStateStuff>> {
+        self.shard_states_keeper().wait_state(id, timeout, stopper).await
+    }
+
+    fn states_prefetch_distance(&self) -> u32 {
+        self.shard_states_keeper().prefetcher().distance()
+    }
+
+    fn prefetch_states(&self, block: &BlockStuff) {
+        self.shard_states_keeper().prefetch_states(block)
+    }
+}
+
+#[async_trait::async_trait]
+impl RempSupport for Engine {
+    // Remp messages
+    async fn check_remp_duplicate(&self, message_id: &UInt256) -> Result<RempDuplicateStatus> {
+        self.remp_service()
+            .ok_or_else(|| error!("Can't get message status because remp service was not set"))?
+            .check_remp_duplicate(message_id)
+    }
+
+    async fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>> {
+        self.remp_service()
+            .ok_or_else(|| error!("Can't filter messages because remp service was not set"))?
+            .filter_fresh_messages(ids)
+    }
+
+    fn trace_remp_message(&self, message_id: &UInt256) -> Result<Vec<MessageTraceEvent>> {
+        self.remp_service()
+            .ok_or_else(|| error!("Can't trace message because remp service was not set"))?
+            .trace_message(message_id)
+    }
+
+    fn remp_message_cache_snapshot(&self) -> Result<Option<MessageCacheSnapshot>> {
+        match self.remp_service() {
+            None => Ok(None),
+            Some(service) => service.message_cache_snapshot().map(Some)
+        }
+    }
+
+    async fn push_message_to_remp(&self, data: ton_api::ton::bytes) -> Result<()> {
+        let (id, _message) = create_ext_message(&data)?;
+        let remp_message = ton_api::ton::ton_node::rempmessage::RempMessage {
+            message: data,
+            id: id.clone(),
+            timestamp: 0,
+            signature: Vec::new().into()
+        }.into_boxed();
+        let zero_source = Arc::new(KeyId::from_data([0; 32]));
+        // Message is already prevalidated, so it doesn't go through the queue again
+        self.remp_service()
+            .ok_or_else(|| error!("REMP service is not enabled"))?
+            .process_message(remp_message, &zero_source).await;
+        Ok(())
+    }
+
+    fn queue_remp_message(&self, message: RempMessage, source: &Arc<KeyId>) -> Result<bool> {
+        Ok(self.ext_messages_prevalidation().push(ExtMessageTask::Remp(message), source.clone(), true))
+    }
+
+    fn remp_capability(&self) -> bool {
+        Engine::remp_capability(self)
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn remp_core_telemetry(&self) -> &RempCoreTelemetry {
+        Engine::remp_core_telemetry(self)
+    }
+
+    fn send_remp_message(&self, to: Arc<KeyId>, message: &RempMessage) -> Result<()> {
+        self.network().remp().send_message(to, message)
+    }
+
+    async fn send_remp_receipt(&self, to: Arc<KeyId>, receipt: RempReceipt) -> Result<()> {
+        let validators: Vec<CatchainNode> = self.load_actual_config_params().await?
+            .validator_set()?.list()
+            .iter().map(|vd| validatordescr_to_catchain_node(vd)).collect();
+        let (key, adnl_id) = self.network
+            .get_validator_key(&validators).await?
+            .ok_or_else(|| error!("Can't get validator's key"))?;
+        if key.id().data() != receipt.source_id().as_slice() {
+            fail!("given source_id {} is not correspond to key {}", hex::encode(receipt.source_id().as_slice()), hex::encode(key.id().data()))
+        }
+        self.network().remp().combine_and_send_receipt(to, receipt, adnl_id).await
+    }
+
+    fn sign_remp_receipt(&self, receipt: &RempReceipt) -> Result<Vec<u8>> {
+        let receipt_bytes = serialize_boxed(receipt)?;
+        let key = self.network.public_overlay_key()?;
+        if key.id().data() != receipt.source_id().as_slice() {
+            fail!("given source_id {} is not correspond to key {}", hex::encode(receipt.source_id().as_slice()), hex::encode(key.id().data()))
+        }
+        let signature = key.sign(&receipt_bytes)?;
+        Ok(signature)
+    }
+
+    fn set_remp_core_interface(&self, rci: Arc<dyn RempCoreInterface>) -> Result<()> {
+        if let Some(rs) = self.remp_service() {
+            rs.set_remp_core_interface(rci)?;
+        } else {
+            log::warn!("Attempt to set remp_core_interface while remp service is disabled");
+        }
+        Ok(())
+    }
+
+    fn remp_messages_db(&self) -> Result<Arc<RempMessagesDb>> {
+        Ok(self.db().remp_messages_db())
+    }
+
+    fn remp_pending_records_db(&self) -> Result<Arc<RempMessagesDb>> {
+        Ok(self.db().remp_pending_records_db())
+    }
+
+    fn ext_messages_rate_limiter(&self) -> Option<Arc<ExtMessagesRateLimiter>> {
+        Engine::ext_messages_rate_limiter(self).cloned()
+    }
+
+    #[cfg(feature = "external_db")]
+    fn remp_history_exporter(&self) -> Option<Arc<RempHistoryExporter>> {
+        Engine::remp_history_exporter(self).cloned()
+    }
+
+    fn ext_message_limits(&self) -> Option<Arc<ExtMessageLimits>> {
+        Some(Engine::ext_message_limits(self).clone())
+    }
+
+    fn time_service(&self) -> Option<Arc<TimeService>> {
+        Engine::time_service(self).cloned()
+    }
+}
+
+#[async_trait::async_trait]
+impl BroadcastSupport for Engine {
+    async fn broadcast_to_public_overlay(
+        &self, 
+        to: &AccountIdPrefixFull, 
+        data: &[u8]
+    ) -> Result<BroadcastSendInfo> {
+        let overlay = self.get_full_node_overlay(0, to.workchain_id, to.prefix).await?;
+        overlay.broadcast_external_message(data).await
+    }
+
+    async fn redirect_external_message(&self, message_data: &[u8], id: UInt256) -> Result<()> {
+        if !self.check_sync().await? {
+            fail!("Can't process external message because node is out of sync");
+        }
+
+        let remp_way = self.remp_capability() && self.remp_client().is_some();
+        if remp_way {
+            self.remp_client()
+                .ok_or_else(|| error!("redirect_external_message: remp client is not set"))?
+                .clone()
+                .process_remp_message(message_data.into(), id.clone());
+            log::debug!(
+                target: EXT_MESSAGES_TRACE_TARGET,
+                "Redirected external message {:x} to REMP",
+                id,
+            );
+            Ok(())
+        } else {
+            let parsed = match self.ext_message_time_window() {
+                Some((max_age, max_skew)) => 
+                    create_ext_message_with_time_check(message_data, self.now(), max_age, max_skew),
+                None => create_ext_message(message_data)
+            }.and_then(|(id, message)| {
+                self.ext_message_limits().check(message_data.len(), &message)?;
+                Ok((id, message))
+            });
+            match parsed {
+                Err(e) => {
+                    let err = format!(
+                        "Can't deserialize external message with len {}: {}",
+                        message_data.len(), e,
+                    );
+                    log::warn!(target: EXT_MESSAGES_TRACE_TARGET, "{}", &err);
+                    fail!("{}", err);
+                }
+                Ok((id, message)) => {
+                    match redirect_external_message(self, message, id.clone(), message_data).await {
+                        Err(e) => {
+                            let err = format!(
+                                "Can't redirect external message {:x}: {}",
+                                id, e,
+                            );
+                            log::error!(target: EXT_MESSAGES_TRACE_TARGET, "{}", &err);
+                            fail!("{}", err);
+                        }
+                        Ok(info) => {
+                            log::debug!(
+                                target: EXT_MESSAGES_TRACE_TARGET,
+                                "Redirected external message {:x} to {} nodes by {} packages",
+                                id, info.send_to, info.packets,
+                            );
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send_block_broadcast(&self, broadcast: BlockBroadcast) -> Result<()> {
+        let mut target_wcs = vec!();
+
+        // If Wc2WcQueueUpdates enabled - send master block to all WCs
+        if broadcast.id.shard().is_masterchain() {
+            let mc_state = self.load_last_applied_mc_state().await?;
+            if mc_state.config_params()?.has_capability(GlobalCapabilities::CapWorkchains) {
+                mc_state.shard_state_extra()?.shards().iterate_with_keys(|workchain_id: i32, _| {
+                    target_wcs.push(workchain_id);
+                    Ok(true)
+                })?;
+                target_wcs.push(MASTERCHAIN_ID);
+            } else {
+                target_wcs.push(broadcast.id.shard().workchain_id());
+            }
+        } else {
+            target_wcs.push(broadcast.id.shard().workchain_id());
+        }
+
+        // Key block gets full fanout, unknown block is taken as a key one
+        let is_key_block = broadcast.id.shard().is_masterchain() && match self.load_block_handle(&broadcast.id) {
+            Ok(Some(handle)) => handle.is_key_block().unwrap_or(true),
+            _ => true
+        };
+
+        for wc in target_wcs {
+            log::trace!("send_block_broadcast {} to {}", broadcast.id, wc);
+            let overlay = self.get_full_node_overlay(0, wc, SHARD_FULL).await?;
+            overlay.send_block_broadcast(broadcast.clone(), is_key_block).await?;
+        }
+
+        #[cfg(feature = "telemetry")]
+        self.full_node_telemetry().sent_block_broadcast();
+
+        Ok(())
+    }
+
+    fn process_direct_block_broadcast(self: Arc<Self>, broadcast: BlockBroadcast, src: Arc<KeyId>) {
+        self.process_block_broadcast(broadcast, src, true)
+    }
+
+    async fn send_queue_update_broadcast(&self, broadcast: QueueUpdateBroadcast) -> Result<()> {
+        // TODO select right overlay
+        let overlay = self.get_full_node_overlay(
+            0,
+            broadcast.target_wc,
+            SHARD_FULL, //broadcast.id.shard as u64
+        ).await?;
+        
+        log::trace!("send_queue_update_broadcast {} to {}", broadcast.id, broadcast.target_wc);
+        overlay.send_queue_update_broadcast(broadcast).await?;
+        #[cfg(feature = "telemetry")]
+        self.full_node_telemetry().sent_block_broadcast(); // TODO
+        Ok(())
+    }
+
+    async fn send_mesh_update_broadcast(&self, broadcast: MeshUpdateBroadcast) -> Result<()> {
+        let overlay = self.get_full_node_overlay(
+            broadcast.target_nw,
+            MASTERCHAIN_ID,
+            SHARD_FULL,
+        ).await?;
+        
+        log::trace!("send_mesh_update_broadcast {} to {}", broadcast.id, broadcast.target_nw);
+        overlay.send_mesh_update_broadcast(broadcast).await?;
+        #[cfg(feature = "telemetry")]
+        self.full_node_telemetry().sent_block_broadcast(); // TODO
+        Ok(())
+    }
+
+    async fn send_top_shard_block_description(
+        &self,
+        tbd: Arc<TopBlockDescrStuff>,
+        cc_seqno: u32,
+        is_resend: bool,
+    ) -> Result<()> {
+
+        if !is_resend {
+            let id = tbd.proof_for();
+            if let Err(e) = self.shard_blocks().process_shard_block(
+                id, cc_seqno, || Ok(tbd.clone()), false, self).await {
+                log::error!("Can't add own shard top block {}: {}", id, e);
+            }
+        }
+
+        let mut target_wcs = vec!();
+        if self.load_actual_config_params().await?.has_capability(GlobalCapabilities::CapWorkchains) {
+            target_wcs.push(tbd.proof_for().shard().workchain_id());
+            target_wcs.push(MASTERCHAIN_ID);
+        } else {
+            target_wcs.push(MASTERCHAIN_ID);
+        }
+
+        for wc in target_wcs {
+            let overlay = self.get_full_node_overlay(0, wc, SHARD_FULL).await?;
+            overlay.send_top_shard_block_description(&tbd).await?;
+        }
+
+        #[cfg(feature = "telemetry")]
+        self.full_node_telemetry().sent_top_block_broadcast();
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl ValidatorSupport for Engine {
+    fn get_validator_status(&self) -> bool {
+        self.network.config_handler().get_validator_status()
+    }
+
+    fn validator_network(&self) -> Arc<dyn PrivateOverlayOperations> {
+        Engine::validator_network(self)
+    }
+
+    async fn set_validator_list(
+        &self, 
+        validator_list_id: UInt256,
+        validators: &Vec<CatchainNode>
+    ) -> Result<Option<Arc<dyn KeyOption>>> {
+        self.validator_network().set_validator_list(validator_list_id, validators).await
+    }
+
+    fn activate_validator_list(&self, validator_list_id: UInt256) -> Result<()> {
+        self.network().activate_validator_list(validator_list_id)
+    }
+
+    async fn get_validator_bls_key(&self, key_id: &Arc<KeyId>) -> Option<Arc<dyn KeyOption>> {
+        self.network().get_validator_bls_key(key_id).await
+    }
+
+    fn validation_status(&self) -> ValidationStatus {
+        self.validation_status()
+    }
+
+    fn set_validation_status(&self, status: ValidationStatus) {
+        self.set_validation_status(status)
+    }
+
+    fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+        self.last_validation_time()
+    }
+
+    fn set_last_validation_time(&self, shard: ShardIdent, time: u64) {
+        self.set_last_validation_time(shard, time)
+    }
+
+    fn remove_last_validation_time(&self, shard: &ShardIdent) {
+        self.remove_last_validation_time(shard)
+    }
+
+    fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+        self.last_collation_time()
+    }
+
+    fn set_last_collation_time(&self, shard: ShardIdent, time: u64) {
+        self.set_last_collation_time(shard, time)
+    }
+
+    fn remove_last_collation_time(&self, shard: &ShardIdent) {
+        self.remove_last_collation_time(shard)
+    }
+
+    fn validator_sessions_history(&self) -> Option<Arc<ValidatorSessionsHistory>> {
+        Some(Engine::validator_sessions_history(self).clone())
+    }
+
+    fn last_config_check(&self) -> Option<Arc<ConfigCheckResult>> {
+        Engine::last_config_check(self)
+    }
+
+    async fn validator_schedule(&self, lookahead_cc: u32) -> Result<ValidatorSchedule> {
+        Engine::validator_schedule(self, lookahead_cc).await
+    }
+
+    async fn key_rotation_plan(&self) -> Result<RotationPlan> {
+        Engine::key_rotation_plan(self).await
+    }
+
+    async fn provide_rotation_keys(&self, keys: RotationKeys) -> Result<()> {
+        Engine::provide_rotation_keys(self, keys).await
+    }
+
+    async fn remove_validator_list(&self, validator_list_id: UInt256) -> Result<bool> {
+        self.validator_network().remove_validator_list(validator_list_id).await
+    }
+
+    fn create_catchain_client(
+        &self,
+        validator_list_id: UInt256,
+        overlay_short_id : &Arc<PrivateOverlayShortId>,
+        nodes_public_keys : &Vec<CatchainNode>,
+        listener : CatchainOverlayListenerPtr,
+        _log_replay_listener: CatchainOverlayLogReplayListenerPtr
+    ) -> Result<Arc<dyn CatchainOverlay + Send>> {
+        self.validator_network().create_catchain_client(
+            validator_list_id,
+            overlay_short_id,
+            nodes_public_keys,
+            listener,
+            _log_replay_listener
+        )
+    }
+
+    fn stop_catchain_client(&self, overlay_short_id: &Arc<PrivateOverlayShortId>) {
+        self.validator_network().stop_catchain_client(overlay_short_id)
+    }
+}
+
+async fn redirect_external_message(
+    engine: &dyn EngineOperations, 
+    message: Message, 
+    id: UInt256,
+    message_data: &[u8]
+) -> Result<BroadcastSendInfo> {
+    let message = Arc::new(message);
+    engine.new_external_message(&id, message.clone())?;
+    if let Some(header) = message.ext_in_header() {
+        let res = engine.broadcast_to_public_overlay(
+            &AccountIdPrefixFull::checked_prefix(&header.dst)?,
+            message_data
+        ).await;
+        #[cfg(feature = "telemetry")]
+        engine.full_node_telemetry().sent_ext_msg_broadcast();
+        jaeger::broadcast_sended(id.to_hex_string());
+        res
+    } else {
+        fail!("External message is not properly formatted: {}", message)
+    }
+}
+
+// Loads and pins the state reporting explicitly if it is already removed or about to be
+async fn load_and_pin_available_state(
+    engine: &Engine,
+    block_id: &BlockIdExt
+) -> Result<PinnedShardStateGuard> {
+    let handle = engine.load_block_handle(block_id)?
+        .ok_or_else(|| error!("Cannot load handle for block {}", block_id))?;
+    if !handle.has_state() {
+        fail!(NodeError::StateNotAvailable(block_id.clone()))
+    }
+    engine.load_and_pin_state(block_id).await.map_err(|e| {
+        match e.downcast_ref::<StorageError>() {
+            Some(StorageError::StateIsAllowedToGc(_)) =>
+                error!(NodeError::StateNotAvailable(block_id.clone())),
+            _ => e
+        }
+    })
 }
@@ -12,25 +12,51 @@
 */
 
 use crate::{
-    block::BlockStuff, block_proof::BlockProofStuff, 
+    applied_blocks::{AppliedBlockStream, ShardFilter}, archives_gc::ArchivesGcStats,
+    block::BlockStuff, block_proof::{BlockProofStuff, BlockProvenance}, 
+    collator_test_bundle::{ConfigPatch, DryRunReport, ReplayOutcome},
     config::{CollatorConfig, CollatorTestBundlesGeneralConfig, TonNodeConfig},
-    engine::{EngineFlags, now_duration}, internal_db::BlockResult,
-    network::{control::ControlServer, full_node_client::FullNodeOverlayClient},
-    shard_state::ShardStateStuff, shard_states_keeper::PinnedShardStateGuard,
-    types::top_block_descr::{TopBlockDescrStuff, TopBlockDescrId},
-    validator::validator_manager::ValidationStatus
+    config_check::ConfigCheckResult,
+    config_reload::ReloadReport,
+    engine::{EngineFlags, Stopper, now_duration},
+    ext_messages::{
+        limits::ExtMessageLimits, prevalidation::{PrevalidationLane, PrevalidationStats},
+        rate_limiter::ExtMessagesRateLimiter
+    },
+    full_node::apply_throttle::ApplyThrottle,
+    internal_db::{
+        BlockResult, consistency::{ConsistencyReport, RepairMode}, storage_usage::StorageUsageReport,
+        persistent_state_reader::PersistentStateReader
+    },
+    manual_gc::{GcKind, GcProgress, GcTicket},
+    mesh_queues_keeper::{MeshQueueUpdate, MeshQueueUpdateResult, PruneStats, QueueLag},
+    network::{
+        block_range::{BlockRangeQuery, DownloadedBlockRange},
+        control::ControlServer, full_node_client::FullNodeOverlayClient,
+        neighbours_quality::NeighbourQuality
+    },
+    network_snapshot::SignedSnapshot, node_status::NodeStatus,
+    shard_state::{AccountStateProof, ShardStateStuff}, shard_states_keeper::{PinGuard, PinnedShardStateGuard},
+    time_service::TimeService, types::top_block_descr::{TopBlockDescrStuff, TopBlockDescrId},
+    validator::{
+        key_rotation::{RotationKeys, RotationPlan},
+        message_cache::{MessageCacheSnapshot, MessageTraceEvent}, validator_manager::ValidationStatus,
+        validator_schedule::{ValidatorSchedule, ValidatorSessionsHistory}
+    }
 };
+#[cfg(feature = "external_db")]
+use crate::external_db::remp_history::RempHistoryExporter;
 #[cfg(feature = "slashing")]
 use crate::validator::slashing::ValidatedBlockStat;
 #[cfg(feature = "telemetry")]
 use crate::{
     full_node::telemetry::{FullNodeTelemetry, RempClientTelemetry},
-    validator::telemetry::{CollatorValidatorTelemetry, RempCoreTelemetry},
+    validator::telemetry::{CollationTimingTelemetry, CollatorValidatorTelemetry, RempCoreTelemetry},
     network::telemetry::FullNodeNetworkTelemetry,
 };
 
 #[cfg(feature = "telemetry")]
-use adnl::telemetry::Metric;
+use adnl::telemetry::{Metric, MetricBuilder};
 use adnl::{
     BroadcastSendInfo, OverlayId, OverlayShortId, PrivateOverlayShortId, common::Subscriber
 };
@@ -43,8 +69,12 @@ use ever_block::{
     Deserializable, KeyId, KeyOption, MASTERCHAIN_ID, Message, OutMsgQueue, Result, 
     ShardAccount, ShardIdent, UInt256, OutMsgQueueInfo
 };
-use std::{collections::HashSet, sync::{Arc, atomic::AtomicU64}};
-use storage::{StorageAlloc, block_handle_db::BlockHandle};
+use std::{collections::HashSet, sync::{Arc, atomic::AtomicU64}, time::Duration};
+use storage::{
+    StorageAlloc, account_tx_index::{TxContinuation, TxLocation},
+    archives::mapped_packages::ArchiveSliceData, block_handle_db::{BlockHandle, NodeStateEntry},
+    remp_messages_db::RempMessagesDb
+};
 #[cfg(feature = "telemetry")]
 use storage::StorageTelemetry;
 use ton_api::ton::ton_node::{
@@ -65,6 +95,14 @@ pub struct EngineTelemetry {
     pub validator_peers: Arc<Metric>,
     pub validator_sets: Arc<Metric>,
     pub old_state_cell_load_time: Arc<Metric>,
+    pub pinned_states: Arc<Metric>,
+    pub sync_queue_depth: Arc<Metric>,
+    pub sync_downloads: Arc<Metric>,
+    pub sync_checked_proofs: Arc<MetricBuilder>,
+    pub broken_block_files: Arc<Metric>,
+    pub apply_throttle_blocks_per_sec: Arc<Metric>,
+    pub apply_throttle_state_tasks: Arc<Metric>,
+    pub applied_blocks: Arc<MetricBuilder>,
 }
 
 pub struct EngineAlloc {
@@ -144,22 +182,240 @@ pub trait PrivateOverlayOperations: Sync + Send {
     fn stop_catchain_client(&self, overlay_short_id: &Arc<PrivateOverlayShortId>);
 }
 
-// TODO make separate traits for read and write operations (may be critical and not etc.)
+// Read access to stored blocks and their links
 #[async_trait::async_trait]
 #[allow(unused)]
-pub trait EngineOperations : Sync + Send {
+pub trait BlockAccess : Sync + Send {
 
-    fn processed_workchain(&self) -> Option<i32> { None }
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        unimplemented!()
+    }
 
-    async fn is_foreign_wc(&self, workchain_id: i32) -> Result<(bool, i32)> { unimplemented!() }
+    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
+        unimplemented!()
+    }
 
-    fn get_validator_status(&self) -> bool { unimplemented!() }
+    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        unimplemented!()
+    }
 
-    fn validator_network(&self) -> Arc<dyn PrivateOverlayOperations> {
+    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
         unimplemented!()
     }
 
-    fn calc_overlay_id(&self, workchain: i32, shard: u64) -> Result<(Arc<OverlayShortId>, OverlayId)> {
+    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        unimplemented!()
+    }
+
+    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        unimplemented!()
+    }
+}
+
+// Read access to shard states
+#[async_trait::async_trait]
+#[allow(unused)]
+pub trait StateAccess : Sync + Send {
+
+    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+        unimplemented!()
+    }
+
+    // This function WAITS the shard account belonging to the shard's last committed state.
+    async fn load_account(
+        self: Arc<Self>,
+        wc: i32,
+        address: AccountId,
+    ) -> Result<(ShardAccount, ShardIdent)> {
+
+        let last_mc_state = self.load_last_applied_mc_state().await?;
+
+        if wc == MASTERCHAIN_ID {
+            let acc = last_mc_state.state()?.read_accounts()?.account(&address)?
+                .ok_or_else(|| error!("Can't get account {:x} from last master state {}", address, last_mc_state.block_id()))?;
+            Ok((acc, last_mc_state.block_id().shard().clone()))
+        } else {
+            let prefix = AccountIdPrefixFull::workchain(wc, u64::construct_from(&mut address.clone())?);
+            let shard_header = last_mc_state.shards()?.find_shard_by_prefix(&prefix)?
+                .ok_or_else(|| error!("Can't get shard for prefix {}", prefix))?;
+            let last_shard_state = self.wait_state(
+                &shard_header.block_id,
+                Some(10_000),
+                false,
+            ).await?;
+            let acc = last_shard_state.state()?.read_accounts()?.account(&address)?
+                .ok_or_else(|| error!("Can't get account {:x} from state {}", address, last_shard_state.block_id()))?;
+            Ok((acc, last_shard_state.block_id().shard().clone()))
+        }
+    }
+
+    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        unimplemented!()
+    }
+
+    async fn wait_state(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        timeout_ms: Option<u64>,
+        allow_block_downloading: bool
+    ) -> Result<Arc<ShardStateStuff>> {
+        unimplemented!()
+    }
+
+    // Passively waits until the state is stored, stop of given stopper cancels waiting
+    async fn wait_stored_state(
+        &self,
+        id: &BlockIdExt,
+        timeout: Option<Duration>,
+        stopper: &Stopper
+    ) -> Result<Arc<ShardStateStuff>> {
+        unimplemented!()
+    }
+
+    // How many blocks ahead of apply are announced to prefetch their states, 0 - none
+    fn states_prefetch_distance(&self) -> u32 {
+        0
+    }
+
+    // Hint only: states needed to apply the block are warmed up in background
+    fn prefetch_states(&self, block: &BlockStuff) {}
+}
+
+// Operations used by REMP components
+#[async_trait::async_trait]
+#[allow(unused)]
+pub trait RempSupport : BlockAccess + StateAccess + Sync + Send {
+
+    fn send_remp_message(&self, to: Arc<KeyId>, message: &RempMessage) -> Result<()> {
+        unimplemented!()
+    }
+
+    async fn send_remp_receipt(&self, to: Arc<KeyId>, receipt: RempReceipt) -> Result<()> {
+        unimplemented!()
+    }
+
+    fn sign_remp_receipt(&self, receipt: &RempReceipt) -> Result<Vec<u8>> {
+        unimplemented!()
+    }
+
+    async fn check_remp_duplicate(&self, message_id: &UInt256) -> Result<RempDuplicateStatus> {
+        unimplemented!()
+    }
+
+    async fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>> {
+        unimplemented!()
+    }
+
+    fn trace_remp_message(&self, message_id: &UInt256) -> Result<Vec<MessageTraceEvent>> {
+        unimplemented!()
+    }
+
+    // None if REMP is not enabled
+    fn remp_message_cache_snapshot(&self) -> Result<Option<MessageCacheSnapshot>> {
+        Ok(None)
+    }
+
+    async fn push_message_to_remp(&self, data: ton_api::ton::bytes) -> Result<()> {
+        unimplemented!()
+    }
+
+    // Queues REMP message from other node for prevalidation, false if the message is dropped
+    fn queue_remp_message(&self, message: RempMessage, source: &Arc<KeyId>) -> Result<bool> {
+        unimplemented!()
+    }
+
+    fn remp_capability(&self) -> bool { 
+        false 
+    }
+
+    fn set_remp_core_interface(&self, rci: Arc<dyn RempCoreInterface>) -> Result<()> {
+        unimplemented!()
+    }
+
+    fn remp_messages_db(&self) -> Result<Arc<RempMessagesDb>> {
+        unimplemented!()
+    }
+
+    fn remp_pending_records_db(&self) -> Result<Arc<RempMessagesDb>> {
+        unimplemented!()
+    }
+
+    fn ext_messages_rate_limiter(&self) -> Option<Arc<ExtMessagesRateLimiter>> {
+        None
+    }
+
+    // Sink of message status transitions, if their history is exported
+    #[cfg(feature = "external_db")]
+    fn remp_history_exporter(&self) -> Option<Arc<RempHistoryExporter>> {
+        None
+    }
+
+    fn ext_message_limits(&self) -> Option<Arc<ExtMessageLimits>> {
+        None
+    }
+
+    fn time_service(&self) -> Option<Arc<TimeService>> {
+        None
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn remp_core_telemetry(&self) -> &RempCoreTelemetry {
+        unimplemented!()
+    }
+}
+
+// Sending of broadcasts and redirecting of external messages
+#[async_trait::async_trait]
+#[allow(unused)]
+pub trait BroadcastSupport : Sync + Send {
+
+    async fn broadcast_to_public_overlay(
+        &self, 
+        to: &AccountIdPrefixFull, 
+        data: &[u8]
+    ) -> Result<BroadcastSendInfo> {
+        unimplemented!()    
+    }
+
+    async fn send_block_broadcast(&self, broadcast: BlockBroadcast) -> Result<()> {
+        unimplemented!()
+    }
+
+    // Block broadcast sent to us as an overlay message by a neighbour's fanout
+    fn process_direct_block_broadcast(self: Arc<Self>, broadcast: BlockBroadcast, src: Arc<KeyId>) {
+        unimplemented!()
+    }
+
+    async fn send_queue_update_broadcast(&self, broadcast: QueueUpdateBroadcast) -> Result<()> {
+        unimplemented!()
+    }
+
+    async fn send_mesh_update_broadcast(&self, broadcast: MeshUpdateBroadcast) -> Result<()> {
+        unimplemented!()
+    }
+
+    async fn send_top_shard_block_description(
+        &self,
+        tbd: Arc<TopBlockDescrStuff>,
+        cc_seqno: u32,
+        is_resend: bool,
+    ) -> Result<()> {
+        unimplemented!()
+    }
+
+    async fn redirect_external_message(&self, message_data: &[u8], id: UInt256) -> Result<()> {
+        unimplemented!()
+    }
+}
+
+// Validator lists, catchain clients and validation status
+#[async_trait::async_trait]
+#[allow(unused)]
+pub trait ValidatorSupport : Sync + Send {
+
+    fn get_validator_status(&self) -> bool { unimplemented!() }
+
+    fn validator_network(&self) -> Arc<dyn PrivateOverlayOperations> {
         unimplemented!()
     }
 
@@ -170,54 +426,247 @@ pub trait EngineOperations : Sync + Send {
     fn set_validation_status(&self, status: ValidationStatus) {
         unimplemented!()
     }
-
-    fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
-        unimplemented!()
+
+    fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+        unimplemented!()
+    }
+
+    fn set_last_validation_time(&self, shard: ShardIdent, time: u64) {
+        unimplemented!()
+    }
+
+    fn remove_last_validation_time(&self, shard: &ShardIdent) {
+        unimplemented!()
+    }
+
+    fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+        unimplemented!()
+    }
+
+    fn set_last_collation_time(&self, shard: ShardIdent, time: u64) {
+        unimplemented!()
+    }
+
+    fn remove_last_collation_time(&self, shard: &ShardIdent) {
+        unimplemented!()
+    }
+
+    // Latest sessions the node has participated in, None if not tracked
+    fn validator_sessions_history(&self) -> Option<Arc<ValidatorSessionsHistory>> {
+        None
+    }
+
+    // Config check of the latest applied key block, None while no key block is checked
+    fn last_config_check(&self) -> Option<Arc<ConfigCheckResult>> {
+        None
+    }
+
+    // Our membership in the next `lookahead_cc` sessions of every shard
+    async fn validator_schedule(&self, lookahead_cc: u32) -> Result<ValidatorSchedule> {
+        unimplemented!()
+    }
+
+    // State of automatic validator key rotation and its next window
+    async fn key_rotation_plan(&self) -> Result<RotationPlan> {
+        unimplemented!()
+    }
+
+    // Keys to use by the next rotation instead of generated ones
+    async fn provide_rotation_keys(&self, keys: RotationKeys) -> Result<()> {
+        unimplemented!()
+    }
+
+    // Validator specific operations
+    async fn set_validator_list(
+        &self, 
+        validator_list_id: UInt256,
+        validators: &Vec<CatchainNode>
+    ) -> Result<Option<Arc<dyn KeyOption>>> {
+        unimplemented!()
+    }
+
+    fn activate_validator_list(&self, validator_list_id: UInt256) -> Result<()> {
+        unimplemented!()
+    }
+
+    async fn remove_validator_list(&self, validator_list_id: UInt256) -> Result<bool> {
+        unimplemented!()
+    }
+
+    async fn get_validator_bls_key(&self, key_id: &Arc<KeyId>) -> Option<Arc<dyn KeyOption>> {
+        unimplemented!()
+    }
+
+    fn create_catchain_client(
+        &self,
+        validator_list_id: UInt256,
+        overlay_short_id : &Arc<PrivateOverlayShortId>,
+        nodes_public_keys : &Vec<CatchainNode>,
+        listener : CatchainOverlayListenerPtr,
+        _log_replay_listener: CatchainOverlayLogReplayListenerPtr
+    ) -> Result<Arc<dyn CatchainOverlay + Send>> {
+        unimplemented!()
+    }
+
+    fn stop_catchain_client(&self, overlay_short_id: &Arc<PrivateOverlayShortId>) {
+        unimplemented!()
+    }
+}
+
+// Trait objects can't be upcast, so narrower capability object is made by wrapping of
+// wider one (e.g. Arc<dyn EngineOperations>) into one more Arc, which forwards the calls.
+#[async_trait::async_trait]
+impl<T: BlockAccess + ?Sized> BlockAccess for Arc<T> {
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        (**self).load_block_handle(id)
+    }
+    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
+        (**self).load_block(handle).await
+    }
+    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        (**self).load_block_prev1(id)
+    }
+    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        (**self).load_block_prev2(id)
+    }
+    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        (**self).load_block_next1(id)
+    }
+    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        (**self).load_block_next2(id)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: StateAccess + ?Sized> StateAccess for Arc<T> {
+    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+        (**self).load_last_applied_mc_state().await
+    }
+    async fn load_account(
+        self: Arc<Self>,
+        wc: i32,
+        address: AccountId,
+    ) -> Result<(ShardAccount, ShardIdent)> {
+        T::load_account(Arc::clone(&*self), wc, address).await
+    }
+    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        (**self).load_state(block_id).await
+    }
+    async fn wait_state(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        timeout_ms: Option<u64>,
+        allow_block_downloading: bool
+    ) -> Result<Arc<ShardStateStuff>> {
+        T::wait_state(Arc::clone(&*self), id, timeout_ms, allow_block_downloading).await
+    }
+    async fn wait_stored_state(
+        &self,
+        id: &BlockIdExt,
+        timeout: Option<Duration>,
+        stopper: &Stopper
+    ) -> Result<Arc<ShardStateStuff>> {
+        (**self).wait_stored_state(id, timeout, stopper).await
+    }
+    fn states_prefetch_distance(&self) -> u32 {
+        (**self).states_prefetch_distance()
+    }
+    fn prefetch_states(&self, block: &BlockStuff) {
+        (**self).prefetch_states(block)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: RempSupport + ?Sized> RempSupport for Arc<T> {
+    fn send_remp_message(&self, to: Arc<KeyId>, message: &RempMessage) -> Result<()> {
+        (**self).send_remp_message(to, message)
+    }
+    async fn send_remp_receipt(&self, to: Arc<KeyId>, receipt: RempReceipt) -> Result<()> {
+        (**self).send_remp_receipt(to, receipt).await
+    }
+    fn sign_remp_receipt(&self, receipt: &RempReceipt) -> Result<Vec<u8>> {
+        (**self).sign_remp_receipt(receipt)
+    }
+    async fn check_remp_duplicate(&self, message_id: &UInt256) -> Result<RempDuplicateStatus> {
+        (**self).check_remp_duplicate(message_id).await
+    }
+    async fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>> {
+        (**self).filter_fresh_messages(ids).await
+    }
+    fn trace_remp_message(&self, message_id: &UInt256) -> Result<Vec<MessageTraceEvent>> {
+        (**self).trace_remp_message(message_id)
+    }
+    fn remp_message_cache_snapshot(&self) -> Result<Option<MessageCacheSnapshot>> {
+        (**self).remp_message_cache_snapshot()
+    }
+    async fn push_message_to_remp(&self, data: ton_api::ton::bytes) -> Result<()> {
+        (**self).push_message_to_remp(data).await
+    }
+    fn queue_remp_message(&self, message: RempMessage, source: &Arc<KeyId>) -> Result<bool> {
+        (**self).queue_remp_message(message, source)
+    }
+    fn remp_capability(&self) -> bool {
+        (**self).remp_capability()
+    }
+    fn set_remp_core_interface(&self, rci: Arc<dyn RempCoreInterface>) -> Result<()> {
+        (**self).set_remp_core_interface(rci)
+    }
+    fn remp_messages_db(&self) -> Result<Arc<RempMessagesDb>> {
+        (**self).remp_messages_db()
+    }
+    fn remp_pending_records_db(&self) -> Result<Arc<RempMessagesDb>> {
+        (**self).remp_pending_records_db()
+    }
+    fn ext_messages_rate_limiter(&self) -> Option<Arc<ExtMessagesRateLimiter>> {
+        (**self).ext_messages_rate_limiter()
+    }
+    #[cfg(feature = "external_db")]
+    fn remp_history_exporter(&self) -> Option<Arc<RempHistoryExporter>> {
+        (**self).remp_history_exporter()
+    }
+    fn ext_message_limits(&self) -> Option<Arc<ExtMessageLimits>> {
+        (**self).ext_message_limits()
     }
-
-    fn set_last_validation_time(&self, shard: ShardIdent, time: u64) {
-        unimplemented!()
+    fn time_service(&self) -> Option<Arc<TimeService>> {
+        (**self).time_service()
     }
-
-    fn remove_last_validation_time(&self, shard: &ShardIdent) {
-        unimplemented!()
+    #[cfg(feature = "telemetry")]
+    fn remp_core_telemetry(&self) -> &RempCoreTelemetry {
+        (**self).remp_core_telemetry()
     }
+}
 
-    fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
-        unimplemented!()
-    }
+pub fn block_access<T: BlockAccess + ?Sized + 'static>(engine: Arc<T>) -> Arc<dyn BlockAccess> {
+    Arc::new(engine)
+}
 
-    fn set_last_collation_time(&self, shard: ShardIdent, time: u64) {
-        unimplemented!()
-    }
+pub fn state_access<T: StateAccess + ?Sized + 'static>(engine: Arc<T>) -> Arc<dyn StateAccess> {
+    Arc::new(engine)
+}
 
-    fn remove_last_collation_time(&self, shard: &ShardIdent) {
-        unimplemented!()
-    }
+pub fn remp_support<T: RempSupport + ?Sized + 'static>(engine: Arc<T>) -> Arc<dyn RempSupport> {
+    Arc::new(engine)
+}
 
-    fn get_config_for_hardfork(&self) -> Option<ConfigParams>{
-        None
-    }
+// All the engine operations. Components needing only some of them should take narrower
+// capability traits above, so they can be tested with small mocks.
+// TODO make separate traits for read and write operations (may be critical and not etc.)
+#[async_trait::async_trait]
+#[allow(unused)]
+pub trait EngineOperations:
+    BlockAccess + StateAccess + RempSupport + BroadcastSupport + ValidatorSupport + Sync + Send
+{
 
-    // Validator specific operations
-    async fn set_validator_list(
-        &self, 
-        validator_list_id: UInt256,
-        validators: &Vec<CatchainNode>
-    ) -> Result<Option<Arc<dyn KeyOption>>> {
-        unimplemented!()
-    }
+    fn processed_workchain(&self) -> Option<i32> { None }
 
-    fn activate_validator_list(&self, validator_list_id: UInt256) -> Result<()> {
-        unimplemented!()
-    }
+    async fn is_foreign_wc(&self, workchain_id: i32) -> Result<(bool, i32)> { unimplemented!() }
 
-    async fn remove_validator_list(&self, validator_list_id: UInt256) -> Result<bool> {
+    fn calc_overlay_id(&self, workchain: i32, shard: u64) -> Result<(Arc<OverlayShortId>, OverlayId)> {
         unimplemented!()
     }
 
-    async fn get_validator_bls_key(&self, key_id: &Arc<KeyId>) -> Option<Arc<dyn KeyOption>> {
-        unimplemented!()
+    fn get_config_for_hardfork(&self) -> Option<ConfigParams>{
+        None
     }
 
     fn set_sync_status(&self, status: u32) {
@@ -228,24 +677,9 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
-    fn create_catchain_client(
-        &self,
-        validator_list_id: UInt256,
-        overlay_short_id : &Arc<PrivateOverlayShortId>,
-        nodes_public_keys : &Vec<CatchainNode>,
-        listener : CatchainOverlayListenerPtr,
-        _log_replay_listener: CatchainOverlayLogReplayListenerPtr
-    ) -> Result<Arc<dyn CatchainOverlay + Send>> {
-        unimplemented!()
-    }
-
-    fn stop_catchain_client(&self, overlay_short_id: &Arc<PrivateOverlayShortId>) {
-        unimplemented!()
-    }
-
     // Block related operations
 
-    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+    fn load_mesh_block_handle(&self, nw_id: i32, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
         unimplemented!()
     }
     async fn load_applied_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
@@ -254,9 +688,6 @@ pub trait EngineOperations : Sync + Send {
     async fn wait_applied_block(&self, id: &BlockIdExt, timeout_ms: Option<u64>) -> Result<Arc<BlockHandle>> {
         unimplemented!()
     }
-    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
-        unimplemented!()
-    }
     async fn load_block_raw(&self, handle: &BlockHandle) -> Result<Vec<u8>> {
         unimplemented!()
     }
@@ -301,9 +732,6 @@ pub trait EngineOperations : Sync + Send {
             }
         }
     }
-    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
-        unimplemented!()
-    }
     fn load_shard_client_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
         unimplemented!()
     }
@@ -319,6 +747,15 @@ pub trait EngineOperations : Sync + Send {
     fn clear_last_rotation_block_id(&self) -> Result<()> {
         unimplemented!()
     }
+    fn save_session_checkpoint(&self, session_id: &SessionId, data: Vec<u8>) -> Result<()> {
+        unimplemented!()
+    }
+    fn load_session_checkpoint(&self, session_id: &SessionId) -> Result<Option<Vec<u8>>> {
+        unimplemented!()
+    }
+    fn drop_stale_session_checkpoints(&self, active: &HashSet<SessionId>) -> Result<usize> {
+        unimplemented!()
+    }
     fn save_block_candidate(
         &self, 
         session_id: &SessionId, 
@@ -339,6 +776,12 @@ pub trait EngineOperations : Sync + Send {
     async fn find_mc_block_by_seq_no(&self, seqno: u32) -> Result<Arc<BlockHandle>> {
         unimplemented!()
     }
+    async fn find_mc_block_by_utime(&self, utime: u32) -> Result<Option<Arc<BlockHandle>>> {
+        unimplemented!()
+    }
+    async fn mc_blocks_in_utime_range(&self, from: u32, to: u32) -> Result<Vec<BlockIdExt>> {
+        unimplemented!()
+    }
     fn find_full_block_id(&self, root_hash: &UInt256) -> Result<Option<BlockIdExt>> {
         unimplemented!()
     }
@@ -436,6 +879,10 @@ pub trait EngineOperations : Sync + Send {
     async fn load_block_proof_raw(&self, handle: &BlockHandle, is_link: bool) -> Result<Vec<u8>> {
         unimplemented!()
     }
+    // Serialized proofs of the block back to the init key block, in order of verification
+    async fn build_proof_chain(&self, id: &BlockIdExt) -> Result<Vec<Vec<u8>>> {
+        unimplemented!()
+    }
 
     #[cfg(feature = "external_db")]
     async fn process_block_in_ext_db(
@@ -477,34 +924,6 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
-    // This function WAITS the shard account belonging to the shard's last committed state.
-    async fn load_account(
-        self: Arc<Self>,
-        wc: i32,
-        address: AccountId,
-    ) -> Result<(ShardAccount, ShardIdent)> {
-
-        let last_mc_state = self.load_last_applied_mc_state().await?;
-
-        if wc == MASTERCHAIN_ID {
-            let acc = last_mc_state.state()?.read_accounts()?.account(&address)?
-                .ok_or_else(|| error!("Can't get account {:x} from last master state {}", address, last_mc_state.block_id()))?;
-            Ok((acc, last_mc_state.block_id().shard().clone()))
-        } else {
-            let prefix = AccountIdPrefixFull::workchain(wc, u64::construct_from(&mut address.clone())?);
-            let shard_header = last_mc_state.shards()?.find_shard_by_prefix(&prefix)?
-                .ok_or_else(|| error!("Can't get shard for prefix {}", prefix))?;
-            let last_shard_state = self.wait_state(
-                &shard_header.block_id,
-                Some(10_000),
-                false,
-            ).await?;
-            let acc = last_shard_state.state()?.read_accounts()?.account(&address)?
-                .ok_or_else(|| error!("Can't get account {:x} from state {}", address, last_shard_state.block_id()))?;
-            Ok((acc, last_shard_state.block_id().shard().clone()))
-        }
-    }
-
     // State related operations
 
     async fn download_and_store_state(
@@ -528,14 +947,52 @@ pub trait EngineOperations : Sync + Send {
     async fn load_mc_zero_state(&self) -> Result<Arc<ShardStateStuff>> {
         unimplemented!()
     }
-    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
-        unimplemented!()
-    }
-
     // It is prohibited to use any cell from the state after the guard's disposal.
     async fn load_and_pin_state(&self, block_id: &BlockIdExt) -> Result<PinnedShardStateGuard> {
         unimplemented!()
     }
+    // Protects stored state from GC (including persistent one) until the guard's disposal
+    fn pin_state(&self, block_id: &BlockIdExt) -> Result<PinGuard> {
+        unimplemented!()
+    }
+    // Returns count of pinned states and total count of pins
+    fn pinned_states_stats(&self) -> Result<(usize, u32)> {
+        unimplemented!()
+    }
+    // Returns Merkle proof of the account's shard state at given masterchain block
+    // and the proof link of the shard block
+    async fn generate_account_proof(
+        &self,
+        mc_block_id: &BlockIdExt,
+        account: &AccountIdPrefixFull
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        unimplemented!()
+    }
+    // Returns the latest known account state with Merkle proof and proof chain of its block.
+    // Fails with StateNotAvailable error if the state is garbage collected
+    async fn generate_account_state_proof(
+        &self,
+        workchain_id: i32,
+        account_id: &AccountId
+    ) -> Result<AccountStateProof> {
+        unimplemented!()
+    }
+    // Indexed transactions of account with masterchain seqno in given range, both ends included.
+    // Fails with NotIndexed error if the index is disabled
+    fn account_transactions(
+        &self,
+        workchain_id: i32,
+        account_id: &AccountId,
+        from_seq_no: u32,
+        to_seq_no: u32,
+        limit: usize,
+        continuation: Option<TxContinuation>
+    ) -> Result<(Vec<TxLocation>, Option<TxContinuation>)> {
+        unimplemented!()
+    }
+    async fn load_account_transaction(&self, location: &TxLocation) -> Result<Vec<u8>> {
+        unimplemented!()
+    }
     async fn load_persistent_state_size(&self, block_id: &BlockIdExt) -> Result<u64> {
         unimplemented!()
     }
@@ -547,12 +1004,10 @@ pub trait EngineOperations : Sync + Send {
     ) -> Result<Vec<u8>> {
         unimplemented!()
     }
-    async fn wait_state(
-        self: Arc<Self>,
-        id: &BlockIdExt,
-        timeout_ms: Option<u64>,
-        allow_block_downloading: bool
-    ) -> Result<Arc<ShardStateStuff>> {
+    async fn open_persistent_state_reader(
+        &self,
+        handle: &Arc<BlockHandle>
+    ) -> Result<PersistentStateReader> {
         unimplemented!()
     }
     async fn store_state(
@@ -578,28 +1033,18 @@ pub trait EngineOperations : Sync + Send {
     fn store_block_prev1(&self, handle: &Arc<BlockHandle>, prev: &BlockIdExt) -> Result<()> {
         unimplemented!()
     }
-    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
-        unimplemented!()
-    }
     fn store_block_prev2(&self, handle: &Arc<BlockHandle>, prev2: &BlockIdExt) -> Result<()> {
         unimplemented!()
     }
-    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
-        unimplemented!()
-    }
     fn store_block_next1(&self, handle: &Arc<BlockHandle>, next: &BlockIdExt) -> Result<()> {
         unimplemented!()
     }
-    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
-        unimplemented!()
-    }
     fn store_block_next2(&self, handle: &Arc<BlockHandle>, next2: &BlockIdExt) -> Result<()> {
         unimplemented!()
     }
-    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+    fn store_queue_update_verified(&self, handle: &Arc<BlockHandle>) -> Result<bool> {
         unimplemented!()
     }
-
     // Global node's state
 
     async fn check_sync(&self) -> Result<bool> {
@@ -714,6 +1159,11 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
+    // Is got from node config
+    fn trusted_key_block(&self) -> Option<&BlockIdExt> {
+        unimplemented!()
+    }
+
     fn test_bundles_config(&self) -> &CollatorTestBundlesGeneralConfig {
         unimplemented!()
     }
@@ -722,6 +1172,24 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
+    // Collates next block of the shard with patched config, the candidate is thrown away
+    async fn collate_dry_run(
+        self: Arc<Self>,
+        shard: ShardIdent,
+        config_patch: ConfigPatch
+    ) -> Result<DryRunReport> {
+        unimplemented!()
+    }
+
+    // Validates stored block again, inputs are saved as collator test bundle if path is given
+    async fn replay_block_validation(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        bundle_path: Option<&str>
+    ) -> Result<ReplayOutcome> {
+        unimplemented!()
+    }
+
     fn db_root_dir(&self) -> Result<&str> {
         Ok(TonNodeConfig::DEFAULT_DB_ROOT)
     }
@@ -738,67 +1206,78 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
-    // I/O
+    async fn check_db_consistency(&self, mode: RepairMode) -> Result<ConsistencyReport> {
+        unimplemented!()
+    }
 
-    async fn broadcast_to_public_overlay(
-        &self, 
-        to: &AccountIdPrefixFull, 
-        data: &[u8]
-    ) -> Result<BroadcastSendInfo> {
-        unimplemented!()    
+    async fn storage_usage_report(&self) -> Result<StorageUsageReport> {
+        unimplemented!()
     }
 
-    async fn send_block_broadcast(&self, broadcast: BlockBroadcast) -> Result<()> {
+    async fn node_status(&self) -> NodeStatus {
         unimplemented!()
     }
 
-    async fn send_queue_update_broadcast(&self, broadcast: QueueUpdateBroadcast) -> Result<()> {
+    // Signed by the node key, rebuilt only when the masterchain advances
+    async fn network_snapshot(&self) -> Result<SignedSnapshot> {
         unimplemented!()
     }
 
-    async fn send_mesh_update_broadcast(&self, broadcast: MeshUpdateBroadcast) -> Result<()> {
+    // Stats of the last archives GC pass by disk watermarks, None if there was no pass yet
+    fn archives_gc_stats(&self) -> Result<Option<ArchivesGcStats>> {
         unimplemented!()
     }
 
-    async fn send_top_shard_block_description(
-        &self,
-        tbd: Arc<TopBlockDescrStuff>,
-        cc_seqno: u32,
-        is_resend: bool,
-    ) -> Result<()> {
+    // Active neighbours of full node overlays with their quality scores
+    fn neighbours_quality(&self) -> Vec<(Arc<OverlayShortId>, Arc<KeyId>, NeighbourQuality)> {
         unimplemented!()
     }
 
-    async fn redirect_external_message(&self, message_data: &[u8], id: UInt256) -> Result<()> {
+    // Collator and signers of applied block, None if the block or its proof is unknown
+    async fn block_provenance(&self, id: &BlockIdExt) -> Result<Option<BlockProvenance>> {
         unimplemented!()
     }
 
-    // Remp
+    // Queues of external messages prevalidation with dropped messages per lane and source
+    fn ext_messages_prevalidation_stats(
+        &self
+    ) -> (PrevalidationStats, Vec<(PrevalidationLane, Arc<KeyId>, u64)>) {
+        unimplemented!()
+    }
 
-    fn send_remp_message(&self, to: Arc<KeyId>, message: &RempMessage) -> Result<()> {
+    // Full node states (last applied block etc.) to move them to other node
+    async fn export_node_state(&self) -> Result<Vec<(String, BlockIdExt)>> {
         unimplemented!()
     }
 
-    async fn send_remp_receipt(&self, to: Arc<KeyId>, receipt: RempReceipt) -> Result<()> {
+    fn import_node_state(&self, entries: &[(String, BlockIdExt)], overwrite: bool) -> Result<usize> {
         unimplemented!()
     }
 
-    fn sign_remp_receipt(&self, receipt: &RempReceipt) -> Result<Vec<u8>> {
+    // All full node and validator states with decoded block ids, for debugging
+    fn list_full_node_state_keys(&self) -> Result<Vec<NodeStateEntry>> {
         unimplemented!()
     }
 
-    async fn check_remp_duplicate(&self, message_id: &UInt256) -> Result<RempDuplicateStatus> {
+    fn list_validator_state_keys(&self) -> Result<Vec<NodeStateEntry>> {
         unimplemented!()
     }
 
-    async fn push_message_to_remp(&self, data: ton_api::ton::bytes) -> Result<()> {
+    // Applies options of new config which can be changed without restart
+    fn reload_config(&self, new: TonNodeConfig) -> Result<ReloadReport> {
         unimplemented!()
     }
 
-    fn remp_capability(&self) -> bool { 
-        false 
+    fn reload_config_from_file(&self) -> Result<ReloadReport> {
+        unimplemented!()
     }
 
+    async fn encrypt_validator_keystore(&self) -> Result<usize> {
+        unimplemented!()
+    }
+
+    // Remp
+
     fn smft_capability(&self) -> bool { 
         false 
     }
@@ -811,8 +1290,8 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
-    fn set_remp_core_interface(&self, rci: Arc<dyn RempCoreInterface>) -> Result<()> {
-        unimplemented!()
+    fn apply_throttle(&self) -> Option<Arc<ApplyThrottle>> {
+        None
     }
 
     // Boot specific operations
@@ -825,11 +1304,25 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
+    // Notifications are emitted when block is marked as applied for the first time
+    fn subscribe_applied_blocks(&self, filter: ShardFilter) -> Result<AppliedBlockStream> {
+        unimplemented!()
+    }
+
+    // Starts GC pass of given kind or returns ticket of the pass in flight
+    fn trigger_gc(self: Arc<Self>, kind: GcKind) -> Result<GcTicket> {
+        unimplemented!()
+    }
+
+    fn gc_status(&self, ticket: &GcTicket) -> Result<GcProgress> {
+        unimplemented!()
+    }
+
     async fn get_archive_id(&self, mc_seq_no: u32) -> Option<u64> {
         unimplemented!()
     }
 
-    async fn get_archive_slice(&self, archive_id: u64, offset: u64, limit: u32) -> Result<Vec<u8>> {
+    async fn get_archive_slice(&self, archive_id: u64, offset: u64, limit: u32) -> Result<ArchiveSliceData> {
         unimplemented!()
     }
 
@@ -841,13 +1334,41 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
-    #[cfg(feature = "telemetry")]
-    fn full_node_telemetry(&self) -> &FullNodeTelemetry {
+    // Downloads consecutive masterchain blocks, only peers serving ranges are asked
+    async fn download_block_range(&self, query: &BlockRangeQuery) -> Result<DownloadedBlockRange> {
+        unimplemented!()
+    }
+
+    // Max masterchain seqno known from broadcasts and downloads
+    fn last_known_mc_block_seqno(&self) -> u32 {
+        unimplemented!()
+    }
+
+    fn sync_download_concurrency(&self) -> usize {
+        unimplemented!()
+    }
+
+    fn sync_max_downloaded_archives(&self) -> usize {
+        unimplemented!()
+    }
+
+    // Count of threads checking block proofs of imported archive
+    fn sync_proof_check_threads(&self) -> usize {
+        unimplemented!()
+    }
+
+    // zstd level of served block data, 0 means no compression
+    fn block_compression_level(&self) -> i32 {
+        unimplemented!()
+    }
+
+    // Checks capability reported by peer from any full node overlay
+    fn peer_has_capability(&self, peer: &Arc<KeyId>, capability: i64) -> bool {
         unimplemented!()
     }
 
     #[cfg(feature = "telemetry")]
-    fn remp_core_telemetry(&self) -> &RempCoreTelemetry {
+    fn full_node_telemetry(&self) -> &FullNodeTelemetry {
         unimplemented!()
     }
 
@@ -861,6 +1382,11 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
+    #[cfg(feature = "telemetry")]
+    fn collation_timing_telemetry(&self) -> &CollationTimingTelemetry {
+        unimplemented!()
+    }
+
     #[cfg(feature = "telemetry")]
     fn full_node_service_telemetry(&self) -> &FullNodeNetworkTelemetry {
         unimplemented!()
@@ -906,6 +1432,10 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!();
     }
 
+    fn stopper(&self) -> &Stopper {
+        unimplemented!();
+    }
+
     fn release_stop(&self, mask: u32) {
         unimplemented!();
     }
@@ -996,6 +1526,29 @@ pub trait EngineOperations : Sync + Send {
         unimplemented!()
     }
 
+    // Replayed updates are skipped, ones arrived before their previous queues are buffered
+    fn apply_mesh_queue_update(
+        &self,
+        nw_id: i32,
+        shard: &ShardIdent,
+        update: MeshQueueUpdate
+    ) -> Result<MeshQueueUpdateResult> {
+        unimplemented!()
+    }
+
+    fn drop_mesh_network(&self, nw_id: i32) -> Result<()> {
+        unimplemented!()
+    }
+
+    async fn prune_mesh_queues(&self, nw_id: i32) -> Result<PruneStats> {
+        unimplemented!()
+    }
+
+    // Lags of workchains in consuming queues, for every pair of source and destination
+    fn queue_lag_table(&self) -> Vec<QueueLag> {
+        unimplemented!()
+    }
+
     fn create_handle_for_mesh(
         &self,
         block: &BlockStuff // mesh kit or update
@@ -1014,6 +1567,19 @@ pub struct ChainRange {
     pub shard_blocks: Vec<BlockIdExt>
 }
 
+// Status change of REMP message recorded in message cache
+#[cfg(feature = "external_db")]
+#[derive(Clone, Debug)]
+pub struct RempStatusTransition {
+    pub message_id: UInt256,
+    pub message_uid: UInt256,
+    // Peer which has sent the message, None if it is not known
+    pub origin: Option<Arc<KeyId>>,
+    pub status: RempMessageStatus,
+    pub master_cc: u32,
+    pub timestamp_ms: u64,
+}
+
 /// External DB should implement this trait and put itself into engine's new function
 #[cfg(feature = "external_db")]
 #[async_trait::async_trait]
@@ -1037,6 +1603,8 @@ pub trait ExternalDb : Sync + Send {
         status: &RempReceipt,
         signature: &[u8]
     ) -> Result<()>;
+    fn process_remp_history_enabled(&self) -> bool;
+    async fn process_remp_status_transition(&self, transition: &RempStatusTransition) -> Result<()>;
 }
 
 pub enum Server {
@@ -1061,6 +1629,11 @@ pub enum RempDuplicateStatus {
 pub trait RempCoreInterface: Sync + Send {
     async fn process_incoming_message(&self, message: &RempMessage, source: Arc<KeyId>) -> Result<()>;
     fn check_remp_duplicate(&self, message_id: &UInt256) -> Result<RempDuplicateStatus>;
+    /// Pairs are (message id, message uid), true is returned for messages which may be collated
+    fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>>;
+    /// Starts tracing of the message (if not yet) and returns its events collected so far
+    fn trace_message(&self, message_id: &UInt256) -> Result<Vec<MessageTraceEvent>>;
+    fn message_cache_snapshot(&self) -> Result<MessageCacheSnapshot>;
 }
 
 #[async_trait::async_trait]
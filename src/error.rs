@@ -11,7 +11,10 @@
 * limitations under the License.
 */
 
-#[derive(Debug, thiserror::Error)]
+use ever_block::{error, Error};
+use storage::error::StorageError;
+
+#[derive(Clone, Debug, thiserror::Error)]
 pub enum NodeError {
     #[error("Invalid argument: {0}")]
     InvalidArg(String),
@@ -23,8 +26,136 @@ pub enum NodeError {
     ValidatorReject(String),
     #[error("{0}")]
     ValidatorSoftReject(String),
+    #[error("External message created at {created_at} is out of allowed time window {min_time}..={max_time}")]
+    ExtMessageOutOfTimeWindow { created_at: u32, min_time: u32, max_time: u32 },
+    // External message breaks acceptance limits of its destination workchain
+    #[error("External message to workchain {workchain_id} is rejected: {reason}")]
+    ExtMessageRejected { workchain_id: i32, reason: crate::ext_messages::limits::ExtMessageRejectReason },
+    #[error("State of block {0} is not available, probably it was garbage collected")]
+    StateNotAvailable(ever_block::BlockIdExt),
+    // Kinds below tell callers whether the operation may be retried.
+    // The message is kept as is, so they are displayed like untyped errors.
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    NotYetApplied(String),
+    #[error("{0}")]
+    DbCorruption(String),
+    #[error("{0}")]
+    NetworkTimeout(String),
+    // Proof contradicts trusted data, e.g. key block from config
+    #[error("{0}")]
+    InvalidProof(String),
+    #[error("{0}")]
+    Cancelled(String),
+    // Local wait is over, unlike NetworkTimeout nobody was asked
+    #[error("{0}")]
+    Timeout(String),
+    // Top shard block description is rejected before it gets into the pool
+    #[error("{0}")]
+    TopBlockBadSignatures(String),
+    #[error("{0}")]
+    TopBlockBrokenChain(String),
+    #[error("Top shard block {id} refers to masterchain block {ref_mc_seq_no} older than {min_mc_seq_no}")]
+    TopBlockStaleRef { id: ever_block::BlockIdExt, ref_mc_seq_no: u32, min_mc_seq_no: u32 },
+    // Queue update doesn't match out queue hashes declared in its block
+    #[error("{0}")]
+    QueueUpdateMismatch(String),
+    // Account is too big to be served with proof
+    #[error("Account state proof takes {size} bytes, more than {max_size} allowed")]
+    AccountProofTooLarge { size: usize, max_size: usize },
+    // Control client key is unknown or has no category of the command
+    #[error("Control key {key_id} is not permitted to run {command}")]
+    PermissionDenied { key_id: String, command: String },
+    // Node is started on a read-only database, so nothing can be changed
+    #[error("{command} is not allowed: node is running in read-only mode")]
+    ReadOnlyMode { command: String },
+    // Limit of concurrent control commands of the category is reached
+    #[error("{command} is refused: {running} commands of {category} category are running")]
+    ControlBusy { command: String, category: String, running: usize },
+    #[error("{command} is not finished in {timeout_ms} ms")]
+    ControlTimeout { command: String, timeout_ms: u64 },
+    // Blocks would get wrong timestamps, so collation waits for the clock to be fixed
+    #[error("Local clock drift {drift_ms} ms is more than {max_drift_ms} ms allowed for collation")]
+    ClockDrift { drift_ms: i64, max_drift_ms: u64 },
+    #[error("Account transactions are not indexed, set `account_transactions_index` in config")]
+    NotIndexed,
     #[cfg(feature = "external_db")]
     #[error("{0}")]
     #[allow(dead_code)]
     Other(String),
 }
+
+impl NodeError {
+
+    // Retrying doesn't help after these errors
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self, 
+            Self::DbCorruption(_) | Self::InvalidProof(_) | Self::Cancelled(_) | Self::ReadOnlyMode { .. }
+        )
+    }
+
+    // Description is bad by itself, so the peer which has sent it is to blame
+    pub fn is_top_block_rejection(err: &Error) -> bool {
+        matches!(
+            err.downcast_ref::<NodeError>(),
+            Some(Self::TopBlockBadSignatures(_) | Self::TopBlockBrokenChain(_) | Self::TopBlockStaleRef { .. })
+        )
+    }
+
+    // Node is stopping, the operation is to be given up without complaints
+    pub fn is_cancelled(err: &Error) -> bool {
+        matches!(err.downcast_ref::<NodeError>(), Some(Self::Cancelled(_)))
+    }
+
+    // Queue update is bad by itself, so the peer which has sent it is to blame
+    pub fn is_queue_update_mismatch(err: &Error) -> bool {
+        matches!(err.downcast_ref::<NodeError>(), Some(Self::QueueUpdateMismatch(_)))
+    }
+
+    // Typed error is kept as is, storage errors are converted, others get given kind
+    pub fn classify(err: Error, kind: impl FnOnce(String) -> Self) -> Self {
+        let err = match err.downcast::<NodeError>() {
+            Ok(err) => return err,
+            Err(err) => err
+        };
+        match err.downcast::<StorageError>() {
+            Ok(err) => err.into(),
+            Err(err) => kind(err.to_string())
+        }
+    }
+
+}
+
+impl From<StorageError> for NodeError {
+    fn from(err: StorageError) -> Self {
+        let msg = err.to_string();
+        match err {
+            StorageError::KeyNotFound(..) | StorageError::StateIsAllowedToGc(_) => Self::NotFound(msg),
+            StorageError::DbIsDropped => Self::Cancelled(msg),
+            StorageError::OutOfRange => Self::DbCorruption(msg),
+            StorageError::HasActiveTransactions | StorageError::ReadOnly => Self::InvalidOperation(msg),
+        }
+    }
+}
+
+// Errors of unknown kind are retried as before
+pub fn is_retryable(err: &Error) -> bool {
+    match err.downcast_ref::<NodeError>() {
+        Some(err) => err.is_retryable(),
+        None => match err.downcast_ref::<StorageError>() {
+            Some(StorageError::DbIsDropped) | Some(StorageError::OutOfRange) |
+            Some(StorageError::ReadOnly) => false,
+            _ => true
+        }
+    }
+}
+
+// Copy of the error for other awaiters of the same operation, typed error keeps its kind
+pub fn copy_error(err: &Error) -> Error {
+    match err.downcast_ref::<NodeError>() {
+        Some(err) => error!(err.clone()),
+        None => error!("{}", err)
+    }
+}
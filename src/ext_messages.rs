@@ -11,14 +11,18 @@
 * limitations under the License.
 */
 
-use crate::engine::now_duration;
-use adnl::common::add_unbound_object_to_map_with_update;
-use lockfree::map::Map;
-use std::sync::{Arc, atomic::{AtomicU64, Ordering, AtomicU32}};
+use crate::{engine::now_duration, error::NodeError};
+use std::{
+    collections::{HashMap, VecDeque}, sync::{Arc, atomic::{AtomicU64, Ordering}}
+};
 use ton_api::ton::ton_node::{RempMessageStatus, RempMessageLevel};
-use ever_block::{Deserializable, ShardIdent, Message};
+use ever_block::{AccountId, Deserializable, Serializable, ShardIdent, Message};
 use ever_block::{Result, types::UInt256, fail, read_boc};
 
+pub mod limits;
+pub mod prevalidation;
+pub mod rate_limiter;
+
 #[cfg(test)]
 #[path = "tests/test_ext_messages.rs"]
 mod tests;
@@ -118,108 +122,287 @@ impl MessageKeeper {
     }
 }
 
-#[derive(Clone)]
-struct MessageDescription {
-    id: UInt256,
-    workchain_id: i32,
+/// Limits of external messages pool, `None` means unlimited
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MessagesPoolLimits {
+    pub max_messages: Option<u32>,
+    pub max_bytes: Option<u64>,
+    pub max_account_messages: Option<u32>,
+}
+
+/// Occupancy of external messages pool
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessagesPoolStats {
+    pub accounts: usize,
+    pub messages: usize,
+    pub bytes: u64,
+    pub evicted: u64,
+    pub expired: u64,
+}
+
+// workchain id and destination account id
+type AccountKey = (i32, AccountId);
+
+struct PoolEntry {
+    keeper: MessageKeeper,
+    account: AccountKey,
+    seqno: u64,
+    timestamp: u32,
+    size: u64,
+}
+
+struct AccountBucket {
+    // seqnos and hashes of messages in order of arrival
+    queue: VecDeque<(u64, UInt256)>,
     prefix: u64,
+    bytes: u64,
 }
 
-struct OrderMap {
-    seqno: Arc<AtomicU32>,
-    map: Map<u32, MessageDescription>,
+struct PoolState {
+    // map by hash of message
+    messages: HashMap<UInt256, PoolEntry>,
+    // messages by destination account
+    buckets: HashMap<AccountKey, AccountBucket>,
+    // messages received before this time are expired
+    min_timestamp: u32,
+    next_seqno: u64,
+    bytes: u64,
+    evicted: u64,
+    expired: u64,
 }
 
-impl OrderMap {
-    fn new(id: UInt256, workchain_id: i32, prefix: u64) -> Self {
-        let seqno = Arc::new(AtomicU32::new(1));
-        let map = Map::new();
-        map.insert(0, MessageDescription { id, workchain_id, prefix });
-        Self { seqno, map }
+fn is_expired(timestamp: u32, now: u32) -> bool {
+    timestamp.saturating_add(MESSAGE_LIFETIME) < now
+}
+
+// Size of message is estimated by data of its cells
+fn message_size(message: &Message) -> Result<u64> {
+    let cell = message.serialize()?;
+    Ok((cell.tree_bits_count() + 7) / 8)
+}
+
+impl PoolState {
+
+    fn new(min_timestamp: u32) -> Self {
+        Self {
+            messages: HashMap::new(),
+            buckets: HashMap::new(),
+            min_timestamp,
+            next_seqno: 0,
+            bytes: 0,
+            evicted: 0,
+            expired: 0,
+        }
+    }
+
+    fn stats(&self) -> MessagesPoolStats {
+        MessagesPoolStats {
+            accounts: self.buckets.len(),
+            messages: self.messages.len(),
+            bytes: self.bytes,
+            evicted: self.evicted,
+            expired: self.expired,
+        }
+    }
+
+    fn insert(&mut self, id: UInt256, account: AccountKey, keeper: MessageKeeper, now: u32, size: u64) {
+        let seqno = self.next_seqno;
+        self.next_seqno += 1;
+        let bucket = self.buckets.entry(account.clone()).or_insert_with(|| AccountBucket {
+            queue: VecDeque::new(),
+            prefix: account.1.clone().get_next_u64().unwrap_or_default(),
+            bytes: 0,
+        });
+        bucket.queue.push_back((seqno, id.clone()));
+        bucket.bytes += size;
+        self.bytes += size;
+        self.messages.insert(id, PoolEntry { keeper, account, seqno, timestamp: now, size });
+    }
+
+    // Removes message from its account bucket, empty bucket is removed as well
+    fn remove(&mut self, id: &UInt256) -> Option<PoolEntry> {
+        let entry = self.messages.remove(id)?;
+        self.bytes -= entry.size;
+        if let Some(bucket) = self.buckets.get_mut(&entry.account) {
+            if let Ok(index) = bucket.queue.binary_search_by_key(&entry.seqno, |(seqno, _)| *seqno) {
+                bucket.queue.remove(index);
+            }
+            bucket.bytes -= entry.size;
+            if bucket.queue.is_empty() {
+                self.buckets.remove(&entry.account);
+            }
+        }
+        Some(entry)
+    }
+
+    fn remove_expired(&mut self, id: &UInt256) {
+        if self.remove(id).is_some() {
+            self.expired += 1;
+            log::debug!(
+                target: EXT_MESSAGES_TRACE_TARGET,
+                "removing external message {:x} because it is expired", id
+            );
+        }
+    }
+
+    // Drops expired messages from the head of account queue, returns false if time is out
+    fn drop_expired(&mut self, account: &AccountKey, now: u32, finish_time_ms: u64) -> bool {
+        for checked in 1.. {
+            let id = match self.buckets.get(account).and_then(|bucket| bucket.queue.front()) {
+                Some((_, id)) => id.clone(),
+                None => break
+            };
+            match self.messages.get(&id) {
+                Some(entry) if !is_expired(entry.timestamp, now) => break,
+                _ => self.remove_expired(&id)
+            }
+            if checked % 256 == 0 && finish_time_ms < now_duration().as_millis() as u64 {
+                return false
+            }
+        }
+        true
+    }
+
+    fn clear_expired_messages(&mut self, now: u32) {
+        let min_timestamp = now.saturating_sub(MESSAGE_LIFETIME);
+        if min_timestamp <= self.min_timestamp {
+            return
+        }
+        self.min_timestamp = min_timestamp;
+        let accounts = self.buckets.keys().cloned().collect::<Vec<_>>();
+        for account in accounts {
+            self.drop_expired(&account, now, u64::MAX);
+        }
+    }
+
+    // Evicts the oldest messages of the largest accounts until a message of given size fits
+    // Makes room for given count of messages of given total size
+    fn evict(&mut self, limits: &MessagesPoolLimits, count: usize, size: u64) {
+        loop {
+            let over_messages = limits.max_messages
+                .map_or(false, |max| self.messages.len() + count > max as usize);
+            let over_bytes = limits.max_bytes.map_or(false, |max| self.bytes + size > max);
+            if !over_messages && !over_bytes {
+                break
+            }
+            // Account of the oldest message wins among equal ones
+            let largest = self.buckets.values()
+                .filter_map(|bucket| {
+                    let (seqno, id) = bucket.queue.front()?;
+                    let len = bucket.queue.len() as u64;
+                    let size = if over_bytes { (bucket.bytes, len) } else { (len, bucket.bytes) };
+                    Some((size, std::cmp::Reverse(*seqno), id))
+                })
+                .max_by_key(|(size, seqno, _)| (*size, *seqno))
+                .map(|(_, _, id)| id.clone());
+            let Some(id) = largest else {
+                break
+            };
+            self.remove(&id);
+            self.evicted += 1;
+            log::debug!(
+                target: EXT_MESSAGES_TRACE_TARGET,
+                "evicted external message {:x} because pool is full", id
+            );
+        }
     }
-    fn insert(&self, id: UInt256, workchain_id: i32, prefix: u64) {
-        let seqno = self.seqno.fetch_add(1, Ordering::Relaxed);
-        self.map.insert(seqno, MessageDescription { id, workchain_id, prefix });
+
+    // Finds the first active message of account starting from given seqno,
+    // expired messages are removed on the way
+    fn next_active(&mut self, account: &AccountKey, seqno: u64, now: u32) -> Option<(u64, Arc<Message>, UInt256)> {
+        let bucket = self.buckets.get(account)?;
+        let start = bucket.queue.partition_point(|(s, _)| *s < seqno);
+        let mut expired = Vec::new();
+        let mut found = None;
+        for (seqno, id) in bucket.queue.range(start..) {
+            let Some(entry) = self.messages.get(id) else {
+                continue
+            };
+            if is_expired(entry.timestamp, now) {
+                expired.push(id.clone());
+            } else if entry.keeper.check_active(now) {
+                found = Some((*seqno, entry.keeper.message().clone(), id.clone()));
+                break
+            }
+        }
+        for id in expired {
+            self.remove_expired(&id);
+        }
+        found
     }
 }
 
 pub struct MessagesPool {
-    // map by hash of message
-    messages: Map<UInt256, MessageKeeper>,
-    // map by timestamp, inside map by seqno for hash of message, workchain_id and prefix of dst address
-    order: Map<u32, Arc<OrderMap>>,
-    // minimal timestamp
-    min_timestamp: AtomicU32,
-
-    // maximum number of messages in pool
-    maximum_queue_length: Option<u32>,
-
-    // total number of messages in pool
-    total_messages: AtomicU32,
-    #[cfg(test)]
-    total_in_order: AtomicU32,
+    state: parking_lot::Mutex<PoolState>,
+    limits: parking_lot::RwLock<MessagesPoolLimits>,
 }
 
 impl MessagesPool {
 
     pub fn new(now: u32, maximum_queue_length: Option<u32>) -> Self {
-        metrics::gauge!("ext_messages_len", 0f64);
-        metrics::gauge!("ext_messages_expired", 0f64);
+        let limits = MessagesPoolLimits {
+            max_messages: maximum_queue_length,
+            ..Default::default()
+        };
+        Self::with_limits(now, limits)
+    }
+
+    pub fn with_limits(now: u32, limits: MessagesPoolLimits) -> Self {
+        let state = PoolState::new(now);
+        Self::update_metrics(&state);
         Self {
-            messages: Map::with_hasher(Default::default()),
-            order: Map::with_hasher(Default::default()),
-            min_timestamp: AtomicU32::new(now),
-            maximum_queue_length,
-            total_messages: AtomicU32::new(0),
-            #[cfg(test)]
-            total_in_order: AtomicU32::new(0),
+            state: parking_lot::Mutex::new(state),
+            limits: parking_lot::RwLock::new(limits),
         }
     }
 
-    pub fn new_message_raw(&self, data: &[u8], now: u32) -> Result<()> {
-        let (id, message) = create_ext_message(data)?;
-        let message = Arc::new(message);
-        self.new_message(&id, message, now)?;
-        Ok(())
+    pub fn limits(&self) -> MessagesPoolLimits {
+        self.limits.read().clone()
+    }
+
+    // Lowered limits are applied at once by eviction, but messages above new limit
+    // per account are kept until they are processed or expired
+    pub fn set_limits(&self, limits: MessagesPoolLimits) {
+        log::info!(target: EXT_MESSAGES_TRACE_TARGET, "external messages pool limits: {:?}", limits);
+        *self.limits.write() = limits.clone();
+        let mut state = self.state.lock();
+        state.evict(&limits, 0, 0);
+        Self::update_metrics(&state);
     }
 
     pub fn new_message(&self, id: &UInt256, message: Arc<Message>, now: u32) -> Result<()> {
-        let timestamp = self.min_timestamp.load(Ordering::Relaxed);
-        if now < timestamp {
-            fail!("now {} is less than minimum {} for {:x}", now, timestamp, id)
+        let account = (
+            message.dst_workchain_id().unwrap_or_default(),
+            message.int_dst_account_id().unwrap_or_default()
+        );
+        let size = message_size(&message)?;
+        let mut state = self.state.lock();
+        if now < state.min_timestamp {
+            fail!("now {} is less than minimum {} for {:x}", now, state.min_timestamp, id)
         }
-        if self.messages.get(id).is_some() {
+        if state.messages.contains_key(id) {
             return Ok(());
         }
-        for timestamp in self.min_timestamp.load(Ordering::Relaxed)..now.saturating_sub(MESSAGE_LIFETIME) {
-            self.clear_expired_messages(timestamp, u64::MAX);
-            self.increment_min_timestamp(timestamp);
+        state.clear_expired_messages(now);
+        let limits = self.limits.read().clone();
+        if limits.max_messages == Some(0) {
+            fail!("maximum number of messages in pool is reached")
+        }
+        if let Some(max_bytes) = limits.max_bytes {
+            if size > max_bytes {
+                fail!("external message {:x} of {} bytes doesn't fit into pool", id, size)
+            }
         }
-        if let Some(maximum_queue_length) = self.maximum_queue_length {
-            if self.total_messages.load(Ordering::Relaxed) >= maximum_queue_length {
-                fail!("maximum number of messages in pool is reached")
+        if let Some(max_account_messages) = limits.max_account_messages {
+            if state.buckets.get(&account).map_or(0, |bucket| bucket.queue.len()) >= max_account_messages as usize {
+                fail!("maximum number of messages in pool for account {}:{:x} is reached", account.0, account.1)
             }
         }
+        state.evict(&limits, 1, size);
 
         log::debug!(target: EXT_MESSAGES_TRACE_TARGET, "adding external message {:x}", id);
-        let workchain_id = message.dst_workchain_id().unwrap_or_default();
-        let prefix = message.int_dst_account_id().map_or(0, |mut slice| slice.get_next_u64().unwrap_or_default());
-        self.messages.insert(id.clone(), MessageKeeper::new(message));
-        self.total_messages.fetch_add(1, Ordering::Relaxed);
-        #[cfg(test)]
-        self.total_in_order.fetch_add(1, Ordering::Relaxed);
-        #[cfg(not(feature = "statsd"))]
-        metrics::increment_gauge!("ext_messages_len", 1f64);
-
-        add_unbound_object_to_map_with_update(&self.order, now, |map| {
-            if let Some(map) = map {
-                map.insert(id.clone(), workchain_id, prefix);
-                Ok(None)
-            } else {
-                let entry = Arc::new(OrderMap::new(id.clone(), workchain_id, prefix));
-                Ok(Some(entry))
-            }
-        })?;
+        state.insert(id.clone(), account, MessageKeeper::new(message), now, size);
+        Self::update_metrics(&state);
         Ok(())
     }
 
@@ -238,74 +421,45 @@ impl MessagesPool {
         _to_delete: Vec<(UInt256, i32)>, 
         now: u32
     ) -> Result<()> {
+        let mut state = self.state.lock();
         for (id, reason) in &to_delay {
-            let result = self.messages.remove_with(id, |(_, keeper)| {
-                if keeper.can_postpone() {
-                    log::debug!(
-                        target: EXT_MESSAGES_TRACE_TARGET,
-                        "complete_messages: postponed external message {:x} with reason {} while enumerating to_delay list",
-                        id, reason
-                    );
-                    keeper.postpone(now);
-                    false
-                } else {
-                    true
-                }
-            });
-            if result.is_some() {
+            let Some(entry) = state.messages.get(id) else {
+                continue
+            };
+            if entry.keeper.can_postpone() {
+                log::debug!(
+                    target: EXT_MESSAGES_TRACE_TARGET,
+                    "complete_messages: postponed external message {:x} with reason {} while enumerating to_delay list",
+                    id, reason
+                );
+                entry.keeper.postpone(now);
+            } else {
                 log::debug!(
                     target: EXT_MESSAGES_TRACE_TARGET,
                     "complete_messages: removing external message {:x} with reason {} because can't postpone",
                     id, reason,
                 );
-                #[cfg(not(feature = "statsd"))]
-                metrics::decrement_gauge!("ext_messages_len", 1f64);
-                self.total_messages.fetch_sub(1, Ordering::Relaxed);
+                state.remove(id);
             }
         }
+        Self::update_metrics(&state);
         Ok(())
     }
 
     pub fn total_messages(&self) -> u32 {
-        self.total_messages.load(Ordering::Relaxed)
+        self.state.lock().messages.len() as u32
     }
 
-    fn increment_min_timestamp(&self, timestamp: u32) {
-        let _ = self.min_timestamp.compare_exchange(timestamp, timestamp + 1, Ordering::Relaxed, Ordering::Relaxed);
+    pub fn stats(&self) -> MessagesPoolStats {
+        self.state.lock().stats()
     }
 
-    fn clear_expired_messages(&self, timestamp: u32, finish_time_ms: u64) -> bool {
-        let order = match self.order.get(&timestamp) {
-            Some(guard) => guard.val().clone(),
-            None => return true,
-        };
-        log::debug!(
-            target: EXT_MESSAGES_TRACE_TARGET,
-            "removing order map for timestamp {} because it is expired", timestamp
-        );
-        for seqno in 0..order.seqno.load(Ordering::Relaxed) {
-            if finish_time_ms < now_duration().as_millis() as u64 {
-                self.order.insert(timestamp, order);
-                return false;
-            }
-            if let Some(guard) = order.map.remove(&seqno) {
-                if let Some(guard) = self.messages.remove(&guard.val().id) {
-                    #[cfg(not(feature = "statsd"))]
-                    metrics::increment_gauge!("ext_messages_expired", 1f64);
-                    #[cfg(not(feature = "statsd"))]
-                    metrics::decrement_gauge!("ext_messages_len", 1f64);
-                    log::debug!(
-                        target: EXT_MESSAGES_TRACE_TARGET,
-                        "removing external message {:x} because it is expired", guard.key()
-                    );
-                    self.total_messages.fetch_sub(1, Ordering::Relaxed);
-                }
-                #[cfg(test)]
-                self.total_in_order.fetch_sub(1, Ordering::Relaxed);
-            }
-        }
-        self.order.remove(&timestamp);
-        true
+    fn update_metrics(state: &PoolState) {
+        metrics::gauge!("ext_messages_len", state.messages.len() as f64);
+        metrics::gauge!("ext_messages_accounts", state.buckets.len() as f64);
+        metrics::gauge!("ext_messages_bytes", state.bytes as f64);
+        metrics::gauge!("ext_messages_evicted", state.evicted as f64);
+        metrics::gauge!("ext_messages_expired", state.expired as f64);
     }
 }
 
@@ -316,82 +470,68 @@ impl MessagesPool {
     }
 
     pub fn has_messages(&self) -> bool {
-        self.messages.iter().next().is_some()
+        !self.state.lock().messages.is_empty()
     }
 
     pub fn clear(&mut self) {
-        self.messages.clear()
+        let state = self.state.get_mut();
+        *state = PoolState::new(state.min_timestamp)
     }
 }
 
+// Takes messages of accounts in turn, so each account gets a fair share of a block
 pub struct MessagePoolIter {
     pool: Arc<MessagesPool>,
-    shard: ShardIdent,
     now: u32,
-    timestamp: u32,
-    seqno: u32,
     finish_time_ms: u64,
+    // accounts of the shard with seqno of the next message to check
+    accounts: VecDeque<(AccountKey, u64)>,
 }
 
 impl MessagePoolIter {
     fn new(pool: Arc<MessagesPool>, shard: ShardIdent, now: u32, finish_time_ms: u64) -> Self {
-        let timestamp = pool.min_timestamp.load(Ordering::Relaxed);
+        // accounts are ordered by their oldest messages
+        let mut accounts = pool.state.lock().buckets.iter()
+            .filter(|(account, bucket)| shard.contains_prefix(account.0, bucket.prefix))
+            .filter_map(|(account, bucket)| Some((bucket.queue.front()?.0, account.clone())))
+            .collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|(seqno, _)| *seqno);
         Self {
             pool,
-            shard,
             now,
-            timestamp,
-            seqno: 0,
             finish_time_ms,
+            accounts: accounts.into_iter().map(|(_, account)| (account, 0)).collect(),
         }
     }
-
-    fn find_in_map(&mut self, map: &Map<u32, MessageDescription>) -> Option<(Arc<Message>, UInt256)> {
-        // if link is valid we check if message is for desired shard and is active
-        let descr = map.get(&self.seqno)?;
-        let keeper = self.pool.messages.get(&descr.val().id)?;
-        if self.shard.contains_prefix(descr.val().workchain_id, descr.val().prefix) && keeper.val().check_active(self.now) {
-            return Some((keeper.val().message().clone(), descr.val().id.clone()));
-        }
-        // let descr = map.get(&self.seqno)?.1.clone();
-        // let keeper = self.pool.messages.get(&descr.id)?.1.clone();
-        // if self.shard.contains_prefix(descr.workchain_id, descr.prefix) && keeper.check_active(self.now) {
-        //     return Some((keeper.message().clone(), descr.id));
-        // }
-        None
-    }
 }
 
 impl Iterator for MessagePoolIter {
     type Item = (Arc<Message>, UInt256);
 
     fn next(&mut self) -> Option<Self::Item> {
-        // iterate timestamp
-        while self.timestamp <= self.now {
+        while let Some((account, seqno)) = self.accounts.pop_front() {
             if self.finish_time_ms < now_duration().as_millis() as u64 {
                 return None;
             }
-            // check if this order map is expired
-            if self.timestamp + MESSAGE_LIFETIME < self.now {
-                if !self.pool.clear_expired_messages(self.timestamp, self.finish_time_ms) {
-                    return None;
-                }
-                // level was removed or not present try to move bottom margin
-                self.pool.increment_min_timestamp(self.timestamp);
-            } else if let Some(order) = self.pool.order.get(&self.timestamp).map(|guard| guard.val().clone()) {
-                while self.seqno < order.seqno.load(Ordering::Relaxed) {
-                    if self.finish_time_ms < now_duration().as_millis() as u64 {
-                        return None;
-                    }
-                    let result = self.find_in_map(&order.map);
-                    self.seqno += 1;
-                    if result.is_some() {
-                        return result;
-                    }
-                }
+            let mut state = self.pool.state.lock();
+            let expired = state.expired;
+            let in_time = state.drop_expired(&account, self.now, self.finish_time_ms);
+            let found = if in_time {
+                state.next_active(&account, seqno, self.now)
+            } else {
+                None
+            };
+            if state.expired != expired {
+                MessagesPool::update_metrics(&state);
+            }
+            if !in_time {
+                return None;
+            }
+            // account is skipped till the end of iteration if it has no more active messages
+            if let Some((seqno, message, id)) = found {
+                self.accounts.push_back((account, seqno + 1));
+                return Some((message, id));
             }
-            self.timestamp += 1;
-            self.seqno = 0;
         }
         None
     }
@@ -425,6 +565,37 @@ pub fn create_ext_message(data: &[u8]) -> Result<(UInt256, Message)> {
     }
 }
 
+/// Checks that message creation time fits into window 
+/// `now - max_age_secs ..= now + max_future_skew_secs`
+pub fn check_ext_message_time(
+    created_at: u32, 
+    now: u32, 
+    max_age_secs: u32, 
+    max_future_skew_secs: u32
+) -> Result<()> {
+    let min_time = now.saturating_sub(max_age_secs);
+    let max_time = now.saturating_add(max_future_skew_secs);
+    if created_at < min_time || created_at > max_time {
+        fail!(NodeError::ExtMessageOutOfTimeWindow { created_at, min_time, max_time })
+    }
+    Ok(())
+}
+
+/// The same as `create_ext_message`, but also rejects too old messages and messages from future.
+/// Messages which have no creation time in header are not checked.
+pub fn create_ext_message_with_time_check(
+    data: &[u8], 
+    now: u32, 
+    max_age_secs: u32, 
+    max_future_skew_secs: u32
+) -> Result<(UInt256, Message)> {
+    let (id, message) = create_ext_message(data)?;
+    if let Some((created_at, _lt)) = message.at_and_lt() {
+        check_ext_message_time(created_at, now, max_age_secs, max_future_skew_secs)?;
+    }
+    Ok((id, message))
+}
+
 pub fn get_level_and_level_change(status: &RempMessageStatus) -> (RempMessageLevel, i32) {
     match status {
         RempMessageStatus::TonNode_RempAccepted(a) => (a.level.clone(), 1),
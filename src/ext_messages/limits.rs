@@ -0,0 +1,171 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    config::ExtMessageLimitsConfig, error::NodeError,
+    ext_messages::{MAX_EXTERNAL_MESSAGE_DEPTH, MAX_EXTERNAL_MESSAGE_SIZE}
+};
+use std::{collections::{HashMap, HashSet}, fmt};
+use ever_block::{error, fail, ConfigParamEnum, ConfigParams, Message, Result, MASTERCHAIN_ID};
+
+#[cfg(test)]
+#[path = "../tests/test_ext_message_limits.rs"]
+mod tests;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExtMessageRejectReason {
+    TooLarge { size: u64, max_size: u32 },
+    TooDeepBody { depth: u16, max_depth: u16 },
+    ImportFeeTooLow { fee: u128, min_fee: u64 },
+    UntrackedWorkchain,
+    UnknownWorkchain,
+}
+
+impl fmt::Display for ExtMessageRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooLarge { size, max_size } =>
+                write!(f, "message size {} exceeds limit {}", size, max_size),
+            Self::TooDeepBody { depth, max_depth } =>
+                write!(f, "body cell depth {} exceeds limit {}", depth, max_depth),
+            Self::ImportFeeTooLow { fee, min_fee } =>
+                write!(f, "import fee {} is less than minimum {}", fee, min_fee),
+            Self::UntrackedWorkchain => write!(f, "workchain is not tracked by the node"),
+            Self::UnknownWorkchain => write!(f, "workchain is absent in network config"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EffectiveLimits {
+    pub max_size: u32,
+    pub max_body_depth: u16,
+    pub min_import_fee: u64,
+}
+
+// Limits of the latest known network config
+#[derive(Clone, Debug, Default)]
+struct NetworkLimits {
+    max_size: Option<u32>,
+    max_depth: Option<u16>,
+    // None while the config is not known
+    workchains: Option<HashSet<i32>>,
+}
+
+// Acceptance limits of external messages. Values of the network config (param 43)
+// take precedence over the node config ones, which are used when the network
+// doesn't define the limit. Minimum import fee is set by the node config only.
+pub struct ExtMessageLimits {
+    node_limits: HashMap<i32, ExtMessageLimitsConfig>,
+    processed_workchain: Option<i32>,
+    network: parking_lot::RwLock<NetworkLimits>,
+}
+
+impl ExtMessageLimits {
+
+    pub fn new(node_limits: HashMap<i32, ExtMessageLimitsConfig>, processed_workchain: Option<i32>) -> Self {
+        Self {
+            node_limits,
+            processed_workchain,
+            network: parking_lot::RwLock::new(NetworkLimits::default()),
+        }
+    }
+
+    // Takes limits and workchains of the masterchain config, it is called on every key block
+    pub fn refresh(&self, config: &ConfigParams) -> Result<()> {
+        let (max_size, max_depth) = match config.config(43)? {
+            Some(ConfigParamEnum::ConfigParam43(limits)) =>
+                (Some(limits.max_ext_msg_size), Some(limits.max_ext_msg_depth)),
+            _ => (None, None)
+        };
+        let workchains = match config.config(12)? {
+            Some(ConfigParamEnum::ConfigParam12(param)) => {
+                let mut workchains = param.workchains.export_keys::<i32>()?.into_iter().collect::<HashSet<_>>();
+                workchains.insert(MASTERCHAIN_ID);
+                Some(workchains)
+            }
+            _ => None
+        };
+        *self.network.write() = NetworkLimits { max_size, max_depth, workchains };
+        Ok(())
+    }
+
+    pub fn limits(&self, workchain_id: i32) -> EffectiveLimits {
+        let network = self.network.read();
+        let node = self.node_limits.get(&workchain_id);
+        EffectiveLimits {
+            max_size: network.max_size
+                .or_else(|| node.and_then(|limits| limits.max_size))
+                .unwrap_or(MAX_EXTERNAL_MESSAGE_SIZE as u32),
+            max_body_depth: network.max_depth
+                .or_else(|| node.and_then(|limits| limits.max_body_depth))
+                .unwrap_or(MAX_EXTERNAL_MESSAGE_DEPTH),
+            min_import_fee: node.and_then(|limits| limits.min_import_fee).unwrap_or(0),
+        }
+    }
+
+    // Fails with NodeError::ExtMessageRejected if the message is not to be accepted
+    pub fn check(&self, size: usize, message: &Message) -> Result<()> {
+        let workchain_id = message.dst_workchain_id()
+            .ok_or_else(|| error!("Can't get workchain id from message"))?;
+        if let Some(reason) = self.reject_reason(workchain_id, size, message)? {
+            fail!(NodeError::ExtMessageRejected { workchain_id, reason })
+        }
+        Ok(())
+    }
+
+    fn reject_reason(
+        &self,
+        workchain_id: i32,
+        size: usize,
+        message: &Message
+    ) -> Result<Option<ExtMessageRejectReason>> {
+        if workchain_id != MASTERCHAIN_ID {
+            if let Some(processed_wc) = self.processed_workchain {
+                if processed_wc != workchain_id {
+                    return Ok(Some(ExtMessageRejectReason::UntrackedWorkchain))
+                }
+            }
+        }
+        if let Some(workchains) = &self.network.read().workchains {
+            if !workchains.contains(&workchain_id) {
+                return Ok(Some(ExtMessageRejectReason::UnknownWorkchain))
+            }
+        }
+        let limits = self.limits(workchain_id);
+        if size as u64 > limits.max_size as u64 {
+            return Ok(Some(ExtMessageRejectReason::TooLarge { size: size as u64, max_size: limits.max_size }))
+        }
+        let depth = Self::body_depth(message)?;
+        if depth > limits.max_body_depth {
+            return Ok(Some(ExtMessageRejectReason::TooDeepBody { depth, max_depth: limits.max_body_depth }))
+        }
+        let fee = message.ext_in_header().map_or(0, |header| header.import_fee.as_u128());
+        if fee < limits.min_import_fee as u128 {
+            return Ok(Some(ExtMessageRejectReason::ImportFeeTooLow { fee, min_fee: limits.min_import_fee }))
+        }
+        Ok(None)
+    }
+
+    // Depth of the body cell tree: zero for a body without references
+    fn body_depth(message: &Message) -> Result<u16> {
+        let Some(body) = message.body() else {
+            return Ok(0)
+        };
+        let mut depth = 0;
+        for i in 0..body.remaining_references() {
+            depth = depth.max(body.reference(i)?.repr_depth() + 1);
+        }
+        Ok(depth)
+    }
+}
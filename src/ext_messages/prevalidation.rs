@@ -0,0 +1,240 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{config::ExtMessagesPrevalidationConfig, validator::validator_utils::get_adnl_id};
+use std::{collections::{HashMap, HashSet, VecDeque}, fmt, sync::Arc};
+use ever_block::{ConfigParams, KeyId, Result};
+
+#[cfg(test)]
+#[path = "../tests/test_prevalidation.rs"]
+mod tests;
+
+// High lane tasks taken in a row before one low lane task, so the low lane is not starved
+const HIGH_LANE_BURST: u32 = 8;
+// Drops from sources over this count are counted per lane only
+const MAX_DROP_SOURCES: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PrevalidationLane {
+    // REMP messages from validators
+    High,
+    Low,
+}
+
+impl fmt::Display for PrevalidationLane {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::High => write!(f, "high"),
+            Self::Low => write!(f, "low"),
+        }
+    }
+}
+
+pub struct PrevalidationTask<T> {
+    pub task: T,
+    pub source: Arc<KeyId>,
+    pub lane: PrevalidationLane,
+}
+
+enum Scheduled<T> {
+    Task(PrevalidationTask<T>),
+    Idle,
+    Stopped,
+}
+
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub struct PrevalidationStats {
+    pub high_queued: usize,
+    pub low_queued: usize,
+    pub high_dropped: u64,
+    pub low_dropped: u64,
+}
+
+struct PrevalidationState<T> {
+    high: VecDeque<PrevalidationTask<T>>,
+    low: VecDeque<PrevalidationTask<T>>,
+    // High lane tasks taken since the last low lane one
+    high_streak: u32,
+    stopped: bool,
+    high_dropped: u64,
+    low_dropped: u64,
+    source_drops: HashMap<(PrevalidationLane, Arc<KeyId>), u64>,
+    // ADNL ids of the current and next validators
+    priority_sources: HashSet<Arc<KeyId>>,
+}
+
+impl<T> PrevalidationState<T> {
+    fn count_drop(&mut self, lane: PrevalidationLane, source: &Arc<KeyId>) {
+        match lane {
+            PrevalidationLane::High => self.high_dropped += 1,
+            PrevalidationLane::Low => self.low_dropped += 1,
+        }
+        let key = (lane, source.clone());
+        if let Some(count) = self.source_drops.get_mut(&key) {
+            *count += 1;
+        } else if self.source_drops.len() < MAX_DROP_SOURCES {
+            self.source_drops.insert(key, 1);
+        }
+    }
+}
+
+// Queue of external messages waiting for prevalidation workers. REMP messages from validators
+// go to the bounded high lane, others go to the low lane which drops its oldest message when full
+pub struct PrevalidationQueue<T> {
+    config: ExtMessagesPrevalidationConfig,
+    state: parking_lot::Mutex<PrevalidationState<T>>,
+    notify: tokio::sync::Notify,
+}
+
+impl<T> PrevalidationQueue<T> {
+
+    pub fn new(config: ExtMessagesPrevalidationConfig) -> Self {
+        Self {
+            config,
+            state: parking_lot::Mutex::new(PrevalidationState {
+                high: VecDeque::new(),
+                low: VecDeque::new(),
+                high_streak: 0,
+                stopped: false,
+                high_dropped: 0,
+                low_dropped: 0,
+                source_drops: HashMap::new(),
+                priority_sources: HashSet::new(),
+            }),
+            notify: tokio::sync::Notify::new(),
+        }
+    }
+
+    pub fn workers(&self) -> u32 {
+        self.config.workers.max(1)
+    }
+
+    // Takes validators of the current and next sets as priority sources
+    pub fn refresh(&self, config: &ConfigParams) -> Result<()> {
+        let cur_vset = config.validator_set()?;
+        let next_vset = config.next_validator_set()?;
+        let sources = cur_vset.list().iter().chain(next_vset.list().iter())
+            .map(get_adnl_id)
+            .collect();
+        self.set_priority_sources(sources);
+        Ok(())
+    }
+
+    pub fn set_priority_sources(&self, sources: HashSet<Arc<KeyId>>) {
+        self.state.lock().priority_sources = sources;
+    }
+
+    // Returns false if the task is dropped instead of queued
+    pub fn push(&self, task: T, source: Arc<KeyId>, remp: bool) -> bool {
+        let mut state = self.state.lock();
+        let lane = if remp && state.priority_sources.contains(&source) {
+            PrevalidationLane::High
+        } else {
+            PrevalidationLane::Low
+        };
+        if state.stopped {
+            state.count_drop(lane, &source);
+            return false
+        }
+        let queued = match lane {
+            PrevalidationLane::High => {
+                if state.high.len() >= self.config.high_lane_capacity as usize {
+                    state.count_drop(lane, &source);
+                    false
+                } else {
+                    state.high.push_back(PrevalidationTask { task, source, lane });
+                    true
+                }
+            }
+            PrevalidationLane::Low => {
+                if state.low.len() >= self.config.low_lane_capacity as usize {
+                    match state.low.pop_front() {
+                        Some(oldest) => state.count_drop(lane, &oldest.source),
+                        // Zero capacity
+                        None => {
+                            state.count_drop(lane, &source);
+                            return false
+                        }
+                    }
+                }
+                state.low.push_back(PrevalidationTask { task, source, lane });
+                true
+            }
+        };
+        drop(state);
+        if queued {
+            self.notify.notify_one();
+        }
+        queued
+    }
+
+    // Waits for the next task, None after stop when the high lane is drained
+    pub async fn next_task(&self) -> Option<PrevalidationTask<T>> {
+        loop {
+            // Created before the check to not miss notification between them
+            let notified = self.notify.notified();
+            match self.schedule() {
+                Scheduled::Task(task) => return Some(task),
+                Scheduled::Stopped => return None,
+                Scheduled::Idle => notified.await
+            }
+        }
+    }
+
+    fn schedule(&self) -> Scheduled<T> {
+        let mut state = self.state.lock();
+        if !state.high.is_empty() && (state.low.is_empty() || state.high_streak < HIGH_LANE_BURST) {
+            state.high_streak += 1;
+            if let Some(task) = state.high.pop_front() {
+                return Scheduled::Task(task)
+            }
+        }
+        state.high_streak = 0;
+        match state.low.pop_front() {
+            Some(task) => Scheduled::Task(task),
+            None if state.stopped => Scheduled::Stopped,
+            None => Scheduled::Idle
+        }
+    }
+
+    // Drops low lane tasks and new tasks, high lane tasks are still given to workers
+    pub fn stop(&self) {
+        let mut state = self.state.lock();
+        state.stopped = true;
+        while let Some(task) = state.low.pop_front() {
+            state.count_drop(PrevalidationLane::Low, &task.source);
+        }
+        drop(state);
+        self.notify.notify_waiters();
+    }
+
+    pub fn stats(&self) -> PrevalidationStats {
+        let state = self.state.lock();
+        PrevalidationStats {
+            high_queued: state.high.len(),
+            low_queued: state.low.len(),
+            high_dropped: state.high_dropped,
+            low_dropped: state.low_dropped,
+        }
+    }
+
+    // Dropped tasks per lane and source, the most dropping sources first
+    pub fn source_drops(&self) -> Vec<(PrevalidationLane, Arc<KeyId>, u64)> {
+        let mut drops = self.state.lock().source_drops.iter()
+            .map(|((lane, source), count)| (*lane, source.clone(), *count))
+            .collect::<Vec<_>>();
+        drops.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        drops
+    }
+
+}
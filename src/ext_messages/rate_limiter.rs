@@ -0,0 +1,121 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::config::ExtMessagesRateLimitConfig;
+use std::{collections::HashMap, sync::Arc, time::{Duration, Instant}};
+use ever_block::KeyId;
+
+#[cfg(test)]
+#[path = "../tests/test_rate_limiter.rs"]
+mod tests;
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: u32, now: Instant) -> Self {
+        Self { tokens: burst as f64, updated_at: now }
+    }
+
+    fn refill(&mut self, rate: u32, burst: u32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(burst as f64);
+        self.updated_at = now;
+    }
+
+    fn has_token(&self) -> bool {
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+struct RateLimiterState {
+    global: Option<TokenBucket>,
+    sources: HashMap<Arc<KeyId>, TokenBucket>,
+    cleaned_at: Instant,
+}
+
+// Token bucket limits for incoming external messages: per source and global
+pub struct ExtMessagesRateLimiter {
+    config: ExtMessagesRateLimitConfig,
+    idle_timeout: Duration,
+    state: parking_lot::Mutex<RateLimiterState>,
+}
+
+impl ExtMessagesRateLimiter {
+    pub fn new(config: ExtMessagesRateLimitConfig) -> Self {
+        Self::with_time(config, Instant::now())
+    }
+
+    fn with_time(config: ExtMessagesRateLimitConfig, now: Instant) -> Self {
+        let global = if config.global_messages_per_sec > 0 {
+            Some(TokenBucket::new(config.global_burst, now))
+        } else {
+            None
+        };
+        Self {
+            idle_timeout: Duration::from_secs(config.source_idle_timeout_sec as u64),
+            config,
+            state: parking_lot::Mutex::new(RateLimiterState {
+                global,
+                sources: HashMap::new(),
+                cleaned_at: now,
+            }),
+        }
+    }
+
+    // Returns false if the message from the source is over quota and must be dropped
+    pub fn allow(&self, source: &Arc<KeyId>) -> bool {
+        self.allow_at(source, Instant::now())
+    }
+
+    fn allow_at(&self, source: &Arc<KeyId>, now: Instant) -> bool {
+        let mut state = self.state.lock();
+        if now.saturating_duration_since(state.cleaned_at) >= self.idle_timeout {
+            let idle_timeout = self.idle_timeout;
+            state.sources.retain(
+                |_, bucket| now.saturating_duration_since(bucket.updated_at) < idle_timeout
+            );
+            state.cleaned_at = now;
+        }
+        let config = &self.config;
+        let RateLimiterState { global, sources, .. } = &mut *state;
+        let bucket = sources.entry(source.clone())
+            .or_insert_with(|| TokenBucket::new(config.source_burst, now));
+        bucket.refill(config.source_messages_per_sec, config.source_burst, now);
+        if let Some(global) = global.as_mut() {
+            global.refill(config.global_messages_per_sec, config.global_burst, now);
+            if !global.has_token() {
+                return false
+            }
+        }
+        if !bucket.has_token() {
+            return false
+        }
+        bucket.take();
+        if let Some(global) = global.as_mut() {
+            global.take();
+        }
+        true
+    }
+
+    #[cfg(test)]
+    fn sources_count(&self) -> usize {
+        self.state.lock().sources.len()
+    }
+}
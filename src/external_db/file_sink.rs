@@ -0,0 +1,204 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{config::FileSinkConfig, external_db::WriteData};
+use ever_block::{base64_encode, fail, Result};
+use std::{
+    fs::{File, OpenOptions}, io::Write, path::PathBuf,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering}, time::{Duration, Instant}
+};
+
+#[cfg(test)]
+#[path = "tests/test_file_sink.rs"]
+mod tests;
+
+// No space left on device
+const ENOSPC: i32 = 28;
+
+struct ActiveFile {
+    file: File,
+    path: PathBuf,
+    first_mc_seq_no: u32,
+    last_mc_seq_no: u32,
+    size: u64,
+    opened_at: Instant,
+}
+
+// Writes documents of one kind into newline-delimited JSON files. A file is rotated by size
+// or age and then named by the range of masterchain seqnos of its documents.
+pub(super) struct FileSink {
+    kind: String,
+    enabled: bool,
+    directory: PathBuf,
+    max_file_size: u64,
+    rotation_interval: Duration,
+    retry_timeout: Duration,
+    mc_seq_no: AtomicU32,
+    file: parking_lot::Mutex<Option<ActiveFile>>,
+    documents: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl FileSink {
+    pub fn new(config: &FileSinkConfig, kind: &str, enabled: bool) -> Result<Self> {
+        let directory = PathBuf::from(&config.directory);
+        if enabled {
+            std::fs::create_dir_all(&directory)?;
+        }
+        Ok(Self {
+            kind: kind.to_string(),
+            enabled,
+            directory,
+            max_file_size: config.max_file_size,
+            rotation_interval: Duration::from_secs(config.rotation_interval_sec),
+            retry_timeout: Duration::from_millis(config.retry_timeout_ms as u64),
+            mc_seq_no: AtomicU32::new(0),
+            file: parking_lot::Mutex::new(None),
+            documents: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        })
+    }
+
+    pub fn documents_written(&self) -> u64 {
+        self.documents.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn open(&self, mc_seq_no: u32) -> std::io::Result<ActiveFile> {
+        // Files being written have extra extension, so they are not caught by ingestion
+        let path = self.directory.join(format!("{}_{:010}.ndjson.part", self.kind, mc_seq_no));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(ActiveFile {
+            file,
+            path,
+            first_mc_seq_no: mc_seq_no,
+            last_mc_seq_no: mc_seq_no,
+            size,
+            opened_at: Instant::now()
+        })
+    }
+
+    fn seal(&self, active: &ActiveFile) -> std::io::Result<()> {
+        active.file.sync_all()?;
+        let name = format!("{}_{:010}-{:010}", self.kind, active.first_mc_seq_no, active.last_mc_seq_no);
+        // Several files may cover the same range if they are rotated often
+        let mut path = self.directory.join(format!("{}.ndjson", name));
+        let mut index = 0;
+        while path.exists() {
+            index += 1;
+            path = self.directory.join(format!("{}.{}.ndjson", name, index));
+        }
+        std::fs::rename(&active.path, &path)?;
+        log::info!("External db file {} is written, {} bytes", path.display(), active.size);
+        Ok(())
+    }
+
+    fn try_write(&self, line: &[u8]) -> std::io::Result<()> {
+        let mc_seq_no = self.mc_seq_no.load(Ordering::Relaxed);
+        let mut guard = self.file.lock();
+        let active = match guard.take() {
+            Some(active) if active.size > 0 && (
+                active.size + line.len() as u64 > self.max_file_size ||
+                active.opened_at.elapsed() >= self.rotation_interval
+            ) => {
+                if let Err(e) = self.seal(&active) {
+                    *guard = Some(active);
+                    return Err(e)
+                }
+                self.open(mc_seq_no)?
+            }
+            Some(active) => active,
+            None => self.open(mc_seq_no)?
+        };
+        let active = guard.insert(active);
+        if let Err(e) = active.file.write_all(line) {
+            // Partially written line is cut off, so the file has whole documents only
+            active.file.set_len(active.size).ok();
+            return Err(e)
+        }
+        active.size += line.len() as u64;
+        active.last_mc_seq_no = active.last_mc_seq_no.max(mc_seq_no);
+        Ok(())
+    }
+
+    async fn write_line(&self, line: Vec<u8>) -> Result<()> {
+        if !self.enabled {
+            fail!("File sink {} is disabled", self.kind)
+        }
+        loop {
+            match self.try_write(&line) {
+                Ok(()) => break,
+                // Documents are not dropped, block processing waits until space is freed
+                Err(e) if e.raw_os_error() == Some(ENOSPC) => log::error!(
+                    "No space left to write external db file {}, waiting: {}", self.kind, e
+                ),
+                Err(e) => fail!("Can't write external db file {}: {}", self.kind, e)
+            }
+            futures_timer::Delay::new(self.retry_timeout).await;
+        }
+        self.documents.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(line.len() as u64, Ordering::Relaxed);
+        metrics::counter!("external_db_file_documents", 1, "kind" => self.kind.clone());
+        metrics::counter!("external_db_file_bytes", line.len() as u64, "kind" => self.kind.clone());
+        Ok(())
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        if let Some(active) = self.file.get_mut().take() {
+            if let Err(e) = self.seal(&active) {
+                log::error!("Can't finish external db file {}: {}", active.path.display(), e);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WriteData for FileSink {
+
+    fn enabled(&self) -> bool { self.enabled }
+
+    fn sharding_depth(&self) -> u32 { 0 }
+
+    fn set_mc_seq_no(&self, mc_seq_no: u32) {
+        self.mc_seq_no.fetch_max(mc_seq_no, Ordering::Relaxed);
+    }
+
+    async fn write_data(&self, _key: String, data: String, _attributes: Option<&[(&str, &[u8])]>, _partition_key: Option<u32>) -> Result<()> {
+        // Some documents are pretty printed, but a document must take one line
+        let mut line = if data.contains('\n') {
+            serde_json::to_string(&serde_json::from_str::<serde_json::Value>(&data)?)?
+        } else {
+            data
+        };
+        line.push('\n');
+        self.write_line(line.into_bytes()).await
+    }
+
+    async fn write_raw_data(&self, key: Vec<u8>, data: Vec<u8>, attributes: Option<&[(&str, &[u8])]>, _partition_key: Option<u32>) -> Result<()> {
+        let mut doc = serde_json::Map::new();
+        doc.insert("id".to_string(), hex::encode(&key).into());
+        doc.insert("boc".to_string(), base64_encode(&data).into());
+        for (name, value) in attributes.unwrap_or_default() {
+            doc.insert(name.to_string(), hex::encode(value).into());
+        }
+        let mut line = serde_json::to_string(&doc)?;
+        line.push('\n');
+        self.write_line(line.into_bytes()).await
+    }
+}
@@ -12,8 +12,8 @@
 */
 
 use crate::{
-    engine_traits::{ExternalDb, EngineOperations, ChainRange}, config::ExternalDbConfig,
-    engine::Engine, block::BlockStuff,
+    engine_traits::{ExternalDb, EngineOperations, ChainRange}, 
+    config::{ExternalDbConfig, FileSinkConfig}, engine::Engine, block::BlockStuff,
 };
 use processor::Processor;
 use std::sync::Arc;
@@ -21,6 +21,8 @@ use ever_block::BlockIdExt;
 use ever_block::{Result, error, fail};
 
 mod processor;
+mod file_sink;
+pub mod remp_history;
 #[cfg(feature = "external_db")]
 mod kafka_producer;
 #[cfg(feature = "external_db")]
@@ -38,6 +40,8 @@ pub trait WriteData : Sync + Send {
     fn sharding_depth(&self) -> u32;
     async fn write_data(&self, key: String, data: String, attributes: Option<&[(&str, &[u8])]>, partition_key: Option<u32>) -> Result<()>;
     async fn write_raw_data(&self, key: Vec<u8>, data: Vec<u8>, attributes: Option<&[(&str, &[u8])]>, partition_key: Option<u32>) -> Result<()>;
+    // Masterchain block which the next documents belong to
+    fn set_mc_seq_no(&self, _mc_seq_no: u32) {}
 }
 
 #[allow(dead_code)]
@@ -67,6 +71,11 @@ pub fn create_external_db(
     config: ExternalDbConfig, front_workchain_ids: Vec<i32>, control_id: Option<[u8; 32]>
 ) -> Result<Arc<dyn ExternalDb>> {
 
+    if let Some(file_sink) = &config.file_sink {
+        return create_file_external_db(
+            file_sink, config.bad_blocks_storage, front_workchain_ids, control_id
+        )
+    }
     let max_account_bytes_size = match config.account_producer.big_messages_storage {
         Some(_) => config.account_producer.big_message_max_size,
         None => Some(config.account_producer.message_max_size),
@@ -82,6 +91,7 @@ pub fn create_external_db(
         write_chain_range: kafka_producer::KafkaProducer::new(config.chain_range_producer)?,
         write_remp_statuses: kafka_producer::KafkaProducer::new(config.remp_statuses_producer)?,
         write_shard_hashes: kafka_producer::KafkaProducer::new(config.shard_hashes_producer)?,
+        write_remp_history: kafka_producer::KafkaProducer::new(config.remp_history_producer)?,
     };
     if writers.write_shard_hashes.enabled() && control_id.is_none() {
         fail!("Control server config should be specified is shard hashes writer is enabled")
@@ -99,6 +109,56 @@ pub fn create_external_db(
     )
 }
 
+// Documents are written into local files instead of kafka
+fn create_file_external_db(
+    config: &FileSinkConfig, 
+    bad_blocks_storage: String,
+    front_workchain_ids: Vec<i32>, 
+    control_id: Option<[u8; 32]>
+) -> Result<Arc<dyn ExternalDb>> {
+
+    let sink = |kind: &str| {
+        // Shard hashes need control server id, so they are written by default only if it is set.
+        // REMP history is written only on demand.
+        let enabled = match &config.documents {
+            Some(documents) => documents.iter().any(|document| document == kind),
+            None => match kind {
+                "shard_hashes" => control_id.is_some(),
+                "remp_history" => false,
+                _ => true
+            }
+        };
+        file_sink::FileSink::new(config, kind, enabled)
+    };
+    let writers = processor::Writers {
+        write_block: sink("blocks")?,
+        write_raw_block: sink("raw_blocks")?,
+        write_message: sink("messages")?,
+        write_transaction: sink("transactions")?,
+        write_account: sink("accounts")?,
+        write_block_proof: sink("block_proofs")?,
+        write_raw_block_proof: sink("raw_block_proofs")?,
+        write_chain_range: sink("chain_ranges")?,
+        write_remp_statuses: sink("remp_statuses")?,
+        write_shard_hashes: sink("shard_hashes")?,
+        write_remp_history: sink("remp_history")?,
+    };
+    if writers.write_shard_hashes.enabled() && control_id.is_none() {
+        fail!("Control server config should be specified is shard hashes writer is enabled")
+    }
+    Ok(
+        Arc::new(
+            Processor::new(
+                writers,
+                bad_blocks_storage,
+                front_workchain_ids,
+                None,
+                control_id.unwrap_or_default(),
+            )
+        )
+    )
+}
+
 pub fn start_external_db_worker(
     engine: Arc<dyn EngineOperations>,
     mut external_db_block: BlockIdExt
@@ -12,8 +12,9 @@
 */
 
 use crate::{
-    block::BlockStuff, block_proof::BlockProofStuff, engine_traits::{ChainRange, ExternalDb},
-    error::NodeError, external_db::WriteData, shard_state::ShardStateStuff
+    block::BlockStuff, block_proof::BlockProofStuff,
+    engine_traits::{ChainRange, ExternalDb, RempStatusTransition}, error::NodeError,
+    external_db::{remp_history, WriteData}, shard_state::ShardStateStuff
 };
 
 use ever_block::{
@@ -112,6 +113,20 @@ pub struct Writers<T: 'static + WriteData> {
     pub write_chain_range: T,
     pub write_remp_statuses: T,
     pub write_shard_hashes: T,
+    pub write_remp_history: T,
+}
+
+impl<T: 'static + WriteData> Writers<T> {
+    fn set_mc_seq_no(&self, mc_seq_no: u32) {
+        for writer in [
+            &self.write_block, &self.write_raw_block, &self.write_message, &self.write_transaction,
+            &self.write_account, &self.write_block_proof, &self.write_raw_block_proof,
+            &self.write_chain_range, &self.write_remp_statuses, &self.write_shard_hashes,
+            &self.write_remp_history
+        ] {
+            writer.set_mc_seq_no(mc_seq_no)
+        }
+    }
 }
 
 pub(super) struct Processor<T: 'static + WriteData> {
@@ -486,6 +501,7 @@ impl<T: 'static + WriteData> Processor<T> {
         if !self.process_workchain(block_stuff.id().shard().workchain_id()) {
             return Ok(())
         }
+        self.writers.set_mc_seq_no(mc_seq_no);
         let process_block = self.writers.write_block.enabled();
         let process_raw_block = self.writers.write_raw_block.enabled();
         let process_message = self.writers.write_message.enabled();
@@ -882,6 +898,7 @@ impl<T: WriteData> ExternalDb for Processor<T> {
     }
 
     async fn process_chain_range(&self, range: &ChainRange) -> Result<()> {
+        self.writers.set_mc_seq_no(range.master_block.seq_no());
         if self.writers.write_chain_range.enabled() {
             let master_block_id = range.master_block.root_hash().to_hex_string();
             let mut data = ChainRangeData {
@@ -929,6 +946,22 @@ impl<T: WriteData> ExternalDb for Processor<T> {
         Ok(())
     }
 
+    fn process_remp_history_enabled(&self) -> bool {
+        self.writers.write_remp_history.enabled()
+    }
+
+    async fn process_remp_status_transition(&self, transition: &RempStatusTransition) -> Result<()> {
+        if self.writers.write_remp_history.enabled() {
+            self.writers.write_remp_history.write_data(
+                format!("{:x}", transition.message_id),
+                serde_json::to_string(&remp_history::transition_document(transition))?,
+                None,
+                None
+            ).await?;
+        }
+        Ok(())
+    }
+
     fn process_shard_hashes_enabled(&self) -> bool {
         self.writers.write_shard_hashes.enabled()
     }
@@ -0,0 +1,141 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    config::RempHistoryConfig,
+    engine_traits::{ExternalDb, RempStatusTransition},
+    ext_messages::{get_level_and_level_change, is_finally_accepted, is_finally_rejected}
+};
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+use tokio::sync::mpsc::{error::TrySendError, Receiver, Sender};
+use ever_block::UInt256;
+use ton_api::ton::ton_node::{RempMessageLevel, RempMessageStatus};
+
+#[cfg(test)]
+#[path = "tests/test_remp_history.rs"]
+mod tests;
+
+// Message cache pushes transitions here, the worker takes them to external dbs
+pub struct RempHistoryExporter {
+    sender: Sender<RempStatusTransition>,
+    intermediate_statuses: bool,
+    dropped: AtomicU64,
+}
+
+impl RempHistoryExporter {
+
+    pub fn new(config: &RempHistoryConfig) -> (Self, Receiver<RempStatusTransition>) {
+        let (sender, receiver) = tokio::sync::mpsc::channel(config.buffer_size.max(1));
+        let exporter = Self {
+            sender,
+            intermediate_statuses: config.intermediate_statuses,
+            dropped: AtomicU64::new(0),
+        };
+        (exporter, receiver)
+    }
+
+    pub fn is_exported(&self, status: &RempMessageStatus) -> bool {
+        self.intermediate_statuses || is_final(status)
+    }
+
+    // Never waits: if the sink is behind, the transition is dropped and counted
+    pub fn export(&self, transition: RempStatusTransition) {
+        match self.sender.try_send(transition) {
+            Ok(()) => (),
+            Err(TrySendError::Full(transition)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                metrics::counter!("remp_history_dropped", 1);
+                log::debug!(
+                    target: "remp",
+                    "REMP history is full, transition of {:x} is dropped ({} in total)",
+                    transition.message_id, dropped
+                );
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+pub fn start_remp_history_worker(
+    exporter: Arc<RempHistoryExporter>,
+    mut receiver: Receiver<RempStatusTransition>,
+    ext_db: Vec<Arc<dyn ExternalDb>>
+) -> tokio::task::JoinHandle<()> {
+    log::info!("start_remp_history_worker");
+    tokio::spawn(async move {
+        let mut reported_dropped = 0;
+        while let Some(transition) = receiver.recv().await {
+            for db in ext_db.iter().filter(|db| db.process_remp_history_enabled()) {
+                if let Err(e) = db.process_remp_status_transition(&transition).await {
+                    log::warn!(
+                        target: "remp",
+                        "Cannot export status transition of {:x}: {}", transition.message_id, e
+                    );
+                }
+            }
+            let dropped = exporter.dropped();
+            if dropped != reported_dropped {
+                log::warn!(target: "remp", "{} REMP status transitions are dropped", dropped);
+                reported_dropped = dropped;
+            }
+        }
+        log::info!("REMP history worker is finished");
+    })
+}
+
+fn is_final(status: &RempMessageStatus) -> bool {
+    is_finally_accepted(status) || is_finally_rejected(status)
+}
+
+fn level_name(level: &RempMessageLevel) -> &'static str {
+    match level {
+        RempMessageLevel::TonNode_RempFullnode => "fullnode",
+        RempMessageLevel::TonNode_RempQueue => "queue",
+        RempMessageLevel::TonNode_RempCollator => "collator",
+        RempMessageLevel::TonNode_RempShardchain => "shardchain",
+        RempMessageLevel::TonNode_RempMasterchain => "masterchain"
+    }
+}
+
+// Compact document for the analytics, block id is its root hash
+pub(super) fn transition_document(transition: &RempStatusTransition) -> serde_json::Value {
+    let (status, block_id) = match &transition.status {
+        RempMessageStatus::TonNode_RempNew => ("new", None),
+        RempMessageStatus::TonNode_RempSentToValidators(_) => ("sent_to_validators", None),
+        RempMessageStatus::TonNode_RempIgnored(ignored) => ("ignored", Some(&ignored.block_id)),
+        RempMessageStatus::TonNode_RempAccepted(accepted) => ("accepted", Some(&accepted.block_id)),
+        RempMessageStatus::TonNode_RempRejected(rejected) => ("rejected", Some(&rejected.block_id)),
+        RempMessageStatus::TonNode_RempDuplicate(dup) => ("duplicate", Some(&dup.block_id)),
+        RempMessageStatus::TonNode_RempTimeout => ("timeout", None)
+    };
+    let (level, _) = get_level_and_level_change(&transition.status);
+    serde_json::json!({
+        "id": transition.message_id.to_hex_string(),
+        "uid": transition.message_uid.to_hex_string(),
+        "origin": transition.origin.as_ref().map(|origin| origin.to_string()),
+        "status": status,
+        "level": level_name(&level),
+        "final": is_final(&transition.status),
+        "block_id": block_id
+            .filter(|id| *id.root_hash() != UInt256::default())
+            .map(|id| id.root_hash().to_hex_string()),
+        "master_cc": transition.master_cc,
+        "timestamp": transition.timestamp_ms,
+    })
+}
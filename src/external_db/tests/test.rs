@@ -157,6 +157,7 @@ async fn test_external_db_processor_async(
         write_chain_range: TestWriter::new(enabled, write_data),
         write_remp_statuses: TestWriter::new(enabled, write_data),
         write_shard_hashes: TestWriter::new(enabled, write_data),
+        write_remp_history: TestWriter::new(enabled, write_data),
     };
 
     let p = Processor::new(
@@ -0,0 +1,185 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::{
+    block::BlockStuff, block_proof::BlockProofStuff, engine_traits::{ChainRange, ExternalDb},
+    external_db::processor::{Processor, Writers}
+};
+use ever_block::{BlockIdExt, ShardIdent, UInt256};
+
+fn test_config(name: &str, max_file_size: u64) -> FileSinkConfig {
+    let directory = format!("target/test_file_sink/{}", name);
+    std::fs::remove_dir_all(&directory).ok();
+    FileSinkConfig {
+        directory,
+        max_file_size,
+        rotation_interval_sec: 3600,
+        retry_timeout_ms: 10,
+        documents: None,
+    }
+}
+
+// Returns names of files in the directory and documents of each file
+fn read_files(directory: &str) -> Vec<(String, Vec<serde_json::Value>)> {
+    let mut files = std::fs::read_dir(directory).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    files.into_iter().map(|name| {
+        let data = std::fs::read_to_string(format!("{}/{}", directory, name)).unwrap();
+        assert!(data.ends_with('\n'), "{}", name);
+        let docs = data.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        (name, docs)
+    }).collect()
+}
+
+#[tokio::test]
+async fn test_file_sink_rotation() -> Result<()> {
+    // Each line is 23 bytes long, so a file takes 4 documents
+    let config = test_config("rotation", 23 * 4);
+    let sink = FileSink::new(&config, "docs", true)?;
+    let mut bytes = 0;
+    for mc_seq_no in 1..=10 {
+        sink.set_mc_seq_no(mc_seq_no);
+        for n in 0..3 {
+            let doc = format!("{{\"mc\":\"{:03}\",\"n\":\"{:03}\"}}", mc_seq_no, n);
+            bytes += doc.len() as u64 + 1;
+            sink.write_data(String::new(), doc, None, None).await?;
+        }
+    }
+    assert_eq!(sink.documents_written(), 30);
+    assert_eq!(sink.bytes_written(), bytes);
+
+    // Last file is still being written
+    let files = read_files(&config.directory);
+    assert!(files.iter().any(|(name, _)| name == "docs_0000000010.ndjson.part"));
+    drop(sink);
+
+    let files = read_files(&config.directory);
+    let expected = [(1, 2), (2, 3), (3, 4), (5, 6), (6, 7), (7, 8), (9, 10), (10, 10)];
+    assert_eq!(files.len(), expected.len());
+    let mut docs = Vec::new();
+    for ((name, file_docs), (first, last)) in files.into_iter().zip(expected) {
+        assert_eq!(name, format!("docs_{:010}-{:010}.ndjson", first, last));
+        for doc in file_docs.iter() {
+            let mc_seq_no = doc["mc"].as_str().unwrap().parse::<u32>().unwrap();
+            assert!(mc_seq_no >= first && mc_seq_no <= last, "{} in {}", mc_seq_no, name);
+        }
+        docs.extend(file_docs);
+    }
+    assert_eq!(docs.len(), 30);
+    for (i, doc) in docs.iter().enumerate() {
+        assert_eq!(doc["mc"].as_str().unwrap(), format!("{:03}", i / 3 + 1));
+        assert_eq!(doc["n"].as_str().unwrap(), format!("{:03}", i % 3));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_file_sink_documents() -> Result<()> {
+    let config = test_config("documents", 1 << 20);
+    let mut sink = FileSink::new(&config, "docs", true)?;
+    sink.rotation_interval = std::time::Duration::from_millis(50);
+
+    // Pretty printed document is written in one line
+    sink.set_mc_seq_no(5);
+    let doc = serde_json::json!({ "id": "1", "list": [1, 2, 3] });
+    sink.write_data("1".to_string(), format!("{:#}", doc), None, None).await?;
+    let attributes = [("mc_seq_no", &5_u32.to_be_bytes()[..])];
+    sink.write_raw_data(vec![1, 2], vec![3, 4, 5], Some(&attributes), None).await?;
+
+    // Old file is rotated by the next document
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    sink.set_mc_seq_no(7);
+    sink.write_data("2".to_string(), "{\"id\":\"2\"}".to_string(), None, None).await?;
+    drop(sink);
+
+    let files = read_files(&config.directory);
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0].0, "docs_0000000005-0000000005.ndjson");
+    assert_eq!(files[0].1[0], doc);
+    assert_eq!(files[0].1[1], serde_json::json!({ "id": "0102", "boc": "AwQF", "mc_seq_no": "00000005" }));
+    assert_eq!(files[1].0, "docs_0000000007-0000000007.ndjson");
+    assert_eq!(files[1].1, vec![serde_json::json!({ "id": "2" })]);
+
+    // Disabled sink writes nothing
+    let sink = FileSink::new(&test_config("disabled", 1 << 20), "docs", false)?;
+    assert!(sink.write_data(String::new(), "{}".to_string(), None, None).await.is_err());
+    assert_eq!(sink.documents_written(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_file_sink_processor() -> Result<()> {
+    let config = test_config("processor", 1 << 20);
+    let sink = |kind: &str| FileSink::new(&config, kind, kind != "shard_hashes");
+    let writers = Writers {
+        write_block: sink("blocks")?,
+        write_raw_block: sink("raw_blocks")?,
+        write_message: sink("messages")?,
+        write_transaction: sink("transactions")?,
+        write_account: sink("accounts")?,
+        write_block_proof: sink("block_proofs")?,
+        write_raw_block_proof: sink("raw_block_proofs")?,
+        write_chain_range: sink("chain_ranges")?,
+        write_remp_statuses: sink("remp_statuses")?,
+        write_shard_hashes: sink("shard_hashes")?,
+        write_remp_history: sink("remp_history")?,
+    };
+    let processor = Processor::new(
+        writers,
+        "target/test_file_sink/processor_bad_blocks".to_owned(),
+        vec![-1, 0],
+        None,
+        [1u8; 32],
+    );
+
+    // Synthetic masterchain blocks with one shard block each
+    for seq_no in 100..105_u32 {
+        let id = |shard: ShardIdent| BlockIdExt::with_params(
+            shard, seq_no, UInt256::from_le_bytes(&seq_no.to_le_bytes()), UInt256::default()
+        );
+        let range = ChainRange {
+            master_block: id(ShardIdent::masterchain()),
+            shard_blocks: vec![id(ShardIdent::full(0))],
+        };
+        processor.process_chain_range(&range).await?;
+    }
+
+    let block = BlockStuff::read_block_from_file("src/tests/static/test_master_block_proof/block__3082183")?;
+    let proof = BlockProofStuff::read_from_file(
+        block.id(), "src/tests/static/test_master_block_proof/proof__3082183", false
+    )?;
+    processor.process_block_impl(&block, Some(&proof), None, None, 3082183, false).await?;
+    drop(processor);
+
+    let files = read_files(&config.directory);
+    let file = |name: &str| files.iter().find(|(file, _)| file == name)
+        .unwrap_or_else(|| panic!("no file {}", name)).1.clone();
+    let ranges = file("chain_ranges_0000000100-0000000104.ndjson");
+    assert_eq!(ranges.len(), 5);
+    for (i, range) in ranges.iter().enumerate() {
+        assert_eq!(range["master_block"]["seq_no"].as_u64(), Some(100 + i as u64));
+        assert_eq!(range["shard_blocks_ids"].as_array().unwrap().len(), 1);
+    }
+    let blocks = file("blocks_0003082183-0003082183.ndjson");
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0]["id"].as_str().unwrap(), block.id().root_hash().to_hex_string());
+    let raw_blocks = file("raw_blocks_0003082183-0003082183.ndjson");
+    assert_eq!(raw_blocks[0]["id"].as_str().unwrap(), block.id().root_hash().to_hex_string());
+    assert_eq!(raw_blocks[0]["mc_seq_no"].as_str().unwrap(), hex::encode(3082183_u32.to_be_bytes()));
+    assert_eq!(file("block_proofs_0003082183-0003082183.ndjson").len(), 1);
+    assert!(files.iter().all(|(name, _)| !name.starts_with("shard_hashes")));
+    Ok(())
+}
@@ -0,0 +1,212 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::{
+    config::DuplicatePolicy,
+    external_db::{processor::{Processor, Writers}, WriteData},
+    validator::message_cache::{MessageCache, RempMessageOrigin, RmqMessage}
+};
+#[cfg(feature = "telemetry")]
+use adnl::telemetry::Metric;
+use ever_block::{BlockIdExt, KeyId, Result, ShardIdent, SliceData};
+use std::time::Duration;
+use ton_api::ton::ton_node::rempmessagestatus::{RempAccepted, RempSentToValidators};
+
+// Mock sink capturing written documents
+#[derive(Clone)]
+struct CaptureWriter {
+    enabled: bool,
+    documents: Arc<parking_lot::Mutex<Vec<(String, serde_json::Value)>>>,
+}
+
+impl CaptureWriter {
+    fn new(enabled: bool) -> Self {
+        Self { enabled, documents: Arc::new(parking_lot::Mutex::new(Vec::new())) }
+    }
+}
+
+#[async_trait::async_trait]
+impl WriteData for CaptureWriter {
+    fn enabled(&self) -> bool { self.enabled }
+    fn sharding_depth(&self) -> u32 { 0 }
+    async fn write_data(
+        &self,
+        key: String,
+        data: String,
+        _attributes: Option<&[(&str, &[u8])]>,
+        _partition_key: Option<u32>
+    ) -> Result<()> {
+        self.documents.lock().push((key, serde_json::from_str(&data)?));
+        Ok(())
+    }
+    async fn write_raw_data(
+        &self,
+        _key: Vec<u8>,
+        _data: Vec<u8>,
+        _attributes: Option<&[(&str, &[u8])]>,
+        _partition_key: Option<u32>
+    ) -> Result<()> {
+        unreachable!()
+    }
+}
+
+fn history_sink(history: &CaptureWriter) -> Arc<dyn ExternalDb> {
+    let disabled = CaptureWriter::new(false);
+    let writers = Writers {
+        write_block: disabled.clone(),
+        write_raw_block: disabled.clone(),
+        write_message: disabled.clone(),
+        write_transaction: disabled.clone(),
+        write_account: disabled.clone(),
+        write_block_proof: disabled.clone(),
+        write_raw_block_proof: disabled.clone(),
+        write_chain_range: disabled.clone(),
+        write_remp_statuses: disabled.clone(),
+        write_shard_hashes: disabled,
+        write_remp_history: history.clone(),
+    };
+    Arc::new(Processor::new(
+        writers,
+        "target/test_remp_history/bad_blocks".to_owned(),
+        vec![-1, 0],
+        None,
+        [1u8; 32],
+    ))
+}
+
+fn history_cache(exporter: &Arc<RempHistoryExporter>) -> Result<MessageCache> {
+    let cache = MessageCache::with_metrics(
+        None,
+        DuplicatePolicy::LowestId,
+        None,
+        #[cfg(feature = "telemetry")]
+        Metric::without_totals("remp history cache size", 0)
+    ).with_history_exporter(Some(exporter.clone()));
+    for cc in 1..=2 {
+        cache.try_set_master_cc_start_time(cc, cc.into(), vec!())?;
+        cache.update_master_cc_ranges(cc, Duration::from_secs(1))?;
+    }
+    Ok(cache)
+}
+
+// Message is received from peer, forwarded to validators, accepted by shardchain and masterchain
+fn process_message(cache: &MessageCache, body: u8) -> Result<(Arc<RmqMessage>, BlockIdExt)> {
+    let message = Arc::new(RmqMessage::make_test_message(&SliceData::new(vec![body, 0x80]))?);
+    let id = &message.message_id;
+    let origin = RempMessageOrigin::new(KeyId::from_data([7; 32]), 0)?;
+    cache.add_external_message_status(
+        id, &message.message_uid, Some(message.clone()), Some(Arc::new(origin)),
+        RempMessageStatus::TonNode_RempNew, |_old, new| new.clone(), 2
+    )?;
+    let forwarded = RempSentToValidators { sent_to: 3, total_validators: 5 };
+    cache.update_message_status(id, RempMessageStatus::TonNode_RempSentToValidators(forwarded))?;
+    let block_id = |shard, seq_no| BlockIdExt::with_params(
+        shard, seq_no, UInt256::from([seq_no as u8; 32]), UInt256::from([1; 32])
+    );
+    let shard_block = block_id(ShardIdent::full(0), 10);
+    let mc_block = block_id(ShardIdent::masterchain(), 5);
+    for (level, block) in [
+        (RempMessageLevel::TonNode_RempShardchain, &shard_block),
+        (RempMessageLevel::TonNode_RempMasterchain, &mc_block)
+    ] {
+        let accepted = RempAccepted { level, block_id: block.clone(), master_id: mc_block.clone() };
+        cache.update_message_status(id, RempMessageStatus::TonNode_RempAccepted(accepted))?;
+    }
+    Ok((message, mc_block))
+}
+
+async fn wait_documents(writer: &CaptureWriter, count: usize) -> Vec<(String, serde_json::Value)> {
+    for _ in 0..100 {
+        if writer.documents.lock().len() >= count {
+            break
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    writer.documents.lock().clone()
+}
+
+#[tokio::test]
+async fn test_remp_history_message_lifecycle() -> Result<()> {
+    let config = RempHistoryConfig { intermediate_statuses: true, buffer_size: 16 };
+    let (exporter, receiver) = RempHistoryExporter::new(&config);
+    let exporter = Arc::new(exporter);
+    let cache = history_cache(&exporter)?;
+    let history = CaptureWriter::new(true);
+    start_remp_history_worker(exporter.clone(), receiver, vec![history_sink(&history)]);
+
+    let (message, mc_block) = process_message(&cache, 1)?;
+    let documents = wait_documents(&history, 4).await;
+    let statuses = documents.iter()
+        .map(|(_, doc)| (doc["status"].as_str().unwrap(), doc["level"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(statuses, vec![
+        ("new", "queue"),
+        ("sent_to_validators", "fullnode"),
+        ("accepted", "shardchain"),
+        ("accepted", "masterchain")
+    ]);
+    for (key, doc) in &documents {
+        assert_eq!(key, &format!("{:x}", message.message_id));
+        assert_eq!(doc["uid"].as_str().unwrap(), message.message_uid.to_hex_string());
+        assert_eq!(doc["origin"].as_str().unwrap(), KeyId::from_data([7; 32]).to_string());
+        assert_eq!(doc["master_cc"].as_u64(), Some(2));
+        assert!(doc["timestamp"].as_u64().unwrap() > 0);
+    }
+    let (_, last) = &documents[3];
+    assert_eq!(last["final"].as_bool(), Some(true));
+    assert_eq!(last["block_id"].as_str().unwrap(), mc_block.root_hash().to_hex_string());
+    assert!(documents[..3].iter().all(|(_, doc)| doc["final"].as_bool() == Some(false)));
+    assert!(documents[1].1["block_id"].is_null());
+    assert_eq!(exporter.dropped(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_remp_history_final_statuses() -> Result<()> {
+    let config = RempHistoryConfig { intermediate_statuses: false, buffer_size: 16 };
+    let (exporter, receiver) = RempHistoryExporter::new(&config);
+    let exporter = Arc::new(exporter);
+    let cache = history_cache(&exporter)?;
+    let history = CaptureWriter::new(true);
+    start_remp_history_worker(exporter.clone(), receiver, vec![history_sink(&history)]);
+
+    // Only the final acceptance is exported
+    let (message, _) = process_message(&cache, 2)?;
+    let documents = wait_documents(&history, 1).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(history.documents.lock().len(), 1);
+    assert_eq!(documents[0].0, format!("{:x}", message.message_id));
+    assert_eq!(documents[0].1["status"].as_str(), Some("accepted"));
+    assert_eq!(documents[0].1["level"].as_str(), Some("masterchain"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_remp_history_backpressure() -> Result<()> {
+    // Nobody takes transitions from the buffer, so extra ones are dropped at once
+    let config = RempHistoryConfig { intermediate_statuses: true, buffer_size: 2 };
+    let (exporter, mut receiver) = RempHistoryExporter::new(&config);
+    let exporter = Arc::new(exporter);
+    let cache = history_cache(&exporter)?;
+    process_message(&cache, 3)?;
+    assert_eq!(exporter.dropped(), 2);
+
+    // Buffered transitions are the earliest ones
+    let first = receiver.recv().await.unwrap();
+    assert!(matches!(first.status, RempMessageStatus::TonNode_RempNew));
+    assert_eq!(first.origin, Some(KeyId::from_data([7; 32])));
+    let second = receiver.recv().await.unwrap();
+    assert!(matches!(second.status, RempMessageStatus::TonNode_RempSentToValidators(_)));
+    Ok(())
+}
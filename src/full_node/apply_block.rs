@@ -12,7 +12,8 @@
 */
 
 use crate::{
-    block::BlockStuff, engine_traits::EngineOperations, shard_state::ShardStateStuff,
+    block::BlockStuff, block_proof::BlockProofStuff, engine_traits::EngineOperations, 
+    error::NodeError, mesh_queues_keeper::MeshQueueUpdate, shard_state::ShardStateStuff,
     validating_utils::{UNREGISTERED_CHAIN_MAX_LEN, fmt_block_id_short}
 };
 use std::{ops::Deref, sync::Arc, time::Instant};
@@ -20,7 +21,7 @@ use storage::block_handle_db::BlockHandle;
 use ever_block::{error, fail, Result};
 use ever_block::{
     BlockIdExt, MerkleProof, Deserializable, Serializable, ShardIdent,
-    OutMsgQueueInfo, ConnectedNwOutDescr,
+    ConnectedNwOutDescr,
 };
 
 pub const MAX_RECURSION_DEPTH: u32 = UNREGISTERED_CHAIN_MAX_LEN * 2;
@@ -39,9 +40,18 @@ pub async fn apply_block(
     log::trace!("apply_block: block: {}", handle.id());
 
     let prev_ids = block.construct_prev_id()?;
-    check_prev_blocks(&prev_ids, engine, mc_seq_no, pre_apply, recursion_depth).await?;
+    if let Some(nw_id) = handle.mesh_nw_id() {
+        check_prev_mesh_blocks(nw_id, &prev_ids, engine.deref())?;
+    } else {
+        check_prev_blocks(&prev_ids, engine, mc_seq_no, pre_apply, recursion_depth).await?;
+    }
 
     if handle.is_queue_update() {
+        // Result is kept in the handle, so the update is verified once
+        if !handle.is_empty_queue_update() && !handle.is_queue_update_verified() {
+            BlockProofStuff::verify_queue_update(block)?;
+            engine.store_queue_update_verified(handle)?;
+        }
         calc_out_msg_queue(handle, block, &prev_ids, engine).await?;
         set_prev_ids(&handle, &prev_ids, engine.deref())?;
         set_next_ids(&handle, &prev_ids, engine.deref())?;
@@ -51,6 +61,10 @@ pub async fn apply_block(
         set_next_ids(&handle, &prev_ids, engine.deref())?;
     } else {
         if !handle.has_state() {
+            let _permit = match engine.apply_throttle() {
+                Some(throttle) => throttle.acquire_state_task().await?,
+                None => None
+            };
             calc_shard_state(handle, block, &prev_ids, engine).await?;
         }
         set_prev_ids(&handle, &prev_ids, engine.deref())?;
@@ -91,6 +105,23 @@ async fn check_prev_blocks(
     Ok(())
 }
 
+// Mesh updates are applied one by one by mesh client, so prev block must be already applied
+fn check_prev_mesh_blocks(
+    nw_id: i32,
+    prev_ids: &(BlockIdExt, Option<BlockIdExt>),
+    engine: &dyn EngineOperations
+) -> Result<()> {
+    for prev_id in std::iter::once(&prev_ids.0).chain(prev_ids.1.iter()) {
+        let prev_handle = engine.load_mesh_block_handle(nw_id, prev_id)?.ok_or_else(
+            || error!("Cannot load handle for prev mesh block {}", prev_id)
+        )?;
+        if !prev_handle.is_applied() {
+            fail!("Prev mesh block {} is not applied", prev_id)
+        }
+    }
+    Ok(())
+}
+
 // Gets prev block(s) state and applies merkle update from block to calculate new state
 pub async fn calc_shard_state(
     handle: &Arc<BlockHandle>,
@@ -199,6 +230,12 @@ pub async fn calc_out_msg_queue(
             let now = std::time::Instant::now();
             let cf = engine_cloned.db_cells_factory()?;
             let (ss_root, _metrics) = merkle_update.apply_for_with_cells_factory(&prev_ss_root, &cf)?;
+            if ss_root.repr_hash() != merkle_update.new_hash {
+                fail!(NodeError::QueueUpdateMismatch(format!(
+                    "Queue update {} gives out queue with hash {:x} instead of declared {:x}",
+                    block_id, ss_root.repr_hash(), merkle_update.new_hash
+                )))
+            }
             let elapsed = now.elapsed();
             log::trace!("TIME: calc_out_msg_queue: applied Merkle update {}ms   {}",
                 elapsed.as_millis(), block_id);
@@ -243,41 +280,25 @@ pub async fn calc_mesh_queues(
         prev_ids: &(BlockIdExt, Option<BlockIdExt>),
         engine: &Arc<dyn EngineOperations>
     ) -> Result<()> {
-        let old_queue = engine.load_mesh_queue(
-            mesh_update.network_global_id(),
-            &prev_ids.0,
-            &src_shard
-        )?;
-        let old_queue_root = old_queue.write_to_new_cell()?.into_cell()?;
-
-        let old_hash = old_queue_root.repr_hash();
-        if old_hash != cn_descr.out_queue_update.old_hash {
-            fail!(
-                "INTERNAL ERROR: mesh update {}: old queue hash mismatch for {} ({} != {})", 
-                mesh_update.id(), src_shard, old_hash, cn_descr.out_queue_update.old_hash
-            );
-        }
-
-        let new_queue = if cn_descr.out_queue_update.old_hash != cn_descr.out_queue_update.new_hash {
-            let new_queue_root = mesh_update.mesh_update(&src_shard)?.apply_for(&old_queue_root)?;
-            let new_hash = new_queue_root.repr_hash();
-            if new_queue_root.repr_hash() != cn_descr.out_queue_update.new_hash {
-                fail!(
-                    "INTERNAL ERROR: mesh update {}: new queue hash mismatch for {} ({} != {})", 
-                    mesh_update.id(), src_shard, new_hash, cn_descr.out_queue_update.new_hash
-                );
-            }
-            Arc::new(OutMsgQueueInfo::construct_from_cell(new_queue_root)?)
-        } else {
-            old_queue
+        let out_queue_update = &cn_descr.out_queue_update;
+        let update = MeshQueueUpdate {
+            prev_id: prev_ids.0.clone(),
+            id: mesh_update.id().clone(),
+            old_hash: out_queue_update.old_hash.clone(),
+            new_hash: out_queue_update.new_hash.clone(),
+            update: if out_queue_update.old_hash != out_queue_update.new_hash {
+                Some(mesh_update.mesh_update(src_shard)?)
+            } else {
+                None
+            },
         };
-
-        engine.store_mesh_queue(
+        let result = engine.apply_mesh_queue_update(
             mesh_update.network_global_id(),
-            &mesh_update.id(),
-            &src_shard,
-            new_queue
+            src_shard,
+            update
         )?;
+        log::trace!("calc_mesh_queues: mesh update {}, shard {}: {:?}",
+            mesh_update.id(), src_shard, result);
 
         Ok(())
     }
@@ -322,11 +343,11 @@ pub fn set_next_ids(
     match prev_ids {
         (prev_id1, Some(prev_id2)) => {
             // After merge
-            let prev_handle1 = engine.load_block_handle(&prev_id1)?.ok_or_else(
+            let prev_handle1 = load_prev_handle(handle, &prev_id1, engine)?.ok_or_else(
                 || error!("Cannot load handle for prev1 block {}", prev_id1)
             )?;
             engine.store_block_next1(&prev_handle1, handle.id())?;
-            let prev_handle2 = engine.load_block_handle(&prev_id2)?.ok_or_else(
+            let prev_handle2 = load_prev_handle(handle, &prev_id2, engine)?.ok_or_else(
                 || error!("Cannot load handle for prev2 block {}", prev_id2)
             )?;
             engine.store_block_next1(&prev_handle2, handle.id())?;
@@ -335,7 +356,7 @@ pub fn set_next_ids(
             // if after split and it is second ("1" branch) shard - set next2 for prev block
             let prev_shard = prev_id.shard().clone();
             let shard = handle.id().shard().clone();
-            let prev_handle = engine.load_block_handle(&prev_id)?.ok_or_else(
+            let prev_handle = load_prev_handle(handle, &prev_id, engine)?.ok_or_else(
                 || error!("Cannot load handle for prev block {}", prev_id)
             )?;
             if (prev_shard != shard) && (prev_shard.split()?.1 == shard) {
@@ -348,6 +369,18 @@ pub fn set_next_ids(
     Ok(())
 }
 
+// Prev block of mesh update is from the same mesh network
+fn load_prev_handle(
+    handle: &BlockHandle,
+    prev_id: &BlockIdExt,
+    engine: &dyn EngineOperations
+) -> Result<Option<Arc<BlockHandle>>> {
+    match handle.mesh_nw_id() {
+        Some(nw_id) => engine.load_mesh_block_handle(nw_id, prev_id),
+        None => engine.load_block_handle(prev_id)
+    }
+}
+
 // Set prev block ids for (pre-)applied block
 pub fn set_prev_ids(
     handle: &Arc<BlockHandle>,
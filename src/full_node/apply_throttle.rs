@@ -0,0 +1,159 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    config::CatchUpThrottleConfig, engine_traits::EngineOperations,
+    validating_utils::is_in_current_validator_set
+};
+use ever_block::{error, Result};
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[cfg(test)]
+#[path = "../tests/test_apply_throttle.rs"]
+mod tests;
+
+// Keeps count of semaphore permits small
+const MAX_STATE_TASKS: u32 = 1024;
+// Waiting for quota is split into steps, so switched limits are taken into account soon
+const MAX_WAIT_STEP: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThrottleLimits {
+    pub blocks_per_sec: u32,
+    pub state_tasks: u32,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(now: Instant) -> Self {
+        Self { tokens: 1.0, updated_at: now }
+    }
+
+    // Burst is one second of the rate
+    fn refill(&mut self, rate: u32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(rate.max(1) as f64);
+        self.updated_at = now;
+    }
+
+    // Takes a token or returns time to wait for it
+    fn take_or_wait(&mut self, rate: u32, now: Instant) -> Option<Duration> {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / rate as f64))
+        }
+    }
+}
+
+// Limits rate of applied blocks and count of concurrently computed states.
+// Tight limits are used while the node is in the current validator set, relaxed otherwise.
+pub struct ApplyThrottle {
+    config: CatchUpThrottleConfig,
+    validator: AtomicBool,
+    bucket: parking_lot::Mutex<TokenBucket>,
+    // A state task takes weighted count of permits, so the limit is switched on the fly
+    state_tasks: Arc<Semaphore>,
+    state_permits: u32,
+}
+
+impl ApplyThrottle {
+    pub fn new(config: CatchUpThrottleConfig) -> Self {
+        Self::with_time(config, Instant::now())
+    }
+
+    fn with_time(mut config: CatchUpThrottleConfig, now: Instant) -> Self {
+        config.validator_state_tasks = config.validator_state_tasks.min(MAX_STATE_TASKS);
+        config.state_tasks = config.state_tasks.min(MAX_STATE_TASKS);
+        let state_permits = [config.validator_state_tasks, config.state_tasks].iter()
+            .filter(|tasks| **tasks > 0)
+            .product::<u32>();
+        Self {
+            config,
+            validator: AtomicBool::new(false),
+            bucket: parking_lot::Mutex::new(TokenBucket::new(now)),
+            state_tasks: Arc::new(Semaphore::new(state_permits as usize)),
+            state_permits,
+        }
+    }
+
+    pub fn is_validator(&self) -> bool {
+        self.validator.load(Ordering::Relaxed)
+    }
+
+    pub fn limits(&self) -> ThrottleLimits {
+        if self.is_validator() {
+            ThrottleLimits {
+                blocks_per_sec: self.config.validator_blocks_per_sec,
+                state_tasks: self.config.validator_state_tasks,
+            }
+        } else {
+            ThrottleLimits {
+                blocks_per_sec: self.config.blocks_per_sec,
+                state_tasks: self.config.state_tasks,
+            }
+        }
+    }
+
+    // Switches limits by membership of the node in the current validator set.
+    // Returns true if limits are switched.
+    pub fn refresh(&self, engine: &dyn EngineOperations) -> bool {
+        let validator = is_in_current_validator_set(engine);
+        if self.validator.swap(validator, Ordering::Relaxed) == validator {
+            return false
+        }
+        log::info!(
+            "Apply throttle: {} limits {:?}",
+            if validator { "validator" } else { "catch-up" }, self.limits()
+        );
+        true
+    }
+
+    // Waits for quota to apply one more block
+    pub async fn acquire_block(&self) {
+        while let Some(wait) = self.try_acquire_block_at(Instant::now()) {
+            tokio::time::sleep(wait.min(MAX_WAIT_STEP)).await;
+        }
+    }
+
+    fn try_acquire_block_at(&self, now: Instant) -> Option<Duration> {
+        match self.limits().blocks_per_sec {
+            0 => None,
+            rate => self.bucket.lock().take_or_wait(rate, now)
+        }
+    }
+
+    // Permit is released on drop, None if state tasks are not limited now
+    pub async fn acquire_state_task(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        let Some(weight) = self.state_task_weight() else {
+            return Ok(None)
+        };
+        let permit = self.state_tasks.clone().acquire_many_owned(weight).await
+            .map_err(|e| error!("Can't acquire state task permit: {}", e))?;
+        Ok(Some(permit))
+    }
+
+    fn state_task_weight(&self) -> Option<u32> {
+        match self.limits().state_tasks {
+            0 => None,
+            tasks => Some(self.state_permits / tasks)
+        }
+    }
+}
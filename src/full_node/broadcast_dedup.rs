@@ -0,0 +1,165 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::config::BroadcastDedupConfig;
+use ever_block::{BlockIdExt, UInt256};
+use std::{
+    collections::{HashMap, VecDeque}, sync::{Arc, atomic::{AtomicU64, Ordering}},
+    time::{Duration, Instant}
+};
+
+#[cfg(test)]
+#[path = "../tests/test_broadcast_dedup.rs"]
+mod tests;
+
+type DedupKey = (UInt256, UInt256);
+
+struct DedupEntry {
+    // Distinguishes entries of the same key, so a stale order record doesn't evict a new entry
+    seq: u64,
+    added_at: Instant,
+}
+
+#[derive(Default)]
+struct DedupWindow {
+    entries: HashMap<DedupKey, DedupEntry>,
+    order: VecDeque<(DedupKey, u64)>,
+    next_seq: u64,
+    // Deduplicated broadcasts of the current minute
+    minute_started: Option<Instant>,
+    minute_count: u64,
+}
+
+// Window of block broadcasts being processed or recently processed, keyed by
+// (root hash, file hash). Copies of a broadcast from other neighbours are dropped
+// before deserialization while the first copy is in the window.
+pub struct BroadcastDedup {
+    window: usize,
+    ttl: Duration,
+    state: parking_lot::Mutex<DedupWindow>,
+    deduplicated: AtomicU64,
+}
+
+// Keeps the entry while broadcast is processed. Dropped without `complete` (on error or panic)
+// it removes the entry, so a valid later copy can still be processed.
+pub struct BroadcastDedupGuard {
+    dedup: Arc<BroadcastDedup>,
+    key: DedupKey,
+    seq: u64,
+    completed: bool,
+}
+
+impl BroadcastDedupGuard {
+    pub fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for BroadcastDedupGuard {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.dedup.remove(&self.key, self.seq)
+        }
+    }
+}
+
+impl BroadcastDedup {
+
+    pub fn new(config: &BroadcastDedupConfig) -> Self {
+        Self {
+            window: config.window.max(1),
+            ttl: Duration::from_secs(config.ttl_sec as u64),
+            state: parking_lot::Mutex::new(DedupWindow::default()),
+            deduplicated: AtomicU64::new(0),
+        }
+    }
+
+    // Returns guard if broadcast of the block is to be processed, None for a duplicate
+    pub fn try_start(self: &Arc<Self>, id: &BlockIdExt) -> Option<BroadcastDedupGuard> {
+        self.try_start_at(id, Instant::now())
+    }
+
+    fn try_start_at(self: &Arc<Self>, id: &BlockIdExt, now: Instant) -> Option<BroadcastDedupGuard> {
+        let key = (id.root_hash().clone(), id.file_hash().clone());
+        let mut state = self.state.lock();
+        self.expire(&mut state, now);
+        Self::roll_minute(&mut state, now);
+        if state.entries.contains_key(&key) {
+            self.deduplicated.fetch_add(1, Ordering::Relaxed);
+            state.minute_count += 1;
+            return None
+        }
+        while state.entries.len() >= self.window {
+            match state.order.pop_front() {
+                Some((oldest, seq)) => Self::remove_entry(&mut state, &oldest, seq),
+                None => break
+            }
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.entries.insert(key.clone(), DedupEntry { seq, added_at: now });
+        state.order.push_back((key.clone(), seq));
+        Some(BroadcastDedupGuard { dedup: self.clone(), key, seq, completed: false })
+    }
+
+    pub fn deduplicated(&self) -> u64 {
+        self.deduplicated.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn remove(&self, key: &DedupKey, seq: u64) {
+        Self::remove_entry(&mut self.state.lock(), key, seq)
+    }
+
+    fn remove_entry(state: &mut DedupWindow, key: &DedupKey, seq: u64) {
+        if state.entries.get(key).map_or(false, |entry| entry.seq == seq) {
+            state.entries.remove(key);
+        }
+    }
+
+    // Entries are ordered by time, so expired ones are at the front
+    fn expire(&self, state: &mut DedupWindow, now: Instant) {
+        while let Some((key, seq)) = state.order.front() {
+            let expired = match state.entries.get(key) {
+                Some(entry) if entry.seq == *seq => now.saturating_duration_since(entry.added_at) >= self.ttl,
+                // Entry was removed or replaced, the record is stale
+                _ => true
+            };
+            if !expired {
+                break
+            }
+            let (key, seq) = state.order.pop_front().unwrap();
+            Self::remove_entry(state, &key, seq);
+        }
+    }
+
+    // Reports count of deduplicated broadcasts when a minute is over
+    fn roll_minute(state: &mut DedupWindow, now: Instant) {
+        match state.minute_started {
+            Some(started) if now.saturating_duration_since(started) < Duration::from_secs(60) => (),
+            Some(_) => {
+                metrics::gauge!("block_broadcasts_deduplicated_per_minute", state.minute_count as f64);
+                state.minute_started = Some(now);
+                state.minute_count = 0;
+            }
+            None => state.minute_started = Some(now)
+        }
+    }
+}
@@ -206,7 +206,7 @@ impl<'a> Boot<'a> {
         last_known_block: BlockIdExt,
     ) -> Result<(Arc<BlockHandle>, BlockProofOrZerostate)> {
 
-        let Ok(Some(handle)) = self.engine.load_block_handle(&last_known_block) else {
+        let Ok(Some(handle)) = self.engine.load_mesh_block_handle(self.nw_id, &last_known_block) else {
             fail!("last known block {} is not in DB", self.descr);
         };
 
@@ -222,7 +222,7 @@ impl<'a> Boot<'a> {
                 self.engine.load_state(&last_key_block_id).await?
             )
         } else {
-            let handle = self.engine.load_block_handle(&last_key_block_id)?
+            let handle = self.engine.load_mesh_block_handle(self.nw_id, &last_key_block_id)?
                 .ok_or_else(|| error!("last key block {} is not in DB", last_key_block_id))?;
             BlockProofOrZerostate::with_key_block(
                 self.engine.load_block_proof(&handle, false).await?
@@ -389,7 +389,7 @@ impl<'a> Boot<'a> {
         log::debug!("{}: download_and_check_block_proof {}", self.descr, block_id);
 
         // Try to load from DB
-        if let Some(handle) = self.engine.load_block_handle(block_id)? {
+        if let Some(handle) = self.engine.load_mesh_block_handle(self.nw_id, block_id)? {
             if let Ok(proof) = self.engine.load_block_proof(&handle, is_hardfork).await {
                 return Ok((handle, proof));
             }
@@ -672,7 +672,7 @@ impl ConnectedNwClient {
             let (mesh_update, proof) = loop {
                 if last_mc_block.has_next1() {
                     let next = self.engine.load_block_next1(last_mc_block.id())?;
-                    if let Some(next_mc_block) = self.engine.load_block_handle(&next)? {
+                    if let Some(next_mc_block) = self.engine.load_mesh_block_handle(self.nw_id, &next)? {
                         if next_mc_block.is_applied() {
                             log::trace!("{}: {} already has next block {}", 
                                 self.descr, last_mc_block.id(), next_mc_block.id());
@@ -953,6 +953,12 @@ impl MeshClient {
         }
         for nw_id in to_delete {
             self.clients.remove(&nw_id);
+            if let Err(e) = self.engine.drop_mesh_network(nw_id) {
+                log::warn!("MeshClient: can't drop data of removed network {}: {}", nw_id, e);
+            }
+            if let Err(e) = self.engine.prune_mesh_queues(nw_id).await {
+                log::warn!("MeshClient: can't prune queues of removed network {}: {}", nw_id, e);
+            }
         }
 
         Ok(())
@@ -13,7 +13,10 @@
 
 pub mod state_helper;
 pub mod apply_block;
+pub mod apply_throttle;
+pub mod broadcast_dedup;
 pub mod shard_client;
+pub mod shard_pipelines;
 #[cfg(feature = "telemetry")]
 pub mod telemetry;
 pub mod counters;
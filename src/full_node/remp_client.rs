@@ -643,6 +643,9 @@ impl RempClient {
                 }
             }
         }
+        if let Some(limits) = engine.ext_message_limits() {
+            limits.check(raw_message.len(), &message)?;
+        }
 
         let dst_address = message.int_dst_account_id()
             .ok_or_else(|| error!("Can't get standart destination address from message"))?;
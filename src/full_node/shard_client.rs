@@ -14,6 +14,7 @@
 use crate::{
     block::BlockStuff, block_proof::BlockProofStuff, engine::Engine, 
     engine_traits::EngineOperations, error::NodeError,
+    full_node::shard_pipelines::{ShardApplyJob, ShardPipelines, SHARD_PIPELINE_QUEUE_LEN},
     validator::validator_utils::{
         check_crypto_signatures, calc_subset_for_masterchain,
     },
@@ -21,7 +22,8 @@ use crate::{
 };
 use crate::validator::validator_utils::calc_subset_for_workchain_standard;
 
-use std::{sync::Arc, mem::drop, time::Duration};
+use std::{sync::Arc, time::Duration};
+use storage::block_handle_db::BlockOrigin;
 use ever_block::{
     BlockIdExt, BlockSignaturesPure, CryptoSignaturePair, CryptoSignature, 
     GlobalCapabilities, ProofChain, MerkleProof, Deserializable, Block, Serializable,
@@ -95,6 +97,8 @@ async fn load_master_blocks_cycle(
                 attempt = 0;
                 id
             },
+            // Node is stopping
+            Err(e) if NodeError::is_cancelled(&e) => break Ok(()),
             Err(e) => {
                 log::error!(
                     "Error while load and apply next master block, prev: {}: attempt: {}, err: {:?}",
@@ -133,7 +137,8 @@ async fn load_next_master_block(
         fail!("Invalid next master block got: {}, prev: {}", block.id(), prev_id);
     }
 
-    let prev_state = engine.clone().wait_state(&prev_id, None, true).await?;
+    // Previous block is applied, so its state is stored or is being stored
+    let prev_state = engine.wait_stored_state(prev_id, None, engine.stopper()).await?;
     proof.check_with_master_state(&prev_state)?;
     let mut next_handle = loop {
         if let Some(next_handle) = engine.load_block_handle(block.id())? {
@@ -145,6 +150,7 @@ async fn load_next_master_block(
             }
         }
         if let Some(next_handle) = engine.store_block(&block).await?.to_non_created() {
+            next_handle.set_origin(BlockOrigin::Download);
             break next_handle
         } else {
             continue
@@ -165,16 +171,21 @@ async fn load_next_master_block(
 
 }
 
-// TODO: We limited this window to 1 thread instead of 2 because of the issue with archives.
-//       If we still need to process 2 parallel MC blocks or more, we should develop an algorithm
-//       to mark correctly shard blocks with appropriate mc_seq_no despite of application order.
-const SHARD_CLIENT_WINDOW: usize = 1;
-
 async fn load_shard_blocks_cycle(
     engine: Arc<dyn EngineOperations>, 
     shards_mc_block_id: &BlockIdExt
 ) -> Result<()> {
-    let semaphore = Arc::new(tokio::sync::Semaphore::new(SHARD_CLIENT_WINDOW));
+    let mut pipelines = ShardPipelines::new(engine.clone(), SHARD_PIPELINE_QUEUE_LEN);
+    let result = dispatch_shard_blocks(&engine, &mut pipelines, shards_mc_block_id).await;
+    pipelines.stop().await;
+    result
+}
+
+async fn dispatch_shard_blocks(
+    engine: &Arc<dyn EngineOperations>,
+    pipelines: &mut ShardPipelines,
+    shards_mc_block_id: &BlockIdExt
+) -> Result<()> {
     let mut mc_handle = engine.load_block_handle(&shards_mc_block_id)?.ok_or_else(
         || error!("Cannot load handle for shard master block {}", shards_mc_block_id)
     )?;
@@ -193,98 +204,21 @@ async fn load_shard_blocks_cycle(
         mc_handle = r.0;
         let mc_block = r.1;
 
-        log::trace!("load_shard_blocks_cycle: waiting semaphore: {}", mc_block.id());
-        let semaphore_permit = Arc::clone(&semaphore).acquire_owned().await?;
-
         log::trace!("load_shard_blocks_cycle: process next mc block: {}", mc_block.id());
-
-        let engine = Arc::clone(&engine);
-        tokio::spawn(async move {
-            if let Err(e) = load_shard_blocks(engine.clone(), semaphore_permit, &mc_block).await {
-                log::error!("FATAL!!! Unexpected error in shard blocks processing for mc block {}: {:?}", mc_block.id(), e);
-            }
-        });
+        let jobs = shard_apply_jobs(engine, &mc_block).await?;
+        match pipelines.dispatch(mc_block.id(), jobs).await {
+            Err(e) if NodeError::is_cancelled(&e) => break Ok(()),
+            result => result?
+        }
     }
 }
 
-pub async fn load_shard_blocks(
-    engine: Arc<dyn EngineOperations>,
-    semaphore_permit: tokio::sync::OwnedSemaphorePermit,
+// Jobs for all top shard blocks of the mc block, already applied blocks are skipped by pipelines
+async fn shard_apply_jobs(
+    engine: &Arc<dyn EngineOperations>,
     mc_block: &BlockStuff,
-) -> Result<()> {
-
-    fn start_apply_block_task(
-        engine: Arc<dyn EngineOperations>,
-        shard_block_id: BlockIdExt,
-        mc_seq_no: u32,
-        msg: String,
-    ) -> tokio::task::JoinHandle<()> {
-        tokio::spawn(async move {
-            let mut attempt = 0;
-            log::trace!("load_shard_blocks_cycle: {}, applying block...", msg);
-            loop { 
-                if let Err(e) = Arc::clone(&engine).download_and_apply_block(
-                    &shard_block_id, 
-                    mc_seq_no, 
-                    false
-                ).await {
-                    log::error!(
-                        "Error while applying shard block (attempt {}) {}: {:?}",
-                        attempt, shard_block_id, e
-                    );
-                    attempt += 1;
-                    // TODO make method to ban bad peer who gave bad block
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                    if engine.check_stop() {
-                        break;
-                    }
-                } else {
-                    log::trace!("load_shard_blocks_cycle: {}, applied block", msg);
-                    break;
-                }
-            }
-        })
-    }
-
-    fn start_apply_proof_chain_task(
-        proof_chain: ProofChain,
-        own_wc: i32,
-        engine: Arc<dyn EngineOperations>,
-        shard_block_id: BlockIdExt,
-        mc_seq_no: u32,
-        msg: String,
-    ) -> tokio::task::JoinHandle<()> {
-        tokio::spawn(async move {
-            let mut attempt = 0;
-            log::trace!("load_shard_blocks_cycle: {}, applying proof chain...", msg);
-            loop { 
-                if let Err(e) = apply_proof_chain(
-                    &proof_chain,
-                    own_wc,
-                    &engine,
-                    &shard_block_id, 
-                    mc_seq_no, 
-                    false,
-                    false,
-                ).await {
-                    log::error!(
-                        "Error while applying proof chain (attempt {}) {}: {:?}",
-                        attempt, shard_block_id, e
-                    );
-                    attempt += 1;
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                    if engine.check_stop() {
-                        break;
-                    }
-                } else {
-                    log::trace!("load_shard_blocks_cycle: {}, applied proof chain", msg);
-                    break;
-                }
-            }
-        })
-    }
-
-    let mut apply_tasks = Vec::new();
+) -> Result<Vec<ShardApplyJob>> {
+    let mut jobs = Vec::new();
     let mc_seq_no = mc_block.id().seq_no();
 
     if engine.load_actual_config_params().await?.has_capability(GlobalCapabilities::CapWorkchains) {
@@ -292,77 +226,22 @@ pub async fn load_shard_blocks(
         for (shard_block_id, shard_header) in mc_block.top_blocks_all_headers()? {
             let wc = shard_block_id.shard().workchain_id();
             let (is_foreign_wc, own_wc) = engine.is_foreign_wc(wc).await?;
-            let msg = format!(
-                "load_shard_blocks: mc block {}, {} {}", 
-                mc_block.id(),
-                if is_foreign_wc {
-                    format!("queue update (wc: {})", wc)
-                } else {
-                    "shard block".to_owned()
-                },
-                shard_block_id
-            );
-            if let Some(shard_block_handle) = engine.load_block_handle(&shard_block_id)? {
-                if shard_block_handle.is_applied() {
-                    continue;
-                }
-            }
-            if is_foreign_wc {
-                apply_tasks.push(start_apply_proof_chain_task(
-                    shard_header.proof_chain
-                        .ok_or_else(|| error!("INTERNAL ERROR: no proof chain for {}", shard_block_id))?,
-                    own_wc,
-                    engine.clone(),
-                    shard_block_id.clone(),
-                    mc_seq_no,
-                    msg,
-                ));
+            let proof_chain = if is_foreign_wc {
+                let proof_chain = shard_header.proof_chain
+                    .ok_or_else(|| error!("INTERNAL ERROR: no proof chain for {}", shard_block_id))?;
+                Some((proof_chain, own_wc))
             } else {
-                apply_tasks.push(start_apply_block_task(
-                    engine.clone(),
-                    shard_block_id.clone(),
-                    mc_seq_no,
-                    msg,
-                ));
-            }
+                None
+            };
+            jobs.push(ShardApplyJob { block_id: shard_block_id, mc_seq_no, proof_chain });
         }
     } else {
         // Apply full shard blocks (classic single wc config)
         for shard_block_id in mc_block.top_blocks(BASE_WORKCHAIN_ID)? {
-            let msg = format!(
-                "load_shard_blocks: mc block {}, shard block {}", 
-                mc_block.id(),
-                shard_block_id
-            );
-            if let Some(shard_block_handle) = engine.load_block_handle(&shard_block_id)? {
-                if shard_block_handle.is_applied() {
-                    continue;
-                }
-            }
-            apply_tasks.push(start_apply_block_task(
-                engine.clone(),
-                shard_block_id.clone(),
-                mc_seq_no,
-                msg,
-            ));
+            jobs.push(ShardApplyJob { block_id: shard_block_id, mc_seq_no, proof_chain: None });
         }
     };
-
-    futures::future::join_all(apply_tasks)
-        .await
-        .into_iter()
-        .find(|r| r.is_err())
-        .unwrap_or(Ok(()))?;
-
-    if engine.check_stop() {
-        return Ok(());
-    }
-
-    log::trace!("load_shard_blocks_cycle: processed mc block: {}", mc_block.id());
-    engine.save_shard_client_mc_block_id(mc_block.id())?;
-    drop(semaphore_permit);
-    Ok(())
-
+    Ok(jobs)
 }
 
 pub async fn apply_proof_chain(
@@ -509,7 +388,8 @@ pub async fn process_block_broadcast(
     if let Some(handle) = engine.load_block_handle(broadcast.id())? {
         if handle.has_data() {
             #[cfg(feature = "telemetry")] {
-                let duplicate = handle.got_by_broadcast();
+                let duplicate = 
+                    matches!(handle.origin(), BlockOrigin::Broadcast | BlockOrigin::Synthesized);
                 let unneeded = !duplicate;
                 engine.full_node_telemetry().new_block_broadcast(
                     broadcast.id(),
@@ -546,6 +426,7 @@ pub async fn process_block_broadcast(
             broadcast.data(),
         )?;
         BlockProofStuff::check_queue_update(&block)?;
+        BlockProofStuff::verify_queue_update(&block)?;
 
         validate_brodcast(broadcast, &last_applied_mc_state, broadcast.id())?;
 
@@ -610,8 +491,10 @@ pub async fn process_block_broadcast(
         return Ok(None);
     };
 
-    #[cfg(feature = "telemetry")]
-    handle.set_got_by_broadcast(true);
+    handle.set_origin(BlockOrigin::Broadcast);
+    if block.is_queue_update() {
+        engine.store_queue_update_verified(&handle)?;
+    }
 
     if let Some(proof) = proof_opt.as_ref() {
         if !handle.has_proof() {
@@ -0,0 +1,268 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    engine_traits::EngineOperations, error::NodeError, full_node::shard_client::apply_proof_chain,
+};
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, atomic::{AtomicU32, AtomicUsize, Ordering}},
+    time::Duration,
+};
+use ever_block::{fail, BlockIdExt, ProofChain, Result, ShardIdent};
+
+#[cfg(test)]
+#[path = "../tests/test_shard_pipelines.rs"]
+mod tests;
+
+// Queued shard blocks of one shard, mc blocks are not dispatched further while it is full
+pub const SHARD_PIPELINE_QUEUE_LEN: usize = 16;
+
+pub struct ShardApplyJob {
+    pub block_id: BlockIdExt,
+    // Seqno of the first mc block referring the shard block.
+    // It is applied before the job is dispatched
+    pub mc_seq_no: u32,
+    // Queue update of foreign workchain is applied by proof chain
+    pub proof_chain: Option<(ProofChain, i32)>,
+}
+
+// Mc blocks being dispatched with counts of their shard blocks not applied yet
+struct ShardClientProgress {
+    pending: parking_lot::Mutex<BTreeMap<u32, (BlockIdExt, usize)>>,
+    last_mc_seq_no: AtomicU32,
+}
+
+impl ShardClientProgress {
+
+    fn start(&self, engine: &dyn EngineOperations, mc_block_id: &BlockIdExt, jobs: usize) -> Result<()> {
+        self.last_mc_seq_no.store(mc_block_id.seq_no(), Ordering::Relaxed);
+        let mut pending = self.pending.lock();
+        pending.insert(mc_block_id.seq_no(), (mc_block_id.clone(), jobs));
+        Self::save_completed(engine, &mut pending)
+    }
+
+    fn complete(&self, engine: &dyn EngineOperations, mc_seq_no: u32) -> Result<()> {
+        let mut pending = self.pending.lock();
+        if let Some((_, jobs)) = pending.get_mut(&mc_seq_no) {
+            *jobs = jobs.saturating_sub(1);
+        }
+        Self::save_completed(engine, &mut pending)
+    }
+
+    // Shard client mc block moves over mc blocks whose shard blocks and shard blocks
+    // of all previous mc blocks are applied. Saved under lock to keep the order
+    fn save_completed(
+        engine: &dyn EngineOperations,
+        pending: &mut BTreeMap<u32, (BlockIdExt, usize)>
+    ) -> Result<()> {
+        let mut completed = None;
+        while let Some(entry) = pending.first_entry() {
+            if entry.get().1 > 0 {
+                break
+            }
+            completed = Some(entry.remove().0);
+        }
+        if let Some(mc_block_id) = completed {
+            log::trace!("shard pipelines: processed mc block: {}", mc_block_id);
+            engine.save_shard_client_mc_block_id(&mc_block_id)?;
+        }
+        Ok(())
+    }
+
+}
+
+struct ShardPipeline {
+    sender: tokio::sync::mpsc::Sender<ShardApplyJob>,
+    depth: Arc<AtomicUsize>,
+    // The same top block of next mc blocks is not queued again
+    last_queued: Option<BlockIdExt>,
+    done: tokio::sync::watch::Receiver<bool>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+// Shard blocks of each shard are applied by the own task in order of mc blocks, so a slow shard
+// delays only itself. Pipelines of split or merged shards apply their queued blocks before
+// pipelines of the new shards start
+pub struct ShardPipelines {
+    engine: Arc<dyn EngineOperations>,
+    pipelines: HashMap<ShardIdent, ShardPipeline>,
+    retired: Vec<tokio::task::JoinHandle<()>>,
+    progress: Arc<ShardClientProgress>,
+    queue_len: usize,
+}
+
+impl ShardPipelines {
+
+    pub fn new(engine: Arc<dyn EngineOperations>, queue_len: usize) -> Self {
+        Self {
+            engine,
+            pipelines: HashMap::new(),
+            retired: Vec::new(),
+            progress: Arc::new(ShardClientProgress {
+                pending: parking_lot::Mutex::new(BTreeMap::new()),
+                last_mc_seq_no: AtomicU32::new(0),
+            }),
+            queue_len: queue_len.max(1),
+        }
+    }
+
+    // Takes jobs for all top shard blocks of applied mc block
+    pub async fn dispatch(&mut self, mc_block_id: &BlockIdExt, jobs: Vec<ShardApplyJob>) -> Result<()> {
+        let shards = jobs.iter().map(|job| job.block_id.shard().clone()).collect::<HashSet<_>>();
+        let retired = self.pipelines.keys()
+            .filter(|shard| !shards.contains(shard))
+            .cloned()
+            .collect::<Vec<_>>();
+        let retired = retired.into_iter()
+            .filter_map(|shard| self.pipelines.remove(&shard).map(|pipeline| (shard, pipeline)))
+            .collect::<Vec<_>>();
+        for shard in &shards {
+            if !self.pipelines.contains_key(shard) {
+                let predecessors = retired.iter()
+                    .filter(|(retired, _)| retired.intersect_with(shard))
+                    .map(|(_, pipeline)| pipeline.done.clone())
+                    .collect();
+                self.start_pipeline(shard.clone(), predecessors);
+            }
+        }
+        self.retired.retain(|task| !task.is_finished());
+        for (shard, pipeline) in retired {
+            log::info!("shard pipelines: pipeline of shard {} is retired", shard);
+            metrics::gauge!("shard_client_queue_depth", 0.0, "shard" => shard.to_string());
+            self.retired.push(pipeline.task);
+        }
+
+        let mut queued = Vec::new();
+        for job in jobs {
+            let Some(pipeline) = self.pipelines.get_mut(job.block_id.shard()) else {
+                continue
+            };
+            if pipeline.last_queued.as_ref() == Some(&job.block_id) {
+                continue
+            }
+            pipeline.last_queued = Some(job.block_id.clone());
+            if let Some(handle) = self.engine.load_block_handle(&job.block_id)? {
+                if handle.is_applied() {
+                    continue
+                }
+            }
+            queued.push(job);
+        }
+        self.progress.start(self.engine.as_ref(), mc_block_id, queued.len())?;
+        for job in queued {
+            let shard = job.block_id.shard().clone();
+            let Some(pipeline) = self.pipelines.get(&shard) else {
+                continue
+            };
+            let depth = pipeline.depth.fetch_add(1, Ordering::Relaxed) + 1;
+            metrics::gauge!("shard_client_queue_depth", depth as f64, "shard" => shard.to_string());
+            if pipeline.sender.send(job).await.is_err() {
+                fail!(NodeError::Cancelled(format!("pipeline of shard {} is stopped", shard)))
+            }
+        }
+        Ok(())
+    }
+
+    // Waits for pipeline tasks which finish on engine stop
+    pub async fn stop(self) {
+        let tasks = self.pipelines.into_values().map(|pipeline| pipeline.task).chain(self.retired);
+        futures::future::join_all(tasks).await;
+    }
+
+    fn start_pipeline(&mut self, shard: ShardIdent, predecessors: Vec<tokio::sync::watch::Receiver<bool>>) {
+        log::info!(
+            "shard pipelines: pipeline of shard {} is started after {} pipelines",
+            shard, predecessors.len()
+        );
+        let (sender, receiver) = tokio::sync::mpsc::channel(self.queue_len);
+        let (done_sender, done) = tokio::sync::watch::channel(false);
+        let depth = Arc::new(AtomicUsize::new(0));
+        let task = tokio::spawn(run_pipeline(
+            self.engine.clone(),
+            shard.clone(),
+            receiver,
+            depth.clone(),
+            predecessors,
+            self.progress.clone(),
+            done_sender
+        ));
+        self.pipelines.insert(shard, ShardPipeline { sender, depth, last_queued: None, done, task });
+    }
+
+}
+
+async fn run_pipeline(
+    engine: Arc<dyn EngineOperations>,
+    shard: ShardIdent,
+    mut receiver: tokio::sync::mpsc::Receiver<ShardApplyJob>,
+    depth: Arc<AtomicUsize>,
+    predecessors: Vec<tokio::sync::watch::Receiver<bool>>,
+    progress: Arc<ShardClientProgress>,
+    done: tokio::sync::watch::Sender<bool>,
+) {
+    for mut predecessor in predecessors {
+        while !*predecessor.borrow() {
+            if predecessor.changed().await.is_err() {
+                break
+            }
+        }
+    }
+    let shard_label = shard.to_string();
+    while let Some(job) = receiver.recv().await {
+        let depth = depth.fetch_sub(1, Ordering::Relaxed).saturating_sub(1);
+        metrics::gauge!("shard_client_queue_depth", depth as f64, "shard" => shard_label.clone());
+        let lag = progress.last_mc_seq_no.load(Ordering::Relaxed).saturating_sub(job.mc_seq_no);
+        metrics::gauge!("shard_client_apply_lag", lag as f64, "shard" => shard_label.clone());
+        if !apply_job(&engine, &job).await {
+            break
+        }
+        if let Err(e) = progress.complete(engine.as_ref(), job.mc_seq_no) {
+            log::error!("shard pipelines: can't save shard client mc block: {:?}", e);
+        }
+    }
+    let _ = done.send(true);
+}
+
+// Returns false if the node is stopped before the block is applied
+async fn apply_job(engine: &Arc<dyn EngineOperations>, job: &ShardApplyJob) -> bool {
+    let mut attempt = 0;
+    loop {
+        if engine.check_stop() {
+            return false
+        }
+        let result = match &job.proof_chain {
+            Some((proof_chain, own_wc)) => apply_proof_chain(
+                proof_chain, *own_wc, engine, &job.block_id, job.mc_seq_no, false, false
+            ).await.map(|_| ()),
+            None => Arc::clone(engine).download_and_apply_block(&job.block_id, job.mc_seq_no, false).await
+        };
+        match result {
+            Ok(()) => {
+                log::trace!("shard pipelines: applied block {} (mc {})", job.block_id, job.mc_seq_no);
+                return true
+            }
+            Err(e) if NodeError::is_cancelled(&e) => return false,
+            Err(e) => {
+                log::error!(
+                    "Error while applying shard block (attempt {}) {}: {:?}",
+                    attempt, job.block_id, e
+                );
+                attempt += 1;
+                // TODO make method to ban bad peer who gave bad block
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
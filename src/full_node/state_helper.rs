@@ -11,11 +11,187 @@
 * limitations under the License.
 */
 
-use crate::network::full_node_client::FullNodeOverlayClient;
+use crate::{engine::Stopper, error::NodeError, network::full_node_client::FullNodeOverlayClient};
 
-use std::{sync::Arc, collections::HashSet};
-use ever_block::BlockIdExt;
+use std::{
+    sync::Arc, collections::HashSet, fs::{File, OpenOptions}, io::{Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf}
+};
+use ever_block::{BlockIdExt, ByteOrderRead, UInt256, sha256_digest};
 use ever_block::{error, fail, KeyId, Result};
+use storage::traits::Serializable;
+
+#[cfg(test)]
+#[path = "../tests/test_state_helper.rs"]
+mod tests;
+
+// Prefix of full node state keys with progress of persistent state downloads
+pub const STATE_DOWNLOAD_PROGRESS_PREFIX: &str = "StateDownload:";
+// Part max size
+const STATE_PART_SIZE: usize = 1 << 20;
+
+// Storage of download progress records, so download continues after restart
+pub trait DownloadProgressStore: Send + Sync {
+    fn load_download_progress(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    fn save_download_progress(&self, key: &str, progress: &[u8]) -> Result<()>;
+    fn drop_download_progress(&self, key: &str) -> Result<()>;
+}
+
+// Progress of persistent state download: every chunk written to the part file is hashed,
+// so on resume the file is verified against the record and continued from the last 
+// verified chunk instead of offset zero
+#[derive(Debug, PartialEq)]
+struct StateDownloadProgress {
+    id: BlockIdExt,
+    chunk_size: u32,
+    chunk_hashes: Vec<UInt256>,
+}
+
+impl StateDownloadProgress {
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut data = self.id.to_vec()?;
+        data.extend_from_slice(&self.chunk_size.to_le_bytes());
+        data.extend_from_slice(&(self.chunk_hashes.len() as u32).to_le_bytes());
+        for hash in &self.chunk_hashes {
+            data.extend_from_slice(hash.as_slice());
+        }
+        Ok(data)
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let id = BlockIdExt::deserialize(&mut cursor)?;
+        let chunk_size = cursor.read_le_u32()?;
+        let count = cursor.read_le_u32()?;
+        let mut chunk_hashes = Vec::new();
+        for _ in 0..count {
+            chunk_hashes.push(UInt256::from(cursor.read_u256()?));
+        }
+        if cursor.position() as usize != data.len() {
+            fail!("State download progress has {} extra bytes", data.len() - cursor.position() as usize)
+        }
+        Ok(Self { id, chunk_size, chunk_hashes })
+    }
+
+    fn verified_size(&self) -> u64 {
+        self.chunk_hashes.len() as u64 * self.chunk_size as u64
+    }
+}
+
+// Part file of persistent state being downloaded with its progress record
+pub struct StateDownload<'a> {
+    store: &'a dyn DownloadProgressStore,
+    key: String,
+    file: File,
+    progress: StateDownloadProgress,
+}
+
+impl<'a> StateDownload<'a> {
+
+    // Opens the part file and continues from its last verified chunk. Unverified tail 
+    // of the file is cut off, mismatched record or file start the download from zero.
+    pub fn open(
+        store: &'a dyn DownloadProgressStore,
+        dir: &Path,
+        id: &BlockIdExt,
+        msg_queue_for: Option<i32>,
+        chunk_size: u32,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let key = state_download_key(id, msg_queue_for);
+        let path = state_download_path(dir, id, msg_queue_for);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(&path)?;
+        let mut progress = StateDownloadProgress { id: id.clone(), chunk_size, chunk_hashes: Vec::new() };
+        match store.load_download_progress(&key)?.map(|data| StateDownloadProgress::deserialize(&data)) {
+            Some(Ok(saved)) if saved.id == *id && saved.chunk_size == chunk_size => {
+                let mut chunk = vec![0; chunk_size as usize];
+                for hash in saved.chunk_hashes {
+                    if read_chunk(&mut file, &mut chunk)? < chunk.len() || 
+                        UInt256::from(sha256_digest(&chunk)) != hash 
+                    {
+                        break
+                    }
+                    progress.chunk_hashes.push(hash);
+                }
+            }
+            Some(Ok(_)) => log::warn!("state download {}: progress of other download is dropped", key),
+            Some(Err(e)) => log::warn!("state download {}: broken progress is dropped: {}", key, e),
+            None => ()
+        }
+        file.set_len(progress.verified_size())?;
+        file.seek(SeekFrom::End(0))?;
+        store.save_download_progress(&key, &progress.serialize()?)?;
+        if !progress.chunk_hashes.is_empty() {
+            log::info!("state download {}: resumed from offset {}", key, progress.verified_size());
+        }
+        Ok(Self { store, key, file, progress })
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.progress.verified_size()
+    }
+
+    // Writes the next chunk, only full chunks are recorded since the last one ends download
+    pub fn append(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file.write_all(chunk)?;
+        if chunk.len() == self.progress.chunk_size as usize {
+            self.file.sync_data()?;
+            self.progress.chunk_hashes.push(UInt256::from(sha256_digest(chunk)));
+            self.store.save_download_progress(&self.key, &self.progress.serialize()?)?;
+        }
+        Ok(())
+    }
+
+    pub fn data(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+// Removes progress record and part file when the state is imported or is found broken
+pub fn finish_state_download(
+    store: &dyn DownloadProgressStore,
+    dir: &Path,
+    id: &BlockIdExt,
+    msg_queue_for: Option<i32>
+) -> Result<()> {
+    store.drop_download_progress(&state_download_key(id, msg_queue_for))?;
+    match std::fs::remove_file(state_download_path(dir, id, msg_queue_for)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(())
+    }
+}
+
+fn state_download_key(id: &BlockIdExt, msg_queue_for: Option<i32>) -> String {
+    match msg_queue_for {
+        Some(wc) => format!("{}{}:queue{}", STATE_DOWNLOAD_PROGRESS_PREFIX, id, wc),
+        None => format!("{}{}", STATE_DOWNLOAD_PROGRESS_PREFIX, id)
+    }
+}
+
+fn state_download_path(dir: &Path, id: &BlockIdExt, msg_queue_for: Option<i32>) -> PathBuf {
+    let mut name = format!(
+        "{}_{:016x}_{}_{:x}", 
+        id.shard().workchain_id(), id.shard().shard_prefix_with_tag(), id.seq_no(), id.root_hash()
+    );
+    if let Some(wc) = msg_queue_for {
+        name.push_str(&format!("_queue{}", wc));
+    }
+    dir.join(name + ".part")
+}
+
+fn read_chunk(file: &mut File, chunk: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < chunk.len() {
+        match file.read(&mut chunk[read..])? {
+            0 => break,
+            len => read += len
+        }
+    }
+    Ok(read)
+}
 
 pub async fn download_persistent_state(
     id: &BlockIdExt,
@@ -25,17 +201,21 @@ pub async fn download_persistent_state(
     active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>,
     bad_peers: &mut HashSet<Arc<KeyId>>,
     attempts: Option<usize>,
-    check_stop: &(dyn Fn() -> Result<()> + Sync + Send),
+    progress_store: &dyn DownloadProgressStore,
+    download_dir: &Path,
+    stopper: &Stopper,
 ) -> Result<Arc<Vec<u8>>> {
     let mut result = None;
     for _ in 0..10 {
         match download_persistent_state_iter(
-            id, msg_queue_for, master_id, overlay, active_peers, bad_peers, attempts, check_stop,
+            id, msg_queue_for, master_id, overlay, active_peers, bad_peers, attempts, 
+            progress_store, download_dir, stopper,
         ).await {
+            Err(e) if NodeError::is_cancelled(&e) => return Err(e),
             Err(e) => {
                 log::warn!("download_persistent_state_iter err: {}", e);
                 result = Some(Err(e));
-                futures_timer::Delay::new(std::time::Duration::from_millis(1000)).await;
+                stopper.delay(id, std::time::Duration::from_millis(1000)).await?;
                 continue;
             },
             Ok(res) => { 
@@ -54,7 +234,9 @@ async fn download_persistent_state_iter(
     active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>,
     bad_peers: &mut HashSet<Arc<KeyId>>,
     mut attempts: Option<usize>,
-    check_stop: &(dyn Fn() -> Result<()> + Sync + Send),
+    progress_store: &dyn DownloadProgressStore,
+    download_dir: &Path,
+    stopper: &Stopper,
 ) -> Result<Arc<Vec<u8>>> {
 
     if id.seq_no == 0 {
@@ -69,14 +251,16 @@ async fn download_persistent_state_iter(
 
     // Check
     let peer = loop {
-        check_stop()?;
         if let Some(remained) = attempts.as_mut() {
             if *remained == 0 {
                 fail!("Can't find peer to load {} {}", descr, id)
             }
             *remained -= 1;
         }
-        match overlay.check_persistent_state(id, msg_queue_for, master_id, active_peers, bad_peers).await {
+        let result = stopper.cancellable(
+            &descr, overlay.check_persistent_state(id, msg_queue_for, master_id, active_peers, bad_peers)
+        ).await?;
+        match result {
             Err(e) => 
                 log::warn!("check_persistent_state descr {}: {}, {}: {}", descr, id.shard(), id.seq_no(), e),
             Ok(None) => 
@@ -84,29 +268,37 @@ async fn download_persistent_state_iter(
             Ok(Some(p)) => 
                 break p
         }
-        futures_timer::Delay::new(std::time::Duration::from_millis(100)).await;
+        stopper.delay(&descr, std::time::Duration::from_millis(100)).await?;
     };
 
     // Download
     log::info!("download_persistent_state: start: id: {}, master_id: {}", id, master_id);
     let now = std::time::Instant::now();
 
-    let max_size = 1 << 20; // part max size
-    let mut offset = 0;
+    let max_size = STATE_PART_SIZE;
+    let mut download = StateDownload::open(
+        progress_store, download_dir, id, msg_queue_for, max_size as u32
+    )?;
+    let mut offset = download.offset() as usize;
     let mut peer_attempt = 0;
     let mut part_attempt = 0;
     let mut errors = 0;
-    let mut state_bytes = vec!();
     loop {
-        check_stop()?;
-        let result = overlay.download_persistent_state_part(
-            id, msg_queue_for, master_id, offset, max_size, peer.clone(), peer_attempt
-        ).await;
+        // Downloaded parts are kept, so stopped download is resumed after restart
+        let result = stopper.cancellable(
+            &descr,
+            overlay.download_persistent_state_part(
+                id, msg_queue_for, master_id, offset, max_size, peer.clone(), peer_attempt
+            )
+        ).await?;
         match result {
             Ok(next_bytes) => {
                 part_attempt = 0;
                 let len = next_bytes.len();
-                state_bytes.extend_from_slice(&next_bytes);
+                if len > max_size {
+                    fail!("Got part of {} bytes instead of {} at most", len, max_size)
+                }
+                download.append(&next_bytes)?;
                 //if (offset / max_size) % 10 == 0 {
                     log::info!("download_persistent_state {}: got part offset: {}", id.shard(), offset);
                 //}
@@ -128,7 +320,7 @@ async fn download_persistent_state_iter(
                     fail!("Error download_persistent_state_part after {} attempts: {}", 
                         part_attempt, e)
                 }
-                futures_timer::Delay::new(std::time::Duration::from_millis(100)).await;
+                stopper.delay(&descr, std::time::Duration::from_millis(100)).await?;
             }
         }
     }
@@ -136,5 +328,5 @@ async fn download_persistent_state_iter(
     log::info!("download_persistent_state: DOWNLOADED {} {}sec, id: {}, master_id: {} ", 
         descr, now.elapsed().as_secs(), id, master_id);
 
-    Ok(Arc::new(state_bytes))
+    Ok(Arc::new(download.data()?))
 }
\ No newline at end of file
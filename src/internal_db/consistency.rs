@@ -0,0 +1,271 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::internal_db::InternalDb;
+use ever_block::{BlockIdExt, Result, UInt256};
+use std::{collections::HashSet, fmt, sync::Arc};
+use storage::{
+    TimeChecker,
+    archives::package_entry_id::{GetFileNameShort, PackageEntryId},
+    block_handle_db::BlockHandle,
+};
+
+// Max count of offending block ids listed in the report
+pub const MAX_REPORTED_IDS: usize = 100;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum RepairMode {
+    DryRun, // Only report found mismatches
+    Fix, // Clear stale flags and delete orphaned files
+}
+
+#[derive(Debug, Default)]
+pub struct ConsistencyReport {
+    pub checked_handles: u64,
+    // Flag is set, but block's file is absent
+    pub missing_data: u64,
+    pub missing_proof: u64,
+    pub missing_proof_link: u64,
+    // Block is marked as archived, but its archive package is absent
+    pub missing_archive: u64,
+    // Block's file is present, but flag is not set
+    pub unflagged_files: u64,
+    // File doesn't belong to any block handle
+    pub orphaned_files: u64,
+    // State is stored in cells DB, but flag is not set
+    pub unflagged_states: u64,
+    // State is stored in cells DB, but there is no block handle for it
+    pub orphaned_states: u64,
+    pub missing_persistent_states: u64,
+    pub unflagged_persistent_states: u64,
+    pub orphaned_persistent_states: u64,
+    // Count of repaired mismatches (RepairMode::Fix only)
+    pub repaired: u64,
+    // First MAX_REPORTED_IDS offending blocks
+    pub offending_ids: Vec<BlockIdExt>,
+}
+
+impl ConsistencyReport {
+    pub fn mismatches(&self) -> u64 {
+        self.missing_data + self.missing_proof + self.missing_proof_link +
+        self.missing_archive + self.unflagged_files + self.orphaned_files +
+        self.unflagged_states + self.orphaned_states + self.missing_persistent_states +
+        self.unflagged_persistent_states + self.orphaned_persistent_states
+    }
+
+    fn add_offender(&mut self, id: &BlockIdExt) {
+        if self.offending_ids.len() < MAX_REPORTED_IDS {
+            self.offending_ids.push(id.clone());
+        }
+    }
+}
+
+impl fmt::Display for ConsistencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "checked handles: {}, missing data: {}, missing proofs: {}, missing proof links: {}, \
+            missing archives: {}, unflagged files: {}, orphaned files: {}, unflagged states: {}, \
+            orphaned states: {}, missing persistent states: {}, unflagged persistent states: {}, \
+            orphaned persistent states: {}, repaired: {}",
+            self.checked_handles, self.missing_data, self.missing_proof, self.missing_proof_link,
+            self.missing_archive, self.unflagged_files, self.orphaned_files, self.unflagged_states,
+            self.orphaned_states, self.missing_persistent_states, self.unflagged_persistent_states,
+            self.orphaned_persistent_states, self.repaired
+        )
+    }
+}
+
+impl InternalDb {
+
+    // Cross-checks block handles' flags against the presence of the underlying artifacts.
+    // States removed by the states GC keep their flags, so a flag without a state
+    // in cells DB is not considered as mismatch. Orphaned states are only reported.
+    pub async fn check_and_repair_consistency(&self, mode: RepairMode) -> Result<ConsistencyReport> {
+        let _tc = TimeChecker::new(format!("check_and_repair_consistency {:?}", mode), 10_000);
+        let fix = mode == RepairMode::Fix;
+        let mut report = ConsistencyReport::default();
+
+        let mut ids = Vec::new();
+        self.block_handle_storage.for_each_keys(&mut |id| {
+            ids.push(id);
+            Ok(true)
+        })?;
+
+        // Names of unapplied files which belong to existing handles
+        let mut known_files = HashSet::new();
+        for id in ids {
+            let handle = match self.load_block_handle(&id)? {
+                Some(handle) => handle,
+                None => continue
+            };
+            report.checked_handles += 1;
+            let mut changed = false;
+            if handle.is_archived() {
+                if self.archive_manager.check_archived(&handle).await? == Some(false) {
+                    log::warn!("check_and_repair_consistency: archive is absent for {}", handle.id());
+                    report.missing_archive += 1;
+                    report.add_offender(handle.id());
+                    if fix {
+                        handle.reset_archived();
+                        handle.reset_data();
+                        handle.reset_proof();
+                        handle.reset_proof_link();
+                        changed = true;
+                    }
+                }
+            } else {
+                changed |= self.check_unapplied_files(&handle, fix, &mut known_files, &mut report).await?;
+            }
+            if handle.has_persistent_state() &&
+               !self.has_persistent_state_file(handle.id()).await?
+            {
+                log::warn!("check_and_repair_consistency: persistent state is absent for {}", handle.id());
+                report.missing_persistent_states += 1;
+                report.add_offender(handle.id());
+                if fix {
+                    handle.reset_persistent_state();
+                    changed = true;
+                }
+            }
+            if changed {
+                self.store_block_handle(&handle, None)?;
+                report.repaired += 1;
+            }
+        }
+
+        for name in self.archive_manager.unapplied_file_names().await? {
+            if !known_files.contains(&name) {
+                log::warn!("check_and_repair_consistency: orphaned file {}", name);
+                report.orphaned_files += 1;
+                if fix {
+                    self.archive_manager.remove_unapplied_file(&name).await?;
+                    report.repaired += 1;
+                }
+            }
+        }
+
+        let mut states = Vec::new();
+        self.shard_state_dynamic_db.enumerate_ids(&mut |id| {
+            states.push(id.clone());
+            Ok(true)
+        })?;
+        for id in states {
+            match self.load_block_handle(&id)? {
+                None => {
+                    log::warn!("check_and_repair_consistency: no handle for stored state {}", id);
+                    report.orphaned_states += 1;
+                    report.add_offender(&id);
+                }
+                Some(handle) => if !handle.has_saved_state() {
+                    log::warn!("check_and_repair_consistency: state flag is not set for {}", id);
+                    report.unflagged_states += 1;
+                    report.add_offender(&id);
+                    if fix {
+                        handle.set_state_both();
+                        self.store_block_handle(&handle, None)?;
+                        report.repaired += 1;
+                    }
+                }
+            }
+        }
+
+        let mut persistent_states = Vec::new();
+        self.shard_state_persistent_db.for_each_key(&mut |key| {
+            persistent_states.push(UInt256::from(key));
+            Ok(true)
+        })?;
+        for root_hash in persistent_states {
+            match self.block_handle_storage.load_handle_by_root_hash(&root_hash)? {
+                None => {
+                    log::warn!("check_and_repair_consistency: orphaned persistent state {:x}", root_hash);
+                    report.orphaned_persistent_states += 1;
+                    if fix {
+                        self.shard_state_persistent_db.delete_file(&root_hash).await?;
+                        report.repaired += 1;
+                    }
+                }
+                // The state may be being saved now, so it is only reported
+                Some(handle) => if !handle.has_persistent_state() {
+                    log::warn!(
+                        "check_and_repair_consistency: persistent state flag is not set for {}",
+                        handle.id()
+                    );
+                    report.unflagged_persistent_states += 1;
+                    report.add_offender(handle.id());
+                }
+            }
+        }
+
+        log::info!("check_and_repair_consistency {:?}: {}", mode, report);
+        Ok(report)
+    }
+
+    async fn check_unapplied_files(
+        &self,
+        handle: &Arc<BlockHandle>,
+        fix: bool,
+        known_files: &mut HashSet<String>,
+        report: &mut ConsistencyReport,
+    ) -> Result<bool> {
+        let mut changed = false;
+        let entries = [
+            PackageEntryId::<_, UInt256, UInt256>::Block(handle.id()),
+            PackageEntryId::<_, UInt256, UInt256>::Proof(handle.id()),
+            PackageEntryId::<_, UInt256, UInt256>::ProofLink(handle.id()),
+        ];
+        for entry_id in entries.iter() {
+            known_files.insert(entry_id.filename_short());
+            // Proofs of mesh blocks are stored in separate DB
+            if handle.is_mesh() && !matches!(entry_id, PackageEntryId::Block(_)) {
+                continue
+            }
+            let _lock = match entry_id {
+                PackageEntryId::Block(_) => handle.block_file_lock().write().await,
+                _ => handle.proof_file_lock().write().await
+            };
+            let flag = match entry_id {
+                PackageEntryId::Block(_) => handle.has_data(),
+                PackageEntryId::Proof(_) => handle.has_proof(),
+                _ => handle.has_proof_link()
+            };
+            let exists = self.archive_manager.check_file(handle, entry_id);
+            if flag && !exists {
+                log::warn!("check_and_repair_consistency: {} is absent", entry_id);
+                match entry_id {
+                    PackageEntryId::Block(_) => report.missing_data += 1,
+                    PackageEntryId::Proof(_) => report.missing_proof += 1,
+                    _ => report.missing_proof_link += 1
+                }
+                report.add_offender(handle.id());
+                if fix {
+                    match entry_id {
+                        PackageEntryId::Block(_) => handle.reset_data(),
+                        PackageEntryId::Proof(_) => handle.reset_proof(),
+                        _ => handle.reset_proof_link()
+                    }
+                    changed = true;
+                }
+            } else if !flag && exists {
+                log::warn!("check_and_repair_consistency: {} is stored, but not flagged", entry_id);
+                report.unflagged_files += 1;
+                report.add_offender(handle.id());
+                if fix {
+                    self.archive_manager.remove_unapplied_file(&entry_id.filename_short()).await?;
+                    report.repaired += 1;
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
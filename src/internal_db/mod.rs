@@ -12,7 +12,9 @@
 */
 
 use crate::{
-    block::{BlockStuff, BlockKind}, block_proof::BlockProofStuff, engine_traits::EngineAlloc, error::NodeError,
+    block::{BlockStuff, BlockKind}, block_proof::{BlockProofStuff, BlockProvenance}, 
+    engine_traits::EngineAlloc, error::NodeError,
+    full_node::state_helper::DownloadProgressStore,
     shard_state::ShardStateStuff, types::top_block_descr::{TopBlockDescrId, TopBlockDescrStuff},
     internal_db::restore::check_db,
 
@@ -22,28 +24,40 @@ use crate::engine_traits::EngineTelemetry;
 
 use std::{
     cmp::min, collections::{HashMap, HashSet}, io::Cursor, mem::size_of, path::{Path, PathBuf},
-    sync::{Arc, atomic::{AtomicBool, AtomicU32, Ordering}}, time::{UNIX_EPOCH, Duration}, ops::Deref
+    sync::{Arc, atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering}}, 
+    time::{UNIX_EPOCH, Duration}, ops::Deref
 };
 use storage::{
-    StorageAlloc, TimeChecker,
-    archives::{archive_manager::ArchiveManager, package_entry_id::PackageEntryId},
-    block_handle_db::{self, BlockHandle, BlockHandleDb, BlockHandleStorage}, 
-    block_info_db::BlockInfoDb, db::rocksdb::RocksDb, block_handle_db::NodeStateDb, 
+    GcCounters, StorageAlloc, TimeChecker,
+    account_tx_index::{AccountTxIndex, TxContinuation, TxLocation},
+    archives::{
+        archive_manager::{ArchiveManager, ArchivePackageInfo}, mapped_packages::ArchiveSliceData,
+        package::{read_package_from, Package},
+        package_entry::PackageEntry, package_entry_id::{GetFileName, PackageEntryId}
+    },
+    block_handle_db::{self, BlockHandle, BlockHandleDb, BlockHandleStorage, FileHashIndexDb}, 
+    block_info_db::BlockInfoDb, block_provenance_db::BlockProvenanceDb, db::rocksdb::RocksDb, 
+    block_handle_db::{NodeStateDb, NodeStateEntry}, 
     types::BlockMeta, db::filedb::FileDb, shard_top_blocks_db::ShardTopBlocksDb,
+    fsync::{FsyncConfig, FsyncControl, FsyncControls},
+    mc_utime_index::McUtimeIndex,
+    remp_messages_db::RempMessagesDb,
     traits::Serializable, shardstate_db_async::CellsDbConfig,
 };
 use storage::shardstate_db_async::{self, AllowStateGcResolver, ShardStateDb};
 #[cfg(feature = "telemetry")]
 use storage::StorageTelemetry;
-use ever_block::{Block, BlockIdExt, INVALID_WORKCHAIN_ID, CellsFactory};
+use ever_block::{Block, BlockIdExt, INVALID_WORKCHAIN_ID, CellsFactory, ShardIdent};
 use ever_block::{
     error, fail, Result, UInt256, Cell, BocWriterStack, MAX_SAFE_DEPTH, DoneCellsStorage,
+    AccountBlock, AccountId, Deserializable, HashmapAugType, write_boc,
 };
 
 /// Full node state keys
 pub const INITIAL_MC_BLOCK: &str         = "InitMcBlockId";
 pub const LAST_APPLIED_MC_BLOCK: &str    = "LastMcBlockId";
 pub const PSS_KEEPER_MC_BLOCK: &str      = "PssKeeperBlockId";
+pub const LAST_PERSISTENT_STATE_BLOCK: &str = "LastPersistentStateBlockId";
 pub const SHARD_CLIENT_MC_BLOCK: &str    = "ShardsClientMcBlockId";
 pub const ARCHIVES_GC_BLOCK: &str        = "ArchivesGcMcBlockId";
 pub const EXTERNAL_DB_BLOCK: &str        = "ExternalDBMcBlockId";
@@ -53,6 +67,7 @@ pub const LAST_UNNEEDED_KEY_BLOCK: &str  = storage::db::rocksdb::LAST_UNNEEDED_K
 pub const LAST_MESH_KEYBLOCK: &str       = "LastMeshKeyBlockId";
 pub const LAST_MESH_MC_BLOCK: &str       = "LastMeshMcBlockId";
 pub const LAST_MESH_HARDFORK_BLOCK: &str = "LastMeshHardforkBlockId";
+pub const MESH_QUEUE_WATERMARK: &str     = "MeshQueueWatermark";
 
 pub const DB_VERSION: &str  = "DbVersion";
 
@@ -142,6 +157,10 @@ impl BlockResult {
 
 pub mod state_gc_resolver;
 pub mod restore;
+pub mod consistency;
+pub mod persistent_state_reader;
+pub mod persistent_state_delta;
+pub mod storage_usage;
 mod update;
 
 struct SsCallback { 
@@ -164,9 +183,11 @@ impl SsCallback {
 impl storage::shardstate_db_async::Callback for SsCallback {
     async fn invoke(&self, job: storage::shardstate_db_async::Job, ok: bool) {
         if ok {
-            self.handle.set_state_saved();
-            if let Err(e) = self.block_handle_storage.save_handle(&self.handle, None) {
-                log::error!("SsCallback: failed to save block handle: {}", e);
+            // State may be saved before `store_shard_state_dynamic` sets its flag
+            if self.handle.set_state_both() {
+                if let Err(e) = self.block_handle_storage.mark_dirty(&self.handle, None) {
+                    log::error!("SsCallback: failed to save block handle: {}", e);
+                }
             }
         }
         if let Some(inner) = &self.inner {
@@ -180,8 +201,19 @@ pub struct InternalDbConfig {
     pub db_directory: String,
     pub cells_gc_interval_sec: u32,
     pub cells_db_config: CellsDbConfig,
+    #[serde(default)]
+    pub fsync: FsyncConfig,
+    // DB is opened for inspection, nothing is written, updated or collected
+    #[serde(default)]
+    pub read_only: bool,
+    // Transactions of applied blocks are indexed by account
+    #[serde(default)]
+    pub account_tx_index: bool,
 }
 
+// Called for a block whose stored file turned out to be broken, e.g. to download it again
+pub type BrokenBlockHandler = Arc<dyn Fn(&BlockIdExt) + Send + Sync>;
+
 pub struct InternalDb {
     db: Arc<RocksDb>,
     block_handle_storage: Arc<BlockHandleStorage>,
@@ -190,14 +222,23 @@ pub struct InternalDb {
     next1_block_db: BlockInfoDb,
     next2_block_db: BlockInfoDb,
     shard_state_persistent_db: Arc<FileDb>,
+    shard_state_persistent_delta_db: Arc<FileDb>,
     shard_state_dynamic_db: Arc<ShardStateDb>,
     archive_manager: Arc<ArchiveManager>,
     shard_top_blocks_db: ShardTopBlocksDb,
+    mc_utime_index: McUtimeIndex,
+    block_provenance_db: BlockProvenanceDb,
     full_node_state_db: Arc<NodeStateDb>,
     mesh_key_block_proofs_db: BlockInfoDb,
+    remp_messages_db: Arc<RempMessagesDb>,
+    remp_pending_records_db: Arc<RempMessagesDb>,
+    account_tx_index: Option<AccountTxIndex>,
+    fsync: FsyncControls,
 
     config: InternalDbConfig,
     cells_gc_interval: Arc<AtomicU32>,
+    broken_block_files: AtomicU64,
+    broken_block_handler: parking_lot::RwLock<Option<BrokenBlockHandler>>,
     #[cfg(feature = "telemetry")]
     telemetry: Arc<EngineTelemetry>,
     allocated: Arc<EngineAlloc>,
@@ -217,13 +258,26 @@ impl InternalDb {
         telemetry: Arc<EngineTelemetry>,
         allocated: Arc<EngineAlloc>,
     ) -> Result<Self> {
+        let read_only = config.read_only;
         let mut db = Self::construct(
             config,
-            allow_update,
+            allow_update && !read_only,
             #[cfg(feature = "telemetry")]
             telemetry,
             allocated,
         ).await?;
+        if read_only {
+            let version = db.load_db_version()?;
+            if version != CURRENT_DB_VERSION && !db.block_handle_storage.is_empty()? {
+                fail!(
+                    "DB version {} can't be updated to current supported one {} in read-only mode", 
+                    version, 
+                    CURRENT_DB_VERSION
+                )
+            }
+            log::info!("DB VERSION {}, opened read-only", version);
+            return Ok(db)
+        }
         let version = db.resolve_db_version()?;
         if version != CURRENT_DB_VERSION {
             if allow_update {
@@ -251,29 +305,56 @@ impl InternalDb {
         telemetry: Arc<EngineTelemetry>,
         allocated: Arc<EngineAlloc>,
     ) -> Result<Self> {
+        let fsync = FsyncControls::with_config(&config.fsync)?;
         let mut hi_perf_cfs = HashSet::new();
         hi_perf_cfs.insert(CELLS_CF_NAME.to_string());
-        let db = RocksDb::with_options(config.db_directory.as_str(), "db", hi_perf_cfs, false)?;
-        let db_catchain = RocksDb::with_path(config.db_directory.as_str(), "catchains")?;
+        let read_only = config.read_only;
+        let db = RocksDb::with_options(config.db_directory.as_str(), "db", hi_perf_cfs, read_only)?;
+        let db_catchain = if read_only {
+            RocksDb::read_only(config.db_directory.as_str(), "catchains")?
+        } else {
+            RocksDb::with_path(config.db_directory.as_str(), "catchains")?
+        };
         let block_handle_db = Arc::new(
-            BlockHandleDb::with_db(db.clone(), "block_handle_db", true)?
+            BlockHandleDb::with_db_and_fsync(
+                db.clone(), "block_handle_db", true, &fsync.block_handles
+            )?
         );
         let full_node_state_db = Arc::new(
-            NodeStateDb::with_db(db.clone(), storage::db::rocksdb::NODE_STATE_DB_NAME, true)?
+            NodeStateDb::with_db_and_fsync(
+                db.clone(), storage::db::rocksdb::NODE_STATE_DB_NAME, true, &fsync.node_state
+            )?
         );
         let validator_state_db = Arc::new(
-            NodeStateDb::with_db(db_catchain, "validator_state_db", true)?
+            NodeStateDb::with_db_and_fsync(db_catchain, "validator_state_db", true, &fsync.node_state)?
+        );
+        let file_hash_db = Arc::new(
+            FileHashIndexDb::with_db_and_fsync(
+                db.clone(), "block_handle_file_hash_db", true, &fsync.block_handles
+            )?
         );
-        let block_handle_storage = Arc::new(
+        let block_handle_storage = if read_only {
+            BlockHandleStorage::read_only(
+                block_handle_db.clone(), 
+                full_node_state_db.clone(), 
+                validator_state_db,
+                Some(file_hash_db),
+                #[cfg(feature = "telemetry")]
+                telemetry.storage.clone(),
+                allocated.storage.clone()
+            )
+        } else {
             BlockHandleStorage::with_dbs(
                 block_handle_db.clone(), 
                 full_node_state_db.clone(), 
                 validator_state_db,
+                Some(file_hash_db),
                 #[cfg(feature = "telemetry")]
                 telemetry.storage.clone(),
                 allocated.storage.clone()
             )
-        );
+        };
+        let block_handle_storage = Arc::new(block_handle_storage);
 
         let mut assume_old_cells = false;
         if let Some(db_slice) = full_node_state_db.try_get(&ASSUME_OLD_FORMAT_CELLS)? {
@@ -303,6 +384,7 @@ impl InternalDb {
             &config,
             assume_old_cells,
             false,
+            &fsync.cells,
             #[cfg(feature = "telemetry")]
             telemetry.storage.clone(),
             allocated.storage.clone()
@@ -314,6 +396,7 @@ impl InternalDb {
                 db.clone(),
                 Arc::new(PathBuf::from(&config.db_directory)),
                 last_unneeded_key_block.seq_no(),
+                fsync.block_data.clone(),
                 #[cfg(feature = "telemetry")]
                 telemetry.storage.clone(),
                 allocated.storage.clone()
@@ -327,28 +410,59 @@ impl InternalDb {
             prev2_block_db: BlockInfoDb::with_db(db.clone(), "prev2_block_db", true)?,
             next1_block_db: BlockInfoDb::with_db(db.clone(), "next1_block_db", true)?,
             next2_block_db: BlockInfoDb::with_db(db.clone(), "next2_block_db", true)?,
-            shard_state_persistent_db: Arc::new(FileDb::with_path(
-                Path::new(config.db_directory.as_str()).join("shard_state_persistent_db")
+            shard_state_persistent_db: Arc::new(Self::open_file_db(
+                &config, "shard_state_persistent_db"
+            )),
+            shard_state_persistent_delta_db: Arc::new(Self::open_file_db(
+                &config, "shard_state_persistent_delta_db"
             )),
             shard_state_dynamic_db,
             archive_manager,
             shard_top_blocks_db: ShardTopBlocksDb::with_db(db.clone(), "shard_top_blocks_db", true)?,
+            mc_utime_index: McUtimeIndex::with_db(db.clone(), "mc_utime_db", true)?,
+            block_provenance_db: BlockProvenanceDb::with_db(db.clone(), "block_provenance_db", true)?,
             full_node_state_db,
             mesh_key_block_proofs_db: BlockInfoDb::with_db(db.clone(), "mesh_key_block_proofs_db", true)?,
+            remp_messages_db: Arc::new(RempMessagesDb::with_db(db.clone(), "remp_messages_db", true)?),
+            remp_pending_records_db: Arc::new(
+                RempMessagesDb::with_db(db.clone(), "remp_pending_records_db", true)?
+            ),
+            account_tx_index: if config.account_tx_index {
+                Some(AccountTxIndex::with_db(db.clone(), "account_tx_db", true)?)
+            } else {
+                None
+            },
+            fsync,
 
             cells_gc_interval: Arc::new(AtomicU32::new(config.cells_gc_interval_sec)),
+            broken_block_files: AtomicU64::new(0),
+            broken_block_handler: parking_lot::RwLock::new(None),
             config,
             #[cfg(feature = "telemetry")]
             telemetry, 
             allocated
         };
+        if !read_only {
+            db.fsync.start_flushers();
+        }
 
         Ok(db)
     }
 
+    fn open_file_db(config: &InternalDbConfig, name: &str) -> FileDb {
+        let path = Path::new(config.db_directory.as_str()).join(name);
+        if config.read_only {
+            FileDb::read_only(path)
+        } else {
+            FileDb::with_path(path)
+        }
+    }
+
     fn resolve_db_version(&self) -> Result<u32> {
         if self.block_handle_storage.is_empty()? {
             self.store_db_version(CURRENT_DB_VERSION)?;
+            // All blocks of new database are indexed while applied
+            self.mc_utime_index.set_complete()?;
             Ok(CURRENT_DB_VERSION)
         } else {
             self.load_db_version()
@@ -375,6 +489,7 @@ impl InternalDb {
         config: &InternalDbConfig,
         assume_old_cells: bool,
         update_cells: bool,
+        fsync: &FsyncControl,
         #[cfg(feature = "telemetry")]
         telemetry: Arc<StorageTelemetry>,
         allocated: Arc<StorageAlloc>,
@@ -387,6 +502,7 @@ impl InternalDb {
             assume_old_cells,
             update_cells,
             config.cells_db_config.clone(),
+            fsync,
             #[cfg(feature = "telemetry")]
             telemetry,
             allocated
@@ -412,6 +528,7 @@ impl InternalDb {
             &self.config,
             false,
             false,
+            &self.fsync.cells,
             #[cfg(feature = "telemetry")]
             self.telemetry.storage.clone(),
             self.allocated.storage.clone()
@@ -428,6 +545,7 @@ impl InternalDb {
                 &self.config,
                 true,
                 true,
+                &self.fsync.cells,
                 #[cfg(feature = "telemetry")]
                 self.telemetry.storage.clone(),
                 self.allocated.storage.clone()
@@ -445,13 +563,38 @@ impl InternalDb {
         self.shard_state_dynamic_db.stop().await
     }
 
+    #[cfg(test)]
+    pub async fn flush_block_handles(&self) -> Result<()> {
+        self.block_handle_storage.flush().await
+    }
+
+    // Queued handle writes are finished, later ones are refused
+    pub async fn stop_block_handles(&self) -> Result<()> {
+        self.block_handle_storage.stop().await
+    }
+
+    // Final flush of storage components synced periodically
+    pub fn stop_fsync(&self) -> Result<()> {
+        self.fsync.stop()
+    }
+
     fn store_block_handle(
         &self, 
         handle: &Arc<BlockHandle>,
         callback: Option<Arc<dyn block_handle_db::Callback>>
     ) -> Result<()> {
         let _tc = TimeChecker::new(format!("store_block_handle {}", handle.id()), 30);
-        self.block_handle_storage.save_handle(handle, callback)
+        self.block_handle_storage.mark_dirty(handle, callback)
+    }
+
+    // For flags which must be persisted without coalescing with other changes
+    fn flush_block_handle(
+        &self, 
+        handle: &Arc<BlockHandle>,
+        callback: Option<Arc<dyn block_handle_db::Callback>>
+    ) -> Result<()> {
+        let _tc = TimeChecker::new(format!("flush_block_handle {}", handle.id()), 30);
+        self.block_handle_storage.flush_handle(handle, callback)
     }
 
     fn load_block_linkage(
@@ -501,7 +644,15 @@ impl InternalDb {
     ) -> Result<BlockResult> {
         let _tc = TimeChecker::new(format!("create_or_load_block_handle {}", id), 30);
 
-        if let Some(handle) = self.load_block_handle(id)? {
+        let mesh_nw_id = match &kind {
+            BlockKind::MeshKit { network_id } | BlockKind::MeshUpdate { network_id } => Some(*network_id),
+            _ => None
+        };
+        let load = |id: &BlockIdExt| match mesh_nw_id {
+            Some(nw_id) => self.load_mesh_block_handle(nw_id, id),
+            None => self.load_block_handle(id)
+        };
+        if let Some(handle) = load(id)? {
             return Ok(BlockResult::with_status(handle, DataStatus::Fetched))
         }
         let meta = if let Some(block) = block {
@@ -525,9 +676,13 @@ impl InternalDb {
         } else {
             fail!("Cannot create handle for block {} without data", id)
         };
-        if let Some(handle) = self.block_handle_storage.create_handle(id.clone(), meta, callback)? {
+        let created = match mesh_nw_id {
+            Some(nw_id) => self.block_handle_storage.create_mesh_handle(nw_id, id.clone(), meta, callback)?,
+            None => self.block_handle_storage.create_handle(id.clone(), meta, callback)?
+        };
+        if let Some(handle) = created {
             Ok(BlockResult::with_status(handle, DataStatus::Created))
-        } else if let Some(handle) = self.load_block_handle(id)? {
+        } else if let Some(handle) = load(id)? {
             Ok(BlockResult::with_status(handle, DataStatus::Fetched))
         } else {
             fail!("Cannot create handle for block {}", id)
@@ -539,6 +694,25 @@ impl InternalDb {
         self.block_handle_storage.load_handle_by_id(id)
     }
 
+    pub fn load_mesh_block_handle(
+        &self, 
+        nw_id: i32, 
+        id: &BlockIdExt
+    ) -> Result<Option<Arc<BlockHandle>>> {
+        let _tc = TimeChecker::new(format!("load_mesh_block_handle {} {}", nw_id, id), 30);
+        match self.block_handle_storage.load_mesh_handle(nw_id, id.root_hash())? {
+            Some(handle) if handle.id() != id => {
+                fail!("Mesh {} block handle {} mismatches requested id {}", nw_id, handle.id(), id)
+            }
+            handle => Ok(handle)
+        }
+    }
+
+    pub fn load_block_handle_by_file_hash(&self, fh: &UInt256) -> Result<Option<Arc<BlockHandle>>> {
+        let _tc = TimeChecker::new(format!("load_block_handle_by_file_hash {:x}", fh), 30);
+        self.block_handle_storage.load_handle_by_file_hash(fh)
+    }
+
     pub async fn store_block_data(
         &self, 
         block: &BlockStuff,
@@ -585,10 +759,53 @@ impl InternalDb {
     pub async fn load_block_data_raw(&self, handle: &BlockHandle) -> Result<Vec<u8>> {
         let _tc = TimeChecker::new(format!("load_block_data_raw {}", handle.id()), 100);
         if !handle.has_data() {
-            fail!("This block is not stored yet: {:?}", handle);
+            fail!(NodeError::NotFound(format!("This block is not stored yet: {:?}", handle)));
         }
         let entry_id = PackageEntryId::<_, UInt256, UInt256>::Block(handle.id());
-        self.archive_manager.get_file(handle, &entry_id).await
+        let data = self.archive_manager.get_file(handle, &entry_id).await?;
+        // Unapplied file might be written partially if node was stopped abnormally.
+        // Queue updates and mesh blocks are not checked: their data is not the block file itself.
+        if handle.is_data_verified() || handle.is_archived() || 
+           handle.is_queue_update() || handle.is_mesh()
+        {
+            return Ok(data)
+        }
+        if UInt256::calc_file_hash(&data) != *handle.id().file_hash() {
+            self.on_broken_block_file(handle).await?;
+            fail!(
+                NodeError::NotFound(
+                    format!("Stored file of block {} is broken and is removed", handle.id())
+                )
+            );
+        }
+        handle.set_data_verified();
+        Ok(data)
+    }
+
+    pub fn set_broken_block_handler(&self, handler: BrokenBlockHandler) {
+        *self.broken_block_handler.write() = Some(handler);
+    }
+
+    pub fn broken_block_files(&self) -> u64 {
+        self.broken_block_files.load(Ordering::Relaxed)
+    }
+
+    async fn on_broken_block_file(&self, handle: &BlockHandle) -> Result<()> {
+        log::warn!("Stored file of block {} doesn't match its file hash", handle.id());
+        handle.reset_data();
+        if let Some(stored) = self.load_block_handle(handle.id())? {
+            stored.reset_data();
+            self.flush_block_handle(&stored, None)?;
+        }
+        self.archive_manager.remove_block_file(handle).await?;
+        let _count = self.broken_block_files.fetch_add(1, Ordering::Relaxed) + 1;
+        #[cfg(feature = "telemetry")]
+        self.telemetry.broken_block_files.update(_count);
+        let handler = self.broken_block_handler.read().clone();
+        if let Some(handler) = handler {
+            handler(handle.id())
+        }
+        Ok(())
     }
 
     pub fn find_mc_block_by_seq_no(&self, seqno: u32) -> Result<Arc<BlockHandle>> {
@@ -603,6 +820,60 @@ impl InternalDb {
         )
     }
 
+    // Seq_no of the latest applied masterchain block generated not later than given time
+    pub fn find_mc_seq_no_by_utime(&self, utime: u32) -> Result<Option<u32>> {
+        let _tc = TimeChecker::new(format!("find_mc_seq_no_by_utime {}", utime), 100);
+        self.build_mc_utime_index()?;
+        let found = self.mc_utime_index.find(utime)?;
+        if self.mc_utime_index.is_complete()? {
+            return Ok(found)
+        }
+        // Index is found corrupted, it is rebuilt by the next request
+        self.scan_mc_utime_index()?.find(utime)
+    }
+
+    // Seq_nos of applied masterchain blocks generated in given time range, without gaps
+    pub fn mc_seq_nos_in_utime_range(&self, from: u32, to: u32) -> Result<Vec<u32>> {
+        let _tc = TimeChecker::new(format!("mc_seq_nos_in_utime_range {}..{}", from, to), 100);
+        self.build_mc_utime_index()?;
+        let seq_nos = self.mc_utime_index.range(from, to)?;
+        if self.mc_utime_index.is_complete()? {
+            return Ok(seq_nos)
+        }
+        self.scan_mc_utime_index()?.range(from, to)
+    }
+
+    // Database made by old node has no index, it is built once by scan of block handles.
+    // Corrupted index is built again the same way.
+    fn build_mc_utime_index(&self) -> Result<()> {
+        if self.mc_utime_index.is_complete()? {
+            return Ok(())
+        }
+        let _tc = TimeChecker::new("build_mc_utime_index".to_string(), 10000);
+        if let Some(count) = self.fill_mc_utime_index(&self.mc_utime_index)? {
+            log::info!("Index of masterchain blocks by time is built, {} blocks", count);
+        }
+        Ok(())
+    }
+
+    // Slow path while the index is degraded
+    fn scan_mc_utime_index(&self) -> Result<McUtimeIndex> {
+        let _tc = TimeChecker::new("scan_mc_utime_index".to_string(), 10000);
+        let index = McUtimeIndex::in_memory();
+        self.fill_mc_utime_index(&index)?;
+        Ok(index)
+    }
+
+    fn fill_mc_utime_index(&self, index: &McUtimeIndex) -> Result<Option<usize>> {
+        index.build_if_incomplete(|add| {
+            self.block_handle_storage.for_each_applied_mc_meta(&mut |id, meta| {
+                add(meta.gen_utime, id.seq_no());
+                Ok(true)
+            })?;
+            Ok(())
+        })
+    }
+
     pub fn find_mc_block_by_seq_no_without_state(&self, seqno: u32) -> Result<Arc<BlockHandle>> {
         let _tc = TimeChecker::new(format!("find_mc_block_by_seq_no_without_state {}", seqno), 300);
         let mut found = None;
@@ -663,6 +934,7 @@ impl InternalDb {
                 {
                     let entry_id = PackageEntryId::<_, UInt256, UInt256>::ProofLink(id);
                     self.archive_manager.add_file(&entry_id, proof.data().to_vec()).await?;
+                    self.store_block_provenance(proof);
                     if handle.set_proof_link() {
                         self.store_block_handle(&handle, callback)?;
                         result = BlockResult::with_status(handle.clone(), DataStatus::Updated)
@@ -680,6 +952,7 @@ impl InternalDb {
                 {
                     let entry_id = PackageEntryId::<_, UInt256, UInt256>::Proof(id);
                     self.archive_manager.add_file(&entry_id, proof.data().to_vec()).await?;
+                    self.store_block_provenance(proof);
                     if handle.set_proof() {
                         self.store_block_handle(&handle, callback)?;
                         result = BlockResult::with_status(handle.clone(), DataStatus::Updated)
@@ -751,6 +1024,49 @@ impl InternalDb {
         Ok(result)
     }
 
+    // Proofs are stored checked, so provenance is recorded from them. Provenance of proof link
+    // doesn't replace one of full proof which has signatures
+    fn store_block_provenance(&self, proof: &BlockProofStuff) {
+        let result = || -> Result<()> {
+            let key = proof.id().root_hash();
+            if proof.is_link() && self.block_provenance_db.try_get(key)?.is_some() {
+                return Ok(())
+            }
+            self.block_provenance_db.put(key, &proof.provenance()?.serialize())
+        };
+        if let Err(e) = result() {
+            log::warn!("Can't store provenance of block {}: {}", proof.id(), e);
+        }
+    }
+
+    // Provenance of blocks stored before it was recorded is read from the stored proof 
+    // and backfilled
+    pub async fn load_block_provenance(&self, id: &BlockIdExt) -> Result<Option<BlockProvenance>> {
+        let _tc = TimeChecker::new(format!("load_block_provenance {}", id), 100);
+        if let Some(data) = self.block_provenance_db.try_get(id.root_hash())? {
+            return Ok(Some(BlockProvenance::deserialize(&data)?))
+        }
+        let Some(handle) = self.load_block_handle(id)? else {
+            return Ok(None)
+        };
+        let mut is_link = false;
+        if handle.is_mesh() || !handle.has_proof_or_link(&mut is_link) {
+            return Ok(None)
+        }
+        let provenance = self.load_block_proof(&handle, is_link).await?.provenance()?;
+        self.block_provenance_db.put(id.root_hash(), &provenance.serialize())?;
+        log::debug!("Backfilled provenance of block {}", id);
+        Ok(Some(provenance))
+    }
+
+    // Handles are dropped together with provenance records of their blocks
+    fn drop_block_handles(&self, ids: Vec<BlockIdExt>) -> Result<()> {
+        for id in &ids {
+            self.block_provenance_db.delete(id.root_hash())?;
+        }
+        self.block_handle_storage.drop_handles(ids, None)
+    }
+
     pub async fn load_block_proof(&self, handle: &BlockHandle, is_link: bool) -> Result<BlockProofStuff> {
         let _tc = TimeChecker::new(format!("load_block_proof {} {}", if is_link {"link"} else {""}, handle.id()), 100);
         let raw_proof = self.load_block_proof_raw_(handle, is_link).await?;
@@ -775,7 +1091,9 @@ impl InternalDb {
                 (PackageEntryId::<_, UInt256, UInt256>::Proof(handle.id()), handle.has_proof())
             };
             if !inited {
-                fail!("This proof{} is not in the archive: {:?}", if is_link { "link" } else { "" }, handle);
+                fail!(NodeError::NotFound(format!(
+                    "This proof{} is not in the archive: {:?}", if is_link { "link" } else { "" }, handle
+                )));
             }
             self.archive_manager.get_file(handle, &entry_id).await
         }
@@ -966,6 +1284,19 @@ impl InternalDb {
     ) -> Result<Arc<ShardStateStuff>> {
         let _tc = TimeChecker::new(format!("load_shard_state_persistent {}", id), 1000);
 
+        if !self.shard_state_persistent_db.contains(id).await? &&
+           self.shard_state_persistent_delta_db.contains(id).await?
+        {
+            let (root, _, _) = self.load_persistent_state_root(id, abort).await?;
+            return ShardStateStuff::from_state_root_cell(
+                id.clone(),
+                root,
+                #[cfg(feature = "telemetry")]
+                &self.telemetry,
+                &self.allocated
+            )
+        }
+
         // Fast (in-memory) version
         let data = self.shard_state_persistent_db.read_whole_file(id).await?;
         ShardStateStuff::deserialize_state_inmem(
@@ -980,6 +1311,7 @@ impl InternalDb {
 
     pub async fn load_shard_state_persistent_size(&self, id: &BlockIdExt) -> Result<u64> {
         let _tc = TimeChecker::new(format!("load_shard_state_persistent_size {}", id), 50);
+        self.materialize_persistent_state(id).await?;
         self.shard_state_persistent_db.get_file_size(id).await
     }
 
@@ -987,51 +1319,132 @@ impl InternalDb {
         &self,
         calc_ttl: impl Fn(u32) -> (u32, bool),
         zerostate_id: &BlockIdExt,
+        is_pinned: impl Fn(&BlockIdExt) -> bool,
+        counters: &GcCounters,
     ) -> Result<()> {
         let _tc = TimeChecker::new(format!("shard_state_persistent_gc"), 5000);
-        let mut for_delete = HashSet::new();
-        self.shard_state_persistent_db.for_each_key(&mut |key| {
+        let mut for_delete = Vec::new();
+        let mut delta_keys = Vec::new();
+        if self.shard_state_persistent_delta_db.path().exists() {
+            self.shard_state_persistent_delta_db.for_each_key(&mut |key| {
+                delta_keys.push(UInt256::from(key));
+                Ok(true)
+            })?;
+        }
+        {
+            // Materialized delta has both files
+            let mut scanned = HashSet::new();
+            let mut check = |root_hash: UInt256| -> Result<()> {
+                if !scanned.insert(root_hash.clone()) {
+                    return Ok(());
+                }
+                counters.add_scanned(1);
 
-            let root_hash = UInt256::from(key);
+                if &root_hash == zerostate_id.root_hash() {
+                    log::info!("  Zerostate: {:x}", zerostate_id.root_hash());
+                    return Ok(());
+                }
 
-            if &root_hash == zerostate_id.root_hash() {
-                log::info!("  Zerostate: {:x}", zerostate_id.root_hash());
-                return Ok(true);
+                let convert_to_utc = |t| {
+                    chrono::prelude::DateTime::<chrono::Utc>::from(
+                        UNIX_EPOCH + Duration::from_secs(t as u64)
+                    ).naive_utc()
+                };
+
+                match self.block_handle_storage.load_handle_by_root_hash(&root_hash)? {
+                    None => log::warn!("shard_state_persistent_gc: can't load handle for {:x}", root_hash),
+                    Some(handle) => {
+                        let gen_utime = handle.gen_utime()?;
+                        let (ttl, expired) = calc_ttl(gen_utime);
+                        log::info!(
+                            "{} Persistent state: {:x}, mc block: {}, gen_utime: {} UTC ({}), expired at: {} UTC ({})",
+                            if expired {"X"} else {" "},
+                            root_hash,
+                            handle.masterchain_ref_seq_no(),
+                            convert_to_utc(gen_utime),
+                            handle.gen_utime()?,
+                            convert_to_utc(ttl),
+                            ttl
+                        );
+                        if expired {
+                            if is_pinned(handle.id()) {
+                                log::info!("  Persistent state {:x} is pinned, skipped", root_hash);
+                            } else {
+                                for_delete.push(handle);
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            };
+            self.shard_state_persistent_db.for_each_key(&mut |key| {
+                check(UInt256::from(key))?;
+                Ok(true)
+            })?;
+            for root_hash in &delta_keys {
+                check(root_hash.clone())?;
             }
+        }
 
-            let convert_to_utc = |t| {
-                chrono::prelude::DateTime::<chrono::Utc>::from(
-                    UNIX_EPOCH + Duration::from_secs(t as u64)
-                ).naive_utc()
+        // Bases of alive deltas are kept while the deltas are alive
+        let expired = for_delete.iter()
+            .map(|handle| handle.id().root_hash().clone())
+            .collect::<HashSet<_>>();
+        let mut required = HashSet::new();
+        for root_hash in &delta_keys {
+            if expired.contains(root_hash) {
+                continue
+            }
+            let mut id = match self.block_handle_storage.load_handle_by_root_hash(root_hash)? {
+                Some(handle) => handle.id().clone(),
+                None => continue
             };
-
-            match self.block_handle_storage.load_handle_by_root_hash(&root_hash)? {
-                None => log::warn!("shard_state_persistent_gc: can't load handle for {:x}", root_hash),
-                Some(handle) => {
-                    let gen_utime = handle.gen_utime()?;
-                    let (ttl, expired) = calc_ttl(gen_utime);
-                    log::info!(
-                        "{} Persistent state: {:x}, mc block: {}, gen_utime: {} UTC ({}), expired at: {} UTC ({})",
-                        if expired {"X"} else {" "},
-                        root_hash,
-                        handle.masterchain_ref_seq_no(),
-                        convert_to_utc(gen_utime),
-                        handle.gen_utime()?,
-                        convert_to_utc(ttl),
-                        ttl
-                    );
-                    if expired {
-                        for_delete.insert(handle.id().clone());
+            loop {
+                match self.load_persistent_state_manifest(&id).await {
+                    Ok(Some(manifest)) => {
+                        if !required.insert(manifest.base_id.root_hash().clone()) {
+                            break
+                        }
+                        id = manifest.base_id;
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::warn!("shard_state_persistent_gc: {}", e);
+                        break
                     }
                 }
             }
-            Ok(true)
-        })?;
-
-        for id in for_delete {
-            match self.shard_state_persistent_db.delete_file(&id).await {
-                Ok(_) => log::debug!("shard_state_persistent_gc: {:x} deleted", id.root_hash()),
-                Err(e) => log::warn!("shard_state_persistent_gc: can't delete {:x}: {}", id.root_hash(), e)
+        }
+        for_delete.retain(|handle| {
+            let required = required.contains(handle.id().root_hash());
+            if required {
+                log::info!("  Persistent state {:x} is a base of delta, skipped", handle.id().root_hash());
+            }
+            !required
+        });
+
+        for handle in for_delete {
+            let id = handle.id();
+            // The state is being read now, it will be deleted next time
+            let _lock = match handle.persistent_state_lock().try_write() {
+                Ok(lock) => lock,
+                Err(_) => {
+                    log::info!("shard_state_persistent_gc: {:x} is being read, skipped", id.root_hash());
+                    continue
+                }
+            };
+            for db in [&self.shard_state_persistent_db, &self.shard_state_persistent_delta_db] {
+                if !db.contains(id).await.unwrap_or(false) {
+                    continue
+                }
+                let size = db.get_file_size(id).await.unwrap_or(0);
+                match db.delete_file(id).await {
+                    Ok(_) => {
+                        log::debug!("shard_state_persistent_gc: {:x} deleted", id.root_hash());
+                        counters.add_removed(size);
+                    }
+                    Err(e) => log::warn!("shard_state_persistent_gc: can't delete {:x}: {}", id.root_hash(), e)
+                }
             }
         }
 
@@ -1084,8 +1497,8 @@ impl InternalDb {
     ) -> Result<()> {
         self.store_block_linkage(
             handle, next, &self.next1_block_db, "store_block_next1", 
-            |handle| handle.has_next1(),
-            |handle| handle.set_next1(),
+            |handle| handle.has_next1() && handle.next1_id().is_some(),
+            |handle| handle.set_next1() | handle.set_next1_id(next),
             callback
         )
     }
@@ -1104,8 +1517,8 @@ impl InternalDb {
     ) -> Result<()> {
         self.store_block_linkage(
             handle, next2, &self.next2_block_db, "store_block_next2", 
-            |handle| handle.has_next2(),
-            |handle| handle.set_next2(),
+            |handle| handle.has_next2() && handle.next2_id().is_some(),
+            |handle| handle.set_next2() | handle.set_next2_id(next2),
             callback
         )
     }
@@ -1114,6 +1527,16 @@ impl InternalDb {
         self.load_block_linkage(id, &self.next2_block_db, "load_block_next2")
     }
 
+    pub fn store_queue_update_verified(&self, handle: &Arc<BlockHandle>) -> Result<bool> {
+        let _tc = TimeChecker::new(format!("store_queue_update_verified {}", handle.id()), 30);
+        if handle.set_queue_update_verified() {
+            self.store_block_handle(handle, None)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn store_block_applied(
         &self, 
         handle: &Arc<BlockHandle>,
@@ -1121,13 +1544,86 @@ impl InternalDb {
     ) -> Result<bool> {
         let _tc = TimeChecker::new(format!("store_block_applied {}", handle.id()), 30);
         if handle.set_block_applied() {
-            self.store_block_handle(&handle, callback)?;
+            self.flush_block_handle(handle, callback)?;
+            if handle.id().shard().is_masterchain() {
+                self.mc_utime_index.add(handle.gen_utime()?, handle.id().seq_no())?;
+            }
             Ok(true)
         } else {
             Ok(false)
         }
     }
 
+    pub fn is_account_tx_indexed(&self) -> bool {
+        self.account_tx_index.is_some()
+    }
+
+    // Indexes transactions of applied block referred by given masterchain block
+    pub fn index_account_transactions(&self, block: &BlockStuff, mc_seq_no: u32) -> Result<()> {
+        let Some(index) = &self.account_tx_index else {
+            return Ok(())
+        };
+        let _tc = TimeChecker::new(format!("index_account_transactions {}", block.id()), 30);
+        let workchain_id = block.id().shard().workchain_id();
+        let mut locations = Vec::new();
+        block.block()?.read_extra()?.read_account_blocks()?.iterate_objects(|account_block: AccountBlock| {
+            let account = UInt256::construct_from(&mut account_block.account_id().clone())?;
+            account_block.transactions().iterate_slices(|mut key, transaction_slice| {
+                locations.push(TxLocation {
+                    workchain_id,
+                    account: account.clone(),
+                    mc_seq_no,
+                    lt: key.get_next_u64()?,
+                    block_root_hash: block.id().root_hash().clone(),
+                    tx_hash: transaction_slice.reference(0)?.repr_hash(),
+                });
+                Ok(true)
+            })?;
+            Ok(true)
+        })?;
+        index.add(&locations)
+    }
+
+    // Transactions of account with masterchain seqno in given range, both ends included
+    pub fn account_transactions(
+        &self,
+        workchain_id: i32,
+        account: &UInt256,
+        from_seq_no: u32,
+        to_seq_no: u32,
+        limit: usize,
+        continuation: Option<TxContinuation>
+    ) -> Result<(Vec<TxLocation>, Option<TxContinuation>)> {
+        let _tc = TimeChecker::new(format!("account_transactions {}:{:x}", workchain_id, account), 100);
+        let index = self.account_tx_index.as_ref().ok_or_else(|| error!(NodeError::NotIndexed))?;
+        index.find(workchain_id, account, from_seq_no, to_seq_no, limit, continuation)
+    }
+
+    // BOC of indexed transaction, fails if its block is not stored any more
+    pub async fn load_account_transaction(&self, location: &TxLocation) -> Result<Vec<u8>> {
+        let handle = self.block_handle_storage.load_handle_by_root_hash(&location.block_root_hash)?
+            .ok_or_else(|| error!(NodeError::NotFound(
+                format!("Block with root hash {:x} is not stored", location.block_root_hash)
+            )))?;
+        let block = self.load_block_data(&handle).await?;
+        let account_id = AccountId::from(location.account.clone());
+        let transaction = match block.block()?.read_extra()?.read_account_blocks()?.get_serialized(account_id)? {
+            Some(account_block) => account_block.transactions().get_as_cell(&location.lt)?,
+            None => None
+        };
+        let transaction = transaction.ok_or_else(|| error!(NodeError::NotFound(format!(
+            "Transaction {} of account {:x} is not found in block {}",
+            location.lt, location.account, handle.id()
+        ))))?;
+        if transaction.repr_hash() != location.tx_hash {
+            fail!(
+                "Transaction {} of account {:x} in block {} has hash {:x} instead of indexed {:x}",
+                location.lt, location.account, handle.id(), transaction.repr_hash(), location.tx_hash
+            )
+        }
+        write_boc(&transaction)
+    }
+
     pub async fn archive_block(
         &self, 
         id: &BlockIdExt,
@@ -1144,7 +1640,7 @@ impl InternalDb {
             &handle,
             || {
                 if handle.set_archived() {
-                    self.store_block_handle(&handle, callback.clone())?;
+                    self.flush_block_handle(&handle, callback.clone())?;
                 }
                 Ok(())
             }
@@ -1174,6 +1670,28 @@ impl InternalDb {
         self.block_handle_storage.save_full_node_state(key.to_string(), block_id)
     }
 
+    pub async fn export_node_state(&self) -> Result<Vec<(String, BlockIdExt)>> {
+        let _tc = TimeChecker::new("export_node_state".to_string(), 100);
+        // Queued saves must reach db to be exported
+        self.block_handle_storage.flush().await?;
+        self.block_handle_storage.export_node_state()
+    }
+
+    pub fn list_full_node_state_keys(&self) -> Result<Vec<NodeStateEntry>> {
+        let _tc = TimeChecker::new("list_full_node_state_keys".to_string(), 100);
+        self.block_handle_storage.list_full_node_state_keys()
+    }
+
+    pub fn list_validator_state_keys(&self) -> Result<Vec<NodeStateEntry>> {
+        let _tc = TimeChecker::new("list_validator_state_keys".to_string(), 100);
+        self.block_handle_storage.list_validator_state_keys()
+    }
+
+    pub fn import_node_state(&self, entries: &[(String, BlockIdExt)], overwrite: bool) -> Result<usize> {
+        let _tc = TimeChecker::new("import_node_state".to_string(), 100);
+        self.block_handle_storage.import_node_state(entries, overwrite)
+    }
+
     pub fn drop_full_node_mesh_state(&self, nw_id: i32, key: &'static str) -> Result<()> {
         let key = format!("{key}{nw_id}");
         let _tc = TimeChecker::new(format!("drop_full_node_mesh_state {}", key), 30);
@@ -1192,6 +1710,32 @@ impl InternalDb {
         self.block_handle_storage.save_full_node_state(key, block_id)
     }
 
+    // Last applied update of mesh queue from the shard of connected network
+    pub fn load_mesh_queue_watermark(&self, nw_id: i32, shard: &ShardIdent) -> Result<Option<Arc<BlockIdExt>>> {
+        let key = format!("{MESH_QUEUE_WATERMARK}{nw_id}:{shard}");
+        let _tc = TimeChecker::new(format!("load_mesh_queue_watermark {}", key), 30);
+        self.block_handle_storage.load_full_node_state(&key)
+    }
+
+    pub fn save_mesh_queue_watermark(&self, nw_id: i32, shard: &ShardIdent, block_id: &BlockIdExt) -> Result<()> {
+        let key = format!("{MESH_QUEUE_WATERMARK}{nw_id}:{shard}");
+        let _tc = TimeChecker::new(format!("save_mesh_queue_watermark {}", key), 30);
+        self.block_handle_storage.save_full_node_state(key, block_id)
+    }
+
+    pub fn drop_mesh_queue_watermarks(&self, nw_id: i32) -> Result<usize> {
+        let prefix = format!("{MESH_QUEUE_WATERMARK}{nw_id}:");
+        let _tc = TimeChecker::new(format!("drop_mesh_queue_watermarks {}", prefix), 100);
+        let mut dropped = 0;
+        for entry in self.block_handle_storage.list_full_node_state_keys()? {
+            if entry.key.starts_with(&prefix) {
+                self.block_handle_storage.drop_full_node_state(entry.key)?;
+                dropped += 1;
+            }
+        }
+        Ok(dropped)
+    }
+
     pub fn drop_validator_state(&self, key: &'static str) -> Result<()> {
         let _tc = TimeChecker::new(format!("drop_validator_state {}", key), 30);
         self.block_handle_storage.drop_validator_state(key.to_string())
@@ -1207,12 +1751,57 @@ impl InternalDb {
         self.block_handle_storage.save_validator_state(key.to_string(), block_id)
     }
 
+    pub fn save_validator_state_with_ttl(
+        &self, 
+        key: String, 
+        block_id: &BlockIdExt, 
+        ttl_secs: u32
+    ) -> Result<()> {
+        let _tc = TimeChecker::new(format!("save_validator_state_with_ttl {}", key), 30);
+        self.block_handle_storage.save_validator_state_with_ttl(key, block_id, ttl_secs)
+    }
+
+    pub fn save_session_checkpoint(&self, session_id: &UInt256, data: Vec<u8>) -> Result<()> {
+        let _tc = TimeChecker::new(format!("save_session_checkpoint {:x}", session_id), 30);
+        self.block_handle_storage.save_session_checkpoint(session_id, data)
+    }
+
+    pub fn load_session_checkpoint(&self, session_id: &UInt256) -> Result<Option<Vec<u8>>> {
+        let _tc = TimeChecker::new(format!("load_session_checkpoint {:x}", session_id), 30);
+        self.block_handle_storage.load_session_checkpoint(session_id)
+    }
+
+    pub fn save_validator_record(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        let _tc = TimeChecker::new(format!("save_validator_record {}", name), 30);
+        self.block_handle_storage.save_validator_record(name, data)
+    }
+
+    pub fn load_validator_record(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let _tc = TimeChecker::new(format!("load_validator_record {}", name), 30);
+        self.block_handle_storage.load_validator_record(name)
+    }
+
+    pub fn drop_stale_session_checkpoints(&self, active: &HashSet<UInt256>) -> Result<usize> {
+        let _tc = TimeChecker::new(format!("drop_stale_session_checkpoints"), 100);
+        self.block_handle_storage.drop_stale_session_checkpoints(active)
+    }
+
+    pub fn gc_expired_validator_states(&self, now: u32, counters: &GcCounters) -> Result<usize> {
+        let _tc = TimeChecker::new(format!("gc_expired_validator_states"), 100);
+        self.block_handle_storage.gc_expired_states(now, counters)
+    }
+
     pub async fn get_archive_id(&self, mc_seq_no: u32) -> Option<u64> {
         let _tc = TimeChecker::new(format!("get_archive_id {}", mc_seq_no), 30);
         self.archive_manager.get_archive_id(mc_seq_no).await
     }
 
-    pub async fn get_archive_slice(&self, archive_id: u64, offset: u64, limit: u32) -> Result<Vec<u8>> {
+    pub async fn get_archive_slice(
+        &self,
+        archive_id: u64,
+        offset: u64,
+        limit: u32
+    ) -> Result<ArchiveSliceData> {
         let _tc = TimeChecker::new(
             format!("get_archive_slice id: {}, offset: {}, limit: {}", archive_id, offset, limit),
             300
@@ -1220,17 +1809,216 @@ impl InternalDb {
         self.archive_manager.get_archive_slice(archive_id, offset, limit).await
     }
 
+    // Writes masterchain blocks from the range with their proofs and referenced shard blocks 
+    // with proof links into a new package file. Returns the number of written entries.
+    pub async fn export_archive_range(
+        &self,
+        mc_seqno_from: u32,
+        mc_seqno_to: u32,
+        dest: &Path
+    ) -> Result<u64> {
+        let _tc = TimeChecker::new(
+            format!("export_archive_range {}..={} to {}", mc_seqno_from, mc_seqno_to, dest.display()),
+            1000
+        );
+        if mc_seqno_from > mc_seqno_to {
+            fail!(NodeError::InvalidArg(
+                format!("Wrong masterchain seqno range {}..={}", mc_seqno_from, mc_seqno_to)
+            ))
+        }
+        if dest.exists() {
+            fail!(NodeError::InvalidArg(format!("File {} already exists", dest.display())))
+        }
+
+        // Collect and check all the blocks first to not produce incomplete package
+        let mut mc_handle = self.find_mc_block_by_seq_no(mc_seqno_from)
+            .or_else(|_| self.find_mc_block_by_seq_no_without_state(mc_seqno_from))?;
+        let mut prev_tops = match self.load_block_prev1(mc_handle.id()) {
+            Ok(prev_id) if mc_seqno_from > 0 => self.load_top_blocks(&prev_id).await.unwrap_or_default(),
+            _ => HashSet::new()
+        };
+        let mut handles = Vec::new();
+        loop {
+            self.check_exported_block(&mc_handle, mc_seqno_from, mc_seqno_to)?;
+            let tops = self.load_top_blocks(mc_handle.id()).await?;
+            handles.push(mc_handle.clone());
+
+            // Shard blocks between previous and current top blocks. If previous ones 
+            // are unknown, only the current top blocks are exported
+            let mut stack: Vec<BlockIdExt> = tops.iter().cloned().collect();
+            let mut visited = HashSet::new();
+            while let Some(id) = stack.pop() {
+                if id.seq_no() == 0 || prev_tops.contains(&id) || !visited.insert(id.clone()) {
+                    continue
+                }
+                let handle = self.load_block_handle(&id)?.ok_or_else(
+                    || error!("Cannot load handle for shard block {}", id)
+                )?;
+                self.check_exported_block(&handle, mc_seqno_from, mc_seqno_to)?;
+                handles.push(handle);
+                if !prev_tops.is_empty() {
+                    stack.push(self.load_block_prev1(&id)?);
+                    if let Some(prev2) = self.load_block_prev2(&id)? {
+                        stack.push(prev2);
+                    }
+                }
+            }
+
+            if mc_handle.id().seq_no() >= mc_seqno_to {
+                break
+            }
+            let next_id = self.load_block_next1(mc_handle.id())?;
+            mc_handle = self.load_block_handle(&next_id)?.ok_or_else(
+                || error!("Cannot load handle for master block {}", next_id)
+            )?;
+            prev_tops = tops;
+        }
+
+        let package = Package::open(dest.to_path_buf(), false, true).await?;
+        let mut entries = 0;
+        for handle in handles {
+            let is_link = !handle.id().shard().is_masterchain();
+            let block_entry_id = PackageEntryId::<_, UInt256, UInt256>::Block(handle.id());
+            let data = self.load_block_data_raw(&handle).await?;
+            package.append_entry(&PackageEntry::with_data(block_entry_id.filename(), data), |_, _| Ok(())).await?;
+            let proof_entry_id = if is_link {
+                PackageEntryId::<_, UInt256, UInt256>::ProofLink(handle.id())
+            } else {
+                PackageEntryId::<_, UInt256, UInt256>::Proof(handle.id())
+            };
+            let data = self.load_block_proof_raw(&handle, is_link).await?;
+            package.append_entry(&PackageEntry::with_data(proof_entry_id.filename(), data), |_, _| Ok(())).await?;
+            entries += 2;
+        }
+        log::info!(
+            target: "storage", 
+            "Exported {} entries for mc blocks {}..={} to {}", 
+            entries, mc_seqno_from, mc_seqno_to, dest.display()
+        );
+        Ok(entries)
+    }
+
+    fn check_exported_block(&self, handle: &BlockHandle, mc_seqno_from: u32, mc_seqno_to: u32) -> Result<()> {
+        if !handle.has_data() {
+            fail!(
+                "Can't export archive for mc blocks {}..={}: block {} has no data", 
+                mc_seqno_from, mc_seqno_to, handle.id()
+            )
+        }
+        let mut is_link = false;
+        if !handle.has_proof_or_link(&mut is_link) {
+            fail!(
+                "Can't export archive for mc blocks {}..={}: block {} has no proof{}", 
+                mc_seqno_from, mc_seqno_to, handle.id(), if is_link { " link" } else { "" }
+            )
+        }
+        Ok(())
+    }
+
+    async fn load_top_blocks(&self, mc_block_id: &BlockIdExt) -> Result<HashSet<BlockIdExt>> {
+        let handle = self.load_block_handle(mc_block_id)?.ok_or_else(
+            || error!("Cannot load handle for master block {}", mc_block_id)
+        )?;
+        Ok(self.load_block_data(&handle).await?.top_blocks_all()?.into_iter().collect())
+    }
+
+    // Stores blocks and proofs from the package file, the proofs are not checked and
+    // the blocks are not applied. Returns the number of imported entries.
+    pub async fn import_archive_package(&self, path: &Path) -> Result<u64> {
+        let _tc = TimeChecker::new(format!("import_archive_package {}", path.display()), 1000);
+        let file = tokio::fs::File::open(path).await
+            .map_err(|e| error!("Can't open archive package {}: {}", path.display(), e))?;
+        let mut reader = read_package_from(file).await?;
+        let mut proofs = Vec::new();
+        let mut entries = 0;
+        while let Some(entry) = reader.next().await? {
+            match PackageEntryId::from_filename(entry.filename())? {
+                PackageEntryId::Block(id) => {
+                    let data = entry.take_data();
+                    if UInt256::calc_file_hash(&data) != *id.file_hash() {
+                        fail!(NodeError::InvalidData(format!("Wrong file hash of block {} in package", id)))
+                    }
+                    let block = BlockStuff::deserialize_block(id, data)?;
+                    self.store_block_data(&block, None).await?;
+                }
+                PackageEntryId::Proof(id) =>
+                    proofs.push(BlockProofStuff::deserialize(&id, entry.take_data(), false)?),
+                PackageEntryId::ProofLink(id) =>
+                    proofs.push(BlockProofStuff::deserialize(&id, entry.take_data(), true)?),
+                entry_id => fail!("Unsupported archive package entry {:?}", entry_id)
+            }
+            entries += 1;
+        }
+        for proof in proofs {
+            let handle = self.load_block_handle(proof.id())?;
+            self.store_block_proof(proof.id(), handle, &proof, None).await?;
+        }
+        log::info!(target: "storage", "Imported {} entries from {}", entries, path.display());
+        Ok(entries)
+    }
+
     pub async fn clean_unapplied_files(&self, ids: &[BlockIdExt]) {
         let _tc = TimeChecker::new("clean_unapplied_files".to_owned(), 300);
         self.archive_manager.clean_unapplied_files(ids).await;
     }
 
-    pub async fn archive_gc(&self, last_unneeded_key_block: &BlockIdExt) -> Result<()> {
+    pub async fn archive_gc(
+        &self, 
+        last_unneeded_key_block: &BlockIdExt, 
+        counters: &GcCounters
+    ) -> Result<()> {
         let _tc = TimeChecker::new(format!("archive_gc {}", last_unneeded_key_block), 300);
-        self.archive_manager.gc(last_unneeded_key_block).await;
+        self.archive_manager.gc(last_unneeded_key_block, counters).await;
+        if let Some(index) = &self.account_tx_index {
+            let removed = index.prune(0, last_unneeded_key_block.seq_no())?;
+            log::debug!("archive_gc: removed {} indexed account transactions", removed);
+        }
         self.save_full_node_state(LAST_UNNEEDED_KEY_BLOCK, last_unneeded_key_block)
     }
 
+    // Directory of archive packages and unapplied block files
+    pub fn archive_dir(&self) -> PathBuf {
+        Path::new(self.config.db_directory.as_str()).join(ArchiveManager::ARCHIVE_DIR)
+    }
+
+    pub async fn archive_packages(&self) -> Vec<ArchivePackageInfo> {
+        self.archive_manager.archive_packages().await
+    }
+
+    // Removes archive package of blocks, its blocks are not archived any more. Key blocks 
+    // are still archived because they are kept in packages of key blocks as well.
+    pub async fn remove_archive_package(&self, archive_id: u32, counters: &GcCounters) -> Result<u64> {
+        let _tc = TimeChecker::new(format!("remove_archive_package {}", archive_id), 300);
+        let end_seq_no = if self.account_tx_index.is_some() {
+            self.archive_manager.archive_packages().await.into_iter()
+                .find(|package| package.archive_id == archive_id)
+                .map(|package| package.end_seq_no)
+        } else {
+            None
+        };
+        let (size, root_hashes) = self.archive_manager.remove_archive_package(archive_id).await?;
+        if let (Some(index), Some(end_seq_no)) = (&self.account_tx_index, end_seq_no) {
+            let removed = index.prune(archive_id, end_seq_no)?;
+            log::debug!("remove_archive_package: removed {} indexed account transactions", removed);
+        }
+        for root_hash in root_hashes {
+            let Some(handle) = self.block_handle_storage.load_handle_by_root_hash(&root_hash)? else {
+                continue
+            };
+            if !handle.is_archived() || handle.is_key_block()? {
+                continue
+            }
+            handle.reset_archived();
+            handle.reset_data();
+            handle.reset_proof();
+            handle.reset_proof_link();
+            self.store_block_handle(&handle, None)?;
+        }
+        counters.add_scanned(1);
+        counters.add_removed(size);
+        Ok(size)
+    }
+
     pub fn assign_mc_ref_seq_no(
         &self, 
         handle: &Arc<BlockHandle>, 
@@ -1299,6 +2087,14 @@ impl InternalDb {
         self.shard_top_blocks_db.delete(&id.to_bytes()?)
     }
 
+    pub fn remp_messages_db(&self) -> Arc<RempMessagesDb> {
+        self.remp_messages_db.clone()
+    }
+
+    pub fn remp_pending_records_db(&self) -> Arc<RempMessagesDb> {
+        self.remp_pending_records_db.clone()
+    }
+
     pub fn db_root_dir(&self) -> Result<&str> {
         Ok(&self.config.db_directory)
     }
@@ -1331,24 +2127,26 @@ impl InternalDb {
         }).await?;
 
         // truncate handles and prev/next links
-        fn clear_dbs(db: &InternalDb, id: BlockIdExt) {
+        fn clear_dbs(db: &InternalDb, id: BlockIdExt, handles: &mut Vec<BlockIdExt>) {
             log::trace!("truncate_database: trying to drop handle {}", id);
-            let _ = db.block_handle_storage.drop_handle(id.clone(), None);
             let _ = db.prev2_block_db.delete(&id);
             let _ = db.prev1_block_db.delete(&id);
             let _ = db.next2_block_db.delete(&id);
             let _ = db.next1_block_db.delete(&id);
+            handles.push(id);
         }
 
+        let mut handles = Vec::new();
+
         self.next1_block_db.for_each(&mut |_key, val| {
             let id = BlockIdExt::deserialize(&mut Cursor::new(&val))?;
             if id.shard().is_masterchain() && id.seq_no() >= mc_block_id.seq_no() {
-                clear_dbs(self, id);
+                clear_dbs(self, id, &mut handles);
             } else {
                 for tb in &top_blocks {
                     if id.shard().intersect_with(tb.shard()) {
                         if id.seq_no() > tb.seq_no() {
-                            clear_dbs(self, id);
+                            clear_dbs(self, id, &mut handles);
                             break;
                         }
                     }
@@ -1361,13 +2159,14 @@ impl InternalDb {
             for tb in &top_blocks {
                 if id.shard().intersect_with(tb.shard()) {
                     if id.seq_no() > tb.seq_no() {
-                        clear_dbs(self, id);
+                        clear_dbs(self, id, &mut handles);
                         break;
                     }
                 }
             }
             Ok(true)
         })?;
+        self.drop_block_handles(handles)?;
 
         // truncate info related with last handles
         fn clear_last_handle(db: &InternalDb, id: &BlockIdExt) {
@@ -1428,9 +2227,73 @@ impl InternalDb {
         self.block_handle_storage.load_full_block_id(root_hash)
     }
 
+    pub fn for_each_key_block_handle(
+        &self,
+        predicate: &mut dyn FnMut(Arc<BlockHandle>) -> Result<bool>
+    ) -> Result<bool> {
+        self.block_handle_storage.for_each_key_block_handle(predicate)
+    }
+
+    pub fn for_each_mesh_handle(
+        &self,
+        nw_id: i32,
+        predicate: &mut dyn FnMut(Arc<BlockHandle>) -> Result<bool>
+    ) -> Result<bool> {
+        self.block_handle_storage.for_each_mesh_handle(nw_id, predicate)
+    }
+
+    pub fn drop_mesh_handles(&self, nw_id: i32, with_queue_updates: bool) -> Result<usize> {
+        let _tc = TimeChecker::new(format!("drop_mesh_handles {}", nw_id), 1000);
+        self.block_handle_storage.drop_mesh_handles(nw_id, with_queue_updates)
+    }
+
+    // Drops handles and stored data of queue updates for given workchain.
+    // Returns number of dropped handles and freed bytes
+    pub async fn drop_queue_update_handles(&self, target_wc: i32) -> Result<(usize, u64)> {
+        let _tc = TimeChecker::new(format!("drop_queue_update_handles {}", target_wc), 1000);
+        let mut handles = Vec::new();
+        self.block_handle_storage.for_each_queue_update_handle(target_wc, &mut |handle| {
+            handles.push(handle);
+            Ok(true)
+        })?;
+        let mut bytes = 0;
+        for handle in &handles {
+            if handle.has_data() && !handle.is_archived() {
+                bytes += self.archive_manager.remove_block_file(handle).await?;
+            }
+        }
+        let count = handles.len();
+        if count > 0 {
+            let ids = handles.iter().map(|handle| handle.id().clone()).collect();
+            self.drop_block_handles(ids)?;
+        }
+        Ok((count, bytes))
+    }
+
     pub fn cells_factory(&self) -> Result<Arc<dyn CellsFactory>> {
         self.shard_state_dynamic_db.cells_factory()
     }
+
+    // Directory of persistent state part files being downloaded
+    pub fn state_downloads_dir(&self) -> PathBuf {
+        Path::new(self.config.db_directory.as_str()).join("state_downloads")
+    }
+}
+
+// Progress records are written directly, not by handle storer, as part files are synced
+// right before the record
+impl DownloadProgressStore for InternalDb {
+    fn load_download_progress(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.full_node_state_db.try_get_raw(key.as_bytes())?.map(|slice| slice.as_ref().to_vec()))
+    }
+
+    fn save_download_progress(&self, key: &str, progress: &[u8]) -> Result<()> {
+        self.full_node_state_db.put_raw(key.as_bytes(), progress)
+    }
+
+    fn drop_download_progress(&self, key: &str) -> Result<()> {
+        self.full_node_state_db.delete_raw(key.as_bytes())
+    }
 }
 
 #[cfg(test)]
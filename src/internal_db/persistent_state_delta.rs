@@ -0,0 +1,256 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{error::NodeError, internal_db::InternalDb, shard_state::ShardStateStuff};
+use ever_block::{
+    error, fail, write_boc, BlockIdExt, BocReader, ByteOrderRead, Cell, Deserializable,
+    MerkleUpdate, Result, UInt256,
+};
+use std::{io::Cursor, ops::Deref, path::Path, sync::Arc};
+use storage::{TimeChecker, block_handle_db::{self, BlockHandle}, traits::Serializable};
+
+const DELTA_MAGIC: u32 = 0x746c6564; // "delt"
+const DELTA_VERSION: u8 = 1;
+// magic, version, checksum
+const DELTA_HEADER_LEN: usize = 4 + 1 + 32;
+// Protects from walking an endless chain in a broken db
+const MAX_DELTA_CHAIN_LEN: u32 = 1024;
+// Materialized states are written here first and then moved into the persistent states db
+const PERSISTENT_STATE_TEMP_DIR: &str = "shard_state_persistent_tmp";
+
+// Describes the persistent state a delta is made over
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeltaManifest {
+    pub base_id: BlockIdExt,
+    // Hash of the base's stored file, which is a delta too if the base is not a full snapshot
+    pub base_file_hash: UInt256,
+    // Count of deltas down to the full snapshot including this one
+    pub chain_len: u32,
+}
+
+// Delta file is: magic, version, sha256 of the rest, manifest, BOC of merkle update
+pub fn build_delta(manifest: &DeltaManifest, base_root: &Cell, root: &Cell) -> Result<Vec<u8>> {
+    let update = MerkleUpdate::create(base_root, root)?;
+    let mut body = Vec::new();
+    manifest.base_id.serialize(&mut body)?;
+    body.extend_from_slice(manifest.base_file_hash.as_slice());
+    body.extend_from_slice(&manifest.chain_len.to_le_bytes());
+    body.extend_from_slice(&ever_block::Serializable::write_to_bytes(&update)?);
+    let mut data = Vec::with_capacity(DELTA_HEADER_LEN + body.len());
+    data.extend_from_slice(&DELTA_MAGIC.to_le_bytes());
+    data.push(DELTA_VERSION);
+    data.extend_from_slice(UInt256::calc_file_hash(&body).as_slice());
+    data.extend_from_slice(&body);
+    Ok(data)
+}
+
+// Returns manifest and offset of the merkle update
+pub fn parse_delta_manifest(data: &[u8]) -> Result<(DeltaManifest, usize)> {
+    if data.len() < DELTA_HEADER_LEN {
+        fail!("Persistent state delta is too short: {} bytes", data.len())
+    }
+    let mut reader = Cursor::new(data);
+    if reader.read_le_u32()? != DELTA_MAGIC {
+        fail!("Not a persistent state delta")
+    }
+    let version = reader.read_byte()?;
+    if version != DELTA_VERSION {
+        fail!("Unsupported persistent state delta version {}", version)
+    }
+    let checksum = UInt256::from(reader.read_u256()?);
+    if checksum != UInt256::calc_file_hash(&data[DELTA_HEADER_LEN..]) {
+        fail!("Persistent state delta checksum mismatch")
+    }
+    let base_id = BlockIdExt::deserialize(&mut reader)?;
+    let base_file_hash = UInt256::from(reader.read_u256()?);
+    let chain_len = reader.read_le_u32()?;
+    if chain_len == 0 || chain_len > MAX_DELTA_CHAIN_LEN {
+        fail!("Bad persistent state delta chain length {}", chain_len)
+    }
+    Ok((DeltaManifest { base_id, base_file_hash, chain_len }, reader.position() as usize))
+}
+
+pub fn parse_delta(data: &[u8]) -> Result<(DeltaManifest, MerkleUpdate)> {
+    let (manifest, offset) = parse_delta_manifest(data)?;
+    let update = MerkleUpdate::construct_from_bytes(&data[offset..])?;
+    Ok((manifest, update))
+}
+
+impl InternalDb {
+
+    // Saves the state as difference with the base persistent state of the same shard
+    pub async fn store_shard_state_persistent_delta(
+        &self,
+        handle: &Arc<BlockHandle>,
+        state: Arc<ShardStateStuff>,
+        base_id: &BlockIdExt,
+        callback: Option<Arc<dyn block_handle_db::Callback>>,
+        abort: Arc<dyn Fn() -> bool + Send + Sync>
+    ) -> Result<()> {
+        log::info!(
+            "store_shard_state_persistent_delta block id: {}, base: {}", state.block_id(), base_id
+        );
+        if handle.id() != state.block_id() {
+            fail!(NodeError::InvalidArg("`state` and `handle` mismatch".to_string()))
+        }
+        if handle.has_persistent_state() {
+            log::info!("store_shard_state_persistent_delta {}: already saved", handle.id());
+            return Ok(())
+        }
+        let now = std::time::Instant::now();
+        let (base_root, base_file_hash, base_chain_len) =
+            self.load_persistent_state_root(base_id, abort.deref()).await?;
+        let manifest = DeltaManifest {
+            base_id: base_id.clone(),
+            base_file_hash,
+            chain_len: base_chain_len + 1
+        };
+        let root = state.root_cell().clone();
+        // Drop state - don't keep in memory a root cell that keeps full tree!
+        std::mem::drop(state);
+        let data = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+            let data = build_delta(&manifest, &base_root, &root)?;
+            if abort() {
+                fail!("Persistent state delta saving was aborted")
+            }
+            Ok(data)
+        }).await??;
+        self.shard_state_persistent_delta_db.write_whole_file(handle.id(), &data).await?;
+        log::info!(
+            "store_shard_state_persistent_delta {} DONE; size {}, chain {}, TIME {}ms",
+            handle.id(), data.len(), base_chain_len + 1, now.elapsed().as_millis()
+        );
+        if handle.set_persistent_state() {
+            self.store_block_handle(handle, callback)?;
+        }
+        Ok(())
+    }
+
+    // Count of deltas down to the full snapshot, zero for a full snapshot
+    pub async fn persistent_state_delta_chain_len(&self, id: &BlockIdExt) -> Result<u32> {
+        match self.load_persistent_state_manifest(id).await? {
+            Some(manifest) => Ok(manifest.chain_len),
+            None if self.shard_state_persistent_db.contains(id).await? => Ok(0),
+            None => fail!("There is no persistent state {}", id)
+        }
+    }
+
+    pub(crate) async fn load_persistent_state_manifest(
+        &self,
+        id: &BlockIdExt
+    ) -> Result<Option<DeltaManifest>> {
+        if !self.shard_state_persistent_delta_db.contains(id).await? {
+            return Ok(None)
+        }
+        let data = self.shard_state_persistent_delta_db.read_whole_file(id).await?;
+        let (manifest, _) = parse_delta_manifest(&data)
+            .map_err(|e| error!("Broken persistent state delta {}: {}", id, e))?;
+        Ok(Some(manifest))
+    }
+
+    pub(crate) async fn has_persistent_state_file(&self, id: &BlockIdExt) -> Result<bool> {
+        Ok(
+            self.shard_state_persistent_db.contains(id).await? ||
+            self.shard_state_persistent_delta_db.contains(id).await?
+        )
+    }
+
+    // Returns state root, hash of the stored file and delta chain length.
+    // The chain is walked down to the full snapshot and deltas are applied back.
+    pub(crate) async fn load_persistent_state_root(
+        &self,
+        id: &BlockIdExt,
+        abort: &dyn Fn() -> bool
+    ) -> Result<(Cell, UInt256, u32)> {
+        let mut deltas = Vec::new();
+        let mut current = id.clone();
+        let mut expected_file_hash: Option<UInt256> = None;
+        let mut info = None;
+        let mut root = loop {
+            if abort() {
+                fail!("Persistent state {} loading was aborted", id)
+            }
+            let delta = self.shard_state_persistent_delta_db.contains(&current).await?;
+            let data = if delta {
+                self.shard_state_persistent_delta_db.read_whole_file(&current).await?
+            } else {
+                self.shard_state_persistent_db.read_whole_file(&current).await?
+            };
+            let file_hash = UInt256::calc_file_hash(&data);
+            if let Some(expected_file_hash) = expected_file_hash.take() {
+                if expected_file_hash != file_hash {
+                    fail!("Persistent state {} doesn't match the manifest of its delta", current)
+                }
+            }
+            if !delta {
+                info.get_or_insert((file_hash, 0));
+                break BocReader::new()
+                    .set_abort(abort)
+                    .read_inmem(Arc::new(data))?
+                    .withdraw_single_root()?
+            }
+            let (manifest, update) = parse_delta(&data)
+                .map_err(|e| error!("Broken persistent state delta {}: {}", current, e))?;
+            info.get_or_insert((file_hash, manifest.chain_len));
+            if deltas.len() as u32 >= MAX_DELTA_CHAIN_LEN {
+                fail!("Too long chain of persistent state deltas for {}", id)
+            }
+            expected_file_hash = Some(manifest.base_file_hash);
+            deltas.push((current, update));
+            current = manifest.base_id;
+        };
+        for (id, update) in deltas.into_iter().rev() {
+            if abort() {
+                fail!("Persistent state {} loading was aborted", id)
+            }
+            root = update.apply_for(&root)
+                .map_err(|e| error!("Can't apply persistent state delta {}: {}", id, e))?;
+            if root.repr_hash() != *id.root_hash() {
+                fail!("Persistent state delta {} gives wrong root {:x}", id, root.repr_hash())
+            }
+        }
+        let (file_hash, chain_len) = info.ok_or_else(|| error!("INTERNAL ERROR: no state info"))?;
+        Ok((root, file_hash, chain_len))
+    }
+
+    // Full file is made for a delta on demand, e.g. to serve it to booting nodes
+    pub(crate) async fn materialize_persistent_state(&self, id: &BlockIdExt) -> Result<()> {
+        if self.shard_state_persistent_db.contains(id).await? ||
+           !self.shard_state_persistent_delta_db.contains(id).await?
+        {
+            return Ok(())
+        }
+        let handle = self.load_block_handle(id)?.ok_or_else(
+            || error!("Cannot load handle for persistent state {}", id)
+        )?;
+        let _lock = handle.persistent_state_lock().write().await;
+        if self.shard_state_persistent_db.contains(id).await? {
+            // Has been materialized while waiting for the lock
+            return Ok(())
+        }
+        let _tc = TimeChecker::new(format!("materialize_persistent_state {}", id), 10000);
+        let (root, _, chain_len) = self.load_persistent_state_root(id, &|| false).await?;
+        let data = tokio::task::spawn_blocking(move || write_boc(&root)).await??;
+        let temp_dir = Path::new(self.config.db_directory.as_str()).join(PERSISTENT_STATE_TEMP_DIR);
+        tokio::fs::create_dir_all(&temp_dir).await?;
+        let temp_file = temp_dir.join(format!("{:x}", id.root_hash()));
+        tokio::fs::write(&temp_file, &data).await?;
+        self.shard_state_persistent_db.move_file_into(id, &temp_file).await?;
+        log::info!(
+            "materialize_persistent_state {}: {} bytes from chain of {} deltas",
+            id, data.len(), chain_len
+        );
+        Ok(())
+    }
+
+}
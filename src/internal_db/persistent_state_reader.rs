@@ -0,0 +1,102 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::internal_db::InternalDb;
+use ever_block::{error, fail, BlockIdExt, Result};
+use std::{cmp::min, io::SeekFrom, sync::Arc};
+use storage::{TimeChecker, block_handle_db::BlockHandle};
+use tokio::{io::{AsyncReadExt, AsyncSeekExt}, sync::OwnedRwLockReadGuard};
+
+pub const DEFAULT_PERSISTENT_STATE_CHUNK_SIZE: usize = 1 << 21;
+
+// Reads persistent state file by chunks. The file is kept opened and the handle's
+// persistent state lock is held while the reader is alive, so the state can't be
+// removed by GC in the middle of reading.
+pub struct PersistentStateReader {
+    id: BlockIdExt,
+    file: tokio::fs::File,
+    total_size: u64,
+    position: u64,
+    chunk_size: usize,
+    _lock: OwnedRwLockReadGuard<()>,
+}
+
+impl PersistentStateReader {
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn id(&self) -> &BlockIdExt {
+        &self.id
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // Returns up to max_len bytes from given offset. Empty chunk means end of file.
+    pub async fn read_chunk(&mut self, offset: u64, max_len: usize) -> Result<Vec<u8>> {
+        if offset >= self.total_size {
+            return Ok(vec![])
+        }
+        let len = min(max_len as u64, self.total_size - offset) as usize;
+        self.file.seek(SeekFrom::Start(offset)).await?;
+        let mut chunk = vec![0; len];
+        self.file.read_exact(&mut chunk).await
+            .map_err(|e| error!("Can't read persistent state {} at {}: {}", self.id, offset, e))?;
+        Ok(chunk)
+    }
+
+    // Sequentially reads the state by chunk_size parts. Empty chunk means end of file.
+    pub async fn next_chunk(&mut self) -> Result<Vec<u8>> {
+        let chunk = self.read_chunk(self.position, self.chunk_size).await?;
+        self.position += chunk.len() as u64;
+        Ok(chunk)
+    }
+}
+
+impl InternalDb {
+
+    pub async fn open_persistent_state_reader(
+        &self,
+        handle: &Arc<BlockHandle>
+    ) -> Result<PersistentStateReader> {
+        let _tc = TimeChecker::new(format!("open_persistent_state_reader {}", handle.id()), 50);
+        // Must be done before the lock is taken, materialization locks the state for write
+        self.materialize_persistent_state(handle.id()).await?;
+        let lock = handle.persistent_state_lock().clone().read_owned().await;
+        if !handle.has_persistent_state() {
+            fail!("Shard state {} doesn't have a persistent state", handle.id())
+        }
+        let file = self.shard_state_persistent_db.open_file(handle.id()).await?;
+        let total_size = file.metadata().await?.len();
+        Ok(PersistentStateReader {
+            id: handle.id().clone(),
+            file,
+            total_size,
+            position: 0,
+            chunk_size: DEFAULT_PERSISTENT_STATE_CHUNK_SIZE,
+            _lock: lock,
+        })
+    }
+}
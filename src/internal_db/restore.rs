@@ -476,21 +476,24 @@ async fn check_one_block(
     let block = BlockStuff::deserialize_block_checked(id.clone(), block_data)?;
     let _proof = db.load_block_proof(&handle, !id.is_masterchain()).await?;
 
-    if !handle.has_data() {
-        log::warn!("Block {} has handle.has_data() false", id);
-        handle.set_data();
-        db.store_block_handle(&handle, None)?;
-    }
     if id.shard().is_masterchain() {
+        if !handle.has_data() {
+            log::warn!("Block {} has handle.has_data() false", id);
+            handle.set_data();
+            db.store_block_handle(&handle, None)?;
+        }
         if !handle.has_proof() {
             log::warn!("Block {} has handle.has_proof() false", id);
             handle.set_proof();
             db.store_block_handle(&handle, None)?;
         }
-    } else {
-        if !handle.has_proof_link() {
-            log::warn!("Block {} has handle.has_proof_or_link() false", id);
-            handle.set_proof_link();
+    } else if !handle.has_data() || !handle.has_proof_link() {
+        log::warn!(
+            "Block {} has handle.has_data() {} handle.has_proof_link() {}",
+            id, handle.has_data(), handle.has_proof_link()
+        );
+        // Both flags are set and saved at once
+        if handle.set_data_and_proof_link() {
             db.store_block_handle(&handle, None)?;
         }
     }
@@ -569,10 +572,13 @@ async fn check_one_block(
     if should_has_next {
         // next 1
         match db.load_block_next1(id) {
-            Ok(_) => {
+            Ok(next1) => {
+                let mut updated = handle.set_next1_id(&next1);
                 if !handle.has_next1() {
                     log::warn!("Applied block {} has handle.has_next1() false", id);
-                    handle.set_next1();
+                    updated |= handle.set_next1();
+                }
+                if updated {
                     db.store_block_handle(&handle, None)?;
                 }
             },
@@ -582,10 +588,16 @@ async fn check_one_block(
         // next 2 (before split)
         if block.block()?.read_info()?.before_split() {
             match db.load_block_next2(id) {
-                Ok(_) => {
+                Ok(next2) => {
+                    let mut updated = false;
+                    if let Some(next2) = &next2 {
+                        updated |= handle.set_next2_id(next2);
+                    }
                     if !handle.has_next2() {
                         log::warn!("Applied block is before split, but {} has handle.has_next2() false", id);
-                        handle.set_next2();
+                        updated |= handle.set_next2();
+                    }
+                    if updated {
                         db.store_block_handle(&handle, None)?;
                     }
                 },
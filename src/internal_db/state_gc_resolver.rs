@@ -17,7 +17,7 @@ use ever_block::{Result, fail};
 use adnl::common::add_unbound_object_to_map_with_update;
 use crate::engine_traits::EngineOperations;
 use std::{
-    sync::atomic::{AtomicU32, Ordering},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
     collections::{HashSet, HashMap},
 };
 
@@ -26,7 +26,7 @@ pub struct AllowStateGcSmartResolver {
     min_ref_mc_block: AtomicU32,
     min_actual_ss: lockfree::map::Map<ShardIdent, AtomicU32>,
     min_mesh_mc_block: lockfree::map::Map<i32, AtomicU32>,
-    life_time_sec: u64,
+    life_time_sec: AtomicU64,
     pinned_roots: parking_lot::RwLock<HashMap<BlockIdExt, u32>>,
 }
 
@@ -37,11 +37,16 @@ impl AllowStateGcSmartResolver {
             min_ref_mc_block: AtomicU32::new(0),
             min_actual_ss: lockfree::map::Map::new(),
             min_mesh_mc_block: lockfree::map::Map::new(),
-            life_time_sec,
+            life_time_sec: AtomicU64::new(life_time_sec),
             pinned_roots: parking_lot::RwLock::new(HashMap::new()),
         }
     }
 
+    // Can be changed at runtime by config reload
+    pub fn set_life_time_sec(&self, life_time_sec: u64) {
+        self.life_time_sec.store(life_time_sec, Ordering::Relaxed);
+    }
+
     pub async fn advance(&self, mc_block_id: &BlockIdExt, engine: &dyn EngineOperations) -> Result<bool> {
         let seqno = mc_block_id.seq_no();
         if seqno <= self.last_processed_block.fetch_max(seqno, Ordering::Relaxed) {
@@ -149,6 +154,29 @@ impl AllowStateGcSmartResolver {
         Ok(true)
     }
 
+    // Pins state regardless of whether it is already allowed to be garbage collected
+    pub fn force_pin_state(&self, block_id: &BlockIdExt) {
+        let mut pinned_roots = self.pinned_roots.write();
+        let counter = pinned_roots.entry(block_id.clone()).or_insert(0);
+        *counter += 1;
+        log::trace!("AllowStateGcSmartResolver::force_pin_state: {} pins for {}", counter, block_id);
+    }
+
+    pub fn is_pinned(&self, block_id: &BlockIdExt) -> bool {
+        self.pinned_roots.read().get(block_id).map(|c| *c > 0).unwrap_or(false)
+    }
+
+    // Returns count of pinned states and total count of pins
+    pub fn pinned_stats(&self) -> (usize, u32) {
+        let pinned_roots = self.pinned_roots.read();
+        (pinned_roots.len(), pinned_roots.values().sum())
+    }
+
+    #[cfg(test)]
+    pub fn set_min_ref_mc_seqno(&self, seqno: u32) {
+        self.min_ref_mc_block.store(seqno, Ordering::Relaxed);
+    }
+
     pub fn add_pin_for_state(&self, block_id: &BlockIdExt) -> Result<()> {
         if let Some(counter) = self.pinned_roots.write().get_mut(block_id) {
             *counter += 1;
@@ -183,7 +211,7 @@ impl AllowStateGcSmartResolver {
         saved_at: u64,
         gc_utime: u64
     ) -> Result<bool> {
-        if gc_utime > saved_at && gc_utime - saved_at < self.life_time_sec {
+        if gc_utime > saved_at && gc_utime - saved_at < self.life_time_sec.load(Ordering::Relaxed) {
             return Ok(false)
         }
         if nw_id == 0 {
@@ -228,8 +256,7 @@ impl AllowStateGcResolver for AllowStateGcSmartResolver {
         gc_utime: u64
     ) -> Result<bool> {
         let old = AllowStateGcSmartResolver::allow_state_gc(self, nw_id, block_id, saved_at, gc_utime)?;
-        let pinned = nw_id == 0 &&
-                     self.pinned_roots.read().get(block_id).map(|c| *c == 0).unwrap_or(true);
+        let pinned = nw_id == 0 && self.is_pinned(block_id);
         Ok(old && !pinned)
     }
 }
\ No newline at end of file
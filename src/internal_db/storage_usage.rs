@@ -0,0 +1,268 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::internal_db::{InternalDb, CELLS_CF_NAME};
+use ever_block::{Result, ShardIdent, UInt256};
+use std::{
+    collections::BTreeMap, fs::Metadata, io::ErrorKind, path::{Path, PathBuf},
+    time::{Duration, SystemTime}
+};
+use storage::{
+    archives::{
+        archive_manager::ArchiveManager, package_entries_index::read_package_entry_sizes,
+        package_entry_id::{parse_short_filename, PackageEntryId}
+    },
+    db::filedb::FileDb
+};
+
+// Upper bounds of archive package age buckets, the last bucket is for older packages
+pub const ARCHIVE_AGE_BUCKETS_DAYS: [u32; 3] = [1, 7, 30];
+// Key for block files which shard can't be recognized
+pub const OTHER_BLOCK_FILES: &str = "other";
+// Column families of archive slices are reported together
+pub const ARCHIVE_SLICES_CFS: &str = "archive_slices";
+const ARCHIVE_SLICE_CF_PREFIXES: [&str; 4] = ["entry_meta_", "offsets_", "entries_index_", "status_"];
+const PACKAGE_EXTENSION: &str = "pack";
+
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsageItem {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl UsageItem {
+    pub fn add(&mut self, count: u64, bytes: u64) {
+        self.count += count;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct AgeBucketUsage {
+    // None for the bucket of the oldest packages
+    pub max_age_days: Option<u32>,
+    #[serde(flatten)]
+    pub usage: UsageItem,
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotUsage {
+    // Masterchain block of the snapshot, None if block handle of the state is not found
+    pub mc_seq_no: Option<u32>,
+    pub states: UsageItem,
+    pub deltas: UsageItem,
+}
+
+// Estimation of disk space used by the node database. It is built from file system
+// metadata and RocksDB properties, only headers of archive package entries are read
+// to break block files down by shard.
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct StorageUsageReport {
+    // Block data files (blocks, proofs, signatures) in archives and unapplied files
+    pub block_files_by_shard: BTreeMap<String, UsageItem>,
+    pub archive_packages_by_age: Vec<AgeBucketUsage>,
+    pub unapplied_files: UsageItem,
+    // Count is an estimated count of cells, None if there is no cells column family
+    pub shard_state_cells: Option<UsageItem>,
+    pub persistent_states: Vec<SnapshotUsage>,
+    // Count is an estimated count of keys
+    pub column_families: BTreeMap<String, UsageItem>,
+    pub total_bytes: u64,
+}
+
+impl Default for StorageUsageReport {
+    fn default() -> Self {
+        let archive_packages_by_age = ARCHIVE_AGE_BUCKETS_DAYS.iter()
+            .map(|days| Some(*days))
+            .chain(std::iter::once(None))
+            .map(|max_age_days| AgeBucketUsage { max_age_days, usage: UsageItem::default() })
+            .collect();
+        Self {
+            block_files_by_shard: BTreeMap::new(),
+            archive_packages_by_age,
+            unapplied_files: UsageItem::default(),
+            shard_state_cells: None,
+            persistent_states: Vec::new(),
+            column_families: BTreeMap::new(),
+            total_bytes: 0,
+        }
+    }
+}
+
+impl StorageUsageReport {
+
+    pub fn add_block_file(&mut self, shard: Option<&ShardIdent>, bytes: u64) {
+        let key = match shard {
+            Some(shard) => shard.to_string(),
+            None => OTHER_BLOCK_FILES.to_string()
+        };
+        self.block_files_by_shard.entry(key).or_default().add(1, bytes);
+    }
+
+    pub fn add_archive_package(&mut self, age: Duration, bytes: u64) {
+        let days = age.as_secs() / 86400;
+        let bucket = self.archive_packages_by_age.iter_mut()
+            .find(|bucket| bucket.max_age_days.map_or(true, |max| days < max as u64));
+        if let Some(bucket) = bucket {
+            bucket.usage.add(1, bytes);
+        }
+    }
+
+    pub fn add_persistent_state(&mut self, mc_seq_no: Option<u32>, is_delta: bool, bytes: u64) {
+        let snapshot = match self.persistent_states.iter_mut().position(|s| s.mc_seq_no == mc_seq_no) {
+            Some(pos) => &mut self.persistent_states[pos],
+            None => {
+                self.persistent_states.push(SnapshotUsage { mc_seq_no, ..Default::default() });
+                self.persistent_states.last_mut().unwrap()
+            }
+        };
+        if is_delta {
+            snapshot.deltas.add(1, bytes);
+        } else {
+            snapshot.states.add(1, bytes);
+        }
+    }
+
+    pub fn add_column_family(&mut self, name: &str, keys: u64, bytes: u64) {
+        if name == CELLS_CF_NAME {
+            self.shard_state_cells.get_or_insert_with(UsageItem::default).add(keys, bytes);
+            return
+        }
+        let name = if ARCHIVE_SLICE_CF_PREFIXES.iter().any(|prefix| name.starts_with(prefix)) {
+            ARCHIVE_SLICES_CFS
+        } else {
+            name
+        };
+        self.column_families.entry(name.to_string()).or_default().add(keys, bytes);
+    }
+
+    // Sorts snapshots and sums up components. Block files are a breakdown
+    // of archive packages and unapplied files, so they are not summed again.
+    pub fn finish(&mut self) {
+        self.persistent_states.sort_by_key(|snapshot| snapshot.mc_seq_no);
+        self.total_bytes = self.archive_packages_by_age.iter().map(|b| b.usage.bytes).sum::<u64>()
+            + self.unapplied_files.bytes
+            + self.shard_state_cells.as_ref().map_or(0, |cells| cells.bytes)
+            + self.persistent_states.iter().map(|s| s.states.bytes + s.deltas.bytes).sum::<u64>()
+            + self.column_families.values().map(|cf| cf.bytes).sum::<u64>();
+    }
+}
+
+// Shard of block which the entry of archive package belongs to
+fn archive_entry_shard(filename: &str) -> Option<ShardIdent> {
+    match PackageEntryId::from_filename(filename).ok()? {
+        PackageEntryId::Block(id) |
+        PackageEntryId::ZeroState(id) |
+        PackageEntryId::Proof(id) |
+        PackageEntryId::ProofLink(id) |
+        PackageEntryId::Signatures(id) |
+        PackageEntryId::BlockInfo(id) |
+        PackageEntryId::PersistentState { block_id: id, .. } |
+        PackageEntryId::Candidate { block_id: id, .. } => Some(id.shard().clone()),
+        PackageEntryId::Empty => None
+    }
+}
+
+fn unapplied_file_shard(filename: &str) -> Option<ShardIdent> {
+    let (workchain_id, shard_prefix_tagged, _) = parse_short_filename(filename).ok()?;
+    ShardIdent::with_tagged_prefix(workchain_id, shard_prefix_tagged).ok()
+}
+
+// Collects files of the directory tree, absent directory has no files
+pub(crate) fn collect_files(dir: &Path, files: &mut Vec<(PathBuf, Metadata)>) -> Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into())
+    };
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if metadata.is_file() {
+            files.push((entry.path(), metadata));
+        }
+    }
+    Ok(())
+}
+
+impl InternalDb {
+
+    pub async fn storage_usage_report(&self) -> Result<StorageUsageReport> {
+        let mut report = StorageUsageReport::default();
+        let root = Path::new(self.config.db_directory.as_str());
+        let now = SystemTime::now();
+
+        let mut packages = Vec::new();
+        collect_files(&root.join(ArchiveManager::ARCHIVE_DIR).join("packages"), &mut packages)?;
+        collect_files(&root.join("files").join("packages"), &mut packages)?;
+        for (path, metadata) in packages {
+            if path.extension().map_or(true, |ext| ext != PACKAGE_EXTENSION) {
+                continue
+            }
+            let age = metadata.modified().ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+            report.add_archive_package(age, metadata.len());
+            match read_package_entry_sizes(&path).await {
+                Ok(entries) => for (filename, size) in entries {
+                    report.add_block_file(archive_entry_shard(&filename).as_ref(), size);
+                }
+                Err(e) => log::warn!("storage_usage_report: can't read package {}: {}", path.display(), e)
+            }
+        }
+
+        let mut unapplied = Vec::new();
+        collect_files(self.archive_manager.unapplied_files_path(), &mut unapplied)?;
+        for (path, metadata) in unapplied {
+            report.unapplied_files.add(1, metadata.len());
+            let shard = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(unapplied_file_shard);
+            report.add_block_file(shard.as_ref(), metadata.len());
+        }
+
+        for (db, is_delta) in [
+            (&self.shard_state_persistent_db, false),
+            (&self.shard_state_persistent_delta_db, true)
+        ] {
+            for root_hash in Self::file_db_keys(db)? {
+                let bytes = db.get_file_size(&root_hash).await?;
+                let mc_seq_no = self.block_handle_storage.load_handle_by_root_hash(&root_hash)?
+                    .map(|handle| handle.masterchain_ref_seq_no());
+                report.add_persistent_state(mc_seq_no, is_delta, bytes);
+            }
+        }
+
+        for name in self.db.column_families()? {
+            if let Some((keys, bytes)) = self.db.cf_usage(&name)? {
+                report.add_column_family(&name, keys, bytes);
+            }
+        }
+
+        report.finish();
+        Ok(report)
+    }
+
+    fn file_db_keys(db: &FileDb) -> Result<Vec<UInt256>> {
+        let mut keys = Vec::new();
+        if db.path().exists() {
+            db.for_each_key(&mut |key| {
+                keys.push(UInt256::from(key));
+                Ok(true)
+            })?;
+        }
+        Ok(keys)
+    }
+}
@@ -0,0 +1,291 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use openssl::{
+    hash::MessageDigest, pkcs5::pbkdf2_hmac, rand::rand_bytes,
+    symm::{decrypt_aead, encrypt_aead, Cipher}
+};
+use std::{
+    collections::HashMap, fmt, io::{Read, Write}, ops::Deref, path::Path,
+    sync::atomic::{compiler_fence, Ordering}
+};
+use ever_block::{error, fail, KeyOptionJson, Result};
+
+#[cfg(test)]
+#[path = "tests/test_keystore.rs"]
+mod tests;
+
+// Container layout: magic, version (u8), KDF id (u8), KDF iterations (u32 LE), salt, nonce,
+// then AES-256-GCM ciphertext and tag. The whole header is authenticated as AAD
+const KEYSTORE_MAGIC: [u8; 4] = *b"EVKS";
+const KEYSTORE_VERSION: u8 = 1;
+const KDF_PBKDF2_SHA256: u8 = 1;
+const KDF_ITERATIONS: u32 = 600_000;
+// Protects boot from a forged container demanding endless key derivation
+const KDF_MAX_ITERATIONS: u32 = 10_000_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = 4 + 1 + 1 + 4 + SALT_LEN + NONCE_LEN;
+const MAX_PASSPHRASE_LEN: usize = 4096;
+
+// Buffer of secret data, it is overwritten with zeros on drop
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+
+    pub fn new(data: Vec<u8>) -> Self {
+        Self(data)
+    }
+
+    // Cut tail is zeroized too
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.0.len() {
+            zeroize(&mut self.0[len..]);
+            self.0.truncate(len);
+        }
+    }
+
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes(<{} bytes>)", self.0.len())
+    }
+}
+
+// Volatile writes are not elided by the compiler as dead stores
+fn zeroize(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+// Where the passphrase of the keystore is taken from at boot: environment variable,
+// inherited file descriptor (e.g. a pipe from KMS agent) or terminal prompt
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PassphraseSource {
+    Env(String),
+    Fd(i32),
+    Prompt,
+}
+
+// Validator keys are kept in the encrypted file instead of `validator_key_ring` of the config
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct KeystoreConfig {
+    // Relative to configs dir
+    pub file: String,
+    pub passphrase: PassphraseSource,
+}
+
+pub fn read_passphrase(source: &PassphraseSource) -> Result<SecretBytes> {
+    let passphrase = match source {
+        PassphraseSource::Env(name) => {
+            let value = std::env::var(name).map_err(
+                |e| error!("Passphrase of validator keystore is missing: variable {}: {}", name, e)
+            )?;
+            SecretBytes::new(value.into_bytes())
+        }
+        PassphraseSource::Fd(fd) => read_passphrase_from_fd(*fd)?,
+        PassphraseSource::Prompt => read_passphrase_from_terminal()?
+    };
+    if passphrase.is_empty() {
+        fail!("Passphrase of validator keystore is missing: {:?} gives empty passphrase", source)
+    }
+    Ok(passphrase)
+}
+
+fn trim_line_end(mut line: SecretBytes) -> SecretBytes {
+    let len = line.iter().rposition(|byte| (*byte != b'\n') && (*byte != b'\r')).map_or(0, |pos| pos + 1);
+    line.truncate(len);
+    line
+}
+
+#[cfg(unix)]
+fn read_passphrase_from_fd(fd: i32) -> Result<SecretBytes> {
+    use std::os::unix::io::FromRawFd;
+    // Buffer is not reallocated, so no copies of the passphrase are left behind
+    let mut data = SecretBytes::new(Vec::with_capacity(MAX_PASSPHRASE_LEN + 1));
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    (&mut file).take(MAX_PASSPHRASE_LEN as u64 + 1).read_to_end(&mut data.0)
+        .map_err(|e| error!("Can't read passphrase of validator keystore from fd {}: {}", fd, e))?;
+    if data.len() > MAX_PASSPHRASE_LEN {
+        fail!("Passphrase of validator keystore from fd {} is longer than {} bytes", fd, MAX_PASSPHRASE_LEN)
+    }
+    Ok(trim_line_end(data))
+}
+
+#[cfg(not(unix))]
+fn read_passphrase_from_fd(fd: i32) -> Result<SecretBytes> {
+    fail!("Can't read passphrase of validator keystore from fd {}: not supported on the platform", fd)
+}
+
+#[cfg(unix)]
+fn read_passphrase_from_terminal() -> Result<SecretBytes> {
+    use std::{io::BufRead, os::unix::io::AsRawFd};
+    let stdin = std::io::stdin();
+    let fd = stdin.as_raw_fd();
+    if unsafe { libc::isatty(fd) } != 1 {
+        fail!("Can't prompt passphrase of validator keystore: stdin is not a terminal")
+    }
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+        fail!("Can't prompt passphrase of validator keystore: {}", std::io::Error::last_os_error())
+    }
+    let mut silent = saved;
+    silent.c_lflag &= !libc::ECHO;
+    silent.c_lflag |= libc::ECHONL;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    eprint!("Passphrase of validator keystore: ");
+    std::io::stderr().flush().ok();
+    let mut line = String::with_capacity(MAX_PASSPHRASE_LEN);
+    let result = stdin.lock().take(MAX_PASSPHRASE_LEN as u64).read_line(&mut line);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &saved) };
+    let line = SecretBytes::new(line.into_bytes());
+    result.map_err(|e| error!("Can't prompt passphrase of validator keystore: {}", e))?;
+    Ok(trim_line_end(line))
+}
+
+#[cfg(not(unix))]
+fn read_passphrase_from_terminal() -> Result<SecretBytes> {
+    fail!("Can't prompt passphrase of validator keystore: not supported on the platform")
+}
+
+pub fn is_keystore_container(data: &[u8]) -> bool {
+    data.starts_with(&KEYSTORE_MAGIC)
+}
+
+fn derive_key(passphrase: &[u8], salt: &[u8], iterations: u32) -> Result<SecretBytes> {
+    let mut key = SecretBytes::new(vec![0; KEY_LEN]);
+    pbkdf2_hmac(passphrase, salt, iterations as usize, MessageDigest::sha256(), &mut key.0)?;
+    Ok(key)
+}
+
+pub fn seal_container(data: &[u8], passphrase: &[u8]) -> Result<Vec<u8>> {
+    seal_with_iterations(data, passphrase, KDF_ITERATIONS)
+}
+
+fn seal_with_iterations(data: &[u8], passphrase: &[u8], iterations: u32) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand_bytes(&mut salt)?;
+    rand_bytes(&mut nonce)?;
+    let mut container = Vec::with_capacity(HEADER_LEN + data.len() + TAG_LEN);
+    container.extend_from_slice(&KEYSTORE_MAGIC);
+    container.push(KEYSTORE_VERSION);
+    container.push(KDF_PBKDF2_SHA256);
+    container.extend_from_slice(&iterations.to_le_bytes());
+    container.extend_from_slice(&salt);
+    container.extend_from_slice(&nonce);
+    let key = derive_key(passphrase, &salt, iterations)?;
+    let mut tag = [0u8; TAG_LEN];
+    let encrypted = encrypt_aead(Cipher::aes_256_gcm(), &key, Some(&nonce), &container, data, &mut tag)?;
+    container.extend_from_slice(&encrypted);
+    container.extend_from_slice(&tag);
+    Ok(container)
+}
+
+pub fn open_container(container: &[u8], passphrase: &[u8]) -> Result<SecretBytes> {
+    if !is_keystore_container(container) {
+        fail!("Not a validator keystore container")
+    }
+    if container.len() < HEADER_LEN + TAG_LEN {
+        fail!("Validator keystore container is truncated")
+    }
+    if container[4] != KEYSTORE_VERSION {
+        fail!("Unsupported version {} of validator keystore container", container[4])
+    }
+    if container[5] != KDF_PBKDF2_SHA256 {
+        fail!("Unsupported key derivation {} of validator keystore container", container[5])
+    }
+    let mut iterations = [0u8; 4];
+    iterations.copy_from_slice(&container[6..10]);
+    let iterations = u32::from_le_bytes(iterations);
+    if (iterations == 0) || (iterations > KDF_MAX_ITERATIONS) {
+        fail!("Wrong key derivation iterations {} of validator keystore container", iterations)
+    }
+    let salt = &container[10..10 + SALT_LEN];
+    let nonce = &container[10 + SALT_LEN..HEADER_LEN];
+    let (encrypted, tag) = container[HEADER_LEN..].split_at(container.len() - HEADER_LEN - TAG_LEN);
+    let key = derive_key(passphrase, salt, iterations)?;
+    let data = decrypt_aead(
+        Cipher::aes_256_gcm(), &key, Some(nonce), &container[..HEADER_LEN], encrypted, tag
+    ).map_err(|_| error!("Can't decrypt validator keystore: wrong passphrase or corrupted container"))?;
+    Ok(SecretBytes::new(data))
+}
+
+// None if there is no keystore file yet. Plain JSON file is accepted too,
+// so keys can be put into the keystore by hand and encrypted later
+pub fn load_key_ring(
+    configs_dir: &str,
+    keystore: &KeystoreConfig,
+    passphrase: &[u8]
+) -> Result<Option<HashMap<String, KeyOptionJson>>> {
+    let path = Path::new(configs_dir).join(&keystore.file);
+    if !path.exists() {
+        return Ok(None)
+    }
+    let data = SecretBytes::new(
+        std::fs::read(&path).map_err(|e| error!("Can't read validator keystore {}: {}", path.display(), e))?
+    );
+    let key_ring = if is_keystore_container(&data) {
+        let json = open_container(&data, passphrase)?;
+        serde_json::from_slice(&json)?
+    } else {
+        log::warn!("Validator keystore {} is not encrypted", path.display());
+        serde_json::from_slice(&data)?
+    };
+    Ok(Some(key_ring))
+}
+
+// File is replaced at once, so a failed write doesn't lose the keys
+pub fn save_key_ring(
+    configs_dir: &str,
+    keystore: &KeystoreConfig,
+    passphrase: &[u8],
+    key_ring: &HashMap<String, KeyOptionJson>
+) -> Result<()> {
+    let json = SecretBytes::new(serde_json::to_vec(key_ring)?);
+    let container = seal_container(&json, passphrase)?;
+    let path = Path::new(configs_dir).join(&keystore.file);
+    let tmp_path = path.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)] {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp_path)
+        .map_err(|e| error!("Can't write validator keystore {}: {}", tmp_path.display(), e))?;
+    file.write_all(&container)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, &path)
+        .map_err(|e| error!("Can't write validator keystore {}: {}", path.display(), e))?;
+    Ok(())
+}
@@ -11,22 +11,33 @@
 * limitations under the License.
 */
 
+pub mod applied_blocks;
+pub mod archives_gc;
 pub mod block;
 pub mod block_proof;
 pub mod boot;
 pub mod collator_test_bundle;
 pub mod config;
+pub mod config_check;
+pub mod config_reload;
 pub mod error;
 pub mod engine;
 pub mod engine_traits;
 pub mod engine_operations;
 pub mod full_node;
 pub mod internal_db;
+pub mod keystore;
 pub mod macros;
+pub mod manual_gc;
 pub mod network;
+pub mod network_snapshot;
+pub mod node_status;
+pub mod offline_verification;
 pub mod rng;
+pub mod self_test;
 pub mod shard_state;
 pub mod sync;
+pub mod time_service;
 pub mod types;
 pub mod validating_utils;
 pub mod validator;
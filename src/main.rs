@@ -11,11 +11,15 @@
 * limitations under the License.
 */
 
+mod applied_blocks;
+mod archives_gc;
 mod block;
 mod block_proof;
 mod boot;
 mod collator_test_bundle;
 mod config;
+mod config_check;
+mod config_reload;
 mod engine;
 mod engine_traits;
 mod engine_operations;
@@ -24,11 +28,17 @@ mod error;
 mod external_db;
 mod full_node;
 mod internal_db;
+mod keystore;
 mod macros;
+mod manual_gc;
 mod network;
+mod network_snapshot;
+mod node_status;
 mod rng;
+mod self_test;
 mod shard_state;
 mod sync;
+mod time_service;
 mod types;
 mod validating_utils;
 mod validator;
@@ -227,11 +237,17 @@ static GLOBAL: TracingAllocator = TracingAllocator {
 
 fn init_logger<T: AsRef<std::path::Path>>(log_config_path: Option<T>) {
 
+    // Logger is initialized by handle to reload its config at runtime
     if let Some(path) = log_config_path {
-        if let Err(err) = log4rs::init_file(path, Default::default()) {
-            println!("Error while initializing log by {}: {}", err, err);
-        } else {
-            return;
+        match log4rs::config::load_config_file(path, Default::default()) {
+            Ok(config) => match log4rs::init_config(config) {
+                Ok(handle) => {
+                    config_reload::set_log_handle(handle);
+                    return;
+                }
+                Err(err) => println!("Error init log: {}", err)
+            }
+            Err(err) => println!("Error while initializing log by {}: {}", err, err)
         }
     }
 
@@ -253,9 +269,9 @@ fn init_logger<T: AsRef<std::path::Path>>(log_config_path: Option<T>) {
         )
         .unwrap();
 
-    let result = log4rs::init_config(config);
-    if let Err(e) = result {
-        println!("Error init log: {}", e);
+    match log4rs::init_config(config) {
+        Ok(handle) => config_reload::set_log_handle(handle),
+        Err(e) => println!("Error init log: {}", e)
     }
 }
 
@@ -392,9 +408,16 @@ fn main() {
             .short("f")
             .long("force-check-db")
             .help("start check & restore db process forcedly with refilling cells database"))
+        .arg(clap::Arg::with_name("read_only")
+            .long("read-only")
+            .help("open database read-only for inspection: nothing is synced or written, \
+                only read-only control queries are served"))
         .arg(clap::Arg::with_name("process_conf_and_exit")
             .long("process-conf-and-exit")
-            .help("finish node after config file processing (reading or generating)."));
+            .help("finish node after config file processing (reading or generating)."))
+        .arg(clap::Arg::with_name("self_test")
+            .long("self-test")
+            .help("check storage, crypto, clock and config, print report and exit with non-zero code on fail"));
 
     let matches = app.get_matches();
 
@@ -402,8 +425,10 @@ fn main() {
         initial_sync_disabled: matches.is_present("initial_sync_disabled"),
         starting_block_disabled: matches.is_present("starting_block_disabled"),
         force_check_db: matches.is_present("force_check_db"),
+        read_only: matches.is_present("read_only"),
     };
     let process_conf_and_exit = matches.is_present("process_conf_and_exit");
+    let self_test_and_exit = matches.is_present("self_test");
 
     let config_dir_path = match matches.value_of("config") {
         Some(config) => {
@@ -440,6 +465,19 @@ fn main() {
     init_logger(config.log_config_path());
     log::info!("{}", version);
 
+    if self_test_and_exit {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Can't create self-test tokio runtime");
+        let report = runtime.block_on(self_test::run_self_test(&config));
+        match report.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => println!("Can't print self-test report: {}", e)
+        }
+        std::process::exit(report.exit_code());
+    }
+
     #[cfg(feature = "statsd")]
     engine::init_statsd_exporter();
     #[cfg(feature = "prometheus")]
@@ -515,6 +553,8 @@ fn main() {
 
     let validator_rt_handle = validator_runtime.handle().clone();
     let db_dir = config.internal_db_path().to_string();
+    // Termination beacon of read-only DB belongs to the node which writes it
+    let read_only = flags.read_only;
     runtime.block_on(async move {
         match start_engine(
             config, 
@@ -526,7 +566,9 @@ fn main() {
             Err(e) => {
                 if stopper.check_stop() {
                     log::warn!("Node stopped ({})", e);
-                    set_graceful_termination(&db_dir);
+                    if !read_only {
+                        set_graceful_termination(&db_dir);
+                    }
                 } else {
                     log::error!("Can't start node's Engine: {:?}", e);
                 }
@@ -537,7 +579,9 @@ fn main() {
                 log::warn!("Still safe stopping node...");
                 engine.wait_stop().await;
                 log::warn!("Node stopped");
-                set_graceful_termination(&db_dir);
+                if !read_only {
+                    set_graceful_termination(&db_dir);
+                }
             }
         }
     });
@@ -0,0 +1,141 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use ever_block::{fail, Result};
+use std::{
+    collections::HashMap, fmt, future::Future,
+    sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}
+};
+use storage::GcCounters;
+
+#[cfg(test)]
+#[path = "tests/test_manual_gc.rs"]
+mod tests;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GcKind {
+    ShardStates, // Expired persistent states
+    Archives, // Archive slices older than archives life time
+    BlockHandles, // Expired validator states in block handle storage
+}
+
+impl GcKind {
+    pub fn from_name(kind: &str) -> Result<Self> {
+        match kind {
+            "shard_states" => Ok(GcKind::ShardStates),
+            "archives" => Ok(GcKind::Archives),
+            "block_handles" => Ok(GcKind::BlockHandles),
+            _ => fail!("Unknown GC kind {}", kind)
+        }
+    }
+}
+
+impl fmt::Display for GcKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            GcKind::ShardStates => "shard_states",
+            GcKind::Archives => "archives",
+            GcKind::BlockHandles => "block_handles",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GcTicket {
+    pub kind: GcKind,
+    pub id: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GcProgress {
+    pub scanned: u64,
+    pub removed: u64,
+    pub bytes_freed: u64,
+    pub finished: bool,
+    pub error: Option<String>,
+}
+
+struct GcPass {
+    ticket: GcTicket,
+    counters: Arc<GcCounters>,
+    finished: AtomicBool,
+    error: parking_lot::Mutex<Option<String>>,
+}
+
+// Runs manually triggered GC passes, each one in a dedicated task.
+// Only one pass of each kind may run at a time, status is kept for the last pass.
+#[derive(Default)]
+pub struct ManualGc {
+    passes: parking_lot::Mutex<HashMap<GcKind, Arc<GcPass>>>,
+    next_id: AtomicU64,
+}
+
+impl ManualGc {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts the pass made by `run`, or returns the ticket of the pass in flight
+    pub fn trigger<F, R>(&self, kind: GcKind, run: F) -> GcTicket
+    where
+        F: FnOnce(Arc<GcCounters>) -> R,
+        R: Future<Output = Result<()>> + Send + 'static
+    {
+        let mut passes = self.passes.lock();
+        if let Some(pass) = passes.get(&kind) {
+            if !pass.finished.load(Ordering::Relaxed) {
+                log::info!("Manual GC {} is already running, ticket {}", kind, pass.ticket.id);
+                return pass.ticket
+            }
+        }
+        let ticket = GcTicket { kind, id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1 };
+        let pass = Arc::new(GcPass {
+            ticket,
+            counters: Arc::new(GcCounters::default()),
+            finished: AtomicBool::new(false),
+            error: parking_lot::Mutex::new(None),
+        });
+        passes.insert(kind, pass.clone());
+        let work = run(pass.counters.clone());
+        log::info!("Manual GC {} started, ticket {}", kind, ticket.id);
+        tokio::spawn(async move {
+            match work.await {
+                Ok(()) => log::info!("Manual GC {} finished, ticket {}", kind, ticket.id),
+                Err(e) => {
+                    log::error!("Manual GC {} failed, ticket {}: {}", kind, ticket.id, e);
+                    pass.error.lock().replace(e.to_string());
+                }
+            }
+            pass.finished.store(true, Ordering::Relaxed);
+        });
+        ticket
+    }
+
+    pub fn status(&self, ticket: &GcTicket) -> Result<GcProgress> {
+        let pass = match self.passes.lock().get(&ticket.kind) {
+            Some(pass) if pass.ticket == *ticket => pass.clone(),
+            _ => fail!("Unknown or outdated GC ticket {} {}", ticket.kind, ticket.id)
+        };
+        let finished = pass.finished.load(Ordering::Relaxed);
+        let progress = GcProgress {
+            scanned: pass.counters.scanned.load(Ordering::Relaxed),
+            removed: pass.counters.removed.load(Ordering::Relaxed),
+            bytes_freed: pass.counters.bytes_freed.load(Ordering::Relaxed),
+            finished,
+            error: pass.error.lock().clone(),
+        };
+        Ok(progress)
+    }
+}
@@ -1,18 +1,193 @@
-use std::sync::Arc;
+use crate::internal_db::InternalDb;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap}, sync::{Arc, atomic::{AtomicU32, Ordering}},
+    time::{Duration, Instant}
+};
 use storage::shardstate_db_async::AllowStateGcResolver;
-use ever_block::{BlockIdExt, ShardIdent, OutMsgQueueInfo, Result, fail};
+use ever_block::{
+    BlockIdExt, Deserializable, MerkleUpdate, ShardIdent, OutMsgQueueInfo, Result, Serializable,
+    UInt256, error, fail
+};
+
+#[cfg(test)]
+#[path = "tests/test_mesh_queues_keeper.rs"]
+mod tests;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct PruneStats {
+    pub handles: usize,
+    pub bytes: u64,
+}
+
+// Lag of the destination workchain in consuming of the queue from the source workchain.
+// Seqnos are ones of masterchain blocks which commit queue updates.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueueLag {
+    pub src_wc: i32,
+    pub dst_wc: i32,
+    // Newest applied queue update, empty or not
+    pub created: u32,
+    // Destination has processed messages up to this one
+    pub consumed: u32,
+    pub lag: u32,
+}
+
+#[derive(Default)]
+struct QueueLagEntry {
+    created: u32,
+    // Non-empty updates which are not consumed yet
+    pending: BTreeSet<u32>,
+    warned_at: Option<Instant>,
+}
+
+// Warning about the lag of one pair is logged at most once per this interval
+const QUEUE_LAG_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+pub struct QueueLagTracker {
+    entries: parking_lot::Mutex<BTreeMap<(i32, i32), QueueLagEntry>>,
+    // Processed up to info of destination workchains
+    consumed: parking_lot::Mutex<HashMap<i32, u32>>,
+    // Zero disables warnings
+    warning_threshold: AtomicU32,
+}
+
+impl QueueLagTracker {
+
+    pub fn set_warning_threshold(&self, threshold: u32) {
+        self.warning_threshold.store(threshold, Ordering::Relaxed)
+    }
+
+    // Empty update advances seqno, but it has nothing to consume
+    pub fn queue_update_applied(&self, src_wc: i32, dst_wc: i32, mc_seq_no: u32, empty: bool) {
+        let consumed = self.consumed(dst_wc);
+        let mut entries = self.entries.lock();
+        let entry = entries.entry((src_wc, dst_wc)).or_default();
+        entry.created = entry.created.max(mc_seq_no);
+        if !empty && mc_seq_no > consumed {
+            entry.pending.insert(mc_seq_no);
+        }
+        self.report(src_wc, dst_wc, entry, consumed);
+    }
+
+    // Processed up to info of destination covers queues from all sources
+    pub fn processed_upto(&self, dst_wc: i32, mc_seq_no: u32) {
+        let consumed = {
+            let mut consumed = self.consumed.lock();
+            let consumed = consumed.entry(dst_wc).or_default();
+            *consumed = (*consumed).max(mc_seq_no);
+            *consumed
+        };
+        let mut entries = self.entries.lock();
+        for ((src_wc, dst), entry) in entries.iter_mut() {
+            if *dst == dst_wc {
+                entry.pending = entry.pending.split_off(&consumed.saturating_add(1));
+                self.report(*src_wc, dst_wc, entry, consumed);
+            }
+        }
+    }
+
+    pub fn is_tracked(&self, dst_wc: i32) -> bool {
+        self.entries.lock().keys().any(|(_, dst)| *dst == dst_wc)
+    }
+
+    pub fn remove_workchain(&self, workchain_id: i32) {
+        self.entries.lock().retain(|(src, dst), _| *src != workchain_id && *dst != workchain_id);
+        self.consumed.lock().remove(&workchain_id);
+    }
+
+    pub fn lag_table(&self) -> Vec<QueueLag> {
+        let entries = self.entries.lock();
+        entries.iter().map(|((src_wc, dst_wc), entry)| {
+            let consumed = self.consumed(*dst_wc);
+            QueueLag {
+                src_wc: *src_wc,
+                dst_wc: *dst_wc,
+                created: entry.created,
+                consumed,
+                lag: Self::lag(entry, consumed),
+            }
+        }).collect()
+    }
+
+    fn consumed(&self, dst_wc: i32) -> u32 {
+        self.consumed.lock().get(&dst_wc).copied().unwrap_or_default()
+    }
+
+    // Updates after the oldest pending one are not consumed, older ones are consumed or empty
+    fn lag(entry: &QueueLagEntry, consumed: u32) -> u32 {
+        match entry.pending.iter().next() {
+            Some(oldest) => entry.created.saturating_sub(consumed.max(oldest - 1)),
+            None => 0
+        }
+    }
+
+    fn report(&self, src_wc: i32, dst_wc: i32, entry: &mut QueueLagEntry, consumed: u32) {
+        let lag = Self::lag(entry, consumed);
+        metrics::gauge!(
+            "queue_lag", lag as f64, "src" => src_wc.to_string(), "dst" => dst_wc.to_string()
+        );
+        let threshold = self.warning_threshold.load(Ordering::Relaxed);
+        if threshold == 0 || lag <= threshold {
+            return
+        }
+        if entry.warned_at.map_or(true, |at| at.elapsed() >= QUEUE_LAG_WARNING_INTERVAL) {
+            entry.warned_at = Some(Instant::now());
+            log::warn!(
+                "Workchain {dst_wc} lags consuming queue from {src_wc} by {lag} blocks: \
+                created {}, consumed {consumed}", entry.created
+            );
+        }
+    }
+}
+
+// Update of the queue from one shard of connected network, committed by its masterchain block
+pub struct MeshQueueUpdate {
+    pub prev_id: BlockIdExt,
+    pub id: BlockIdExt,
+    pub old_hash: UInt256,
+    pub new_hash: UInt256,
+    // None if the queue is not changed
+    pub update: Option<MerkleUpdate>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MeshQueueUpdateResult {
+    // Given update and buffered ones following it, count of all of them
+    Applied(usize),
+    // Update is not above the watermark, so it was applied before
+    Replayed,
+    // Previous queue is not known yet, update is kept until it is
+    Buffered,
+}
 
 pub struct MeshQueuesKeeper {
     queues: lockfree::map::Map<(i32, BlockIdExt, ShardIdent), Arc<OutMsgQueueInfo>>,
+    // Workchains whose queues were pruned, until they are tracked again
+    pruned: lockfree::set::Set<i32>,
+    lags: QueueLagTracker,
+    // Highest seqnos of applied updates per network and shard, loaded from db on first use.
+    // The lock is held while update is applied, so the check and the apply are atomic
+    watermarks: parking_lot::Mutex<HashMap<(i32, ShardIdent), u32>>,
+    // Updates arrived before their previous queues, by seqno
+    pending: parking_lot::Mutex<HashMap<(i32, ShardIdent), BTreeMap<u32, MeshQueueUpdate>>>,
 }
 
 impl MeshQueuesKeeper {
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
             queues: lockfree::map::Map::new(),
+            pruned: lockfree::set::Set::new(),
+            lags: QueueLagTracker::default(),
+            watermarks: parking_lot::Mutex::new(HashMap::new()),
+            pending: parking_lot::Mutex::new(HashMap::new()),
         })
     }
 
+    pub fn queue_lags(&self) -> &QueueLagTracker {
+        &self.lags
+    }
+
     // ShardStatesKeeper calls this method from clean_cache_worker if cache_resolver advanced
     pub fn gc(
         &self, 
@@ -44,6 +219,41 @@ impl MeshQueuesKeeper {
         Ok(())
     }
 
+    // Called when connected network is removed from mesh config
+    pub fn drop_network(&self, nw_id: i32, db: &InternalDb) -> Result<usize> {
+        for guard in &self.queues {
+            if guard.key().0 == nw_id {
+                self.queues.remove(guard.key());
+            }
+        }
+        self.pending.lock().retain(|(id, _), _| *id != nw_id);
+        self.watermarks.lock().retain(|(id, _), _| *id != nw_id);
+        let watermarks = db.drop_mesh_queue_watermarks(nw_id)?;
+        let dropped = db.drop_mesh_handles(nw_id, false)?;
+        log::info!(
+            "MeshQueuesKeeper::drop_network: {nw_id}, dropped {dropped} handles, {watermarks} watermarks"
+        );
+        Ok(dropped)
+    }
+
+    // Called when workchain is removed from mesh config: drops in-memory queues,
+    // queue update handles and their data
+    pub async fn prune_queues_for(&self, workchain_id: i32, db: &InternalDb) -> Result<PruneStats> {
+        let _ = self.pruned.insert(workchain_id);
+        self.lags.remove_workchain(workchain_id);
+        for guard in &self.queues {
+            if guard.key().0 == workchain_id {
+                self.queues.remove(guard.key());
+            }
+        }
+        self.pending.lock().retain(|(id, _), _| *id != workchain_id);
+        let (handles, bytes) = db.drop_queue_update_handles(workchain_id).await?;
+        log::info!(
+            "MeshQueuesKeeper::prune_queues_for: {workchain_id}, dropped {handles} handles, {bytes} bytes"
+        );
+        Ok(PruneStats { handles, bytes })
+    }
+
     pub fn store_mesh_queue(
         &self,
         nw_id: i32,
@@ -51,8 +261,122 @@ impl MeshQueuesKeeper {
         shard: &ShardIdent,
         queue: Arc<OutMsgQueueInfo>
     ) -> Result<()> {
+        self.pruned.remove(&nw_id);
         let key = (nw_id, mc_block_id.clone(), shard.clone());
         let _ = self.queues.insert(key, queue);
+        // Queue of mesh kit covers all the updates before it
+        let mut watermarks = self.watermarks.lock();
+        if let Some(watermark) = watermarks.get_mut(&(nw_id, shard.clone())) {
+            *watermark = (*watermark).max(mc_block_id.seq_no());
+        }
+        Ok(())
+    }
+
+    // Updates not above the watermark are skipped, so replays after restart change nothing.
+    // The watermark is saved after the new queue is stored: crash between them
+    // leads to re-apply of the update, never to skip of it
+    pub fn apply_mesh_queue_update(
+        &self,
+        nw_id: i32,
+        shard: &ShardIdent,
+        update: MeshQueueUpdate,
+        db: &InternalDb
+    ) -> Result<MeshQueueUpdateResult> {
+        let mut watermarks = self.watermarks.lock();
+        let watermark_key = (nw_id, shard.clone());
+        let watermark = match watermarks.get(&watermark_key) {
+            Some(watermark) => *watermark,
+            None => {
+                let watermark = db.load_mesh_queue_watermark(nw_id, shard)?
+                    .map_or(0, |id| id.seq_no());
+                // Queue of mesh kit may be stored before the watermark is loaded
+                let watermark = self.queues.iter()
+                    .filter(|guard| guard.key().0 == nw_id && &guard.key().2 == shard)
+                    .map(|guard| guard.key().1.seq_no())
+                    .fold(watermark, u32::max);
+                watermarks.insert(watermark_key.clone(), watermark);
+                watermark
+            }
+        };
+        if update.id.seq_no() <= watermark {
+            log::debug!(
+                "MeshQueuesKeeper: update {} of {nw_id} {shard} is replayed, watermark {watermark}",
+                update.id
+            );
+            return Ok(MeshQueueUpdateResult::Replayed)
+        }
+        if self.queues.get(&(nw_id, update.prev_id.clone(), shard.clone())).is_none() {
+            log::debug!(
+                "MeshQueuesKeeper: update {} of {nw_id} {shard} is buffered until {} is applied",
+                update.id, update.prev_id
+            );
+            self.pending.lock().entry(watermark_key).or_default().insert(update.id.seq_no(), update);
+            return Ok(MeshQueueUpdateResult::Buffered)
+        }
+
+        let mut update = update;
+        let mut applied = 0;
+        loop {
+            self.apply_one(nw_id, shard, &update)?;
+            db.save_mesh_queue_watermark(nw_id, shard, &update.id)?;
+            watermarks.insert(watermark_key.clone(), update.id.seq_no());
+            applied += 1;
+
+            // Buffered update may follow the applied one
+            let mut pending = self.pending.lock();
+            let Some(buffered) = pending.get_mut(&watermark_key) else {
+                break
+            };
+            *buffered = buffered.split_off(&(update.id.seq_no() + 1));
+            let follows = buffered.first_key_value()
+                .map_or(false, |(_, next)| next.prev_id == update.id);
+            let next = if follows {
+                buffered.pop_first().map(|(_, next)| next)
+            } else {
+                None
+            };
+            if buffered.is_empty() {
+                pending.remove(&watermark_key);
+            }
+            match next {
+                Some(next) => update = next,
+                None => break
+            }
+        }
+        Ok(MeshQueueUpdateResult::Applied(applied))
+    }
+
+    fn apply_one(&self, nw_id: i32, shard: &ShardIdent, update: &MeshQueueUpdate) -> Result<()> {
+        let old_queue = self.load_mesh_queue(nw_id, &update.prev_id, shard)?;
+        let old_queue_root = old_queue.write_to_new_cell()?.into_cell()?;
+
+        let old_hash = old_queue_root.repr_hash();
+        if old_hash != update.old_hash {
+            fail!(
+                "INTERNAL ERROR: mesh update {}: old queue hash mismatch for {} ({} != {})",
+                update.id, shard, old_hash, update.old_hash
+            );
+        }
+
+        let new_queue = if update.old_hash != update.new_hash {
+            let merkle_update = update.update.as_ref().ok_or_else(
+                || error!("Mesh update {} doesn't contain queue update for {}", update.id, shard)
+            )?;
+            let new_queue_root = merkle_update.apply_for(&old_queue_root)?;
+            let new_hash = new_queue_root.repr_hash();
+            if new_hash != update.new_hash {
+                fail!(
+                    "INTERNAL ERROR: mesh update {}: new queue hash mismatch for {} ({} != {})",
+                    update.id, shard, new_hash, update.new_hash
+                );
+            }
+            Arc::new(OutMsgQueueInfo::construct_from_cell(new_queue_root)?)
+        } else {
+            old_queue
+        };
+
+        self.pruned.remove(&nw_id);
+        let _ = self.queues.insert((nw_id, update.id.clone(), shard.clone()), new_queue);
         Ok(())
     }
 
@@ -65,6 +389,8 @@ impl MeshQueuesKeeper {
         let key = (nw_id, mc_block_id.clone(), shard.clone());
         if let Some(queue_root) = self.queues.get(&key) {
             Ok(queue_root.val().clone())
+        } else if self.pruned.contains(&nw_id) {
+            fail!("Mesh queue {nw_id} {mc_block_id} {shard}: queue no longer tracked")
         } else {
             fail!("Mesh queue {nw_id} {mc_block_id} {shard} not found", )
         }
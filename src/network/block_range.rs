@@ -0,0 +1,296 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{block::BlockStuff, block_proof::BlockProofStuff};
+
+use std::cmp::min;
+use ever_block::{error, fail, BlockIdExt, Result, ShardIdent, UInt256};
+
+#[cfg(test)]
+#[path = "tests/test_block_range.rs"]
+mod tests;
+
+// Range of consecutive applied blocks is requested by `tonNode.downloadBlocks` with two ids:
+// the first one has seqno of the range start and zero hashes, the second one has seqno
+// of the range end, the tag and max answer size in root hash and zero file hash.
+// t-node doesn't serve the query for real block ids, so both kinds are told apart.
+// The answer is raw packed range, see `pack_block_range`
+const BLOCK_RANGE_TAG: [u8; 4] = *b"BRNG";
+const BLOCK_ID_LEN: usize = 4 + 8 + 4 + 32 + 32;
+// Flag, length of block and length of proof
+const ENTRY_HEADER_LEN: usize = BLOCK_ID_LEN + 1 + 4 + 4;
+// Tag, continuation flag and seqno, count of entries
+const RANGE_HEADER_LEN: usize = 4 + 1 + 4 + 4;
+
+// Hard caps of one answer, both sides enforce them. Answer fits RLDP answer limit
+pub const BLOCK_RANGE_MAX_BYTES: u32 = 8 << 20;
+pub const BLOCK_RANGE_MAX_COUNT: u32 = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockRangeQuery {
+    pub shard: ShardIdent,
+    pub from_seqno: u32,
+    pub to_seqno: u32,
+    pub max_bytes: u32,
+}
+
+impl BlockRangeQuery {
+
+    pub fn to_block_ids(&self) -> Vec<BlockIdExt> {
+        let mut root_hash = [0u8; 32];
+        root_hash[..4].copy_from_slice(&BLOCK_RANGE_TAG);
+        root_hash[4..8].copy_from_slice(&self.max_bytes.to_le_bytes());
+        vec![
+            BlockIdExt::with_params(self.shard.clone(), self.from_seqno, UInt256::default(), UInt256::default()),
+            BlockIdExt::with_params(self.shard.clone(), self.to_seqno, UInt256::from(root_hash), UInt256::default()),
+        ]
+    }
+
+    // None if ids are not a range query
+    pub fn from_block_ids(ids: &[BlockIdExt]) -> Option<Self> {
+        let [from, to] = ids else {
+            return None
+        };
+        let tag = &to.root_hash().as_slice()[..4];
+        if (tag != BLOCK_RANGE_TAG) || (from.shard() != to.shard()) ||
+            (from.root_hash() != &UInt256::default()) || (from.file_hash() != &UInt256::default()) ||
+            (to.file_hash() != &UInt256::default())
+        {
+            return None
+        }
+        let mut max_bytes = [0u8; 4];
+        max_bytes.copy_from_slice(&to.root_hash().as_slice()[4..8]);
+        Some(Self {
+            shard: from.shard().clone(),
+            from_seqno: from.seq_no(),
+            to_seqno: to.seq_no(),
+            max_bytes: u32::from_le_bytes(max_bytes),
+        })
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.from_seqno > self.to_seqno {
+            fail!("Invalid block range {}..={}", self.from_seqno, self.to_seqno)
+        }
+        if self.max_bytes == 0 {
+            fail!("Zero answer size of block range query")
+        }
+        Ok(())
+    }
+
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockRangeEntry {
+    pub id: BlockIdExt,
+    pub block: Vec<u8>,
+    pub proof: Vec<u8>,
+    pub is_link: bool,
+}
+
+impl BlockRangeEntry {
+    fn packed_len(&self) -> usize {
+        ENTRY_HEADER_LEN + self.block.len() + self.proof.len()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockRange {
+    pub entries: Vec<BlockRangeEntry>,
+    // Seqno to continue from if the answer is cut by the limits,
+    // None if all blocks the peer has up to the range end are served
+    pub next_seqno: Option<u32>,
+}
+
+// Verified blocks of the range answer
+pub struct DownloadedBlockRange {
+    pub blocks: Vec<(BlockStuff, BlockProofStuff)>,
+    pub next_seqno: Option<u32>,
+}
+
+// Collects consecutive blocks of the range while they fit the limits
+pub struct BlockRangeBuilder {
+    to_seqno: u32,
+    max_bytes: usize,
+    max_count: usize,
+    size: usize,
+    range: BlockRange,
+}
+
+impl BlockRangeBuilder {
+
+    pub fn new(query: &BlockRangeQuery) -> Self {
+        let count = query.to_seqno.saturating_sub(query.from_seqno).saturating_add(1);
+        Self {
+            to_seqno: query.to_seqno,
+            max_bytes: min(query.max_bytes, BLOCK_RANGE_MAX_BYTES) as usize,
+            max_count: min(count, BLOCK_RANGE_MAX_COUNT) as usize,
+            size: RANGE_HEADER_LEN,
+            range: BlockRange::default(),
+        }
+    }
+
+    // Returns false if the entry doesn't fit, the range is continued from it then.
+    // The first entry is taken over requested size if it fits the hard cap,
+    // otherwise a big block would never be served
+    pub fn push(&mut self, entry: BlockRangeEntry) -> bool {
+        if self.is_complete() {
+            return false
+        }
+        let size = self.size + entry.packed_len();
+        let fits = (size <= self.max_bytes) ||
+            (self.range.entries.is_empty() && (size <= BLOCK_RANGE_MAX_BYTES as usize));
+        if !fits {
+            if !self.range.entries.is_empty() {
+                self.range.next_seqno = Some(entry.id.seq_no());
+            }
+            return false
+        }
+        let seq_no = entry.id.seq_no();
+        self.size = size;
+        self.range.entries.push(entry);
+        if (self.range.entries.len() >= self.max_count) && (seq_no < self.to_seqno) {
+            self.range.next_seqno = Some(seq_no + 1);
+        }
+        true
+    }
+
+    // Nothing is taken more
+    pub fn is_complete(&self) -> bool {
+        self.range.next_seqno.is_some() ||
+            self.range.entries.last().map_or(false, |entry| entry.id.seq_no() >= self.to_seqno)
+    }
+
+    pub fn finish(self) -> BlockRange {
+        self.range
+    }
+
+}
+
+fn write_block_id(data: &mut Vec<u8>, id: &BlockIdExt) {
+    data.extend_from_slice(&id.shard().workchain_id().to_le_bytes());
+    data.extend_from_slice(&id.shard().shard_prefix_with_tag().to_le_bytes());
+    data.extend_from_slice(&id.seq_no().to_le_bytes());
+    data.extend_from_slice(id.root_hash().as_slice());
+    data.extend_from_slice(id.file_hash().as_slice());
+}
+
+fn write_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    data.extend_from_slice(bytes);
+}
+
+// Layout (little endian): tag, continuation flag (u8) and seqno (u32), count of entries (u32),
+// then entries: block id (wc, shard, seqno, root hash, file hash), proof link flag (u8),
+// block length (u32) and data, proof length (u32) and data
+pub fn pack_block_range(range: &BlockRange) -> Vec<u8> {
+    let size = RANGE_HEADER_LEN + range.entries.iter().map(|entry| entry.packed_len()).sum::<usize>();
+    let mut data = Vec::with_capacity(size);
+    data.extend_from_slice(&BLOCK_RANGE_TAG);
+    data.push(range.next_seqno.is_some() as u8);
+    data.extend_from_slice(&range.next_seqno.unwrap_or_default().to_le_bytes());
+    data.extend_from_slice(&(range.entries.len() as u32).to_le_bytes());
+    for entry in &range.entries {
+        write_block_id(&mut data, &entry.id);
+        data.push(entry.is_link as u8);
+        write_bytes(&mut data, &entry.block);
+        write_bytes(&mut data, &entry.proof);
+    }
+    data
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            fail!("Block range answer is truncated")
+        }
+        let (ret, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(ret)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut ret = [0u8; N];
+        ret.copy_from_slice(self.take(N)?);
+        Ok(ret)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn block_id(&mut self) -> Result<BlockIdExt> {
+        let workchain_id = i32::from_le_bytes(self.array()?);
+        let shard = ShardIdent::with_tagged_prefix(workchain_id, u64::from_le_bytes(self.array()?))?;
+        let seq_no = self.u32()?;
+        let root_hash = UInt256::from(self.array::<32>()?);
+        let file_hash = UInt256::from(self.array::<32>()?);
+        Ok(BlockIdExt::with_params(shard, seq_no, root_hash, file_hash))
+    }
+
+}
+
+// Unpacks the answer to the query, checking it follows the query and the hard caps
+pub fn unpack_block_range(data: &[u8], query: &BlockRangeQuery) -> Result<BlockRange> {
+    if data.len() > BLOCK_RANGE_MAX_BYTES as usize {
+        fail!("Block range answer is too big: {} bytes", data.len())
+    }
+    let mut reader = Reader { data };
+    if reader.array::<4>()? != BLOCK_RANGE_TAG {
+        fail!("Block range answer has wrong tag")
+    }
+    let has_next = reader.u8()? != 0;
+    let next_seqno = reader.u32()?;
+    let count = reader.u32()?;
+    if count > BLOCK_RANGE_MAX_COUNT {
+        fail!("Block range answer has too many blocks: {}", count)
+    }
+    let mut range = BlockRange::default();
+    for i in 0..count {
+        let id = reader.block_id()?;
+        let expected = query.from_seqno.checked_add(i)
+            .filter(|seq_no| *seq_no <= query.to_seqno)
+            .ok_or_else(|| error!("Block range answer has blocks out of range"))?;
+        if (id.shard() != &query.shard) || (id.seq_no() != expected) {
+            fail!("Block range answer has unexpected block {} instead of seqno {}", id, expected)
+        }
+        let is_link = reader.u8()? != 0;
+        let block = reader.bytes()?;
+        let proof = reader.bytes()?;
+        range.entries.push(BlockRangeEntry { id, block, proof, is_link });
+    }
+    if !reader.data.is_empty() {
+        fail!("Block range answer has {} extra bytes", reader.data.len())
+    }
+    if has_next {
+        if (count == 0) || (next_seqno != query.from_seqno + count) || (next_seqno > query.to_seqno) {
+            fail!("Block range answer has wrong continuation seqno {}", next_seqno)
+        }
+        range.next_seqno = Some(next_seqno);
+    }
+    Ok(range)
+}
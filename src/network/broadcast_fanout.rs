@@ -0,0 +1,206 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{config::BroadcastFanoutConfig, network::neighbours_quality::NeighboursQuality};
+
+use rand::seq::SliceRandom;
+use std::{
+    collections::{HashMap, HashSet, VecDeque}, sync::{Arc, atomic::{AtomicU64, Ordering}},
+    time::{Duration, Instant}
+};
+use ever_block::{BlockIdExt, KeyId, Result, UInt256};
+
+#[cfg(test)]
+#[path = "tests/test_broadcast_fanout.rs"]
+mod tests;
+
+type FanoutKey = (UInt256, UInt256);
+
+struct KnownEntry {
+    peers: HashSet<Arc<KeyId>>,
+    added_at: Instant,
+}
+
+#[derive(Default)]
+struct KnownWindow {
+    entries: HashMap<FanoutKey, KnownEntry>,
+    // Every key is pushed once, when its entry is created
+    order: VecDeque<FanoutKey>,
+}
+
+#[async_trait::async_trait]
+pub trait FanoutTransport : Sync + Send {
+    fn neighbours(&self) -> Vec<Arc<KeyId>>;
+    // Usual overlay broadcast, returns count of nodes it was sent to
+    async fn broadcast_all(&self, data: &[u8]) -> Result<u32>;
+    async fn send_to(&self, peer: &Arc<KeyId>, data: &[u8]) -> Result<()>;
+}
+
+// Neighbours known to have a block, keyed by (root hash, file hash): they sent or advertised it
+// to us, answered a query with it, or got it from us. Block broadcast is sent to at most
+// `max_fanout` other neighbours, those which bring new blocks most often go first.
+// Masterchain key blocks are always broadcast to all neighbours.
+pub struct BroadcastFanout {
+    max_fanout: usize,
+    window: usize,
+    ttl: Duration,
+    state: parking_lot::Mutex<KnownWindow>,
+    suppressed_known: AtomicU64,
+    suppressed_by_cap: AtomicU64,
+    full_fanouts: AtomicU64,
+}
+
+impl BroadcastFanout {
+
+    pub fn new(config: &BroadcastFanoutConfig) -> Self {
+        Self {
+            max_fanout: config.max_fanout.max(1),
+            window: config.window.max(1),
+            ttl: Duration::from_secs(config.ttl_sec as u64),
+            state: parking_lot::Mutex::new(KnownWindow::default()),
+            suppressed_known: AtomicU64::new(0),
+            suppressed_by_cap: AtomicU64::new(0),
+            full_fanouts: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_known(&self, id: &BlockIdExt, peer: &Arc<KeyId>) {
+        self.record_known_at(id, std::slice::from_ref(peer), Instant::now())
+    }
+
+    // Returns count of neighbours the broadcast was sent to
+    pub async fn send(
+        &self,
+        transport: &dyn FanoutTransport,
+        id: &BlockIdExt,
+        is_key_block: bool,
+        quality: Option<&NeighboursQuality>,
+        data: &[u8]
+    ) -> Result<usize> {
+        if is_key_block {
+            self.full_fanouts.fetch_add(1, Ordering::Relaxed);
+            metrics::increment_counter!("block_broadcast_full_fanouts");
+            return Ok(transport.broadcast_all(data).await? as usize)
+        }
+        let targets = self.select_targets(id, transport.neighbours(), quality, Instant::now());
+        let mut sent = 0;
+        for peer in targets.iter() {
+            match transport.send_to(peer, data).await {
+                Ok(()) => sent += 1,
+                Err(e) => log::debug!("Can't send block broadcast {} to {}: {}", id, peer, e)
+            }
+        }
+        Ok(sent)
+    }
+
+    pub fn suppressed_known(&self) -> u64 {
+        self.suppressed_known.load(Ordering::Relaxed)
+    }
+
+    pub fn suppressed_by_cap(&self) -> u64 {
+        self.suppressed_by_cap.load(Ordering::Relaxed)
+    }
+
+    pub fn full_fanouts(&self) -> u64 {
+        self.full_fanouts.load(Ordering::Relaxed)
+    }
+
+    // Chosen neighbours are recorded as known, so the block is not sent to them again
+    fn select_targets(
+        &self,
+        id: &BlockIdExt,
+        neighbours: Vec<Arc<KeyId>>,
+        quality: Option<&NeighboursQuality>,
+        now: Instant
+    ) -> Vec<Arc<KeyId>> {
+        let key = (id.root_hash().clone(), id.file_hash().clone());
+        let count = neighbours.len();
+        let mut targets = {
+            let mut state = self.state.lock();
+            self.expire(&mut state, now);
+            match state.entries.get(&key) {
+                Some(entry) => neighbours.into_iter().filter(|peer| !entry.peers.contains(peer)).collect(),
+                None => neighbours
+            }
+        };
+        let known = count - targets.len();
+        // Shuffled first, so neighbours of equal quality are chosen randomly
+        targets.shuffle(&mut rand::thread_rng());
+        if let Some(quality) = quality {
+            let mut ranked = targets.into_iter()
+                .map(|peer| {
+                    let quality = quality.quality(&peer);
+                    (peer, quality)
+                })
+                .collect::<Vec<_>>();
+            ranked.sort_by(|(_, a), (_, b)| {
+                b.novelty.partial_cmp(&a.novelty)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then(b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal))
+            });
+            targets = ranked.into_iter().map(|(peer, _)| peer).collect();
+        }
+        let by_cap = targets.len().saturating_sub(self.max_fanout);
+        targets.truncate(self.max_fanout);
+        self.record_known_at(id, &targets, now);
+
+        log::trace!(
+            "Block broadcast {}: {} neighbours chosen, {} know the block, {} skipped by fanout cap",
+            id, targets.len(), known, by_cap
+        );
+        if known > 0 {
+            self.suppressed_known.fetch_add(known as u64, Ordering::Relaxed);
+            metrics::counter!("block_broadcast_suppressed_known", known as u64);
+        }
+        if by_cap > 0 {
+            self.suppressed_by_cap.fetch_add(by_cap as u64, Ordering::Relaxed);
+            metrics::counter!("block_broadcast_suppressed_by_cap", by_cap as u64);
+        }
+        targets
+    }
+
+    fn record_known_at(&self, id: &BlockIdExt, peers: &[Arc<KeyId>], now: Instant) {
+        let key = (id.root_hash().clone(), id.file_hash().clone());
+        let mut state = self.state.lock();
+        self.expire(&mut state, now);
+        if !state.entries.contains_key(&key) {
+            while state.entries.len() >= self.window {
+                match state.order.pop_front() {
+                    Some(oldest) => {
+                        state.entries.remove(&oldest);
+                    }
+                    None => break
+                }
+            }
+            state.entries.insert(key.clone(), KnownEntry { peers: HashSet::new(), added_at: now });
+            state.order.push_back(key.clone());
+        }
+        if let Some(entry) = state.entries.get_mut(&key) {
+            entry.peers.extend(peers.iter().cloned());
+        }
+    }
+
+    // Entries are ordered by time, so expired ones are at the front
+    fn expire(&self, state: &mut KnownWindow, now: Instant) {
+        while let Some(key) = state.order.front() {
+            let expired = state.entries.get(key)
+                .map_or(true, |entry| now.saturating_duration_since(entry.added_at) >= self.ttl);
+            if !expired {
+                break
+            }
+            if let Some(key) = state.order.pop_front() {
+                state.entries.remove(&key);
+            }
+        }
+    }
+}
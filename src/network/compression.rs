@@ -0,0 +1,67 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use ever_block::{error, fail, Result};
+
+#[cfg(test)]
+#[path = "tests/test_compression.rs"]
+mod tests;
+
+// Compressed block data is the tag, original length (u32 LE) and zstd frame.
+// The tag doesn't match any BOC magic, so raw and compressed data are told apart
+const COMPRESSED_BLOCK_TAG: [u8; 4] = *b"ZBLK";
+const COMPRESSED_BLOCK_HEADER_LEN: usize = 8;
+// Protection from decompression bombs, real blocks are much smaller
+const MAX_DECOMPRESSED_BLOCK_SIZE: usize = 64 << 20;
+
+pub const DEFAULT_BLOCK_COMPRESSION_LEVEL: i32 = 3;
+
+pub fn is_compressed_block(data: &[u8]) -> bool {
+    data.len() >= COMPRESSED_BLOCK_HEADER_LEN && data[..4] == COMPRESSED_BLOCK_TAG
+}
+
+// Returns None if compression doesn't make data smaller
+pub fn compress_block(data: &[u8], level: i32) -> Result<Option<Vec<u8>>> {
+    if data.len() > MAX_DECOMPRESSED_BLOCK_SIZE {
+        return Ok(None)
+    }
+    let compressed = zstd::bulk::compress(data, level)
+        .map_err(|e| error!("Cannot compress block data: {}", e))?;
+    if compressed.len() + COMPRESSED_BLOCK_HEADER_LEN >= data.len() {
+        return Ok(None)
+    }
+    let mut ret = Vec::with_capacity(compressed.len() + COMPRESSED_BLOCK_HEADER_LEN);
+    ret.extend_from_slice(&COMPRESSED_BLOCK_TAG);
+    ret.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    ret.extend_from_slice(&compressed);
+    Ok(Some(ret))
+}
+
+// Raw data is returned as is
+pub fn decompress_block(data: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_compressed_block(&data) {
+        return Ok(data)
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&data[4..COMPRESSED_BLOCK_HEADER_LEN]);
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_DECOMPRESSED_BLOCK_SIZE {
+        fail!("Compressed block is too big: {} bytes", len)
+    }
+    let ret = zstd::bulk::decompress(&data[COMPRESSED_BLOCK_HEADER_LEN..], len)
+        .map_err(|e| error!("Cannot decompress block data: {}", e))?;
+    if ret.len() != len {
+        fail!("Decompressed block has length {} instead of {}", ret.len(), len)
+    }
+    Ok(ret)
+}
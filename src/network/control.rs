@@ -13,9 +13,16 @@
 
 use crate::{
     collator_test_bundle::CollatorTestBundle, config::{KeyRing, NodeConfigHandler},
-    engine_traits::EngineOperations, engine::Engine, network::node_network::NodeNetwork,
+    engine_traits::EngineOperations, error::NodeError,
+    internal_db::consistency::{ConsistencyReport, RepairMode},
+    manual_gc::{GcKind, GcProgress, GcTicket},
+    network::{
+        control_limits::{ControlLimits, ControlPermit, DetachedCommands, DetachedStatus},
+        control_permissions::{ControlCommandCategory, ControlPermissions}, node_network::NodeNetwork
+    },
+    node_status::sync_status_name,
     shard_states_keeper::PinnedShardStateGuard, 
-    validator::validator_utils::validatordescr_to_catchain_node,
+    validator::{key_rotation::RotationKeys, validator_utils::validatordescr_to_catchain_node},
     validating_utils::{supported_version, supported_capabilities}
 };
 
@@ -23,7 +30,8 @@ use adnl::{
     common::{QueryResult, Subscriber, AdnlPeers},
     server::{AdnlServer, AdnlServerConfig}
 };
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
+use storage::block_handle_db::{deserialize_node_state, serialize_node_state};
 use ton_api::{
     deserialize_boxed, IntoBoxed,
     ton::{
@@ -48,12 +56,79 @@ use ton_api::{
     }
 };
 use ever_block::{
-    error, fail, AccountId, BlockIdExt, BlsKeyOption, Ed25519KeyOption, KeyId, MASTERCHAIN_ID,
+    base64_encode, error, fail, AccountId, BlockIdExt, BlsKeyOption, Ed25519KeyOption, KeyId, MASTERCHAIN_ID,
     MerkleProof, MsgAddressInt, read_single_root_boc, Result, Serializable, ShardIdent, 
     ShardAccount, UInt256
 };
 use ever_block_json::serialize_config_param;
 
+// Filters of GetSelectedStats query which run DB consistency check instead of getting stats
+pub const DB_CONSISTENCY_CHECK_FILTER: &str = "db_consistency_check";
+pub const DB_CONSISTENCY_FIX_FILTER: &str = "db_consistency_fix";
+// Filter of GetSelectedStats query to get estimation of storage usage by component
+pub const STORAGE_USAGE_FILTER: &str = "storage_usage";
+// Filter of GetSelectedStats query to get structured node status, values are json
+pub const NODE_STATUS_FILTER: &str = "node_status_json";
+// Filter of GetSelectedStats query to get stats of the last archives GC pass by disk watermarks
+pub const ARCHIVES_GC_STATUS_FILTER: &str = "archives_gc_status";
+// Filter of GetSelectedStats query to get quality scores of full node overlay neighbours
+pub const NEIGHBOURS_QUALITY_FILTER: &str = "neighbours_quality";
+// Filter of GetSelectedStats query to get queues and drops of external messages prevalidation
+pub const EXT_MESSAGES_PREVALIDATION_FILTER: &str = "ext_messages_prevalidation";
+// Filter prefixes of GetSelectedStats query to start manual GC pass ("gc_trigger:<kind>")
+// and to get its progress ("gc_status:<kind>:<ticket id>")
+pub const GC_TRIGGER_FILTER_PREFIX: &str = "gc_trigger:";
+pub const GC_STATUS_FILTER_PREFIX: &str = "gc_status:";
+// Filters of GetSelectedStats query to export full node states and to import them back
+// ("node_state_import:<hex of export data>"), import with overwrite replaces differing states
+pub const NODE_STATE_EXPORT_FILTER: &str = "node_state_export";
+pub const NODE_STATE_IMPORT_FILTER_PREFIX: &str = "node_state_import:";
+pub const NODE_STATE_OVERWRITE_FILTER_PREFIX: &str = "node_state_import_overwrite:";
+// Filter of GetSelectedStats query to list full node and validator states for debugging
+pub const NODE_STATE_KEYS_FILTER: &str = "node_state_keys";
+// Filter prefix of GetSelectedStats query to trace REMP message and get its trace
+// ("remp_trace:<hex message id>")
+pub const REMP_TRACE_FILTER_PREFIX: &str = "remp_trace:";
+// Filter prefix of GetSelectedStats query to get collator and signers of block
+// ("block_provenance:<block id>")
+pub const BLOCK_PROVENANCE_FILTER_PREFIX: &str = "block_provenance:";
+// Filter prefix of GetSelectedStats query to get account state with Merkle proof and proof chain
+// of its block ("account_state_proof:<address>")
+pub const ACCOUNT_STATE_PROOF_FILTER_PREFIX: &str = "account_state_proof:";
+// Version of account state proof answer, increased on format change
+const ACCOUNT_STATE_PROOF_VERSION: u32 = 1;
+// Filter prefix of GetSelectedStats query to get indexed transactions of account
+// ("account_transactions:<address>:<from seqno>:<to seqno>:<limit>[:<continuation>]")
+pub const ACCOUNT_TRANSACTIONS_FILTER_PREFIX: &str = "account_transactions:";
+const MAX_ACCOUNT_TRANSACTIONS_LIMIT: usize = 100;
+// Filter of GetSelectedStats query to get signed snapshot of the network by the last applied
+// mc block, values are hex of the snapshot data, its signature and the node public key
+pub const NETWORK_SNAPSHOT_FILTER: &str = "network_snapshot";
+// Filter of GetSelectedStats query to reload node config from its file
+pub const CONFIG_RELOAD_FILTER: &str = "config_reload";
+// Filter of GetSelectedStats query to move validator keys of the config into encrypted keystore
+pub const KEYSTORE_ENCRYPT_FILTER: &str = "keystore_encrypt";
+// Filter of GetSelectedStats query to get audit log of control commands
+pub const CONTROL_AUDIT_FILTER: &str = "control_audit";
+// Filter prefix of GetSelectedStats query to get our membership in the next sessions
+// ("validator_schedule:<count of catchain seqnos>") and filter to get latest participated sessions
+pub const VALIDATOR_SCHEDULE_FILTER_PREFIX: &str = "validator_schedule:";
+pub const VALIDATOR_SESSIONS_FILTER: &str = "validator_sessions";
+const MAX_VALIDATOR_SCHEDULE_LOOKAHEAD: u32 = 100;
+// Filter of GetSelectedStats query to get state of validator key rotation and filter prefix
+// to provide keys for the next rotation ("key_rotation_keys:<hex key hash>:<hex adnl key hash>")
+pub const KEY_ROTATION_FILTER: &str = "key_rotation";
+pub const KEY_ROTATION_KEYS_FILTER_PREFIX: &str = "key_rotation_keys:";
+// Filter prefix of GetSelectedStats query to get status and result of detached command,
+// the ticket is returned by the command ("detached_status:<ticket>")
+pub const DETACHED_STATUS_FILTER_PREFIX: &str = "detached_status:";
+// Code of ControlQueryError when the client's key is not permitted to run the command
+pub const PERMISSION_DENIED_ERROR_CODE: ton::int = -2;
+// Codes of ControlQueryError when too many commands of the category are running
+// and when the command is not finished in time
+pub const BUSY_ERROR_CODE: ton::int = -3;
+pub const TIMEOUT_ERROR_CODE: ton::int = -4;
+
 pub struct ControlServer {
     adnl: AdnlServer
 }
@@ -64,6 +139,8 @@ impl ControlServer {
         data_source: DataSource,
         key_ring: Arc<dyn KeyRing>,
         node_config: Arc<NodeConfigHandler>,
+        permissions: Arc<ControlPermissions>,
+        limits: Arc<ControlLimits>,
         network: Option<&NodeNetwork>
     ) -> Result<Self> {
         let ret = Self {
@@ -71,7 +148,9 @@ impl ControlServer {
                 config,
                 vec![
                     Arc::new(
-                        ControlQuerySubscriber::new(data_source, key_ring, node_config, network)?
+                        ControlQuerySubscriber::new(
+                            data_source, key_ring, node_config, permissions, limits, network
+                        )?
                     )
                 ]
             ).await?
@@ -96,6 +175,9 @@ struct ControlQuerySubscriber {
     data_source: DataSource,
     key_ring: Arc<dyn KeyRing>,
     config: Arc<NodeConfigHandler>,
+    permissions: Arc<ControlPermissions>,
+    limits: Arc<ControlLimits>,
+    detached: DetachedCommands,
     public_overlay_adnl_id: Option<Arc<KeyId>>
 }
 
@@ -105,6 +187,8 @@ impl ControlQuerySubscriber {
         data_source: DataSource,
         key_ring: Arc<dyn KeyRing>,
         config: Arc<NodeConfigHandler>,
+        permissions: Arc<ControlPermissions>,
+        limits: Arc<ControlLimits>,
         network: Option<&NodeNetwork>,
     ) -> Result<Self> {
         let key_id = if let Some (network) = network {
@@ -116,6 +200,9 @@ impl ControlQuerySubscriber {
             data_source,
             key_ring,
             config,
+            permissions,
+            limits,
+            detached: DetachedCommands::new(),
             public_overlay_adnl_id: key_id
         };
         Ok(ret)
@@ -260,17 +347,7 @@ impl ControlQuerySubscriber {
     }
 
     fn convert_sync_status(&self, sync_status: u32 ) -> String {
-        match sync_status {
-            Engine::SYNC_STATUS_START_BOOT => "start_boot".to_string(),
-            Engine::SYNC_STATUS_LOAD_MASTER_STATE => "load_master_state".to_string(),
-            Engine::SYNC_STATUS_LOAD_SHARD_STATES => "load_shard_states".to_string(),
-            Engine::SYNC_STATUS_FINISH_BOOT => "finish_boot".to_string(),
-            Engine::SYNC_STATUS_SYNC_BLOCKS => "synchronization_by_blocks".to_string(),
-            Engine::SYNC_STATUS_FINISH_SYNC => "synchronization_finished".to_string(),
-            Engine::SYNC_STATUS_CHECKING_DB => "checking_db".to_string(),
-            Engine::SYNC_STATUS_DB_BROKEN => "db_broken".to_string(),
-            _ => "no_set_status".to_string()
-        }
+        sync_status_name(sync_status).to_string()
     }
 
     fn block_id_to_json(block_id: &BlockIdExt) -> String {
@@ -480,8 +557,393 @@ impl ControlQuerySubscriber {
 
         Self::add_stats(&mut stats, "validation_status", format!("\"{:?}\"", engine.validation_status()));
 
+        // pinned states keep disk space from being reclaimed by GC
+        if let Ok((states, pins)) = engine.pinned_states_stats() {
+            Self::add_stats(&mut stats, "pinned_states", states);
+            Self::add_stats(&mut stats, "pinned_states_pins", pins);
+        }
+
+        Ok(Stats { stats: stats.into() })
+
+    }
+
+    // Check runs detached, its report is got by the ticket
+    fn check_db_consistency(&self, command: &str, mode: RepairMode) -> Result<Stats> {
+        let engine = self.engine()?.clone();
+        let ticket = self.detached.spawn(command, async move {
+            let report = engine.check_db_consistency(mode).await?;
+            Ok(Self::consistency_stats(&report))
+        });
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "command", command);
+        Self::add_stats(&mut stats, "ticket", ticket);
+        Ok(Stats { stats: stats.into() })
+    }
+
+    fn consistency_stats(report: &ConsistencyReport) -> Stats {
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "checked_handles", report.checked_handles);
+        Self::add_stats(&mut stats, "missing_data", report.missing_data);
+        Self::add_stats(&mut stats, "missing_proof", report.missing_proof);
+        Self::add_stats(&mut stats, "missing_proof_link", report.missing_proof_link);
+        Self::add_stats(&mut stats, "missing_archive", report.missing_archive);
+        Self::add_stats(&mut stats, "unflagged_files", report.unflagged_files);
+        Self::add_stats(&mut stats, "orphaned_files", report.orphaned_files);
+        Self::add_stats(&mut stats, "unflagged_states", report.unflagged_states);
+        Self::add_stats(&mut stats, "orphaned_states", report.orphaned_states);
+        Self::add_stats(&mut stats, "missing_persistent_states", report.missing_persistent_states);
+        Self::add_stats(&mut stats, "unflagged_persistent_states", report.unflagged_persistent_states);
+        Self::add_stats(&mut stats, "orphaned_persistent_states", report.orphaned_persistent_states);
+        Self::add_stats(&mut stats, "repaired", report.repaired);
+        let ids = report.offending_ids.iter().map(|id| Self::block_id_to_json(id)).collect::<Vec<_>>();
+        Self::add_stats(&mut stats, "offending_ids", format!("[{}]", ids.join(",")));
+        Stats { stats: stats.into() }
+    }
+
+    // Result stats follow the status when the command is finished
+    fn detached_status(&self, ticket: &str) -> Result<Stats> {
+        let ticket = ticket.parse()
+            .map_err(|e| error!("Invalid control command ticket {}: {}", ticket, e))?;
+        let (command, status) = self.detached.status(ticket)?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "command", command);
+        Self::add_stats(&mut stats, "ticket", ticket);
+        match status {
+            DetachedStatus::Running => Self::add_stats(&mut stats, "finished", false),
+            DetachedStatus::Finished(result) => {
+                Self::add_stats(&mut stats, "finished", true);
+                stats.extend(result.stats.iter().cloned());
+            }
+            DetachedStatus::Failed(error) => {
+                Self::add_stats(&mut stats, "finished", true);
+                Self::add_stats(&mut stats, "error", error);
+            }
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    fn gc_stats(ticket: &GcTicket, progress: Option<GcProgress>) -> Stats {
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "kind", ticket.kind);
+        Self::add_stats(&mut stats, "ticket", ticket.id);
+        if let Some(progress) = progress {
+            Self::add_stats(&mut stats, "scanned", progress.scanned);
+            Self::add_stats(&mut stats, "removed", progress.removed);
+            Self::add_stats(&mut stats, "bytes_freed", progress.bytes_freed);
+            Self::add_stats(&mut stats, "finished", progress.finished);
+            if let Some(error) = progress.error {
+                Self::add_stats(&mut stats, "error", error);
+            }
+        }
+        Stats { stats: stats.into() }
+    }
+
+    fn trigger_gc(&self, kind: &str) -> Result<Stats> {
+        let kind = GcKind::from_name(kind)?;
+        let ticket = self.engine()?.clone().trigger_gc(kind)?;
+        Ok(Self::gc_stats(&ticket, None))
+    }
+
+    fn gc_status(&self, ticket: &str) -> Result<Stats> {
+        let (kind, id) = ticket.split_once(':')
+            .ok_or_else(|| error!("GC ticket must be in form <kind>:<id>"))?;
+        let ticket = GcTicket {
+            kind: GcKind::from_name(kind)?,
+            id: id.parse().map_err(|e| error!("Invalid GC ticket id {}: {}", id, e))?
+        };
+        let progress = self.engine()?.gc_status(&ticket)?;
+        Ok(Self::gc_stats(&ticket, Some(progress)))
+    }
+
+    async fn export_node_state(&self) -> Result<Stats> {
+        let entries = self.engine()?.export_node_state().await?;
+        let mut stats = Vec::new();
+        for (key, id) in entries.iter() {
+            Self::add_stats(&mut stats, key, Self::block_id_to_json(id));
+        }
+        let data = serialize_node_state(&entries)?;
+        Self::add_stats(&mut stats, "data", format!("\"{}\"", hex::encode(data)));
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Stat key is component of the report, value is its json
+    async fn storage_usage_report(&self) -> Result<Stats> {
+        let report = self.engine()?.storage_usage_report().await?;
+        let mut stats = Vec::new();
+        if let serde_json::Value::Object(components) = serde_json::to_value(&report)? {
+            for (component, value) in components {
+                Self::add_stats(&mut stats, component, value);
+            }
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Stat key is top level field of the status, value is its json
+    async fn node_status(&self) -> Result<Stats> {
+        let status = self.engine()?.node_status().await;
+        let mut stats = Vec::new();
+        if let serde_json::Value::Object(fields) = serde_json::to_value(&status)? {
+            for (field, value) in fields {
+                Self::add_stats(&mut stats, field, value);
+            }
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Stat key is field of the last pass stats, no stats if there was no pass yet
+    fn archives_gc_status(&self) -> Result<Stats> {
+        let mut stats = Vec::new();
+        if let Some(last_run) = self.engine()?.archives_gc_stats()? {
+            if let serde_json::Value::Object(fields) = serde_json::to_value(&last_run)? {
+                for (field, value) in fields {
+                    Self::add_stats(&mut stats, field, value);
+                }
+            }
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Stat key is "<overlay>:<peer>", value is json of the neighbour quality
+    fn neighbours_quality(&self) -> Result<Stats> {
+        let mut stats = Vec::new();
+        for (overlay, peer, quality) in self.engine()?.neighbours_quality() {
+            Self::add_stats(&mut stats, format!("{}:{}", overlay, peer), serde_json::to_value(&quality)?);
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Totals, then dropped messages count with "<lane>:<source>" key
+    fn ext_messages_prevalidation(&self) -> Result<Stats> {
+        let (totals, drops) = self.engine()?.ext_messages_prevalidation_stats();
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "high_queued", totals.high_queued);
+        Self::add_stats(&mut stats, "low_queued", totals.low_queued);
+        Self::add_stats(&mut stats, "high_dropped", totals.high_dropped);
+        Self::add_stats(&mut stats, "low_dropped", totals.low_dropped);
+        for (lane, source, count) in drops {
+            Self::add_stats(&mut stats, format!("{}:{}", lane, source), count);
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Stat key is "<shard>:<cc_seqno>", value is json of the scheduled session
+    async fn validator_schedule(&self, lookahead: &str) -> Result<Stats> {
+        let lookahead = lookahead.parse::<u32>()
+            .map_err(|e| error!("Invalid count of catchain seqnos {}: {}", lookahead, e))?;
+        if lookahead > MAX_VALIDATOR_SCHEDULE_LOOKAHEAD {
+            fail!("Count of catchain seqnos must not exceed {}", MAX_VALIDATOR_SCHEDULE_LOOKAHEAD)
+        }
+        let schedule = self.engine()?.validator_schedule(lookahead).await?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "mc_seq_no", schedule.mc_seq_no);
+        for session in schedule.sessions {
+            let key = format!("{}:{}", session.shard, session.cc_seqno);
+            Self::add_stats(&mut stats, key, serde_json::to_value(&session)?);
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Stat key is "<shard>:<cc_seqno>", value is json of the session counters, oldest session first
+    fn validator_sessions(&self) -> Result<Stats> {
+        let history = self.engine()?.validator_sessions_history()
+            .ok_or_else(|| error!("Validator sessions history is not available"))?;
+        let mut stats = Vec::new();
+        for session in history.sessions() {
+            let key = format!("{}:{}", session.shard, session.cc_seqno);
+            Self::add_stats(&mut stats, key, serde_json::to_value(&session)?);
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    async fn network_snapshot(&self) -> Result<Stats> {
+        let snapshot = self.engine()?.network_snapshot().await?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "data", hex::encode(&snapshot.data));
+        Self::add_stats(&mut stats, "signature", hex::encode(&snapshot.signature));
+        Self::add_stats(&mut stats, "public_key", hex::encode(&snapshot.public_key));
+        Ok(Stats { stats: stats.into() })
+    }
+
+    async fn key_rotation(&self) -> Result<Stats> {
+        let plan = self.engine()?.key_rotation_plan().await?;
+        let keys_json = |keys: &Option<RotationKeys>| match keys {
+            Some(keys) => serde_json::json!({
+                "key_id": hex::encode(keys.key_id),
+                "adnl_key_id": hex::encode(keys.adnl_key_id)
+            }),
+            None => serde_json::Value::Null
+        };
+        let old_keys = plan.state.old_keys.iter()
+            .map(|(key_id, election_id)| serde_json::json!({
+                "key_id": hex::encode(key_id),
+                "election_id": election_id
+            }))
+            .collect::<Vec<_>>();
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "phase", serde_json::json!(plan.state.phase.to_string()));
+        Self::add_stats(&mut stats, "election_id", plan.state.election_id);
+        Self::add_stats(&mut stats, "last_election_id", plan.state.last_election_id);
+        Self::add_stats(&mut stats, "new_keys", keys_json(&plan.state.new_keys));
+        Self::add_stats(&mut stats, "old_keys", serde_json::json!(old_keys));
+        Self::add_stats(&mut stats, "provided_keys", keys_json(&plan.state.provided_keys));
+        Self::add_stats(&mut stats, "next_window", serde_json::json!({
+            "election_id": plan.next_window.election_id,
+            "opens_at": plan.next_window.opens_at,
+            "closes_at": plan.next_window.closes_at
+        }));
+        Ok(Stats { stats: stats.into() })
+    }
+
+    async fn provide_rotation_keys(&self, keys: &str) -> Result<Stats> {
+        let parse = |hash: &str| -> Result<[u8; 32]> {
+            hex::decode(hash)?.as_slice().try_into()
+                .map_err(|_| error!("Key hash {} must be 32 bytes", hash))
+        };
+        let keys = match keys.split(':').collect::<Vec<_>>().as_slice() {
+            [key_id, adnl_key_id] => RotationKeys { key_id: parse(key_id)?, adnl_key_id: parse(adnl_key_id)? },
+            _ => fail!("Keys must be given as <hex key hash>:<hex adnl key hash>")
+        };
+        // Keys must be generated in the key ring beforehand
+        for key_id in [&keys.key_id, &keys.adnl_key_id] {
+            self.key_ring.find(key_id)?;
+        }
+        self.engine()?.provide_rotation_keys(keys).await?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "provided", true);
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Stat key is "<db>:<state key>", value is decoded block id or decoding error
+    fn list_node_state_keys(&self) -> Result<Stats> {
+        let engine = self.engine()?;
+        let mut stats = Vec::new();
+        for (db, entries) in [
+            ("full_node", engine.list_full_node_state_keys()?),
+            ("validator", engine.list_validator_state_keys()?)
+        ] {
+            for entry in entries {
+                let value = match &entry.id {
+                    Ok(id) => serde_json::json!({
+                        "workchain": id.shard().workchain_id(),
+                        "shard":     id.shard().to_string(),
+                        "seq_no":    id.seq_no(),
+                        "rh":        format!("{:x}", id.root_hash)
+                    }),
+                    Err(error) => serde_json::json!({ "error": error })
+                };
+                Self::add_stats(&mut stats, format!("{}:{}", db, entry.key), value);
+            }
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    fn reload_config(&self) -> Result<Stats> {
+        let report = self.engine()?.reload_config_from_file()?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "applied", serde_json::json!(report.applied));
+        Self::add_stats(&mut stats, "rejected", serde_json::json!(report.rejected));
+        Self::add_stats(&mut stats, "errors", serde_json::json!(report.errors));
+        Ok(Stats { stats: stats.into() })
+    }
+
+    async fn encrypt_keystore(&self) -> Result<Stats> {
+        let count = self.engine()?.encrypt_validator_keystore().await?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "keys", count);
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Stat key is the event number, events are in order of their appearance
+    fn trace_remp_message(&self, message_id: &str) -> Result<Stats> {
+        let message_id = UInt256::from_str(message_id)
+            .map_err(|e| error!("Invalid message id {}: {}", message_id, e))?;
+        let trace = self.engine()?.trace_remp_message(&message_id)?;
+        let mut stats = Vec::new();
+        for (i, event) in trace.iter().enumerate() {
+            let time = event.time.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+            let value = serde_json::json!({
+                "time_ms": time.as_millis() as u64,
+                "point":   format!("{:?}", event.point),
+                "details": event.details
+            });
+            Self::add_stats(&mut stats, i, value);
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    async fn block_provenance(&self, block_id: &str) -> Result<Stats> {
+        let block_id = BlockIdExt::from_str(block_id)
+            .map_err(|e| error!("Invalid block id {}: {}", block_id, e))?;
+        let provenance = self.engine()?.block_provenance(&block_id).await?
+            .ok_or_else(|| error!("No provenance of block {}", block_id))?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "created_by", format!("{:x}", provenance.created_by));
+        Self::add_stats(&mut stats, "catchain_seqno", provenance.catchain_seqno);
+        Self::add_stats(&mut stats, "validator_list_hash_short", provenance.validator_list_hash_short);
+        let signers = provenance.signers.iter().map(|id| format!("{:x}", id)).collect::<Vec<_>>();
+        Self::add_stats(&mut stats, "signers", serde_json::to_value(signers)?);
+        Ok(Stats { stats: stats.into() })
+    }
+
+    async fn account_state_proof(&self, address: &str) -> Result<Stats> {
+        let address: MsgAddressInt = address.parse()?;
+        let proof = self.engine()?
+            .generate_account_state_proof(address.workchain_id(), &address.address()).await?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "version", ACCOUNT_STATE_PROOF_VERSION);
+        Self::add_stats(&mut stats, "block_id", proof.block_id);
+        Self::add_stats(&mut stats, "state_root_hash", format!("{:x}", proof.state_root_hash));
+        Self::add_stats(&mut stats, "exists", proof.exists);
+        Self::add_stats(&mut stats, "state_proof", base64_encode(&proof.state_proof));
+        let proof_chain = proof.proof_chain.iter().map(base64_encode).collect::<Vec<_>>();
+        Self::add_stats(&mut stats, "proof_chain", serde_json::to_value(proof_chain)?);
+        Ok(Stats { stats: stats.into() })
+    }
+
+    async fn account_transactions(&self, query: &str) -> Result<Stats> {
+        let parts = query.split(':').collect::<Vec<_>>();
+        if parts.len() != 5 && parts.len() != 6 {
+            fail!("Invalid account transactions query {}", query)
+        }
+        let address: MsgAddressInt = format!("{}:{}", parts[0], parts[1]).parse()?;
+        let from_seq_no = parts[2].parse::<u32>()?;
+        let to_seq_no = parts[3].parse::<u32>()?;
+        let limit = parts[4].parse::<usize>()?;
+        if limit == 0 || limit > MAX_ACCOUNT_TRANSACTIONS_LIMIT {
+            fail!("Limit of account transactions must be 1..={}", MAX_ACCOUNT_TRANSACTIONS_LIMIT)
+        }
+        let continuation = parts.get(5).map(|continuation| continuation.parse()).transpose()?;
+        let engine = self.engine()?;
+        let (locations, continuation) = engine.account_transactions(
+            address.workchain_id(), &address.address(), from_seq_no, to_seq_no, limit, continuation
+        )?;
+        let mut transactions = Vec::with_capacity(locations.len());
+        for location in locations {
+            let boc = engine.load_account_transaction(&location).await?;
+            transactions.push(serde_json::json!({
+                "mc_seq_no": location.mc_seq_no,
+                "lt": location.lt,
+                "block_root_hash": format!("{:x}", location.block_root_hash),
+                "hash": format!("{:x}", location.tx_hash),
+                "boc": base64_encode(&boc),
+            }));
+        }
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "transactions", serde_json::Value::Array(transactions));
+        if let Some(continuation) = continuation {
+            Self::add_stats(&mut stats, "continuation", continuation.to_string());
+        }
         Ok(Stats { stats: stats.into() })
+    }
 
+    fn import_node_state(&self, data: &str, overwrite: bool) -> Result<Stats> {
+        let data = hex::decode(data).map_err(|e| error!("Invalid node state data: {}", e))?;
+        let entries = deserialize_node_state(&data)?;
+        let imported = self.engine()?.import_node_state(&entries, overwrite)?;
+        let mut stats = Vec::new();
+        Self::add_stats(&mut stats, "imported", imported);
+        Self::add_stats(&mut stats, "skipped", entries.len() - imported);
+        Ok(Stats { stats: stats.into() })
     }
 
     async fn process_generate_keypair(&self, key_type: i32) -> Result<KeyHash> {
@@ -568,13 +1030,76 @@ impl ControlQuerySubscriber {
         Ok(Success::Engine_Validator_Success)
     }
 
+    // Stat key is the record number for audit records (oldest first)
+    // and "denied:<key id>:<command>" for counters of denied attempts
+    fn control_audit(&self) -> Result<Stats> {
+        let mut stats = Vec::new();
+        for (i, record) in self.permissions.audit_log().iter().enumerate() {
+            let value = serde_json::json!({
+                "time":     record.time,
+                "key_id":   record.key_id,
+                "command":  record.command,
+                "category": format!("{:?}", record.category),
+                "allowed":  record.allowed
+            });
+            Self::add_stats(&mut stats, i, value);
+        }
+        for (key_id, command, count) in self.permissions.denied_counters() {
+            Self::add_stats(&mut stats, format!("denied:{}:{}", key_id, command), count);
+        }
+        Ok(Stats { stats: stats.into() })
+    }
+
+    // Filters of GetSelectedStats query run commands of different categories
+    fn stats_filter_command(filter: &str) -> (String, ControlCommandCategory) {
+        for (prefix, category) in [
+            (GC_TRIGGER_FILTER_PREFIX, ControlCommandCategory::Gc),
+            (GC_STATUS_FILTER_PREFIX, ControlCommandCategory::Gc),
+            (DETACHED_STATUS_FILTER_PREFIX, ControlCommandCategory::ReadOnly),
+            (NODE_STATE_OVERWRITE_FILTER_PREFIX, ControlCommandCategory::Admin),
+            (NODE_STATE_IMPORT_FILTER_PREFIX, ControlCommandCategory::Admin),
+            (REMP_TRACE_FILTER_PREFIX, ControlCommandCategory::ReadOnly),
+            (BLOCK_PROVENANCE_FILTER_PREFIX, ControlCommandCategory::ReadOnly),
+            (ACCOUNT_STATE_PROOF_FILTER_PREFIX, ControlCommandCategory::ReadOnly),
+            (ACCOUNT_TRANSACTIONS_FILTER_PREFIX, ControlCommandCategory::ReadOnly),
+            (VALIDATOR_SCHEDULE_FILTER_PREFIX, ControlCommandCategory::ReadOnly),
+            (KEY_ROTATION_KEYS_FILTER_PREFIX, ControlCommandCategory::ValidatorOps),
+        ] {
+            if filter.starts_with(prefix) {
+                return (format!("GetSelectedStats:{}", prefix.trim_end_matches(':')), category)
+            }
+        }
+        let category = match filter {
+            NODE_STATE_EXPORT_FILTER | NODE_STATE_KEYS_FILTER | DB_CONSISTENCY_CHECK_FILTER |
+            CONTROL_AUDIT_FILTER | STORAGE_USAGE_FILTER | VALIDATOR_SESSIONS_FILTER |
+            ARCHIVES_GC_STATUS_FILTER | NEIGHBOURS_QUALITY_FILTER | NODE_STATUS_FILTER |
+            EXT_MESSAGES_PREVALIDATION_FILTER | KEY_ROTATION_FILTER | NETWORK_SNAPSHOT_FILTER =>
+                ControlCommandCategory::ReadOnly,
+            CONFIG_RELOAD_FILTER | DB_CONSISTENCY_FIX_FILTER | KEYSTORE_ENCRYPT_FILTER =>
+                ControlCommandCategory::Admin,
+            _ => return ("GetSelectedStats".to_string(), ControlCommandCategory::ReadOnly)
+        };
+        (format!("GetSelectedStats:{}", filter), category)
+    }
+
+    // Permitted command gets its execution budget
+    fn admit(
+        &self,
+        peers: &AdnlPeers,
+        command: &str,
+        category: ControlCommandCategory
+    ) -> Result<ControlPermit> {
+        self.permissions.check(peers.other(), command, category)?;
+        self.limits.admit(command, category)
+    }
+
     fn set_states_gc_interval(&self, interval_ms: u32) -> Result<Success> {
         self.engine()?.adjust_states_gc_interval(interval_ms);
         self.config.store_states_gc_interval(interval_ms);
         Ok(Success::Engine_Validator_Success)
     }
 
-    async fn try_consume_query_impl(&self, object: TLObject, _peers: &AdnlPeers) -> Result<QueryResult> {
+    async fn try_consume_query_impl(&self, object: TLObject, peers: &AdnlPeers) -> Result<QueryResult> {
         log::debug!("recieve object (control server): {:?}", object);
         let query = match object.downcast::<ControlQuery>() {
             Ok(query) => deserialize_boxed(&query.data[..])?,
@@ -583,7 +1108,8 @@ impl ControlQuerySubscriber {
         log::debug!("query (control server): {:?}", query);
         let query = match query.downcast::<ton::rpc::raw::GetShardAccountState>() {
             Ok(account) => {
-                let answer = self.get_account_state(account.account_address).await?;
+                let permit = self.admit(peers, "GetShardAccountState", ControlCommandCategory::ReadOnly)?;
+                let answer = permit.run(self.get_account_state(account.account_address)).await?;
                 return QueryResult::consume_boxed(
                     answer,
                     #[cfg(feature = "telemetry")]
@@ -594,7 +1120,8 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::raw::GetShardAccountMeta>() {
             Ok(account) => {
-                let answer = self.get_account_meta(account.account_address).await?;
+                let permit = self.admit(peers, "GetShardAccountMeta", ControlCommandCategory::ReadOnly)?;
+                let answer = permit.run(self.get_account_meta(account.account_address)).await?;
                 return QueryResult::consume_boxed(
                     answer,
                     #[cfg(feature = "telemetry")]
@@ -605,7 +1132,10 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::raw::GetAccountByBlock>() {
             Ok(account) => {
-                let answer = self.get_account_by_block(account.account_id, account.block_root_hash).await?;
+                let permit = self.admit(peers, "GetAccountByBlock", ControlCommandCategory::ReadOnly)?;
+                let answer = permit.run(
+                    self.get_account_by_block(account.account_id, account.block_root_hash)
+                ).await?;
                 return QueryResult::consume_boxed(
                     answer,
                     #[cfg(feature = "telemetry")]
@@ -616,7 +1146,10 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::raw::GetAccountMetaByBlock>() {
             Ok(account) => {
-                let answer = self.get_account_meta_by_block(account.account_id, account.block_root_hash).await?;
+                let permit = self.admit(peers, "GetAccountMetaByBlock", ControlCommandCategory::ReadOnly)?;
+                let answer = permit.run(
+                    self.get_account_meta_by_block(account.account_id, account.block_root_hash)
+                ).await?;
                 return QueryResult::consume_boxed(
                     answer,
                     #[cfg(feature = "telemetry")]
@@ -627,7 +1160,8 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::raw::GetAppliedShardsInfo>() {
             Ok(_) => {
-                let answer = self.get_applied_shards_info().await?;
+                let permit = self.admit(peers, "GetAppliedShardsInfo", ControlCommandCategory::ReadOnly)?;
+                let answer = permit.run(self.get_applied_shards_info()).await?;
                 return QueryResult::consume_boxed(
                     answer,
                     #[cfg(feature = "telemetry")]
@@ -637,88 +1171,116 @@ impl ControlQuerySubscriber {
             Err(query) => query
         };
         let query = match query.downcast::<GenerateKeyPair>() {
-            Ok(_params) => return QueryResult::consume(
-                self.process_generate_keypair(Ed25519KeyOption::KEY_TYPE).await?,
-                #[cfg(feature = "telemetry")]
-                None
-            ),
+            Ok(_params) => {
+                let permit = self.admit(peers, "GenerateKeyPair", ControlCommandCategory::ValidatorOps)?;
+                return QueryResult::consume(
+                    permit.run(self.process_generate_keypair(Ed25519KeyOption::KEY_TYPE)).await?,
+                    #[cfg(feature = "telemetry")]
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<GenerateBlsKeyPair>() {
-            Ok(_params) => return QueryResult::consume(
-                self.process_generate_keypair(BlsKeyOption::KEY_TYPE).await?,
-                #[cfg(feature = "telemetry")]
-                None
-            ),
+            Ok(_params) => {
+                let permit = self.admit(peers, "GenerateBlsKeyPair", ControlCommandCategory::ValidatorOps)?;
+                return QueryResult::consume(
+                    permit.run(self.process_generate_keypair(BlsKeyOption::KEY_TYPE)).await?,
+                    #[cfg(feature = "telemetry")]
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<ExportPublicKey>() {
-            Ok(query) => return QueryResult::consume_boxed(
-                self.export_public_key(query.key_hash.as_slice())?,
-                #[cfg(feature = "telemetry")]
-                None
-            ),
+            Ok(query) => {
+                let _permit = self.admit(peers, "ExportPublicKey", ControlCommandCategory::ReadOnly)?;
+                return QueryResult::consume_boxed(
+                    self.export_public_key(query.key_hash.as_slice())?,
+                    #[cfg(feature = "telemetry")]
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<Sign>() {
-            Ok(query) => return QueryResult::consume(
-                self.process_sign_data(query.key_hash.as_slice(), &query.data)?,
-                #[cfg(feature = "telemetry")]
-                None
-            ),
+            Ok(query) => {
+                let _permit = self.admit(peers, "Sign", ControlCommandCategory::ValidatorOps)?;
+                return QueryResult::consume(
+                    self.process_sign_data(query.key_hash.as_slice(), &query.data)?,
+                    #[cfg(feature = "telemetry")]
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<AddValidatorPermanentKey>() {
-            Ok(query) => return QueryResult::consume_boxed(
-                self.add_validator_permanent_key(
-                    query.key_hash.as_slice(), query.election_date, query.ttl
-                ).await?,
-                #[cfg(feature = "telemetry")]
-                None
-            ),
+            Ok(query) => {
+                let permit = self.admit(peers, "AddValidatorPermanentKey", ControlCommandCategory::ValidatorOps)?;
+                return QueryResult::consume_boxed(
+                    permit.run(self.add_validator_permanent_key(
+                        query.key_hash.as_slice(), query.election_date, query.ttl
+                    )).await?,
+                    #[cfg(feature = "telemetry")]
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<AddValidatorTempKey>() {
-            Ok(query) => return QueryResult::consume_boxed(
-                self.add_validator_temp_key(
-                    query.permanent_key_hash.as_slice(), query.key_hash.as_slice(), query.ttl
-                )?,
-                #[cfg(feature = "telemetry")]
-                None
-            ),
+            Ok(query) => {
+                let _permit = self.admit(peers, "AddValidatorTempKey", ControlCommandCategory::ValidatorOps)?;
+                return QueryResult::consume_boxed(
+                    self.add_validator_temp_key(
+                        query.permanent_key_hash.as_slice(), query.key_hash.as_slice(), query.ttl
+                    )?,
+                    #[cfg(feature = "telemetry")]
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<AddValidatorAdnlAddress>() {
-            Ok(query) => return QueryResult::consume_boxed(
-                self.add_validator_adnl_address(
-                    query.permanent_key_hash.as_slice(), query.key_hash.as_slice(), query.ttl
-                ).await?,
-                #[cfg(feature = "telemetry")]
-                None
-            ),
+            Ok(query) => {
+                let permit = self.admit(peers, "AddValidatorAdnlAddress", ControlCommandCategory::ValidatorOps)?;
+                return QueryResult::consume_boxed(
+                    permit.run(self.add_validator_adnl_address(
+                        query.permanent_key_hash.as_slice(), query.key_hash.as_slice(), query.ttl
+                    )).await?,
+                    #[cfg(feature = "telemetry")]
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<AddValidatorBlsKey>() {
-            Ok(query) => return QueryResult::consume_boxed(
-                self.add_validator_bls_key(
-                    query.permanent_key_hash.as_slice(), query.key_hash.as_slice(), query.ttl
-                ).await?,
-                None
-            ),
+            Ok(query) => {
+                let permit = self.admit(peers, "AddValidatorBlsKey", ControlCommandCategory::ValidatorOps)?;
+                return QueryResult::consume_boxed(
+                    permit.run(self.add_validator_bls_key(
+                        query.permanent_key_hash.as_slice(), query.key_hash.as_slice(), query.ttl
+                    )).await?,
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<AddAdnlId>() {
-            Ok(query) => return QueryResult::consume_boxed(
-                self.add_adnl_address(query.key_hash.as_slice(), query.category)?,
-                #[cfg(feature = "telemetry")]
-                None
-            ),
+            Ok(query) => {
+                let _permit = self.admit(peers, "AddAdnlId", ControlCommandCategory::ValidatorOps)?;
+                return QueryResult::consume_boxed(
+                    self.add_adnl_address(query.key_hash.as_slice(), query.category)?,
+                    #[cfg(feature = "telemetry")]
+                    None
+                )
+            },
             Err(query) => query
         };
         let query = match query.downcast::<GetBundle>() {
             Ok(query) => {
+                let permit = self.admit(peers, "GetBundle", ControlCommandCategory::Admin)?;
                 return QueryResult::consume_boxed(
-                    self.prepare_bundle(query.block_id.clone()).await?,
+                    permit.run(self.prepare_bundle(query.block_id.clone())).await?,
                 #[cfg(feature = "telemetry")]
                 None
                 )
@@ -727,11 +1289,12 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<GetFutureBundle>() {
             Ok(query) => {
+                let permit = self.admit(peers, "GetFutureBundle", ControlCommandCategory::Admin)?;
                 let prev_block_ids = query.prev_block_ids.iter().map(
                     |id| id.clone()
                 ).collect();
                 return QueryResult::consume_boxed(
-                    self.prepare_future_bundle(prev_block_ids).await?,
+                    permit.run(self.prepare_future_bundle(prev_block_ids)).await?,
                     #[cfg(feature = "telemetry")]
                     None
                 )
@@ -740,9 +1303,10 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::lite_server::SendMessage>() {
             Ok(query) => {
+                let permit = self.admit(peers, "SendMessage", ControlCommandCategory::ValidatorOps)?;
                 let message_data = query.body;
                 return QueryResult::consume_boxed(
-                    self.redirect_external_message(&message_data).await?,
+                    permit.run(self.redirect_external_message(&message_data)).await?,
                     #[cfg(feature = "telemetry")]
                     None
                 )
@@ -751,8 +1315,9 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::lite_server::GetConfigParams>() {
             Ok(query) => {
+                let permit = self.admit(peers, "GetConfigParams", ControlCommandCategory::ReadOnly)?;
                 let param_number = query.param_list.iter().next().ok_or_else(|| error!("Invalid param_number"))?;
-                let answer = self.get_config_params(*param_number as u32).await?;
+                let answer = permit.run(self.get_config_params(*param_number as u32)).await?;
 
                 return QueryResult::consume_boxed(
                     answer.into_boxed(),
@@ -764,7 +1329,8 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::lite_server::GetConfigAll>() {
             Ok(_) => {
-                let answer = self.get_all_config_params().await?;
+                let permit = self.admit(peers, "GetConfigAll", ControlCommandCategory::ReadOnly)?;
+                let answer = permit.run(self.get_all_config_params()).await?;
                 return QueryResult::consume_boxed(
                     answer.into_boxed(),
                     #[cfg(feature = "telemetry")]
@@ -775,7 +1341,8 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::engine::validator::GetStats>() {
             Ok(_) => {
-                let answer = self.get_selected_stats(None).await?;
+                let permit = self.admit(peers, "GetStats", ControlCommandCategory::ReadOnly)?;
+                let answer = permit.run(self.get_selected_stats(None)).await?;
                 return QueryResult::consume_boxed(
                     answer.into_boxed(),
                     #[cfg(feature = "telemetry")]
@@ -786,7 +1353,51 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::engine::validator::GetSelectedStats>() {
             Ok(get_stats) => {
-                let answer = self.get_selected_stats(Some(&get_stats.filter)).await?;
+                let filter = get_stats.filter.as_str();
+                let (command, category) = Self::stats_filter_command(filter);
+                let permit = self.admit(peers, &command, category)?;
+                let answer = if let Some(kind) = filter.strip_prefix(GC_TRIGGER_FILTER_PREFIX) {
+                    self.trigger_gc(kind)?
+                } else if let Some(ticket) = filter.strip_prefix(GC_STATUS_FILTER_PREFIX) {
+                    self.gc_status(ticket)?
+                } else if let Some(ticket) = filter.strip_prefix(DETACHED_STATUS_FILTER_PREFIX) {
+                    self.detached_status(ticket)?
+                } else if let Some(data) = filter.strip_prefix(NODE_STATE_OVERWRITE_FILTER_PREFIX) {
+                    self.import_node_state(data, true)?
+                } else if let Some(data) = filter.strip_prefix(NODE_STATE_IMPORT_FILTER_PREFIX) {
+                    self.import_node_state(data, false)?
+                } else if let Some(id) = filter.strip_prefix(REMP_TRACE_FILTER_PREFIX) {
+                    self.trace_remp_message(id)?
+                } else if let Some(id) = filter.strip_prefix(BLOCK_PROVENANCE_FILTER_PREFIX) {
+                    permit.run(self.block_provenance(id)).await?
+                } else if let Some(address) = filter.strip_prefix(ACCOUNT_STATE_PROOF_FILTER_PREFIX) {
+                    permit.run(self.account_state_proof(address)).await?
+                } else if let Some(query) = filter.strip_prefix(ACCOUNT_TRANSACTIONS_FILTER_PREFIX) {
+                    permit.run(self.account_transactions(query)).await?
+                } else if let Some(lookahead) = filter.strip_prefix(VALIDATOR_SCHEDULE_FILTER_PREFIX) {
+                    permit.run(self.validator_schedule(lookahead)).await?
+                } else if let Some(keys) = filter.strip_prefix(KEY_ROTATION_KEYS_FILTER_PREFIX) {
+                    permit.run(self.provide_rotation_keys(keys)).await?
+                } else {
+                    match filter {
+                        NODE_STATE_EXPORT_FILTER => permit.run(self.export_node_state()).await?,
+                        NODE_STATE_KEYS_FILTER => self.list_node_state_keys()?,
+                        CONFIG_RELOAD_FILTER => self.reload_config()?,
+                        KEYSTORE_ENCRYPT_FILTER => permit.run(self.encrypt_keystore()).await?,
+                        CONTROL_AUDIT_FILTER => self.control_audit()?,
+                        DB_CONSISTENCY_CHECK_FILTER => self.check_db_consistency(filter, RepairMode::DryRun)?,
+                        DB_CONSISTENCY_FIX_FILTER => self.check_db_consistency(filter, RepairMode::Fix)?,
+                        STORAGE_USAGE_FILTER => permit.run(self.storage_usage_report()).await?,
+                        NODE_STATUS_FILTER => permit.run(self.node_status()).await?,
+                        ARCHIVES_GC_STATUS_FILTER => self.archives_gc_status()?,
+                        NEIGHBOURS_QUALITY_FILTER => self.neighbours_quality()?,
+                        EXT_MESSAGES_PREVALIDATION_FILTER => self.ext_messages_prevalidation()?,
+                        VALIDATOR_SESSIONS_FILTER => self.validator_sessions()?,
+                        KEY_ROTATION_FILTER => permit.run(self.key_rotation()).await?,
+                        NETWORK_SNAPSHOT_FILTER => permit.run(self.network_snapshot()).await?,
+                        filter => permit.run(self.get_selected_stats(Some(filter))).await?
+                    }
+                };
                 return QueryResult::consume_boxed(
                     answer.into_boxed(),
                     #[cfg(feature = "telemetry")]
@@ -797,6 +1408,7 @@ impl ControlQuerySubscriber {
         };
         let query = match query.downcast::<ton::rpc::engine::validator::SetStatesGcInterval>() {
             Ok(query) => {
+                let _permit = self.admit(peers, "SetStatesGcInterval", ControlCommandCategory::Gc)?;
                 return QueryResult::consume_boxed(
                     self.set_states_gc_interval(query.interval_ms as u32)?,
                     #[cfg(feature = "telemetry")]
@@ -819,7 +1431,12 @@ impl Subscriber for ControlQuerySubscriber {
             Err(err) => QueryResult::consume_boxed(
                 ton::engine::validator::ControlQueryError::Engine_Validator_ControlQueryError(
                     ton::engine::validator::controlqueryerror::ControlQueryError {
-                        code: -1,
+                        code: match err.downcast_ref::<NodeError>() {
+                            Some(NodeError::PermissionDenied { .. }) => PERMISSION_DENIED_ERROR_CODE,
+                            Some(NodeError::ControlBusy { .. }) => BUSY_ERROR_CODE,
+                            Some(NodeError::ControlTimeout { .. }) => TIMEOUT_ERROR_CODE,
+                            _ => -1
+                        },
                         message: err.to_string()
                     }
                 ),
@@ -0,0 +1,223 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{error::NodeError, network::control_permissions::ControlCommandCategory};
+use ever_block::{error, fail, Result};
+use std::{
+    collections::{BTreeMap, HashMap}, future::Future,
+    sync::{Arc, atomic::{AtomicU64, Ordering}}, time::Duration
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use ton_api::ton::engine::validator::stats::Stats;
+
+#[cfg(test)]
+#[path = "tests/test_control_limits.rs"]
+mod tests;
+
+// Count of finished detached commands whose results are kept
+pub const DETACHED_RESULTS_LEN: usize = 16;
+
+// Execution budget of control commands of one category
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ControlBudget {
+    // Command is aborted after this time, zero disables the timeout
+    pub timeout_ms: u64,
+    // More commands are refused as busy
+    pub max_concurrent: usize,
+}
+
+impl ControlBudget {
+    pub fn default_for(category: ControlCommandCategory) -> Self {
+        let (timeout_ms, max_concurrent) = match category {
+            ControlCommandCategory::ReadOnly => (30_000, 16),
+            ControlCommandCategory::Gc => (10_000, 2),
+            ControlCommandCategory::ValidatorOps => (30_000, 4),
+            ControlCommandCategory::Admin => (120_000, 2),
+        };
+        Self { timeout_ms, max_concurrent }
+    }
+}
+
+impl Default for ControlBudget {
+    fn default() -> Self {
+        Self::default_for(ControlCommandCategory::ReadOnly)
+    }
+}
+
+// Budgets by category, categories not mentioned get default budgets
+pub type ControlLimitsConfig = BTreeMap<ControlCommandCategory, ControlBudget>;
+
+struct CategoryLimit {
+    budget: ControlBudget,
+    semaphore: Arc<Semaphore>,
+}
+
+// Limits of control commands execution, so a heavy command can't hold the control server
+pub struct ControlLimits {
+    limits: HashMap<ControlCommandCategory, CategoryLimit>,
+}
+
+impl ControlLimits {
+
+    pub fn new(config: Option<&ControlLimitsConfig>) -> Result<Self> {
+        let mut limits = HashMap::new();
+        for category in [
+            ControlCommandCategory::ReadOnly,
+            ControlCommandCategory::Gc,
+            ControlCommandCategory::ValidatorOps,
+            ControlCommandCategory::Admin,
+        ] {
+            let budget = config.and_then(|config| config.get(&category)).cloned()
+                .unwrap_or_else(|| ControlBudget::default_for(category));
+            if budget.max_concurrent == 0 {
+                fail!("Control limit of concurrent {:?} commands must be positive", category)
+            }
+            let semaphore = Arc::new(Semaphore::new(budget.max_concurrent));
+            limits.insert(category, CategoryLimit { budget, semaphore });
+        }
+        Ok(Self { limits })
+    }
+
+    // Permit is held while the command runs
+    pub fn admit(&self, command: &str, category: ControlCommandCategory) -> Result<ControlPermit> {
+        let limit = self.limits.get(&category)
+            .ok_or_else(|| error!("INTERNAL ERROR: no control limit for {:?}", category))?;
+        let permit = limit.semaphore.clone().try_acquire_owned().map_err(|_| {
+            log::warn!("Control command {} ({:?}) is refused: too many running", command, category);
+            metrics::counter!("control_busy_commands", 1, "command" => command.to_string());
+            error!(NodeError::ControlBusy {
+                command: command.to_string(),
+                category: format!("{:?}", category),
+                running: limit.budget.max_concurrent
+            })
+        })?;
+        Ok(ControlPermit {
+            _permit: permit,
+            command: command.to_string(),
+            timeout_ms: limit.budget.timeout_ms,
+        })
+    }
+}
+
+pub struct ControlPermit {
+    _permit: OwnedSemaphorePermit,
+    command: String,
+    timeout_ms: u64,
+}
+
+impl ControlPermit {
+    // Work is dropped on timeout, so nothing is held after the error is answered
+    pub async fn run<T>(&self, work: impl Future<Output = Result<T>>) -> Result<T> {
+        if self.timeout_ms == 0 {
+            return work.await
+        }
+        match tokio::time::timeout(Duration::from_millis(self.timeout_ms), work).await {
+            Ok(result) => result,
+            Err(_) => {
+                let command = self.command.clone();
+                log::warn!("Control command {} is aborted after {} ms", command, self.timeout_ms);
+                metrics::counter!("control_timed_out_commands", 1, "command" => command.clone());
+                fail!(NodeError::ControlTimeout { command, timeout_ms: self.timeout_ms })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum DetachedStatus {
+    Running,
+    Finished(Stats),
+    Failed(String),
+}
+
+struct DetachedCommand {
+    command: String,
+    status: parking_lot::Mutex<DetachedStatus>,
+}
+
+impl DetachedCommand {
+    fn is_running(&self) -> bool {
+        matches!(*self.status.lock(), DetachedStatus::Running)
+    }
+}
+
+// Long-running maintenance commands, each one in a dedicated task. Only one task
+// of each command may run at a time, results are kept for the latest finished ones.
+#[derive(Default)]
+pub struct DetachedCommands {
+    commands: parking_lot::Mutex<BTreeMap<u64, Arc<DetachedCommand>>>,
+    next_id: AtomicU64,
+}
+
+impl DetachedCommands {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts the work, or returns the ticket of the same command in flight
+    pub fn spawn<F>(&self, command: &str, work: F) -> u64
+    where
+        F: Future<Output = Result<Stats>> + Send + 'static
+    {
+        let mut commands = self.commands.lock();
+        for (ticket, running) in commands.iter() {
+            if running.command == command && running.is_running() {
+                log::info!("Control command {} is already running, ticket {}", command, ticket);
+                return *ticket
+            }
+        }
+        let finished = commands.values()
+            .filter(|detached| !detached.is_running())
+            .count();
+        if finished >= DETACHED_RESULTS_LEN {
+            let oldest = commands.iter()
+                .find(|(_, detached)| !detached.is_running())
+                .map(|(ticket, _)| *ticket);
+            if let Some(oldest) = oldest {
+                commands.remove(&oldest);
+            }
+        }
+        let ticket = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let detached = Arc::new(DetachedCommand {
+            command: command.to_string(),
+            status: parking_lot::Mutex::new(DetachedStatus::Running),
+        });
+        commands.insert(ticket, detached.clone());
+        log::info!("Control command {} started, ticket {}", command, ticket);
+        tokio::spawn(async move {
+            let status = match work.await {
+                Ok(stats) => {
+                    log::info!("Control command {} finished, ticket {}", detached.command, ticket);
+                    DetachedStatus::Finished(stats)
+                }
+                Err(e) => {
+                    let command = &detached.command;
+                    log::error!("Control command {} failed, ticket {}: {}", command, ticket, e);
+                    DetachedStatus::Failed(e.to_string())
+                }
+            };
+            *detached.status.lock() = status;
+        });
+        ticket
+    }
+
+    // Command name and its status
+    pub fn status(&self, ticket: u64) -> Result<(String, DetachedStatus)> {
+        match self.commands.lock().get(&ticket) {
+            Some(detached) => Ok((detached.command.clone(), detached.status.lock().clone())),
+            None => fail!("Unknown or outdated control command ticket {}", ticket)
+        }
+    }
+}
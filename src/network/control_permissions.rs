@@ -0,0 +1,165 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::error::NodeError;
+use ever_block::{base64_decode, error, fail, Ed25519KeyOption, KeyId, Result};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, VecDeque}, sync::Arc};
+
+#[cfg(test)]
+#[path = "tests/test_control_permissions.rs"]
+mod tests;
+
+// Count of the latest audited commands kept in memory
+pub const CONTROL_AUDIT_LOG_LEN: usize = 256;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ControlCommandCategory {
+    // Stats, accounts, config params, node states export
+    ReadOnly,
+    // Manual GC and GC tuning
+    Gc,
+    // Validator keys, signing, external messages
+    ValidatorOps,
+    // Node states import, config reload, DB repair, collator bundles.
+    // Key with this category may run commands of any category.
+    Admin,
+}
+
+// Allowed categories by base64 public key of control client, as in control server clients list
+pub type ControlPermissionsConfig = BTreeMap<String, BTreeSet<ControlCommandCategory>>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControlAuditRecord {
+    pub time: u64,
+    pub key_id: String,
+    pub command: String,
+    pub category: ControlCommandCategory,
+    pub allowed: bool,
+}
+
+#[derive(Default)]
+struct ControlAudit {
+    log: VecDeque<ControlAuditRecord>,
+    // Denied attempts by key id and command
+    denied: BTreeMap<(String, String), u64>,
+}
+
+// Permissions of control clients. Without config every client authorized by control server
+// may run any command, with config the clients not mentioned there may run nothing.
+// Denied attempts and executed commands other than read-only ones are audited.
+pub struct ControlPermissions {
+    keys: parking_lot::RwLock<Option<HashMap<Arc<KeyId>, BTreeSet<ControlCommandCategory>>>>,
+    audit: parking_lot::Mutex<ControlAudit>,
+    // Node runs on read-only DB, only read-only commands are allowed to anybody
+    read_only: bool,
+}
+
+impl ControlPermissions {
+
+    pub fn new(config: Option<&ControlPermissionsConfig>) -> Result<Self> {
+        Ok(Self {
+            keys: parking_lot::RwLock::new(Self::parse(config)?),
+            audit: parking_lot::Mutex::new(ControlAudit::default()),
+            read_only: false,
+        })
+    }
+
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    // Permissions are replaced as a whole, invalid config leaves old ones
+    pub fn update(&self, config: Option<&ControlPermissionsConfig>) -> Result<()> {
+        let keys = Self::parse(config)?;
+        *self.keys.write() = keys;
+        Ok(())
+    }
+
+    pub fn check(&self, key_id: &KeyId, command: &str, category: ControlCommandCategory) -> Result<()> {
+        let allowed = match &*self.keys.read() {
+            None => true,
+            Some(keys) => keys.get(key_id).map_or(false, |categories| {
+                categories.contains(&category) || categories.contains(&ControlCommandCategory::Admin)
+            })
+        };
+        if allowed && category == ControlCommandCategory::ReadOnly {
+            return Ok(())
+        }
+        if allowed && self.read_only {
+            log::warn!("Control command {} ({:?}) is refused in read-only mode", command, category);
+            fail!(NodeError::ReadOnlyMode { command: command.to_string() })
+        }
+        let record = ControlAuditRecord {
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs(),
+            key_id: key_id.to_string(),
+            command: command.to_string(),
+            category,
+            allowed,
+        };
+        let mut audit = self.audit.lock();
+        if !allowed {
+            log::warn!("Control command {} ({:?}) is denied for key {}", command, category, key_id);
+            *audit.denied.entry((record.key_id.clone(), record.command.clone())).or_default() += 1;
+            metrics::counter!(
+                "control_denied_commands", 1,
+                "key" => record.key_id.clone(), "command" => record.command.clone()
+            );
+        }
+        if audit.log.len() >= CONTROL_AUDIT_LOG_LEN {
+            audit.log.pop_front();
+        }
+        audit.log.push_back(record);
+        drop(audit);
+        if !allowed {
+            fail!(NodeError::PermissionDenied { key_id: key_id.to_string(), command: command.to_string() })
+        }
+        Ok(())
+    }
+
+    // Oldest records go first
+    pub fn audit_log(&self) -> Vec<ControlAuditRecord> {
+        self.audit.lock().log.iter().cloned().collect()
+    }
+
+    pub fn denied_count(&self, key_id: &KeyId, command: &str) -> u64 {
+        let audit = self.audit.lock();
+        audit.denied.get(&(key_id.to_string(), command.to_string())).copied().unwrap_or_default()
+    }
+
+    // Denied attempts as (key id, command, count)
+    pub fn denied_counters(&self) -> Vec<(String, String, u64)> {
+        self.audit.lock().denied.iter()
+            .map(|((key_id, command), count)| (key_id.clone(), command.clone(), *count))
+            .collect()
+    }
+
+    fn parse(
+        config: Option<&ControlPermissionsConfig>
+    ) -> Result<Option<HashMap<Arc<KeyId>, BTreeSet<ControlCommandCategory>>>> {
+        let Some(config) = config else {
+            return Ok(None)
+        };
+        let mut keys = HashMap::new();
+        for (pub_key, categories) in config {
+            let data = base64_decode(pub_key)
+                .map_err(|e| error!("Bad public key {} in control permissions: {}", pub_key, e))?;
+            let data: &[u8; 32] = data.as_slice().try_into()
+                .map_err(|_| error!("Bad public key {} in control permissions: wrong length", pub_key))?;
+            let key_id = Ed25519KeyOption::from_public_key(data).id().clone();
+            keys.insert(key_id, categories.clone());
+        }
+        Ok(Some(keys))
+    }
+}
@@ -12,10 +12,13 @@
 */
 
 use crate::{
-    block::BlockStuff, block_proof::BlockProofStuff,
+    block::BlockStuff, block_proof::BlockProofStuff, error::NodeError,
     network::{
+        block_range::{unpack_block_range, BlockRangeQuery, DownloadedBlockRange},
+        broadcast_fanout::FanoutTransport,
+        compression::decompress_block,
         neighbours::{
-            Neighbours, Neighbour, 
+            Neighbours, Neighbour, CAPABILITY_BLOCK_RANGES,
             UPDATE_FLAG_IS_REGISTER, UPDATE_FLAG_IS_REG_IN_COMMON_STAT, UPDATE_FLAG_IS_RDPL
         },
         node_network::NetworkContext
@@ -29,7 +32,6 @@ use adnl::{
     node::AdnlNode
 };
 use adnl::{BroadcastSendInfo, OverlayShortId, OverlayNode};
-use rand::seq::SliceRandom;
 use std::{io::Cursor, time::Instant, sync::Arc, time::Duration, collections::HashSet};
 #[cfg(feature = "telemetry")]
 use std::sync::atomic::Ordering;
@@ -45,7 +47,8 @@ use ton_api::{
             PrepareZeroState, GetNextKeyBlockIds, GetArchiveInfo, GetArchiveSlice,
             PrepareBlockProof, PrepareKeyBlockProof, PrepareQueueUpdate, DownloadQueueUpdate,
             PreparePersistentMsgQueue, DownloadPersistentMsgQueueSlice,
-            DownloadMeshUpdate, DownloadLatestMeshKit, DownloadNextMeshUpdate, DownloadMeshKit
+            DownloadMeshUpdate, DownloadLatestMeshKit, DownloadNextMeshUpdate, DownloadMeshKit,
+            DownloadBlocks
         },
         ton_node::{ 
             ArchiveInfo, Broadcast,
@@ -66,7 +69,7 @@ use ever_block::{fail, KeyId, Result};
 #[async_trait::async_trait]
 pub trait FullNodeOverlayClient : Sync + Send {
     async fn broadcast_external_message(&self, msg: &[u8]) -> Result<BroadcastSendInfo>;
-    async fn send_block_broadcast(&self, broadcast: BlockBroadcast) -> Result<()>;
+    async fn send_block_broadcast(&self, broadcast: BlockBroadcast, is_key_block: bool) -> Result<()>;
     async fn send_queue_update_broadcast(&self, broadcast: QueueUpdateBroadcast) -> Result<()>;
     async fn send_mesh_update_broadcast(&self, broadcast: MeshUpdateBroadcast) -> Result<()>;
     async fn send_top_shard_block_description(&self, tbd: &TopBlockDescrStuff) -> Result<()>;
@@ -105,6 +108,7 @@ pub trait FullNodeOverlayClient : Sync + Send {
         max_size: i32
     ) -> Result<Vec<BlockIdExt>>;
     async fn download_next_block_full(&self, prev_id: &BlockIdExt) -> Result<(BlockStuff, BlockProofStuff)>;
+    async fn download_block_range(&self, query: &BlockRangeQuery) -> Result<DownloadedBlockRange>;
     async fn download_archive(
         &self,
         mc_seq_no: u32,
@@ -167,6 +171,8 @@ declare_counted!(
         #[cfg(feature = "telemetry")]
         tag_download_next_block_full: u32,
         #[cfg(feature = "telemetry")]
+        tag_download_blocks: u32,
+        #[cfg(feature = "telemetry")]
         tag_download_persistent_state_slice: u32,
         #[cfg(feature = "telemetry")]
         tag_download_persistent_msg_queue_slice: u32,
@@ -210,6 +216,7 @@ declare_counted!(
 impl NodeClientOverlay {
 
     const ADNL_ATTEMPTS: u32 = 50;
+    const DOWNLOAD_CANDIDATES: usize = 3;
     const TIMEOUT_PREPARE: u64 = 6000; // Milliseconds
     const TIMEOUT_DELTA: u64 = 50; // Milliseconds
     const TIMEOUT_NO_NEIGHBOURS: u64 = 1000; // Milliseconds
@@ -246,6 +253,8 @@ impl NodeClientOverlay {
             #[cfg(feature = "telemetry")]
             tag_download_next_block_full: tag_from_boxed_type::<DownloadNextBlockFull>(),
             #[cfg(feature = "telemetry")]
+            tag_download_blocks: tag_from_boxed_type::<DownloadBlocks>(),
+            #[cfg(feature = "telemetry")]
             tag_download_persistent_state_slice:
                 tag_from_boxed_type::<DownloadPersistentStateSlice>(),
             #[cfg(feature = "telemetry")]
@@ -306,6 +315,55 @@ impl NodeClientOverlay {
         &self.peers
     }
 
+    // Neighbour which gave us a block doesn't need its broadcast
+    fn record_known_block(&self, result: &Result<(BlockStuff, BlockProofStuff)>, peer: &Neighbour) {
+        if let (Some(fanout), Ok((block, _))) = (&self.network_context.broadcast_fanout, result) {
+            fanout.record_known(block.id(), peer.id())
+        }
+    }
+
+    // Few neighbours are chosen as usual, the best of them by download score is used
+    fn choose_download_peer(&self) -> Result<Option<Arc<Neighbour>>> {
+        self.choose_download_peer_with(None)
+    }
+
+    // Same, but only neighbours announced the capability are taken if it is set
+    fn choose_download_peer_with(&self, capability: Option<i64>) -> Result<Option<Arc<Neighbour>>> {
+        let mut candidates: Vec<Arc<Neighbour>> = Vec::new();
+        for _ in 0..Self::DOWNLOAD_CANDIDATES * 2 {
+            if let Some(peer) = self.peers.choose_neighbour()? {
+                if capability.map_or(false, |capability| !peer.has_capability(capability)) {
+                    continue
+                }
+                if candidates.iter().all(|c| c.id() != peer.id()) {
+                    candidates.push(peer);
+                    if candidates.len() >= Self::DOWNLOAD_CANDIDATES {
+                        break
+                    }
+                }
+            } else {
+                break
+            }
+        }
+        let ids = candidates.iter().map(|c| c.id().clone()).collect::<Vec<_>>();
+        Ok(self.network_context.peer_scoring.choose(&ids).map(|i| candidates.swap_remove(i)))
+    }
+
+    fn score_download<T>(&self, peer: &Arc<Neighbour>, started: Instant, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.network_context.peer_scoring.record_success(peer.id(), started.elapsed()),
+            Err(e) if NodeError::is_queue_update_mismatch(e) => {
+                log::warn!("Download from {} is rejected: {}", peer.id(), e);
+                self.network_context.peer_scoring.quarantine(peer.id())
+            }
+            Err(e) => {
+                log::debug!("Download from {} failed: {}", peer.id(), e);
+                self.network_context.peer_scoring.record_failure(peer.id())
+            }
+        }
+        result
+    }
+
     async fn send_adnl_query_to_peer<R, D>(
         &self,
         peer: &Arc<Neighbour>,
@@ -403,10 +461,10 @@ impl NodeClientOverlay {
         };
         match self.send_adnl_query_to_peer::<R, D>(&peer, data, timeout).await {
             Ok(Some(answer)) => Ok((answer, peer)),
-            Ok(None) => fail!(
+            Ok(None) => fail!(NodeError::NetworkTimeout(format!(
                 "Cannot send query {:?} to peer {}: no reply", 
                 data.object, peer.id()
-            ),
+            ))),
             Err(e) => fail!(
                 "Cannot send query {:?} to peer {}: {}", 
                 data.object, peer.id(), e
@@ -452,7 +510,7 @@ impl NodeClientOverlay {
         let mut all_peers = self.peers.all_peers().iter()
             .map(|peer| peer.clone())
             .collect::<Vec<_>>();
-        all_peers.shuffle(&mut rand::thread_rng());
+        self.network_context.peer_scoring.rank(&mut all_peers);
         for peer in all_peers.iter() {
             if let Some(active_peers) = active_peers {
                 if active_peers.contains(peer) {
@@ -499,7 +557,7 @@ impl NodeClientOverlay {
         let attempts = attempts.unwrap_or(Self::ADNL_ATTEMPTS);
 
         for _ in 0..attempts {
-            let peer = if let Some(p) = self.peers.choose_neighbour()? {
+            let peer = if let Some(p) = self.choose_download_peer()? {
                 p
             } else {
                 tokio::time::sleep(Duration::from_millis(Self::TIMEOUT_NO_NEIGHBOURS)).await;
@@ -518,13 +576,18 @@ impl NodeClientOverlay {
                     return Err(e)
                 },
                 Ok(Some(answer)) => return Ok((answer, peer)),
-                Ok(None) => if let Some(active_peers) = active_peers {
-                    active_peers.remove(peer.id());
+                Ok(None) => {
+                    self.network_context.peer_scoring.record_failure(peer.id());
+                    if let Some(active_peers) = active_peers {
+                        active_peers.remove(peer.id());
+                    }
                 }
             }
         }
 
-        fail!("Cannot send query {:?} in {} attempts", data.object, attempts)
+        fail!(NodeError::NetworkTimeout(
+            format!("Cannot send query {:?} in {} attempts", data.object, attempts)
+        ))
 
     }
 
@@ -620,13 +683,49 @@ impl NodeClientOverlay {
                 roundtrip, 
                 UPDATE_FLAG_IS_RDPL | UPDATE_FLAG_IS_REGISTER | UPDATE_FLAG_IS_REG_IN_COMMON_STAT
             );
-            fail!("No RLDP answer to {:?} from {}", request.object, peer.id())
+            fail!(NodeError::NetworkTimeout(
+                format!("No RLDP answer to {:?} from {}", request.object, peer.id())
+            ))
         }
 
     }
 
 }
 
+#[async_trait::async_trait]
+impl FanoutTransport for NodeClientOverlay {
+
+    fn neighbours(&self) -> Vec<Arc<KeyId>> {
+        self.peers.active_ids()
+    }
+
+    async fn broadcast_all(&self, data: &[u8]) -> Result<u32> {
+        let broadcast = TaggedByteSlice {
+            object: data,
+            #[cfg(feature = "telemetry")]
+            tag: self.tag_block_broadcast
+        };
+        let info = self.network_context.overlay.broadcast(
+            &self.overlay_id,
+            &broadcast,
+            None,
+            false
+        ).await?;
+        Ok(info.send_to)
+    }
+
+    // Received by `FullNodeOverlayService` as a custom message
+    async fn send_to(&self, peer: &Arc<KeyId>, data: &[u8]) -> Result<()> {
+        let message = TaggedByteSlice {
+            object: data,
+            #[cfg(feature = "telemetry")]
+            tag: self.tag_block_broadcast
+        };
+        self.network_context.overlay.message(peer, &message, &self.overlay_id).await
+    }
+
+}
+
 #[async_trait::async_trait]
 impl FullNodeOverlayClient for NodeClientOverlay {
 
@@ -649,22 +748,22 @@ impl FullNodeOverlayClient for NodeClientOverlay {
         ).await
     }
 
-    async fn send_block_broadcast(&self, broadcast: BlockBroadcast) -> Result<()> {
+    async fn send_block_broadcast(&self, broadcast: BlockBroadcast, is_key_block: bool) -> Result<()> {
         let id = broadcast.id.clone();
-        let broadcast = TaggedByteSlice {
-            object: &serialize_boxed(&broadcast.into_boxed())?,
-            #[cfg(feature = "telemetry")]
-            tag: self.tag_block_broadcast
+        let data = serialize_boxed(&broadcast.into_boxed())?;
+        let send_to = match &self.network_context.broadcast_fanout {
+            Some(fanout) => fanout.send(
+                self,
+                &id,
+                is_key_block,
+                Some(&self.network_context.neighbours_quality),
+                &data
+            ).await?,
+            None => self.broadcast_all(&data).await? as usize
         };
-        let info = self.network_context.overlay.broadcast(
-            &self.overlay_id,
-            &broadcast,
-            None,
-            false
-        ).await?;
         log::trace!(
             "send_block_broadcast {} (overlay {}) sent to {} nodes",
-            self.overlay_id, id, info.send_to
+            id, self.overlay_id, send_to
         );
         Ok(())
     }
@@ -778,6 +877,7 @@ impl FullNodeOverlayClient for NodeClientOverlay {
         };
 
         // Download
+        let started = Instant::now();
         let (proof, is_link) = match prepare {
             PreparedProof::TonNode_PreparedProofEmpty => {
                 fail!("Got `TonNode_PreparedProofEmpty` from {}", good_peer.id())
@@ -792,9 +892,9 @@ impl FullNodeOverlayClient for NodeClientOverlay {
                             #[cfg(feature = "telemetry")]
                             tag: self.tag_download_key_block_proof
                         },
-                        good_peer,
+                        good_peer.clone(),
                         0
-                    ).await?
+                    ).await
                 } else {
                     self.send_rldp_query_raw(
                         &TaggedObject {
@@ -804,9 +904,9 @@ impl FullNodeOverlayClient for NodeClientOverlay {
                             #[cfg(feature = "telemetry")]
                             tag: self.tag_download_block_proof
                         },
-                        good_peer,
+                        good_peer.clone(),
                         0
-                    ).await?
+                    ).await
                 };
                 (proof, false)
             },
@@ -820,9 +920,9 @@ impl FullNodeOverlayClient for NodeClientOverlay {
                             #[cfg(feature = "telemetry")]
                             tag: self.tag_download_key_block_proof_link
                         },
-                        good_peer,
+                        good_peer.clone(),
                         0
-                    ).await?
+                    ).await
                 } else {
                     self.send_rldp_query_raw(
                         &TaggedObject {
@@ -832,15 +932,16 @@ impl FullNodeOverlayClient for NodeClientOverlay {
                             #[cfg(feature = "telemetry")]
                             tag: self.tag_download_block_proof_link
                         },
-                        good_peer,
+                        good_peer.clone(),
                         0
-                    ).await?
+                    ).await
                 };
                 (proof, true)
             }
         };
 
-        BlockProofStuff::deserialize(block_id, proof, is_link)
+        let proof = proof.and_then(|proof| BlockProofStuff::deserialize(block_id, proof, is_link));
+        self.score_download(&good_peer, started, proof)
 
 /*
 Ok(if key_block {
@@ -902,35 +1003,44 @@ Ok(if key_block {
 
         // Download
         match prepare {
-            Prepared::TonNode_NotFound => fail!("Got `TonNode_NotFound` from {}", peer.id()),
+            Prepared::TonNode_NotFound => fail!(
+                NodeError::NotFound(format!("Got `TonNode_NotFound` from {}", peer.id()))
+            ),
             Prepared::TonNode_Prepared => {
-                let (data_full, _): (DataFull, _) = self.send_rldp_query_typed(
-                    &TaggedObject {
-                        object: DownloadBlockFull {
-                            block: id.clone(),
+                let started = Instant::now();
+                let result: Result<(BlockStuff, BlockProofStuff)> = async {
+                    let (data_full, _): (DataFull, _) = self.send_rldp_query_typed(
+                        &TaggedObject {
+                            object: DownloadBlockFull {
+                                block: id.clone(),
+                            },
+                            #[cfg(feature = "telemetry")]
+                            tag: self.tag_download_block_full
                         },
-                        #[cfg(feature = "telemetry")]
-                        tag: self.tag_download_block_full
-                    },
-                    peer,
-                    0,
-                ).await?;
-                match data_full {
-                    DataFull::TonNode_DataFullEmpty => {
-                        fail!("prepareBlock receives Prepared, but DownloadBlockFull receives DataFullEmpty");
-                    },
-                    DataFull::TonNode_DataFull(data_full) => {
-                        if id != &data_full.id {
-                            fail!("Block with another id was received");
+                        peer.clone(),
+                        0,
+                    ).await?;
+                    match data_full {
+                        DataFull::TonNode_DataFullEmpty => {
+                            fail!("prepareBlock receives Prepared, but DownloadBlockFull receives DataFullEmpty");
+                        },
+                        DataFull::TonNode_DataFull(data_full) => {
+                            if id != &data_full.id {
+                                fail!("Block with another id was received");
+                            }
+                            // File hash is checked against decompressed data
+                            let block = decompress_block(data_full.block)?;
+                            let block = BlockStuff::deserialize_block_checked(id.clone(), block)?;
+                            let proof = BlockProofStuff::deserialize(
+                                block.id(),
+                                data_full.proof,
+                                data_full.is_link.into())?;
+                            Ok((block, proof))
                         }
-                        let block = BlockStuff::deserialize_block_checked(id.clone(), data_full.block)?;
-                        let proof = BlockProofStuff::deserialize(
-                            block.id(),
-                            data_full.proof,
-                            data_full.is_link.into())?;
-                        Ok((block, proof))
                     }
-                }
+                }.await;
+                self.record_known_block(&result, &peer);
+                self.score_download(&peer, started, result)
             }
         }
 
@@ -960,9 +1070,12 @@ Ok(if key_block {
 
         // Download
         match prepare {
-            Prepared::TonNode_NotFound => fail!("Got `TonNode_NotFound` from {}", good_peer.id()),
+            Prepared::TonNode_NotFound => fail!(
+                NodeError::NotFound(format!("Got `TonNode_NotFound` from {}", good_peer.id()))
+            ),
             Prepared::TonNode_Prepared => {
-                let update_bytes = self.send_rldp_query_raw(
+                let started = Instant::now();
+                let update = self.send_rldp_query_raw(
                     &TaggedObject {
                         object: DownloadQueueUpdate {
                             block: id.clone(),
@@ -971,10 +1084,16 @@ Ok(if key_block {
                         #[cfg(feature = "telemetry")]
                         tag: self.tag_download_queue_update
                     },
-                    good_peer,
+                    good_peer.clone(),
                     0
-                ).await?;
-                BlockStuff::deserialize_queue_update(id.clone(), target_wc, false, update_bytes)
+                ).await.and_then(|update_bytes| {
+                    let update = BlockStuff::deserialize_queue_update(
+                        id.clone(), target_wc, false, update_bytes
+                    )?;
+                    BlockProofStuff::verify_queue_update(&update)?;
+                    Ok(update)
+                });
+                self.score_download(&good_peer, started, update)
             }
         }
     }
@@ -1038,7 +1157,8 @@ Ok(if key_block {
         peer: Arc<Neighbour>,
         attempt: u32,
     ) -> Result<Vec<u8>> {
-        if let Some(target_wc) = msg_queue_for {
+        let started = Instant::now();
+        let part = if let Some(target_wc) = msg_queue_for {
             let request = TaggedObject {
                 object: DownloadPersistentMsgQueueSlice {
                     block: block_id.clone(),
@@ -1050,7 +1170,7 @@ Ok(if key_block {
                 #[cfg(feature = "telemetry")]
                 tag: self.tag_download_persistent_msg_queue_slice
             };
-            self.send_rldp_query_raw(&request, peer, attempt).await
+            self.send_rldp_query_raw(&request, peer.clone(), attempt).await
         } else {
             let request = TaggedObject {
                 object: DownloadPersistentStateSlice {
@@ -1062,8 +1182,9 @@ Ok(if key_block {
                 #[cfg(feature = "telemetry")]
                 tag: self.tag_download_persistent_state_slice
             };
-            self.send_rldp_query_raw(&request, peer, attempt).await
-        }
+            self.send_rldp_query_raw(&request, peer.clone(), attempt).await
+        };
+        self.score_download(&peer, started, part)
     }
 
     // tonNode.prepareZeroState block:tonNode.blockIdExt = tonNode.PreparedState;
@@ -1091,7 +1212,7 @@ Ok(if key_block {
         // Download
         match prepare {
             PreparedState::TonNode_NotFoundState => {
-                fail!("Got `TonNode_NotFoundState` from {}", good_peer.id())
+                fail!(NodeError::NotFound(format!("Got `TonNode_NotFoundState` from {}", good_peer.id())))
             },
             PreparedState::TonNode_PreparedState => {
                 let state_bytes = self.send_rldp_query_raw(
@@ -1160,7 +1281,7 @@ Ok(if key_block {
         };
 
         // Set neighbor
-        let peer = if let Some(p) = self.peers.choose_neighbour()? {
+        let peer = if let Some(p) = self.choose_download_peer()? {
             p
         } else {
             tokio::time::sleep(Duration::from_millis(Self::TIMEOUT_NO_NEIGHBOURS)).await;
@@ -1169,30 +1290,71 @@ Ok(if key_block {
         log::trace!("USE PEER {}, REQUEST {:?}", peer.id(), request.object);
 
         // Download
-        let (data_full, peer): (DataFull, _) = self.send_rldp_query_typed(
-            &request,
-            peer,
-            0
-        ).await?;
+        let started = Instant::now();
+        let data_full = match self.send_rldp_query_typed(&request, peer.clone(), 0).await {
+            Ok((DataFull::TonNode_DataFullEmpty, _)) => {
+                // No next block yet, it is not the peer's fault
+                fail!(NodeError::NotFound(format!("Got `TonNode_DataFullEmpty` from {}", peer.id())))
+            },
+            Ok((DataFull::TonNode_DataFull(data_full), _)) => Ok(data_full),
+            Err(e) => Err(e)
+        };
 
         // Parse
-        match data_full {
-            DataFull::TonNode_DataFullEmpty =>  {
-                fail!("Got `TonNode_DataFullEmpty` from {}", peer.id())
+        let result = data_full.and_then(|data_full| {
+            // File hash is checked against decompressed data
+            let block = BlockStuff::deserialize_block_checked(
+                data_full.id.clone(),
+                decompress_block(data_full.block.to_vec())?
+            )?;
+            let proof = BlockProofStuff::deserialize(
+                block.id(),
+                data_full.proof.to_vec(),
+                data_full.is_link.clone().into()
+            )?;
+            Ok((block, proof))
+        });
+        self.record_known_block(&result, &peer);
+        self.score_download(&peer, started, result)
+
+    }
+
+    // tonNode.downloadBlocks blocks:(vector tonNode.blockIdExt) = tonNode.Data;
+    // Ids carry the range query, see `BlockRangeQuery`
+    async fn download_block_range(&self, query: &BlockRangeQuery) -> Result<DownloadedBlockRange> {
+
+        query.check()?;
+        let request = TaggedObject {
+            object: DownloadBlocks {
+                blocks: query.to_block_ids().into()
             },
-            DataFull::TonNode_DataFull(data_full) => {
-                let block = BlockStuff::deserialize_block_checked(
-                    data_full.id.clone(),
-                    data_full.block.to_vec()
-                )?;
-                let proof = BlockProofStuff::deserialize(
-                    block.id(),
-                    data_full.proof.to_vec(),
-                    data_full.is_link.clone().into()
-                )?;
-                Ok((block, proof))
+            #[cfg(feature = "telemetry")]
+            tag: self.tag_download_blocks
+        };
+
+        // Set neighbor
+        let peer = if let Some(p) = self.choose_download_peer_with(Some(CAPABILITY_BLOCK_RANGES))? {
+            p
+        } else {
+            fail!(NodeError::NotFound("no neighbour serves block ranges".to_string()))
+        };
+        log::trace!("USE PEER {}, REQUEST {:?}", peer.id(), query);
+
+        // Download and parse
+        let started = Instant::now();
+        let result: Result<DownloadedBlockRange> = async {
+            let data = self.send_rldp_query_raw(&request, peer.clone(), 0).await?;
+            let range = unpack_block_range(&data, query)?;
+            let mut blocks = Vec::with_capacity(range.entries.len());
+            for entry in range.entries {
+                // File hash is checked against decompressed data
+                let block = BlockStuff::deserialize_block_checked(entry.id, decompress_block(entry.block)?)?;
+                let proof = BlockProofStuff::deserialize(block.id(), entry.proof, entry.is_link)?;
+                blocks.push((block, proof));
             }
-        }
+            Ok(DownloadedBlockRange { blocks, next_seqno: range.next_seqno })
+        }.await;
+        self.score_download(&peer, started, result)
 
     }
 
@@ -14,7 +14,11 @@
 use crate::{
     engine_traits::EngineOperations, 
     block::{make_queue_update_from_block_raw, make_mesh_kit_raw, make_mesh_update_raw},
-    network::neighbours::{PROTOCOL_CAPABILITIES, PROTOCOL_VERSION}
+    network::{
+        block_range::{pack_block_range, BlockRangeBuilder, BlockRangeEntry, BlockRangeQuery},
+        compression::compress_block,
+        neighbours::{CAPABILITY_COMPRESSED_BLOCKS, PROTOCOL_CAPABILITIES, PROTOCOL_VERSION}
+    }
 };
 
 use adnl::common::{
@@ -24,12 +28,12 @@ use std::{cmp::min, fmt::Debug, sync::Arc, ops::Deref};
 #[cfg(feature = "telemetry")]
 use ton_api::{tag_from_boxed_type, tag_from_boxed_object};
 use ton_api::{
-    serialize_boxed, 
+    deserialize_boxed, serialize_boxed,
     AnyBoxedSerialize, IntoBoxed,
     ton::{
         self, TLObject,
         rpc::ton_node::{
-            DownloadBlock, DownloadBlockFull, DownloadBlockProof, DownloadBlockProofLink, 
+            DownloadBlock, DownloadBlockFull, DownloadBlockProof, DownloadBlockProofLink, DownloadBlocks,
             DownloadKeyBlockProof, DownloadKeyBlockProofLink, DownloadLatestMeshKit, DownloadMeshKit,
             DownloadMeshUpdate, DownloadNextBlockFull, DownloadNextMeshUpdate, 
             DownloadPersistentMsgQueueSlice, DownloadPersistentState, DownloadPersistentStateSlice, 
@@ -47,7 +51,7 @@ use ton_api::{
     }
 };
 use ever_block::BlockIdExt;
-use ever_block::{fail, error, Result};
+use ever_block::{fail, error, KeyId, Result};
 
 // max part size for partially transmitted data like archives and states
 const PART_MAX_SIZE: usize = 1 << 21; 
@@ -327,7 +331,8 @@ impl FullNodeOverlayService {
     // tonNode.downloadNextBlockFull prev_block:tonNode.blockIdExt = tonNode.DataFull;
     async fn download_next_block_full(
         &self, 
-        query: DownloadNextBlockFull
+        query: DownloadNextBlockFull,
+        peer: &Arc<KeyId>
     ) -> Result<TaggedObject<DataFullBoxed>> {
         let mut answer = DataFullBoxed::TonNode_DataFullEmpty;
         if let Some(prev_handle) = self.engine.load_block_handle(&query.prev_block)? {
@@ -338,6 +343,7 @@ impl FullNodeOverlayService {
                     let has_proof = next_handle.has_proof();
                     if next_handle.has_data() && (has_proof || has_proof_link) {
                         let block = self.engine.load_block_raw(&next_handle).await?;
+                        let block = self.encode_block_data(block, peer)?;
                         let proof = self.engine.load_block_proof_raw(&next_handle, has_proof_link).await?;
                         answer = DataFull {
                             id: next_id.into(),
@@ -366,7 +372,8 @@ impl FullNodeOverlayService {
     // tonNode.downloadBlockFull block:tonNode.blockIdExt = tonNode.DataFull;
     async fn download_block_full(
         &self, 
-        query: DownloadBlockFull
+        query: DownloadBlockFull,
+        peer: &Arc<KeyId>
     ) -> Result<TaggedObject<DataFullBoxed>> {
         let mut answer = DataFullBoxed::TonNode_DataFullEmpty;
         if let Some(handle) = self.engine.load_block_handle(&query.block)? {
@@ -374,6 +381,7 @@ impl FullNodeOverlayService {
             let has_proof = handle.has_proof();
             if handle.has_data() && (has_proof || has_proof_link) {
                 let block = self.engine.load_block_raw(&handle).await?;
+                let block = self.encode_block_data(block, peer)?;
                 let proof = self.engine.load_block_proof_raw(&handle, has_proof_link).await?;
                 answer = DataFull {
                     id: query.block,
@@ -397,6 +405,20 @@ impl FullNodeOverlayService {
         Ok(answer)
     }
 
+    // Block data is compressed only for peers which reported support of it,
+    // so old peers always get raw data
+    fn encode_block_data(&self, data: Vec<u8>, peer: &Arc<KeyId>) -> Result<Vec<u8>> {
+        let level = self.engine.block_compression_level();
+        if level != 0 && self.engine.peer_has_capability(peer, CAPABILITY_COMPRESSED_BLOCKS) {
+            if let Some(compressed) = compress_block(&data, level)? {
+                metrics::counter!("served_block_bytes", compressed.len() as u64, "encoding" => "zstd");
+                return Ok(compressed)
+            }
+        }
+        metrics::counter!("served_block_bytes", data.len() as u64, "encoding" => "raw");
+        Ok(data)
+    }
+
     // tonNode.downloadQueueUpdate block:tonNode.blockIdExt target_wc:int = tonNode.Data;
     async fn download_queue_update(
         &self, 
@@ -565,11 +587,12 @@ impl FullNodeOverlayService {
     }
 
     // tonNode.downloadBlock block:tonNode.blockIdExt = tonNode.Data;
-    async fn download_block(&self, query: DownloadBlock) -> Result<TaggedByteVec> {
+    async fn download_block(&self, query: DownloadBlock, peer: &Arc<KeyId>) -> Result<TaggedByteVec> {
         if let Some(handle) = self.engine.load_block_handle(&query.block)? {
             if handle.has_data() {
+                let block = self.engine.load_block_raw(&handle).await?;
                 let answer = TaggedByteVec {
-                    object: self.engine.load_block_raw(&handle).await?,
+                    object: self.encode_block_data(block, peer)?,
                     #[cfg(feature = "telemetry")]
                     tag: 0x8000000A // Raw reply do download block
                 };
@@ -580,7 +603,51 @@ impl FullNodeOverlayService {
     }
 
     // tonNode.downloadBlocks blocks:(vector tonNode.blockIdExt) = tonNode.DataList;
-    // Not supported in t-node
+    // Not supported in t-node for real block ids, only for ranges of masterchain blocks
+    async fn download_blocks(&self, query: DownloadBlocks, peer: &Arc<KeyId>) -> Result<TaggedByteVec> {
+        let Some(query) = BlockRangeQuery::from_block_ids(&query.blocks) else {
+            fail!("`tonNode.downloadBlocks` request is supported only for block ranges")
+        };
+        query.check()?;
+        if !query.shard.is_masterchain() {
+            fail!("Block ranges are served only for masterchain, {} requested", query.shard)
+        }
+        let mut builder = BlockRangeBuilder::new(&query);
+        if let Some(last_id) = self.engine.load_last_applied_mc_block_id()? {
+            let last_state = self.engine.load_state(&last_id).await?;
+            let to_seqno = min(query.to_seqno, last_id.seq_no());
+            for seq_no in query.from_seqno..=to_seqno {
+                let id = if seq_no == last_id.seq_no() {
+                    last_id.deref().clone()
+                } else {
+                    last_state.find_block_id(seq_no)?
+                };
+                // Only applied blocks are served, the range is cut at the first other one
+                let Some(handle) = self.engine.load_block_handle(&id)? else {
+                    break
+                };
+                if !handle.is_applied() || !handle.has_data() || !handle.has_proof() {
+                    break
+                }
+                let block = self.engine.load_block_raw(&handle).await?;
+                let entry = BlockRangeEntry {
+                    block: self.encode_block_data(block, peer)?,
+                    proof: self.engine.load_block_proof_raw(&handle, false).await?,
+                    is_link: false,
+                    id,
+                };
+                if !builder.push(entry) || builder.is_complete() {
+                    break
+                }
+            }
+        }
+        let answer = TaggedByteVec {
+            object: pack_block_range(&builder.finish()),
+            #[cfg(feature = "telemetry")]
+            tag: 0x8000000F // Raw reply to download block range
+        };
+        Ok(answer)
+    }
 
     // tonNode.downloadPersistentState block:tonNode.blockIdExt masterchain_block:tonNode.blockIdExt = tonNode.Data;
     async fn download_persistent_state(
@@ -601,16 +668,13 @@ impl FullNodeOverlayService {
         &self, 
         query: DownloadPersistentStateSlice
     ) -> Result<TaggedByteVec> {
-        if query.max_size as usize > PART_MAX_SIZE {
-            fail!("Part size {} is too big, max is {}", query.max_size, PART_MAX_SIZE);
-        }
         if let Some(handle) = self.engine.load_block_handle(&query.block)? {
             if handle.has_persistent_state() {
-                let data = self.engine.load_persistent_state_slice(
-                    &handle,
-                    query.offset as u64,
-                    query.max_size as u64
-                ).await?;
+                let mut reader = self.engine.open_persistent_state_reader(&handle).await?;
+                if query.max_size as usize > reader.chunk_size() {
+                    fail!("Part size {} is too big, max is {}", query.max_size, reader.chunk_size());
+                }
+                let data = reader.read_chunk(query.offset as u64, query.max_size as usize).await?;
                 let answer = TaggedByteVec {
                     object: data,
                     #[cfg(feature = "telemetry")]
@@ -627,20 +691,17 @@ impl FullNodeOverlayService {
         &self, 
         query: DownloadPersistentMsgQueueSlice
     ) -> Result<TaggedByteVec> {
-        if query.max_size as usize > PART_MAX_SIZE {
-            fail!("Part size {} is too big, max is {}", query.max_size, PART_MAX_SIZE);
-        }
         if let Some(handle) = self.engine.load_block_handle(&query.block)? {
             if handle.has_persistent_state() {
                 if let Some(wc) = handle.is_queue_update_for() {
                     if wc != query.target_wc {
                         fail!("{} is a queue for wc {} not {}", query.block, wc, query.target_wc)
                     }
-                    let data = self.engine.load_persistent_state_slice(
-                        &handle,
-                        query.offset as u64,
-                        query.max_size as u64
-                    ).await?;
+                    let mut reader = self.engine.open_persistent_state_reader(&handle).await?;
+                    if query.max_size as usize > reader.chunk_size() {
+                        fail!("Part size {} is too big, max is {}", query.max_size, reader.chunk_size());
+                    }
+                    let data = reader.read_chunk(query.offset as u64, query.max_size as usize).await?;
                     let answer = TaggedByteVec {
                         object: data,
                         #[cfg(feature = "telemetry")]
@@ -773,12 +834,14 @@ impl FullNodeOverlayService {
         if query.max_size as usize > PART_MAX_SIZE {
             fail!("Part size {} is too big, max is {}", query.max_size, PART_MAX_SIZE);
         }
+        let data = self.engine.get_archive_slice(
+            query.archive_id as u64,
+            query.offset as u64,
+            query.max_size as u32
+        ).await?;
+        // Answer owns its buffer, so mapped data is copied into it once
         let answer = TaggedByteVec {
-            object: self.engine.get_archive_slice(
-                query.archive_id as u64, 
-                query.offset as u64, 
-                query.max_size as u32
-            ).await?,
+            object: data.into_vec(),
             #[cfg(feature = "telemetry")]
             tag: 0x8000000E // Raw reply to download archive slice
         };
@@ -986,7 +1049,7 @@ impl Subscriber for FullNodeOverlayService {
 
         let query = match self.consume_query::<DownloadNextBlockFull, _, _>(
             query,
-            &Self::download_next_block_full
+            &|service, query| service.download_next_block_full(query, adnl_peers.other())
         ).await? {
             Ok(answer) => return Ok(answer),
             Err(query) => query
@@ -994,7 +1057,7 @@ impl Subscriber for FullNodeOverlayService {
 
         let query = match self.consume_query::<DownloadBlockFull, _, _>(
             query,
-            &Self::download_block_full
+            &|service, query| service.download_block_full(query, adnl_peers.other())
         ).await? {
             Ok(answer) => return Ok(answer),
             Err(query) => query
@@ -1002,7 +1065,15 @@ impl Subscriber for FullNodeOverlayService {
 
         let query = match self.consume_query_raw::<DownloadBlock, _>(
             query,
-            &Self::download_block
+            &|service, query| service.download_block(query, adnl_peers.other())
+        ).await? {
+            Ok(answer) => return Ok(answer),
+            Err(query) => query
+        };
+
+        let query = match self.consume_query_raw::<DownloadBlocks, _>(
+            query,
+            &|service, query| service.download_blocks(query, adnl_peers.other())
         ).await? {
             Ok(answer) => return Ok(answer),
             Err(query) => query
@@ -1139,4 +1210,16 @@ impl Subscriber for FullNodeOverlayService {
         log::warn!("Unsupported full node query {:?}", query);
         fail!("Unsupported full node query {:?}", query);
     }
+
+    // Block broadcasts sent directly by neighbours' fanout
+    async fn try_consume_custom(&self, data: &[u8], peers: &AdnlPeers) -> Result<bool> {
+        match deserialize_boxed(data).map(|object| object.downcast::<ton_node::Broadcast>()) {
+            Ok(Ok(ton_node::Broadcast::TonNode_BlockBroadcast(broadcast))) => {
+                log::trace!("Direct block broadcast {} from {}", broadcast.id, peers.other());
+                self.engine.clone().process_direct_block_broadcast(broadcast, peers.other().clone());
+                Ok(true)
+            }
+            _ => Ok(false)
+        }
+    }
 }
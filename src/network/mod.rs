@@ -12,11 +12,18 @@
 */
 
 pub mod catchain_client;
+pub mod block_range;
+pub mod broadcast_fanout;
+pub mod compression;
 pub mod node_network;
 pub mod neighbours;
+pub mod neighbours_quality;
+pub mod peer_scoring;
 pub mod full_node_client;
 pub mod full_node_service;
 pub mod control;
+pub mod control_limits;
+pub mod control_permissions;
 #[cfg(feature = "telemetry")]
 pub mod telemetry;
 pub mod remp;
\ No newline at end of file
@@ -11,7 +11,11 @@
 * limitations under the License.
 */
 
-use crate::types::spawn_cancelable;
+use crate::{
+    config::NeighboursRebalanceConfig,
+    network::neighbours_quality::{rebalance_neighbours, NeighboursQuality, NeighboursTransport},
+    types::spawn_cancelable
+};
 
 use adnl::{common::{Query, TaggedTlObject, Wait}, node::{AddressCache, AdnlNode}};
 use adnl::DhtNode;
@@ -52,6 +56,7 @@ pub struct Neighbours {
     dht: Arc<DhtNode>,
     fail_attempts: AtomicU64,
     all_attempts: AtomicU64,
+    quality: Arc<NeighboursQuality>,
     start: Instant,
     cancellation_token: tokio_util::sync::CancellationToken,
     #[cfg(feature = "telemetry")]
@@ -59,9 +64,14 @@ pub struct Neighbours {
 }
 
 const CAPABILITY_COMPATIBLE: i64 = 0x01;
+// Node accepts compressed block data in block download answers
+pub const CAPABILITY_COMPRESSED_BLOCKS: i64 = 0x02;
+// Node serves ranges of applied blocks, see network::block_range
+pub const CAPABILITY_BLOCK_RANGES: i64 = 0x04;
 const VERSION_COMPATIBLE: i32 = 2;
 
-pub const PROTOCOL_CAPABILITIES: i64 = CAPABILITY_COMPATIBLE;
+pub const PROTOCOL_CAPABILITIES: i64 =
+    CAPABILITY_COMPATIBLE | CAPABILITY_COMPRESSED_BLOCKS | CAPABILITY_BLOCK_RANGES;
 pub const PROTOCOL_VERSION: i32 = VERSION_COMPATIBLE;
 pub const BETTER_REPLACE_UNRELIABILITY: i32 = 5;
 pub const FAIL_UNRELIABILITY: i32 = 10;
@@ -100,7 +110,7 @@ impl Neighbour {
         let capabilities = self.capabilities.load(Ordering::Relaxed);
         if version < PROTOCOL_VERSION {
             unr += 4;
-        } else if (version == PROTOCOL_VERSION) && (capabilities < PROTOCOL_CAPABILITIES) {
+        } else if (version == PROTOCOL_VERSION) && (capabilities & CAPABILITY_COMPATIBLE == 0) {
             unr += 2;
         }
         return unr;
@@ -156,10 +166,9 @@ impl Neighbour {
         }
     }
 
-// Unused
-//    pub fn capabilities(&self) -> i64 {
-//        self.capabilities.load(Ordering::Relaxed)
-//    }
+    pub fn has_capability(&self, capability: i64) -> bool {
+        self.capabilities.load(Ordering::Relaxed) & capability != 0
+    }
 
     pub fn roundtrip_adnl(&self) -> Option<u64> {
         Self::roundtrip(&self.roundtrip_adnl)
@@ -230,6 +239,7 @@ impl Neighbours {
         overlay: &Arc<OverlayNode>,
         overlay_id: Arc<OverlayShortId>,
         default_rldp_roundtrip: &Option<u32>,
+        quality: Arc<NeighboursQuality>,
         cancellation_token: tokio_util::sync::CancellationToken
     ) -> Result<Self> {
         let default_rldp_roundtrip = default_rldp_roundtrip.unwrap_or(
@@ -245,6 +255,7 @@ impl Neighbours {
             network_id,
             fail_attempts: AtomicU64::new(0),
             all_attempts: AtomicU64::new(0),
+            quality,
             start: Instant::now(),
             cancellation_token,
             #[cfg(feature = "telemetry")]
@@ -392,6 +403,23 @@ impl Neighbours {
         )
     }
 
+    pub fn start_rebalance(self: Arc<Self>, config: NeighboursRebalanceConfig) {
+        if config.interval_sec == 0 {
+            return
+        }
+        spawn_cancelable(
+            self.cancellation_token.clone(),
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_secs(config.interval_sec as u64)).await;
+                    if let Err(e) = rebalance_neighbours(&self.quality, self.as_ref(), &config) {
+                        log::warn!("rebalance neighbours (overlay: {}) err: {}", self.overlay_id, e);
+                    }
+                }
+            }
+        )
+    }
+
     pub fn start_ping(self: Arc<Self>) {
         spawn_cancelable(
             self.cancellation_token.clone(),
@@ -474,12 +502,21 @@ impl Neighbours {
         });
     }
 
+    pub fn active_ids(&self) -> Vec<Arc<KeyId>> {
+        self.peers.get_iter().map(|neighbour| neighbour.id().clone()).collect()
+    }
+
     pub fn log_neighbors_stat(&self) {
         log::debug!(
             target: "telemetry", 
             "Neighbours: overlay {} count {}",
             self.overlay_id, self.peers.count()
         );
+        log::debug!(
+            target: "telemetry",
+            "Neighbours quality: overlay {}\n{}",
+            self.overlay_id, self.quality.report(&self.active_ids())
+        );
         let node_stat = self.fail_attempts.load(Ordering::Relaxed) as f64 /
             self.all_attempts.load(Ordering::Relaxed) as f64;
         for neighbour in self.peers.get_iter() {
@@ -578,6 +615,7 @@ impl Neighbours {
         update_flag: u8,
     ) {
         log::trace!("update_neighbour_stats");
+        self.quality.record_query(neighbour.id(), roundtrip, update_flag & UPDATE_FLAG_SUCCESS > 0);
         if update_flag & UPDATE_FLAG_SUCCESS > 0 {
             neighbour.query_success(roundtrip, update_flag & UPDATE_FLAG_IS_RDPL > 0);
         } else {
@@ -732,6 +770,40 @@ impl Neighbours {
 
 }
 
+impl NeighboursTransport for Neighbours {
+
+    fn active_neighbours(&self) -> Vec<Arc<KeyId>> {
+        self.active_ids()
+    }
+
+    fn replacement_candidates(&self) -> Result<Vec<Arc<KeyId>>> {
+        let cache = AddressCache::with_limit((MAX_NEIGHBOURS * 2 + 1) as u32);
+        self.overlay.get_cached_random_peers(&cache, &self.overlay_id, (MAX_NEIGHBOURS * 2) as u32)?;
+        let mut candidates = Vec::new();
+        let (mut iter, mut current) = cache.first();
+        while let Some(peer) = current {
+            // Peers known to be bad are not taken back
+            if !self.reserve.find_in_reserve(&peer).map_or(false, |v| !v.is_good()) {
+                candidates.push(peer);
+            }
+            current = cache.next(&mut iter);
+        }
+        Ok(candidates)
+    }
+
+    // Replaced neighbour goes to reserve and stays in overlay, so it may come back later
+    fn replace_neighbour(&self, old: &Arc<KeyId>, new: Arc<KeyId>) -> Result<bool> {
+        let old = self.peers.get(old).ok_or_else(|| error!("Neighbour {} is not active", old))?;
+        let reserve_peer = self.reserve.find_in_reserve(&new);
+        if !self.peers.replace(old.id(), new, reserve_peer.clone(), false)? {
+            return Ok(false)
+        }
+        self.reserve.on_active_replaced(&reserve_peer, old);
+        Ok(true)
+    }
+
+}
+
 #[derive(Clone)]
 pub struct NeighboursCache {
     cache: Arc<NeighboursCacheCore>
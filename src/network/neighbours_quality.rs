@@ -0,0 +1,194 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::config::NeighboursRebalanceConfig;
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use ever_block::{KeyId, Result};
+
+#[cfg(test)]
+#[path = "tests/test_neighbours_quality.rs"]
+mod tests;
+
+// Weights of the last sample in decaying averages
+const QUERY_EWMA_ALPHA: f64 = 0.1;
+const BROADCAST_EWMA_ALPHA: f64 = 0.05;
+// Latency at which latency factor of the score is 1/2, also latency of unknown peer
+const LATENCY_REFERENCE_MS: f64 = 500.0;
+// Novelty of unknown peer
+const NEUTRAL_NOVELTY: f64 = 0.5;
+// Peers not seen for the longest time are forgotten over this count
+const MAX_SCORED_PEERS: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct NeighbourQuality {
+    pub latency_ms: f64,
+    pub failure_rate: f64,
+    // Share of broadcasts which brought a block we didn't have
+    pub novelty: f64,
+    pub samples: u64,
+    pub score: f64,
+}
+
+struct QualityRecord {
+    latency_ms: f64,
+    failure_rate: f64,
+    novelty: f64,
+    samples: u64,
+    updated_at: Instant,
+}
+
+impl QualityRecord {
+
+    fn new(now: Instant) -> Self {
+        Self {
+            latency_ms: LATENCY_REFERENCE_MS,
+            failure_rate: 0.0,
+            novelty: NEUTRAL_NOVELTY,
+            samples: 0,
+            updated_at: now,
+        }
+    }
+
+    fn score(&self) -> f64 {
+        (1.0 - self.failure_rate) * LATENCY_REFERENCE_MS / (LATENCY_REFERENCE_MS + self.latency_ms) *
+            (0.5 + self.novelty)
+    }
+
+    fn quality(&self) -> NeighbourQuality {
+        NeighbourQuality {
+            latency_ms: self.latency_ms,
+            failure_rate: self.failure_rate,
+            novelty: self.novelty,
+            samples: self.samples,
+            score: self.score(),
+        }
+    }
+
+}
+
+// Quality of overlay neighbours by queries and broadcasts. Records are kept after neighbour
+// is rotated out, so it comes back with its old score
+pub struct NeighboursQuality {
+    peers: parking_lot::Mutex<HashMap<Arc<KeyId>, QualityRecord>>,
+}
+
+impl NeighboursQuality {
+
+    pub fn new() -> Self {
+        Self { peers: parking_lot::Mutex::new(HashMap::new()) }
+    }
+
+    pub fn record_query(&self, peer: &Arc<KeyId>, roundtrip_ms: u64, success: bool) {
+        self.update(peer, |record| {
+            record.failure_rate += QUERY_EWMA_ALPHA * (if success { 0.0 } else { 1.0 } - record.failure_rate);
+            if success {
+                record.latency_ms += QUERY_EWMA_ALPHA * (roundtrip_ms as f64 - record.latency_ms);
+            }
+        })
+    }
+
+    pub fn record_broadcast(&self, peer: &Arc<KeyId>, is_new: bool) {
+        self.update(peer, |record| {
+            record.novelty += BROADCAST_EWMA_ALPHA * (if is_new { 1.0 } else { 0.0 } - record.novelty);
+        })
+    }
+
+    // Unknown peer has neutral quality
+    pub fn quality(&self, peer: &Arc<KeyId>) -> NeighbourQuality {
+        match self.peers.lock().get(peer) {
+            Some(record) => record.quality(),
+            None => QualityRecord::new(Instant::now()).quality()
+        }
+    }
+
+    pub fn table(&self, peers: &[Arc<KeyId>]) -> Vec<(Arc<KeyId>, NeighbourQuality)> {
+        peers.iter().map(|peer| (peer.clone(), self.quality(peer))).collect()
+    }
+
+    pub fn report(&self, peers: &[Arc<KeyId>]) -> String {
+        let mut table = self.table(peers);
+        table.sort_by(|(_, a), (_, b)| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        table.iter()
+            .map(|(peer, quality)| format!(
+                "{} score {:.3} latency {:.0} ms failures {:.3} novelty {:.3} samples {}",
+                peer, quality.score, quality.latency_ms, quality.failure_rate, quality.novelty,
+                quality.samples
+            ))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn update(&self, peer: &Arc<KeyId>, update: impl FnOnce(&mut QualityRecord)) {
+        let now = Instant::now();
+        let mut peers = self.peers.lock();
+        if !peers.contains_key(peer) && peers.len() >= MAX_SCORED_PEERS {
+            let oldest = peers.iter().min_by_key(|(_, record)| record.updated_at).map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                peers.remove(&oldest);
+            }
+        }
+        let record = peers.entry(peer.clone()).or_insert_with(|| QualityRecord::new(now));
+        update(record);
+        record.samples += 1;
+        record.updated_at = now;
+    }
+
+}
+
+// Operations on neighbours of one overlay needed for rotation
+pub trait NeighboursTransport {
+    fn active_neighbours(&self) -> Vec<Arc<KeyId>>;
+    // Overlay peers which may replace a neighbour
+    fn replacement_candidates(&self) -> Result<Vec<Arc<KeyId>>>;
+    fn replace_neighbour(&self, old: &Arc<KeyId>, new: Arc<KeyId>) -> Result<bool>;
+}
+
+// Replaces the worst neighbour with the best candidate if the candidate is expected to be better.
+// Returns replaced and new neighbours
+pub fn rebalance_neighbours(
+    quality: &NeighboursQuality,
+    transport: &dyn NeighboursTransport,
+    config: &NeighboursRebalanceConfig
+) -> Result<Option<(Arc<KeyId>, Arc<KeyId>)>> {
+    let active = transport.active_neighbours();
+    if active.len() < config.min_neighbours as usize {
+        log::trace!("neighbours rebalance: only {} neighbours, skipped", active.len());
+        return Ok(None)
+    }
+    let worst = quality.table(&active).into_iter()
+        .filter(|(_, quality)| quality.samples >= config.min_samples as u64)
+        .min_by(|(_, a), (_, b)| a.score.partial_cmp(&b.score).unwrap_or(std::cmp::Ordering::Equal));
+    let Some((worst, worst_quality)) = worst else {
+        return Ok(None)
+    };
+    let candidates = transport.replacement_candidates()?.into_iter()
+        .filter(|candidate| !active.contains(candidate))
+        .collect::<Vec<_>>();
+    let best = quality.table(&candidates).into_iter()
+        .max_by(|(_, a), (_, b)| a.score.partial_cmp(&b.score).unwrap_or(std::cmp::Ordering::Equal));
+    let Some((best, best_quality)) = best else {
+        return Ok(None)
+    };
+    if best_quality.score <= worst_quality.score {
+        return Ok(None)
+    }
+    if !transport.replace_neighbour(&worst, best.clone())? {
+        return Ok(None)
+    }
+    log::info!(
+        "neighbours rebalance: {} (score {:.3}) replaced by {} (score {:.3})",
+        worst, worst_quality.score, best, best_quality.score
+    );
+    Ok(Some((worst, best)))
+}
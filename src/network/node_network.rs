@@ -13,13 +13,15 @@
 
 use crate::{
     config::{ 
-        ConfigEvent, ConnectivityCheckBroadcastConfig, NodeConfigHandler, NodeConfigSubscriber, TonNodeConfig
+        ConfigEvent, ConnectivityCheckBroadcastConfig, NeighboursRebalanceConfig, NodeConfigHandler,
+        NodeConfigSubscriber, TonNodeConfig
     },
     engine_traits::{EngineAlloc, OverlayOperations, PrivateOverlayOperations},
     network::{
-        catchain_client::CatchainClient,
+        broadcast_fanout::BroadcastFanout, catchain_client::CatchainClient,
         full_node_client::{FullNodeOverlayClient, NodeClientOverlay},
-        neighbours::{self, Neighbours}, remp::RempNode,
+        neighbours::{self, Neighbours}, neighbours_quality::{NeighbourQuality, NeighboursQuality},
+        peer_scoring::PeerScoring, remp::RempNode,
     },
     types::{awaiters_pool::AwaitersPool, spawn_cancelable},
 };
@@ -57,6 +59,9 @@ pub (crate) struct NetworkContext {
     pub rldp: Arc<RldpNode>,
     pub remp: Arc<RempNode>,
     pub broadcast_hops: Option<u8>,
+    pub peer_scoring: Arc<PeerScoring>,
+    pub neighbours_quality: Arc<NeighboursQuality>,
+    pub broadcast_fanout: Option<Arc<BroadcastFanout>>,
     #[cfg(feature = "telemetry")]
     pub telemetry: Arc<FullNodeNetworkTelemetry>,
     #[cfg(feature = "telemetry")]
@@ -74,6 +79,7 @@ pub struct NodeNetwork {
     config_handler: Arc<NodeConfigHandler>,
     connectivity_check_config: ConnectivityCheckBroadcastConfig,
     default_rldp_roundtrip: Option<u32>,
+    neighbours_rebalance: NeighboursRebalanceConfig,
     cancellation_token: tokio_util::sync::CancellationToken,
     #[cfg(feature = "telemetry")]
     tag_connectivity_check_broadcast: u32,
@@ -177,6 +183,9 @@ impl NodeNetwork {
         NodeNetwork::periodic_store_ip_addr(dht.clone(), overlay_key, None, cancellation_token.clone());
 
         let default_rldp_roundtrip = config.default_rldp_roundtrip();
+        let neighbours_rebalance = config.neighbours_rebalance();
+        let broadcast_fanout = config.block_broadcast_fanout()
+            .map(|config| Arc::new(BroadcastFanout::new(config)));
 
         NodeNetwork::find_dht_nodes(dht.clone(), None, cancellation_token.clone());
         let (config_handler, config_handler_context) = NodeConfigHandler::create(
@@ -204,6 +213,9 @@ impl NodeNetwork {
             rldp,
             remp,
             broadcast_hops,
+            peer_scoring: Arc::new(PeerScoring::new()),
+            neighbours_quality: Arc::new(NeighboursQuality::new()),
+            broadcast_fanout,
             #[cfg(feature = "telemetry")]
             telemetry: Arc::new(
                 FullNodeNetworkTelemetry::new(FullNodeNetworkTelemetryKind::Client)
@@ -228,6 +240,7 @@ impl NodeNetwork {
             runtime_handle: tokio::runtime::Handle::current(),
             config_handler,
             default_rldp_roundtrip,
+            neighbours_rebalance,
             connectivity_check_config,
             cancellation_token,
             #[cfg(feature = "telemetry")]
@@ -277,6 +290,38 @@ impl NodeNetwork {
         }
     }
 
+    // Active neighbours of every full node overlay with their quality
+    pub fn neighbours_quality_table(&self) -> Vec<(Arc<OverlayShortId>, Arc<KeyId>, NeighbourQuality)> {
+        let mut table = Vec::new();
+        for guard in self.overlays.iter() {
+            let peers = guard.val().peers().active_ids();
+            for (peer, quality) in self.network_context.neighbours_quality.table(&peers) {
+                table.push((guard.key().clone(), peer, quality));
+            }
+        }
+        table
+    }
+
+    pub fn neighbours_quality(&self) -> &NeighboursQuality {
+        &self.network_context.neighbours_quality
+    }
+
+    pub fn broadcast_fanout(&self) -> Option<&Arc<BroadcastFanout>> {
+        self.network_context.broadcast_fanout.as_ref()
+    }
+
+    // Capabilities are known for peers pinged as neighbours in any full node overlay
+    pub fn peer_has_capability(&self, peer: &Arc<KeyId>, capability: i64) -> bool {
+        for guard in self.overlays.iter() {
+            if let Some(neighbour) = guard.val().peers().peer(peer) {
+                if neighbour.has_capability(capability) {
+                    return true
+                }
+            }
+        }
+        false
+    }
+
     pub async fn stop_adnl(&self) {
         log::info!("Stopping node network loops...");
         self.cancellation_token.cancel();
@@ -615,6 +660,7 @@ impl NodeNetwork {
             &self.network_context.overlay,
             overlay_id_short.clone(),
             &self.default_rldp_roundtrip,
+            self.network_context.neighbours_quality.clone(),
             self.cancellation_token.clone()
         )?;
 
@@ -636,6 +682,7 @@ impl NodeNetwork {
         Neighbours::start_ping(Arc::clone(&peers));
         Neighbours::start_reload(Arc::clone(&peers));
         Neighbours::start_rnd_peers_process(Arc::clone(&peers));
+        Neighbours::start_rebalance(Arc::clone(&peers), self.neighbours_rebalance.clone());
         NodeNetwork::start_update_peers(self.clone(), &client_overlay, network_id);
         NodeNetwork::process_overlay_peers(
             peers.clone(), 
@@ -911,6 +958,10 @@ impl NodeNetwork {
         &self.network_context.telemetry
     }
 
+    pub fn peer_scoring(&self) -> &PeerScoring {
+        &self.network_context.peer_scoring
+    }
+
     fn current_validator_set_context<'a>(
         &'a self
     ) -> Option<lockfree::map::ReadGuard<'a, UInt256, Arc<ValidatorSetContext>>>  {
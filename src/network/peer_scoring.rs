@@ -0,0 +1,257 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::rng::random::secure_seeded_rng;
+
+use rand::{Rng, rngs::StdRng, seq::SliceRandom};
+use std::{collections::HashMap, sync::Arc, time::{Duration, Instant}};
+use ever_block::KeyId;
+
+#[cfg(test)]
+#[path = "tests/test_peer_scoring.rs"]
+mod tests;
+
+// Consecutive failures after which peer is not asked for some time
+const QUARANTINE_FAILURES: u32 = 3;
+const QUARANTINE_TIME: Duration = Duration::from_secs(60);
+// Success and failure counters are halved during this period
+const SCORE_HALF_LIFE: Duration = Duration::from_secs(300);
+// Latency at which latency factor of the score is 1/2
+const LATENCY_REFERENCE_MS: f64 = 500.0;
+// Weight of the last sample in latency EWMA
+const LATENCY_EWMA_ALPHA: f64 = 0.2;
+
+struct PeerScore {
+    successes: f64,
+    failures: f64,
+    consecutive_failures: u32,
+    latency_ms: Option<f64>,
+    quarantined_until: Option<Instant>,
+    updated_at: Instant,
+    // Broadcasts which were found bad by themselves, they don't change the score
+    rejected_broadcasts: u64,
+}
+
+impl PeerScore {
+
+    fn new(now: Instant) -> Self {
+        Self {
+            successes: 0.0,
+            failures: 0.0,
+            consecutive_failures: 0,
+            latency_ms: None,
+            quarantined_until: None,
+            updated_at: now,
+            rejected_broadcasts: 0,
+        }
+    }
+
+    fn decay(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        let factor = 0.5_f64.powf(elapsed.as_secs_f64() / SCORE_HALF_LIFE.as_secs_f64());
+        self.successes *= factor;
+        self.failures *= factor;
+        self.updated_at = now;
+        if let Some(until) = self.quarantined_until {
+            if until <= now {
+                self.quarantined_until = None
+            }
+        }
+    }
+
+    fn score(&self) -> f64 {
+        let reliability = (self.successes + 1.0) / (self.successes + self.failures + 2.0);
+        let latency = self.latency_ms.unwrap_or(LATENCY_REFERENCE_MS);
+        reliability * LATENCY_REFERENCE_MS / (LATENCY_REFERENCE_MS + latency)
+    }
+
+}
+
+// Download peers scoring shared by all download kinds
+pub struct PeerScoring {
+    peers: parking_lot::Mutex<HashMap<Arc<KeyId>, PeerScore>>,
+    rng: parking_lot::Mutex<StdRng>,
+}
+
+impl PeerScoring {
+
+    pub fn new() -> Self {
+        Self::with_rng(secure_seeded_rng())
+    }
+
+    pub fn with_rng(rng: StdRng) -> Self {
+        Self {
+            peers: parking_lot::Mutex::new(HashMap::new()),
+            rng: parking_lot::Mutex::new(rng),
+        }
+    }
+
+    pub fn record_success(&self, peer: &Arc<KeyId>, latency: Duration) {
+        self.record(peer, Some(latency), Instant::now())
+    }
+
+    pub fn record_failure(&self, peer: &Arc<KeyId>) {
+        self.record(peer, None, Instant::now())
+    }
+
+    // Peer has served provably bad data, so it is not asked until quarantine is over
+    pub fn quarantine(&self, peer: &Arc<KeyId>) {
+        let now = Instant::now();
+        let mut peers = self.peers.lock();
+        let score = peers.entry(peer.clone()).or_insert_with(|| PeerScore::new(now));
+        score.decay(now);
+        score.failures += 1.0;
+        score.quarantined_until = Some(now + QUARANTINE_TIME);
+    }
+
+    pub fn record_rejected_broadcast(&self, peer: &Arc<KeyId>) {
+        let now = Instant::now();
+        let mut peers = self.peers.lock();
+        peers.entry(peer.clone()).or_insert_with(|| PeerScore::new(now)).rejected_broadcasts += 1;
+    }
+
+    pub fn rejected_broadcasts(&self, peer: &Arc<KeyId>) -> u64 {
+        self.peers.lock().get(peer).map_or(0, |score| score.rejected_broadcasts)
+    }
+
+    // Weighted random choice of candidate, quarantined peers are skipped
+    // while there are others. Returns index in candidates
+    pub fn choose(&self, candidates: &[Arc<KeyId>]) -> Option<usize> {
+        self.choose_at(candidates, Instant::now())
+    }
+
+    // Orders peers from better to worse, peers with equal score are shuffled
+    pub fn rank(&self, peers: &mut Vec<Arc<KeyId>>) {
+        self.rank_at(peers, Instant::now())
+    }
+
+    pub fn report(&self) -> String {
+        let now = Instant::now();
+        let mut peers = self.peers.lock();
+        if peers.is_empty() {
+            return "No one peer".to_string()
+        }
+        let mut lines = peers.iter_mut()
+            .map(|(id, score)| {
+                score.decay(now);
+                (score.score(), format!(
+                    "{} score {:.3} ok {:.1} failed {:.1} latency {} ms rejected {}{}",
+                    id, score.score(), score.successes, score.failures,
+                    score.latency_ms.map(|l| l as u64).unwrap_or_default(),
+                    score.rejected_broadcasts,
+                    if score.quarantined_until.is_some() { " quarantined" } else { "" }
+                ))
+            })
+            .collect::<Vec<_>>();
+        lines.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        lines.into_iter().map(|(_, line)| line).collect::<Vec<_>>().join("\n")
+    }
+
+    fn record(&self, peer: &Arc<KeyId>, latency: Option<Duration>, now: Instant) {
+        let mut peers = self.peers.lock();
+        let score = peers.entry(peer.clone()).or_insert_with(|| PeerScore::new(now));
+        score.decay(now);
+        if let Some(latency) = latency {
+            let latency = latency.as_millis() as f64;
+            score.successes += 1.0;
+            score.consecutive_failures = 0;
+            score.latency_ms = Some(match score.latency_ms {
+                Some(old) => old + LATENCY_EWMA_ALPHA * (latency - old),
+                None => latency
+            });
+        } else {
+            score.failures += 1.0;
+            score.consecutive_failures += 1;
+            if score.consecutive_failures >= QUARANTINE_FAILURES {
+                log::debug!(
+                    "Peer {} is quarantined after {} failures", peer, score.consecutive_failures
+                );
+                score.quarantined_until = Some(now + QUARANTINE_TIME);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn score_at(&self, peer: &Arc<KeyId>, now: Instant) -> f64 {
+        let mut peers = self.peers.lock();
+        match peers.get_mut(peer) {
+            Some(score) => {
+                score.decay(now);
+                score.score()
+            }
+            None => PeerScore::new(now).score()
+        }
+    }
+
+    #[cfg(test)]
+    fn is_quarantined_at(&self, peer: &Arc<KeyId>, now: Instant) -> bool {
+        let mut peers = self.peers.lock();
+        match peers.get_mut(peer) {
+            Some(score) => {
+                score.decay(now);
+                score.quarantined_until.is_some()
+            }
+            None => false
+        }
+    }
+
+    // Quarantined peers get zero weight
+    fn weights(&self, peers: &[Arc<KeyId>], now: Instant) -> Vec<f64> {
+        let mut scores = self.peers.lock();
+        peers.iter()
+            .map(|peer| match scores.get_mut(peer) {
+                Some(score) => {
+                    score.decay(now);
+                    if score.quarantined_until.is_some() { 0.0 } else { score.score() }
+                }
+                None => PeerScore::new(now).score()
+            })
+            .collect()
+    }
+
+    fn choose_at(&self, candidates: &[Arc<KeyId>], now: Instant) -> Option<usize> {
+        if candidates.is_empty() {
+            return None
+        }
+        let weights = self.weights(candidates, now);
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            // Everyone is quarantined, ask the one which is released first
+            let peers = self.peers.lock();
+            return candidates.iter()
+                .enumerate()
+                .min_by_key(|(_, peer)| peers.get(*peer).and_then(|score| score.quarantined_until))
+                .map(|(i, _)| i)
+        }
+        let mut point = self.rng.lock().gen::<f64>() * total;
+        for (i, weight) in weights.iter().enumerate() {
+            if *weight <= 0.0 {
+                continue
+            }
+            if point < *weight {
+                return Some(i)
+            }
+            point -= weight;
+        }
+        weights.iter().rposition(|weight| *weight > 0.0)
+    }
+
+    fn rank_at(&self, peers: &mut Vec<Arc<KeyId>>, now: Instant) {
+        peers.shuffle(&mut *self.rng.lock());
+        let weights = self.weights(peers, now);
+        let mut ranked = peers.drain(..).zip(weights).collect::<Vec<_>>();
+        ranked.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        peers.extend(ranked.into_iter().map(|(peer, _)| peer));
+    }
+
+}
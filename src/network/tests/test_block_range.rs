@@ -0,0 +1,159 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+fn query(from_seqno: u32, to_seqno: u32, max_bytes: u32) -> BlockRangeQuery {
+    BlockRangeQuery { shard: ShardIdent::masterchain(), from_seqno, to_seqno, max_bytes }
+}
+
+fn entry(seq_no: u32, size: usize) -> BlockRangeEntry {
+    BlockRangeEntry {
+        id: BlockIdExt::with_params(
+            ShardIdent::masterchain(),
+            seq_no,
+            UInt256::from([seq_no as u8; 32]),
+            UInt256::from([(seq_no as u8).wrapping_add(1); 32])
+        ),
+        block: vec![seq_no as u8; size],
+        proof: vec![0xAA; 10],
+        is_link: seq_no % 2 == 0,
+    }
+}
+
+// Serves applied blocks from `available`, as the node overlay service does
+fn serve(available: &[BlockRangeEntry], query: &BlockRangeQuery) -> Vec<u8> {
+    let mut builder = BlockRangeBuilder::new(query);
+    for entry in available.iter().filter(|entry| entry.id.seq_no() >= query.from_seqno) {
+        if !builder.push(entry.clone()) || builder.is_complete() {
+            break
+        }
+    }
+    pack_block_range(&builder.finish())
+}
+
+#[test]
+fn test_block_range_query_ids() {
+    let query = BlockRangeQuery {
+        shard: ShardIdent::with_tagged_prefix(0, 0x4000_0000_0000_0000).unwrap(),
+        from_seqno: 100,
+        to_seqno: 200,
+        max_bytes: 1 << 20,
+    };
+    let ids = query.to_block_ids();
+    assert_eq!(BlockRangeQuery::from_block_ids(&ids), Some(query));
+
+    // Real block ids are not a range query
+    let real = vec![entry(1, 0).id, entry(2, 0).id];
+    assert_eq!(BlockRangeQuery::from_block_ids(&real), None);
+    assert_eq!(BlockRangeQuery::from_block_ids(&ids[..1]), None);
+}
+
+#[test]
+fn test_block_range_pack_unpack() {
+    let query = query(10, 20, 1 << 20);
+    let range = BlockRange {
+        entries: (10..15).map(|seq_no| entry(seq_no, 100 * seq_no as usize)).collect(),
+        next_seqno: Some(15),
+    };
+    let data = pack_block_range(&range);
+    assert_eq!(unpack_block_range(&data, &query).unwrap(), range);
+
+    let empty = BlockRange::default();
+    assert_eq!(unpack_block_range(&pack_block_range(&empty), &query).unwrap(), empty);
+
+    // Truncated and extended answers are refused
+    assert!(unpack_block_range(&data[..data.len() - 1], &query).is_err());
+    let mut extended = data.clone();
+    extended.push(0);
+    assert!(unpack_block_range(&extended, &query).is_err());
+}
+
+#[test]
+fn test_block_range_unpack_checks_query() {
+    let range = BlockRange { entries: vec![entry(10, 10), entry(11, 10)], next_seqno: None };
+    let data = pack_block_range(&range);
+    assert!(unpack_block_range(&data, &query(10, 11, 1000)).is_ok());
+    // Not from the requested start
+    assert!(unpack_block_range(&data, &query(9, 11, 1000)).is_err());
+    // Over the requested end
+    assert!(unpack_block_range(&data, &query(10, 10, 1000)).is_err());
+    // Gap in seqnos
+    let range = BlockRange { entries: vec![entry(10, 10), entry(12, 10)], next_seqno: None };
+    assert!(unpack_block_range(&pack_block_range(&range), &query(10, 20, 1000)).is_err());
+    // Continuation must follow the last block
+    let range = BlockRange { entries: vec![entry(10, 10)], next_seqno: Some(12) };
+    assert!(unpack_block_range(&pack_block_range(&range), &query(10, 20, 1000)).is_err());
+    // Other shard
+    let mut other = entry(10, 10);
+    other.id = BlockIdExt::with_params(ShardIdent::full(0), 10, UInt256::default(), UInt256::default());
+    let range = BlockRange { entries: vec![other], next_seqno: None };
+    assert!(unpack_block_range(&pack_block_range(&range), &query(10, 20, 1000)).is_err());
+}
+
+#[test]
+fn test_block_range_builder_limits() {
+    // Byte limit
+    let mut builder = BlockRangeBuilder::new(&query(1, 100, 1000));
+    assert!(builder.push(entry(1, 400)));
+    assert!(builder.push(entry(2, 300)));
+    assert!(!builder.push(entry(3, 300)));
+    assert!(builder.is_complete());
+    let range = builder.finish();
+    assert_eq!(range.entries.len(), 2);
+    assert_eq!(range.next_seqno, Some(3));
+
+    // The first block is served over requested size
+    let mut builder = BlockRangeBuilder::new(&query(1, 100, 100));
+    assert!(builder.push(entry(1, 1000)));
+    assert!(!builder.push(entry(2, 1)));
+    assert_eq!(builder.finish().next_seqno, Some(2));
+
+    // Count limit
+    let mut builder = BlockRangeBuilder::new(&query(0, 1000, BLOCK_RANGE_MAX_BYTES));
+    for seq_no in 0..BLOCK_RANGE_MAX_COUNT {
+        assert!(builder.push(entry(seq_no, 1)));
+    }
+    assert!(builder.is_complete());
+    let range = builder.finish();
+    assert_eq!(range.entries.len(), BLOCK_RANGE_MAX_COUNT as usize);
+    assert_eq!(range.next_seqno, Some(BLOCK_RANGE_MAX_COUNT));
+
+    // Range end is reached, nothing to continue
+    let mut builder = BlockRangeBuilder::new(&query(5, 6, 1000));
+    assert!(builder.push(entry(5, 1)));
+    assert!(!builder.is_complete());
+    assert!(builder.push(entry(6, 1)));
+    assert!(builder.is_complete());
+    assert!(!builder.push(entry(7, 1)));
+    assert_eq!(builder.finish().next_seqno, None);
+}
+
+#[test]
+fn test_block_range_continuation() {
+    let available = (1..=50).map(|seq_no| entry(seq_no, 200 + seq_no as usize)).collect::<Vec<_>>();
+    let mut received = Vec::new();
+    let mut next = Some(1);
+    let mut requests = 0;
+    while let Some(from_seqno) = next {
+        let query = query(from_seqno, 60, 2000);
+        let range = unpack_block_range(&serve(&available, &query), &query).unwrap();
+        next = range.next_seqno;
+        received.extend(range.entries);
+        requests += 1;
+        assert!(requests < 50);
+    }
+    // Served up to the last applied block, though the range end is further
+    assert!(requests > 1);
+    assert_eq!(received, available);
+}
@@ -0,0 +1,158 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::ShardIdent;
+
+// Overlay which records what is sent through it
+struct MockOverlay {
+    neighbours: Vec<Arc<KeyId>>,
+    broadcasts: parking_lot::Mutex<u32>,
+    sent: parking_lot::Mutex<Vec<Arc<KeyId>>>,
+}
+
+impl MockOverlay {
+    fn new(neighbours: &[u8]) -> Self {
+        Self {
+            neighbours: neighbours.iter().map(|i| peer(*i)).collect(),
+            broadcasts: parking_lot::Mutex::new(0),
+            sent: parking_lot::Mutex::new(Vec::new()),
+        }
+    }
+    fn take_sent(&self) -> HashSet<Arc<KeyId>> {
+        std::mem::take(&mut *self.sent.lock()).into_iter().collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl FanoutTransport for MockOverlay {
+    fn neighbours(&self) -> Vec<Arc<KeyId>> {
+        self.neighbours.clone()
+    }
+    async fn broadcast_all(&self, _data: &[u8]) -> Result<u32> {
+        *self.broadcasts.lock() += 1;
+        Ok(self.neighbours.len() as u32)
+    }
+    async fn send_to(&self, peer: &Arc<KeyId>, _data: &[u8]) -> Result<()> {
+        self.sent.lock().push(peer.clone());
+        Ok(())
+    }
+}
+
+fn peer(i: u8) -> Arc<KeyId> {
+    KeyId::from_data([i; 32])
+}
+
+fn peers(ids: &[u8]) -> HashSet<Arc<KeyId>> {
+    ids.iter().map(|i| peer(*i)).collect()
+}
+
+fn fanout(max_fanout: usize, window: usize, ttl_sec: u32) -> BroadcastFanout {
+    BroadcastFanout::new(&BroadcastFanoutConfig { max_fanout, window, ttl_sec })
+}
+
+fn block_id(seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(
+        ShardIdent::masterchain(),
+        seq_no,
+        UInt256::with_array([seq_no as u8; 32]),
+        UInt256::with_array([!(seq_no as u8); 32])
+    )
+}
+
+#[tokio::test]
+async fn test_broadcast_fanout_skips_known() -> Result<()> {
+    let fanout = fanout(8, 16, 60);
+    let overlay = MockOverlay::new(&[1, 2, 3, 4, 5]);
+    fanout.record_known(&block_id(1), &peer(2));
+    fanout.record_known(&block_id(1), &peer(3));
+
+    assert_eq!(fanout.send(&overlay, &block_id(1), false, None, b"block").await?, 3);
+    assert_eq!(overlay.take_sent(), peers(&[1, 4, 5]));
+    assert_eq!(*overlay.broadcasts.lock(), 0);
+    assert_eq!(fanout.suppressed_known(), 2);
+
+    // Neighbours got the block from us
+    assert_eq!(fanout.send(&overlay, &block_id(1), false, None, b"block").await?, 0);
+    assert!(overlay.take_sent().is_empty());
+    assert_eq!(fanout.suppressed_known(), 7);
+
+    // Other block is sent to everyone
+    assert_eq!(fanout.send(&overlay, &block_id(2), false, None, b"block").await?, 5);
+    assert_eq!(overlay.take_sent(), peers(&[1, 2, 3, 4, 5]));
+    assert_eq!(fanout.suppressed_by_cap(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_broadcast_fanout_key_block() -> Result<()> {
+    let fanout = fanout(2, 16, 60);
+    let overlay = MockOverlay::new(&[1, 2, 3, 4, 5]);
+    for i in 1..=5 {
+        fanout.record_known(&block_id(1), &peer(i));
+    }
+    // Key block goes to all neighbours despite of known ones and the cap
+    assert_eq!(fanout.send(&overlay, &block_id(1), true, None, b"block").await?, 5);
+    assert_eq!(*overlay.broadcasts.lock(), 1);
+    assert!(overlay.take_sent().is_empty());
+    assert_eq!(fanout.full_fanouts(), 1);
+    assert_eq!(fanout.suppressed_known(), 0);
+    assert_eq!(fanout.suppressed_by_cap(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_broadcast_fanout_cap() -> Result<()> {
+    let fanout = fanout(2, 16, 60);
+    let overlay = MockOverlay::new(&[1, 2, 3, 4, 5]);
+    let quality = NeighboursQuality::new();
+    for _ in 0..20 {
+        for i in 1..=5 {
+            quality.record_broadcast(&peer(i), i >= 4);
+        }
+    }
+    // Neighbours which bring new blocks are preferred
+    assert_eq!(fanout.send(&overlay, &block_id(1), false, Some(&quality), b"block").await?, 2);
+    assert_eq!(overlay.take_sent(), peers(&[4, 5]));
+    assert_eq!(fanout.suppressed_by_cap(), 3);
+
+    // Random choice without quality, chosen ones are not repeated
+    assert_eq!(fanout.send(&overlay, &block_id(1), false, None, b"block").await?, 2);
+    let sent = overlay.take_sent();
+    assert!(sent.iter().all(|id| peers(&[1, 2, 3]).contains(id)));
+    assert_eq!(fanout.send(&overlay, &block_id(1), false, None, b"block").await?, 1);
+    let mut all = sent;
+    all.extend(overlay.take_sent());
+    assert_eq!(all, peers(&[1, 2, 3]));
+    assert_eq!(fanout.suppressed_known(), 2 + 4);
+    assert_eq!(fanout.suppressed_by_cap(), 4);
+    Ok(())
+}
+
+#[test]
+fn test_broadcast_fanout_window_and_ttl() {
+    let fanout = fanout(8, 2, 10);
+    let neighbours = vec![peer(1), peer(2)];
+    let now = Instant::now();
+    fanout.record_known_at(&block_id(1), &[peer(1)], now);
+    fanout.record_known_at(&block_id(2), &[peer(1)], now);
+    fanout.record_known_at(&block_id(3), &[peer(1)], now);
+    // The oldest block is evicted
+    assert_eq!(fanout.select_targets(&block_id(1), neighbours.clone(), None, now).len(), 2);
+    let targets = fanout.select_targets(&block_id(3), neighbours.clone(), None, now);
+    assert_eq!(targets, vec![peer(2)]);
+
+    // Known neighbours are forgotten after TTL
+    let later = now + Duration::from_secs(11);
+    assert_eq!(fanout.select_targets(&block_id(3), neighbours, None, later).len(), 2);
+}
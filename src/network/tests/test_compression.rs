@@ -0,0 +1,48 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::block::BlockStuff;
+
+#[test]
+fn test_block_compression_round_trip() {
+    let name = "src/tests/static/test_master_block_proof/key_block__3082181";
+    let block = BlockStuff::read_block_from_file(name).unwrap();
+
+    let compressed = compress_block(block.data(), DEFAULT_BLOCK_COMPRESSION_LEVEL)
+        .unwrap().expect("block must be compressible");
+    assert!(is_compressed_block(&compressed));
+    assert!(compressed.len() < block.data().len());
+
+    // File hash is checked against decompressed data
+    let data = decompress_block(compressed.clone()).unwrap();
+    let restored = BlockStuff::deserialize_block_checked(block.id().clone(), data).unwrap();
+    assert_eq!(restored.data(), block.data());
+
+    // Raw data from old peers passes through
+    let raw = decompress_block(block.data().to_vec()).unwrap();
+    assert_eq!(raw, block.data());
+
+    // Damaged data is rejected
+    let mut broken = compressed.clone();
+    broken[4] ^= 1;
+    assert!(decompress_block(broken).is_err());
+    let mut broken = compressed;
+    broken.truncate(broken.len() / 2);
+    assert!(decompress_block(broken).is_err());
+}
+
+#[test]
+fn test_block_compression_incompressible() {
+    assert!(compress_block(&[0xb5, 0xee, 0x9c, 0x72], DEFAULT_BLOCK_COMPRESSION_LEVEL).unwrap().is_none());
+}
@@ -0,0 +1,162 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use std::time::Instant;
+
+use ControlCommandCategory::{Admin, Gc, ReadOnly};
+
+fn limits(
+    category: ControlCommandCategory,
+    timeout_ms: u64,
+    max_concurrent: usize
+) -> ControlLimits {
+    let budget = ControlBudget { timeout_ms, max_concurrent };
+    let config = ControlLimitsConfig::from([(category, budget)]);
+    ControlLimits::new(Some(&config)).unwrap()
+}
+
+async fn slow_command(delay_ms: u64) -> Result<u64> {
+    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    Ok(delay_ms)
+}
+
+fn is_busy(result: &Result<ControlPermit>) -> bool {
+    matches!(
+        result.as_ref().err().and_then(|err| err.downcast_ref::<NodeError>()),
+        Some(NodeError::ControlBusy { .. })
+    )
+}
+
+fn stats(key: &str) -> Stats {
+    Stats {
+        stats: vec![
+            ton_api::ton::engine::validator::onestat::OneStat {
+                key: key.to_string(),
+                value: "1".to_string()
+            }
+        ].into()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_control_limits_concurrency_cap() {
+    let limits = Arc::new(limits(ReadOnly, 0, 2));
+
+    // Two slow commands take all the permits of the category
+    let mut running = Vec::new();
+    for _ in 0..2 {
+        let permit = limits.admit("GetStats", ReadOnly).unwrap();
+        running.push(tokio::spawn(async move { permit.run(slow_command(300)).await }));
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(is_busy(&limits.admit("GetStats", ReadOnly)));
+    // Other categories have their own permits
+    limits.admit("SetStatesGcInterval", Gc).unwrap();
+
+    for command in running {
+        assert_eq!(command.await.unwrap().unwrap(), 300);
+    }
+    // Permits are returned after the commands are finished
+    let _permits = [
+        limits.admit("GetStats", ReadOnly).unwrap(),
+        limits.admit("GetStats", ReadOnly).unwrap()
+    ];
+    assert!(is_busy(&limits.admit("GetStats", ReadOnly)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_control_limits_timeout() {
+    let limits = limits(Admin, 100, 1);
+
+    let permit = limits.admit("GetBundle", Admin).unwrap();
+    let started = Instant::now();
+    let err = permit.run(slow_command(5_000)).await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_millis(2_000));
+    match err.downcast_ref::<NodeError>() {
+        Some(NodeError::ControlTimeout { command, timeout_ms }) => {
+            assert_eq!(command, "GetBundle");
+            assert_eq!(*timeout_ms, 100);
+        }
+        _ => panic!("unexpected error {}", err)
+    }
+    // Permit of aborted command is returned with it
+    drop(permit);
+    let permit = limits.admit("GetBundle", Admin).unwrap();
+    assert_eq!(permit.run(slow_command(10)).await.unwrap(), 10);
+
+    // Handler error is passed as is
+    let err = permit.run(async { Err::<(), _>(error!("handler failed")) }).await.unwrap_err();
+    assert!(err.downcast_ref::<NodeError>().is_none());
+    assert_eq!(err.to_string(), "handler failed");
+}
+
+#[test]
+fn test_control_limits_config() {
+    // Zero timeout disables it, but zero concurrency would refuse all
+    let budget = ControlBudget { timeout_ms: 0, max_concurrent: 0 };
+    let config = ControlLimitsConfig::from([(Gc, budget)]);
+    assert!(ControlLimits::new(Some(&config)).is_err());
+
+    let config: ControlLimitsConfig = serde_json::from_str(
+        r#"{ "ReadOnly": { "timeout_ms": 5000 }, "Admin": { "max_concurrent": 1 } }"#
+    ).unwrap();
+    assert_eq!(config[&ReadOnly], ControlBudget { timeout_ms: 5000, max_concurrent: 16 });
+    assert_eq!(config[&Admin].max_concurrent, 1);
+    ControlLimits::new(Some(&config)).unwrap();
+    ControlLimits::new(None).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_detached_commands() {
+    let detached = DetachedCommands::new();
+    let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+    let ticket = detached.spawn("db_consistency_check", async move {
+        receiver.await.map_err(|_| error!("cancelled"))?;
+        Ok(stats("checked_handles"))
+    });
+    // Command in flight is not started again
+    assert_eq!(detached.spawn("db_consistency_check", async { Ok(stats("other")) }), ticket);
+    let (command, status) = detached.status(ticket).unwrap();
+    assert_eq!(command, "db_consistency_check");
+    assert!(matches!(status, DetachedStatus::Running));
+
+    sender.send(()).unwrap();
+    let status = loop {
+        match detached.status(ticket).unwrap().1 {
+            DetachedStatus::Running => tokio::time::sleep(Duration::from_millis(10)).await,
+            status => break status
+        }
+    };
+    match status {
+        DetachedStatus::Finished(result) => assert_eq!(result.stats[0].key, "checked_handles"),
+        status => panic!("unexpected status {:?}", status)
+    }
+
+    // Failed command keeps its error, finished command may be started again
+    let failed = detached.spawn("db_consistency_check", async { fail!("db is broken") });
+    assert_ne!(failed, ticket);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    match detached.status(failed).unwrap().1 {
+        DetachedStatus::Failed(error) => assert_eq!(error, "db is broken"),
+        status => panic!("unexpected status {:?}", status)
+    }
+    assert!(detached.status(1000).is_err());
+
+    // Only the latest results are kept
+    for _ in 0..DETACHED_RESULTS_LEN {
+        detached.spawn("db_consistency_fix", async { Ok(stats("repaired")) });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(detached.status(ticket).is_err());
+}
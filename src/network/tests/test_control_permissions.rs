@@ -0,0 +1,148 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::{base64_encode, KeyOption};
+
+use ControlCommandCategory::{Admin, Gc, ReadOnly, ValidatorOps};
+
+const ALL: [ControlCommandCategory; 4] = [ReadOnly, Gc, ValidatorOps, Admin];
+
+fn gen_key() -> (String, Arc<KeyId>) {
+    let key = Ed25519KeyOption::generate().unwrap();
+    (base64_encode(key.pub_key().unwrap()), key.id().clone())
+}
+
+fn is_denied(result: Result<()>) -> bool {
+    matches!(
+        result.unwrap_err().downcast_ref::<NodeError>(),
+        Some(NodeError::PermissionDenied { .. })
+    )
+}
+
+#[test]
+fn test_control_permissions_by_category() {
+    let (monitoring_key, monitoring) = gen_key();
+    let (operator_key, operator) = gen_key();
+    let (admin_key, admin) = gen_key();
+    let (_, unknown) = gen_key();
+    let config = ControlPermissionsConfig::from([
+        (monitoring_key, [ReadOnly].into()),
+        (operator_key, [ReadOnly, Gc, ValidatorOps].into()),
+        (admin_key, [Admin].into()),
+    ]);
+    let permissions = ControlPermissions::new(Some(&config)).unwrap();
+
+    for category in ALL {
+        let command = format!("{:?}Command", category);
+        let monitoring_result = permissions.check(&monitoring, &command, category);
+        if category == ReadOnly {
+            monitoring_result.unwrap();
+        } else {
+            assert!(is_denied(monitoring_result));
+        }
+        let operator_result = permissions.check(&operator, &command, category);
+        if category == Admin {
+            assert!(is_denied(operator_result));
+        } else {
+            operator_result.unwrap();
+        }
+        // Admin may run everything, unknown key may run nothing
+        permissions.check(&admin, &command, category).unwrap();
+        assert!(is_denied(permissions.check(&unknown, &command, category)));
+    }
+    assert_eq!(permissions.denied_count(&monitoring, "GcCommand"), 1);
+    assert_eq!(permissions.denied_count(&monitoring, "ReadOnlyCommand"), 0);
+    assert_eq!(permissions.denied_count(&operator, "AdminCommand"), 1);
+    assert_eq!(permissions.denied_count(&unknown, "ReadOnlyCommand"), 1);
+    permissions.check(&unknown, "ReadOnlyCommand", ReadOnly).unwrap_err();
+    assert_eq!(permissions.denied_count(&unknown, "ReadOnlyCommand"), 2);
+    assert_eq!(permissions.denied_counters().len(), 8);
+
+    // Without config every key may run everything
+    let permissions = ControlPermissions::new(None).unwrap();
+    for category in ALL {
+        permissions.check(&unknown, "Command", category).unwrap();
+    }
+    assert!(permissions.denied_counters().is_empty());
+}
+
+#[test]
+fn test_control_permissions_audit_log() {
+    let (key, key_id) = gen_key();
+    let config = ControlPermissionsConfig::from([(key.clone(), [ReadOnly, Gc].into())]);
+    let permissions = ControlPermissions::new(Some(&config)).unwrap();
+
+    // Allowed read-only commands are not audited
+    permissions.check(&key_id, "GetStats", ReadOnly).unwrap();
+    permissions.check(&key_id, "SetStatesGcInterval", Gc).unwrap();
+    permissions.check(&key_id, "Sign", ValidatorOps).unwrap_err();
+    let log = permissions.audit_log();
+    assert_eq!(log.len(), 2);
+    assert_eq!((log[0].command.as_str(), log[0].allowed), ("SetStatesGcInterval", true));
+    assert_eq!((log[1].command.as_str(), log[1].category, log[1].allowed), ("Sign", ValidatorOps, false));
+    assert_eq!(log[1].key_id, key_id.to_string());
+
+    // Only the latest records are kept
+    for i in 0..CONTROL_AUDIT_LOG_LEN {
+        permissions.check(&key_id, &format!("Command{}", i), Admin).unwrap_err();
+    }
+    let log = permissions.audit_log();
+    assert_eq!(log.len(), CONTROL_AUDIT_LOG_LEN);
+    assert_eq!(log[0].command, "Command0");
+    assert_eq!(log[CONTROL_AUDIT_LOG_LEN - 1].command, format!("Command{}", CONTROL_AUDIT_LOG_LEN - 1));
+}
+
+#[test]
+fn test_control_permissions_update() {
+    let (key, key_id) = gen_key();
+    let permissions = ControlPermissions::new(
+        Some(&ControlPermissionsConfig::from([(key.clone(), [ReadOnly].into())]))
+    ).unwrap();
+    assert!(is_denied(permissions.check(&key_id, "GcCommand", Gc)));
+
+    permissions.update(Some(&ControlPermissionsConfig::from([(key.clone(), [Gc].into())]))).unwrap();
+    permissions.check(&key_id, "GcCommand", Gc).unwrap();
+    assert!(is_denied(permissions.check(&key_id, "ReadOnlyCommand", ReadOnly)));
+
+    // Invalid config is rejected as a whole
+    let invalid = ControlPermissionsConfig::from([
+        (key.clone(), [Admin].into()),
+        ("not a key".to_string(), [Admin].into()),
+    ]);
+    permissions.update(Some(&invalid)).unwrap_err();
+    let short = ControlPermissionsConfig::from([(base64_encode([1u8; 16]), [Admin].into())]);
+    permissions.update(Some(&short)).unwrap_err();
+    assert!(ControlPermissions::new(Some(&short)).is_err());
+    permissions.check(&key_id, "GcCommand", Gc).unwrap();
+    assert!(is_denied(permissions.check(&key_id, "AdminCommand", Admin)));
+
+    permissions.update(None).unwrap();
+    permissions.check(&key_id, "AdminCommand", Admin).unwrap();
+}
+
+#[test]
+fn test_control_permissions_read_only_mode() {
+    let (_, unknown) = gen_key();
+    let permissions = ControlPermissions::new(None).unwrap().with_read_only(true);
+    permissions.check(&unknown, "GetStats", ReadOnly).unwrap();
+    for category in [Gc, ValidatorOps, Admin] {
+        let err = permissions.check(&unknown, "Command", category).unwrap_err();
+        assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::ReadOnlyMode { .. })));
+        assert_eq!(err.to_string(), "Command is not allowed: node is running in read-only mode");
+    }
+    // Not permitted key is still denied as usual
+    let config = ControlPermissionsConfig::new();
+    let permissions = ControlPermissions::new(Some(&config)).unwrap().with_read_only(true);
+    assert!(is_denied(permissions.check(&unknown, "Command", Gc)));
+}
@@ -0,0 +1,148 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+// Overlay with active neighbours and other known peers
+struct MockTransport {
+    active: parking_lot::Mutex<Vec<Arc<KeyId>>>,
+    candidates: Vec<Arc<KeyId>>,
+}
+
+impl MockTransport {
+    fn new(active: &[u8], candidates: &[u8]) -> Self {
+        Self {
+            active: parking_lot::Mutex::new(active.iter().map(|i| peer(*i)).collect()),
+            candidates: candidates.iter().map(|i| peer(*i)).collect(),
+        }
+    }
+}
+
+impl NeighboursTransport for MockTransport {
+    fn active_neighbours(&self) -> Vec<Arc<KeyId>> {
+        self.active.lock().clone()
+    }
+    fn replacement_candidates(&self) -> Result<Vec<Arc<KeyId>>> {
+        Ok(self.candidates.clone())
+    }
+    fn replace_neighbour(&self, old: &Arc<KeyId>, new: Arc<KeyId>) -> Result<bool> {
+        let mut active = self.active.lock();
+        match active.iter().position(|id| id == old) {
+            Some(i) => {
+                active[i] = new;
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+}
+
+fn peer(i: u8) -> Arc<KeyId> {
+    KeyId::from_data([i; 32])
+}
+
+fn config(min_neighbours: u32, min_samples: u32) -> NeighboursRebalanceConfig {
+    NeighboursRebalanceConfig { interval_sec: 1, min_neighbours, min_samples }
+}
+
+// Every active neighbour answers fast, except given bad one which mostly fails
+fn record_queries(quality: &NeighboursQuality, active: &[u8], bad: u8, count: usize) {
+    for _ in 0..count {
+        for i in active {
+            if *i == bad {
+                quality.record_query(&peer(*i), 2000, false);
+            } else {
+                quality.record_query(&peer(*i), 100, true);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_neighbours_rebalance_rotates_worst() -> Result<()> {
+    let quality = NeighboursQuality::new();
+    let transport = MockTransport::new(&[1, 2, 3, 4], &[1, 2, 5, 6]);
+    record_queries(&quality, &[1, 2, 3, 4], 3, 30);
+    // Peer 6 is known to be fast, peer 5 is unknown
+    record_queries(&quality, &[6], 0, 5);
+
+    let rotated = rebalance_neighbours(&quality, &transport, &config(4, 20))?;
+    assert_eq!(rotated, Some((peer(3), peer(6))));
+    assert_eq!(transport.active_neighbours(), [peer(1), peer(2), peer(6), peer(4)]);
+    Ok(())
+}
+
+#[test]
+fn test_neighbours_rebalance_limits() -> Result<()> {
+    let quality = NeighboursQuality::new();
+    record_queries(&quality, &[1, 2, 3, 4], 3, 10);
+
+    // Too few neighbours
+    let transport = MockTransport::new(&[1, 2, 3, 4], &[5]);
+    assert_eq!(rebalance_neighbours(&quality, &transport, &config(5, 5))?, None);
+    // Too few samples of the bad neighbour
+    assert_eq!(rebalance_neighbours(&quality, &transport, &config(4, 20))?, None);
+    // No candidates except active neighbours
+    let transport = MockTransport::new(&[1, 2, 3, 4], &[1, 2, 3, 4]);
+    assert_eq!(rebalance_neighbours(&quality, &transport, &config(4, 5))?, None);
+
+    // Candidate is not better than the worst neighbour
+    let quality = NeighboursQuality::new();
+    record_queries(&quality, &[1, 2, 3, 4], 0, 30);
+    let transport = MockTransport::new(&[1, 2, 3, 4], &[5]);
+    assert_eq!(rebalance_neighbours(&quality, &transport, &config(4, 20))?, None);
+    assert_eq!(transport.active_neighbours(), [peer(1), peer(2), peer(3), peer(4)]);
+    Ok(())
+}
+
+#[test]
+fn test_neighbours_rebalance_keeps_score() -> Result<()> {
+    let quality = NeighboursQuality::new();
+    let transport = MockTransport::new(&[1, 2], &[3]);
+    record_queries(&quality, &[1, 2], 2, 30);
+    let bad_score = quality.quality(&peer(2)).score;
+
+    assert_eq!(rebalance_neighbours(&quality, &transport, &config(2, 20))?, Some((peer(2), peer(3))));
+    // Rotated out peer is still scored and rotated back only instead of a worse neighbour
+    assert_eq!(quality.quality(&peer(2)).score, bad_score);
+    let transport = MockTransport::new(&[1, 3], &[2]);
+    record_queries(&quality, &[1, 3], 0, 30);
+    assert_eq!(rebalance_neighbours(&quality, &transport, &config(2, 20))?, None);
+    Ok(())
+}
+
+#[test]
+fn test_neighbours_quality_score() {
+    let quality = NeighboursQuality::new();
+    let neutral = quality.quality(&peer(1));
+    assert_eq!(neutral.samples, 0);
+    assert!((neutral.score - 0.5).abs() < 1e-9);
+
+    for _ in 0..50 {
+        quality.record_broadcast(&peer(1), true);
+        quality.record_broadcast(&peer(2), false);
+    }
+    let novel = quality.quality(&peer(1));
+    let stale = quality.quality(&peer(2));
+    assert_eq!(novel.samples, 50);
+    assert!(novel.novelty > NEUTRAL_NOVELTY && stale.novelty < NEUTRAL_NOVELTY);
+    assert!(novel.score > neutral.score && stale.score < neutral.score);
+
+    // Failures don't change latency estimation
+    quality.record_query(&peer(1), 10_000, false);
+    assert_eq!(quality.quality(&peer(1)).latency_ms, novel.latency_ms);
+    assert!(quality.quality(&peer(1)).failure_rate > 0.0);
+
+    let report = quality.report(&[peer(2), peer(1)]);
+    assert!(report.lines().next().unwrap().starts_with(&peer(1).to_string()));
+}
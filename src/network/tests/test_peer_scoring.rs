@@ -0,0 +1,160 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use rand::SeedableRng;
+
+// Answers of the peers: None means the peer always serves broken data
+struct MockOverlay {
+    peers: Vec<(Arc<KeyId>, Option<Duration>)>,
+}
+
+impl MockOverlay {
+
+    fn new() -> Self {
+        Self {
+            peers: vec!(
+                (KeyId::from_data([1; 32]), None),
+                (KeyId::from_data([2; 32]), Some(Duration::from_millis(3000))),
+                (KeyId::from_data([3; 32]), Some(Duration::from_millis(100))),
+            )
+        }
+    }
+
+    fn ids(&self) -> Vec<Arc<KeyId>> {
+        self.peers.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    // Makes one download and returns index of the peer which was asked
+    fn download(&self, scoring: &PeerScoring, now: Instant) -> usize {
+        let i = scoring.choose_at(&self.ids(), now).unwrap();
+        let (peer, answer) = &self.peers[i];
+        scoring.record(peer, *answer, now);
+        i
+    }
+
+}
+
+#[test]
+fn test_peer_scoring_traffic_shift() {
+    let overlay = MockOverlay::new();
+    let scoring = PeerScoring::with_rng(StdRng::seed_from_u64(1));
+    let start = Instant::now();
+    let mut now = start;
+
+    let mut counts = [0; 3];
+    for i in 0..300 {
+        let peer = overlay.download(&scoring, now);
+        if i >= 100 {
+            counts[peer] += 1;
+        }
+        now += Duration::from_secs(1);
+    }
+
+    // Failing peer is only probed after quarantine, slow one gets a small share
+    assert!(counts[0] <= 200 / QUARANTINE_TIME.as_secs() as usize + 1, "{:?}", counts);
+    assert!(counts[1] > 0, "{:?}", counts);
+    assert!(counts[2] > 4 * counts[1], "{:?}", counts);
+
+    let ids = overlay.ids();
+    assert!(scoring.is_quarantined_at(&ids[0], now));
+    assert!(scoring.score_at(&ids[2], now) > scoring.score_at(&ids[1], now));
+    let mut ranked = vec!(ids[0].clone(), ids[1].clone(), ids[2].clone());
+    scoring.rank_at(&mut ranked, now);
+    assert_eq!(ranked, vec!(ids[2].clone(), ids[1].clone(), ids[0].clone()));
+
+    // Same seed gives the same traffic
+    let scoring2 = PeerScoring::with_rng(StdRng::seed_from_u64(1));
+    let mut now2 = start;
+    let mut counts2 = [0; 3];
+    for i in 0..300 {
+        let peer = overlay.download(&scoring2, now2);
+        if i >= 100 {
+            counts2[peer] += 1;
+        }
+        now2 += Duration::from_secs(1);
+    }
+    assert_eq!(counts, counts2);
+}
+
+#[test]
+fn test_peer_scoring_quarantine_and_decay() {
+    let scoring = PeerScoring::with_rng(StdRng::seed_from_u64(2));
+    let bad = KeyId::from_data([1; 32]);
+    let good = KeyId::from_data([2; 32]);
+    let mut now = Instant::now();
+
+    for _ in 0..QUARANTINE_FAILURES - 1 {
+        scoring.record(&bad, None, now);
+    }
+    assert!(!scoring.is_quarantined_at(&bad, now));
+    scoring.record(&bad, None, now);
+    assert!(scoring.is_quarantined_at(&bad, now));
+    let quarantined_score = scoring.score_at(&bad, now);
+
+    // Quarantined peer is not chosen while there are others
+    let candidates = vec!(bad.clone(), good.clone());
+    for _ in 0..20 {
+        assert_eq!(scoring.choose_at(&candidates, now), Some(1));
+    }
+    // But it is asked when there is no one else
+    assert_eq!(scoring.choose_at(&candidates[..1], now), Some(0));
+    assert_eq!(scoring.choose_at(&[], now), None);
+
+    // Quarantine is over and failures are forgotten over time
+    now += QUARANTINE_TIME;
+    assert!(!scoring.is_quarantined_at(&bad, now));
+    now += SCORE_HALF_LIFE * 4;
+    assert!(scoring.score_at(&bad, now) > quarantined_score);
+
+    // Recovered peer is trusted again after success, another failure quarantines it at once
+    scoring.record(&bad, None, now);
+    assert!(scoring.is_quarantined_at(&bad, now));
+    now += QUARANTINE_TIME;
+    scoring.record(&bad, Some(Duration::from_millis(200)), now);
+    scoring.record(&bad, None, now);
+    assert!(!scoring.is_quarantined_at(&bad, now));
+}
+
+#[test]
+fn test_peer_scoring_rejected_broadcasts() {
+    let scoring = PeerScoring::with_rng(StdRng::seed_from_u64(3));
+    let peer = KeyId::from_data([1; 32]);
+    let now = Instant::now();
+    let score = scoring.score_at(&peer, now);
+    assert_eq!(scoring.rejected_broadcasts(&peer), 0);
+
+    for _ in 0..QUARANTINE_FAILURES {
+        scoring.record_rejected_broadcast(&peer);
+    }
+    assert_eq!(scoring.rejected_broadcasts(&peer), QUARANTINE_FAILURES as u64);
+    // Downloads from the peer are not affected
+    assert!(!scoring.is_quarantined_at(&peer, now));
+    assert_eq!(scoring.score_at(&peer, now), score);
+}
+
+#[test]
+fn test_peer_scoring_quarantine_at_once() {
+    let scoring = PeerScoring::with_rng(StdRng::seed_from_u64(4));
+    let bad = KeyId::from_data([1; 32]);
+    let good = KeyId::from_data([2; 32]);
+    let now = Instant::now();
+
+    scoring.quarantine(&bad);
+    assert!(scoring.is_quarantined_at(&bad, now));
+    assert!(scoring.score_at(&bad, now) < scoring.score_at(&good, now));
+    for _ in 0..10 {
+        assert_eq!(scoring.choose_at(&[bad.clone(), good.clone()], now), Some(1));
+    }
+    assert!(!scoring.is_quarantined_at(&bad, now + QUARANTINE_TIME + Duration::from_secs(1)));
+}
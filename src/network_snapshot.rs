@@ -0,0 +1,171 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::shard_state::ShardStateStuff;
+
+use ever_block::{
+    error, BlockIdExt, Ed25519KeyOption, KeyOption, Result, Serializable, UInt256
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/test_network_snapshot.rs"]
+mod tests;
+
+// Increased on any change of the snapshot fields or their meaning
+pub const NETWORK_SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotTopBlock {
+    pub shard: String,
+    pub seq_no: u32,
+    pub root_hash: String,
+    pub file_hash: String,
+    // Newer top block known from shard blocks pool, but not committed to the masterchain yet
+    pub pending_seq_no: Option<u32>,
+}
+
+// Where the network is by the last applied masterchain block. Serialized data is signed,
+// so it is deterministic: fields go in the declaration order and top blocks are sorted.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct NetworkSnapshot {
+    pub version: u32,
+    pub created_at: u32,
+    pub mc_seq_no: u32,
+    pub mc_root_hash: String,
+    pub mc_file_hash: String,
+    pub config_version: u32,
+    pub capabilities: u64,
+    // Hash of the current validator set (config param 34)
+    pub validator_set_hash: String,
+    pub top_blocks: Vec<SnapshotTopBlock>,
+}
+
+impl NetworkSnapshot {
+
+    pub fn new(
+        mc_block_id: &BlockIdExt,
+        config_version: u32,
+        capabilities: u64,
+        validator_set_hash: &UInt256,
+        top_blocks: &[BlockIdExt],
+        pending_blocks: &[BlockIdExt],
+        created_at: u32,
+    ) -> Self {
+        let mut top_blocks = top_blocks.iter()
+            .map(|id| {
+                let pending_seq_no = pending_blocks.iter()
+                    .filter(|pending| pending.shard() == id.shard())
+                    .map(|pending| pending.seq_no())
+                    .filter(|seq_no| *seq_no > id.seq_no())
+                    .max();
+                (id, pending_seq_no)
+            })
+            .collect::<Vec<_>>();
+        top_blocks.sort_by_key(|(id, _)| {
+            (id.shard().workchain_id(), id.shard().shard_prefix_with_tag())
+        });
+        Self {
+            version: NETWORK_SNAPSHOT_VERSION,
+            created_at,
+            mc_seq_no: mc_block_id.seq_no(),
+            mc_root_hash: format!("{:x}", mc_block_id.root_hash()),
+            mc_file_hash: format!("{:x}", mc_block_id.file_hash()),
+            config_version,
+            capabilities,
+            validator_set_hash: format!("{:x}", validator_set_hash),
+            top_blocks: top_blocks.into_iter()
+                .map(|(id, pending_seq_no)| SnapshotTopBlock {
+                    shard: id.shard().to_string(),
+                    seq_no: id.seq_no(),
+                    root_hash: format!("{:x}", id.root_hash()),
+                    file_hash: format!("{:x}", id.file_hash()),
+                    pending_seq_no,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn from_mc_state(
+        mc_state: &ShardStateStuff,
+        pending_blocks: &[BlockIdExt],
+        created_at: u32
+    ) -> Result<Self> {
+        let config = mc_state.config_params()?;
+        let validator_set_hash = config.validator_set()?.serialize()?.repr_hash();
+        Ok(Self::new(
+            mc_state.block_id(),
+            config.global_version(),
+            config.capabilities(),
+            &validator_set_hash,
+            &mc_state.top_blocks_all()?,
+            pending_blocks,
+            created_at
+        ))
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedSnapshot {
+    pub data: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub public_key: Vec<u8>,
+}
+
+impl SignedSnapshot {
+    pub fn sign(snapshot: &NetworkSnapshot, key: &Arc<dyn KeyOption>) -> Result<Self> {
+        let data = snapshot.serialize()?;
+        let signature = key.sign(&data)?;
+        Ok(Self { data, signature, public_key: key.pub_key()?.to_vec() })
+    }
+}
+
+pub fn verify_snapshot(data: &[u8], signature: &[u8], public_key: &[u8]) -> Result<()> {
+    let public_key: &[u8; 32] = public_key.try_into().map_err(|_| {
+        error!("Bad public key of network snapshot: wrong length {}", public_key.len())
+    })?;
+    Ed25519KeyOption::from_public_key(public_key).verify(data, signature)
+        .map_err(|e| error!("Bad signature of network snapshot: {}", e))
+}
+
+// Snapshot is built once per masterchain block
+#[derive(Default)]
+pub struct NetworkSnapshotCache {
+    cached: parking_lot::Mutex<Option<(u32, SignedSnapshot)>>,
+}
+
+impl NetworkSnapshotCache {
+
+    pub fn get(&self, mc_seq_no: u32) -> Option<SignedSnapshot> {
+        match &*self.cached.lock() {
+            Some((seq_no, snapshot)) if *seq_no == mc_seq_no => Some(snapshot.clone()),
+            _ => None
+        }
+    }
+
+    // Snapshot of an older block never replaces the newer one
+    pub fn put(&self, mc_seq_no: u32, snapshot: SignedSnapshot) {
+        let mut cached = self.cached.lock();
+        if cached.as_ref().map_or(true, |(cached_seq_no, _)| *cached_seq_no <= mc_seq_no) {
+            *cached = Some((mc_seq_no, snapshot));
+        }
+    }
+}
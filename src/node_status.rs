@@ -0,0 +1,173 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::engine::Engine;
+
+use std::collections::BTreeMap;
+use ever_block::{BlockIdExt, ShardIdent};
+
+#[cfg(test)]
+#[path = "tests/test_node_status.rs"]
+mod tests;
+
+// Increased on any change of field names or their meaning, new fields are added
+// without increment. Absent values are serialized as nulls, fields are never omitted
+pub const NODE_STATUS_SCHEMA_VERSION: u32 = 1;
+
+pub fn sync_status_name(sync_status: u32) -> &'static str {
+    match sync_status {
+        Engine::SYNC_STATUS_START_BOOT => "start_boot",
+        Engine::SYNC_STATUS_LOAD_MASTER_STATE => "load_master_state",
+        Engine::SYNC_STATUS_LOAD_SHARD_STATES => "load_shard_states",
+        Engine::SYNC_STATUS_FINISH_BOOT => "finish_boot",
+        Engine::SYNC_STATUS_SYNC_BLOCKS => "synchronization_by_blocks",
+        Engine::SYNC_STATUS_FINISH_SYNC => "synchronization_finished",
+        Engine::SYNC_STATUS_CHECKING_DB => "checking_db",
+        Engine::SYNC_STATUS_DB_BROKEN => "db_broken",
+        _ => "no_set_status"
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockStatus {
+    pub shard: String,
+    pub seq_no: u32,
+    pub root_hash: String,
+    // None if block handle is not found
+    pub gen_utime: Option<u32>,
+    pub lag_sec: Option<u32>,
+}
+
+impl BlockStatus {
+    pub fn new(id: &BlockIdExt, gen_utime: Option<u32>, now: u32) -> Self {
+        Self {
+            shard: id.shard().to_string(),
+            seq_no: id.seq_no(),
+            root_hash: format!("{:x}", id.root_hash()),
+            gen_utime,
+            lag_sec: gen_utime.map(|gen_utime| now.saturating_sub(gen_utime)),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidatorStatus {
+    pub validation_status: String,
+    // Keyed by shard, None if the node has never validated or collated for the shard
+    pub last_validation_ago_sec: BTreeMap<String, Option<u64>>,
+    pub last_collation_ago_sec: BTreeMap<String, Option<u64>>,
+}
+
+impl ValidatorStatus {
+    pub fn ago_sec(map: &lockfree::map::Map<ShardIdent, u64>, now: u32) -> BTreeMap<String, Option<u64>> {
+        map.iter()
+            .map(|item| {
+                let ago = match *item.val() {
+                    0 => None,
+                    time => Some((now as u64).saturating_sub(time))
+                };
+                (item.key().to_string(), ago)
+            })
+            .collect()
+    }
+}
+
+// Queue depths are taken from telemetry, so they are None without telemetry feature
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RempStatus {
+    pub incoming_queue: Option<u64>,
+    pub response_queue: Option<u64>,
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageStatus {
+    pub pinned_states: Option<usize>,
+    pub pinned_states_pins: Option<u32>,
+    // By the last archives GC pass, None if GC by watermarks is off or has not run yet
+    pub used_bytes: Option<u64>,
+    pub high_watermark_bytes: Option<u64>,
+    // Rebuildable columns found corrupted, slow path is used until they are rebuilt
+    pub degraded_columns: Vec<String>,
+}
+
+// Block broadcasts not sent to neighbours since start
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BroadcastFanoutStatus {
+    // Neighbours known to have the block
+    pub suppressed_known: u64,
+    pub suppressed_by_cap: u64,
+    // Key blocks broadcast to all neighbours
+    pub full_fanouts: u64,
+}
+
+// Status of the node assembled from its workers state and telemetry,
+// it is dumped as single line JSON by the periodic log and the control query
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct NodeStatus {
+    pub schema_version: u32,
+    pub node_version: String,
+    pub timestamp: u32,
+    pub sync_status: String,
+    pub last_applied_mc_block: Option<BlockStatus>,
+    pub last_known_mc_seq_no: u32,
+    // Masterchain blocks known from the network, but not applied yet
+    pub mc_seq_no_lag: Option<u32>,
+    pub shard_client_mc_block: Option<BlockStatus>,
+    // Top shard blocks of the shard client masterchain block
+    pub shards: Option<Vec<BlockStatus>>,
+    pub pss_keeper_mc_block: Option<BlockStatus>,
+    pub archives_gc_mc_block: Option<BlockStatus>,
+    // None without external_db feature
+    pub external_db_mc_block: Option<BlockStatus>,
+    pub validator: ValidatorStatus,
+    // None if REMP is not enabled
+    pub remp: Option<RempStatus>,
+    pub storage: StorageStatus,
+    // Count of neighbours by overlay
+    pub peers: BTreeMap<String, usize>,
+    // None if block broadcast fanout is not configured
+    pub block_broadcast_fanout: Option<BroadcastFanoutStatus>,
+    // Estimated offset of the local clock, None if time service is not configured
+    pub clock_drift_ms: Option<i64>,
+}
+
+impl NodeStatus {
+
+    pub fn new(sync_status: u32, now: u32) -> Self {
+        Self {
+            schema_version: NODE_STATUS_SCHEMA_VERSION,
+            node_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: now,
+            sync_status: sync_status_name(sync_status).to_string(),
+            last_applied_mc_block: None,
+            last_known_mc_seq_no: 0,
+            mc_seq_no_lag: None,
+            shard_client_mc_block: None,
+            shards: None,
+            pss_keeper_mc_block: None,
+            archives_gc_mc_block: None,
+            external_db_mc_block: None,
+            validator: ValidatorStatus::default(),
+            remp: None,
+            storage: StorageStatus::default(),
+            peers: BTreeMap::new(),
+            block_broadcast_fanout: None,
+            clock_drift_ms: None,
+        }
+    }
+
+    pub fn to_json_line(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+}
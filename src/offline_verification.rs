@@ -0,0 +1,144 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+
+// Verification of proofs given as bytes: no engine, storage or runtime is needed,
+// so the functions may be called by crate consumers from any context
+
+use crate::{block_proof::BlockProofStuff, boot::check_trusted_key_block, error::NodeError};
+
+use ever_block::{
+    error, fail, BlockIdExt, BlockProof, BocReader, Deserializable, MerkleProof, Result, UInt256
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/test_offline_verification.rs"]
+mod tests;
+
+/// The newest block proved by the proof chain
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct VerifiedHead {
+    /// Last block of the chain, masterchain or shard one
+    pub id: BlockIdExt,
+    /// Last masterchain block of the chain
+    pub mc_block: BlockIdExt,
+    /// Newest key block of the chain, the trusted one if there are no others
+    pub key_block: BlockIdExt,
+    pub gen_utime: u32,
+}
+
+fn read_proof(data: &[u8]) -> Result<BlockProofStuff> {
+    let id = BlockProof::construct_from_bytes(data)?.proof_for;
+    let is_link = !id.is_masterchain();
+    BlockProofStuff::deserialize(&id, data.to_vec(), is_link)
+}
+
+// Shard block must be listed in shard hashes of the masterchain block. Proofs created by
+// accept_block have shard hashes pruned, so such proofs must be built with them included
+fn check_shard_block_link(mc_proof: &BlockProofStuff, shard_proof: &BlockProofStuff) -> Result<()> {
+    shard_proof.check_proof_link()?;
+    let id = shard_proof.id();
+    let (mc_block, _) = mc_proof.virtualize_block()?;
+    let mc_extra = mc_block.read_extra()?.read_custom()?.ok_or_else(
+        || error!(NodeError::InvalidData(format!(
+            "proof for masterchain block {} doesn't contain masterchain block extra", mc_proof.id()
+        )))
+    )?;
+    let descr = mc_extra.shards().get_shard(id.shard())?.ok_or_else(
+        || error!(NodeError::InvalidProof(format!(
+            "masterchain block {} doesn't list shard {}", mc_proof.id(), id.shard()
+        )))
+    )?.descr;
+    if descr.seq_no != id.seq_no || descr.root_hash != id.root_hash || descr.file_hash != id.file_hash {
+        fail!(NodeError::InvalidProof(format!(
+            "masterchain block {} doesn't commit block {}: shard {} top block has seqno {}, root hash {:x}",
+            mc_proof.id(), id, id.shard(), descr.seq_no, descr.root_hash
+        )))
+    }
+    Ok(())
+}
+
+/// Verifies the chain which starts from the proof of the trusted key block, then goes the chain
+/// built by `build_proof_chain`: proofs of key blocks from the oldest one, proof of masterchain
+/// block and proof link of shard block. Each masterchain proof is signed by validators of the
+/// previous key block, shard block must be committed by the masterchain block before it.
+pub fn verify_block_proof_chain(proofs: &[Vec<u8>], trusted_key_block: &BlockIdExt) -> Result<VerifiedHead> {
+    let (trusted, chain) = proofs.split_first().ok_or_else(
+        || error!(NodeError::InvalidArg("proof chain is empty".to_string()))
+    )?;
+    let mut key_proof = read_proof(trusted)?;
+    // Trusted block is committed by its hashes, so its signatures are not checked
+    check_trusted_key_block(trusted_key_block, &key_proof)?;
+    key_proof.check_proof_as_link()?;
+    let mut head = VerifiedHead {
+        id: trusted_key_block.clone(),
+        mc_block: trusted_key_block.clone(),
+        key_block: trusted_key_block.clone(),
+        gen_utime: key_proof.virtualize_block()?.0.read_info()?.gen_utime().as_u32(),
+    };
+    let mut mc_proof = None;
+    for data in chain {
+        let proof = read_proof(data)?;
+        // Only shard block may go after non key masterchain block, and nothing goes after it
+        if !head.id.is_masterchain() || (!proof.is_link() && head.mc_block != head.key_block) {
+            fail!(NodeError::InvalidData(format!(
+                "proof chain goes on with block {} after non key block {}", proof.id(), head.id
+            )))
+        }
+        if proof.is_link() {
+            check_shard_block_link(mc_proof.as_ref().unwrap_or(&key_proof), &proof)?;
+        } else {
+            proof.check_with_prev_key_block_proof(&key_proof)?;
+        }
+        let info = proof.virtualize_block()?.0.read_info()?;
+        head.id = proof.id().clone();
+        head.gen_utime = info.gen_utime().as_u32();
+        if info.key_block() {
+            head.mc_block = head.id.clone();
+            head.key_block = head.id.clone();
+            key_proof = proof;
+        } else if !proof.is_link() {
+            head.mc_block = head.id.clone();
+            mc_proof = Some(proof);
+        }
+    }
+    Ok(head)
+}
+
+/// Checks both proofs and that the shard block is committed by the masterchain block,
+/// signatures of the masterchain block are not checked
+pub fn verify_shard_block_link(mc_proof: &[u8], shard_proof: &[u8]) -> Result<()> {
+    let mc_proof = read_proof(mc_proof)?;
+    mc_proof.check_proof_as_link()?;
+    let shard_proof = read_proof(shard_proof)?;
+    if !shard_proof.is_link() {
+        fail!(NodeError::InvalidArg(format!(
+            "proof for {} is given instead of shard block proof link", shard_proof.id()
+        )))
+    }
+    check_shard_block_link(&mc_proof, &shard_proof)
+}
+
+/// Verifies that the Merkle proof (of the whole state or its part) has the expected state root
+pub fn verify_state_proof(proof: &[u8], expected_root: &UInt256) -> Result<()> {
+    let root = BocReader::new().read_inmem(Arc::new(proof.to_vec()))?.withdraw_single_root()?;
+    let merkle_proof = MerkleProof::construct_from_cell(root)?;
+    let virt_root_hash = merkle_proof.proof.clone().virtualize(1).repr_hash();
+    if merkle_proof.hash != *expected_root || virt_root_hash != *expected_root {
+        fail!(NodeError::InvalidProof(format!(
+            "state proof has root hash {:x}, {:x} is expected", virt_root_hash, expected_root
+        )))
+    }
+    Ok(())
+}
@@ -16,6 +16,7 @@
 #![allow(dead_code)]
 
 use openssl::rand::rand_bytes;
+use rand::{SeedableRng, rngs::StdRng};
 
 #[cfg(test)]
 #[path = "tests/test_random.rs"]
@@ -78,3 +79,8 @@ pub fn secure_256_bits() -> [u8; 32] {
     rand_bytes(&mut buf).unwrap();
     buf
 }
+
+// Fast non-crypto generator seeded from secure source
+pub fn secure_seeded_rng() -> StdRng {
+    StdRng::from_seed(secure_256_bits())
+}
@@ -0,0 +1,373 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::config::TonNodeConfig;
+use ever_block::{
+    error, fail, sha256_digest, BlockIdExt, Cell, Ed25519KeyOption, Result, ShardIdent, UInt256
+};
+use futures::FutureExt;
+use std::{
+    future::Future, panic::AssertUnwindSafe, path::{Path, PathBuf}, sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH}
+};
+use storage::{
+    archives::{package::Package, package_entry::PackageEntry},
+    block_handle_db::{BlockHandleDb, BlockHandleStorage, NodeStateDb},
+    db::rocksdb::RocksDb, types::BlockMeta
+};
+#[cfg(feature = "telemetry")]
+use storage::StorageTelemetry;
+
+#[cfg(test)]
+#[path = "tests/test_self_test.rs"]
+mod tests;
+
+// Count of block handles written, read and deleted by storage check
+pub const SELF_TEST_HANDLES: u32 = 1000;
+// Count and size of block data entries written by storage check
+pub const SELF_TEST_ENTRIES: u32 = 64;
+pub const SELF_TEST_ENTRY_SIZE: usize = 64 * 1024;
+// Throughputs below these ones are reported as warnings
+const MIN_HANDLE_OPS_PER_SEC: f64 = 1000.0;
+const MIN_DATA_MB_PER_SEC: f64 = 10.0;
+// Clock before this time (2024-01-01) is considered broken
+const MIN_SANE_UNIX_TIME: u64 = 1704067200;
+const MAX_SANE_UNIX_TIME: u64 = 4102444800;
+// Allowed median clock offset from peers, seconds
+const CLOCK_OFFSET_WARN: u64 = 5;
+const CLOCK_OFFSET_FAIL: u64 = 60;
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SelfTestStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub status: SelfTestStatus,
+    pub details: String,
+    pub time_ms: u64,
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default)]
+pub struct SelfTestReport {
+    checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+
+    pub fn add(&mut self, check: SelfTestCheck) {
+        match check.status {
+            SelfTestStatus::Pass => log::info!("Self-test {}: pass, {}", check.name, check.details),
+            SelfTestStatus::Warn => log::warn!("Self-test {}: warn, {}", check.name, check.details),
+            SelfTestStatus::Fail => log::error!("Self-test {}: fail, {}", check.name, check.details)
+        }
+        self.checks.push(check)
+    }
+
+    pub fn checks(&self) -> &[SelfTestCheck] {
+        &self.checks
+    }
+
+    // The worst status of all checks
+    pub fn status(&self) -> SelfTestStatus {
+        self.checks.iter().map(|check| check.status).max().unwrap_or(SelfTestStatus::Pass)
+    }
+
+    pub fn count(&self, status: SelfTestStatus) -> usize {
+        self.checks.iter().filter(|check| check.status == status).count()
+    }
+
+    // Warnings don't make process fail
+    pub fn exit_code(&self) -> i32 {
+        if self.status() == SelfTestStatus::Fail { 1 } else { 0 }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let json = serde_json::json!({
+            "status": self.status(),
+            "passed": self.count(SelfTestStatus::Pass),
+            "warnings": self.count(SelfTestStatus::Warn),
+            "failed": self.count(SelfTestStatus::Fail),
+            "checks": self.checks,
+        });
+        Ok(serde_json::to_string_pretty(&json)?)
+    }
+}
+
+// Runs one check, error or panic of the check makes it failed
+pub async fn run_check<F>(name: &str, check: F) -> SelfTestCheck
+where
+    F: Future<Output = Result<(SelfTestStatus, String)>>
+{
+    let started = Instant::now();
+    let (status, details) = match AssertUnwindSafe(check).catch_unwind().await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => (SelfTestStatus::Fail, e.to_string()),
+        Err(panic) => {
+            let msg = panic.downcast_ref::<&str>().map(|msg| msg.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            (SelfTestStatus::Fail, format!("panicked {}", msg))
+        }
+    };
+    SelfTestCheck {
+        name: name.to_string(),
+        status,
+        details,
+        time_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+pub async fn run_self_test(config: &TonNodeConfig) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    report.add(run_check("crypto", async { check_crypto() }).await);
+    report.add(run_check("cpu_features", async { Ok(check_cpu_features()) }).await);
+    report.add(run_check("clock", async { check_clock(unix_time_now(), &[]) }).await);
+    report.add(run_check("config", async { check_config(config) }).await);
+    report.add(run_check("storage", check_storage(Path::new(config.internal_db_path()))).await);
+    report
+}
+
+// Directory removed on drop, so it is cleaned up even if check panics
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(root: &Path) -> Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+        let path = root.join(format!("self_test_{}_{}", std::process::id(), nanos));
+        std::fs::create_dir_all(&path)
+            .map_err(|e| error!("Can't create self-test directory {}: {}", path.display(), e))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            log::warn!("Can't remove self-test directory {}: {}", self.path.display(), e)
+        }
+    }
+}
+
+fn self_test_block_id(seq_no: u32) -> BlockIdExt {
+    let mut root_hash = [0; 32];
+    root_hash[..4].copy_from_slice(&seq_no.to_be_bytes());
+    BlockIdExt::with_params(
+        ShardIdent::masterchain(), seq_no, UInt256::with_array(root_hash), UInt256::default()
+    )
+}
+
+// Write, read and delete cycles through block handle storage and block data package
+// in temporary directory inside of the given one
+pub async fn check_storage(root: &Path) -> Result<(SelfTestStatus, String)> {
+    let dir = TempDir::new(root)?;
+    let (handle_ops, data_mb) = check_storage_in(&dir.path).await?;
+    let details = format!(
+        "block handles {:.0} ops/s, block data {:.1} MB/s in {}",
+        handle_ops, data_mb, root.display()
+    );
+    if (handle_ops < MIN_HANDLE_OPS_PER_SEC) || (data_mb < MIN_DATA_MB_PER_SEC) {
+        Ok((SelfTestStatus::Warn, format!("slow storage: {}", details)))
+    } else {
+        Ok((SelfTestStatus::Pass, details))
+    }
+}
+
+async fn check_storage_in(dir: &Path) -> Result<(f64, f64)> {
+    let path = dir.to_str().ok_or_else(|| error!("Bad self-test path {}", dir.display()))?;
+    let db = RocksDb::with_path(path, "db")?;
+    let handle_storage = BlockHandleStorage::with_dbs(
+        Arc::new(BlockHandleDb::with_db(db.clone(), "block_handle_db", true)?),
+        Arc::new(NodeStateDb::with_db(db.clone(), "node_state_db", true)?),
+        Arc::new(NodeStateDb::with_db(db.clone(), "validator_state_db", true)?),
+        None,
+        #[cfg(feature = "telemetry")]
+        Arc::new(StorageTelemetry::default()),
+        Arc::new(storage::StorageAlloc::default())
+    );
+
+    let started = Instant::now();
+    for seq_no in 0..SELF_TEST_HANDLES {
+        let id = self_test_block_id(seq_no);
+        let handle = handle_storage.create_handle(id.clone(), BlockMeta::default(), None)?
+            .ok_or_else(|| error!("Block handle {} already exists", id))?;
+        handle.set_data();
+        handle_storage.mark_dirty(&handle, None)?;
+    }
+    handle_storage.flush().await?;
+    // Handles are not referenced any more so they are read from DB, not from cache
+    for seq_no in 0..SELF_TEST_HANDLES {
+        let id = self_test_block_id(seq_no);
+        let handle = handle_storage.load_handle_by_id(&id)?
+            .ok_or_else(|| error!("Block handle {} is not read back", id))?;
+        if !handle.has_data() {
+            fail!("Block handle {} is read back without data flag", id)
+        }
+    }
+    for seq_no in 0..SELF_TEST_HANDLES {
+        handle_storage.drop_handle(self_test_block_id(seq_no), None)?;
+    }
+    handle_storage.flush().await?;
+    for seq_no in 0..SELF_TEST_HANDLES {
+        let id = self_test_block_id(seq_no);
+        if handle_storage.load_handle_by_id(&id)?.is_some() {
+            fail!("Block handle {} is not deleted", id)
+        }
+    }
+    let handle_ops = 3.0 * SELF_TEST_HANDLES as f64 / started.elapsed().as_secs_f64().max(1e-6);
+
+    let started = Instant::now();
+    let package = Package::open(dir.join("self_test.pack"), false, true).await?;
+    let mut offsets = Vec::new();
+    for i in 0..SELF_TEST_ENTRIES {
+        let data = (0..SELF_TEST_ENTRY_SIZE).map(|j| (i as usize + j) as u8).collect::<Vec<_>>();
+        let entry = PackageEntry::with_data(format!("self_test_{}", i), data);
+        package.append_entry(&entry, |offset, _| {
+            offsets.push(offset);
+            Ok(())
+        }).await?;
+    }
+    package.open_file().await?.sync_all().await?;
+    for (i, offset) in offsets.into_iter().enumerate() {
+        let entry = package.read_entry(offset).await?;
+        let expected = (0..SELF_TEST_ENTRY_SIZE).map(|j| (i + j) as u8);
+        if (entry.filename() != &format!("self_test_{}", i)) || !entry.data().iter().copied().eq(expected) {
+            fail!("Block data entry {} is read back corrupted", i)
+        }
+    }
+    package.remove().await?;
+    let data_mb = 2.0 * (SELF_TEST_ENTRIES as usize * SELF_TEST_ENTRY_SIZE) as f64 /
+        (1024.0 * 1024.0) / started.elapsed().as_secs_f64().max(1e-6);
+
+    Ok((handle_ops, data_mb))
+}
+
+// Known vectors for hash, signature and cell hash
+pub fn check_crypto() -> Result<(SelfTestStatus, String)> {
+    let hash = sha256_digest(b"abc");
+    if hex::encode(hash) != "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad" {
+        fail!("sha256 gives wrong hash {}", hex::encode(hash))
+    }
+
+    // RFC 8032, test 1
+    let secret: [u8; 32] = hex::decode(
+        "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60"
+    )?.as_slice().try_into()?;
+    let key = Ed25519KeyOption::from_private_key(&secret)?;
+    if hex::encode(key.pub_key()?) != "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a" {
+        fail!("ed25519 gives wrong public key {}", hex::encode(key.pub_key()?))
+    }
+    let mut signature = key.sign(&[])?;
+    let expected = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+    if hex::encode(&signature) != expected {
+        fail!("ed25519 gives wrong signature {}", hex::encode(&signature))
+    }
+    key.verify(&[], &signature).map_err(|e| error!("ed25519 rejects valid signature: {}", e))?;
+    signature[0] ^= 1;
+    if key.verify(&[], &signature).is_ok() {
+        fail!("ed25519 accepts tampered signature")
+    }
+
+    let hash = Cell::default().repr_hash();
+    if hash.as_hex_string() != "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7" {
+        fail!("empty cell has wrong hash {:x}", hash)
+    }
+    Ok((SelfTestStatus::Pass, "sha256, ed25519 and cell hash match known vectors".to_string()))
+}
+
+pub fn check_cpu_features() -> (SelfTestStatus, String) {
+    #[cfg(target_arch = "x86_64")] {
+        let mut missing = Vec::new();
+        if !is_x86_feature_detected!("aes") {
+            missing.push("aes")
+        }
+        if !is_x86_feature_detected!("sse4.2") {
+            missing.push("sse4.2")
+        }
+        if !is_x86_feature_detected!("avx2") {
+            missing.push("avx2")
+        }
+        if !missing.is_empty() {
+            return (SelfTestStatus::Warn, format!("missing CPU features: {}", missing.join(", ")))
+        }
+        (SelfTestStatus::Pass, "aes, sse4.2, avx2 are available".to_string())
+    }
+    #[cfg(not(target_arch = "x86_64"))] {
+        (SelfTestStatus::Pass, "CPU features are not checked on this architecture".to_string())
+    }
+}
+
+fn unix_time_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default()
+}
+
+// Checks local time against sane bounds and median time of peers if any
+pub fn check_clock(now: u64, peer_times: &[u64]) -> Result<(SelfTestStatus, String)> {
+    if !(MIN_SANE_UNIX_TIME..MAX_SANE_UNIX_TIME).contains(&now) {
+        fail!("local clock {} is out of sane bounds", now)
+    }
+    if peer_times.is_empty() {
+        return Ok((
+            SelfTestStatus::Pass,
+            format!("local clock {} is within bounds, peers are not checked", now)
+        ))
+    }
+    let mut offsets = peer_times.iter().map(|time| *time as i64 - now as i64).collect::<Vec<_>>();
+    offsets.sort_unstable();
+    let offset = offsets[offsets.len() / 2];
+    let details = format!("median offset from {} peers is {}s", peer_times.len(), offset);
+    if offset.unsigned_abs() > CLOCK_OFFSET_FAIL {
+        fail!("{}", details)
+    }
+    if offset.unsigned_abs() > CLOCK_OFFSET_WARN {
+        return Ok((SelfTestStatus::Warn, details))
+    }
+    Ok((SelfTestStatus::Pass, details))
+}
+
+// Files referenced by config are readable, keys decode, listening ports are bindable
+pub fn check_config(config: &TonNodeConfig) -> Result<(SelfTestStatus, String)> {
+    let mut warnings = Vec::new();
+    config.load_global_config().map_err(|e| error!("can't load global config: {}", e))?;
+    if let Some(path) = config.log_config_path() {
+        if let Err(e) = std::fs::File::open(&path) {
+            warnings.push(format!("log config {} is not readable: {}", path.display(), e))
+        }
+    }
+    for path in config.unsafe_catchain_patches_files() {
+        std::fs::read(&path).map_err(|e| error!("catchain patch {} is not readable: {}", path, e))?;
+    }
+    let keys = config.check_validator_keys()?;
+
+    let json = serde_json::to_value(config)?;
+    if let Some(address) = json.pointer("/adnl_node/ip_address").and_then(|addr| addr.as_str()) {
+        std::net::UdpSocket::bind(address)
+            .map_err(|e| error!("ADNL address {} is not bindable: {}", address, e))?;
+    }
+    if let Some(address) = json.pointer("/control_server/address").and_then(|addr| addr.as_str()) {
+        std::net::TcpListener::bind(address)
+            .map_err(|e| error!("control server address {} is not bindable: {}", address, e))?;
+    }
+
+    if warnings.is_empty() {
+        Ok((SelfTestStatus::Pass, format!("config is consistent, {} validator keys", keys)))
+    } else {
+        Ok((SelfTestStatus::Warn, warnings.join("; ")))
+    }
+}
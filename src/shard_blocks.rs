@@ -24,13 +24,16 @@ use adnl::{
         Counter
     }
 };
-use ever_block::{BlockIdExt, TopBlockDescr, Deserializable, BlockSignatures};
+use ever_block::{
+    BlockIdExt, TopBlockDescr, Deserializable, BlockSignatures, McShardRecord, ShardDescr,
+    ShardIdent
+};
 use ever_block::{fail, Result};
 use std::{
     sync::{Arc, atomic::{AtomicU32, Ordering}},
     time::Duration,
     ops::Deref,
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
 };
 use rand::Rng;
 
@@ -48,6 +51,79 @@ pub enum ShardBlockProcessingResult {
     MightBeAdded(Arc<TopBlockDescrStuff>)
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShardTopologyChange {
+    Split {
+        mc_seq_no: u32,
+        parent: ShardIdent,
+        left: ShardIdent,
+        right: ShardIdent,
+    },
+    Merge {
+        mc_seq_no: u32,
+        left: ShardIdent,
+        right: ShardIdent,
+        merged: ShardIdent,
+    },
+}
+
+impl ShardTopologyChange {
+    pub fn mc_seq_no(&self) -> u32 {
+        match self {
+            ShardTopologyChange::Split { mc_seq_no, .. } => *mc_seq_no,
+            ShardTopologyChange::Merge { mc_seq_no, .. } => *mc_seq_no,
+        }
+    }
+}
+
+// How many last topology changes are kept for consumers
+const MAX_TOPOLOGY_CHANGES: usize = 64;
+// Top blocks referring to older masterchain blocks are rejected
+pub const DEFAULT_MC_REF_HORIZON: u32 = 64;
+
+#[derive(Default)]
+struct ShardTopology {
+    mc_seq_no: u32,
+    // Empty until the first masterchain state is seen
+    shards: HashSet<ShardIdent>,
+    // Shards which were split or merged away, their top blocks are stale
+    retired: HashSet<ShardIdent>,
+    changes: VecDeque<ShardTopologyChange>,
+}
+
+pub fn shard_topology_delta(
+    mc_seq_no: u32,
+    old: &HashSet<ShardIdent>,
+    new: &HashSet<ShardIdent>,
+) -> Vec<ShardTopologyChange> {
+    let mut removed = old.difference(new).collect::<Vec<_>>();
+    removed.sort_by_key(|shard| (shard.workchain_id(), shard.shard_prefix_with_tag()));
+    let mut changes = Vec::new();
+    for shard in removed {
+        if let Ok((left, right)) = shard.split() {
+            if new.contains(&left) && new.contains(&right) {
+                changes.push(ShardTopologyChange::Split {
+                    mc_seq_no, parent: shard.clone(), left, right
+                });
+                continue
+            }
+        }
+        // Merge is reported once, for the left child
+        if !shard.is_left_child() {
+            continue
+        }
+        if let Ok(merged) = shard.merge() {
+            let right = shard.sibling();
+            if new.contains(&merged) && old.contains(&right) && !new.contains(&right) {
+                changes.push(ShardTopologyChange::Merge {
+                    mc_seq_no, left: shard.clone(), right, merged
+                });
+            }
+        }
+    }
+    changes
+}
+
 declare_counted!(
     struct ShardBlocksPoolItem {
         top_block: Arc<TopBlockDescrStuff>
@@ -59,6 +135,10 @@ pub struct ShardBlocksPool {
     shard_blocks: lockfree::map::Map<TopBlockDescrId, ShardBlocksPoolItem>,
     storage_sender: Option<tokio::sync::mpsc::UnboundedSender<StoreAction>>,
     is_fake: bool,
+    mc_ref_horizon: AtomicU32,
+    // Mc seqno check and top blocks filtering are done under this lock,
+    // so parent and children shards are never current at the same time
+    topology: parking_lot::RwLock<ShardTopology>,
 }
 
 impl ShardBlocksPool {
@@ -94,10 +174,16 @@ impl ShardBlocksPool {
             shard_blocks: tsbs,
             storage_sender: Some(sender.clone()),
             is_fake,
+            mc_ref_horizon: AtomicU32::new(DEFAULT_MC_REF_HORIZON),
+            topology: parking_lot::RwLock::new(ShardTopology::default()),
         };
         Ok((ret, receiver))
     }
 
+    pub fn set_mc_ref_horizon(&self, horizon: u32) {
+        self.mc_ref_horizon.store(horizon, Ordering::Relaxed)
+    }
+
     pub async fn process_shard_block_raw(
         &self,
         id: &BlockIdExt,
@@ -152,7 +238,9 @@ impl ShardBlocksPool {
             };
 
             if !self.is_fake {
-                tbds.validate(&engine.load_last_applied_mc_state().await?)?;
+                let last_mc_state = engine.load_last_applied_mc_state().await?;
+                tbds.check_mc_ref(last_mc_state.seq_no(), self.mc_ref_horizon.load(Ordering::Relaxed))?;
+                tbds.validate(&last_mc_state)?;
             }
 
             if check_only {
@@ -163,29 +251,39 @@ impl ShardBlocksPool {
             // add
             // This is so-called "interactive insertion"
             let mut old = None;
-            let added = add_counted_object_to_map_with_update(
-                &self.shard_blocks,
-                tbds_id.clone(),
-                |found| {
-                    if let Some(found) = found {
-                        // someone already added the value into map
-                        if id.seq_no() <= found.top_block.proof_for().seq_no() {
-                            return Ok(None)
-                        }
-                        old.replace(found.top_block.clone());
-                    } 
-                    let top_blocks = &engine.engine_allocated().top_blocks;
-                    let ret = ShardBlocksPoolItem { 
-                        top_block: tbds.clone(),
-                        counter: top_blocks.clone().into() 
-                    };
-                    #[cfg(feature = "telemetry")]
-                    engine.engine_telemetry().top_blocks.update(
-                        top_blocks.load(Ordering::Relaxed)
-                    );
-                    Ok(Some(ret))
+            // Topology lock is held to not add stale top block while pool is updated
+            let added = {
+                let topology = self.topology.read();
+                if topology.retired.contains(id.shard()) {
+                    fail!(
+                        "Shard {} doesn't exist after mc block {}, top block {} is stale",
+                        id.shard(), topology.mc_seq_no, id
+                    )
                 }
-            )?;
+                add_counted_object_to_map_with_update(
+                    &self.shard_blocks,
+                    tbds_id.clone(),
+                    |found| {
+                        if let Some(found) = found {
+                            // someone already added the value into map
+                            if id.seq_no() <= found.top_block.proof_for().seq_no() {
+                                return Ok(None)
+                            }
+                            old.replace(found.top_block.clone());
+                        } 
+                        let top_blocks = &engine.engine_allocated().top_blocks;
+                        let ret = ShardBlocksPoolItem { 
+                            top_block: tbds.clone(),
+                            counter: top_blocks.clone().into() 
+                        };
+                        #[cfg(feature = "telemetry")]
+                        engine.engine_telemetry().top_blocks.update(
+                            top_blocks.load(Ordering::Relaxed)
+                        );
+                        Ok(Some(ret))
+                    }
+                )?
+            };
             if !added {
                 continue
             }
@@ -256,6 +354,7 @@ impl ShardBlocksPool {
     ) -> Result<Vec<Arc<TopBlockDescrStuff>>> {
         let last_mc_seq_no = last_mc_state.block_id().seq_no;
 
+        let topology = self.topology.read();
         let mc_seqno = self.last_mc_seq_no.load(Ordering::Relaxed);
         if let Some(actual_last_mc_seqno) = actual_last_mc_seqno {
             *actual_last_mc_seqno = mc_seqno;
@@ -268,6 +367,9 @@ impl ShardBlocksPool {
             let mut returned_list = string_builder::Builder::default();
             let mut blocks = Vec::new();
             for guard in self.shard_blocks.iter() {
+                if topology.retired.contains(&guard.key().id) {
+                    continue
+                }
                 if !only_own || guard.val().top_block.is_own() {
                     blocks.push(guard.val().top_block.clone());
                     returned_list.append(format!("\n{} {}", guard.key().cc_seqno, guard.key().id));
@@ -280,10 +382,21 @@ impl ShardBlocksPool {
     }
 
     pub async fn update_shard_blocks(&self, last_mc_state: &Arc<ShardStateStuff>) -> Result<()> {
+        let mut shards = Vec::new();
+        last_mc_state.shards()?.iterate_shards(|ident: ShardIdent, descr: ShardDescr| {
+            shards.push(McShardRecord::from_shard_descr(ident, descr));
+            Ok(true)
+        })?;
+        let mut topology = self.topology.write();
         self.last_mc_seq_no.store(last_mc_state.block_id().seq_no(), Ordering::Relaxed);
+        self.apply_topology(&mut topology, last_mc_state.block_id().seq_no(), &shards);
         let mut removed_list = string_builder::Builder::default();
+        let horizon = self.mc_ref_horizon.load(Ordering::Relaxed);
         for block in self.shard_blocks.iter() {
-            if block.val().top_block.validate(last_mc_state).is_err() {
+            let top_block = &block.val().top_block;
+            if top_block.check_mc_ref(last_mc_state.seq_no(), horizon).is_err() ||
+                top_block.validate(last_mc_state).is_err()
+            {
                 self.shard_blocks.remove(block.key());
                 self.send_to_storage(StoreAction::Remove(block.key().clone()));
                 removed_list.append(format!("\n{} {}", block.key().cc_seqno, block.key().id));
@@ -294,6 +407,76 @@ impl ShardBlocksPool {
         Ok(())
     }
 
+    // Applies shard configuration of the given mc block, returns split/merge events
+    pub fn update_topology(
+        &self,
+        mc_seq_no: u32,
+        shards: &[McShardRecord]
+    ) -> Vec<ShardTopologyChange> {
+        let mut topology = self.topology.write();
+        self.apply_topology(&mut topology, mc_seq_no, shards)
+    }
+
+    // Split/merge events registered after the given mc block
+    pub fn topology_changes(&self, since_mc_seq_no: u32) -> Vec<ShardTopologyChange> {
+        self.topology.read().changes.iter()
+            .filter(|change| change.mc_seq_no() > since_mc_seq_no)
+            .cloned()
+            .collect()
+    }
+
+    fn apply_topology(
+        &self,
+        topology: &mut ShardTopology,
+        mc_seq_no: u32,
+        shards: &[McShardRecord]
+    ) -> Vec<ShardTopologyChange> {
+        if mc_seq_no < topology.mc_seq_no {
+            log::warn!(
+                "apply_topology: mc block {} is older than current {}", mc_seq_no, topology.mc_seq_no
+            );
+            return Vec::new()
+        }
+        let new = shards.iter().map(|record| record.shard().clone()).collect::<HashSet<_>>();
+        let changes = if topology.shards.is_empty() {
+            Vec::new()
+        } else {
+            shard_topology_delta(mc_seq_no, &topology.shards, &new)
+        };
+        for change in &changes {
+            log::info!("apply_topology: {:?}", change);
+            match change {
+                ShardTopologyChange::Split { parent, .. } => {
+                    topology.retired.insert(parent.clone());
+                }
+                ShardTopologyChange::Merge { left, right, .. } => {
+                    topology.retired.insert(left.clone());
+                    topology.retired.insert(right.clone());
+                }
+            }
+            topology.changes.push_back(change.clone());
+            if topology.changes.len() > MAX_TOPOLOGY_CHANGES {
+                topology.changes.pop_front();
+            }
+        }
+        // Shard might appear again after the opposite event
+        topology.retired.retain(|shard| !new.contains(shard));
+        topology.shards = new;
+        topology.mc_seq_no = mc_seq_no;
+
+        let mut removed_list = string_builder::Builder::default();
+        for block in self.shard_blocks.iter() {
+            if topology.retired.contains(&block.key().id) {
+                self.shard_blocks.remove(block.key());
+                self.send_to_storage(StoreAction::Remove(block.key().clone()));
+                removed_list.append(format!("\n{} {}", block.key().cc_seqno, block.key().id));
+            }
+        }
+        log::trace!("apply_topology mc block {} removed: {}", 
+            mc_seq_no, removed_list.string().unwrap_or_default());
+        changes
+    }
+
     fn send_to_storage(&self, action: StoreAction) {
         if let Some(storage_sender) = self.storage_sender.as_ref() {
             match storage_sender.send(action) {
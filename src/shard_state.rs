@@ -20,15 +20,27 @@ use adnl::{declare_counted, common::{CountedObject, Counter}};
 use std::sync::atomic::Ordering;
 use std::{io::Write, sync::Arc, collections::HashMap};
 use ever_block::{
-    BlockIdExt, ShardAccount, ShardIdent, ShardStateUnsplit, ShardStateSplit, 
+    AccountIdPrefixFull, BlockIdExt, ShardAccount, ShardIdent, ShardStateUnsplit, ShardStateSplit, 
     Serializable, Deserializable, ConfigParams, McShardRecord, 
     McStateExtra, ShardDescr, ShardHashes, HashmapAugType, InRefValue, BinTree, 
     BinTreeType, WorkchainDescr, OutMsgQueue, ProcessedInfo, MerkleProof, ConnectedNwDescr,
 };
 use ever_block::{
-    AccountId, Cell, SliceData, error, fail, Result, UInt256, BocWriter, BocReader, read_single_root_boc,
+    AccountId, Cell, SliceData, error, fail, Result, UInt256, UsageTree, BocWriter, BocReader,
+    read_single_root_boc,
 };
 
+// Account state proven for clients which don't trust the node
+pub struct AccountStateProof {
+    pub block_id: BlockIdExt,
+    pub state_root_hash: UInt256,
+    pub exists: bool,
+    // Merkle proof of the account from the state root
+    pub state_proof: Vec<u8>,
+    // Proofs of the block from the init key block, see build_proof_chain
+    pub proof_chain: Vec<Vec<u8>>,
+}
+
 //    #[derive(Debug, Default, Clone, Eq, PartialEq)]
 // It is a wrapper around various shard state's representations and properties.
 declare_counted!(
@@ -295,6 +307,55 @@ impl ShardStateStuff {
     pub fn shard_account(&self, address: &AccountId) -> Result<Option<ShardAccount>> {
         self.state()?.read_accounts()?.account(address)
     }
+
+    // Builds a Merkle proof of the first account matching the prefix. The proof is rooted
+    // at the state root and contains the whole account cell.
+    pub fn account_proof(&self, prefix: &AccountIdPrefixFull) -> Result<(UInt256, Vec<u8>)> {
+        if !self.shard().contains_full_prefix(prefix) {
+            fail!(NodeError::InvalidArg(
+                format!("Account prefix {} doesn't belong to state {}", prefix, self.block_id)
+            ))
+        }
+        let usage_tree = UsageTree::with_root(self.root.clone());
+        let state = ShardStateUnsplit::construct_from_cell(usage_tree.root_cell())?;
+        let mut key = [0; 32];
+        key[..8].copy_from_slice(&prefix.prefix.to_be_bytes());
+        let (account_id, shard_account) = state
+            .read_accounts()?
+            .find_leaf(&UInt256::from(key), true, true, false)?
+            .filter(|(id, _)| id.as_slice()[..8] == key[..8])
+            .ok_or_else(|| error!("Account {} is not found in state {}", prefix, self.block_id))?;
+        let account_hash = shard_account.account_cell().repr_hash();
+        let proof = MerkleProof::create_with_subtrees(
+            &self.root,
+            |h| usage_tree.contains(h),
+            |h| h == &account_hash
+        )?;
+        Ok((account_id, proof.write_to_bytes()?))
+    }
+    // Builds a Merkle proof of the account state rooted at the state root. The proof contains 
+    // the whole account cell if the account exists, otherwise it proves the account's absence
+    pub fn account_state_proof(&self, account_id: &AccountId, max_size: usize) -> Result<(bool, Vec<u8>)> {
+        if !self.shard().contains_account(account_id.clone())? {
+            fail!(NodeError::InvalidArg(
+                format!("Account {:x} doesn't belong to state {}", account_id, self.block_id)
+            ))
+        }
+        let usage_tree = UsageTree::with_root(self.root.clone());
+        let state = ShardStateUnsplit::construct_from_cell(usage_tree.root_cell())?;
+        let account_hash = state.read_accounts()?.account(account_id)?
+            .map(|shard_account| shard_account.account_cell().repr_hash());
+        let proof = MerkleProof::create_with_subtrees(
+            &self.root,
+            |h| usage_tree.contains(h),
+            |h| Some(h) == account_hash.as_ref()
+        )?.write_to_bytes()?;
+        if proof.len() > max_size {
+            fail!(NodeError::AccountProofTooLarge { size: proof.len(), max_size })
+        }
+        Ok((account_hash.is_some(), proof))
+    }
+
 // Unused
 //    pub fn withdraw_state(self) -> ShardStateUnsplit { 
 //        self.shard_state 
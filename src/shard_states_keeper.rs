@@ -2,22 +2,27 @@ use crate::{
     internal_db::{
         InternalDb, state_gc_resolver::AllowStateGcSmartResolver, LAST_APPLIED_MC_BLOCK,
     },
+    block::BlockStuff,
     shard_state::ShardStateStuff,
-    engine_traits::{EngineOperations, EngineAlloc},
+    engine_traits::{BlockAccess, EngineOperations, EngineAlloc, StateAccess},
     engine::{Engine, Stopper},
+    error::NodeError,
     boot,
-    config::ShardStatesCacheMode, mesh_queues_keeper::MeshQueuesKeeper,
+    config::{PersistentStatePolicy, ShardStatesCacheMode}, mesh_queues_keeper::MeshQueuesKeeper,
 };
 #[cfg(feature = "telemetry")]
 use crate::engine_traits::EngineTelemetry;
 use storage::{
     block_handle_db::BlockHandle, shardstate_db_async::AllowStateGcResolver, 
-    shardstate_db_async::SsNotificationCallback, error::StorageError,
+    shardstate_db_async::SsNotificationCallback, error::StorageError, GcCounters,
 };
 use ever_block::{BlockIdExt, ShardIdent};
 use ever_block::{fail, error, Result, UInt256, BocReader, Cell};
 use adnl::common::add_unbound_object_to_map_with_update;
-use std::{ ops::Deref, sync::Arc, time::{Duration, Instant} };
+use std::{
+    collections::HashMap, ops::Deref,
+    sync::{Arc, atomic::{AtomicU32, AtomicU64, Ordering}}, time::{Duration, Instant}
+};
 
 pub struct PinnedShardStateGuard {
     state: Arc<ShardStateStuff>,
@@ -55,6 +60,539 @@ impl Drop for PinnedShardStateGuard {
     }
 }
 
+// Keeps state with given id from garbage collection (both cells and persistent) while alive.
+// Pin counter is decremented exactly once when the guard is dropped.
+pub struct PinGuard {
+    block_id: BlockIdExt,
+    gc_resolver: Arc<AllowStateGcSmartResolver>,
+}
+impl PinGuard {
+    pub fn new(block_id: BlockIdExt, gc_resolver: Arc<AllowStateGcSmartResolver>) -> Self {
+        gc_resolver.force_pin_state(&block_id);
+        Self { block_id, gc_resolver }
+    }
+    pub fn block_id(&self) -> &BlockIdExt {
+        &self.block_id
+    }
+}
+impl Clone for PinGuard {
+    fn clone(&self) -> Self {
+        Self::new(self.block_id.clone(), self.gc_resolver.clone())
+    }
+}
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.gc_resolver.unpin_state(&self.block_id) {
+            log::error!("INTERNAL ERROR: {}", e);
+        }
+    }
+}
+
+// Waiters of states which are not stored yet, keyed by block id.
+// Entry lives while somebody waits for the state.
+#[derive(Default)]
+pub struct StateWaiters {
+    waiters: parking_lot::Mutex<HashMap<BlockIdExt, (tokio::sync::watch::Sender<()>, usize)>>,
+}
+impl StateWaiters {
+    pub fn subscribe(self: &Arc<Self>, block_id: &BlockIdExt) -> StateWaiter {
+        let mut waiters = self.waiters.lock();
+        let (tx, count) = waiters.entry(block_id.clone())
+            .or_insert_with(|| (tokio::sync::watch::channel(()).0, 0));
+        *count += 1;
+        StateWaiter {
+            block_id: block_id.clone(),
+            rx: tx.subscribe(),
+            waiters: self.clone(),
+        }
+    }
+    pub fn notify(&self, block_id: &BlockIdExt) {
+        if let Some((tx, _)) = self.waiters.lock().get(block_id) {
+            tx.send(()).ok();
+        }
+    }
+    // Count of states which are waited for
+    pub fn count(&self) -> usize {
+        self.waiters.lock().len()
+    }
+}
+
+pub struct StateWaiter {
+    block_id: BlockIdExt,
+    rx: tokio::sync::watch::Receiver<()>,
+    waiters: Arc<StateWaiters>,
+}
+impl StateWaiter {
+    // Waits until is_stored() returns true. Check is repeated after every notification,
+    // so the notification which comes between subscription and the first check is not lost.
+    pub async fn wait(
+        &mut self,
+        timeout: Option<Duration>,
+        stopper: &Stopper,
+        is_stored: impl Fn() -> Result<bool>,
+    ) -> Result<()> {
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        loop {
+            if is_stored()? {
+                return Ok(())
+            }
+            if stopper.check_stop() {
+                fail!(NodeError::Cancelled(format!("Stopped while waiting for state {}", self.block_id)))
+            }
+            let timer = async move {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => futures::future::pending::<()>().await
+                }
+            };
+            tokio::select! {
+                changed = self.rx.changed() => changed?,
+                _ = stopper.cancelled() => fail!(
+                    NodeError::Cancelled(format!("Stopped while waiting for state {}", self.block_id))
+                ),
+                _ = timer => fail!(
+                    NodeError::Timeout(format!("Timeout while waiting for state {}", self.block_id))
+                )
+            }
+        }
+    }
+}
+impl Drop for StateWaiter {
+    fn drop(&mut self) {
+        let mut waiters = self.waiters.waiters.lock();
+        if let Some((_, count)) = waiters.get_mut(&self.block_id) {
+            *count -= 1;
+            if *count == 0 {
+                waiters.remove(&self.block_id);
+            }
+        }
+    }
+}
+
+// Approximate memory retained by one cell of cached state
+pub const STATE_CELL_RETAINED_BYTES: u64 = 128;
+
+struct StateUsage {
+    bytes: u64,
+    last_access: AtomicU64,
+}
+
+// Tracks approximate memory retained by cached states and chooses least recently used
+// states to unload when the ceiling is exceeded. The newest state of each shard is
+// never chosen: it is the last applied one and the base for collation in the shard.
+pub struct StatesMemoryGovernor {
+    // 0 means no ceiling
+    ceiling: AtomicU64,
+    usages: lockfree::map::Map<BlockIdExt, StateUsage>,
+    retained: AtomicU64,
+    clock: AtomicU64,
+    // Unloaded states, to tell reload from the first load
+    unloaded: lockfree::map::Map<BlockIdExt, ()>,
+    unloads: AtomicU64,
+    reloads: AtomicU64,
+}
+
+impl StatesMemoryGovernor {
+
+    pub fn new() -> Self {
+        Self {
+            ceiling: AtomicU64::new(0),
+            usages: lockfree::map::Map::new(),
+            retained: AtomicU64::new(0),
+            clock: AtomicU64::new(0),
+            unloaded: lockfree::map::Map::new(),
+            unloads: AtomicU64::new(0),
+            reloads: AtomicU64::new(0),
+        }
+    }
+
+    pub fn estimate_bytes(state: &ShardStateStuff) -> u64 {
+        state.root_cell().tree_cell_count() * STATE_CELL_RETAINED_BYTES
+    }
+
+    pub fn set_ceiling(&self, bytes: u64) {
+        self.ceiling.store(bytes, Ordering::Relaxed)
+    }
+
+    pub fn ceiling(&self) -> u64 {
+        self.ceiling.load(Ordering::Relaxed)
+    }
+
+    pub fn retained(&self) -> u64 {
+        self.retained.load(Ordering::Relaxed)
+    }
+
+    pub fn unloads(&self) -> u64 {
+        self.unloads.load(Ordering::Relaxed)
+    }
+
+    pub fn reloads(&self) -> u64 {
+        self.reloads.load(Ordering::Relaxed)
+    }
+
+    // Returns true if the state was unloaded before
+    pub fn state_cached(&self, block_id: &BlockIdExt, bytes: u64) -> bool {
+        let usage = StateUsage { bytes, last_access: AtomicU64::new(self.tick()) };
+        if let Some(old) = self.usages.insert(block_id.clone(), usage) {
+            self.retained.fetch_sub(old.val().bytes, Ordering::Relaxed);
+        }
+        self.retained.fetch_add(bytes, Ordering::Relaxed);
+        self.unloaded.remove(block_id).is_some()
+    }
+
+    pub fn state_accessed(&self, block_id: &BlockIdExt) {
+        if let Some(usage) = self.usages.get(block_id) {
+            usage.val().last_access.store(self.tick(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn state_removed(&self, block_id: &BlockIdExt) {
+        if let Some(usage) = self.usages.remove(block_id) {
+            self.retained.fetch_sub(usage.val().bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub fn state_unloaded(&self, block_id: &BlockIdExt) {
+        self.state_removed(block_id);
+        self.unloaded.insert(block_id.clone(), ());
+        self.unloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn state_reloaded(&self, block_id: &BlockIdExt, latency: Duration) {
+        self.reloads.fetch_add(1, Ordering::Relaxed);
+        log::debug!("State {} reloaded after unloading TIME {}ms", block_id, latency.as_millis());
+        metrics::histogram!("shard_state_reload_time", latency);
+    }
+
+    // Unloaded states which are not needed anymore are not waited for reload
+    pub fn forget_unloaded(&self, is_outdated: impl Fn(&BlockIdExt) -> bool) {
+        for guard in &self.unloaded {
+            if is_outdated(guard.key()) {
+                self.unloaded.remove(guard.key());
+            }
+        }
+    }
+
+    // Chooses least recently used states to unload to fit the ceiling
+    pub fn select_unloads(&self, can_unload: impl Fn(&BlockIdExt) -> bool) -> Vec<BlockIdExt> {
+        let ceiling = self.ceiling();
+        let retained = self.retained();
+        if ceiling == 0 || retained <= ceiling {
+            return Vec::new()
+        }
+        let mut newest = HashMap::<ShardIdent, u32>::new();
+        for guard in &self.usages {
+            let seq_no = newest.entry(guard.key().shard().clone()).or_default();
+            *seq_no = (*seq_no).max(guard.key().seq_no());
+        }
+        let mut candidates = Vec::new();
+        for guard in &self.usages {
+            let id = guard.key();
+            if newest.get(id.shard()) != Some(&id.seq_no()) && can_unload(id) {
+                let usage = guard.val();
+                candidates.push((usage.last_access.load(Ordering::Relaxed), usage.bytes, id.clone()));
+            }
+        }
+        candidates.sort_by_key(|(last_access, _, _)| *last_access);
+        let mut excess = retained - ceiling;
+        let mut unloads = Vec::new();
+        for (_, bytes, id) in candidates {
+            if excess == 0 {
+                break
+            }
+            excess = excess.saturating_sub(bytes);
+            unloads.push(id);
+        }
+        unloads
+    }
+
+    pub fn report(&self) {
+        metrics::gauge!("shard_states_retained_bytes", self.retained() as f64);
+        metrics::gauge!("shard_states_unloads", self.unloads() as f64);
+        metrics::gauge!("shard_states_reloads", self.reloads() as f64);
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Default for StatesMemoryGovernor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Announcements which wait for the prefetch worker, later ones are dropped
+pub const STATES_PREFETCH_QUEUE_LEN: usize = 64;
+
+// States which are loaded to apply the upcoming block
+pub struct PrefetchRequest {
+    pub block_id: BlockIdExt,
+    pub prev_ids: Vec<BlockIdExt>,
+    // Masterchain block refers to the previous key block
+    pub key_block_seqno: Option<u32>,
+}
+
+impl PrefetchRequest {
+    pub fn from_block(block: &BlockStuff) -> Result<Self> {
+        let (prev1, prev2) = block.construct_prev_id()?;
+        let mut prev_ids = vec!(prev1);
+        prev_ids.extend(prev2);
+        let key_block_seqno = if block.id().shard().is_masterchain() {
+            Some(block.block()?.read_info()?.prev_key_block_seqno()).filter(|seq_no| *seq_no > 0)
+        } else {
+            None
+        };
+        Ok(Self { block_id: block.id().clone(), prev_ids, key_block_seqno })
+    }
+}
+
+#[async_trait::async_trait]
+pub trait StatesLoader: Sync + Send {
+    fn is_applied(&self, block_id: &BlockIdExt) -> Result<bool>;
+    fn is_cached(&self, block_id: &BlockIdExt) -> bool;
+    // Prefetched states must not push out the states in use
+    fn has_budget(&self) -> bool;
+    fn key_block_id(&self, seq_no: u32) -> Result<BlockIdExt>;
+    async fn warm_state(&self, block_id: &BlockIdExt) -> Result<()>;
+}
+
+// Warms states cache for blocks which the sync pipeline is going to apply, so apply
+// doesn't wait for evicted states to be loaded from DB. It is best-effort only:
+// any failure just leaves the state cold.
+pub struct StatesPrefetcher {
+    // How many blocks ahead are announced, 0 disables prefetch
+    distance: AtomicU32,
+    sender: parking_lot::Mutex<Option<tokio::sync::mpsc::Sender<PrefetchRequest>>>,
+    // States warmed by prefetcher which are not requested by apply yet
+    expected: lockfree::map::Map<BlockIdExt, ()>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl StatesPrefetcher {
+
+    pub fn new() -> Self {
+        Self {
+            distance: AtomicU32::new(0),
+            sender: parking_lot::Mutex::new(None),
+            expected: lockfree::map::Map::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_distance(&self, distance: u32) {
+        self.distance.store(distance, Ordering::Relaxed)
+    }
+
+    pub fn distance(&self) -> u32 {
+        self.distance.load(Ordering::Relaxed)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn start(self: &Arc<Self>, loader: Arc<dyn StatesLoader>) {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(STATES_PREFETCH_QUEUE_LEN);
+        *self.sender.lock() = Some(sender);
+        let prefetcher = self.clone();
+        tokio::spawn(async move {
+            while let Some(request) = receiver.recv().await {
+                if let Err(e) = prefetcher.prefetch(loader.as_ref(), &request).await {
+                    log::debug!("Cannot prefetch states for {}: {}", request.block_id, e);
+                }
+            }
+        });
+    }
+
+    // Never waits: announcement is dropped when the worker is behind
+    pub fn announce(&self, request: PrefetchRequest) {
+        if self.distance() == 0 {
+            return
+        }
+        if let Some(sender) = self.sender.lock().as_ref() {
+            if let Err(e) = sender.try_send(request) {
+                let block_id = e.into_inner().block_id;
+                log::debug!("States prefetch queue is full, {} is dropped", block_id);
+            }
+        }
+    }
+
+    // Apply of a block requests the state, prefetched one is a hit if it is still cached
+    pub fn state_requested(&self, block_id: &BlockIdExt, cached: bool) {
+        if self.expected.remove(block_id).is_none() {
+            return
+        }
+        if cached {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            metrics::counter!("shard_states_prefetch_hits", 1);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            metrics::counter!("shard_states_prefetch_misses", 1);
+        }
+    }
+
+    pub fn forget_expected(&self, is_outdated: impl Fn(&BlockIdExt) -> bool) {
+        for guard in &self.expected {
+            if is_outdated(guard.key()) {
+                self.expected.remove(guard.key());
+            }
+        }
+    }
+
+    async fn prefetch(&self, loader: &dyn StatesLoader, request: &PrefetchRequest) -> Result<()> {
+        let mut key_block_seqno = request.key_block_seqno;
+        let mut prev_ids = request.prev_ids.iter().cloned();
+        loop {
+            let id = match prev_ids.next() {
+                Some(id) => id,
+                None => match key_block_seqno.take() {
+                    Some(seq_no) => loader.key_block_id(seq_no)?,
+                    None => return Ok(())
+                }
+            };
+            if loader.is_applied(&request.block_id)? {
+                log::debug!("Prefetch for {} is dropped: block is applied", request.block_id);
+                return Ok(())
+            }
+            if loader.is_cached(&id) {
+                continue
+            }
+            if !loader.has_budget() {
+                log::debug!("Prefetch of {} is skipped: states memory ceiling is reached", id);
+                return Ok(())
+            }
+            self.expected.insert(id.clone(), ());
+            match loader.warm_state(&id).await {
+                Ok(()) => log::trace!("State {} is prefetched for {}", id, request.block_id),
+                // State may be not stored yet, it is not expected warm then
+                Err(e) => {
+                    self.expected.remove(&id);
+                    log::debug!("Cannot prefetch state {} for {}: {}", id, request.block_id, e);
+                }
+            }
+        }
+    }
+}
+
+impl Default for StatesPrefetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Count of states of every shard which are already used to apply next blocks but are not 
+// saved into DB yet. Block application waits for the oldest states to be saved when the 
+// limit is reached, so it doesn't run too far ahead of states saving. 
+pub struct UnsavedStatesBacklog {
+    // 0 means no limit
+    limit: AtomicU32,
+    unsaved: parking_lot::Mutex<HashMap<ShardIdent, u32>>,
+    saved: tokio::sync::Notify,
+}
+
+impl UnsavedStatesBacklog {
+
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit: AtomicU32::new(limit),
+            unsaved: parking_lot::Mutex::new(HashMap::new()),
+            saved: tokio::sync::Notify::new(),
+        }
+    }
+
+    pub fn set_limit(&self, limit: u32) {
+        self.limit.store(limit, Ordering::Relaxed);
+        self.saved.notify_waiters();
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn unsaved(&self, shard: &ShardIdent) -> u32 {
+        self.unsaved.lock().get(shard).copied().unwrap_or_default()
+    }
+
+    pub fn total(&self) -> u32 {
+        self.unsaved.lock().values().sum()
+    }
+
+    // Takes place for a state of the shard to be saved in background. Returns false 
+    // if the place is not free yet.
+    pub fn try_reserve(&self, shard: &ShardIdent) -> bool {
+        let limit = self.limit();
+        let mut unsaved = self.unsaved.lock();
+        let count = unsaved.entry(shard.clone()).or_default();
+        if limit != 0 && *count >= limit {
+            return false
+        }
+        *count += 1;
+        Self::report(shard, *count);
+        true
+    }
+
+    // Waits while the shard has too many unsaved states
+    pub async fn reserve(&self, shard: &ShardIdent, check_stop: impl Fn() -> Result<()>) -> Result<()> {
+        let now = Instant::now();
+        loop {
+            // Waiter is registered before the check, so release between them is not missed
+            let saved = self.saved.notified();
+            if self.try_reserve(shard) {
+                break
+            }
+            check_stop()?;
+            if tokio::time::timeout(Duration::from_secs(1), saved).await.is_err() {
+                log::warn!(
+                    "Waiting for states of {} to be saved, {} states are not saved yet: TIME {}ms",
+                    shard, self.unsaved(shard), now.elapsed().as_millis()
+                );
+            }
+        }
+        metrics::histogram!("shard_states_backlog_wait_time", now.elapsed());
+        Ok(())
+    }
+
+    // State of the shard is saved or its saving is over with error
+    pub fn release(&self, shard: &ShardIdent) {
+        {
+            let mut unsaved = self.unsaved.lock();
+            let count = match unsaved.get_mut(shard) {
+                Some(count) => count,
+                None => return
+            };
+            *count = count.saturating_sub(1);
+            Self::report(shard, *count);
+            if *count == 0 {
+                unsaved.remove(shard);
+            }
+        }
+        self.saved.notify_waiters();
+    }
+
+    fn report(shard: &ShardIdent, count: u32) {
+        metrics::gauge!("shard_states_unsaved_backlog", count as f64, "shard" => shard.to_string());
+    }
+}
+
+struct BacklogCallback {
+    backlog: Arc<UnsavedStatesBacklog>,
+    shard: ShardIdent,
+}
+
+#[async_trait::async_trait]
+impl storage::shardstate_db_async::Callback for BacklogCallback {
+    async fn invoke(&self, _job: storage::shardstate_db_async::Job, _ok: bool) {
+        self.backlog.release(&self.shard);
+    }
+}
 
 /// This structs works beetween engine and db.
 /// ValidatorManager  Collator  ValidatorQuery  etc.   <- high level node commponents
@@ -75,8 +613,16 @@ pub struct ShardStatesKeeper {
     stopper: Arc<Stopper>,
     max_catch_up_depth: u32,
     skip_saving_pss: bool,
+    persistent_state_policy: Option<PersistentStatePolicy>,
+    persistent_state_delta_chain_limit: u32,
+    // Last saved persistent state of each shard, it is a base for the next delta
+    last_persistent_states: lockfree::map::Map<ShardIdent, BlockIdExt>,
     states_cache_mode: ShardStatesCacheMode,
     mesh_queues_keeper: Arc<MeshQueuesKeeper>,
+    state_waiters: Arc<StateWaiters>,
+    memory_governor: StatesMemoryGovernor,
+    prefetcher: Arc<StatesPrefetcher>,
+    unsaved_states: Arc<UnsavedStatesBacklog>,
     #[cfg(feature = "telemetry")]
     telemetry: Arc<EngineTelemetry>,
     allocated: Arc<EngineAlloc>,
@@ -84,10 +630,14 @@ pub struct ShardStatesKeeper {
 
 impl ShardStatesKeeper {
 
+    pub const DEFAULT_MAX_UNSAVED_STATES: u32 = 8;
+
     pub fn new(
         db: Arc<InternalDb>,
         enable_shard_state_persistent_gc: bool,
         skip_saving_pss: bool,
+        persistent_state_policy: Option<PersistentStatePolicy>,
+        persistent_state_delta_chain_limit: u32,
         states_cache_mode: ShardStatesCacheMode,
         cells_lifetime_sec: u64,
         stopper: Arc<Stopper>,
@@ -113,8 +663,15 @@ impl ShardStatesKeeper {
             stopper,
             max_catch_up_depth,
             skip_saving_pss,
+            persistent_state_policy,
+            persistent_state_delta_chain_limit,
+            last_persistent_states: lockfree::map::Map::new(),
             states_cache_mode,
             mesh_queues_keeper,
+            state_waiters: Arc::new(StateWaiters::default()),
+            memory_governor: StatesMemoryGovernor::new(),
+            prefetcher: Arc::new(StatesPrefetcher::new()),
+            unsaved_states: Arc::new(UnsavedStatesBacklog::new(Self::DEFAULT_MAX_UNSAVED_STATES)),
             #[cfg(feature = "telemetry")]
             telemetry,
             allocated,
@@ -132,6 +689,7 @@ impl ShardStatesKeeper {
         log::trace!("start");
 
         self.restore_states(last_applied_mc_block, shard_client_mc_block.clone()).await?;
+        self.prefetcher.start(self.clone());
 
         let engine_ = engine.clone();
         let self_ = self.clone();
@@ -187,18 +745,116 @@ impl ShardStatesKeeper {
         self.gc_resolver.allow_state_gc(0, block_id, 0, u64::MAX)
     }
 
+    // Protects state from GC until returned guard is dropped.
+    // The state which is already garbage collected can't be restored this way.
+    pub fn pin_state(&self, block_id: &BlockIdExt) -> Result<PinGuard> {
+        let handle = self.db.load_block_handle(block_id)?
+            .ok_or_else(|| error!("Cannot load block handle for {}", block_id))?;
+        if !handle.has_state() {
+            fail!("Can't pin state {} - it is not stored", block_id)
+        }
+        Ok(PinGuard::new(block_id.clone(), self.gc_resolver.clone()))
+    }
+
+    pub fn unpin_state(&self, guard: PinGuard) {
+        log::trace!("unpin_state {}", guard.block_id());
+        drop(guard)
+    }
+
+    pub fn set_cells_lifetime_sec(&self, cells_lifetime_sec: u64) {
+        self.gc_resolver.set_life_time_sec(cells_lifetime_sec)
+    }
+
+    // Returns count of pinned states and total count of pins
+    pub fn pinned_states_stats(&self) -> (usize, u32) {
+        self.gc_resolver.pinned_stats()
+    }
+
+    // Saved states are unloaded from cache when they retain more memory than the ceiling,
+    // 0 disables unloading
+    pub fn set_states_memory_ceiling(&self, bytes: u64) {
+        self.memory_governor.set_ceiling(bytes)
+    }
+
+    pub fn memory_governor(&self) -> &StatesMemoryGovernor {
+        &self.memory_governor
+    }
+
+    // Count of states of a shard which may be saved in background, 0 means no limit
+    pub fn set_max_unsaved_states(&self, limit: u32) {
+        self.unsaved_states.set_limit(limit)
+    }
+
+    pub fn unsaved_states(&self) -> &UnsavedStatesBacklog {
+        &self.unsaved_states
+    }
+
+    pub fn prefetcher(&self) -> &StatesPrefetcher {
+        &self.prefetcher
+    }
+
+    // States for the block are warmed in background, failure only leaves them cold
+    pub fn prefetch_states(&self, block: &BlockStuff) {
+        if self.prefetcher.distance() == 0 {
+            return
+        }
+        match PrefetchRequest::from_block(block) {
+            Ok(request) => self.prefetcher.announce(request),
+            Err(e) => log::debug!("Cannot prefetch states for {}: {}", block.id(), e)
+        }
+    }
+
+    fn cache_loaded_state(
+        &self,
+        block_id: &BlockIdExt,
+        state: &Arc<ShardStateStuff>,
+        load_started: Instant
+    ) -> Result<()> {
+        let handle = self.db.load_block_handle(block_id)?
+            .ok_or_else(|| error!("Cannot load block handle for {}", block_id))?;
+        self.states.insert(block_id.clone(), (state.clone(), handle));
+        self.state_cached(state, load_started);
+        Ok(())
+    }
+
+    fn state_cached(&self, state: &ShardStateStuff, load_started: Instant) {
+        let bytes = StatesMemoryGovernor::estimate_bytes(state);
+        if self.memory_governor.state_cached(state.block_id(), bytes) {
+            self.memory_governor.state_reloaded(state.block_id(), load_started.elapsed());
+        }
+        self.enforce_memory_ceiling();
+    }
+
+    // Pinned states and states which are not saved yet are never unloaded.
+    // Unloaded state is loaded again from DB on demand.
+    fn enforce_memory_ceiling(&self) {
+        let unloads = self.memory_governor.select_unloads(|id| {
+            !self.gc_resolver.is_pinned(id) &&
+                self.states.get(id).map_or(false, |guard| guard.val().1.has_saved_state())
+        });
+        for id in unloads {
+            if self.states.remove(&id).is_some() {
+                log::debug!("State {} unloaded from cache due to memory ceiling", id);
+                self.memory_governor.state_unloaded(&id);
+            }
+        }
+        self.memory_governor.report();
+    }
+
     #[async_recursion::async_recursion]
     pub async fn load_state(self: &Arc<Self>, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
         log::trace!("load_state {}", block_id);
-        if let Some(state) = self.states.get(block_id) {
+        let cached = self.states.get(block_id).map(|guard| guard.val().0.clone());
+        self.prefetcher.state_requested(block_id, cached.is_some());
+        if let Some(state) = cached {
             log::trace!("load_state {} FROM CACHE", block_id);
-            return Ok(state.val().0.clone())
+            self.memory_governor.state_accessed(block_id);
+            return Ok(state)
         } else {
+            let now = Instant::now();
             let state = match self.db.load_shard_state_dynamic(block_id) {
                 Ok(s) => {
-                    let handle = self.db.load_block_handle(block_id)?
-                        .ok_or_else(|| error!("Cannot load block handle for {}", block_id))?;
-                    self.states.insert(block_id.clone(), (s.clone(), handle));
+                    self.cache_loaded_state(block_id, &s, now)?;
                     log::trace!("load_state {} FROM DB", block_id);
                     s
                 }
@@ -217,6 +873,22 @@ impl ShardStatesKeeper {
         }
     }
 
+    // Resolves as soon as the state is stored. Fails with Cancelled error on node stop
+    // and with Timeout error when timeout is over.
+    pub async fn wait_state(
+        self: &Arc<Self>,
+        block_id: &BlockIdExt,
+        timeout: Option<Duration>,
+        stopper: &Stopper,
+    ) -> Result<Arc<ShardStateStuff>> {
+        let mut waiter = self.state_waiters.subscribe(block_id);
+        waiter.wait(timeout, stopper, || {
+            Ok(self.db.load_block_handle(block_id)?.map_or(false, |handle| handle.has_state()))
+        }).await?;
+        drop(waiter);
+        self.load_state(block_id).await
+    }
+
     // It is prohibited to use any cell from the state after the guard's disposal.
     pub async fn load_and_pin_state(self: &Arc<Self>, block_id: &BlockIdExt) -> Result<PinnedShardStateGuard> {
         log::trace!("load_and_pin_state {}", block_id);
@@ -242,16 +914,29 @@ impl ShardStatesKeeper {
                 Some(cb.clone() as Arc<dyn storage::shardstate_db_async::Callback>), 
                 Some(cb),
             )
+        } else if force || !handle.has_saved_state() {
+            // State is saved in background, next blocks are applied over the state in cache
+            let shard = handle.id().shard().clone();
+            self.unsaved_states.reserve(&shard, || self.check_stop()).await?;
+            let cb = BacklogCallback { backlog: self.unsaved_states.clone(), shard };
+            (Some(Arc::new(cb) as Arc<dyn storage::shardstate_db_async::Callback>), None)
         } else {
             (None, None)
         };
-        let saving = self.db.store_shard_state_dynamic(
-            handle,
-            &state,
-            None,
-            cb1,
-            force
-        ).await?.1;
+        let reserved = cb1.is_some() && cb2.is_none();
+        let saving = match self.db.store_shard_state_dynamic(handle, &state, None, cb1, force).await {
+            Ok((_, saving)) => saving,
+            Err(e) => {
+                if reserved {
+                    self.unsaved_states.release(handle.id().shard());
+                }
+                return Err(e)
+            }
+        };
+        if reserved && !saving {
+            // State was saved concurrently, callback is not invoked
+            self.unsaved_states.release(handle.id().shard());
+        }
 
         if let (true, Some(cb)) = (saving, cb2) {
             let now = Instant::now();
@@ -286,6 +971,10 @@ impl ShardStatesKeeper {
                 Ok(Some((state.clone(), handle.clone())))
             }
         )?;
+        if saved {
+            self.state_cached(&state, Instant::now());
+        }
+        self.state_waiters.notify(handle.id());
 
         Ok((state, saved))
     }
@@ -438,10 +1127,13 @@ impl ShardStatesKeeper {
         let try_get_state = |handle: &Arc<BlockHandle>| {
             if let Some(state) = self.states.get(handle.id()) {
                 log::trace!("load_state {} FROM CACHE", handle.id());
+                self.memory_governor.state_accessed(handle.id());
                 Some(state.val().0.clone())
             } else if handle.has_saved_state() {
+                let now = Instant::now();
                 if let Ok(state) = self.db.load_shard_state_dynamic(handle.id()) {
                     self.states.insert(handle.id().clone(), (state.clone(), handle.clone()));
+                    self.state_cached(&state, now);
                     Some(state)
                 } else {
                     log::warn!("Can't load state for {} from DB, but handle.has_saved_state() == true", handle.id());
@@ -628,10 +1320,18 @@ impl ShardStatesKeeper {
                         if self.cache_resolver.allow_state_gc(0, &guard.0, 0, 0)? {
                             if guard.val().1.has_saved_state() {
                                 self.states.remove(&guard.0);
+                                self.memory_governor.state_removed(&guard.0);
                                 cleaned +=1;
                             }
                         }
                     }
+                    self.memory_governor.forget_unloaded(
+                        |id| self.cache_resolver.allow_state_gc(0, id, 0, 0).unwrap_or(false)
+                    );
+                    self.prefetcher.forget_expected(
+                        |id| self.cache_resolver.allow_state_gc(0, id, 0, 0).unwrap_or(false)
+                    );
+                    self.memory_governor.report();
                     log::debug!(
                         "clean_cache_worker: TIME {time}ms, cleaned: {cleaned}, total: {total}",
                         time = now.elapsed().as_millis(),
@@ -652,6 +1352,21 @@ impl ShardStatesKeeper {
         }
     }
 
+    pub async fn persistent_states_gc(
+        &self,
+        engine: &Arc<Engine>,
+        counters: &GcCounters
+    ) -> Result<()> {
+        let calc_ttl = |t| {
+            let ttl = engine.persistent_state_ttl(t, boot::PSS_PERIOD_BITS);
+            let expired = ttl <= engine.now();
+            (ttl, expired)
+        };
+        let zerostate_id = engine.zero_state_id();
+        let is_pinned = |id: &BlockIdExt| self.gc_resolver.is_pinned(id);
+        self.db.shard_state_persistent_gc(calc_ttl, zerostate_id, is_pinned, counters).await
+    }
+
     async fn pss_worker(
         &self,
         engine: &Arc<Engine>,
@@ -664,10 +1379,22 @@ impl ShardStatesKeeper {
         let mut handle = engine.load_block_handle(ss_keeper_block)?.ok_or_else(
             || error!("Cannot load handle for ss keeper block {}", ss_keeper_block)
         )?;
+        let last_saved_id = match engine.load_last_persistent_state_block_id()? {
+            Some(id) => (*id).clone(),
+            None => engine.init_mc_block_id().clone()
+        };
+        let (mut last_saved_utime, mut last_saved_seqno) = match engine.load_block_handle(&last_saved_id)? {
+            Some(last_saved) => (last_saved.gen_utime()?, last_saved_id.seq_no()),
+            None => (0, last_saved_id.seq_no())
+        };
         loop {
             let mc_state = engine.load_state(handle.id()).await?;
             let mut is_persistent_state = false;
-            if handle.id().seq_no() != 0 && handle.is_key_block()? {
+            if let Some(policy) = &self.persistent_state_policy {
+                is_persistent_state = check_persistent_state_policy(
+                    policy, last_saved_utime, last_saved_seqno, &handle
+                )?;
+            } else if handle.id().seq_no() != 0 && handle.is_key_block()? {
                 if let Some(prev_key_block_id) = mc_state
                     .shard_state_extra()?
                     .prev_blocks
@@ -693,17 +1420,17 @@ impl ShardStatesKeeper {
                 } else {
                     // store states
                     self.save_persistent_state(&engine, handle.clone()).await?;
+                    if !engine.check_stop() {
+                        engine.save_last_persistent_state_block_id(handle.id())?;
+                        last_saved_utime = handle.gen_utime()?;
+                        last_saved_seqno = handle.id().seq_no();
+                    }
 
                     // gc iteration for persistent/stored states
 
                     if self.enable_persistent_gc {
-                        let calc_ttl = |t| {
-                            let ttl = engine.persistent_state_ttl(t, boot::PSS_PERIOD_BITS);
-                            let expired = ttl <= engine.now();
-                            (ttl, expired)
-                        };
-                        let zerostate_id = engine.zero_state_id();
-                        if let Err(e) = self.db.shard_state_persistent_gc(calc_ttl, zerostate_id).await {
+                        let counters = GcCounters::default();
+                        if let Err(e) = self.persistent_states_gc(engine, &counters).await {
                             log::warn!("persistent states gc: {}", e);
                         }
                     }
@@ -801,10 +1528,51 @@ impl ShardStatesKeeper {
         abort: Arc<dyn Fn() -> bool + Send + Sync>,
     ) -> Result<()> {
         let ss = self.wait_fully_stored_state(engine, handle.id()).await?;
-        self.db.store_shard_state_persistent(handle, ss, None, abort.clone()).await?;
+        let mut saved = false;
+        if let Some(base_id) = self.persistent_state_delta_base(handle).await {
+            match self.db.store_shard_state_persistent_delta(
+                handle, ss.clone(), &base_id, None, abort.clone()
+            ).await {
+                Ok(()) => saved = true,
+                Err(e) => log::warn!(
+                    "Can't save persistent state {} as delta over {}, saving full one: {}",
+                    handle.id(), base_id, e
+                )
+            }
+        }
+        if !saved {
+            self.db.store_shard_state_persistent(handle, ss, None, abort.clone()).await?;
+        }
+        self.last_persistent_states.insert(handle.id().shard().clone(), handle.id().clone());
         Ok(())
     }
 
+    // Previous persistent state of the same shard is a base for delta while the chain is short.
+    // After restart there is no base, so the first state is saved in full.
+    async fn persistent_state_delta_base(&self, handle: &Arc<BlockHandle>) -> Option<BlockIdExt> {
+        if self.persistent_state_delta_chain_limit == 0 || handle.has_persistent_state() {
+            return None
+        }
+        let base_id = self.last_persistent_states.get(handle.id().shard())?.val().clone();
+        if &base_id == handle.id() {
+            return None
+        }
+        match self.db.persistent_state_delta_chain_len(&base_id).await {
+            Ok(chain_len) if chain_len < self.persistent_state_delta_chain_limit => Some(base_id),
+            Ok(chain_len) => {
+                log::info!(
+                    "Persistent state {} is saved in full, chain of {} deltas is over {}",
+                    handle.id(), chain_len, base_id
+                );
+                None
+            }
+            Err(e) => {
+                log::warn!("Can't use persistent state {} as base for delta: {}", base_id, e);
+                None
+            }
+        }
+    }
+
     async fn wait_and_store_persistent_state(
         &self, 
         engine: &Engine, 
@@ -822,3 +1590,55 @@ impl ShardStatesKeeper {
         }
     }
 }
+
+// Decides if the state of given masterchain block must be saved as persistent one
+// according to custom policy. last_saved_* describe the last saved persistent state.
+pub fn check_persistent_state_policy(
+    policy: &PersistentStatePolicy,
+    last_saved_utime: u32,
+    last_saved_seqno: u32,
+    handle: &BlockHandle,
+) -> Result<bool> {
+    if handle.id().seq_no() == 0 || !handle.is_key_block()? {
+        return Ok(false)
+    }
+    let interval_passed = |interval_sec: u32| -> Result<bool> {
+        Ok(handle.gen_utime()?.saturating_sub(last_saved_utime) >= interval_sec)
+    };
+    let gap_passed = |seqno_gap: u32| handle.id().seq_no().saturating_sub(last_saved_seqno) >= seqno_gap;
+    let result = match policy {
+        PersistentStatePolicy::EveryKeyBlock => true,
+        PersistentStatePolicy::TimeInterval(interval_sec) => interval_passed(*interval_sec)?,
+        PersistentStatePolicy::SeqnoGap(seqno_gap) => gap_passed(*seqno_gap),
+        PersistentStatePolicy::Composite { interval_sec, seqno_gap } =>
+            interval_passed(*interval_sec)? || gap_passed(*seqno_gap)
+    };
+    Ok(result)
+}
+
+#[async_trait::async_trait]
+impl StatesLoader for ShardStatesKeeper {
+    fn is_applied(&self, block_id: &BlockIdExt) -> Result<bool> {
+        Ok(self.db.load_block_handle(block_id)?.map_or(false, |handle| handle.is_applied()))
+    }
+    fn is_cached(&self, block_id: &BlockIdExt) -> bool {
+        self.states.get(block_id).is_some()
+    }
+    fn has_budget(&self) -> bool {
+        let ceiling = self.memory_governor.ceiling();
+        ceiling == 0 || self.memory_governor.retained() < ceiling
+    }
+    fn key_block_id(&self, seq_no: u32) -> Result<BlockIdExt> {
+        Ok(self.db.find_mc_block_by_seq_no(seq_no)?.id().clone())
+    }
+    async fn warm_state(&self, block_id: &BlockIdExt) -> Result<()> {
+        self.check_stop()?;
+        let now = Instant::now();
+        let state = self.db.load_shard_state_dynamic(block_id)?;
+        self.cache_loaded_state(block_id, &state, now)
+    }
+}
+
+#[cfg(test)]
+#[path = "tests/test_shard_states_keeper.rs"]
+mod tests;
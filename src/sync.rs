@@ -13,24 +13,30 @@
 
 use crate::{
     block::{BlockIdExtExtention, BlockStuff}, block_proof::BlockProofStuff, boot,
-    engine_traits::EngineOperations
+    engine_traits::EngineOperations, error::{is_retryable, NodeError},
+    network::block_range::{BlockRangeQuery, BLOCK_RANGE_MAX_BYTES}, shard_state::ShardStateStuff,
+    validator::validator_utils::{batch_signature_check, SignatureBatch},
 };
 
 use adnl::common::Wait;
-use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+use std::{cmp::max, collections::{BTreeMap, HashMap}, fmt::Debug, sync::Arc, time::Instant};
 use storage::{
     archives::{
         ARCHIVE_PACKAGE_SIZE, package::read_package_from, 
         package_entry_id::PackageEntryId
     },
-    block_handle_db::BlockHandle
+    block_handle_db::{BlockHandle, BlockOrigin}
 };
-use ever_block::{BlockIdExt, BASE_WORKCHAIN_ID};
+use ever_block::{BlockIdExt, ShardIdent, BASE_WORKCHAIN_ID};
 use ever_block::{error, fail, KeyId, Result};
 
 //type PreDownloadTask = (u32, JoinHandle<Result<Vec<u8>>>);
 
 const TARGET: &str = "sync";
+// Signatures of several proofs are verified by one batch of at least this count
+const SIGNATURE_BATCH_MIN_LEN: usize = 2048;
+// Gap to the masterchain head closed by block ranges, bigger gaps are synced by archives
+const SYNC_BLOCK_RANGE_MAX_GAP: u32 = 512;
 
 #[async_trait::async_trait]
 pub trait StopSyncChecker {
@@ -47,7 +53,7 @@ pub(crate) async fn start_sync(
         seq_no: u32, 
         last_mc_block_id: &Arc<BlockIdExt>, 
         data: &Vec<u8>
-    ) -> Result<bool> {
+    ) -> Result<()> {
         log::info!(target: TARGET, "Reading package for MC seq_no = {}", seq_no);
         let maps = Arc::new(read_package(data).await?);
         log::info!(
@@ -58,123 +64,15 @@ pub(crate) async fn start_sync(
         );
         import_package(maps, engine, last_mc_block_id).await?;
         log::info!(target: TARGET, "Package imported for MC seq_no = {}", seq_no);
-        Ok(true)
-    }
-
-    fn download(
-        engine: &Arc<dyn EngineOperations>, 
-        wait: &Arc<Wait<(u32, Result<Option<Vec<u8>>>)>>, 
-        seq_no: u32,
-        active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>
-    ) {
-        wait.request();
-        let engine = engine.clone();
-        let wait = wait.clone();
-        let active_peers = active_peers.clone();
-        tokio::spawn(
-            async move {
-                let res = download_archive(engine, seq_no, &active_peers).await;
-                wait.respond(Some((seq_no, res)));
-            }
-        );
-        log::info!(target: TARGET, "Download scheduled for MC seq_no = {}", seq_no);
-    }
-
-    async fn force_redownload(
-        engine: &Arc<dyn EngineOperations>, 
-        wait: &Arc<Wait<(u32, Result<Option<Vec<u8>>>)>>, 
-        queue: &mut Vec<(u32, ArchiveStatus)>,
-        active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>
-    ) -> Result<()> {
-        // Find latest downloaded archive
-        let mut latest = None;
-        for (seq_no, status) in queue.iter_mut() {
-            if let ArchiveStatus::Downloaded(_) = status {
-                if let Some(latest) = latest {
-                    if latest >= *seq_no {
-                        continue
-                    }
-                }
-                latest = Some(*seq_no)
-            }
-        }
-        // Redownload previous not found ones 
-        if let Some(latest) = latest {
-            for (seq_no, status) in queue.iter_mut() {
-                if let ArchiveStatus::NotFound = status {
-                    if latest <= *seq_no {
-                        continue
-                    }
-                    *status = ArchiveStatus::Downloading;
-                    download(engine, wait, *seq_no, active_peers)
-                }
-            }
-        }
-        // Redownload earliest not found if only not found remained
-        if !engine.check_sync().await? {
-            let mut earliest = None;
-            for (seq_no, status) in queue.iter_mut() {
-                if let ArchiveStatus::NotFound = status {
-                    if let Some(earliest) = earliest {
-                        if earliest <= *seq_no {
-                            continue
-                        }
-                    }
-                    earliest = Some(*seq_no)
-                } else {
-                    // There is another status
-                    return Ok(())
-                }
-            }
-            if let Some(earliest) = earliest {
-                for (seq_no, status) in queue.iter_mut() {
-                    if earliest == *seq_no {
-                        *status = ArchiveStatus::Downloading;
-                        download(engine, wait, *seq_no, active_peers);
-                        break
-                    }
-                }
-            }
-        }
-        Ok(())
-    }
-
-    async fn new_downloads(
-        engine: &Arc<dyn EngineOperations>, 
-        wait: &Arc<Wait<(u32, Result<Option<Vec<u8>>>)>>, 
-        queue: &mut Vec<(u32, ArchiveStatus)>,
-        active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>,
-        mut sync_mc_seq_no: u32,
-        concurrency: usize
-    ) -> Result<()> {
-        force_redownload(engine, wait, queue, active_peers).await?;
-        while wait.count() < concurrency {
-            if queue.iter().count() > concurrency {
-                // Do not download too much in advance due to possible OOM
-                break
-            } 
-            if queue.iter().position(|(seq_no, _)| seq_no == &sync_mc_seq_no).is_none() {
-                queue.push((sync_mc_seq_no, ArchiveStatus::Downloading));
-                download(engine, wait, sync_mc_seq_no, active_peers);
-            }
-            sync_mc_seq_no += ARCHIVE_PACKAGE_SIZE;
-        }
         Ok(())
     }
 
-    enum ArchiveStatus {
-        Downloading,
-        NotFound,
-        Downloaded(Vec<u8>)
-    }
-
-    const MAX_CONCURRENCY: usize = 8;
-
     log::info!(target: TARGET, "Started sync");
-    let active_peers = Arc::new(lockfree::set::Set::new());
-    let mut queue: Vec<(u32, ArchiveStatus)> = Vec::new();
-    let (wait, mut reader) = Wait::new();
-    let mut concurrency = 1;
+    let mut queue = DownloadQueue::new(
+        engine.clone(),
+        engine.sync_download_concurrency(),
+        engine.sync_max_downloaded_archives()
+    );
 
     'check: while !engine.check_sync().await? {
 
@@ -208,138 +106,240 @@ pub(crate) async fn start_sync(
             last_mc_block_id.seq_no(), mc_block_id, sc_block_id,
         );
 
-        // Try to find proper # in queue
         let sync_mc_seq_no = last_mc_block_id.seq_no() + 1;
+        log::info!(target: TARGET, "Continue sync with MC seq_no {}", sync_mc_seq_no);
+        let head_seq_no = engine.last_known_mc_block_seqno();
+        if (head_seq_no >= sync_mc_seq_no) &&
+            (head_seq_no - last_mc_block_id.seq_no() <= SYNC_BLOCK_RANGE_MAX_GAP)
+        {
+            match sync_block_range(&engine, &last_mc_block_id, head_seq_no).await {
+                Ok(true) => continue 'check,
+                Ok(false) => (),
+                Err(e) if NodeError::is_cancelled(&e) => return Err(e),
+                Err(e) => log::warn!(
+                    target: TARGET,
+                    "Cannot sync by block range from MC seq_no {}: {}, archives are used",
+                    sync_mc_seq_no, e
+                )
+            }
+        }
         loop {
-            new_downloads(
-                &engine, &wait, &mut queue, &active_peers, sync_mc_seq_no, concurrency
-            ).await?;
-            if let Some(index) = queue.iter().position(
-                |(seq_no, status)| match status {
-                    ArchiveStatus::Downloaded(_) => seq_no <= &sync_mc_seq_no,
-                    _ => false
-                }
-            ) {
-                if let (seq_no, ArchiveStatus::Downloaded(data)) = queue.remove(index) {
-                    match apply(&engine, seq_no, &last_mc_block_id, &data).await {
-                        Ok(true) => continue 'check,
-                        Ok(false) => (),
-                        Err(e) => log::error!(
+            queue.new_downloads(sync_mc_seq_no).await?;
+            // Packages are applied strictly in order, the ones downloaded in advance wait
+            if let Some((seq_no, data)) = queue.take_ready(sync_mc_seq_no) {
+                match apply(&engine, seq_no, &last_mc_block_id, &data).await {
+                    Ok(()) => {
+                        queue.set_full_concurrency();
+                        continue 'check
+                    },
+                    Err(e) if NodeError::is_cancelled(&e) => return Err(e),
+                    Err(e) => {
+                        log::error!(
                             target: TARGET,
-                            "Cannot apply queued package for MC seq_no = {}: {}",
+                            "Cannot apply package for MC seq_no = {}: {}",
                             seq_no, e
-                        )
+                        );
+                        if !is_retryable(&e) {
+                            return Err(e)
+                        }
+                        queue.download(seq_no)
                     }
-                } else {
-                    fail!("INTERNAL ERROR: sync queue broken")
                 }
+            } else if engine.check_sync().await? {
+                break 'check
             } else {
-                break
+                queue.wait_download().await?
             }
         }
 
-        log::info!(target: TARGET, "Continue sync with MC seq_no {}", sync_mc_seq_no);
+    }
 
-        // Otherwise download
-        while !engine.check_sync().await? {
-            new_downloads(
-                &engine, &wait, &mut queue, &active_peers, sync_mc_seq_no, concurrency
-            ).await?;
-/*
-            while wait.count() < concurrency {
-                if queue.iter().count() > concurrency {
-                    // Do not download too much in advance due to possible OOM
-                    break
-                } 
-                if queue.iter().position(|(seq_no, _)| seq_no == &sync_mc_seq_no).is_none() {
-                    queue.push((sync_mc_seq_no, ArchiveStatus::Downloading));
-                    download(&engine, &wait, sync_mc_seq_no, &active_peers);
+    log::info!(target: TARGET, "Sync complete");
+    Ok(())
+
+}
+
+pub const DEFAULT_SYNC_DOWNLOAD_CONCURRENCY: usize = 16;
+pub const DEFAULT_SYNC_MAX_DOWNLOADED_ARCHIVES: usize = 16;
+
+type DownloadResult = (u32, Result<Option<Vec<u8>>>);
+
+enum ArchiveStatus {
+    Downloading,
+    NotFound,
+    Downloaded(Vec<u8>)
+}
+
+// Bounded pool of archive downloads. Archives are downloaded concurrently and may arrive
+// in any order, but are handed out for applying strictly by MC seq_no.
+struct DownloadQueue {
+    engine: Arc<dyn EngineOperations>,
+    active_peers: Arc<lockfree::set::Set<Arc<KeyId>>>,
+    wait: Arc<Wait<DownloadResult>>,
+    reader: tokio::sync::mpsc::UnboundedReceiver<Option<DownloadResult>>,
+    archives: BTreeMap<u32, ArchiveStatus>,
+    concurrency: usize,
+    max_concurrency: usize,
+    // Limits count of archives kept in memory (downloaded or being downloaded)
+    max_downloaded: usize,
+}
+
+impl DownloadQueue {
+
+    fn new(
+        engine: Arc<dyn EngineOperations>, 
+        max_concurrency: usize, 
+        max_downloaded: usize
+    ) -> Self {
+        let (wait, reader) = Wait::new();
+        Self {
+            engine,
+            active_peers: Arc::new(lockfree::set::Set::new()),
+            wait,
+            reader,
+            archives: BTreeMap::new(),
+            // Start with single download until the first package is applied
+            concurrency: 1,
+            max_concurrency: max(max_concurrency, 1),
+            max_downloaded: max(max_downloaded, 1),
+        }
+    }
+
+    fn set_full_concurrency(&mut self) {
+        self.concurrency = self.max_concurrency
+    }
+
+    fn in_flight(&self) -> usize {
+        self.archives.values().filter(|s| matches!(s, ArchiveStatus::Downloading)).count()
+    }
+
+    fn downloaded(&self) -> usize {
+        self.archives.values().filter(|s| matches!(s, ArchiveStatus::Downloaded(_))).count()
+    }
+
+    fn download(&mut self, seq_no: u32) {
+        self.archives.insert(seq_no, ArchiveStatus::Downloading);
+        self.wait.request();
+        let engine = self.engine.clone();
+        let wait = self.wait.clone();
+        let active_peers = self.active_peers.clone();
+        tokio::spawn(
+            async move {
+                let res = download_archive(engine, seq_no, &active_peers).await;
+                wait.respond(Some((seq_no, res)));
+            }
+        );
+        log::info!(target: TARGET, "Download scheduled for MC seq_no = {}", seq_no);
+        self.report();
+    }
+
+    async fn force_redownload(&mut self) -> Result<()> {
+        // Redownload not found archives preceding the latest downloaded one
+        let latest = self.archives.iter().rev()
+            .find(|(_, status)| matches!(status, ArchiveStatus::Downloaded(_)))
+            .map(|(seq_no, _)| *seq_no);
+        if let Some(latest) = latest {
+            let not_found = self.archives.range(..latest)
+                .filter(|(_, status)| matches!(status, ArchiveStatus::NotFound))
+                .map(|(seq_no, _)| *seq_no)
+                .collect::<Vec<_>>();
+            for seq_no in not_found {
+                self.download(seq_no)
+            }
+        }
+        // Redownload earliest not found if only not found remained
+        if !self.engine.check_sync().await? {
+            if self.archives.values().all(|status| matches!(status, ArchiveStatus::NotFound)) {
+                if let Some(earliest) = self.archives.keys().next().cloned() {
+                    self.download(earliest)
                 }
-                sync_mc_seq_no += ARCHIVE_PACKAGE_SIZE;
             }
-*/
-            match wait.wait(&mut reader, false).await {
-                Some(Some((seq_no, Err(e)))) => {
-                    log::error!(
-                        target: TARGET,
-                        "Error while downloading package seq_no {}: {}",
-                        seq_no, e
-                    );
-                    download(&engine, &wait, seq_no, &active_peers)
-                },
-                Some(Some((seq_no_recv, Ok(data)))) => {
-                    if let Some(index) = queue.iter().position(
-                        |(seq_no_send, status)| match status {
-                            ArchiveStatus::Downloading => seq_no_send == &seq_no_recv,
-                            _ => false
-                        }
-                    ) {
-                        if let Some(data) = data {
-/*
-                            // Redownload all previously not found archives
-                            for (seq_no, status) in queue.iter_mut() {
-                                if let ArchiveStatus::NotFound = status {
-                                    if *seq_no < seq_no_recv {
-                                        *status = ArchiveStatus::Downloading;
-                                        download(&engine, &wait, *seq_no, &active_peers)
-                                    }
-                                }
-                            }
-*/
-                            if seq_no_recv <= last_mc_block_id.seq_no() + 1 {
-                                match apply(&engine, seq_no_recv, &last_mc_block_id, &data).await {
-                                    Ok(ok) => {
-                                        queue.remove(index);
-                                        if ok {
-                                            concurrency = MAX_CONCURRENCY;
-                                            break
-                                        }
-                                    },
-                                    Err(e) => {
-                                        log::error!(
-                                            target: TARGET,
-                                            "Cannot apply downloaded package for MC seq_no = {}: {}",
-                                            seq_no_recv, e
-                                        );
-                                        download(&engine, &wait, seq_no_recv, &active_peers)
-                                    }
-                                }
-                            } else {
-                                let (_, status) = &mut queue[index];
-                                *status = ArchiveStatus::Downloaded(data);
-                                force_redownload(&engine, &wait, &mut queue, &active_peers).await?;
-                            }
-                        } else {
-                            let (_, status) = &mut queue[index];
-                            *status = ArchiveStatus::NotFound;
-                            force_redownload(&engine, &wait, &mut queue, &active_peers).await?;
-/*                            if queue.iter().position(
-                                |(seq_no, status)| match status {
-                                    ArchiveStatus::Downloaded(_) => seq_no > &seq_no_recv,
-                                    _ => false
-                                }
-                            ).is_some() {
-                                // Redownload because there is a later archive
-                                download(&engine, &wait, seq_no_recv, &active_peers);
-                            } else {
-                                // Mark not found
-                                let (_, status) = &mut queue[index];
-                                *status = ArchiveStatus::NotFound;
-                            }
-*/
-                        }
-                    } else {
-                        fail!("INTERNAL ERROR: sync queue broken")
-                    }
-                },
-                _ => fail!("INTERNAL ERROR: sync broken")
+        }
+        Ok(())
+    }
+
+    async fn new_downloads(&mut self, mut sync_mc_seq_no: u32) -> Result<()> {
+        self.force_redownload().await?;
+        // Archive for sync seq_no is always requested, otherwise the pipeline stalls
+        if !self.archives.contains_key(&sync_mc_seq_no) {
+            self.download(sync_mc_seq_no)
+        }
+        while self.in_flight() < self.concurrency {
+            if self.archives.len() >= self.max_downloaded {
+                // Do not download too much in advance due to possible OOM
+                break
             }
+            if !self.archives.contains_key(&sync_mc_seq_no) {
+                self.download(sync_mc_seq_no)
+            }
+            sync_mc_seq_no += ARCHIVE_PACKAGE_SIZE;
         }
+        Ok(())
+    }
+
+    // Earliest downloaded archive which may be applied on top of sync seq_no
+    fn take_ready(&mut self, sync_mc_seq_no: u32) -> Option<(u32, Vec<u8>)> {
+        let seq_no = self.archives.range(..=sync_mc_seq_no)
+            .find(|(_, status)| matches!(status, ArchiveStatus::Downloaded(_)))
+            .map(|(seq_no, _)| *seq_no)?;
+        let ret = match self.archives.remove(&seq_no) {
+            Some(ArchiveStatus::Downloaded(data)) => Some((seq_no, data)),
+            _ => None
+        };
+        self.report();
+        ret
+    }
 
+    async fn wait_download(&mut self) -> Result<()> {
+        if self.in_flight() == 0 {
+            fail!("INTERNAL ERROR: sync queue broken")
+        }
+        match self.wait.wait(&mut self.reader, false).await {
+            // Other downloads are cancelled as well, they are not waited for
+            Some(Some((_, Err(e)))) if NodeError::is_cancelled(&e) => return Err(e),
+            Some(Some((seq_no, Err(e)))) => {
+                log::error!(
+                    target: TARGET,
+                    "Error while downloading package seq_no {}: {}",
+                    seq_no, e
+                );
+                if !is_retryable(&e) {
+                    return Err(e)
+                }
+                // Failed peer is excluded from active peers by overlay client,
+                // so the archive is requested from another one. Other downloads go on.
+                self.download(seq_no)
+            },
+            Some(Some((seq_no, Ok(data)))) => {
+                match self.archives.get_mut(&seq_no) {
+                    Some(status) if matches!(status, ArchiveStatus::Downloading) => {
+                        *status = match data {
+                            Some(data) => ArchiveStatus::Downloaded(data),
+                            None => ArchiveStatus::NotFound
+                        };
+                    },
+                    _ => fail!("INTERNAL ERROR: sync queue broken")
+                }
+                self.report();
+                self.force_redownload().await?;
+            },
+            _ => fail!("INTERNAL ERROR: sync broken")
+        }
+        Ok(())
     }
 
-    log::info!(target: TARGET, "Sync complete");
-    Ok(())
+    fn report(&self) {
+        log::debug!(
+            target: TARGET,
+            "Sync queue: {} archives downloaded, {} downloading",
+            self.downloaded(), self.in_flight()
+        );
+        #[cfg(feature = "telemetry")] {
+            let telemetry = self.engine.engine_telemetry();
+            telemetry.sync_queue_depth.update(self.downloaded() as u64);
+            telemetry.sync_downloads.update(self.in_flight() as u64);
+        }
+    }
 
 }
 
@@ -349,7 +349,11 @@ async fn download_archive(
     active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>
 ) -> Result<Option<Vec<u8>>> {
     log::info!(target: "sync", "Requesting archive for MC seq_no = {}", mc_seq_no);
-    match engine.download_archive(mc_seq_no, active_peers).await {
+    let result = engine.stopper().cancellable(
+        format!("Download archive for MC seq_no = {}", mc_seq_no),
+        engine.download_archive(mc_seq_no, active_peers)
+    ).await?;
+    match result {
         Ok(Some(data)) => {
             log::info!(
                 target: "sync",
@@ -363,10 +367,15 @@ async fn download_archive(
             log::info!(target: "sync", "No archive found for MC seq_no = {}", mc_seq_no);
             Ok(None)
         },
-        Err(e) => fail!(
-            "Download archive failed for MC seq_no = {}, err: {}, active peers {}", 
-            mc_seq_no, e, active_peers.iter().count()
-        )
+        Err(e) => {
+            // Original error is returned to keep its kind
+            log::warn!(
+                target: "sync",
+                "Download archive failed for MC seq_no = {}, err: {}, active peers {}", 
+                mc_seq_no, e, active_peers.iter().count()
+            );
+            Err(e)
+        }
     }
 }
 
@@ -408,6 +417,41 @@ async fn download_and_import_package(
 }
 */
 
+// Downloads masterchain blocks next to the last applied one and imports them as a package.
+// Returns false if peers have no blocks to serve
+async fn sync_block_range(
+    engine: &Arc<dyn EngineOperations>,
+    last_mc_block_id: &Arc<BlockIdExt>,
+    to_seq_no: u32
+) -> Result<bool> {
+    let query = BlockRangeQuery {
+        shard: ShardIdent::masterchain(),
+        from_seqno: last_mc_block_id.seq_no() + 1,
+        to_seqno: to_seq_no,
+        max_bytes: BLOCK_RANGE_MAX_BYTES,
+    };
+    let range = engine.download_block_range(&query).await?;
+    if range.blocks.is_empty() {
+        return Ok(false)
+    }
+    log::info!(
+        target: TARGET,
+        "Downloaded range of {} masterchain blocks from seq_no {}",
+        range.blocks.len(), query.from_seqno
+    );
+    let mut maps = BlockMaps::default();
+    for (block, proof) in range.blocks {
+        let id = Arc::new(block.id().clone());
+        maps.mc_blocks_ids.insert(id.seq_no(), Arc::clone(&id));
+        maps.blocks.insert(
+            id,
+            BlocksEntry { block: Some(Arc::new(block)), proof: Some(Arc::new(proof)) }
+        );
+    }
+    import_package(Arc::new(maps), engine, last_mc_block_id).await?;
+    Ok(true)
+}
+
 async fn import_package(
     maps: Arc<BlockMaps>,
     engine: &Arc<dyn EngineOperations>,
@@ -417,6 +461,7 @@ async fn import_package(
         fail!("Archive doesn't contain any masterchain blocks!");
     }
 
+    check_proofs(engine, &maps, last_mc_block_id).await?;
     import_mc_blocks(engine, &maps, last_mc_block_id).await?;
     import_shard_blocks(engine, maps).await?;
 
@@ -492,6 +537,7 @@ async fn read_package(data: &Vec<u8>) -> Result<BlockMaps> {
     Ok(maps)
 }
 
+// Proof must be already checked by `check_proofs`
 async fn save_block(
     engine: &Arc<dyn EngineOperations>,
     block_id: &BlockIdExt,
@@ -513,10 +559,10 @@ async fn save_block(
         };
         fail!("Proof{} not found in archive: {}", link_str, block_id);
     };
-    proof.check_proof(engine.as_ref()).await?;
     let handle = engine.store_block(&block).await?.to_non_created().ok_or_else(
         || error!("INTERNAL ERROR: mismatch in block {} store result during sync", block_id)
     )?;
+    handle.set_origin(BlockOrigin::Download);
     let handle = engine.store_block_proof(0, block_id, Some(handle), &proof).await?
         .to_non_created()
         .ok_or_else(
@@ -551,13 +597,241 @@ async fn wait_for(tasks: Vec<tokio::task::JoinHandle<Result<()>>>) -> Result<()>
     Ok(())
 }
 
+// What a masterchain block proof is checked with
+enum ProofBase {
+    ZeroState(Arc<ShardStateStuff>),
+    KeyBlock(Arc<BlockProofStuff>),
+    // Previous key block is imported from the same package, so it is checked first
+    InPackage,
+}
+
+struct ProofCheck {
+    proof: Arc<BlockProofStuff>,
+    base: Option<ProofBase>,
+}
+
+impl ProofCheck {
+    // Signatures are added to the batch if it is given
+    fn check(&self, prev_key_block: Option<&ProofCheck>, batch: Option<&mut SignatureBatch>) -> Result<()> {
+        let base = match (&self.base, prev_key_block) {
+            (None, _) => return self.proof.check_proof_link(),
+            (Some(ProofBase::ZeroState(state)), _) => {
+                return match batch {
+                    Some(batch) => self.proof.check_with_master_state_deferred(state, batch),
+                    None => self.proof.check_with_master_state(state)
+                }
+            }
+            (Some(ProofBase::KeyBlock(proof)), _) => proof,
+            (Some(ProofBase::InPackage), Some(prev)) => &prev.proof,
+            (Some(ProofBase::InPackage), None) => {
+                fail!("INTERNAL ERROR: no previous key block to check proof {}", self.proof.id())
+            }
+        };
+        match batch {
+            Some(batch) => self.proof.check_with_prev_key_block_proof_deferred(base, batch),
+            None => self.proof.check_with_prev_key_block_proof(base)
+        }
+    }
+}
+
+// Runs checks on blocking threads, at most `parallelism` at once. A check starts only
+// after successful check it depends on, which must precede it in the list.
+async fn check_in_parallel<T: Send + Sync + 'static>(
+    items: Vec<(T, Option<usize>)>,
+    parallelism: usize,
+    check: Arc<dyn Fn(&T, Option<&T>) -> Result<()> + Send + Sync>,
+) -> Result<()> {
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(max(parallelism, 1)));
+    let mut senders = Vec::with_capacity(items.len());
+    let mut receivers = Vec::with_capacity(items.len());
+    for _ in 0..items.len() {
+        let (sender, receiver) = tokio::sync::watch::channel(None);
+        senders.push(sender);
+        receivers.push(receiver);
+    }
+    let mut dependencies = Vec::with_capacity(items.len());
+    for (index, (_, depends_on)) in items.iter().enumerate() {
+        match depends_on {
+            Some(dep) if *dep >= index => {
+                fail!("INTERNAL ERROR: check {} depends on later check {}", index, dep)
+            },
+            Some(dep) => dependencies.push(Some((*dep, receivers[*dep].clone()))),
+            None => dependencies.push(None)
+        }
+    }
+    let items = Arc::new(items);
+    let mut tasks = Vec::with_capacity(items.len());
+    for (index, (sender, dependency)) in senders.into_iter().zip(dependencies).enumerate() {
+        let items = items.clone();
+        let semaphore = semaphore.clone();
+        let check = check.clone();
+        tasks.push(tokio::spawn(async move {
+            let result = async {
+                let dependency = if let Some((dep, mut receiver)) = dependency {
+                    loop {
+                        let done = *receiver.borrow();
+                        match done {
+                            Some(true) => break,
+                            Some(false) => fail!("Check {} depends on failed check {}", index, dep),
+                            None => receiver.changed().await?
+                        }
+                    }
+                    Some(dep)
+                } else {
+                    None
+                };
+                let _permit = semaphore.acquire_owned().await?;
+                tokio::task::spawn_blocking(move || {
+                    check(&items[index].0, dependency.map(|dep| &items[dep].0))
+                }).await?
+            }.await;
+            sender.send(Some(result.is_ok())).ok();
+            result
+        }));
+    }
+    wait_for(tasks).await
+}
+
+fn merge_signature_batches(batches: Vec<SignatureBatch>) -> Vec<SignatureBatch> {
+    let mut merged = Vec::new();
+    let mut current = SignatureBatch::new();
+    for batch in batches {
+        current.append(batch);
+        if current.len() >= SIGNATURE_BATCH_MIN_LEN {
+            merged.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        merged.push(current);
+    }
+    merged
+}
+
+// Checks all proofs to be imported from the package before blocks are applied in order
+async fn check_proofs(
+    engine: &Arc<dyn EngineOperations>,
+    maps: &BlockMaps,
+    last_mc_block_id: &BlockIdExt
+) -> Result<()> {
+    let now = Instant::now();
+    let mut checks = Vec::new();
+    // Index of the check of in-package key block by its seq_no
+    let mut key_blocks: HashMap<u32, usize> = HashMap::new();
+    let mut bases: HashMap<u32, Arc<BlockProofStuff>> = HashMap::new();
+    let mut zero_state = None;
+    for id in maps.mc_blocks_ids.values() {
+        if id.seq_no() <= last_mc_block_id.seq_no() {
+            continue
+        }
+        if let Some(handle) = engine.load_block_handle(id)? {
+            if handle.is_applied() {
+                continue
+            }
+        }
+        let Some(proof) = maps.blocks.get(id).and_then(|entry| entry.proof.clone()) else {
+            // Reported by `save_block`
+            continue
+        };
+        let (virt_block, _) = proof.virtualize_block()?;
+        let info = virt_block.read_info()?;
+        let prev_key_block_seqno = info.prev_key_block_seqno();
+        let (base, depends_on) = if let Some(index) = key_blocks.get(&prev_key_block_seqno) {
+            (ProofBase::InPackage, Some(*index))
+        } else if prev_key_block_seqno == 0 {
+            let state = match &zero_state {
+                Some(state) => Arc::clone(state),
+                None => {
+                    let state = engine.load_mc_zero_state().await?;
+                    zero_state = Some(state.clone());
+                    state
+                }
+            };
+            (ProofBase::ZeroState(state), None)
+        } else {
+            let proof = match bases.get(&prev_key_block_seqno) {
+                Some(proof) => proof.clone(),
+                None => {
+                    let handle = engine.find_mc_block_by_seq_no(prev_key_block_seqno).await
+                        .map_err(|err| error!(
+                            "Couldn't find previous MC key block by seq_no = {}: {}",
+                            prev_key_block_seqno, err
+                        ))?;
+                    let proof = Arc::new(engine.load_block_proof(&handle, false).await?);
+                    bases.insert(prev_key_block_seqno, proof.clone());
+                    proof
+                }
+            };
+            (ProofBase::KeyBlock(proof), None)
+        };
+        if info.key_block() {
+            key_blocks.insert(id.seq_no(), checks.len());
+        }
+        checks.push((ProofCheck { proof, base: Some(base) }, depends_on));
+    }
+    for (id, entry) in maps.blocks.iter() {
+        if !id.is_masterchain() {
+            if let Some(proof) = &entry.proof {
+                checks.push((ProofCheck { proof: proof.clone(), base: None }, None));
+            }
+        }
+    }
+    let count = checks.len();
+    #[cfg(feature = "telemetry")]
+    let telemetry = engine.engine_telemetry().clone();
+    // Signatures are verified after all other checks by batches across proofs. Proof signed by
+    // validators of in-package key block is checked before the key block signatures, but nothing
+    // is applied until all signatures are verified
+    let batches = batch_signature_check().then(|| Arc::new(parking_lot::Mutex::new(Vec::new())));
+    check_in_parallel(
+        checks,
+        engine.sync_proof_check_threads(),
+        Arc::new({
+            let batches = batches.clone();
+            move |check: &ProofCheck, prev: Option<&ProofCheck>| -> Result<()> {
+                match &batches {
+                    Some(batches) => {
+                        let mut batch = SignatureBatch::new();
+                        check.check(prev, Some(&mut batch))?;
+                        batches.lock().push(batch);
+                    }
+                    None => check.check(prev, None)?
+                }
+                #[cfg(feature = "telemetry")]
+                telemetry.sync_checked_proofs.update(1);
+                Ok(())
+            }
+        })
+    ).await?;
+    if let Some(batches) = batches {
+        let batches = merge_signature_batches(std::mem::take(&mut *batches.lock()));
+        check_in_parallel(
+            batches.into_iter().map(|batch| (batch, None)).collect(),
+            engine.sync_proof_check_threads(),
+            Arc::new(|batch: &SignatureBatch, _: Option<&SignatureBatch>| batch.verify())
+        ).await?;
+    }
+    let elapsed = now.elapsed();
+    log::info!(
+        target: TARGET,
+        "Checked {} proofs in {}ms, {:.1} proofs/sec",
+        count, elapsed.as_millis(), count as f64 / elapsed.as_secs_f64().max(0.001)
+    );
+    Ok(())
+}
+
 async fn import_mc_blocks(
     engine: &Arc<dyn EngineOperations>,
     maps: &BlockMaps,
     mut last_mc_block_id: &Arc<BlockIdExt>
 ) -> Result<()> {
 
-    for id in maps.mc_blocks_ids.values() {
+    let distance = engine.states_prefetch_distance() as usize;
+    let mc_blocks_ids = maps.mc_blocks_ids.values().collect::<Vec<_>>();
+    for (i, id) in mc_blocks_ids.iter().copied().enumerate() {
+
+        for ahead in prefetch_window(i, distance).filter_map(|j| mc_blocks_ids.get(j)) {
+            prefetch_block_states(engine, maps, ahead);
+        }
 
         if id.seq_no() <= last_mc_block_id.seq_no() {
             if id.seq_no() == last_mc_block_id.seq_no() {
@@ -619,7 +893,12 @@ async fn import_shard_blocks(
         None => fail!("INTERNAL ERROR: No shard client MC block set in sync")
     };
     let workchain_id = engine.processed_workchain().unwrap_or(BASE_WORKCHAIN_ID);
-    for mc_block_id in maps.mc_blocks_ids.values() {
+    let distance = engine.states_prefetch_distance() as usize;
+    let mc_blocks_ids = maps.mc_blocks_ids.values().collect::<Vec<_>>();
+    for (i, mc_block_id) in mc_blocks_ids.iter().copied().enumerate() {
+        for ahead in prefetch_window(i, distance).filter_map(|j| mc_blocks_ids.get(j)) {
+            prefetch_shard_states(engine, &maps, ahead, workchain_id);
+        }
         let mc_seq_no = mc_block_id.seq_no();
         if mc_seq_no <= shard_client_mc_block_id.seq_no() {
             log::debug!(
@@ -651,9 +930,15 @@ async fn import_shard_blocks(
                     mc_handle.id()
                 );
 
-                let handle = engine.load_block_handle(&id)?.ok_or_else(
-                    || error!("Cannot load handle for shard block {}", id)
-                )?;
+                let handle = match engine.load_block_handle(&id)? {
+                    Some(handle) => handle,
+                    // Block ranges carry masterchain blocks only
+                    None if !maps.blocks.contains_key(&id) => {
+                        log::debug!(target: "sync", "Downloading shardchain block: {}...", id);
+                        return Arc::clone(&engine).download_and_apply_block(&id, mc_seq_no, false).await
+                    },
+                    None => fail!("Cannot load handle for shard block {}", id)
+                };
                 if handle.is_applied() {
                     log::debug!(target: "sync", "Skipped already applied block: {}", id);
                     return Ok(());
@@ -703,6 +988,42 @@ async fn import_shard_blocks(
     Ok(())
 }
 
+// Blocks which get into the prefetch window when i-th block is imported.
+// Every block is announced once, the first ones are all announced at start.
+fn prefetch_window(i: usize, distance: usize) -> std::ops::Range<usize> {
+    let end = i + distance + 1;
+    match distance {
+        0 => 0..0,
+        _ if i == 0 => 1..end,
+        _ => end - 1..end
+    }
+}
+
+fn prefetch_block_states(engine: &Arc<dyn EngineOperations>, maps: &BlockMaps, id: &BlockIdExt) {
+    if let Some(block) = maps.blocks.get(id).and_then(|entry| entry.block.as_ref()) {
+        engine.prefetch_states(block)
+    }
+}
+
+fn prefetch_shard_states(
+    engine: &Arc<dyn EngineOperations>,
+    maps: &BlockMaps,
+    mc_block_id: &BlockIdExt,
+    workchain_id: i32
+) {
+    let Some(mc_block) = maps.blocks.get(mc_block_id).and_then(|entry| entry.block.as_ref()) else {
+        return
+    };
+    match mc_block.top_blocks(workchain_id) {
+        Ok(ids) => for id in ids {
+            prefetch_block_states(engine, maps, &id)
+        },
+        Err(e) => log::debug!(
+            target: "sync", "Cannot prefetch shard states for {}: {}", mc_block_id, e
+        )
+    }
+}
+
 #[cfg(test)]
 #[path = "tests/test_sync.rs"]
 mod tests;
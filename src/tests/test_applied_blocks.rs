@@ -0,0 +1,103 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::collator_test_bundle::create_block_handle_storage;
+use ever_block::{ShardIdent, UInt256, BASE_WORKCHAIN_ID, MASTERCHAIN_ID};
+use storage::{block_handle_db::BlockHandleStorage, types::BlockMeta};
+
+fn apply_handle(
+    storage: &BlockHandleStorage,
+    notifier: &AppliedBlocksNotifier,
+    workchain_id: i32,
+    seq_no: u32
+) -> Arc<BlockHandle> {
+    let id = BlockIdExt {
+        shard_id: ShardIdent::with_tagged_prefix(workchain_id, ever_block::SHARD_FULL).unwrap(),
+        seq_no,
+        root_hash: UInt256::from([seq_no as u8; 32]),
+        file_hash: UInt256::from([workchain_id as u8; 32]),
+    };
+    let handle = storage.create_handle(id, BlockMeta::default(), None).unwrap().unwrap();
+    if handle.set_block_applied() {
+        notifier.notify(&handle, None);
+    }
+    handle
+}
+
+fn applied_seq_no(event: Option<AppliedBlockEvent>) -> u32 {
+    match event {
+        Some(AppliedBlockEvent::Applied(block)) => block.id().seq_no(),
+        _ => panic!("applied block is expected")
+    }
+}
+
+#[tokio::test]
+async fn test_applied_blocks_delivery_and_filter() {
+    let storage = create_block_handle_storage();
+    let notifier = AppliedBlocksNotifier::new(DEFAULT_APPLIED_BLOCKS_CHANNEL_SIZE);
+    let mut mc = notifier.subscribe(ShardFilter::Masterchain).unwrap();
+    let mut wc = notifier.subscribe(ShardFilter::Workchain(BASE_WORKCHAIN_ID)).unwrap();
+    let mut all = notifier.subscribe(ShardFilter::All).unwrap();
+
+    let mc_handle = apply_handle(&storage, &notifier, MASTERCHAIN_ID, 1);
+    apply_handle(&storage, &notifier, BASE_WORKCHAIN_ID, 2);
+    apply_handle(&storage, &notifier, BASE_WORKCHAIN_ID, 3);
+
+    // Repeated applying is not notified
+    assert!(!mc_handle.set_block_applied());
+
+    assert_eq!(applied_seq_no(mc.recv().await), 1);
+    assert!(mc.receiver.try_recv().is_err());
+    assert_eq!(applied_seq_no(wc.recv().await), 2);
+    assert_eq!(applied_seq_no(wc.recv().await), 3);
+    assert!(wc.receiver.try_recv().is_err());
+    for seq_no in 1..=3 {
+        assert_eq!(applied_seq_no(all.recv().await), seq_no);
+    }
+    assert!(all.receiver.try_recv().is_err());
+    assert_eq!(notifier.overflowed(), 0);
+
+    // Dropped subscriber is removed on next notification
+    drop(mc);
+    apply_handle(&storage, &notifier, MASTERCHAIN_ID, 4);
+    assert_eq!(notifier.subscribers(), 2);
+}
+
+#[tokio::test]
+async fn test_applied_blocks_lag() {
+    let storage = create_block_handle_storage();
+    let notifier = AppliedBlocksNotifier::new(2);
+    let mut slow = notifier.subscribe(ShardFilter::All).unwrap();
+    let mut fast = notifier.subscribe(ShardFilter::All).unwrap();
+
+    for seq_no in 1..=5 {
+        apply_handle(&storage, &notifier, MASTERCHAIN_ID, seq_no);
+        // Fast subscriber keeps up
+        assert_eq!(applied_seq_no(fast.recv().await), seq_no);
+    }
+    assert_eq!(notifier.overflowed(), 3);
+
+    // Slow subscriber gets the first blocks and then lag notification
+    assert_eq!(applied_seq_no(slow.recv().await), 1);
+    assert_eq!(applied_seq_no(slow.recv().await), 2);
+    apply_handle(&storage, &notifier, MASTERCHAIN_ID, 6);
+    match slow.recv().await {
+        Some(AppliedBlockEvent::Lagged(skipped)) => assert_eq!(skipped, 3),
+        _ => panic!("lag notification is expected")
+    }
+    assert_eq!(applied_seq_no(slow.recv().await), 6);
+    assert_eq!(applied_seq_no(fast.recv().await), 6);
+    assert!(slow.receiver.try_recv().is_err());
+    assert_eq!(notifier.overflowed(), 3);
+}
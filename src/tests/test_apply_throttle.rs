@@ -0,0 +1,153 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::validator::validator_manager::ValidationStatus;
+use crate::engine_traits::{
+    BlockAccess, BroadcastSupport, RempSupport, StateAccess, ValidatorSupport
+};
+use std::sync::atomic::AtomicU8;
+
+fn test_config() -> CatchUpThrottleConfig {
+    CatchUpThrottleConfig {
+        validator_blocks_per_sec: 10,
+        validator_state_tasks: 2,
+        blocks_per_sec: 100,
+        state_tasks: 0,
+    }
+}
+
+struct TestEngine {
+    validation_status: AtomicU8,
+}
+
+impl TestEngine {
+    fn new(status: ValidationStatus) -> Self {
+        Self { validation_status: AtomicU8::new(status as u8) }
+    }
+    fn set(&self, status: ValidationStatus) {
+        self.validation_status.store(status as u8, Ordering::Relaxed)
+    }
+}
+
+impl EngineOperations for TestEngine {}
+
+impl BlockAccess for TestEngine {}
+impl StateAccess for TestEngine {}
+impl RempSupport for TestEngine {}
+impl BroadcastSupport for TestEngine {}
+
+impl ValidatorSupport for TestEngine {
+    fn validation_status(&self) -> ValidationStatus {
+        ValidationStatus::from_u8(self.validation_status.load(Ordering::Relaxed))
+    }
+}
+
+#[test]
+fn test_apply_throttle_rate() {
+    let now = Instant::now();
+    let throttle = ApplyThrottle::with_time(test_config(), now);
+    let engine = TestEngine::new(ValidationStatus::Active);
+    assert!(throttle.refresh(&engine));
+    assert_eq!(throttle.limits(), ThrottleLimits { blocks_per_sec: 10, state_tasks: 2 });
+
+    // One token at start, then one per 100ms
+    assert_eq!(throttle.try_acquire_block_at(now), None);
+    let wait = throttle.try_acquire_block_at(now).unwrap();
+    assert!(wait > Duration::from_millis(99) && wait <= Duration::from_millis(100));
+    let wait = throttle.try_acquire_block_at(now + Duration::from_millis(50)).unwrap();
+    assert!(wait > Duration::from_millis(49) && wait <= Duration::from_millis(50));
+    assert_eq!(throttle.try_acquire_block_at(now + Duration::from_millis(100)), None);
+
+    // Burst is one second of the rate
+    let now = now + Duration::from_secs(10);
+    for _ in 0..10 {
+        assert_eq!(throttle.try_acquire_block_at(now), None);
+    }
+    assert!(throttle.try_acquire_block_at(now).is_some());
+
+    // Zero rate is not limited
+    let mut config = test_config();
+    config.validator_blocks_per_sec = 0;
+    let throttle = ApplyThrottle::with_time(config, now);
+    assert!(throttle.refresh(&engine));
+    for _ in 0..1000 {
+        assert_eq!(throttle.try_acquire_block_at(now), None);
+    }
+}
+
+#[test]
+fn test_apply_throttle_validator_switch() {
+    let now = Instant::now();
+    let throttle = ApplyThrottle::with_time(test_config(), now);
+    let engine = TestEngine::new(ValidationStatus::Disabled);
+    assert!(!throttle.refresh(&engine));
+    assert!(!throttle.is_validator());
+    assert_eq!(throttle.limits(), ThrottleLimits { blocks_per_sec: 100, state_tasks: 0 });
+
+    // Node which waits for sync doesn't validate yet
+    engine.set(ValidationStatus::Waiting);
+    assert!(!throttle.refresh(&engine));
+    assert!(!throttle.is_validator());
+
+    engine.set(ValidationStatus::Countdown);
+    assert!(throttle.refresh(&engine));
+    assert!(throttle.is_validator());
+    engine.set(ValidationStatus::Active);
+    assert!(!throttle.refresh(&engine));
+    assert_eq!(throttle.limits(), ThrottleLimits { blocks_per_sec: 10, state_tasks: 2 });
+
+    // Tokens over the validator's burst are dropped while switched
+    let now = now + Duration::from_secs(10);
+    for _ in 0..10 {
+        assert_eq!(throttle.try_acquire_block_at(now), None);
+    }
+    assert!(throttle.try_acquire_block_at(now).is_some());
+
+    engine.set(ValidationStatus::Disabled);
+    assert!(throttle.refresh(&engine));
+    assert!(!throttle.is_validator());
+    assert_eq!(throttle.try_acquire_block_at(now + Duration::from_millis(20)), None);
+}
+
+#[tokio::test]
+async fn test_apply_throttle_state_tasks() {
+    let mut config = test_config();
+    config.state_tasks = 3;
+    let throttle = ApplyThrottle::with_time(config, Instant::now());
+    let engine = TestEngine::new(ValidationStatus::Disabled);
+    let timeout = Duration::from_millis(50);
+
+    // Three tasks are allowed out of validator set
+    let mut permits = Vec::new();
+    for _ in 0..3 {
+        permits.push(throttle.acquire_state_task().await.unwrap().unwrap());
+    }
+    assert!(tokio::time::timeout(timeout, throttle.acquire_state_task()).await.is_err());
+    permits.clear();
+
+    // Two tasks are allowed in validator set, running ones are counted
+    let permit = throttle.acquire_state_task().await.unwrap().unwrap();
+    engine.set(ValidationStatus::Active);
+    assert!(throttle.refresh(&engine));
+    let _second = throttle.acquire_state_task().await.unwrap().unwrap();
+    assert!(tokio::time::timeout(timeout, throttle.acquire_state_task()).await.is_err());
+    drop(permit);
+    let _third = tokio::time::timeout(timeout, throttle.acquire_state_task()).await
+        .unwrap().unwrap().unwrap();
+    assert!(tokio::time::timeout(timeout, throttle.acquire_state_task()).await.is_err());
+
+    // Zero limit gives no permits
+    let throttle = ApplyThrottle::with_time(test_config(), Instant::now());
+    assert!(throttle.acquire_state_task().await.unwrap().is_none());
+}
@@ -0,0 +1,95 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+struct FakeUsage {
+    used: u64,
+    total: u64,
+}
+
+impl DiskUsageProvider for FakeUsage {
+    fn used_bytes(&self) -> Result<u64> {
+        Ok(self.used)
+    }
+    fn total_bytes(&self) -> Result<u64> {
+        Ok(self.total)
+    }
+}
+
+fn make_gc(
+    high_watermark: DiskWatermark,
+    low_watermark: DiskWatermark,
+    preserve_key_block_packages: bool,
+    used: u64
+) -> Result<ArchivesWatermarkGc> {
+    let config = ArchivesWatermarksConfig {
+        high_watermark,
+        low_watermark,
+        preserve_key_block_packages,
+        ..Default::default()
+    };
+    ArchivesWatermarkGc::new(config, Arc::new(FakeUsage { used, total: 10_000 }))
+}
+
+// Packages of 100 bytes each: [0, 10), [10, 20), ..., [90, 100)
+fn make_packages() -> Vec<ArchivePackageInfo> {
+    (0..10).rev().map(|i| ArchivePackageInfo { archive_id: i * 10, end_seq_no: i * 10 + 10, size: 100 })
+        .collect()
+}
+
+#[test]
+fn test_archives_gc_thresholds() -> Result<()> {
+    let usage = make_gc(DiskWatermark::Percent(85), DiskWatermark::Percent(75), false, 9_000)?.usage()?;
+    assert_eq!(usage, DiskUsage { used: 9_000, high_watermark: 8_500, low_watermark: 7_500 });
+    assert!(usage.above_high_watermark());
+
+    let usage = make_gc(DiskWatermark::Bytes(9_500), DiskWatermark::Percent(50), false, 9_000)?.usage()?;
+    assert_eq!(usage, DiskUsage { used: 9_000, high_watermark: 9_500, low_watermark: 5_000 });
+    assert!(!usage.above_high_watermark());
+
+    assert!(make_gc(DiskWatermark::Percent(101), DiskWatermark::Percent(75), false, 0).is_err());
+    assert!(make_gc(DiskWatermark::Bytes(100), DiskWatermark::Bytes(200), false, 0)?.usage().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_archives_gc_selection_order() -> Result<()> {
+    let gc = make_gc(DiskWatermark::Bytes(900), DiskWatermark::Bytes(750), false, 1_000)?;
+    let usage = gc.usage()?;
+    let packages = make_packages();
+    // The oldest packages go first until usage is below the low watermark
+    assert_eq!(gc.select_packages(&packages, 100, &BTreeSet::new(), &usage), [0, 10, 20]);
+
+    // Retention floor keeps newer packages even if the low watermark is not reached
+    assert_eq!(gc.select_packages(&packages, 25, &BTreeSet::new(), &usage), [0, 10]);
+    assert!(gc.select_packages(&packages, 5, &BTreeSet::new(), &usage).is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_archives_gc_key_block_packages() -> Result<()> {
+    let packages = make_packages();
+    let key_blocks = [0, 20].into_iter().collect::<BTreeSet<_>>();
+
+    let gc = make_gc(DiskWatermark::Bytes(900), DiskWatermark::Bytes(750), true, 1_000)?;
+    let usage = gc.usage()?;
+    assert_eq!(gc.select_packages(&packages, 100, &key_blocks, &usage), [10, 30, 40]);
+    // Key block packages are removed after all others within the retention floor
+    assert_eq!(gc.select_packages(&packages, 30, &key_blocks, &usage), [10, 0, 20]);
+
+    // Without preservation key blocks don't matter
+    let gc = make_gc(DiskWatermark::Bytes(900), DiskWatermark::Bytes(750), false, 1_000)?;
+    assert_eq!(gc.select_packages(&packages, 100, &key_blocks, &usage), [0, 10, 20]);
+    Ok(())
+}
@@ -11,7 +11,14 @@
 * limitations under the License.
 */
 
-use crate::{block::BlockStuff, block_proof::BlockProofStuff};
+use super::*;
+use crate::collator_test_bundle::create_block_handle_storage;
+use crate::engine_traits::{
+    BlockAccess, BroadcastSupport, RempSupport, StateAccess, ValidatorSupport
+};
+use ever_block::{MerkleUpdate, OutQueueUpdate, OutQueueUpdates, ShardIdent, UInt256};
+use storage::{block_handle_db::{BlockHandle, BlockHandleStorage}, types::BlockMeta};
+use std::collections::HashMap;
 
 #[test]
 fn test_check_master_blocks_proof() {
@@ -55,3 +62,217 @@ fn test_check_master_blocks_proof_shuffle() {
         block_proof.check_with_prev_key_block_proof(&key_block_proof).unwrap(); 
     }
 }
+
+#[test]
+fn test_check_master_blocks_proof_deferred() {
+
+    let name = "src/tests/static/test_master_block_proof/key_block__3082181";
+    let key_block = BlockStuff::read_block_from_file(name).unwrap();
+
+    let bytes = std::fs::read("src/tests/static/test_master_block_proof/key_proof__3082181").unwrap();
+    let key_block_proof = BlockProofStuff::deserialize(key_block.id(), bytes, false).unwrap();
+
+    // Signatures of all proofs are verified by one batch
+    let mut batch = SignatureBatch::new();
+    let mut signatures = 0;
+    for seqno in 3082182..=3082200 {
+        let name = format!("src/tests/static/test_master_block_proof/block__{}", seqno);
+        let block = BlockStuff::read_block_from_file(&name).unwrap();
+
+        let name = format!("src/tests/static/test_master_block_proof/proof__{}", seqno);
+        let bytes = std::fs::read(&name).unwrap();
+        let block_proof = BlockProofStuff::deserialize(block.id(), bytes, false).unwrap();
+
+        block_proof.check_with_prev_key_block_proof_deferred(&key_block_proof, &mut batch).unwrap();
+        assert!(batch.len() > signatures);
+        signatures = batch.len();
+    }
+    batch.verify().unwrap();
+}
+
+const PROOFS_DIR: &str = "src/tests/static/test_master_block_proof";
+
+struct ProofChainEngine {
+    storage: BlockHandleStorage,
+    handles: HashMap<BlockIdExt, Arc<BlockHandle>>,
+    mc_blocks: HashMap<u32, BlockIdExt>,
+    files: HashMap<BlockIdExt, String>,
+}
+
+impl ProofChainEngine {
+    fn new() -> Self {
+        Self {
+            storage: create_block_handle_storage(),
+            handles: HashMap::new(),
+            mc_blocks: HashMap::new(),
+            files: HashMap::new(),
+        }
+    }
+
+    fn add_block(&mut self, id: &BlockIdExt, file: String, mc_seqno: u32) -> Arc<BlockHandle> {
+        let handle = self.storage.create_handle(id.clone(), BlockMeta::default(), None)
+            .unwrap().unwrap();
+        if id.is_masterchain() {
+            handle.set_proof();
+            self.mc_blocks.insert(id.seq_no(), id.clone());
+        } else {
+            handle.set_proof_link();
+            handle.set_masterchain_ref_seq_no(mc_seqno).unwrap();
+        }
+        self.handles.insert(id.clone(), handle.clone());
+        self.files.insert(id.clone(), file);
+        handle
+    }
+
+    fn add_mc_block(&mut self, seqno: u32) -> BlockIdExt {
+        let (block, proof) = if seqno == 3082181 {
+            ("key_block", "key_proof")
+        } else {
+            ("block", "proof")
+        };
+        let block = BlockStuff::read_block_from_file(&format!("{}/{}__{}", PROOFS_DIR, block, seqno)).unwrap();
+        self.add_block(block.id(), format!("{}/{}__{}", PROOFS_DIR, proof, seqno), 0);
+        block.id().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl EngineOperations for ProofChainEngine {
+    async fn find_mc_block_by_seq_no(&self, seqno: u32) -> Result<Arc<BlockHandle>> {
+        let id = self.mc_blocks.get(&seqno).ok_or_else(|| error!("no mc block {}", seqno))?;
+        Ok(self.handles[id].clone())
+    }
+
+    async fn load_block_proof_raw(&self, handle: &BlockHandle, is_link: bool) -> Result<Vec<u8>> {
+        if is_link != !handle.id().is_masterchain() {
+            fail!("wrong kind of proof for {}", handle.id())
+        }
+        Ok(std::fs::read(&self.files[handle.id()])?)
+    }
+}
+
+impl BlockAccess for ProofChainEngine {
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        Ok(self.handles.get(id).cloned())
+    }
+}
+
+impl StateAccess for ProofChainEngine {}
+impl RempSupport for ProofChainEngine {}
+impl BroadcastSupport for ProofChainEngine {}
+impl ValidatorSupport for ProofChainEngine {}
+
+fn check_chain(chain: &[Vec<u8>], expected: &[&BlockIdExt]) {
+    assert_eq!(chain.len(), expected.len());
+    for (data, id) in chain.iter().zip(expected) {
+        let proof = BlockProofStuff::deserialize(id, data.clone(), !id.is_masterchain()).unwrap();
+        proof.virtualize_block().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_build_proof_chain() {
+    let mut engine = ProofChainEngine::new();
+    let key_block = engine.add_mc_block(3082181);
+    let block = engine.add_mc_block(3082185);
+    let mc_block = engine.add_mc_block(3082190);
+
+    let shard_proof_file = "src/tests/static/test_shard_block_proof/proof_4377262".to_string();
+    let shard_proof = BlockProof::construct_from_bytes(&std::fs::read(&shard_proof_file).unwrap()).unwrap();
+    let shard_block = shard_proof.proof_for.clone();
+    engine.add_block(&shard_block, shard_proof_file, 3082190);
+
+    // Key block before the trusted one is not stored
+    let key_proof = BlockProofStuff::deserialize(
+        &key_block, std::fs::read(&engine.files[&key_block]).unwrap(), false
+    ).unwrap();
+    let trusted = key_proof.virtualize_block().unwrap().0.read_info().unwrap().prev_key_block_seqno();
+    assert!(trusted < 3082181);
+
+    // Key block goes first, then the block signed by its validators
+    let chain = build_proof_chain(&engine, &block, trusted, 16).await.unwrap();
+    check_chain(&chain, &[&key_block, &block]);
+    assert_eq!(chain[0], std::fs::read(&engine.files[&key_block]).unwrap());
+
+    // Shard block is checked by the masterchain block which commits it
+    let chain = build_proof_chain(&engine, &shard_block, trusted, 16).await.unwrap();
+    check_chain(&chain, &[&key_block, &mc_block, &shard_block]);
+
+    // Key block itself
+    let chain = build_proof_chain(&engine, &key_block, trusted, 16).await.unwrap();
+    check_chain(&chain, &[&key_block]);
+
+    // Trusted key block is not walked through
+    let chain = build_proof_chain(&engine, &block, 3082181, 16).await.unwrap();
+    check_chain(&chain, &[&block]);
+    let chain = build_proof_chain(&engine, &key_block, 3082181, 16).await.unwrap();
+    check_chain(&chain, &[&key_block]);
+
+    // Limits
+    build_proof_chain(&engine, &shard_block, trusted, 2).await.unwrap_err();
+    build_proof_chain(&engine, &block, 3082186, 16).await.unwrap_err();
+    let unknown = BlockIdExt::with_params(ShardIdent::masterchain(), 3082186, UInt256::default(), UInt256::default());
+    build_proof_chain(&engine, &unknown, trusted, 16).await.unwrap_err();
+}
+
+// Queue update for wc 1 of workchain block with given out queue update
+fn make_queue_update(update: MerkleUpdate, empty: bool) -> Result<BlockStuff> {
+    let mut updates = OutQueueUpdates::new();
+    updates.set(&1, &OutQueueUpdate { update, is_empty: empty })?;
+    let mut info = BlockInfo::default();
+    info.set_shard(ShardIdent::with_workchain_id(0)?);
+    info.set_seq_no(10)?;
+    let mut block = Block::default();
+    block.write_info(&info)?;
+    block.out_msg_queue_updates = Some(updates);
+    let block = BlockStuff::from_block(block)?;
+    let data = MerkleProof::create(block.root_cell(), |_| true)?.write_to_bytes()?;
+    BlockStuff::deserialize_queue_update(block.id().clone(), 1, empty, data)
+}
+
+#[test]
+fn test_verify_queue_update() -> Result<()> {
+    let old_queue = 1u32.serialize()?;
+    let new_queue = 2u32.serialize()?;
+    let update = MerkleUpdate::create(&old_queue, &new_queue)?;
+    let queue_update = make_queue_update(update.clone(), false)?;
+    BlockProofStuff::check_queue_update(&queue_update)?;
+    assert!(BlockProofStuff::verify_queue_update(&queue_update)?);
+
+    // Update declares hash of other queue than it carries
+    let mut tampered = update.clone();
+    tampered.new_hash = 3u32.serialize()?.repr_hash();
+    let err = make_queue_update(tampered, false)
+        .and_then(|queue_update| BlockProofStuff::verify_queue_update(&queue_update))
+        .expect_err("tampered queue update must be rejected");
+    // Broken cell may be refused while the block is built, otherwise verification refuses it
+    assert!(err.downcast_ref::<NodeError>().is_none() || NodeError::is_queue_update_mismatch(&err));
+    Ok(())
+}
+
+#[test]
+fn test_verify_empty_queue_update_skipped() -> Result<()> {
+    let queue = 1u32.serialize()?;
+    let update = MerkleUpdate::create(&queue, &queue)?;
+    let queue_update = make_queue_update(update, true)?;
+    assert!(!BlockProofStuff::verify_queue_update(&queue_update)?);
+    Ok(())
+}
+
+#[test]
+fn test_block_provenance() -> Result<()> {
+    let name = "src/tests/static/test_master_block_proof/key_block__3082181";
+    let key_block = BlockStuff::read_block_from_file(name)?;
+    let bytes = std::fs::read("src/tests/static/test_master_block_proof/key_proof__3082181")?;
+    let proof = BlockProofStuff::deserialize(key_block.id(), bytes, false)?;
+
+    let provenance = proof.provenance()?;
+    assert_eq!(provenance.created_by, *key_block.block()?.read_extra()?.created_by());
+    assert_ne!(provenance.created_by, UInt256::default());
+    assert!(!provenance.signers.is_empty());
+
+    let data = provenance.serialize();
+    assert_eq!(BlockProvenance::deserialize(&data)?, provenance);
+    assert!(BlockProvenance::deserialize(&data[..data.len() - 1]).is_err());
+    Ok(())
+}
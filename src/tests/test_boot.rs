@@ -0,0 +1,322 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::{
+    collator_test_bundle::{create_block_handle_storage, create_engine_allocated},
+    engine_traits::{BlockAccess, BroadcastSupport, EngineAlloc, RempSupport, StateAccess, ValidatorSupport}
+};
+#[cfg(feature = "telemetry")]
+use crate::collator_test_bundle::create_engine_telemetry;
+use ever_block::{ShardStateUnsplit, UInt256};
+use std::{collections::HashMap, sync::atomic::{AtomicU32, Ordering}};
+use storage::{block_handle_db::BlockHandleStorage, types::BlockMeta};
+
+fn mc_block_id(seq_no: u32) -> BlockIdExt {
+    BlockIdExt {
+        shard_id: ShardIdent::masterchain(),
+        seq_no,
+        root_hash: UInt256::from([seq_no as u8; 32]),
+        file_hash: UInt256::from([seq_no as u8 + 1; 32]),
+    }
+}
+
+#[test]
+fn test_trusted_key_block_mismatch() {
+    let name = "src/tests/static/test_master_block_proof/key_block__3082181";
+    let key_block = BlockStuff::read_block_from_file(name).unwrap();
+    let bytes = std::fs::read("src/tests/static/test_master_block_proof/key_proof__3082181").unwrap();
+    let proof = BlockProofStuff::deserialize(key_block.id(), bytes, false).unwrap();
+
+    check_trusted_key_block(key_block.id(), &proof).unwrap();
+
+    let mut wrong_file_hash = key_block.id().clone();
+    wrong_file_hash.file_hash = UInt256::from([1; 32]);
+    let err = check_trusted_key_block(&wrong_file_hash, &proof).unwrap_err();
+    // boot must not retry it
+    assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::InvalidProof(_))));
+
+    let mut wrong_root_hash = key_block.id().clone();
+    wrong_root_hash.root_hash = UInt256::from([1; 32]);
+    assert!(check_trusted_key_block(&wrong_root_hash, &proof).is_err());
+
+    // proof of non-key block is not accepted
+    let name = "src/tests/static/test_master_block_proof/block__3082182";
+    let block = BlockStuff::read_block_from_file(name).unwrap();
+    let bytes = std::fs::read("src/tests/static/test_master_block_proof/proof__3082182").unwrap();
+    let proof = BlockProofStuff::deserialize(block.id(), bytes, false).unwrap();
+    assert!(check_trusted_key_block(block.id(), &proof).is_err());
+}
+
+#[test]
+fn test_choose_init_block() {
+    let zero_state_id = mc_block_id(0);
+    let init_block = mc_block_id(10);
+    let trusted = mc_block_id(20);
+    let hardfork = mc_block_id(30);
+
+    let choose = |
+        init_block: Option<&BlockIdExt>,
+        trusted: Option<&BlockIdExt>,
+        hardfork: Option<&BlockIdExt>,
+        saved: Option<&BlockIdExt>,
+        from_zerostate: bool
+    | choose_init_block(&zero_state_id, init_block.cloned(), trusted, hardfork, saved, from_zerostate);
+
+    assert_eq!(choose(None, None, None, None, false).unwrap(), zero_state_id);
+    assert_eq!(choose(Some(&init_block), None, None, None, false).unwrap(), init_block);
+    assert_eq!(choose(Some(&init_block), Some(&trusted), None, None, false).unwrap(), trusted);
+    assert_eq!(choose(Some(&init_block), Some(&trusted), None, None, true).unwrap(), zero_state_id);
+
+    // hardfork after trusted key block
+    assert!(choose(Some(&init_block), Some(&trusted), Some(&hardfork), None, false).is_err());
+}
+
+#[test]
+fn test_resume_interrupted_fast_boot() {
+    let zero_state_id = mc_block_id(0);
+    let trusted = mc_block_id(20);
+
+    // key block chosen by interrupted boot is newer than trusted one
+    let saved = mc_block_id(40);
+    let init = choose_init_block(&zero_state_id, None, Some(&trusted), None, Some(&saved), false);
+    assert_eq!(init.unwrap(), saved);
+
+    // outdated saved block doesn't override trusted one
+    let saved = mc_block_id(5);
+    let init = choose_init_block(&zero_state_id, None, Some(&trusted), None, Some(&saved), false);
+    assert_eq!(init.unwrap(), trusted);
+}
+
+struct ZeroStateEngine {
+    attempts: AtomicU32,
+}
+
+#[async_trait::async_trait]
+impl EngineOperations for ZeroStateEngine {
+
+    fn check_stop(&self) -> bool {
+        false
+    }
+
+    async fn download_zerostate(
+        &self,
+        _mesh_nw_id: i32,
+        id: &BlockIdExt,
+    ) -> Result<(Arc<ShardStateStuff>, Vec<u8>)> {
+        if self.attempts.fetch_add(1, Ordering::Relaxed) == 0 {
+            fail!(NodeError::NetworkTimeout(format!("No RLDP answer for {}", id)))
+        }
+        fail!(NodeError::DbCorruption("Broken zero state in db".to_string()))
+    }
+}
+
+impl BlockAccess for ZeroStateEngine {
+    fn load_block_handle(&self, _id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        Ok(None)
+    }
+}
+
+impl StateAccess for ZeroStateEngine {}
+impl RempSupport for ZeroStateEngine {}
+impl BroadcastSupport for ZeroStateEngine {}
+impl ValidatorSupport for ZeroStateEngine {}
+
+#[tokio::test]
+async fn test_download_zerostate_stops_on_fatal_error() {
+    let engine = ZeroStateEngine { attempts: AtomicU32::new(0) };
+    let err = download_zerostate(&engine, &mc_block_id(0)).await.unwrap_err();
+    // timeout is retried, broken db is not
+    assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::DbCorruption(_))));
+    assert_eq!(engine.attempts.load(Ordering::Relaxed), 2);
+}
+
+// Masterchain blocks 10..=20 are applied, their states are stored or pruned
+struct PrunedStateEngine {
+    storage: BlockHandleStorage,
+    handles: HashMap<BlockIdExt, Arc<BlockHandle>>,
+    // States are marked in handles, but are removed from db
+    pruned: HashSet<u32>,
+    init_block_id: BlockIdExt,
+    trusted_block_id: Option<BlockIdExt>,
+    last_applied: parking_lot::Mutex<Option<BlockIdExt>>,
+    shard_client: parking_lot::Mutex<Option<BlockIdExt>>,
+    allocated: Arc<EngineAlloc>,
+}
+
+impl PrunedStateEngine {
+
+    fn new(stored: &[u32], pruned: &[u32], persistent: &[u32]) -> Self {
+        let mut engine = Self {
+            storage: create_block_handle_storage(),
+            handles: HashMap::new(),
+            pruned: pruned.iter().cloned().collect(),
+            init_block_id: mc_block_id(10),
+            trusted_block_id: None,
+            last_applied: parking_lot::Mutex::new(Some(mc_block_id(20))),
+            shard_client: parking_lot::Mutex::new(Some(mc_block_id(20))),
+            allocated: create_engine_allocated(),
+        };
+        for seq_no in 10..=20 {
+            let handle = engine.storage.create_handle(mc_block_id(seq_no), BlockMeta::default(), None)
+                .unwrap().unwrap();
+            handle.set_block_applied();
+            handle.set_prev1();
+            if stored.contains(&seq_no) || pruned.contains(&seq_no) {
+                handle.set_state();
+            }
+            if persistent.contains(&seq_no) {
+                handle.set_persistent_state();
+            }
+            engine.handles.insert(mc_block_id(seq_no), handle);
+        }
+        engine
+    }
+
+    fn last_applied(&self) -> Option<BlockIdExt> {
+        self.last_applied.lock().clone()
+    }
+
+    fn shard_client(&self) -> Option<BlockIdExt> {
+        self.shard_client.lock().clone()
+    }
+
+}
+
+#[async_trait::async_trait]
+impl EngineOperations for PrunedStateEngine {
+
+    async fn load_persistent_state_size(&self, block_id: &BlockIdExt) -> Result<u64> {
+        match self.handles.get(block_id) {
+            Some(handle) if handle.has_persistent_state() => Ok(1000),
+            _ => fail!(NodeError::NotFound(format!("No persistent state {}", block_id)))
+        }
+    }
+
+    fn init_mc_block_id(&self) -> &BlockIdExt {
+        &self.init_block_id
+    }
+
+    fn trusted_key_block(&self) -> Option<&BlockIdExt> {
+        self.trusted_block_id.as_ref()
+    }
+
+    fn save_last_applied_mc_block_id(&self, last_mc_block: &BlockIdExt) -> Result<()> {
+        *self.last_applied.lock() = Some(last_mc_block.clone());
+        Ok(())
+    }
+
+    fn load_shard_client_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
+        Ok(self.shard_client().map(Arc::new))
+    }
+
+    fn save_shard_client_mc_block_id(&self, id: &BlockIdExt) -> Result<()> {
+        *self.shard_client.lock() = Some(id.clone());
+        Ok(())
+    }
+
+}
+
+impl BlockAccess for PrunedStateEngine {
+
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        Ok(self.handles.get(id).cloned())
+    }
+
+    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        Ok(mc_block_id(id.seq_no() - 1))
+    }
+
+}
+
+#[async_trait::async_trait]
+impl StateAccess for PrunedStateEngine {
+    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        let has_state = self.handles.get(block_id).map_or(false, |handle| handle.has_state());
+        if !has_state || self.pruned.contains(&block_id.seq_no()) {
+            fail!(NodeError::NotFound(format!("State {} is not found", block_id)))
+        }
+        ShardStateStuff::from_state(
+            block_id.clone(),
+            ShardStateUnsplit::default(),
+            #[cfg(feature = "telemetry")]
+            &create_engine_telemetry(),
+            &self.allocated
+        )
+    }
+}
+
+impl RempSupport for PrunedStateEngine {}
+impl BroadcastSupport for PrunedStateEngine {}
+impl ValidatorSupport for PrunedStateEngine {}
+
+fn state_not_found(seq_no: u32) -> Error {
+    error!(NodeError::NotFound(format!("State {} is not found", mc_block_id(seq_no))))
+}
+
+#[tokio::test]
+async fn test_find_rollback_block() {
+    // The most recent stored state is chosen, pruned ones are skipped
+    let engine = PrunedStateEngine::new(&[10, 12, 14], &[16, 18, 20], &[]);
+    let handle = find_rollback_block(&engine, &mc_block_id(20)).await.unwrap();
+    assert_eq!(handle.id(), &mc_block_id(14));
+
+    // Persistent state file is good as well
+    let engine = PrunedStateEngine::new(&[10, 12], &[16, 20], &[13, 15]);
+    let handle = find_rollback_block(&engine, &mc_block_id(20)).await.unwrap();
+    assert_eq!(handle.id(), &mc_block_id(15));
+}
+
+#[tokio::test]
+async fn test_find_rollback_block_stops_at_init_block() {
+    // Init block is the last candidate
+    let engine = PrunedStateEngine::new(&[10], &[20], &[]);
+    let handle = find_rollback_block(&engine, &mc_block_id(20)).await.unwrap();
+    assert_eq!(handle.id(), &mc_block_id(10));
+
+    // Available state before trusted key block is not taken
+    let mut engine = PrunedStateEngine::new(&[10, 12], &[20], &[]);
+    engine.trusted_block_id = Some(mc_block_id(15));
+    let err = find_rollback_block(&engine, &mc_block_id(20)).await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::NotFound(_))));
+}
+
+#[tokio::test]
+async fn test_recover_pruned_state() {
+    let engine = PrunedStateEngine::new(&[10, 17], &[18, 19, 20], &[]);
+    let id = recover_pruned_state(&engine, &mc_block_id(20), state_not_found(20), true).await.unwrap();
+    assert_eq!(id, mc_block_id(17));
+    assert_eq!(engine.last_applied(), Some(mc_block_id(17)));
+    assert_eq!(engine.shard_client(), Some(mc_block_id(17)));
+
+    // Shard client behind the rollback block stays as is
+    let engine = PrunedStateEngine::new(&[10, 17], &[18, 19, 20], &[]);
+    *engine.shard_client.lock() = Some(mc_block_id(12));
+    recover_pruned_state(&engine, &mc_block_id(20), state_not_found(20), true).await.unwrap();
+    assert_eq!(engine.shard_client(), Some(mc_block_id(12)));
+}
+
+#[tokio::test]
+async fn test_recover_pruned_state_disabled() {
+    let engine = PrunedStateEngine::new(&[10, 17], &[18, 19, 20], &[]);
+    let err = recover_pruned_state(&engine, &mc_block_id(20), state_not_found(20), false).await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::NotFound(_))));
+    assert_eq!(engine.last_applied(), Some(mc_block_id(20)));
+    assert_eq!(engine.shard_client(), Some(mc_block_id(20)));
+
+    // Other errors are not a reason for rollback
+    let err = error!(NodeError::DbCorruption("Broken state".to_string()));
+    let err = recover_pruned_state(&engine, &mc_block_id(20), err, true).await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::DbCorruption(_))));
+    assert_eq!(engine.last_applied(), Some(mc_block_id(20)));
+}
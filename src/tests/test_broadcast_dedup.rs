@@ -0,0 +1,118 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::{Result, ShardIdent};
+use std::sync::atomic::AtomicU32;
+
+fn dedup(window: usize, ttl_sec: u32) -> Arc<BroadcastDedup> {
+    Arc::new(BroadcastDedup::new(&BroadcastDedupConfig { window, ttl_sec }))
+}
+
+fn block_id(seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(
+        ShardIdent::masterchain(),
+        seq_no,
+        UInt256::with_array([seq_no as u8; 32]),
+        UInt256::with_array([!(seq_no as u8); 32])
+    )
+}
+
+// Stands for the heavy part of broadcast processing
+async fn process_broadcast(
+    dedup: Arc<BroadcastDedup>,
+    id: BlockIdExt,
+    processed: Arc<AtomicU32>,
+    fail: bool
+) -> Result<()> {
+    let Some(guard) = dedup.try_start(&id) else {
+        return Ok(())
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    if fail {
+        ever_block::fail!("Bad broadcast {}", id)
+    }
+    processed.fetch_add(1, Ordering::Relaxed);
+    guard.complete();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_broadcast_dedup_concurrent_copies() {
+    let dedup = dedup(16, 60);
+    let processed = Arc::new(AtomicU32::new(0));
+    let tasks = (0..3).map(|_| {
+        tokio::spawn(process_broadcast(dedup.clone(), block_id(1), processed.clone(), false))
+    }).collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+    assert_eq!(processed.load(Ordering::Relaxed), 1);
+    assert_eq!(dedup.deduplicated(), 2);
+
+    // Completed broadcast stays in the window
+    process_broadcast(dedup.clone(), block_id(1), processed.clone(), false).await.unwrap();
+    assert_eq!(processed.load(Ordering::Relaxed), 1);
+    assert_eq!(dedup.deduplicated(), 3);
+}
+
+#[tokio::test]
+async fn test_broadcast_dedup_failed_processing() {
+    let dedup = dedup(16, 60);
+    let processed = Arc::new(AtomicU32::new(0));
+    assert!(process_broadcast(dedup.clone(), block_id(1), processed.clone(), true).await.is_err());
+    assert!(dedup.is_empty());
+
+    // Valid later copy is processed
+    process_broadcast(dedup.clone(), block_id(1), processed.clone(), false).await.unwrap();
+    assert_eq!(processed.load(Ordering::Relaxed), 1);
+    assert_eq!(dedup.deduplicated(), 0);
+
+    // Panic while processing removes the entry too
+    let task = tokio::spawn({
+        let dedup = dedup.clone();
+        async move {
+            let _guard = dedup.try_start(&block_id(2));
+            panic!("processing panicked")
+        }
+    });
+    assert!(task.await.is_err());
+    assert!(dedup.try_start(&block_id(2)).is_some());
+}
+
+#[test]
+fn test_broadcast_dedup_window_and_ttl() {
+    let dedup = dedup(2, 10);
+    let now = Instant::now();
+    dedup.try_start_at(&block_id(1), now).unwrap().complete();
+    dedup.try_start_at(&block_id(2), now).unwrap().complete();
+    dedup.try_start_at(&block_id(3), now).unwrap().complete();
+    // The oldest one is evicted
+    assert_eq!(dedup.len(), 2);
+    assert!(dedup.try_start_at(&block_id(2), now).is_none());
+    assert!(dedup.try_start_at(&block_id(3), now).is_none());
+    dedup.try_start_at(&block_id(1), now).unwrap().complete();
+
+    // Entries expire after TTL
+    let later = now + Duration::from_secs(11);
+    assert!(dedup.try_start_at(&block_id(1), later).is_some());
+    assert_eq!(dedup.deduplicated(), 2);
+
+    // Same root hash with other file hash is another broadcast
+    let id = block_id(5);
+    dedup.try_start_at(&id, later).unwrap().complete();
+    let other = BlockIdExt::with_params(
+        id.shard().clone(), id.seq_no(), id.root_hash().clone(), UInt256::default()
+    );
+    assert!(dedup.try_start_at(&other, later).is_some());
+}
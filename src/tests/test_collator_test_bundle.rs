@@ -0,0 +1,184 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::validator::message_cache::{RempMessageOrigin, RmqMessage};
+use ever_block::SliceData;
+use ton_api::ton::ton_node::{
+    RempMessageLevel, rempmessagestatus::{RempAccepted, RempIgnored, RempRejected}
+};
+
+fn block_id(shard: ShardIdent, seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(shard, seq_no, UInt256::from([seq_no as u8; 32]), UInt256::from([1; 32]))
+}
+
+fn test_index() -> CollatorTestBundleIndex {
+    let mc_id = block_id(ShardIdent::masterchain(), 10);
+    CollatorTestBundleIndex {
+        id: block_id(ShardIdent::masterchain(), 11),
+        top_shard_blocks: vec![block_id(ShardIdent::full(0), 20)],
+        external_messages: vec![UInt256::from([5; 32])],
+        last_mc_state: mc_id.clone(),
+        min_ref_mc_seqno: 10,
+        mc_states: vec![mc_id.clone()],
+        neighbors: vec![],
+        prev_blocks: vec![mc_id],
+        created_by: UInt256::from([7; 32]),
+        rand_seed: Some(UInt256::from([9; 32])),
+        now_ms: 1_000_000,
+        fake: true,
+        contains_ethalon: false,
+        contains_candidate: false,
+        notes: String::new(),
+        remp: None,
+    }
+}
+
+fn index_from_value(value: serde_json::Value) -> Result<CollatorTestBundleIndex> {
+    serde_json::from_value::<CollatorTestBundleIndexJson>(value)?.try_into()
+}
+
+fn test_body(byte: u8) -> SliceData {
+    SliceData::new(vec![byte; 64])
+}
+
+fn add_message(cache: &MessageCache, message: &Arc<RmqMessage>, with_body: bool, cc: u32) -> Result<()> {
+    cache.add_external_message_status(
+        &message.message_id, &message.message_uid,
+        if with_body { Some(message.clone()) } else { None },
+        Some(Arc::new(RempMessageOrigin::create_empty()?)),
+        RempMessageStatus::TonNode_RempNew,
+        |_old, new| new.clone(),
+        cc
+    )?;
+    Ok(())
+}
+
+async fn replay(queue: &BundleRempQueue, prev_blocks_ids: &[&BlockIdExt]) -> Result<Vec<UInt256>> {
+    queue.init_queue(&block_id(ShardIdent::masterchain(), 10), prev_blocks_ids).await?;
+    let mut ids = Vec::new();
+    while let Some((_message, id)) = queue.get_next_message_for_collation().await? {
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+#[test]
+fn test_bundle_index_versions() -> Result<()> {
+    let mut value = serde_json::to_value(CollatorTestBundleIndexJson::from(&test_index()))?;
+    assert_eq!(value["version"], serde_json::json!(COLLATOR_TEST_BUNDLE_VERSION));
+    assert!(value.get("remp").is_none());
+
+    // Bundles saved before versioning have neither version nor REMP section
+    let object = value.as_object_mut().unwrap();
+    object.remove("version");
+    object.insert("now".to_string(), serde_json::json!(1000));
+    let index = index_from_value(value.clone())?;
+    assert_eq!(index.id, test_index().id);
+    assert_eq!(index.now_ms, 1_000_000);
+    assert!(index.remp.is_none());
+
+    value["version"] = serde_json::json!(COLLATOR_TEST_BUNDLE_VERSION + 1);
+    let err = index_from_value(value).err().unwrap();
+    assert!(err.to_string().contains("is not supported"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bundle_remp_replay() -> Result<()> {
+    let cache = MessageCache::with_metrics(
+        None,
+        DuplicatePolicy::LowestId,
+        None,
+        #[cfg(feature = "telemetry")]
+        Metric::without_totals("test message cache size", 0)
+    );
+    for cc in 1..=3 {
+        cache.try_set_master_cc_start_time(cc, cc.into(), vec!())?;
+        cache.update_master_cc_ranges(cc, Duration::from_secs(1))?;
+    }
+
+    let new = Arc::new(RmqMessage::make_test_message(&test_body(1))?);
+    let ignored = Arc::new(RmqMessage::make_test_message(&test_body(2))?);
+    let rejected = Arc::new(RmqMessage::make_test_message(&test_body(3))?);
+    let no_body = Arc::new(RmqMessage::make_test_message(&test_body(4))?);
+    // Equal uids: the lowest id wins
+    let mut equal = [
+        Arc::new(RmqMessage::make_test_message(&test_body(5))?),
+        Arc::new(RmqMessage::make_test_message(&test_body(5))?),
+    ];
+    equal.sort_by(|m1, m2| m1.message_id.cmp(&m2.message_id));
+    // Equal uids: the uid is already in a shard block
+    let accepted = Arc::new(RmqMessage::make_test_message(&test_body(6))?);
+    let accepted_duplicate = Arc::new(RmqMessage::make_test_message(&test_body(6))?);
+
+    for message in [&new, &ignored, &rejected, &equal[1], &accepted] {
+        add_message(&cache, message, true, 2)?;
+    }
+    for message in [&equal[0], &accepted_duplicate] {
+        add_message(&cache, message, true, 3)?;
+    }
+    add_message(&cache, &no_body, false, 3)?;
+
+    cache.update_message_status(&ignored.message_id, RempMessageStatus::TonNode_RempIgnored(RempIgnored {
+        level: RempMessageLevel::TonNode_RempCollator,
+        block_id: BlockIdExt::default()
+    }))?;
+    cache.update_message_status(&rejected.message_id, RempMessageStatus::TonNode_RempRejected(RempRejected {
+        level: RempMessageLevel::TonNode_RempQueue,
+        block_id: BlockIdExt::default(),
+        error: "test".to_string()
+    }))?;
+    cache.update_message_status(&accepted.message_id, RempMessageStatus::TonNode_RempAccepted(RempAccepted {
+        level: RempMessageLevel::TonNode_RempShardchain,
+        block_id: block_id(ShardIdent::full(0), 20),
+        master_id: BlockIdExt::default()
+    }))?;
+
+    let mut selected = cache.collation_candidates()?.into_iter().map(|(id, _, _)| id).collect::<Vec<_>>();
+    selected.sort();
+    let mut expected = vec![new.message_id.clone(), ignored.message_id.clone(), equal[0].message_id.clone()];
+    expected.sort();
+    assert_eq!(selected, expected);
+
+    // Section goes through index.json
+    let snapshot = cache.snapshot()?;
+    assert_eq!(snapshot.messages.len(), 8);
+    let mut index = test_index();
+    index.remp = Some(snapshot);
+    let json = serde_json::to_string(&CollatorTestBundleIndexJson::from(&index))?;
+    let restored = index_from_value(serde_json::from_str(&json)?)?.remp.unwrap();
+    assert_eq!(restored.master_cc_range, 2..=3);
+    assert_eq!(restored.sessions, vec![(1, 1), (2, 2), (3, 3)]);
+    let rejected_info = restored.messages.iter().find(|m| m.message_id == rejected.message_id).unwrap();
+    assert_eq!(rejected_info.status, cache.get_message_status(&rejected.message_id)?.unwrap().to_string());
+    assert_eq!(rejected_info.message_uid, rejected.message_uid);
+
+    let original = BundleRempQueue::new(index.remp.as_ref().unwrap())?;
+    let replayed = BundleRempQueue::new(&restored)?;
+    for message in [&new, &ignored, &rejected, &no_body, &equal[0], &equal[1], &accepted, &accepted_duplicate] {
+        let id = &message.message_id;
+        assert_eq!(replayed.cache.get_message_status(id)?, cache.get_message_status(id)?);
+        assert_eq!(replayed.cache.get_message_origin(id)?, cache.get_message_origin(id)?);
+        assert_eq!(replayed.cache.check_message_duplicates(id)?, cache.check_message_duplicates(id)?);
+    }
+
+    // Order depends on previous blocks only, as in the collation queue
+    let prev = block_id(ShardIdent::full(0), 19);
+    let ids = replay(&replayed, &[&prev]).await?;
+    assert_eq!(ids, replay(&original, &[&prev]).await?);
+    let mut ordered = expected.clone();
+    ordered.sort_by_key(|id| RempQueueCollatorInterfaceImpl::compute_ordering_hash(id, &[&prev]));
+    assert_eq!(ids, ordered);
+    Ok(())
+}
@@ -0,0 +1,134 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::block::BlockStuff;
+use ever_block::{
+    BlockLimits, ConfigParam12, ConfigParam34, ParamLimits, Workchains
+};
+
+fn key_block_config() -> (BlockIdExt, ConfigParams) {
+    let block = BlockStuff::read_block_from_file("src/tests/static/key_block.boc").unwrap();
+    (block.id().clone(), block.get_config_params().unwrap())
+}
+
+fn violated_rules(config: &ConfigParams) -> Vec<&'static str> {
+    let (block_id, _) = key_block_config();
+    let result = check_config(&block_id, config);
+    for violation in &result.violations {
+        println!("{}", violation);
+    }
+    let mut rules = result.violations.iter().map(|violation| violation.rule).collect::<Vec<_>>();
+    rules.dedup();
+    rules
+}
+
+#[test]
+fn test_config_check_key_block() {
+    let (block_id, config) = key_block_config();
+    let result = check_config(&block_id, &config);
+    assert!(result.is_ok(), "{:?}", result.violations);
+    assert_eq!(result.block_id, block_id);
+}
+
+#[test]
+fn test_config_check_validator_sets() {
+    let (_, mut config) = key_block_config();
+    let vset = config.validator_set().unwrap();
+    let list = vset.list().iter()
+        .map(|descr| {
+            let mut descr = descr.clone();
+            descr.weight = 0;
+            descr
+        })
+        .collect();
+    let cur_validators = ValidatorSet::new(vset.utime_since(), vset.utime_until(), vset.main(), list).unwrap();
+    config.set_config(ConfigParamEnum::ConfigParam34(ConfigParam34 { cur_validators })).unwrap();
+    assert_eq!(violated_rules(&config), ["validator sets"]);
+}
+
+#[test]
+fn test_config_check_catchain_config() {
+    let (_, mut config) = key_block_config();
+    let mut cc_config = config.catchain_config().unwrap();
+    cc_config.shard_validators_num = 0;
+    cc_config.mc_catchain_lifetime = 0;
+    config.set_config(ConfigParamEnum::ConfigParam28(cc_config)).unwrap();
+    let result = check_config(&key_block_config().0, &config);
+    let catchain_violations = result.violations.iter()
+        .filter(|violation| violation.rule == "catchain config")
+        .count();
+    assert_eq!(catchain_violations, 2);
+}
+
+#[test]
+fn test_config_check_consensus_config() {
+    let (_, mut config) = key_block_config();
+    let mut param = match config.config(29).unwrap() {
+        Some(ConfigParamEnum::ConfigParam29(param)) => param,
+        _ => panic!("key block has no consensus config")
+    };
+    param.consensus_config.round_candidates = 0;
+    param.consensus_config.consensus_timeout_ms = MAX_CONSENSUS_TIMEOUT_MS + 1;
+    config.set_config(ConfigParamEnum::ConfigParam29(param)).unwrap();
+    assert_eq!(violated_rules(&config), ["consensus config"]);
+}
+
+#[test]
+fn test_config_check_workchains() {
+    let (_, mut config) = key_block_config();
+    let workchains = Workchains::default();
+    config.set_config(ConfigParamEnum::ConfigParam12(ConfigParam12 { workchains })).unwrap();
+    assert!(violated_rules(&config).contains(&"workchains"));
+}
+
+#[test]
+fn test_config_check_gas_limits() {
+    let (_, mut config) = key_block_config();
+    let mut prices = match config.config(21).unwrap() {
+        Some(ConfigParamEnum::ConfigParam21(prices)) => prices,
+        _ => panic!("key block has no gas prices")
+    };
+    prices.block_gas_limit = prices.gas_limit - 1;
+    config.set_config(ConfigParamEnum::ConfigParam21(prices)).unwrap();
+    assert_eq!(violated_rules(&config), ["gas limits"]);
+}
+
+#[test]
+fn test_config_check_block_limits() {
+    let (_, mut config) = key_block_config();
+    let limits = config.block_limits(false).unwrap();
+    let degenerate = BlockLimits::with_limits(
+        limits.bytes().clone(),
+        ParamLimits::with_limits(0, 0, 0).unwrap(),
+        limits.lt_delta().clone()
+    );
+    config.set_config(ConfigParamEnum::ConfigParam23(degenerate)).unwrap();
+    assert_eq!(violated_rules(&config), ["block limits"]);
+}
+
+#[test]
+fn test_config_check_all_rules_reported() {
+    let (_, mut config) = key_block_config();
+    let mut cc_config = config.catchain_config().unwrap();
+    cc_config.shard_catchain_lifetime = 0;
+    config.set_config(ConfigParamEnum::ConfigParam28(cc_config)).unwrap();
+    let mut prices = match config.config(20).unwrap() {
+        Some(ConfigParamEnum::ConfigParam20(prices)) => prices,
+        _ => panic!("key block has no gas prices")
+    };
+    prices.gas_limit = 0;
+    config.set_config(ConfigParamEnum::ConfigParam20(prices)).unwrap();
+    // One broken rule doesn't hide the others
+    assert_eq!(violated_rules(&config), ["catchain config", "gas limits"]);
+}
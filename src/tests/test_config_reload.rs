@@ -0,0 +1,168 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::network::control_permissions::ControlCommandCategory;
+use serde_json::json;
+
+fn config(json: Value) -> TonNodeConfig {
+    serde_json::from_value(json).unwrap()
+}
+
+fn running_config() -> Value {
+    json!({
+        "internal_db_path": "node_db",
+        "gc": {
+            "enable_for_archives": true,
+            "archives_life_time_hours": 48,
+            "enable_for_shard_state_persistent": false,
+            "cells_gc_config": { "gc_interval_sec": 900, "cells_lifetime_sec": 1800 }
+        },
+        "validator_keys": []
+    })
+}
+
+// Reloads config and returns report with applied changes
+fn reload(
+    reloader: &ConfigReloader,
+    new: &TonNodeConfig,
+    failing: bool
+) -> (ReloadReport, Vec<ConfigChange>) {
+    let mut changes = Vec::new();
+    let report = reloader.reload(new, |change| {
+        changes.push(change.clone());
+        if failing {
+            fail!("test failure")
+        }
+        Ok(())
+    }).unwrap();
+    (report, changes)
+}
+
+#[test]
+fn test_config_reload_apply_and_reject() {
+    let reloader = ConfigReloader::new(&config(running_config())).unwrap();
+
+    // Same config changes nothing
+    let (report, changes) = reload(&reloader, &config(running_config()), false);
+    assert!(report.applied.is_empty() && report.rejected.is_empty() && report.errors.is_empty());
+    assert!(changes.is_empty());
+
+    let mut new = running_config();
+    new["internal_db_path"] = json!("other_db");
+    new["gc"]["archives_life_time_hours"] = json!(24);
+    new["gc"]["cells_gc_config"]["gc_interval_sec"] = json!(600);
+    new["collator_config"] = json!({
+        "external_messages_maximum_queue_length": 100,
+        "external_messages_maximum_queue_bytes": 1000000
+    });
+    new["validator_keys"] = json!([{ "election_id": 1, "validator_key_id": "key" }]);
+    let new_config = config(new.clone());
+    let (report, changes) = reload(&reloader, &new_config, false);
+    assert_eq!(report.applied, [
+        "collator_config/external_messages_maximum_queue_bytes",
+        "collator_config/external_messages_maximum_queue_length",
+        "gc/archives_life_time_hours",
+        "gc/cells_gc_config/gc_interval_sec",
+    ]);
+    // Keys written by the node are not reported
+    assert_eq!(report.rejected, ["internal_db_path"]);
+    assert!(report.errors.is_empty());
+    // Options of the same subsystem give one change
+    assert_eq!(changes, [
+        ConfigChange::ExtMessagesLimits(MessagesPoolLimits {
+            max_messages: Some(100),
+            max_bytes: Some(1000000),
+            max_account_messages: None
+        }),
+        ConfigChange::ArchivesLifeTime(Some(24)),
+        ConfigChange::StatesGcInterval(600),
+    ]);
+
+    // Applied options are running now, rejected ones are still pending
+    let (report, changes) = reload(&reloader, &new_config, false);
+    assert!(report.applied.is_empty());
+    assert_eq!(report.rejected, ["internal_db_path"]);
+    assert!(changes.is_empty());
+
+    // Missing option is the same as null one
+    new["gc"].as_object_mut().unwrap().remove("archives_life_time_hours");
+    new["gc"]["enable_for_archives"] = json!(false);
+    new["default_rldp_roundtrip_ms"] = json!(2000);
+    let (report, changes) = reload(&reloader, &config(new), false);
+    assert_eq!(report.applied, ["gc/archives_life_time_hours", "gc/enable_for_archives"]);
+    assert_eq!(report.rejected, ["default_rldp_roundtrip_ms", "internal_db_path"]);
+    assert_eq!(changes, [ConfigChange::ArchivesLifeTime(None)]);
+}
+
+#[test]
+fn test_config_reload_errors() {
+    let reloader = ConfigReloader::new(&config(running_config())).unwrap();
+    let mut new = running_config();
+    new["gc"]["cells_gc_config"]["cells_lifetime_sec"] = json!(600);
+    let new = config(new);
+
+    // Failed change is reported and retried by next reload
+    let (report, changes) = reload(&reloader, &new, true);
+    assert!(report.applied.is_empty());
+    assert_eq!(report.errors, ["gc/cells_gc_config/cells_lifetime_sec: test failure"]);
+    assert_eq!(changes, [ConfigChange::CellsLifetime(600)]);
+    let (report, changes) = reload(&reloader, &new, false);
+    assert_eq!(report.applied, ["gc/cells_gc_config/cells_lifetime_sec"]);
+    assert!(report.errors.is_empty());
+    assert_eq!(changes, [ConfigChange::CellsLifetime(600)]);
+    let (_, changes) = reload(&reloader, &new, false);
+    assert!(changes.is_empty());
+}
+
+#[test]
+fn test_config_reload_log_config() {
+    let directory = "target/test_config_reload";
+    std::fs::create_dir_all(directory).unwrap();
+    let path = format!("{}/log_cfg.yml", directory);
+    std::fs::write(&path, "root:\n  level: info\n").unwrap();
+    let mut running = running_config();
+    running["log_config_name"] = json!(path);
+    let reloader = ConfigReloader::new(&config(running.clone())).unwrap();
+
+    // Changed content of log config file is reloaded
+    std::fs::write(&path, "root:\n  level: debug\n").unwrap();
+    let (report, changes) = reload(&reloader, &config(running.clone()), false);
+    assert_eq!(report.applied, ["log_config"]);
+    assert_eq!(changes, [ConfigChange::LogConfig(Some(PathBuf::from(&path)))]);
+    let (_, changes) = reload(&reloader, &config(running), false);
+    assert!(changes.is_empty());
+
+    // Log config can't be dropped
+    assert!(reload_log_config(None).is_err());
+}
+
+#[test]
+fn test_config_reload_control_permissions() {
+    const KEY: &str = "RYokIiD5AFkzfTBgC6NhtAGFKm0+gwhN4suTzaW0Sjw=";
+    let reloader = ConfigReloader::new(&config(running_config())).unwrap();
+    let mut new = running_config();
+    new["control_permissions"] = json!({ KEY: ["ReadOnly", "Gc"] });
+    let (report, changes) = reload(&reloader, &config(new), false);
+    assert_eq!(report.applied, ["control_permissions"]);
+    let expected = ControlPermissionsConfig::from([(
+        KEY.to_string(),
+        [ControlCommandCategory::ReadOnly, ControlCommandCategory::Gc].into()
+    )]);
+    assert_eq!(changes, [ConfigChange::ControlPermissions(Some(expected))]);
+
+    // Removed permissions allow everything again
+    let (report, changes) = reload(&reloader, &config(running_config()), false);
+    assert_eq!(report.applied, ["control_permissions"]);
+    assert_eq!(changes, [ConfigChange::ControlPermissions(None)]);
+}
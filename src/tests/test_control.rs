@@ -13,10 +13,18 @@
 
 use crate::{
     collator_test_bundle::create_engine_allocated, 
-    config::TonNodeConfig, engine::Engine, engine_traits::EngineOperations, 
+    config::TonNodeConfig, engine::Engine, engine_traits::{
+        BlockAccess, BroadcastSupport, EngineOperations, RempSupport, StateAccess, ValidatorSupport
+    },
     internal_db::{InternalDb, InternalDbConfig, state_gc_resolver::AllowStateGcSmartResolver}, 
     network::{
-        control::{ControlQuerySubscriber, ControlServer, DataSource, StatusReporter},
+        control::{
+            ControlQuerySubscriber, ControlServer, DataSource, StatusReporter, BUSY_ERROR_CODE,
+            CONTROL_AUDIT_FILTER, NODE_STATE_EXPORT_FILTER, PERMISSION_DENIED_ERROR_CODE,
+            TIMEOUT_ERROR_CODE
+        },
+        control_limits::{ControlBudget, ControlLimits, ControlLimitsConfig},
+        control_permissions::{ControlCommandCategory, ControlPermissions, ControlPermissionsConfig},
         node_network::NodeNetwork
     },
     shard_state::ShardStateStuff, 
@@ -33,7 +41,7 @@ use adnl::{
 };
 use std::{
     collections::HashMap, fs::{copy, remove_dir_all}, ops::Deref, 
-    sync::{Arc, atomic::{AtomicBool, Ordering}}, time::SystemTime
+    sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}, time::{Duration, SystemTime}
 };
 use storage::block_handle_db::BlockHandle;
 use ton_api::{ 
@@ -135,6 +143,18 @@ async fn start_control_with_options(
     data_source: DataSource,
     config: Option<TonNodeConfig>,
     server_only: bool
+) -> Result<(ControlServer, Option<AdnlClient>, Arc<KeyId>)> {
+    let permissions = Arc::new(ControlPermissions::new(None)?);
+    let limits = Arc::new(ControlLimits::new(None)?);
+    start_control_with_permissions(data_source, config, server_only, permissions, limits).await
+}
+
+async fn start_control_with_permissions(
+    data_source: DataSource,
+    config: Option<TonNodeConfig>,
+    server_only: bool,
+    permissions: Arc<ControlPermissions>,
+    limits: Arc<ControlLimits>
 ) -> Result<(ControlServer, Option<AdnlClient>, Arc<KeyId>)> {
     copy("./configs/ton-global.config-sample.json", "./target/ton-global.config-sample.json")?;
     let config = if let Some(config) = config {
@@ -156,6 +176,8 @@ async fn start_control_with_options(
         data_source,
         network.config_handler(),
         network.config_handler(),
+        permissions,
+        limits,
         Some(&network)
     ).await?;
     let client = if server_only {
@@ -220,13 +242,21 @@ async fn test_get_all_config_params() {
         }
     }
 
+    impl EngineOperations for TestEngine {}
+
+    impl BlockAccess for TestEngine {}
+
     #[async_trait::async_trait]
-    impl EngineOperations for TestEngine {
+    impl StateAccess for TestEngine {
         async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
             Ok(self.state.clone())
         }
     }
 
+    impl RempSupport for TestEngine {}
+    impl BroadcastSupport for TestEngine {}
+    impl ValidatorSupport for TestEngine {}
+
     init_test_log();
     let engine = Arc::new(TestEngine::new());
     let (control, mut client, _) = start_control(
@@ -303,21 +333,9 @@ async fn test_getaccount() {
 
     #[async_trait::async_trait]
     impl EngineOperations for TestEngine {
-        async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
-            Ok(self.master_state.clone())
-        }
         fn load_shard_client_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
             Ok(Some(Arc::new(self.master_state_id.clone())))
         }
-        async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
-            if *block_id == self.master_state_id {
-                Ok(self.master_state.clone())
-            } else if *block_id == self.shard_state_id {
-                Ok(self.shard_state.clone())
-            } else {
-                fail!("Wrong block ID {}", block_id)
-            }
-        }
         async fn load_and_pin_state(&self, block_id: &BlockIdExt) -> Result<PinnedShardStateGuard> {
             if *block_id == self.master_state_id {
                 PinnedShardStateGuard::new(
@@ -343,9 +361,30 @@ async fn test_getaccount() {
                 None
             })
         }
+    }
+
+    impl BlockAccess for TestEngine {}
 
+    #[async_trait::async_trait]
+    impl StateAccess for TestEngine {
+        async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+            Ok(self.master_state.clone())
+        }
+        async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+            if *block_id == self.master_state_id {
+                Ok(self.master_state.clone())
+            } else if *block_id == self.shard_state_id {
+                Ok(self.shard_state.clone())
+            } else {
+                fail!("Wrong block ID {}", block_id)
+            }
+        }
     }
 
+    impl RempSupport for TestEngine {}
+    impl BroadcastSupport for TestEngine {}
+    impl ValidatorSupport for TestEngine {}
+
     init_test_log();
     let engine = Arc::new(TestEngine::new());
     let control = start_control_with_options(
@@ -456,6 +495,12 @@ async fn test_get_applied_shards_info() {
         fn load_shard_client_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
             Ok(Some(Arc::new(self.shard_client_master_state_id.clone())))
         }
+    }
+
+    impl BlockAccess for TestEngine {}
+
+    #[async_trait::async_trait]
+    impl StateAccess for TestEngine {
         async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
             if *block_id == self.shard_client_master_state_id {
                 Ok(self.shard_client_master_state.clone())
@@ -465,6 +510,10 @@ async fn test_get_applied_shards_info() {
         }
     }
 
+    impl RempSupport for TestEngine {}
+    impl BroadcastSupport for TestEngine {}
+    impl ValidatorSupport for TestEngine {}
+
     init_test_log();
     let engine = Arc::new(TestEngine::new());
     let control = start_control_with_options(
@@ -509,18 +558,169 @@ async fn test_connect_to_control() {
 
 }
 
+// Returns error code of the answer, None for successful answer
+async fn query_error_code(client: &mut AdnlClient, query: TLObject) -> Result<Option<ton::int>> {
+    let control_query = TaggedTlObject {
+        object: TLObject::new(
+            ControlQuery {
+                data: serialize_boxed(&query)?
+            },
+        ),
+        #[cfg(feature = "telemetry")]
+        tag: tag_from_boxed_type::<ControlQuery>()
+    };
+    match client.query(&control_query).await?.downcast::<ControlQueryError>() {
+        Ok(error) => Ok(Some(error.only().code)),
+        Err(_) => Ok(None)
+    }
+}
+
+#[tokio::test]
+async fn test_control_permissions() {
+
+    struct TestSource;
+    impl StatusReporter for TestSource {
+        fn get_report(&self) -> u32 {
+            0
+        }
+    }
+
+    // Public key of the test client
+    const CLIENT_KEY: &str = "RYokIiD5AFkzfTBgC6NhtAGFKm0+gwhN4suTzaW0Sjw=";
+
+    init_test_log();
+    let config = ControlPermissionsConfig::from([
+        (CLIENT_KEY.to_string(), [ControlCommandCategory::ReadOnly].into())
+    ]);
+    let permissions = Arc::new(ControlPermissions::new(Some(&config)).unwrap());
+    let limits = Arc::new(ControlLimits::new(None).unwrap());
+    let (control, client, _) = start_control_with_permissions(
+        DataSource::Status(Arc::new(TestSource)), None, false, permissions.clone(), limits
+    ).await.unwrap();
+    let mut client = client.unwrap();
+    let gc_trigger = || TLObject::new(GetSelectedStats { filter: "gc_trigger:cells".into() });
+
+    // Allowed read-only command
+    let _: Stats = request(&mut client, GetStats).await.unwrap();
+    // Denied validator and GC commands
+    let code = query_error_code(&mut client, TLObject::new(GenerateKeyPair)).await.unwrap();
+    assert_eq!(code, Some(PERMISSION_DENIED_ERROR_CODE));
+    let code = query_error_code(&mut client, gc_trigger()).await.unwrap();
+    assert_eq!(code, Some(PERMISSION_DENIED_ERROR_CODE));
+
+    // Audit log is read-only
+    let audit: Stats = request(
+        &mut client, GetSelectedStats { filter: CONTROL_AUDIT_FILTER.into() }
+    ).await.unwrap();
+    let audit = audit.only().stats.iter().map(|stat| stat.key.clone()).collect::<Vec<_>>();
+    assert_eq!(audit.len(), 4);
+    assert!(audit.iter().any(|key| key.starts_with("denied:") && key.ends_with(":GenerateKeyPair")));
+    assert!(audit.iter().any(|key| key.ends_with(":GetSelectedStats:gc_trigger")));
+
+    // Updated permissions are applied at once: GC command is allowed now
+    // and fails only since there is no engine
+    let config = ControlPermissionsConfig::from([
+        (CLIENT_KEY.to_string(), [ControlCommandCategory::ReadOnly, ControlCommandCategory::Gc].into())
+    ]);
+    permissions.update(Some(&config)).unwrap();
+    let code = query_error_code(&mut client, gc_trigger()).await.unwrap();
+    assert_eq!(code, Some(-1));
+
+    // Unknown client may run nothing
+    permissions.update(Some(&ControlPermissionsConfig::new())).unwrap();
+    let code = query_error_code(&mut client, TLObject::new(GetStats)).await.unwrap();
+    assert_eq!(code, Some(PERMISSION_DENIED_ERROR_CODE));
+
+    client.shutdown().await.unwrap();
+    control.shutdown().await;
+}
+
+struct TestSlowEngine {
+    delay_ms: AtomicU64
+}
+
+#[async_trait::async_trait]
+impl EngineOperations for TestSlowEngine {
+    async fn export_node_state(&self) -> Result<Vec<(String, BlockIdExt)>> {
+        let delay_ms = self.delay_ms.load(Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        Ok(Vec::new())
+    }
+}
+
+impl BlockAccess for TestSlowEngine {}
+impl StateAccess for TestSlowEngine {}
+impl RempSupport for TestSlowEngine {}
+impl BroadcastSupport for TestSlowEngine {}
+impl ValidatorSupport for TestSlowEngine {}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_control_limits() {
+
+    init_test_log();
+    let engine = Arc::new(TestSlowEngine { delay_ms: AtomicU64::new(500) });
+    let config = ControlLimitsConfig::from([
+        (ControlCommandCategory::ReadOnly, ControlBudget { timeout_ms: 1000, max_concurrent: 1 })
+    ]);
+    let permissions = Arc::new(ControlPermissions::new(None).unwrap());
+    let limits = Arc::new(ControlLimits::new(Some(&config)).unwrap());
+    let (control, client, _) = start_control_with_permissions(
+        DataSource::Engine(engine.clone()), None, false, permissions, limits
+    ).await.unwrap();
+    let mut client1 = client.unwrap();
+    let (_, client_config) = AdnlClientConfig::from_json(ADNL_CLIENT_CONFIG).unwrap();
+    let mut client2 = AdnlClient::connect(&client_config).await.unwrap();
+    let node_state_export = || TLObject::new(
+        GetSelectedStats { filter: NODE_STATE_EXPORT_FILTER.into() }
+    );
+
+    // Second command of the category is refused while the first one runs
+    let (code1, code2) = tokio::join!(
+        query_error_code(&mut client1, node_state_export()),
+        async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            query_error_code(&mut client2, node_state_export()).await
+        }
+    );
+    assert_eq!(code1.unwrap(), None);
+    assert_eq!(code2.unwrap(), Some(BUSY_ERROR_CODE));
+    // Other categories are not limited by it
+    let code = query_error_code(&mut client2, TLObject::new(GenerateKeyPair)).await.unwrap();
+    assert_eq!(code, None);
+
+    // Stuck command is aborted and its permit is returned
+    engine.delay_ms.store(3000, Ordering::Relaxed);
+    let code = query_error_code(&mut client1, node_state_export()).await.unwrap();
+    assert_eq!(code, Some(TIMEOUT_ERROR_CODE));
+    engine.delay_ms.store(0, Ordering::Relaxed);
+    let code = query_error_code(&mut client2, node_state_export()).await.unwrap();
+    assert_eq!(code, None);
+
+    client1.shutdown().await.unwrap();
+    client2.shutdown().await.unwrap();
+    control.shutdown().await;
+}
+
 struct TestSendMsgEngine {
     expected_data: Vec<u8>
 }
 
+impl EngineOperations for TestSendMsgEngine {}
+
+impl BlockAccess for TestSendMsgEngine {}
+impl StateAccess for TestSendMsgEngine {}
+impl RempSupport for TestSendMsgEngine {}
+
 #[async_trait::async_trait]
-impl EngineOperations for TestSendMsgEngine {
+impl BroadcastSupport for TestSendMsgEngine {
     async fn redirect_external_message(&self, message_data: &[u8], _id: UInt256) -> Result<()> {
         assert_eq!(message_data, &self.expected_data);
         Ok(())
     }
 }
 
+impl ValidatorSupport for TestSendMsgEngine {}
+
 #[tokio::test]
 async fn test_control_send_message() {
 
@@ -706,23 +906,39 @@ async fn test_stats() {
         fn get_sync_status(&self) -> u32 {
             Engine::SYNC_STATUS_SYNC_BLOCKS
         }
-        fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
-            &self.last_validation_time
+        fn load_last_applied_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
+            Ok(Some(Arc::new(self.master_state_id.clone())))
         }
-        fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
-            &self.last_validation_time
+        fn load_shard_client_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
+            Ok(Some(Arc::new(self.master_state_id.clone())))
+        }
+        fn pinned_states_stats(&self) -> Result<(usize, u32)> {
+            Ok((0, 0))
         }
+    }
+
+    impl BlockAccess for TestEngine {
         fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
             self.db.load_block_handle(id)
         }
-        fn load_last_applied_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
-            Ok(Some(Arc::new(self.master_state_id.clone())))
-        }
+    }
+
+    #[async_trait::async_trait]
+    impl StateAccess for TestEngine {
         async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
             Ok(self.master_state.clone())
         }
-        fn load_shard_client_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
-            Ok(Some(Arc::new(self.master_state_id.clone())))
+    }
+
+    impl RempSupport for TestEngine {}
+    impl BroadcastSupport for TestEngine {}
+
+    impl ValidatorSupport for TestEngine {
+        fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+            &self.last_validation_time
+        }
+        fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+            &self.last_validation_time
         }
         fn validation_status(&self) -> ValidationStatus {
             ValidationStatus::Active
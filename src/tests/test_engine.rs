@@ -32,7 +32,8 @@ fn start_node(
     let flags = EngineFlags {
         initial_sync_disabled: true,
         starting_block_disabled: false,
-        force_check_db: false
+        force_check_db: false,
+        read_only: false,
     };
     rt.block_on(
         run(
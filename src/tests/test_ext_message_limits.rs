@@ -0,0 +1,146 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::{
+    BuilderData, ConfigParam12, ExternalInboundMessageHeader, MsgAddressInt, SizeLimitsConfig,
+    SliceData, WorkchainDescr, Workchains
+};
+
+fn create_message(workchain_id: i32, body_depth: u16, import_fee: u64) -> Message {
+    let mut hdr = ExternalInboundMessageHeader::default();
+    hdr.dst = MsgAddressInt::with_standart(None, workchain_id as i8, [1; 32].into()).unwrap();
+    hdr.import_fee = import_fee.into();
+    let mut body = BuilderData::new().into_cell().unwrap();
+    for _ in 0..body_depth {
+        let mut builder = BuilderData::new();
+        builder.checked_append_reference(body).unwrap();
+        body = builder.into_cell().unwrap();
+    }
+    Message::with_ext_in_header_and_body(hdr, SliceData::load_cell(body).unwrap())
+}
+
+fn reject_reason(limits: &ExtMessageLimits, size: usize, message: &Message) -> Option<ExtMessageRejectReason> {
+    match limits.check(size, message) {
+        Ok(()) => None,
+        Err(e) => match e.downcast_ref::<NodeError>() {
+            Some(NodeError::ExtMessageRejected { reason, .. }) => Some(reason.clone()),
+            _ => panic!("Unexpected error {}", e)
+        }
+    }
+}
+
+fn node_limits(workchain_id: i32, max_size: u32, max_body_depth: u16, min_import_fee: u64) -> ExtMessageLimits {
+    let limits = ExtMessageLimitsConfig {
+        max_size: Some(max_size),
+        max_body_depth: Some(max_body_depth),
+        min_import_fee: Some(min_import_fee),
+    };
+    ExtMessageLimits::new(HashMap::from([(workchain_id, limits)]), None)
+}
+
+fn network_config(size_limits: Option<(u32, u16)>, workchains: &[i32]) -> ConfigParams {
+    let mut config = ConfigParams::new();
+    if let Some((max_ext_msg_size, max_ext_msg_depth)) = size_limits {
+        let limits = SizeLimitsConfig { max_ext_msg_size, max_ext_msg_depth, ..Default::default() };
+        config.set_config(ConfigParamEnum::ConfigParam43(limits)).unwrap();
+    }
+    if !workchains.is_empty() {
+        let mut descrs = Workchains::default();
+        for workchain_id in workchains {
+            descrs.set(workchain_id, &WorkchainDescr::default()).unwrap();
+        }
+        config.set_config(ConfigParamEnum::ConfigParam12(ConfigParam12 { workchains: descrs })).unwrap();
+    }
+    config
+}
+
+#[test]
+fn test_ext_message_size_limit() {
+    let limits = node_limits(0, 1000, 16, 0);
+    let message = create_message(0, 0, 0);
+    assert_eq!(reject_reason(&limits, 1000, &message), None);
+    assert_eq!(
+        reject_reason(&limits, 1001, &message),
+        Some(ExtMessageRejectReason::TooLarge { size: 1001, max_size: 1000 })
+    );
+}
+
+#[test]
+fn test_ext_message_body_depth_limit() {
+    let limits = node_limits(0, 1000, 3, 0);
+    assert_eq!(reject_reason(&limits, 100, &create_message(0, 0, 0)), None);
+    assert_eq!(reject_reason(&limits, 100, &create_message(0, 3, 0)), None);
+    assert_eq!(
+        reject_reason(&limits, 100, &create_message(0, 4, 0)),
+        Some(ExtMessageRejectReason::TooDeepBody { depth: 4, max_depth: 3 })
+    );
+}
+
+#[test]
+fn test_ext_message_import_fee_limit() {
+    let limits = node_limits(0, 1000, 16, 10);
+    assert_eq!(reject_reason(&limits, 100, &create_message(0, 0, 10)), None);
+    assert_eq!(
+        reject_reason(&limits, 100, &create_message(0, 0, 9)),
+        Some(ExtMessageRejectReason::ImportFeeTooLow { fee: 9, min_fee: 10 })
+    );
+    // Limits are per workchain
+    assert_eq!(reject_reason(&limits, 100, &create_message(-1, 0, 0)), None);
+}
+
+#[test]
+fn test_ext_message_workchain_check() {
+    let limits = ExtMessageLimits::new(HashMap::new(), Some(0));
+    assert_eq!(reject_reason(&limits, 100, &create_message(0, 0, 0)), None);
+    assert_eq!(reject_reason(&limits, 100, &create_message(-1, 0, 0)), None);
+    assert_eq!(
+        reject_reason(&limits, 100, &create_message(1, 0, 0)),
+        Some(ExtMessageRejectReason::UntrackedWorkchain)
+    );
+
+    // Node which tracks all workchains rejects ones absent in network config
+    let limits = ExtMessageLimits::new(HashMap::new(), None);
+    assert_eq!(reject_reason(&limits, 100, &create_message(1, 0, 0)), None);
+    limits.refresh(&network_config(None, &[0])).unwrap();
+    assert_eq!(reject_reason(&limits, 100, &create_message(0, 0, 0)), None);
+    assert_eq!(reject_reason(&limits, 100, &create_message(-1, 0, 0)), None);
+    assert_eq!(
+        reject_reason(&limits, 100, &create_message(1, 0, 0)),
+        Some(ExtMessageRejectReason::UnknownWorkchain)
+    );
+}
+
+#[test]
+fn test_ext_message_limits_fallback() {
+    let limits = node_limits(0, 1000, 16, 10);
+    let node = EffectiveLimits { max_size: 1000, max_body_depth: 16, min_import_fee: 10 };
+    let defaults = EffectiveLimits {
+        max_size: MAX_EXTERNAL_MESSAGE_SIZE as u32,
+        max_body_depth: MAX_EXTERNAL_MESSAGE_DEPTH,
+        min_import_fee: 0
+    };
+    assert_eq!(limits.limits(0), node);
+    assert_eq!(limits.limits(-1), defaults);
+
+    // Network config takes precedence, import fee is not defined by it
+    limits.refresh(&network_config(Some((2000, 8)), &[])).unwrap();
+    assert_eq!(limits.limits(0), EffectiveLimits { max_size: 2000, max_body_depth: 8, min_import_fee: 10 });
+    assert_eq!(limits.limits(-1), EffectiveLimits { max_size: 2000, max_body_depth: 8, min_import_fee: 0 });
+    assert_eq!(reject_reason(&limits, 1500, &create_message(0, 0, 10)), None);
+
+    // Limits are back to the node config ones when the network doesn't define them
+    limits.refresh(&network_config(None, &[])).unwrap();
+    assert_eq!(limits.limits(0), node);
+    assert_eq!(limits.limits(-1), defaults);
+}
@@ -109,6 +109,57 @@ fn test_create_ext_message() {
     create_ext_message(&data).unwrap();
 }
 
+#[test]
+fn test_create_ext_message_with_time_check() {
+    // External inbound header has no creation time, so the message is never checked
+    let msg = Message::with_ext_in_header(ExternalInboundMessageHeader::default());
+    let b = msg.serialize().unwrap();
+    let data = write_boc(&b.into()).unwrap();
+
+    let (id, _) = create_ext_message(&data).unwrap();
+    let (id_checked, _) = create_ext_message_with_time_check(&data, u32::MAX, 0, 0).unwrap();
+    assert_eq!(id, id_checked);
+    create_ext_message_with_time_check(&data, 0, 0, 0).unwrap();
+
+    // Other checks are still performed
+    let big_data = [0; MAX_EXTERNAL_MESSAGE_SIZE + 6];
+    create_ext_message_with_time_check(&big_data, 1000, 100, 10).expect_err("it must return error");
+}
+
+fn check_time_window_error(created_at: u32, now: u32, max_age: u32, max_skew: u32, min_time: u32, max_time: u32) {
+    let err = check_ext_message_time(created_at, now, max_age, max_skew).expect_err("it must return error");
+    match err.downcast_ref::<NodeError>() {
+        Some(NodeError::ExtMessageOutOfTimeWindow { created_at: c, min_time: lwb, max_time: upb }) => {
+            assert_eq!(*c, created_at);
+            assert_eq!(*lwb, min_time);
+            assert_eq!(*upb, max_time);
+        }
+        _ => panic!("unexpected error {}", err)
+    }
+}
+
+#[test]
+fn test_check_ext_message_time() {
+    let now = 1_000_000;
+
+    // Too old
+    check_ext_message_time(now - 100, now, 100, 10).unwrap();
+    check_time_window_error(now - 101, now, 100, 10, now - 100, now + 10);
+
+    // Too far in the future
+    check_ext_message_time(now + 10, now, 100, 10).unwrap();
+    check_time_window_error(now + 11, now, 100, 10, now - 100, now + 10);
+
+    check_ext_message_time(now, now, 0, 0).unwrap();
+    check_time_window_error(now - 1, now, 0, 0, now, now);
+    check_time_window_error(now + 1, now, 0, 0, now, now);
+
+    // No overflows at the ends of time range
+    check_ext_message_time(0, 50, 100, 10).unwrap();
+    check_ext_message_time(u32::MAX, u32::MAX - 5, 100, 10).unwrap();
+    check_ext_message_time(0, u32::MAX, u32::MAX, 0).unwrap();
+}
+
 #[test]
 fn test_message_keeper() {
     let m = Message::with_ext_in_header(ExternalInboundMessageHeader::default());
@@ -320,14 +371,18 @@ async fn test_ext_messages_multi_threads() {
 fn test_external_messages_maximum_queue_length() {
     let maximum_queue_length = 10;
     let mp = Arc::new(MessagesPool::new(0, Some(maximum_queue_length)));
-    for i in 0..maximum_queue_length {
+    let mut ids = Vec::new();
+    for i in 0..=maximum_queue_length {
         let m = create_external_message(0, vec!(i as u8));
-        let id = m.hash().unwrap();
-        mp.new_message(&id, m, 0).unwrap();
+        ids.push(m.hash().unwrap());
+        mp.new_message(ids.last().unwrap(), m, 0).unwrap();
     }
-    let m = create_external_message(0, vec!(maximum_queue_length as u8));
-    let id = m.hash().unwrap();
-    mp.new_message(&id, m, 0).unwrap_err();
+    // the oldest message is evicted to give place to the new one
+    assert_eq!(mp.total_messages(), maximum_queue_length);
+    assert_eq!(mp.stats().evicted, 1);
+    let pool_ids = mp.get_messages(&ShardIdent::full(0), 0).unwrap()
+        .into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+    assert_eq!(pool_ids, ids[1..]);
 }
 
 #[test]
@@ -359,3 +414,171 @@ fn test_external_messages_big_load() {
     assert_eq!(0, count);
     assert!((n as u64) < limit * 3);
 }
+
+fn add_messages(mp: &MessagesPool, dst: u8, salts: std::ops::Range<u32>, now: u32) -> Vec<UInt256> {
+    salts.map(|salt| {
+        let m = create_external_message(dst, salt.to_be_bytes().to_vec());
+        let id = m.hash().unwrap();
+        mp.new_message(&id, m, now).unwrap();
+        id
+    }).collect()
+}
+
+#[test]
+fn test_messages_pool_fairness() {
+    let mp = Arc::new(MessagesPool::new(0, None));
+    // noisy account sends a lot of messages before quiet ones
+    let noisy = add_messages(&mp, 0x10, 0..20, 0);
+    let quiet = [0x20, 0x50, 0x90].map(|dst| add_messages(&mp, dst, 0..2, 1));
+    let stats = mp.stats();
+    assert_eq!((stats.accounts, stats.messages, stats.evicted), (4, 26, 0));
+
+    // every account gives one message per round
+    let ids = mp.get_messages(&ShardIdent::full(0), 1).unwrap()
+        .into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+    assert_eq!(ids.len(), 26);
+    for round in 0..2 {
+        assert_eq!(ids[round * 4], noisy[round]);
+        for (i, quiet) in quiet.iter().enumerate() {
+            assert_eq!(ids[round * 4 + i + 1], quiet[round]);
+        }
+    }
+    assert_eq!(ids[8..], noisy[2..]);
+
+    // a block which takes only a few messages still has all the accounts
+    let ids = mp.clone().iter(ShardIdent::full(0), 1, u64::MAX).take(4)
+        .map(|(_, id)| id).collect::<Vec<_>>();
+    assert_eq!(ids, [noisy[0].clone(), quiet[0][0].clone(), quiet[1][0].clone(), quiet[2][0].clone()]);
+
+    // postponed message is skipped, rest of its account goes on
+    mp.complete_messages(vec!((quiet[0][0].clone(), String::new())), vec!(), 1).unwrap();
+    mp.get_messages(&ShardIdent::full(0), 1).unwrap();
+    mp.complete_messages(vec!((quiet[0][0].clone(), String::new())), vec!(), 1).unwrap();
+    let ids = mp.clone().iter(ShardIdent::full(0), 2, u64::MAX).take(8)
+        .map(|(_, id)| id).collect::<Vec<_>>();
+    assert_eq!(ids[..4], [noisy[0].clone(), quiet[0][1].clone(), quiet[1][0].clone(), quiet[2][0].clone()]);
+    assert_eq!(ids[4..], [noisy[1].clone(), quiet[1][1].clone(), quiet[2][1].clone(), noisy[2].clone()]);
+
+    // only accounts of the shard are taken
+    let shard = ShardIdent::with_tagged_prefix(0, 0xC000_0000_0000_0000).unwrap();
+    let ids = mp.get_messages(&shard, 2).unwrap();
+    assert_eq!(ids.len(), 2);
+}
+
+#[test]
+fn test_messages_pool_eviction() {
+    let limits = MessagesPoolLimits { max_messages: Some(10), ..Default::default() };
+    let mp = Arc::new(MessagesPool::with_limits(0, limits));
+    let noisy = add_messages(&mp, 0x10, 0..8, 0);
+    let mut quiet = [0x20, 0x50].map(|dst| add_messages(&mp, dst, 0..1, 0)).concat();
+    assert_eq!(mp.stats().evicted, 0);
+
+    // the oldest message of the largest account is evicted
+    quiet.extend(add_messages(&mp, 0x90, 0..1, 0));
+    let stats = mp.stats();
+    assert_eq!((stats.accounts, stats.messages, stats.evicted), (4, 10, 1));
+
+    // noisy account evicts its own messages only
+    let noisy = [noisy, add_messages(&mp, 0x10, 8..13, 0)].concat();
+    let stats = mp.stats();
+    assert_eq!((stats.accounts, stats.messages, stats.evicted), (4, 10, 6));
+    let ids = mp.get_messages(&ShardIdent::full(0), 0).unwrap()
+        .into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+    assert_eq!(ids[..4], [noisy[6].clone(), quiet[0].clone(), quiet[1].clone(), quiet[2].clone()]);
+    assert_eq!(ids[4..], noisy[7..]);
+
+    // accounts of equal size lose their oldest messages first
+    let limits = MessagesPoolLimits { max_messages: Some(4), ..Default::default() };
+    let mp = Arc::new(MessagesPool::with_limits(0, limits));
+    let first = add_messages(&mp, 0x10, 0..2, 0);
+    let second = add_messages(&mp, 0x20, 0..2, 0);
+    add_messages(&mp, 0x30, 0..2, 0);
+    let ids = mp.get_messages(&ShardIdent::full(0), 0).unwrap()
+        .into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+    assert_eq!(ids.len(), 4);
+    assert!(!ids.contains(&first[0]) && !ids.contains(&second[0]));
+    assert!(ids.contains(&first[1]) && ids.contains(&second[1]));
+}
+
+#[test]
+fn test_messages_pool_limits() {
+    // per-account cap rejects new messages of the account only
+    let limits = MessagesPoolLimits { max_account_messages: Some(3), ..Default::default() };
+    let mp = Arc::new(MessagesPool::with_limits(0, limits));
+    add_messages(&mp, 0x10, 0..3, 0);
+    let m = create_external_message(0x10, vec!(0xff));
+    mp.new_message(&m.hash().unwrap(), m, 0).unwrap_err();
+    add_messages(&mp, 0x20, 0..3, 0);
+    assert_eq!(mp.total_messages(), 6);
+
+    // byte budget evicts from the account taking the most bytes, not the longest one
+    let big_size = message_size(&create_external_message(0x10, vec!(0; 60))).unwrap();
+    let small_size = message_size(&create_external_message(0x20, vec!(0; 4))).unwrap();
+    let limits = MessagesPoolLimits { max_bytes: Some(big_size * 2 + small_size * 3), ..Default::default() };
+    let mp = Arc::new(MessagesPool::with_limits(0, limits));
+    let mut big_ids = Vec::new();
+    for i in 0..2 {
+        let m = create_external_message(0x10, vec!(i; 60));
+        big_ids.push(m.hash().unwrap());
+        mp.new_message(big_ids.last().unwrap(), m, 0).unwrap();
+    }
+    let small_ids = add_messages(&mp, 0x20, 0..4, 0);
+    let stats = mp.stats();
+    assert_eq!((stats.messages, stats.evicted), (5, 1));
+    assert_eq!(stats.bytes, big_size + small_size * 4);
+    let ids = mp.get_messages(&ShardIdent::full(0), 0).unwrap()
+        .into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+    assert!(!ids.contains(&big_ids[0]) && ids.contains(&big_ids[1]));
+    assert!(small_ids.iter().all(|id| ids.contains(id)));
+
+    // message bigger than the budget is rejected
+    let limits = MessagesPoolLimits { max_bytes: Some(small_size - 1), ..Default::default() };
+    let mp = MessagesPool::with_limits(0, limits);
+    let m = create_external_message(0x20, vec!(0; 4));
+    mp.new_message(&m.hash().unwrap(), m, 0).unwrap_err();
+    assert_eq!(mp.total_messages(), 0);
+}
+
+#[test]
+fn test_messages_pool_expiration() {
+    let mp = Arc::new(MessagesPool::new(0, None));
+    add_messages(&mp, 0x10, 0..2, 0);
+    let quiet = add_messages(&mp, 0x20, 0..1, 100);
+
+    // expired messages are dropped while selecting
+    let ids = mp.get_messages(&ShardIdent::full(0), MESSAGE_LIFETIME + 1).unwrap();
+    assert_eq!(ids.len(), 1);
+    assert_eq!(ids[0].1, quiet[0]);
+    let stats = mp.stats();
+    assert_eq!((stats.accounts, stats.messages, stats.expired), (1, 1, 2));
+
+    // and while inserting, even if account is not selected
+    let shard = ShardIdent::with_tagged_prefix(0, 0x4000_0000_0000_0000).unwrap();
+    add_messages(&mp, 0x90, 0..1, MESSAGE_LIFETIME + 101);
+    let stats = mp.stats();
+    assert_eq!((stats.accounts, stats.messages, stats.expired), (1, 1, 3));
+    assert_eq!(mp.get_messages(&shard, MESSAGE_LIFETIME + 101).unwrap().len(), 0);
+    assert!(mp.new_message(&quiet[0], create_external_message(0x20, vec!(0)), 0).is_err());
+}
+
+#[test]
+fn test_messages_pool_set_limits() {
+    let mp = Arc::new(MessagesPool::new(0, None));
+    add_messages(&mp, 0x10, 0..6, 0);
+    add_messages(&mp, 0x20, 0..2, 0);
+
+    // lowered limit evicts from the largest account at once
+    let limits = MessagesPoolLimits { max_messages: Some(5), ..Default::default() };
+    mp.set_limits(limits.clone());
+    assert_eq!(mp.limits(), limits);
+    let stats = mp.stats();
+    assert_eq!((stats.accounts, stats.messages, stats.evicted), (2, 5, 3));
+
+    // new messages follow the new limits
+    add_messages(&mp, 0x50, 0..1, 0);
+    let stats = mp.stats();
+    assert_eq!((stats.accounts, stats.messages, stats.evicted), (3, 5, 4));
+    mp.set_limits(MessagesPoolLimits::default());
+    add_messages(&mp, 0x50, 1..3, 0);
+    assert_eq!(mp.total_messages(), 7);
+}
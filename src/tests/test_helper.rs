@@ -21,32 +21,45 @@ use crate::{
         LAST_APPLIED_MC_BLOCK, SHARD_CLIENT_MC_BLOCK,
         BlockResult, InternalDb, InternalDbConfig,
     },
-    engine_traits::{EngineAlloc, EngineOperations}, ext_messages::MessagesPool, 
+    engine::Stopper,
+    engine_traits::{
+        BlockAccess, BroadcastSupport, EngineAlloc, EngineOperations, RempCoreInterface,
+        RempDuplicateStatus, RempSupport, StateAccess, ValidatorSupport
+    },
+    ext_messages::MessagesPool,
     network::node_network::NodeNetwork, shard_blocks::ShardBlocksPool, 
     shard_state::ShardStateStuff,
     types::top_block_descr::TopBlockDescrStuff,
-    validator::{collator::Collator, CollatorSettings, validate_query::ValidateQuery}
+    validator::{
+        collator::Collator, CollatorSettings, message_cache::MessageTraceEvent,
+        validate_query::ValidateQuery, validator_manager::ValidationStatus
+    }
 };
 use crate::validator::{
     accept_block::create_top_shard_block_description, validator_utils::compute_validator_set_cc
 };
 #[cfg(feature = "telemetry")]
-use crate::{collator_test_bundle::create_engine_telemetry, engine_traits::EngineTelemetry};
+use crate::{
+    collator_test_bundle::create_engine_telemetry, engine_traits::EngineTelemetry,
+    validator::telemetry::RempCoreTelemetry
+};
 
 use ever_block::{
     error, Account, BlkMasterInfo, Block, BlockIdExt, BlockSignatures, Cell, ConfigParam0,
     ConfigParam34, ConfigParamEnum, ConfigParams, Deserializable, HashmapAugType, InMsgDescr,
     InRefValue, McStateExtra, Message, OutMsgDescr, Serializable, ShardAccount, 
     ShardAccountBlocks, ShardIdent, ShardStateUnsplit, Transaction, U15, UInt256, 
-    ValidatorBaseInfo, ValidatorDescr, ValidatorSet, write_boc, CommonMessage,
+    ValidatorBaseInfo, ValidatorDescr, ValidatorSet, write_boc, CommonMessage, KeyId,
 };
 use ever_block_json::*;
 use std::{
-    future::Future, path::Path, pin::Pin, sync::{{Arc, RwLock}, atomic::{AtomicU32, Ordering}},
-    time::Duration
+    collections::HashMap, future::Future, path::Path, pin::Pin,
+    sync::{{Arc, RwLock}, atomic::{AtomicU32, Ordering}}, time::Duration
+};
+use storage::{block_handle_db::{BlockHandle, Callback, StoreJob}, remp_messages_db::RempMessagesDb};
+use ton_api::ton::ton_node::{
+    broadcast::{BlockBroadcast, MeshUpdateBroadcast, QueueUpdateBroadcast}, RempMessage, RempReceipt
 };
-use storage::block_handle_db::{BlockHandle, Callback, StoreJob};
-use ton_api::ton::ton_node::broadcast::BlockBroadcast;
 
 include!("../../common/src/config.rs");
 include!("../../common/src/test.rs");
@@ -859,18 +872,6 @@ impl EngineOperations for TestEngine {
             .ok_or_else(|| error!("cannot load block handle for {} for masterchain state seqno {}", mc_state_id, seqno))
     }
 
-    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
-        self.db.load_block_handle(id)
-    }
-
-    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
-        self.db.load_shard_state_dynamic(block_id)
-    }
-
-    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
-        self.db.load_block_data(handle).await
-    }
-
     async fn load_block_proof(
         &self, 
         handle: &Arc<BlockHandle>, 
@@ -889,31 +890,10 @@ impl EngineOperations for TestEngine {
         *self.last_applied_mc_block_id.write().unwrap() = Some(Arc::new(last_mc_block.clone()));
         Ok(())
     }
-    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
-        let id = if let Some(id) = self.load_last_applied_mc_block_id()? {
-            id
-        } else {
-            fail!("No last applied MC block set")
-        };
-        self.load_state(&id).await
-    }
     fn load_shard_client_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
         self.db.load_full_node_state(SHARD_CLIENT_MC_BLOCK)
     }
 
-    async fn send_block_broadcast(&self, _broadcast: BlockBroadcast) -> Result<()> {
-        Ok(())
-    }
-
-    async fn send_top_shard_block_description(
-        &self,
-        _tbd: Arc<TopBlockDescrStuff>,
-        _cc_seqno: u32,
-        _resend: bool,
-    ) -> Result<()> {
-        Ok(())
-    }
-
     async fn store_block_proof(
         &self, 
         _mesh_nw_id: i32, // zero for own network
@@ -978,30 +958,14 @@ impl EngineOperations for TestEngine {
         self.db.store_block_applied(handle, None)
     }
 
-    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
-        self.db.load_block_prev1(id)
-    }
-
-    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
-        self.db.load_block_prev2(id)
-    }
-
     fn store_block_next1(&self, handle: &Arc<BlockHandle>, next: &BlockIdExt) -> Result<()> {
         self.db.store_block_next1(handle, next, None)
     }
 
-    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
-        self.db.load_block_next1(id)
-    }
-
     fn store_block_next2(&self, handle: &Arc<BlockHandle>, next2: &BlockIdExt) -> Result<()> {
         self.db.store_block_next2(handle, next2, None)
     }
 
-    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
-        self.db.load_block_next2(id)
-    }
-
     #[cfg(feature = "external_db")]
     async fn process_block_in_ext_db(
         &self,
@@ -1016,15 +980,6 @@ impl EngineOperations for TestEngine {
         Ok(())
     }
 
-    async fn wait_state(
-        self: Arc<Self>,
-        id: &BlockIdExt,
-        _timeout_ms: Option<u64>,
-        _allow_block_downloading: bool
-    ) -> Result<Arc<ShardStateStuff>> {
-        self.load_state(id).await
-    }
-
     async fn store_state(
         &self, 
         handle: &Arc<BlockHandle>, 
@@ -1138,7 +1093,399 @@ impl EngineOperations for TestEngine {
         };
         &*COLLATOR_CONFIG
     }
+}
+
+#[async_trait::async_trait]
+impl BlockAccess for TestEngine {
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        self.db.load_block_handle(id)
+    }
+
+    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
+        self.db.load_block_data(handle).await
+    }
+
+    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        self.db.load_block_prev1(id)
+    }
+
+    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        self.db.load_block_prev2(id)
+    }
+
+    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        self.db.load_block_next1(id)
+    }
+
+    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        self.db.load_block_next2(id)
+    }
+}
+
+#[async_trait::async_trait]
+impl StateAccess for TestEngine {
+    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        self.db.load_shard_state_dynamic(block_id)
+    }
+    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+        let id = if let Some(id) = self.load_last_applied_mc_block_id()? {
+            id
+        } else {
+            fail!("No last applied MC block set")
+        };
+        self.load_state(&id).await
+    }
+
+    async fn wait_state(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        _timeout_ms: Option<u64>,
+        _allow_block_downloading: bool
+    ) -> Result<Arc<ShardStateStuff>> {
+        self.load_state(id).await
+    }
+}
+
+impl RempSupport for TestEngine {}
+
+#[async_trait::async_trait]
+impl BroadcastSupport for TestEngine {
+    async fn send_block_broadcast(&self, _broadcast: BlockBroadcast) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_top_shard_block_description(
+        &self,
+        _tbd: Arc<TopBlockDescrStuff>,
+        _cc_seqno: u32,
+        _resend: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ValidatorSupport for TestEngine {}
+
+// Mocks of capability traits. Every call is recorded by name, so tests can check
+// which operations a component used.
+#[derive(Default)]
+pub struct CallRecorder(parking_lot::Mutex<Vec<&'static str>>);
+
+impl CallRecorder {
+    pub fn record(&self, name: &'static str) {
+        self.0.lock().push(name)
+    }
+    pub fn calls(&self) -> Vec<&'static str> {
+        self.0.lock().clone()
+    }
+    pub fn count(&self, name: &str) -> usize {
+        self.0.lock().iter().filter(|call| **call == name).count()
+    }
+}
+
+#[derive(Default)]
+pub struct MockBlockAccess {
+    pub handles: HashMap<BlockIdExt, Arc<BlockHandle>>,
+    pub blocks: HashMap<BlockIdExt, BlockStuff>,
+    pub prev1: HashMap<BlockIdExt, BlockIdExt>,
+    pub prev2: HashMap<BlockIdExt, BlockIdExt>,
+    pub next1: HashMap<BlockIdExt, BlockIdExt>,
+    pub next2: HashMap<BlockIdExt, BlockIdExt>,
+    pub calls: CallRecorder,
+}
+
+impl MockBlockAccess {
+    fn get<T: Clone>(map: &HashMap<BlockIdExt, T>, id: &BlockIdExt, what: &str) -> Result<T> {
+        map.get(id).cloned().ok_or_else(|| error!("No {} for {} in mock", what, id))
+    }
+}
 
+#[async_trait::async_trait]
+impl BlockAccess for MockBlockAccess {
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        self.calls.record("load_block_handle");
+        Ok(self.handles.get(id).cloned())
+    }
+    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
+        self.calls.record("load_block");
+        Self::get(&self.blocks, handle.id(), "block")
+    }
+    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        self.calls.record("load_block_prev1");
+        Self::get(&self.prev1, id, "prev1")
+    }
+    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        self.calls.record("load_block_prev2");
+        Ok(self.prev2.get(id).cloned())
+    }
+    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        self.calls.record("load_block_next1");
+        Self::get(&self.next1, id, "next1")
+    }
+    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        self.calls.record("load_block_next2");
+        Ok(self.next2.get(id).cloned())
+    }
+}
+
+#[derive(Default)]
+pub struct MockStateAccess {
+    pub states: HashMap<BlockIdExt, Arc<ShardStateStuff>>,
+    pub last_applied_mc_block: Option<BlockIdExt>,
+    pub calls: CallRecorder,
+}
+
+impl MockStateAccess {
+    fn get(&self, id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        self.states.get(id).cloned().ok_or_else(|| error!("No state for {} in mock", id))
+    }
+}
+
+#[async_trait::async_trait]
+impl StateAccess for MockStateAccess {
+    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+        self.calls.record("load_last_applied_mc_state");
+        let id = self.last_applied_mc_block.as_ref()
+            .ok_or_else(|| error!("No last applied mc block in mock"))?;
+        self.get(id)
+    }
+    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        self.calls.record("load_state");
+        self.get(block_id)
+    }
+    async fn wait_state(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        _timeout_ms: Option<u64>,
+        _allow_block_downloading: bool
+    ) -> Result<Arc<ShardStateStuff>> {
+        self.calls.record("wait_state");
+        self.get(id)
+    }
+    async fn wait_stored_state(
+        &self,
+        id: &BlockIdExt,
+        _timeout: Option<Duration>,
+        _stopper: &Stopper
+    ) -> Result<Arc<ShardStateStuff>> {
+        self.calls.record("wait_stored_state");
+        self.get(id)
+    }
+}
+
+// Sent REMP messages and receipts are kept, all the messages are considered fresh
+pub struct MockRempSupport {
+    pub blocks: MockBlockAccess,
+    pub states: Arc<MockStateAccess>,
+    pub sent_messages: parking_lot::Mutex<Vec<(Arc<KeyId>, RempMessage)>>,
+    pub sent_receipts: parking_lot::Mutex<Vec<(Arc<KeyId>, RempReceipt)>>,
+    pub calls: CallRecorder,
+    #[cfg(feature = "telemetry")]
+    pub remp_core_telemetry: RempCoreTelemetry,
+}
+
+impl Default for MockRempSupport {
+    fn default() -> Self {
+        Self {
+            blocks: MockBlockAccess::default(),
+            states: Arc::new(MockStateAccess::default()),
+            sent_messages: parking_lot::Mutex::new(Vec::new()),
+            sent_receipts: parking_lot::Mutex::new(Vec::new()),
+            calls: CallRecorder::default(),
+            #[cfg(feature = "telemetry")]
+            remp_core_telemetry: RempCoreTelemetry::new(10),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockAccess for MockRempSupport {
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        self.blocks.load_block_handle(id)
+    }
+    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
+        self.blocks.load_block(handle).await
+    }
+    fn load_block_prev1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        self.blocks.load_block_prev1(id)
+    }
+    fn load_block_prev2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        self.blocks.load_block_prev2(id)
+    }
+    fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
+        self.blocks.load_block_next1(id)
+    }
+    fn load_block_next2(&self, id: &BlockIdExt) -> Result<Option<BlockIdExt>> {
+        self.blocks.load_block_next2(id)
+    }
+}
+
+#[async_trait::async_trait]
+impl StateAccess for MockRempSupport {
+    async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
+        self.states.load_last_applied_mc_state().await
+    }
+    async fn load_state(&self, block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
+        self.states.load_state(block_id).await
+    }
+    async fn wait_state(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        timeout_ms: Option<u64>,
+        allow_block_downloading: bool
+    ) -> Result<Arc<ShardStateStuff>> {
+        self.states.clone().wait_state(id, timeout_ms, allow_block_downloading).await
+    }
+    async fn wait_stored_state(
+        &self,
+        id: &BlockIdExt,
+        timeout: Option<Duration>,
+        stopper: &Stopper
+    ) -> Result<Arc<ShardStateStuff>> {
+        self.states.wait_stored_state(id, timeout, stopper).await
+    }
+}
+
+#[async_trait::async_trait]
+impl RempSupport for MockRempSupport {
+    fn send_remp_message(&self, to: Arc<KeyId>, message: &RempMessage) -> Result<()> {
+        self.calls.record("send_remp_message");
+        self.sent_messages.lock().push((to, message.clone()));
+        Ok(())
+    }
+    async fn send_remp_receipt(&self, to: Arc<KeyId>, receipt: RempReceipt) -> Result<()> {
+        self.calls.record("send_remp_receipt");
+        self.sent_receipts.lock().push((to, receipt));
+        Ok(())
+    }
+    fn sign_remp_receipt(&self, _receipt: &RempReceipt) -> Result<Vec<u8>> {
+        self.calls.record("sign_remp_receipt");
+        Ok(vec![0; 64])
+    }
+    async fn check_remp_duplicate(&self, _message_id: &UInt256) -> Result<RempDuplicateStatus> {
+        self.calls.record("check_remp_duplicate");
+        Ok(RempDuplicateStatus::Absent)
+    }
+    async fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>> {
+        self.calls.record("filter_fresh_messages");
+        Ok(vec![true; ids.len()])
+    }
+    fn trace_remp_message(&self, _message_id: &UInt256) -> Result<Vec<MessageTraceEvent>> {
+        self.calls.record("trace_remp_message");
+        Ok(Vec::new())
+    }
+    async fn push_message_to_remp(&self, _data: ton_api::ton::bytes) -> Result<()> {
+        self.calls.record("push_message_to_remp");
+        Ok(())
+    }
+    fn remp_capability(&self) -> bool {
+        true
+    }
+    fn set_remp_core_interface(&self, _rci: Arc<dyn RempCoreInterface>) -> Result<()> {
+        self.calls.record("set_remp_core_interface");
+        Ok(())
+    }
+    fn remp_messages_db(&self) -> Result<Arc<RempMessagesDb>> {
+        fail!("No REMP messages db in mock")
+    }
+    fn remp_pending_records_db(&self) -> Result<Arc<RempMessagesDb>> {
+        fail!("No REMP pending records db in mock")
+    }
+    #[cfg(feature = "telemetry")]
+    fn remp_core_telemetry(&self) -> &RempCoreTelemetry {
+        &self.remp_core_telemetry
+    }
+}
+
+#[derive(Default)]
+pub struct MockBroadcastSupport {
+    pub calls: CallRecorder,
+}
+
+#[async_trait::async_trait]
+impl BroadcastSupport for MockBroadcastSupport {
+    async fn send_block_broadcast(&self, _broadcast: BlockBroadcast) -> Result<()> {
+        self.calls.record("send_block_broadcast");
+        Ok(())
+    }
+    async fn send_queue_update_broadcast(&self, _broadcast: QueueUpdateBroadcast) -> Result<()> {
+        self.calls.record("send_queue_update_broadcast");
+        Ok(())
+    }
+    async fn send_mesh_update_broadcast(&self, _broadcast: MeshUpdateBroadcast) -> Result<()> {
+        self.calls.record("send_mesh_update_broadcast");
+        Ok(())
+    }
+    async fn send_top_shard_block_description(
+        &self,
+        _tbd: Arc<TopBlockDescrStuff>,
+        _cc_seqno: u32,
+        _is_resend: bool,
+    ) -> Result<()> {
+        self.calls.record("send_top_shard_block_description");
+        Ok(())
+    }
+    async fn redirect_external_message(&self, _message_data: &[u8], _id: UInt256) -> Result<()> {
+        self.calls.record("redirect_external_message");
+        Ok(())
+    }
+}
+
+pub struct MockValidatorSupport {
+    pub validator_status: bool,
+    pub validation_status: parking_lot::Mutex<ValidationStatus>,
+    pub last_validation_time: lockfree::map::Map<ShardIdent, u64>,
+    pub last_collation_time: lockfree::map::Map<ShardIdent, u64>,
+    pub calls: CallRecorder,
+}
+
+impl Default for MockValidatorSupport {
+    fn default() -> Self {
+        Self {
+            validator_status: true,
+            validation_status: parking_lot::Mutex::new(ValidationStatus::Disabled),
+            last_validation_time: lockfree::map::Map::new(),
+            last_collation_time: lockfree::map::Map::new(),
+            calls: CallRecorder::default(),
+        }
+    }
+}
+
+impl ValidatorSupport for MockValidatorSupport {
+    fn get_validator_status(&self) -> bool {
+        self.calls.record("get_validator_status");
+        self.validator_status
+    }
+    fn validation_status(&self) -> ValidationStatus {
+        self.calls.record("validation_status");
+        *self.validation_status.lock()
+    }
+    fn set_validation_status(&self, status: ValidationStatus) {
+        self.calls.record("set_validation_status");
+        *self.validation_status.lock() = status
+    }
+    fn last_validation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+        &self.last_validation_time
+    }
+    fn set_last_validation_time(&self, shard: ShardIdent, time: u64) {
+        self.calls.record("set_last_validation_time");
+        self.last_validation_time.insert(shard, time);
+    }
+    fn remove_last_validation_time(&self, shard: &ShardIdent) {
+        self.calls.record("remove_last_validation_time");
+        self.last_validation_time.remove(shard);
+    }
+    fn last_collation_time(&self) -> &lockfree::map::Map<ShardIdent, u64> {
+        &self.last_collation_time
+    }
+    fn set_last_collation_time(&self, shard: ShardIdent, time: u64) {
+        self.calls.record("set_last_collation_time");
+        self.last_collation_time.insert(shard, time);
+    }
+    fn remove_last_collation_time(&self, shard: &ShardIdent) {
+        self.calls.record("remove_last_collation_time");
+        self.last_collation_time.remove(shard);
+    }
 }
 
 pub struct WaitForHandle {
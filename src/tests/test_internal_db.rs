@@ -13,9 +13,10 @@
 
 use crate::{
     block::BlockStuff, block_proof::BlockProofStuff,
-    collator_test_bundle::create_engine_allocated, engine_traits::{EngineAlloc, EngineOperations}, 
+    collator_test_bundle::create_engine_allocated, error::NodeError, engine_traits::{EngineAlloc, EngineOperations}, 
     internal_db::{
         BlockResult, InternalDb, InternalDbConfig, CURRENT_DB_VERSION, 
+        consistency::RepairMode, persistent_state_delta::{build_delta, DeltaManifest},
         restore::set_graceful_termination
     },
     shard_state::ShardStateStuff, test_helper::{are_shard_states_equal, WaitForHandle},
@@ -24,13 +25,17 @@ use crate::{
 #[cfg(feature = "telemetry")]
 use crate::{collator_test_bundle::create_engine_telemetry, engine_traits::EngineTelemetry};
 
-use std::{future::{self, Future}, ops::Deref, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    future::{self, Future}, ops::Deref, pin::Pin, sync::{Arc, atomic::{AtomicU32, Ordering}}, time::Duration
+};
 use storage::{
+    GcCounters, archives::package_entry_id::{GetFileNameShort, PackageEntryId},
     block_handle_db::{BlockHandle, Callback}, shardstate_db_async::SsNotificationCallback
 };
 use ever_block::{
     BlockIdExt, ShardIdent, TopBlockDescr, BlockSignatures, ShardStateUnsplit, 
-    Serializable
+    Serializable, BinTree, Block, BlockExtra, BlockInfo, BlockProof, InRefValue, McBlockExtra,
+    MerkleProof, ShardDescr, ShardHashes, SHARD_FULL, write_boc
 };
 use ever_block::{error, fail, Result, sha256_digest_slices, UInt256};
 use storage::types::BlockMeta;
@@ -813,6 +818,12 @@ async fn test_store_ss_lite_impl(path: &str) -> Result<()> {
 struct SGcEngine;
 impl crate::engine_traits::EngineOperations for SGcEngine {}
 
+impl crate::engine_traits::BlockAccess for SGcEngine {}
+impl crate::engine_traits::StateAccess for SGcEngine {}
+impl crate::engine_traits::RempSupport for SGcEngine {}
+impl crate::engine_traits::BroadcastSupport for SGcEngine {}
+impl crate::engine_traits::ValidatorSupport for SGcEngine {}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_shard_state_persistent_gc() {
     init_test_log();
@@ -1748,15 +1759,589 @@ async fn test_shard_state_persistent_gc_impl() -> Result<()> {
         let expired = ttl <= 1638277771; // Tue Nov 30 2021 13:09:31
         (ttl, expired)
     };
-    db.shard_state_persistent_gc(calc_ttl, &BlockIdExt::default()).await?;
+    db.shard_state_persistent_gc(calc_ttl, &BlockIdExt::default(), |_| false, &GcCounters::default()).await?;
 
     let calc_ttl = |t| {
         let ttl = engine.persistent_state_ttl(t, crate::boot::PSS_PERIOD_BITS);
         let expired = ttl <= 1643645379; // Mon Jan 31 2022 16:09:39
         (ttl, expired)
     };
-    db.shard_state_persistent_gc(calc_ttl, &BlockIdExt::default()).await?;
+    db.shard_state_persistent_gc(calc_ttl, &BlockIdExt::default(), |_| false, &GcCounters::default()).await?;
     stop_db(&db).await;
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_state_persistent_gc_counters() {
+    init_test_log();
+    let r = test_shard_state_persistent_gc_counters_impl().await;
+    clean_up(false, "test_shard_state_persistent_gc_counters").await;
+    r.unwrap();
+}
+
+async fn test_shard_state_persistent_gc_counters_impl() -> Result<()> {
+    let db = create_db("test_shard_state_persistent_gc_counters").await?;
+    for (i, time) in [100, 200, 300].iter().enumerate() {
+        let id = BlockIdExt {
+            root_hash: UInt256::from([i as u8 + 1; 32]),
+            ..Default::default()
+        };
+        let mut meta = BlockMeta::default();
+        meta.gen_utime = *time;
+        let handle = db.block_handle_storage.create_handle(id, meta, None)?.unwrap();
+        db.store_shard_state_persistent_raw(&handle, &vec![i as u8; 1024], None).await?;
+    }
+
+    // two oldest states are expired
+    let counters = GcCounters::default();
+    db.shard_state_persistent_gc(|t| (t, t < 300), &BlockIdExt::default(), |_| false, &counters).await?;
+    assert_eq!(counters.scanned.load(Ordering::Relaxed), 3);
+    assert_eq!(counters.removed.load(Ordering::Relaxed), 2);
+    assert_eq!(counters.bytes_freed.load(Ordering::Relaxed), 2048);
+
+    // pinned state is kept
+    let counters = GcCounters::default();
+    db.shard_state_persistent_gc(|t| (t, true), &BlockIdExt::default(), |_| true, &counters).await?;
+    assert_eq!(counters.scanned.load(Ordering::Relaxed), 1);
+    assert_eq!(counters.removed.load(Ordering::Relaxed), 0);
+    assert_eq!(counters.bytes_freed.load(Ordering::Relaxed), 0);
+    stop_db(&db).await;
+    Ok(())
+}
+
+
+fn synthetic_block(
+    shard: ShardIdent,
+    seq_no: u32,
+    top: Option<&BlockIdExt>
+) -> Result<(BlockStuff, BlockProofStuff)> {
+    let mut info = BlockInfo::default();
+    info.set_shard(shard.clone());
+    info.set_seq_no(seq_no)?;
+    let mut block = Block::default();
+    block.write_info(&info)?;
+    if let Some(top) = top {
+        let descr = ShardDescr {
+            seq_no: top.seq_no(),
+            root_hash: top.root_hash().clone(),
+            file_hash: top.file_hash().clone(),
+            ..Default::default()
+        };
+        let mut shards = ShardHashes::default();
+        shards.set(&top.shard().workchain_id(), &InRefValue(BinTree::with_item(&descr)?))?;
+        let mut mc_extra = McBlockExtra::default();
+        *mc_extra.shards_mut() = shards;
+        let mut extra = BlockExtra::default();
+        extra.write_custom(Some(&mc_extra))?;
+        block.write_extra(&extra)?;
+    }
+    let block = BlockStuff::from_block(block)?;
+    let proof = BlockProof {
+        proof_for: block.id().clone(),
+        root: MerkleProof::create(block.root_cell(), |_| true)?.serialize()?,
+        signatures: None,
+    };
+    let proof = BlockProofStuff::new(proof, !shard.is_masterchain())?;
+    Ok((block, proof))
+}
+
+async fn store_synthetic_block(
+    db: &InternalDb,
+    block: &BlockStuff,
+    proof: &BlockProofStuff,
+    prev: Option<&BlockIdExt>
+) -> Result<()> {
+    let handle = db.store_block_data(block, None).await?.to_any();
+    let handle = db.store_block_proof(block.id(), Some(handle), proof, None).await?.to_any();
+    if let Some(prev) = prev {
+        db.store_block_prev1(&handle, prev, None)?;
+        if prev.shard().is_masterchain() {
+            let prev_handle = db.load_block_handle(prev)?.ok_or_else(
+                || error!("Cannot load handle for {}", prev)
+            )?;
+            db.store_block_next1(&prev_handle, block.id(), None)?;
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_export_import_archive() {
+    clean_up(true, "test_export_archive").await;
+    clean_up(true, "test_import_archive").await;
+    let r = test_export_import_archive_impl().await;
+    clean_up(false, "test_export_archive").await;
+    clean_up(false, "test_import_archive").await;
+    r.unwrap();
+}
+
+async fn test_export_import_archive_impl() -> Result<()> {
+    let shard = ShardIdent::with_tagged_prefix(0, SHARD_FULL)?;
+    let mut blocks = Vec::new();
+    let mut exported = Vec::new();
+
+    // shard blocks 0..=6, master blocks 0..=3 refer shard blocks 0, 2, 3, 6 
+    let mut shard_ids = Vec::new();
+    let mut prev = None;
+    for seq_no in 0..=6 {
+        let (block, proof) = synthetic_block(shard.clone(), seq_no, None)?;
+        shard_ids.push(block.id().clone());
+        if seq_no > 0 {
+            exported.push(block.id().clone());
+        }
+        blocks.push((block, proof, prev.clone()));
+        prev = shard_ids.last().cloned();
+    }
+    let mut prev = None;
+    for (seq_no, top) in [0, 2, 3, 6].into_iter().enumerate() {
+        let (block, proof) = synthetic_block(
+            ShardIdent::masterchain(), seq_no as u32, Some(&shard_ids[top])
+        )?;
+        let id = block.id().clone();
+        if seq_no > 0 {
+            exported.push(id.clone());
+        }
+        blocks.push((block, proof, prev.clone()));
+        prev = Some(id);
+    }
+
+    let package_path = std::path::PathBuf::from(format!("{}/test_export_archive/range.pack", DB_PATH));
+    {
+        let db = create_db("test_export_archive").await?;
+        for (block, proof, prev) in blocks.iter() {
+            store_synthetic_block(&db, block, proof, prev.as_ref()).await?;
+        }
+        assert_eq!(db.export_archive_range(1, 3, &package_path).await?, 2 * exported.len() as u64);
+
+        // master block without data breaks export
+        let (block, proof) = synthetic_block(ShardIdent::masterchain(), 4, Some(&shard_ids[6]))?;
+        let handle = db.store_block_proof(block.id(), None, &proof, None).await?.to_any();
+        db.store_block_prev1(&handle, prev.as_ref().unwrap(), None)?;
+        let prev_handle = db.load_block_handle(prev.as_ref().unwrap())?.unwrap();
+        db.store_block_next1(&prev_handle, block.id(), None)?;
+        let broken_path = std::path::PathBuf::from(format!("{}/test_export_archive/broken.pack", DB_PATH));
+        let err = db.export_archive_range(1, 4, &broken_path).await.unwrap_err();
+        assert!(err.to_string().contains("has no data"), "{}", err);
+        assert!(!broken_path.exists());
+        stop_db(&db).await;
+    }
+    {
+        let db = create_db("test_import_archive").await?;
+        assert_eq!(db.import_archive_package(&package_path).await?, 2 * exported.len() as u64);
+        for (block, proof, _) in blocks.iter() {
+            let handle = db.load_block_handle(block.id())?;
+            if !exported.contains(block.id()) {
+                assert!(handle.is_none());
+                continue
+            }
+            let handle = handle.unwrap();
+            assert_eq!(&db.load_block_data_raw(&handle).await?, block.data());
+            let mut is_link = false;
+            assert!(handle.has_proof_or_link(&mut is_link));
+            assert_eq!(&db.load_block_proof_raw(&handle, is_link).await?, proof.data());
+        }
+        stop_db(&db).await;
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_check_and_repair_consistency() {
+    clean_up(true, "test_check_and_repair_consistency").await;
+    let r = test_check_and_repair_consistency_impl().await;
+    clean_up(false, "test_check_and_repair_consistency").await;
+    r.unwrap();
+}
+
+async fn test_check_and_repair_consistency_impl() -> Result<()> {
+    let db = create_db("test_check_and_repair_consistency").await?;
+    let shard = ShardIdent::with_tagged_prefix(0, SHARD_FULL)?;
+    let mut ids = Vec::new();
+    for seq_no in 1..=4 {
+        let (block, proof) = synthetic_block(shard.clone(), seq_no, None)?;
+        store_synthetic_block(&db, &block, &proof, None).await?;
+        ids.push(block.id().clone());
+    }
+    let report = db.check_and_repair_consistency(RepairMode::DryRun).await?;
+    assert_eq!(report.checked_handles, 4);
+    assert_eq!(report.mismatches(), 0);
+
+    // flag is set, but block's file is absent
+    let block_entry = PackageEntryId::<_, UInt256, UInt256>::Block(&ids[0]);
+    db.archive_manager.remove_unapplied_file(&block_entry.filename_short()).await?;
+    // file is present, but flag is not set (there is only proof link for shard block)
+    let proof_entry = PackageEntryId::<_, UInt256, UInt256>::Proof(&ids[1]);
+    db.archive_manager.add_file(&proof_entry, vec![1; 16]).await?;
+    // files of unknown block
+    let (unknown, _) = synthetic_block(shard.clone(), 5, None)?;
+    let unknown_entry = PackageEntryId::<_, UInt256, UInt256>::Block(unknown.id());
+    db.archive_manager.add_file(&unknown_entry, vec![2; 16]).await?;
+    db.shard_state_persistent_db.write_whole_file(unknown.id(), &[3; 16]).await?;
+    // flag is set, but persistent state is absent
+    let handle = db.load_block_handle(&ids[2])?.unwrap();
+    handle.set_persistent_state();
+    db.store_block_handle(&handle, None)?;
+
+    let report = db.check_and_repair_consistency(RepairMode::DryRun).await?;
+    assert_eq!(report.missing_data, 1);
+    assert_eq!(report.missing_proof, 0);
+    assert_eq!(report.missing_proof_link, 0);
+    assert_eq!(report.unflagged_files, 1);
+    assert_eq!(report.orphaned_files, 1);
+    assert_eq!(report.missing_persistent_states, 1);
+    assert_eq!(report.orphaned_persistent_states, 1);
+    assert_eq!(report.mismatches(), 5);
+    assert_eq!(report.repaired, 0);
+    assert_eq!(report.offending_ids.len(), 3);
+    assert!(ids[0..3].iter().all(|id| report.offending_ids.contains(id)));
+    assert!(db.load_block_handle(&ids[0])?.unwrap().has_data());
+    assert!(db.load_block_handle(&ids[2])?.unwrap().has_persistent_state());
+    assert!(db.shard_state_persistent_db.contains(unknown.id()).await?);
+
+    let report = db.check_and_repair_consistency(RepairMode::Fix).await?;
+    assert_eq!(report.mismatches(), 5);
+    assert_eq!(report.repaired, 5);
+    assert!(!db.load_block_handle(&ids[0])?.unwrap().has_data());
+    assert!(!db.load_block_handle(&ids[2])?.unwrap().has_persistent_state());
+    assert!(!db.shard_state_persistent_db.contains(unknown.id()).await?);
+    let handle = db.load_block_handle(&ids[1])?.unwrap();
+    assert!(!db.archive_manager.check_file(&handle, &proof_entry));
+
+    let report = db.check_and_repair_consistency(RepairMode::DryRun).await?;
+    assert_eq!(report.mismatches(), 0);
+    stop_db(&db).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_persistent_state_reader() {
+    clean_up(true, "test_persistent_state_reader").await;
+    let r = test_persistent_state_reader_impl().await;
+    clean_up(false, "test_persistent_state_reader").await;
+    r.unwrap();
+}
+
+async fn test_persistent_state_reader_impl() -> Result<()> {
+    let db = create_db("test_persistent_state_reader").await?;
+    let shard = ShardIdent::with_tagged_prefix(0, SHARD_FULL)?;
+    let (block, proof) = synthetic_block(shard, 1, None)?;
+    store_synthetic_block(&db, &block, &proof, None).await?;
+    let handle = db.load_block_handle(block.id())?.unwrap();
+    assert!(db.open_persistent_state_reader(&handle).await.is_err());
+
+    let chunk_size = 1000;
+    let data = (0..chunk_size * 5 / 2).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    db.store_shard_state_persistent_raw(&handle, &data, None).await?;
+
+    let mut reader = db.open_persistent_state_reader(&handle).await?.with_chunk_size(chunk_size);
+    assert_eq!(reader.total_size(), data.len() as u64);
+    let mut read = Vec::new();
+    let mut chunks = 0;
+    loop {
+        let chunk = reader.next_chunk().await?;
+        if chunk.is_empty() {
+            break
+        }
+        assert!(chunk.len() <= chunk_size);
+        read.extend_from_slice(&chunk);
+        chunks += 1;
+    }
+    assert_eq!(chunks, 3);
+    assert_eq!(read, data);
+    assert_eq!(reader.read_chunk(1500, chunk_size).await?, &data[1500..2500]);
+    assert_eq!(reader.read_chunk(2000, chunk_size).await?, &data[2000..]);
+    assert!(reader.read_chunk(data.len() as u64, chunk_size).await?.is_empty());
+    assert!(reader.read_chunk(data.len() as u64 + 1, chunk_size).await?.is_empty());
+
+    // The state is not deleted by GC while being read
+    db.shard_state_persistent_gc(|_| (0, true), &BlockIdExt::default(), |_| false, &GcCounters::default()).await?;
+    assert!(db.shard_state_persistent_db.contains(block.id()).await?);
+    assert_eq!(reader.read_chunk(0, chunk_size).await?, &data[..chunk_size]);
+    drop(reader);
+    db.shard_state_persistent_gc(|_| (0, true), &BlockIdExt::default(), |_| false, &GcCounters::default()).await?;
+    assert!(!db.shard_state_persistent_db.contains(block.id()).await?);
+
+    stop_db(&db).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_persistent_state_delta() {
+    clean_up(true, "test_persistent_state_delta").await;
+    let r = test_persistent_state_delta_impl().await;
+    clean_up(false, "test_persistent_state_delta").await;
+    r.unwrap();
+}
+
+async fn test_persistent_state_delta_impl() -> Result<()> {
+    let db = create_db("test_persistent_state_delta").await?;
+    let (_, ss) = prepare_ss(
+        #[cfg(feature = "telemetry")]
+        &db.telemetry,
+        &db.allocated
+    )?;
+    let shard = ShardIdent::with_tagged_prefix(0, SHARD_FULL)?;
+    let mut handles = Vec::new();
+    let mut states = Vec::new();
+    for seq_no in 1..=4 {
+        let (block, proof) = synthetic_block(shard.clone(), seq_no, None)?;
+        store_synthetic_block(&db, &block, &proof, None).await?;
+        handles.push(db.load_block_handle(block.id())?.unwrap());
+        let mut state = ss.state()?.clone();
+        state.set_gen_time(state.gen_time() + seq_no);
+        states.push(ShardStateStuff::from_state(
+            block.id().clone(),
+            state,
+            #[cfg(feature = "telemetry")]
+            &db.telemetry,
+            &db.allocated
+        )?);
+    }
+    let abort = Arc::new(|| false);
+
+    // Full snapshot and two deltas over it
+    let full = write_boc(states[0].root_cell())?;
+    db.store_shard_state_persistent_raw(&handles[0], &full, None).await?;
+    for i in 1..=2 {
+        db.store_shard_state_persistent_delta(
+            &handles[i], states[i].clone(), handles[i - 1].id(), None, abort.clone()
+        ).await?;
+        assert!(handles[i].has_persistent_state());
+        assert!(!db.shard_state_persistent_db.contains(handles[i].id()).await?);
+    }
+    assert_eq!(db.persistent_state_delta_chain_len(handles[0].id()).await?, 0);
+    assert_eq!(db.persistent_state_delta_chain_len(handles[2].id()).await?, 2);
+    let delta_size = db.shard_state_persistent_delta_db.get_file_size(handles[2].id()).await?;
+    assert!(delta_size * 10 < full.len() as u64);
+
+    // State is loaded from the chain without materialization
+    let loaded = db.load_shard_state_persistent(handles[2].id(), &|| false).await?;
+    assert_eq!(loaded.root_cell().repr_hash(), states[2].root_cell().repr_hash());
+    assert!(!db.shard_state_persistent_db.contains(handles[2].id()).await?);
+
+    // Materialized state is byte-identical to the full snapshot
+    let expected = write_boc(states[2].root_cell())?;
+    let size = db.load_shard_state_persistent_size(handles[2].id()).await?;
+    assert_eq!(size, expected.len() as u64);
+    assert_eq!(db.load_shard_state_persistent_slice(handles[2].id(), 0, size).await?, expected);
+    let expected = write_boc(states[1].root_cell())?;
+    let mut reader = db.open_persistent_state_reader(&handles[1]).await?;
+    assert_eq!(reader.total_size(), expected.len() as u64);
+    assert_eq!(reader.read_chunk(0, expected.len()).await?, expected);
+    drop(reader);
+
+    // Corrupted manifest gives error and not a wrong state
+    db.store_shard_state_persistent_delta(
+        &handles[3], states[3].clone(), handles[2].id(), None, abort.clone()
+    ).await?;
+    let mut data = db.shard_state_persistent_delta_db.read_whole_file(handles[3].id()).await?;
+    data[40] ^= 1;
+    db.shard_state_persistent_delta_db.write_whole_file(handles[3].id(), &data).await?;
+    let err = db.load_shard_state_persistent(handles[3].id(), &|| false).await.unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+    assert!(db.persistent_state_delta_chain_len(handles[3].id()).await.is_err());
+    assert!(db.load_shard_state_persistent_size(handles[3].id()).await.is_err());
+    assert!(!db.shard_state_persistent_db.contains(handles[3].id()).await?);
+    let manifest = DeltaManifest {
+        base_id: handles[2].id().clone(),
+        base_file_hash: UInt256::default(),
+        chain_len: 3
+    };
+    let data = build_delta(&manifest, states[2].root_cell(), states[3].root_cell())?;
+    db.shard_state_persistent_delta_db.write_whole_file(handles[3].id(), &data).await?;
+    let err = db.load_shard_state_persistent(handles[3].id(), &|| false).await.unwrap_err();
+    assert!(err.to_string().contains("doesn't match the manifest"), "{}", err);
+
+    // Bases of the pinned delta are kept by GC
+    let zerostate_id = BlockIdExt::default();
+    let pinned = handles[2].id().clone();
+    db.shard_state_persistent_gc(|_| (0, true), &zerostate_id, |id| id == &pinned, &GcCounters::default()).await?;
+    for handle in &handles[..3] {
+        assert!(db.has_persistent_state_file(handle.id()).await?);
+    }
+    assert!(!db.has_persistent_state_file(handles[3].id()).await?);
+    db.shard_state_persistent_gc(|_| (0, true), &zerostate_id, |_| false, &GcCounters::default()).await?;
+    for handle in &handles {
+        assert!(!db.shard_state_persistent_db.contains(handle.id()).await?);
+        assert!(!db.shard_state_persistent_delta_db.contains(handle.id()).await?);
+    }
+
+    stop_db(&db).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_broken_block_file() {
+    clean_up(true, "test_broken_block_file").await;
+    let r = test_broken_block_file_impl().await;
+    clean_up(false, "test_broken_block_file").await;
+    r.unwrap();
+}
+
+async fn test_broken_block_file_impl() -> Result<()> {
+    let db = create_db("test_broken_block_file").await?;
+    let broken = Arc::new(AtomicU32::new(0));
+    let broken_clone = broken.clone();
+    db.set_broken_block_handler(Arc::new(move |_: &BlockIdExt| {
+        broken_clone.fetch_add(1, Ordering::Relaxed);
+    }));
+    let shard = ShardIdent::with_tagged_prefix(0, SHARD_FULL)?;
+    let (block, proof) = synthetic_block(shard, 1, None)?;
+    store_synthetic_block(&db, &block, &proof, None).await?;
+    let entry_id = PackageEntryId::<_, UInt256, UInt256>::Block(block.id());
+    let path = db.archive_manager.unapplied_files_path().join(entry_id.filename_short());
+
+    // file is written partially
+    let data = block.data();
+    std::fs::write(&path, &data[..data.len() / 2])?;
+    let handle = db.load_block_handle(block.id())?.unwrap();
+    assert!(handle.has_data());
+    let err = db.load_block_data_raw(&handle).await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::NotFound(_))));
+    assert!(!handle.has_data());
+    assert!(!handle.is_data_verified());
+    assert!(!path.exists());
+    assert_eq!(broken.load(Ordering::Relaxed), 1);
+    assert_eq!(db.broken_block_files(), 1);
+    assert!(!db.load_block_handle(block.id())?.unwrap().has_data());
+
+    // block is downloaded again
+    db.store_block_data(&block, None).await?;
+    let handle = db.load_block_handle(block.id())?.unwrap();
+    assert_eq!(db.load_block_data_raw(&handle).await?, data.to_vec());
+    assert!(handle.is_data_verified());
+
+    // verified file is not checked again
+    std::fs::write(&path, &data[..data.len() / 2])?;
+    assert_eq!(db.load_block_data_raw(&handle).await?.len(), data.len() / 2);
+    assert_eq!(broken.load(Ordering::Relaxed), 1);
+    assert!(handle.has_data());
+    stop_db(&db).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_storage_usage_report() {
+    clean_up(true, "test_storage_usage_report").await;
+    let r = test_storage_usage_report_impl().await;
+    clean_up(false, "test_storage_usage_report").await;
+    r.unwrap();
+}
+
+async fn test_storage_usage_report_impl() -> Result<()> {
+    use crate::internal_db::storage_usage::{AgeBucketUsage, SnapshotUsage, UsageItem, OTHER_BLOCK_FILES};
+    use std::time::SystemTime;
+    use storage::archives::{package::Package, package_entry::PackageEntry, package_entry_id::GetFileName};
+
+    let db = create_db("test_storage_usage_report").await?;
+    let root = std::path::PathBuf::from(&db.config.db_directory);
+    let mc_id = gen_block_id_ext(ShardIdent::masterchain(), 10);
+    let shard_id = gen_block_id_ext(ShardIdent::with_tagged_prefix(0, SHARD_FULL)?, 7);
+
+    // Old package with two entries of masterchain block and one of shard block
+    let old_path = root.join("archive/packages/arch0000/archive.00000.pack");
+    std::fs::create_dir_all(old_path.parent().unwrap())?;
+    let package = Package::open(old_path.clone(), false, true).await?;
+    let mut entries_size = Vec::new();
+    for (entry_id, size) in [
+        (PackageEntryId::<_, UInt256, UInt256>::Block(&mc_id), 1000),
+        (PackageEntryId::<_, UInt256, UInt256>::Proof(&mc_id), 300),
+        (PackageEntryId::<_, UInt256, UInt256>::Block(&shard_id), 500),
+    ] {
+        let filename = entry_id.filename();
+        entries_size.push(8 + filename.len() as u64 + size);
+        package.append_entry(&PackageEntry::with_data(filename, vec![0; size as usize]), |_, _| Ok(())).await?;
+    }
+    std::fs::File::options().write(true).open(&old_path)?
+        .set_modified(SystemTime::now() - Duration::from_secs(10 * 86400))?;
+    let old_size = std::fs::metadata(&old_path)?.len();
+    assert_eq!(old_size, 4 + entries_size.iter().sum::<u64>());
+    // Fresh empty key blocks package
+    let key_path = root.join("archive/packages/key000/key.archive.000000.pack");
+    std::fs::create_dir_all(key_path.parent().unwrap())?;
+    Package::open(key_path, false, true).await?;
+
+    // Unapplied files: shard block and something unknown
+    let unapplied = db.archive_manager.unapplied_files_path();
+    let entry_id = PackageEntryId::<_, UInt256, UInt256>::Block(&shard_id);
+    std::fs::write(unapplied.join(entry_id.filename_short()), vec![1; 200])?;
+    std::fs::write(unapplied.join("garbage"), vec![1; 50])?;
+
+    // Persistent states of two snapshots, delta and a state without handle
+    let mc_id_2 = gen_block_id_ext(ShardIdent::masterchain(), 20);
+    for (id, size) in [(&mc_id, 1024), (&mc_id_2, 2048)] {
+        let handle = db.block_handle_storage.create_handle(id.clone(), BlockMeta::default(), None)?.unwrap();
+        db.store_shard_state_persistent_raw(&handle, &vec![2; size], None).await?;
+    }
+    db.shard_state_persistent_delta_db.write_whole_file(&mc_id_2, &vec![3; 300]).await?;
+    db.shard_state_persistent_db.write_whole_file(&UInt256::from([9; 32]), &vec![4; 512]).await?;
+    db.flush_block_handles().await?;
+
+    let report = db.storage_usage_report().await?;
+    let item = |count, bytes| UsageItem { count, bytes };
+
+    let masterchain = ShardIdent::masterchain().to_string();
+    let basechain = shard_id.shard().to_string();
+    assert_eq!(report.block_files_by_shard.len(), 3);
+    assert_eq!(report.block_files_by_shard[&masterchain], item(2, entries_size[0] + entries_size[1]));
+    assert_eq!(report.block_files_by_shard[&basechain], item(2, entries_size[2] + 200));
+    assert_eq!(report.block_files_by_shard[OTHER_BLOCK_FILES], item(1, 50));
+
+    assert_eq!(report.archive_packages_by_age, vec![
+        AgeBucketUsage { max_age_days: Some(1), usage: item(1, 4) },
+        AgeBucketUsage { max_age_days: Some(7), usage: item(0, 0) },
+        AgeBucketUsage { max_age_days: Some(30), usage: item(1, old_size) },
+        AgeBucketUsage { max_age_days: None, usage: item(0, 0) },
+    ]);
+    assert_eq!(report.unapplied_files, item(2, 250));
+
+    assert_eq!(report.persistent_states, vec![
+        SnapshotUsage { mc_seq_no: None, states: item(1, 512), deltas: item(0, 0) },
+        SnapshotUsage { mc_seq_no: Some(10), states: item(1, 1024), deltas: item(0, 0) },
+        SnapshotUsage { mc_seq_no: Some(20), states: item(1, 2048), deltas: item(1, 300) },
+    ]);
+
+    assert!(report.shard_state_cells.is_some());
+    assert!(report.column_families.contains_key("block_handle_db"));
+    assert!(report.column_families.contains_key(storage::db::rocksdb::NODE_STATE_DB_NAME));
+    assert!(report.column_families.contains_key("remp_messages_db"));
+    assert!(db.db.cf_usage("absent_db")?.is_none());
+    let cfs_bytes = report.column_families.values().map(|cf| cf.bytes).sum::<u64>()
+        + report.shard_state_cells.as_ref().unwrap().bytes;
+    assert_eq!(report.total_bytes, 4 + old_size + 250 + 512 + 1024 + 2048 + 300 + cfs_bytes);
+
+    let json = serde_json::to_value(&report)?;
+    assert_eq!(json["unapplied_files"]["bytes"], 250);
+    assert_eq!(json["archive_packages_by_age"][2]["count"], 1);
+
+    stop_db(&db).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_block_provenance() -> Result<()> {
+    const TEST_NAME: &str = "test_block_provenance";
+    clean_up(true, TEST_NAME).await;
+    let db = create_db(TEST_NAME).await?;
+    let proof = prepare_block_proof()?.extra;
+    let id = proof.id().clone();
+    let expected = proof.provenance()?;
+
+    db.store_block_proof(&id, None, &proof, None).await?;
+    assert_eq!(db.load_block_provenance(&id).await?, Some(expected.clone()));
+
+    // Block stored before provenance was recorded
+    db.block_provenance_db.delete(id.root_hash())?;
+    assert_eq!(db.load_block_provenance(&id).await?, Some(expected));
+    assert!(db.block_provenance_db.try_get(id.root_hash())?.is_some());
+
+    // Record is dropped together with the handle
+    db.drop_block_handles(vec![id.clone()])?;
+    assert!(db.block_provenance_db.try_get(id.root_hash())?.is_none());
+    assert!(db.load_block_provenance(&id).await?.is_none());
+
+    stop_db(&db).await;
+    drop(db);
+    clean_up(false, TEST_NAME).await;
+    Ok(())
+}
@@ -0,0 +1,149 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::Ed25519KeyOption;
+
+// Few iterations keep the tests fast, the format is the same
+const TEST_ITERATIONS: u32 = 1000;
+
+#[test]
+fn test_keystore_container_roundtrip() {
+    let data = b"{\"key\": \"secret\"}";
+    let container = seal_with_iterations(data, b"passphrase", TEST_ITERATIONS).unwrap();
+    assert!(is_keystore_container(&container));
+    assert_eq!(container.len(), HEADER_LEN + data.len() + TAG_LEN);
+    // Data is not stored as is
+    assert!(!container.windows(6).any(|window| window == b"secret"));
+    assert_eq!(&*open_container(&container, b"passphrase").unwrap(), data);
+
+    // Salt and nonce are random
+    let other = seal_with_iterations(data, b"passphrase", TEST_ITERATIONS).unwrap();
+    assert_ne!(container, other);
+
+    let empty = seal_with_iterations(b"", b"passphrase", TEST_ITERATIONS).unwrap();
+    assert!(open_container(&empty, b"passphrase").unwrap().is_empty());
+}
+
+#[test]
+fn test_keystore_container_refused() {
+    let container = seal_with_iterations(b"keys", b"passphrase", TEST_ITERATIONS).unwrap();
+    let err = open_container(&container, b"wrong").unwrap_err();
+    assert!(err.to_string().contains("wrong passphrase"));
+
+    // Any changed byte of header, ciphertext or tag breaks authentication
+    for pos in [5, 10, HEADER_LEN - 1, HEADER_LEN, container.len() - 1] {
+        let mut changed = container.clone();
+        changed[pos] ^= 1;
+        assert!(open_container(&changed, b"passphrase").is_err(), "byte {}", pos);
+    }
+    assert!(open_container(&container[..HEADER_LEN + TAG_LEN - 1], b"passphrase").is_err());
+    assert!(open_container(b"{\"plain\": \"json\"}", b"passphrase").is_err());
+
+    // Forged iterations
+    let mut changed = container.clone();
+    changed[6..10].copy_from_slice(&(KDF_MAX_ITERATIONS + 1).to_le_bytes());
+    assert!(open_container(&changed, b"passphrase").unwrap_err().to_string().contains("iterations"));
+    changed[6..10].copy_from_slice(&0u32.to_le_bytes());
+    assert!(open_container(&changed, b"passphrase").is_err());
+}
+
+#[test]
+fn test_secret_bytes_zeroize() {
+    let mut data = vec![0xAAu8; 64];
+    zeroize(&mut data);
+    assert!(data.iter().all(|byte| *byte == 0));
+
+    let mut secret = SecretBytes::new(b"secret\r\n".to_vec());
+    let ptr = secret.0.as_ptr();
+    secret.truncate(6);
+    assert_eq!(&*secret, b"secret");
+    // Tail is zeroized in place, the buffer is still allocated
+    let tail = unsafe { std::slice::from_raw_parts(ptr.add(6), 2) };
+    assert_eq!(tail, [0, 0]);
+    secret.truncate(10);
+    assert_eq!(secret.len(), 6);
+
+    assert_eq!(format!("{:?}", secret), "SecretBytes(<6 bytes>)");
+    assert_eq!(&*trim_line_end(SecretBytes::new(b"pass\n".to_vec())), b"pass");
+    assert!(trim_line_end(SecretBytes::new(b"\r\n".to_vec())).is_empty());
+}
+
+#[test]
+fn test_keystore_config_json() {
+    let config: KeystoreConfig = serde_json::from_str(
+        r#"{ "file": "keys.enc", "passphrase": { "env": "KEYSTORE_PASS" } }"#
+    ).unwrap();
+    assert_eq!(config.passphrase, PassphraseSource::Env("KEYSTORE_PASS".to_string()));
+    let source: PassphraseSource = serde_json::from_str(r#"{ "fd": 3 }"#).unwrap();
+    assert_eq!(source, PassphraseSource::Fd(3));
+    let source: PassphraseSource = serde_json::from_str(r#""prompt""#).unwrap();
+    assert_eq!(source, PassphraseSource::Prompt);
+}
+
+#[test]
+fn test_read_passphrase_env() {
+    let name = "EVER_NODE_TEST_KEYSTORE_PASSPHRASE";
+    std::env::remove_var(name);
+    let err = read_passphrase(&PassphraseSource::Env(name.to_string())).unwrap_err();
+    assert!(err.to_string().contains("missing"));
+    std::env::set_var(name, "");
+    assert!(read_passphrase(&PassphraseSource::Env(name.to_string())).is_err());
+    std::env::set_var(name, "secret");
+    assert_eq!(&*read_passphrase(&PassphraseSource::Env(name.to_string())).unwrap(), b"secret");
+    std::env::remove_var(name);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_read_passphrase_fd() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let written = unsafe { libc::write(fds[1], b"secret\n".as_ptr() as *const libc::c_void, 7) };
+    assert_eq!(written, 7);
+    unsafe { libc::close(fds[1]) };
+    assert_eq!(&*read_passphrase(&PassphraseSource::Fd(fds[0])).unwrap(), b"secret");
+}
+
+#[test]
+fn test_keystore_key_ring_roundtrip() {
+    let directory = "target/test_keystore";
+    std::fs::create_dir_all(directory).unwrap();
+    let keystore = KeystoreConfig {
+        file: "keys.enc".to_string(),
+        passphrase: PassphraseSource::Prompt,
+    };
+    std::fs::remove_file(Path::new(directory).join(&keystore.file)).ok();
+    assert!(load_key_ring(directory, &keystore, b"passphrase").unwrap().is_none());
+
+    let mut key_ring = HashMap::new();
+    for _ in 0..3 {
+        let (private, public) = Ed25519KeyOption::generate_with_json().unwrap();
+        key_ring.insert(ever_block::base64_encode(public.id().data()), private);
+    }
+    save_key_ring(directory, &keystore, b"passphrase", &key_ring).unwrap();
+    let data = std::fs::read(Path::new(directory).join(&keystore.file)).unwrap();
+    assert!(is_keystore_container(&data));
+
+    let loaded = load_key_ring(directory, &keystore, b"passphrase").unwrap().unwrap();
+    assert_eq!(loaded.len(), 3);
+    for (id, key) in key_ring.iter() {
+        assert_eq!(serde_json::to_value(&loaded[id]).unwrap(), serde_json::to_value(key).unwrap());
+    }
+    let err = load_key_ring(directory, &keystore, b"wrong").unwrap_err();
+    assert!(err.to_string().contains("wrong passphrase"));
+
+    // Plain JSON keystore is still loaded
+    std::fs::write(Path::new(directory).join(&keystore.file), serde_json::to_vec(&key_ring).unwrap()).unwrap();
+    assert_eq!(load_key_ring(directory, &keystore, b"any").unwrap().unwrap().len(), 3);
+}
@@ -0,0 +1,84 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+async fn counting_pass(counters: Arc<GcCounters>, resume: oneshot::Receiver<()>) -> Result<()> {
+    counters.add_scanned(3);
+    counters.add_removed(100);
+    resume.await.ok();
+    counters.add_removed(50);
+    Ok(())
+}
+
+async fn empty_pass(_counters: Arc<GcCounters>) -> Result<()> {
+    Ok(())
+}
+
+async fn failing_pass(_counters: Arc<GcCounters>) -> Result<()> {
+    fail!("broken")
+}
+
+async fn unexpected_pass(_counters: Arc<GcCounters>) -> Result<()> {
+    panic!("second pass is started")
+}
+
+async fn wait_finished(gc: &ManualGc, ticket: &GcTicket) -> GcProgress {
+    loop {
+        let progress = gc.status(ticket).unwrap();
+        if progress.finished {
+            return progress
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_manual_gc_single_pass() {
+    let gc = ManualGc::new();
+    let (resume, receiver) = oneshot::channel();
+    let ticket = gc.trigger(GcKind::Archives, |counters| counting_pass(counters, receiver));
+
+    // Second trigger returns in-flight ticket and doesn't run the pass
+    assert_eq!(gc.trigger(GcKind::Archives, unexpected_pass), ticket);
+
+    // Other kind runs independently
+    let other = gc.trigger(GcKind::BlockHandles, failing_pass);
+    assert_ne!(other.id, ticket.id);
+    let progress = wait_finished(&gc, &other).await;
+    assert!(progress.error.unwrap().starts_with("broken"));
+
+    resume.send(()).unwrap();
+    let progress = wait_finished(&gc, &ticket).await;
+    assert_eq!(
+        progress,
+        GcProgress { scanned: 3, removed: 2, bytes_freed: 150, finished: true, error: None }
+    );
+
+    // Finished pass is replaced by the new one
+    let next = gc.trigger(GcKind::Archives, empty_pass);
+    assert_ne!(next, ticket);
+    assert!(gc.status(&ticket).is_err());
+    wait_finished(&gc, &next).await;
+    assert!(gc.status(&GcTicket { kind: GcKind::ShardStates, id: next.id }).is_err());
+}
+
+#[test]
+fn test_gc_kind_names() {
+    for kind in [GcKind::ShardStates, GcKind::Archives, GcKind::BlockHandles] {
+        assert_eq!(GcKind::from_name(&kind.to_string()).unwrap(), kind);
+    }
+    assert!(GcKind::from_name("cells").is_err());
+}
@@ -0,0 +1,330 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::{
+    block::BlockStuff, collator_test_bundle::create_engine_allocated,
+    internal_db::{InternalDbConfig, restore::set_graceful_termination},
+};
+#[cfg(feature = "telemetry")]
+use crate::collator_test_bundle::create_engine_telemetry;
+use ever_block::{
+    Block, BlockInfo, IhrPendingInfo, MerkleProof, OutMsgQueue, ProcessedInfo, ProcessedInfoKey,
+    ProcessedUpto,
+};
+use std::collections::HashMap;
+
+const DB_PATH: &str = "target/test/test_prune_queues_for";
+const REPLAY_DB_PATH: &str = "target/test/test_mesh_queue_updates_replay";
+
+async fn open_db(db_directory: &str) -> Result<InternalDb> {
+    InternalDb::with_update(
+        InternalDbConfig {
+            db_directory: db_directory.to_string(),
+            ..Default::default()
+        },
+        false,
+        false,
+        false,
+        &|| Ok(()),
+        None,
+        #[cfg(feature = "telemetry")]
+        create_engine_telemetry(),
+        create_engine_allocated(),
+    ).await
+}
+
+fn queue_update(target_wc: i32, seq_no: u32) -> Result<BlockStuff> {
+    let mut info = BlockInfo::default();
+    info.set_shard(ShardIdent::with_workchain_id(0)?);
+    info.set_seq_no(seq_no)?;
+    let mut block = Block::default();
+    block.write_info(&info)?;
+    let block = BlockStuff::from_block(block)?;
+    let data = MerkleProof::create(block.root_cell(), |_| true)?.write_to_bytes()?;
+    BlockStuff::deserialize_queue_update(block.id().clone(), target_wc, false, data)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_prune_queues_for() {
+    std::fs::remove_dir_all(DB_PATH).ok();
+    let r = test_prune_queues_for_impl().await;
+    std::fs::remove_dir_all(DB_PATH).ok();
+    r.unwrap();
+}
+
+async fn test_prune_queues_for_impl() -> Result<()> {
+    let db = open_db(DB_PATH).await?;
+    let keeper = MeshQueuesKeeper::new();
+    let mc_block_id = BlockIdExt::default();
+    let shard = ShardIdent::with_workchain_id(0)?;
+
+    // Two tracked workchains with three queue updates each
+    let mut handles = HashMap::new();
+    let mut sizes = HashMap::new();
+    for wc in [1, 2] {
+        for seq_no in 1..=3 {
+            let update = queue_update(wc, wc as u32 * 10 + seq_no)?;
+            let handle = db.store_block_data(&update, None).await?.to_any();
+            assert_eq!(handle.is_queue_update_for(), Some(wc));
+            *sizes.entry(wc).or_insert(0) += update.data().len() as u64;
+            handles.entry(wc).or_insert_with(Vec::new).push(handle);
+        }
+        keeper.store_mesh_queue(wc, &mc_block_id, &shard, Arc::new(OutMsgQueueInfo::default()))?;
+    }
+    db.flush_block_handles().await?;
+
+    let stats = keeper.prune_queues_for(1, &db).await?;
+    assert_eq!(stats, PruneStats { handles: 3, bytes: sizes[&1] });
+    db.flush_block_handles().await?;
+
+    // Pruned queue is reported as untracked, reads of its data fail cleanly
+    let err = keeper.load_mesh_queue(1, &mc_block_id, &shard).unwrap_err();
+    assert!(err.to_string().contains("queue no longer tracked"));
+    for handle in &handles[&1] {
+        assert!(db.load_block_handle(handle.id())?.is_none());
+        assert!(db.load_block_data_raw(handle).await.is_err());
+    }
+
+    // The other workchain is untouched
+    keeper.load_mesh_queue(2, &mc_block_id, &shard)?;
+    for handle in &handles[&2] {
+        assert!(db.load_block_handle(handle.id())?.is_some());
+        db.load_block_data(handle).await?;
+    }
+
+    // Nothing is left to prune
+    assert_eq!(keeper.prune_queues_for(1, &db).await?, PruneStats::default());
+
+    // Workchain is tracked again after a new queue is stored
+    keeper.store_mesh_queue(1, &mc_block_id, &shard, Arc::new(OutMsgQueueInfo::default()))?;
+    keeper.load_mesh_queue(1, &mc_block_id, &shard)?;
+
+    db.stop_states_db().await;
+    set_graceful_termination(DB_PATH);
+    Ok(())
+}
+
+const NW_ID: i32 = 5;
+const OTHER_NW_ID: i32 = 6;
+
+fn mesh_block_id(seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(
+        ShardIdent::masterchain(),
+        seq_no,
+        UInt256::from([seq_no as u8; 32]),
+        UInt256::from([(seq_no as u8).wrapping_add(1); 32])
+    )
+}
+
+// Queue of mesh block with given seqno has processed up to info for this count of blocks
+const QUEUE_SIZES: [u32; 5] = [0, 1, 1, 3, 4];
+
+fn mesh_queue(seq_no: u32) -> Result<OutMsgQueueInfo> {
+    let mut proc_info = ProcessedInfo::default();
+    for seqno in 1..=QUEUE_SIZES[seq_no as usize] {
+        let key = ProcessedInfoKey::with_params(0x8000_0000_0000_0000, seqno);
+        let hash = UInt256::from([seqno as u8; 32]);
+        let value = ProcessedUpto::with_params(seqno as u64 * 1000, hash, None);
+        proc_info.set(&key, &value)?;
+    }
+    Ok(OutMsgQueueInfo::with_params(OutMsgQueue::default(), proc_info, IhrPendingInfo::default()))
+}
+
+fn mesh_queue_update(seq_no: u32) -> Result<MeshQueueUpdate> {
+    let old_root = mesh_queue(seq_no - 1)?.serialize()?;
+    let new_root = mesh_queue(seq_no)?.serialize()?;
+    let update = if old_root.repr_hash() != new_root.repr_hash() {
+        Some(MerkleUpdate::create(&old_root, &new_root)?)
+    } else {
+        None
+    };
+    Ok(MeshQueueUpdate {
+        prev_id: mesh_block_id(seq_no - 1),
+        id: mesh_block_id(seq_no),
+        old_hash: old_root.repr_hash(),
+        new_hash: new_root.repr_hash(),
+        update,
+    })
+}
+
+fn apply_update(
+    keeper: &MeshQueuesKeeper,
+    nw_id: i32,
+    seq_no: u32,
+    db: &InternalDb
+) -> Result<MeshQueueUpdateResult> {
+    let shard = ShardIdent::masterchain();
+    keeper.apply_mesh_queue_update(nw_id, &shard, mesh_queue_update(seq_no)?, db)
+}
+
+fn check_mesh_queue(keeper: &MeshQueuesKeeper, nw_id: i32, seq_no: u32) -> Result<()> {
+    let shard = ShardIdent::masterchain();
+    let queue = keeper.load_mesh_queue(nw_id, &mesh_block_id(seq_no), &shard)?;
+    assert_eq!(queue.serialize()?.repr_hash(), mesh_queue(seq_no)?.serialize()?.repr_hash());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mesh_queue_updates_replay() {
+    std::fs::remove_dir_all(REPLAY_DB_PATH).ok();
+    let r = test_mesh_queue_updates_replay_impl().await;
+    std::fs::remove_dir_all(REPLAY_DB_PATH).ok();
+    r.unwrap();
+}
+
+async fn test_mesh_queue_updates_replay_impl() -> Result<()> {
+    let shard = ShardIdent::masterchain();
+    {
+        let db = open_db(REPLAY_DB_PATH).await?;
+        let keeper = MeshQueuesKeeper::new();
+        keeper.store_mesh_queue(NW_ID, &mesh_block_id(0), &shard, Arc::new(mesh_queue(0)?))?;
+        for seq_no in 1..=2 {
+            let result = apply_update(&keeper, NW_ID, seq_no, &db)?;
+            assert_eq!(result, MeshQueueUpdateResult::Applied(1));
+        }
+        check_mesh_queue(&keeper, NW_ID, 2)?;
+        // Replay is not applied twice
+        let result = apply_update(&keeper, NW_ID, 1, &db)?;
+        assert_eq!(result, MeshQueueUpdateResult::Replayed);
+
+        // Out-of-order updates are buffered until the gap is filled
+        keeper.store_mesh_queue(OTHER_NW_ID, &mesh_block_id(0), &shard, Arc::new(mesh_queue(0)?))?;
+        for seq_no in [4, 2, 3] {
+            let result = apply_update(&keeper, OTHER_NW_ID, seq_no, &db)?;
+            assert_eq!(result, MeshQueueUpdateResult::Buffered);
+        }
+        let result = apply_update(&keeper, OTHER_NW_ID, 1, &db)?;
+        assert_eq!(result, MeshQueueUpdateResult::Applied(4));
+        for seq_no in 1..=4 {
+            check_mesh_queue(&keeper, OTHER_NW_ID, seq_no)?;
+        }
+
+        db.flush_block_handles().await?;
+        db.stop_states_db().await;
+        set_graceful_termination(REPLAY_DB_PATH);
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    {
+        // Restarted node replays all the updates
+        let db = open_db(REPLAY_DB_PATH).await?;
+        let keeper = MeshQueuesKeeper::new();
+        let result = apply_update(&keeper, NW_ID, 1, &db)?;
+        assert_eq!(result, MeshQueueUpdateResult::Replayed);
+        // Queue is restored from mesh kit
+        keeper.store_mesh_queue(NW_ID, &mesh_block_id(2), &shard, Arc::new(mesh_queue(2)?))?;
+        let results = (1..=4)
+            .map(|seq_no| apply_update(&keeper, NW_ID, seq_no, &db))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(results, vec![
+            MeshQueueUpdateResult::Replayed,
+            MeshQueueUpdateResult::Replayed,
+            MeshQueueUpdateResult::Applied(1),
+            MeshQueueUpdateResult::Applied(1),
+        ]);
+        // Contents are the same as of the single application
+        for seq_no in 2..=4 {
+            check_mesh_queue(&keeper, NW_ID, seq_no)?;
+        }
+
+        // Watermarks of dropped network are removed
+        keeper.drop_network(NW_ID, &db)?;
+        assert!(db.load_mesh_queue_watermark(NW_ID, &shard)?.is_none());
+        let watermark = db.load_mesh_queue_watermark(OTHER_NW_ID, &shard)?;
+        assert_eq!(watermark.map(|id| id.seq_no()), Some(4));
+
+        db.stop_states_db().await;
+        set_graceful_termination(REPLAY_DB_PATH);
+    }
+    Ok(())
+}
+
+fn lag_of(tracker: &QueueLagTracker, src_wc: i32, dst_wc: i32) -> QueueLag {
+    tracker.lag_table().into_iter().find(|lag| lag.src_wc == src_wc && lag.dst_wc == dst_wc)
+        .unwrap_or_else(|| panic!("no lag for {} -> {}", src_wc, dst_wc))
+}
+
+#[test]
+fn test_queue_lag_behind_and_catch_up() {
+    let tracker = QueueLagTracker::default();
+    tracker.set_warning_threshold(3);
+    assert!(tracker.lag_table().is_empty());
+    assert!(!tracker.is_tracked(1));
+
+    // Destination doesn't consume while updates come
+    for mc_seq_no in 10..=15 {
+        tracker.queue_update_applied(0, 1, mc_seq_no, false);
+    }
+    tracker.queue_update_applied(2, 1, 12, false);
+    assert!(tracker.is_tracked(1));
+    assert_eq!(
+        lag_of(&tracker, 0, 1),
+        QueueLag { src_wc: 0, dst_wc: 1, created: 15, consumed: 0, lag: 6 }
+    );
+    assert_eq!(lag_of(&tracker, 2, 1).lag, 1);
+
+    // Processed up to info covers all the sources of destination
+    tracker.processed_upto(1, 12);
+    assert_eq!(lag_of(&tracker, 0, 1).lag, 3);
+    assert_eq!(lag_of(&tracker, 2, 1).lag, 0);
+    // Older info changes nothing
+    tracker.processed_upto(1, 11);
+    assert_eq!(lag_of(&tracker, 0, 1).consumed, 12);
+
+    tracker.processed_upto(1, 15);
+    assert_eq!(
+        lag_of(&tracker, 0, 1),
+        QueueLag { src_wc: 0, dst_wc: 1, created: 15, consumed: 15, lag: 0 }
+    );
+
+    // Falls behind again and catches up
+    tracker.queue_update_applied(0, 1, 16, false);
+    tracker.queue_update_applied(0, 1, 17, false);
+    assert_eq!(lag_of(&tracker, 0, 1).lag, 2);
+    tracker.processed_upto(1, 17);
+    assert_eq!(lag_of(&tracker, 0, 1).lag, 0);
+
+    // Update consumed before it is applied here
+    tracker.processed_upto(1, 20);
+    tracker.queue_update_applied(0, 1, 19, false);
+    assert_eq!(lag_of(&tracker, 0, 1).lag, 0);
+
+    tracker.remove_workchain(1);
+    assert!(tracker.lag_table().is_empty());
+}
+
+#[test]
+fn test_queue_lag_empty_updates() {
+    let tracker = QueueLagTracker::default();
+    tracker.processed_upto(1, 10);
+
+    // Empty updates have nothing to consume
+    for mc_seq_no in 11..=20 {
+        tracker.queue_update_applied(0, 1, mc_seq_no, true);
+    }
+    assert_eq!(
+        lag_of(&tracker, 0, 1),
+        QueueLag { src_wc: 0, dst_wc: 1, created: 20, consumed: 10, lag: 0 }
+    );
+
+    // Non-empty update after empty ones lags by itself only
+    tracker.queue_update_applied(0, 1, 21, false);
+    assert_eq!(lag_of(&tracker, 0, 1).lag, 1);
+    // Empty updates after non-empty one are not consumed until it is
+    for mc_seq_no in 22..=25 {
+        tracker.queue_update_applied(0, 1, mc_seq_no, true);
+    }
+    assert_eq!(lag_of(&tracker, 0, 1).lag, 5);
+    tracker.processed_upto(1, 21);
+    assert_eq!(lag_of(&tracker, 0, 1).lag, 0);
+}
@@ -0,0 +1,111 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::ShardIdent;
+
+fn block_id(shard: ShardIdent, seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(
+        shard, seq_no, UInt256::from([seq_no as u8; 32]), UInt256::from([seq_no as u8 + 1; 32])
+    )
+}
+
+fn shards() -> [ShardIdent; 3] {
+    [
+        ShardIdent::with_tagged_prefix(0, 0x4000_0000_0000_0000).unwrap(),
+        ShardIdent::with_tagged_prefix(0, 0xc000_0000_0000_0000).unwrap(),
+        ShardIdent::with_tagged_prefix(1, 0x8000_0000_0000_0000).unwrap(),
+    ]
+}
+
+fn snapshot(mc_seq_no: u32, top_blocks: &[BlockIdExt], pending: &[BlockIdExt]) -> NetworkSnapshot {
+    NetworkSnapshot::new(
+        &block_id(ShardIdent::masterchain(), mc_seq_no),
+        32,
+        0x2e,
+        &UInt256::from([5; 32]),
+        top_blocks,
+        pending,
+        1_700_000_000
+    )
+}
+
+#[test]
+fn test_network_snapshot_deterministic() {
+    let [left, right, other] = shards();
+    let top_blocks = [block_id(right.clone(), 11), block_id(other.clone(), 7), block_id(left.clone(), 10)];
+    let pending = [block_id(left.clone(), 12), block_id(left.clone(), 13), block_id(other.clone(), 6)];
+    let data = snapshot(100, &top_blocks, &pending).serialize().unwrap();
+
+    // Order of the input doesn't matter
+    let mut shuffled = top_blocks.clone();
+    shuffled.reverse();
+    let mut pending_shuffled = pending.clone();
+    pending_shuffled.rotate_left(1);
+    assert_eq!(snapshot(100, &shuffled, &pending_shuffled).serialize().unwrap(), data);
+
+    let decoded = NetworkSnapshot::deserialize(&data).unwrap();
+    assert_eq!(decoded.serialize().unwrap(), data);
+    assert_eq!(decoded.version, NETWORK_SNAPSHOT_VERSION);
+    assert_eq!(decoded.mc_seq_no, 100);
+    assert_eq!(decoded.created_at, 1_700_000_000);
+    assert_eq!(decoded.validator_set_hash, format!("{:x}", UInt256::from([5; 32])));
+    let tops = decoded.top_blocks.iter()
+        .map(|top| (top.shard.clone(), top.seq_no, top.pending_seq_no))
+        .collect::<Vec<_>>();
+    // Pending block older than the committed one is not reported
+    assert_eq!(tops, vec![
+        (left.to_string(), 10, Some(13)),
+        (right.to_string(), 11, None),
+        (other.to_string(), 7, None),
+    ]);
+}
+
+#[test]
+fn test_network_snapshot_signature() {
+    let key = Ed25519KeyOption::generate().unwrap();
+    let [left, ..] = shards();
+    let signed = SignedSnapshot::sign(&snapshot(100, &[block_id(left, 10)], &[]), &key).unwrap();
+    assert_eq!(signed.public_key, key.pub_key().unwrap());
+    verify_snapshot(&signed.data, &signed.signature, &signed.public_key).unwrap();
+
+    let mut data = signed.data.clone();
+    data[1] ^= 1;
+    assert!(verify_snapshot(&data, &signed.signature, &signed.public_key).is_err());
+    let other_key = Ed25519KeyOption::generate().unwrap();
+    assert!(verify_snapshot(&signed.data, &signed.signature, other_key.pub_key().unwrap()).is_err());
+    assert!(verify_snapshot(&signed.data, &signed.signature, &[1; 16]).is_err());
+}
+
+#[test]
+fn test_network_snapshot_cache() {
+    let key = Ed25519KeyOption::generate().unwrap();
+    let [left, ..] = shards();
+    let signed = |mc_seq_no| {
+        SignedSnapshot::sign(&snapshot(mc_seq_no, &[block_id(left.clone(), 10)], &[]), &key).unwrap()
+    };
+    let cache = NetworkSnapshotCache::default();
+    assert!(cache.get(100).is_none());
+    cache.put(100, signed(100));
+    assert_eq!(cache.get(100), Some(signed(100)));
+
+    // New mc block invalidates the snapshot
+    assert!(cache.get(101).is_none());
+    cache.put(101, signed(101));
+    assert_eq!(cache.get(101), Some(signed(101)));
+    assert!(cache.get(100).is_none());
+
+    // Snapshot built by a lagging request doesn't replace the newer one
+    cache.put(100, signed(100));
+    assert_eq!(cache.get(101), Some(signed(101)));
+}
@@ -0,0 +1,122 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::UInt256;
+use serde_json::json;
+
+fn block_id(shard: ShardIdent, seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(shard, seq_no, UInt256::from([seq_no as u8; 32]), UInt256::default())
+}
+
+#[test]
+fn test_node_status_empty() {
+    let status = NodeStatus::new(Engine::SYNC_STATUS_START_BOOT, 1000);
+    let value = serde_json::to_value(&status).unwrap();
+    // Absent subsystems are nulls, keys are never omitted
+    assert_eq!(value, json!({
+        "schema_version": 1,
+        "node_version": env!("CARGO_PKG_VERSION"),
+        "timestamp": 1000,
+        "sync_status": "start_boot",
+        "last_applied_mc_block": null,
+        "last_known_mc_seq_no": 0,
+        "mc_seq_no_lag": null,
+        "shard_client_mc_block": null,
+        "shards": null,
+        "pss_keeper_mc_block": null,
+        "archives_gc_mc_block": null,
+        "external_db_mc_block": null,
+        "validator": {
+            "validation_status": "",
+            "last_validation_ago_sec": {},
+            "last_collation_ago_sec": {}
+        },
+        "remp": null,
+        "storage": {
+            "pinned_states": null,
+            "pinned_states_pins": null,
+            "used_bytes": null,
+            "high_watermark_bytes": null,
+            "degraded_columns": []
+        },
+        "peers": {},
+        "block_broadcast_fanout": null,
+        "clock_drift_ms": null
+    }));
+}
+
+#[test]
+fn test_node_status_filled() {
+    let now = 1000;
+    let mut status = NodeStatus::new(Engine::SYNC_STATUS_FINISH_SYNC, now);
+    let mc_id = block_id(ShardIdent::masterchain(), 10);
+    status.last_applied_mc_block = Some(BlockStatus::new(&mc_id, Some(990), now));
+    status.last_known_mc_seq_no = 12;
+    status.mc_seq_no_lag = Some(2);
+    // Block handle is not found
+    status.shard_client_mc_block = Some(BlockStatus::new(&mc_id, None, now));
+    status.shards = Some(vec![BlockStatus::new(&block_id(ShardIdent::full(0), 20), Some(1010), now)]);
+    status.remp = Some(RempStatus { incoming_queue: Some(3), response_queue: None });
+    status.storage.pinned_states = Some(2);
+    status.storage.degraded_columns = vec!["mc_utime_db".to_string()];
+    status.peers.insert("overlay".to_string(), 5);
+    status.block_broadcast_fanout = Some(BroadcastFanoutStatus { suppressed_known: 7, ..Default::default() });
+    status.clock_drift_ms = Some(-1500);
+
+    let value = serde_json::to_value(&status).unwrap();
+    assert_eq!(value["sync_status"], json!("synchronization_finished"));
+    assert_eq!(value["last_applied_mc_block"], json!({
+        "shard": ShardIdent::masterchain().to_string(),
+        "seq_no": 10,
+        "root_hash": format!("{:x}", mc_id.root_hash()),
+        "gen_utime": 990,
+        "lag_sec": 10
+    }));
+    assert_eq!(value["mc_seq_no_lag"], json!(2));
+    assert_eq!(value["shard_client_mc_block"]["gen_utime"], json!(null));
+    assert_eq!(value["shard_client_mc_block"]["lag_sec"], json!(null));
+    // Block from the future has no lag
+    assert_eq!(value["shards"][0]["lag_sec"], json!(0));
+    assert_eq!(value["remp"], json!({ "incoming_queue": 3, "response_queue": null }));
+    assert_eq!(value["storage"]["pinned_states"], json!(2));
+    assert_eq!(value["storage"]["used_bytes"], json!(null));
+    assert_eq!(value["storage"]["degraded_columns"], json!(["mc_utime_db"]));
+    assert_eq!(value["peers"], json!({ "overlay": 5 }));
+    assert_eq!(value["block_broadcast_fanout"], json!({
+        "suppressed_known": 7, "suppressed_by_cap": 0, "full_fanouts": 0
+    }));
+    assert_eq!(value["clock_drift_ms"], json!(-1500));
+
+    let line = status.to_json_line().unwrap();
+    assert!(!line.contains('\n'));
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&line).unwrap(), value);
+}
+
+#[test]
+fn test_validator_status_ago() {
+    let times = lockfree::map::Map::new();
+    times.insert(ShardIdent::masterchain(), 900);
+    times.insert(ShardIdent::full(0), 0);
+    let ago = ValidatorStatus::ago_sec(&times, 1000);
+    assert_eq!(ago.get(&ShardIdent::masterchain().to_string()), Some(&Some(100)));
+    // Never validated
+    assert_eq!(ago.get(&ShardIdent::full(0).to_string()), Some(&None));
+}
+
+#[test]
+fn test_sync_status_name() {
+    assert_eq!(sync_status_name(Engine::SYNC_STATUS_SYNC_BLOCKS), "synchronization_by_blocks");
+    assert_eq!(sync_status_name(Engine::SYNC_STATUS_DB_BROKEN), "db_broken");
+    assert_eq!(sync_status_name(u32::MAX), "no_set_status");
+}
@@ -0,0 +1,152 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+
+use super::*;
+use crate::block::BlockStuff;
+use ever_block::{
+    BinTree, BlkMasterInfo, Block, BlockExtra, BlockInfo, Cell, ExtBlkRef, InRefValue,
+    McBlockExtra, Serializable, ShardDescr, ShardHashes, ShardIdent, UnixTime32
+};
+
+const PROOFS_DIR: &str = "src/tests/static/test_master_block_proof";
+
+fn read_mc_proof(seqno: u32) -> (BlockIdExt, Vec<u8>) {
+    let (block, proof) = if seqno == 3082181 {
+        ("key_block", "key_proof")
+    } else {
+        ("block", "proof")
+    };
+    let block = BlockStuff::read_block_from_file(&format!("{}/{}__{}", PROOFS_DIR, block, seqno)).unwrap();
+    let proof = std::fs::read(format!("{}/{}__{}", PROOFS_DIR, proof, seqno)).unwrap();
+    (block.id().clone(), proof)
+}
+
+fn tamper(data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    let middle = data.len() / 2;
+    data[middle] ^= 0x10;
+    data
+}
+
+#[test]
+fn test_verify_block_proof_chain() {
+    let (key_block, key_proof) = read_mc_proof(3082181);
+    let (block, proof) = read_mc_proof(3082185);
+    let (_, next_proof) = read_mc_proof(3082186);
+
+    let head = verify_block_proof_chain(&[key_proof.clone(), proof.clone()], &key_block).unwrap();
+    assert_eq!(head.id, block);
+    assert_eq!(head.mc_block, block);
+    assert_eq!(head.key_block, key_block);
+    assert!(head.gen_utime > 0);
+
+    // Trusted key block alone
+    let head = verify_block_proof_chain(&[key_proof.clone()], &key_block).unwrap();
+    assert_eq!(head.id, key_block);
+
+    // Other trusted block
+    verify_block_proof_chain(&[key_proof.clone(), proof.clone()], &block).unwrap_err();
+    verify_block_proof_chain(&[proof.clone()], &block).unwrap_err();
+    verify_block_proof_chain(&[], &key_block).unwrap_err();
+
+    // Non key block doesn't prove the next one
+    verify_block_proof_chain(&[key_proof.clone(), proof.clone(), next_proof], &key_block).unwrap_err();
+
+    // Block signed by validators of other key block
+    let other = std::fs::read("src/tests/static/test_master_block_proof_shuffle/proof__3236531").unwrap();
+    verify_block_proof_chain(&[key_proof.clone(), other], &key_block).unwrap_err();
+
+    // Tampered bytes
+    verify_block_proof_chain(&[key_proof, tamper(&proof)], &key_block).unwrap_err();
+}
+
+// Masterchain block which lists the top block of the whole workchain 0
+fn synthetic_proofs(listed_seqno: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut info = BlockInfo::default();
+    info.set_shard(ShardIdent::with_workchain_id(0)?);
+    info.set_seq_no(10)?;
+    info.set_gen_utime(UnixTime32::new(1_700_000_000));
+    info.write_master_ref(Some(&BlkMasterInfo {
+        master: ExtBlkRef { end_lt: 0, seq_no: 1, root_hash: UInt256::default(), file_hash: UInt256::default() }
+    }))?;
+    let mut block = Block::default();
+    block.write_info(&info)?;
+    let shard_block = BlockStuff::from_block(block)?;
+
+    let descr = ShardDescr {
+        seq_no: listed_seqno,
+        root_hash: shard_block.id().root_hash().clone(),
+        file_hash: shard_block.id().file_hash().clone(),
+        ..Default::default()
+    };
+    let mut shards = ShardHashes::default();
+    shards.set(&0, &InRefValue(BinTree::with_item(&descr)?))?;
+    let mut mc_extra = McBlockExtra::default();
+    *mc_extra.shards_mut() = shards;
+    let mut extra = BlockExtra::default();
+    extra.write_custom(Some(&mc_extra))?;
+    let mut info = BlockInfo::default();
+    info.set_shard(ShardIdent::masterchain());
+    info.set_seq_no(2)?;
+    info.set_gen_utime(UnixTime32::new(1_700_000_001));
+    let mut block = Block::default();
+    block.write_info(&info)?;
+    block.write_extra(&extra)?;
+    let mc_block = BlockStuff::from_block(block)?;
+
+    let proof = |block: &BlockStuff| -> Result<Vec<u8>> {
+        let proof = BlockProof {
+            proof_for: block.id().clone(),
+            root: MerkleProof::create(block.root_cell(), |_| true)?.serialize()?,
+            signatures: None,
+        };
+        Ok(BlockProofStuff::new(proof, !block.id().is_masterchain())?.data().to_vec())
+    };
+    Ok((proof(&mc_block)?, proof(&shard_block)?))
+}
+
+#[test]
+fn test_verify_shard_block_link() -> Result<()> {
+    let (mc_proof, shard_proof) = synthetic_proofs(10)?;
+    verify_shard_block_link(&mc_proof, &shard_proof)?;
+
+    // Proofs are swapped
+    verify_shard_block_link(&shard_proof, &mc_proof).unwrap_err();
+    // Masterchain block lists other block of the shard
+    let (other_mc_proof, _) = synthetic_proofs(11)?;
+    verify_shard_block_link(&other_mc_proof, &shard_proof).unwrap_err();
+    // Tampered bytes
+    verify_shard_block_link(&tamper(&mc_proof), &shard_proof).unwrap_err();
+    verify_shard_block_link(&mc_proof, &tamper(&shard_proof)).unwrap_err();
+
+    // Shard hashes are pruned in proofs made by validators
+    let (_, mc_proof) = read_mc_proof(3082190);
+    let shard_proof = std::fs::read("src/tests/static/test_shard_block_proof/proof_4377262")?;
+    verify_shard_block_link(&mc_proof, &shard_proof).unwrap_err();
+    Ok(())
+}
+
+#[test]
+fn test_verify_state_proof() -> Result<()> {
+    let data = std::fs::read("src/tests/static/zerostate.boc")?;
+    let root: Cell = BocReader::new().read_inmem(Arc::new(data))?.withdraw_single_root()?;
+    let root_hash = root.repr_hash();
+    let proof = MerkleProof::create(&root, |hash| hash == &root_hash)?.write_to_bytes()?;
+
+    verify_state_proof(&proof, &root_hash)?;
+    verify_state_proof(&proof, &UInt256::default()).unwrap_err();
+    verify_state_proof(&tamper(&proof), &root_hash).unwrap_err();
+    verify_state_proof(&proof[..proof.len() - 1], &root_hash).unwrap_err();
+    Ok(())
+}
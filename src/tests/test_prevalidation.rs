@@ -0,0 +1,143 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+fn source(i: u8) -> Arc<KeyId> {
+    KeyId::from_data([i; 32])
+}
+
+// Source 1 is a validator
+fn make_queue(high_lane_capacity: u32, low_lane_capacity: u32) -> PrevalidationQueue<u32> {
+    let queue = PrevalidationQueue::new(
+        ExtMessagesPrevalidationConfig { workers: 1, high_lane_capacity, low_lane_capacity }
+    );
+    queue.set_priority_sources([source(1)].into_iter().collect());
+    queue
+}
+
+fn next(queue: &PrevalidationQueue<u32>) -> Option<(u32, PrevalidationLane)> {
+    match queue.schedule() {
+        Scheduled::Task(task) => Some((task.task, task.lane)),
+        Scheduled::Idle | Scheduled::Stopped => None
+    }
+}
+
+#[test]
+fn test_prevalidation_lanes() {
+    let queue = make_queue(10, 10);
+    // Only REMP messages from validators have priority
+    assert!(queue.push(1, source(2), true));
+    assert!(queue.push(2, source(1), false));
+    assert!(queue.push(3, source(1), true));
+    assert_eq!(queue.stats(), PrevalidationStats { high_queued: 1, low_queued: 2, ..Default::default() });
+    assert_eq!(next(&queue), Some((3, PrevalidationLane::High)));
+    assert_eq!(next(&queue), Some((1, PrevalidationLane::Low)));
+    assert_eq!(next(&queue), Some((2, PrevalidationLane::Low)));
+    assert!(matches!(queue.schedule(), Scheduled::Idle));
+}
+
+#[test]
+fn test_prevalidation_high_lane_under_low_lane_flood() {
+    let queue = make_queue(100, 1000);
+    let mut pending = Vec::new();
+    for step in 0..10_000u32 {
+        // Garbage from many sources and a validator message from time to time
+        for i in 0..10 {
+            queue.push(1_000_000 + step * 10 + i, source(10 + (i % 5) as u8), false);
+        }
+        if step % 3 == 0 {
+            assert!(queue.push(step, source(1), true));
+            pending.push(step);
+        }
+        if let Some((task, PrevalidationLane::High)) = next(&queue) {
+            assert_eq!(pending.remove(0), task);
+        }
+        // Every high lane task is taken within the step it was queued in
+        assert!(pending.is_empty());
+    }
+    assert_eq!(queue.stats().high_dropped, 0);
+    assert_eq!(queue.stats().low_queued, 1000);
+}
+
+#[test]
+fn test_prevalidation_low_lane_not_starved() {
+    let queue = make_queue(1000, 10);
+    for i in 0..100 {
+        queue.push(i, source(1), true);
+    }
+    queue.push(1000, source(2), false);
+    let steps = (0..=HIGH_LANE_BURST)
+        .position(|_| next(&queue) == Some((1000, PrevalidationLane::Low)));
+    assert_eq!(steps, Some(HIGH_LANE_BURST as usize));
+}
+
+#[test]
+fn test_prevalidation_drops() {
+    let queue = make_queue(2, 3);
+    // Low lane drops its oldest tasks
+    for i in 0..5 {
+        assert!(queue.push(i, source(2 + i as u8 % 2), false));
+    }
+    // High lane drops new tasks
+    for i in 10..13 {
+        assert_eq!(queue.push(i, source(1), true), i < 12);
+    }
+    let stats = queue.stats();
+    assert_eq!((stats.high_dropped, stats.low_dropped), (1, 2));
+    let mut drops = queue.source_drops();
+    drops.sort_by_key(|(lane, source, _)| (*lane, source.to_string()));
+    let mut expected = vec![
+        (PrevalidationLane::High, source(1), 1),
+        (PrevalidationLane::Low, source(2), 1),
+        (PrevalidationLane::Low, source(3), 1),
+    ];
+    expected.sort_by_key(|(lane, source, _)| (*lane, source.to_string()));
+    assert_eq!(drops, expected);
+
+    let low = (0..5).filter_map(|_| next(&queue)).collect::<Vec<_>>();
+    assert_eq!(low, [
+        (10, PrevalidationLane::High), (11, PrevalidationLane::High),
+        (2, PrevalidationLane::Low), (3, PrevalidationLane::Low), (4, PrevalidationLane::Low)
+    ]);
+}
+
+#[tokio::test]
+async fn test_prevalidation_stop_drains_high_lane() {
+    let queue = Arc::new(make_queue(10, 10));
+    for i in 0..3 {
+        queue.push(i, source(1), true);
+        queue.push(10 + i, source(2), false);
+    }
+    queue.stop();
+    // New tasks are dropped, low lane tasks are dropped, high lane tasks are processed
+    assert!(!queue.push(100, source(1), true));
+    let mut processed = Vec::new();
+    while let Some(task) = queue.next_task().await {
+        processed.push(task.task);
+    }
+    assert_eq!(processed, [0, 1, 2]);
+    assert_eq!(queue.stats(), PrevalidationStats { high_dropped: 1, low_dropped: 3, ..Default::default() });
+
+    // Waiting worker is woken by stop
+    let queue = Arc::new(make_queue(10, 10));
+    let worker = tokio::spawn({
+        let queue = queue.clone();
+        async move { queue.next_task().await.map(|task| task.task) }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    queue.push(5, source(1), true);
+    queue.stop();
+    assert_eq!(worker.await.unwrap(), Some(5));
+    assert!(queue.next_task().await.is_none());
+}
@@ -0,0 +1,96 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+fn test_config(global_messages_per_sec: u32, global_burst: u32) -> ExtMessagesRateLimitConfig {
+    ExtMessagesRateLimitConfig {
+        source_messages_per_sec: 10,
+        source_burst: 20,
+        global_messages_per_sec,
+        global_burst,
+        source_idle_timeout_sec: 60,
+    }
+}
+
+fn source(byte: u8) -> Arc<KeyId> {
+    Arc::new(KeyId::from_data([byte; 32]))
+}
+
+#[test]
+fn test_source_over_quota() {
+    let now = Instant::now();
+    let limiter = ExtMessagesRateLimiter::with_time(test_config(0, 0), now);
+    let (greedy, other) = (source(1), source(2));
+
+    for _ in 0..20 {
+        assert!(limiter.allow_at(&greedy, now));
+    }
+    for _ in 0..100 {
+        assert!(!limiter.allow_at(&greedy, now));
+    }
+
+    // other sources are not affected
+    for _ in 0..20 {
+        assert!(limiter.allow_at(&other, now));
+    }
+    assert!(!limiter.allow_at(&other, now));
+
+    // bucket is refilled according to the rate
+    let now = now + Duration::from_millis(500);
+    for _ in 0..5 {
+        assert!(limiter.allow_at(&greedy, now));
+    }
+    assert!(!limiter.allow_at(&greedy, now));
+}
+
+#[test]
+fn test_global_quota() {
+    let now = Instant::now();
+    let limiter = ExtMessagesRateLimiter::with_time(test_config(10, 30), now);
+
+    // messages over source quota don't spend global tokens
+    for _ in 0..20 {
+        assert!(limiter.allow_at(&source(0), now));
+    }
+    for _ in 0..10 {
+        assert!(!limiter.allow_at(&source(0), now));
+    }
+    for i in 1..=10 {
+        assert!(limiter.allow_at(&source(i), now));
+    }
+    assert!(!limiter.allow_at(&source(11), now));
+
+    let now = now + Duration::from_millis(100);
+    assert!(limiter.allow_at(&source(11), now));
+    assert!(!limiter.allow_at(&source(12), now));
+}
+
+#[test]
+fn test_idle_sources_cleanup() {
+    let now = Instant::now();
+    let limiter = ExtMessagesRateLimiter::with_time(test_config(0, 0), now);
+
+    for i in 0..10 {
+        assert!(limiter.allow_at(&source(i), now));
+    }
+    assert_eq!(limiter.sources_count(), 10);
+
+    let now = now + Duration::from_secs(30);
+    assert!(limiter.allow_at(&source(0), now));
+    assert_eq!(limiter.sources_count(), 10);
+
+    let now = now + Duration::from_secs(40);
+    assert!(limiter.allow_at(&source(1), now));
+    assert_eq!(limiter.sources_count(), 2);
+}
@@ -1,5 +1,8 @@
 use crate::{
-    block::BlockStuff, engine_traits::EngineOperations,
+    block::BlockStuff,
+    engine_traits::{
+        BlockAccess, BroadcastSupport, EngineOperations, RempSupport, StateAccess, ValidatorSupport
+    },
     full_node::remp_client::{RempClient}, shard_state::ShardStateStuff, 
     validator::validator_utils::get_adnl_id,
 };
@@ -595,6 +598,62 @@ impl TestRempClientEngine {
 
 #[async_trait::async_trait]
 impl EngineOperations for TestRempClientEngine {
+    async fn wait_next_applied_mc_block(
+        &self, 
+        prev_handle: &BlockHandle,
+        _timeout_ms: Option<u64>
+    ) -> Result<(Arc<BlockHandle>, BlockStuff)> {
+
+        tokio::time::sleep(Duration::from_millis(NEXT_BLOCK_TIMEOUT)).await;
+
+        let id = self.load_block_next1(prev_handle.id())?;
+        let handle = self.load_block_handle(&id)?.ok_or_else(|| error!("Can't load block handle {}", id))?;
+        let block = self.blocks.get(&id).ok_or_else(|| error!("Can't load block {}", id))?;
+        Ok((handle, block.clone()))
+    }
+
+    fn processed_workchain(&self) -> Option<i32> {
+        None
+    }
+
+    async fn check_sync(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn load_last_applied_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
+        Ok(Some(Arc::new(self.state.block_id().clone())))
+    }
+
+    async fn update_validators(
+        &self,
+        _to_resolve: Vec<CatchainNode>,
+        _to_delete: Vec<CatchainNode>
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn check_stop(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "external_db")]
+    async fn process_remp_msg_status_in_ext_db(
+        &self,
+        _id: &UInt256,
+        _status: &RempReceipt,
+        _signature: &[u8],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn remp_client_telemetry(&self) -> &RempClientTelemetry {
+        &self.telemetry
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockAccess for TestRempClientEngine {
     fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
         let handle = self.block_handle_storage.create_handle(
             id.clone(), 
@@ -616,24 +675,6 @@ impl EngineOperations for TestRempClientEngine {
         }
     }
 
-    async fn wait_next_applied_mc_block(
-        &self, 
-        prev_handle: &BlockHandle,
-        _timeout_ms: Option<u64>
-    ) -> Result<(Arc<BlockHandle>, BlockStuff)> {
-
-        tokio::time::sleep(Duration::from_millis(NEXT_BLOCK_TIMEOUT)).await;
-
-        let id = self.load_block_next1(prev_handle.id())?;
-        let handle = self.load_block_handle(&id)?.ok_or_else(|| error!("Can't load block handle {}", id))?;
-        let block = self.blocks.get(&id).ok_or_else(|| error!("Can't load block {}", id))?;
-        Ok((handle, block.clone()))
-    }
-
-    fn processed_workchain(&self) -> Option<i32> {
-        None
-    }
-
     fn load_block_next1(&self, id: &BlockIdExt) -> Result<BlockIdExt> {
         self.find_block(id.shard(), id.seq_no() + 1)
             .ok_or_else(|| error!("The is no next block for {}", id))
@@ -644,33 +685,22 @@ impl EngineOperations for TestRempClientEngine {
         //fail!("The is no next 2 block")
     }
 
-    async fn check_sync(&self) -> Result<bool> {
-        Ok(true)
+    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
+        Ok(self
+            .blocks
+            .get(handle.id())
+            .ok_or_else(|| error!("Can't load block {}", handle.id()))?
+            .clone()
+        )
     }
+}
 
+#[async_trait::async_trait]
+impl StateAccess for TestRempClientEngine {
     async fn load_last_applied_mc_state(&self) -> Result<Arc<ShardStateStuff>> {
         Ok(self.state.clone())
     }
 
-    fn send_remp_message(&self, _to: Arc<KeyId>, _message: &RempMessage) -> Result<()> {
-        self.sent_remp_messages.fetch_add(1, Ordering::Relaxed);
-        Ok(())
-    }
-
-    fn sign_remp_receipt(&self, _receipt: &RempReceipt) -> Result<Vec<u8>> {
-        const SIGNATURE_LEN: usize = 64;
-        self.signed_remp_messages.fetch_add(1, Ordering::Relaxed);
-        let mut signature = vec![0; SIGNATURE_LEN];
-        for i in 0..signature.len() {
-            signature[i] = rand::random::<u8>();
-        }                                                           
-        Ok(signature)
-    }
-
-    fn load_last_applied_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
-        Ok(Some(Arc::new(self.state.block_id().clone())))
-    }
-
     async fn load_state(&self, _block_id: &BlockIdExt) -> Result<Arc<ShardStateStuff>> {
         //if self.state.block_id() == block_id {
             Ok(self.state.clone())
@@ -687,44 +717,28 @@ impl EngineOperations for TestRempClientEngine {
     ) -> Result<Arc<ShardStateStuff>> {
         self.load_state(id).await
     }
+}
 
-    async fn update_validators(
-        &self,
-        _to_resolve: Vec<CatchainNode>,
-        _to_delete: Vec<CatchainNode>
-    ) -> Result<()> {
-        Ok(())
-    }
-
-    async fn load_block(&self, handle: &BlockHandle) -> Result<BlockStuff> {
-        Ok(self
-            .blocks
-            .get(handle.id())
-            .ok_or_else(|| error!("Can't load block {}", handle.id()))?
-            .clone()
-        )
-    }
-
-    fn check_stop(&self) -> bool {
-        false
-    }
-
-    #[cfg(feature = "external_db")]
-    async fn process_remp_msg_status_in_ext_db(
-        &self,
-        _id: &UInt256,
-        _status: &RempReceipt,
-        _signature: &[u8],
-    ) -> Result<()> {
+impl RempSupport for TestRempClientEngine {
+    fn send_remp_message(&self, _to: Arc<KeyId>, _message: &RempMessage) -> Result<()> {
+        self.sent_remp_messages.fetch_add(1, Ordering::Relaxed);
         Ok(())
     }
 
-    #[cfg(feature = "telemetry")]
-    fn remp_client_telemetry(&self) -> &RempClientTelemetry {
-        &self.telemetry
+    fn sign_remp_receipt(&self, _receipt: &RempReceipt) -> Result<Vec<u8>> {
+        const SIGNATURE_LEN: usize = 64;
+        self.signed_remp_messages.fetch_add(1, Ordering::Relaxed);
+        let mut signature = vec![0; SIGNATURE_LEN];
+        for i in 0..signature.len() {
+            signature[i] = rand::random::<u8>();
+        }                                                           
+        Ok(signature)
     }
 }
 
+impl BroadcastSupport for TestRempClientEngine {}
+impl ValidatorSupport for TestRempClientEngine {}
+
 #[tokio::test]
 async fn test_remp_client() -> Result<()> {
 
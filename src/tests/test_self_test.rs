@@ -0,0 +1,72 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+const DB_PATH: &str = "target/self_test";
+
+fn check(name: &str, status: SelfTestStatus) -> SelfTestCheck {
+    SelfTestCheck { name: name.to_string(), status, details: String::new(), time_ms: 0 }
+}
+
+#[tokio::test]
+async fn test_self_test_report() {
+    let mut report = SelfTestReport::default();
+    assert_eq!(report.status(), SelfTestStatus::Pass);
+    assert_eq!(report.exit_code(), 0);
+
+    report.add(check("a", SelfTestStatus::Pass));
+    report.add(check("b", SelfTestStatus::Warn));
+    assert_eq!(report.status(), SelfTestStatus::Warn);
+    assert_eq!(report.exit_code(), 0);
+
+    report.add(run_check("c", async { fail!("broken") }).await);
+    report.add(run_check("d", async { panic!("crashed") }).await);
+    assert_eq!(report.status(), SelfTestStatus::Fail);
+    assert_eq!(report.exit_code(), 1);
+    assert_eq!(report.count(SelfTestStatus::Pass), 1);
+    assert_eq!(report.count(SelfTestStatus::Warn), 1);
+    assert_eq!(report.count(SelfTestStatus::Fail), 2);
+    assert_eq!(report.checks()[2].details, "broken");
+    assert!(report.checks()[3].details.contains("crashed"));
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+    assert_eq!(json["status"], "Fail");
+    assert_eq!(json["failed"], 2);
+    assert_eq!(json["checks"].as_array().unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn test_self_test_storage() {
+    std::fs::create_dir_all(DB_PATH).unwrap();
+    let result = run_check("storage", check_storage(Path::new(DB_PATH))).await;
+    assert_ne!(result.status, SelfTestStatus::Fail, "{}", result.details);
+    // Temporary directory is removed
+    assert_eq!(std::fs::read_dir(DB_PATH).unwrap().count(), 0);
+    std::fs::remove_dir_all(DB_PATH).unwrap();
+}
+
+#[test]
+fn test_self_test_crypto() {
+    assert_eq!(check_crypto().unwrap().0, SelfTestStatus::Pass);
+}
+
+#[test]
+fn test_self_test_clock() {
+    let now = 1720000000;
+    assert_eq!(check_clock(now, &[]).unwrap().0, SelfTestStatus::Pass);
+    assert!(check_clock(1000, &[]).is_err());
+    assert_eq!(check_clock(now, &[now - 1, now, now + 2]).unwrap().0, SelfTestStatus::Pass);
+    assert_eq!(check_clock(now, &[now + 10, now + 20, now]).unwrap().0, SelfTestStatus::Warn);
+    assert!(check_clock(now, &[now - 100, now - 200, now - 300]).is_err());
+}
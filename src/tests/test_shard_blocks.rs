@@ -13,12 +13,15 @@
 
 use super::*;
 use crate::test_helper::gen_master_state;
+use crate::engine_traits::{
+    BlockAccess, BroadcastSupport, RempSupport, StateAccess, ValidatorSupport
+};
 use crate::collator_test_bundle::{create_block_handle_storage, create_engine_allocated};
 #[cfg(all(feature = "telemetry", not(feature = "fast_finality")))]
 use crate::collator_test_bundle::create_engine_telemetry;
 use std::{sync::{atomic::{AtomicU32, Ordering}, Arc}, collections::HashSet};
 use storage::{block_handle_db::{BlockHandle, BlockHandleStorage}, types::BlockMeta};
-use ever_block::{BlockIdExt, ShardIdent};
+use ever_block::{BlockIdExt, ShardDescr, ShardIdent};
 use ever_block::UInt256;
 
 struct TestEngine {
@@ -34,20 +37,6 @@ struct TestEngine {
 #[async_trait::async_trait]
 impl EngineOperations for TestEngine {
 
-    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
-        self.block_handle_storage.create_handle(
-            id.clone(),
-            BlockMeta::with_data(
-                0, 
-                self.last_applied_mc_block_utime.load(Ordering::Relaxed), 
-                0, 
-                id.seq_no(),
-                0
-            ),
-            None
-        )
-    }
-
     fn load_last_applied_mc_block_id(&self) -> Result<Option<Arc<BlockIdExt>>> {
         let ret = BlockIdExt {
             shard_id: ShardIdent::masterchain(),
@@ -78,9 +67,29 @@ impl EngineOperations for TestEngine {
     fn engine_allocated(&self) -> &Arc<EngineAlloc> {
         &self.engine_allocated
     }
+}
 
+impl BlockAccess for TestEngine {
+    fn load_block_handle(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        self.block_handle_storage.create_handle(
+            id.clone(),
+            BlockMeta::with_data(
+                0, 
+                self.last_applied_mc_block_utime.load(Ordering::Relaxed), 
+                0, 
+                id.seq_no(),
+                0
+            ),
+            None
+        )
+    }
 }
 
+impl StateAccess for TestEngine {}
+impl RempSupport for TestEngine {}
+impl BroadcastSupport for TestEngine {}
+impl ValidatorSupport for TestEngine {}
+
 fn build_id(shard: u64, seq_no: u32) -> BlockIdExt {
     BlockIdExt {
         shard_id: ShardIdent::with_tagged_prefix(0, shard).unwrap(),
@@ -225,3 +234,126 @@ fn make_shard_state() -> Arc<ShardStateStuff> {
     );
     ss
 }
+
+fn shard_records(shards: &[u64]) -> Vec<McShardRecord> {
+    shards.iter().map(|prefix| McShardRecord::from_shard_descr(
+        ShardIdent::with_tagged_prefix(0, *prefix).unwrap(),
+        ShardDescr::default()
+    )).collect()
+}
+
+fn shard(prefix: u64) -> ShardIdent {
+    ShardIdent::with_tagged_prefix(0, prefix).unwrap()
+}
+
+#[tokio::test]
+async fn test_shard_blocks_pool_split_merge() {
+
+    let engine = Arc::new(TestEngine {
+        last_applied_mc_block_seqno: AtomicU32::new(0),
+        last_applied_mc_block_utime: AtomicU32::new(0),
+        block_handle_storage: create_block_handle_storage(),
+        shard_blocks: std::sync::Mutex::new(HashSet::default()),
+        #[cfg(feature = "telemetry")]
+        engine_telemetry: create_engine_telemetry(),
+        engine_allocated: create_engine_allocated()
+    });
+    let (pool, receiver) = ShardBlocksPool::new(
+        HashMap::default(), 1, true,
+        #[cfg(feature = "telemetry")]
+        &engine.engine_telemetry,
+        &engine.engine_allocated
+    ).unwrap();
+    save_top_shard_blocks_worker(engine.clone(), receiver);
+    let mc_ss = make_shard_state();
+
+    // Initial configuration doesn't produce events
+    assert!(pool.update_topology(1, &shard_records(&[0x8000_0000_0000_0000])).is_empty());
+    pool.process_shard_block_raw(
+        &build_id(0x8000_0000_0000_0000, 10), 1, vec!(), false, false, engine.as_ref()
+    ).await.unwrap();
+    // Children's top blocks come before split is registered in masterchain
+    for prefix in [0x4000_0000_0000_0000, 0xc000_0000_0000_0000] {
+        pool.process_shard_block_raw(
+            &build_id(prefix, 11), 1, vec!(), false, false, engine.as_ref()
+        ).await.unwrap();
+    }
+    assert_eq!(pool.get_shard_blocks(&mc_ss, engine.as_ref(), false, None).await.unwrap().len(), 3);
+
+    // Split
+    let changes = pool.update_topology(
+        2, &shard_records(&[0x4000_0000_0000_0000, 0xc000_0000_0000_0000])
+    );
+    let split = ShardTopologyChange::Split {
+        mc_seq_no: 2,
+        parent: shard(0x8000_0000_0000_0000),
+        left: shard(0x4000_0000_0000_0000),
+        right: shard(0xc000_0000_0000_0000),
+    };
+    assert_eq!(changes, vec!(split.clone()));
+    let sb = pool.get_shard_blocks(&mc_ss, engine.as_ref(), false, None).await.unwrap();
+    assert_eq!(sb.len(), 2);
+    assert!(sb.iter().all(|tbd| tbd.proof_for().shard() != &shard(0x8000_0000_0000_0000)));
+
+    // Stale parent's top block is not accepted anymore
+    assert!(pool.process_shard_block_raw(
+        &build_id(0x8000_0000_0000_0000, 12), 1, vec!(), false, false, engine.as_ref()
+    ).await.is_err());
+
+    // Same configuration
+    assert!(pool.update_topology(3, &shard_records(
+        &[0x4000_0000_0000_0000, 0xc000_0000_0000_0000]
+    )).is_empty());
+
+    // Merge
+    let changes = pool.update_topology(4, &shard_records(&[0x8000_0000_0000_0000]));
+    let merge = ShardTopologyChange::Merge {
+        mc_seq_no: 4,
+        left: shard(0x4000_0000_0000_0000),
+        right: shard(0xc000_0000_0000_0000),
+        merged: shard(0x8000_0000_0000_0000),
+    };
+    assert_eq!(changes, vec!(merge.clone()));
+    assert!(pool.get_shard_blocks(&mc_ss, engine.as_ref(), false, None).await.unwrap().is_empty());
+    pool.process_shard_block_raw(
+        &build_id(0x8000_0000_0000_0000, 20), 2, vec!(), false, false, engine.as_ref()
+    ).await.unwrap();
+    let sb = pool.get_shard_blocks(&mc_ss, engine.as_ref(), false, None).await.unwrap();
+    assert_eq!(sb.len(), 1);
+    assert_eq!(sb[0].proof_for(), &build_id(0x8000_0000_0000_0000, 20));
+
+    assert_eq!(pool.topology_changes(0), vec!(split, merge.clone()));
+    assert_eq!(pool.topology_changes(2), vec!(merge));
+    assert!(pool.topology_changes(4).is_empty());
+
+    // Invalidated top blocks are removed from storage
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let stored = engine.shard_blocks.lock().unwrap();
+    assert_eq!(stored.len(), 1);
+    assert!(stored.contains(&TopBlockDescrId::new(shard(0x8000_0000_0000_0000), 2)));
+}
+
+#[test]
+fn test_shard_topology_delta() {
+    let set = |shards: &[u64]| shards.iter().map(|prefix| shard(*prefix)).collect::<HashSet<_>>();
+    let old = set(&[0x2000_0000_0000_0000, 0x6000_0000_0000_0000, 0xc000_0000_0000_0000]);
+    let new = set(&[0x4000_0000_0000_0000, 0xa000_0000_0000_0000, 0xe000_0000_0000_0000]);
+    assert_eq!(
+        shard_topology_delta(7, &old, &new),
+        vec!(
+            ShardTopologyChange::Merge {
+                mc_seq_no: 7,
+                left: shard(0x2000_0000_0000_0000),
+                right: shard(0x6000_0000_0000_0000),
+                merged: shard(0x4000_0000_0000_0000),
+            },
+            ShardTopologyChange::Split {
+                mc_seq_no: 7,
+                parent: shard(0xc000_0000_0000_0000),
+                left: shard(0xa000_0000_0000_0000),
+                right: shard(0xe000_0000_0000_0000),
+            },
+        )
+    );
+    assert!(shard_topology_delta(7, &new, &new).is_empty());
+}
@@ -0,0 +1,167 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::engine_traits::{
+    BlockAccess, BroadcastSupport, RempSupport, StateAccess, ValidatorSupport
+};
+use std::time::Instant;
+use storage::block_handle_db::BlockHandle;
+use ever_block::UInt256;
+
+// Applies blocks of the slow shard the given time
+struct TestEngine {
+    slow_shard: Option<ShardIdent>,
+    slow_apply: Duration,
+    applied: parking_lot::Mutex<Vec<BlockIdExt>>,
+    shard_client_mc_block: parking_lot::Mutex<Option<BlockIdExt>>,
+}
+
+impl TestEngine {
+    fn new(slow_shard: Option<ShardIdent>, slow_apply: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            slow_shard,
+            slow_apply,
+            applied: parking_lot::Mutex::new(Vec::new()),
+            shard_client_mc_block: parking_lot::Mutex::new(None),
+        })
+    }
+
+    fn applied_in(&self, shard: &ShardIdent) -> Vec<u32> {
+        self.applied.lock().iter().filter(|id| id.shard() == shard).map(|id| id.seq_no()).collect()
+    }
+
+    fn shard_client_mc_seq_no(&self) -> Option<u32> {
+        self.shard_client_mc_block.lock().as_ref().map(|id| id.seq_no())
+    }
+}
+
+#[async_trait::async_trait]
+impl EngineOperations for TestEngine {
+
+    async fn download_and_apply_block(
+        self: Arc<Self>,
+        id: &BlockIdExt,
+        _mc_seq_no: u32,
+        _pre_apply: bool
+    ) -> Result<()> {
+        if self.slow_shard.as_ref() == Some(id.shard()) {
+            tokio::time::sleep(self.slow_apply).await;
+        } else {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        self.applied.lock().push(id.clone());
+        Ok(())
+    }
+
+    fn save_shard_client_mc_block_id(&self, id: &BlockIdExt) -> Result<()> {
+        *self.shard_client_mc_block.lock() = Some(id.clone());
+        Ok(())
+    }
+
+    fn check_stop(&self) -> bool {
+        false
+    }
+
+}
+
+impl BlockAccess for TestEngine {
+    fn load_block_handle(&self, _id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
+        Ok(None)
+    }
+}
+impl StateAccess for TestEngine {}
+impl RempSupport for TestEngine {}
+impl BroadcastSupport for TestEngine {}
+impl ValidatorSupport for TestEngine {}
+
+fn shard(prefix: u64) -> ShardIdent {
+    ShardIdent::with_tagged_prefix(0, prefix).unwrap()
+}
+
+fn block(shard: &ShardIdent, seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(shard.clone(), seq_no, UInt256::from([seq_no as u8; 32]), UInt256::default())
+}
+
+fn mc_block(seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(ShardIdent::masterchain(), seq_no, UInt256::default(), UInt256::default())
+}
+
+fn jobs(mc_seq_no: u32, blocks: &[BlockIdExt]) -> Vec<ShardApplyJob> {
+    blocks.iter()
+        .map(|block_id| ShardApplyJob { block_id: block_id.clone(), mc_seq_no, proof_chain: None })
+        .collect()
+}
+
+async fn wait_for(timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let start = Instant::now();
+    while !condition() {
+        if start.elapsed() > timeout {
+            return false
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    true
+}
+
+#[tokio::test]
+async fn test_shard_pipelines_slow_shard() -> Result<()> {
+    let (slow, fast) = (shard(0x4000_0000_0000_0000), shard(0xc000_0000_0000_0000));
+    let engine = TestEngine::new(Some(slow.clone()), Duration::from_millis(300));
+    let mut pipelines = ShardPipelines::new(engine.clone(), SHARD_PIPELINE_QUEUE_LEN);
+    for mc_seq_no in 1..=10 {
+        let blocks = [block(&slow, mc_seq_no), block(&fast, mc_seq_no)];
+        pipelines.dispatch(&mc_block(mc_seq_no), jobs(mc_seq_no, &blocks)).await?;
+    }
+
+    // Fast shard doesn't wait for the slow one
+    assert!(wait_for(Duration::from_secs(2), || engine.applied_in(&fast).len() == 10).await);
+    assert_eq!(engine.applied_in(&fast), (1..=10).collect::<Vec<_>>());
+    // Shard client mc block is moved only when all shards are applied
+    let shard_client_mc_seq_no = engine.shard_client_mc_seq_no().unwrap_or(0);
+    let slow_applied = engine.applied_in(&slow).len() as u32;
+    assert!(slow_applied < 10);
+    assert!(shard_client_mc_seq_no <= slow_applied);
+
+    assert!(wait_for(Duration::from_secs(10), || engine.shard_client_mc_seq_no() == Some(10)).await);
+    assert_eq!(engine.applied_in(&slow), (1..=10).collect::<Vec<_>>());
+    pipelines.stop().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shard_pipelines_split_merge() -> Result<()> {
+    let parent = ShardIdent::full(0);
+    let (left, right) = (shard(0x4000_0000_0000_0000), shard(0xc000_0000_0000_0000));
+    let engine = TestEngine::new(Some(parent.clone()), Duration::from_millis(100));
+    let mut pipelines = ShardPipelines::new(engine.clone(), SHARD_PIPELINE_QUEUE_LEN);
+
+    pipelines.dispatch(&mc_block(1), jobs(1, &[block(&parent, 1)])).await?;
+    pipelines.dispatch(&mc_block(2), jobs(2, &[block(&parent, 2)])).await?;
+    // Split: children start after queued blocks of the parent
+    pipelines.dispatch(&mc_block(3), jobs(3, &[block(&left, 3), block(&right, 3)])).await?;
+    // The same top block is not queued again
+    pipelines.dispatch(&mc_block(4), jobs(4, &[block(&left, 3), block(&right, 4)])).await?;
+    // Merge: parent starts after queued blocks of both children
+    pipelines.dispatch(&mc_block(5), jobs(5, &[block(&parent, 5)])).await?;
+
+    assert!(wait_for(Duration::from_secs(5), || engine.shard_client_mc_seq_no() == Some(5)).await);
+    let applied = engine.applied.lock().clone();
+    assert_eq!(applied.len(), 6);
+    assert_eq!(&applied[..2], &[block(&parent, 1), block(&parent, 2)]);
+    assert_eq!(applied[5], block(&parent, 5));
+    assert_eq!(engine.applied_in(&left), [3]);
+    assert_eq!(engine.applied_in(&right), [3, 4]);
+    pipelines.stop().await;
+    Ok(())
+}
@@ -71,3 +71,77 @@ fn test_shard_state_deserilaize() {
     assert_eq!(new_ss.state().unwrap(), &ss);
 }
 
+
+#[tokio::test]
+async fn test_account_proof() {
+    use crate::{collator_test_bundle::CollatorTestBundle, engine_traits::StateAccess};
+    use ever_block::MASTERCHAIN_ID;
+
+    let bundle = CollatorTestBundle::build_with_zero_state(
+        "src/tests/static/zerostate.boc",
+        &["src/tests/static/basestate0.boc"]
+    ).await.unwrap();
+    let state = bundle.load_last_applied_mc_state().await.unwrap();
+
+    let (expected_id, _) = state.state().unwrap().read_accounts().unwrap()
+        .find_leaf(&UInt256::default(), true, true, false).unwrap()
+        .expect("zerostate must contain accounts");
+    let prefix = AccountIdPrefixFull::workchain(
+        MASTERCHAIN_ID,
+        u64::from_be_bytes(expected_id.as_slice()[..8].try_into().unwrap())
+    );
+    let (account_id, proof) = state.account_proof(&prefix).unwrap();
+    assert_eq!(account_id, expected_id);
+
+    let proof = MerkleProof::construct_from_bytes(&proof).unwrap();
+    assert_eq!(proof.hash, state.root_cell().repr_hash());
+    let virt_state = ShardStateUnsplit::construct_from_cell(proof.proof.virtualize(1)).unwrap();
+    let shard_account = virt_state.read_accounts().unwrap().get(&account_id).unwrap()
+        .expect("proof must contain requested account");
+    let expected = state.state().unwrap().read_accounts().unwrap().get(&account_id).unwrap().unwrap();
+    assert_eq!(shard_account.read_account().unwrap(), expected.read_account().unwrap());
+
+    // account of another workchain can't be proven by masterchain state
+    assert!(state.account_proof(&AccountIdPrefixFull::workchain(0, prefix.prefix)).is_err());
+}
+
+#[tokio::test]
+async fn test_account_state_proof() {
+    use crate::{collator_test_bundle::CollatorTestBundle, engine_traits::StateAccess};
+
+    let bundle = CollatorTestBundle::build_with_zero_state(
+        "src/tests/static/zerostate.boc",
+        &["src/tests/static/basestate0.boc"]
+    ).await.unwrap();
+    let state = bundle.load_last_applied_mc_state().await.unwrap();
+    let accounts = state.state().unwrap().read_accounts().unwrap();
+    let (existing_id, _) = accounts.find_leaf(&UInt256::default(), true, true, false).unwrap()
+        .expect("zerostate must contain accounts");
+    let absent_id = AccountId::from([0x5A; 32]);
+    assert!(accounts.account(&absent_id).unwrap().is_none());
+
+    for account_id in [existing_id, absent_id.clone()] {
+        let expected = accounts.account(&account_id).unwrap();
+        let (exists, proof) = state.account_state_proof(&account_id, 1 << 20).unwrap();
+        assert_eq!(exists, expected.is_some());
+
+        let proof = MerkleProof::construct_from_bytes(&proof).unwrap();
+        assert_eq!(proof.hash, state.root_cell().repr_hash());
+        // Absence is proven by the same pruned dictionary without the account
+        let virt_state = ShardStateUnsplit::construct_from_cell(proof.proof.virtualize(1)).unwrap();
+        let proven = virt_state.read_accounts().unwrap().account(&account_id).unwrap();
+        match (proven, expected) {
+            (Some(proven), Some(expected)) =>
+                assert_eq!(proven.read_account().unwrap(), expected.read_account().unwrap()),
+            (None, None) => (),
+            _ => panic!("proof of account {:x} doesn't match the state", account_id)
+        }
+    }
+
+    match state.account_state_proof(&absent_id, 10) {
+        Err(err) => assert!(matches!(
+            err.downcast_ref::<NodeError>(), Some(NodeError::AccountProofTooLarge { max_size: 10, .. })
+        )),
+        Ok(_) => panic!("too large proof must be refused")
+    }
+}
@@ -0,0 +1,529 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+
+use super::*;
+use crate::collator_test_bundle::create_block_handle_storage;
+use std::{collections::HashSet, sync::atomic::AtomicBool, thread};
+use storage::{block_handle_db::{BlockHandleStorage, FLAG_KEY_BLOCK}, types::BlockMeta};
+
+fn mc_block_id(seq_no: u32) -> BlockIdExt {
+    BlockIdExt {
+        shard_id: ShardIdent::masterchain(),
+        seq_no,
+        root_hash: UInt256::from([seq_no as u8; 32]),
+        file_hash: UInt256::from([seq_no as u8; 32]),
+    }
+}
+
+#[test]
+fn test_concurrent_pin_unpin() {
+    let gc_resolver = Arc::new(AllowStateGcSmartResolver::new(0));
+    let id = mc_block_id(1);
+    let mut threads = Vec::new();
+    for _ in 0..8 {
+        let gc_resolver = gc_resolver.clone();
+        let id = id.clone();
+        threads.push(thread::spawn(move || {
+            for _ in 0..1000 {
+                let guard = PinGuard::new(id.clone(), gc_resolver.clone());
+                let cloned = guard.clone();
+                assert!(gc_resolver.is_pinned(&id));
+                drop(guard);
+                drop(cloned);
+            }
+        }));
+    }
+    // guard must be released while unwinding
+    let panicked = {
+        let gc_resolver = gc_resolver.clone();
+        let id = id.clone();
+        thread::spawn(move || {
+            let _guard = PinGuard::new(id, gc_resolver);
+            panic!("test panic");
+        })
+    };
+    let long_living = PinGuard::new(id.clone(), gc_resolver.clone());
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert!(panicked.join().is_err());
+    assert_eq!(gc_resolver.pinned_stats(), (1, 1));
+    drop(long_living);
+    assert_eq!(gc_resolver.pinned_stats(), (0, 0));
+    assert!(!gc_resolver.is_pinned(&id));
+}
+
+#[test]
+fn test_gc_skips_pinned_states() {
+    let gc_resolver = Arc::new(AllowStateGcSmartResolver::new(0));
+    gc_resolver.set_min_ref_mc_seqno(10);
+    let old_id = mc_block_id(5);
+    let actual_id = mc_block_id(15);
+    let allow = |id| AllowStateGcResolver::allow_state_gc(gc_resolver.as_ref(), 0, id, 0, u64::MAX).unwrap();
+
+    assert!(allow(&old_id));
+    assert!(!allow(&actual_id));
+
+    let guard1 = PinGuard::new(old_id.clone(), gc_resolver.clone());
+    let guard2 = guard1.clone();
+    assert!(!allow(&old_id));
+    assert_eq!(gc_resolver.pinned_stats(), (1, 2));
+
+    drop(guard1);
+    assert!(!allow(&old_id));
+    drop(guard2);
+    assert!(allow(&old_id));
+
+    // pinned state which is not allowed to gc yet stays protected after resolver is advanced
+    let guard = PinGuard::new(actual_id.clone(), gc_resolver.clone());
+    gc_resolver.set_min_ref_mc_seqno(20);
+    assert!(!allow(&actual_id));
+    drop(guard);
+    assert!(allow(&actual_id));
+}
+
+fn mc_handle(
+    storage: &BlockHandleStorage,
+    seq_no: u32,
+    gen_utime: u32,
+    key_block: bool
+) -> Arc<BlockHandle> {
+    let flags = if key_block { FLAG_KEY_BLOCK } else { 0 };
+    let meta = BlockMeta::with_data(flags, gen_utime, 0, 0, 0);
+    storage.create_handle(mc_block_id(seq_no), meta, None).unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn test_persistent_state_policy_every_key_block() {
+    let storage = create_block_handle_storage();
+    let policy = PersistentStatePolicy::EveryKeyBlock;
+    let check = |handle: Arc<BlockHandle>| {
+        check_persistent_state_policy(&policy, 1000, 10, &handle).unwrap()
+    };
+    assert!(check(mc_handle(&storage, 11, 1001, true)));
+    assert!(!check(mc_handle(&storage, 12, 100_000, false)));
+    assert!(!check(mc_handle(&storage, 0, 0, true)));
+}
+
+#[tokio::test]
+async fn test_persistent_state_policy_time_interval() {
+    let storage = create_block_handle_storage();
+    let policy = PersistentStatePolicy::TimeInterval(3600);
+    let check = |handle: Arc<BlockHandle>| {
+        check_persistent_state_policy(&policy, 1000, 10, &handle).unwrap()
+    };
+    assert!(!check(mc_handle(&storage, 11, 4599, true)));
+    assert!(check(mc_handle(&storage, 12, 4600, true)));
+    assert!(!check(mc_handle(&storage, 13, 5000, false)));
+    // clock skew: block is older than the last saved state
+    assert!(!check(mc_handle(&storage, 14, 900, true)));
+}
+
+#[tokio::test]
+async fn test_persistent_state_policy_seqno_gap() {
+    let storage = create_block_handle_storage();
+    let policy = PersistentStatePolicy::SeqnoGap(100);
+    let check = |handle: Arc<BlockHandle>| {
+        check_persistent_state_policy(&policy, 1000, 10, &handle).unwrap()
+    };
+    assert!(!check(mc_handle(&storage, 109, 100_000, true)));
+    assert!(check(mc_handle(&storage, 110, 1000, true)));
+    assert!(!check(mc_handle(&storage, 200, 1000, false)));
+    assert!(!check(mc_handle(&storage, 5, 100_000, true)));
+}
+
+#[tokio::test]
+async fn test_persistent_state_policy_composite() {
+    let storage = create_block_handle_storage();
+    let policy = PersistentStatePolicy::Composite { interval_sec: 3600, seqno_gap: 100 };
+    let check = |handle: Arc<BlockHandle>| {
+        check_persistent_state_policy(&policy, 1000, 10, &handle).unwrap()
+    };
+    assert!(!check(mc_handle(&storage, 109, 4599, true)));
+    assert!(check(mc_handle(&storage, 110, 4599, true)));
+    assert!(check(mc_handle(&storage, 50, 4600, true)));
+    assert!(!check(mc_handle(&storage, 200, 10_000, false)));
+}
+
+fn is_error(err: &ever_block::Error, check: impl Fn(&NodeError) -> bool) -> bool {
+    err.downcast_ref::<NodeError>().map_or(false, check)
+}
+
+#[tokio::test]
+async fn test_wait_state_resolves() {
+    let waiters = Arc::new(StateWaiters::default());
+    let stopper = Stopper::new();
+    let id = mc_block_id(1);
+    let stored = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let mut waiter = waiters.subscribe(&id);
+    let mut other = waiters.subscribe(&mc_block_id(2));
+    assert_eq!(waiters.count(), 2);
+
+    let (waiters_, stored_, id_) = (waiters.clone(), stored.clone(), id.clone());
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stored_.store(true, std::sync::atomic::Ordering::Relaxed);
+        waiters_.notify(&id_);
+    });
+    let now = Instant::now();
+    waiter.wait(
+        Some(Duration::from_secs(10)),
+        &stopper,
+        || Ok(stored.load(std::sync::atomic::Ordering::Relaxed))
+    ).await.unwrap();
+    assert!(now.elapsed() < Duration::from_secs(5));
+
+    // Notification of another state doesn't wake the waiter up
+    let err = other.wait(Some(Duration::from_millis(50)), &stopper, || Ok(false)).await.unwrap_err();
+    assert!(is_error(&err, |err| matches!(err, NodeError::Timeout(_))));
+
+    // Entries are removed when waiters are dropped
+    drop(waiter);
+    assert_eq!(waiters.count(), 1);
+    drop(other);
+    assert_eq!(waiters.count(), 0);
+}
+
+#[tokio::test]
+async fn test_wait_state_timeout() {
+    let waiters = Arc::new(StateWaiters::default());
+    let stopper = Stopper::new();
+    let id = mc_block_id(1);
+    let mut waiter1 = waiters.subscribe(&id);
+    let mut waiter2 = waiters.subscribe(&id);
+    assert_eq!(waiters.count(), 1);
+
+    let now = Instant::now();
+    let err = waiter1.wait(Some(Duration::from_millis(100)), &stopper, || Ok(false)).await.unwrap_err();
+    assert!(is_error(&err, |err| matches!(err, NodeError::Timeout(_))));
+    assert!(now.elapsed() >= Duration::from_millis(100));
+    drop(waiter1);
+    assert_eq!(waiters.count(), 1);
+
+    // Notification without stored state doesn't resolve waiting
+    waiters.notify(&id);
+    let err = waiter2.wait(Some(Duration::from_millis(50)), &stopper, || Ok(false)).await.unwrap_err();
+    assert!(is_error(&err, |err| matches!(err, NodeError::Timeout(_))));
+    drop(waiter2);
+    assert_eq!(waiters.count(), 0);
+}
+
+#[tokio::test]
+async fn test_wait_state_cancelled_on_stop() {
+    let waiters = Arc::new(StateWaiters::default());
+    let stopper = Arc::new(Stopper::new());
+    let mut waiter1 = waiters.subscribe(&mc_block_id(1));
+
+    let stopper_ = stopper.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stopper_.set_stop();
+    });
+    let err = waiter1.wait(None, &stopper, || Ok(false)).await.unwrap_err();
+    assert!(is_error(&err, |err| matches!(err, NodeError::Cancelled(_))));
+    drop(waiter1);
+
+    // Stopped node doesn't wait at all
+    let mut waiter2 = waiters.subscribe(&mc_block_id(2));
+    let err = waiter2.wait(None, &stopper, || Ok(false)).await.unwrap_err();
+    assert!(is_error(&err, |err| matches!(err, NodeError::Cancelled(_))));
+    drop(waiter2);
+    assert_eq!(waiters.count(), 0);
+}
+
+fn shard_block_id(seq_no: u32) -> BlockIdExt {
+    BlockIdExt {
+        shard_id: ShardIdent::with_tagged_prefix(0, 0x8000_0000_0000_0000).unwrap(),
+        seq_no,
+        root_hash: UInt256::from([seq_no as u8 + 100; 32]),
+        file_hash: UInt256::from([seq_no as u8 + 100; 32]),
+    }
+}
+
+#[test]
+fn test_memory_governor_unloads_lru() {
+    let governor = StatesMemoryGovernor::new();
+    for seq_no in 1..=5 {
+        assert!(!governor.state_cached(&mc_block_id(seq_no), 100));
+    }
+    governor.state_cached(&shard_block_id(1), 100);
+    assert_eq!(governor.retained(), 600);
+
+    // Nothing is unloaded without ceiling
+    assert!(governor.select_unloads(|_| true).is_empty());
+
+    governor.set_ceiling(350);
+    governor.state_accessed(&mc_block_id(1));
+    governor.state_accessed(&mc_block_id(3));
+    // mc 5 and shard 1 are the newest ones in their shards
+    assert_eq!(governor.select_unloads(|_| true), vec![mc_block_id(2), mc_block_id(4), mc_block_id(1)]);
+    for id in governor.select_unloads(|_| true) {
+        governor.state_unloaded(&id);
+    }
+    assert_eq!(governor.retained(), 300);
+    assert_eq!(governor.unloads(), 3);
+    assert!(governor.select_unloads(|_| true).is_empty());
+
+    // Removed state is not accounted
+    governor.state_removed(&mc_block_id(3));
+    assert_eq!(governor.retained(), 200);
+}
+
+#[test]
+fn test_memory_governor_skips_pinned() {
+    let gc_resolver = Arc::new(AllowStateGcSmartResolver::new(0));
+    let governor = StatesMemoryGovernor::new();
+    governor.set_ceiling(250);
+    for seq_no in 1..=5 {
+        governor.state_cached(&mc_block_id(seq_no), 100);
+    }
+    let pin = PinGuard::new(mc_block_id(1), gc_resolver.clone());
+    let can_unload = |id: &BlockIdExt| !gc_resolver.is_pinned(id);
+    assert_eq!(governor.select_unloads(can_unload), vec![mc_block_id(2), mc_block_id(3), mc_block_id(4)]);
+
+    // Ceiling can't be reached when only pinned and newest states remain
+    for id in governor.select_unloads(can_unload) {
+        governor.state_unloaded(&id);
+    }
+    assert_eq!(governor.retained(), 200);
+    governor.set_ceiling(150);
+    assert!(governor.select_unloads(can_unload).is_empty());
+
+    drop(pin);
+    assert_eq!(governor.select_unloads(can_unload), vec![mc_block_id(1)]);
+}
+
+#[test]
+fn test_memory_governor_reload() {
+    let governor = StatesMemoryGovernor::new();
+    governor.set_ceiling(200);
+    // Cache and "load from DB" are emulated by map of states
+    let cache = lockfree::map::Map::new();
+    let load_state = |seq_no: u32| {
+        let id = mc_block_id(seq_no);
+        if cache.get(&id).is_some() {
+            governor.state_accessed(&id);
+        } else {
+            cache.insert(id.clone(), ());
+            if governor.state_cached(&id, 100) {
+                governor.state_reloaded(&id, Duration::from_millis(1));
+            }
+            for id in governor.select_unloads(|_| true) {
+                cache.remove(&id);
+                governor.state_unloaded(&id);
+            }
+        }
+    };
+    for seq_no in 1..=3 {
+        load_state(seq_no);
+    }
+    assert!(cache.get(&mc_block_id(1)).is_none());
+    assert_eq!((governor.unloads(), governor.reloads()), (1, 0));
+
+    // Unloaded state is loaded again and the least recently used one is unloaded instead
+    load_state(1);
+    assert!(cache.get(&mc_block_id(1)).is_some());
+    assert!(cache.get(&mc_block_id(2)).is_none());
+    assert_eq!((governor.unloads(), governor.reloads()), (2, 1));
+    assert_eq!(governor.retained(), 200);
+
+    // Outdated unloaded states are not counted as reloaded
+    governor.forget_unloaded(|id| id.seq_no() < 3);
+    load_state(2);
+    assert_eq!(governor.reloads(), 1);
+}
+
+#[tokio::test]
+async fn test_unsaved_states_backlog() {
+    let backlog = Arc::new(UnsavedStatesBacklog::new(2));
+    let mc = ShardIdent::masterchain();
+    let shard = ShardIdent::with_tagged_prefix(0, 0x8000_0000_0000_0000).unwrap();
+    backlog.reserve(&mc, || Ok(())).await.unwrap();
+    backlog.reserve(&mc, || Ok(())).await.unwrap();
+    assert!(!backlog.try_reserve(&mc));
+    // Other shard has its own limit
+    assert!(backlog.try_reserve(&shard));
+    assert_eq!(backlog.unsaved(&mc), 2);
+    assert_eq!(backlog.total(), 3);
+
+    // Application of the next block waits until the oldest state is saved
+    let waiting = tokio::spawn({
+        let backlog = backlog.clone();
+        let mc = mc.clone();
+        async move { backlog.reserve(&mc, || Ok(())).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+    let callback = BacklogCallback { backlog: backlog.clone(), shard: mc.clone() };
+    storage::shardstate_db_async::Callback::invoke(
+        &callback, storage::shardstate_db_async::Job::DeleteState(mc_block_id(1)), true
+    ).await;
+    tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap().unwrap();
+    assert_eq!(backlog.unsaved(&mc), 2);
+
+    // Waiting is cancelled on stop
+    let err = backlog.reserve(&mc, || fail!("Stopped")).await.unwrap_err();
+    assert!(err.to_string().contains("Stopped"));
+
+    // No limit
+    backlog.set_limit(0);
+    assert!(backlog.try_reserve(&mc));
+    for _ in 0..3 {
+        backlog.release(&mc);
+    }
+    backlog.release(&shard);
+    assert_eq!(backlog.total(), 0);
+    // Extra release is ignored
+    backlog.release(&mc);
+    assert_eq!(backlog.unsaved(&mc), 0);
+}
+
+// Loader which takes a while to load a state, like DB does for evicted states
+#[derive(Default)]
+struct SlowLoader {
+    latency: Duration,
+    cached: parking_lot::Mutex<HashSet<BlockIdExt>>,
+    applied: parking_lot::Mutex<HashSet<BlockIdExt>>,
+    missing: parking_lot::Mutex<HashSet<BlockIdExt>>,
+    loads: parking_lot::Mutex<Vec<BlockIdExt>>,
+    no_budget: AtomicBool,
+}
+
+impl SlowLoader {
+    fn with_latency(latency_ms: u64) -> Arc<Self> {
+        Arc::new(Self { latency: Duration::from_millis(latency_ms), ..Default::default() })
+    }
+    fn is_loading(&self, block_id: &BlockIdExt) -> bool {
+        self.loads.lock().contains(block_id)
+    }
+    async fn wait_loaded(&self, block_id: &BlockIdExt) {
+        for _ in 0..200 {
+            if self.is_cached(block_id) {
+                return
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("State {} is not prefetched", block_id)
+    }
+}
+
+#[async_trait::async_trait]
+impl StatesLoader for SlowLoader {
+    fn is_applied(&self, block_id: &BlockIdExt) -> Result<bool> {
+        Ok(self.applied.lock().contains(block_id))
+    }
+    fn is_cached(&self, block_id: &BlockIdExt) -> bool {
+        self.cached.lock().contains(block_id)
+    }
+    fn has_budget(&self) -> bool {
+        !self.no_budget.load(Ordering::Relaxed)
+    }
+    fn key_block_id(&self, seq_no: u32) -> Result<BlockIdExt> {
+        Ok(mc_block_id(seq_no))
+    }
+    async fn warm_state(&self, block_id: &BlockIdExt) -> Result<()> {
+        self.loads.lock().push(block_id.clone());
+        tokio::time::sleep(self.latency).await;
+        if self.missing.lock().contains(block_id) {
+            fail!("State {} is not stored", block_id)
+        }
+        self.cached.lock().insert(block_id.clone());
+        Ok(())
+    }
+}
+
+fn prefetch_request(seq_no: u32, key_block_seqno: Option<u32>) -> PrefetchRequest {
+    PrefetchRequest {
+        block_id: mc_block_id(seq_no),
+        prev_ids: vec!(mc_block_id(seq_no - 1)),
+        key_block_seqno,
+    }
+}
+
+fn start_prefetcher(loader: &Arc<SlowLoader>) -> Arc<StatesPrefetcher> {
+    let prefetcher = Arc::new(StatesPrefetcher::new());
+    prefetcher.set_distance(2);
+    prefetcher.start(loader.clone());
+    prefetcher
+}
+
+// Apply of the next block loads the state
+fn apply(prefetcher: &StatesPrefetcher, loader: &SlowLoader, seq_no: u32) {
+    let id = mc_block_id(seq_no);
+    prefetcher.state_requested(&id, loader.is_cached(&id));
+}
+
+#[tokio::test]
+async fn test_states_prefetch_hits() {
+    let loader = SlowLoader::with_latency(300);
+    let prefetcher = start_prefetcher(&loader);
+
+    prefetcher.announce(prefetch_request(3, None));
+    prefetcher.announce(prefetch_request(4, Some(1)));
+    loader.wait_loaded(&mc_block_id(2)).await;
+    apply(&prefetcher, &loader, 2);
+    assert_eq!((prefetcher.hits(), prefetcher.misses()), (1, 0));
+
+    // Apply is ahead of prefetch: the state is still loading
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(loader.is_loading(&mc_block_id(3)));
+    apply(&prefetcher, &loader, 3);
+    assert_eq!((prefetcher.hits(), prefetcher.misses()), (1, 1));
+
+    // Key block state is warmed too
+    loader.wait_loaded(&mc_block_id(1)).await;
+    apply(&prefetcher, &loader, 1);
+    assert_eq!((prefetcher.hits(), prefetcher.misses()), (2, 1));
+
+    // Every prefetched state is accounted once, states not prefetched are not accounted
+    apply(&prefetcher, &loader, 1);
+    apply(&prefetcher, &loader, 7);
+    assert_eq!((prefetcher.hits(), prefetcher.misses()), (2, 1));
+}
+
+#[tokio::test]
+async fn test_states_prefetch_skips() {
+    let loader = SlowLoader::with_latency(10);
+    let prefetcher = start_prefetcher(&loader);
+
+    // Block is applied already, previous state is warm, previous state is not stored
+    loader.applied.lock().insert(mc_block_id(3));
+    loader.cached.lock().insert(mc_block_id(3));
+    loader.missing.lock().insert(mc_block_id(4));
+    for seq_no in 3..=6 {
+        prefetcher.announce(prefetch_request(seq_no, None));
+    }
+    // Failure doesn't stop the worker
+    loader.wait_loaded(&mc_block_id(5)).await;
+    assert_eq!(*loader.loads.lock(), vec!(mc_block_id(4), mc_block_id(5)));
+    for seq_no in 2..=5 {
+        apply(&prefetcher, &loader, seq_no);
+    }
+    assert_eq!((prefetcher.hits(), prefetcher.misses()), (1, 0));
+
+    // Prefetch doesn't go over the memory budget
+    loader.no_budget.store(true, Ordering::Relaxed);
+    prefetcher.announce(prefetch_request(8, None));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!loader.is_loading(&mc_block_id(7)));
+    loader.no_budget.store(false, Ordering::Relaxed);
+    prefetcher.announce(prefetch_request(9, None));
+    loader.wait_loaded(&mc_block_id(8)).await;
+
+    // Nothing is announced when prefetch is disabled
+    prefetcher.set_distance(0);
+    prefetcher.announce(prefetch_request(11, None));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!loader.is_loading(&mc_block_id(10)));
+}
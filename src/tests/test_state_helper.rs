@@ -0,0 +1,135 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::ShardIdent;
+use std::{collections::HashMap, sync::Mutex};
+
+const DB_PATH: &str = "target/test/state_download";
+const CHUNK_SIZE: u32 = 1000;
+
+#[derive(Default)]
+struct MemoryProgressStore {
+    records: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl DownloadProgressStore for MemoryProgressStore {
+    fn load_download_progress(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.records.lock().unwrap().get(key).cloned())
+    }
+    fn save_download_progress(&self, key: &str, progress: &[u8]) -> Result<()> {
+        self.records.lock().unwrap().insert(key.to_string(), progress.to_vec());
+        Ok(())
+    }
+    fn drop_download_progress(&self, key: &str) -> Result<()> {
+        self.records.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+fn state_id() -> BlockIdExt {
+    BlockIdExt::with_params(ShardIdent::masterchain(), 100, UInt256::from([1; 32]), UInt256::from([2; 32]))
+}
+
+fn state_data() -> Vec<u8> {
+    (0..10_500u32).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+// Serves parts like peers do and fails after given count of parts
+fn download(download: &mut StateDownload, data: &[u8], fail_after: Option<usize>) -> Result<()> {
+    let mut parts = 0;
+    loop {
+        if Some(parts) == fail_after {
+            fail!("Connection dropped")
+        }
+        let offset = download.offset() as usize;
+        let end = data.len().min(offset + CHUNK_SIZE as usize);
+        download.append(&data[offset..end])?;
+        parts += 1;
+        if end - offset < CHUNK_SIZE as usize {
+            return Ok(())
+        }
+    }
+}
+
+#[test]
+fn test_state_download_resume() -> Result<()> {
+    let dir = PathBuf::from(DB_PATH).join("resume");
+    std::fs::remove_dir_all(&dir).ok();
+    let store = MemoryProgressStore::default();
+    let data = state_data();
+    for fail_after in [0, 1, 4, 3] {
+        let mut state_download = StateDownload::open(&store, &dir, &state_id(), None, CHUNK_SIZE)?;
+        let offset = state_download.offset();
+        assert!(download(&mut state_download, &data, Some(fail_after)).is_err());
+        assert_eq!(state_download.offset(), offset + fail_after as u64 * CHUNK_SIZE as u64);
+    }
+    // Restarted download continues from the last saved chunk
+    let mut state_download = StateDownload::open(&store, &dir, &state_id(), None, CHUNK_SIZE)?;
+    assert_eq!(state_download.offset(), 8 * CHUNK_SIZE as u64);
+    download(&mut state_download, &data, None)?;
+    assert_eq!(state_download.data()?, data);
+    drop(state_download);
+
+    finish_state_download(&store, &dir, &state_id(), None)?;
+    assert!(store.records.lock().unwrap().is_empty());
+    assert!(!state_download_path(&dir, &state_id(), None).exists());
+    Ok(())
+}
+
+#[test]
+fn test_state_download_verify_on_resume() -> Result<()> {
+    let dir = PathBuf::from(DB_PATH).join("verify");
+    std::fs::remove_dir_all(&dir).ok();
+    let store = MemoryProgressStore::default();
+    let data = state_data();
+    let path = state_download_path(&dir, &state_id(), Some(0));
+
+    let mut state_download = StateDownload::open(&store, &dir, &state_id(), Some(0), CHUNK_SIZE)?;
+    assert!(download(&mut state_download, &data, Some(5)).is_err());
+    // Torn write after the last saved chunk is cut off
+    state_download.append(&data[5000..5300])?;
+    drop(state_download);
+    let state_download = StateDownload::open(&store, &dir, &state_id(), Some(0), CHUNK_SIZE)?;
+    assert_eq!(state_download.offset(), 5 * CHUNK_SIZE as u64);
+    assert_eq!(std::fs::metadata(&path)?.len(), 5 * CHUNK_SIZE as u64);
+    drop(state_download);
+
+    // Corrupted chunk and all next ones are downloaded again
+    let mut file_data = std::fs::read(&path)?;
+    file_data[2500] ^= 0xFF;
+    std::fs::write(&path, &file_data)?;
+    let mut state_download = StateDownload::open(&store, &dir, &state_id(), Some(0), CHUNK_SIZE)?;
+    assert_eq!(state_download.offset(), 2 * CHUNK_SIZE as u64);
+    download(&mut state_download, &data, None)?;
+    assert_eq!(state_download.data()?, data);
+    drop(state_download);
+
+    // Progress of other chunk size is not used
+    let state_download = StateDownload::open(&store, &dir, &state_id(), Some(0), CHUNK_SIZE * 2)?;
+    assert_eq!(state_download.offset(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_state_download_progress_serialization() -> Result<()> {
+    let progress = StateDownloadProgress {
+        id: state_id(),
+        chunk_size: CHUNK_SIZE,
+        chunk_hashes: vec![UInt256::from([3; 32]), UInt256::from([4; 32])],
+    };
+    let data = progress.serialize()?;
+    assert_eq!(StateDownloadProgress::deserialize(&data)?, progress);
+    assert!(StateDownloadProgress::deserialize(&data[..data.len() - 1]).is_err());
+    Ok(())
+}
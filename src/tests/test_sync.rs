@@ -12,6 +12,13 @@
 */
 
 use super::*;
+use crate::{engine::Stopper, error::NodeError};
+use crate::engine_traits::{
+    BlockAccess, BroadcastSupport, RempSupport, StateAccess, ValidatorSupport
+};
+#[cfg(feature = "telemetry")]
+use crate::{collator_test_bundle::create_engine_telemetry, engine_traits::EngineTelemetry};
+use std::{collections::HashMap, time::Duration};
 
 #[tokio::test]
 async fn test_read_package() -> Result<()> {
@@ -31,3 +38,250 @@ async fn test_read_package() -> Result<()> {
     Ok(())
 }
 
+
+struct TestEngine {
+    attempts: std::sync::Mutex<HashMap<u32, u32>>,
+    archives: u32,
+    failing_seq_no: u32,
+    // Error of the first attempt for failing seq_no, untyped timeout if none
+    failure: Option<NodeError>,
+    stopper: Stopper,
+    #[cfg(feature = "telemetry")]
+    engine_telemetry: Arc<EngineTelemetry>,
+}
+
+#[async_trait::async_trait]
+impl EngineOperations for TestEngine {
+
+    async fn check_sync(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    async fn download_archive(
+        &self, 
+        masterchain_seqno: u32,
+        _active_peers: &Arc<lockfree::set::Set<Arc<KeyId>>>
+    ) -> Result<Option<Vec<u8>>> {
+        let attempt = {
+            let mut attempts = self.attempts.lock().unwrap();
+            let attempt = attempts.entry(masterchain_seqno).or_insert(0);
+            *attempt += 1;
+            *attempt
+        };
+        // The earlier archive is, the longer it is downloaded
+        let index = (masterchain_seqno - 1) / ARCHIVE_PACKAGE_SIZE;
+        tokio::time::sleep(Duration::from_millis(10 * self.archives.saturating_sub(index) as u64)).await;
+        if (masterchain_seqno == self.failing_seq_no) && (attempt == 1) {
+            match &self.failure {
+                Some(failure) => fail!(failure.clone()),
+                None => fail!("Timeout from peer")
+            }
+        }
+        Ok(Some(masterchain_seqno.to_le_bytes().to_vec()))
+    }
+
+    fn stopper(&self) -> &Stopper {
+        &self.stopper
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn engine_telemetry(&self) -> &Arc<EngineTelemetry> {
+        &self.engine_telemetry
+    }
+}
+
+impl BlockAccess for TestEngine {}
+impl StateAccess for TestEngine {}
+impl RempSupport for TestEngine {}
+impl BroadcastSupport for TestEngine {}
+impl ValidatorSupport for TestEngine {}
+
+#[tokio::test]
+async fn test_download_queue_order_and_retry() -> Result<()> {
+    const CONCURRENCY: usize = 4;
+    const ARCHIVES: u32 = 10;
+    let failing_seq_no = 1 + 2 * ARCHIVE_PACKAGE_SIZE;
+    let engine = Arc::new(TestEngine {
+        attempts: std::sync::Mutex::new(HashMap::new()),
+        archives: ARCHIVES,
+        failing_seq_no,
+        failure: None,
+        stopper: Stopper::new(),
+        #[cfg(feature = "telemetry")]
+        engine_telemetry: create_engine_telemetry(),
+    });
+    let mut queue = DownloadQueue::new(engine.clone(), CONCURRENCY, CONCURRENCY);
+    queue.set_full_concurrency();
+
+    let mut sync_mc_seq_no = 1;
+    let mut applied = Vec::new();
+    let mut downloaded_in_advance = false;
+    while applied.len() < ARCHIVES as usize {
+        queue.new_downloads(sync_mc_seq_no).await?;
+        assert!(queue.in_flight() <= CONCURRENCY);
+        assert!(queue.archives.len() <= CONCURRENCY);
+        if let Some((seq_no, data)) = queue.take_ready(sync_mc_seq_no) {
+            assert_eq!(seq_no, sync_mc_seq_no);
+            assert_eq!(data, seq_no.to_le_bytes().to_vec());
+            applied.push(seq_no);
+            sync_mc_seq_no += ARCHIVE_PACKAGE_SIZE;
+        } else {
+            queue.wait_download().await?;
+            downloaded_in_advance |= queue.archives.iter().any(
+                |(seq_no, status)| 
+                    (*seq_no > sync_mc_seq_no) && matches!(status, ArchiveStatus::Downloaded(_))
+            );
+        }
+    }
+
+    // Archives are downloaded out of order, but handed out strictly in order
+    assert!(downloaded_in_advance);
+    let expected = (0..ARCHIVES).map(|i| 1 + i * ARCHIVE_PACKAGE_SIZE).collect::<Vec<_>>();
+    assert_eq!(applied, expected);
+
+    // Failed download was retried
+    let attempts = engine.attempts.lock().unwrap();
+    for seq_no in expected {
+        let expected_attempts = if seq_no == failing_seq_no { 2 } else { 1 };
+        assert_eq!(attempts.get(&seq_no), Some(&expected_attempts));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_queue_stops_on_fatal_error() -> Result<()> {
+    let failures = [
+        (NodeError::NetworkTimeout("No RLDP answer".to_string()), true),
+        (NodeError::NotFound("Got `TonNode_NotFound`".to_string()), true),
+        (NodeError::DbCorruption("Broken block data".to_string()), false),
+        (NodeError::InvalidProof("Proof doesn't match trusted key block".to_string()), false),
+    ];
+    for (failure, retryable) in failures {
+        let engine = Arc::new(TestEngine {
+            attempts: std::sync::Mutex::new(HashMap::new()),
+            archives: 1,
+            failing_seq_no: 1,
+            failure: Some(failure.clone()),
+            stopper: Stopper::new(),
+            #[cfg(feature = "telemetry")]
+            engine_telemetry: create_engine_telemetry(),
+        });
+        let mut queue = DownloadQueue::new(engine.clone(), 1, 1);
+        queue.new_downloads(1).await?;
+        let result = queue.wait_download().await;
+        if retryable {
+            result?;
+            while queue.in_flight() > 0 {
+                queue.wait_download().await?;
+            }
+            assert!(queue.take_ready(1).is_some());
+            assert_eq!(engine.attempts.lock().unwrap().get(&1), Some(&2));
+        } else {
+            let err = result.expect_err("fatal error must stop the queue");
+            assert_eq!(err.to_string(), failure.to_string());
+            assert_eq!(engine.attempts.lock().unwrap().get(&1), Some(&1));
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_queue_cancelled_on_stop() -> Result<()> {
+    const CONCURRENCY: usize = 4;
+    // Every download takes minutes
+    let engine = Arc::new(TestEngine {
+        attempts: std::sync::Mutex::new(HashMap::new()),
+        archives: 10_000,
+        failing_seq_no: 0,
+        failure: None,
+        stopper: Stopper::new(),
+        #[cfg(feature = "telemetry")]
+        engine_telemetry: create_engine_telemetry(),
+    });
+    let mut queue = DownloadQueue::new(engine.clone(), CONCURRENCY, CONCURRENCY);
+    queue.set_full_concurrency();
+    queue.new_downloads(1).await?;
+    assert_eq!(queue.in_flight(), CONCURRENCY);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let stopped_at = Instant::now();
+    engine.stopper.set_stop();
+    for _ in 0..CONCURRENCY {
+        let err = queue.wait_download().await.expect_err("download must be cancelled");
+        assert!(NodeError::is_cancelled(&err));
+    }
+    assert!(stopped_at.elapsed() < Duration::from_millis(500));
+    // Downloads are not retried after stop
+    assert!(engine.attempts.lock().unwrap().values().all(|attempts| *attempts == 1));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_check_proofs_in_parallel_respects_key_blocks() -> Result<()> {
+    const PARALLELISM: usize = 4;
+    const BLOCKS: usize = 12;
+    const KEY_BLOCK: usize = 5;
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let running = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let max_running = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    // Blocks after the key block are signed by its validator set
+    let chain = (0..BLOCKS)
+        .map(|i| (i, if i > KEY_BLOCK { Some(KEY_BLOCK) } else { None }))
+        .collect::<Vec<_>>();
+    let check = {
+        let events = events.clone();
+        let running = running.clone();
+        let max_running = max_running.clone();
+        Arc::new(move |block: &usize, prev_key_block: Option<&usize>| -> Result<()> {
+            if *block > KEY_BLOCK {
+                assert_eq!(prev_key_block, Some(&KEY_BLOCK));
+            } else {
+                assert!(prev_key_block.is_none());
+            }
+            let now = running.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, std::sync::atomic::Ordering::SeqCst);
+            events.lock().unwrap().push((*block, true));
+            std::thread::sleep(Duration::from_millis(20));
+            events.lock().unwrap().push((*block, false));
+            running.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        })
+    };
+    check_in_parallel(chain.clone(), PARALLELISM, check).await?;
+
+    let events = events.lock().unwrap().clone();
+    assert_eq!(events.len(), 2 * BLOCKS);
+    let key_block_checked = events.iter().position(|event| *event == (KEY_BLOCK, false)).unwrap();
+    for (i, (block, started)) in events.iter().enumerate() {
+        if *started && (*block > KEY_BLOCK) {
+            assert!(i > key_block_checked, "block {} checked before key block", block);
+        }
+    }
+    let max_running = max_running.load(std::sync::atomic::Ordering::SeqCst);
+    assert!(max_running > 1);
+    assert!(max_running <= PARALLELISM);
+
+    // Dependent checks fail after failed key block, the others pass
+    let checked = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let check = {
+        let checked = checked.clone();
+        Arc::new(move |block: &usize, _: Option<&usize>| -> Result<()> {
+            if *block == KEY_BLOCK {
+                fail!("Bad signatures")
+            }
+            checked.lock().unwrap().push(*block);
+            Ok(())
+        })
+    };
+    let err = check_in_parallel(chain, PARALLELISM, check).await.unwrap_err();
+    assert!(err.to_string().contains("Bad signatures"));
+    let mut checked = checked.lock().unwrap().clone();
+    checked.sort();
+    assert_eq!(checked, (0..KEY_BLOCK).collect::<Vec<_>>());
+
+    // Dependency must precede the check
+    let check = Arc::new(|_: &usize, _: Option<&usize>| -> Result<()> { Ok(()) });
+    assert!(check_in_parallel(vec![(0, Some(1)), (1, None)], PARALLELISM, check).await.is_err());
+    Ok(())
+}
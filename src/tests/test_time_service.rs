@@ -0,0 +1,148 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+const START: u32 = 1_700_000_000;
+
+fn config() -> TimeServiceConfig {
+    TimeServiceConfig {
+        window: 8,
+        min_observations: 3,
+        expected_block_delay_ms: 2000,
+        ..Default::default()
+    }
+}
+
+// Blocks are collated every 5 seconds by the network clock and applied after `delays` ms,
+// `offset` is what has to be added to the local clock
+fn observe_blocks(estimator: &mut OffsetEstimator, offset: i64, delays: &[i64]) {
+    for (i, delay) in delays.iter().enumerate() {
+        let gen_ms = (START as i64 + 5 * i as i64) * 1000;
+        estimator.observe_block((gen_ms / 1000) as u32, (gen_ms + delay - offset) as u64);
+    }
+}
+
+fn ntp_timestamp(ms: u64) -> [u8; 8] {
+    let mut data = [0; 8];
+    data[0..4].copy_from_slice(&((ms / 1000 + NTP_UNIX_OFFSET_SEC) as u32).to_be_bytes());
+    data[4..8].copy_from_slice(&(((((ms % 1000) << 32) + 999) / 1000) as u32).to_be_bytes());
+    data
+}
+
+fn ntp_response(server_received_ms: u64, server_sent_ms: u64) -> Vec<u8> {
+    let mut response = vec![0; NTP_PACKET_LEN];
+    response[0] = 0x24;
+    response[1] = 2;
+    response[32..40].copy_from_slice(&ntp_timestamp(server_received_ms));
+    response[40..48].copy_from_slice(&ntp_timestamp(server_sent_ms));
+    response
+}
+
+#[test]
+fn test_offset_estimator_blocks() {
+    let mut estimator = OffsetEstimator::new(&config());
+    observe_blocks(&mut estimator, 0, &[800, 1500]);
+    assert_eq!(estimator.estimate(), None);
+
+    // Usual lag of applying is not a drift
+    observe_blocks(&mut estimator, 0, &[800, 1500, 2500, 1200]);
+    assert_eq!(estimator.estimate(), Some((0, DriftSource::Blocks)));
+
+    // Local clock is behind: blocks look generated in the future.
+    // The fastest applied block is skipped with the highest quarter
+    let mut estimator = OffsetEstimator::new(&config());
+    observe_blocks(&mut estimator, 5000, &[800, 1500, 2500, 1200]);
+    assert_eq!(estimator.estimate(), Some((5000 - 1200, DriftSource::Blocks)));
+
+    // Local clock is ahead: lag is more than expected
+    let mut estimator = OffsetEstimator::new(&config());
+    observe_blocks(&mut estimator, -20000, &[800, 1500, 2500, 1200]);
+    assert_eq!(estimator.estimate(), Some((-20000 - 1200 + 3000, DriftSource::Blocks)));
+
+    // A block of a validator with fast clock and a late one don't matter
+    let mut estimator = OffsetEstimator::new(&config());
+    observe_blocks(&mut estimator, -20000, &[800, 1500, -30000, 2500, 50000, 1200, 900, 1000]);
+    assert_eq!(estimator.estimate(), Some((-20000 - 900 + 3000, DriftSource::Blocks)));
+}
+
+#[test]
+fn test_offset_estimator_window_and_ntp() {
+    let mut estimator = OffsetEstimator::new(&config());
+    observe_blocks(&mut estimator, -20000, &[1000; 8]);
+    // Old observations are evicted
+    observe_blocks(&mut estimator, 0, &[1000; 8]);
+    assert_eq!(estimator.estimate(), Some((0, DriftSource::Blocks)));
+
+    // NTP takes precedence, median is used
+    for offset in [-150, 30000, -120, -100, -130] {
+        estimator.observe_ntp(offset);
+    }
+    assert_eq!(estimator.estimate(), Some((-120, DriftSource::Ntp)));
+}
+
+#[test]
+fn test_ntp_offset() {
+    // Local clock is 1500 ms behind, delay is 40 ms each way
+    let sent = 1_700_000_000_000;
+    let response = ntp_response(sent + 1540, sent + 1550);
+    assert_eq!(ntp_offset_ms(&response, sent, sent + 90).unwrap(), 1500);
+
+    let mut refused = response.clone();
+    refused[1] = 0;
+    assert!(ntp_offset_ms(&refused, sent, sent + 90).is_err());
+    let mut client = response.clone();
+    client[0] = NTP_CLIENT_HEADER;
+    assert!(ntp_offset_ms(&client, sent, sent + 90).is_err());
+    assert!(ntp_offset_ms(&response[..40], sent, sent + 90).is_err());
+}
+
+#[test]
+fn test_time_service_refuses_collation() {
+    let service = TimeService::new(TimeServiceConfig { max_collation_drift_ms: 10000, ..config() });
+    service.check_collation().unwrap();
+
+    service.offset_ms.store(-10000, Ordering::Relaxed);
+    service.check_collation().unwrap();
+    service.offset_ms.store(-10001, Ordering::Relaxed);
+    let err = service.check_collation().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<NodeError>(),
+        Some(NodeError::ClockDrift { drift_ms: -10001, max_drift_ms: 10000 })
+    ));
+    service.offset_ms.store(10001, Ordering::Relaxed);
+    assert!(service.check_collation().is_err());
+
+    // Adjusted time follows the drift
+    let local = local_now_ms();
+    let adjusted = service.adjusted_now_ms();
+    assert!(adjusted >= local + 10001 && adjusted < local + 11001);
+
+    // Zero threshold disables the check
+    let service = TimeService::new(TimeServiceConfig { max_collation_drift_ms: 0, ..config() });
+    service.offset_ms.store(1_000_000, Ordering::Relaxed);
+    service.check_collation().unwrap();
+}
+
+#[test]
+fn test_time_service_keeps_offset_without_observations() {
+    let service = TimeService::new(config());
+    service.refresh();
+    assert_eq!(service.drift_ms(), 0);
+    for i in 0..3 {
+        service.estimator.lock().observe_block(START + i, (START + i) as u64 * 1000 - 60000);
+    }
+    service.refresh();
+    assert_eq!(service.drift_ms(), 60000);
+    assert!(service.adjusted_now() >= (local_now_ms() / 1000) as u32 + 59);
+}
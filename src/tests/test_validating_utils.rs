@@ -0,0 +1,154 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::{
+    CatchainConfig, ConfigParam34, ConfigParamEnum, ShardStateUnsplit, SigPubKey
+};
+use std::sync::Arc;
+
+struct TestKeyBlock {
+    id: BlockIdExt,
+    config: ConfigParams,
+    cc_seqno: u32,
+    pruned: bool,
+}
+
+struct TestKeyBlockHistory {
+    key_blocks: Vec<TestKeyBlock>,
+}
+
+#[async_trait::async_trait]
+impl KeyBlockHistory for TestKeyBlockHistory {
+    fn key_block_ids(&self, max_seq_no: u32) -> Result<Vec<BlockIdExt>> {
+        Ok(self.key_blocks.iter().map(|kb| kb.id.clone()).filter(|id| id.seq_no() <= max_seq_no).collect())
+    }
+
+    async fn load_key_block_config(&self, id: &BlockIdExt, _shard: &ShardIdent) -> Result<(ConfigParams, u32)> {
+        match self.key_blocks.iter().find(|kb| &kb.id == id) {
+            Some(kb) if !kb.pruned => Ok((kb.config.clone(), kb.cc_seqno)),
+            _ => fail!("Key block {} is pruned", id)
+        }
+    }
+}
+
+fn make_id(seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(ShardIdent::masterchain(), seq_no, UInt256::rand(), UInt256::rand())
+}
+
+fn make_config(utime_since: u32) -> Result<ConfigParams> {
+    let mut list = Vec::new();
+    for weight in 1..=7 {
+        let key = SigPubKey::from_bytes(UInt256::rand().as_slice())?;
+        list.push(ValidatorDescr::with_params(key, weight, None, None));
+    }
+    let vset = ValidatorSet::new(utime_since, utime_since + 1000, 4, list)?;
+    let cc_config = CatchainConfig {
+        isolate_mc_validators: false,
+        shuffle_mc_validators: true,
+        mc_catchain_lifetime: 250,
+        shard_catchain_lifetime: 250,
+        shard_validators_lifetime: 1000,
+        shard_validators_num: 3,
+    };
+    let mut config = ConfigParams::new();
+    config.set_config(ConfigParamEnum::ConfigParam28(cc_config))?;
+    config.set_config(ConfigParamEnum::ConfigParam34(ConfigParam34 { cur_validators: vset }))?;
+    Ok(config)
+}
+
+fn make_mc_state(seq_no: u32, cc_seqno: u32, config: &ConfigParams) -> Result<Arc<ShardStateStuff>> {
+    let mut extra = McStateExtra {
+        config: config.clone(),
+        ..Default::default()
+    };
+    extra.validator_info.catchain_seqno = cc_seqno;
+    let mut ss = ShardStateUnsplit::with_ident(ShardIdent::masterchain());
+    ss.write_custom(Some(&extra))?;
+    ShardStateStuff::from_state(
+        make_id(seq_no),
+        ss,
+        #[cfg(feature = "telemetry")]
+        &crate::collator_test_bundle::create_engine_telemetry(),
+        &crate::collator_test_bundle::create_engine_allocated()
+    )
+}
+
+// Key blocks 10, 20, 30 are generated in sessions 5, 9, 14 with configs since 1000, 2000, 3000
+fn make_history() -> Result<TestKeyBlockHistory> {
+    let mut key_blocks = Vec::new();
+    for (seq_no, cc_seqno, utime_since) in [(10, 5, 1000), (20, 9, 2000), (30, 14, 3000)] {
+        key_blocks.push(TestKeyBlock {
+            id: make_id(seq_no),
+            config: make_config(utime_since)?,
+            cc_seqno,
+            pruned: false
+        });
+    }
+    Ok(TestKeyBlockHistory { key_blocks })
+}
+
+#[tokio::test]
+async fn test_compute_validator_set_at() -> Result<()> {
+    let history = make_history()?;
+    let shard = ShardIdent::masterchain();
+    let mc_state = make_mc_state(35, 16, &history.key_blocks[2].config)?;
+
+    // Boundary sessions are resolved to the config of the preceding key block
+    for (cc_seqno, key_block) in [(6, 0), (9, 0), (10, 1), (14, 1), (15, 2), (16, 2)] {
+        let (validators, utime_since) = history.compute_validator_set_at(&shard, cc_seqno, &mc_state).await?;
+        let config = &history.key_blocks[key_block].config;
+        assert_eq!(utime_since, config.validator_set()?.utime_since(), "cc_seqno {}", cc_seqno);
+        assert_eq!(validators, compute_validator_set_by_config(config, &shard, cc_seqno)?);
+        assert_eq!(validators.len(), 4);
+    }
+
+    // Current session matches the live path
+    let (validators, _) = history.compute_validator_set_at(&shard, 16, &mc_state).await?;
+    assert_eq!(validators, compute_validator_set_cc(&mc_state, &shard, 35, 16, &mut 0)?);
+
+    // Sessions are shuffled by cc_seqno
+    let (first, _) = history.compute_validator_set_at(&shard, 10, &mc_state).await?;
+    let mut shuffled = false;
+    for cc_seqno in 11..=14 {
+        let (validators, _) = history.compute_validator_set_at(&shard, cc_seqno, &mc_state).await?;
+        shuffled |= validators != first;
+    }
+    assert!(shuffled);
+
+    // Future session and sessions before the oldest key block can't be resolved
+    assert!(history.compute_validator_set_at(&shard, 17, &mc_state).await.is_err());
+    let err = history.compute_validator_set_at(&shard, 5, &mc_state).await.unwrap_err();
+    assert!(err.to_string().contains("pruned"), "{}", err);
+
+    // Key blocks newer than the state are not used
+    let old_state = make_mc_state(25, 12, &history.key_blocks[1].config)?;
+    let (_, utime_since) = history.compute_validator_set_at(&shard, 12, &old_state).await?;
+    assert_eq!(utime_since, 2000);
+    assert!(history.compute_validator_set_at(&shard, 15, &old_state).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_compute_validator_set_at_pruned() -> Result<()> {
+    let mut history = make_history()?;
+    history.key_blocks[0].pruned = true;
+    let shard = ShardIdent::masterchain();
+    let mc_state = make_mc_state(35, 16, &history.key_blocks[2].config)?;
+
+    let err = history.compute_validator_set_at(&shard, 7, &mc_state).await.unwrap_err();
+    assert!(err.to_string().contains("pruned"), "{}", err);
+    let (_, utime_since) = history.compute_validator_set_at(&shard, 10, &mc_state).await?;
+    assert_eq!(utime_since, 2000);
+    Ok(())
+}
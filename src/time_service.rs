@@ -0,0 +1,222 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{config::TimeServiceConfig, engine::now_duration, error::NodeError};
+
+use ever_block::{error, fail, Result};
+use std::{
+    collections::VecDeque, sync::atomic::{AtomicI64, Ordering}, time::Duration
+};
+
+#[cfg(test)]
+#[path = "tests/test_time_service.rs"]
+mod tests;
+
+const NTP_PACKET_LEN: usize = 48;
+// LI = 0, VN = 4, Mode = 3 (client)
+const NTP_CLIENT_HEADER: u8 = 0x23;
+const NTP_MODE_SERVER: u8 = 4;
+// Seconds between 1900 (NTP era) and 1970 (unix epoch)
+const NTP_UNIX_OFFSET_SEC: u64 = 2_208_988_800;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DriftSource {
+    Blocks,
+    Ntp,
+}
+
+// Offset is the value to add to the local clock to get the network time.
+// A masterchain block is applied after it was collated, so its gen_utime gives only a lower
+// bound of the offset. The upper bound is taken as the lower one plus the expected delay, and
+// the offset closest to zero within the bounds is chosen, so the usual lag is not taken as drift.
+// NTP gives the offset itself and takes precedence.
+pub struct OffsetEstimator {
+    window: usize,
+    min_observations: usize,
+    block_delay_ms: i64,
+    blocks: VecDeque<i64>,
+    ntp: VecDeque<i64>,
+}
+
+impl OffsetEstimator {
+
+    pub fn new(config: &TimeServiceConfig) -> Self {
+        Self {
+            window: config.window.max(1),
+            min_observations: config.min_observations.max(1),
+            // gen_utime is rounded down to seconds
+            block_delay_ms: config.expected_block_delay_ms as i64 + 1000,
+            blocks: VecDeque::new(),
+            ntp: VecDeque::new(),
+        }
+    }
+
+    pub fn observe_block(&mut self, gen_utime: u32, local_ms: u64) {
+        Self::push(&mut self.blocks, self.window, gen_utime as i64 * 1000 - local_ms as i64)
+    }
+
+    pub fn observe_ntp(&mut self, offset_ms: i64) {
+        Self::push(&mut self.ntp, self.window, offset_ms)
+    }
+
+    // None if there are not enough observations
+    pub fn estimate(&self) -> Option<(i64, DriftSource)> {
+        if !self.ntp.is_empty() {
+            let mut offsets = self.ntp.iter().cloned().collect::<Vec<_>>();
+            offsets.sort_unstable();
+            return Some((offsets[offsets.len() / 2], DriftSource::Ntp))
+        }
+        if self.blocks.len() < self.min_observations {
+            return None
+        }
+        let mut bounds = self.blocks.iter().cloned().collect::<Vec<_>>();
+        bounds.sort_unstable();
+        // Highest quarter is skipped: blocks of validators with fast clocks are there
+        let lower = bounds[bounds.len() - 1 - bounds.len() / 4];
+        let upper = lower + self.block_delay_ms;
+        let offset = if lower > 0 {
+            lower
+        } else if upper < 0 {
+            upper
+        } else {
+            0
+        };
+        Some((offset, DriftSource::Blocks))
+    }
+
+    fn push(observations: &mut VecDeque<i64>, window: usize, value: i64) {
+        if observations.len() >= window {
+            observations.pop_front();
+        }
+        observations.push_back(value);
+    }
+}
+
+pub struct TimeService {
+    config: TimeServiceConfig,
+    estimator: parking_lot::Mutex<OffsetEstimator>,
+    offset_ms: AtomicI64,
+}
+
+impl TimeService {
+
+    pub fn new(config: TimeServiceConfig) -> Self {
+        Self {
+            estimator: parking_lot::Mutex::new(OffsetEstimator::new(&config)),
+            config,
+            offset_ms: AtomicI64::new(0),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_sec.max(1) as u64)
+    }
+
+    // Called for masterchain blocks applied in real time, not while the node is syncing
+    pub fn observe_block(&self, gen_utime: u32) {
+        self.estimator.lock().observe_block(gen_utime, local_now_ms())
+    }
+
+    pub async fn update(&self) {
+        if let Some(server) = &self.config.ntp_server {
+            let timeout = Duration::from_millis(self.config.ntp_timeout_ms.max(1));
+            match query_ntp(server, timeout).await {
+                Ok(offset) => self.estimator.lock().observe_ntp(offset),
+                Err(e) => log::warn!("Can't query NTP server {}: {}", server, e)
+            }
+        }
+        self.refresh()
+    }
+
+    pub fn drift_ms(&self) -> i64 {
+        self.offset_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn adjusted_now_ms(&self) -> u64 {
+        (local_now_ms() as i64 + self.drift_ms()).max(0) as u64
+    }
+
+    pub fn adjusted_now(&self) -> u32 {
+        (self.adjusted_now_ms() / 1000) as u32
+    }
+
+    pub fn check_collation(&self) -> Result<()> {
+        let drift_ms = self.drift_ms();
+        let max_drift_ms = self.config.max_collation_drift_ms;
+        if max_drift_ms > 0 && drift_ms.unsigned_abs() > max_drift_ms {
+            fail!(NodeError::ClockDrift { drift_ms, max_drift_ms })
+        }
+        Ok(())
+    }
+
+    // Previous offset is kept until there are enough observations
+    fn refresh(&self) {
+        let Some((offset, source)) = self.estimator.lock().estimate() else {
+            return
+        };
+        self.offset_ms.store(offset, Ordering::Relaxed);
+        metrics::gauge!("clock_drift_ms", offset as f64);
+        if offset.unsigned_abs() > self.config.warn_drift_ms {
+            log::warn!("Local clock drift is {} ms (estimated by {:?}), adjusted time is used", offset, source);
+        } else {
+            log::debug!("Local clock drift is {} ms (estimated by {:?})", offset, source);
+        }
+    }
+}
+
+fn local_now_ms() -> u64 {
+    now_duration().as_millis() as u64
+}
+
+async fn query_ntp(server: &str, timeout: Duration) -> Result<i64> {
+    let server = server.to_string();
+    tokio::task::spawn_blocking(move || -> Result<i64> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.connect(&server)?;
+        let mut packet = [0u8; NTP_PACKET_LEN];
+        packet[0] = NTP_CLIENT_HEADER;
+        let sent_ms = local_now_ms();
+        socket.send(&packet)?;
+        let len = socket.recv(&mut packet)?;
+        let received_ms = local_now_ms();
+        ntp_offset_ms(&packet[..len], sent_ms, received_ms)
+    }).await?
+}
+
+// Offset by server receive and transmit timestamps, network delay is taken as symmetric
+fn ntp_offset_ms(response: &[u8], sent_ms: u64, received_ms: u64) -> Result<i64> {
+    if response.len() < NTP_PACKET_LEN {
+        fail!("NTP response is too short: {} bytes", response.len())
+    }
+    if response[0] & 0x07 != NTP_MODE_SERVER {
+        fail!("NTP response has wrong mode {}", response[0] & 0x07)
+    }
+    // Kiss-o'-death packet
+    if response[1] == 0 {
+        fail!("NTP server refused the query")
+    }
+    let server_received_ms = ntp_timestamp_ms(&response[32..40])?;
+    let server_sent_ms = ntp_timestamp_ms(&response[40..48])?;
+    Ok(
+        ((server_received_ms - sent_ms as i64) + (server_sent_ms - received_ms as i64)) / 2
+    )
+}
+
+fn ntp_timestamp_ms(data: &[u8]) -> Result<i64> {
+    let seconds = u32::from_be_bytes(data[0..4].try_into()?) as u64;
+    let fraction = u32::from_be_bytes(data[4..8].try_into()?) as u64;
+    let seconds = seconds.checked_sub(NTP_UNIX_OFFSET_SEC)
+        .ok_or_else(|| error!("NTP timestamp {} is before unix epoch", seconds))?;
+    Ok((seconds * 1000 + ((fraction * 1000) >> 32)) as i64)
+}
@@ -11,14 +11,14 @@
 * limitations under the License.
 */
 
-use crate::engine_traits::EngineAlloc;
+use crate::{engine_traits::EngineAlloc, error::{copy_error, NodeError}};
 #[cfg(feature = "telemetry")]
 use crate::engine_traits::EngineTelemetry;
 use std::{
     sync::{Arc, atomic::AtomicBool, atomic::Ordering}, fmt::Display, hash::Hash, cmp::Ord, 
     time::Duration
 };
-use ever_block::{Result, error};
+use ever_block::{Error, Result, error};
 use adnl::{declare_counted, common::{add_counted_object_to_map, CountedObject, Counter}};
 
 #[cfg(test)]
@@ -28,8 +28,8 @@ mod tests;
 declare_counted!(
     struct OperationAwaiters<R> {
        is_started: AtomicBool,
-       tx: tokio::sync::watch::Sender<Option<std::result::Result<R, String>>>,
-       rx: tokio::sync::watch::Receiver<Option<std::result::Result<R, String>>>
+       tx: tokio::sync::watch::Sender<Option<std::result::Result<R, Error>>>,
+       rx: tokio::sync::watch::Receiver<Option<std::result::Result<R, Error>>>
    }
 );
 
@@ -156,7 +156,9 @@ impl<I, R> AwaitersPool<I, R> where
                 return Ok(None)
             } else if let Some(timeout_ms) = timeout_ms {
                 tokio::time::timeout(Duration::from_millis(timeout_ms), rx.changed()).await
-                    .map_err(|_| error!("{}: timeout {}", self.description, id))?
+                    .map_err(|_| error!(NodeError::NetworkTimeout(
+                        format!("{}: timeout {}", self.description, id)
+                    )))?
             } else {
                 rx.changed().await
             };
@@ -166,7 +168,7 @@ impl<I, R> AwaitersPool<I, R> where
 
             let r = match &*rx.borrow() {
                 Some(Ok(r)) => Ok(Some(r.clone())),
-                Some(Err(e)) => Err(error!("{}", e)),
+                Some(Err(e)) => Err(copy_error(e)),
                 None => continue
             };
             log::trace!("{}: wait_operation: done {}", self.description, id);
@@ -188,8 +190,8 @@ impl<I, R> AwaitersPool<I, R> where
 
         let r = match result {
             Ok(ref r) => Ok(r.clone()),
-            Err(ref e) => Err(format!("{}", e)), // Error doesn't impl Clone, 
-                                                 // so it is impossible to clone full result
+            // Error doesn't impl Clone, so only typed node error is copied with its kind
+            Err(ref e) => Err(copy_error(e)),
         };
         let _ = op_awaiters.tx.send(Some(r));
         result
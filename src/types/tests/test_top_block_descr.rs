@@ -0,0 +1,132 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::{
+    BlockSignaturesPure, CryptoSignature, CryptoSignaturePair, Ed25519KeyOption, SigPubKey,
+    ValidatorBaseInfo
+};
+
+fn shard() -> ShardIdent {
+    ShardIdent::with_tagged_prefix(0, 0x8000_0000_0000_0000).unwrap()
+}
+
+fn block_id(shard: &ShardIdent, seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(
+        shard.clone(),
+        seq_no,
+        UInt256::with_array([seq_no as u8; 32]),
+        UInt256::default()
+    )
+}
+
+// Pre-parsed description, links go from the top block down to the head
+fn descr(seq_nos: &[u32], mc_seq_nos: &[u32], head_prev: u32) -> TopBlockDescrStuff {
+    TopBlockDescrStuff {
+        tbd: TopBlockDescr::with_id_and_signatures(
+            block_id(&shard(), seq_nos[0]),
+            BlockSignatures::default()
+        ),
+        chain_blk_ids: seq_nos.iter().map(|seq_no| block_id(&shard(), *seq_no)).collect(),
+        chain_mc_blk_ids: mc_seq_nos.iter()
+            .map(|seq_no| block_id(&ShardIdent::masterchain(), *seq_no))
+            .collect(),
+        chain_head_prev: vec!(block_id(&shard(), head_prev)),
+        ..Default::default()
+    }
+}
+
+fn is_broken_chain(descr: TopBlockDescrStuff) -> bool {
+    let err = descr.check_chain().unwrap_err();
+    matches!(err.downcast_ref::<NodeError>(), Some(NodeError::TopBlockBrokenChain(_)))
+}
+
+#[test]
+fn test_top_block_descr_broken_chain() {
+    descr(&[12, 11, 10], &[7, 7, 6], 9).check_chain().unwrap();
+
+    // Seqno goes back
+    assert!(is_broken_chain(descr(&[12, 13, 12], &[7, 7, 7], 11)));
+    // Seqno is skipped
+    assert!(is_broken_chain(descr(&[12, 10], &[7, 7], 9)));
+    // Head doesn't follow its previous block
+    assert!(is_broken_chain(descr(&[12, 11], &[7, 7], 11)));
+    // Older block refers to newer masterchain block
+    assert!(is_broken_chain(descr(&[12, 11], &[6, 7], 10)));
+    // Link of another shard
+    let mut other = descr(&[12, 11], &[7, 7], 10);
+    other.chain_blk_ids[1] = block_id(&shard().split().unwrap().0, 11);
+    assert!(is_broken_chain(other));
+}
+
+#[test]
+fn test_top_block_descr_stale_ref() {
+    let descr = descr(&[12, 11], &[100, 99], 10);
+    descr.check_mc_ref(164, 64).unwrap();
+    descr.check_mc_ref(50, 64).unwrap();
+
+    let err = descr.check_mc_ref(165, 64).unwrap_err();
+    match err.downcast_ref::<NodeError>() {
+        Some(NodeError::TopBlockStaleRef { id, ref_mc_seq_no: 100, min_mc_seq_no: 101 }) => {
+            assert_eq!(id, &block_id(&shard(), 12))
+        }
+        _ => panic!("Unexpected error {}", err)
+    }
+}
+
+#[test]
+fn test_top_block_descr_forged_signatures() {
+    let id = block_id(&shard(), 12);
+    let data = Block::build_data_for_sign(id.root_hash(), id.file_hash());
+    let mut validators = Vec::new();
+    let mut keys = Vec::new();
+    for _ in 0..3 {
+        let (pvt_key, pub_key) = Ed25519KeyOption::generate_with_json().unwrap();
+        let public_key = SigPubKey::from_bytes(pub_key.pub_key().unwrap()).unwrap();
+        validators.push(ValidatorDescr::with_params(public_key, 10, None, None));
+        keys.push((Ed25519KeyOption::from_private_key_json(&pvt_key).unwrap(), pub_key));
+    }
+
+    // The first validator signs another block if signature is forged
+    let signed = |forged: bool| {
+        let mut signatures = BlockSignaturesPure::with_weight(30);
+        for (i, (pvt_key, pub_key)) in keys.iter().enumerate() {
+            let data = if forged && i == 0 {
+                Block::build_data_for_sign(&UInt256::default(), id.file_hash())
+            } else {
+                data.clone()
+            };
+            let signature = CryptoSignature::from_bytes(&pvt_key.sign(&data).unwrap()).unwrap();
+            signatures.add_sigpair(CryptoSignaturePair::with_params(
+                pub_key.id().data().clone().into(),
+                signature
+            ));
+        }
+        let signatures = BlockSignatures::with_params(ValidatorBaseInfo::with_params(0, 0), signatures);
+        TopBlockDescrStuff {
+            tbd: TopBlockDescr::with_id_and_signatures(id.clone(), signatures),
+            ..Default::default()
+        }
+    };
+
+    assert_eq!(signed(false).check_signatures(&validators).unwrap(), (30, 30));
+    let err = signed(true).check_signatures(&validators).unwrap_err();
+    assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::TopBlockBadSignatures(_))));
+
+    // Signatures of more than two thirds of weight are needed
+    let (_, pub_key) = Ed25519KeyOption::generate_with_json().unwrap();
+    let public_key = SigPubKey::from_bytes(pub_key.pub_key().unwrap()).unwrap();
+    validators.push(ValidatorDescr::with_params(public_key, 20, None, None));
+    let err = signed(false).check_signatures(&validators).unwrap_err();
+    assert!(matches!(err.downcast_ref::<NodeError>(), Some(NodeError::TopBlockBadSignatures(_))));
+}
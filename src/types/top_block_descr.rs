@@ -13,6 +13,7 @@
 
 use crate::{
     block::construct_and_check_prev_stuff,
+    error::NodeError,
     shard_state::ShardStateStuff,
     validator::validator_utils::check_crypto_signatures, 
     validating_utils::UNREGISTERED_CHAIN_MAX_LEN,
@@ -22,7 +23,7 @@ use crate::validator::validator_utils::calc_subset_for_workchain_standard;
 use ever_block::{
     error, fail, AddSub, Block, BlockInfo, BlockIdExt, BlockSignatures, BocReader, BocWriter,
     Cell, CopyleftRewards, CurrencyCollection, Deserializable, HashmapAugType, McShardRecord,
-    MerkleProof, Result, Serializable, ShardIdent, TopBlockDescr, UInt256
+    MerkleProof, Result, Serializable, ShardIdent, TopBlockDescr, UInt256, ValidatorDescr
 };
 use std::{
     cmp::{max, Ordering, PartialOrd, Ord}, convert::TryInto, fmt, io::{Write, Read, Cursor},
@@ -32,6 +33,10 @@ use ton_api::ton::ton_node::newshardblock::NewShardBlock;
 
 pub use self::id::TopBlockDescrId;
 
+#[cfg(test)]
+#[path = "tests/test_top_block_descr.rs"]
+mod tests;
+
 bitflags::bitflags! {
     pub struct Mode: u8 {
         const DEFAULT = 0;
@@ -50,6 +55,8 @@ pub struct TopBlockDescrStuff {
     chain_mc_blk_ids: Vec<BlockIdExt>,
     chain_blk_ids: Vec<BlockIdExt>,
     chain_fees: Vec<ProofFunds>,
+    // Shard descriptions of links without fees, they are summed up on demand
+    chain_records: Vec<McShardRecord>,
     chain_head_prev: Vec<BlockIdExt>,
     creators: Vec<UInt256>,
     is_fake: bool,
@@ -96,6 +103,7 @@ impl TopBlockDescrStuff {
         let mut chain_mc_blk_ids = vec!();
         let mut chain_blk_ids = vec!();
         let mut chain_fees = vec!();
+        let mut chain_records = vec!();
         let mut creators = vec!();
 
         let mut cur_id = tbd.proof_for().clone();
@@ -136,26 +144,28 @@ impl TopBlockDescrStuff {
             chain_mc_blk_ids.push(parsed.cur_mc_id.clone());
             chain_blk_ids.push(cur_id.clone());
             chain_fees.push(parsed.funds);
+            chain_records.push(parsed.record);
             creators.push(parsed.creator);
 
             next_info = Some((cur_id, parsed.cur_mc_id, parsed.cur_info));
             cur_id = parsed.prev1_id;
         }
 
-        Ok(
-            Self {
-                tbd,
-                info,
-                chain_mc_blk_ids,
-                chain_blk_ids,
-                chain_fees,
-                chain_head_prev,
-                creators,
-                is_fake,
-                is_own,
-                signarutes_validation_result: AtomicI8::new(0),
-            }
-        )
+        let ret = Self {
+            tbd,
+            info,
+            chain_mc_blk_ids,
+            chain_blk_ids,
+            chain_fees,
+            chain_records,
+            chain_head_prev,
+            creators,
+            is_fake,
+            is_own,
+            signarutes_validation_result: AtomicI8::new(0),
+        };
+        ret.check_chain()?;
+        Ok(ret)
     }
 
     pub fn from_bytes(bytes: &[u8], is_fake: bool)-> Result<Self> {
@@ -231,10 +241,10 @@ impl TopBlockDescrStuff {
 //    }
 
     pub fn top_block_mc_seqno_and_creator(&self) -> Result<(u32, UInt256)> {
-        let merkle_proof = MerkleProof::construct_from_cell(self.tbd.chain()[0].clone())?;
-        let block_virt_root = merkle_proof.proof.clone().virtualize(1);
-        let block = Block::construct_from_cell(block_virt_root)?;
-        Ok((block.read_info()?.read_master_id()?.seq_no, block.read_extra()?.created_by().clone()))
+        match (self.chain_mc_blk_ids.first(), self.creators.first()) {
+            (Some(mc_id), Some(creator)) => Ok((mc_id.seq_no(), creator.clone())),
+            _ => fail!("ShardTopBlockDescr for {} has no links", self.proof_for())
+        }
     }
 
     pub fn get_prev_descr(&self, pos: usize, sum_cnt: usize) -> Result<McShardRecord> {
@@ -242,15 +252,7 @@ impl TopBlockDescrStuff {
             fail!("Invalid arguments")
         }
 
-        let merkle_proof = MerkleProof::construct_from_cell(self.tbd.chain()[pos].clone())?;
-        let block_virt_root = merkle_proof.proof.clone().virtualize(1);
-        let block = Block::construct_from_cell(block_virt_root)?;
-        let mut shard_rec = McShardRecord::from_block(&block, self.chain_blk_ids[pos].clone())?;
-
-        shard_rec.descr.fees_collected = CurrencyCollection::default();
-        shard_rec.descr.funds_created = CurrencyCollection::default();
-        shard_rec.descr.copyleft_rewards = CopyleftRewards::default();
-
+        let mut shard_rec = self.chain_records[pos].clone();
         for i in 0..sum_cnt {
             let chain_fees = &self.chain_fees[pos + i];
             shard_rec.descr.fees_collected.add(&chain_fees.fees_collected)?;
@@ -298,6 +300,99 @@ impl TopBlockDescrStuff {
         self.validate_internal(last_mc_block_id, last_mc_state, res_flags, mode)
     }
 
+    // Links go from the top block down to the head. They must be of the same shard with seqno
+    // decreased by one, and masterchain blocks they refer to must not become newer.
+    fn check_chain(&self) -> Result<()> {
+        let broken = |msg: String| error!(NodeError::TopBlockBrokenChain(format!(
+            "ShardTopBlockDescr for {} is invalid: {}", self.proof_for(), msg
+        )));
+        if let Some(top_id) = self.chain_blk_ids.first() {
+            if top_id != self.proof_for() {
+                return Err(broken(format!("its chain starts from block {}", top_id)))
+            }
+        }
+        for (i, id) in self.chain_blk_ids.iter().enumerate() {
+            if id.shard() != self.proof_for().shard() {
+                return Err(broken(format!("link for block {} is of another shard", id)))
+            }
+            let Some(prev_id) = self.chain_blk_ids.get(i + 1) else {
+                continue
+            };
+            if prev_id.seq_no() + 1 != id.seq_no() {
+                return Err(broken(format!(
+                    "intermediate link for block {} changes seqno from {} not by one", id, prev_id.seq_no()
+                )))
+            }
+            let (mc_id, prev_mc_id) = (&self.chain_mc_blk_ids[i], &self.chain_mc_blk_ids[i + 1]);
+            if mc_id.seq_no() < prev_mc_id.seq_no() {
+                return Err(broken(format!(
+                    "link for block {} refers to masterchain block {} while the next block \
+                    refers to an older masterchain block {}",
+                    prev_id, prev_mc_id, mc_id
+                )))
+            }
+            if mc_id.seq_no() == prev_mc_id.seq_no() && mc_id != prev_mc_id {
+                return Err(broken(format!(
+                    "link for block {} refers to masterchain block {} while the next block \
+                    refers to a different same height masterchain block {}",
+                    prev_id, prev_mc_id, mc_id
+                )))
+            }
+        }
+        if let Some(head_id) = self.chain_blk_ids.last() {
+            let max_prev_seq_no = self.chain_head_prev.iter().map(|id| id.seq_no()).max();
+            if max_prev_seq_no.map_or(true, |seq_no| seq_no + 1 != head_id.seq_no()) {
+                return Err(broken(format!(
+                    "initial link for block {} changes seqno from {:?} not by one", head_id, max_prev_seq_no
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    // Description is stale if the masterchain block its top block refers to is older
+    // than last one by more than horizon
+    pub fn check_mc_ref(&self, last_mc_seq_no: u32, horizon: u32) -> Result<()> {
+        let Some(mc_id) = self.chain_mc_blk_ids.first() else {
+            return Ok(())
+        };
+        let min_mc_seq_no = last_mc_seq_no.saturating_sub(horizon);
+        if mc_id.seq_no() < min_mc_seq_no {
+            fail!(NodeError::TopBlockStaleRef {
+                id: self.proof_for().clone(),
+                ref_mc_seq_no: mc_id.seq_no(),
+                min_mc_seq_no
+            })
+        }
+        Ok(())
+    }
+
+    // Returns weight of valid signatures and total weight of validators
+    fn check_signatures(&self, validators: &[ValidatorDescr]) -> Result<(u64, u64)> {
+        let bad = |msg: String| error!(NodeError::TopBlockBadSignatures(format!(
+            "ShardTopBlockDescr for {} {}", self.proof_for(), msg
+        )));
+        let signatures = self.tbd.signatures()
+            .ok_or_else(|| bad("has no signatures".to_string()))?;
+        let checked_data = Block::build_data_for_sign(
+            &self.proof_for().root_hash,
+            &self.proof_for().file_hash
+        );
+        let total_weight: u64 = validators.iter().map(|v| v.weight).sum();
+        let weight = check_crypto_signatures(&signatures.pure_signatures, validators, &checked_data)
+            .map_err(|err| bad(format!("does not have valid signatures: {}", err)))?;
+        if !self.is_fake && weight * 3 <= total_weight * 2 {
+            return Err(bad(format!("has too small signatures weight {} of {}", weight, total_weight)))
+        }
+        if !self.is_fake && weight != signatures.pure_signatures.weight() {
+            return Err(bad(format!(
+                "has incorrect signature weight {} (actual weight is {})",
+                signatures.pure_signatures.weight(), weight
+            )))
+        }
+        Ok((weight, total_weight))
+    }
+
     pub fn validate(&self, last_mc_state: &Arc<ShardStateStuff>) -> Result<i32> {
         let mut res_flags = 0;
 
@@ -401,27 +496,8 @@ impl TopBlockDescrStuff {
 
         */
 
-        if let Some((next_id, next_mc_id, next_info)) = next_info {
-
-            if next_mc_id.seq_no < mc_block_id.seq_no {
-                fail!(
-                    "link for block {} refers to masterchain block {} while the next block \
-                    refers to an older masterchain block {}",
-                    cur_id,
-                    mc_block_id,
-                    next_mc_id
-                )
-            }
-
-            if (next_mc_id.seq_no == mc_block_id.seq_no) && (next_mc_id != mc_block_id) {
-                fail!(
-                    "link for block {} refers to masterchain block {} while the next block \
-                    refers to a different same height masterchain block {}",
-                    cur_id,
-                    mc_block_id,
-                    next_mc_id
-                )
-            }
+        // Seqnos and masterchain refs of links are checked by check_chain
+        if let Some((next_id, _next_mc_id, next_info)) = next_info {
 
             if info.before_split() {
                 fail!("intermediate link for block {} is declared to be before a split", cur_id)
@@ -461,39 +537,13 @@ impl TopBlockDescrStuff {
             if info.after_split() || info.after_merge() {
                 fail!("intermediate link for block {} is after a split or a merge", cur_id);
             }
-            if prev1_id.seq_no() + 1 != cur_id.seq_no() {
-                fail!(
-                    "intermediate link for block {} increases seqno by more than one from {}",
-                    cur_id,
-                    prev1_id.seq_no()
-                )
-            }
-        } else {
-            /*
-            hd_after_split_ = info.after_split;
-            hd_after_merge_ = info.after_merge;
-            CHECK(link_prev_.size() == 1U + info.after_merge);
-            BlockSeqno sq = link_prev_[0].id.seqno;
-            */
-
-            let max_prev = if let Some(prev2_id) = prev2_id.as_ref() {
-                if prev1_id.seq_no() > prev2_id.seq_no() {
-                    &prev1_id
-                } else {
-                    prev2_id
-                }
-            } else {
-                &prev1_id
-            };
-            if max_prev.seq_no() + 1 != cur_id.seq_no() {
-                fail!(
-                    "initial link for block {} increases seqno by more than one from previous {}",
-                    cur_id,
-                    max_prev
-                )
-            }
         }
 
+        let mut record = McShardRecord::from_block(&block, cur_id.clone())?;
+        record.descr.fees_collected = CurrencyCollection::default();
+        record.descr.funds_created = CurrencyCollection::default();
+        record.descr.copyleft_rewards = CopyleftRewards::default();
+
         Ok(ParsedProof {
             prev1_id,
             prev2_id,
@@ -504,6 +554,7 @@ impl TopBlockDescrStuff {
                 funds_created: value_flow.created,
                 copyleft_rewards: value_flow.copyleft_rewards,
             },
+            record,
             creator: extra.created_by,
         })
     }
@@ -780,7 +831,7 @@ impl TopBlockDescrStuff {
         }
 
         if !vset_ok {
-            fail!(
+            fail!(NodeError::TopBlockBadSignatures(format!(
                 "ShardTopBlockDescr for {} is invalid because it refers to shard validator set \
                 with hash {} and catchain_seqno {} while the current masterchain configuration \
                 expects {} and {}",
@@ -789,7 +840,7 @@ impl TopBlockDescrStuff {
                 signatures.validator_info.catchain_seqno,
                 subset.short_hash,
                 cc_seqno
-            )
+            )))
         }
 
         // check range
@@ -801,7 +852,9 @@ impl TopBlockDescrStuff {
         // }
 
         match self.signarutes_validation_result.load(atomic::Ordering::Relaxed) {
-            -1 => fail!("ShardTopBlockDescr for {} has bad signatures (according to cached result)", self.proof_for()),
+            -1 => fail!(NodeError::TopBlockBadSignatures(format!(
+                "ShardTopBlockDescr for {} has bad signatures (according to cached result)", self.proof_for()
+            ))),
             1 => {
                 // log::debug!("ShardTopBlockDescr for {} has valid signatures (according to cached result)", self.proof_for());
                 *res_flags |= 0x10;
@@ -810,45 +863,15 @@ impl TopBlockDescrStuff {
             _ => ()
         }
         
-        let checked_data = Block::build_data_for_sign(
-            &self.proof_for().root_hash,
-            &self.proof_for().file_hash
-        );
-        let total_weight: u64 = subset.validators.iter().map(|v| v.weight).sum();
-        let weight = match check_crypto_signatures(&signatures.pure_signatures, &subset.validators, &checked_data) {
+        let (weight, total_weight) = match self.check_signatures(&subset.validators) {
             Err(err) => {
                 *res_flags |= 0x21;
                 self.signarutes_validation_result.store(-1, atomic::Ordering::Relaxed);
-                fail!(
-                    "ShardTopBlockDescr for {} does not have valid signatures: {}",
-                    self.proof_for(),
-                    err
-                )
+                return Err(err)
             }
-            Ok(w) => w
+            Ok(weights) => weights
         };
-        if !self.is_fake && weight * 3 <= total_weight * 2 {
-            *res_flags |= 0x21;
-            self.signarutes_validation_result.store(-1, atomic::Ordering::Relaxed);
-            fail!(
-                "ShardTopBlockDescr for {} has too small signatures weight {} of {}",
-                self.proof_for(),
-                weight, total_weight
-            );
-        }
         *res_flags |= 0x10;  // signatures checked ok
-
-        // Check weight
-        if !self.is_fake && weight != signatures.pure_signatures.weight() {
-            self.signarutes_validation_result.store(-1, atomic::Ordering::Relaxed);
-            fail!(
-                "ShardTopBlockDescr for {} has incorrect signature weight {} (actual weight is {})",
-                self.proof_for(),
-                signatures.pure_signatures.weight(),
-                weight
-            );
-        }
-
         self.signarutes_validation_result.store(1, atomic::Ordering::Relaxed);
 
         log::debug!(
@@ -868,6 +891,7 @@ struct ParsedProof {
     cur_mc_id: BlockIdExt,
     cur_info: BlockInfo,
     funds: ProofFunds,
+    record: McShardRecord,
     creator: UInt256,
 }
 
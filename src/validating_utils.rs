@@ -11,14 +11,21 @@
 * limitations under the License.
 */
 
-use crate::{validator::validator_utils::compute_validator_set_cc, shard_state::ShardStateStuff};
+use crate::{
+    engine_traits::EngineOperations, internal_db::InternalDb, shard_state::ShardStateStuff,
+    validator::validator_utils::{compute_validator_set_by_config, compute_validator_set_cc}
+};
 use ever_block::{
-    ShardIdent, BlockIdExt, ConfigParams, McStateExtra, ShardHashes, ValidatorSet, McShardRecord,
-    INVALID_WORKCHAIN_ID, MASTERCHAIN_ID, GlobalCapabilities,
+    ShardIdent, BlockIdExt, ConfigParams, McStateExtra, ShardHashes, ValidatorDescr, ValidatorSet,
+    McShardRecord, INVALID_WORKCHAIN_ID, MASTERCHAIN_ID, GlobalCapabilities,
 };
 use ever_block::{fail, error, Result, Sha256, UInt256};
 use std::{collections::HashSet, cmp::max, iter::Iterator};
 
+#[cfg(test)]
+#[path = "tests/test_validating_utils.rs"]
+mod tests;
+
 #[cfg(not(feature = "fast_finality_extra"))]
 pub const UNREGISTERED_CHAIN_MAX_LEN: u32 = 8;
 #[cfg(feature = "fast_finality_extra")]
@@ -291,6 +298,100 @@ pub fn check_cur_validator_set(
     Ok(true)
 }
 
+// True if the node is in the current validator set and validates or is about to
+pub fn is_in_current_validator_set(engine: &dyn EngineOperations) -> bool {
+    engine.validation_status().allows_validate()
+}
+
+fn shard_cc_seqno(mc_state: &ShardStateStuff, shard: &ShardIdent) -> Result<u32> {
+    if shard.is_masterchain() {
+        Ok(mc_state.shard_state_extra()?.validator_info.catchain_seqno)
+    } else {
+        mc_state.shards()?.calc_shard_cc_seqno(shard)
+    }
+}
+
+// Stored key blocks, they give validator sets of past sessions.
+// Config of a key block is used by sessions started after the block.
+#[async_trait::async_trait]
+pub trait KeyBlockHistory: Sync + Send {
+    // Ids of stored masterchain key blocks with seqno up to `max_seq_no`, in seqno order
+    fn key_block_ids(&self, max_seq_no: u32) -> Result<Vec<BlockIdExt>>;
+
+    // Returns config of the key block and catchain seqno of `shard` at the key block
+    async fn load_key_block_config(&self, id: &BlockIdExt, shard: &ShardIdent) -> Result<(ConfigParams, u32)>;
+
+    // Returns validator set of `shard` session `cc_seqno` and utime_since of the config used.
+    // `mc_state` is the newest state to look from.
+    async fn compute_validator_set_at(
+        &self,
+        shard: &ShardIdent,
+        cc_seqno: u32,
+        mc_state: &ShardStateStuff
+    ) -> Result<(Vec<ValidatorDescr>, u32)> {
+        let current_cc_seqno = shard_cc_seqno(mc_state, shard)?;
+        if cc_seqno > current_cc_seqno {
+            fail!(
+                "Session {} of {} is not started yet, current is {} at {}",
+                cc_seqno, shard, current_cc_seqno, mc_state.block_id()
+            )
+        }
+        let ids = self.key_block_ids(mc_state.seq_no())?;
+        for id in ids.iter().rev() {
+            let (config, key_block_cc_seqno) = self.load_key_block_config(id, shard).await
+                .map_err(|e| error!(
+                    "Can't get config of key block {} for session {} of {}: {}", id, cc_seqno, shard, e
+                ))?;
+            if key_block_cc_seqno < cc_seqno {
+                let validators = compute_validator_set_by_config(&config, shard, cc_seqno)?;
+                return Ok((validators, config.validator_set()?.utime_since()))
+            }
+        }
+        match ids.first() {
+            Some(id) => fail!(
+                "Key block with config for session {} of {} is pruned, the oldest stored one is {}",
+                cc_seqno, shard, id
+            ),
+            None => fail!("There are no stored key blocks to find session {} of {}", cc_seqno, shard)
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl KeyBlockHistory for InternalDb {
+    fn key_block_ids(&self, max_seq_no: u32) -> Result<Vec<BlockIdExt>> {
+        let mut ids = Vec::new();
+        self.for_each_key_block_handle(&mut |handle| {
+            if handle.id().shard().is_masterchain() && handle.id().seq_no() <= max_seq_no {
+                ids.push(handle.id().clone());
+            }
+            Ok(true)
+        })?;
+        Ok(ids)
+    }
+
+    // Block is preferred, masterchain config may be also read from the proof
+    async fn load_key_block_config(&self, id: &BlockIdExt, shard: &ShardIdent) -> Result<(ConfigParams, u32)> {
+        let handle = self.load_block_handle(id)?
+            .ok_or_else(|| error!("Cannot load handle of key block {}", id))?;
+        if handle.has_data() {
+            let block = self.load_block_data(&handle).await?;
+            let cc_seqno = if shard.is_masterchain() {
+                block.block()?.read_info()?.gen_catchain_seqno()
+            } else {
+                block.shards()?.calc_shard_cc_seqno(shard)?
+            };
+            Ok((block.get_config_params()?, cc_seqno))
+        } else if handle.has_proof() && shard.is_masterchain() {
+            let proof = self.load_block_proof(&handle, false).await?;
+            let (block, _) = proof.virtualize_block()?;
+            Ok((proof.get_config_params()?, block.read_info()?.gen_catchain_seqno()))
+        } else {
+            fail!("Key block {} is pruned", id)
+        }
+    }
+}
+
 pub fn may_update_shard_block_info(
     shards: &ShardHashes,
     new_info: &McShardRecord,
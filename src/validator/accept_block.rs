@@ -22,7 +22,7 @@ use crate::{
     validator::validator_utils::check_crypto_signatures,
     validating_utils::{UNREGISTERED_CHAIN_MAX_LEN, fmt_block_id_short},
 };
-use storage::block_handle_db::BlockHandle;
+use storage::block_handle_db::{BlockHandle, BlockOrigin};
 use std::{cmp::{max, min}, sync::Arc, ops::Deref, time::Duration, collections::HashSet};
 use ever_block::{
     error, fail, Block, BlockIdExt, BlockProof, BlockSignatures, BlockSignaturesPure,
@@ -235,8 +235,11 @@ pub async fn accept_block_routine(
         None
     };
 
-    #[cfg(feature = "telemetry")]
-    let mut block_broadcast = block_opt.is_some();
+    let origin = if block_opt.is_some() {
+        BlockOrigin::Synthesized
+    } else {
+        BlockOrigin::Download
+    };
 
     let block_descr = fmt_block_id_short(&id);
 
@@ -265,17 +268,10 @@ pub async fn accept_block_routine(
     } else {
         log::debug!(target: "validator", "({}): accept_block: storing block", block_descr);
         let result = engine.store_block(&block).await?;
-        #[cfg(feature = "telemetry")]
-        if block_broadcast {
-            block_broadcast = result._is_updated();
-        }
         let handle = result.to_non_created().ok_or_else(
             || error!("INTERNAL ERROR: accept for block {} mismatch", id)
         )?;
-        #[cfg(feature = "telemetry")]
-        if block_broadcast {
-            handle.set_got_by_broadcast(true);
-        }
+        handle.set_origin(origin);
         handle
     };
 
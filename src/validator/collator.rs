@@ -1176,6 +1176,12 @@ impl Collator {
             if self.prev_blocks_ids.len() > 1 { format!("{}", self.prev_blocks_ids[1]) } else { "".to_owned() }
         );
         self.init_timeout();
+        if let Some(time_service) = self.engine.time_service() {
+            time_service.check_collation().map_err(|e| {
+                log::warn!("{}: COLLATION REFUSED: {}", self.collated_block_descr, e);
+                e
+            })?;
+        }
 
         let mut collator_data;
         let mut attempt = 0;
@@ -1273,6 +1279,9 @@ impl Collator {
             collator_data.execute_count as u32,
             collator_data.block_limit_status.gas_used()
         );
+        #[cfg(not(test))]
+        #[cfg(feature = "telemetry")]
+        self.engine.collation_timing_telemetry().collation_finished(&self.shard, self.started.elapsed());
 
         Ok((candidate, state))
     }
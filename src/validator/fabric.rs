@@ -225,6 +225,16 @@ pub async fn run_collate_query (
 
     let next_block_descr = fmt_next_block_descr_from_next_seqno(&shard, get_first_block_seqno_after_prevs(&prev));
 
+    // Collation changes statuses of REMP messages, so their context for a test bundle is taken before
+    let remp_context = if remp_collator_interface.is_some() && engine.test_bundles_config().collator.is_enable() {
+        engine.remp_message_cache_snapshot().unwrap_or_else(|e| {
+            log::warn!("({}): cannot take REMP messages for test bundle: {}", next_block_descr, e);
+            None
+        })
+    } else {
+        None
+    };
+
     let collator = collator::Collator::new(
         shard.clone(),
         min_mc_seqno,
@@ -279,6 +289,9 @@ pub async fn run_collate_query (
                                 Err(e) => log::error!("({}): Error while test bundle for {} building: {}", next_block_descr, id, e),
                                 Ok(mut b) => {
                                     b.set_notes(err_str.to_string());
+                                    if let Some(remp_context) = remp_context {
+                                        b.set_remp_context(remp_context);
+                                    }
                                     if let Err(e) = b.save(&path) {
                                         log::error!("({}): Error while test bundle for {} saving: {}", next_block_descr, id, e);
                                     } else {
@@ -0,0 +1,380 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::config::KeyRotationConfig;
+
+use ever_block::{error, fail, ByteOrderRead, ConfigParamEnum, ConfigParams, Result};
+use std::{fmt, io::Cursor, time::Duration};
+
+#[cfg(test)]
+#[path = "tests/test_key_rotation.rs"]
+mod tests;
+
+// Key of the rotation state in the validator state DB
+pub const KEY_ROTATION_STATE: &str = "KeyRotationState";
+const KEY_ROTATION_STATE_VERSION: u8 = 1;
+
+// Election timing by the masterchain config
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElectionTiming {
+    pub validators_elected_for: u32,
+    pub elections_start_before: u32,
+    pub elections_end_before: u32,
+    // End of the round of the current validator set
+    pub cur_until: u32,
+    // Validator set of the next round is elected already
+    pub next_elected: bool,
+}
+
+impl ElectionTiming {
+
+    pub fn from_config(config: &ConfigParams) -> Result<Self> {
+        let elector_params = match config.config(15)? {
+            Some(ConfigParamEnum::ConfigParam15(param)) => param,
+            _ => fail!("No election timing parameters (15) in config")
+        };
+        Ok(Self {
+            validators_elected_for: elector_params.validators_elected_for,
+            elections_start_before: elector_params.elections_start_before,
+            elections_end_before: elector_params.elections_end_before,
+            cur_until: config.validator_set()?.utime_until(),
+            next_elected: config.next_validator_set()?.total() > 0,
+        })
+    }
+
+    // Keys for the next elections are rotated after the elections are announced
+    // and not later than `margin_sec` before they end
+    pub fn next_window(&self, margin_sec: u32) -> RotationWindow {
+        let election_id = if self.next_elected {
+            self.cur_until.saturating_add(self.validators_elected_for)
+        } else {
+            self.cur_until
+        };
+        RotationWindow {
+            election_id,
+            opens_at: election_id.saturating_sub(self.elections_start_before),
+            closes_at: election_id.saturating_sub(self.elections_end_before).saturating_sub(margin_sec),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RotationWindow {
+    // Elections are identified by the start of the elected round
+    pub election_id: u32,
+    pub opens_at: u32,
+    pub closes_at: u32,
+}
+
+impl RotationWindow {
+    fn contains(&self, now: u32) -> bool {
+        self.opens_at <= now && now < self.closes_at
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotationPhase {
+    // Waiting for the rotation window of the next elections
+    Idle = 0,
+    // New keys are generated or taken from the provided ones, not registered yet
+    Prepared = 1,
+    // New keys are registered, old keys are used until the round of new keys starts
+    Registered = 2,
+    // Round of new keys has started, old keys are retired as their sessions end
+    Retiring = 3,
+}
+
+impl RotationPhase {
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Idle),
+            1 => Ok(Self::Prepared),
+            2 => Ok(Self::Registered),
+            3 => Ok(Self::Retiring),
+            _ => fail!("Unknown key rotation phase {}", value)
+        }
+    }
+}
+
+impl fmt::Display for RotationPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// Pair of validator key and its ADNL key, both are hashes of keys in the key ring
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RotationKeys {
+    pub key_id: [u8; 32],
+    pub adnl_key_id: [u8; 32],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RotationState {
+    pub phase: RotationPhase,
+    // Elections new keys are rotated for, 0 while idle
+    pub election_id: u32,
+    // The latest elections keys were rotated for
+    pub last_election_id: u32,
+    pub new_keys: Option<RotationKeys>,
+    // Validator keys with their election ids to retire
+    pub old_keys: Vec<([u8; 32], i32)>,
+    // Keys provided by operator for the next rotation instead of generated ones
+    pub provided_keys: Option<RotationKeys>,
+}
+
+impl Default for RotationState {
+    fn default() -> Self {
+        Self {
+            phase: RotationPhase::Idle,
+            election_id: 0,
+            last_election_id: 0,
+            new_keys: None,
+            old_keys: Vec::new(),
+            provided_keys: None,
+        }
+    }
+}
+
+impl RotationState {
+
+    pub fn serialize(&self) -> Vec<u8> {
+        fn put_keys(data: &mut Vec<u8>, keys: &Option<RotationKeys>) {
+            match keys {
+                Some(keys) => {
+                    data.push(1);
+                    data.extend_from_slice(&keys.key_id);
+                    data.extend_from_slice(&keys.adnl_key_id);
+                }
+                None => data.push(0)
+            }
+        }
+        let mut data = vec![KEY_ROTATION_STATE_VERSION, self.phase as u8];
+        data.extend_from_slice(&self.election_id.to_le_bytes());
+        data.extend_from_slice(&self.last_election_id.to_le_bytes());
+        put_keys(&mut data, &self.new_keys);
+        put_keys(&mut data, &self.provided_keys);
+        data.extend_from_slice(&(self.old_keys.len() as u32).to_le_bytes());
+        for (key_id, election_id) in &self.old_keys {
+            data.extend_from_slice(key_id);
+            data.extend_from_slice(&election_id.to_le_bytes());
+        }
+        data
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        fn get_keys(cursor: &mut Cursor<&[u8]>) -> Result<Option<RotationKeys>> {
+            match cursor.read_byte()? {
+                0 => Ok(None),
+                _ => Ok(Some(RotationKeys { key_id: cursor.read_u256()?, adnl_key_id: cursor.read_u256()? }))
+            }
+        }
+        let mut cursor = Cursor::new(data);
+        let version = cursor.read_byte()?;
+        if version != KEY_ROTATION_STATE_VERSION {
+            fail!("Unsupported version {} of key rotation state", version)
+        }
+        let phase = RotationPhase::from_u8(cursor.read_byte()?)?;
+        let election_id = cursor.read_le_u32()?;
+        let last_election_id = cursor.read_le_u32()?;
+        let new_keys = get_keys(&mut cursor)?;
+        let provided_keys = get_keys(&mut cursor)?;
+        let count = cursor.read_le_u32()?;
+        let mut old_keys = Vec::new();
+        for _ in 0..count {
+            old_keys.push((cursor.read_u256()?, cursor.read_le_u32()? as i32));
+        }
+        if phase != RotationPhase::Idle && new_keys.is_none() {
+            fail!("Key rotation state in phase {} has no new keys", phase)
+        }
+        Ok(Self { phase, election_id, last_election_id, new_keys, old_keys, provided_keys })
+    }
+}
+
+// Key management the rotation is done through
+#[async_trait::async_trait]
+pub trait KeyRotationOps: Send + Sync {
+    // Generates key pair in the key ring, returns hash of the public key
+    async fn generate_key(&self) -> Result<[u8; 32]>;
+    // Registers validator key for the elections and its ADNL address, repeated call changes nothing
+    async fn register_keys(&self, keys: &RotationKeys, election_id: i32) -> Result<()>;
+    // Removes validator key with its ADNL key, an already removed key is not an error
+    async fn retire_key(&self, key_id: &[u8; 32], election_id: i32) -> Result<()>;
+    // Registered validator keys with their election ids
+    fn validator_keys(&self) -> Result<Vec<([u8; 32], i32)>>;
+    // True while a validator session signs with the key
+    fn key_in_use(&self, key_id: &[u8; 32]) -> bool;
+    fn load_rotation_state(&self) -> Result<Option<Vec<u8>>>;
+    fn save_rotation_state(&self, data: &[u8]) -> Result<()>;
+}
+
+// Rotation plan for the control query
+pub struct RotationPlan {
+    pub state: RotationState,
+    pub next_window: RotationWindow,
+}
+
+// Every elections validator keys are replaced with new ones. The state is saved after every
+// step, so after restart rotation goes on from the same phase; steps are safe to repeat.
+pub struct KeyRotation {
+    config: KeyRotationConfig,
+    // Loaded on first use
+    state: tokio::sync::Mutex<Option<RotationState>>,
+}
+
+impl KeyRotation {
+
+    pub fn new(config: KeyRotationConfig) -> Self {
+        Self { config, state: tokio::sync::Mutex::new(None) }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_sec.max(1) as u64)
+    }
+
+    pub async fn plan(&self, ops: &dyn KeyRotationOps, timing: &ElectionTiming) -> Result<RotationPlan> {
+        let mut state = self.state.lock().await;
+        let state = Self::loaded(&mut state, ops)?;
+        Ok(RotationPlan { state: state.clone(), next_window: timing.next_window(self.config.margin_sec) })
+    }
+
+    // Keys are used by the next rotation, they must be in the key ring
+    pub async fn provide_keys(&self, ops: &dyn KeyRotationOps, keys: RotationKeys) -> Result<()> {
+        let mut state = self.state.lock().await;
+        let state = Self::loaded(&mut state, ops)?;
+        if state.phase != RotationPhase::Idle {
+            fail!("Keys can't be provided while rotation is in phase {}", state.phase)
+        }
+        if ops.validator_keys()?.iter().any(|(key_id, _)| *key_id == keys.key_id) {
+            fail!("Key {} is already a validator key", hex::encode(keys.key_id))
+        }
+        state.provided_keys = Some(keys);
+        ops.save_rotation_state(&state.serialize())
+    }
+
+    // Makes all steps possible at the moment, returns the resulting phase
+    pub async fn update(
+        &self,
+        ops: &dyn KeyRotationOps,
+        timing: &ElectionTiming,
+        now: u32
+    ) -> Result<RotationPhase> {
+        let mut state = self.state.lock().await;
+        let state = Self::loaded(&mut state, ops)?;
+        while self.step(state, ops, timing, now).await? {
+            ops.save_rotation_state(&state.serialize())?;
+            log::info!(
+                "Key rotation for elections {}: phase {}", state.election_id, state.phase
+            );
+        }
+        Ok(state.phase)
+    }
+
+    fn loaded<'a>(
+        state: &'a mut Option<RotationState>,
+        ops: &dyn KeyRotationOps
+    ) -> Result<&'a mut RotationState> {
+        if state.is_none() {
+            let loaded = match ops.load_rotation_state()? {
+                Some(data) => RotationState::deserialize(&data)?,
+                None => RotationState::default()
+            };
+            *state = Some(loaded);
+        }
+        state.as_mut().ok_or_else(|| error!("Key rotation state is not loaded"))
+    }
+
+    // False if nothing is changed
+    async fn step(
+        &self,
+        state: &mut RotationState,
+        ops: &dyn KeyRotationOps,
+        timing: &ElectionTiming,
+        now: u32
+    ) -> Result<bool> {
+        match state.phase {
+            RotationPhase::Idle => {
+                let window = timing.next_window(self.config.margin_sec);
+                if window.election_id <= state.last_election_id || !window.contains(now) {
+                    return Ok(false)
+                }
+                let validator_keys = ops.validator_keys()?;
+                // Node is not a validator, nothing to rotate
+                if validator_keys.is_empty() {
+                    return Ok(false)
+                }
+                // Key for the elections is registered by operator
+                if validator_keys.iter().any(|(_, election_id)| *election_id as u32 >= window.election_id) {
+                    log::info!(
+                        "Validator key for elections {} is registered already, rotation is skipped",
+                        window.election_id
+                    );
+                    state.last_election_id = window.election_id;
+                    return Ok(true)
+                }
+                let keys = match state.provided_keys.take() {
+                    Some(keys) => keys,
+                    None => RotationKeys {
+                        key_id: ops.generate_key().await?,
+                        adnl_key_id: ops.generate_key().await?,
+                    }
+                };
+                state.phase = RotationPhase::Prepared;
+                state.election_id = window.election_id;
+                state.new_keys = Some(keys);
+                Ok(true)
+            }
+            RotationPhase::Prepared => {
+                let keys = state.new_keys.ok_or_else(|| error!("No new keys to register"))?;
+                ops.register_keys(&keys, state.election_id as i32).await?;
+                state.old_keys = ops.validator_keys()?.into_iter()
+                    .filter(|(key_id, election_id)| {
+                        *key_id != keys.key_id && (*election_id as u32) < state.election_id
+                    })
+                    .collect();
+                state.phase = RotationPhase::Registered;
+                Ok(true)
+            }
+            RotationPhase::Registered => {
+                if now < state.election_id {
+                    return Ok(false)
+                }
+                state.phase = RotationPhase::Retiring;
+                Ok(true)
+            }
+            RotationPhase::Retiring => {
+                let mut changed = false;
+                let mut i = 0;
+                while i < state.old_keys.len() {
+                    let (key_id, election_id) = state.old_keys[i];
+                    if ops.key_in_use(&key_id) {
+                        i += 1;
+                        continue
+                    }
+                    ops.retire_key(&key_id, election_id).await?;
+                    log::info!("Validator key {} of elections {} is retired", hex::encode(key_id), election_id);
+                    state.old_keys.remove(i);
+                    changed = true;
+                }
+                if state.old_keys.is_empty() {
+                    state.phase = RotationPhase::Idle;
+                    state.last_election_id = state.election_id;
+                    state.election_id = 0;
+                    state.new_keys = None;
+                    changed = true;
+                }
+                Ok(changed)
+            }
+        }
+    }
+}
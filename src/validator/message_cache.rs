@@ -13,8 +13,9 @@
 
 use std::{
     cmp::max, 
-    collections::HashSet,
+    collections::{HashSet, VecDeque},
     fmt, fmt::{Display, Formatter},
+    io::{Cursor, Read, Write},
     ops::RangeInclusive,
     sync::{Arc, atomic::{AtomicU32, Ordering, Ordering::Relaxed}},
     time::{Duration, SystemTime}
@@ -25,10 +26,13 @@ use dashmap::{DashMap, DashSet};
 #[cfg(feature = "telemetry")]
 use adnl::telemetry::Metric;
 
+#[cfg(feature = "external_db")]
+use crate::{engine_traits::RempStatusTransition, external_db::remp_history::RempHistoryExporter};
 use crate::{
+    config::DuplicatePolicy,
     engine_traits::RempDuplicateStatus,
     ext_messages::{
-        create_ext_message,
+        create_ext_message, create_ext_message_with_time_check,
         get_level_and_level_change, get_level_numeric_value, is_finally_accepted, 
         is_finally_rejected
     },
@@ -39,18 +43,19 @@ use crate::{
 };
 
 use catchain::serialize_tl_boxed_object;
+use storage::remp_messages_db::RempMessagesDb;
 
 use ton_api::{
     IntoBoxed,
     ton::ton_node::{
-        rempmessagestatus::{RempAccepted, RempIgnored},
+        rempmessagestatus::{RempAccepted, RempIgnored, RempRejected},
         RempMessageStatus, RempMessageLevel,
         rempcatchainrecordv2::RempCatchainMessageHeaderV2
     }
 };
 
 use ever_block::{
-    error, fail, BlockIdExt, Deserializable, ExternalInboundMessageHeader, 
+    error, fail, BlockIdExt, ByteOrderRead, Deserializable, ExternalInboundMessageHeader, 
     GetRepresentationHash,
     KeyId, Message,
     MsgAddressInt, MsgAddrStd, Result, Serializable, SliceData, UInt256, UnixTime32
@@ -61,6 +66,27 @@ use ever_block_json::unix_time_to_system_time;
 #[path = "tests/test_message_cache.rs"]
 mod tests;
 
+// Bound memory taken by message traces
+const MAX_TRACED_MESSAGES: usize = 1024;
+const MAX_TRACE_EVENTS: usize = 128;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageTracePoint {
+    Added,
+    StatusChanged,
+    Queued,
+    Forwarded,
+    Collation,
+    BlockIndexed,
+}
+
+#[derive(Clone, Debug)]
+pub struct MessageTraceEvent {
+    pub time: SystemTime,
+    pub point: MessageTracePoint,
+    pub details: String,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct RmqMessage {
     pub message: Arc<Message>,
@@ -84,6 +110,18 @@ impl RmqMessage {
         Self::new_with_id (Arc::new(message), message_id)
     }
 
+    pub fn from_raw_message_with_time_check(
+        raw_msg: &ton_api::ton::bytes, 
+        now: u32, 
+        max_age_secs: u32, 
+        max_future_skew_secs: u32
+    ) -> Result<Self> {
+        let (message_id, message) = create_ext_message_with_time_check(
+            raw_msg, now, max_age_secs, max_future_skew_secs
+        )?;
+        Self::new_with_id (Arc::new(message), message_id)
+    }
+
     pub fn as_remp_message_body(&self) -> ton_api::ton::ton_node::RempMessageBody {
         ton_api::ton::ton_node::rempmessagebody::RempMessageBody {
             message: self.message.write_to_bytes().unwrap().into()
@@ -227,6 +265,8 @@ pub struct RempMessageOrigin {
     pub source_key: Arc<KeyId>,
     pub source_idx: u32,
     pub timestamp: u32,
+    // Unix time given by client, the message is not collated after it
+    pub deadline: Option<u32>,
 }
 
 impl RempMessageOrigin {
@@ -234,7 +274,8 @@ impl RempMessageOrigin {
         Ok(RempMessageOrigin { 
             source_key,
             source_idx,
-            timestamp: Self::timestamp_now()?
+            timestamp: Self::timestamp_now()?,
+            deadline: None
         })
     }
 
@@ -242,10 +283,20 @@ impl RempMessageOrigin {
         RempMessageOrigin { 
             source_key: self.source_key.clone(),
             source_idx,
-            timestamp: self.timestamp
+            timestamp: self.timestamp,
+            deadline: self.deadline
         }
     }
 
+    pub fn with_deadline(mut self, deadline: Option<u32>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn is_expired(&self, now: u32) -> bool {
+        self.deadline.map_or(false, |deadline| deadline < now)
+    }
+
     pub fn as_remp_catchain_record(&self, message_id: &UInt256, message_uid: &UInt256, master_cc: u32) -> ton_api::ton::ton_node::RempCatchainRecordV2 {
         ton_api::ton::ton_node::rempcatchainrecordv2::RempCatchainMessageHeaderV2 {
             message_id: message_id.clone().into(),
@@ -279,11 +330,21 @@ impl RempMessageOrigin {
         Ok(RempMessageOrigin {
             source_key: KeyId::from_data([0; 32]),
             source_idx: 0,
-            timestamp: RempMessageOrigin::timestamp_now()?
+            timestamp: RempMessageOrigin::timestamp_now()?,
+            deadline: None
         })
     }
 }
 
+/// Status of the message rejected because its deadline has passed
+pub fn deadline_expired_status() -> RempMessageStatus {
+    RempMessageStatus::TonNode_RempRejected(RempRejected {
+        level: RempMessageLevel::TonNode_RempQueue,
+        block_id: BlockIdExt::default(),
+        error: "message deadline has passed".to_string()
+    })
+}
+
 impl Display for RempMessageOrigin {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let source_key = if self.has_no_source_key() {
@@ -295,7 +356,11 @@ impl Display for RempMessageOrigin {
 
         write!(f, "source_key {}, source_idx {}, timestamp {}",
                source_key, self.source_idx, self.timestamp
-        )
+        )?;
+        if let Some(deadline) = self.deadline {
+            write!(f, ", deadline {}", deadline)?;
+        }
+        Ok(())
     }
 }
 
@@ -329,6 +394,134 @@ impl Display for RempMessageWithOrigin {
     }
 }
 
+/// Message cache record, as it is kept in the persistent db:
+/// master cc, uid, origin (if known), body (if known) and statuses
+struct PersistedRempMessage {
+    master_cc: u32,
+    header: Arc<RempMessageHeader>,
+    origin: Option<Arc<RempMessageOrigin>>,
+    message: Option<Arc<RmqMessage>>,
+    status: Option<RempMessageStatus>,
+    finally_accepted: Option<RempMessageStatus>,
+}
+
+impl PersistedRempMessage {
+    fn write_chunk(data: &mut Vec<u8>, chunk: &[u8]) -> Result<()> {
+        data.write_all(&(chunk.len() as u32).to_le_bytes())?;
+        data.write_all(chunk)?;
+        Ok(())
+    }
+
+    fn read_chunk(reader: &mut Cursor<&[u8]>) -> Result<Option<Vec<u8>>> {
+        let len = reader.read_le_u32()? as usize;
+        if len == 0 {
+            return Ok(None)
+        }
+        let mut chunk = vec![0; len];
+        reader.read_exact(&mut chunk)?;
+        Ok(Some(chunk))
+    }
+
+    fn write_status(data: &mut Vec<u8>, status: &Option<RempMessageStatus>) -> Result<()> {
+        match status {
+            None => Self::write_chunk(data, &[]),
+            Some(status) => Self::write_chunk(data, &serialize_tl_boxed_object!(status))
+        }
+    }
+
+    fn read_status(reader: &mut Cursor<&[u8]>) -> Result<Option<RempMessageStatus>> {
+        match Self::read_chunk(reader)? {
+            None => Ok(None),
+            Some(raw) => Ok(Some(catchain::utils::deserialize_tl_boxed_object(&raw)?))
+        }
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.write_all(&self.master_cc.to_le_bytes())?;
+        data.write_all(self.header.message_uid.as_slice())?;
+        match &self.origin {
+            None => data.write_all(&[0])?,
+            Some(origin) => {
+                // Origins without deadline are written as before
+                data.write_all(&[if origin.deadline.is_some() { 2 } else { 1 }])?;
+                data.write_all(origin.source_key.data())?;
+                data.write_all(&origin.source_idx.to_le_bytes())?;
+                data.write_all(&origin.timestamp.to_le_bytes())?;
+                if let Some(deadline) = origin.deadline {
+                    data.write_all(&deadline.to_le_bytes())?;
+                }
+            }
+        }
+        match &self.message {
+            None => Self::write_chunk(&mut data, &[])?,
+            Some(message) => Self::write_chunk(&mut data, &message.message.write_to_bytes()?)?
+        }
+        Self::write_status(&mut data, &self.status)?;
+        Self::write_status(&mut data, &self.finally_accepted)?;
+        Ok(data)
+    }
+
+    fn deserialize(message_id: &UInt256, data: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(data);
+        let master_cc = reader.read_le_u32()?;
+        let message_uid = UInt256::from(reader.read_u256()?);
+        let origin = match reader.read_byte()? {
+            0 => None,
+            tag @ (1 | 2) => Some(Arc::new(RempMessageOrigin {
+                source_key: KeyId::from_data(reader.read_u256()?),
+                source_idx: reader.read_le_u32()?,
+                timestamp: reader.read_le_u32()?,
+                deadline: if tag == 2 { Some(reader.read_le_u32()?) } else { None }
+            })),
+            tag => fail!("Persisted message {:x}: incorrect origin tag {}", message_id, tag)
+        };
+        let message = match Self::read_chunk(&mut reader)? {
+            None => None,
+            Some(body) => {
+                let (id, message) = create_ext_message(&body)?;
+                if &id != message_id {
+                    fail!("Persisted message {:x}: body has different id {:x}", message_id, id)
+                }
+                Some(Arc::new(RmqMessage::new_with_id(Arc::new(message), id)?))
+            }
+        };
+        let status = Self::read_status(&mut reader)?;
+        let finally_accepted = Self::read_status(&mut reader)?;
+        if status.is_none() && finally_accepted.is_none() {
+            fail!("Persisted message {:x} has no status", message_id)
+        }
+
+        Ok(Self {
+            master_cc,
+            header: RempMessageHeader::new_arc(message_id, &message_uid),
+            origin,
+            message,
+            status,
+            finally_accepted
+        })
+    }
+}
+
+/// Cached message as it is kept in the snapshot: persisted record,
+/// uid, status and origin are for humans reading the snapshot
+pub struct MessageCacheSnapshotMessage {
+    pub message_id: UInt256,
+    pub message_uid: UInt256,
+    pub status: String,
+    pub origin: String,
+    pub record: Vec<u8>,
+}
+
+/// Cache contents are saved into collator test bundles to replay collation with the same
+/// REMP messages. Sessions are pairs (master cc, start time) of the stored master cc range
+pub struct MessageCacheSnapshot {
+    pub master_cc_range: RangeInclusive<u32>,
+    pub duplicate_policy: DuplicatePolicy,
+    pub sessions: Vec<(u32, u32)>,
+    pub messages: Vec<MessageCacheSnapshotMessage>,
+}
+
 pub struct MessageCacheSession {
     master_cc: u32,
 
@@ -349,6 +542,7 @@ pub struct MessageCacheSession {
     message_events: LockfreeMapSet<UInt256, u32>, //Map<UInt256, Vec<UnixTime32>>,
     message_status: DashMap<UInt256, RempMessageStatus>,
     message_finally_accepted: DashMap<UInt256, RempMessageStatus>,
+    message_traces: DashMap<UInt256, VecDeque<MessageTraceEvent>>,
 
     blocks_processed: DashSet<BlockIdExt>
 }
@@ -623,6 +817,21 @@ impl MessageCacheSession {
         self.message_events.append_to_set(msg_id, &UnixTime32::now().as_u32())
     }
 
+    // The oldest events are dropped when the trace is full
+    fn append_trace_event(&self, msg_id: &UInt256, event: MessageTraceEvent) {
+        let mut trace = self.message_traces.entry(msg_id.clone()).or_default();
+        if trace.len() >= MAX_TRACE_EVENTS {
+            trace.pop_front();
+        }
+        trace.push_back(event);
+    }
+
+    fn get_trace(&self, msg_id: &UInt256) -> Vec<MessageTraceEvent> {
+        self.message_traces.get(msg_id)
+            .map(|trace| trace.value().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
     fn list_ids(&self) -> Vec<UInt256> {
         self.message_headers.iter().map(|v| v.key().clone()).collect()
     }
@@ -646,7 +855,10 @@ impl MessageCacheSession {
             match (self.messages.get(&id), message_status) {
                 (Some(_m),Some(status)) => {
                     if is_finally_accepted(&status) { stats.accepted_in_session += 1 }
-                    else if is_finally_rejected(&status) { stats.rejected_in_session += 1 }
+                    else if is_finally_rejected(&status) {
+                        stats.rejected_in_session += 1;
+                        if status == deadline_expired_status() { stats.expired_by_deadline += 1 }
+                    }
                 },
                 (None,Some(_status)) => stats.has_only_header += 1,
                 (m, h) => {
@@ -661,6 +873,106 @@ impl MessageCacheSession {
         stats
     }
 
+    /// Counts messages by their current statuses. Message ids are collected first,
+    /// so no cache locks are held during the whole walk.
+    fn collect_stats(&self) -> RempSessionStats {
+        let mut stats = RempSessionStats::default();
+        for id in self.list_ids() {
+            let status = match self.get_message_status(&id) {
+                Ok(Some(s)) => s,
+                // Removed in parallel
+                Ok(None) if !self.is_message_present(&id) => continue,
+                Ok(None) | Err(_) => {
+                    stats.total += 1;
+                    stats.incorrect += 1;
+                    continue
+                }
+            };
+
+            stats.total += 1;
+            if !self.messages.contains_key(&id) {
+                stats.has_only_header += 1;
+            }
+
+            match &status {
+                s if is_finally_accepted(s) => stats.accepted_in_session += 1,
+                s if is_finally_rejected(s) => {
+                    stats.rejected_in_session += 1;
+                    if *s == deadline_expired_status() { stats.expired_by_deadline += 1 }
+                },
+                RempMessageStatus::TonNode_RempAccepted(RempAccepted { level: RempMessageLevel::TonNode_RempShardchain, .. }) =>
+                    stats.accepted_in_shardchain += 1,
+                RempMessageStatus::TonNode_RempIgnored(_) => stats.ignored += 1,
+                _ => ()
+            }
+        }
+        stats
+    }
+
+    fn remove_message(&self, msg_id: &UInt256) -> Result<()> {
+        if let Some((_, hdr)) = self.message_headers.remove(msg_id) {
+            self.ids_for_uid.remove_from_set(&hdr.message_uid, msg_id)?;
+        }
+        self.message_origins.remove(msg_id);
+        self.messages.remove(msg_id);
+        self.message_status.remove(msg_id);
+        self.message_finally_accepted.remove(msg_id);
+        self.message_events.remove_set(msg_id);
+        self.message_traces.remove(msg_id);
+        Ok(())
+    }
+
+    /// Removes at most `count` oldest messages with final status (finally accepted or rejected).
+    /// Messages without origin (taken from blocks) are considered the oldest ones.
+    /// Returns ids of removed messages.
+    fn evict_finalized(&self, count: usize) -> Result<Vec<UInt256>> {
+        let mut candidates: Vec<(u32, UInt256)> = Vec::new();
+        for id in self.list_ids() {
+            match self.get_message_status(&id)? {
+                Some(s) if is_finally_accepted(&s) || is_finally_rejected(&s) => {
+                    let timestamp = self.message_origins.get(&id).map(|o| o.value().timestamp).unwrap_or(0);
+                    candidates.push((timestamp, id))
+                },
+                _ => ()
+            }
+        }
+        candidates.sort();
+
+        let mut evicted = Vec::new();
+        for (_timestamp, id) in candidates.into_iter().take(count) {
+            log::trace!(target: "remp", "Evicting message from cache: {}", self.message_info(&id));
+            self.remove_message(&id)?;
+            evicted.push(id);
+        }
+        Ok(evicted)
+    }
+
+    fn get_persisted_message(&self, msg_id: &UInt256) -> Option<PersistedRempMessage> {
+        let header = self.message_headers.get(msg_id)?.value().clone();
+        Some(PersistedRempMessage {
+            master_cc: self.master_cc,
+            header,
+            origin: self.message_origins.get(msg_id).map(|o| o.value().clone()),
+            message: self.messages.get(msg_id).map(|m| m.value().clone()),
+            status: self.message_status.get(msg_id).map(|s| s.value().clone()),
+            finally_accepted: self.message_finally_accepted.get(msg_id).map(|s| s.value().clone()),
+        })
+    }
+
+    fn restore_message(&self, persisted: PersistedRempMessage) -> Result<()> {
+        let msg_id = persisted.header.message_id.clone();
+        if let Some(status) = persisted.status {
+            self.message_status.insert(msg_id.clone(), status);
+        }
+        if let Some(status) = persisted.finally_accepted {
+            self.set_finally_accepted_status(&msg_id, status)?;
+        }
+        match persisted.message {
+            None => self.insert_message_header(&msg_id, persisted.header, persisted.origin),
+            Some(message) => self.insert_message(message, persisted.header, persisted.origin).map(|_| ())
+        }
+    }
+
     fn new(master_cc: u32, start_time: UnixTime32, inf_shards: Vec<BlockIdExt>) -> Self {
         Self {
             master_cc,
@@ -672,6 +984,7 @@ impl MessageCacheSession {
             messages: DashMap::default(),
             message_status: DashMap::default(),
             message_finally_accepted: DashMap::default(),
+            message_traces: DashMap::default(),
             inf_shards: HashSet::from_iter(inf_shards.into_iter()),
             blocks_processed: DashSet::default(),
         }
@@ -685,6 +998,13 @@ pub struct MessageCache {
     master_cc_seqno_lwb: AtomicU32, // Minimal actual master_cc_seqno
     master_cc_seqno_curr: AtomicU32, // Current (that is, maximal) master_cc_seqno
 
+    max_cached_messages: Option<usize>,
+    duplicate_policy: DuplicatePolicy,
+    persistent_db: Option<Arc<RempMessagesDb>>,
+    traced_messages: DashSet<UInt256>,
+    #[cfg(feature = "external_db")]
+    history_exporter: Option<Arc<RempHistoryExporter>>,
+
     #[cfg(feature = "telemetry")]
     cache_size_metric: Arc<Metric>,
 }
@@ -722,7 +1042,344 @@ impl MessageCache {
             || error!("Cannot find message {:x} to change its status to {:?}", message_id, new_status)
         )?;
 
-        session.update_message_status(message_id, new_status)
+        self.trace_event(message_id, MessageTracePoint::StatusChanged, || new_status.to_string());
+        #[cfg(feature = "external_db")]
+        let exported = new_status.clone();
+        session.update_message_status(message_id, new_status)?;
+        self.write_through(message_id);
+        #[cfg(feature = "external_db")]
+        self.export_transition(&session, message_id, &exported);
+        Ok(())
+    }
+
+    /// Starts collecting trace of the message, which may be not in cache yet.
+    /// Returns false if too many messages are traced already.
+    pub fn trace_message(&self, message_id: &UInt256) -> bool {
+        if self.traced_messages.contains(message_id) {
+            return true
+        }
+        if self.traced_messages.len() >= MAX_TRACED_MESSAGES {
+            return false
+        }
+        self.traced_messages.insert(message_id.clone());
+        true
+    }
+
+    /// Appends event to the message trace; `details` are computed for traced messages only
+    pub fn trace_event<F: FnOnce() -> String>(&self, message_id: &UInt256, point: MessageTracePoint, details: F) {
+        if !self.traced_messages.contains(message_id) {
+            return
+        }
+        match self.get_session_for_message(message_id) {
+            Some(session) => session.append_trace_event(
+                message_id,
+                MessageTraceEvent { time: SystemTime::now(), point, details: details() }
+            ),
+            None => log::warn!(target: "remp", "Traced message {:x} is not in cache, {:?} event is lost", message_id, point)
+        }
+    }
+
+    /// Returns events of traced message in order of their appearance, None if the message is not traced
+    pub fn get_message_trace(&self, message_id: &UInt256) -> Option<Vec<MessageTraceEvent>> {
+        if !self.traced_messages.contains(message_id) {
+            return None
+        }
+        Some(self.get_session_for_message(message_id).map(|s| s.get_trace(message_id)).unwrap_or_default())
+    }
+
+    /// Passes the status to the history exporter, if the history is exported.
+    /// Exporter never waits for its sink, so it is safe to call it on status change.
+    #[cfg(feature = "external_db")]
+    fn export_transition(&self, session: &MessageCacheSession, message_id: &UInt256, status: &RempMessageStatus) {
+        let exporter = match &self.history_exporter {
+            Some(exporter) if exporter.is_exported(status) => exporter,
+            _ => return
+        };
+        let message_uid = match session.message_headers.get(message_id) {
+            Some(header) => header.value().message_uid.clone(),
+            None => {
+                log::warn!(target: "remp", "No header for message {:x}, its status {} is not exported", message_id, status);
+                return
+            }
+        };
+        let origin = session.message_origins.get(message_id)
+            .filter(|origin| !origin.value().has_no_source_key())
+            .map(|origin| origin.value().source_key.clone());
+        let timestamp_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis() as u64);
+        exporter.export(RempStatusTransition {
+            message_id: message_id.clone(),
+            message_uid,
+            origin,
+            status: status.clone(),
+            master_cc: session.master_cc,
+            timestamp_ms
+        });
+    }
+
+    /// Stores current message info into the persistent db, if persistence is enabled.
+    /// Db errors are logged only: the cache itself remains correct.
+    fn write_through(&self, message_id: &UInt256) {
+        if let Some(db) = &self.persistent_db {
+            if let Err(e) = self.persist_message(db, message_id) {
+                log::error!(target: "remp", "Cannot persist message {:x}: {}", message_id, e);
+            }
+        }
+    }
+
+    /// Removes messages from the persistent db, if persistence is enabled
+    fn forget_persisted(&self, message_ids: &[UInt256]) {
+        if let Some(db) = &self.persistent_db {
+            for message_id in message_ids {
+                if let Err(e) = db.delete(message_id) {
+                    log::error!(target: "remp", "Cannot remove persisted message {:x}: {}", message_id, e);
+                }
+            }
+        }
+    }
+
+    fn persist_message(&self, db: &RempMessagesDb, message_id: &UInt256) -> Result<bool> {
+        let persisted = match self.get_session_for_message(message_id) {
+            None => return Ok(false),
+            Some(session) => match session.get_persisted_message(message_id) {
+                None => return Ok(false),
+                Some(persisted) => persisted
+            }
+        };
+        db.put(message_id, &persisted.serialize()?)?;
+        Ok(true)
+    }
+
+    /// Stores all cached messages into `db`. Returns number of stored messages.
+    pub fn persist(&self, db: &RempMessagesDb) -> Result<usize> {
+        let mut persisted = 0;
+        for cc in self.get_master_cc_stored_range() {
+            if let Some(session) = self.sessions.get(&cc) {
+                for message_id in session.val().list_ids() {
+                    if self.persist_message(db, &message_id)? {
+                        persisted += 1;
+                    }
+                }
+            }
+        }
+        Ok(persisted)
+    }
+
+    /// Loads messages from `db` into the cache. Messages from master cc sessions
+    /// outside `current_cc_range` (or from sessions not created yet),
+    /// as well as unreadable records, are removed from `db`.
+    /// Returns numbers of restored and dropped messages.
+    pub fn restore(&self, db: &RempMessagesDb, current_cc_range: &RangeInclusive<u32>) -> Result<(usize, usize)> {
+        let mut records = Vec::new();
+        let mut dropped = Vec::new();
+        db.for_each(&mut |key, value| {
+            let message_id = UInt256::from_slice(key);
+            match PersistedRempMessage::deserialize(&message_id, value) {
+                Ok(persisted) => records.push(persisted),
+                Err(e) => {
+                    log::warn!(target: "remp", "Cannot restore persisted message {:x}: {}", message_id, e);
+                    dropped.push(message_id)
+                }
+            }
+            Ok(true)
+        })?;
+
+        let mut restored = 0;
+        for persisted in records {
+            let message_id = persisted.header.message_id.clone();
+            let session = match self.sessions.get(&persisted.master_cc) {
+                Some(session) if current_cc_range.contains(&persisted.master_cc) => session.val().clone(),
+                _ => {
+                    log::trace!(target: "remp", "Dropping persisted message {:x}: master cc {} is not in actual range {:?}",
+                        message_id, persisted.master_cc, current_cc_range
+                    );
+                    dropped.push(message_id);
+                    continue
+                }
+            };
+            if self.is_message_present(&message_id) {
+                continue
+            }
+            session.restore_message(persisted)?;
+            restored += 1;
+        }
+
+        for message_id in dropped.iter() {
+            db.delete(message_id)?;
+        }
+
+        #[cfg(feature = "telemetry")]
+        self.cache_size_metric.update(self.all_messages_count().0 as u64);
+
+        log::info!(target: "remp", "Message cache restored: {} messages restored, {} dropped", restored, dropped.len());
+        Ok((restored, dropped.len()))
+    }
+
+    /// Restores the cache from its own persistent db, if persistence is enabled
+    pub fn restore_persisted(&self, current_cc_range: &RangeInclusive<u32>) -> Result<(usize, usize)> {
+        match &self.persistent_db {
+            None => Ok((0, 0)),
+            Some(db) => self.restore(db, current_cc_range)
+        }
+    }
+
+    /// Takes sessions and messages of the stored master cc range
+    pub fn snapshot(&self) -> Result<MessageCacheSnapshot> {
+        let mut sessions = Vec::new();
+        let mut messages = Vec::new();
+        for cc in self.get_master_cc_stored_range() {
+            let session = match self.sessions.get(&cc) {
+                None => continue,
+                Some(session) => session.val().clone()
+            };
+            sessions.push((cc, session.start_time.as_u32()));
+            let mut ids = session.list_ids();
+            ids.sort();
+            for message_id in ids {
+                let persisted = match session.get_persisted_message(&message_id) {
+                    None => continue,
+                    Some(persisted) => persisted
+                };
+                messages.push(MessageCacheSnapshotMessage {
+                    message_uid: persisted.header.message_uid.clone(),
+                    status: session.get_message_status(&message_id)?
+                        .map(|status| status.to_string()).unwrap_or_default(),
+                    origin: persisted.origin.as_ref()
+                        .map(|origin| origin.to_string()).unwrap_or_default(),
+                    record: persisted.serialize()?,
+                    message_id,
+                });
+            }
+        }
+        let lwb = self.master_cc_seqno_lwb.load(Relaxed);
+        let curr = self.master_cc_seqno_curr.load(Relaxed);
+        Ok(MessageCacheSnapshot {
+            master_cc_range: lwb ..= curr,
+            duplicate_policy: self.duplicate_policy,
+            sessions,
+            messages
+        })
+    }
+
+    /// Fills empty cache from the snapshot. Returns number of restored messages.
+    /// Infimum blocks of sessions are not kept in the snapshot, so they are empty.
+    pub fn restore_snapshot(&self, snapshot: &MessageCacheSnapshot) -> Result<usize> {
+        for (master_cc, start_time) in snapshot.sessions.iter() {
+            self.try_set_master_cc_start_time(*master_cc, UnixTime32::new(*start_time), vec!())?;
+        }
+        self.set_master_cc_range(&snapshot.master_cc_range)?;
+        for message in snapshot.messages.iter() {
+            let persisted = PersistedRempMessage::deserialize(&message.message_id, &message.record)?;
+            let session = self.sessions.get(&persisted.master_cc)
+                .ok_or_else(|| error!("Snapshot message {:x}: session {} is absent",
+                    message.message_id, persisted.master_cc
+                ))?
+                .val().clone();
+            session.restore_message(persisted)?;
+        }
+        Ok(snapshot.messages.len())
+    }
+
+    /// Messages which may be collated by the rules of the collation queue: New or Ignored
+    /// ones with known body and origin, which are fresh by duplicate check.
+    /// The queue is not kept in test bundles, so their collation is replayed by the cache.
+    pub fn collation_candidates(&self) -> Result<Vec<(UInt256, Arc<Message>, Arc<RempMessageOrigin>)>> {
+        let mut candidates = Vec::new();
+        for cc in self.get_master_cc_stored_range() {
+            let session = match self.sessions.get(&cc) {
+                None => continue,
+                Some(session) => session.val().clone()
+            };
+            for message_id in session.list_ids() {
+                match session.get_message_status(&message_id)? {
+                    Some(RempMessageStatus::TonNode_RempNew)
+                    | Some(RempMessageStatus::TonNode_RempIgnored(_)) => (),
+                    _ => continue
+                }
+                let (message, origin) = match (
+                    session.messages.get(&message_id),
+                    session.message_origins.get(&message_id)
+                ) {
+                    (Some(message), Some(origin)) => (message.value().clone(), origin.value().clone()),
+                    _ => continue
+                };
+                candidates.push((message_id, message, origin));
+            }
+        }
+        // The first of equal uids wins in the filter, so the order must not depend on maps
+        candidates.sort_by(|(id1, _, _), (id2, _, _)| id1.cmp(id2));
+        let ids = candidates.iter()
+            .map(|(id, message, _)| (id.clone(), message.message_uid.clone()))
+            .collect::<Vec<_>>();
+        let fresh = self.filter_fresh_messages(&ids)?;
+        Ok(candidates.into_iter().zip(fresh.into_iter())
+            .filter(|(_, is_fresh)| *is_fresh)
+            .map(|((id, message, origin), _)| (id, message.message.clone(), origin))
+            .collect())
+    }
+
+    /// Evicts at most `count` oldest finalized messages, starting from the oldest session.
+    /// Returns number of evicted messages.
+    fn evict_finalized_messages(&self, count: usize) -> Result<usize> {
+        let mut evicted = 0;
+        for cc in self.get_master_cc_stored_range() {
+            if evicted >= count {
+                break
+            }
+            if let Some(session) = self.sessions.get(&cc) {
+                let ids = session.val().evict_finalized(count - evicted)?;
+                self.forget_persisted(&ids);
+                evicted += ids.len();
+            }
+        }
+
+        #[cfg(feature = "telemetry")]
+        self.cache_size_metric.update(self.all_messages_count().0 as u64);
+
+        Ok(evicted)
+    }
+
+    pub fn cache_overflow_status(cached: usize, max_cached: usize) -> RempMessageStatus {
+        let rejected = RempRejected {
+            level: RempMessageLevel::TonNode_RempQueue,
+            block_id: Default::default(),
+            error: format!(
+                "Message cache overflow ({} messages cached, {} max allowed)", cached, max_cached
+            )
+        };
+        RempMessageStatus::TonNode_RempRejected(rejected)
+    }
+
+    /// Makes room for a new message if the cache size is limited.
+    /// Returns rejection status if the cache is full and nothing can be evicted.
+    fn check_cache_overflow(&self) -> Result<Option<RempMessageStatus>> {
+        let max_cached = match self.max_cached_messages {
+            None => return Ok(None),
+            Some(max_cached) => max_cached
+        };
+
+        let cached = self.all_messages_count().0;
+        if cached < max_cached {
+            return Ok(None)
+        }
+
+        // Evict a bit more than necessary to avoid scanning the cache on each new message
+        let to_evict = cached - max_cached + 1 + max_cached / 16;
+        let evicted = self.evict_finalized_messages(to_evict)?;
+        log::debug!(target: "remp", "Message cache is full ({} of {} messages), {} finalized messages evicted",
+            cached, max_cached, evicted
+        );
+
+        if cached - evicted >= max_cached {
+            Ok(Some(Self::cache_overflow_status(cached - evicted, max_cached)))
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_message_present(&self, message_id: &UInt256) -> bool {
+        self.get_session_for_message(message_id).is_some()
     }
 
     fn get_session_for_message(&self, message_id: &UInt256) -> Option<Arc<MessageCacheSession>> {
@@ -862,6 +1519,9 @@ impl MessageCache {
     /// If we know something about message -- that's more important than anything we discover from RMQ
     /// If we do not know anything -- TODO: if all reject, then 'Rejected'. Otherwise 'New'
     /// Actual -- get it as granted ("imprinting")
+    /// If the cache is full, finalized messages are evicted; if there is nothing to evict,
+    /// the message is not added and cache overflow rejection status is returned
+    /// (messages with final status are always added).
     /// Returns old status, new (added) status, and body_updated flag
     pub fn add_external_message_status<F>(&self,
         message_id: &UInt256, message_uid: &UInt256, message: Option<Arc<RmqMessage>>, message_origin: Option<Arc<RempMessageOrigin>>,
@@ -872,6 +1532,16 @@ impl MessageCache {
     {
         match self.get_session_for_message(message_id) {
             None => {
+                if !is_finally_accepted(&status_if_new) && !is_finally_rejected(&status_if_new) {
+                    if let Some(rejected) = self.check_cache_overflow()? {
+                        log::warn!(target: "remp", "Message {:x} is rejected: {}", message_id, rejected);
+                        return Ok((None, rejected, false))
+                    }
+                }
+                else {
+                    self.check_cache_overflow()?;
+                }
+
                 let session = self.sessions
                     .get(&master_cc)
                     .ok_or_else(|| error!("Master cc session {} is not created; current master cc ranges {:?}",
@@ -885,12 +1555,16 @@ impl MessageCache {
 
                 let body_updated = match message {
                     None => {
-                        self.insert_message_header( session, header, message_origin, &status_if_new)?;
+                        self.insert_message_header( session.clone(), header, message_origin, &status_if_new)?;
                         false
                     },
                     Some(message) =>
-                        self.insert_message(session, message, header, message_origin.clone(), &status_if_new)?
+                        self.insert_message(session.clone(), message, header, message_origin.clone(), &status_if_new)?
                 };
+                self.write_through(message_id);
+                #[cfg(feature = "external_db")]
+                self.export_transition(&session, message_id, &status_if_new);
+                self.trace_event(message_id, MessageTracePoint::Added, || format!("{}, master cc {}", status_if_new, master_cc));
                 Ok((None, status_if_new, body_updated))
             },
             Some(session) => {
@@ -904,6 +1578,14 @@ impl MessageCache {
                 let (old_status, final_status) =
                     session.alter_message_status(&message_id, |old| status_updater(old,&status_if_new))?;
 
+                if body_updated || old_status != final_status {
+                    self.write_through(message_id);
+                }
+                if old_status != final_status {
+                    self.trace_event(message_id, MessageTracePoint::StatusChanged, || format!("{} => {}", old_status, final_status));
+                    #[cfg(feature = "external_db")]
+                    self.export_transition(&session, message_id, &final_status);
+                }
                 Ok((Some(old_status), final_status, body_updated))
             },
         }
@@ -913,11 +1595,16 @@ impl MessageCache {
     /// `data` --- Message body with ids
     /// `return` --- Whether the body added or not
     pub fn update_message_body(&self, data: Arc<RmqMessage>) -> Result<bool> {
+        self.update_message_body_with_origin(data, None)
+    }
+
+    /// Same as `update_message_body`, origin is stored if it is not known yet
+    pub fn update_message_body_with_origin(&self, data: Arc<RmqMessage>, origin: Option<Arc<RempMessageOrigin>>) -> Result<bool> {
         let (old_status, new_status, body_updated) = self.add_external_message_status(
             &data.message_id,
             &data.message_uid,
             Some(data.clone()),
-            None,
+            origin,
             RempMessageStatus::TonNode_RempNew, |old,_new| old.clone(),
             self.master_cc_seqno_curr.load(Ordering::Relaxed)
         )?;
@@ -947,6 +1634,12 @@ impl MessageCache {
             old_status.clone()
         })?;
 
+        if before != after {
+            self.write_through(msg_id);
+            self.trace_event(msg_id, MessageTracePoint::StatusChanged, || format!("{} => {}", before, after));
+            #[cfg(feature = "external_db")]
+            self.export_transition(&session, msg_id, &after);
+        }
         Ok(before != after)
     }
 
@@ -975,10 +1668,36 @@ impl MessageCache {
         res
     }
 
-    /// Returns None if `id` is the lowest message id for `uid`
-    /// Returns minimal message id for `uid` otherwise
-    pub fn get_lower_id_for_uid(&self, id: &UInt256, uid: &UInt256) -> Result<Option<UInt256>> {
+    /// Returns messages with the same uid, which compete with `id` for collation
+    /// according to duplicate policy (`id` itself is included).
+    fn get_competing_messages(&self, id: &UInt256, uid: &UInt256) -> Result<Vec<UInt256>> {
         let equivalent_msgs = self.get_messages_for_uid(uid);
+        if self.duplicate_policy != DuplicatePolicy::PreferLocal {
+            return Ok(equivalent_msgs)
+        }
+
+        // Messages to other destinations do not compete with the message.
+        // Messages with unknown destination (no body in cache) compete with any message.
+        let dst = match self.get_message(id)?.and_then(|m| m.message.dst()) {
+            None => return Ok(equivalent_msgs),
+            Some(dst) => dst
+        };
+        let mut competing = Vec::new();
+        for other in equivalent_msgs.into_iter() {
+            let same_dst = match self.get_message(&other)?.and_then(|m| m.message.dst()) {
+                None => true,
+                Some(other_dst) => other_dst == dst
+            };
+            if same_dst {
+                competing.push(other);
+            }
+        }
+        Ok(competing)
+    }
+
+    /// Returns None if `id` is the lowest message id among `equivalent_msgs` for `uid`
+    /// Returns minimal message id otherwise
+    fn get_lower_id(&self, id: &UInt256, uid: &UInt256, equivalent_msgs: &Vec<UInt256>) -> Result<Option<UInt256>> {
         log::trace!(target: "remp", "Looking for lower id for uid {:x}, ids {:?}", uid, equivalent_msgs);
 
         if !equivalent_msgs.contains(id) {
@@ -994,17 +1713,23 @@ impl MessageCache {
 
     /// Checks, whether `message_id` can be collated or validated. There are three possible outcomes:
     /// * Absent: `message_id` is absent from cache --- cannot be collated/validated.
-    /// * Fresh: `message_id` is smallest among messages with same uid and there are no
-    /// other accepted by shardchain or masterchain messages with the same uid.
-    /// * Duplicate: `message_id` is not smallest among messages with the same uid,
-    /// or a message with the same uid is accedpted by shardchain or masterchain.
+    /// * Fresh: `message_id` wins among competing messages with same uid (see below) and there are no
+    /// other accepted by shardchain or masterchain competing messages with the same uid.
+    /// * Duplicate: `message_id` does not win among competing messages with the same uid,
+    /// or a competing message with the same uid is accedpted by shardchain or masterchain.
+    ///
+    /// The winner depends on duplicate policy:
+    /// * LowestId: the smallest message id among all messages with the same uid;
+    /// * FirstAccepted: the message which was first accepted for collation;
+    /// * PreferLocal: the smallest message id among messages with the same uid and the same
+    /// destination (messages to other destinations do not compete).
     pub fn check_message_duplicates(&self, message_id: &UInt256) -> Result<RempDuplicateStatus> {
         let uid = match self.get_message_uid(message_id)? {
             None => return Ok(RempDuplicateStatus::Absent),
             Some(hdr) => hdr
         };
 
-        let equivalent_msgs = self.get_messages_for_uid(&uid);
+        let equivalent_msgs = self.get_competing_messages(message_id, &uid)?;
         //log::trace!(target: "remp", "Attached to uid {:x}, ids {:?}", uid, equivalent_msgs);
 
         // Check whether a message with same uid was already accepted by shardchain or masterchain
@@ -1028,8 +1753,20 @@ impl MessageCache {
             Some((_lvl, d @ RempDuplicateStatus::Fresh(_))) => d,
         };
 
+        if self.duplicate_policy == DuplicatePolicy::FirstAccepted {
+            // Check whether some other message with same uid is already taken for collation
+            for other in equivalent_msgs.iter().filter(|id| *id != message_id) {
+                if let Some(RempMessageStatus::TonNode_RempAccepted(acc)) = self.get_message_status(other)? {
+                    if acc.level == RempMessageLevel::TonNode_RempQueue || acc.level == RempMessageLevel::TonNode_RempCollator {
+                        return Ok(RempDuplicateStatus::Duplicate(BlockIdExt::default(), uid.clone(), other.clone()))
+                    }
+                }
+            }
+            return Ok(fresh_duplicate_status)
+        }
+
         // Check whether message_id is minimal among other messages with same uid
-        match self.get_lower_id_for_uid(&message_id, &uid)? {
+        match self.get_lower_id(&message_id, &uid, &equivalent_msgs)? {
             None => Ok(fresh_duplicate_status),
             Some(lowest_msg_id) => {
                 if self.get_session_for_message(&lowest_msg_id).is_none() {
@@ -1042,6 +1779,32 @@ impl MessageCache {
         }
     }
 
+    /// Bulk pre-filter before collation: for each (message id, uid) pair returns true if the
+    /// message is `Fresh` according to `check_message_duplicates`. Messages absent from cache or
+    /// cached with another uid are not fresh. Unless policy is PreferLocal, only the first fresh
+    /// message for each uid is kept, so equal-uid messages do not reach collator together.
+    /// The result is a hint: the check during collation/validation stays authoritative.
+    pub fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>> {
+        let mut taken_uids = HashSet::new();
+        let mut fresh = Vec::with_capacity(ids.len());
+        for (id, uid) in ids {
+            let is_fresh = match self.check_message_duplicates(id)? {
+                RempDuplicateStatus::Fresh(cached_uid) if &cached_uid == uid => {
+                    self.duplicate_policy == DuplicatePolicy::PreferLocal || taken_uids.insert(uid)
+                }
+                RempDuplicateStatus::Fresh(cached_uid) => {
+                    log::warn!(target: "remp",
+                        "Message cache: message {:x} has uid {:x}, but {:x} is expected", id, cached_uid, uid
+                    );
+                    false
+                }
+                RempDuplicateStatus::Absent | RempDuplicateStatus::Duplicate(_, _, _) => false
+            };
+            fresh.push(is_fresh);
+        }
+        Ok(fresh)
+    }
+
     pub fn message_stats(&self) -> String {
         let (count,with_origins,with_bodies) = self.all_messages_count();
         format!("All REMP messages count = {}, of them: with origins (via catchain) = {}, with bodies (broadcasted) = {}", count, with_origins, with_bodies)
@@ -1081,6 +1844,19 @@ impl MessageCache {
         Ok(())
     }
 
+    pub fn get_session_stats(&self, master_cc: u32) -> Result<RempSessionStats> {
+        let session = self.sessions.get(&master_cc)
+            .ok_or_else(|| error!("Session {} is unknown", master_cc))?
+            .val().clone();
+        Ok(session.collect_stats())
+    }
+
+    pub fn get_all_sessions(&self) -> Vec<u32> {
+        let mut sessions: Vec<u32> = self.sessions.iter().map(|s| *s.key()).collect();
+        sessions.sort();
+        sessions
+    }
+
     pub fn is_block_processed(&self, blk: &BlockIdExt) -> Result<bool> {
         let session = self
             .get_session_for_block(blk)
@@ -1237,6 +2013,13 @@ impl MessageCache {
             if let Some(session) = self.sessions.remove(&cc_to_remove) {
                 log::debug!(target: "remp", "Removing & gc MessageCacheSession {}", session.val());
                 stats.add(&session.val().gc_all());
+                let ids = session.val().list_ids();
+                self.forget_persisted(&ids);
+                if !self.traced_messages.is_empty() {
+                    for id in ids.iter() {
+                        self.traced_messages.remove(id);
+                    }
+                }
 
                 #[cfg(feature = "telemetry")]
                 self.cache_size_metric.update(self.all_messages_count().0 as u64);
@@ -1248,6 +2031,9 @@ impl MessageCache {
     }
 
     pub fn with_metrics(
+        max_cached_messages: Option<usize>,
+        duplicate_policy: DuplicatePolicy,
+        persistent_db: Option<Arc<RempMessagesDb>>,
         #[cfg(feature = "telemetry")]
         cache_size_metric: Arc<Metric>,
     ) -> Self {
@@ -1257,8 +2043,21 @@ impl MessageCache {
             master_cc_seqno_stored: AtomicU32::new(u32::MAX),
             master_cc_seqno_lwb: AtomicU32::new(1),
             master_cc_seqno_curr: AtomicU32::new(0),
+            max_cached_messages,
+            duplicate_policy,
+            persistent_db,
+            traced_messages: DashSet::new(),
+            #[cfg(feature = "external_db")]
+            history_exporter: None,
             #[cfg(feature = "telemetry")]
             cache_size_metric,
         }
     }
+
+    /// Status transitions of messages are passed to `exporter`
+    #[cfg(feature = "external_db")]
+    pub fn with_history_exporter(mut self, exporter: Option<Arc<RempHistoryExporter>>) -> Self {
+        self.history_exporter = exporter;
+        self
+    }
 }
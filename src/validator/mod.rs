@@ -17,14 +17,18 @@ mod log_parser;
 pub mod accept_block;
 pub mod catchain_overlay;
 mod reliable_message_queue;
+pub mod rmq_pending_journal;
 pub mod remp_catchain;
 pub mod remp_manager;
 pub mod remp_block_parser;
 mod validator_group;
 pub mod validator_utils;
+pub mod validator_schedule;
+pub mod key_rotation;
 pub mod validator_manager;
 pub mod validator_session_listener;
 pub mod sessions_computing;
+pub mod session_checkpoint;
 pub mod message_cache;
 pub mod candidate_db;
 pub mod collator;
@@ -19,9 +19,10 @@ use std::{
     sync::Arc,
     time::{Duration, SystemTime}
 };
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use dashmap::DashMap;
 
+use ton_api::IntoBoxed;
 use ton_api::ton::ton_node::{
     RempMessageStatus, RempMessageLevel,
     rempmessagestatus::{RempAccepted, RempIgnored, RempRejected}, 
@@ -29,16 +30,19 @@ use ton_api::ton::ton_node::{
     RempCatchainRecordV2,
     rempcatchainrecordv2::{RempCatchainMessageHeaderV2, RempCatchainMessageDigestV2}
 };
-use ever_block::{ShardIdent, Message, BlockIdExt, ValidatorDescr, Sha256};
+use ever_block::{ShardIdent, Message, BlockIdExt, ValidatorDescr, Sha256, UnixTime32};
 use ever_block::{UInt256, Result, fail, gen_random_index, error};
 
 use catchain::{PrivateKey, PublicKey};
 use crate::{
-    engine_traits::{EngineOperations, RempDuplicateStatus, RempQueueCollatorInterface},
+    engine_traits::{
+        block_access, remp_support, EngineOperations, RempDuplicateStatus,
+        RempQueueCollatorInterface, RempSupport
+    },
     ext_messages::{get_level_and_level_change, is_finally_accepted, is_finally_rejected, MAX_EXTERNAL_MESSAGE_SIZE},
     validator::{
         mutex_wrapper::MutexWrapper,
-        message_cache::{RmqMessage, RempMessageHeader, RempMessageOrigin, RempMessageWithOrigin},
+        message_cache::{deadline_expired_status, MessageTracePoint, RmqMessage, RempMessageHeader, RempMessageOrigin, RempMessageWithOrigin},
         remp_manager::RempManager,
         remp_block_parser::{process_block_messages_by_blockid, BlockProcessor},
         remp_catchain::{RempCatchainInfo, RempCatchainInstance},
@@ -73,10 +77,12 @@ struct MessageQueueImpl {
 
 pub struct MessageQueue {
     remp_manager: Arc<RempManager>,
-    engine: Arc<dyn EngineOperations>,
+    engine: Arc<dyn RempSupport>,
     catchain_info: Arc<RempCatchainInfo>,
     catchain_instance: RempCatchainInstance,
     queues: MutexWrapper<MessageQueueImpl>,
+    /// Pending records of previous queues are replayed once, before the first new record
+    journal_replayed: AtomicBool,
 }
 
 impl MessageQueueImpl {
@@ -138,7 +144,7 @@ impl MessageQueueImpl {
 
 impl MessageQueue {
     pub fn create(
-        engine: Arc<dyn EngineOperations>,
+        engine: Arc<dyn RempSupport>,
         remp_manager: Arc<RempManager>,
         remp_catchain_info: Arc<RempCatchainInfo>,
     ) -> Result<Self> {
@@ -164,14 +170,15 @@ impl MessageQueue {
             catchain_info: remp_catchain_info,
             catchain_instance: remp_catchain_instance,
             queues,
+            journal_replayed: AtomicBool::new(false),
         });
     }
 
     pub async fn create_and_start(
         engine: Arc<dyn EngineOperations>, manager: Arc<RempManager>, info: Arc<RempCatchainInfo>, local_key: PrivateKey
     ) -> Result<Arc<Self>> {
-        let queue = Arc::new(Self::create(engine, manager, info)?);
-        queue.clone().start(local_key.clone()).await?;
+        let queue = Arc::new(Self::create(remp_support(engine.clone()), manager, info)?);
+        queue.clone().start(engine, local_key.clone()).await?;
         Ok(queue)
     }
 
@@ -229,12 +236,17 @@ impl MessageQueue {
         }
     }
 
-    pub async fn start(self: Arc<MessageQueue>, local_key: PrivateKey) -> Result<()> {
+    // Catchain of the queue needs full engine
+    pub async fn start(
+        self: Arc<MessageQueue>,
+        engine: Arc<dyn EngineOperations>,
+        local_key: PrivateKey
+    ) -> Result<()> {
         self.set_queue_status(MessageQueueStatus::Created, MessageQueueStatus::Starting).await?;
         log::trace!(target: "remp", "RMQ {}: starting", self);
 
         let catchain_instance_res = self.remp_manager.catchain_store.start_catchain(
-            self.engine.clone(), self.remp_manager.clone(), self.catchain_info.clone(), local_key
+            engine, self.remp_manager.clone(), self.catchain_info.clone(), local_key
         ).await;
 
         match catchain_instance_res {
@@ -291,7 +303,11 @@ impl MessageQueue {
         }
 
         let origin_with_idx = Arc::new(msg.origin.new_with_updated_source_idx(self.catchain_info.local_idx as u32));
-        let body_updated = self.remp_manager.message_cache.update_message_body(Arc::new(msg.message.clone()))?;
+        // Catchain records do not carry the deadline, so such origin is cached before its record
+        let deadline_origin = origin_with_idx.deadline.map(|_| origin_with_idx.clone());
+        let body_updated = self.remp_manager.message_cache.update_message_body_with_origin(
+            Arc::new(msg.message.clone()), deadline_origin
+        )?;
         log::trace!(target: "remp", "Point 3. Pushing to RMQ {}; message {}, {}{}",
             self, msg, origin_with_idx,
             (if body_updated { " + broadcast" } else { "" }).to_string()
@@ -473,6 +489,10 @@ impl MessageQueue {
                             "Point 4. RMQ {}. Message {:x} master_cc_seqno {} from validator {} has final status {}, skipping",
                             self, remp_message_header.message_id, message_master_seqno, remp_message_origin.source_idx, new_status
                         );
+                self.remp_manager.message_cache.trace_event(
+                    &remp_message_header.message_id, MessageTracePoint::Queued,
+                    || format!("RMQ {}: final status from validator {}, skipped", self, remp_message_origin.source_idx)
+                );
                 #[cfg(feature = "telemetry")]
                 self.engine.remp_core_telemetry().add_to_cache_attempt(false);
             }
@@ -485,6 +505,16 @@ impl MessageQueue {
                                 Some(x) => format!(" (old status {})", x)
                             }
                         );
+                self.remp_manager.message_cache.trace_event(
+                    &remp_message_header.message_id, MessageTracePoint::Queued,
+                    || format!("RMQ {}: from validator {}, pending collation", self, remp_message_origin.source_idx)
+                );
+                self.remp_manager.pending_journal.add(
+                    &self.catchain_info.general_session_info.shard,
+                    &remp_message_header.message_id,
+                    &catchain_record.clone().into_boxed(),
+                    remp_node_sender
+                );
                 self.add_pending_collation(&remp_message_header.message_id, remp_message_origin, remp_node_sender, Some(new_status)).await?;
                 #[cfg(feature = "telemetry")]
                 self.engine.remp_core_telemetry().add_to_cache_attempt(true);
@@ -530,6 +560,25 @@ impl MessageQueue {
         }
     }
 
+    /// Replays journaled records, which were received by previous queues of the shard
+    /// but not taken for collation. Records already known are deduplicated by message cache.
+    /// Returns number of replayed records.
+    pub async fn replay_pending_journal(&self) -> Result<usize> {
+        if self.journal_replayed.swap(true, Ordering::Relaxed) {
+            return Ok(0)
+        }
+        let records = self.remp_manager.pending_journal.records_to_replay(
+            &self.catchain_info.general_session_info.shard, &self.catchain_info.master_cc_range
+        );
+        for (record, relayer) in records.iter() {
+            self.process_pending_remp_catchain_record(record, *relayer).await?;
+        }
+        if !records.is_empty() {
+            log::info!(target: "remp", "RMQ {}: {} pending records replayed from journal", self, records.len());
+        }
+        Ok(records.len())
+    }
+
     /// Check pending queues, activate catchain exchange
     fn poll_outbound_queues(&self) -> Result<()> {
         self.catchain_instance.poll_outbound_queues(
@@ -561,6 +610,8 @@ impl MessageQueue {
             self.catchain_instance.rmq_catchain_receiver_len()?
         );
 
+        self.replay_pending_journal().await?;
+
         // Process new messages from RMQ catchain and send them to collator.
         // Knowledge hierarchy:
         // 1. Our final knowledge (we received it from masterblock/we rejected it);
@@ -643,6 +694,14 @@ impl MessageQueue {
                 }
                 return Ok((true, None))
             },
+            None if !self.remp_manager.message_cache.is_message_present(msg_id) => {
+                log::debug!(
+                    target: "remp",
+                    "Point 5. RMQ {}: message {:x} found in pending_collation queue, but is absent from message cache (evicted or rejected); removing from queue",
+                    self, msg_id
+                );
+                return Ok((false, None))
+            },
             None => {
                 log::warn!(
                     target: "remp",
@@ -665,6 +724,14 @@ impl MessageQueue {
             }
         };
 
+        if origin.is_expired(UnixTime32::now().as_u32()) {
+            log::trace!(target: "remp", "Point 5. RMQ {}: rejecting message {:x}: deadline {} has passed",
+                self, msg_id, origin.deadline.unwrap_or_default()
+            );
+            self.update_status_send_response(msg_id, origin.clone(), deadline_expired_status());
+            return Ok((false, None))
+        }
+
         match self.remp_manager.message_cache.check_message_duplicates(&message.message_id)? {
             RempDuplicateStatus::Absent => fail!("Message {:x} is present in cache, but check_message_duplicates = Absent", &message.message_id),
             RempDuplicateStatus::Fresh(_) =>
@@ -721,22 +788,29 @@ impl MessageQueue {
                         "Point 5. RMQ {}: message {:x} found in pending_collation queue, error retriving it from messages/message_statuses: {}",
                         self, msgid, e
                     );
+                    self.remp_manager.pending_journal.remove(&msgid);
                     continue
                 },
                 Ok((leave_in_queue, None)) => {
                     if leave_in_queue {
                         self.put_back_to_collation_queue(&msgid, SystemTime::now()).await?;
+                    } else {
+                        self.remp_manager.pending_journal.remove(&msgid);
                     }
                     continue
                 },
                 Ok((_, Some(x))) => x
             };
+            self.remp_manager.pending_journal.remove(&msgid);
 
             let new_status = RempMessageStatus::TonNode_RempAccepted (RempAccepted {
                 level: RempMessageLevel::TonNode_RempQueue,
                 block_id: BlockIdExt::default(),
                 master_id: BlockIdExt::default()
             });
+            self.remp_manager.message_cache.trace_event(
+                &msgid, MessageTracePoint::Collation, || format!("RMQ {}: taken for collation", self)
+            );
             self.update_status_send_response(&msgid, origin.clone(), new_status);
 
             return Ok(Some((msgid,message,origin)));
@@ -755,6 +829,48 @@ impl MessageQueue {
         Ok(prepared_for_collation)
     }
 
+    /// Rejects messages which are known duplicates before they are given to collator.
+    /// Statuses may change after the check, so collator and validator still check duplicates.
+    pub async fn filter_fresh_for_collation(
+        &self, 
+        messages: Vec<(UInt256, Arc<Message>, Arc<RempMessageOrigin>)>
+    ) -> Result<Vec<(UInt256, Arc<Message>, Arc<RempMessageOrigin>)>> {
+        let cache = &self.remp_manager.message_cache;
+        let mut ids = Vec::with_capacity(messages.len());
+        for (id, _, _) in messages.iter() {
+            ids.push((id.clone(), cache.get_message_uid(id)?.unwrap_or_default()));
+        }
+        let fresh = match self.engine.filter_fresh_messages(&ids).await {
+            Ok(fresh) if fresh.len() == messages.len() => fresh,
+            Ok(fresh) => fail!(
+                "RMQ {}: {} filter results for {} messages", self, fresh.len(), messages.len()
+            ),
+            Err(e) => {
+                log::warn!(target: "remp", "RMQ {}: cannot filter messages for collation: {}", self, e);
+                return Ok(messages)
+            }
+        };
+
+        let mut filtered = Vec::with_capacity(messages.len());
+        for ((id, message, origin), is_fresh) in messages.into_iter().zip(fresh.into_iter()) {
+            if is_fresh {
+                filtered.push((id, message, origin));
+                continue
+            }
+            let duplicate_info = cache.duplicate_info(&cache.check_message_duplicates(&id)?);
+            log::trace!(target: "remp", "Point 5. RMQ {}: rejecting message {:x} before collation: '{}'",
+                self, id, duplicate_info
+            );
+            let rejected = RempRejected {
+                level: RempMessageLevel::TonNode_RempQueue,
+                block_id: BlockIdExt::default(),
+                error: duplicate_info
+            };
+            self.update_status_send_response(&id, origin, RempMessageStatus::TonNode_RempRejected(rejected));
+        }
+        Ok(filtered)
+    }
+
     pub async fn all_accepted_by_collator_to_ignored(&self) -> Result<Vec<UInt256>> {
         let mut downgrading = Vec::new();
 
@@ -926,6 +1042,16 @@ impl MessageQueue {
         (overload_message.clone(), Self::rejected_due_to_overload_status(overload_message))
     }
 
+    pub fn is_source_over_quota(&self, origin: &RempMessageOrigin) -> Option<(String, RempMessageStatus)> {
+        // Messages without source are broadcasts, they are limited on receiving
+        let limiter = self.remp_manager.ext_messages_rate_limiter.as_ref()?;
+        if origin.has_no_source_key() || limiter.allow(&origin.source_key) {
+            return None
+        }
+        let overload_message = format!("Source {} is overloaded: rate limit exceeded", origin.source_key);
+        Some((overload_message.clone(), Self::rejected_due_to_overload_status(overload_message)))
+    }
+
     pub async fn is_queue_overloaded(&self) -> Option<(String, RempMessageStatus)> {
         let queue_len = self.get_queue_len().await;
         match self.remp_manager.options.get_message_queue_max_len() {
@@ -1030,7 +1156,7 @@ impl RmqQueueManager {
             )?);
 
             self.cur_queue = Some(Arc::new(MessageQueue::create(
-                self.engine.clone(), self.remp_manager.clone(), remp_catchain_info)?
+                remp_support(self.engine.clone()), self.remp_manager.clone(), remp_catchain_info)?
             ));
         }
         Ok(())
@@ -1179,6 +1305,10 @@ impl RmqQueueManager {
                         uid: message.message_uid.clone()
                     });
                     rejected_message_digests.push(digest);
+                    self.remp_manager.message_cache.trace_event(
+                        msgid, MessageTracePoint::Forwarded,
+                        || format!("RMQ {}: reject digest to {} next queues", self, next_queues.len())
+                    );
                 }
                 else if !is_finally_accepted(&message_status) {
                     if MessageQueue::is_final_status(&message_status) {
@@ -1202,6 +1332,10 @@ impl RmqQueueManager {
                             sent = sent + 1;
                         }
                     }
+                    self.remp_manager.message_cache.trace_event(
+                        msgid, MessageTracePoint::Forwarded,
+                        || format!("RMQ {}: broadcast to {} next queues, status {}", self, next_queues.len(), message_status)
+                    );
                 }
             }
 
@@ -1236,7 +1370,7 @@ impl RmqQueueManager {
 
     pub async fn start(&self, local_key: PrivateKey) -> Result<()> {
         if let Some(cur_queue) = &self.cur_queue {
-            cur_queue.clone().start(local_key).await
+            cur_queue.clone().start(self.engine.clone(), local_key).await
         }
         else {
             log::warn!(target: "remp", "Cannot start RMQ queue for {} -- no current queue",
@@ -1270,7 +1404,7 @@ impl RmqQueueManager {
     pub async fn prepare_messages_for_collation (&self) -> Result<Vec<(UInt256, Arc<Message>, Arc<RempMessageOrigin>)>> {
         if let Some(queue) = &self.cur_queue {
             let messages = queue.prepare_messages_for_collation().await?;
-            Ok(messages)
+            queue.filter_fresh_for_collation(messages).await
         }
         else {
             fail!("Preparing messages for collation: RMQ {} is not started", self)
@@ -1298,7 +1432,9 @@ impl RmqQueueManager {
                     }
                 )
             });
-            process_block_messages_by_blockid(self.engine.clone(), &self.remp_manager.message_cache, id, proc).await?;
+            process_block_messages_by_blockid(
+                block_access(self.engine.clone()), &self.remp_manager.message_cache, id, proc
+            ).await?;
 
             // Point 7, Part 1. Collect and restart collation for all accepted by collator, but ignored in shardchain.
             let returned_msgs = queue.all_accepted_by_collator_to_ignored().await?;
@@ -1332,7 +1468,12 @@ impl RmqQueueManager {
             'a: loop {
                 match self.remp_manager.poll_incoming(&self.shard).await {
                     (Some(msg), _) => {
-                        if let Some((overload_message, status)) = cur_queue.is_queue_overloaded().await {
+                        if let Some((overload_message, status)) = cur_queue.is_source_over_quota(&msg.origin) {
+                            log::warn!(target: "remp", "Point 3. RMQ {}: {}, ignoring incoming message {}", self, overload_message, msg);
+                            cur_queue.send_response_to_fullnode(&msg.get_message_id(), Arc::new(msg.origin.clone()), status);
+                            cnt_rejected_overload+=1;
+                        }
+                        else if let Some((overload_message, status)) = cur_queue.is_queue_overloaded().await {
                             log::warn!(target: "remp", "Point 3. RMQ {}: {}, ignoring incoming message {}", self, overload_message, msg);
                             cur_queue.send_response_to_fullnode(&msg.get_message_id(), Arc::new(msg.origin.clone()), status);
                             cnt_rejected_overload+=1;
@@ -1445,7 +1586,7 @@ impl RempQueueCollatorInterfaceImpl {
         }
     }
 
-    fn compute_ordering_hash(msg_id: &UInt256, prev_blocks_ids: &[&BlockIdExt]) -> UInt256 {
+    pub fn compute_ordering_hash(msg_id: &UInt256, prev_blocks_ids: &[&BlockIdExt]) -> UInt256 {
         let mut hasher = Sha256::new();
         for prev_id in prev_blocks_ids {
             hasher.update(prev_id.root_hash().as_slice());
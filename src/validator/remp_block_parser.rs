@@ -22,9 +22,9 @@ use ever_block::{BlockIdExt, Deserializable, HashmapAugType, InMsg, ShardIdent};
 use ton_api::ton::ton_node::{RempMessageLevel, RempMessageStatus, rempmessagestatus::RempAccepted};
 use storage::block_handle_db::BlockHandle;
 
-use crate::{block::BlockStuff, engine_traits::EngineOperations};
+use crate::{block::BlockStuff, engine_traits::{BlockAccess, EngineOperations}};
 use crate::types::shard_blocks_observer::ShardBlocksObserver;
-use crate::validator::message_cache::MessageCache;
+use crate::validator::message_cache::{MessageCache, MessageTracePoint};
 use crate::validator::sessions_computing::{SessionValidatorsCache, SessionValidatorsList};
 use crate::validator::validator_utils::get_message_uid;
 
@@ -57,7 +57,7 @@ pub async fn process_block_messages (message_cache: &MessageCache, block: BlockS
 }
 
 pub async fn process_block_messages_by_blockid (
-    engine: Arc<dyn EngineOperations>, message_cache: &MessageCache, id: BlockIdExt, msg_processor: Arc<dyn BlockProcessor>
+    engine: Arc<dyn BlockAccess>, message_cache: &MessageCache, id: BlockIdExt, msg_processor: Arc<dyn BlockProcessor>
 ) -> Result<()> {
     let handle = engine.load_block_handle(&id)?
         .ok_or_else(|| error!("Cannot load handle {}", id))?;
@@ -94,6 +94,10 @@ impl BlockProcessor for RempMasterBlockIndexingProcessor {
         ) {
             log::warn!(target: "remp", "Update message {:x}, uid {:x} status failed: {}", message_id, message_uid, e);
         }
+        self.message_cache.trace_event(
+            message_id, MessageTracePoint::BlockIndexed,
+            || format!("block {}, master block {}", self.block_id, self.master_id)
+        );
     }
 }
 
@@ -226,7 +230,7 @@ impl RempMasterblockObserver {
 }
 
 /// Returns first non-processed block before `start`, but after `target_mc`
-pub async fn check_history_up_to_cc(engine: Arc<dyn EngineOperations>, message_cache: Arc<MessageCache>, start: &Vec<BlockIdExt>, target_cc: u32)
+pub async fn check_history_up_to_cc(engine: Arc<dyn BlockAccess>, message_cache: Arc<MessageCache>, start: &Vec<BlockIdExt>, target_cc: u32)
     -> Result<Option<BlockIdExt>>
 {
     let mut blocks_to_process = start.clone();
@@ -258,14 +262,14 @@ pub async fn check_history_up_to_cc(engine: Arc<dyn EngineOperations>, message_c
     return Ok(None);
 }
 
-async fn get_block_cc_seqno(engine: Arc<dyn EngineOperations>, blk: &BlockIdExt) -> Result<u32> {
+async fn get_block_cc_seqno(engine: Arc<dyn BlockAccess>, blk: &BlockIdExt) -> Result<u32> {
     let handle = engine.load_block_handle(blk)?.ok_or_else(|| error!("Cannot load info for block {}", blk))?;
     let block_stuff = engine.load_block(&handle).await?;
     let block_info = block_stuff.block()?.read_info()?;
     Ok(block_info.gen_catchain_seqno())
 }
 
-pub async fn find_previous_sessions(engine: Arc<dyn EngineOperations>, session_cache: &SessionValidatorsCache, prev: &Vec<BlockIdExt>, curr_cc_seqno: u32, curr_shard: &ShardIdent)
+pub async fn find_previous_sessions(engine: Arc<dyn BlockAccess>, session_cache: &SessionValidatorsCache, prev: &Vec<BlockIdExt>, curr_cc_seqno: u32, curr_shard: &ShardIdent)
     -> Result<Arc<SessionValidatorsList>>
 {
     if let Some(prev_list) = session_cache.get_prev_list (curr_shard, curr_cc_seqno) {
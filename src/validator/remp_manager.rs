@@ -15,7 +15,8 @@ use std::{
     fmt, fmt::{Display, Formatter},
     collections::{HashMap, HashSet, VecDeque},
     ops::RangeInclusive,
-    sync::Arc,
+    str::FromStr,
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
     time::Duration
 };
 use std::cmp::{max, Reverse};
@@ -27,10 +28,17 @@ use ever_block::{error, fail, KeyId, Result, SliceData, UInt256};
 
 use crate::{
     config::RempConfig,
-    engine_traits::{EngineOperations, RempCoreInterface, RempDuplicateStatus},
+    engine::now_duration,
+    engine_traits::{state_access, RempCoreInterface, RempDuplicateStatus, RempSupport},
+    ext_messages::{limits::ExtMessageLimits, rate_limiter::ExtMessagesRateLimiter},
+    time_service::TimeService,
     validator::{
-        message_cache::{RmqMessage, RempMessageOrigin, RempMessageWithOrigin, MessageCache}, mutex_wrapper::MutexWrapper,
+        message_cache::{
+            MessageCache, MessageCacheSnapshot, MessageTraceEvent, RmqMessage, RempMessageOrigin, RempMessageWithOrigin
+        },
+        mutex_wrapper::MutexWrapper,
         remp_catchain::RempCatchainStore,
+        rmq_pending_journal::RmqPendingJournal,
         validator_utils::get_shard_by_message
     }
 };
@@ -41,31 +49,34 @@ use std::time::SystemTime;
 #[cfg(feature = "telemetry")]
 use adnl::telemetry::Metric;
 use chrono::{DateTime, Utc};
-use crossbeam_channel::TryRecvError;
 use rand::Rng;
+use storage::instrumented_channel::{self, instrumented_channel, TryRecvError};
 
 pub struct RempInterfaceQueues {
     message_cache: Arc<MessageCache>,
     runtime: Arc<tokio::runtime::Handle>,
-    pub engine: Arc<dyn EngineOperations>,
+    ext_message_time_window: Option<(u32, u32)>,
+    ext_message_limits: Option<Arc<ExtMessageLimits>>,
+    time_service: Option<Arc<TimeService>>,
+    pub engine: Arc<dyn RempSupport>,
     pub incoming_sender: 
-        crossbeam_channel::Sender<Arc<RempMessageWithOrigin>>,
+        instrumented_channel::Sender<Arc<RempMessageWithOrigin>>,
     pub response_receiver: 
-        crossbeam_channel::Receiver<(UInt256, UInt256, Arc<RempMessageOrigin>, RempMessageStatus)>
+        instrumented_channel::Receiver<(UInt256, UInt256, Arc<RempMessageOrigin>, RempMessageStatus)>
 }
 
 pub struct RempDelayer {
     max_incoming_broadcast_delay_millis: u32,
     random_seed: u64,
 
-    pub incoming_receiver: crossbeam_channel::Receiver<Arc<RempMessageWithOrigin>>,
+    pub incoming_receiver: instrumented_channel::Receiver<Arc<RempMessageWithOrigin>>,
     pub delayed_incoming_sender: crossbeam_channel::Sender<Arc<RempMessageWithOrigin>>,
     delay_heap: MutexWrapper<(BinaryHeap<(Reverse<SystemTime>, UInt256)>, HashMap<UInt256, Arc<RempMessageWithOrigin>>)>,
 }
 
 impl RempDelayer {
     pub fn new (random_seed: u64, options: &RempConfig,
-                incoming_receiver: crossbeam_channel::Receiver<Arc<RempMessageWithOrigin>>,
+                incoming_receiver: instrumented_channel::Receiver<Arc<RempMessageWithOrigin>>,
                 delayed_incoming_sender: crossbeam_channel::Sender<Arc<RempMessageWithOrigin>>) -> Self {
         Self {
             max_incoming_broadcast_delay_millis: options.get_max_incoming_broadcast_delay_millis(),
@@ -321,13 +332,13 @@ impl<T: fmt::Display, Q: RempQueue<T>> RempQueueDispatcher<T,Q> {
 }
 
 pub struct RempIncomingQueue {
-    engine: Arc<dyn EngineOperations>,
+    engine: Arc<dyn RempSupport>,
     pub incoming_receiver: crossbeam_channel::Receiver<Arc<RempMessageWithOrigin>>
 }
 
 impl RempIncomingQueue {
     pub fn new(
-        engine: Arc<dyn EngineOperations>, 
+        engine: Arc<dyn RempSupport>, 
         incoming_receiver: crossbeam_channel::Receiver<Arc<RempMessageWithOrigin>>
     ) -> Self {
         RempIncomingQueue { engine, incoming_receiver }
@@ -347,7 +358,7 @@ impl RempQueue<RempMessageWithOrigin> for RempIncomingQueue {
     }
 
     async fn compute_shard(&self, msg: Arc<RempMessageWithOrigin>) -> Result<ShardIdent> {
-        get_shard_by_message(self.engine.clone(), msg.message.message.clone()).await
+        get_shard_by_message(state_access(self.engine.clone()), msg.message.message.clone()).await
     }
 }
 
@@ -386,19 +397,24 @@ impl RempQueue<CollatorResult> for CollatorInterfaceWrapper {
 // Point 6. collator receipt queue -         with dispatcher     @ RempManager
 // Point 7.          ... then returns back to step 5
 
-#[derive(Default)]
+#[derive(Debug, Default)]
 pub struct RempSessionStats {
     pub total: usize,
     pub accepted_in_session: usize,
     pub rejected_in_session: usize,
+    // Part of rejected ones, whose client deadline has passed
+    pub expired_by_deadline: usize,
     pub has_only_header: usize,
-    pub incorrect: usize
+    pub incorrect: usize,
+    // Filled for live sessions only (see `get_session_stats`)
+    pub accepted_in_shardchain: usize,
+    pub ignored: usize
 }
 
 impl Display for RempSessionStats {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{} total ({} finally accepted, {} finally rejected, {} lost), {} only status in cache, {} incorrect state",
-               self.total, self.accepted_in_session, self.rejected_in_session,
+        write!(f, "{} total ({} finally accepted, {} finally rejected, {} of them by deadline, {} lost), {} only status in cache, {} incorrect state",
+               self.total, self.accepted_in_session, self.rejected_in_session, self.expired_by_deadline,
                self.total - self.accepted_in_session - self.rejected_in_session,
                self.has_only_header,
                self.incorrect
@@ -411,8 +427,11 @@ impl RempSessionStats {
         self.total += addtional.total;
         self.accepted_in_session += addtional.accepted_in_session;
         self.rejected_in_session += addtional.rejected_in_session;
+        self.expired_by_deadline += addtional.expired_by_deadline;
         self.has_only_header += addtional.has_only_header;
         self.incorrect += addtional.incorrect;
+        self.accepted_in_shardchain += addtional.accepted_in_shardchain;
+        self.ignored += addtional.ignored;
     }
 }
 
@@ -421,23 +440,78 @@ pub struct RempManager {
 
     pub catchain_store: Arc<RempCatchainStore>,
     pub message_cache: Arc<MessageCache>,
+    pub pending_journal: Arc<RmqPendingJournal>,
+    persisted_messages_restored: AtomicBool,
+    pub ext_messages_rate_limiter: Option<Arc<ExtMessagesRateLimiter>>,
     incoming_delayer: RempDelayer,
     incoming_dispatcher: RempQueueDispatcher<RempMessageWithOrigin, RempIncomingQueue>,
     //pub collator_receipt_dispatcher: RempQueueDispatcher<CollatorResult, CollatorInterfaceWrapper>,
-    pub response_sender: crossbeam_channel::Sender<(UInt256 /* local_id */, UInt256 /* message_id */, Arc<RempMessageOrigin>, RempMessageStatus)>
+    pub response_sender: instrumented_channel::Sender<(UInt256 /* local_id */, UInt256 /* message_id */, Arc<RempMessageOrigin>, RempMessageStatus)>
 }
 
 impl RempManager {
-    pub fn create_with_options(engine: Arc<dyn EngineOperations>, opt: RempConfig, runtime: Arc<tokio::runtime::Handle>)
+    pub fn create_with_options(engine: Arc<dyn RempSupport>, opt: RempConfig, runtime: Arc<tokio::runtime::Handle>)
         -> (Self, RempInterfaceQueues) 
     {
-        let (incoming_sender, incoming_receiver) = crossbeam_channel::unbounded();
+        let (incoming_sender, incoming_receiver) = instrumented_channel(
+            "REMP incoming",
+            opt.get_incoming_queue_limits(),
+            #[cfg(feature = "telemetry")]
+            engine.remp_core_telemetry().incoming_queue_telemetry()
+        );
         let (delayed_incoming_sender, delayed_incoming_receiver) = crossbeam_channel::unbounded();
-        let (response_sender, response_receiver) = crossbeam_channel::unbounded();
-        let message_cache = Arc::new(MessageCache::with_metrics(
+        let (response_sender, response_receiver) = instrumented_channel(
+            "REMP response",
+            opt.get_response_queue_limits(),
+            #[cfg(feature = "telemetry")]
+            engine.remp_core_telemetry().response_queue_telemetry()
+        );
+        let persistent_db = if opt.is_persistent_message_cache() {
+            match engine.remp_messages_db() {
+                Ok(db) => Some(db),
+                Err(e) => {
+                    log::error!(target: "remp", "Cannot open REMP messages db, message cache is not persistent: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let pending_journal_db = if persistent_db.is_some() {
+            match engine.remp_pending_records_db() {
+                Ok(db) => Some(db),
+                Err(e) => {
+                    log::error!(target: "remp", "Cannot open REMP pending records db, journal is not persistent: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let pending_journal = Arc::new(RmqPendingJournal::with_db(pending_journal_db));
+        match pending_journal.restore() {
+            Ok(0) => (),
+            Ok(restored) => log::info!(target: "remp", "{} pending records restored from journal", restored),
+            Err(e) => log::error!(target: "remp", "Cannot restore pending records journal: {}", e)
+        }
+        let message_cache = MessageCache::with_metrics(
+            opt.get_max_cached_messages(),
+            opt.get_duplicate_policy(),
+            persistent_db,
             #[cfg(feature = "telemetry")]
             engine.remp_core_telemetry().cache_size_metric()
-        ));
+        );
+        #[cfg(feature = "external_db")]
+        let message_cache = message_cache.with_history_exporter(engine.remp_history_exporter());
+        let message_cache = Arc::new(message_cache);
+        for id in opt.get_traced_messages() {
+            match UInt256::from_str(id) {
+                Ok(id) => if !message_cache.trace_message(&id) {
+                    log::warn!(target: "remp", "Too many traced messages, {:x} is not traced", id)
+                },
+                Err(e) => log::warn!(target: "remp", "Invalid traced message id {}: {}", id, e)
+            }
+        }
 
         let mut delay_random_rng = rand::thread_rng();
         let delay_random_seed: u64 = delay_random_rng.gen();
@@ -445,6 +519,9 @@ impl RempManager {
             options: opt.clone(),
             catchain_store: Arc::new(RempCatchainStore::new()),
             message_cache: message_cache.clone(),
+            pending_journal,
+            persisted_messages_restored: AtomicBool::new(false),
+            ext_messages_rate_limiter: engine.ext_messages_rate_limiter(),
             incoming_delayer: RempDelayer::new(delay_random_seed, &opt, incoming_receiver, delayed_incoming_sender),
             incoming_dispatcher: RempQueueDispatcher::with_metric(
                 "incoming".to_string(),
@@ -456,8 +533,11 @@ impl RempManager {
             ),
             response_sender: response_sender
         }, RempInterfaceQueues { 
+            ext_message_limits: engine.ext_message_limits(),
+            time_service: engine.time_service(),
             engine,
             runtime,
+            ext_message_time_window: opt.get_ext_message_time_window(),
             message_cache: message_cache.clone(), 
             incoming_sender, 
             response_receiver 
@@ -494,8 +574,9 @@ impl RempManager {
     }
 
     pub fn queue_response_to_fullnode(&self, local_key_id: UInt256, message_id: UInt256, origin: Arc<RempMessageOrigin>, status: RempMessageStatus) -> Result<()> {
-        self.response_sender.send((local_key_id, message_id, origin, status))?;
-        Ok(())
+        self.response_sender.send((local_key_id, message_id, origin, status)).map_err(
+            |e| error!("Cannot queue response for message {:x}: {}", message_id, e)
+        )
     }
 
     /// Garbage collects all messages from message cache, which are older than master cc `actual_lwb`
@@ -503,6 +584,16 @@ impl RempManager {
         self.message_cache.gc_old_messages(actual_lwb).await
     }
 
+    /// Returns current stats for messages of master cc session `master_cc`
+    pub fn get_session_stats(&self, master_cc: u32) -> Result<RempSessionStats> {
+        self.message_cache.get_session_stats(master_cc)
+    }
+
+    /// Returns master cc seqnos of all sessions known to message cache
+    pub fn get_all_sessions(&self) -> Vec<u32> {
+        self.message_cache.get_all_sessions()
+    }
+
     pub fn create_master_cc_session(&self, new_cc_seqno: u32, new_time: UnixTime32, inf_blocks: Vec<BlockIdExt>) -> Result<()> {
         self.message_cache.try_set_master_cc_start_time(new_cc_seqno, new_time, inf_blocks)
     }
@@ -520,6 +611,26 @@ impl RempManager {
         self.message_cache.update_master_cc_ranges(new_cc_seqno, rp_guarantee)
     }
 
+    /// Restores message cache contents saved before the node restart (if persistence is enabled).
+    /// Messages of master cc sessions outside `current_cc_range` are dropped.
+    /// Only the first call does the work, subsequent calls return (0, 0).
+    pub fn restore_persisted_messages(&self, current_cc_range: &RangeInclusive<u32>) -> Result<(usize, usize)> {
+        if self.persisted_messages_restored.swap(true, Ordering::Relaxed) {
+            return Ok((0, 0))
+        }
+        self.message_cache.restore_persisted(current_cc_range)
+    }
+
+    /// Starts tracing of the message, false if too many messages are traced already
+    pub fn trace_message(&self, message_id: &UInt256) -> bool {
+        self.message_cache.trace_message(message_id)
+    }
+
+    /// Returns ordered events of the traced message, None if the message is not traced
+    pub fn get_message_trace(&self, message_id: &UInt256) -> Option<Vec<MessageTraceEvent>> {
+        self.message_cache.get_message_trace(message_id)
+    }
+
     pub fn calc_rp_guarantee(&self, config: &CatchainConfig) -> Duration {
         Duration::from_secs(config.mc_catchain_lifetime as u64)
     }
@@ -562,6 +673,14 @@ impl RempInterfaceQueues {
         }
     }
 
+    pub fn get_session_stats(&self, master_cc: u32) -> Result<RempSessionStats> {
+        self.message_cache.get_session_stats(master_cc)
+    }
+
+    pub fn get_all_sessions(&self) -> Vec<u32> {
+        self.message_cache.get_all_sessions()
+    }
+
     pub async fn send_response_to_fullnode(
         &self, local_key_id: UInt256, message_id: UInt256, origin: Arc<RempMessageOrigin>, status: RempMessageStatus
     ) {
@@ -595,9 +714,9 @@ impl RempInterfaceQueues {
             match self.response_receiver.try_recv() {
                 Ok((local_key_id, hdr, origin, status)) => 
                     self.send_response_to_fullnode(local_key_id, hdr, origin, status).await,
-                Err(crossbeam_channel::TryRecvError::Empty) => 
+                Err(TryRecvError::Empty) => 
                     tokio::time::sleep(Duration::from_millis(1)).await,
-                Err(crossbeam_channel::TryRecvError::Disconnected) => return
+                Err(TryRecvError::Disconnected) => return
             }
         }
     }
@@ -606,8 +725,21 @@ impl RempInterfaceQueues {
 #[async_trait::async_trait]
 impl RempCoreInterface for RempInterfaceQueues {
     async fn process_incoming_message(&self, message: &ton_api::ton::ton_node::RempMessage, source: Arc<KeyId>) -> Result<()> {
+        let now = self.time_service.as_ref()
+            .map_or_else(|| now_duration().as_secs() as u32, |time_service| time_service.adjusted_now());
         // build message
-        let remp_message = RmqMessage::from_raw_message(message.message())?;
+        let remp_message = match self.ext_message_time_window {
+            Some((max_age, max_skew)) => RmqMessage::from_raw_message_with_time_check(
+                message.message(),
+                now,
+                max_age,
+                max_skew
+            )?,
+            None => RmqMessage::from_raw_message(message.message())?
+        };
+        if let Some(limits) = &self.ext_message_limits {
+            limits.check(message.message().len(), &remp_message.message)?;
+        }
         if message.id() != &remp_message.message_id {
             fail!("Message with computed id {:x} has different id {:x} in RempMessage struct, message will be ignored",
                 remp_message.message_id, message.id()
@@ -620,19 +752,32 @@ impl RempCoreInterface for RempInterfaceQueues {
             );
         }
         else {
+            // Non-zero envelope timestamp is the deadline given by client
+            let deadline = match *message.timestamp() {
+                timestamp if timestamp > 0 => Some(timestamp as u32),
+                _ => None
+            };
             let remp_message_origin = RempMessageOrigin::new (
                 source,
                 0
-            )?;
+            )?.with_deadline(deadline);
+            if remp_message_origin.is_expired(now) {
+                fail!("Message {:x} deadline {} has passed, message will be ignored",
+                    remp_message.message_id, remp_message_origin.deadline.unwrap_or_default()
+                );
+            }
 
             log::trace!(target: "remp",
                 "Point 1. Adding incoming message {} to incoming queue, known message info {}",
                 remp_message, self.message_cache.get_message_info(&remp_message.message_id)?
             );
+            let message_id = remp_message.message_id.clone();
             self.incoming_sender.send(Arc::new(RempMessageWithOrigin {
                 message: remp_message,
                 origin: remp_message_origin
-            }))?;
+            })).map_err(
+                |e| error!("Cannot queue incoming message {:x}: {}", message_id, e)
+            )?;
             #[cfg(feature = "telemetry")]
             self.engine.remp_core_telemetry().in_channel_from_fullnode(self.incoming_sender.len());
         }
@@ -652,4 +797,28 @@ impl RempCoreInterface for RempInterfaceQueues {
         }
         return res
     }
+
+    fn trace_message(&self, message_id: &UInt256) -> Result<Vec<MessageTraceEvent>> {
+        if !self.message_cache.trace_message(message_id) {
+            fail!("Too many REMP messages are traced, can't trace {:x}", message_id)
+        }
+        Ok(self.message_cache.get_message_trace(message_id).unwrap_or_default())
+    }
+
+    fn message_cache_snapshot(&self) -> Result<MessageCacheSnapshot> {
+        self.message_cache.snapshot()
+    }
+
+    fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>> {
+        let res = self.message_cache.filter_fresh_messages(ids);
+        match &res {
+            Ok(fresh) =>
+                log::trace!(target: "remp", "RempInterfaceQueues: {} of {} messages are fresh",
+                    fresh.iter().filter(|f| **f).count(), ids.len()
+                ),
+            Err(e) =>
+                log::error!(target: "remp", "RempInterfaceQueues: filtering of {} messages failed: {:?}", ids.len(), e)
+        }
+        res
+    }
 }
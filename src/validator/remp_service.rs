@@ -13,7 +13,7 @@
 
 use crate::{
     engine_traits::{EngineOperations, RempCoreInterface, RempDuplicateStatus},
-    network::remp::RempMessagesSubscriber
+    network::remp::RempMessagesSubscriber, validator::message_cache::{MessageCacheSnapshot, MessageTraceEvent}
 };
 
 use std::sync::{Arc, Weak};
@@ -48,11 +48,36 @@ impl RempService {
         self.get_core_interface()?.check_remp_duplicate(id)
     }
 
+    pub fn filter_fresh_messages(&self, ids: &[(UInt256, UInt256)]) -> Result<Vec<bool>> {
+        self.get_core_interface()?.filter_fresh_messages(ids)
+    }
+
+    pub fn trace_message(&self, id: &UInt256) -> Result<Vec<MessageTraceEvent>> {
+        self.get_core_interface()?.trace_message(id)
+    }
+
+    pub fn message_cache_snapshot(&self) -> Result<MessageCacheSnapshot> {
+        self.get_core_interface()?.message_cache_snapshot()
+    }
+
+    fn get_engine(&self) -> Result<Arc<dyn EngineOperations>> {
+        self.engine
+            .get().ok_or_else(|| error!("engine was not set"))?
+            .upgrade().ok_or_else(|| error!("engine weak reference is null"))
+    }
+
+    // Called by prevalidation worker
+    pub async fn process_message(&self, message: ton_api::ton::ton_node::RempMessage, source: &Arc<KeyId>) {
+        let id = message.id().clone();
+        match self.process_incoming_message(&message, source).await {
+            Ok(_) => log::trace!(target: "remp", "Point 0. Processed incoming REMP message {:x}", id),
+            Err(e) => log::error!(target: "remp", "Point 0. Error processing incoming REMP message {:x}: {}", id, e)
+        }
+    }
+
     async fn process_incoming_message(&self, message: &ton_api::ton::ton_node::RempMessage, source: &Arc<KeyId>) -> Result<()> {
         // TODO send error receipt in case of any error
-        let engine = self.engine
-            .get().ok_or_else(|| error!("engine was not set"))?
-            .upgrade().ok_or_else(|| error!("engine weak reference is null"))?;
+        let engine = self.get_engine()?;
 
         let remp_core = self.get_core_interface()?;
 
@@ -75,9 +100,13 @@ impl RempMessagesSubscriber for RempService {
 
         let id = message.id().clone();
         log::trace!(target: "remp", "Point 0. Processing incoming REMP message {:x}", id);
-        match self.process_incoming_message(&message, source).await {
-            Ok(_) => log::trace!(target: "remp", "Point 0. Processed incoming REMP message {:x}", id),
-            Err(e) => log::error!(target: "remp", "Point 0. Error processing incoming REMP message {:x}: {}", id, e)
+        match self.get_engine().and_then(|engine| engine.queue_remp_message(message, source)) {
+            Ok(true) => log::trace!(target: "remp", "Point 0. Queued incoming REMP message {:x}", id),
+            Ok(false) => log::warn!(
+                target: "remp", "Point 0. Dropped incoming REMP message {:x} from {}: prevalidation queue is full",
+                id, source
+            ),
+            Err(e) => log::error!(target: "remp", "Point 0. Error queueing incoming REMP message {:x}: {}", id, e)
         }
         Ok(())
     }
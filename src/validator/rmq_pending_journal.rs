@@ -0,0 +1,154 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use std::{io::{Cursor, Read}, ops::RangeInclusive, sync::Arc};
+use dashmap::DashMap;
+
+use catchain::serialize_tl_boxed_object;
+use storage::remp_messages_db::RempMessagesDb;
+use ton_api::ton::ton_node::RempCatchainRecordV2;
+use ever_block::{ByteOrderRead, Result, ShardIdent, UInt256};
+
+/// Journal of REMP catchain records, which are accepted by RMQ but not yet
+/// taken for collation. When RMQ is replaced by a new one (new catchain session),
+/// the records are replayed into the new queue, so pending messages are not lost
+/// even if other validators do not re-send them.
+pub struct RmqPendingJournal {
+    /// Shard of RMQ, record and index of relaying RMQ node by message id
+    records: DashMap<UInt256, (ShardIdent, RempCatchainRecordV2, u32)>,
+    db: Option<Arc<RempMessagesDb>>,
+}
+
+impl RmqPendingJournal {
+    pub fn with_db(db: Option<Arc<RempMessagesDb>>) -> Self {
+        Self { records: DashMap::new(), db }
+    }
+
+    fn master_cc(record: &RempCatchainRecordV2) -> Option<u32> {
+        match record {
+            RempCatchainRecordV2::TonNode_RempCatchainMessageHeaderV2(header) =>
+                Some(header.masterchain_seqno as u32),
+            RempCatchainRecordV2::TonNode_RempCatchainMessageDigestV2(_) => None
+        }
+    }
+
+    fn serialize(shard: &ShardIdent, record: &RempCatchainRecordV2, relayer: u32) -> Result<Vec<u8>> {
+        let mut data = shard.workchain_id().to_le_bytes().to_vec();
+        data.extend_from_slice(&shard.shard_prefix_with_tag().to_le_bytes());
+        data.extend_from_slice(&relayer.to_le_bytes());
+        data.extend_from_slice(&serialize_tl_boxed_object!(record));
+        Ok(data)
+    }
+
+    fn deserialize(data: &[u8]) -> Result<(ShardIdent, RempCatchainRecordV2, u32)> {
+        let mut reader = Cursor::new(data);
+        let workchain_id = reader.read_le_u32()? as i32;
+        let shard = ShardIdent::with_tagged_prefix(workchain_id, reader.read_le_u64()?)?;
+        let relayer = reader.read_le_u32()?;
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw)?;
+        Ok((shard, catchain::utils::deserialize_tl_boxed_object(&raw)?, relayer))
+    }
+
+    /// Loads records, journaled before node restart. Unreadable records are removed.
+    /// Returns number of loaded records.
+    pub fn restore(&self) -> Result<usize> {
+        let Some(db) = &self.db else {
+            return Ok(0)
+        };
+        let mut dropped = Vec::new();
+        db.for_each(&mut |key, value| {
+            let message_id = UInt256::from_slice(key);
+            match Self::deserialize(value) {
+                Ok(entry) => { self.records.insert(message_id, entry); },
+                Err(e) => {
+                    log::warn!(target: "remp", "Cannot restore journaled record {:x}: {}", message_id, e);
+                    dropped.push(message_id)
+                }
+            }
+            Ok(true)
+        })?;
+        for message_id in dropped.iter() {
+            db.delete(message_id)?;
+        }
+        Ok(self.records.len())
+    }
+
+    /// Journals message record. Only message headers are journaled:
+    /// digests carry rejects, which are applied to message cache at once.
+    /// Db errors are logged only: the journal in memory remains correct.
+    pub fn add(&self, shard: &ShardIdent, message_id: &UInt256, record: &RempCatchainRecordV2, relayer: u32) {
+        if Self::master_cc(record).is_none() {
+            return
+        }
+        if let Some(db) = &self.db {
+            if let Err(e) = Self::serialize(shard, record, relayer).and_then(|data| db.put(message_id, &data)) {
+                log::error!(target: "remp", "Cannot journal record {:x}: {}", message_id, e);
+            }
+        }
+        self.records.insert(message_id.clone(), (shard.clone(), record.clone(), relayer));
+    }
+
+    /// Removes record of the message, taken for collation or dropped from RMQ
+    pub fn remove(&self, message_id: &UInt256) {
+        if self.records.remove(message_id).is_none() {
+            return
+        }
+        if let Some(db) = &self.db {
+            if let Err(e) = db.delete(message_id) {
+                log::error!(target: "remp", "Cannot remove journaled record {:x}: {}", message_id, e);
+            }
+        }
+    }
+
+    pub fn contains(&self, message_id: &UInt256) -> bool {
+        self.records.contains_key(message_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns records to replay into RMQ of `shard` with `master_cc_range`. The records stay
+    /// in the journal until collation; records of the shard with master cc outside the range
+    /// are discarded.
+    pub fn records_to_replay(
+        &self,
+        shard: &ShardIdent,
+        master_cc_range: &RangeInclusive<u32>
+    ) -> Vec<(RempCatchainRecordV2, u32)> {
+        let mut replay = Vec::new();
+        let mut discarded = Vec::new();
+        for entry in self.records.iter() {
+            let (record_shard, record, relayer) = entry.value();
+            if record_shard != shard {
+                continue
+            }
+            match Self::master_cc(record) {
+                Some(cc) if master_cc_range.contains(&cc) => replay.push((record.clone(), *relayer)),
+                _ => discarded.push(entry.key().clone())
+            }
+        }
+        for message_id in discarded.iter() {
+            log::trace!(target: "remp", "Discarding journaled record {:x}: master cc is not in range {:?}",
+                message_id, master_cc_range
+            );
+            self.remove(message_id);
+        }
+        replay
+    }
+}
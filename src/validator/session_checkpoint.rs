@@ -0,0 +1,210 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use std::{collections::VecDeque, io::Cursor, time::{Duration, Instant}};
+use ever_block::{fail, ByteOrderRead, Result, UInt256};
+
+#[cfg(test)]
+#[path = "tests/test_session_checkpoint.rs"]
+mod tests;
+
+const CHECKPOINT_VERSION: u8 = 1;
+// Only the tail of the session is needed to rejoin it
+const MAX_APPROVED: usize = 64;
+const MAX_SEEN: usize = 256;
+// Checkpoint is written not more often than this
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApprovedCandidate {
+    pub round: u32,
+    pub root_hash: UInt256,
+    pub file_hash: UInt256,
+}
+
+// Minimal state of validator session needed after restart
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionCheckpoint {
+    session_id: UInt256,
+    round: u32,
+    approved: VecDeque<ApprovedCandidate>,
+    seen: VecDeque<UInt256>,
+}
+
+impl SessionCheckpoint {
+
+    pub fn new(session_id: UInt256) -> Self {
+        Self {
+            session_id,
+            round: 0,
+            approved: VecDeque::new(),
+            seen: VecDeque::new(),
+        }
+    }
+
+    pub fn session_id(&self) -> &UInt256 {
+        &self.session_id
+    }
+
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    pub fn approved(&self) -> impl Iterator<Item = &ApprovedCandidate> {
+        self.approved.iter()
+    }
+
+    pub fn seen(&self) -> impl Iterator<Item = &UInt256> {
+        self.seen.iter()
+    }
+
+    // Returns true if round is changed
+    pub fn set_round(&mut self, round: u32) -> bool {
+        if round > self.round {
+            self.round = round;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn add_seen(&mut self, root_hash: &UInt256) -> bool {
+        if self.seen.contains(root_hash) {
+            return false
+        }
+        if self.seen.len() >= MAX_SEEN {
+            self.seen.pop_front();
+        }
+        self.seen.push_back(root_hash.clone());
+        true
+    }
+
+    pub fn add_approved(&mut self, round: u32, root_hash: &UInt256, file_hash: &UInt256) -> bool {
+        if self.is_approved(round, root_hash, file_hash) {
+            return false
+        }
+        if self.approved.len() >= MAX_APPROVED {
+            self.approved.pop_front();
+        }
+        self.approved.push_back(
+            ApprovedCandidate { round, root_hash: root_hash.clone(), file_hash: file_hash.clone() }
+        );
+        true
+    }
+
+    pub fn is_approved(&self, round: u32, root_hash: &UInt256, file_hash: &UInt256) -> bool {
+        self.approved.iter().any(|approved| {
+            approved.round == round && &approved.root_hash == root_hash &&
+                &approved.file_hash == file_hash
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(
+            1 + 32 + 4 * 3 + self.approved.len() * (4 + 64) + self.seen.len() * 32
+        );
+        data.push(CHECKPOINT_VERSION);
+        data.extend_from_slice(self.session_id.as_slice());
+        data.extend_from_slice(&self.round.to_le_bytes());
+        data.extend_from_slice(&(self.approved.len() as u32).to_le_bytes());
+        for approved in &self.approved {
+            data.extend_from_slice(&approved.round.to_le_bytes());
+            data.extend_from_slice(approved.root_hash.as_slice());
+            data.extend_from_slice(approved.file_hash.as_slice());
+        }
+        data.extend_from_slice(&(self.seen.len() as u32).to_le_bytes());
+        for root_hash in &self.seen {
+            data.extend_from_slice(root_hash.as_slice());
+        }
+        data
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(data);
+        let version = reader.read_byte()?;
+        if version != CHECKPOINT_VERSION {
+            fail!("Unsupported session checkpoint version {}", version)
+        }
+        let session_id = UInt256::from(reader.read_u256()?);
+        let round = reader.read_le_u32()?;
+        let count = reader.read_le_u32()? as usize;
+        if count > MAX_APPROVED {
+            fail!("Too many approved candidates in session checkpoint: {}", count)
+        }
+        let mut approved = VecDeque::with_capacity(count);
+        for _ in 0..count {
+            approved.push_back(ApprovedCandidate {
+                round: reader.read_le_u32()?,
+                root_hash: UInt256::from(reader.read_u256()?),
+                file_hash: UInt256::from(reader.read_u256()?),
+            });
+        }
+        let count = reader.read_le_u32()? as usize;
+        if count > MAX_SEEN {
+            fail!("Too many seen candidates in session checkpoint: {}", count)
+        }
+        let mut seen = VecDeque::with_capacity(count);
+        for _ in 0..count {
+            seen.push_back(UInt256::from(reader.read_u256()?));
+        }
+        if (reader.position() as usize) != data.len() {
+            fail!("Session checkpoint has {} extra bytes", data.len() - reader.position() as usize)
+        }
+        Ok(Self { session_id, round, approved, seen })
+    }
+
+}
+
+// Tracks changes of the checkpoint and limits rate of its writes
+pub struct SessionCheckpointer {
+    checkpoint: SessionCheckpoint,
+    dirty: bool,
+    saved_at: Option<Instant>,
+}
+
+impl SessionCheckpointer {
+
+    pub fn new(checkpoint: SessionCheckpoint) -> Self {
+        Self {
+            checkpoint,
+            dirty: false,
+            saved_at: None,
+        }
+    }
+
+    pub fn checkpoint(&self) -> &SessionCheckpoint {
+        &self.checkpoint
+    }
+
+    pub fn update(&mut self, update: impl FnOnce(&mut SessionCheckpoint) -> bool) {
+        if update(&mut self.checkpoint) {
+            self.dirty = true
+        }
+    }
+
+    // Returns serialized checkpoint if it has changes and was not written during interval
+    pub fn take_due(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if !self.dirty {
+            return None
+        }
+        if let Some(saved_at) = self.saved_at {
+            if now.saturating_duration_since(saved_at) < CHECKPOINT_INTERVAL {
+                return None
+            }
+        }
+        self.dirty = false;
+        self.saved_at = Some(now);
+        Some(self.checkpoint.serialize())
+    }
+
+}
@@ -22,12 +22,17 @@ use std::{
     collections::HashMap,
 };
 use ever_block::ShardIdent;
+use storage::instrumented_channel::ChannelTelemetry;
 
 const TR_PER_BLOCK_STEPS: usize = 10;
 const TR_PER_BLOCK_STEP: u32 = 100;
 const GAS_PER_BLOCK_STEPS: usize = 10;
 const GAS_PER_BLOCK_STEP: u32 = 500000;
 const LONG_ATTEMPT_CUTOFF_MS: u32 = 1000;
+// Upper bounds of latency histogram buckets, ms; the last bucket is unbounded
+const LATENCY_BUCKETS_MS: [u32; 14] = [
+    10, 25, 50, 100, 250, 500, 750, 1000, 1500, 2000, 3000, 5000, 10000, 20000
+];
 
 #[derive(Default)]
 pub struct CollatorValidatorTelemetry {
@@ -343,6 +348,201 @@ impl ShardTelemetrySample {
     }
 }
 
+struct LatencyHistogram {
+    buckets: [AtomicU32; LATENCY_BUCKETS_MS.len() + 1],
+    max: AtomicU32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencySummary {
+    pub count: u32,
+    pub p50: u32,
+    pub p95: u32,
+    pub p99: u32,
+    pub max: u32,
+}
+
+impl LatencyHistogram {
+
+    fn new() -> Self {
+        Self {
+            buckets: Default::default(),
+            max: AtomicU32::new(0),
+        }
+    }
+
+    fn add(&self, time: Duration) {
+        let time = min(time.as_millis(), u32::MAX as u128) as u32;
+        let index = LATENCY_BUCKETS_MS.iter().position(|bound| time <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(time, Ordering::Relaxed);
+    }
+
+    fn reset(&self) -> LatencySummary {
+        let mut counts = [0; LATENCY_BUCKETS_MS.len() + 1];
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            counts[i] = bucket.swap(0, Ordering::Relaxed);
+        }
+        Self::summarize(&counts, self.max.swap(0, Ordering::Relaxed))
+    }
+
+    fn summary(&self) -> LatencySummary {
+        let mut counts = [0; LATENCY_BUCKETS_MS.len() + 1];
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            counts[i] = bucket.load(Ordering::Relaxed);
+        }
+        Self::summarize(&counts, self.max.load(Ordering::Relaxed))
+    }
+
+    fn summarize(counts: &[u32], max: u32) -> LatencySummary {
+        let count: u32 = counts.iter().sum();
+        LatencySummary {
+            count,
+            p50: Self::percentile(counts, count, max, 50),
+            p95: Self::percentile(counts, count, max, 95),
+            p99: Self::percentile(counts, count, max, 99),
+            max,
+        }
+    }
+
+    // Upper bound of the bucket holding the given rank, never above observed maximum
+    fn percentile(counts: &[u32], count: u32, max: u32, percent: u64) -> u32 {
+        if count == 0 {
+            return 0
+        }
+        let rank = ((count as u64 * percent + 99) / 100) as u32;
+        let mut seen = 0;
+        for (i, bucket) in counts.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                return match LATENCY_BUCKETS_MS.get(i) {
+                    Some(bound) => min(*bound, max),
+                    None => max
+                }
+            }
+        }
+        max
+    }
+}
+
+struct ShardTimings {
+    collation: LatencyHistogram,
+    validation: LatencyHistogram,
+    broadcast: LatencyHistogram,
+}
+
+impl ShardTimings {
+    fn new() -> Self {
+        Self {
+            collation: LatencyHistogram::new(),
+            validation: LatencyHistogram::new(),
+            broadcast: LatencyHistogram::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShardTimingsSummary {
+    pub shard: ShardIdent,
+    pub collation: LatencySummary,
+    pub validation: LatencySummary,
+    // From collation start to block broadcast, own blocks only
+    pub broadcast: LatencySummary,
+}
+
+// Per-shard latency histograms. The values are accumulated since the last report
+#[derive(Default)]
+pub struct CollationTimingTelemetry {
+    shardes: lockfree::map::Map<ShardIdent, ShardTimings>
+}
+
+impl CollationTimingTelemetry {
+
+    pub fn collation_finished(&self, shard: &ShardIdent, time: Duration) {
+        self.update(shard, |timings| timings.collation.add(time))
+    }
+
+    pub fn validation_finished(&self, shard: &ShardIdent, time: Duration) {
+        self.update(shard, |timings| timings.validation.add(time))
+    }
+
+    pub fn block_broadcast(&self, shard: &ShardIdent, since_collation_start: Duration) {
+        self.update(shard, |timings| timings.broadcast.add(since_collation_start))
+    }
+
+    pub fn collation_summary(&self, shard: &ShardIdent) -> LatencySummary {
+        self.shardes.get(shard).map(|t| t.val().collation.summary()).unwrap_or_default()
+    }
+
+    pub fn validation_summary(&self, shard: &ShardIdent) -> LatencySummary {
+        self.shardes.get(shard).map(|t| t.val().validation.summary()).unwrap_or_default()
+    }
+
+    pub fn broadcast_summary(&self, shard: &ShardIdent) -> LatencySummary {
+        self.shardes.get(shard).map(|t| t.val().broadcast.summary()).unwrap_or_default()
+    }
+
+    pub fn summaries(&self) -> Vec<ShardTimingsSummary> {
+        let mut ret = self.shardes.iter().map(|t| ShardTimingsSummary {
+            shard: t.key().clone(),
+            collation: t.val().collation.summary(),
+            validation: t.val().validation.summary(),
+            broadcast: t.val().broadcast.summary(),
+        }).collect::<Vec<_>>();
+        ret.sort_by_key(|s| (s.shard.workchain_id(), s.shard.shard_prefix_with_tag()));
+        ret
+    }
+
+    pub fn report(&self) -> String {
+        let mut shardes = self.shardes.iter().map(|t| ShardTimingsSummary {
+            shard: t.key().clone(),
+            collation: t.val().collation.reset(),
+            validation: t.val().validation.reset(),
+            broadcast: t.val().broadcast.reset(),
+        }).collect::<Vec<_>>();
+        shardes.sort_by_key(|s| (s.shard.workchain_id(), s.shard.shard_prefix_with_tag()));
+
+        if shardes.is_empty() {
+            return "No one block".to_owned();
+        }
+
+        let mut report = string_builder::Builder::default();
+        report.append(format!(
+            "time, ms            {:>8} {:>5} {:>5} {:>5} {:>5}\n", "count", "p50", "p95", "p99", "max"
+        ));
+        for s in shardes {
+            report.append(format!("***\n{}:\n", s.shard));
+            for (name, summary) in [
+                ("collation", &s.collation),
+                ("validation", &s.validation),
+                ("broadcast", &s.broadcast)
+            ] {
+                report.append(format!(
+                    "    {:<16}{:>8} {:>5} {:>5} {:>5} {:>5}\n",
+                    name, summary.count, summary.p50, summary.p95, summary.p99, summary.max
+                ));
+            }
+        }
+        report.string().expect("unexpected error while building collation timing telemetry report")
+    }
+
+    fn update(&self, shard: &ShardIdent, f: impl Fn(&ShardTimings)) {
+        add_unbound_object_to_map_with_update(
+            &self.shardes,
+            shard.clone(),
+            |found| if let Some(found) = found {
+                f(found);
+                Ok(None)
+            } else {
+                let t = ShardTimings::new();
+                f(&t);
+                Ok(Some(t))
+            }
+        ).expect("Can't return error");
+    }
+}
+
 struct RempQueueTelemetry {
     pub got_from_fullnode: AtomicUsize,
     pub in_channel_to_catchain: Arc<Metric>,
@@ -394,6 +594,8 @@ pub struct RempCoreTelemetry {
     combined_receipt_size_bytes: Arc<Metric>,
     combined_receipt_inners: Arc<Metric>,
     combined_receipts_send_rate: Arc<MetricBuilder>,
+    incoming_queue: Arc<ChannelTelemetry>,
+    response_queue: Arc<ChannelTelemetry>,
 }
 
 impl RempCoreTelemetry {
@@ -432,9 +634,25 @@ impl RempCoreTelemetry {
                 Metric::with_total_amount("combined receipts sending rate", period_sec),
                 Self::PERIOD_MEASURE_NANO
             ),
+            incoming_queue: Self::channel_telemetry("incoming queue", period_sec),
+            response_queue: Self::channel_telemetry("response queue", period_sec),
         }
     }
 
+    fn channel_telemetry(name: &str, period_sec: u64) -> Arc<ChannelTelemetry> {
+        Arc::new(ChannelTelemetry {
+            depth: Metric::without_totals(&format!("{} depth", name), period_sec),
+            enqueued: MetricBuilder::with_metric_and_period(
+                Metric::with_total_amount(&format!("{} in rate", name), period_sec),
+                Self::PERIOD_MEASURE_NANO
+            ),
+            overflows: MetricBuilder::with_metric_and_period(
+                Metric::with_total_amount(&format!("{} overflow rate", name), period_sec),
+                Self::PERIOD_MEASURE_NANO
+            ),
+        })
+    }
+
     pub fn message_from_fullnode(&self) {
         self.got_from_fullnode.fetch_add(1, Ordering::Relaxed);
     }
@@ -516,6 +734,14 @@ impl RempCoreTelemetry {
     }
 
     #[allow(dead_code)]
+    pub fn incoming_queue_telemetry(&self) -> Arc<ChannelTelemetry> {
+        self.incoming_queue.clone()
+    }
+
+    pub fn response_queue_telemetry(&self) -> Arc<ChannelTelemetry> {
+        self.response_queue.clone()
+    }
+
     pub fn cache_size(&self, size: usize) {
         self.cache_size.update(size as u64);
     }
@@ -637,6 +863,11 @@ impl RempCoreTelemetry {
         reset_and_print_metric(&self.combined_receipt_size_bytes, &mut report);
         reset_and_print_metric(&self.combined_receipt_inners, &mut report);
         reset_and_print_metric(self.combined_receipts_send_rate.metric(), &mut report);
+        for queue in [&self.incoming_queue, &self.response_queue] {
+            reset_and_print_metric(&queue.depth, &mut report);
+            reset_and_print_metric(queue.enqueued.metric(), &mut report);
+            reset_and_print_metric(queue.overflows.metric(), &mut report);
+        }
 
         report.string().expect("unexpected error while building remp core telemetry report")
     }
@@ -13,7 +13,8 @@
 
 use super::*;
 use crate::{
-    collator_test_bundle::CollatorTestBundle, engine_traits::EngineOperations, 
+    collator_test_bundle::{CollatorTestBundle, ConfigPatch, MissingArtifact, ReplayOutcome}, 
+    engine_traits::{EngineOperations, StateAccess}, 
     test_helper::test_async, types::messages::{count_matching_bits, MsgEnvelopeStuff},
     validator::{
         CollatorSettings, collator,
@@ -21,7 +22,7 @@ use crate::{
         validator_utils::compute_validator_set_cc,
     },
 };
-use ever_block::{Result, AccountIdPrefixFull};
+use ever_block::{Result, AccountIdPrefixFull, ConfigParamEnum};
 use pretty_assertions::assert_eq;
 use std::{fs::{create_dir_all, remove_dir_all}, sync::Arc};
 
@@ -128,6 +129,105 @@ async fn test_collate_first_block() {
     ).await;
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_collate_dry_run() -> Result<()> {
+    let mut bundle = CollatorTestBundle::build_with_zero_state(
+        "src/tests/static/zerostate.boc",
+        &["src/tests/static/basestate0.boc", "src/tests/static/basestate0.boc"]
+    ).await?;
+    let mc_state = bundle.load_last_applied_mc_state().await?;
+    let mut gas_prices = match mc_state.config_params()?.config(20)? {
+        Some(ConfigParamEnum::ConfigParam20(gas_prices)) => gas_prices,
+        _ => panic!("no gas prices in zerostate")
+    };
+    let block_gas_limit = gas_prices.block_gas_limit;
+    gas_prices.block_gas_limit /= 2;
+    bundle.patch_config(&ConfigPatch { params: vec![ConfigParamEnum::ConfigParam20(gas_prices)] })?;
+
+    let bundle = Arc::new(bundle);
+    let patched = bundle.load_last_applied_mc_state().await?;
+    assert_eq!(patched.block_id(), mc_state.block_id());
+    match patched.config_params()?.config(20)? {
+        Some(ConfigParamEnum::ConfigParam20(gas_prices)) => 
+            assert_eq!(gas_prices.block_gas_limit, block_gas_limit / 2),
+        _ => panic!("no gas prices in patched state")
+    }
+
+    let report = bundle.clone().dry_run().await?;
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert_eq!(report.block_id.as_ref().map(|id| id.seq_no()), Some(1));
+    assert!(report.candidate_size > 0);
+
+    // Source state is the same
+    match mc_state.config_params()?.config(20)? {
+        Some(ConfigParamEnum::ConfigParam20(gas_prices)) => 
+            assert_eq!(gas_prices.block_gas_limit, block_gas_limit),
+        _ => panic!("no gas prices in source state")
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_replay_block_validation() -> Result<()> {
+    let build_bundle = || CollatorTestBundle::build_with_zero_state(
+        "src/tests/static/zerostate.boc",
+        &["src/tests/static/basestate0.boc", "src/tests/static/basestate0.boc"]
+    );
+    let bundle = Arc::new(build_bundle().await?);
+    let mc_state = bundle.load_last_applied_mc_state().await?;
+    let cc_seqno = mc_state.shard_state_extra()?.validator_info.catchain_seqno;
+    let mut cc_seqno_with_delta = 0;
+    let nodes = compute_validator_set_cc(
+        &mc_state, bundle.block_id().shard(), 1, cc_seqno, &mut cc_seqno_with_delta
+    )?;
+    let collator = collator::Collator::new(
+        bundle.block_id().shard().clone(),
+        0,
+        bundle.prev_blocks_ids().clone(),
+        ValidatorSet::with_cc_seqno(0, 0, 0, cc_seqno_with_delta, nodes)?,
+        bundle.created_by().clone(),
+        bundle.clone(),
+        None,
+        None,
+        CollatorSettings::default(),
+    )?;
+    let (candidate, _) = collator.collate().await?;
+
+    // Collated block passes validation
+    let mut bundle = build_bundle().await?;
+    bundle.set_candidate(candidate.clone());
+    assert_eq!(bundle.block_id(), &candidate.block_id);
+    match Arc::new(bundle).replay_validation().await? {
+        ReplayOutcome::Validated { .. } => (),
+        outcome => panic!("unexpected outcome {:?}", outcome)
+    }
+
+    // Broken block fails with the validator's error
+    let mut broken = candidate.clone();
+    broken.data.truncate(broken.data.len() / 2);
+    let mut bundle = build_bundle().await?;
+    bundle.set_candidate(broken);
+    match Arc::new(bundle).replay_validation().await? {
+        ReplayOutcome::Failed { error, .. } => assert!(!error.is_empty()),
+        outcome => panic!("unexpected outcome {:?}", outcome)
+    }
+
+    // Block which is not stored can't be replayed
+    let engine = Arc::new(build_bundle().await?) as Arc<dyn EngineOperations>;
+    let outcome = CollatorTestBundle::replay_block_validation(&engine, &candidate.block_id, None).await?;
+    match outcome {
+        ReplayOutcome::MissingArtifacts(missing) => assert_eq!(
+            missing, 
+            [
+                MissingArtifact::BlockProof(candidate.block_id.clone()), 
+                MissingArtifact::BlockData(candidate.block_id.clone())
+            ]
+        ),
+        outcome => panic!("unexpected outcome {:?}", outcome)
+    }
+    Ok(())
+}
+
 // prepare for testing purposes
 fn prepare_test_env_message(
     src_prefix: u64, 
@@ -0,0 +1,261 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use std::collections::HashSet;
+
+const ROUND: u32 = 1_000_000;
+const ELECTED_FOR: u32 = 65536;
+const START_BEFORE: u32 = 32768;
+const END_BEFORE: u32 = 8192;
+const MARGIN: u32 = 600;
+
+fn config() -> KeyRotationConfig {
+    KeyRotationConfig { margin_sec: MARGIN, ..Default::default() }
+}
+
+fn timing(cur_until: u32, next_elected: bool) -> ElectionTiming {
+    ElectionTiming {
+        validators_elected_for: ELECTED_FOR,
+        elections_start_before: START_BEFORE,
+        elections_end_before: END_BEFORE,
+        cur_until,
+        next_elected,
+    }
+}
+
+fn key(i: u8) -> [u8; 32] {
+    [i; 32]
+}
+
+// Key ring and config of the node, the saved state survives "restart"
+#[derive(Default)]
+struct MockOps {
+    generated: parking_lot::Mutex<u8>,
+    validator_keys: parking_lot::Mutex<Vec<([u8; 32], i32)>>,
+    adnl_keys: parking_lot::Mutex<Vec<[u8; 32]>>,
+    in_use: parking_lot::Mutex<HashSet<[u8; 32]>>,
+    saved: parking_lot::Mutex<Option<Vec<u8>>>,
+    fail_register: parking_lot::Mutex<bool>,
+}
+
+impl MockOps {
+    fn with_key(key_id: [u8; 32], election_id: i32) -> Self {
+        let ops = Self::default();
+        ops.validator_keys.lock().push((key_id, election_id));
+        *ops.generated.lock() = 100;
+        ops
+    }
+    fn saved_phase(&self) -> RotationPhase {
+        RotationState::deserialize(self.saved.lock().as_ref().unwrap()).unwrap().phase
+    }
+}
+
+#[async_trait::async_trait]
+impl KeyRotationOps for MockOps {
+    async fn generate_key(&self) -> Result<[u8; 32]> {
+        let mut generated = self.generated.lock();
+        *generated += 1;
+        Ok(key(*generated))
+    }
+    async fn register_keys(&self, keys: &RotationKeys, election_id: i32) -> Result<()> {
+        if *self.fail_register.lock() {
+            fail!("Config file is not writable")
+        }
+        let mut validator_keys = self.validator_keys.lock();
+        if !validator_keys.contains(&(keys.key_id, election_id)) {
+            validator_keys.push((keys.key_id, election_id));
+            self.adnl_keys.lock().push(keys.adnl_key_id);
+        }
+        Ok(())
+    }
+    async fn retire_key(&self, key_id: &[u8; 32], election_id: i32) -> Result<()> {
+        self.validator_keys.lock().retain(|key| *key != (*key_id, election_id));
+        Ok(())
+    }
+    fn validator_keys(&self) -> Result<Vec<([u8; 32], i32)>> {
+        Ok(self.validator_keys.lock().clone())
+    }
+    fn key_in_use(&self, key_id: &[u8; 32]) -> bool {
+        self.in_use.lock().contains(key_id)
+    }
+    fn load_rotation_state(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.saved.lock().clone())
+    }
+    fn save_rotation_state(&self, data: &[u8]) -> Result<()> {
+        *self.saved.lock() = Some(data.to_vec());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rotation_window() {
+    // Elections of the next round are going on
+    let window = timing(ROUND, false).next_window(MARGIN);
+    assert_eq!(window, RotationWindow {
+        election_id: ROUND,
+        opens_at: ROUND - START_BEFORE,
+        closes_at: ROUND - END_BEFORE - MARGIN,
+    });
+    assert!(!window.contains(ROUND - START_BEFORE - 1));
+    assert!(window.contains(ROUND - START_BEFORE));
+    assert!(!window.contains(ROUND - END_BEFORE - MARGIN));
+
+    // Next round is elected, the window is for the round after it
+    let window = timing(ROUND, true).next_window(MARGIN);
+    assert_eq!(window.election_id, ROUND + ELECTED_FOR);
+    assert_eq!(window.opens_at, ROUND + ELECTED_FOR - START_BEFORE);
+}
+
+#[test]
+fn test_rotation_state_serialization() {
+    let state = RotationState {
+        phase: RotationPhase::Retiring,
+        election_id: ROUND,
+        last_election_id: ROUND - ELECTED_FOR,
+        new_keys: Some(RotationKeys { key_id: key(1), adnl_key_id: key(2) }),
+        old_keys: vec![(key(3), (ROUND - ELECTED_FOR) as i32), (key(4), 1)],
+        provided_keys: Some(RotationKeys { key_id: key(5), adnl_key_id: key(6) }),
+    };
+    assert_eq!(RotationState::deserialize(&state.serialize()).unwrap(), state);
+    let state = RotationState::default();
+    assert_eq!(RotationState::deserialize(&state.serialize()).unwrap(), state);
+
+    let mut broken = RotationState { phase: RotationPhase::Registered, ..Default::default() }.serialize();
+    assert!(RotationState::deserialize(&broken).is_err());
+    broken[0] = 0;
+    assert!(RotationState::deserialize(&broken).is_err());
+}
+
+#[tokio::test]
+async fn test_key_rotation_full_cycle() -> Result<()> {
+    let old_election = (ROUND - ELECTED_FOR) as i32;
+    let ops = MockOps::with_key(key(1), old_election);
+    let rotation = KeyRotation::new(config());
+    let timing = timing(ROUND, false);
+
+    // Before elections nothing happens
+    assert_eq!(rotation.update(&ops, &timing, ROUND - START_BEFORE - 1).await?, RotationPhase::Idle);
+    assert!(ops.saved.lock().is_none());
+
+    // New keys are generated and registered in one go
+    assert_eq!(rotation.update(&ops, &timing, ROUND - START_BEFORE).await?, RotationPhase::Registered);
+    assert_eq!(ops.validator_keys()?, [(key(1), old_election), (key(101), ROUND as i32)]);
+    assert_eq!(*ops.adnl_keys.lock(), [key(102)]);
+    let plan = rotation.plan(&ops, &timing).await?;
+    assert_eq!(plan.state.election_id, ROUND);
+    assert_eq!(plan.state.old_keys, [(key(1), old_election)]);
+    assert_eq!(ops.saved_phase(), RotationPhase::Registered);
+
+    // Old key signs until its round ends
+    ops.in_use.lock().insert(key(1));
+    assert_eq!(rotation.update(&ops, &timing, ROUND - 1).await?, RotationPhase::Registered);
+    assert_eq!(rotation.update(&ops, &timing, ROUND).await?, RotationPhase::Retiring);
+    assert_eq!(ops.validator_keys()?.len(), 2);
+
+    // Session of the old key has ended
+    ops.in_use.lock().clear();
+    assert_eq!(rotation.update(&ops, &timing, ROUND + 10).await?, RotationPhase::Idle);
+    assert_eq!(ops.validator_keys()?, [(key(101), ROUND as i32)]);
+    let plan = rotation.plan(&ops, &timing).await?;
+    assert_eq!(plan.state.last_election_id, ROUND);
+    assert_eq!(plan.state.new_keys, None);
+
+    // The same elections are not rotated twice
+    assert_eq!(rotation.update(&ops, &timing, ROUND - START_BEFORE + 100).await?, RotationPhase::Idle);
+    assert_eq!(ops.validator_keys()?.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_key_rotation_restart() -> Result<()> {
+    let old_election = (ROUND - ELECTED_FOR) as i32;
+    let ops = MockOps::with_key(key(1), old_election);
+    // Next round is elected, rotation is for the round after it
+    let timing = timing(ROUND, true);
+    let election_id = ROUND + ELECTED_FOR;
+    let now = election_id - START_BEFORE;
+
+    // Registration fails: keys are generated and saved, node is restarted
+    *ops.fail_register.lock() = true;
+    let rotation = KeyRotation::new(config());
+    assert!(rotation.update(&ops, &timing, now).await.is_err());
+    assert_eq!(ops.saved_phase(), RotationPhase::Prepared);
+    assert_eq!(ops.validator_keys()?.len(), 1);
+
+    // The same keys are registered after restart, nothing is generated again
+    *ops.fail_register.lock() = false;
+    let rotation = KeyRotation::new(config());
+    assert_eq!(rotation.update(&ops, &timing, now + 60).await?, RotationPhase::Registered);
+    assert_eq!(*ops.generated.lock(), 102);
+    assert_eq!(ops.validator_keys()?, [(key(1), old_election), (key(101), election_id as i32)]);
+
+    // Restart while retiring
+    let rotation = KeyRotation::new(config());
+    ops.in_use.lock().insert(key(1));
+    assert_eq!(rotation.update(&ops, &timing, election_id).await?, RotationPhase::Retiring);
+    let rotation = KeyRotation::new(config());
+    assert_eq!(rotation.plan(&ops, &timing).await?.state.phase, RotationPhase::Retiring);
+    assert_eq!(rotation.update(&ops, &timing, election_id + 60).await?, RotationPhase::Retiring);
+    ops.in_use.lock().clear();
+    assert_eq!(rotation.update(&ops, &timing, election_id + 120).await?, RotationPhase::Idle);
+    assert_eq!(ops.validator_keys()?, [(key(101), election_id as i32)]);
+    assert_eq!(ops.saved_phase(), RotationPhase::Idle);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_key_rotation_skips() -> Result<()> {
+    let timing = timing(ROUND, false);
+    let now = ROUND - START_BEFORE;
+
+    // Not a validator
+    let ops = MockOps::default();
+    let rotation = KeyRotation::new(config());
+    assert_eq!(rotation.update(&ops, &timing, now).await?, RotationPhase::Idle);
+    assert_eq!(*ops.generated.lock(), 0);
+
+    // Window is missed
+    let ops = MockOps::with_key(key(1), 1);
+    let rotation = KeyRotation::new(config());
+    assert_eq!(rotation.update(&ops, &timing, ROUND - END_BEFORE - MARGIN).await?, RotationPhase::Idle);
+    assert_eq!(ops.validator_keys()?.len(), 1);
+
+    // Operator has registered a key for the elections
+    ops.validator_keys.lock().push((key(2), ROUND as i32));
+    assert_eq!(rotation.update(&ops, &timing, now).await?, RotationPhase::Idle);
+    assert_eq!(rotation.plan(&ops, &timing).await?.state.last_election_id, ROUND);
+    assert_eq!(*ops.generated.lock(), 100);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_key_rotation_provided_keys() -> Result<()> {
+    let ops = MockOps::with_key(key(1), 1);
+    let timing = timing(ROUND, false);
+    let rotation = KeyRotation::new(config());
+    let provided = RotationKeys { key_id: key(50), adnl_key_id: key(51) };
+    assert!(rotation.provide_keys(&ops, RotationKeys { key_id: key(1), adnl_key_id: key(51) }).await.is_err());
+    rotation.provide_keys(&ops, provided).await?;
+
+    assert_eq!(rotation.update(&ops, &timing, ROUND - START_BEFORE).await?, RotationPhase::Registered);
+    assert_eq!(*ops.generated.lock(), 100);
+    let plan = rotation.plan(&ops, &timing).await?;
+    assert_eq!(plan.state.new_keys, Some(provided));
+    assert_eq!(plan.state.provided_keys, None);
+    assert_eq!(ops.validator_keys()?, [(key(1), 1), (key(50), ROUND as i32)]);
+
+    // Keys are not changed in the middle of rotation
+    assert!(rotation.provide_keys(&ops, RotationKeys { key_id: key(60), adnl_key_id: key(61) }).await.is_err());
+    Ok(())
+}
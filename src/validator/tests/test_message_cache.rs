@@ -20,10 +20,12 @@ use adnl::telemetry::Metric;
 use ton_api::ton::ton_node::{RempMessageLevel, RempMessageStatus, rempmessagestatus::RempAccepted};
 use ever_block::{BlockIdExt, ShardIdent};
 use ever_block::{Result, SliceData, error, UInt256};
+use crate::config::DuplicatePolicy;
 use crate::engine_traits::RempDuplicateStatus;
 use crate::ext_messages::get_level_and_level_change;
 use crate::validator::message_cache::{MessageCache, RmqMessage, RempMessageOrigin};
 use crate::validator::reliable_message_queue::MessageQueue;
+use storage::remp_messages_db::RempMessagesDb;
 
 //use crate::test_helper::init_test_log;
 
@@ -118,6 +120,9 @@ impl MessageCacheTestbench {
         let rt = tokio::runtime::Runtime::new()?;
 
         let cache = MessageCache::with_metrics(
+            None,
+            DuplicatePolicy::LowestId,
+            None,
             #[cfg(feature = "telemetry")]
                 Metric::without_totals("message_cache cache_size_metric", 0)
         );
@@ -356,6 +361,80 @@ fn do_message_cache_simple_random_test() -> Result<()> {
     })
 }
 
+fn add_new_message(cache: &MessageCache, msg: &Arc<RmqMessage>, cc: u32) -> Result<()> {
+    cache.add_external_message_status(
+        &msg.message_id, &msg.message_uid,
+        Some(msg.clone()),
+        Some(Arc::new(RempMessageOrigin::create_empty()?)),
+        RempMessageStatus::TonNode_RempNew,
+        |_old,new| new.clone(),
+        cc
+    )?;
+    Ok(())
+}
+
+fn do_test_message_cache_filter_fresh(policy: DuplicatePolicy) -> Result<()> {
+    let cache = MessageCache::with_metrics(
+        None,
+        policy,
+        None,
+        #[cfg(feature = "telemetry")]
+            Metric::without_totals("message_cache cache_size_metric", 0)
+    );
+    create_sessions(&cache, 2)?;
+
+    let body = gen_random_body(0)?;
+    let mut msg1 = Arc::new(RmqMessage::make_test_message(&body)?);
+    let mut msg2 = Arc::new(RmqMessage::make_test_message(&body)?);
+    if msg1.message_id > msg2.message_id {
+        swap(&mut msg1, &mut msg2);
+    }
+    assert_eq!(msg1.message_uid, msg2.message_uid);
+    let other = Arc::new(RmqMessage::make_test_message(&gen_random_body(1)?)?);
+    let absent = Arc::new(RmqMessage::make_test_message(&gen_random_body(2)?)?);
+
+    // Messages with equal uid are added in two catchain sessions
+    add_new_message(&cache, &msg2, 1)?;
+    add_new_message(&cache, &msg1, 2)?;
+    add_new_message(&cache, &other, 2)?;
+
+    let uid = msg1.message_uid.clone();
+    let ids = vec!(
+        (msg2.message_id.clone(), uid.clone()),
+        (msg1.message_id.clone(), uid.clone()),
+        (other.message_id.clone(), other.message_uid.clone()),
+        (absent.message_id.clone(), absent.message_uid.clone()),
+        (other.message_id.clone(), uid.clone()),
+    );
+    let fresh = cache.filter_fresh_messages(&ids)?;
+    let expected = match policy {
+        // The first in the batch wins, the other one is duplicate of it
+        DuplicatePolicy::FirstAccepted => vec!(true, false, true, false, false),
+        _ => vec!(false, true, true, false, false),
+    };
+    assert_eq!(fresh, expected);
+
+    // Status changed after filtering: uid is in a block now, no one is fresh
+    let block_id = BlockIdExt::with_params(ShardIdent::masterchain(), 1, UInt256::rand(), UInt256::rand());
+    cache.update_message_status(&msg2.message_id, RempMessageStatus::TonNode_RempAccepted(RempAccepted {
+        level: RempMessageLevel::TonNode_RempShardchain,
+        block_id: block_id.clone(),
+        master_id: BlockIdExt::default()
+    }))?;
+    assert_eq!(cache.filter_fresh_messages(&ids[..3])?, vec!(false, false, true));
+    assert_eq!(
+        cache.check_message_duplicates(&msg1.message_id)?, 
+        RempDuplicateStatus::Duplicate(block_id, uid, msg2.message_id.clone())
+    );
+    Ok(())
+}
+
+#[test]
+pub fn test_message_cache_filter_fresh() -> Result<()> {
+    do_test_message_cache_filter_fresh(DuplicatePolicy::LowestId)?;
+    do_test_message_cache_filter_fresh(DuplicatePolicy::FirstAccepted)
+}
+
 #[test]
 pub fn test_message_cache_add_remove() -> Result<()> {
     do_test_message_cache_add_remove(false)
@@ -409,3 +488,94 @@ pub fn test_message_cache_cc_ranges_and_gc() -> Result<()> {
         Ok(())
     })
 }
+
+fn make_persistent_testbench_cache(db: Option<Arc<RempMessagesDb>>) -> MessageCache {
+    MessageCache::with_metrics(
+        None,
+        DuplicatePolicy::LowestId,
+        db,
+        #[cfg(feature = "telemetry")]
+            Metric::without_totals("message_cache cache_size_metric", 0)
+    )
+}
+
+fn create_sessions(cache: &MessageCache, max_cc: u32) -> Result<std::ops::RangeInclusive<u32>> {
+    let mut range = 1..=1;
+    for cc in 1..=max_cc {
+        cache.try_set_master_cc_start_time(cc, cc.into(), vec!())?;
+        range = cache.update_master_cc_ranges(cc, Duration::from_secs(1))?;
+    }
+    Ok(range)
+}
+
+#[test]
+pub fn test_message_cache_persistence() -> Result<()> {
+    let write_through_db = Arc::new(RempMessagesDb::in_memory());
+    let cache = make_persistent_testbench_cache(Some(write_through_db.clone()));
+    let max_cc = 3;
+    let range = create_sessions(&cache, max_cc)?;
+    assert_eq!(range, 2..=max_cc);
+
+    let mut random_thread = thread_rng();
+    let shared_bodies: Vec<SliceData> = (0..10).map(|i| gen_random_body(i)).collect::<Result<_>>()?;
+    let mut old_ids = Vec::new();
+    let mut ids = Vec::new();
+    for i in 0..200 {
+        let cc = i % max_cc + 1;
+        // Messages of the session to be dropped have unique bodies, so they do not affect others
+        let body = if cc < *range.start() {
+            gen_random_body(i as i32)?
+        } else {
+            shared_bodies[random_thread.gen_range(0, shared_bodies.len())].clone()
+        };
+        let msg = Arc::new(RmqMessage::make_test_message(&body)?);
+        let origin = if random_thread.gen_bool(0.5) {
+            Some(Arc::new(RempMessageOrigin::create_empty()?))
+        } else {
+            None
+        };
+        cache.add_external_message_status(
+            &msg.message_id, &msg.message_uid,
+            if random_thread.gen_bool(0.8) { Some(msg.clone()) } else { None },
+            origin,
+            RempMessageStatus::TonNode_RempNew,
+            |_old,new| new.clone(),
+            cc
+        )?;
+        if random_thread.gen_bool(0.3) {
+            let acc = RempAccepted {
+                level: if random_thread.gen_bool(0.3) { RempMessageLevel::TonNode_RempMasterchain } else { RempMessageLevel::TonNode_RempShardchain },
+                block_id: BlockIdExt::with_params(ShardIdent::masterchain(), cc, UInt256::rand(), UInt256::rand()),
+                master_id: Default::default()
+            };
+            cache.update_message_status(&msg.message_id, RempMessageStatus::TonNode_RempAccepted(acc))?;
+        }
+        if cc < *range.start() { old_ids.push(msg.message_id.clone()) } else { ids.push(msg.message_id.clone()) }
+    }
+
+    let persisted_db = RempMessagesDb::in_memory();
+    assert_eq!(cache.persist(&persisted_db)?, cache.all_messages_count().0);
+    assert_eq!(persisted_db.len()?, write_through_db.len()?);
+
+    for db in [&persisted_db, write_through_db.as_ref()] {
+        let restored_cache = make_persistent_testbench_cache(None);
+        let restored_range = create_sessions(&restored_cache, max_cc)?;
+        assert_eq!(restored_range, range);
+
+        let (restored, dropped) = restored_cache.restore(db, &restored_range)?;
+        assert_eq!(restored, ids.len());
+        assert_eq!(dropped, old_ids.len());
+        assert_eq!(db.len()?, ids.len());
+
+        for id in ids.iter() {
+            assert_eq!(restored_cache.check_message_duplicates(id)?, cache.check_message_duplicates(id)?);
+            assert_eq!(restored_cache.get_message_status(id)?, cache.get_message_status(id)?);
+            assert_eq!(restored_cache.get_message(id)?, cache.get_message(id)?);
+            assert_eq!(restored_cache.get_message_origin(id)?, cache.get_message_origin(id)?);
+        }
+        for id in old_ids.iter() {
+            assert_eq!(restored_cache.check_message_duplicates(id)?, RempDuplicateStatus::Absent);
+        }
+    }
+    Ok(())
+}
@@ -15,7 +15,7 @@ use std::{collections::HashSet, str::FromStr, sync::Arc, time::Duration};
 use std::ops::RangeInclusive;
 use std::thread::sleep;
 
-use ton_api::ton::ton_node::{RempMessageLevel, RempMessageStatus, rempmessagestatus::{RempAccepted, RempIgnored}};
+use ton_api::ton::ton_node::{RempMessageLevel, RempMessageStatus, rempmessagestatus::{RempAccepted, RempIgnored, RempRejected}};
 
 use catchain::PublicKey;
 
@@ -27,10 +27,11 @@ use ever_block::{
 };
 
 use crate::{
-    config::RempConfig,
-    engine_traits::{EngineOperations, RempCoreInterface, RempDuplicateStatus},
+    config::{DuplicatePolicy, RempConfig},
+    engine_traits::{RempCoreInterface, RempDuplicateStatus},
+    test_helper::MockRempSupport,
     validator::{
-        message_cache::{RempMessageOrigin, RempMessageWithOrigin},
+        message_cache::{deadline_expired_status, MessageTracePoint, RempMessageOrigin, RempMessageWithOrigin},
         reliable_message_queue::{MessageQueue, RmqMessage},
         remp_block_parser::{BlockProcessor, RempMasterBlockIndexingProcessor},
         remp_catchain::{REMP_CATCHAIN_RECORDS_PER_BLOCK, REMP_MAX_BLOCK_PAYLOAD_LEN, RempCatchain, RempCatchainInfo},
@@ -42,9 +43,6 @@ use crate::{
     }
 };
 
-#[cfg(feature = "telemetry")]
-use crate::validator::telemetry::RempCoreTelemetry;
-
 #[test]
 fn test_rmq_message_serialize() -> Result<()> {
     let pre_message = Message::with_ext_in_header_and_body(
@@ -155,29 +153,8 @@ fn test_rmq_max_payload_constants() -> Result<()> {
     Ok(())
 }
 
-struct RmqTestEngine {
-    #[cfg(feature = "telemetry")]
-    remp_core_telemetry: RempCoreTelemetry,
-}
-
-impl EngineOperations for RmqTestEngine {
-    #[cfg(feature = "telemetry")]
-    fn remp_core_telemetry(&self) -> &RempCoreTelemetry {
-        &self.remp_core_telemetry
-    }
-}
-
-impl RmqTestEngine {
-    pub fn new() -> Self {
-        Self {
-            #[cfg(feature = "telemetry")]
-            remp_core_telemetry : RempCoreTelemetry::new(10),
-        }
-    }
-}
-
 struct RmqTestbench {
-    engine: Arc<RmqTestEngine>,
+    engine: Arc<MockRempSupport>,
     remp_manager: Arc<RempManager>,
     remp_interface_queues: RempInterfaceQueues,
     params: Arc<GeneralSessionInfo>,
@@ -199,9 +176,17 @@ impl RmqTestbench {
     }
 
     async fn new(runtime_handle: &tokio::runtime::Handle, masterchain_seqno: u32, rp_guarantee: Duration) -> Result<Self> {
-        let engine = Arc::new(RmqTestEngine::new());
+        Self::new_with_config(runtime_handle, masterchain_seqno, rp_guarantee, RempConfig::create_empty()).await
+    }
+
+    async fn new_with_config(
+        runtime_handle: &tokio::runtime::Handle, 
+        masterchain_seqno: u32, 
+        rp_guarantee: Duration, 
+        remp_config: RempConfig
+    ) -> Result<Self> {
+        let engine = Arc::new(MockRempSupport::default());
 
-        let remp_config = RempConfig::create_empty();
         let (remp_manager_value, remp_interface_queues) = RempManager::create_with_options(
             engine.clone(), remp_config.clone(), Arc::new(runtime_handle.clone())
         );
@@ -258,7 +243,19 @@ impl RmqTestbench {
         self.remp_manager.message_cache.update_message_body(Arc::new(msg.message.clone()))
     }
 
-    fn replace_message_queue(&mut self, masterchain_range: &RangeInclusive<u32>) -> Result<()> {
+    // Message received by this validator: its origin (with deadline) is cached before the record
+    async fn send_own_pending_message(&self, msg: &RempMessageWithOrigin, masterchain_seqno: u32) -> Result<bool> {
+        let body_updated = self.remp_manager.message_cache.update_message_body_with_origin(
+            Arc::new(msg.message.clone()), Some(Arc::new(msg.origin.clone()))
+        )?;
+        self.message_queue.process_pending_remp_catchain_record(
+            &msg.as_remp_catchain_record(masterchain_seqno),
+            0
+        ).await?;
+        Ok(body_updated)
+    }
+
+    async fn replace_message_queue(&mut self, masterchain_range: &RangeInclusive<u32>) -> Result<()> {
         let info = Arc::new(RempCatchainInfo::create(
             self.params.clone(), masterchain_range,
             &self.curr_validators, &self.next_validators,
@@ -267,13 +264,14 @@ impl RmqTestbench {
         self.message_queue = MessageQueue::create(
             self.engine.clone(), self.remp_manager.clone(), info
         )?;
+        self.message_queue.replay_pending_journal().await?;
         Ok(())
     }
 
     async fn advance_master_cc(&mut self, masterchain_seqno: u32, mc_time: UnixTime32) -> Result<RempSessionStats> {
         self.remp_manager.create_master_cc_session(masterchain_seqno, mc_time, vec!())?;
         let new_range = self.remp_manager.advance_master_cc(masterchain_seqno, self.rp_guarantee)?;
-        self.replace_message_queue(&new_range)?;
+        self.replace_message_queue(&new_range).await?;
         Ok(self.remp_manager.gc_old_messages(*new_range.start()).await)
     }
 }
@@ -349,17 +347,43 @@ fn remp_simple_forwarding_test() -> Result<()> {
 }
 
 #[test]
-fn remp_simple_collation_equal_uids_test() -> Result<()> {
+fn remp_filter_fresh_with_mock_test() -> Result<()> {
+    let runtime = RmqTestbench::create_runtime()?;
+    let runtime_handle = runtime.handle().clone();
+
+    runtime.block_on(async move {
+        // The queue is built over REMP capability mock only, no full engine is needed
+        let testbench = RmqTestbench::new(&runtime_handle, 1, Duration::from_secs(10)).await?;
+        let messages = (0..3).map(|_| {
+            let m = make_test_random_message_with_origin()?;
+            Ok((m.get_message_id().clone(), m.message.message.clone(), Arc::new(m.origin)))
+        }).collect::<Result<Vec<_>>>()?;
+
+        let filtered = testbench.message_queue.filter_fresh_for_collation(messages.clone()).await?;
+        assert_eq!(
+            filtered.iter().map(|(id, _, _)| id).collect::<Vec<_>>(),
+            messages.iter().map(|(id, _, _)| id).collect::<Vec<_>>()
+        );
+        assert_eq!(testbench.engine.calls.calls(), vec!["filter_fresh_messages"]);
+        assert!(testbench.engine.sent_receipts.lock().is_empty());
+        Ok(())
+    })
+}
+
+fn do_remp_simple_collation_equal_uids_test(policy: DuplicatePolicy) -> Result<()> {
     //init_test_log();
     let runtime = RmqTestbench::create_runtime()?;
     let runtime_handle = runtime.handle().clone();
 
     runtime.block_on(async move {
-        let testbench = RmqTestbench::new(&runtime_handle, 2, Duration::from_secs(10)).await?;
+        let mut remp_config = RempConfig::create_empty();
+        remp_config.set_duplicate_policy(policy);
+        let testbench = RmqTestbench::new_with_config(&runtime_handle, 2, Duration::from_secs(10), remp_config).await?;
 
         let body1 = SliceData::from(UInt256::rand());
         let body2 = SliceData::from(UInt256::rand());
 
+        // All messages have different destinations
         let mut msgs = Vec::new();
         for _cnt in 0..5 {
             msgs.push(make_test_message_with_origin(&body1)?);
@@ -367,21 +391,11 @@ fn remp_simple_collation_equal_uids_test() -> Result<()> {
         }
 
         let (_min_id,uid_for_min_id) = msgs.iter().map(|a| (a.get_message_id(), &a.message.message_uid)).min().unwrap();
-        let msg_to_collate = msgs.iter().filter(|x| &x.message.message_uid != uid_for_min_id).map(|x| x.get_message_id()).min().unwrap();
         let (acc_id,acc_uid) = msgs.iter().filter(|x| &x.message.message_uid == uid_for_min_id).map(|x| (x.get_message_id(),&x.message.message_uid)).max().unwrap();
 
-        let mut must_be_collated = HashSet::<UInt256>::new();
-        must_be_collated.insert(msg_to_collate.clone());
-        let must_be_rejected : Vec<RempMessageWithOrigin> = msgs.iter()
-            .filter(|a| !must_be_collated.contains(a.get_message_id()) && a.get_message_id() != acc_id)
-            .map(|a| a.clone())
-            .collect();
-
         for m in msgs.iter() {
-            let pc = if must_be_collated.contains(m.get_message_id()) { "C" } else { "" }.to_string();
             let pa = if m.get_message_id() == acc_id { "A" } else { "" }.to_string();
-            let pr = if must_be_rejected.contains(&m) { "R" } else { "" }.to_string();
-            println!("Pending msg: {:x} {}{}{}", m.get_message_id(), pc, pa, pr);
+            println!("Pending msg: {:x} {}", m.get_message_id(), pa);
 
             // All msg ids are different, therefore body must be added
             assert!(testbench.send_pending_message(m, testbench.message_queue.catchain_info.get_master_cc_seqno()).await?);
@@ -406,12 +420,39 @@ fn remp_simple_collation_equal_uids_test() -> Result<()> {
 //            testbench.engine.new_remp_message(msg_id.clone(), msg)?;
 //        }
 
-        for (id, _msg, _origin) in messages.iter() {
+        let collated: HashSet<UInt256> = messages.iter().map(|(id, _msg, _origin)| id.clone()).collect();
+        for id in collated.iter() {
             println!("collated: {:x}", id);
-            assert!(must_be_collated.remove(&id));
         }
-        assert!(must_be_collated.is_empty());
 
+        let other_uid_msgs: Vec<&UInt256> = msgs.iter()
+            .filter(|x| &x.message.message_uid != uid_for_min_id)
+            .map(|x| x.get_message_id())
+            .collect();
+        match policy {
+            DuplicatePolicy::LowestId => {
+                // Lowest id among messages with not accepted uid wins
+                let msg_to_collate = other_uid_msgs.iter().min().unwrap();
+                assert_eq!(collated, HashSet::from([(*msg_to_collate).clone()]));
+            }
+            DuplicatePolicy::FirstAccepted => {
+                // Exactly one message with not accepted uid wins, whichever is taken first
+                assert_eq!(collated.len(), 1);
+                assert!(other_uid_msgs.iter().any(|id| collated.contains(*id)));
+            }
+            DuplicatePolicy::PreferLocal => {
+                // Messages to different destinations do not compete
+                let expected: HashSet<UInt256> = msgs.iter()
+                    .map(|m| m.get_message_id().clone())
+                    .filter(|id| id != acc_id)
+                    .collect();
+                assert_eq!(collated, expected);
+            }
+        }
+
+        let must_be_rejected : Vec<&RempMessageWithOrigin> = msgs.iter()
+            .filter(|a| !collated.contains(a.get_message_id()) && a.get_message_id() != acc_id)
+            .collect();
         for id in must_be_rejected.iter() {
             let status = testbench.remp_manager.message_cache.get_message_status(id.get_message_id())?;
             if let Some(RempMessageStatus::TonNode_RempRejected(rejected)) = &status {
@@ -421,12 +462,33 @@ fn remp_simple_collation_equal_uids_test() -> Result<()> {
             else {
                 panic!("Expected rejected status, found {:?}", status);
             }
+
+            // Duplicate check gives the same answer
+            match testbench.remp_interface_queues.check_remp_duplicate(id.get_message_id())? {
+                RempDuplicateStatus::Duplicate(_, uid, _) => assert_eq!(uid, id.message.message_uid),
+                d => panic!("Expected duplicate status for {}, found {:?}", id, d)
+            }
         }
 
         Ok(())
     })
 }
 
+#[test]
+fn remp_simple_collation_equal_uids_test() -> Result<()> {
+    do_remp_simple_collation_equal_uids_test(DuplicatePolicy::LowestId)
+}
+
+#[test]
+fn remp_simple_collation_equal_uids_first_accepted_test() -> Result<()> {
+    do_remp_simple_collation_equal_uids_test(DuplicatePolicy::FirstAccepted)
+}
+
+#[test]
+fn remp_simple_collation_equal_uids_prefer_local_test() -> Result<()> {
+    do_remp_simple_collation_equal_uids_test(DuplicatePolicy::PreferLocal)
+}
+
 #[test]
 fn remp_simple_expiration_test() -> Result<()> {
     //init_test_log();
@@ -451,13 +513,26 @@ fn remp_simple_expiration_test() -> Result<()> {
         println!("Collected old messages 5: {}", testbench.advance_master_cc(5, 50.into()).await?);
 
         let mut msgs = Vec::new();
-        for b in bodies.iter() {
+        for (i, b) in bodies.iter().enumerate() {
+            if i == bodies.len() / 2 {
+                // Session is restarted mid-stream: records pending in the old queue are replayed
+                let range = testbench.message_queue.catchain_info.master_cc_range.clone();
+                testbench.replace_message_queue(&range).await?;
+            }
             let m = make_test_message_with_origin(b)?;
             println!("Pending msg 5: {:x}", m.get_message_id());
             testbench.send_pending_message(&m, testbench.message_queue.catchain_info.get_master_cc_seqno()).await?;
             msgs.push(m);
         }
 
+        sleep(Duration::from_millis(10)); // To overcome SystemTime inconsistency and make tests reproducible.
+        let collated: HashSet<UInt256> = testbench.message_queue.prepare_messages_for_collation().await?
+            .into_iter().map(|(id, _msg, _origin)| id).collect();
+        for m in msgs.iter() {
+            assert!(collated.contains(m.get_message_id()), "Pending message {} is lost", m);
+        }
+        assert!(testbench.remp_manager.pending_journal.is_empty());
+
         for m in msgs.iter() {
             assert_eq!(
                 testbench.remp_interface_queues.check_remp_duplicate(m.get_message_id())?,
@@ -495,6 +570,43 @@ fn remp_simple_expiration_test() -> Result<()> {
                 RempDuplicateStatus::Duplicate(_, uid, _) => assert_eq!(m.message.message_uid, uid)
             }
         }
+
+        // Per-message deadlines: the passed one expires before master cc session does,
+        // the distant one does not change cc-based expiration
+        let now = UnixTime32::now().as_u32();
+        let mut short = make_test_random_message_with_origin()?;
+        short.origin = short.origin.with_deadline(Some(now - 1));
+        let mut long = make_test_random_message_with_origin()?;
+        long.origin = long.origin.with_deadline(Some(now + 3600));
+        let no_deadline = make_test_random_message_with_origin()?;
+        for m in [&short, &long, &no_deadline] {
+            testbench.send_own_pending_message(m, testbench.message_queue.catchain_info.get_master_cc_seqno()).await?;
+        }
+
+        sleep(Duration::from_millis(10)); // To overcome SystemTime inconsistency and make tests reproducible.
+        let collated: HashSet<UInt256> = testbench.message_queue.prepare_messages_for_collation().await?
+            .into_iter().map(|(id, _msg, _origin)| id).collect();
+        assert!(!collated.contains(short.get_message_id()));
+        assert!(collated.contains(long.get_message_id()));
+        assert!(collated.contains(no_deadline.get_message_id()));
+        assert_eq!(
+            testbench.remp_manager.message_cache.get_message_status(short.get_message_id())?,
+            Some(deadline_expired_status())
+        );
+        assert!(testbench.remp_manager.pending_journal.is_empty());
+
+        let mut stats = RempSessionStats::default();
+        for cc in 7..=9 {
+            stats.add(&testbench.advance_master_cc(cc, (cc * 10).into()).await?);
+        }
+        println!("Collected old messages 9: {}", stats);
+        assert_eq!(stats.expired_by_deadline, 1);
+        for m in [&short, &long, &no_deadline] {
+            assert_eq!(
+                testbench.remp_interface_queues.check_remp_duplicate(m.get_message_id())?,
+                RempDuplicateStatus::Absent
+            );
+        }
         Ok(())
     })
 }
@@ -648,3 +760,271 @@ fn remp_simple_advance_special_cases() -> Result<()> {
         Ok(())
     })
 }
+
+async fn make_cache_limited_testbench(runtime_handle: &tokio::runtime::Handle, max_cached_messages: usize) -> Result<RmqTestbench> {
+    let mut remp_config = RempConfig::create_empty();
+    remp_config.set_max_cached_messages(Some(max_cached_messages));
+    RmqTestbench::new_with_config(runtime_handle, 2, Duration::from_secs(10), remp_config).await
+}
+
+#[test]
+fn remp_cache_overflow_eviction_test() -> Result<()> {
+    //init_test_log();
+    let runtime = RmqTestbench::create_runtime()?;
+    let runtime_handle = runtime.handle().clone();
+
+    runtime.block_on(async move {
+        let testbench = make_cache_limited_testbench(&runtime_handle, 5).await?;
+        let cache = &testbench.remp_manager.message_cache;
+        let master_cc = testbench.message_queue.catchain_info.get_master_cc_seqno();
+
+        let mut msgs = Vec::new();
+        for _ in 0..5 {
+            let m = make_test_random_message_with_origin()?;
+            assert!(testbench.send_pending_message(&m, master_cc).await?);
+            msgs.push(m);
+        }
+
+        // Two messages get final status and may be evicted
+        for m in msgs[0..2].iter() {
+            cache.add_external_message_status(
+                m.get_message_id(), &m.message.message_uid, None, None,
+                RempMessageStatus::TonNode_RempAccepted(RempAccepted {
+                    level: RempMessageLevel::TonNode_RempMasterchain,
+                    block_id: Default::default(),
+                    master_id: Default::default()
+                }),
+                |_o,n| n.clone(),
+                master_cc
+            )?;
+        }
+
+        let m6 = make_test_random_message_with_origin()?;
+        assert!(testbench.send_pending_message(&m6, master_cc).await?);
+        assert_eq!(cache.get_message_status(m6.get_message_id())?, Some(RempMessageStatus::TonNode_RempNew));
+        assert_eq!(cache.all_messages_count().0, 5);
+
+        let evicted = msgs[0..2].iter().filter(|m| !cache.is_message_present(m.get_message_id())).count();
+        assert_eq!(evicted, 1);
+        for m in msgs[2..].iter() {
+            assert!(cache.is_message_present(m.get_message_id()));
+        }
+
+        // Evicted message is silently dropped from collation queue
+        sleep(Duration::from_millis(10)); // To overcome SystemTime inconsistency and make tests reproducible.
+        let collated: HashSet<UInt256> = testbench.message_queue.prepare_messages_for_collation().await?
+            .into_iter().map(|(id, _msg, _origin)| id).collect();
+        let expected: HashSet<UInt256> = msgs[2..].iter().chain(std::iter::once(&m6))
+            .map(|m| m.get_message_id().clone()).collect();
+        assert_eq!(collated, expected);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn remp_cache_overflow_rejection_test() -> Result<()> {
+    //init_test_log();
+    let runtime = RmqTestbench::create_runtime()?;
+    let runtime_handle = runtime.handle().clone();
+
+    runtime.block_on(async move {
+        let testbench = make_cache_limited_testbench(&runtime_handle, 5).await?;
+        let cache = &testbench.remp_manager.message_cache;
+        let master_cc = testbench.message_queue.catchain_info.get_master_cc_seqno();
+
+        let mut msgs = Vec::new();
+        for _ in 0..5 {
+            let m = make_test_random_message_with_origin()?;
+            assert!(testbench.send_pending_message(&m, master_cc).await?);
+            msgs.push(m);
+        }
+
+        // Nothing to evict: new message is not stored, its body is not added
+        let m6 = make_test_random_message_with_origin()?;
+        assert!(!testbench.send_pending_message(&m6, master_cc).await?);
+        assert!(!cache.is_message_present(m6.get_message_id()));
+        assert_eq!(cache.all_messages_count().0, 5);
+
+        let m7 = make_test_random_message_with_origin()?;
+        let (old_status, new_status, body_updated) = cache.add_external_message_status(
+            m7.get_message_id(), &m7.message.message_uid,
+            Some(Arc::new(m7.message.clone())), Some(Arc::new(m7.origin.clone())),
+            RempMessageStatus::TonNode_RempNew,
+            |_o,n| n.clone(),
+            master_cc
+        )?;
+        assert_eq!(old_status, None);
+        assert!(!body_updated);
+        match new_status {
+            RempMessageStatus::TonNode_RempRejected(rejected) => {
+                assert_eq!(rejected.level, RempMessageLevel::TonNode_RempQueue);
+                assert!(rejected.error.contains("overflow"), "unexpected reason: {}", rejected.error);
+            }
+            s => panic!("Expected rejected status, found {}", s)
+        }
+        assert!(!cache.is_message_present(m7.get_message_id()));
+
+        // Messages with final status are added anyway
+        let from_block = push_random_msgs(&testbench, 1).await?;
+        assert!(cache.is_message_present(&from_block[0].message_id));
+        assert_eq!(cache.all_messages_count().0, 6);
+
+        // Rejected message in collation queue does not break collation
+        sleep(Duration::from_millis(10)); // To overcome SystemTime inconsistency and make tests reproducible.
+        let collated: HashSet<UInt256> = testbench.message_queue.prepare_messages_for_collation().await?
+            .into_iter().map(|(id, _msg, _origin)| id).collect();
+        let expected: HashSet<UInt256> = msgs.iter().map(|m| m.get_message_id().clone()).collect();
+        assert_eq!(collated, expected);
+
+        Ok(())
+    })
+}
+
+fn add_random_msg_with_status(testbench: &RmqTestbench, status: RempMessageStatus, master_cc: u32) -> Result<RempMessageWithOrigin> {
+    let m = make_test_random_message_with_origin()?;
+    testbench.remp_manager.message_cache.add_external_message_status(
+        m.get_message_id(), &m.message.message_uid,
+        Some(Arc::new(m.message.clone())), Some(Arc::new(m.origin.clone())),
+        status,
+        |_o,n| n.clone(),
+        master_cc
+    )?;
+    Ok(m)
+}
+
+#[test]
+fn remp_session_stats_test() -> Result<()> {
+    //init_test_log();
+    let runtime = RmqTestbench::create_runtime()?;
+    let runtime_handle = runtime.handle().clone();
+
+    runtime.block_on(async move {
+        let testbench = RmqTestbench::new(&runtime_handle, 2, Duration::from_secs(10)).await?;
+        assert_eq!(testbench.remp_manager.get_all_sessions(), vec!(1, 2));
+        assert_eq!(testbench.remp_interface_queues.get_all_sessions(), vec!(1, 2));
+
+        let blk1 = BlockIdExt::with_params(
+            testbench.params.shard.clone(),
+            5129,
+            UInt256::rand(),
+            UInt256::rand()
+        );
+        let accepted_shardchain = RempMessageStatus::TonNode_RempAccepted(RempAccepted {
+            level: RempMessageLevel::TonNode_RempShardchain,
+            block_id: blk1.clone(),
+            master_id: Default::default()
+        });
+        let rejected = RempMessageStatus::TonNode_RempRejected(RempRejected {
+            level: RempMessageLevel::TonNode_RempQueue,
+            block_id: Default::default(),
+            error: "test reject".to_string()
+        });
+        let ignored = RempMessageStatus::TonNode_RempIgnored(RempIgnored {
+            level: RempMessageLevel::TonNode_RempShardchain,
+            block_id: blk1.clone()
+        });
+
+        // Session 1
+        add_random_msg_with_status(&testbench, RempMessageStatus::TonNode_RempNew, 1)?;
+        add_random_msg_with_status(&testbench, RempMessageStatus::TonNode_RempNew, 1)?;
+        add_random_msg_with_status(&testbench, accepted_shardchain, 1)?;
+        add_random_msg_with_status(&testbench, rejected, 1)?;
+
+        // Session 2
+        add_random_msg_with_status(&testbench, ignored, 2)?;
+        add_random_msg_with_status(&testbench, RempMessageStatus::TonNode_RempNew, 2)?;
+        push_random_msgs(&testbench, 3).await?;
+
+        let stats = testbench.remp_manager.get_session_stats(1)?;
+        println!("Session 1 stats: {}", stats);
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.accepted_in_shardchain, 1);
+        assert_eq!(stats.accepted_in_session, 0);
+        assert_eq!(stats.rejected_in_session, 1);
+        assert_eq!(stats.ignored, 0);
+        assert_eq!(stats.has_only_header, 0);
+        assert_eq!(stats.incorrect, 0);
+
+        let stats = testbench.remp_interface_queues.get_session_stats(2)?;
+        println!("Session 2 stats: {}", stats);
+        assert_eq!(stats.total, 5);
+        assert_eq!(stats.accepted_in_shardchain, 0);
+        assert_eq!(stats.accepted_in_session, 3);
+        assert_eq!(stats.rejected_in_session, 0);
+        assert_eq!(stats.ignored, 1);
+        assert_eq!(stats.has_only_header, 3);
+        assert_eq!(stats.incorrect, 0);
+
+        assert!(testbench.remp_manager.get_session_stats(3).is_err());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn remp_message_trace_test() -> Result<()> {
+    let runtime = RmqTestbench::create_runtime()?;
+    let runtime_handle = runtime.handle().clone();
+
+    runtime.block_on(async move {
+        let m1 = make_test_random_message_with_origin()?;
+        let m2 = make_test_random_message_with_origin()?;
+        let m3 = make_test_random_message_with_origin()?;
+
+        let mut remp_config = RempConfig::create_empty();
+        remp_config.set_traced_messages(vec!(format!("{:x}", m1.get_message_id())));
+        let mut testbench = RmqTestbench::new_with_config(&runtime_handle, 1, Duration::from_secs(10), remp_config).await?;
+        let cache = testbench.remp_manager.message_cache.clone();
+
+        // m2 is traced by request, m3 is not traced
+        assert!(testbench.remp_manager.trace_message(m2.get_message_id()));
+        assert_eq!(testbench.remp_interface_queues.trace_message(m2.get_message_id())?.len(), 0);
+        assert!(testbench.remp_manager.get_message_trace(m3.get_message_id()).is_none());
+
+        for m in [&m1, &m2, &m3] {
+            testbench.send_pending_message(m, testbench.message_queue.catchain_info.get_master_cc_seqno()).await?;
+        }
+        sleep(Duration::from_millis(10)); // To overcome SystemTime inconsistency and make tests reproducible.
+        let collated = testbench.message_queue.prepare_messages_for_collation().await?;
+        assert_eq!(collated.len(), 3);
+
+        let blk1 = BlockIdExt::with_params(testbench.params.shard.clone(), 5129, UInt256::rand(), UInt256::rand());
+        let proc = RempMasterBlockIndexingProcessor::new(blk1.clone(), blk1.clone(), cache.clone(), 1);
+        for m in [&m1, &m3] {
+            proc.process_message(m.get_message_id(), &m.message.message_uid).await;
+        }
+
+        let trace = testbench.remp_manager.get_message_trace(m1.get_message_id()).unwrap();
+        for event in trace.iter() {
+            println!("m1 trace: {:?}", event);
+        }
+        let points: Vec<MessageTracePoint> = trace.iter().map(|e| e.point).collect();
+        assert_eq!(points, vec!(
+            MessageTracePoint::Added,
+            MessageTracePoint::Queued,
+            MessageTracePoint::Collation,
+            MessageTracePoint::StatusChanged,
+            MessageTracePoint::StatusChanged,
+            MessageTracePoint::BlockIndexed
+        ));
+        assert!(trace.windows(2).all(|w| w[0].time <= w[1].time));
+        assert!(trace[5].details.contains(&blk1.to_string()));
+
+        // m2 is not found in block
+        let points: Vec<MessageTracePoint> = testbench.remp_manager.get_message_trace(m2.get_message_id())
+            .unwrap().iter().map(|e| e.point).collect();
+        assert_eq!(points.len(), 4);
+        assert_eq!(points.last(), Some(&MessageTracePoint::StatusChanged));
+        assert!(testbench.remp_manager.get_message_trace(m3.get_message_id()).is_none());
+
+        // Traces expire together with master cc session
+        testbench.advance_master_cc(2, 10.into()).await?;
+        assert_eq!(testbench.remp_manager.get_message_trace(m1.get_message_id()).unwrap().len(), 6);
+        testbench.advance_master_cc(3, 20.into()).await?;
+        assert!(testbench.remp_manager.get_message_trace(m1.get_message_id()).is_none());
+        assert!(testbench.remp_manager.get_message_trace(m2.get_message_id()).is_none());
+
+        Ok(())
+    })
+}
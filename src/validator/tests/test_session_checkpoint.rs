@@ -0,0 +1,94 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+fn hash(n: u32) -> UInt256 {
+    let mut data = [0; 32];
+    data[..4].copy_from_slice(&n.to_le_bytes());
+    UInt256::from(data)
+}
+
+#[test]
+fn test_session_checkpoint_serialize_restore() {
+    let mut checkpoint = SessionCheckpoint::new(hash(1000));
+    for round in 0..100 {
+        assert!(checkpoint.add_seen(&hash(round)));
+        if round % 2 == 0 {
+            assert!(checkpoint.add_approved(round, &hash(round), &hash(round + 500)));
+        }
+        assert!(checkpoint.set_round(round + 1));
+    }
+    assert!(!checkpoint.set_round(50));
+    assert!(!checkpoint.add_seen(&hash(99)));
+    assert!(!checkpoint.add_approved(98, &hash(98), &hash(598)));
+
+    let data = checkpoint.serialize();
+    let restored = SessionCheckpoint::deserialize(&data).unwrap();
+    assert_eq!(restored, checkpoint);
+    assert_eq!(restored.session_id(), &hash(1000));
+    assert_eq!(restored.round(), 100);
+    assert_eq!(restored.seen().count(), 100);
+    assert_eq!(restored.approved().count(), 50);
+    assert!(restored.is_approved(98, &hash(98), &hash(598)));
+    assert!(!restored.is_approved(98, &hash(98), &hash(599)));
+    assert!(!restored.is_approved(99, &hash(99), &hash(599)));
+
+    // Old entries are evicted
+    let mut checkpoint = restored;
+    for round in 100..400 {
+        checkpoint.add_seen(&hash(round));
+        checkpoint.add_approved(round, &hash(round), &hash(round + 500));
+    }
+    assert_eq!(checkpoint.seen().count(), MAX_SEEN);
+    assert_eq!(checkpoint.approved().count(), MAX_APPROVED);
+    assert!(!checkpoint.is_approved(98, &hash(98), &hash(598)));
+    assert_eq!(SessionCheckpoint::deserialize(&checkpoint.serialize()).unwrap(), checkpoint);
+
+    // Broken data is rejected
+    assert!(SessionCheckpoint::deserialize(&data[..data.len() - 1]).is_err());
+    let mut broken = data.clone();
+    broken.push(0);
+    assert!(SessionCheckpoint::deserialize(&broken).is_err());
+    let mut broken = data;
+    broken[0] = CHECKPOINT_VERSION + 1;
+    assert!(SessionCheckpoint::deserialize(&broken).is_err());
+    assert!(SessionCheckpoint::deserialize(&[]).is_err());
+}
+
+#[test]
+fn test_session_checkpoint_rate_limit() {
+    let mut checkpointer = SessionCheckpointer::new(SessionCheckpoint::new(hash(1)));
+    let mut now = Instant::now();
+
+    // Nothing to write without changes
+    assert!(checkpointer.take_due(now).is_none());
+    checkpointer.update(|checkpoint| checkpoint.set_round(1));
+    let data = checkpointer.take_due(now).unwrap();
+    assert_eq!(SessionCheckpoint::deserialize(&data).unwrap().round(), 1);
+
+    // Changes during interval are written at once after it
+    for round in 2..10 {
+        checkpointer.update(|checkpoint| checkpoint.set_round(round));
+        assert!(checkpointer.take_due(now).is_none());
+        now += CHECKPOINT_INTERVAL / 10;
+    }
+    now += CHECKPOINT_INTERVAL;
+    let data = checkpointer.take_due(now).unwrap();
+    assert_eq!(SessionCheckpoint::deserialize(&data).unwrap().round(), 9);
+    assert!(checkpointer.take_due(now + CHECKPOINT_INTERVAL).is_none());
+
+    // Update which changes nothing is not written
+    checkpointer.update(|checkpoint| checkpoint.set_round(5));
+    assert!(checkpointer.take_due(now + CHECKPOINT_INTERVAL).is_none());
+}
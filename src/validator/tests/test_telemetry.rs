@@ -164,3 +164,69 @@ time, ms (min avg max)           500 549 599
 ");
 }
 
+
+#[test]
+pub fn test_latency_histogram_percentiles() {
+    let h = LatencyHistogram::new();
+    assert_eq!(h.summary(), LatencySummary::default());
+
+    // 90 fast, 9 medium and 1 slow sample
+    for _ in 0..90 {
+        h.add(Duration::from_millis(40));
+    }
+    for _ in 0..9 {
+        h.add(Duration::from_millis(600));
+    }
+    h.add(Duration::from_millis(30000));
+    assert_eq!(
+        h.summary(),
+        LatencySummary { count: 100, p50: 50, p95: 750, p99: 750, max: 30000 }
+    );
+
+    // Percentiles don't exceed the observed maximum
+    let h = LatencyHistogram::new();
+    h.add(Duration::from_millis(120));
+    assert_eq!(
+        h.reset(),
+        LatencySummary { count: 1, p50: 120, p95: 120, p99: 120, max: 120 }
+    );
+    assert_eq!(h.summary(), LatencySummary::default());
+}
+
+#[test]
+pub fn test_collation_timing_telemetry() {
+    let master = ShardIdent::masterchain();
+    let shard = ShardIdent::with_tagged_prefix(0, 0x4000000000000000).unwrap();
+    let t = CollationTimingTelemetry::default();
+    assert_eq!(t.report(), "No one block");
+
+    for i in 1..=10 {
+        t.collation_finished(&shard, Duration::from_millis(i * 100));
+        t.validation_finished(&master, Duration::from_millis(i));
+    }
+    t.block_broadcast(&shard, Duration::from_millis(1200));
+
+    assert_eq!(
+        t.collation_summary(&shard),
+        LatencySummary { count: 10, p50: 500, p95: 1000, p99: 1000, max: 1000 }
+    );
+    assert_eq!(t.validation_summary(&shard), LatencySummary::default());
+    assert_eq!(
+        t.validation_summary(&master),
+        LatencySummary { count: 10, p50: 10, p95: 10, p99: 10, max: 10 }
+    );
+    assert_eq!(t.collation_summary(&master), LatencySummary::default());
+    assert_eq!(t.broadcast_summary(&shard).count, 1);
+
+    let summaries = t.summaries();
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].shard, master);
+    assert_eq!(summaries[1].shard, shard);
+    assert_eq!(summaries[1].broadcast, t.broadcast_summary(&shard));
+
+    let r = t.report();
+    println!("{}", r);
+    assert!(r.contains(&format!("***\n{}:\n    collation             10   500  1000  1000  1000\n", shard)));
+    // Report starts the new period
+    assert_eq!(t.collation_summary(&shard), LatencySummary::default());
+}
@@ -0,0 +1,169 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ever_block::{
+    BlockIdExt, CatchainConfig, ConfigParam36, McStateExtra, ShardStateUnsplit, SigPubKey,
+    ValidatorSet
+};
+
+fn adnl_addr(index: u8) -> UInt256 {
+    UInt256::from([index + 1; 32])
+}
+
+fn local_id(index: u8) -> Vec<Arc<KeyId>> {
+    vec![KeyId::from_data(*adnl_addr(index).as_slice())]
+}
+
+// 7 validators, first 4 of them validate masterchain as the list is not shuffled
+fn make_vset(utime_since: u32, utime_until: u32) -> Result<ValidatorSet> {
+    let mut list = Vec::new();
+    for index in 0..7 {
+        let key = SigPubKey::from_bytes(UInt256::rand().as_slice())?;
+        list.push(ValidatorDescr::with_params(key, 10 - index as u64, Some(adnl_addr(index)), None));
+    }
+    ValidatorSet::new(utime_since, utime_until, 4, list)
+}
+
+fn make_mc_state(cc_seqno: u32, next_vset: Option<ValidatorSet>) -> Result<ShardStateStuff> {
+    let cc_config = CatchainConfig {
+        isolate_mc_validators: false,
+        shuffle_mc_validators: false,
+        mc_catchain_lifetime: 100,
+        shard_catchain_lifetime: 100,
+        shard_validators_lifetime: 1000,
+        shard_validators_num: 7,
+    };
+    let mut config = ConfigParams::new();
+    config.set_config(ConfigParamEnum::ConfigParam28(cc_config))?;
+    config.set_config(ConfigParamEnum::ConfigParam34(ConfigParam34 { cur_validators: make_vset(0, 1000)? }))?;
+    if let Some(next_validators) = next_vset {
+        config.set_config(ConfigParamEnum::ConfigParam36(ConfigParam36 { next_validators }))?;
+    }
+    let mut extra = McStateExtra {
+        config,
+        ..Default::default()
+    };
+    extra.validator_info.catchain_seqno = cc_seqno;
+    let mut ss = ShardStateUnsplit::with_ident(ShardIdent::masterchain());
+    ss.write_custom(Some(&extra))?;
+    let id = BlockIdExt::with_params(ShardIdent::masterchain(), 100, UInt256::rand(), UInt256::rand());
+    ShardStateStuff::from_state(
+        id,
+        ss,
+        #[cfg(feature = "telemetry")]
+        &crate::collator_test_bundle::create_engine_telemetry(),
+        &crate::collator_test_bundle::create_engine_allocated()
+    )
+}
+
+#[test]
+fn test_validator_schedule_assignment() -> Result<()> {
+    let mc_state = make_mc_state(10, None)?;
+
+    let schedule = compute_validator_schedule(&mc_state, &local_id(0), 3, 450)?;
+    assert_eq!(schedule.mc_seq_no, 100);
+    assert_eq!(schedule.sessions.len(), 3);
+    for (i, session) in schedule.sessions.iter().enumerate() {
+        assert_eq!(session.shard, ShardIdent::masterchain().to_string());
+        assert_eq!(session.cc_seqno, 11 + i as u32);
+        assert_eq!(session.utime_since, 500 + 100 * i as u32);
+        assert_eq!(session.utime_until, session.utime_since + 100);
+        assert!(session.validator_set_known);
+        assert!(session.in_validator_set);
+        assert!(session.in_group);
+    }
+
+    // In the set but out of the masterchain group
+    let schedule = compute_validator_schedule(&mc_state, &local_id(6), 3, 450)?;
+    assert!(schedule.sessions.iter().all(|session| session.in_validator_set && !session.in_group));
+
+    // Not a validator at all
+    let schedule = compute_validator_schedule(&mc_state, &local_id(7), 3, 450)?;
+    assert!(schedule.sessions.iter().all(|session| !session.in_validator_set && !session.in_group));
+
+    // Groups match the live assignment
+    let config = mc_state.config_params()?;
+    for session in compute_validator_schedule(&mc_state, &local_id(2), 3, 450)?.sessions {
+        let group = compute_validator_set_by_config(config, &ShardIdent::masterchain(), session.cc_seqno)?;
+        assert_eq!(session.in_group, contains_local(&group, &local_id(2)));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_validator_schedule_next_set() -> Result<()> {
+    // Sessions after the current set expires are unknown until the next set is elected
+    let mc_state = make_mc_state(10, None)?;
+    let schedule = compute_validator_schedule(&mc_state, &local_id(0), 4, 750)?;
+    let known = schedule.sessions.iter().map(|session| session.validator_set_known).collect::<Vec<_>>();
+    assert_eq!(known, [true, true, false, false]);
+    assert!(!schedule.sessions[2].in_validator_set);
+
+    // Validator 0 is not elected in the next set
+    let mut next = make_vset(1000, 2000)?.list().to_vec();
+    next.remove(0);
+    let mc_state = make_mc_state(10, Some(ValidatorSet::new(1000, 2000, 4, next)?))?;
+    let schedule = compute_validator_schedule(&mc_state, &local_id(0), 4, 750)?;
+    assert!(schedule.sessions.iter().all(|session| session.validator_set_known));
+    let in_set = schedule.sessions.iter().map(|session| session.in_validator_set).collect::<Vec<_>>();
+    assert_eq!(in_set, [true, true, false, false]);
+    Ok(())
+}
+
+#[test]
+fn test_validator_sessions_history() {
+    let history = ValidatorSessionsHistory::new(2);
+    let first = Arc::new(SessionStats::new(ShardIdent::masterchain(), 1, UInt256::from([1; 32])));
+    history.add(first.clone(), 100);
+    // Restart of the same session is not duplicated
+    history.add(first.clone(), 200);
+    first.block_committed(true, true);
+    first.block_committed(true, false);
+    first.block_committed(false, false);
+    first.round_skipped();
+    first.finish(300);
+
+    let sessions = history.sessions();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].cc_seqno, 1);
+    assert_eq!(sessions[0].started_at, 100);
+    assert_eq!(sessions[0].finished_at, Some(300));
+    assert_eq!(sessions[0].blocks_committed, 3);
+    assert_eq!(sessions[0].blocks_signed, 2);
+    assert_eq!(sessions[0].blocks_collated, 1);
+    assert_eq!(sessions[0].rounds_missed, 2);
+
+    // The oldest session is evicted
+    for cc_seqno in 2..=3 {
+        let session = SessionStats::new(ShardIdent::masterchain(), cc_seqno, UInt256::from([cc_seqno as u8; 32]));
+        history.add(Arc::new(session), 300 + cc_seqno);
+    }
+    let sessions = history.sessions();
+    assert_eq!(sessions.iter().map(|session| session.cc_seqno).collect::<Vec<_>>(), [2, 3]);
+    assert_eq!(sessions[0].finished_at, None);
+}
+
+#[test]
+fn test_validator_sessions_history_key_in_use() {
+    let history = ValidatorSessionsHistory::new(4);
+    let session = Arc::new(
+        SessionStats::new(ShardIdent::masterchain(), 1, UInt256::from([1; 32])).with_validator_key([7; 32])
+    );
+    history.add(session.clone(), 100);
+    history.add(Arc::new(SessionStats::new(ShardIdent::masterchain(), 2, UInt256::from([2; 32]))), 100);
+    assert!(history.key_in_use(&[7; 32]));
+    assert!(!history.key_in_use(&[8; 32]));
+    session.finish(200);
+    assert!(!history.key_in_use(&[7; 32]));
+}
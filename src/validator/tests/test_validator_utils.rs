@@ -217,3 +217,61 @@ fn test_create_new_proof_with_workchains() {
     let _proof = create_new_proof_link(&block_stuff).unwrap();
 }
 
+
+// Signatures of given count of validators for each message, the bad ones are made for other data
+fn signature_batch(messages: &[Vec<u8>], validators: usize, bad: &[(usize, usize)]) -> SignatureBatch {
+    let keys = (0..validators).map(|_| Ed25519KeyOption::generate().unwrap()).collect::<Vec<_>>();
+    let mut batch = SignatureBatch::new();
+    for (i, message) in messages.iter().enumerate() {
+        batch.set_owner(format!("proof {}", i));
+        let message = Arc::new(message.clone());
+        for (j, key) in keys.iter().enumerate() {
+            let data = if bad.contains(&(i, j)) { b"other data".to_vec() } else { message.to_vec() };
+            let signature = CryptoSignature::from_bytes(&key.sign(&data).unwrap()).unwrap();
+            let public_key = SigPubKey::from_bytes(key.pub_key().unwrap()).unwrap();
+            batch.add(&public_key, &message, signature, key.id().clone());
+        }
+    }
+    batch
+}
+
+#[test]
+fn test_signature_batch_isolates_bad_signature() {
+    let messages = (0..3u8).map(|i| vec![i; 64]).collect::<Vec<_>>();
+    let batch = signature_batch(&messages, 20, &[]);
+    assert_eq!(batch.len(), 60);
+    assert!(batch.verify_batch());
+    batch.verify().unwrap();
+
+    // The only bad signature fails the batch and is found by individual checks
+    let batch = signature_batch(&messages, 20, &[(1, 7)]);
+    assert!(!batch.verify_batch());
+    let err = batch.verify().unwrap_err().to_string();
+    let bad_signer = &batch.items[20 + 7].signer;
+    assert_eq!(err, format!("proof 1: bad signature from validator with pub_key {}", bad_signer));
+
+    // Batches of several proofs are merged
+    let mut merged = signature_batch(&messages[..1], 5, &[]);
+    merged.append(signature_batch(&messages[1..], 5, &[(0, 0)]));
+    assert_eq!(merged.len(), 15);
+    assert!(merged.verify().unwrap_err().to_string().starts_with("proof 0: bad signature"));
+    assert!(SignatureBatch::new().verify().is_ok());
+}
+
+#[test]
+fn test_signature_batch_benchmark() {
+    // A package of masterchain proofs signed by a large validator set
+    let messages = (0..10u8).map(|i| vec![i; 64]).collect::<Vec<_>>();
+    let batch = signature_batch(&messages, 300, &[]);
+
+    let now = std::time::Instant::now();
+    batch.verify_each().unwrap();
+    let each = now.elapsed();
+    let now = std::time::Instant::now();
+    assert!(batch.verify_batch());
+    let batched = now.elapsed();
+    println!(
+        "{} signatures: individually {}ms, batch {}ms",
+        batch.len(), each.as_millis(), batched.as_millis()
+    );
+}
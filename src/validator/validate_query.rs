@@ -4521,6 +4521,9 @@ impl ValidateQuery {
             base.transactions_executed.load(Ordering::Relaxed),
             gas_used as u32
         );
+        #[cfg(not(test))]
+        #[cfg(feature = "telemetry")]
+        self.engine.collation_timing_telemetry().validation_finished(&self.shard, now.elapsed());
 
         Ok(())
     }
@@ -13,6 +13,8 @@
 
 use std::{cmp::max, sync::{*, atomic::{Ordering, AtomicU64}}, time::*};
 use std::ops::RangeInclusive;
+#[cfg(feature = "telemetry")]
+use std::collections::HashMap;
 use crossbeam_channel::Receiver;
 
 use catchain::utils::get_hash;
@@ -39,14 +41,16 @@ use validator_session_listener::{
 use super::*;
 use super::fabric::*;
 use crate::{
-    engine_traits::EngineOperations,
+    engine_traits::{block_access, EngineOperations},
     validator::{
         catchain_overlay::CatchainOverlayManagerImpl,
         mutex_wrapper::MutexWrapper,
         reliable_message_queue::RmqQueueManager,
         remp_manager::RempManager,
         remp_block_parser::check_history_up_to_cc,
+        session_checkpoint::{SessionCheckpoint, SessionCheckpointer},
         sessions_computing::GeneralSessionInfo,
+        validator_schedule::SessionStats,
         validator_utils::{
             validatordescr_to_session_node,
             validator_query_candidate_to_validator_block_candidate, ValidatorListHash,
@@ -116,6 +120,13 @@ pub struct ValidatorGroupImpl {
 
     next_block_seqno: Option<u32>,
     next_block_descr: Arc<String>,
+
+    // Collation start of own candidates by root hash, until the block is committed
+    #[cfg(feature = "telemetry")]
+    collation_starts: HashMap<UInt256, Instant>,
+
+    // Present if session checkpoints are enabled, restored before session start
+    checkpointer: Option<SessionCheckpointer>,
 }
 
 impl Drop for ValidatorGroupImpl {
@@ -298,6 +309,11 @@ impl ValidatorGroupImpl {
 
             next_block_seqno: None,
             next_block_descr,
+
+            #[cfg(feature = "telemetry")]
+            collation_starts: HashMap::new(),
+
+            checkpointer: None,
         }
     }
 
@@ -332,6 +348,19 @@ impl ValidatorGroupImpl {
         self.last_known_round = max(self.last_known_round, round);
         return (self.last_known_round, self.prev_block_ids.clone(), self.min_masterchain_block_id.clone(), self.min_ts)
     }
+
+    pub fn update_checkpoint(&mut self, update: impl FnOnce(&mut SessionCheckpoint) -> bool) {
+        if let Some(checkpointer) = self.checkpointer.as_mut() {
+            checkpointer.update(update)
+        }
+    }
+
+    pub fn is_approved_before_restart(&self, round: u32, root_hash: &UInt256, file_hash: &UInt256) -> bool {
+        match &self.checkpointer {
+            Some(checkpointer) => checkpointer.checkpoint().is_approved(round, root_hash, file_hash),
+            None => false
+        }
+    }
 }
 
 pub struct ValidatorGroup {
@@ -348,6 +377,7 @@ pub struct ValidatorGroup {
     validator_set: ValidatorSet,
     #[allow(dead_code)]
     allow_unsafe_self_blocks_resync: bool,
+    session_checkpoints: bool,
 
     group_impl: Arc<MutexWrapper<ValidatorGroupImpl>>,
     callback: Arc<dyn SessionListener + Send + Sync>,
@@ -358,6 +388,7 @@ pub struct ValidatorGroup {
     verification_manager: Option<VerificationManagerPtr>,
     last_validation_time: AtomicU64,
     last_collation_time: AtomicU64,
+    session_stats: Arc<SessionStats>,
 }
 
 impl ValidatorGroup {
@@ -371,6 +402,7 @@ impl ValidatorGroup {
         remp_manager: Option<Arc<RempManager>>,
         engine: Arc<dyn EngineOperations>,
         allow_unsafe_self_blocks_resync: bool,
+        session_checkpoints: bool,
         #[cfg(feature = "slashing")]
         slashing_manager: SlashingManagerPtr,
         verification_manager: Option<VerificationManagerPtr>,
@@ -381,6 +413,11 @@ impl ValidatorGroup {
         );
         let id = format!("Val. group {} {:x}", general_session_info.shard, session_id);
         let (listener, receiver) = ValidatorSessionListener::create();
+        let session_stats = Arc::new(SessionStats::new(
+            general_session_info.shard.clone(),
+            general_session_info.catchain_seqno,
+            session_id.clone()
+        ).with_validator_key(*local_key.id().data()));
 
         log::trace!(target: "validator", "Creating validator group: {}", id);
         ValidatorGroup {
@@ -392,6 +429,7 @@ impl ValidatorGroup {
             config,
             engine,
             allow_unsafe_self_blocks_resync,
+            session_checkpoints,
             remp_manager,
             group_impl: Arc::new(MutexWrapper::new(group_impl, id)),
             callback: Arc::new(listener),
@@ -400,7 +438,8 @@ impl ValidatorGroup {
             slashing_manager,
             verification_manager,
             last_validation_time: AtomicU64::new(0),
-            last_collation_time: AtomicU64::new(0)
+            last_collation_time: AtomicU64::new(0),
+            session_stats,
         }
     }
 
@@ -448,11 +487,15 @@ impl ValidatorGroup {
                 tokio::time::sleep_until(start_at).await;
             }
 
+            if self.session_checkpoints {
+                self.restore_checkpoint().await;
+            }
+
             let callback = self.make_validator_session_callback();
             self.group_impl.execute_sync(|group_impl|
             {
                 if group_impl.status <= ValidatorGroupStatus::Active {
-                    if let Err(e) = group_impl.start(
+                    match group_impl.start(
                         callback,
                         prev,
                         min_masterchain_block_id,
@@ -463,9 +506,11 @@ impl ValidatorGroup {
                         &master_cc_range_cloned,
                         start_remp_session,
                         rt
-                    )
-                    {
-                        log::error!(target: "validator", "Cannot start group: {}", e);
+                    ) {
+                        Ok(()) => if let Some(history) = self.engine.validator_sessions_history() {
+                            history.add(self.session_stats.clone(), self.engine.now());
+                        },
+                        Err(e) => log::error!(target: "validator", "Cannot start group: {}", e)
                     }
                 }
                 else {
@@ -508,6 +553,7 @@ impl ValidatorGroup {
 
     pub async fn stop(self: Arc<ValidatorGroup>, rt: tokio::runtime::Handle, new_master_cc_range: Option<RangeInclusive<u32>>) -> Result<()> {
         self.set_status(ValidatorGroupStatus::Stopping).await?;
+        self.session_stats.finish(self.engine.now());
         log::debug!(target: "validator", "Stopping group: {}", self.info().await);
         let group_impl = self.group_impl.clone();
         let self_clone = self.clone();
@@ -541,6 +587,57 @@ impl ValidatorGroup {
         Ok(())
     }
 
+    async fn restore_checkpoint(&self) {
+        let checkpoint = match self.engine.load_session_checkpoint(&self.session_id) {
+            Ok(Some(data)) => match SessionCheckpoint::deserialize(&data) {
+                Ok(checkpoint) if checkpoint.session_id() == &self.session_id => Some(checkpoint),
+                Ok(checkpoint) => {
+                    log::warn!(
+                        target: "validator", "Checkpoint of session {:x} is found for session {:x}", 
+                        checkpoint.session_id(), self.session_id
+                    );
+                    None
+                }
+                Err(e) => {
+                    log::warn!(target: "validator", "Cannot restore checkpoint of session {:x}: {}", self.session_id, e);
+                    None
+                }
+            }
+            Ok(None) => None,
+            Err(e) => {
+                log::warn!(target: "validator", "Cannot load checkpoint of session {:x}: {}", self.session_id, e);
+                None
+            }
+        };
+        let checkpoint = match checkpoint {
+            Some(checkpoint) => {
+                log::info!(
+                    target: "validator", 
+                    "Session {:x} is restored from checkpoint: round {}, {} approved, {} seen candidates",
+                    self.session_id, checkpoint.round(), checkpoint.approved().count(), 
+                    checkpoint.seen().count()
+                );
+                checkpoint
+            }
+            None => SessionCheckpoint::new(self.session_id.clone())
+        };
+        self.group_impl.execute_sync(|group_impl| {
+            group_impl.checkpointer = Some(SessionCheckpointer::new(checkpoint))
+        }).await;
+    }
+
+    // Writes checkpoint if it is due, so the session DB is not written on each event
+    async fn save_checkpoint(&self) {
+        let data = self.group_impl.execute_sync(|group_impl| {
+            group_impl.checkpointer.as_mut().and_then(|checkpointer| checkpointer.take_due(Instant::now()))
+        }).await;
+        if let Some(data) = data {
+            if let Err(e) = self.engine.save_session_checkpoint(&self.session_id, data) {
+                log::warn!(target: "validator", "Cannot save checkpoint of session {:x}: {}", self.session_id, e);
+            }
+        }
+    }
+
     async fn save_block_candidate(&self, vb_candidate: ValidatorBlockCandidate) -> Result<()> {
         self.engine.save_block_candidate(&self.session_id, vb_candidate)
     }
@@ -628,7 +725,7 @@ impl ValidatorGroup {
             }
             Some(mc_range) => {
                 if let Some(unknown_block) = check_history_up_to_cc(
-                    self.engine.clone(), remp.message_cache.clone(), mc_blocks, *mc_range.start()
+                    block_access(self.engine.clone()), remp.message_cache.clone(), mc_blocks, *mc_range.start()
                 ).await? {
                     log::warn!(target: "validator", "Shard {} history from {:?} up to master cc {} is not fully known: block {} is not processed",
                         self.info().await, mc_blocks, *mc_range.start(), unknown_block
@@ -672,6 +769,8 @@ impl ValidatorGroup {
             Ok(true) => self.get_remp_queue_collator_interface().await
         };
 
+        #[cfg(feature = "telemetry")]
+        let collation_started = Instant::now();
         let result = match mm_block_id {
             Some(mc) => {
                 match run_collate_query (
@@ -691,6 +790,14 @@ impl ValidatorGroup {
             None => Err(error!("Min masterchain block id missing")),
         };
 
+        #[cfg(feature = "telemetry")]
+        if let Ok(candidate) = &result {
+            let root_hash = candidate.id.root_hash.clone();
+            self.group_impl.execute_sync(|group_impl|
+                group_impl.collation_starts.insert(root_hash, collation_started)
+            ).await;
+        }
+
         let candidate = match self.verification_manager.clone() {
             Some(_) => match &result {
                 Ok(candidate) => Some(candidate.clone()),
@@ -836,7 +943,10 @@ impl ValidatorGroup {
 
         let result = {
             let (lk_round, prev_block_ids, mm_block_id, min_ts) =
-                self.group_impl.execute_sync(|group_impl| group_impl.update_round(round)).await;
+                self.group_impl.execute_sync(|group_impl| {
+                    group_impl.update_checkpoint(|checkpoint| checkpoint.add_seen(&candidate.block_id.root_hash));
+                    group_impl.update_round(round)
+                }).await;
 
             if round < lk_round {
                 log::error!(target: "validator", "({}): round {} < self.last_known_round {}", next_block_descr, round, lk_round);
@@ -864,7 +974,21 @@ impl ValidatorGroup {
             };
             candidate.block_id = next_block_id;
 
+            let approved_before_restart = self.group_impl.execute_sync(|group_impl| 
+                group_impl.is_approved_before_restart(
+                    round, &candidate.block_id.root_hash, &candidate.block_id.file_hash
+                )
+            ).await;
+
             match mm_block_id {
+                Some(_) if approved_before_restart => {
+                    log::info!(
+                        target: "validator", 
+                        "({}): candidate {} was approved before restart, validation is skipped",
+                        next_block_descr, candidate_id
+                    );
+                    Ok(SystemTime::now())
+                }
                 Some(mc) => {
                     run_validate_query(
                         self.shard().clone(),
@@ -882,6 +1006,16 @@ impl ValidatorGroup {
             }
         };
 
+        if result.is_ok() {
+            let (root_hash, file_hash) = (&candidate.block_id.root_hash, &candidate.block_id.file_hash);
+            self.group_impl.execute_sync(|group_impl| 
+                group_impl.update_checkpoint(
+                    |checkpoint| checkpoint.add_approved(round, root_hash, file_hash)
+                )
+            ).await;
+        }
+        self.save_checkpoint().await;
+
         let result_message = match &result {
             Ok(x) => {
                 let vb_candidate = validator_query_candidate_to_validator_block_candidate(
@@ -933,6 +1067,8 @@ impl ValidatorGroup {
 
         let data_vec = data.data().to_vec();
         let we_generated = source.id() == self.local_key.id();
+        let we_signed = sig_set.iter().any(|(id, _)| id == self.local_key.id());
+        self.session_stats.block_committed(we_signed, we_generated);
 
         log::info!(target: "validator", 
             "({}): ValidatorGroup::on_block_committed: source {}, data size = {}, {}" ,
@@ -940,11 +1076,14 @@ impl ValidatorGroup {
             source.id(), data_vec.len(), self.info_round(round).await
         );
 
+        #[cfg(feature = "telemetry")]
+        let root_hash_to_commit = root_hash.clone();
         let (next_block_id, prev_block_ids) = match
             self.group_impl.execute_sync(|group_impl| {
                 if round >= group_impl.last_known_round {
                     group_impl.last_known_round = round + 1;
                 };
+                group_impl.update_checkpoint(|checkpoint| checkpoint.set_round(round + 1));
 
                 match group_impl.create_next_block_id(root_hash, file_hash, self.shard().clone()) {
                     Ok(x) => Ok((x, group_impl.prev_block_ids.clone())),
@@ -977,6 +1116,19 @@ impl ValidatorGroup {
             self.engine.clone(),
         ).await;
 
+        #[cfg(feature = "telemetry")]
+        {
+            let collation_started = self.group_impl.execute_sync(|group_impl| {
+                let started = group_impl.collation_starts.remove(&root_hash_to_commit);
+                // Candidates of previous rounds will not be committed anymore
+                group_impl.collation_starts.clear();
+                started
+            }).await;
+            if let (Ok(()), Some(started), true) = (&result, collation_started, we_generated) {
+                self.engine.collation_timing_telemetry().block_broadcast(self.shard(), started.elapsed());
+            }
+        }
+
         if let Ok(()) = result {
             if let Some(rmq) = self.get_reliable_message_queue().await {
                 log::trace!(target: "remp", "Processing committed shardblock {}", next_block_id);
@@ -1002,6 +1154,7 @@ impl ValidatorGroup {
             
             (full_result, prevs_to_string(&group_impl.prev_block_ids))
         }).await;
+        self.save_checkpoint().await;
 
         match full_result {
             Ok(()) => log::info!(
@@ -1031,12 +1184,15 @@ impl ValidatorGroup {
             self.get_next_block_descr().await,
             self.info_round(round).await
         );
+        self.session_stats.round_skipped();
 
-        self.group_impl.execute_sync(|group_impl|
+        self.group_impl.execute_sync(|group_impl| {
             if round > group_impl.last_known_round {
                 group_impl.last_known_round = round + 1;
             }
-        ).await;
+            group_impl.update_checkpoint(|checkpoint| checkpoint.set_round(round + 1));
+        }).await;
+        self.save_checkpoint().await;
     }
 
     pub async fn on_get_approved_candidate(
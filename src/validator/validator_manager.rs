@@ -14,7 +14,7 @@
 use crate::{
     config::{RempConfig, ValidatorManagerConfig},
     engine::Engine,
-    engine_traits::EngineOperations,
+    engine_traits::{block_access, remp_support, EngineOperations},
     shard_state::ShardStateStuff,
     validator::{
         remp_block_parser::{RempMasterblockObserver, find_previous_sessions},
@@ -221,7 +221,7 @@ pub enum ValidationStatus {
 }
 
 impl ValidationStatus {
-    fn allows_validate(&self) -> bool {
+    pub fn allows_validate(&self) -> bool {
         match self {
             Self::Disabled | Self::Waiting => false,
             Self::Countdown | Self::Active => true
@@ -354,7 +354,9 @@ impl ValidatorManagerImpl {
         remp_config: RempConfig,
     ) -> (Self, Option<Arc<RempInterfaceQueues>>) {
         let (remp_manager, remp_interface_queues) = if remp_config.is_service_enabled() {
-            let (m, i) = RempManager::create_with_options(engine.clone(), remp_config.clone(), Arc::new(rt.clone()));
+            let (m, i) = RempManager::create_with_options(
+                remp_support(engine.clone()), remp_config.clone(), Arc::new(rt.clone())
+            );
             (Some(Arc::new(m)), Some(Arc::new(i)))
         } else {
             (None, None)
@@ -563,12 +565,25 @@ impl ValidatorManagerImpl {
         remp.catchain_store.clone().gc_catchain_sessions(self.rt.clone(), active_remp_sessions).await;
     }
 
+    // Checkpoints are dropped even if they are disabled now, to clean up after previous runs
+    fn garbage_collect_session_checkpoints(&mut self) -> Result<()> {
+        let active_sessions = self.validator_sessions.keys().cloned().collect();
+        let dropped = self.engine.drop_stale_session_checkpoints(&active_sessions)?;
+        if dropped > 0 {
+            log::info!(target: "validator_manager", "Dropped {} stale session checkpoints", dropped);
+        }
+        Ok(())
+    }
+
     async fn garbage_collect(&mut self) {
         if let Err(e) = self.garbage_collect_lists().await {
             log::error!(target: "validator_manager", "Error while garbage collecting validator lists: `{}`", e);
         }
         self.garbage_collect_message_cache().await;
         self.garbage_collect_remp_sessions().await;
+        if let Err(e) = self.garbage_collect_session_checkpoints() {
+            log::error!(target: "validator_manager", "Error while garbage collecting session checkpoints: `{}`", e);
+        }
     }
 
     async fn stop_and_remove_sessions(&mut self, sessions_to_remove: &HashSet<UInt256>, new_master_cc_range: Option<RangeInclusive<u32>>) {
@@ -729,7 +744,7 @@ impl ValidatorManagerImpl {
 
         // Find previous sessions according to stored info
         let prev_sessions_from_cache = match find_previous_sessions(
-            self.engine.clone(), &self.session_info_cache,
+            block_access(self.engine.clone()), &self.session_info_cache,
             prev_blocks, next_session_info.catchain_seqno, &next_session_info.shard
         ).await {
             Err(e) => {
@@ -772,6 +787,7 @@ impl ValidatorManagerImpl {
                     if self.engine.check_sync().await?
                         && later_than_hardfork
                         && good_history
+                        && self.config_check_passed()
                     {
                         self.block_observer = self.initialize_replay_protection_history(last_masterchain_block).await?;
                         if last_masterchain_block.seq_no == 0 && self.config.no_countdown_for_zerostate {
@@ -802,6 +818,25 @@ impl ValidatorManagerImpl {
         Ok(())
     }
 
+    // In strict mode validation is not started under the config which fails the check,
+    // the node keeps following the chain
+    fn config_check_passed(&self) -> bool {
+        if !self.config.strict_config_check {
+            return true
+        }
+        match self.engine.last_config_check() {
+            Some(check) if !check.is_ok() => {
+                log::warn!(
+                    target: "validator_manager",
+                    "Validation is not started: config of key block {} fails {} check rules",
+                    check.block_id, check.violations.len()
+                );
+                false
+            }
+            _ => true
+        }
+    }
+
     async fn disable_validation(&mut self) -> Result<()> {
         self.engine.set_validation_status(ValidationStatus::Disabled);
 
@@ -980,6 +1015,7 @@ impl ValidatorManagerImpl {
                 let remp_manager = self.remp_manager.clone();
                 let verification_manager = self.verification_manager.lock().clone();
                 let allow_unsafe_self_blocks_resync = self.config.unsafe_resync_catchains.contains(&cc_seqno);
+                let session_checkpoints = self.config.session_checkpoints;
                 let session = self.validator_sessions.entry(session_id.clone()).or_insert_with(||
                     Arc::new(ValidatorGroup::new(
                         general_session_info.clone(),
@@ -991,6 +1027,7 @@ impl ValidatorManagerImpl {
                         remp_manager,
                         engine,
                         allow_unsafe_self_blocks_resync,
+                        session_checkpoints,
                         #[cfg(feature = "slashing")]
                         slashing_manager,
                         verification_manager,
@@ -1080,6 +1117,9 @@ impl ValidatorManagerImpl {
             if master_cc_seqno > 0 && self.engine.validation_status().allows_validate() {
                 let rp_guarantee = remp.calc_rp_guarantee(&catchain_config);
                 master_cc_range = remp.advance_master_cc(master_cc_seqno, rp_guarantee)?;
+                if let Err(e) = remp.restore_persisted_messages(&master_cc_range) {
+                    log::error!(target: "remp", "Cannot restore persisted REMP messages: {}", e);
+                }
             }
         }
 
@@ -1316,6 +1356,7 @@ impl ValidatorManagerImpl {
                         self.remp_manager.clone(),
                         self.engine.clone(),
                         self.config.unsafe_resync_catchains.contains(next_cc_seqno),
+                        self.config.session_checkpoints,
                         #[cfg(feature = "slashing")]
                         self.slashing_manager.clone(),
                         verification_manager
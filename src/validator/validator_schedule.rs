@@ -0,0 +1,250 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    shard_state::ShardStateStuff,
+    validator::validator_utils::{compute_validator_set_by_config, validatordescr_to_catchain_node}
+};
+use std::{collections::VecDeque, sync::{Arc, atomic::{AtomicU32, Ordering}}};
+use ever_block::{
+    ConfigParam34, ConfigParamEnum, ConfigParams, KeyId, Result, ShardDescr, ShardIdent, UInt256,
+    ValidatorDescr
+};
+
+#[cfg(test)]
+#[path = "tests/test_validator_schedule.rs"]
+mod tests;
+
+// Count of the latest participated sessions kept in history
+pub const SESSIONS_HISTORY_LEN: usize = 32;
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ScheduledSession {
+    pub shard: String,
+    pub cc_seqno: u32,
+    // Approximate window: sessions are rotated on boundaries of catchain lifetime
+    pub utime_since: u32,
+    pub utime_until: u32,
+    // False if validator set of the window is not elected yet
+    pub validator_set_known: bool,
+    pub in_validator_set: bool,
+    pub in_group: bool,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ValidatorSchedule {
+    pub mc_seq_no: u32,
+    pub sessions: Vec<ScheduledSession>,
+}
+
+fn contains_local(validators: &[ValidatorDescr], local_adnl_ids: &[Arc<KeyId>]) -> bool {
+    validators.iter().any(|val| local_adnl_ids.contains(&validatordescr_to_catchain_node(val).adnl_id))
+}
+
+// Computes next `lookahead_cc` sessions of every shard of the masterchain state,
+// groups are assigned the same way as for live sessions
+pub fn compute_validator_schedule(
+    mc_state: &ShardStateStuff,
+    local_adnl_ids: &[Arc<KeyId>],
+    lookahead_cc: u32,
+    now: u32
+) -> Result<ValidatorSchedule> {
+    let config = mc_state.config_params()?;
+    let cc_config = config.catchain_config()?;
+    let cur_until = config.validator_set()?.utime_until();
+    let next_config = match config.next_validator_set()? {
+        next if next.total() > 0 => {
+            let mut next_config = config.clone();
+            next_config.set_config(ConfigParamEnum::ConfigParam34(ConfigParam34 { cur_validators: next }))?;
+            Some(next_config)
+        }
+        _ => None
+    };
+
+    let shard_hashes = mc_state.shards()?;
+    let mut shards = vec![(ShardIdent::masterchain(), mc_state.shard_state_extra()?.validator_info.catchain_seqno)];
+    shard_hashes.iterate_shards(|shard: ShardIdent, _descr: ShardDescr| {
+        let cc_seqno = shard_hashes.calc_shard_cc_seqno(&shard)?;
+        shards.push((shard, cc_seqno));
+        Ok(true)
+    })?;
+
+    let mut sessions = Vec::new();
+    for (shard, cc_seqno) in shards {
+        let lifetime = if shard.is_masterchain() {
+            cc_config.mc_catchain_lifetime
+        } else {
+            cc_config.shard_catchain_lifetime
+        }.max(1);
+        let current_since = now - now % lifetime;
+        for ahead in 1..=lookahead_cc {
+            let utime_since = current_since.saturating_add(ahead.saturating_mul(lifetime));
+            let session_config: Option<&ConfigParams> = if utime_since < cur_until {
+                Some(config)
+            } else {
+                next_config.as_ref()
+            };
+            let mut session = ScheduledSession {
+                shard: shard.to_string(),
+                cc_seqno: cc_seqno + ahead,
+                utime_since,
+                utime_until: utime_since.saturating_add(lifetime),
+                validator_set_known: session_config.is_some(),
+                in_validator_set: false,
+                in_group: false,
+            };
+            if let Some(session_config) = session_config {
+                session.in_validator_set = contains_local(session_config.validator_set()?.list(), local_adnl_ids);
+                if session.in_validator_set {
+                    match compute_validator_set_by_config(session_config, &shard, session.cc_seqno) {
+                        Ok(group) => session.in_group = contains_local(&group, local_adnl_ids),
+                        Err(e) => log::warn!(
+                            target: "validator", "Can't compute group of {} session {}: {}", shard, session.cc_seqno, e
+                        )
+                    }
+                }
+            }
+            sessions.push(session);
+        }
+    }
+    Ok(ValidatorSchedule { mc_seq_no: mc_state.seq_no(), sessions })
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ParticipatedSession {
+    pub shard: String,
+    pub cc_seqno: u32,
+    pub session_id: String,
+    pub started_at: u32,
+    pub finished_at: Option<u32>,
+    pub blocks_committed: u32,
+    pub blocks_signed: u32,
+    pub blocks_collated: u32,
+    // Skipped rounds and rounds committed without our signature
+    pub rounds_missed: u32,
+}
+
+// Counters of a session, updated by its validator group
+pub struct SessionStats {
+    shard: ShardIdent,
+    cc_seqno: u32,
+    session_id: UInt256,
+    // Hash of the local key the session signs with
+    validator_key_id: Option<[u8; 32]>,
+    started_at: AtomicU32,
+    finished_at: AtomicU32,
+    blocks_committed: AtomicU32,
+    blocks_signed: AtomicU32,
+    blocks_collated: AtomicU32,
+    rounds_missed: AtomicU32,
+}
+
+impl SessionStats {
+
+    pub fn new(shard: ShardIdent, cc_seqno: u32, session_id: UInt256) -> Self {
+        Self {
+            shard,
+            cc_seqno,
+            session_id,
+            validator_key_id: None,
+            started_at: AtomicU32::new(0),
+            finished_at: AtomicU32::new(0),
+            blocks_committed: AtomicU32::new(0),
+            blocks_signed: AtomicU32::new(0),
+            blocks_collated: AtomicU32::new(0),
+            rounds_missed: AtomicU32::new(0),
+        }
+    }
+
+    pub fn with_validator_key(mut self, key_id: [u8; 32]) -> Self {
+        self.validator_key_id = Some(key_id);
+        self
+    }
+
+    pub fn block_committed(&self, signed: bool, collated: bool) {
+        self.blocks_committed.fetch_add(1, Ordering::Relaxed);
+        if signed {
+            self.blocks_signed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.rounds_missed.fetch_add(1, Ordering::Relaxed);
+        }
+        if collated {
+            self.blocks_collated.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn round_skipped(&self) {
+        self.rounds_missed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self, now: u32) {
+        self.finished_at.store(now, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> ParticipatedSession {
+        let finished_at = self.finished_at.load(Ordering::Relaxed);
+        ParticipatedSession {
+            shard: self.shard.to_string(),
+            cc_seqno: self.cc_seqno,
+            session_id: format!("{:x}", self.session_id),
+            started_at: self.started_at.load(Ordering::Relaxed),
+            finished_at: (finished_at != 0).then_some(finished_at),
+            blocks_committed: self.blocks_committed.load(Ordering::Relaxed),
+            blocks_signed: self.blocks_signed.load(Ordering::Relaxed),
+            blocks_collated: self.blocks_collated.load(Ordering::Relaxed),
+            rounds_missed: self.rounds_missed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Ring buffer of the latest sessions the node has participated in
+pub struct ValidatorSessionsHistory {
+    capacity: usize,
+    sessions: parking_lot::Mutex<VecDeque<Arc<SessionStats>>>,
+}
+
+impl ValidatorSessionsHistory {
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            sessions: parking_lot::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Adds started session, a restarted session is kept once with its first start time
+    pub fn add(&self, stats: Arc<SessionStats>, now: u32) {
+        let _ = stats.started_at.compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+        let mut sessions = self.sessions.lock();
+        if sessions.iter().any(|session| session.session_id == stats.session_id) {
+            return
+        }
+        sessions.push_back(stats);
+        while sessions.len() > self.capacity {
+            sessions.pop_front();
+        }
+    }
+
+    // True if a session signing with the key is not finished
+    pub fn key_in_use(&self, key_id: &[u8; 32]) -> bool {
+        self.sessions.lock().iter().any(|session| {
+            session.validator_key_id.as_ref() == Some(key_id) &&
+                session.finished_at.load(Ordering::Relaxed) == 0
+        })
+    }
+
+    // Oldest session first
+    pub fn sessions(&self) -> Vec<ParticipatedSession> {
+        self.sessions.lock().iter().map(|session| session.snapshot()).collect()
+    }
+}
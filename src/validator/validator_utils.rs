@@ -12,7 +12,7 @@
 */
 
 use crate::{
-    block::BlockIdExtExtention, engine_traits::EngineOperations, 
+    block::BlockIdExtExtention, engine_traits::{EngineOperations, StateAccess}, 
     shard_state::ShardStateStuff
 };
 
@@ -25,7 +25,10 @@ use ever_block::{
     WorkchainDescr
 };
 use ever_block::CatchainConfig;
-use std::{collections::HashMap, fmt::Debug, hash::Hash, sync::Arc};
+use std::{
+    collections::HashMap, fmt::Debug, hash::Hash,
+    sync::{Arc, atomic::{AtomicBool, Ordering}}
+};
 use ton_api::ton::engine::validator::validator::groupmember::GroupMember;
 use validator_session::SessionNode;
 
@@ -80,21 +83,135 @@ pub fn pairvec_val_to_sigs(
     return Ok(BlockSignatures::with_params(vset_info, pure_sigs));
 }
 
+// Signatures are verified by batches unless conservative check is configured
+static BATCH_SIGNATURE_CHECK: AtomicBool = AtomicBool::new(true);
+
+pub fn set_batch_signature_check(enabled: bool) {
+    BATCH_SIGNATURE_CHECK.store(enabled, Ordering::Relaxed);
+}
+
+pub fn batch_signature_check() -> bool {
+    BATCH_SIGNATURE_CHECK.load(Ordering::Relaxed)
+}
+
+struct SignatureItem {
+    public_key: SigPubKey,
+    signature: CryptoSignature,
+    message: Arc<Vec<u8>>,
+    signer: Arc<KeyId>,
+    owner: Option<Arc<String>>,
+}
+
+// Signatures collected to be verified at once, possibly from several proofs
+#[derive(Default)]
+pub struct SignatureBatch {
+    items: Vec<SignatureItem>,
+    owner: Option<Arc<String>>,
+}
+
+impl SignatureBatch {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // What signatures added next belong to, to report it in error
+    pub fn set_owner(&mut self, owner: String) {
+        self.owner = Some(Arc::new(owner));
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn append(&mut self, mut other: SignatureBatch) {
+        self.items.append(&mut other.items);
+    }
+
+    fn add(&mut self, public_key: &SigPubKey, message: &Arc<Vec<u8>>, signature: CryptoSignature, signer: Arc<KeyId>) {
+        self.items.push(SignatureItem {
+            public_key: public_key.clone(),
+            signature,
+            message: message.clone(),
+            signer,
+            owner: self.owner.clone(),
+        })
+    }
+
+    // Bad signature is searched by individual checks only if the batch check fails
+    pub fn verify(&self) -> Result<()> {
+        if self.items.len() > 1 && batch_signature_check() && self.verify_batch() {
+            return Ok(())
+        }
+        self.verify_each()
+    }
+
+    // Batch equation is cofactored, so the batch may fail where individual checks pass,
+    // which are done then
+    fn verify_batch(&self) -> bool {
+        let mut keys = Vec::with_capacity(self.items.len());
+        let mut signatures = Vec::with_capacity(self.items.len());
+        for item in &self.items {
+            let Ok(key) = ed25519_dalek::VerifyingKey::try_from(&item.public_key.key_bytes()[..]) else {
+                return false
+            };
+            let Ok(signature) = ed25519_dalek::Signature::from_slice(&item.signature.as_bytes()[..]) else {
+                return false
+            };
+            keys.push(key);
+            signatures.push(signature);
+        }
+        let messages = self.items.iter().map(|item| item.message.as_slice()).collect::<Vec<_>>();
+        ed25519_dalek::verify_batch(&messages, &signatures, &keys).is_ok()
+    }
+
+    fn verify_each(&self) -> Result<()> {
+        for item in &self.items {
+            if !item.public_key.verify_signature(&item.message, &item.signature) {
+                match &item.owner {
+                    Some(owner) => fail!(
+                        "{}: bad signature from validator with pub_key {}", owner, item.signer
+                    ),
+                    None => fail!("bad signature from validator with pub_key {}", item.signer)
+                }
+            }
+        }
+        Ok(())
+    }
+
+}
+
 pub fn check_crypto_signatures(signatures: &BlockSignaturesPure, validators_list: &[ValidatorDescr], data: &[u8]) -> Result<u64> {
+    let mut batch = SignatureBatch::new();
+    let weight = collect_crypto_signatures(signatures, validators_list, data, &mut batch)?;
+    batch.verify()?;
+    Ok(weight)
+}
+
+// Calculates weight of signatures of validators from the list, the signatures
+// themselves are not checked but added to the batch
+pub fn collect_crypto_signatures(
+    signatures: &BlockSignaturesPure,
+    validators_list: &[ValidatorDescr],
+    data: &[u8],
+    batch: &mut SignatureBatch
+) -> Result<u64> {
     // Calc validators short ids
     let validators_map = validators_list.iter().map(|desc| {
         let key = Ed25519KeyOption::from_public_key(desc.public_key.as_slice()).id().clone();
         (key, desc)
     }).collect::<HashMap<_, _>>();
-    // Check signatures
+    let message = Arc::new(data.to_vec());
     let mut weight = 0;
     signatures.signatures().iterate_slices(|_key, ref mut slice| {
         let sign = CryptoSignaturePair::construct_from(slice)?;
         let key = KeyId::from_data(sign.node_id_short.inner());
         if let Some(vd) = validators_map.get(&key) {
-            if !vd.public_key.verify_signature(data, &sign.sign) {
-                fail!("bad signature from validator with pub_key {}", key)
-            }
+            batch.add(&vd.public_key, &message, sign.sign, key);
             weight += vd.weight;
         }
         Ok(true)
@@ -211,18 +328,25 @@ pub fn compute_validator_set_cc(
     cc_seqno_delta: &mut u32
 ) -> Result<Vec<ValidatorDescr>> {
     let config = mc_state.config_params()?;
-    let vset = config.validator_set()?;
     if (*cc_seqno_delta & 0xfffffffe) != 0 {
         fail!("seqno_delta>1 is not implemented yet");
     }
     *cc_seqno_delta += cc_seqno;
+    let _ = seq_no;
+    compute_validator_set_by_config(config, shard, *cc_seqno_delta)
+}
+
+// Shuffles validators of the config for the shard session the same way for live and past sessions
+pub fn compute_validator_set_by_config(
+    config: &ConfigParams,
+    shard: &ShardIdent,
+    cc_seqno: u32
+) -> Result<Vec<ValidatorDescr>> {
+    let vset = config.validator_set()?;
     let workchain_info = if shard.is_masterchain() {
-        calc_subset_for_masterchain(&vset, config, *cc_seqno_delta)?
+        calc_subset_for_masterchain(&vset, config, cc_seqno)?
     } else {
-         {
-            let _ = seq_no;
-            calc_subset_for_workchain_standard(&vset, config, shard, *cc_seqno_delta)?
-        }
+        calc_subset_for_workchain_standard(&vset, config, shard, cc_seqno)?
     };
 
     Ok(workchain_info.validators)
@@ -415,7 +539,7 @@ pub fn calc_subset_for_workchain_standard(
     }
 }
 
-pub async fn get_shard_by_message(engine: Arc<dyn EngineOperations>, message: Arc<Message>) -> Result<ShardIdent> {
+pub async fn get_shard_by_message(engine: Arc<dyn StateAccess>, message: Arc<Message>) -> Result<ShardIdent> {
     let dst_wc = message.dst_workchain_id()
         .ok_or_else(|| error!("Can't get workchain id from message"))?;
     let dst_address = message.int_dst_account_id()
@@ -513,7 +637,6 @@ pub struct LockfreeMapSet<K, V> where V: Ord, V: Clone+Debug, K: Clone+Hash+Ord+
 }
 
 impl<K,V> LockfreeMapSet<K,V> where V: Ord, V: Clone+Debug, K: Clone+Hash+Ord+Debug {
-    #[allow(dead_code)]
     fn remove_and_sort(src: &Vec<V>, old_to_remove: &V) -> Vec<V> {
         let mut canonized: Vec<V> = src.iter().filter(|x| *x != old_to_remove).cloned().collect();
         canonized.sort();
@@ -561,7 +684,6 @@ impl<K,V> LockfreeMapSet<K,V> where V: Ord, V: Clone+Debug, K: Clone+Hash+Ord+De
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn remove_from_set(&self, msg_uid: &K, msg_id: &V) -> Result<()> {
         if let Some(mut t) = self.map.get_mut(msg_uid) {
             *t = Self::remove_and_sort(t.value(), msg_id)
@@ -570,6 +692,10 @@ impl<K,V> LockfreeMapSet<K,V> where V: Ord, V: Clone+Debug, K: Clone+Hash+Ord+De
         Ok(())
     }
 
+    pub fn remove_set(&self, msg_uid: &K) -> Vec<V> {
+        self.map.remove(msg_uid).map(|(_k, v)| v).unwrap_or_default()
+    }
+
     pub fn get_set(&self, msg_uid: &K) -> Vec<V> {
         match self.map.get(msg_uid) {
             None => Vec::new(),
@@ -13,7 +13,7 @@
 
 use std::{sync::Arc, collections::HashSet};
 use storage::{
-    db::rocksdb::RocksDb,
+    db::rocksdb::RocksDb, fsync::{FsyncControl, FsyncPolicy},
     shardstate_db_async::{AllowStateGcResolver, CellsDbConfig, ShardStateDb, SsNotificationCallback},
     StorageAlloc,
 };
@@ -160,7 +160,9 @@ async fn main() -> Result<()> {
                 prefill_cells_counters: false,
                 cache_cells_counters: true,
                 cache_size_bytes: 10000000,
+                adaptive_cache: None,
             },
+            &FsyncControl::new("cells", FsyncPolicy::Never),
             #[cfg(feature = "telemetry")]
             Arc::new(StorageTelemetry::default()),
             Arc::new(StorageAlloc::default()),
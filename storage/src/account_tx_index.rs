@@ -0,0 +1,352 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{db_impl_base, db::{rocksdb::RocksDb, traits::KvcWriteable}};
+use ever_block::{ByteOrderRead, Result, UInt256};
+use std::{collections::{BTreeMap, BTreeSet}, io::Cursor, sync::Arc};
+
+#[cfg(test)]
+#[path = "tests/test_account_tx_index.rs"]
+mod tests;
+
+db_impl_base!(AccountTxDb, KvcWriteable, Vec<u8>);
+
+// Transactions are grouped into buckets by this period of masterchain seqno
+const BUCKET_SEQ_NO: u32 = 100;
+// Key prefixes
+const ENTRIES_PREFIX: u8 = 0;         // account, bucket -> entries ordered by (mc seqno, lt)
+const ACCOUNT_BUCKETS_PREFIX: u8 = 1; // account -> ordered buckets with entries
+const BUCKET_ACCOUNTS_PREFIX: u8 = 2; // bucket -> accounts with entries
+const FIRST_BUCKET_KEY: u8 = 3;       // lowest bucket which may have entries
+const ENTRY_LEN: usize = 4 + 8 + 32 + 32;
+const ACCOUNT_LEN: usize = 4 + 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxLocation {
+    pub workchain_id: i32,
+    pub account: UInt256,
+    // Seqno of the masterchain block which refers to the block of transaction
+    pub mc_seq_no: u32,
+    pub lt: u64,
+    pub block_root_hash: UInt256,
+    pub tx_hash: UInt256,
+}
+
+// Paging goes on after the transaction with this position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxContinuation {
+    pub mc_seq_no: u32,
+    pub lt: u64,
+}
+
+impl TxContinuation {
+    fn of(location: &TxLocation) -> Self {
+        Self { mc_seq_no: location.mc_seq_no, lt: location.lt }
+    }
+}
+
+impl std::fmt::Display for TxContinuation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.mc_seq_no, self.lt)
+    }
+}
+
+impl std::str::FromStr for TxContinuation {
+    type Err = ever_block::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (mc_seq_no, lt) = s.split_once('.')
+            .ok_or_else(|| ever_block::error!("Invalid continuation {}", s))?;
+        Ok(Self { mc_seq_no: mc_seq_no.parse()?, lt: lt.parse()? })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    mc_seq_no: u32,
+    lt: u64,
+    block_root_hash: UInt256,
+    tx_hash: UInt256,
+}
+
+impl Entry {
+    fn position(&self) -> (u32, u64) {
+        (self.mc_seq_no, self.lt)
+    }
+}
+
+fn account_key(prefix: u8, workchain_id: i32, account: &UInt256) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + ACCOUNT_LEN + 4);
+    key.push(prefix);
+    key.extend_from_slice(&workchain_id.to_be_bytes());
+    key.extend_from_slice(account.as_slice());
+    key
+}
+
+fn entries_key(workchain_id: i32, account: &UInt256, bucket: u32) -> Vec<u8> {
+    let mut key = account_key(ENTRIES_PREFIX, workchain_id, account);
+    key.extend_from_slice(&bucket.to_be_bytes());
+    key
+}
+
+fn bucket_accounts_key(bucket: u32) -> Vec<u8> {
+    let mut key = vec![BUCKET_ACCOUNTS_PREFIX];
+    key.extend_from_slice(&bucket.to_be_bytes());
+    key
+}
+
+// Index of transactions by account. Entries of an account are kept in buckets of masterchain
+// seqnos, every bucket knows its accounts, so old buckets are pruned without full scan.
+pub struct AccountTxIndex {
+    db: AccountTxDb,
+    lock: parking_lot::Mutex<()>,
+}
+
+impl AccountTxIndex {
+
+    pub fn with_db(db: Arc<RocksDb>, family: impl ToString, create_if_not_exist: bool) -> Result<Self> {
+        Ok(Self::with_storage(AccountTxDb::with_db(db, family, create_if_not_exist)?))
+    }
+
+    pub fn in_memory() -> Self {
+        Self::with_storage(AccountTxDb::in_memory())
+    }
+
+    fn with_storage(db: AccountTxDb) -> Self {
+        Self { db, lock: parking_lot::Mutex::new(()) }
+    }
+
+    // Adding the same transaction twice changes nothing, so a block may be indexed again
+    pub fn add(&self, locations: &[TxLocation]) -> Result<()> {
+        let mut grouped = BTreeMap::<(u32, i32, UInt256), Vec<Entry>>::new();
+        for location in locations {
+            let entry = Entry {
+                mc_seq_no: location.mc_seq_no,
+                lt: location.lt,
+                block_root_hash: location.block_root_hash.clone(),
+                tx_hash: location.tx_hash.clone(),
+            };
+            grouped.entry((location.mc_seq_no / BUCKET_SEQ_NO, location.workchain_id, location.account.clone()))
+                .or_default()
+                .push(entry);
+        }
+        let _guard = self.lock.lock();
+        let mut new_accounts = BTreeMap::<u32, Vec<(i32, UInt256)>>::new();
+        for ((bucket, workchain_id, account), new) in grouped {
+            let key = entries_key(workchain_id, &account, bucket);
+            let mut entries = self.load_entries(&key)?;
+            let was_empty = entries.is_empty();
+            let len = entries.len();
+            for entry in new {
+                if let Err(pos) = entries.binary_search_by_key(&entry.position(), Entry::position) {
+                    entries.insert(pos, entry);
+                }
+            }
+            if entries.len() == len {
+                continue
+            }
+            self.save_entries(&key, &entries)?;
+            if was_empty {
+                let mut buckets = self.load_account_buckets(workchain_id, &account)?;
+                buckets.insert(bucket);
+                self.save_account_buckets(workchain_id, &account, &buckets)?;
+                new_accounts.entry(bucket).or_default().push((workchain_id, account));
+            }
+        }
+        for (bucket, new) in new_accounts {
+            let mut accounts = self.load_bucket_accounts(bucket)?;
+            accounts.extend(new);
+            self.save_bucket_accounts(bucket, &accounts)?;
+            if self.first_bucket()?.map_or(true, |first| bucket < first) {
+                self.db.put(&vec![FIRST_BUCKET_KEY], &bucket.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Transactions with masterchain seqno in given range, both ends included, in order of
+    // (mc seqno, lt). Continuation is returned if there are more transactions.
+    pub fn find(
+        &self,
+        workchain_id: i32,
+        account: &UInt256,
+        from_seq_no: u32,
+        to_seq_no: u32,
+        limit: usize,
+        after: Option<TxContinuation>
+    ) -> Result<(Vec<TxLocation>, Option<TxContinuation>)> {
+        let mut found = Vec::new();
+        if limit == 0 || from_seq_no > to_seq_no {
+            return Ok((found, None))
+        }
+        let start = after.map_or(from_seq_no, |after| after.mc_seq_no.max(from_seq_no));
+        if start > to_seq_no {
+            return Ok((found, None))
+        }
+        let buckets = self.load_account_buckets(workchain_id, account)?;
+        for bucket in buckets.range(start / BUCKET_SEQ_NO..=to_seq_no / BUCKET_SEQ_NO) {
+            for entry in self.load_entries(&entries_key(workchain_id, account, *bucket))? {
+                if !(from_seq_no..=to_seq_no).contains(&entry.mc_seq_no) {
+                    continue
+                }
+                if after.map_or(false, |after| entry.position() <= (after.mc_seq_no, after.lt)) {
+                    continue
+                }
+                if found.len() == limit {
+                    let continuation = found.last().map(TxContinuation::of);
+                    return Ok((found, continuation))
+                }
+                found.push(TxLocation {
+                    workchain_id,
+                    account: account.clone(),
+                    mc_seq_no: entry.mc_seq_no,
+                    lt: entry.lt,
+                    block_root_hash: entry.block_root_hash,
+                    tx_hash: entry.tx_hash,
+                });
+            }
+        }
+        Ok((found, None))
+    }
+
+    // Removes transactions with masterchain seqno in given range, the end is excluded.
+    // Returns count of removed transactions.
+    pub fn prune(&self, from_seq_no: u32, to_seq_no: u32) -> Result<usize> {
+        let _guard = self.lock.lock();
+        let Some(first) = self.first_bucket()? else {
+            return Ok(0)
+        };
+        if from_seq_no >= to_seq_no {
+            return Ok(0)
+        }
+        let last = (to_seq_no - 1) / BUCKET_SEQ_NO;
+        let mut removed = 0;
+        for bucket in (from_seq_no / BUCKET_SEQ_NO).max(first)..=last {
+            let accounts = self.load_bucket_accounts(bucket)?;
+            if accounts.is_empty() {
+                continue
+            }
+            let mut kept = Vec::with_capacity(accounts.len());
+            for (workchain_id, account) in accounts.iter() {
+                let key = entries_key(*workchain_id, account, bucket);
+                let mut entries = self.load_entries(&key)?;
+                let len = entries.len();
+                entries.retain(|entry| !(from_seq_no..to_seq_no).contains(&entry.mc_seq_no));
+                removed += len - entries.len();
+                if entries.is_empty() {
+                    self.db.delete(&key)?;
+                    let mut buckets = self.load_account_buckets(*workchain_id, account)?;
+                    buckets.remove(&bucket);
+                    self.save_account_buckets(*workchain_id, account, &buckets)?;
+                } else {
+                    if entries.len() != len {
+                        self.save_entries(&key, &entries)?;
+                    }
+                    kept.push((*workchain_id, account.clone()));
+                }
+            }
+            if kept.len() != accounts.len() {
+                self.save_bucket_accounts(bucket, &kept)?;
+            }
+        }
+        // Buckets may be pruned partially, so the first one is moved only over empty buckets
+        let mut new_first = first;
+        while new_first <= last && self.load_bucket_accounts(new_first)?.is_empty() {
+            new_first += 1;
+        }
+        if new_first != first {
+            self.db.put(&vec![FIRST_BUCKET_KEY], &new_first.to_le_bytes())?;
+        }
+        Ok(removed)
+    }
+
+    fn first_bucket(&self) -> Result<Option<u32>> {
+        let Some(data) = self.db.try_get(&vec![FIRST_BUCKET_KEY])? else {
+            return Ok(None)
+        };
+        Ok(Some(Cursor::new(data.as_ref()).read_le_u32()?))
+    }
+
+    fn load_entries(&self, key: &Vec<u8>) -> Result<Vec<Entry>> {
+        let Some(data) = self.db.try_get(key)? else {
+            return Ok(Vec::new())
+        };
+        let mut cursor = Cursor::new(data.as_ref());
+        let mut entries = Vec::with_capacity(data.len() / ENTRY_LEN);
+        for _ in 0..data.len() / ENTRY_LEN {
+            entries.push(Entry {
+                mc_seq_no: cursor.read_le_u32()?,
+                lt: cursor.read_le_u64()?,
+                block_root_hash: UInt256::from(cursor.read_u256()?),
+                tx_hash: UInt256::from(cursor.read_u256()?),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn save_entries(&self, key: &Vec<u8>, entries: &[Entry]) -> Result<()> {
+        let mut data = Vec::with_capacity(entries.len() * ENTRY_LEN);
+        for entry in entries {
+            data.extend_from_slice(&entry.mc_seq_no.to_le_bytes());
+            data.extend_from_slice(&entry.lt.to_le_bytes());
+            data.extend_from_slice(entry.block_root_hash.as_slice());
+            data.extend_from_slice(entry.tx_hash.as_slice());
+        }
+        self.db.put(key, &data)
+    }
+
+    fn load_account_buckets(&self, workchain_id: i32, account: &UInt256) -> Result<BTreeSet<u32>> {
+        let key = account_key(ACCOUNT_BUCKETS_PREFIX, workchain_id, account);
+        let Some(data) = self.db.try_get(&key)? else {
+            return Ok(BTreeSet::new())
+        };
+        let mut cursor = Cursor::new(data.as_ref());
+        let mut buckets = BTreeSet::new();
+        for _ in 0..data.len() / 4 {
+            buckets.insert(cursor.read_le_u32()?);
+        }
+        Ok(buckets)
+    }
+
+    fn save_account_buckets(&self, workchain_id: i32, account: &UInt256, buckets: &BTreeSet<u32>) -> Result<()> {
+        let key = account_key(ACCOUNT_BUCKETS_PREFIX, workchain_id, account);
+        if buckets.is_empty() {
+            return self.db.delete(&key)
+        }
+        let data = buckets.iter().flat_map(|bucket| bucket.to_le_bytes()).collect::<Vec<_>>();
+        self.db.put(&key, &data)
+    }
+
+    fn load_bucket_accounts(&self, bucket: u32) -> Result<Vec<(i32, UInt256)>> {
+        let Some(data) = self.db.try_get(&bucket_accounts_key(bucket))? else {
+            return Ok(Vec::new())
+        };
+        let mut cursor = Cursor::new(data.as_ref());
+        let mut accounts = Vec::with_capacity(data.len() / ACCOUNT_LEN);
+        for _ in 0..data.len() / ACCOUNT_LEN {
+            accounts.push((cursor.read_le_u32()? as i32, UInt256::from(cursor.read_u256()?)));
+        }
+        Ok(accounts)
+    }
+
+    fn save_bucket_accounts(&self, bucket: u32, accounts: &[(i32, UInt256)]) -> Result<()> {
+        if accounts.is_empty() {
+            return self.db.delete(&bucket_accounts_key(bucket))
+        }
+        let mut data = Vec::with_capacity(accounts.len() * ACCOUNT_LEN);
+        for (workchain_id, account) in accounts {
+            data.extend_from_slice(&workchain_id.to_le_bytes());
+            data.extend_from_slice(account.as_slice());
+        }
+        self.db.put(&bucket_accounts_key(bucket), &data)
+    }
+}
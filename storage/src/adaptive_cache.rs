@@ -0,0 +1,129 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::shardstate_db_async::AdaptiveCacheConfig;
+
+#[cfg(test)]
+#[path = "tests/test_adaptive_cache.rs"]
+mod tests;
+
+// Capacity is changed by this share of current value per adjustment
+const STEP_PERCENT: u64 = 10;
+// Hit rate of fewer requests is not reliable
+const MIN_REQUESTS: u64 = 1000;
+// Cache is shrunk if hit rate is above the target by this margin
+const HIT_RATE_MARGIN: f64 = 0.02;
+// Cache is not grown if it doesn't take this share of capacity yet
+const FULL_PERCENT: u64 = 90;
+// Memory usage above this share of ceiling is considered as pressure
+const MEMORY_HIGH_PERCENT: u64 = 90;
+
+/// Cache statistics over the period since previous adjustment
+#[derive(Clone, Debug, Default)]
+pub struct CacheSample {
+    pub hits: u64,
+    pub misses: u64,
+    pub cache_bytes: u64,
+    pub memory_used: Option<u64>,
+    pub memory_ceiling: Option<u64>,
+}
+
+impl CacheSample {
+    pub fn hit_rate(&self) -> Option<f64> {
+        match self.hits + self.misses {
+            0 => None,
+            requests => Some(self.hits as f64 / requests as f64)
+        }
+    }
+}
+
+// Computes cache capacity from hit rate and memory pressure. Capacity goes up while hit rate
+// is below the target and there is free memory, and goes down under memory pressure or when
+// hit rate is well above the target.
+pub struct CacheController {
+    min_size: u64,
+    max_size: u64,
+    target_hit_rate: f64,
+    capacity: u64,
+}
+
+impl CacheController {
+    pub fn new(config: &AdaptiveCacheConfig, initial_size: u64) -> Self {
+        let max_size = config.max_size_bytes.max(config.min_size_bytes);
+        Self {
+            min_size: config.min_size_bytes,
+            max_size,
+            target_hit_rate: config.target_hit_rate,
+            capacity: initial_size.clamp(config.min_size_bytes, max_size),
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    // Returns new capacity
+    pub fn update(&mut self, sample: &CacheSample) -> u64 {
+        let step = (self.capacity * STEP_PERCENT / 100).max(1);
+        let memory = sample.memory_used.zip(sample.memory_ceiling).map(
+            |(used, ceiling)| (used, ceiling / 100 * MEMORY_HIGH_PERCENT)
+        );
+        let capacity = match memory {
+            // At least the excess over the watermark is freed
+            Some((used, high)) if used > high =>
+                self.capacity.saturating_sub(step.max(used - high)),
+            _ if sample.hits + sample.misses < MIN_REQUESTS => self.capacity,
+            _ => {
+                let hit_rate = sample.hit_rate().unwrap_or_default();
+                let full = sample.cache_bytes >= self.capacity / 100 * FULL_PERCENT;
+                let has_memory = memory.map_or(true, |(used, high)| used + step <= high);
+                if hit_rate < self.target_hit_rate && full && has_memory {
+                    self.capacity + step
+                } else if hit_rate > self.target_hit_rate + HIT_RATE_MARGIN {
+                    self.capacity - step
+                } else {
+                    self.capacity
+                }
+            }
+        };
+        self.capacity = capacity.clamp(self.min_size, self.max_size);
+        self.capacity
+    }
+}
+
+fn read_number(path: &str) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// Memory limit of the cgroup the node runs in, v2 or v1 hierarchy.
+// Huge v1 value means no limit.
+pub fn cgroup_memory_limit() -> Option<u64> {
+    read_number("/sys/fs/cgroup/memory.max")
+        .or_else(|| read_number("/sys/fs/cgroup/memory/memory.limit_in_bytes"))
+        .filter(|limit| *limit < (1 << 60))
+}
+
+// Memory used by the cgroup if it is limited, resident size of the process otherwise
+pub fn memory_used(cgroup: bool) -> Option<u64> {
+    if cgroup {
+        if let Some(used) = read_number("/sys/fs/cgroup/memory.current")
+            .or_else(|| read_number("/sys/fs/cgroup/memory/memory.usage_in_bytes"))
+        {
+            return Some(used)
+        }
+    }
+    const PAGE_SIZE: u64 = 4096;
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(pages * PAGE_SIZE)
+}
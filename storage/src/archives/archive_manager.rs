@@ -12,18 +12,22 @@
 */
 
 use crate::{
-    StorageAlloc,
+    GcCounters, StorageAlloc,
     archives::{
-        archive_slice::ArchiveSlice, file_maps::{FileDescription, FileMaps}, 
+        archive_slice::ArchiveSlice, file_maps::{FileDescription, FileMaps},
+        mapped_packages::{ArchiveSliceData, MappedPackages, MAX_MAPPED_PACKAGES},
         get_mc_seq_no, package_entry::PackageEntry, 
         package_entry_id::{GetFileNameShort, PackageEntryId, parse_short_filename},
         package_id::PackageId, ARCHIVE_SLICE_SIZE, KEY_ARCHIVE_PACKAGE_SIZE
     },
-    block_handle_db::BlockHandle, db::rocksdb::RocksDb
+    block_handle_db::BlockHandle, db::rocksdb::RocksDb, fsync::FsyncControl
 };
 #[cfg(feature = "telemetry")]
 use crate::StorageTelemetry;
-use std::{borrow::Borrow, hash::Hash, io::ErrorKind, path::PathBuf, sync::Arc, time::Instant};
+use std::{
+    borrow::Borrow, collections::HashSet, hash::Hash, io::ErrorKind, path::PathBuf, sync::Arc,
+    time::Instant
+};
 use tokio::io::AsyncWriteExt;
 use ever_block::{
     BlockIdExt, ShardIdent,
@@ -34,11 +38,22 @@ use ever_block::{error, fail, Result, UInt256};
 #[path = "tests/test_archive_manager.rs"]
 mod tests;
 
+// Package of blocks as seen by archives GC
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchivePackageInfo {
+    pub archive_id: u32,
+    // Masterchain seqno the next package starts from
+    pub end_seq_no: u32,
+    pub size: u64,
+}
+
 pub struct ArchiveManager {
     db: Arc<RocksDb>,
     db_root_path: Arc<PathBuf>,
     unapplied_files_path: PathBuf,
     file_maps: FileMaps,
+    mapped_packages: Arc<MappedPackages>,
+    fsync: Arc<FsyncControl>,
     #[cfg(feature = "telemetry")]
     telemetry: Arc<StorageTelemetry>,
     allocated: Arc<StorageAlloc>
@@ -52,27 +67,36 @@ impl ArchiveManager {
         db: Arc<RocksDb>,
         db_root_path: Arc<PathBuf>,
         last_unneeded_key_block: u32,
+        fsync: Arc<FsyncControl>,
         #[cfg(feature = "telemetry")]
         telemetry: Arc<StorageTelemetry>,
         allocated: Arc<StorageAlloc>
     ) -> Result<Self> {
+        let mapped_packages = Arc::new(MappedPackages::new(MAX_MAPPED_PACKAGES));
         let file_maps = FileMaps::new(
             db.clone(),
             &db_root_path,
             last_unneeded_key_block,
+            &mapped_packages,
             #[cfg(feature = "telemetry")]
             &telemetry,
             &allocated
         ).await?;
         let unapplied_files_path = db_root_path.join(Self::ARCHIVE_DIR).join("unapplied");
-        tokio::fs::create_dir_all(unapplied_files_path.as_path()).await.map_err(
-            |e| error!("Cannot create unapplied files directory {:?}: {}", unapplied_files_path, e)
-        )?;
+        if !db.is_read_only() {
+            tokio::fs::create_dir_all(unapplied_files_path.as_path()).await.map_err(
+                |e| error!("Cannot create unapplied files directory {:?}: {}", unapplied_files_path, e)
+            )?;
+        }
+        // Package indexes are in RocksDB
+        fsync.register_db(&db);
         Ok(Self {
             db,
             db_root_path,
             unapplied_files_path,
             file_maps,
+            mapped_packages,
+            fsync,
             #[cfg(feature = "telemetry")]
             telemetry,
             allocated
@@ -95,6 +119,7 @@ impl ArchiveManager {
     {
         log::debug!(target: "storage", "Saving unapplied file: {}", entry_id);
 
+        self.db.check_writable()?;
         if data.is_empty() {
             fail!("Added file's ({}) data can't have zero length", entry_id);
         }
@@ -108,6 +133,7 @@ impl ArchiveManager {
             .map_err(|err| error!("{} : {}", err, filename.display()))?;
         file.write_all(&data).await?;
         file.flush().await?;
+        self.fsync.file_written(&filename).await?;
 
         Ok(())
     }
@@ -129,6 +155,39 @@ impl ArchiveManager {
         }
     }
 
+    // Checks if the package which archived block belongs to is present.
+    // Returns None if the package has been already removed by archives GC.
+    pub async fn check_archived(&self, handle: &BlockHandle) -> Result<Option<bool>> {
+        let mc_seq_no = get_mc_seq_no(handle);
+        if self.file_maps.files().get_closest_id(mc_seq_no).await.is_none() {
+            return Ok(None)
+        }
+        let package_id = self.get_package_id(mc_seq_no, false).await?;
+        Ok(Some(self.get_file_desc(&package_id, false).await?.is_some()))
+    }
+
+    // Returns names of all unapplied (not archived yet) files
+    pub async fn unapplied_file_names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut dir = tokio::fs::read_dir(self.unapplied_files_path.as_path()).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            match entry.file_name().into_string() {
+                Ok(name) => names.push(name),
+                Err(name) => log::warn!(
+                    target: "storage", "unapplied_file_names: unreadable file name {:?}", name
+                )
+            }
+        }
+        Ok(names)
+    }
+
+    pub async fn remove_unapplied_file(&self, name: &str) -> Result<()> {
+        self.db.check_writable()?;
+        let filename = self.unapplied_files_path.join(name);
+        tokio::fs::remove_file(&filename).await
+            .map_err(|err| error!("Cannot remove file {}: {}", filename.display(), err))
+    }
+
     pub async fn get_file<B, U256, PK>(
         &self,
         handle: &BlockHandle,
@@ -177,6 +236,7 @@ impl ArchiveManager {
         mut on_success: impl FnMut() -> Result<()>,
     ) -> Result<()> {
 
+        self.db.check_writable()?;
         if !handle.set_moving_to_archive() {
             return Ok(());
         }
@@ -246,6 +306,7 @@ impl ArchiveManager {
         &self,
         handle: &BlockHandle
     ) -> Result<()> {
+        self.db.check_writable()?;
         let proof_filename = if handle.has_proof_link() {
             let entry_id = PackageEntryId::<_, UInt256, UInt256>::ProofLink(handle.id());
             log::debug!(target: "storage", "Remove unapplied proof link file: {}", entry_id);
@@ -263,8 +324,27 @@ impl ArchiveManager {
         Self::remove(handle, proof_filename, block_filename).await
     }
 
+    /// Removes unapplied block file, returns its size or 0 if there was no file
+    pub async fn remove_block_file(&self, handle: &BlockHandle) -> Result<u64> {
+        self.db.check_writable()?;
+        let entry_id = PackageEntryId::<_, UInt256, UInt256>::Block(handle.id());
+        let filename = self.unapplied_files_path.join(entry_id.filename_short());
+        let _lock = handle.block_file_lock().write().await;
+        let size = match tokio::fs::metadata(&filename).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return Ok(0)
+        };
+        log::debug!(target: "storage", "Remove unapplied block file: {}", entry_id);
+        tokio::fs::remove_file(&filename).await
+            .map_err(|err| error!("Cannot remove file with block {:?}: {}", filename, err))?;
+        Ok(size)
+    }
+
     pub async fn clean_unapplied_files(&self, ids: &[BlockIdExt]) {
         const MAX_SLOT_MS: u128 = 500;
+        if self.db.is_read_only() {
+            return
+        }
         fn parse_entry(entry: &tokio::fs::DirEntry) -> Result<(ShardIdent, u32)> {
             let (workchain_id, shard_prefix_tagged, seq_no) = parse_short_filename(
                 &entry.file_name().into_string().map_err(|_| error!("unreadable file name"))?
@@ -321,7 +401,7 @@ impl ArchiveManager {
         archive_id: u64, 
         offset: u64, 
         limit: u32
-    ) -> Result<Vec<u8>> {
+    ) -> Result<ArchiveSliceData> {
         let fd = match self.get_file_desc(&PackageId::for_block(archive_id as u32), false).await? {
             Some(file_desc) => file_desc,
             None => {
@@ -338,12 +418,26 @@ impl ArchiveManager {
         fd.archive_slice().get_slice(archive_id, offset, limit).await
     }
 
-    pub async fn gc(&self, last_unneeded_key_block: &BlockIdExt) {
-        if let Err(e) = self.file_maps.files().gc(last_unneeded_key_block).await {
+    pub async fn gc(&self, last_unneeded_key_block: &BlockIdExt, counters: &GcCounters) {
+        if self.db.is_read_only() {
+            return
+        }
+        if let Err(e) = self.file_maps.files().gc(last_unneeded_key_block, counters).await {
             log::info!(target: "storage", "archive_manager gc is error: {:?}", e);
         }
     }
 
+    // Packages of blocks (not key blocks ones) ordered from the oldest one
+    pub async fn archive_packages(&self) -> Vec<ArchivePackageInfo> {
+        self.file_maps.files().packages().await
+    }
+
+    // Removes package of blocks, returns its size and root hashes of its blocks
+    pub async fn remove_archive_package(&self, archive_id: u32) -> Result<(u64, HashSet<UInt256>)> {
+        self.db.check_writable()?;
+        self.file_maps.files().remove_package(archive_id).await
+    }
+
     async fn get_package_entry<B, U256, PK>(
         &self, 
         handle: &BlockHandle, 
@@ -430,7 +524,7 @@ impl ArchiveManager {
         let fd = self.get_file_desc(&package_id, true).await?
             .ok_or_else(|| error!("Expected some value for {:?}", package_id))?;
 
-        fd.archive_slice().add_file(Some(handle), entry_id, data).await
+        fd.archive_slice().add_file(Some(handle), entry_id, data, &self.fsync).await
     }
 
     async fn read_temp_file<B, U256, PK>(
@@ -491,6 +585,7 @@ impl ArchiveManager {
             Arc::clone(&self.db_root_path),
             id.id(),
             id.package_type(),
+            self.mapped_packages.clone(),
             #[cfg(feature = "telemetry")]
             self.telemetry.clone(),
             self.allocated.clone()
@@ -540,6 +635,7 @@ impl ArchiveManager {
     }
 
     pub async fn trunc<F: Fn(&BlockIdExt) -> bool>(&self, block_id: &BlockIdExt, delete_condition: &F) -> Result<()> {
+        self.db.check_writable()?;
         self.file_maps.trunc(block_id, delete_condition).await
     }
 
@@ -15,18 +15,21 @@ use crate::{
     StorageAlloc, 
     archives::{
         get_mc_seq_no_opt, ARCHIVE_PACKAGE_SIZE, KEY_ARCHIVE_PACKAGE_SIZE,
-        archive_manager::ArchiveManager, package::{Package, read_package_from},
+        archive_manager::ArchiveManager, mapped_packages::{ArchiveSliceData, MappedPackages},
+        package::{Package, PKG_HEADER_SIZE, read_package_from},
+        package_entries_index::{PackageEntriesIndex, PackageEntriesIndexDb},
         package_entry::PackageEntry, package_entry_id::{GetFileName, PackageEntryId},
         package_entry_meta::PackageEntryMeta, package_entry_meta_db::PackageEntryMetaDb,
         package_id::{PackageId, PackageType}, package_info::PackageInfo,
         package_offsets_db::PackageOffsetsDb, package_status_db::PackageStatusDb, 
         package_status_key::PackageStatusKey
     },
-    block_handle_db::BlockHandle, db::rocksdb::RocksDb, traits::Serializable
+    block_handle_db::BlockHandle, db::rocksdb::RocksDb, fsync::FsyncControl,
+    traits::Serializable
 };
 #[cfg(feature = "telemetry")]
 use crate::StorageTelemetry;
-use std::{borrow::Borrow, hash::Hash, io::SeekFrom, path::PathBuf, sync::Arc};
+use std::{borrow::Borrow, collections::HashSet, hash::Hash, io::SeekFrom, path::PathBuf, sync::Arc};
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use ever_block::BlockIdExt;
 use ever_block::{error, fail, Result, UInt256};
@@ -46,9 +49,13 @@ pub struct ArchiveSlice {
     slice_size: u32,
     package_type: PackageType,
     finalized: bool,
+    // Packages are opened without creation and never changed
+    read_only: bool,
     index_db: PackageEntryMetaDb,
     offsets_db: PackageOffsetsDb,
+    entries_index_db: PackageEntriesIndexDb,
     package_status_db: PackageStatusDb,
+    mapped_packages: Arc<MappedPackages>,
     #[cfg(feature = "telemetry")]
     telemetry: Arc<StorageTelemetry>,
     allocated: Arc<StorageAlloc>
@@ -63,15 +70,19 @@ impl ArchiveSlice {
         package_type: PackageType,
         finalized: bool,
         create_if_not_exist: bool,
+        mapped_packages: Arc<MappedPackages>,
         #[cfg(feature = "telemetry")]
         telemetry: Arc<StorageTelemetry>,
         allocated: Arc<StorageAlloc>
     ) -> Result<Self> {
 
+        let read_only = db.is_read_only();
         let packages_path = db_root_path.join(ArchiveManager::ARCHIVE_DIR).join("packages");
-        tokio::fs::create_dir_all(packages_path.as_path()).await.map_err(
-            |e| error!("Cannot create archive packages directory {:?}: {}", packages_path, e)
-        )?;
+        if !read_only {
+            tokio::fs::create_dir_all(packages_path.as_path()).await.map_err(
+                |e| error!("Cannot create archive packages directory {:?}: {}", packages_path, e)
+            )?;
+        }
 
         let (prefix, slice_size, sliced_mode) =  if package_type == PackageType::KeyBlocks {
             ("key_", KEY_ARCHIVE_PACKAGE_SIZE, false)
@@ -88,6 +99,12 @@ impl ArchiveSlice {
             format!("offsets_{}db_{}", prefix, archive_id),
             create_if_not_exist,
         )?;
+        // Slices made before indexing of entries have no such table
+        let entries_index_db = PackageEntriesIndexDb::with_db(
+            db.clone(),
+            format!("entries_index_{}db_{}", prefix, archive_id),
+            true,
+        )?;
         let package_status_db = PackageStatusDb::with_db(
             db, 
             format!("status_{}db_{}", prefix, archive_id),
@@ -102,9 +119,12 @@ impl ArchiveSlice {
             slice_size,
             package_type,
             finalized,
+            read_only,
             index_db,
             offsets_db,
+            entries_index_db,
             package_status_db,
+            mapped_packages,
             #[cfg(feature = "telemetry")]
             telemetry,
             allocated,
@@ -117,6 +137,7 @@ impl ArchiveSlice {
         db_root_path: Arc<PathBuf>,
         archive_id: u32,
         package_type: PackageType,
+        mapped_packages: Arc<MappedPackages>,
         #[cfg(feature = "telemetry")]
         telemetry: Arc<StorageTelemetry>,
         allocated: Arc<StorageAlloc>
@@ -128,6 +149,7 @@ impl ArchiveSlice {
             package_type,
             false,
             true,
+            mapped_packages,
             #[cfg(feature = "telemetry")]
             telemetry,
             allocated,
@@ -173,6 +195,7 @@ impl ArchiveSlice {
         package_type: PackageType,
         finalized: bool,
         cleanup_if_broken: bool,
+        mapped_packages: Arc<MappedPackages>,
         #[cfg(feature = "telemetry")]
         telemetry: Arc<StorageTelemetry>,
         allocated: Arc<StorageAlloc>,
@@ -184,6 +207,7 @@ impl ArchiveSlice {
             package_type,
             finalized,
             false,
+            mapped_packages,
             #[cfg(feature = "telemetry")]
             telemetry,
             allocated,
@@ -214,7 +238,7 @@ impl ArchiveSlice {
                     Ok(p) => packages.push(p),
                     Err(e) => {
                         log::error!(target: "storage", "Can't read slice #{}: {}. Stopped slices reading", i, e);
-                        if cleanup_if_broken {
+                        if cleanup_if_broken && !self.read_only {
                             log::info!(target: "storage", "Destroy slice #{}", i);
                             match self.destroy_broken().await {
                                 Ok(_) => log::info!(target: "storage", "Destroyed slice #{}", i),
@@ -238,12 +262,16 @@ impl ArchiveSlice {
         self.package_type
     }
 
-    pub async fn destroy(&mut self) -> Result<()> {
+    // Returns total size of removed packages
+    pub async fn destroy(&mut self) -> Result<u64> {
+        let mut size = 0;
         for pi in self.packages.write().await.drain(..) {
+            size += pi.package().size();
+            self.mapped_packages.unmap(pi.package_id());
             pi.package().remove().await?;
         }
         self.destroy_dbs()?;
-        Ok(())
+        Ok(size)
     }
 
     pub async fn destroy_broken(&mut self) -> Result<()> {
@@ -286,6 +314,9 @@ impl ArchiveSlice {
         if !self.offsets_db.destroy()? {
             fail!("offsets_db of slice {} was not destroyed", self.archive_id);
         }
+        if !self.entries_index_db.destroy()? {
+            fail!("entries_index_db of slice {} was not destroyed", self.archive_id);
+        }
         if !self.package_status_db.destroy()? {
             fail!("package_status_db of slice {} was not destroyed", self.archive_id);
         }
@@ -296,6 +327,25 @@ impl ArchiveSlice {
         self.archive_id
     }
 
+    // Total size of packages of the slice
+    pub async fn size(&self) -> u64 {
+        self.packages.read().await.iter().map(|pi| pi.package().size()).sum()
+    }
+
+    // Root hashes of blocks which have entries in the slice. Indexes are used where they
+    // are valid, other packages are read.
+    pub async fn block_root_hashes(&self) -> Result<HashSet<UInt256>> {
+        let mut root_hashes = HashSet::new();
+        for package_info in self.packages.read().await.iter() {
+            let index = match self.package_entries_index(package_info).await? {
+                Some(index) => index,
+                None => Arc::new(PackageEntriesIndex::build(package_info.package().path()).await?)
+            };
+            root_hashes.extend(index.root_hashes().cloned());
+        }
+        Ok(root_hashes)
+    }
+
     fn get_index_opt(&self, mc_seq_no: u32) -> Option<u32> {
         if self.package_type == PackageType::KeyBlocks {
             (mc_seq_no / KEY_ARCHIVE_PACKAGE_SIZE).checked_sub(self.archive_id).map(|value| value / self.slice_size)
@@ -325,7 +375,13 @@ impl ArchiveSlice {
         None
     }
 
-    pub async fn add_file<B, U256, PK>(&self, block_handle: Option<&BlockHandle>, entry_id: &PackageEntryId<B, U256, PK>, data: Vec<u8>) -> Result<Vec<u8>>
+    pub async fn add_file<B, U256, PK>(
+        &self,
+        block_handle: Option<&BlockHandle>,
+        entry_id: &PackageEntryId<B, U256, PK>,
+        data: Vec<u8>,
+        fsync: &FsyncControl
+    ) -> Result<Vec<u8>>
     where
         B: Borrow<BlockIdExt> + Hash,
         U256: Borrow<UInt256> + Hash,
@@ -357,6 +413,7 @@ impl ArchiveSlice {
                 self.offsets_db.put_value(&offset_key, offset)
             }
         ).await?;
+        fsync.file_written(package_info.package().get_path()).await?;
 
         Ok(entry.take_data())
     }
@@ -371,34 +428,125 @@ impl ArchiveSlice {
         U256: Borrow<UInt256> + Hash,
         PK: Borrow<UInt256> + Hash
     {
-        let offset_key = entry_id.into();
-        let offset = match self.offsets_db.try_get_value(&offset_key)? {
-            Some(offset) => offset,
-            None => return Ok(None)
-        };
+        let entry = match self.read_indexed_entry(block_handle, entry_id).await? {
+            Some(entry) => entry,
+            None => {
+                let offset_key = entry_id.into();
+                let offset = match self.offsets_db.try_get_value(&offset_key)? {
+                    Some(offset) => offset,
+                    None => return Ok(None)
+                };
 
-        let package_info = self.choose_package(get_mc_seq_no_opt(block_handle), false).await?;
+                let package_info = self.choose_package(get_mc_seq_no_opt(block_handle), false).await?;
+
+                log::debug!(
+                    target: "storage",
+                    "Reading package entry: {:?}, offset: {}",
+                    package_info.package().get_path(),
+                    offset
+                );
+                package_info.package().read_entry(offset).await?
+            }
+        };
+        if entry.data().is_empty() {
+            fail!("Read entry ({}) is corrupted! It can't have zero length!", entry_id);
+        }
+        Ok(Some(entry))
+    }
 
+    // Reads entry by index of its package if the package is not written any more,
+    // otherwise the place of entry is taken from offsets db
+    async fn read_indexed_entry<B, U256, PK>(
+        &self, 
+        block_handle: Option<&BlockHandle>, 
+        entry_id: &PackageEntryId<B, U256, PK>
+    ) -> Result<Option<PackageEntry>>
+    where
+        B: Borrow<BlockIdExt> + Hash,
+        U256: Borrow<UInt256> + Hash,
+        PK: Borrow<UInt256> + Hash
+    {
+        if block_handle.is_none() {
+            return Ok(None)
+        }
+        // Package may be absent for the entry which is not stored yet
+        let Ok(package_info) = self.choose_package(get_mc_seq_no_opt(block_handle), false).await else {
+            return Ok(None)
+        };
+        let Some(index) = self.package_entries_index(&package_info).await? else {
+            return Ok(None)
+        };
+        let Some(place) = index.get(entry_id) else {
+            return Ok(None)
+        };
         log::debug!(
             target: "storage",
-            "Reading package entry: {:?}, offset: {}",
+            "Reading indexed package entry: {:?}, offset: {}",
             package_info.package().get_path(),
-            offset
+            place.offset
         );
-        let entry = package_info.package().read_entry(offset).await?;
-        if entry.data().is_empty() {
-            fail!("Read entry ({}) is corrupted! It can't have zero length!", entry_id);
+        Ok(Some(package_info.package().read_entry_at(&place).await?))
+    }
+
+    // Returns index of entries of package which is not written any more. Index is loaded
+    // from db, or it is built for the packages which were not indexed or changed since then.
+    async fn package_entries_index(
+        &self,
+        package_info: &PackageInfo
+    ) -> Result<Option<Arc<PackageEntriesIndex>>> {
+        if self.is_package_written(package_info).await {
+            return Ok(None)
         }
-        Ok(Some(entry))
+        let package = package_info.package();
+        let mut cached = package_info.entries_index().lock().await;
+        if let Some(index) = cached.as_ref() {
+            if index.package_size() == package.size() {
+                return Ok(Some(index.clone()))
+            }
+        }
+        let key = package_info.idx().into();
+        let index = match self.entries_index_db.try_get_value(&key)? {
+            Some(index) if index.package_size() == package.size() => index,
+            _ => {
+                let index = PackageEntriesIndex::build(package.path()).await?;
+                self.entries_index_db.put_value(&key, &index)?;
+                log::info!(
+                    target: "storage", 
+                    "Indexed {} entries of package {}", index.len(), package.get_path()
+                );
+                index
+            }
+        };
+        let index = Arc::new(index);
+        *cached = Some(index.clone());
+        Ok(Some(index))
+    }
+
+    // Only the last package of not finalized slice is appended
+    async fn is_package_written(&self, package_info: &PackageInfo) -> bool {
+        if self.finalized {
+            return false
+        }
+        let packages = self.packages.read().await;
+        packages.last().map(|last| last.idx() <= package_info.idx()).unwrap_or(true)
     }
 
-    pub async fn get_slice(&self, archive_id: u64, offset: u64, limit: u32) -> Result<Vec<u8>> {
+    // Packages which are not written any more are served from their mappings
+    pub async fn get_slice(&self, archive_id: u64, offset: u64, limit: u32) -> Result<ArchiveSliceData> {
         if archive_id as u32 != self.archive_id {
             fail!("Bad archive ID (archive_id = {}, expected {})!", archive_id as u32, self.archive_id);
         }
 
         let mc_seq_no = (archive_id >> 32) as u32;
         let package_info = self.choose_package(mc_seq_no, false).await?;
+        if !self.is_package_written(&package_info).await {
+            let package = package_info.package();
+            let file_size = PKG_HEADER_SIZE as u64 + package.size();
+            let slice = self.mapped_packages.slice(
+                package_info.package_id(), package.path(), file_size, offset, limit
+            )?;
+            return Ok(ArchiveSliceData::Mapped(slice))
+        }
         let mut file = tokio::fs::File::open(package_info.package().path()).await?;
         let mut buffer = vec![0; limit as usize];
         file.seek(SeekFrom::Start(offset)).await?;
@@ -414,13 +562,27 @@ impl ArchiveSlice {
         }
         buffer.resize(actual_read, 0);
 
-        Ok(buffer)
+        Ok(ArchiveSliceData::Read(buffer))
     }
 
     async fn new_package(&self, idx: u32, seq_no: u32, size: u64, version: u32) -> Result<Arc<PackageInfo>> {
         log::debug!(target: "storage", "Adding package, seq_no: {}, size: {} bytes, version: {}", seq_no, size, version);
         let package_id = PackageId::with_values(seq_no, self.package_type);
         let path = package_id.full_path(self.db_root_path.as_path(), "pack");
+        if self.read_only {
+            let package = Package::open(path.clone(), true, false).await.map_err(
+                |e| error!("Failed to open archive \"{}\" read-only: {}.", path.display(), e)
+            )?;
+            return Ok(Arc::new(PackageInfo::with_data(
+                package_id,
+                package,
+                idx,
+                version,
+                #[cfg(feature = "telemetry")]
+                &self.telemetry,
+                &self.allocated
+            )))
+        }
         std::fs::create_dir_all(path.parent().unwrap())
             .map_err(|err| error!("Cannot create directory: {:?} : {}", path.parent(), err))?;
 
@@ -464,11 +626,11 @@ impl ArchiveSlice {
         }
 
         let idx = self.get_index(mc_seq_no)?;
-        {
+        let (pi, written) = {
             let mut write_guard = self.packages.write().await;
             let package_count = write_guard.len();
             if (idx as usize) < package_count {
-                Ok(Arc::clone(&write_guard[idx as usize]))
+                return Ok(Arc::clone(&write_guard[idx as usize]))
             } else {
                 if !force_create {
                     fail!("mc_seq_no is too big");
@@ -496,11 +658,23 @@ impl ArchiveSlice {
                 let index_entry = PackageEntryMeta::with_data(0, DEFAULT_PKG_VERSION);
                 self.index_db.put_value(&idx.into(), &index_entry)?;
                 self.package_status_db.put_value(&PackageStatusKey::TotalSlices, idx + 1)?;
+                let written = write_guard.last().cloned();
                 write_guard.push(Arc::clone(&pi));
 
-                Ok(pi)
+                (pi, written)
+            }
+        };
+
+        // Previous package is not written any more, so it is indexed
+        if let Some(written) = written {
+            if let Err(e) = self.package_entries_index(&written).await {
+                log::warn!(
+                    target: "storage", 
+                    "Can't index package {}: {}", written.package().get_path(), e
+                );
             }
         }
+        Ok(pi)
     }
 
     /// truncs slice starting from master block_id
@@ -519,6 +693,8 @@ impl ArchiveSlice {
         let mut guard = self.packages.write().await;
         if guard.len() > index as usize + 1 {
             for ref mut package_info in guard.drain(index as usize + 1..) {
+                self.entries_index_db.delete(&package_info.idx().into())?;
+                self.mapped_packages.unmap(package_info.package_id());
                 Arc::get_mut(package_info)
                     .ok_or_else(|| error!("slice incorrect {}", index))?
                     .destroy().await?;
@@ -534,6 +710,10 @@ impl ArchiveSlice {
         let package_info = guard.last_mut()
             .ok_or_else(|| error!("slice incorrect {}", index))?;
 
+        // Index and mapping of the changed package are outdated
+        self.entries_index_db.delete(&package_info.idx().into())?;
+        self.mapped_packages.unmap(package_info.package_id());
+        *package_info.entries_index().lock().await = None;
         if !self.sliced_mode {
             package_info.package().truncate(offset).await?;
         } else {
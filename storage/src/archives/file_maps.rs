@@ -12,9 +12,11 @@
 */
 
 use crate::{
-    StorageAlloc, 
+    GcCounters, StorageAlloc, 
     archives::{
-        archive_slice::ArchiveSlice, package_id::{PackageId, PackageType},
+        archive_manager::ArchivePackageInfo, archive_slice::ArchiveSlice,
+        mapped_packages::MappedPackages,
+        package_id::{PackageId, PackageType},
         package_index_db::{PackageIndexDb, PackageIndexEntry}
     },
     db::rocksdb::RocksDb
@@ -22,11 +24,11 @@ use crate::{
 #[cfg(feature = "telemetry")]
 use crate::StorageTelemetry;
 use adnl::{declare_counted, common::{CountedObject, Counter}};
-use std::{path::PathBuf, sync::Arc};
+use std::{collections::HashSet, path::PathBuf, sync::Arc};
 #[cfg(feature = "telemetry")]
 use std::sync::atomic::Ordering;
 use ever_block::{Result, error, fail};
-use ever_block::BlockIdExt;
+use ever_block::{BlockIdExt, UInt256};
 
 use super::ARCHIVE_SLICE_SIZE;
 
@@ -54,7 +56,7 @@ impl FileDescription {
         &self.archive_slice
     }
 
-    async fn destroy(&mut self) -> Result<()> {
+    async fn destroy(&mut self) -> Result<u64> {
         self.archive_slice.destroy().await
     }
 
@@ -85,6 +87,7 @@ impl FileMap {
         path: impl ToString,
         package_type: PackageType,
         last_unneeded_key_block: u32,
+        mapped_packages: &Arc<MappedPackages>,
         #[cfg(feature = "telemetry")]
         telemetry: &Arc<StorageTelemetry>,
         allocated: &Arc<StorageAlloc>
@@ -93,10 +96,13 @@ impl FileMap {
         let storage = PackageIndexDb::with_db(db.clone(), path, true)?;
         let mut index_pairs = Vec::new();
 
-        storage.for_each_deserialized(|key, value| {
+        let scanned = storage.for_each_deserialized(|key, value| {
             index_pairs.push((key, value));
             Ok(true)
-        })?;
+        });
+        if storage.degrade_on_corruption(scanned)?.is_none() {
+            index_pairs = Self::restore_index(&db, &storage, package_type)?;
+        }
 
         index_pairs.sort_by_key(|pair| pair.0);
 
@@ -116,6 +122,7 @@ impl FileMap {
                 package_type,
                 finalized,
                 unneeded,
+                mapped_packages.clone(),
                 #[cfg(feature = "telemetry")]
                 telemetry.clone(),
                 allocated.clone()
@@ -123,7 +130,7 @@ impl FileMap {
                 Ok(s) => s,
                 Err(e) => {
                     log::warn!(target: "storage", "Can't read archive slice {}: {}", key, e);
-                    if unneeded {
+                    if unneeded && !db.is_read_only() {
                         match storage.delete(&key.into()) {
                             Ok(_) => log::info!(target: "storage", "Deleted archive slice from index {}", key),
                             Err(e) => log::info!(target: "storage", "Can't delete archive slice from index {}: {}", key, e),
@@ -156,6 +163,37 @@ impl FileMap {
         })
     }
 
+    // Slices are found by their status tables, deleted flags are lost
+    fn restore_index(
+        db: &RocksDb,
+        storage: &PackageIndexDb,
+        package_type: PackageType
+    ) -> Result<Vec<(u32, PackageIndexEntry)>> {
+        let prefix = if package_type == PackageType::KeyBlocks {
+            "status_key_db_"
+        } else {
+            "status_db_"
+        };
+        let mut index_pairs = Vec::new();
+        for family in db.column_families()? {
+            if let Some(Ok(key)) = family.strip_prefix(prefix).map(|id| id.parse::<u32>()) {
+                index_pairs.push((key, PackageIndexEntry::new()));
+            }
+        }
+        log::warn!(
+            target: "storage",
+            "Index of archive slices {} is restored, {} slices",
+            storage.column_name(), index_pairs.len()
+        );
+        if !db.is_read_only() {
+            for (key, value) in &index_pairs {
+                storage.put_value(&(*key).into(), value)?;
+            }
+            storage.set_rebuilt();
+        }
+        Ok(index_pairs)
+    }
+
     pub async fn put(
         &self, 
         mc_seq_no: u32, 
@@ -201,7 +239,7 @@ impl FileMap {
         marked_packages
     }
 
-    pub async fn gc(&self, last_unneeded_key_block: &BlockIdExt) -> Result<()> {
+    pub async fn gc(&self, last_unneeded_key_block: &BlockIdExt, counters: &GcCounters) -> Result<()> {
         log::info!(
             target: "storage",
             "Archives GC started, last_unneeded_key_block: {}",
@@ -213,6 +251,7 @@ impl FileMap {
             "Archives GC: found {} unneeded slices",
             slices.len()
         );
+        counters.add_scanned(slices.len() as u64);
 
         'a: while let Some(key) = slices.pop() {
             let mut guard = self.elements.write().await;
@@ -222,15 +261,19 @@ impl FileMap {
                     position = Some(p);
                     match Arc::get_mut(&mut entry.value) {
                         Some(file_description) => {
-                            if let Err(e) = file_description.destroy().await {
-                                log::error!(target: "storage", "Archives GC: can't destroy archive slice {}: {:?}", key, e);
-                                continue 'a;
-                            } else {
-                                if let Err(e) = self.storage.delete(&key.into()) {
-                                    log::error!(target: "storage", "Archives GC: can't delete {} from index: {:?}", key, e);
+                            match file_description.destroy().await {
+                                Err(e) => {
+                                    log::error!(target: "storage", "Archives GC: can't destroy archive slice {}: {:?}", key, e);
                                     continue 'a;
                                 }
-                                log::info!(target: "storage", "Archives GC: collected {}.", key);
+                                Ok(size) => {
+                                    counters.add_removed(size);
+                                    if let Err(e) = self.storage.delete(&key.into()) {
+                                        log::error!(target: "storage", "Archives GC: can't delete {} from index: {:?}", key, e);
+                                        continue 'a;
+                                    }
+                                    log::info!(target: "storage", "Archives GC: collected {}.", key);
+                                }
                             }
                         },
                         None => { 
@@ -250,6 +293,46 @@ impl FileMap {
         Ok(())
     }
 
+    // Packages of blocks ordered from the oldest one
+    pub async fn packages(&self) -> Vec<ArchivePackageInfo> {
+        let elements = self.elements.read().await;
+        let mut packages = Vec::new();
+        for (i, entry) in elements.iter().enumerate() {
+            let archive_slice = &entry.value.archive_slice;
+            if archive_slice.package_type() != PackageType::Blocks {
+                continue
+            }
+            let end_seq_no = match elements.get(i + 1) {
+                Some(next) => next.value.archive_slice.archive_id(),
+                None => archive_slice.archive_id() + ARCHIVE_SLICE_SIZE
+            };
+            packages.push(ArchivePackageInfo {
+                archive_id: entry.key,
+                end_seq_no,
+                size: archive_slice.size().await
+            });
+        }
+        packages
+    }
+
+    // Removes the package with its index entry, returns its size and root hashes of its blocks
+    pub async fn remove_package(&self, key: u32) -> Result<(u64, HashSet<UInt256>)> {
+        let root_hashes = match self.get(key).await {
+            Some(file_description) => file_description.archive_slice.block_root_hashes().await?,
+            None => fail!("Slice {} not found", key)
+        };
+        let mut guard = self.elements.write().await;
+        let position = guard.iter().position(|entry| entry.key == key)
+            .ok_or_else(|| error!("Slice {} not found", key))?;
+        let file_description = Arc::get_mut(&mut guard[position].value)
+            .ok_or_else(|| error!("Slice {} is in use", key))?;
+        let size = file_description.destroy().await?;
+        self.storage.delete(&key.into())?;
+        guard.remove(position);
+        log::info!(target: "storage", "Archives GC: removed slice {}, {} bytes", key, size);
+        Ok((size, root_hashes))
+    }
+
     pub async fn get(&self, mc_seq_no: u32) -> Option<Arc<FileDescription>> {
         let guard = self.elements.read().await;
         log::trace!(target: "storage", "Searching for file description (elements count = {})", guard.len());
@@ -323,6 +406,7 @@ impl FileMaps {
         db: Arc<RocksDb>,
         db_root_path: &Arc<PathBuf>,
         last_unneeded_key_block: u32,
+        mapped_packages: &Arc<MappedPackages>,
         #[cfg(feature = "telemetry")]
         telemetry: &Arc<StorageTelemetry>,
         allocated: &Arc<StorageAlloc>
@@ -334,6 +418,7 @@ impl FileMaps {
                 "files",
                 PackageType::Blocks,
                 last_unneeded_key_block,
+                mapped_packages,
                 #[cfg(feature = "telemetry")]
                 telemetry,
                 allocated
@@ -344,6 +429,7 @@ impl FileMaps {
                 "key_files",
                 PackageType::KeyBlocks,
                 0,
+                mapped_packages,
                 #[cfg(feature = "telemetry")]
                 telemetry,
                 allocated
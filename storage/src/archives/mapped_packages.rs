@@ -0,0 +1,123 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::archives::package_id::{PackageId, PackageType};
+use memmap2::Mmap;
+use std::{num::NonZeroUsize, ops::{Deref, Range}, path::Path, sync::Arc};
+use ever_block::{error, Result};
+
+#[cfg(test)]
+#[path = "tests/test_mapped_packages.rs"]
+mod tests;
+
+pub const MAX_MAPPED_PACKAGES: usize = 64;
+
+// Part of mapped package file. The mapping is alive while the slice is held,
+// even if the package is unmapped from the pool or removed meanwhile
+pub struct MappedSlice {
+    map: Arc<Mmap>,
+    range: Range<usize>
+}
+
+impl Deref for MappedSlice {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.map[self.range.clone()]
+    }
+}
+
+// Data of archive slice served to peers
+pub enum ArchiveSliceData {
+    // Package is not written any more, data is taken from its mapping without copying
+    Mapped(MappedSlice),
+    // Package is still written, data is read from the file
+    Read(Vec<u8>)
+}
+
+impl ArchiveSliceData {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Self::Mapped(_))
+    }
+
+    // Copies the mapped data, read data is returned as is
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            Self::Mapped(slice) => slice.to_vec(),
+            Self::Read(data) => data
+        }
+    }
+}
+
+impl Deref for ArchiveSliceData {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(slice) => slice,
+            Self::Read(data) => data
+        }
+    }
+}
+
+// Mappings of finalized package files, the least recently used one is unmapped when the
+// limit is reached. Unmapping only drops the pool's reference, so slices being sent are valid.
+pub struct MappedPackages {
+    maps: parking_lot::Mutex<lru::LruCache<(PackageType, u32), Arc<Mmap>>>
+}
+
+impl MappedPackages {
+
+    pub fn new(max_mapped: usize) -> Self {
+        let max_mapped = NonZeroUsize::new(max_mapped).unwrap_or(NonZeroUsize::MIN);
+        Self { maps: parking_lot::Mutex::new(lru::LruCache::new(max_mapped)) }
+    }
+
+    // File must not be changed while it is mapped. Mapping of other than `file_size`
+    // length is outdated, so the file is mapped again
+    pub fn slice(
+        &self,
+        package_id: &PackageId,
+        path: &Path,
+        file_size: u64,
+        offset: u64,
+        limit: u32
+    ) -> Result<MappedSlice> {
+        let key = (package_id.package_type(), package_id.id());
+        let map = {
+            let mut maps = self.maps.lock();
+            match maps.get(&key) {
+                Some(map) if map.len() as u64 == file_size => map.clone(),
+                _ => {
+                    let file = std::fs::File::open(path)
+                        .map_err(|e| error!("Cannot open package {}: {}", path.display(), e))?;
+                    // Safe while the file is not truncated: finalized packages are only removed
+                    let map = Arc::new(unsafe { Mmap::map(&file) }
+                        .map_err(|e| error!("Cannot map package {}: {}", path.display(), e))?);
+                    log::debug!(target: "storage", "Mapped package {}", path.display());
+                    maps.put(key, map.clone());
+                    map
+                }
+            }
+        };
+        let start = (offset.min(map.len() as u64)) as usize;
+        let end = start.saturating_add(limit as usize).min(map.len());
+        Ok(MappedSlice { map, range: start..end })
+    }
+
+    pub fn unmap(&self, package_id: &PackageId) {
+        self.maps.lock().pop(&(package_id.package_type(), package_id.id()));
+    }
+
+    pub fn mapped_count(&self) -> usize {
+        self.maps.lock().len()
+    }
+}
@@ -19,6 +19,8 @@ pub mod archive_manager;
 pub mod package;
 pub mod package_entry_id;
 pub mod package_entry;
+pub mod package_entries_index;
+pub mod mapped_packages;
 
 mod package_status_db;
 mod package_status_key;
@@ -11,9 +11,11 @@
 * limitations under the License.
 */
 
-use crate::archives::package_entry::{PackageEntry, PKG_ENTRY_HEADER_SIZE};
+use crate::archives::{
+    package_entries_index::PackageEntryPlace, package_entry::{PackageEntry, PKG_ENTRY_HEADER_SIZE}
+};
 use std::{io::SeekFrom, path::{Path, PathBuf}, sync::atomic::{AtomicU64, Ordering}};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use ever_block::{error, fail, Result};
 
 #[cfg(test)]
@@ -124,6 +126,28 @@ impl Package {
             .ok_or_else(|| error!("Package::read_entry: Unexpected end of file"))
     }
 
+    // Reads entry with known size by the single read
+    pub async fn read_entry_at(&self, place: &PackageEntryPlace) -> Result<PackageEntry> {
+        if self.size() < place.offset + place.size {
+            fail!(
+                "Unexpected end of file while reading archives entry with offset: {}, size: {}",
+                place.offset, place.size
+            )
+        }
+
+        let mut file = self.open_file().await?;
+        file.seek(SeekFrom::Start(PKG_HEADER_SIZE as u64 + place.offset)).await?;
+        let mut buf = vec![0; place.size as usize];
+        file.read_exact(&mut buf).await?;
+
+        let entry = PackageEntry::read_from(&mut &buf[..]).await?
+            .ok_or_else(|| error!("Package::read_entry_at: Unexpected end of entry"))?;
+        if PKG_ENTRY_HEADER_SIZE + entry.filename().len() + entry.data().len() != buf.len() {
+            fail!("Entry with offset {} has size other than {}", place.offset, place.size)
+        }
+        Ok(entry)
+    }
+
     pub async fn append_entry(
         &self,
         entry: &PackageEntry,
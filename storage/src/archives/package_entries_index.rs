@@ -0,0 +1,238 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    db_impl_serializable,
+    archives::{
+        package::{read_package_from, PKG_HEADER_SIZE},
+        package_entry::{PackageEntryHeader, PKG_ENTRY_HEADER_SIZE},
+        package_entry_id::PackageEntryId
+    },
+    db::traits::{KvcWriteable, U32Key}, traits::Serializable
+};
+use std::{
+    borrow::Borrow, collections::HashMap, hash::Hash, io::{Read, SeekFrom, Write}, path::Path
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use ever_block::{error, fail, BlockIdExt, ByteOrderRead, Result, UInt256};
+
+#[cfg(test)]
+#[path = "tests/test_package_entries_index.rs"]
+mod tests;
+
+// Kinds of indexed entries, the entries of one block differ by kind only
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum PackageEntryKind {
+    Block = 1,
+    Proof = 2,
+    ProofLink = 3,
+}
+
+impl PackageEntryKind {
+    pub fn with_entry_id<B, U256, PK>(
+        entry_id: &PackageEntryId<B, U256, PK>
+    ) -> Option<(Self, &UInt256)>
+    where
+        B: Borrow<BlockIdExt> + Hash,
+        U256: Borrow<UInt256> + Hash,
+        PK: Borrow<UInt256> + Hash
+    {
+        match entry_id {
+            PackageEntryId::Block(id) => Some((Self::Block, id.borrow().root_hash())),
+            PackageEntryId::Proof(id) => Some((Self::Proof, id.borrow().root_hash())),
+            PackageEntryId::ProofLink(id) => Some((Self::ProofLink, id.borrow().root_hash())),
+            _ => None
+        }
+    }
+
+    fn with_byte(byte: u8) -> Result<Self> {
+        match byte {
+            1 => Ok(Self::Block),
+            2 => Ok(Self::Proof),
+            3 => Ok(Self::ProofLink),
+            _ => fail!("Unknown package entry kind {}", byte)
+        }
+    }
+}
+
+// Place of entry in package file. Offset is counted from the end of package header
+// as in offsets db, size includes entry header and filename.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackageEntryPlace {
+    pub offset: u64,
+    pub size: u64,
+}
+
+// Index of package entries by block root hashes. It is built for packages not written
+// any more and is valid only while package has the same size as indexed one.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PackageEntriesIndex {
+    entries: HashMap<(PackageEntryKind, UInt256), PackageEntryPlace>,
+    package_size: u64,
+}
+
+impl PackageEntriesIndex {
+
+    // Builds index by the single pass through package file
+    pub async fn build(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path).await
+            .map_err(|e| error!("Can't open package {} to index: {}", path.display(), e))?;
+        let mut reader = read_package_from(file).await?;
+        let mut index = Self::default();
+        while let Some(entry) = reader.next().await? {
+            let size = (PKG_ENTRY_HEADER_SIZE + entry.filename().len() + entry.data().len()) as u64;
+            // Entries of other kinds (some of them can't be even parsed) are not indexed
+            if let Ok(entry_id) = PackageEntryId::from_filename(entry.filename()) {
+                if let Some((kind, root_hash)) = PackageEntryKind::with_entry_id(&entry_id) {
+                    // The later entry wins like in offsets db
+                    let place = PackageEntryPlace { offset: index.package_size, size };
+                    index.entries.insert((kind, root_hash.clone()), place);
+                }
+            }
+            index.package_size += size;
+        }
+        Ok(index)
+    }
+
+    pub fn get<B, U256, PK>(&self, entry_id: &PackageEntryId<B, U256, PK>) -> Option<PackageEntryPlace>
+    where
+        B: Borrow<BlockIdExt> + Hash,
+        U256: Borrow<UInt256> + Hash,
+        PK: Borrow<UInt256> + Hash
+    {
+        let (kind, root_hash) = PackageEntryKind::with_entry_id(entry_id)?;
+        self.entries.get(&(kind, root_hash.clone())).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Root hashes of indexed blocks, a hash may repeat for entries of other kinds
+    pub fn root_hashes(&self) -> impl Iterator<Item = &UInt256> {
+        self.entries.keys().map(|(_, root_hash)| root_hash)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Size of package data (without package header) at the moment of indexing
+    pub fn package_size(&self) -> u64 {
+        self.package_size
+    }
+}
+
+impl Serializable for PackageEntriesIndex {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.package_size.to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        for ((kind, root_hash), place) in self.entries.iter() {
+            writer.write_all(&[*kind as u8])?;
+            writer.write_all(root_hash.as_slice())?;
+            writer.write_all(&place.offset.to_le_bytes())?;
+            writer.write_all(&place.size.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        let package_size = reader.read_le_u64()?;
+        let count = reader.read_le_u32()?;
+        let mut entries = HashMap::with_capacity(count as usize);
+        for _ in 0..count {
+            let kind = PackageEntryKind::with_byte(reader.read_byte()?)?;
+            let root_hash = UInt256::from(reader.read_u256()?);
+            let offset = reader.read_le_u64()?;
+            let size = reader.read_le_u64()?;
+            entries.insert((kind, root_hash), PackageEntryPlace { offset, size });
+        }
+        Ok(Self { entries, package_size })
+    }
+}
+
+// Indexes of packages of archive slice by package index in slice
+db_impl_serializable!(PackageEntriesIndexDb, KvcWriteable, U32Key, PackageEntriesIndex);
+
+// Checks index against the package file: every indexed place must start with entry header
+// of the indexed size and of the indexed block. Returns count of checked entries.
+pub async fn verify_package_index(path: impl AsRef<Path>, index: &PackageEntriesIndex) -> Result<usize> {
+    let path = path.as_ref();
+    let mut file = tokio::fs::File::open(path).await
+        .map_err(|e| error!("Can't open package {} to verify index: {}", path.display(), e))?;
+    let data_size = file.metadata().await?.len().saturating_sub(PKG_HEADER_SIZE as u64);
+    if data_size != index.package_size {
+        fail!(
+            "Package {} has size {} while indexed size is {}",
+            path.display(), data_size, index.package_size
+        )
+    }
+    let mut places = index.entries.iter().collect::<Vec<_>>();
+    places.sort_by_key(|(_, place)| place.offset);
+    for ((kind, root_hash), place) in places {
+        if place.offset + place.size > data_size {
+            fail!("Indexed entry {:x} at {} is out of package {}", root_hash, place.offset, path.display())
+        }
+        file.seek(SeekFrom::Start(PKG_HEADER_SIZE as u64 + place.offset)).await?;
+        let mut buf = [0; PKG_ENTRY_HEADER_SIZE];
+        file.read_exact(&mut buf).await?;
+        let header = PackageEntryHeader::from_slice(&buf).map_err(
+            |e| error!("Bad entry header at {} in package {}: {}", place.offset, path.display(), e)
+        )?;
+        if header.calc_entry_size() != place.size {
+            fail!(
+                "Entry at {} in package {} has size {} while indexed size is {}",
+                place.offset, path.display(), header.calc_entry_size(), place.size
+            )
+        }
+        let mut filename = vec![0; header.filename_size() as usize];
+        file.read_exact(&mut filename).await?;
+        let entry_id = PackageEntryId::from_filename(&String::from_utf8(filename)?)?;
+        if PackageEntryKind::with_entry_id(&entry_id) != Some((*kind, root_hash)) {
+            fail!(
+                "Entry at {} in package {} is {} while indexed one is {:?} {:x}",
+                place.offset, path.display(), entry_id, kind, root_hash
+            )
+        }
+    }
+    Ok(index.len())
+}
+
+// Reads filenames and sizes (including entry header and filename) of package entries.
+// Data of entries is skipped, a partially written entry at the end is not counted.
+pub async fn read_package_entry_sizes(path: impl AsRef<Path>) -> Result<Vec<(String, u64)>> {
+    let path = path.as_ref();
+    let mut file = tokio::fs::File::open(path).await
+        .map_err(|e| error!("Can't open package {} to read entries: {}", path.display(), e))?;
+    let package_size = file.metadata().await?.len();
+    let mut offset = PKG_HEADER_SIZE as u64;
+    let mut entries = Vec::new();
+    while offset + PKG_ENTRY_HEADER_SIZE as u64 <= package_size {
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut buf = [0; PKG_ENTRY_HEADER_SIZE];
+        file.read_exact(&mut buf).await?;
+        let header = PackageEntryHeader::from_slice(&buf).map_err(
+            |e| error!("Bad entry header at {} in package {}: {}", offset, path.display(), e)
+        )?;
+        let size = header.calc_entry_size();
+        if offset + size > package_size {
+            break
+        }
+        let mut filename = vec![0; header.filename_size() as usize];
+        file.read_exact(&mut filename).await?;
+        entries.push((String::from_utf8(filename)?, size));
+        offset += size;
+    }
+    Ok(entries)
+}
@@ -11,7 +11,9 @@
 * limitations under the License.
 */
 
-use crate::{db_impl_cbor, db::traits::{KvcWriteable, U32Key}};
+use crate::{
+    db_impl_cbor, db::{degradation::RebuildStrategy, traits::{KvcWriteable, U32Key}}
+};
 use std::convert::TryInto;
 use ever_block::Result;
 
@@ -39,7 +41,10 @@ impl PackageIndexEntry {
     }
 }
 
-db_impl_cbor!(PackageIndexDb, KvcWriteable, U32Key, PackageIndexEntry);
+// Corrupted index is restored from the tables of archive slices
+db_impl_cbor!(
+    PackageIndexDb, KvcWriteable, U32Key, PackageIndexEntry, rebuild: RebuildStrategy::Restore
+);
 
 impl PackageIndexDb {
     pub fn for_each_deserialized(&self, mut predicate: impl FnMut(u32, PackageIndexEntry) -> Result<bool>) -> Result<bool> {
@@ -11,7 +11,10 @@
 * limitations under the License.
 */
 
-use crate::{StorageAlloc, archives::{package::Package, package_id::PackageId}};
+use crate::{
+    StorageAlloc,
+    archives::{package::Package, package_entries_index::PackageEntriesIndex, package_id::PackageId}
+};
 #[cfg(feature = "telemetry")]
 use crate::StorageTelemetry;
 use adnl::{declare_counted, common::{CountedObject, Counter}};
@@ -26,7 +29,9 @@ declare_counted!(
         package_id: PackageId,
         package: Package,
         idx: u32,
-        version: u32
+        version: u32,
+        // Cached index of entries, the lock serializes building of it
+        entries_index: tokio::sync::Mutex<Option<Arc<PackageEntriesIndex>>>
     }
 );
 
@@ -46,6 +51,7 @@ impl PackageInfo {
             package, 
             idx, 
             version,
+            entries_index: tokio::sync::Mutex::new(None),
             counter: allocated.packages.clone().into() 
         };
         #[cfg(feature = "telemetry")]
@@ -55,7 +61,6 @@ impl PackageInfo {
         ret
     }
 
-    #[allow(dead_code)]
     pub const fn package_id(&self) -> &PackageId {
         &self.package_id
     }
@@ -76,6 +81,10 @@ impl PackageInfo {
         self.version
     }
 
+    pub fn entries_index(&self) -> &tokio::sync::Mutex<Option<Arc<PackageEntriesIndex>>> {
+        &self.entries_index
+    }
+
     pub async fn destroy(&mut self) -> Result<()> {
         self.package.remove().await
     }
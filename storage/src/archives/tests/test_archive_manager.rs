@@ -13,10 +13,11 @@
 
 use crate::{
     archives::{
-        ARCHIVE_PACKAGE_SIZE, archive_manager::ArchiveManager, 
+        ARCHIVE_PACKAGE_SIZE, ARCHIVE_SLICE_SIZE, archive_manager::ArchiveManager, 
         package_entry_id::{GetFileNameShort, PackageEntryId},
     },
     block_handle_db::{FLAG_KEY_BLOCK, BlockHandleStorage}, db::rocksdb::RocksDb,
+    fsync::{FsyncControl, FsyncPolicy},
     tests::utils::create_block_handle_storage, types::BlockMeta, StorageAlloc,
 };
 #[cfg(feature = "telemetry")]
@@ -48,6 +49,7 @@ async fn test_scenario() -> Result<()> {
         db.clone(),
         Arc::new(db_root),
         0,
+        FsyncControl::new("block data", FsyncPolicy::Never),
         #[cfg(feature = "telemetry")]
         Arc::new(StorageTelemetry::default()),
         Arc::new(StorageAlloc::default()),
@@ -135,6 +137,7 @@ async fn test_scenario_keyblocks_10m() -> Result<()> {
         db.clone(),
         Arc::new(path),
         0,
+        FsyncControl::new("block data", FsyncPolicy::Never),
         #[cfg(feature = "telemetry")]
         Arc::new(StorageTelemetry::default()),
         Arc::new(StorageAlloc::default()),
@@ -178,7 +181,7 @@ async fn test_scenario_keyblocks_10m() -> Result<()> {
         assert!(handle.is_key_block()?);
         assert!(handle.has_proof());
         assert!(handle.is_archived());
-        block_handle_storage.save_handle(&handle, None)?;
+        block_handle_storage.mark_dirty(&handle, None)?;
 
         tokio::time::sleep(std::time::Duration::from_millis(1)).await;
 
@@ -224,6 +227,7 @@ async fn test_clean_unapplied_files() {
         db.clone(),
         Arc::new(path),
         0,
+        FsyncControl::new("block data", FsyncPolicy::Never),
         #[cfg(feature = "telemetry")]
         Arc::new(StorageTelemetry::default()),
         Arc::new(StorageAlloc::default()),
@@ -277,6 +281,7 @@ impl TestArchiveManager {
             db.clone(),
             Arc::new(path),
             0,
+            FsyncControl::new("block data", FsyncPolicy::Never),
             #[cfg(feature = "telemetry")]
             Arc::new(StorageTelemetry::default()),
             Arc::new(StorageAlloc::default()),
@@ -318,7 +323,7 @@ impl TestArchiveManager {
             self.archive_manager.move_to_archive(&handle, || Ok(())).await.unwrap();
             handle.set_archived();
             assert!(handle.is_archived());
-            self.block_handle_storage.save_handle(&handle, None).unwrap()
+            self.block_handle_storage.mark_dirty(&handle, None).unwrap()
         }
     }
 
@@ -375,3 +380,42 @@ async fn test_archive_truncate() {
 
 }
 
+
+#[tokio::test]
+async fn test_remove_archive_package() {
+
+    const DB_NAME: &str = "node_db_remove_package";
+
+    let manager = TestArchiveManager::new(DB_PATH, DB_NAME).await.unwrap();
+    // Key blocks start new slices
+    manager.add_entries(1..30, 0, &[10, 20]).await;
+
+    let packages = manager.archive_manager.archive_packages().await;
+    let bounds = packages.iter().map(|p| (p.archive_id, p.end_seq_no)).collect::<Vec<_>>();
+    assert_eq!(bounds, [(0, 10), (10, 20), (20, 20 + ARCHIVE_SLICE_SIZE)]);
+    assert!(packages.iter().all(|p| p.size > 0));
+
+    let (size, root_hashes) = manager.archive_manager.remove_archive_package(0).await.unwrap();
+    assert_eq!(size, packages[0].size);
+    assert_eq!(root_hashes.len(), 9);
+    for mc_seq_no in 1..10 {
+        let block_id = TestArchiveManager::master_block_id(mc_seq_no, 0);
+        assert!(root_hashes.contains(block_id.root_hash()));
+        let handle = manager.block_handle_storage.load_handle_by_id(&block_id).unwrap().unwrap();
+        let entry_id = PackageEntryId::<&BlockIdExt, &UInt256, &UInt256>::Block(&block_id);
+        manager.archive_manager.get_file(&handle, &entry_id).await.expect_err("block should not be read");
+    }
+    let packages = manager.archive_manager.archive_packages().await;
+    assert_eq!(packages.iter().map(|p| p.archive_id).collect::<Vec<_>>(), [10, 20]);
+    manager.archive_manager.remove_archive_package(0).await.expect_err("package is removed already");
+
+    // Blocks of other packages are there
+    let block_id = TestArchiveManager::master_block_id(15, 0);
+    let handle = manager.block_handle_storage.load_handle_by_id(&block_id).unwrap().unwrap();
+    let entry_id = PackageEntryId::<&BlockIdExt, &UInt256, &UInt256>::Block(&block_id);
+    manager.archive_manager.get_file(&handle, &entry_id).await.unwrap();
+
+    drop(manager);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap()
+
+}
@@ -13,11 +13,14 @@
 
 use crate::{
     archives::{
-        archive_slice::ArchiveSlice, package::PKG_HEADER_SIZE,
-        package_entry::PKG_ENTRY_HEADER_SIZE, package_entry_id::{GetFileName, PackageEntryId},
+        archive_slice::ArchiveSlice, mapped_packages::MappedPackages, package::PKG_HEADER_SIZE,
+        package_entries_index::verify_package_index,
+        package_entry::{PackageEntry, PKG_ENTRY_HEADER_SIZE},
+        package_entry_id::{GetFileName, PackageEntryId},
         package_id::PackageType,
     },
-    block_handle_db::{BlockHandleStorage, FLAG_KEY_BLOCK}, db::rocksdb::RocksDb,
+    block_handle_db::{BlockHandle, BlockHandleStorage, FLAG_KEY_BLOCK}, db::rocksdb::RocksDb,
+    fsync::{FsyncControl, FsyncPolicy},
     tests::utils::create_block_handle_storage, types::BlockMeta,
     StorageAlloc,
 };
@@ -38,7 +41,9 @@ const ARCHIVE_00200_GOLD_PATH: &str = "src/archives/tests/testdata/archive.00200
 
 struct TestContext {
     archive_slice: ArchiveSlice, 
-    block_handle_storage: BlockHandleStorage
+    mapped_packages: Arc<MappedPackages>,
+    block_handle_storage: BlockHandleStorage,
+    fsync: Arc<FsyncControl>
 }
 
 async fn prepare_test(
@@ -48,11 +53,13 @@ async fn prepare_test(
     let db_root = Path::new(DB_PATH).join(name);
     let _ = std::fs::remove_dir_all(&db_root);
     let db = RocksDb::with_path(DB_PATH, name)?;
+    let mapped_packages = Arc::new(MappedPackages::new(2));
     let archive_slice = ArchiveSlice::new_empty(
         db.clone(),
         Arc::new(db_root),
         0,
         package_type,
+        mapped_packages.clone(),
         #[cfg(feature = "telemetry")]
         Arc::new(StorageTelemetry::default()),
         Arc::new(StorageAlloc::default()),
@@ -60,7 +67,9 @@ async fn prepare_test(
     let (block_handle_storage, _) = create_block_handle_storage(None);
     let test_context = TestContext {
         archive_slice, 
-        block_handle_storage
+        mapped_packages,
+        block_handle_storage,
+        fsync: FsyncControl::new("block data", FsyncPolicy::Never)
     };
     Ok((db, test_context))
 }
@@ -90,7 +99,7 @@ async fn test_scenario_gold() -> Result<()> {
                               
         // Populating...
         let entry_id = PackageEntryId::<BlockIdExt, UInt256, UInt256>::Empty;
-        test_context.archive_slice.add_file(None, &entry_id, vec![1, 2, 3]).await?;
+        test_context.archive_slice.add_file(None, &entry_id, vec![1, 2, 3], &test_context.fsync).await?;
 
         let data = vec![1, 2, 3, 4, 5];   
         for mc_seq_no in 0..250 {
@@ -107,7 +116,7 @@ async fn test_scenario_gold() -> Result<()> {
                 || error!("Cannot create handle for block {}", block_id)
             )?;
             let entry_id = PackageEntryId::<_, UInt256, UInt256>::Proof(&block_id);
-            test_context.archive_slice.add_file(Some(&handle), &entry_id, data.clone()).await?;
+            test_context.archive_slice.add_file(Some(&handle), &entry_id, data.clone(), &test_context.fsync).await?;
             let file = test_context.archive_slice.get_file(
                 Some(&handle), &entry_id
             ).await?.ok_or_else(
@@ -140,11 +149,31 @@ async fn test_scenario_gold() -> Result<()> {
             (hdr + entry_id.filename().as_bytes().len()) as u64 + 1,
             2
         ).await?;
-        assert_eq!(read, vec![2, 3]);
+        assert_eq!(&read[..], &[2, 3]);
+        // Package is not written any more
+        assert!(read.is_mapped());
+
+        // The last package is still written, it is read from file
+        let gold = tokio::fs::read(ARCHIVE_00200_GOLD_PATH).await.unwrap();
+        let read = test_context.archive_slice.get_slice(200 << 32, 10, 1000).await?;
+        assert!(!read.is_mapped());
+        assert_eq!(&read[..], &gold[10..gold.len().min(1010)]);
+        assert_eq!(test_context.mapped_packages.mapped_count(), 1);
+
+        // Reading beyond the end gives empty slice
+        let gold = tokio::fs::read(ARCHIVE_00100_GOLD_PATH).await.unwrap();
+        let read = test_context.archive_slice.get_slice(100 << 32, gold.len() as u64 + 10, 10).await?;
+        assert!(read.is_mapped() && read.is_empty());
+        let in_flight = test_context.archive_slice.get_slice(100 << 32, 0, 100).await?;
+        assert_eq!(test_context.mapped_packages.mapped_count(), 2);
 
         // Deleting...
         
         test_context.archive_slice.destroy().await?;
+        assert_eq!(test_context.mapped_packages.mapped_count(), 0);
+        // Slice being sent is still valid after its package is removed
+        assert_eq!(&in_flight[..], &gold[..100]);
+        drop(in_flight);
         assert!(!archive_path.join("archive.00000.pack").exists());
         assert!(!archive_path.join("archive.00100.pack").exists());
         assert!(!archive_path.join("archive.00200.pack").exists());
@@ -181,7 +210,7 @@ async fn test_key_blocks_slice() -> Result<()> {
                 || error!("Cannot create handle for block {}", block_id)
             )?;
             let entry_id = PackageEntryId::<_, UInt256, UInt256>::Block(&block_id);
-            test_context.archive_slice.add_file(Some(&handle), &entry_id, data.clone()).await?;
+            test_context.archive_slice.add_file(Some(&handle), &entry_id, data.clone(), &test_context.fsync).await?;
             let file = test_context.archive_slice.get_file(
                 Some(&handle), &entry_id
             ).await?.ok_or_else(
@@ -201,3 +230,91 @@ async fn test_key_blocks_slice() -> Result<()> {
     ).await 
 
 }
+
+async fn get_proof(slice: &ArchiveSlice, handle: &BlockHandle) -> Result<PackageEntry> {
+    let entry_id = PackageEntryId::<_, UInt256, UInt256>::Proof(handle.id());
+    let file = slice.get_file(Some(handle), &entry_id).await?.ok_or_else(
+        || error!("Cannot get file from archive for block {}", handle.id())
+    )?;
+    assert_eq!(file.filename(), &entry_id.filename());
+    Ok(file)
+}
+
+#[tokio::test]
+async fn test_package_entries_index_lazy() -> Result<()> {
+    const NAME: &str = "test_package_entries_index_lazy";
+    let (db, test_context) = prepare_test(NAME, PackageType::Blocks).await?;
+    let data = vec![1, 2, 3, 4, 5];
+    let mut handles = Vec::new();
+    for mc_seq_no in 0..250 {
+        let block_id = BlockIdExt::with_params(
+            ShardIdent::masterchain(), 
+            mc_seq_no, 
+            UInt256::rand(), 
+            UInt256::rand()
+        );
+        let meta = BlockMeta::with_data(0, 0, 0, 0, 0);
+        let handle = test_context.block_handle_storage.create_handle(
+            block_id.clone(), meta, None
+        )?.ok_or_else(
+            || error!("Cannot create handle for block {}", block_id)
+        )?;
+        let entry_id = PackageEntryId::<_, UInt256, UInt256>::Proof(&block_id);
+        test_context.archive_slice.add_file(Some(&handle), &entry_id, data.clone(), &test_context.fsync).await?;
+        handles.push(handle);
+    }
+
+    // Packages are indexed when next one is created, the last one is still written
+    let slice = &test_context.archive_slice;
+    assert_eq!(slice.entries_index_db.try_get_value(&0.into())?.map(|index| index.len()), Some(100));
+    assert_eq!(slice.entries_index_db.try_get_value(&1.into())?.map(|index| index.len()), Some(100));
+    assert!(slice.entries_index_db.try_get_value(&2.into())?.is_none());
+
+    // Indexes of old packages are absent
+    for idx in 0..2 {
+        slice.entries_index_db.delete(&idx.into())?;
+    }
+    let db_root = slice.db_root_path.clone();
+    drop(test_context.archive_slice);
+    let slice = ArchiveSlice::with_data(
+        db.clone(),
+        db_root,
+        0,
+        PackageType::Blocks,
+        true,
+        false,
+        test_context.mapped_packages.clone(),
+        #[cfg(feature = "telemetry")]
+        Arc::new(StorageTelemetry::default()),
+        Arc::new(StorageAlloc::default()),
+    ).await?;
+
+    // Package is indexed on first access only, and the index is persisted
+    assert_eq!(get_proof(&slice, &handles[150]).await?.data(), &data);
+    assert!(slice.entries_index_db.try_get_value(&0.into())?.is_none());
+    let index = slice.entries_index_db.get_value(&1.into())?;
+    assert_eq!(index.len(), 100);
+    // All packages of finalized slice are indexed
+    get_proof(&slice, &handles[220]).await?;
+    assert_eq!(slice.entries_index_db.get_value(&2.into())?.len(), 50);
+
+    // Indexed entry is read directly. The first entry of package is corrupted,
+    // so it would break any scan through the package
+    let path = slice.packages.read().await[1].package().path().to_path_buf();
+    let mut package = std::fs::read(&path)?;
+    package[PKG_HEADER_SIZE] ^= 0xFF;
+    std::fs::write(&path, &package)?;
+    assert!(verify_package_index(&path, &index).await.is_err());
+    let now = std::time::Instant::now();
+    for _ in 0..10 {
+        for mc_seq_no in 101..200 {
+            assert_eq!(get_proof(&slice, &handles[mc_seq_no]).await?.data(), &data);
+        }
+    }
+    println!("990 indexed reads took {} ms", now.elapsed().as_millis());
+    assert_eq!(slice.entries_index_db.get_value(&1.into())?, index);
+
+    drop(slice);
+    destroy_db(db, NAME).await;
+    Ok(())
+}
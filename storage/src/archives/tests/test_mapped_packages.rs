@@ -0,0 +1,92 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use std::path::PathBuf;
+
+const DB_PATH: &str = "../target/test/mapped_packages";
+
+fn write_package(name: &str, id: u32, size: usize) -> (PackageId, PathBuf, Vec<u8>) {
+    let dir = Path::new(DB_PATH).join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("archive.{:05}.pack", id));
+    let data = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    std::fs::write(&path, &data).unwrap();
+    (PackageId::for_block(id), path, data)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mapped_packages_concurrent_reads() {
+    let (id, path, data) = write_package("concurrent", 0, 100_000);
+    let mapped = Arc::new(MappedPackages::new(4));
+    let mut tasks = Vec::new();
+    for i in 0..16u64 {
+        let mapped = mapped.clone();
+        let (id, path) = (id.clone(), path.clone());
+        tasks.push(tokio::spawn(async move {
+            let mut read = Vec::new();
+            let mut offset = i * 1000;
+            while offset < 100_000 {
+                let slice = mapped.slice(&id, &path, 100_000, offset, 4096).unwrap();
+                read.extend_from_slice(&slice);
+                offset += slice.len() as u64;
+            }
+            (i * 1000, read)
+        }));
+    }
+    for task in tasks {
+        let (offset, read) = task.await.unwrap();
+        assert_eq!(&read[..], &data[offset as usize..]);
+    }
+    // All readers share the one mapping
+    assert_eq!(mapped.mapped_count(), 1);
+}
+
+#[test]
+fn test_mapped_packages_limit() {
+    let mapped = MappedPackages::new(2);
+    let packages = (0..3).map(|i| write_package("limit", i * 100, 1000)).collect::<Vec<_>>();
+    let (id, path, data) = &packages[0];
+    let held = mapped.slice(id, path, 1000, 900, 200).unwrap();
+    assert_eq!(&held[..], &data[900..]);
+    for (id, path, _) in &packages[1..] {
+        mapped.slice(id, path, 1000, 0, 10).unwrap();
+    }
+    // The least recently used package is unmapped, its slice is valid
+    assert_eq!(mapped.mapped_count(), 2);
+    assert_eq!(&held[..], &data[900..]);
+
+    // Package of other size is mapped again
+    let (id, path, _) = &packages[1];
+    std::fs::write(path, vec![7; 1500]).unwrap();
+    let slice = mapped.slice(id, path, 1500, 1400, 200).unwrap();
+    assert_eq!(&slice[..], &[7; 100]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mapped_packages_removed_while_read() {
+    let (id, path, data) = write_package("removed", 0, 10_000);
+    let mapped = Arc::new(MappedPackages::new(4));
+    let slice = mapped.slice(&id, &path, 10_000, 0, 10_000).unwrap();
+
+    // Package is removed by GC while its slice is being sent
+    let send = tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        slice.to_vec()
+    });
+    mapped.unmap(&id);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(mapped.mapped_count(), 0);
+    assert_eq!(send.await.unwrap(), data);
+    assert!(mapped.slice(&id, &path, 10_000, 0, 10).is_err());
+}
@@ -0,0 +1,98 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::archives::{package::Package, package_entry_id::GetFileName};
+use ever_block::ShardIdent;
+
+const DB_PATH: &str = "../target/test";
+const ARCHIVE_00100_GOLD_PATH: &str = "src/archives/tests/testdata/archive.00100.pack.gold";
+
+fn gold_block_id(mc_seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(
+        ShardIdent::masterchain(),
+        mc_seq_no,
+        UInt256::with_array([mc_seq_no as u8; 32]),
+        UInt256::default()
+    )
+}
+
+#[tokio::test]
+async fn test_package_entries_index() -> Result<()> {
+    let index = PackageEntriesIndex::build(ARCHIVE_00100_GOLD_PATH).await?;
+    let file_size = std::fs::metadata(ARCHIVE_00100_GOLD_PATH)?.len();
+    assert_eq!(index.len(), 100);
+    assert_eq!(index.package_size(), file_size - PKG_HEADER_SIZE as u64);
+    assert_eq!(verify_package_index(ARCHIVE_00100_GOLD_PATH, &index).await?, 100);
+    assert_eq!(PackageEntriesIndex::from_slice(&index.to_vec()?)?, index);
+
+    let package = Package::open(ARCHIVE_00100_GOLD_PATH.into(), true, false).await?;
+    for mc_seq_no in 100..200 {
+        let id = gold_block_id(mc_seq_no);
+        let entry_id = PackageEntryId::<_, UInt256, UInt256>::Proof(&id);
+        let place = index.get(&entry_id).ok_or_else(|| error!("No entry for {}", id))?;
+        let entry = package.read_entry_at(&place).await?;
+        assert_eq!(entry.filename(), &entry_id.filename());
+        assert_eq!(entry.data(), &vec![1, 2, 3, 4, 5]);
+        assert!(index.get(&PackageEntryId::<_, UInt256, UInt256>::Block(&id)).is_none());
+    }
+    assert!(index.get(&PackageEntryId::<_, UInt256, UInt256>::Proof(&gold_block_id(200))).is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_verify_package_index() -> Result<()> {
+    let path = Path::new(DB_PATH).join("test_verify_package_index.pack");
+    std::fs::create_dir_all(DB_PATH)?;
+    std::fs::copy(ARCHIVE_00100_GOLD_PATH, &path)?;
+    let index = PackageEntriesIndex::build(&path).await?;
+    verify_package_index(&path, &index).await?;
+
+    // Offset pointing to the middle of entry
+    let mut shifted = PackageEntriesIndex::from_slice(&index.to_vec()?)?;
+    for place in shifted.entries.values_mut().take(1) {
+        place.offset += 1;
+    }
+    assert!(verify_package_index(&path, &shifted).await.is_err());
+
+    // Wrong size of entry
+    let mut resized = PackageEntriesIndex::from_slice(&index.to_vec()?)?;
+    for place in resized.entries.values_mut().take(1) {
+        place.size -= 1;
+    }
+    assert!(verify_package_index(&path, &resized).await.is_err());
+
+    // Another block at indexed place
+    let mut swapped = PackageEntriesIndex::from_slice(&index.to_vec()?)?;
+    let proof_150 = (PackageEntryKind::Proof, gold_block_id(150).root_hash().clone());
+    let proof_151 = (PackageEntryKind::Proof, gold_block_id(151).root_hash().clone());
+    let place_150 = swapped.entries[&proof_150];
+    let place_151 = swapped.entries.insert(proof_151, place_150).unwrap();
+    swapped.entries.insert(proof_150.clone(), place_151);
+    assert!(verify_package_index(&path, &swapped).await.is_err());
+
+    // Corrupted magic of entry header
+    let mut data = std::fs::read(&path)?;
+    data[PKG_HEADER_SIZE + index.entries[&proof_150].offset as usize] ^= 0xFF;
+    std::fs::write(&path, &data)?;
+    assert!(verify_package_index(&path, &index).await.is_err());
+
+    // Truncated package
+    data[PKG_HEADER_SIZE + index.entries[&proof_150].offset as usize] ^= 0xFF;
+    data.truncate(data.len() - 1);
+    std::fs::write(&path, &data)?;
+    assert!(verify_package_index(&path, &index).await.is_err());
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
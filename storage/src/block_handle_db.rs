@@ -12,7 +12,12 @@
 */
 
 use crate::{
-    TARGET, StorageAlloc, db_impl_serializable, db::traits::KvcWriteable, 
+    TARGET, GcCounters, StorageAlloc, db_impl_serializable, 
+    db::{degradation::RebuildStrategy, traits::{DbKey, KvcTransaction, KvcTransactional}},
+    error::StorageError,
+    instrumented_channel::{
+        instrumented_channel, ChannelLimits, OverflowPolicy, Receiver, Sender, SendError
+    },
     traits::Serializable, types::BlockMeta, db_impl_base
 };
 #[cfg(feature = "telemetry")]
@@ -25,9 +30,10 @@ use adnl::{
         CountedObject, Counter
     }
 };
-use std::{io::{Cursor, Write, Read}, sync::{Arc, Weak}};
-#[cfg(feature = "telemetry")]
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    collections::HashSet, io::{Cursor, Write, Read}, 
+    sync::{Arc, Weak, atomic::{AtomicBool, AtomicU64, Ordering}}, time::{SystemTime, UNIX_EPOCH}
+};
 use ever_block::{BlockIdExt, ShardIdent};
 use ever_block::{error, fail, Result, UInt256, ByteOrderRead};
 
@@ -39,26 +45,69 @@ const FLAG_DATA: u32                             = 0x00000001;
 const FLAG_PROOF: u32                            = 0x00000002;
 const FLAG_PROOF_LINK: u32                       = 0x00000004;
 //const FLAG_EXT_DB: u32                         = 0x00000008;
-const FLAG_STATE: u32                            = 0x00000010;
+pub(crate) const FLAG_STATE: u32                 = 0x00000010;
 const FLAG_PERSISTENT_STATE: u32                 = 0x00000020;
 const FLAG_NEXT_1: u32                           = 0x00000040;
 const FLAG_NEXT_2: u32                           = 0x00000080;
 const FLAG_PREV_1: u32                           = 0x00000100;
 const FLAG_PREV_2: u32                           = 0x00000200;
-const FLAG_APPLIED: u32                          = 0x00000400;
-pub(crate) const FLAG_KEY_BLOCK: u32             = 0x00000800;
+pub(crate) const FLAG_APPLIED: u32               = 0x00000400;
+pub const FLAG_KEY_BLOCK: u32                    = 0x00000800;
 const FLAG_MOVED_TO_ARCHIVE: u32                 = 0x00002000;
 pub(crate) const FLAG_IS_QUEUE_UPDATE: u32       = 0x00004000;
 pub(crate) const FLAG_IS_EMPTY_QUEUE_UPDATE: u32 = 0x00008000;
-const FLAG_STATE_SAVED: u32                      = 0x00010000;
+pub(crate) const FLAG_STATE_SAVED: u32           = 0x00010000;
 const FLAG_HAS_FULL_ID: u32                      = 0x00020000;
 pub(crate) const FLAG_IS_MESH: u32               = 0x00040000;
+// Two bits for block origin, see BlockOrigin
+const FLAG_ORIGIN_MASK: u32                      = 0x00300000;
+const FLAG_ORIGIN_SHIFT: u32                     = 20;
+const FLAG_HAS_NEXT_IDS: u32                     = 0x00400000;
+// Queue update matches out queue hashes declared in its block
+const FLAG_QUEUE_UPDATE_VERIFIED: u32            = 0x00800000;
 
 
 // not serializing flags (possible flags - 1, 2, 4, 8)
 const FLAG_ARCHIVING: u32 = 0x80000000;
+const FLAG_FILE_HASH_INDEXED: u32 = 0x40000000;
+// Handle is changed and waits for coalesced save
+const FLAG_DIRTY: u32 = 0x20000000;
+// Stored block file has been checked against file hash
+const FLAG_DATA_VERIFIED: u32 = 0x10000000;
+
+db_impl_base!(NodeStateDb, KvcTransactional, &'static str);
+
+// file hash -> root hash. Corrupted index is built again by scan of handles
+db_impl_base!(
+    FileHashIndexDb, KvcTransactional, UInt256,
+    rebuild: RebuildStrategy::ResetMarker(FILE_HASH_INDEX_COMPLETE)
+);
 
-db_impl_base!(NodeStateDb, KvcWriteable, &'static str);
+// Marker of fully built file hash index (regular keys are 32 bytes long)
+const FILE_HASH_INDEX_COMPLETE: &[u8] = b"FileHashIndexComplete";
+
+/// The way block data came to the node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockOrigin {
+    Unknown = 0,     // Not recorded (e.g. handles from older nodes)
+    Broadcast = 1,   // Received as block broadcast
+    Download = 2,    // Downloaded from neighbours or imported from archive
+    Synthesized = 3, // Produced or accepted by our own validator group
+}
+
+impl BlockOrigin {
+    fn from_flags(flags: u32) -> Self {
+        match (flags & FLAG_ORIGIN_MASK) >> FLAG_ORIGIN_SHIFT {
+            1 => BlockOrigin::Broadcast,
+            2 => BlockOrigin::Download,
+            3 => BlockOrigin::Synthesized,
+            _ => BlockOrigin::Unknown
+        }
+    }
+    fn to_flags(self) -> u32 {
+        ((self as u32) << FLAG_ORIGIN_SHIFT) & FLAG_ORIGIN_MASK
+    }
+}
 
 /// Meta information related to block
 #[derive(Debug)]
@@ -68,9 +117,9 @@ pub struct BlockHandle {
     block_file_lock: tokio::sync::RwLock<()>,
     proof_file_lock: tokio::sync::RwLock<()>,
     saving_state_lock: tokio::sync::Mutex<()>,
+    persistent_state_lock: Arc<tokio::sync::RwLock<()>>,
+    cache_key: UInt256,
     block_handle_cache: Arc<BlockHandleCache>,
-    #[cfg(feature = "telemetry")]
-    got_by_broadcast: AtomicBool,
 }
 
 impl BlockHandle {
@@ -82,15 +131,24 @@ impl BlockHandle {
 */
 
     fn with_values(id: BlockIdExt, meta: BlockMeta, block_handle_cache: Arc<BlockHandleCache>) -> Self {
+        let cache_key = handle_cache_key(id.root_hash(), &meta);
         Self {
             id,
             meta,
             block_file_lock: tokio::sync::RwLock::new(()),
             proof_file_lock: tokio::sync::RwLock::new(()),
             saving_state_lock: tokio::sync::Mutex::new(()),
+            persistent_state_lock: Arc::new(tokio::sync::RwLock::new(())),
+            cache_key,
             block_handle_cache,
-            #[cfg(feature = "telemetry")]
-            got_by_broadcast: AtomicBool::new(false),
+        }
+    }
+
+    // Key of the record in handle DB
+    fn db_key(&self) -> Vec<u8> {
+        match self.mesh_nw_id() {
+            Some(nw_id) => mesh_key(nw_id, self.id.root_hash()).to_vec(),
+            None => self.id.root_hash().as_slice().to_vec()
         }
     }
 
@@ -101,6 +159,28 @@ impl BlockHandle {
         writer.write_all(&id.shard().shard_prefix_with_tag().to_le_bytes())?;
         writer.write_all(&id.seq_no().to_le_bytes())?;
         writer.write_all(id.file_hash().as_slice())?;
+        if self.is_flag_set(FLAG_HAS_FULL_ID | FLAG_HAS_NEXT_IDS) {
+            let next_ids = [self.meta.next_id(0), self.meta.next_id(1)];
+            let mask = next_ids.iter().enumerate()
+                .fold(0u8, |mask, (i, id)| if id.is_some() { mask | (1 << i) } else { mask });
+            writer.write_all(&[mask])?;
+            for id in next_ids.iter().flatten() {
+                id.serialize(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn deserialize_next_ids<R: Read>(meta: &BlockMeta, read: &mut R) -> Result<()> {
+        let flags = FLAG_HAS_FULL_ID | FLAG_HAS_NEXT_IDS;
+        if (meta.flags() & flags) == flags {
+            let mask = read.read_byte()?;
+            for i in 0..2 {
+                if (mask & (1 << i)) != 0 {
+                    meta.set_next_id(i, Some(BlockIdExt::deserialize(read)?));
+                }
+            }
+        }
         Ok(())
     }
 
@@ -117,6 +197,7 @@ impl BlockHandle {
                 log::warn!("BlockHandle::deserialize: id mismatch: written {} != given {}", id2, id);
             }
         }
+        Self::deserialize_next_ids(&meta, read)?;
         Ok(meta)
     }
 
@@ -128,6 +209,7 @@ impl BlockHandle {
             id.seq_no = read.read_le_u32()?;
             id.file_hash = UInt256::with_array(read.read_u256()?);
         }
+        Self::deserialize_next_ids(&meta, read)?;
         Ok(meta)
     }
 
@@ -205,6 +287,24 @@ impl BlockHandle {
         self.set_flag(FLAG_STATE_SAVED)
     }
 
+    // Related flags are set by one transition, so no other task sees only a part of them,
+    // and a crash between two saves doesn't leave such a handle. Returns true if any flag
+    // was set by the call, only one of concurrent callers gets true and saves the handle.
+
+    pub fn set_data_and_proof_link(&self) -> bool {
+        self.set_flag(FLAG_DATA | FLAG_PROOF_LINK)
+    }
+
+    pub fn set_state_both(&self) -> bool {
+        self.set_flag(FLAG_STATE | FLAG_STATE_SAVED)
+    }
+
+    // Fails with `StorageError::FlagsPreconditionFailed` if any of `expected_clear` flags is set.
+    // Returns flags before the change
+    pub fn set_flags_masked(&self, set_mask: u32, expected_clear: u32) -> Result<u32> {
+        self.meta.set_flags_masked(set_mask, expected_clear)
+    }
+
     pub fn set_persistent_state(&self) -> bool {
         self.set_flag(FLAG_PERSISTENT_STATE)
     }
@@ -217,6 +317,15 @@ impl BlockHandle {
         self.set_flag(FLAG_NEXT_2)
     }
 
+    // Unlike flags, next ids may be changed (e.g. after truncation). Returns true if id was changed.
+    pub fn set_next1_id(&self, id: &BlockIdExt) -> bool {
+        self.set_next_id(0, id)
+    }
+
+    pub fn set_next2_id(&self, id: &BlockIdExt) -> bool {
+        self.set_next_id(1, id)
+    }
+
     pub fn set_prev1(&self) -> bool {
         self.set_flag(FLAG_PREV_1)
     }
@@ -238,7 +347,7 @@ impl BlockHandle {
     }
 
     pub fn reset_data(&self) {
-        self.meta.reset(FLAG_DATA, true)
+        self.meta.reset(FLAG_DATA | FLAG_DATA_VERIFIED, true)
     }
 
     pub fn reset_proof(&self) {
@@ -250,11 +359,13 @@ impl BlockHandle {
     }
 
     pub fn reset_next1(&self) {
-        self.meta.reset(FLAG_NEXT_1, false)
+        self.meta.reset(FLAG_NEXT_1, false);
+        self.meta.set_next_id(0, None);
     }
 
     pub fn reset_next2(&self) {
-        self.meta.reset(FLAG_NEXT_2, false)
+        self.meta.reset(FLAG_NEXT_2, false);
+        self.meta.set_next_id(1, None);
     }
 
     pub fn has_data(&self) -> bool {
@@ -300,6 +411,10 @@ impl BlockHandle {
         self.is_flag_set(FLAG_PERSISTENT_STATE)
     }
 
+    pub fn reset_persistent_state(&self) {
+        self.meta.reset(FLAG_PERSISTENT_STATE, false)
+    }
+
     pub fn has_next1(&self) -> bool {
         self.is_flag_set(FLAG_NEXT_1)
     }
@@ -308,6 +423,15 @@ impl BlockHandle {
         self.is_flag_set(FLAG_NEXT_2)
     }
 
+    // None if next id was not recorded (e.g. handles from older nodes)
+    pub fn next1_id(&self) -> Option<BlockIdExt> {
+        self.meta.next_id(0)
+    }
+
+    pub fn next2_id(&self) -> Option<BlockIdExt> {
+        self.meta.next_id(1)
+    }
+
     pub fn has_prev1(&self) -> bool {
         self.is_flag_set(FLAG_PREV_1)
     }
@@ -379,6 +503,10 @@ impl BlockHandle {
         self.set_flag(FLAG_MOVED_TO_ARCHIVE)
     }
 
+    pub fn reset_archived(&self) {
+        self.meta.reset(FLAG_MOVED_TO_ARCHIVE, false)
+    }
+
     pub fn is_queue_update(&self) -> bool {
         self.is_flag_set(FLAG_IS_QUEUE_UPDATE)
     }
@@ -391,6 +519,14 @@ impl BlockHandle {
         self.is_flag_set(FLAG_IS_EMPTY_QUEUE_UPDATE)
     }
 
+    pub fn is_queue_update_verified(&self) -> bool {
+        self.is_flag_set(FLAG_QUEUE_UPDATE_VERIFIED)
+    }
+
+    pub fn set_queue_update_verified(&self) -> bool {
+        self.set_flag(FLAG_QUEUE_UPDATE_VERIFIED)
+    }
+
     pub fn is_queue_update_for(&self) -> Option<i32> {
         if self.is_queue_update() {
             Some(self.meta.params as i32)
@@ -423,6 +559,26 @@ impl BlockHandle {
 */
     }
 
+    pub fn origin(&self) -> BlockOrigin {
+        BlockOrigin::from_flags(self.meta.flags())
+    }
+
+    // Origin is set once, subsequent calls do not change it
+    pub fn set_origin(&self, origin: BlockOrigin) -> bool {
+        if origin == BlockOrigin::Unknown {
+            return false
+        }
+        self.meta.set_flags_if_unset(FLAG_ORIGIN_MASK, origin.to_flags())
+    }
+
+    pub fn is_data_verified(&self) -> bool {
+        self.is_flag_set(FLAG_DATA_VERIFIED)
+    }
+
+    pub fn set_data_verified(&self) -> bool {
+        self.set_flag(FLAG_DATA_VERIFIED)
+    }
+
     pub fn set_moving_to_archive(&self) -> bool {
         self.set_flag(FLAG_ARCHIVING)
     }
@@ -439,6 +595,11 @@ impl BlockHandle {
         &self.saving_state_lock
     }
 
+    // Shared so that readers of the persistent state can own the guard
+    pub fn persistent_state_lock(&self) -> &Arc<tokio::sync::RwLock<()>>  {
+        &self.persistent_state_lock
+    }
+
 //    #[inline]
 //    fn flags(&self) -> u32 {
 //        self.meta.flags()
@@ -453,22 +614,28 @@ impl BlockHandle {
     fn set_flag(&self, flag: u32) -> bool {
         (self.meta.set_flags(flag) & flag) != flag
     }
-}
 
-#[cfg(feature = "telemetry")]
-impl BlockHandle {
-    pub fn set_got_by_broadcast(&self, value: bool) {
-        self.got_by_broadcast.store(value, Ordering::Relaxed);
+    // Returns true if handle was clean
+    fn set_dirty(&self) -> bool {
+        self.set_flag(FLAG_DIRTY)
+    }
+
+    // Returns true if handle was dirty
+    fn reset_dirty(&self) -> bool {
+        let was_dirty = self.is_flag_set(FLAG_DIRTY);
+        self.meta.reset(FLAG_DIRTY, false);
+        was_dirty
     }
 
-    pub fn got_by_broadcast(&self) -> bool {
-        self.got_by_broadcast.load(Ordering::Relaxed)
+    fn set_next_id(&self, index: usize, id: &BlockIdExt) -> bool {
+        let updated = self.meta.set_next_id(index, Some(id.clone()));
+        self.set_flag(FLAG_HAS_NEXT_IDS) || updated
     }
 }
 
 impl Drop for BlockHandle {
     fn drop(&mut self) {
-        self.block_handle_cache.remove_with(self.id.root_hash(), |(_id, weak)| {
+        self.block_handle_cache.remove_with(&self.cache_key, |(_id, weak)| {
             weak.object.strong_count() == 0
         });
     }
@@ -477,7 +644,9 @@ impl Drop for BlockHandle {
 // Real value is
 // - BlockMeta if FLAG_HAS_FULL_ID is not set
 // - BlockMeta + wc (i32) + shard (u64) + seqno (u32) + file_hash (UInt256) if FLAG_HAS_FULL_ID is set
-db_impl_serializable!(BlockHandleDb, KvcWriteable, BlockIdExt, BlockMeta);
+// - followed by mask (u8) + next1 id (BlockIdExt, if mask & 1) + next2 id (BlockIdExt, if mask & 2)
+//   if both FLAG_HAS_FULL_ID and FLAG_HAS_NEXT_IDS are set
+db_impl_serializable!(BlockHandleDb, KvcTransactional, BlockIdExt, BlockMeta);
 
 declare_counted!(
     struct HandleObject {
@@ -487,28 +656,176 @@ declare_counted!(
 
 type BlockHandleCache = lockfree::map::Map<UInt256, HandleObject>;
 
+// Mesh handles are keyed by network id and root hash, so they can't collide with
+// native ones. Records written before are migrated on load.
+const MESH_KEY_PREFIX: u8 = 0x6d; // 'm'
+const MESH_KEY_LEN: usize = 1 + 4 + 32;
+
+fn mesh_key(nw_id: i32, rh: &UInt256) -> [u8; MESH_KEY_LEN] {
+    let mut key = [0; MESH_KEY_LEN];
+    key[0] = MESH_KEY_PREFIX;
+    key[1..5].copy_from_slice(&nw_id.to_le_bytes());
+    key[5..].copy_from_slice(rh.as_slice());
+    key
+}
+
+// Cache is keyed by root hash for native handles and by hash of composite key for mesh ones
+fn mesh_cache_key(nw_id: i32, rh: &UInt256) -> UInt256 {
+    UInt256::calc_file_hash(&mesh_key(nw_id, rh))
+}
+
+fn handle_cache_key(rh: &UInt256, meta: &BlockMeta) -> UInt256 {
+    if (meta.flags() & FLAG_IS_MESH) == FLAG_IS_MESH {
+        mesh_cache_key(meta.params as i32, rh)
+    } else {
+        rh.clone()
+    }
+}
+
+// Returns network id for composite key of mesh handle and root hash
+fn parse_handle_key(key: &[u8]) -> Result<(Option<i32>, UInt256)> {
+    match key.len() {
+        32 => Ok((None, UInt256::from(key))),
+        MESH_KEY_LEN if key[0] == MESH_KEY_PREFIX => {
+            let nw_id = i32::from_le_bytes([key[1], key[2], key[3], key[4]]);
+            Ok((Some(nw_id), UInt256::from(&key[5..])))
+        }
+        _ => fail!("Bad block handle key {}", hex::encode(key))
+    }
+}
+
+fn is_mesh_of(meta: &BlockMeta, nw_id: i32) -> bool {
+    (meta.flags() & FLAG_IS_MESH) == FLAG_IS_MESH && meta.params as i32 == nw_id
+}
+
+/// Result of full block ids backfill
+#[derive(Debug, Default, PartialEq)]
+pub struct BackfillStats {
+    pub fixed: usize,
+    pub unresolved: usize,
+    pub skipped: usize, // handles held in cache, fixed by next run
+}
+
+// Storer writes up to this count of queued jobs with one batch
+const STORER_MAX_BATCH: usize = 1024;
+// Storer queue is warned about at the soft limit, senders wait at the hard one
+const STORER_QUEUE_SOFT_LIMIT: usize = 8 * STORER_MAX_BATCH;
+const STORER_QUEUE_HARD_LIMIT: usize = 64 * STORER_MAX_BATCH;
+const STORER_QUEUE_BLOCK_MS: u64 = 1000;
+// Collections written by a storer job
+const BATCH_HANDLES: u8 = 0x01;
+const BATCH_FILE_HASHES: u8 = 0x02;
+const BATCH_FULL_NODE_STATES: u8 = 0x04;
+const BATCH_VALIDATOR_STATES: u8 = 0x08;
+
+// Validator session checkpoints are kept in validator state db with this key prefix
+const SESSION_CHECKPOINT_PREFIX: &str = "session_checkpoint_";
+// Other opaque validator records (e.g. key rotation state) are kept with this key prefix
+const VALIDATOR_RECORD_PREFIX: &str = "validator_record_";
+
+// Raw records are not block ids, so they are skipped while scanning validator states
+fn is_raw_validator_state(key: &[u8]) -> bool {
+    key.starts_with(SESSION_CHECKPOINT_PREFIX.as_bytes()) ||
+        key.starts_with(VALIDATOR_RECORD_PREFIX.as_bytes())
+}
+
 #[derive(Debug)]
 pub enum StoreJob {
     SaveHandle(Arc<BlockHandle>),
+    SaveDirtyHandle(Arc<BlockHandle>), // skipped if handle was saved since marked dirty
     DropHandle(BlockIdExt),
+    DropHandleRange(Vec<BlockIdExt>),
+    DropMeshHandleRange((i32, Vec<BlockIdExt>)),
     SaveFullNodeState((String, Arc<BlockIdExt>)),
     SaveValidatorState((String, Arc<BlockIdExt>)),
+    SaveValidatorStateWithTtl((String, Arc<BlockIdExt>, u32)), // key, id, expiration time
+    SaveValidatorRawState((String, Vec<u8>)),
     DropValidatorState(String),
-    DropFullNodeState(String)
+    DropFullNodeState(String),
+    Barrier
 }
 
 #[async_trait::async_trait]
 pub trait Callback: Sync + Send {
     async fn invoke(&self, job: StoreJob, ok: bool);
 }
+
+type StoreQueueItem = (
+    StoreJob, 
+    Option<Arc<dyn Callback>>, 
+    Option<tokio::sync::oneshot::Sender<Result<()>>>
+);
  
+const NODE_STATE_EXPORT_MAGIC: u32 = 0x7374736e; // "nsts"
+const NODE_STATE_EXPORT_VERSION: u8 = 1;
+
+/// Serializes exported node states: magic, version, count, then length-prefixed key
+/// and block id for each state
+pub fn serialize_node_state(entries: &[(String, BlockIdExt)]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&NODE_STATE_EXPORT_MAGIC.to_le_bytes());
+    data.push(NODE_STATE_EXPORT_VERSION);
+    data.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (key, id) in entries {
+        data.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        data.extend_from_slice(key.as_bytes());
+        id.serialize(&mut data)?;
+    }
+    Ok(data)
+}
+
+pub fn deserialize_node_state(data: &[u8]) -> Result<Vec<(String, BlockIdExt)>> {
+    let mut cursor = Cursor::new(data);
+    if cursor.read_le_u32()? != NODE_STATE_EXPORT_MAGIC {
+        fail!("Not a node state export")
+    }
+    let version = cursor.read_byte()?;
+    if version != NODE_STATE_EXPORT_VERSION {
+        fail!("Unsupported node state export version {}", version)
+    }
+    let count = cursor.read_le_u32()? as usize;
+    let mut entries = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let len = cursor.read_le_u32()? as usize;
+        let start = cursor.position() as usize;
+        if len > data.len() - start {
+            fail!("Node state export is truncated")
+        }
+        let key = String::from_utf8(data[start..start + len].to_vec())?;
+        cursor.set_position((start + len) as u64);
+        entries.push((key, BlockIdExt::deserialize(&mut cursor)?));
+    }
+    if cursor.position() as usize != data.len() {
+        fail!("Node state export has {} extra bytes", data.len() - cursor.position() as usize)
+    }
+    Ok(entries)
+}
+
+/// Node state record listed for debugging, undecodable value is reported as error text
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeStateEntry {
+    pub key: String,
+    pub id: std::result::Result<BlockIdExt, String>,
+}
+
 pub struct BlockHandleStorage {
     handle_db: Arc<BlockHandleDb>,
     handle_cache: Arc<BlockHandleCache>,
     full_node_state_db: Arc<NodeStateDb>,
     validator_state_db: Arc<NodeStateDb>,
     state_cache: lockfree::map::Map<String, Arc<BlockIdExt>>,
-    storer: tokio::sync::mpsc::UnboundedSender<(StoreJob, Option<Arc<dyn Callback>>)>,
+    file_hash_db: Option<Arc<FileHashIndexDb>>,
+    file_hash_index_complete: AtomicBool,
+    storer: Sender<StoreQueueItem>,
+    // Set by stop, the lock is held while a job is queued
+    storer_stopped: parking_lot::RwLock<bool>,
+    // No storer is started, saves and drops fail
+    read_only: bool,
+    pending_jobs: Arc<AtomicU64>,
+    #[cfg(test)]
+    saved_handles: Arc<AtomicU64>,
+    #[cfg(test)]
+    storer_batches: Arc<AtomicU64>,
     #[cfg(feature = "telemetry")]
     telemetry: Arc<StorageTelemetry>,
     allocated: Arc<StorageAlloc>
@@ -520,18 +837,36 @@ impl BlockHandleStorage {
         handle_db: Arc<BlockHandleDb>, 
         full_node_state_db: Arc<NodeStateDb>,
         validator_state_db: Arc<NodeStateDb>,
+        file_hash_db: Option<Arc<FileHashIndexDb>>,
         #[cfg(feature = "telemetry")]
         telemetry: Arc<StorageTelemetry>,
         allocated: Arc<StorageAlloc>
     ) -> Self {
-        let (sender, mut reader) = tokio::sync::mpsc::unbounded_channel();
+        let (sender, reader) = Self::storer_channel(
+            #[cfg(feature = "telemetry")]
+            &telemetry
+        );
+        let pending_jobs = Arc::new(AtomicU64::new(0));
+        #[cfg(test)]
+        let saved_handles = Arc::new(AtomicU64::new(0));
+        #[cfg(test)]
+        let storer_batches = Arc::new(AtomicU64::new(0));
         let ret = Self {
             handle_db: handle_db.clone(),
             handle_cache: Arc::new(lockfree::map::Map::new()),
             full_node_state_db: full_node_state_db.clone(),
             validator_state_db: validator_state_db.clone(),
             state_cache: lockfree::map::Map::new(),
+            file_hash_db: file_hash_db.clone(),
+            file_hash_index_complete: AtomicBool::new(false),
             storer: sender,
+            storer_stopped: parking_lot::RwLock::new(false),
+            read_only: false,
+            pending_jobs: pending_jobs.clone(),
+            #[cfg(test)]
+            saved_handles: saved_handles.clone(),
+            #[cfg(test)]
+            storer_batches: storer_batches.clone(),
             #[cfg(feature = "telemetry")]
             telemetry,
             allocated
@@ -539,92 +874,264 @@ impl BlockHandleStorage {
         tokio::spawn( 
             async move {
 
+                // Pending writes of one storer batch, each collection is committed at once
+                struct StoreBatch {
+                    handles: Box<dyn KvcTransaction<BlockIdExt>>,
+                    file_hashes: Option<Box<dyn KvcTransaction<UInt256>>>,
+                    full_node_states: Box<dyn KvcTransaction<&'static str>>,
+                    validator_states: Box<dyn KvcTransaction<&'static str>>,
+                    // Handles indexed by file hash in this batch
+                    indexed: Vec<Arc<BlockHandle>>,
+                }
+
+                fn begin_batch(
+                    handle_db: &BlockHandleDb,
+                    file_hash_db: Option<&FileHashIndexDb>,
+                    full_node_state_db: &NodeStateDb,
+                    validator_state_db: &NodeStateDb
+                ) -> Result<StoreBatch> {
+                    Ok(StoreBatch {
+                        handles: handle_db.begin_transaction()?,
+                        file_hashes: file_hash_db.map(|db| db.begin_transaction()).transpose()?,
+                        full_node_states: full_node_state_db.begin_transaction()?,
+                        validator_states: validator_state_db.begin_transaction()?,
+                        indexed: Vec::new()
+                    })
+                }
+
+                fn commit<K: DbKey + Send + Sync>(
+                    db: u8,
+                    transaction: Box<dyn KvcTransaction<K>>,
+                    failed: &mut Vec<(u8, String)>
+                ) {
+                    if transaction.is_empty() {
+                        return
+                    }
+                    if let Err(e) = transaction.commit() {
+                        log::error!(target: TARGET, "{} while committing storer batch", e);
+                        failed.push((db, e.to_string()))
+                    }
+                }
+
+                // Returns collections which failed to commit
+                fn commit_batch(batch: StoreBatch) -> Vec<(u8, String)> {
+                    let mut failed = Vec::new();
+                    commit(BATCH_HANDLES, batch.handles, &mut failed);
+                    if let Some(file_hashes) = batch.file_hashes {
+                        commit(BATCH_FILE_HASHES, file_hashes, &mut failed);
+                        if failed.iter().any(|(db, _)| *db == BATCH_FILE_HASHES) {
+                            for handle in batch.indexed {
+                                handle.meta.reset(FLAG_FILE_HASH_INDEXED, false);
+                            }
+                        }
+                    }
+                    commit(BATCH_FULL_NODE_STATES, batch.full_node_states, &mut failed);
+                    commit(BATCH_VALIDATOR_STATES, batch.validator_states, &mut failed);
+                    failed
+                }
+
                 fn save_state(
                     key: &str, 
                     id: &Arc<BlockIdExt>, 
-                    db: &Arc<NodeStateDb>
-                ) -> bool {
+                    expire_at: Option<u32>,
+                    transaction: &mut dyn KvcTransaction<&'static str>
+                ) -> Result<()> {
                     let mut buf = Vec::new();
-                    let result = id.serialize(&mut buf).and_then(|_| db.put_raw(key.as_bytes(), &buf[..]));
-                    if let Err(e) = result {
-                        log::error!(target: TARGET, "ERROR: {} while saving state {}", e, id);
-                        false
-                    } else {
-                        true
-                    }
+                    id.serialize(&mut buf)
+                        .and_then(|_| {
+                            if let Some(expire_at) = expire_at {
+                                buf.extend_from_slice(&expire_at.to_le_bytes());
+                            }
+                            transaction.put_raw(key.as_bytes(), &buf[..])
+                        })
+                        .map_err(|e| error!("ERROR: {} while saving state {}", e, id))
                 }
 
-                fn save_handle(handle: &BlockHandle, db: &BlockHandleDb) -> Result<()> {
+                fn save_handle(
+                    handle: &Arc<BlockHandle>, 
+                    batch: &mut StoreBatch,
+                    #[cfg(test)]
+                    saved_handles: &AtomicU64
+                ) -> Result<u8> {
+                    #[cfg(test)]
+                    saved_handles.fetch_add(1, Ordering::Relaxed);
                     let mut value = Vec::new();
                     handle.serialize(&mut value)?;
-                    db.put_raw(handle.id().root_hash().as_slice(), &value)
+                    batch.handles.put_raw(&handle.db_key(), &value)?;
+                    if let Some(file_hashes) = batch.file_hashes.as_mut() {
+                        // Index is written once per handle lifetime in cache.
+                        // Mesh handles are not indexed since they are found by network id
+                        if handle.is_flag_set(FLAG_HAS_FULL_ID) && !handle.is_mesh() &&
+                            handle.set_flag(FLAG_FILE_HASH_INDEXED) 
+                        {
+                            let id = handle.id();
+                            if let Err(e) = file_hashes.put(id.file_hash(), id.root_hash().as_slice()) {
+                                handle.meta.reset(FLAG_FILE_HASH_INDEXED, false);
+                                return Err(e)
+                            }
+                            batch.indexed.push(handle.clone());
+                            return Ok(BATCH_HANDLES | BATCH_FILE_HASHES)
+                        }
+                    }
+                    Ok(BATCH_HANDLES)
                 }
 
-                while let Some((job, callback)) = reader.recv().await {
-                    let ok = match &job {
-                        StoreJob::SaveHandle(handle) => {
-                            if let Err(e) = save_handle(handle, &handle_db) {
-                                log::error!(
-                                    target: TARGET, 
-                                    "{} while storing handle {}", 
-                                    e, handle.id()
-                                );
-                                false
-                            } else {
-                                true
+                fn drop_file_hashes(ids: &[BlockIdExt], batch: &mut StoreBatch) -> Result<u8> {
+                    match batch.file_hashes.as_mut() {
+                        Some(file_hashes) => {
+                            for id in ids {
+                                file_hashes.delete_raw(id.file_hash().as_slice())?;
                             }
+                            Ok(BATCH_FILE_HASHES)
+                        }
+                        None => Ok(0)
+                    }
+                }
+
+                // Adds job writes into the batch, returns collections written
+                fn prepare_job(
+                    job: &StoreJob,
+                    has_callback: bool,
+                    batch: &mut StoreBatch,
+                    #[cfg(test)]
+                    saved_handles: &AtomicU64
+                ) -> Result<u8> {
+                    match job {
+                        StoreJob::SaveHandle(handle) => 
+                            save_handle(
+                                handle, 
+                                batch,
+                                #[cfg(test)]
+                                saved_handles
+                            ).map_err(
+                                |e| error!("{} while storing handle {}", e, handle.id())
+                            ),
+                        // Flag is reset before serialization, so changes made after
+                        // that are either written now or marked dirty again
+                        StoreJob::SaveDirtyHandle(handle) => if handle.reset_dirty() || has_callback {
+                            save_handle(
+                                handle, 
+                                batch,
+                                #[cfg(test)]
+                                saved_handles
+                            ).map_err(
+                                |e| error!("{} while storing handle {}", e, handle.id())
+                            )
+                        } else {
+                            Ok(0)
                         },
-                        StoreJob::DropHandle(id) => {
-                            if let Err(e) = handle_db.delete(id) {
-                                log::error!(
-                                    target: TARGET, 
-                                    "{} while deleting handle {}", 
-                                    e, id
-                                );
-                                false
-                            } else {
-                                true
-                            }
+                        StoreJob::DropHandle(id) => 
+                            batch.handles.delete(id)
+                                .and_then(|_| drop_file_hashes(std::slice::from_ref(id), batch))
+                                .map(|dbs| dbs | BATCH_HANDLES)
+                                .map_err(|e| error!("{} while deleting handle {}", e, id)),
+                        StoreJob::DropHandleRange(ids) => {
+                            ids.iter()
+                                .try_for_each(|id| batch.handles.delete_raw(id.root_hash().as_slice()))
+                                .and_then(|_| drop_file_hashes(ids, batch))
+                                .map(|dbs| dbs | BATCH_HANDLES)
+                                .map_err(|e| error!("{} while deleting {} handles", e, ids.len()))
+                        },
+                        StoreJob::DropMeshHandleRange((nw_id, ids)) => {
+                            ids.iter()
+                                .try_for_each(
+                                    |id| batch.handles.delete_raw(&mesh_key(*nw_id, id.root_hash()))
+                                )
+                                .map(|_| BATCH_HANDLES)
+                                .map_err(|e| error!(
+                                    "{} while deleting {} handles of mesh network {}", 
+                                    e, ids.len(), nw_id
+                                ))
                         },
                         StoreJob::SaveFullNodeState((key, id)) => 
-                            save_state(key, id, &full_node_state_db),
+                            save_state(key, id, None, batch.full_node_states.as_mut())
+                                .map(|_| BATCH_FULL_NODE_STATES),
                         StoreJob::SaveValidatorState((key, id)) => 
-                            save_state(key, id, &validator_state_db),
-                        StoreJob::DropValidatorState(key) => {
-                            let result = validator_state_db.delete_raw(key.as_bytes());
-                            if let Err(e) = result {
-                                log::error!(
-                                    target: TARGET, 
-                                    "{} while clearing state {}", 
-                                    e, key
-                                );
-                                false
-                            } else {
-                                true
-                            }
+                            save_state(key, id, None, batch.validator_states.as_mut())
+                                .map(|_| BATCH_VALIDATOR_STATES),
+                        StoreJob::SaveValidatorStateWithTtl((key, id, expire_at)) => 
+                            save_state(key, id, Some(*expire_at), batch.validator_states.as_mut())
+                                .map(|_| BATCH_VALIDATOR_STATES),
+                        StoreJob::SaveValidatorRawState((key, data)) => 
+                            batch.validator_states.put_raw(key.as_bytes(), data)
+                                .map(|_| BATCH_VALIDATOR_STATES)
+                                .map_err(|e| error!("{} while saving state {}", e, key)),
+                        StoreJob::DropValidatorState(key) => 
+                            batch.validator_states.delete_raw(key.as_bytes())
+                                .map(|_| BATCH_VALIDATOR_STATES)
+                                .map_err(|e| error!("{} while clearing state {}", e, key)),
+                        StoreJob::DropFullNodeState(key) => 
+                            batch.full_node_states.delete_raw(key.as_bytes())
+                                .map(|_| BATCH_FULL_NODE_STATES)
+                                .map_err(|e| error!("{} while clearing state {}", e, key)),
+                        StoreJob::Barrier => Ok(0)
+                    }
+                }
+
+                let mut items = Vec::new();
+                while let Some(item) = reader.recv().await {
+                    // Jobs available right now are written with one batch per collection.
+                    // Writes of the batch are ordered, so a later job overrides an earlier one
+                    items.push(item);
+                    while items.len() < STORER_MAX_BATCH {
+                        match reader.try_recv() {
+                            Ok(item) => items.push(item),
+                            Err(_) => break
                         }
-                        StoreJob::DropFullNodeState(key) => {
-                            let result = full_node_state_db.delete_raw(key.as_bytes());
-                            if let Err(e) = result {
-                                log::error!(
-                                    target: TARGET, 
-                                    "{} while clearing state {}", 
-                                    e, key
+                    }
+                    let prepared = match begin_batch(
+                        &handle_db, file_hash_db.as_deref(), &full_node_state_db, &validator_state_db
+                    ) {
+                        Ok(mut batch) => {
+                            let mut prepared = Vec::with_capacity(items.len());
+                            for (job, callback, _) in items.iter() {
+                                let result = prepare_job(
+                                    job, 
+                                    callback.is_some(), 
+                                    &mut batch,
+                                    #[cfg(test)]
+                                    &saved_handles
                                 );
-                                false
-                            } else {
-                                true
+                                if let Err(e) = &result {
+                                    log::error!(target: TARGET, "{}", e);
+                                }
+                                prepared.push(result);
                             }
+                            #[cfg(test)]
+                            storer_batches.fetch_add(1, Ordering::Relaxed);
+                            Ok((prepared, commit_batch(batch)))
+                        }
+                        Err(e) => {
+                            log::error!(target: TARGET, "{} while starting storer batch", e);
+                            Err(e.to_string())
                         }
                     };
-                    if let Some(callback) = callback {
-                        callback.invoke(job, ok).await;
+                    // Jobs are reported in order of queueing when the batch is committed
+                    for (i, (job, callback, waiter)) in items.drain(..).enumerate() {
+                        let result = match &prepared {
+                            Ok((prepared, failed)) => match &prepared[i] {
+                                Ok(dbs) => match failed.iter().find(|(db, _)| dbs & db != 0) {
+                                    Some((_, e)) => Err(error!("{} while committing storer batch", e)),
+                                    None => Ok(())
+                                },
+                                Err(e) => Err(error!("{}", e))
+                            },
+                            Err(e) => Err(error!("{} while starting storer batch", e))
+                        };
+                        if let Some(callback) = callback {
+                            callback.invoke(job, result.is_ok()).await;
+                        }
+                        if let Some(waiter) = waiter {
+                            waiter.send(result).ok();
+                        }
+                        pending_jobs.fetch_sub(1, Ordering::Relaxed);
                     }
                 }
                 
                 // Graceful close
                 reader.close();
                 while reader.recv().await.is_some() {
+                    pending_jobs.fetch_sub(1, Ordering::Relaxed);
                 }
 
             }
@@ -632,6 +1139,60 @@ impl BlockHandleStorage {
         ret
     }
 
+    /// Storage over databases opened read-only. Handles and states are only loaded
+    pub fn read_only(
+        handle_db: Arc<BlockHandleDb>, 
+        full_node_state_db: Arc<NodeStateDb>,
+        validator_state_db: Arc<NodeStateDb>,
+        file_hash_db: Option<Arc<FileHashIndexDb>>,
+        #[cfg(feature = "telemetry")]
+        telemetry: Arc<StorageTelemetry>,
+        allocated: Arc<StorageAlloc>
+    ) -> Self {
+        let (sender, _) = Self::storer_channel(
+            #[cfg(feature = "telemetry")]
+            &telemetry
+        );
+        Self {
+            handle_db,
+            handle_cache: Arc::new(lockfree::map::Map::new()),
+            full_node_state_db,
+            validator_state_db,
+            state_cache: lockfree::map::Map::new(),
+            file_hash_db,
+            file_hash_index_complete: AtomicBool::new(false),
+            storer: sender,
+            storer_stopped: parking_lot::RwLock::new(true),
+            read_only: true,
+            pending_jobs: Arc::new(AtomicU64::new(0)),
+            #[cfg(test)]
+            saved_handles: Arc::new(AtomicU64::new(0)),
+            #[cfg(test)]
+            storer_batches: Arc::new(AtomicU64::new(0)),
+            #[cfg(feature = "telemetry")]
+            telemetry,
+            allocated
+        }
+    }
+
+    fn storer_channel(
+        #[cfg(feature = "telemetry")]
+        telemetry: &StorageTelemetry
+    ) -> (Sender<StoreQueueItem>, Receiver<StoreQueueItem>) {
+        // Jobs are never lost, so a sender waits while the storer catches up
+        let limits = ChannelLimits::new(
+            STORER_QUEUE_SOFT_LIMIT,
+            STORER_QUEUE_HARD_LIMIT,
+            OverflowPolicy::Block(STORER_QUEUE_BLOCK_MS)
+        );
+        instrumented_channel(
+            "block handle storer",
+            limits,
+            #[cfg(feature = "telemetry")]
+            telemetry.storer_queue.clone()
+        )
+    }
+
     pub fn create_handle(
         &self, 
         id: BlockIdExt, 
@@ -646,9 +1207,10 @@ impl BlockHandleStorage {
         &self,
         key: String,
     ) -> Result<()> {
+        self.check_writable()?;
         self.delete_state(&key)?;
-        self.storer.send((StoreJob::DropValidatorState(key), None)).map_err(
-            |_| error!("Cannot drop validator state: storer thread dropped")
+        self.send_job(StoreJob::DropValidatorState(key), None).map_err(
+            |_| error!("Cannot drop validator state: storer is stopped")
         )
     }
 
@@ -656,14 +1218,15 @@ impl BlockHandleStorage {
         &self,
         key: String,
     ) -> Result<()> {
+        self.check_writable()?;
         self.delete_state(&key)?;
-        self.storer.send((StoreJob::DropFullNodeState(key), None)).map_err(
-            |_| error!("Cannot drop fullnode state: storer thread dropped")
+        self.send_job(StoreJob::DropFullNodeState(key), None).map_err(
+            |_| error!("Cannot drop fullnode state: storer is stopped")
         )
     }
 
     pub fn load_handle_by_id(&self, id: &BlockIdExt) -> Result<Option<Arc<BlockHandle>>> {
-        self.load_handle(id.clone(), false)
+        self.load_handle(id.clone(), false, None)
     }
 
     pub fn load_handle_by_root_hash(&self, rh: &UInt256) -> Result<Option<Arc<BlockHandle>>> {
@@ -671,7 +1234,100 @@ impl BlockHandleStorage {
             root_hash: rh.clone(),
             ..Default::default()
         };
-        self.load_handle(id, true)
+        self.load_handle(id, true, None)
+    }
+
+    pub fn load_mesh_handle(&self, nw_id: i32, rh: &UInt256) -> Result<Option<Arc<BlockHandle>>> {
+        let id = BlockIdExt {
+            root_hash: rh.clone(),
+            ..Default::default()
+        };
+        self.load_handle(id, true, Some(nw_id))
+    }
+
+    pub fn create_mesh_handle(
+        &self, 
+        nw_id: i32,
+        id: BlockIdExt, 
+        meta: BlockMeta,
+        callback: Option<Arc<dyn Callback>>
+    ) -> Result<Option<Arc<BlockHandle>>> {
+        if !is_mesh_of(&meta, nw_id) {
+            fail!("Cannot create handle of mesh network {} for block {}: meta mismatch", nw_id, id)
+        }
+        self.create_handle(id, meta, callback)
+    }
+
+    /// Loads many handles at once. Cached handles are taken from cache, the rest
+    /// are read from DB with a single batch request. Results follow the order of ids.
+    pub fn load_handles_batch(&self, ids: &[BlockIdExt]) -> Result<Vec<Option<Arc<BlockHandle>>>> {
+        log::trace!(target: TARGET, "load batch of {} block handles", ids.len());
+        let mut ret = Vec::with_capacity(ids.len());
+        let mut to_load = Vec::new();
+        for (i, id) in ids.iter().enumerate() {
+            let weak = self.handle_cache.get(id.root_hash());
+            if let Some(Some(handle)) = weak.map(|weak| weak.val().object.upgrade()) {
+                ret.push(Some(handle))
+            } else {
+                ret.push(None);
+                to_load.push(i)
+            }
+        }
+        if to_load.is_empty() {
+            return Ok(ret)
+        }
+        let keys = to_load.iter().map(|i| &ids[*i].root_hash().as_slice()[..]).collect::<Vec<_>>();
+        let values = self.handle_db.try_get_raw_batch(&keys)?;
+        for (i, data) in to_load.into_iter().zip(values.into_iter()) {
+            if let Some(data) = data {
+                let id = &ids[i];
+                let mut cursor = Cursor::new(data);
+                let meta = BlockHandle::deserialize(id, &mut cursor)?;
+                if (meta.flags() & FLAG_IS_MESH) == FLAG_IS_MESH {
+                    // Not migrated mesh handle
+                    continue
+                }
+                meta.set_flags(FLAG_HAS_FULL_ID);
+                // Handle may be already created by previous id with the same root hash
+                ret[i] = match self.create_handle_and_store(id.clone(), meta, None, false)? {
+                    Some(handle) => Some(handle),
+                    None => self.load_handle(id.clone(), false, None)?
+                };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Loads handle using file hash index. For databases created before the index 
+    /// the index is built by full scan on first miss 
+    pub fn load_handle_by_file_hash(&self, fh: &UInt256) -> Result<Option<Arc<BlockHandle>>> {
+        log::trace!(target: TARGET, "load block handle by file hash {:x}", fh);
+        let file_hash_db = self.file_hash_db.as_ref().ok_or_else(
+            || error!("File hash index is not enabled in block handle storage")
+        )?;
+        let rh = match file_hash_db.degrade_on_corruption(file_hash_db.try_get(fh))? {
+            Some(rh) => rh,
+            None => {
+                // Corrupted index is rebuilt by the scan below
+                self.file_hash_index_complete.store(false, Ordering::Relaxed);
+                None
+            }
+        };
+        if let Some(rh) = rh {
+            let rh = UInt256::from(&rh[..]);
+            if let Some(handle) = self.load_handle_by_root_hash(&rh)? {
+                if handle.id().file_hash() == fh {
+                    return Ok(Some(handle))
+                }
+            }
+        }
+        if self.is_file_hash_index_complete(file_hash_db)? {
+            return Ok(None)
+        }
+        match self.build_file_hash_index(file_hash_db, fh)? {
+            Some(id) => self.load_handle_by_id(&id),
+            None => Ok(None)
+        }
     }
 
     pub fn load_full_block_id(&self, root_hash: &UInt256) -> Result<Option<BlockIdExt>> {
@@ -703,24 +1359,143 @@ impl BlockHandleStorage {
         self.load_state(key, &self.validator_state_db)
     }
 
-    pub fn save_handle(
+    /// Returns all full node states. Records of other types (e.g. db version) are skipped.
+    /// Queued saves are not seen in db, so the storage should be flushed before the export
+    pub fn export_node_state(&self) -> Result<Vec<(String, BlockIdExt)>> {
+        let mut entries = Vec::new();
+        self.full_node_state_db.for_each(&mut |key, value| {
+            let key = match std::str::from_utf8(key) {
+                Ok(key) => key.to_string(),
+                Err(_) => return Ok(true)
+            };
+            let mut cursor = Cursor::new(value);
+            match BlockIdExt::deserialize(&mut cursor) {
+                Ok(id) if cursor.position() as usize == value.len() => entries.push((key, id)),
+                _ => log::debug!(target: TARGET, "export node state: skipped record {}", key)
+            }
+            Ok(true)
+        })?;
+        // Cached value may be newer than the one in db
+        for (key, id) in entries.iter_mut() {
+            if let Some(cached) = self.state_cache.get(key.as_str()) {
+                *id = cached.val().as_ref().clone();
+            }
+        }
+        entries.sort_by(|(key1, _), (key2, _)| key1.cmp(key2));
+        Ok(entries)
+    }
+
+    /// Lists all full node states for debugging. Db is read by iterator, so storer is not
+    /// blocked, and queued saves are taken from cache
+    pub fn list_full_node_state_keys(&self) -> Result<Vec<NodeStateEntry>> {
+        self.list_state_keys(&self.full_node_state_db, false)
+    }
+
+    /// Lists all validator states for debugging, session checkpoints are skipped
+    pub fn list_validator_state_keys(&self) -> Result<Vec<NodeStateEntry>> {
+        self.list_state_keys(&self.validator_state_db, true)
+    }
+
+    /// Saves given full node states. Nothing is saved if some existing state differs
+    /// from the imported one, unless `overwrite` is set. Returns count of saved states
+    pub fn import_node_state(&self, entries: &[(String, BlockIdExt)], overwrite: bool) -> Result<usize> {
+        let mut changed = Vec::new();
+        for (key, id) in entries {
+            match self.load_full_node_state(key)? {
+                Some(existing) if existing.as_ref() == id => continue,
+                Some(existing) if !overwrite => fail!(
+                    "Node state {} is {}, but imported one is {}", key, existing, id
+                ),
+                _ => changed.push((key, id))
+            }
+        }
+        for (key, id) in changed.iter() {
+            self.save_full_node_state(key.to_string(), id)?;
+        }
+        log::info!(target: TARGET, "imported {} of {} node states", changed.len(), entries.len());
+        Ok(changed.len())
+    }
+
+    /// Schedules handle save. Changes of the handle made before its pending save 
+    /// is processed are written at once, so at most one save per handle is queued.
+    /// Jobs with callback are always queued to invoke the callback.
+    pub fn mark_dirty(
+        &self, 
+        handle: &Arc<BlockHandle>, 
+        callback: Option<Arc<dyn Callback>>
+    ) -> Result<()> {
+        self.check_writable()?;
+        if !handle.set_dirty() && callback.is_none() {
+            return Ok(())
+        }
+        self.send_job(StoreJob::SaveDirtyHandle(handle.clone()), callback).map_err(
+            |_| error!("Cannot store handle {}: storer is stopped", handle.id())
+        )
+    }
+
+    /// Queues handle save bypassing coalescing. Used for changes which must not 
+    /// wait for other saves, e.g. applied and archived flags
+    pub fn flush_handle(
         &self, 
         handle: &Arc<BlockHandle>, 
         callback: Option<Arc<dyn Callback>>
     ) -> Result<()> {
-        self.storer.send((StoreJob::SaveHandle(handle.clone()), callback)).map_err(
-            |_| error!("Cannot store handle {}: storer thread dropped", handle.id())
+        self.check_writable()?;
+        handle.reset_dirty();
+        self.send_job(StoreJob::SaveHandle(handle.clone()), callback).map_err(
+            |_| error!("Cannot store handle {}: storer is stopped", handle.id())
         )
     }
 
+    /// Saves handle and waits until it is written into DB
+    pub async fn save_handle_sync(
+        &self, 
+        handle: &Arc<BlockHandle>, 
+        callback: Option<Arc<dyn Callback>>
+    ) -> Result<()> {
+        self.check_writable()?;
+        handle.reset_dirty();
+        self.execute_job(StoreJob::SaveHandle(handle.clone()), callback).await
+    }
+
+    /// Waits until all jobs queued before the call are processed
+    pub async fn flush(&self) -> Result<()> {
+        if self.read_only {
+            return Ok(())
+        }
+        self.execute_job(StoreJob::Barrier, None).await
+    }
+
+    /// Waits until queued jobs are written. Jobs queued after the call are refused,
+    /// so nothing is written when the DB is closed
+    pub async fn stop(&self) -> Result<()> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        {
+            let mut stopped = self.storer_stopped.write();
+            if *stopped {
+                return Ok(())
+            }
+            *stopped = true;
+            self.pending_jobs.fetch_add(1, Ordering::Relaxed);
+            self.storer.send((StoreJob::Barrier, None, Some(sender))).map_err(|_| {
+                self.pending_jobs.fetch_sub(1, Ordering::Relaxed);
+                error!("Cannot stop storer: storer is stopped")
+            })?;
+        }
+        receiver.await.map_err(
+            |_| error!("Cannot complete store job: storer is stopped")
+        )?
+    }
+
     pub fn save_full_node_state(
         &self,
         key: String,
         id: &BlockIdExt
     ) -> Result<()> {
+        self.check_writable()?;
         let refid = self.create_state(key.clone(), id)?;
-        self.storer.send((StoreJob::SaveFullNodeState((key, refid)), None)).map_err(
-            |_| error!("Cannot store full node state {}: storer thread dropped", id)
+        self.send_job(StoreJob::SaveFullNodeState((key, refid)), None).map_err(
+            |_| error!("Cannot store full node state {}: storer is stopped", id)
         )
     }
 
@@ -729,36 +1504,437 @@ impl BlockHandleStorage {
         key: String,
         id: &BlockIdExt
     ) -> Result<()> {
+        self.check_writable()?;
         let refid = self.create_state(key.clone(), id)?;
-        self.storer.send((StoreJob::SaveValidatorState((key, refid)), None)).map_err(
-            |_| error!("Cannot store validator state {}: storer thread dropped", id)
+        self.send_job(StoreJob::SaveValidatorState((key, refid)), None).map_err(
+            |_| error!("Cannot store validator state {}: storer is stopped", id)
+        )
+    }
+
+    /// Saves validator state which is removed by `gc_expired_states` after ttl
+    pub fn save_validator_state_with_ttl(
+        &self,
+        key: String,
+        id: &BlockIdExt,
+        ttl_secs: u32
+    ) -> Result<()> {
+        self.check_writable()?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        let expire_at = now.saturating_add(ttl_secs);
+        let refid = self.create_state(key.clone(), id)?;
+        self.send_job(StoreJob::SaveValidatorStateWithTtl((key, refid, expire_at)), None).map_err(
+            |_| error!("Cannot store validator state {}: storer is stopped", id)
+        )
+    }
+
+    /// Removes validator states expired at `now`. States saved without ttl never expire.
+    /// Returns number of removed states
+    pub fn gc_expired_states(&self, now: u32, counters: &GcCounters) -> Result<usize> {
+        let mut expired = Vec::new();
+        self.validator_state_db.for_each(&mut |key, value| {
+            if is_raw_validator_state(key) {
+                return Ok(true)
+            }
+            counters.add_scanned(1);
+            let mut cursor = Cursor::new(value);
+            BlockIdExt::deserialize(&mut cursor)?;
+            if (cursor.position() as usize) < value.len() {
+                let expire_at = cursor.read_le_u32()?;
+                if expire_at <= now {
+                    let size = (key.len() + value.len()) as u64;
+                    expired.push((String::from_utf8_lossy(key).to_string(), size));
+                }
+            }
+            Ok(true)
+        })?;
+        let count = expired.len();
+        for (key, size) in expired {
+            log::trace!(target: TARGET, "drop expired validator state {}", key);
+            self.drop_validator_state(key)?;
+            counters.add_removed(size);
+        }
+        Ok(count)
+    }
+
+    /// Saves opaque validator session checkpoint. Write is queued, so no fsync is waited
+    pub fn save_session_checkpoint(&self, session_id: &UInt256, data: Vec<u8>) -> Result<()> {
+        self.check_writable()?;
+        let key = Self::session_checkpoint_key(session_id);
+        self.send_job(StoreJob::SaveValidatorRawState((key, data)), None).map_err(
+            |_| error!("Cannot store checkpoint of session {:x}: storer is stopped", session_id)
+        )
+    }
+
+    pub fn load_session_checkpoint(&self, session_id: &UInt256) -> Result<Option<Vec<u8>>> {
+        let key = Self::session_checkpoint_key(session_id);
+        Ok(self.validator_state_db.try_get_raw(key.as_bytes())?.map(|data| data.as_ref().to_vec()))
+    }
+
+    /// Drops checkpoints of sessions which are not in `active`. Returns number of dropped ones
+    pub fn drop_stale_session_checkpoints(&self, active: &HashSet<UInt256>) -> Result<usize> {
+        let active = active.iter()
+            .map(|session_id| Self::session_checkpoint_key(session_id))
+            .collect::<HashSet<_>>();
+        let mut stale = Vec::new();
+        self.validator_state_db.for_each(&mut |key, _value| {
+            if key.starts_with(SESSION_CHECKPOINT_PREFIX.as_bytes()) {
+                let key = String::from_utf8_lossy(key).to_string();
+                if !active.contains(&key) {
+                    stale.push(key);
+                }
+            }
+            Ok(true)
+        })?;
+        let count = stale.len();
+        for key in stale {
+            log::trace!(target: TARGET, "drop stale session checkpoint {}", key);
+            self.drop_validator_state(key)?;
+        }
+        Ok(count)
+    }
+
+    /// Saves opaque validator record under `name`. Write is queued like for checkpoints
+    pub fn save_validator_record(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        self.check_writable()?;
+        let key = format!("{}{}", VALIDATOR_RECORD_PREFIX, name);
+        self.send_job(StoreJob::SaveValidatorRawState((key, data)), None).map_err(
+            |_| error!("Cannot store validator record {}: storer is stopped", name)
         )
     }
 
+    pub fn load_validator_record(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let key = format!("{}{}", VALIDATOR_RECORD_PREFIX, name);
+        Ok(self.validator_state_db.try_get_raw(key.as_bytes())?.map(|data| data.as_ref().to_vec()))
+    }
+
+    fn session_checkpoint_key(session_id: &UInt256) -> String {
+        format!("{}{:x}", SESSION_CHECKPOINT_PREFIX, session_id)
+    }
+
     pub fn drop_handle(
         &self, 
         id: BlockIdExt, 
         callback: Option<Arc<dyn Callback>>
     ) -> Result<()> {
+        self.check_writable()?;
         let _ = self.handle_cache.remove(id.root_hash());
-        self.storer.send((StoreJob::DropHandle(id.clone()), callback)).map_err(
-            |_| error!("Cannot drop handle {}: storer thread dropped", id)
+        self.send_job(StoreJob::DropHandle(id.clone()), callback).map_err(
+            |_| error!("Cannot drop handle {}: storer is stopped", id)
         )?;
         Ok(())
     }
 
+    /// Drops many handles with single DB write. Callback is invoked once for the whole range
+    pub fn drop_handles(
+        &self, 
+        ids: Vec<BlockIdExt>, 
+        callback: Option<Arc<dyn Callback>>
+    ) -> Result<()> {
+        self.check_writable()?;
+        for id in ids.iter() {
+            let _ = self.handle_cache.remove(id.root_hash());
+        }
+        let count = ids.len();
+        self.send_job(StoreJob::DropHandleRange(ids), callback).map_err(
+            |_| error!("Cannot drop {} handles: storer is stopped", count)
+        )
+    }
+
+    /// Number of store jobs queued but not processed yet
+    pub fn pending_jobs(&self) -> u64 {
+        self.pending_jobs.load(Ordering::Relaxed)
+    }
+
+    /// Rewrites records created before full ids were stored. Each record is written 
+    /// separately, so the routine may be re-run after interruption
+    pub fn backfill_full_ids(
+        &self,
+        resolver: &dyn Fn(&UInt256) -> Result<Option<BlockIdExt>>
+    ) -> Result<BackfillStats> {
+        self.check_writable()?;
+        let mut legacy = Vec::new();
+        self.handle_db.for_each(&mut |key_bytes, value_bytes| {
+            let mut cursor = Cursor::new(value_bytes);
+            let meta = BlockMeta::deserialize(&mut cursor)?;
+            if (meta.flags() & FLAG_HAS_FULL_ID) == 0 {
+                legacy.push(parse_handle_key(key_bytes)?.1);
+            }
+            Ok(true)
+        })?;
+        let mut stats = BackfillStats::default();
+        for root_hash in legacy {
+            // Cached handle would overwrite the record with its own meta on next save
+            let weak = self.handle_cache.get(&root_hash);
+            if let Some(Some(_)) = weak.map(|weak| weak.val().object.upgrade()) {
+                stats.skipped += 1;
+                continue
+            }
+            let id = match resolver(&root_hash)? {
+                Some(id) if id.root_hash() == &root_hash => id,
+                Some(id) => {
+                    log::warn!(
+                        target: TARGET, 
+                        "backfill: resolved id {} doesn't match root hash {:x}", id, root_hash
+                    );
+                    stats.unresolved += 1;
+                    continue
+                }
+                None => {
+                    stats.unresolved += 1;
+                    continue
+                }
+            };
+            let data = match self.handle_db.try_get_raw(root_hash.as_slice())? {
+                Some(data) => data,
+                None => continue // dropped meanwhile
+            };
+            let mut cursor = Cursor::new(data);
+            let meta = BlockMeta::deserialize(&mut cursor)?;
+            meta.set_flags(FLAG_HAS_FULL_ID);
+            let handle = BlockHandle::with_values(id, meta, self.handle_cache.clone());
+            let mut value = Vec::new();
+            handle.serialize(&mut value)?;
+            self.handle_db.put_raw(root_hash.as_slice(), &value)?;
+            if let Some(file_hash_db) = &self.file_hash_db {
+                file_hash_db.put(handle.id().file_hash(), root_hash.as_slice())?;
+            }
+            stats.fixed += 1;
+        }
+        log::info!(
+            target: TARGET, 
+            "backfill of full block ids: {} fixed, {} unresolved, {} skipped", 
+            stats.fixed, stats.unresolved, stats.skipped
+        );
+        Ok(stats)
+    }
+
+    /// Iterates over keys of native handles, mesh handles are skipped
     pub fn for_each_keys(&self, predicate: &mut dyn FnMut(BlockIdExt) -> Result<bool>) -> Result<bool> {
         self.handle_db.for_each(&mut |key_bytes, _value_bytes| {
+            let (None, root_hash) = parse_handle_key(key_bytes)? else {
+                return Ok(true)
+            };
             let id = BlockIdExt::with_params(
                 ShardIdent::default(),
                 0, 
-                UInt256::from(key_bytes), 
+                root_hash, 
                 UInt256::default()
             );
             predicate(id)
         })
     }
 
+    /// Iterates over metas of applied native masterchain blocks in random order.
+    /// Handles are not created, so the scan is cheap for large databases.
+    pub fn for_each_applied_mc_meta(
+        &self,
+        predicate: &mut dyn FnMut(&BlockIdExt, &BlockMeta) -> Result<bool>
+    ) -> Result<bool> {
+        self.handle_db.for_each(&mut |key_bytes, value_bytes| {
+            let (None, root_hash) = parse_handle_key(key_bytes)? else {
+                return Ok(true)
+            };
+            let mut cursor = Cursor::new(value_bytes);
+            let mut id = BlockIdExt {
+                root_hash,
+                ..Default::default()
+            };
+            let meta = BlockHandle::deserialize_nonchecked(&mut id, &mut cursor)?;
+            let flags = meta.flags();
+            if (flags & (FLAG_HAS_FULL_ID | FLAG_APPLIED)) != (FLAG_HAS_FULL_ID | FLAG_APPLIED) ||
+                (flags & (FLAG_IS_MESH | FLAG_IS_QUEUE_UPDATE)) != 0 ||
+                !id.shard().is_masterchain()
+            {
+                return Ok(true)
+            }
+            predicate(&id, &meta)
+        })
+    }
+
+    /// Iterates over key block handles only, in seq_no order.
+    /// Non-key handles are skipped without creating handle objects.
+    pub fn for_each_key_block_handle(
+        &self,
+        predicate: &mut dyn FnMut(Arc<BlockHandle>) -> Result<bool>
+    ) -> Result<bool> {
+        let mut key_blocks = Vec::new();
+        self.handle_db.for_each(&mut |key_bytes, value_bytes| {
+            let mut cursor = Cursor::new(value_bytes);
+            let mut id = BlockIdExt {
+                root_hash: parse_handle_key(key_bytes)?.1,
+                ..Default::default()
+            };
+            let meta = BlockHandle::deserialize_nonchecked(&mut id, &mut cursor)?;
+            if (meta.flags() & FLAG_KEY_BLOCK) == FLAG_KEY_BLOCK {
+                key_blocks.push((id, meta));
+            }
+            Ok(true)
+        })?;
+        key_blocks.sort_by_key(|(id, _)| id.seq_no());
+        for (id, meta) in key_blocks {
+            let handle = match self.get_or_create_handle(id, meta)? {
+                Some(handle) => handle,
+                None => continue
+            };
+            if !predicate(handle)? {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    /// Iterates over handles of blocks from given mesh network.
+    /// Handles of other blocks are skipped without creating handle objects.
+    pub fn for_each_mesh_handle(
+        &self,
+        nw_id: i32,
+        predicate: &mut dyn FnMut(Arc<BlockHandle>) -> Result<bool>
+    ) -> Result<bool> {
+        for (composite, id, meta) in self.collect_mesh_handles(nw_id, false)? {
+            if !composite {
+                // Loading migrates the record
+                if let Some(handle) = self.load_mesh_handle(nw_id, id.root_hash())? {
+                    if !predicate(handle)? {
+                        return Ok(false)
+                    }
+                }
+                continue
+            }
+            let handle = match self.get_or_create_handle(id, meta)? {
+                Some(handle) => handle,
+                None => continue
+            };
+            if !predicate(handle)? {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    /// Iterates over handles of queue updates for given workchain.
+    pub fn for_each_queue_update_handle(
+        &self,
+        target_wc: i32,
+        predicate: &mut dyn FnMut(Arc<BlockHandle>) -> Result<bool>
+    ) -> Result<bool> {
+        let found = self.collect_handles(&|meta| {
+            meta.params as i32 == target_wc &&
+                (meta.flags() & FLAG_IS_QUEUE_UPDATE) == FLAG_IS_QUEUE_UPDATE
+        })?;
+        for (id, meta) in found {
+            let handle = match self.get_or_create_handle(id, meta)? {
+                Some(handle) => handle,
+                None => continue
+            };
+            if !predicate(handle)? {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    /// Drops all handles of blocks from given mesh network with single DB write.
+    /// Queue update handles with the same target id are dropped only if requested.
+    pub fn drop_mesh_handles(&self, nw_id: i32, with_queue_updates: bool) -> Result<usize> {
+        let mut ids = Vec::new();
+        let mut mesh_ids = Vec::new();
+        for (composite, id, _) in self.collect_mesh_handles(nw_id, with_queue_updates)? {
+            if composite {
+                mesh_ids.push(id)
+            } else {
+                ids.push(id)
+            }
+        }
+        let count = ids.len() + mesh_ids.len();
+        // Not migrated handles and queue updates are stored under root hash
+        if !ids.is_empty() {
+            for id in ids.iter() {
+                let _ = self.handle_cache.remove(&mesh_cache_key(nw_id, id.root_hash()));
+            }
+            self.drop_handles(ids, None)?;
+        }
+        if !mesh_ids.is_empty() {
+            for id in mesh_ids.iter() {
+                let _ = self.handle_cache.remove(&mesh_cache_key(nw_id, id.root_hash()));
+            }
+            let mesh_count = mesh_ids.len();
+            self.send_job(StoreJob::DropMeshHandleRange((nw_id, mesh_ids)), None).map_err(
+                |_| error!("Cannot drop {} mesh handles: storer is stopped", mesh_count)
+            )?;
+        }
+        log::info!(target: TARGET, "dropped {} handles of mesh network {}", count, nw_id);
+        Ok(count)
+    }
+
+    fn collect_mesh_handles(
+        &self,
+        nw_id: i32,
+        with_queue_updates: bool
+    ) -> Result<Vec<(bool, BlockIdExt, BlockMeta)>> {
+        self.collect_handles_with_keys(&|meta| {
+            if meta.params as i32 != nw_id {
+                return false
+            }
+            let flags = meta.flags();
+            if (flags & FLAG_IS_QUEUE_UPDATE) == FLAG_IS_QUEUE_UPDATE {
+                with_queue_updates
+            } else {
+                (flags & FLAG_IS_MESH) == FLAG_IS_MESH
+            }
+        })
+    }
+
+    fn collect_handles(
+        &self,
+        filter: &dyn Fn(&BlockMeta) -> bool
+    ) -> Result<Vec<(BlockIdExt, BlockMeta)>> {
+        let found = self.collect_handles_with_keys(filter)?
+            .into_iter()
+            .map(|(_, id, meta)| (id, meta))
+            .collect();
+        Ok(found)
+    }
+
+    // Also returns if handle is stored under composite key
+    fn collect_handles_with_keys(
+        &self,
+        filter: &dyn Fn(&BlockMeta) -> bool
+    ) -> Result<Vec<(bool, BlockIdExt, BlockMeta)>> {
+        let mut found = Vec::new();
+        self.handle_db.for_each(&mut |key_bytes, value_bytes| {
+            let (key_nw_id, root_hash) = parse_handle_key(key_bytes)?;
+            let mut cursor = Cursor::new(value_bytes);
+            let mut id = BlockIdExt {
+                root_hash,
+                ..Default::default()
+            };
+            let meta = BlockHandle::deserialize_nonchecked(&mut id, &mut cursor)?;
+            if filter(&meta) {
+                found.push((key_nw_id.is_some(), id, meta));
+            }
+            Ok(true)
+        })?;
+        Ok(found)
+    }
+
+    fn get_or_create_handle(&self, id: BlockIdExt, meta: BlockMeta) -> Result<Option<Arc<BlockHandle>>> {
+        let weak = self.handle_cache.get(&handle_cache_key(id.root_hash(), &meta));
+        if let Some(Some(handle)) = weak.map(|weak| weak.val().object.upgrade()) {
+            Ok(Some(handle))
+        } else {
+            let mesh_nw_id = if (meta.flags() & FLAG_IS_MESH) == FLAG_IS_MESH {
+                Some(meta.params as i32)
+            } else {
+                None
+            };
+            if let Some(handle) = self.create_handle_and_store(id.clone(), meta, None, false)? {
+                Ok(Some(handle))
+            } else {
+                self.load_handle(id, true, mesh_nw_id)
+            }
+        }
+    }
+
     fn create_handle_and_store(
         &self, 
         id: BlockIdExt, 
@@ -766,11 +1942,13 @@ impl BlockHandleStorage {
         callback: Option<Arc<dyn Callback>>,
         store: bool
     ) -> Result<Option<Arc<BlockHandle>>> {
-        let rh = id.root_hash().clone();
+        if store {
+            self.check_writable()?;
+        }
         let ret = Arc::new(BlockHandle::with_values(id, meta, self.handle_cache.clone()));
         let added = add_counted_object_to_map(
             &self.handle_cache, 
-            rh, 
+            ret.cache_key.clone(), 
             || {
                 let ret = HandleObject {
                     object: Arc::downgrade(&ret),
@@ -785,7 +1963,7 @@ impl BlockHandleStorage {
         )?;
         if added {
             if store {
-                self.save_handle(&ret, callback)?
+                self.mark_dirty(&ret, callback)?
             }
             Ok(Some(ret))
         } else {
@@ -793,6 +1971,104 @@ impl BlockHandleStorage {
         }
     }
 
+    fn is_file_hash_index_complete(&self, file_hash_db: &FileHashIndexDb) -> Result<bool> {
+        if self.file_hash_index_complete.load(Ordering::Relaxed) {
+            return Ok(true)
+        }
+        let marker = file_hash_db.try_get_raw(FILE_HASH_INDEX_COMPLETE);
+        if file_hash_db.degrade_on_corruption(marker)?.flatten().is_some() {
+            self.file_hash_index_complete.store(true, Ordering::Relaxed);
+            return Ok(true)
+        }
+        Ok(false)
+    }
+
+    fn build_file_hash_index(
+        &self, 
+        file_hash_db: &FileHashIndexDb,
+        fh: &UInt256
+    ) -> Result<Option<BlockIdExt>> {
+        log::info!(target: TARGET, "building file hash index of block handles...");
+        let mut found = None;
+        let mut indexed = 0;
+        self.handle_db.for_each(&mut |key_bytes, value_bytes| {
+            let (None, root_hash) = parse_handle_key(key_bytes)? else {
+                return Ok(true)
+            };
+            let mut cursor = Cursor::new(value_bytes);
+            if let Some(id) = BlockHandle::deserialize_full_id(&root_hash, &mut cursor)? {
+                // Read-only storage only looks for the handle
+                if self.read_only {
+                    if id.file_hash() == fh {
+                        found = Some(id);
+                        return Ok(false)
+                    }
+                    return Ok(true)
+                }
+                file_hash_db.put(id.file_hash(), key_bytes)?;
+                indexed += 1;
+                if id.file_hash() == fh {
+                    found = Some(id);
+                }
+            }
+            Ok(true)
+        })?;
+        if self.read_only {
+            return Ok(found)
+        }
+        file_hash_db.put_raw(FILE_HASH_INDEX_COMPLETE, &[1])?;
+        self.file_hash_index_complete.store(true, Ordering::Relaxed);
+        file_hash_db.set_rebuilt();
+        log::info!(target: TARGET, "built file hash index of {} block handles", indexed);
+        Ok(found)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            fail!(StorageError::ReadOnly)
+        }
+        Ok(())
+    }
+
+    fn send_job(
+        &self,
+        job: StoreJob,
+        callback: Option<Arc<dyn Callback>>
+    ) -> std::result::Result<(), SendError<StoreQueueItem>> {
+        self.enqueue_job(job, callback, None)
+    }
+
+    fn enqueue_job(
+        &self,
+        job: StoreJob,
+        callback: Option<Arc<dyn Callback>>,
+        waiter: Option<tokio::sync::oneshot::Sender<Result<()>>>
+    ) -> std::result::Result<(), SendError<StoreQueueItem>> {
+        let stopped = self.storer_stopped.read();
+        if *stopped {
+            return Err(SendError::Closed((job, callback, waiter)))
+        }
+        self.pending_jobs.fetch_add(1, Ordering::Relaxed);
+        self.storer.send((job, callback, waiter)).map_err(|e| {
+            self.pending_jobs.fetch_sub(1, Ordering::Relaxed);
+            e
+        })
+    }
+
+    async fn execute_job(
+        &self,
+        job: StoreJob,
+        callback: Option<Arc<dyn Callback>>
+    ) -> Result<()> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.enqueue_job(job, callback, Some(sender)).map_err(
+            |_| error!("Cannot execute store job: storer is stopped")
+        )?;
+        receiver.await.map_err(
+            |_| error!("Cannot complete store job: storer is stopped")
+        )?
+    }
+
     fn create_state(
         &self,
         key: String,
@@ -817,22 +2093,41 @@ impl BlockHandleStorage {
         Ok(())
     }
 
+    // Native handle is never returned for mesh request and vice versa
     fn load_handle(
         &self, 
         mut id: BlockIdExt,
-        rh_only: bool
+        rh_only: bool,
+        mesh_nw_id: Option<i32>
     ) -> Result<Option<Arc<BlockHandle>>> {
-        if rh_only {
+        if let Some(nw_id) = mesh_nw_id {
+            log::trace!(target: TARGET, "load mesh {} block handle {:x}", nw_id, id.root_hash())
+        } else if rh_only {
             log::trace!(target: TARGET, "load block handle by root hash {:x}", id.root_hash())
         } else {
             log::trace!(target: TARGET, "load block handle by id {}", &id)
         }
+        let cache_key = match mesh_nw_id {
+            Some(nw_id) => mesh_cache_key(nw_id, id.root_hash()),
+            None => id.root_hash().clone()
+        };
         let ret = loop {
-            let weak = self.handle_cache.get(id.root_hash());
+            let weak = self.handle_cache.get(&cache_key);
             if let Some(Some(handle)) = weak.map(|weak| weak.val().object.upgrade()) {
                 break Some(handle)
             }
-            if let Some(data) = self.handle_db.try_get_raw(id.root_hash().as_slice())? {
+            let mut legacy = false;
+            let data = match mesh_nw_id {
+                Some(nw_id) => match self.handle_db.try_get_raw(&mesh_key(nw_id, id.root_hash()))? {
+                    Some(data) => Some(data),
+                    None => {
+                        legacy = true;
+                        self.handle_db.try_get_raw(id.root_hash().as_slice())?
+                    }
+                },
+                None => self.handle_db.try_get_raw(id.root_hash().as_slice())?
+            };
+            if let Some(data) = data {
                 let mut cursor = Cursor::new(data);
                 let meta = if rh_only {
                     BlockHandle::deserialize_nonchecked(&mut id, &mut cursor)?
@@ -841,8 +2136,18 @@ impl BlockHandleStorage {
                     meta.set_flags(FLAG_HAS_FULL_ID);
                     meta
                 };
+                let matches = match mesh_nw_id {
+                    Some(nw_id) => is_mesh_of(&meta, nw_id),
+                    None => (meta.flags() & FLAG_IS_MESH) == 0
+                };
+                if !matches {
+                    break None
+                }
                 let handle = self.create_handle_and_store(id.clone(), meta, None, false)?;
                 if let Some(handle) = handle {
+                    if legacy && !self.read_only {
+                        self.migrate_mesh_handle(&handle)?
+                    }
                     break Some(handle)
                 }
             } else {
@@ -852,6 +2157,50 @@ impl BlockHandleStorage {
         Ok(ret)
     }
 
+    // Moves record of mesh handle written under root hash to composite key
+    fn migrate_mesh_handle(&self, handle: &Arc<BlockHandle>) -> Result<()> {
+        log::info!(target: TARGET, "migrate mesh block handle {}", handle.id());
+        self.mark_dirty(handle, None)?;
+        self.send_job(StoreJob::DropHandle(handle.id().clone()), None).map_err(
+            |_| error!("Cannot migrate mesh handle {}: storer is stopped", handle.id())
+        )
+    }
+
+    // Validator states may have expiration time after block id
+    fn list_state_keys(
+        &self,
+        db: &Arc<NodeStateDb>,
+        validator: bool
+    ) -> Result<Vec<NodeStateEntry>> {
+        let mut entries = Vec::new();
+        db.for_each(&mut |key, value| {
+            if validator && is_raw_validator_state(key) {
+                return Ok(true)
+            }
+            let key = String::from_utf8_lossy(key).to_string();
+            let id = if let Some(cached) = self.state_cache.get(key.as_str()) {
+                Ok(cached.val().as_ref().clone())
+            } else {
+                let mut cursor = Cursor::new(value);
+                match BlockIdExt::deserialize(&mut cursor) {
+                    Ok(id) => {
+                        let extra = value.len() - cursor.position() as usize;
+                        if extra == 0 || (validator && extra == 4) {
+                            Ok(id)
+                        } else {
+                            Err(format!("{} extra bytes after block id", extra))
+                        }
+                    }
+                    Err(e) => Err(format!("can't deserialize {} bytes: {}", value.len(), e))
+                }
+            };
+            entries.push(NodeStateEntry { key, id });
+            Ok(true)
+        })?;
+        entries.sort_by(|entry1, entry2| entry1.key.cmp(&entry2.key));
+        Ok(entries)
+    }
+
     fn load_state(
         &self, 
         key: &str, 
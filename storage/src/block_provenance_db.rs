@@ -0,0 +1,18 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{db_impl_base, db::traits::KvcWriteable};
+use ever_block::UInt256;
+
+// Provenance records of blocks keyed by root hash
+db_impl_base!(BlockProvenanceDb, KvcWriteable, UInt256);
@@ -0,0 +1,106 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::error::StorageError;
+use std::collections::BTreeMap;
+use ever_block::{Error, Result};
+
+/// What happens on corruption of a column, declared by each DB in `db_impl_base!`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnCriticality {
+    /// Node can't work without the data, errors are returned as is
+    Critical,
+    /// Data is derived from other columns, so the column is marked degraded and rebuilt
+    Rebuildable(RebuildStrategy),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RebuildStrategy {
+    /// Marker of the complete index is deleted, so the owner builds the index by scan again
+    ResetMarker(&'static [u8]),
+    /// Owner restores the entries from other data when it gets the degraded result
+    Restore,
+}
+
+lazy_static::lazy_static! {
+    // Column name -> first corruption error, until the column is rebuilt
+    static ref DEGRADED_COLUMNS: parking_lot::Mutex<BTreeMap<String, String>> =
+        parking_lot::Mutex::new(BTreeMap::new());
+}
+
+pub fn is_corruption_error(error: &Error) -> bool {
+    if let Some(error) = error.downcast_ref::<rocksdb::Error>() {
+        return error.kind() == rocksdb::ErrorKind::Corruption
+    }
+    matches!(error.downcast_ref::<StorageError>(), Some(StorageError::Corruption(_)))
+}
+
+/// Names of columns found corrupted and not rebuilt yet
+pub fn degraded_columns() -> Vec<String> {
+    DEGRADED_COLUMNS.lock().keys().cloned().collect()
+}
+
+pub fn is_degraded(column: &str) -> bool {
+    DEGRADED_COLUMNS.lock().contains_key(column)
+}
+
+// Returns false if the column is already degraded
+pub(crate) fn mark_degraded(column: &str, error: &Error) -> bool {
+    let mut degraded = DEGRADED_COLUMNS.lock();
+    if degraded.contains_key(column) {
+        log::warn!(target: "storage", "Degraded column {} is still corrupted: {}", column, error);
+        return false
+    }
+    log::error!(
+        target: "storage",
+        "CORRUPTION of column {}: {}. Column is degraded, slow path is used until it is rebuilt",
+        column, error
+    );
+    degraded.insert(column.to_string(), error.to_string());
+    metrics::gauge!("storage_degraded_columns", degraded.len() as f64);
+    true
+}
+
+pub fn mark_rebuilt(column: &str) {
+    let mut degraded = DEGRADED_COLUMNS.lock();
+    if degraded.remove(column).is_some() {
+        log::info!(target: "storage", "Degraded column {} is rebuilt", column);
+        metrics::gauge!("storage_degraded_columns", degraded.len() as f64);
+    }
+}
+
+/// Corruption of rebuildable column gives None, so the caller takes the slow path, and the
+/// rebuild is scheduled by `schedule_rebuild`. Any other error is returned as is.
+pub fn degrade_on_corruption<T>(
+    column: &str,
+    criticality: ColumnCriticality,
+    result: Result<T>,
+    schedule_rebuild: impl FnOnce(RebuildStrategy) -> Result<()>
+) -> Result<Option<T>> {
+    let error = match result {
+        Ok(value) => return Ok(Some(value)),
+        Err(error) => error
+    };
+    let ColumnCriticality::Rebuildable(strategy) = criticality else {
+        return Err(error)
+    };
+    if !is_corruption_error(&error) {
+        return Err(error)
+    }
+    if mark_degraded(column, &error) {
+        if let Err(e) = schedule_rebuild(strategy) {
+            log::error!(target: "storage", "Can't schedule rebuild of column {}: {}", column, e);
+        }
+    }
+    Ok(None)
+}
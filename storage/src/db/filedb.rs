@@ -17,11 +17,12 @@ use crate::{
 };
 use std::{io::{ErrorKind, SeekFrom, Write, Read, Seek}, path::{Path, PathBuf}};
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
-use ever_block::{error, Error, Result};
+use ever_block::{error, fail, Error, Result};
 
 #[derive(Debug)]
 pub struct FileDb {
     path: PathBuf,
+    read_only: bool,
 }
 
 static PATH_CHUNK_MAX_LEN: usize = 4;
@@ -31,6 +32,15 @@ impl FileDb {
     pub fn with_path<P: AsRef<Path>>(path: P) -> Self {
         Self {
             path: path.as_ref().to_path_buf(),
+            read_only: false,
+        }
+    }
+
+    /// Files are only read, no directories are created
+    pub fn read_only<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            read_only: true,
         }
     }
 
@@ -39,6 +49,7 @@ impl FileDb {
     }
 
     pub async fn destroy(&mut self) -> Result<bool> {
+        self.check_writable()?;
         match tokio::fs::metadata(&self.path).await {
             Ok(meta) if meta.is_dir() => tokio::fs::remove_dir_all(&self.path).await?,
             _ => ()
@@ -47,6 +58,7 @@ impl FileDb {
     }
 
     pub fn get_write_object(&self, key: &(dyn DbKey + Send + Sync)) -> Result<impl Write> {
+        self.check_writable()?;
         let path = self.make_path(key.key());
         let dir = path.parent().ok_or_else(|| error!("Unable to get parent path"))?;
         std::fs::create_dir_all(dir)?;
@@ -61,6 +73,7 @@ impl FileDb {
     }
 
     pub async fn write_whole_file(&self, key: &(dyn DbKey + Send + Sync), data: &[u8]) -> Result<()> {
+        self.check_writable()?;
         let path = self.make_path(key.key());
         let dir = path.parent()
             .ok_or_else(|| error!("Unable to get parent path"))?;
@@ -70,6 +83,18 @@ impl FileDb {
         Ok(())
     }
 
+    // Moves already written file under the key, so the file is never seen partially written
+    pub async fn move_file_into(&self, key: &(dyn DbKey + Send + Sync), src: &Path) -> Result<()> {
+        self.check_writable()?;
+        let path = self.make_path(key.key());
+        let dir = path.parent()
+            .ok_or_else(|| error!("Unable to get parent path"))?;
+        tokio::fs::create_dir_all(dir).await?;
+        tokio::fs::rename(src, path).await?;
+
+        Ok(())
+    }
+
     pub async fn read_whole_file(&self, key: &(dyn DbKey + Send + Sync)) -> Result<Vec<u8>> {
        self.read_file_part(key, 0, self.get_file_size(key).await?).await
     }
@@ -86,6 +111,13 @@ impl FileDb {
         Ok(result)
     }
 
+    pub async fn open_file(&self, key: &(dyn DbKey + Send + Sync)) -> Result<tokio::fs::File> {
+        let path = self.make_path(key.key());
+        let file = tokio::fs::File::open(path).await
+            .map_err(|err| Self::transform_io_error(err, key.key()))?;
+        Ok(file)
+    }
+
     pub async fn get_file_size(&self, key: &(dyn DbKey + Send + Sync)) -> Result<u64> {
         let path = self.make_path(key.key());
         let metadata = tokio::fs::metadata(path).await
@@ -104,6 +136,7 @@ impl FileDb {
     }
 
     pub async fn delete_file(&self, key: &(dyn DbKey + Send + Sync)) -> Result<()> {
+        self.check_writable()?;
         let path = self.make_path(key.key());
         if let Err(err) = tokio::fs::remove_file(&path).await {
             if err.kind() != ErrorKind::NotFound {
@@ -126,6 +159,13 @@ impl FileDb {
         Ok(())
     }
 
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            fail!(StorageError::ReadOnly)
+        }
+        Ok(())
+    }
+
     pub(crate) fn make_path(&self, key: &[u8]) -> PathBuf {
         let mut key_str = hex::encode(key);
         let mut result = self.path.clone();
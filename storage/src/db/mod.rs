@@ -13,9 +13,10 @@
 
 pub mod traits;
 pub mod async_adapter;
+pub mod degradation;
 pub mod rocksdb;
 pub mod memorydb;
 pub mod filedb;
 
 #[cfg(test)]
-mod tests;
+pub(crate) mod tests;
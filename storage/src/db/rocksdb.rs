@@ -15,13 +15,12 @@ use crate::{
     db::traits::{
         DbKey, Kvc, KvcReadable, KvcSnapshotable, KvcTransaction, KvcTransactional, KvcWriteable,
     },
-    traits::Serializable,
-    types::DbSlice
+    error::StorageError, fsync::FsyncControl, traits::Serializable, types::DbSlice
 };
 use adnl::common::add_unbound_object_to_map;
 use rocksdb::{
     BlockBasedOptions, BoundColumnFamily, Cache, DBWithThreadMode, IteratorMode, MultiThreaded, 
-    Options, SnapshotWithThreadMode, WriteBatch
+    Options, SnapshotWithThreadMode, WriteBatch, WriteOptions
 };
 use std::{
     fmt::{Debug, Formatter}, ops::Deref, path::Path, sync::{Arc, atomic::{AtomicI32, Ordering}},
@@ -37,7 +36,8 @@ pub const NODE_STATE_DB_NAME: &str = "node_state_db";
 pub struct RocksDb {
     db: Option<DBWithThreadMode<MultiThreaded>>,
     locks: lockfree::map::Map<String, AtomicI32>,
-    hi_perf_cfs: HashSet<String>
+    hi_perf_cfs: HashSet<String>,
+    read_only: bool
 }
 
 impl RocksDb {
@@ -109,6 +109,7 @@ impl RocksDb {
                 db: Some(db),
                 locks: lockfree::map::Map::new(),
                 hi_perf_cfs,
+                read_only,
             };
             return Ok(Arc::new(db))
         }
@@ -247,8 +248,21 @@ impl RocksDb {
         self.db.as_ref().expect("rocksdb was occasionaly destroyed")
     }
 
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fails if the database is opened read-only, so writes are refused before RocksDB call
+    pub fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            fail!(StorageError::ReadOnly)
+        }
+        Ok(())
+    }
+
     // Error is occured if column family is already created
     fn create_cf(&self, name: &str) -> Result<()> {
+        self.check_writable()?;
         let opt = if self.hi_perf_cfs.contains(name) {
             Self::build_hi_perf_cf_options()
         } else {
@@ -259,6 +273,7 @@ impl RocksDb {
     }
 
     pub fn drop_table(&self, name: &str) -> Result<bool> {
+        self.check_writable()?;
         if let Some(lock) = self.locks.get(name) {
             let lock = lock.val();
             if lock.compare_exchange(0, -1000000, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
@@ -273,6 +288,7 @@ impl RocksDb {
     }
 
     pub fn drop_table_force(&self, name: &str) -> Result<()> {
+        self.check_writable()?;
         if self.drop_table(name).is_err() {
             self.db().drop_cf(name)?;
             self.locks.remove(name);
@@ -285,6 +301,27 @@ impl RocksDb {
             .ok_or_else(|| error!("no handle for column family {} in rocksdb", name))
     }
 
+    /// Returns names of all column families of the database
+    pub fn column_families(&self) -> Result<Vec<String>> {
+        Ok(DBWithThreadMode::<MultiThreaded>::list_cf(&Options::default(), self.db().path())?)
+    }
+
+    /// Returns estimated count of keys and size in bytes (SST files and memtables) of column 
+    /// family or None if there is no such family. Data is not scanned, RocksDB properties are used.
+    pub fn cf_usage(&self, name: &str) -> Result<Option<(u64, u64)>> {
+        let cf = match self.db().cf_handle(name) {
+            Some(cf) => cf,
+            None => return Ok(None)
+        };
+        let property = |property: &str| -> Result<u64> {
+            Ok(self.db().property_int_value_cf(&cf, property)?.unwrap_or(0))
+        };
+        let keys = property("rocksdb.estimate-num-keys")?;
+        let bytes = property("rocksdb.total-sst-files-size")? + 
+            property("rocksdb.cur-size-all-mem-tables")?;
+        Ok(Some((keys, bytes)))
+    }
+
     pub fn destroy_db(path: impl AsRef<Path>) -> Result<bool> {
         let opts = Options::default();
         match DBWithThreadMode::<MultiThreaded>::destroy(&opts, path.as_ref()) {
@@ -339,10 +376,12 @@ impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDb {
 
 impl<K: DbKey + Send + Sync> KvcWriteable<K> for RocksDb {
     fn put_raw(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.check_writable()?;
         Ok(self.db().put(key, value)?)
     }
 
     fn delete_raw(&self, key: &[u8]) -> Result<()> {
+        self.check_writable()?;
         Ok(self.db().delete(key)?)
     }
 }
@@ -371,6 +410,8 @@ impl AsRef<DBWithThreadMode<MultiThreaded>> for RocksDb {
 pub struct RocksDbTable {
     db: Arc<RocksDb>,
     family: String,
+    // Writes are synced to disk before completion
+    sync: bool,
 }
 
 impl RocksDbTable {
@@ -386,7 +427,10 @@ impl RocksDbTable {
                 break
             }
             if let Err(e) = db.cf(&family) {
-                if create_if_not_exist {
+                if db.read_only {
+                    // Nothing is created, reads of the missing table fail
+                    log::warn!(target: "storage", "Table {} is not found in read-only DB", family);
+                } else if create_if_not_exist {
                     db.create_cf(&family)?;
                 } else {
                     fail!(e)
@@ -400,15 +444,32 @@ impl RocksDbTable {
         }
         let ret = Self {
             db,
-            family
+            family,
+            sync: false
         };
         Ok(ret)
     }
 
+    pub fn with_fsync(mut self, fsync: &FsyncControl) -> Self {
+        fsync.register_db(&self.db);
+        self.sync = fsync.is_sync_write();
+        self
+    }
+
     fn cf(&self) -> Result<Arc<BoundColumnFamily>> {
         self.db.cf(&self.family)
     }
 
+    fn write_options(&self) -> WriteOptions {
+        write_options(self.sync)
+    }
+
+}
+
+fn write_options(sync: bool) -> WriteOptions {
+    let mut options = WriteOptions::default();
+    options.set_sync(sync);
+    options
 }
 
 impl AsRef<DBWithThreadMode<MultiThreaded>> for RocksDbTable {
@@ -456,6 +517,21 @@ impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDbTable {
         fail!("Attempt to read from dropped table {}", self.family)
     }
 
+    fn try_get_raw_batch(&self, keys: &[&[u8]]) -> Result<Vec<Option<DbSlice>>> {
+        if let Some(lock) = self.db.locks.get(&self.family) {
+            let lock = lock.val();
+            if lock.fetch_add(1, Ordering::Relaxed) >= 0 {
+                let ret = self.db.batched_multi_get_cf(&self.cf()?, keys, false)
+                    .into_iter()
+                    .map(|value| Ok(value?.map(|value| value.into())))
+                    .collect::<Result<Vec<_>>>();
+                lock.fetch_sub(1, Ordering::Relaxed);
+                return ret
+            }
+        }
+        fail!("Attempt to read from dropped table {}", self.family)
+    }
+
     fn for_each(&self, predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<bool> {
         if let Some(lock) = self.db.locks.get(&self.family) {
             let lock = lock.val();
@@ -487,10 +563,11 @@ impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDbTable {
 impl<K: DbKey + Send + Sync> KvcWriteable<K> for RocksDbTable {
 
     fn put_raw(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.db.check_writable()?;
         if let Some(lock) = self.db.locks.get(&self.family) {
             let lock = lock.val();
             if lock.fetch_add(1, Ordering::Relaxed) >= 0 {
-                let ret = self.db.put_cf(&self.cf()?, key, value);
+                let ret = self.db.put_cf_opt(&self.cf()?, key, value, &self.write_options());
                 lock.fetch_sub(1, Ordering::Relaxed);
                 return Ok(ret?)
             }
@@ -499,10 +576,11 @@ impl<K: DbKey + Send + Sync> KvcWriteable<K> for RocksDbTable {
     }
 
     fn delete_raw(&self, key: &[u8]) -> Result<()> {
+        self.db.check_writable()?;
         if let Some(lock) = self.db.locks.get(&self.family) {
             let lock = lock.val();
             if lock.fetch_add(1, Ordering::Relaxed) >= 0 {
-                let ret = self.db.delete_cf(&self.cf()?, key);
+                let ret = self.db.delete_cf_opt(&self.cf()?, key, &self.write_options());
                 lock.fetch_sub(1, Ordering::Relaxed);
                 return Ok(ret?)
             }
@@ -510,6 +588,25 @@ impl<K: DbKey + Send + Sync> KvcWriteable<K> for RocksDbTable {
         fail!("Attempt to delete from dropped table {}", self.family)
     }
 
+    fn delete_raw_batch(&self, keys: &[&[u8]]) -> Result<()> {
+        self.db.check_writable()?;
+        if let Some(lock) = self.db.locks.get(&self.family) {
+            let lock = lock.val();
+            if lock.fetch_add(1, Ordering::Relaxed) >= 0 {
+                let ret = self.cf().and_then(|cf| {
+                    let mut batch = WriteBatch::default();
+                    for key in keys {
+                        batch.delete_cf(&cf, key);
+                    }
+                    Ok(self.db.write_opt(batch, &self.write_options())?)
+                });
+                lock.fetch_sub(1, Ordering::Relaxed);
+                return ret
+            }
+        }
+        fail!("Attempt to delete from dropped table {}", self.family)
+    }
+
 }
 
 /// Implementation of support for take snapshots for RocksDB.
@@ -576,7 +673,7 @@ impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDbSnapshot<'_> {
 /// Implementation of transaction support for key-value collection for RocksDB.
 impl<K: DbKey + Send + Sync> KvcTransactional<K> for RocksDbTable {
     fn begin_transaction(&self) -> Result<Box<dyn KvcTransaction<K>>> {
-        Ok(Box::new(RocksDbTransaction::new(self.db.clone(), self.family.clone())))
+        Ok(Box::new(RocksDbTransaction::new(self.db.clone(), self.family.clone(), self.sync)))
     }
 }
 
@@ -585,16 +682,18 @@ impl<K: DbKey + Send + Sync> KvcTransactional<K> for RocksDbTable {
 pub struct RocksDbTransaction {
     db: Arc<RocksDb>,
     batch: Option<WriteBatch>,
-    family: String
+    family: String,
+    sync: bool
 }
 
 /// Implementation of transaction for key-value collection for RocksDB.
 impl RocksDbTransaction {
-    fn new(db: Arc<RocksDb>, family: String) -> Self {
+    fn new(db: Arc<RocksDb>, family: String, sync: bool) -> Self {
         Self {
             db,
             batch: Some(WriteBatch::default()),
             family,
+            sync,
         }
     }
     fn cf(&self) -> Result<Arc<BoundColumnFamily>> {
@@ -622,7 +721,8 @@ impl<'db, K: DbKey + Send + Sync> KvcTransaction<K> for RocksDbTransaction {
     }
 
     fn commit(self: Box<Self>) -> Result<()> {
-        Ok(self.db.write(self.batch.unwrap())?)
+        self.db.check_writable()?;
+        Ok(self.db.write_opt(self.batch.unwrap(), &write_options(self.sync))?)
     }
 
     fn len(&self) -> usize {
@@ -11,14 +11,22 @@
 * limitations under the License.
 */
 
+pub mod test_degradation;
 pub mod test_filedb;
 pub mod test_memorydb;
 pub mod test_rocksdb;
 
 pub mod utils {
  
-    use crate::{db::traits::DbKey, error::StorageError};
-    use ever_block::Result;
+    use crate::{
+        db::{
+            memorydb::MemoryDb,
+            traits::{DbKey, Kvc, KvcReadable, KvcTransaction, KvcTransactional, KvcWriteable}
+        },
+        error::StorageError, types::DbSlice
+    };
+    use std::{collections::HashSet, sync::Arc};
+    use ever_block::{Error, Result};
 
     pub const KEY0: &[u8] = b"key0";
     pub const KEY1: &[u8] = b"key1";
@@ -52,6 +60,102 @@ pub mod utils {
         }
     }
 
+    /// Collection which fails to read marked keys, like RocksDB with damaged SST file.
+    /// Rewritten key is readable again.
+    #[derive(Clone, Debug)]
+    pub struct CorruptedDb {
+        name: String,
+        db: MemoryDb,
+        corrupted: Arc<parking_lot::Mutex<HashSet<Vec<u8>>>>,
+    }
+
+    impl CorruptedDb {
+        pub fn new(name: &str) -> Self {
+            Self {
+                name: name.to_string(),
+                db: MemoryDb::new(),
+                corrupted: Arc::new(parking_lot::Mutex::new(HashSet::new()))
+            }
+        }
+
+        pub fn corrupt(&self, key: &[u8]) {
+            self.corrupted.lock().insert(key.to_vec());
+        }
+
+        pub fn is_corrupted(&self, key: &[u8]) -> bool {
+            self.corrupted.lock().contains(key)
+        }
+
+        pub fn keys(&self) -> Vec<Vec<u8>> {
+            let mut keys = Vec::new();
+            KvcReadable::<&[u8]>::for_each(&self.db, &mut |key, _| {
+                keys.push(key.to_vec());
+                Ok(true)
+            }).unwrap();
+            keys
+        }
+
+        fn check(&self, key: &[u8]) -> Result<()> {
+            if self.is_corrupted(key) {
+                return Err(corruption(key))
+            }
+            Ok(())
+        }
+    }
+
+    fn corruption(key: &[u8]) -> Error {
+        StorageError::Corruption(format!("block checksum mismatch, key {:?}", key)).into()
+    }
+
+    impl Kvc for CorruptedDb {
+        fn len(&self) -> Result<usize> {
+            self.db.len()
+        }
+
+        fn destroy(&mut self) -> Result<bool> {
+            self.db.destroy()
+        }
+    }
+
+    impl<K: DbKey + Send + Sync> KvcReadable<K> for CorruptedDb {
+        fn get_meta(&self) -> &str {
+            &self.name
+        }
+
+        fn try_get_raw(&self, key: &[u8]) -> Result<Option<DbSlice>> {
+            self.check(key)?;
+            KvcReadable::<K>::try_get_raw(&self.db, key)
+        }
+
+        fn for_each(
+            &self,
+            predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+        ) -> Result<bool> {
+            KvcReadable::<K>::for_each(&self.db, &mut |key, value| {
+                self.check(key)?;
+                predicate(key, value)
+            })
+        }
+    }
+
+    impl<K: DbKey + Send + Sync> KvcWriteable<K> for CorruptedDb {
+        fn put_raw(&self, key: &[u8], value: &[u8]) -> Result<()> {
+            self.corrupted.lock().remove(key);
+            KvcWriteable::<K>::put_raw(&self.db, key, value)
+        }
+
+        fn delete_raw(&self, key: &[u8]) -> Result<()> {
+            self.corrupted.lock().remove(key);
+            KvcWriteable::<K>::delete_raw(&self.db, key)
+        }
+    }
+
+    impl<K: DbKey + Send + Sync> KvcTransactional<K> for CorruptedDb {
+        fn begin_transaction(&self) -> Result<Box<dyn KvcTransaction<K>>> {
+            KvcTransactional::<K>::begin_transaction(&self.db)
+        }
+    }
+
     pub fn expect_error<T>(result: Result<T>, expected_error: StorageError) {
         match result {
             Ok(_) => panic!("We don't expect any value to return"),
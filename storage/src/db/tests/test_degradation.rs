@@ -0,0 +1,70 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    db_impl_base,
+    db::{
+        degradation::{degraded_columns, is_corruption_error, is_degraded, RebuildStrategy},
+        tests::utils::CorruptedDb, traits::{DbKey, KvcReadable, KvcWriteable, U32Key}
+    },
+    error::StorageError
+};
+
+const MARKER: &[u8] = b"complete";
+
+db_impl_base!(TestIndexDb, KvcWriteable, U32Key, rebuild: RebuildStrategy::ResetMarker(MARKER));
+db_impl_base!(TestCriticalDb, KvcWriteable, U32Key);
+
+#[test]
+fn test_degradation_of_rebuildable_column() {
+    let kvc = CorruptedDb::new("test_degradation_index");
+    let db = TestIndexDb::with_kvc(Box::new(kvc.clone()));
+    assert_eq!(db.column_name(), "test_degradation_index");
+    db.put(&1.into(), &[1]).unwrap();
+    db.put(&2.into(), &[2]).unwrap();
+    db.put_raw(MARKER, &[1]).unwrap();
+    kvc.corrupt(U32Key::with_value(1).key());
+
+    // Corrupted read takes the slow path, marker is reset for rebuild
+    assert!(db.degrade_on_corruption(db.try_get(&1.into())).unwrap().is_none());
+    assert!(is_degraded("test_degradation_index"));
+    assert!(degraded_columns().contains(&"test_degradation_index".to_string()));
+    assert!(db.try_get_raw(MARKER).unwrap().is_none());
+
+    // Other keys and other errors are as usual
+    let value = db.degrade_on_corruption(db.try_get(&2.into())).unwrap().unwrap().unwrap();
+    assert_eq!(value.as_ref(), &[2]);
+    let error = db.degrade_on_corruption::<()>(Err(StorageError::ReadOnly.into())).unwrap_err();
+    assert!(!is_corruption_error(&error));
+
+    // Rebuild by the owner
+    db.put(&1.into(), &[1]).unwrap();
+    db.put_raw(MARKER, &[1]).unwrap();
+    db.set_rebuilt();
+    assert!(!is_degraded("test_degradation_index"));
+    let value = db.degrade_on_corruption(db.try_get(&1.into())).unwrap().unwrap().unwrap();
+    assert_eq!(value.as_ref(), &[1]);
+}
+
+#[test]
+fn test_corruption_of_critical_column() {
+    let kvc = CorruptedDb::new("test_degradation_critical");
+    let db = TestCriticalDb::with_kvc(Box::new(kvc.clone()));
+    db.put(&1.into(), &[1]).unwrap();
+    kvc.corrupt(U32Key::with_value(1).key());
+
+    let error = db.degrade_on_corruption(db.try_get(&1.into())).unwrap_err();
+    assert!(is_corruption_error(&error));
+    assert!(!is_degraded("test_degradation_critical"));
+    assert!(kvc.is_corrupted(U32Key::with_value(1).key()));
+}
@@ -210,4 +210,24 @@ async fn test_for_each_key() -> Result<()> {
     assert_eq!(sum, 5050);
 
     Ok(())
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_read_only() -> Result<()> {
+    let db = AutoDestroyableDb::new("filedb_test_read_only");
+    db.write_whole_file(&KEY0, &[0]).await?;
+
+    let read_only = FileDb::read_only(db.path());
+    assert_eq!(&read_only.read_whole_file(&KEY0).await?, &[0]);
+    expect_error(read_only.write_whole_file(&KEY1, &[1]).await, StorageError::ReadOnly);
+    expect_error(read_only.delete_file(&KEY0).await, StorageError::ReadOnly);
+    assert!(read_only.get_write_object(&KEY1).is_err());
+    assert_eq!(&db.read_whole_file(&KEY0).await?, &[0]);
+    expect_key_not_found_error(db.read_whole_file(&KEY1).await, KEY1);
+
+    // Nothing is created for missing store
+    let missing = FileDb::read_only("filedb_test_read_only_missing");
+    expect_error(missing.write_whole_file(&KEY0, &[0]).await, StorageError::ReadOnly);
+    assert!(!missing.path().exists());
+
+    Ok(())
+}
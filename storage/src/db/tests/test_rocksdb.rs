@@ -199,6 +199,44 @@ async fn test_get_slice() -> Result<()> {
 
 }
 
+#[tokio::test]
+async fn test_read_only() -> Result<()> {
+
+    const DB_NAME: &str = "test_read_only";
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME)?;
+    let tb = RocksDbTable::with_db(db.clone(), "test", true)?;
+    tb.put(&KEY0, &[0])?;
+    drop(tb);
+    drop(db);
+
+    let db = RocksDb::read_only(DB_PATH, DB_NAME)?;
+    assert!(db.is_read_only());
+    let tb = RocksDbTable::with_db(db.clone(), "test", true)?;
+    assert_eq!(tb.get(&KEY0)?.as_ref(), &[0]);
+    expect_error(tb.put(&KEY1, &[1]), StorageError::ReadOnly);
+    expect_error(tb.delete(&KEY0), StorageError::ReadOnly);
+    expect_error(KvcWriteable::<&[u8]>::delete_raw_batch(&tb, &[KEY0]), StorageError::ReadOnly);
+    let mut transaction = tb.begin_transaction()?;
+    transaction.put(&KEY1, &[1])?;
+    expect_error(transaction.commit(), StorageError::ReadOnly);
+    expect_error(db.drop_table("test"), StorageError::ReadOnly);
+    assert_eq!(tb.get(&KEY0)?.as_ref(), &[0]);
+    expect_key_not_found_error(tb.get(&KEY1), KEY1);
+
+    // Missing table is not created, reading it fails without panic
+    let missing = RocksDbTable::with_db(db.clone(), "missing", true)?;
+    assert!(missing.get(&KEY0).is_err());
+    assert_eq!(db.column_families()?.iter().filter(|cf| *cf == "missing").count(), 0);
+
+    drop(missing);
+    drop(tb);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+    Ok(())
+
+}
+
 #[tokio::test]
 async fn test_db_open_and_create_column_family() {
 
@@ -43,6 +43,10 @@ pub trait KvcReadable<K: DbKey + Send + Sync>: Kvc {
         self.try_get_raw(key.key())
     }
 
+    fn try_get_raw_batch(&self, keys: &[&[u8]]) -> Result<Vec<Option<DbSlice>>> {
+        keys.iter().map(|key| self.try_get_raw(key)).collect()
+    }
+
     /// Gets value from collection by the key
     fn get(&self, key: &K) -> Result<DbSlice> {
         self.try_get(key)?.ok_or_else(|| {
@@ -102,6 +106,13 @@ pub trait KvcWriteable<K: DbKey + Send + Sync>: KvcReadable<K> {
     }
     
     fn delete_raw(&self, key: &[u8]) -> Result<()>;
+
+    fn delete_raw_batch(&self, keys: &[&[u8]]) -> Result<()> {
+        for key in keys {
+            self.delete_raw(key)?;
+        }
+        Ok(())
+    }
 }
 
 /// Trait for key-value collections with the ability of take snapshots
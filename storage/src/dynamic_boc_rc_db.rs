@@ -200,7 +200,19 @@ impl DynamicBocDb {
     }
 
     pub fn cells_cache_len(&self) -> usize {
-        self.raw_cells_cache.0.len()
+        self.raw_cells_cache.stats().len
+    }
+
+    pub fn cells_cache_stats(&self) -> CellsCacheStats {
+        self.raw_cells_cache.stats()
+    }
+
+    pub(crate) fn set_cells_cache_capacity(&self, size_in_bytes: u64) {
+        self.raw_cells_cache.set_capacity(size_in_bytes)
+    }
+
+    pub(crate) fn resize_cells_cache_step(&self) -> bool {
+        self.raw_cells_cache.resize_step()
     }
 
     // Is not thread-safe!
@@ -953,9 +965,49 @@ impl OrderedCellsStorage for OrderedCellsStorageAdapter {
     }
 }
 
-struct RawCellsCache(
-    quick_cache::sync::Cache<UInt256, bytes::Bytes, CellSizeEstimator, ahash::RandomState>
-);
+type CellsCache = quick_cache::sync::Cache<
+    UInt256, bytes::Bytes, CellSizeEstimator, ahash::RandomState, EvictionCounter
+>;
+
+// Cache is split into segments which are resized one by one. Resized segment keeps its
+// previous cache for a while, hits there are moved to the new one, so the whole cache
+// is never dropped at once.
+const CACHE_SEGMENTS: usize = 16;
+
+struct CacheSegment {
+    capacity: u64,
+    current: CellsCache,
+    previous: Option<CellsCache>,
+    migrated: AtomicU64,
+}
+
+#[derive(Clone)]
+struct EvictionCounter(Arc<AtomicU64>);
+impl quick_cache::Lifecycle<UInt256, bytes::Bytes> for EvictionCounter {
+    type RequestState = ();
+    fn begin_request(&self) -> Self::RequestState {}
+    fn on_evict(&self, _state: &mut Self::RequestState, _key: UInt256, _val: bytes::Bytes) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CellsCacheStats {
+    pub capacity: u64,
+    pub resident_bytes: u64,
+    pub len: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct RawCellsCache {
+    segments: Vec<parking_lot::RwLock<CacheSegment>>,
+    capacity: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: Arc<AtomicU64>,
+}
 
 #[derive(Clone, Copy)]
 pub struct CellSizeEstimator;
@@ -970,6 +1022,28 @@ impl quick_cache::Weighter<UInt256, bytes::Bytes> for CellSizeEstimator {
 impl RawCellsCache {
 
     fn new(size_in_bytes: u64) -> Self {
+        let evictions = Arc::new(AtomicU64::new(0));
+        let segment_size = Self::segment_size(size_in_bytes);
+        let segments = (0..CACHE_SEGMENTS).map(|_| parking_lot::RwLock::new(CacheSegment {
+            capacity: segment_size,
+            current: Self::create_cache(segment_size, &evictions),
+            previous: None,
+            migrated: AtomicU64::new(0)
+        })).collect();
+        Self {
+            segments,
+            capacity: AtomicU64::new(size_in_bytes),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions
+        }
+    }
+
+    fn segment_size(size_in_bytes: u64) -> u64 {
+        (size_in_bytes / CACHE_SEGMENTS as u64).max(1)
+    }
+
+    fn create_cache(size_in_bytes: u64, evictions: &Arc<AtomicU64>) -> CellsCache {
 
         // Percentile 0.1%    from  96 to 127  => 1725119 count
         // Percentile 10%     from 128 to 191  => 82838849 count
@@ -997,24 +1071,83 @@ impl RawCellsCache {
 
         let estimated_cell_cache_capacity = size_in_bytes / (KEY_SIZE + MAX_CELL_SIZE);
         log::trace!("{estimated_cell_cache_capacity},{size_in_bytes}");
-        let raw_cache = quick_cache::sync::Cache::with(
+        quick_cache::sync::Cache::with(
             estimated_cell_cache_capacity as usize,
             size_in_bytes,
             CellSizeEstimator,
             ahash::RandomState::default(),
-            quick_cache::sync::DefaultLifecycle::default()
-        );
-        Self(raw_cache)
+            EvictionCounter(evictions.clone())
+        )
+    }
 
+    fn segment(&self, key: &UInt256) -> &parking_lot::RwLock<CacheSegment> {
+        &self.segments[key.as_slice()[0] as usize % CACHE_SEGMENTS]
     }
 
     fn get_or_insert(&self, db: &Arc<CellDb>, key: &UInt256) -> Result<bytes::Bytes> {
-        if let Some(value) = self.0.get(key) {
+        let segment = self.segment(key).read();
+        if let Some(value) = segment.current.get(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+        if let Some(value) = segment.previous.as_ref().and_then(|previous| previous.get(key)) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            segment.migrated.fetch_add(1, Ordering::Relaxed);
+            segment.current.insert(key.clone(), value.clone());
             return Ok(value);
         }
+        self.misses.fetch_add(1, Ordering::Relaxed);
         let value = bytes::Bytes::copy_from_slice(&db.get(key)?);
-        self.0.insert(key.clone(), value.clone());
+        segment.current.insert(key.clone(), value.clone());
         Ok(value)
     }
 
+    fn set_capacity(&self, size_in_bytes: u64) {
+        self.capacity.store(size_in_bytes, Ordering::Relaxed);
+    }
+
+    // Makes one step of resize to the requested capacity: drops previous caches left
+    // by last step and replaces the cache of one segment. Returns true if resize is done.
+    fn resize_step(&self) -> bool {
+        for segment in self.segments.iter() {
+            let mut segment = segment.write();
+            if let Some(previous) = segment.previous.take() {
+                // Moved cells are still in the current cache
+                let migrated = segment.migrated.swap(0, Ordering::Relaxed);
+                let evicted = (previous.len() as u64).saturating_sub(migrated);
+                self.evictions.fetch_add(evicted, Ordering::Relaxed);
+            }
+        }
+        let segment_size = Self::segment_size(self.capacity.load(Ordering::Relaxed));
+        for segment in self.segments.iter() {
+            if segment.read().capacity == segment_size {
+                continue
+            }
+            let cache = Self::create_cache(segment_size, &self.evictions);
+            let mut segment = segment.write();
+            segment.capacity = segment_size;
+            segment.previous = Some(std::mem::replace(&mut segment.current, cache));
+            return false
+        }
+        true
+    }
+
+    fn stats(&self) -> CellsCacheStats {
+        let mut stats = CellsCacheStats {
+            capacity: self.capacity.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for segment in self.segments.iter() {
+            let segment = segment.read();
+            for cache in std::iter::once(&segment.current).chain(segment.previous.iter()) {
+                stats.resident_bytes += cache.weight();
+                stats.len += cache.len();
+            }
+        }
+        stats
+    }
+
 }
\ No newline at end of file
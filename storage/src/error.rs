@@ -39,4 +39,16 @@ pub enum StorageError {
 
     #[error("Attempt to load state {0} which is already allowed to GC")]
     StateIsAllowedToGc(BlockIdExt),
+
+    /// Storage is opened for reading only
+    #[error("Storage is opened read-only")]
+    ReadOnly,
+
+    /// Some of flags expected to be clear are set
+    #[error("Block flags {0:#x} have some of expected clear flags {1:#x}")]
+    FlagsPreconditionFailed(u32, u32),
+
+    /// Data read from the database is corrupted
+    #[error("Corruption: {0}")]
+    Corruption(String),
 }
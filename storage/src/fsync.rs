@@ -0,0 +1,244 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{db::rocksdb::RocksDb, TARGET};
+use ever_block::{fail, Result};
+use std::{
+    collections::HashSet, io::ErrorKind, path::{Path, PathBuf},
+    sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}, time::Duration,
+};
+
+#[cfg(test)]
+#[path = "tests/test_fsync.rs"]
+mod tests;
+
+// When data written by storage component is synced to disk
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    // Every write is synced before it is completed
+    Always,
+    // Writes are synced by background flusher with given period in seconds
+    Periodic(u64),
+    // Syncing is left to OS
+    #[default]
+    Never,
+}
+
+impl FsyncPolicy {
+    // Max time in seconds while written data may be not synced, None is unlimited
+    fn max_lag_sec(&self) -> Option<u64> {
+        match self {
+            FsyncPolicy::Always => Some(0),
+            FsyncPolicy::Periodic(period) => Some(*period),
+            FsyncPolicy::Never => None,
+        }
+    }
+
+    pub fn is_weaker_than(&self, other: &FsyncPolicy) -> bool {
+        match (self.max_lag_sec(), other.max_lag_sec()) {
+            (Some(lag), Some(other)) => lag > other,
+            (None, Some(_)) => true,
+            (_, None) => false,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct FsyncConfig {
+    pub block_handles: FsyncPolicy,
+    pub node_state: FsyncPolicy,
+    pub block_data: FsyncPolicy,
+    pub cells: FsyncPolicy,
+}
+
+impl FsyncConfig {
+
+    // Block handles keep applied flags of blocks, and node state keeps pointers to the last
+    // applied blocks. A flag must not be persisted with weaker durability than the block data
+    // and the state cells it refers to, otherwise after a crash the data may survive without
+    // the flags, then it is stored once more and append-only packages get duplicated entries.
+    pub fn check(&self) -> Result<()> {
+        let components = [
+            ("block handles", &self.block_handles), ("node state", &self.node_state),
+            ("block data", &self.block_data), ("cells", &self.cells),
+        ];
+        for (name, policy) in components {
+            if *policy == FsyncPolicy::Periodic(0) {
+                fail!("Fsync period of {} must be positive", name)
+            }
+        }
+        for (flag_name, flag) in &components[..2] {
+            for (data_name, data) in &components[2..] {
+                if flag.is_weaker_than(data) {
+                    fail!(
+                        "Fsync policy of {} ({:?}) is weaker than one of {} ({:?})",
+                        flag_name, flag, data_name, data
+                    )
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Fsync policy of one storage component. Components share RocksDB write-ahead log,
+// so syncing of it makes durable all the previous writes of other components too,
+// which is never weaker than their own policies.
+pub struct FsyncControl {
+    name: &'static str,
+    policy: FsyncPolicy,
+    dbs: parking_lot::Mutex<Vec<Arc<RocksDb>>>,
+    dirty_files: parking_lot::Mutex<HashSet<PathBuf>>,
+    flushes: AtomicU64,
+    stopped: AtomicBool,
+}
+
+impl FsyncControl {
+
+    pub fn new(name: &'static str, policy: FsyncPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            name,
+            policy,
+            dbs: parking_lot::Mutex::new(Vec::new()),
+            dirty_files: parking_lot::Mutex::new(HashSet::new()),
+            flushes: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    pub fn policy(&self) -> FsyncPolicy {
+        self.policy
+    }
+
+    pub fn is_sync_write(&self) -> bool {
+        self.policy == FsyncPolicy::Always
+    }
+
+    // Count of done flushes
+    pub fn flushes(&self) -> u64 {
+        self.flushes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn register_db(&self, db: &Arc<RocksDb>) {
+        // Nothing is written into read-only DB
+        if db.is_read_only() {
+            return
+        }
+        let mut dbs = self.dbs.lock();
+        if !dbs.iter().any(|registered| Arc::ptr_eq(registered, db)) {
+            dbs.push(db.clone());
+        }
+    }
+
+    // Called after file is written
+    pub async fn file_written(&self, path: impl AsRef<Path>) -> Result<()> {
+        match self.policy {
+            FsyncPolicy::Always => tokio::fs::File::open(path.as_ref()).await?.sync_data().await?,
+            FsyncPolicy::Periodic(_) => {
+                self.dirty_files.lock().insert(path.as_ref().to_path_buf());
+            }
+            FsyncPolicy::Never => ()
+        }
+        Ok(())
+    }
+
+    // Syncs all written data of component
+    pub fn flush(&self) -> Result<()> {
+        if self.policy == FsyncPolicy::Never {
+            return Ok(())
+        }
+        let dbs = self.dbs.lock().clone();
+        for db in dbs {
+            db.flush_wal(true)?;
+        }
+        let files = std::mem::take(&mut *self.dirty_files.lock());
+        for path in files {
+            match std::fs::File::open(&path) {
+                Ok(file) => file.sync_data()?,
+                // File may be already moved or removed
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => fail!("Can't open {} to sync: {}", path.display(), e)
+            }
+        }
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn start_flusher(self: &Arc<Self>) {
+        let FsyncPolicy::Periodic(period) = self.policy else {
+            return
+        };
+        log::info!(target: TARGET, "Fsync of {} is done every {} sec", self.name, period);
+        let control = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(period)).await;
+                let Some(control) = control.upgrade() else {
+                    break
+                };
+                if control.stopped.load(Ordering::Relaxed) {
+                    break
+                }
+                if let Err(e) = control.flush() {
+                    log::error!(target: TARGET, "Fsync of {} failed: {}", control.name, e);
+                }
+            }
+        });
+    }
+
+    // Stops flusher and does the final flush
+    pub fn stop(&self) -> Result<()> {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.flush()
+    }
+}
+
+// Fsync controls of all storage components
+pub struct FsyncControls {
+    pub block_handles: Arc<FsyncControl>,
+    pub node_state: Arc<FsyncControl>,
+    pub block_data: Arc<FsyncControl>,
+    pub cells: Arc<FsyncControl>,
+}
+
+impl FsyncControls {
+
+    pub fn with_config(config: &FsyncConfig) -> Result<Self> {
+        config.check()?;
+        Ok(Self {
+            block_handles: FsyncControl::new("block handles", config.block_handles),
+            node_state: FsyncControl::new("node state", config.node_state),
+            block_data: FsyncControl::new("block data", config.block_data),
+            cells: FsyncControl::new("cells", config.cells),
+        })
+    }
+
+    fn all(&self) -> [&Arc<FsyncControl>; 4] {
+        [&self.block_handles, &self.node_state, &self.block_data, &self.cells]
+    }
+
+    pub fn start_flushers(&self) {
+        for control in self.all() {
+            control.start_flusher();
+        }
+    }
+
+    // Data is flushed before flags referring to it
+    pub fn stop(&self) -> Result<()> {
+        for control in [&self.block_data, &self.cells, &self.block_handles, &self.node_state] {
+            control.stop()?;
+        }
+        Ok(())
+    }
+}
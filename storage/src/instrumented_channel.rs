@@ -0,0 +1,374 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+#[cfg(feature = "telemetry")]
+use adnl::telemetry::{Metric, MetricBuilder};
+use std::{
+    collections::VecDeque, fmt,
+    sync::{Arc, atomic::{AtomicU64, AtomicUsize, Ordering}}, time::{Duration, Instant},
+};
+
+#[cfg(test)]
+#[path = "tests/test_instrumented_channel.rs"]
+mod tests;
+
+// What sender does when channel depth reaches the hard limit
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Sender waits for free space not longer than given milliseconds,
+    // then the item is queued over the limit, so a stuck receiver can't hang senders forever
+    Block(u64),
+    // New item is refused
+    #[default]
+    DropNew,
+    // The oldest queued item is dropped to free space for the new one
+    DropOldest,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ChannelLimits {
+    // Depth which is warned about when reached, zero disables the warning
+    pub soft_limit: usize,
+    // Depth at which the policy is applied, zero is unbounded channel
+    pub hard_limit: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl ChannelLimits {
+    pub fn new(soft_limit: usize, hard_limit: usize, policy: OverflowPolicy) -> Self {
+        Self { soft_limit, hard_limit, policy }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub depth: usize,
+    pub max_depth: usize,
+    pub enqueued: u64,
+    pub dequeued: u64,
+    pub dropped_new: u64,
+    pub evicted_oldest: u64,
+    // Sends which waited for free space
+    pub blocked: u64,
+    // Blocked sends which queued the item over the hard limit
+    pub block_timeouts: u64,
+    pub soft_limit_hits: u64,
+}
+
+#[cfg(feature = "telemetry")]
+pub struct ChannelTelemetry {
+    pub depth: Arc<Metric>,
+    pub enqueued: Arc<MetricBuilder>,
+    // Dropped, evicted and blocked sends
+    pub overflows: Arc<MetricBuilder>,
+}
+
+#[cfg(feature = "telemetry")]
+impl Default for ChannelTelemetry {
+    fn default() -> Self {
+        Self {
+            depth: Metric::without_totals("", 1),
+            enqueued: MetricBuilder::with_metric_and_period(Metric::with_total_amount("", 1), 1000000000),
+            overflows: MetricBuilder::with_metric_and_period(Metric::with_total_amount("", 1), 1000000000),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+pub enum SendError<T> {
+    // Receiver is closed or dropped
+    Closed(T),
+    // Channel is full and the policy is `DropNew`
+    Full(T),
+}
+
+impl<T> SendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Closed(item) | Self::Full(item) => item
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Closed(_) => write!(f, "channel is closed"),
+            Self::Full(_) => write!(f, "channel is full"),
+        }
+    }
+}
+
+// Item is not printed, so any item type can be sent
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Closed(_) => write!(f, "Closed(..)"),
+            Self::Full(_) => write!(f, "Full(..)"),
+        }
+    }
+}
+
+impl<T: Send> std::error::Error for SendError<T> {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    // All senders are dropped or receiver is closed, and nothing is queued
+    Disconnected,
+}
+
+struct ChannelState<T> {
+    queue: VecDeque<T>,
+    closed: bool,
+    senders: usize,
+}
+
+#[derive(Default)]
+struct ChannelCounters {
+    max_depth: AtomicUsize,
+    enqueued: AtomicU64,
+    dequeued: AtomicU64,
+    dropped_new: AtomicU64,
+    evicted_oldest: AtomicU64,
+    blocked: AtomicU64,
+    block_timeouts: AtomicU64,
+    soft_limit_hits: AtomicU64,
+}
+
+struct Shared<T> {
+    name: String,
+    limits: ChannelLimits,
+    state: parking_lot::Mutex<ChannelState<T>>,
+    // Wakes the receiver
+    items: tokio::sync::Notify,
+    // Wakes blocked senders
+    space: parking_lot::Condvar,
+    counters: ChannelCounters,
+    #[cfg(feature = "telemetry")]
+    telemetry: Arc<ChannelTelemetry>,
+}
+
+impl<T> Shared<T> {
+
+    fn stats(&self) -> ChannelStats {
+        let depth = self.state.lock().queue.len();
+        let counters = &self.counters;
+        ChannelStats {
+            depth,
+            max_depth: counters.max_depth.load(Ordering::Relaxed),
+            enqueued: counters.enqueued.load(Ordering::Relaxed),
+            dequeued: counters.dequeued.load(Ordering::Relaxed),
+            dropped_new: counters.dropped_new.load(Ordering::Relaxed),
+            evicted_oldest: counters.evicted_oldest.load(Ordering::Relaxed),
+            blocked: counters.blocked.load(Ordering::Relaxed),
+            block_timeouts: counters.block_timeouts.load(Ordering::Relaxed),
+            soft_limit_hits: counters.soft_limit_hits.load(Ordering::Relaxed),
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().closed = true;
+        self.space.notify_all();
+        self.items.notify_one();
+    }
+
+    fn count_overflow(&self, counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "telemetry")]
+        self.telemetry.overflows.update(1);
+    }
+
+}
+
+// Multi-producer single-consumer channel which counts its traffic and applies the overflow
+// policy at the hard limit. Items are sent from sync code, and received both sync and async
+pub fn instrumented_channel<T>(
+    name: impl ToString,
+    limits: ChannelLimits,
+    #[cfg(feature = "telemetry")]
+    telemetry: Arc<ChannelTelemetry>,
+) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        name: name.to_string(),
+        limits,
+        state: parking_lot::Mutex::new(ChannelState { queue: VecDeque::new(), closed: false, senders: 1 }),
+        items: tokio::sync::Notify::new(),
+        space: parking_lot::Condvar::new(),
+        counters: ChannelCounters::default(),
+        #[cfg(feature = "telemetry")]
+        telemetry,
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+
+    // Blocks the calling thread only with `Block` policy on the full channel
+    pub fn send(&self, item: T) -> std::result::Result<(), SendError<T>> {
+        let shared = &self.shared;
+        let limits = &shared.limits;
+        let mut state = shared.state.lock();
+        if state.closed {
+            return Err(SendError::Closed(item))
+        }
+        let mut evicted = None;
+        if (limits.hard_limit > 0) && (state.queue.len() >= limits.hard_limit) {
+            match limits.policy {
+                OverflowPolicy::Block(timeout_ms) => {
+                    shared.count_overflow(&shared.counters.blocked);
+                    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+                    while !state.closed && (state.queue.len() >= limits.hard_limit) {
+                        if shared.space.wait_until(&mut state, deadline).timed_out() {
+                            break
+                        }
+                    }
+                    if state.closed {
+                        return Err(SendError::Closed(item))
+                    }
+                    if state.queue.len() >= limits.hard_limit {
+                        shared.counters.block_timeouts.fetch_add(1, Ordering::Relaxed);
+                        log::warn!(
+                            "Channel {}: send is blocked for {}ms, item is queued over limit {}",
+                            shared.name, timeout_ms, limits.hard_limit
+                        );
+                    }
+                }
+                OverflowPolicy::DropNew => {
+                    drop(state);
+                    shared.count_overflow(&shared.counters.dropped_new);
+                    return Err(SendError::Full(item))
+                }
+                OverflowPolicy::DropOldest => {
+                    evicted = state.queue.pop_front();
+                    shared.count_overflow(&shared.counters.evicted_oldest);
+                }
+            }
+        }
+        state.queue.push_back(item);
+        let depth = state.queue.len();
+        drop(state);
+        // Evicted item is dropped out of the lock
+        drop(evicted);
+        shared.counters.enqueued.fetch_add(1, Ordering::Relaxed);
+        shared.counters.max_depth.fetch_max(depth, Ordering::Relaxed);
+        if depth == limits.soft_limit {
+            shared.counters.soft_limit_hits.fetch_add(1, Ordering::Relaxed);
+            log::warn!("Channel {}: depth reached soft limit {}", shared.name, limits.soft_limit);
+        }
+        #[cfg(feature = "telemetry")] {
+            shared.telemetry.depth.update(depth as u64);
+            shared.telemetry.enqueued.update(1);
+        }
+        shared.items.notify_one();
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.stats()
+    }
+
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.items.notify_one();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+
+    pub fn try_recv(&self) -> std::result::Result<T, TryRecvError> {
+        let shared = &self.shared;
+        let mut state = shared.state.lock();
+        match state.queue.pop_front() {
+            Some(item) => {
+                #[cfg(feature = "telemetry")]
+                shared.telemetry.depth.update(state.queue.len() as u64);
+                drop(state);
+                shared.space.notify_one();
+                shared.counters.dequeued.fetch_add(1, Ordering::Relaxed);
+                Ok(item)
+            }
+            None if state.closed || (state.senders == 0) => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty)
+        }
+    }
+
+    // None when nothing is queued and all senders are dropped or the receiver is closed
+    pub async fn recv(&self) -> Option<T> {
+        loop {
+            match self.try_recv() {
+                Ok(item) => return Some(item),
+                Err(TryRecvError::Disconnected) => return None,
+                // Permit is stored if an item is sent before the wait starts
+                Err(TryRecvError::Empty) => self.shared.items.notified().await
+            }
+        }
+    }
+
+    // New items are refused, queued ones may still be received
+    pub fn close(&self) {
+        self.shared.close()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.stats()
+    }
+
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+        let dropped = std::mem::take(&mut self.shared.state.lock().queue);
+        drop(dropped);
+    }
+}
@@ -11,16 +11,23 @@
 * limitations under the License.
 */
 
+pub mod account_tx_index;
+mod adaptive_cache;
 pub mod archives;
 pub mod block_db;
 pub mod block_handle_db;
 pub mod block_info_db;
+pub mod block_provenance_db;
 pub mod catchain_persistent_db;
 mod cell_db;
 pub mod db;
 pub mod dynamic_boc_rc_db;
 pub mod error;
+pub mod fsync;
+pub mod instrumented_channel;
 mod macros; 
+pub mod mc_utime_index;
+pub mod remp_messages_db;
 pub mod shardstate_db_async;
 pub mod traits;
 pub mod types;
@@ -30,7 +37,7 @@ mod tests;
 
 #[cfg(feature = "telemetry")]
 use adnl::telemetry::{Metric, MetricBuilder};
-use std::{sync::{Arc, atomic::AtomicU64}, time::{Duration, Instant}};
+use std::{sync::{Arc, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
 
 pub struct TimeChecker {
     operation: String,
@@ -77,6 +84,11 @@ pub struct StorageTelemetry {
     pub updated_cells: Arc<MetricBuilder>,
     pub new_cells: Arc<MetricBuilder>,
     pub deleted_cells: Arc<MetricBuilder>,
+    pub cells_cache_capacity: Arc<Metric>,
+    pub cells_cache_bytes: Arc<Metric>,
+    pub cells_cache_hit_rate: Arc<Metric>,
+    pub cells_cache_evictions: Arc<Metric>,
+    pub storer_queue: Arc<instrumented_channel::ChannelTelemetry>,
 }
 #[cfg(feature = "telemetry")]
 impl Default for StorageTelemetry {
@@ -95,6 +107,11 @@ impl Default for StorageTelemetry {
             updated_cells: MetricBuilder::with_metric_and_period(Metric::with_total_amount("", 1), 1000000000),
             new_cells: MetricBuilder::with_metric_and_period(Metric::with_total_amount("", 1), 1000000000),
             deleted_cells: MetricBuilder::with_metric_and_period(Metric::with_total_amount("", 1), 1000000000),
+            cells_cache_capacity: Metric::without_totals("", 1),
+            cells_cache_bytes: Metric::without_totals("", 1),
+            cells_cache_hit_rate: Metric::without_totals("", 1),
+            cells_cache_evictions: Metric::without_totals("", 1),
+            storer_queue: Arc::new(instrumented_channel::ChannelTelemetry::default()),
         }
     }
 }
@@ -107,4 +124,22 @@ pub struct StorageAlloc {
     pub storage_cells: Arc<AtomicU64>,
 }
 
+// Progress of GC pass, it is updated while the pass goes on
+#[derive(Debug, Default)]
+pub struct GcCounters {
+    pub scanned: AtomicU64,
+    pub removed: AtomicU64,
+    pub bytes_freed: AtomicU64,
+}
+
+impl GcCounters {
+    pub fn add_scanned(&self, count: u64) {
+        self.scanned.fetch_add(count, Ordering::Relaxed);
+    }
+    pub fn add_removed(&self, bytes_freed: u64) {
+        self.removed.fetch_add(1, Ordering::Relaxed);
+        self.bytes_freed.fetch_add(bytes_freed, Ordering::Relaxed);
+    }
+}
+
 pub(crate) const TARGET: &str = "storage";
@@ -11,9 +11,21 @@
 * limitations under the License.
 */
 
+// Columns are critical unless the DB declares how it is rebuilt on corruption
 #[macro_export]
 macro_rules! db_impl_base {
     ($type: ident, $trait: ident, $key_type: ty) => {
+        $crate::db_impl_base!(
+            @base $type, $trait, $key_type, $crate::db::degradation::ColumnCriticality::Critical
+        );
+    };
+    ($type: ident, $trait: ident, $key_type: ty, rebuild: $strategy: expr) => {
+        $crate::db_impl_base!(
+            @base $type, $trait, $key_type,
+            $crate::db::degradation::ColumnCriticality::Rebuildable($strategy)
+        );
+    };
+    (@base $type: ident, $trait: ident, $key_type: ty, $criticality: expr) => {
         #[derive(Debug)]
         pub struct $type {
             db: Box<dyn $crate::db::traits::$trait<$key_type> + Send + Sync>,
@@ -41,6 +53,67 @@ macro_rules! db_impl_base {
                 Ok(ret)
             }
 
+            /// Constructs new instance using RocksDB with given path and fsync policy
+            #[allow(dead_code)]
+            pub fn with_db_and_fsync(
+                db: std::sync::Arc<$crate::db::rocksdb::RocksDb>, 
+                family: impl ToString,
+                create_if_not_exist: bool,
+                fsync: &$crate::fsync::FsyncControl,
+            ) -> ever_block::Result<Self> {
+                let ret = Self {
+                    db: Box::new(db.table(family, create_if_not_exist)?.with_fsync(fsync))
+                };
+                Ok(ret)
+            }
+
+            /// Constructs new instance over given key-value collection
+            #[allow(dead_code)]
+            pub fn with_kvc(
+                db: Box<dyn $crate::db::traits::$trait<$key_type> + Send + Sync>
+            ) -> Self {
+                Self { db }
+            }
+
+            #[allow(dead_code)]
+            pub const CRITICALITY: $crate::db::degradation::ColumnCriticality = $criticality;
+
+            /// Name of the column in the list of degraded ones
+            #[allow(dead_code)]
+            pub fn column_name(&self) -> String {
+                let meta = self.db.get_meta();
+                if meta.is_empty() {
+                    stringify!($type).to_string()
+                } else {
+                    meta.to_string()
+                }
+            }
+
+            /// Ok(None) means corruption of rebuildable column, the caller takes the slow path
+            #[allow(dead_code)]
+            pub fn degrade_on_corruption<T>(
+                &self,
+                result: ever_block::Result<T>
+            ) -> ever_block::Result<Option<T>> {
+                $crate::db::degradation::degrade_on_corruption(
+                    &self.column_name(),
+                    Self::CRITICALITY,
+                    result,
+                    |strategy| match strategy {
+                        $crate::db::degradation::RebuildStrategy::ResetMarker(marker) => {
+                            self.db.delete_raw(marker)
+                        }
+                        $crate::db::degradation::RebuildStrategy::Restore => Ok(())
+                    }
+                )
+            }
+
+            /// Called by the owner when the degraded column is built again
+            #[allow(dead_code)]
+            pub fn set_rebuilt(&self) {
+                $crate::db::degradation::mark_rebuilt(&self.column_name())
+            }
+
             // /// Constructs new instance using RocksDB with given path
             // #[allow(dead_code)]
             // pub fn with_path(
@@ -141,6 +214,13 @@ macro_rules! db_impl_single {
 macro_rules! db_impl_cbor {
     ($type: ident, $trait: ident, $key_type: ty, $value_type: ty) => {
         $crate::db_impl_base!($type, $trait, $key_type);
+        $crate::db_impl_cbor!(@values $type, $key_type, $value_type);
+    };
+    ($type: ident, $trait: ident, $key_type: ty, $value_type: ty, rebuild: $strategy: expr) => {
+        $crate::db_impl_base!($type, $trait, $key_type, rebuild: $strategy);
+        $crate::db_impl_cbor!(@values $type, $key_type, $value_type);
+    };
+    (@values $type: ident, $key_type: ty, $value_type: ty) => {
 
         impl $type {
             #[allow(dead_code)]
@@ -0,0 +1,229 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use crate::{
+    db_impl_base,
+    db::{degradation::RebuildStrategy, rocksdb::RocksDb, traits::{KvcWriteable, U32Key}}
+};
+use ever_block::{ByteOrderRead, Result};
+use std::{io::Cursor, sync::Arc};
+
+#[cfg(test)]
+#[path = "tests/test_mc_utime_index.rs"]
+mod tests;
+
+// Corrupted index is built again by scan of block handles
+db_impl_base!(
+    McUtimeDb, KvcWriteable, U32Key,
+    rebuild: RebuildStrategy::ResetMarker(COMPLETE_MARKER)
+);
+
+// Blocks are grouped into buckets by this period of gen_utime
+const BUCKET_SEC: u32 = 600;
+// Max clock skew between masterchain blocks, a later block may be older by this time
+pub const MAX_CLOCK_SKEW_SEC: u32 = 60;
+// Service keys are far above any bucket
+const BOUNDS_KEY: u32 = u32::MAX;
+const COMPLETE_KEY: u32 = u32::MAX - 1;
+const COMPLETE_MARKER: &[u8] = &COMPLETE_KEY.to_le_bytes();
+
+fn serialize_entries(entries: &[(u32, u32)]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(entries.len() * 8);
+    for (gen_utime, seq_no) in entries {
+        data.extend_from_slice(&gen_utime.to_le_bytes());
+        data.extend_from_slice(&seq_no.to_le_bytes());
+    }
+    data
+}
+
+// Index of applied masterchain blocks by gen_utime. A bucket keeps (gen_utime, seq_no)
+// pairs of its period ordered by seq_no.
+pub struct McUtimeIndex {
+    db: McUtimeDb,
+    lock: parking_lot::Mutex<()>,
+    build_lock: parking_lot::Mutex<()>,
+}
+
+impl McUtimeIndex {
+
+    pub fn with_db(db: Arc<RocksDb>, family: impl ToString, create_if_not_exist: bool) -> Result<Self> {
+        Ok(Self::with_storage(McUtimeDb::with_db(db, family, create_if_not_exist)?))
+    }
+
+    pub fn in_memory() -> Self {
+        Self::with_storage(McUtimeDb::in_memory())
+    }
+
+    fn with_storage(db: McUtimeDb) -> Self {
+        Self {
+            db,
+            lock: parking_lot::Mutex::new(()),
+            build_lock: parking_lot::Mutex::new(()),
+        }
+    }
+
+    // Index is complete if all applied blocks were added, it is not so for old databases.
+    // Corrupted index is not complete until it is built again.
+    pub fn is_complete(&self) -> Result<bool> {
+        let marker = self.db.try_get(&U32Key::with_value(COMPLETE_KEY));
+        Ok(self.db.degrade_on_corruption(marker)?.flatten().is_some())
+    }
+
+    pub fn set_complete(&self) -> Result<()> {
+        self.db.put(&U32Key::with_value(COMPLETE_KEY), &[1])
+    }
+
+    // Builds incomplete index with blocks given by scan. Blocks which are applied meanwhile
+    // may be added as usual. Returns count of scanned blocks.
+    pub fn build_if_incomplete(
+        &self,
+        scan: impl FnOnce(&mut dyn FnMut(u32, u32)) -> Result<()>
+    ) -> Result<Option<usize>> {
+        if self.is_complete()? {
+            return Ok(None)
+        }
+        let _guard = self.build_lock.lock();
+        if self.is_complete()? {
+            return Ok(None)
+        }
+        // Blocks are scanned in random order, so they are sorted to write every bucket once
+        let mut entries = Vec::new();
+        scan(&mut |gen_utime, seq_no| if gen_utime != 0 {
+            entries.push((gen_utime / BUCKET_SEC, seq_no, gen_utime))
+        })?;
+        entries.sort_unstable();
+        let mut start = 0;
+        while start < entries.len() {
+            let bucket = entries[start].0;
+            let end = start + entries[start..].partition_point(|(b, _, _)| *b == bucket);
+            let new = entries[start..end].iter().map(|(_, seq_no, gen_utime)| (*gen_utime, *seq_no));
+            self.add_to_bucket(bucket, new)?;
+            start = end;
+        }
+        self.set_complete()?;
+        self.db.set_rebuilt();
+        Ok(Some(entries.len()))
+    }
+
+    // Blocks without known time are not indexed
+    pub fn add(&self, gen_utime: u32, seq_no: u32) -> Result<()> {
+        if gen_utime != 0 {
+            self.add_to_bucket(gen_utime / BUCKET_SEC, std::iter::once((gen_utime, seq_no)))?;
+        }
+        Ok(())
+    }
+
+    fn add_to_bucket(&self, bucket: u32, new: impl Iterator<Item = (u32, u32)>) -> Result<()> {
+        let _guard = self.lock.lock();
+        let mut entries = self.load_bucket(bucket)?;
+        let len = entries.len();
+        let bounds = self.bounds()?;
+        let (mut min, mut max) = bounds.unwrap_or((u32::MAX, 0));
+        for (gen_utime, seq_no) in new {
+            if let Err(pos) = entries.binary_search_by_key(&seq_no, |(_, seq_no)| *seq_no) {
+                entries.insert(pos, (gen_utime, seq_no));
+                min = min.min(gen_utime);
+                max = max.max(gen_utime);
+            }
+        }
+        if entries.len() == len {
+            return Ok(())
+        }
+        self.db.put(&U32Key::with_value(bucket), &serialize_entries(&entries))?;
+        if bounds != Some((min, max)) {
+            let mut data = min.to_le_bytes().to_vec();
+            data.extend_from_slice(&max.to_le_bytes());
+            self.db.put(&U32Key::with_value(BOUNDS_KEY), &data)?;
+        }
+        Ok(())
+    }
+
+    // Min and max gen_utime of indexed blocks
+    fn bounds(&self) -> Result<Option<(u32, u32)>> {
+        let data = self.db.try_get(&U32Key::with_value(BOUNDS_KEY));
+        let Some(data) = self.db.degrade_on_corruption(data)?.flatten() else {
+            return Ok(None)
+        };
+        let mut cursor = Cursor::new(data.as_ref());
+        Ok(Some((cursor.read_le_u32()?, cursor.read_le_u32()?)))
+    }
+
+    // Corrupted bucket is empty, it is filled again by the rebuild
+    fn load_bucket(&self, bucket: u32) -> Result<Vec<(u32, u32)>> {
+        let data = self.db.try_get(&U32Key::with_value(bucket));
+        let Some(data) = self.db.degrade_on_corruption(data)?.flatten() else {
+            return Ok(Vec::new())
+        };
+        let mut cursor = Cursor::new(data.as_ref());
+        let mut entries = Vec::with_capacity(data.len() / 8);
+        for _ in 0..data.len() / 8 {
+            entries.push((cursor.read_le_u32()?, cursor.read_le_u32()?));
+        }
+        Ok(entries)
+    }
+
+    // Seq_no of the latest block generated not later than given time. Blocks with bigger
+    // seq_no and less gen_utime, which are made by clock skew, are taken into account.
+    pub fn find(&self, utime: u32) -> Result<Option<u32>> {
+        let Some((min, max)) = self.bounds()? else {
+            return Ok(None)
+        };
+        if utime < min {
+            return Ok(None)
+        }
+        let mut found: Option<(u32, u32)> = None;
+        let mut bucket = utime.min(max) / BUCKET_SEC;
+        loop {
+            for (gen_utime, seq_no) in self.load_bucket(bucket)? {
+                if gen_utime <= utime && found.map_or(true, |(_, found)| found < seq_no) {
+                    found = Some((gen_utime, seq_no))
+                }
+            }
+            // Previous bucket may have a later block only if it is within the skew
+            let enough = found.map_or(false, |(gen_utime, _)| {
+                gen_utime.saturating_sub(MAX_CLOCK_SKEW_SEC) >= bucket * BUCKET_SEC
+            });
+            if enough || bucket <= min / BUCKET_SEC {
+                break
+            }
+            bucket -= 1;
+        }
+        Ok(found.map(|(_, seq_no)| seq_no))
+    }
+
+    // Seq_nos of blocks generated in given time range, both ends included, in order. Blocks
+    // between the first and the last found ones are included even if they are out of range
+    // because of clock skew, so there are no gaps.
+    pub fn range(&self, from: u32, to: u32) -> Result<Vec<u32>> {
+        let Some((min, max)) = self.bounds()? else {
+            return Ok(Vec::new())
+        };
+        if from > to || to < min || from > max {
+            return Ok(Vec::new())
+        }
+        let first_bucket = from.max(min).saturating_sub(MAX_CLOCK_SKEW_SEC) / BUCKET_SEC;
+        let last_bucket = to.min(max).saturating_add(MAX_CLOCK_SKEW_SEC) / BUCKET_SEC;
+        let mut entries = Vec::new();
+        for bucket in first_bucket..=last_bucket {
+            entries.extend(self.load_bucket(bucket)?);
+        }
+        entries.sort_unstable_by_key(|(_, seq_no)| *seq_no);
+        let in_range = |(gen_utime, _): &(u32, u32)| (from..=to).contains(gen_utime);
+        let (Some(first), Some(last)) = (
+            entries.iter().position(in_range), entries.iter().rposition(in_range)
+        ) else {
+            return Ok(Vec::new())
+        };
+        Ok(entries[first..=last].iter().map(|(_, seq_no)| *seq_no).collect())
+    }
+}
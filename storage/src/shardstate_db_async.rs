@@ -12,11 +12,12 @@
 */
 
 use crate::{
-    StorageAlloc, cell_db::CellDb, 
-    db::{rocksdb::RocksDbTable, traits::{DbKey, KvcWriteable}},
+    StorageAlloc, adaptive_cache::{cgroup_memory_limit, memory_used, CacheController, CacheSample},
+    cell_db::CellDb, 
+    db::{rocksdb::RocksDbTable, traits::{DbKey, KvcWriteable}}, fsync::FsyncControl,
     dynamic_boc_rc_db::{
         DynamicBocDb, DoneCellsStorageAdapter, OrderedCellsStorageAdapter, CellsCounters, 
-        CellByHashStorageAdapter
+        CellByHashStorageAdapter, CellsCacheStats
     },
     traits::Serializable,
     TARGET, error::StorageError,
@@ -121,6 +122,8 @@ pub struct CellsDbConfig {
     pub prefill_cells_counters: bool,
     pub cache_cells_counters: bool,
     pub cache_size_bytes: u64,
+    #[serde(default)]
+    pub adaptive_cache: Option<AdaptiveCacheConfig>,
 }
 
 impl Default for CellsDbConfig {
@@ -131,6 +134,32 @@ impl Default for CellsDbConfig {
             prefill_cells_counters: false,
             cache_cells_counters: false,
             cache_size_bytes: 1_000_000_000,
+            adaptive_cache: None,
+        }
+    }
+}
+
+// Cells cache capacity is adjusted between the bounds to keep the target hit rate
+// while process memory stays under the ceiling
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct AdaptiveCacheConfig {
+    pub min_size_bytes: u64,
+    pub max_size_bytes: u64,
+    pub target_hit_rate: f64,
+    // cgroup memory limit is used if not set
+    pub memory_ceiling_bytes: Option<u64>,
+    pub adjust_interval_sec: u32,
+}
+
+impl Default for AdaptiveCacheConfig {
+    fn default() -> Self {
+        Self {
+            min_size_bytes: 256_000_000,
+            max_size_bytes: 8_000_000_000,
+            target_hit_rate: 0.9,
+            memory_ceiling_bytes: None,
+            adjust_interval_sec: 30,
         }
     }
 }
@@ -163,6 +192,7 @@ impl ShardStateDb {
         assume_old_cells: bool,
         update_cells: bool,
         config: CellsDbConfig,
+        fsync: &FsyncControl,
         #[cfg(feature = "telemetry")]
         telemetry: Arc<StorageTelemetry>,
         allocated: Arc<StorageAlloc>
@@ -178,7 +208,7 @@ impl ShardStateDb {
         }
 
         let mut dynamic_boc_db = DynamicBocDb::with_db(
-            Arc::new(CellDb::with_db(db.clone(), cell_db_path, true)?),
+            Arc::new(CellDb::with_db_and_fsync(db.clone(), cell_db_path, true, fsync)?),
             db_root_path,
             assume_old_cells,
             config.cache_size_bytes,
@@ -194,7 +224,9 @@ impl ShardStateDb {
 
         let ss_db = Arc::new(Self {
             db: db.clone(),
-            shardstate_db: Arc::new(RocksDbTable::with_db(db.clone(), shardstate_db_path, true)?),
+            shardstate_db: Arc::new(
+                RocksDbTable::with_db(db.clone(), shardstate_db_path, true)?.with_fsync(fsync)
+            ),
             dynamic_boc_db: Arc::new(dynamic_boc_db),
             storer: sender,
             in_queue: AtomicU32::new(0),
@@ -212,10 +244,77 @@ impl ShardStateDb {
                 ss_db.worker(receiver).await;
             }
         });
+        if let Some(adaptive_cache) = ss_db.config.adaptive_cache.clone() {
+            ss_db.start_cells_cache_adjustment(adaptive_cache);
+        }
 
         Ok(ss_db)
     }
 
+    fn start_cells_cache_adjustment(self: &Arc<Self>, config: AdaptiveCacheConfig) {
+        let cgroup_limit = cgroup_memory_limit();
+        let memory_ceiling = config.memory_ceiling_bytes.or(cgroup_limit);
+        let mut controller = CacheController::new(&config, self.config.cache_size_bytes);
+        self.dynamic_boc_db.set_cells_cache_capacity(controller.capacity());
+        log::info!(
+            target: TARGET, 
+            "Adaptive cells cache: capacity {}, memory ceiling {:?}", 
+            controller.capacity(), memory_ceiling
+        );
+        let ss_db = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let adjust_interval = config.adjust_interval_sec.max(1);
+            let mut last = CellsCacheStats::default();
+            let mut ticks = 0;
+            loop {
+                // Resize goes by one segment per second to spread evictions in time
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let ss_db = match ss_db.upgrade() {
+                    Some(ss_db) => ss_db,
+                    None => break
+                };
+                if ss_db.stop.load(Ordering::Relaxed) & Self::MASK_STOPPED != 0 {
+                    break
+                }
+                ss_db.dynamic_boc_db.resize_cells_cache_step();
+                ticks += 1;
+                if ticks < adjust_interval {
+                    continue
+                }
+                ticks = 0;
+                let stats = ss_db.dynamic_boc_db.cells_cache_stats();
+                let sample = CacheSample {
+                    hits: stats.hits - last.hits,
+                    misses: stats.misses - last.misses,
+                    cache_bytes: stats.resident_bytes,
+                    memory_used: memory_used(cgroup_limit.is_some()),
+                    memory_ceiling,
+                };
+                let capacity = controller.update(&sample);
+                if capacity != stats.capacity {
+                    log::info!(
+                        target: TARGET, 
+                        "Adaptive cells cache: capacity {} -> {}, hit rate {:?}, memory used {:?}", 
+                        stats.capacity, capacity, sample.hit_rate(), sample.memory_used
+                    );
+                    ss_db.dynamic_boc_db.set_cells_cache_capacity(capacity);
+                }
+                #[cfg(feature = "telemetry")] {
+                    let hit_rate = sample.hit_rate().unwrap_or_default() * 100.0;
+                    ss_db.telemetry.cells_cache_capacity.update(capacity);
+                    ss_db.telemetry.cells_cache_bytes.update(stats.resident_bytes);
+                    ss_db.telemetry.cells_cache_hit_rate.update(hit_rate as u64);
+                    ss_db.telemetry.cells_cache_evictions.update(stats.evictions);
+                }
+                last = stats;
+            }
+        });
+    }
+
+    pub fn cells_cache_stats(&self) -> CellsCacheStats {
+        self.dynamic_boc_db.cells_cache_stats()
+    }
+
     pub fn start_gc(
         self: Arc<Self>,
         gc_resolver: Arc<dyn AllowStateGcResolver>,
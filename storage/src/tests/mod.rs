@@ -20,7 +20,8 @@ pub mod utils {
 
     use crate::{
         db::rocksdb::RocksDb,
-        StorageAlloc, block_handle_db::{BlockHandleDb, BlockHandleStorage, NodeStateDb}, 
+        StorageAlloc, 
+        block_handle_db::{BlockHandleDb, BlockHandleStorage, FileHashIndexDb, NodeStateDb}, 
     };
     #[cfg(feature = "telemetry")]
     use crate::StorageTelemetry;
@@ -106,6 +107,20 @@ pub mod utils {
     pub fn create_block_handle_storage(
         db: Option<Arc<RocksDb>>
     ) -> (BlockHandleStorage, Arc<BlockHandleDb>) {
+        create_block_handle_storage_ext(db, true)
+    }
+
+    pub fn create_block_handle_storage_ext(
+        db: Option<Arc<RocksDb>>,
+        file_hash_index: bool
+    ) -> (BlockHandleStorage, Arc<BlockHandleDb>) {
+        let file_hash_db = if !file_hash_index {
+            None
+        } else if let Some(db) = db.clone() {
+            Some(Arc::new(FileHashIndexDb::with_db(db, "block_handles_file_hash", true).unwrap()))
+        } else {
+            Some(Arc::new(FileHashIndexDb::in_memory()))
+        };
         let block_handle_db = if let Some(db) = db.clone() {
             Arc::new(BlockHandleDb::with_db(db, "block_handles", true).unwrap())
         } else {
@@ -123,6 +138,7 @@ pub mod utils {
             } else {
                 Arc::new(NodeStateDb::in_memory())
             },
+            file_hash_db,
             #[cfg(feature = "telemetry")]
             Arc::new(StorageTelemetry::default()),
             Arc::new(StorageAlloc::default()),
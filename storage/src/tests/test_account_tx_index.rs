@@ -0,0 +1,142 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+fn account(i: u8) -> UInt256 {
+    UInt256::with_array([i; 32])
+}
+
+// Synthetic block of masterchain seqno `mc_seq_no`: transactions of given accounts,
+// lt grows with seqno and position in the block
+fn block(mc_seq_no: u32, accounts: &[u8]) -> Vec<TxLocation> {
+    accounts.iter().enumerate().map(|(i, a)| TxLocation {
+        workchain_id: 0,
+        account: account(*a),
+        mc_seq_no,
+        lt: mc_seq_no as u64 * 1000 + i as u64,
+        block_root_hash: UInt256::with_array([mc_seq_no as u8; 32]),
+        tx_hash: UInt256::with_array([(mc_seq_no as u8) ^ (i as u8) ^ *a; 32]),
+    }).collect()
+}
+
+fn positions(locations: &[TxLocation]) -> Vec<(u32, u64)> {
+    locations.iter().map(|location| (location.mc_seq_no, location.lt)).collect()
+}
+
+// Blocks 50..350 cross bucket boundaries, account 1 is in every block twice,
+// account 2 in every tenth block
+fn index_with_blocks() -> AccountTxIndex {
+    let index = AccountTxIndex::in_memory();
+    for seq_no in 50..350 {
+        let mut accounts = vec![1, 3, 1];
+        if seq_no % 10 == 0 {
+            accounts.push(2);
+        }
+        index.add(&block(seq_no, &accounts)).unwrap();
+    }
+    index
+}
+
+#[test]
+fn test_account_tx_index_find() {
+    let index = AccountTxIndex::in_memory();
+    assert_eq!(index.find(0, &account(1), 0, u32::MAX, 10, None).unwrap(), (vec![], None));
+
+    let index = index_with_blocks();
+    let (found, continuation) = index.find(0, &account(2), 0, u32::MAX, 100, None).unwrap();
+    assert_eq!(continuation, None);
+    assert_eq!(found.len(), 30);
+    assert_eq!(found[0], block(50, &[1, 3, 1, 2])[3]);
+    assert!(found.iter().all(|location| location.mc_seq_no % 10 == 0));
+
+    // Range ends are included
+    let (found, _) = index.find(0, &account(1), 99, 101, 100, None).unwrap();
+    assert_eq!(positions(&found), [(99, 99000), (99, 99002), (100, 100000), (100, 100002), (101, 101000), (101, 101002)]);
+    assert!(index.find(0, &account(1), 101, 99, 100, None).unwrap().0.is_empty());
+    assert!(index.find(0, &account(1), 0, 49, 100, None).unwrap().0.is_empty());
+    assert!(index.find(1, &account(1), 0, u32::MAX, 100, None).unwrap().0.is_empty());
+
+    // Indexing the block again changes nothing
+    index.add(&block(100, &[1, 3, 1])).unwrap();
+    assert_eq!(index.find(0, &account(1), 100, 100, 100, None).unwrap().0.len(), 2);
+}
+
+#[test]
+fn test_account_tx_index_paging() {
+    let index = index_with_blocks();
+    let mut pages = 0;
+    let mut all = Vec::new();
+    let mut continuation = None;
+    loop {
+        let (found, next) = index.find(0, &account(1), 95, 305, 7, continuation).unwrap();
+        pages += 1;
+        all.extend(found);
+        match next {
+            Some(next) => {
+                assert_eq!(next, TxContinuation::of(all.last().unwrap()));
+                continuation = Some(next);
+            }
+            None => break
+        }
+    }
+    // 211 blocks with 2 transactions each, the last page is not full
+    assert_eq!(all.len(), 422);
+    assert_eq!(pages, 422 / 7 + 1);
+    let expected = (95..=305).flat_map(|seq_no| [(seq_no, seq_no as u64 * 1000), (seq_no, seq_no as u64 * 1000 + 2)]);
+    assert_eq!(positions(&all), expected.collect::<Vec<_>>());
+
+    // Exactly limit transactions: no continuation
+    let (found, next) = index.find(0, &account(2), 100, 190, 10, None).unwrap();
+    assert_eq!((found.len(), next), (10, None));
+    let (found, next) = index.find(0, &account(2), 100, 200, 10, None).unwrap();
+    assert_eq!((found.len(), next), (10, Some(TxContinuation { mc_seq_no: 190, lt: 190003 })));
+    let (found, next) = index.find(0, &account(2), 100, 200, 10, next).unwrap();
+    assert_eq!((positions(&found), next), (vec![(200, 200003)], None));
+
+    let continuation: TxContinuation = "190.190003".parse().unwrap();
+    assert_eq!(continuation.to_string(), "190.190003");
+    assert!("190".parse::<TxContinuation>().is_err());
+}
+
+#[test]
+fn test_account_tx_index_prune() {
+    let index = index_with_blocks();
+    // Whole bucket 0 and part of bucket 1
+    assert_eq!(index.prune(0, 150).unwrap(), 100 * 3 + 10);
+    assert!(index.find(0, &account(1), 0, 149, 100, None).unwrap().0.is_empty());
+    assert_eq!(index.find(0, &account(1), 0, 150, 100, None).unwrap().0.len(), 2);
+    assert_eq!(index.find(0, &account(2), 0, u32::MAX, 100, None).unwrap().0.len(), 20);
+    assert_eq!(index.first_bucket().unwrap(), Some(1));
+    assert!(index.load_bucket_accounts(0).unwrap().is_empty());
+
+    // Range inside of buckets, e.g. a removed archive package
+    assert_eq!(index.prune(205, 215).unwrap(), 10 * 3 + 1);
+    assert!(index.find(0, &account(3), 205, 214, 100, None).unwrap().0.is_empty());
+    assert_eq!(index.find(0, &account(3), 204, 215, 100, None).unwrap().0.len(), 2);
+    assert_eq!(index.first_bucket().unwrap(), Some(1));
+
+    // Account without transactions in the bucket is dropped from it
+    assert_eq!(index.prune(150, 200).unwrap(), 50 * 3 + 5);
+    let accounts = index.load_bucket_accounts(1).unwrap();
+    assert!(accounts.is_empty());
+    assert_eq!(index.load_account_buckets(0, &account(2)).unwrap().into_iter().collect::<Vec<_>>(), [2, 3]);
+    assert_eq!(index.first_bucket().unwrap(), Some(2));
+
+    // Blocks 200..350 except of 205..215 are left
+    assert_eq!(index.prune(0, 350).unwrap(), 140 * 3 + 14);
+    assert!(index.find(0, &account(1), 0, u32::MAX, 100, None).unwrap().0.is_empty());
+    assert!(index.load_account_buckets(0, &account(1)).unwrap().is_empty());
+    assert_eq!(index.first_bucket().unwrap(), Some(4));
+    assert_eq!(index.prune(0, 350).unwrap(), 0);
+}
@@ -0,0 +1,114 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+fn test_config() -> AdaptiveCacheConfig {
+    AdaptiveCacheConfig {
+        min_size_bytes: 100_000,
+        max_size_bytes: 1_000_000,
+        target_hit_rate: 0.9,
+        memory_ceiling_bytes: None,
+        adjust_interval_sec: 1,
+    }
+}
+
+fn sample(hits: u64, misses: u64, cache_bytes: u64) -> CacheSample {
+    CacheSample { hits, misses, cache_bytes, ..Default::default() }
+}
+
+fn with_memory(mut sample: CacheSample, used: u64) -> CacheSample {
+    sample.memory_used = Some(used);
+    sample.memory_ceiling = Some(10_000_000);
+    sample
+}
+
+#[test]
+fn test_cache_controller_hit_rate() {
+    // Low hit rate grows full cache up to the max
+    let mut controller = CacheController::new(&test_config(), 500_000);
+    let mut trajectory = Vec::new();
+    for _ in 0..8 {
+        let capacity = controller.capacity();
+        trajectory.push(controller.update(&sample(800, 200, capacity)));
+    }
+    assert_eq!(
+        trajectory,
+        [550_000, 605_000, 665_500, 732_050, 805_255, 885_780, 974_358, 1_000_000]
+    );
+
+    // High hit rate shrinks the cache down to the min
+    let mut trajectory = Vec::new();
+    for _ in 0..4 {
+        let capacity = controller.capacity();
+        trajectory.push(controller.update(&sample(990, 10, capacity)));
+    }
+    assert_eq!(trajectory, [900_000, 810_000, 729_000, 656_100]);
+    for _ in 0..50 {
+        controller.update(&sample(990, 10, 0));
+    }
+    assert_eq!(controller.capacity(), 100_000);
+
+    // Initial size is clamped by bounds
+    assert_eq!(CacheController::new(&test_config(), 10).capacity(), 100_000);
+    assert_eq!(CacheController::new(&test_config(), 1 << 40).capacity(), 1_000_000);
+}
+
+#[test]
+fn test_cache_controller_steady() {
+    let mut controller = CacheController::new(&test_config(), 500_000);
+
+    // Too few requests
+    assert_eq!(controller.update(&sample(10, 900, 500_000)), 500_000);
+    // Cache is not full yet, more memory won't help
+    assert_eq!(controller.update(&sample(100, 900, 400_000)), 500_000);
+    // Hit rate is in the target band
+    assert_eq!(controller.update(&sample(900, 100, 500_000)), 500_000);
+    assert_eq!(controller.update(&sample(915, 85, 500_000)), 500_000);
+    assert_eq!(controller.update(&sample(930, 70, 500_000)), 450_000);
+}
+
+#[test]
+fn test_cache_controller_memory_pressure() {
+    let mut controller = CacheController::new(&test_config(), 1_000_000);
+
+    // Watermark is 9M: small excess is freed by the step, big one is freed at once
+    assert_eq!(controller.update(&with_memory(sample(800, 200, 1_000_000), 9_050_000)), 900_000);
+    assert_eq!(controller.update(&with_memory(sample(800, 200, 900_000), 9_300_000)), 600_000);
+    // Pressure works without requests as well
+    assert_eq!(controller.update(&with_memory(sample(0, 0, 600_000), 9_100_000)), 500_000);
+    // No room to grow under the watermark
+    assert_eq!(controller.update(&with_memory(sample(800, 200, 500_000), 8_960_000)), 500_000);
+    assert_eq!(controller.update(&with_memory(sample(800, 200, 500_000), 8_000_000)), 550_000);
+    // Pressure can't shrink below the min
+    assert_eq!(controller.update(&with_memory(sample(800, 200, 550_000), 20_000_000)), 100_000);
+}
+
+#[test]
+fn test_cache_controller_converges() {
+    // Workload where hit rate is proportional to capacity up to 800K
+    let hit_rate = |capacity: u64| (capacity * 1000 / 800_000).min(1000);
+    let mut controller = CacheController::new(&test_config(), 200_000);
+    let mut trajectory = Vec::new();
+    for _ in 0..30 {
+        let capacity = controller.capacity();
+        let hits = hit_rate(capacity);
+        trajectory.push(controller.update(&sample(hits, 1000 - hits, capacity)));
+    }
+    // Grows steadily, then oscillates around the target and settles in the band
+    assert!(trajectory[..14].windows(2).all(|w| w[0] < w[1]), "{:?}", trajectory);
+    let last = *trajectory.last().unwrap();
+    assert!(trajectory[22..].iter().all(|capacity| *capacity == last), "{:?}", trajectory);
+    let rate = hit_rate(last) as f64 / 1000.0;
+    assert!((0.9..=0.92).contains(&rate), "{} {:?}", rate, trajectory);
+}
@@ -12,11 +12,23 @@
 */
 
 use crate::{
-    block_handle_db::FLAG_KEY_BLOCK,
-    db::rocksdb::RocksDb,
-    tests::utils::create_block_handle_storage, 
-    types::BlockMeta
+    GcCounters,
+    block_handle_db::{
+        BackfillStats, BlockHandle, BlockHandleDb, BlockHandleStorage, BlockOrigin, Callback, 
+        FileHashIndexDb, StoreJob, FLAG_APPLIED, FLAG_DATA, FLAG_DIRTY, 
+        FLAG_IS_MESH, FLAG_IS_QUEUE_UPDATE, FLAG_KEY_BLOCK, FLAG_NEXT_1, FLAG_PREV_1, FLAG_PROOF, 
+        FLAG_PROOF_LINK, FLAG_STATE, FLAG_STATE_SAVED, NodeStateDb, NodeStateEntry, STORER_MAX_BATCH,
+        deserialize_node_state, serialize_node_state
+    },
+    StorageAlloc, error::StorageError,
+    db::{
+        degradation::{is_corruption_error, is_degraded}, rocksdb::RocksDb,
+        tests::utils::CorruptedDb, traits::{KvcReadable, KvcWriteable}
+    },
+    tests::utils::{create_block_handle_storage, create_block_handle_storage_ext}, 
+    traits::Serializable, types::BlockMeta
 };
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU32, Ordering}};
 use ever_block::{BlockIdExt, ShardIdent};
 use ever_block::UInt256;
 
@@ -97,6 +109,11 @@ async fn test_block_handle_specific_flags() {
     assert_eq!(handle.is_archived(), true);
     assert_eq!(handle.set_archived(), false);
 
+    assert_eq!(handle.is_queue_update_verified(), false);
+    assert_eq!(handle.set_queue_update_verified(), true);
+    assert_eq!(handle.is_queue_update_verified(), true);
+    assert_eq!(handle.set_queue_update_verified(), false);
+
 }
 
 #[tokio::test]
@@ -123,7 +140,7 @@ async fn test_handle_db() {
         handle.set_data();
         //tokio::task::yield_now().await;
         handle.set_archived();
-        block_handle_storage.save_handle(&handle, None).unwrap();
+        block_handle_storage.mark_dirty(&handle, None).unwrap();
         if mc_seq_no % 13 == 0 {
             assert!(handle.is_key_block().unwrap());
         }
@@ -150,3 +167,1504 @@ async fn test_handle_db() {
 
 }
 
+
+#[tokio::test]
+async fn test_load_handles_batch() {
+
+    const DB_NAME: &str = "test_load_handles_batch";
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (block_handle_storage, _) = create_block_handle_storage(Some(db.clone()));
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+
+    // Even handles stay cached, odd ones are only in DB
+    let mut cached = Vec::new();
+    for seq_no in 0..10_u32 {
+        let handle = block_handle_storage
+            .create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+        handle.set_data();
+        block_handle_storage.mark_dirty(&handle, None).unwrap();
+        if seq_no % 2 == 0 {
+            cached.push(handle);
+        }
+    }
+
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+    let mut ids = (0..12_u32).map(block_id).collect::<Vec<_>>();
+    // Duplicated uncached id
+    ids.push(block_id(3));
+    let handles = block_handle_storage.load_handles_batch(&ids).unwrap();
+    assert_eq!(handles.len(), ids.len());
+
+    for seq_no in 0..10_usize {
+        let handle = handles[seq_no].as_ref().unwrap();
+        assert_eq!(handle.id(), &ids[seq_no]);
+        assert!(handle.has_data());
+        if seq_no % 2 == 0 {
+            assert!(Arc::ptr_eq(handle, &cached[seq_no / 2]));
+        }
+    }
+    assert!(handles[10].is_none());
+    assert!(handles[11].is_none());
+    assert!(Arc::ptr_eq(handles[3].as_ref().unwrap(), handles[12].as_ref().unwrap()));
+
+    // Loaded handles are in cache now
+    let handle = block_handle_storage.load_handle_by_id(&block_id(5)).unwrap().unwrap();
+    assert!(Arc::ptr_eq(&handle, handles[5].as_ref().unwrap()));
+
+    drop(handle);
+    drop(handles);
+    drop(cached);
+    drop(block_handle_storage);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_for_each_key_block_handle() {
+
+    const DB_NAME: &str = "test_for_each_key_block_handle";
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (block_handle_storage, _) = create_block_handle_storage(Some(db.clone()));
+
+    for seq_no in (0..30_u32).rev() {
+        let block_id = BlockIdExt::with_params(
+            ShardIdent::masterchain(), 
+            seq_no, 
+            UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+            UInt256::from_le_bytes(&(seq_no + 1000).to_le_bytes())
+        );
+        let flags = if seq_no % 7 == 0 {
+            FLAG_KEY_BLOCK
+        } else {
+            0
+        };
+        let meta = BlockMeta::with_data(flags, 0, 0, 0, 0);
+        block_handle_storage.create_handle(block_id, meta, None).unwrap().unwrap();
+    }
+
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+    let mut visited = Vec::new();
+    block_handle_storage.for_each_key_block_handle(&mut |handle| {
+        assert!(handle.is_key_block().unwrap());
+        assert_eq!(
+            handle.id().file_hash(), 
+            &UInt256::from_le_bytes(&(handle.id().seq_no() + 1000).to_le_bytes())
+        );
+        assert!(handle.id().shard().is_masterchain());
+        visited.push(handle.id().seq_no());
+        Ok(true)
+    }).unwrap();
+    assert_eq!(visited, vec![0, 7, 14, 21, 28]);
+
+    let mut visited = 0;
+    let completed = block_handle_storage.for_each_key_block_handle(&mut |_| {
+        visited += 1;
+        Ok(visited < 2)
+    }).unwrap();
+    assert!(!completed);
+    assert_eq!(visited, 2);
+
+    drop(block_handle_storage);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_block_origin_serialization() {
+
+    const DB_NAME: &str = "test_block_origin_serialization";
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(Some(db.clone()));
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+    let origins = [
+        BlockOrigin::Unknown, 
+        BlockOrigin::Broadcast, 
+        BlockOrigin::Download, 
+        BlockOrigin::Synthesized
+    ];
+
+    for (seq_no, origin) in origins.iter().enumerate() {
+        let handle = block_handle_storage
+            .create_handle(block_id(seq_no as u32), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(handle.origin(), BlockOrigin::Unknown);
+        assert_eq!(handle.set_origin(*origin), *origin != BlockOrigin::Unknown);
+        assert_eq!(handle.origin(), *origin);
+        if *origin != BlockOrigin::Unknown {
+            // Origin is set only once
+            assert_eq!(handle.set_origin(BlockOrigin::Broadcast), false);
+            assert_eq!(handle.origin(), *origin);
+        }
+        handle.set_data();
+        block_handle_storage.mark_dirty(&handle, None).unwrap();
+    }
+
+    // Record written by older node: plain meta without origin bits
+    let legacy_id = block_id(100);
+    let meta = BlockMeta::with_data(FLAG_KEY_BLOCK, 1, 2, 0, 0);
+    block_handle_db.put_raw(legacy_id.root_hash().as_slice(), &meta.to_vec().unwrap()).unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+    for (seq_no, origin) in origins.iter().enumerate() {
+        let handle = block_handle_storage.load_handle_by_id(&block_id(seq_no as u32)).unwrap().unwrap();
+        assert_eq!(handle.origin(), *origin);
+        assert!(handle.has_data());
+    }
+    let handle = block_handle_storage.load_handle_by_root_hash(legacy_id.root_hash()).unwrap().unwrap();
+    assert_eq!(handle.origin(), BlockOrigin::Unknown);
+    assert!(handle.is_key_block().unwrap());
+    assert_eq!(handle.gen_lt(), 2);
+
+    drop(handle);
+    drop(block_handle_storage);
+    drop(block_handle_db);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_next_ids_serialization() {
+
+    const DB_NAME: &str = "test_next_ids_serialization";
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(Some(db.clone()));
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::with_tagged_prefix(0, 0x4000_0000_0000_0000).unwrap(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::from_le_bytes(&(seq_no + 1000).to_le_bytes())
+    );
+
+    // next1 only, both next1 and next2 (before split), no next ids.
+    // Handles are dropped at once, so they are loaded from DB afterwards
+    {
+        let handle = block_handle_storage.create_handle(block_id(1), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(handle.next1_id(), None);
+        assert_eq!(handle.set_next1_id(&block_id(2)), true);
+        assert_eq!(handle.set_next1_id(&block_id(2)), false);
+        handle.set_next1();
+        block_handle_storage.mark_dirty(&handle, None).unwrap();
+    }
+    {
+        let handle = block_handle_storage.create_handle(block_id(10), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+        handle.set_next1_id(&block_id(11));
+        handle.set_next2_id(&block_id(12));
+        handle.set_data();
+        block_handle_storage.mark_dirty(&handle, None).unwrap();
+    }
+    block_handle_storage.create_handle(block_id(20), BlockMeta::default(), None)
+        .unwrap()
+        .unwrap();
+
+    // Record written by older node: plain meta without next ids
+    let legacy_id = block_id(100);
+    let meta = BlockMeta::with_data(FLAG_KEY_BLOCK, 1, 2, 0, 0);
+    block_handle_db.put_raw(legacy_id.root_hash().as_slice(), &meta.to_vec().unwrap()).unwrap();
+
+    block_handle_storage.flush().await.unwrap();
+
+    {
+        let handle = block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().unwrap();
+        assert!(handle.has_next1());
+        assert_eq!(handle.next1_id(), Some(block_id(2)));
+        assert_eq!(handle.next2_id(), None);
+    }
+    {
+        let handle = block_handle_storage.load_handle_by_root_hash(block_id(10).root_hash()).unwrap().unwrap();
+        assert_eq!(handle.id(), &block_id(10));
+        assert!(handle.has_data());
+        assert_eq!(handle.next1_id(), Some(block_id(11)));
+        assert_eq!(handle.next2_id(), Some(block_id(12)));
+    }
+    {
+        let handle = block_handle_storage.load_handle_by_id(&block_id(20)).unwrap().unwrap();
+        assert_eq!(handle.next1_id(), None);
+        assert_eq!(handle.next2_id(), None);
+    }
+    {
+        let handle = block_handle_storage.load_handle_by_root_hash(legacy_id.root_hash()).unwrap().unwrap();
+        assert!(handle.is_key_block().unwrap());
+        assert_eq!(handle.next1_id(), None);
+        assert_eq!(handle.next2_id(), None);
+    }
+
+    // Reset clears recorded id
+    {
+        let handle = block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().unwrap();
+        handle.reset_next1();
+        assert!(!handle.has_next1());
+        assert_eq!(handle.next1_id(), None);
+        block_handle_storage.mark_dirty(&handle, None).unwrap();
+    }
+    block_handle_storage.flush().await.unwrap();
+    let handle = block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().unwrap();
+    assert_eq!(handle.next1_id(), None);
+
+    drop(handle);
+    drop(block_handle_storage);
+    drop(block_handle_db);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_next_ids_forward_walk() {
+
+    const DB_NAME: &str = "test_next_ids_forward_walk";
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (block_handle_storage, _) = create_block_handle_storage(Some(db.clone()));
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+
+    for seq_no in 1..=3_u32 {
+        let handle = block_handle_storage.create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+        if seq_no < 3 {
+            handle.set_next1();
+            handle.set_next1_id(&block_id(seq_no + 1));
+            block_handle_storage.mark_dirty(&handle, None).unwrap();
+        }
+    }
+    block_handle_storage.flush().await.unwrap();
+
+    let mut visited = Vec::new();
+    let mut next = Some(block_id(1));
+    while let Some(id) = next {
+        let handle = block_handle_storage.load_handle_by_id(&id).unwrap().unwrap();
+        visited.push(handle.id().seq_no());
+        next = handle.next1_id();
+    }
+    assert_eq!(visited, vec![1, 2, 3]);
+
+    drop(block_handle_storage);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+struct DropRangeCallback {
+    invoked: AtomicU32,
+    ok: AtomicBool,
+    count: AtomicU32,
+}
+
+#[async_trait::async_trait]
+impl Callback for DropRangeCallback {
+    async fn invoke(&self, job: StoreJob, ok: bool) {
+        if let StoreJob::DropHandleRange(ids) = job {
+            self.count.store(ids.len() as u32, Ordering::Relaxed);
+        }
+        self.ok.store(ok, Ordering::Relaxed);
+        self.invoked.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[tokio::test]
+async fn test_drop_handles() {
+
+    const DB_NAME: &str = "test_drop_handles";
+    const COUNT: u32 = 5000;
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (block_handle_storage, _) = create_block_handle_storage(Some(db.clone()));
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+
+    for seq_no in 0..COUNT {
+        block_handle_storage.create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+    }
+    while block_handle_storage.pending_jobs() > 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    for seq_no in 0..COUNT {
+        assert!(block_handle_storage.load_handle_by_id(&block_id(seq_no)).unwrap().is_some());
+    }
+
+    // Keep one handle alive: cache entry must be dropped as well
+    let alive = block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().unwrap();
+    let callback = Arc::new(
+        DropRangeCallback {
+            invoked: AtomicU32::new(0),
+            ok: AtomicBool::new(false),
+            count: AtomicU32::new(0),
+        }
+    );
+    let ids = (0..COUNT).filter(|seq_no| seq_no % 10 != 0).map(block_id).collect();
+    block_handle_storage.drop_handles(ids, Some(callback.clone())).unwrap();
+    while block_handle_storage.pending_jobs() > 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(callback.invoked.load(Ordering::Relaxed), 1);
+    assert!(callback.ok.load(Ordering::Relaxed));
+    assert_eq!(callback.count.load(Ordering::Relaxed), COUNT - COUNT / 10);
+    for seq_no in 0..COUNT {
+        let handle = block_handle_storage.load_handle_by_id(&block_id(seq_no)).unwrap();
+        assert_eq!(handle.is_some(), seq_no % 10 == 0);
+    }
+
+    drop(alive);
+    drop(block_handle_storage);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+struct OrderCallback {
+    jobs: std::sync::Mutex<Vec<(String, bool)>>,
+}
+
+#[async_trait::async_trait]
+impl Callback for OrderCallback {
+    async fn invoke(&self, job: StoreJob, ok: bool) {
+        let job = match job {
+            StoreJob::SaveHandle(handle) => format!("save {}", handle.id().seq_no()),
+            StoreJob::DropHandle(id) => format!("drop {}", id.seq_no()),
+            job => format!("{:?}", job)
+        };
+        self.jobs.lock().unwrap().push((job, ok));
+    }
+}
+
+#[tokio::test]
+async fn test_storer_batch_throughput() {
+
+    const DB_NAME: &str = "test_storer_batch_throughput";
+    const COUNT: u32 = 20000;
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(Some(db.clone()));
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::from_le_bytes(&(seq_no + COUNT).to_le_bytes())
+    );
+
+    let now = std::time::Instant::now();
+    for seq_no in 0..COUNT {
+        block_handle_storage.create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+    }
+    block_handle_storage.flush().await.unwrap();
+    let elapsed = now.elapsed();
+    println!(
+        "{} handles stored in {}ms, {:.0} handles/sec", 
+        COUNT, elapsed.as_millis(), COUNT as f64 / elapsed.as_secs_f64()
+    );
+
+    // All jobs are queued before the storer runs, so they fill whole batches
+    let batches = block_handle_storage.storer_batches.load(Ordering::Relaxed);
+    assert_eq!(batches, (COUNT as u64 + 1).div_ceil(STORER_MAX_BATCH as u64));
+    assert_eq!(block_handle_storage.pending_jobs(), 0);
+    for seq_no in 0..COUNT {
+        assert!(block_handle_db.try_get_raw(block_id(seq_no).root_hash().as_slice()).unwrap().is_some());
+        let file_hash = block_id(seq_no).file_hash().clone();
+        let handle = block_handle_storage.load_handle_by_file_hash(&file_hash).unwrap().unwrap();
+        assert_eq!(handle.id(), &block_id(seq_no));
+    }
+
+    drop(block_handle_storage);
+    drop(block_handle_db);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_storer_batch_save_then_drop() {
+
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(None);
+    let callback = Arc::new(OrderCallback { jobs: std::sync::Mutex::new(Vec::new()) });
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::from_le_bytes(&(seq_no + 100).to_le_bytes())
+    );
+    let is_stored = |seq_no: u32| block_handle_db
+        .try_get_raw(block_id(seq_no).root_hash().as_slice())
+        .unwrap()
+        .is_some();
+    let is_indexed = |seq_no: u32| block_handle_storage.file_hash_db.as_ref().unwrap()
+        .try_get(block_id(seq_no).file_hash())
+        .unwrap()
+        .is_some();
+
+    // Storer doesn't run until the first await, so all jobs go into one batch
+    let handle1 = block_handle_storage
+        .create_handle(block_id(1), BlockMeta::default(), Some(callback.clone()))
+        .unwrap()
+        .unwrap();
+    block_handle_storage.drop_handle(block_id(1), Some(callback.clone())).unwrap();
+    let handle2 = block_handle_storage
+        .create_handle(block_id(2), BlockMeta::default(), Some(callback.clone()))
+        .unwrap()
+        .unwrap();
+    block_handle_storage.drop_handle(block_id(3), Some(callback.clone())).unwrap();
+    block_handle_storage.flush_handle(&handle1, Some(callback.clone())).unwrap();
+    block_handle_storage.drop_handle(block_id(1), Some(callback.clone())).unwrap();
+    block_handle_storage.flush().await.unwrap();
+
+    assert_eq!(block_handle_storage.storer_batches.load(Ordering::Relaxed), 1);
+    assert!(!is_stored(1) && !is_indexed(1));
+    assert!(is_stored(2) && is_indexed(2));
+    assert!(!is_stored(3));
+    let jobs = callback.jobs.lock().unwrap().clone();
+    let expected = ["save 1", "drop 1", "save 2", "drop 3", "save 1", "drop 1"];
+    assert_eq!(jobs.len(), expected.len());
+    for ((job, ok), expected) in jobs.iter().zip(expected.iter()) {
+        assert_eq!(job, expected);
+        assert!(ok);
+    }
+
+    // Save after drop in the next batch restores the handle
+    block_handle_storage.drop_handle(block_id(2), None).unwrap();
+    block_handle_storage.flush_handle(&handle2, None).unwrap();
+    block_handle_storage.flush().await.unwrap();
+    assert_eq!(block_handle_storage.storer_batches.load(Ordering::Relaxed), 2);
+    assert!(is_stored(2));
+
+}
+
+#[tokio::test]
+async fn test_flush_ordering() {
+
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(None);
+
+    // Empty queue
+    block_handle_storage.flush().await.unwrap();
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+    let is_stored = |seq_no: u32| block_handle_db
+        .try_get_raw(block_id(seq_no).root_hash().as_slice())
+        .unwrap()
+        .is_some();
+
+    for round in 0..10_u32 {
+        let mut handles = Vec::new();
+        for seq_no in round * 100..(round + 1) * 100 {
+            let handle = block_handle_storage
+                .create_handle(block_id(seq_no), BlockMeta::default(), None)
+                .unwrap()
+                .unwrap();
+            handle.set_data();
+            block_handle_storage.mark_dirty(&handle, None).unwrap();
+            if seq_no % 3 == 0 {
+                block_handle_storage.drop_handle(block_id(seq_no), None).unwrap();
+            }
+            handles.push(handle);
+        }
+        block_handle_storage.flush().await.unwrap();
+        assert_eq!(block_handle_storage.pending_jobs(), 0);
+        for seq_no in round * 100..(round + 1) * 100 {
+            assert_eq!(is_stored(seq_no), seq_no % 3 != 0);
+        }
+    }
+
+    // Sync save is visible right after return, even after queued drop
+    let handle = block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().unwrap();
+    block_handle_storage.drop_handle(block_id(1), None).unwrap();
+    block_handle_storage.save_handle_sync(&handle, None).await.unwrap();
+    assert!(is_stored(1));
+
+}
+
+#[test]
+fn test_flush_after_storer_stopped() {
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let (block_handle_storage, _) = rt.block_on(async { create_block_handle_storage(None) });
+    // Storer task is dropped together with runtime
+    drop(rt);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    assert!(rt.block_on(block_handle_storage.flush()).is_err());
+    let handle = rt.block_on(async {
+        block_handle_storage.create_handle(BlockIdExt::default(), BlockMeta::default(), None)
+    });
+    assert!(handle.is_err());
+
+}
+
+#[tokio::test]
+async fn test_stop_refuses_new_jobs() {
+
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(None);
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+    let is_stored = |seq_no: u32| block_handle_db
+        .try_get_raw(block_id(seq_no).root_hash().as_slice())
+        .unwrap()
+        .is_some();
+
+    // Jobs queued before stop are written
+    for seq_no in 0..100 {
+        block_handle_storage.create_handle(block_id(seq_no), BlockMeta::default(), None).unwrap();
+    }
+    block_handle_storage.stop().await.unwrap();
+    assert_eq!(block_handle_storage.pending_jobs(), 0);
+    assert!((0..100).all(is_stored));
+
+    // Jobs after stop are refused
+    let handle = block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().unwrap();
+    assert!(block_handle_storage.flush_handle(&handle, None).is_err());
+    assert!(block_handle_storage.drop_handle(block_id(1), None).is_err());
+    assert!(block_handle_storage.flush().await.is_err());
+    assert_eq!(block_handle_storage.pending_jobs(), 0);
+    assert!(is_stored(1));
+    // Repeated stop does nothing
+    block_handle_storage.stop().await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_load_handle_by_file_hash() {
+
+    const DB_NAME: &str = "test_load_handle_by_file_hash";
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::from_le_bytes(&(seq_no + 1000).to_le_bytes())
+    );
+
+    // Old database without index
+    let (block_handle_storage, _) = create_block_handle_storage_ext(Some(db.clone()), false);
+    for seq_no in 0..10 {
+        block_handle_storage.create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+    }
+    block_handle_storage.flush().await.unwrap();
+    assert!(block_handle_storage.load_handle_by_file_hash(block_id(1).file_hash()).is_err());
+    drop(block_handle_storage);
+
+    let (block_handle_storage, _) = create_block_handle_storage_ext(Some(db.clone()), true);
+    for seq_no in 10..20 {
+        block_handle_storage.create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+    }
+    block_handle_storage.flush().await.unwrap();
+
+    // Created after index exists
+    let handle = block_handle_storage
+        .load_handle_by_file_hash(block_id(15).file_hash())
+        .unwrap()
+        .unwrap();
+    assert_eq!(handle.id(), &block_id(15));
+
+    // Created before index exists: found by scan with backfill
+    for seq_no in 0..20 {
+        let handle = block_handle_storage
+            .load_handle_by_file_hash(block_id(seq_no).file_hash())
+            .unwrap()
+            .unwrap();
+        assert_eq!(handle.id(), &block_id(seq_no));
+    }
+    assert!(
+        block_handle_storage.load_handle_by_file_hash(block_id(100).file_hash()).unwrap().is_none()
+    );
+
+    // Dropped handle disappears from index
+    block_handle_storage.drop_handle(block_id(3), None).unwrap();
+    block_handle_storage.flush().await.unwrap();
+    assert!(
+        block_handle_storage.load_handle_by_file_hash(block_id(3).file_hash()).unwrap().is_none()
+    );
+    drop(handle);
+    drop(block_handle_storage);
+
+    // Index completeness survives restart
+    let (block_handle_storage, _) = create_block_handle_storage_ext(Some(db.clone()), true);
+    let handle = block_handle_storage
+        .load_handle_by_file_hash(block_id(5).file_hash())
+        .unwrap()
+        .unwrap();
+    assert_eq!(handle.id(), &block_id(5));
+
+    drop(handle);
+    drop(block_handle_storage);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_read_only_storage() {
+
+    const DB_NAME: &str = "test_read_only_storage";
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::from_le_bytes(&(seq_no + 1000).to_le_bytes())
+    );
+    let is_read_only = |result: ever_block::Result<()>| matches!(
+        result.unwrap_err().downcast::<StorageError>(), Ok(StorageError::ReadOnly)
+    );
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (block_handle_storage, _) = create_block_handle_storage(Some(db.clone()));
+    for seq_no in 0..10 {
+        block_handle_storage.create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+    }
+    block_handle_storage.save_full_node_state("state".to_string(), &block_id(5)).unwrap();
+    block_handle_storage.stop().await.unwrap();
+    drop(block_handle_storage);
+    drop(db);
+
+    let db = RocksDb::read_only(DB_PATH, DB_NAME).unwrap();
+    let block_handle_db = Arc::new(BlockHandleDb::with_db(db.clone(), "block_handles", true).unwrap());
+    let block_handle_storage = BlockHandleStorage::read_only(
+        block_handle_db.clone(),
+        Arc::new(NodeStateDb::with_db(db.clone(), "full_node_states", true).unwrap()),
+        Arc::new(NodeStateDb::with_db(db.clone(), "validator_states", true).unwrap()),
+        Some(Arc::new(FileHashIndexDb::with_db(db.clone(), "block_handles_file_hash", true).unwrap())),
+        #[cfg(feature = "telemetry")]
+        Arc::new(crate::StorageTelemetry::default()),
+        Arc::new(StorageAlloc::default()),
+    );
+
+    // Reads succeed
+    let handle = block_handle_storage.load_handle_by_id(&block_id(3)).unwrap().unwrap();
+    assert_eq!(handle.id(), &block_id(3));
+    let by_file_hash = block_handle_storage
+        .load_handle_by_file_hash(block_id(7).file_hash())
+        .unwrap()
+        .unwrap();
+    assert_eq!(by_file_hash.id(), &block_id(7));
+    assert_eq!(
+        block_handle_storage.load_full_node_state("state").unwrap().as_deref(), 
+        Some(&block_id(5))
+    );
+
+    // Writes are rejected
+    let created = block_handle_storage.create_handle(block_id(100), BlockMeta::default(), None);
+    assert!(matches!(
+        created.unwrap_err().downcast::<StorageError>(), Ok(StorageError::ReadOnly)
+    ));
+    assert!(block_handle_storage.load_handle_by_id(&block_id(100)).unwrap().is_none());
+    assert!(handle.set_data());
+    assert!(is_read_only(block_handle_storage.mark_dirty(&handle, None)));
+    assert!(is_read_only(block_handle_storage.flush_handle(&handle, None)));
+    assert!(is_read_only(block_handle_storage.save_handle_sync(&handle, None).await));
+    assert!(is_read_only(block_handle_storage.drop_handle(block_id(1), None)));
+    assert!(is_read_only(block_handle_storage.drop_handles(vec![block_id(2)], None)));
+    assert!(is_read_only(
+        block_handle_storage.save_full_node_state("state".to_string(), &block_id(6))
+    ));
+    assert!(is_read_only(block_handle_storage.drop_full_node_state("state".to_string())));
+    assert!(is_read_only(block_handle_storage.save_session_checkpoint(&UInt256::default(), vec![1])));
+    assert!(is_read_only(block_handle_db.put_raw(block_id(1).root_hash().as_slice(), &[])));
+    block_handle_storage.flush().await.unwrap();
+    block_handle_storage.stop().await.unwrap();
+    assert_eq!(block_handle_storage.pending_jobs(), 0);
+
+    // Nothing is changed
+    assert!(block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().is_some());
+    assert_eq!(
+        block_handle_storage.load_full_node_state("state").unwrap().as_deref(), 
+        Some(&block_id(5))
+    );
+
+    drop(handle);
+    drop(by_file_hash);
+    drop(block_handle_storage);
+    drop(block_handle_db);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_validator_state_ttl_gc() {
+
+    let (block_handle_storage, _) = create_block_handle_storage(None);
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+
+    block_handle_storage.save_validator_state_with_ttl("s1".to_string(), &block_id(1), 0).unwrap();
+    block_handle_storage.save_validator_state_with_ttl("s2".to_string(), &block_id(2), 3600).unwrap();
+    block_handle_storage.save_validator_state("s3".to_string(), &block_id(3)).unwrap();
+    block_handle_storage.flush().await.unwrap();
+
+    // States with ttl are loaded the same way as usual ones
+    assert_eq!(
+        block_handle_storage.load_validator_state("s2").unwrap().unwrap().as_ref(), 
+        &block_id(2)
+    );
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    let counters = GcCounters::default();
+    assert_eq!(block_handle_storage.gc_expired_states(now + 1, &counters).unwrap(), 1);
+    block_handle_storage.flush().await.unwrap();
+    assert_eq!(counters.scanned.load(Ordering::Relaxed), 3);
+    assert_eq!(counters.removed.load(Ordering::Relaxed), 1);
+    assert!(counters.bytes_freed.load(Ordering::Relaxed) > "s1".len() as u64);
+
+    assert!(block_handle_storage.load_validator_state("s1").unwrap().is_none());
+    assert!(block_handle_storage.load_validator_state("s2").unwrap().is_some());
+    assert!(block_handle_storage.load_validator_state("s3").unwrap().is_some());
+
+    // State without ttl never expires
+    assert_eq!(block_handle_storage.gc_expired_states(u32::MAX, &GcCounters::default()).unwrap(), 1);
+    block_handle_storage.flush().await.unwrap();
+    assert!(block_handle_storage.load_validator_state("s2").unwrap().is_none());
+    assert_eq!(
+        block_handle_storage.load_validator_state("s3").unwrap().unwrap().as_ref(), 
+        &block_id(3)
+    );
+
+}
+
+#[tokio::test]
+async fn test_session_checkpoints() {
+
+    let (block_handle_storage, _) = create_block_handle_storage(None);
+    let session_id = |n: u8| UInt256::from([n; 32]);
+
+    for n in 1..=3 {
+        block_handle_storage.save_session_checkpoint(&session_id(n), vec![n; 10]).unwrap();
+    }
+    let block_id = BlockIdExt::with_params(
+        ShardIdent::masterchain(), 1, UInt256::from([9; 32]), UInt256::default()
+    );
+    block_handle_storage.save_validator_state("s1".to_string(), &block_id).unwrap();
+    block_handle_storage.flush().await.unwrap();
+
+    assert_eq!(block_handle_storage.load_session_checkpoint(&session_id(2)).unwrap(), Some(vec![2; 10]));
+    assert!(block_handle_storage.load_session_checkpoint(&session_id(4)).unwrap().is_none());
+
+    // Checkpoints are not seen by ttl gc
+    let counters = GcCounters::default();
+    assert_eq!(block_handle_storage.gc_expired_states(u32::MAX, &counters).unwrap(), 0);
+    assert_eq!(counters.scanned.load(Ordering::Relaxed), 1);
+
+    // Newer checkpoint replaces the older one
+    block_handle_storage.save_session_checkpoint(&session_id(1), vec![11; 5]).unwrap();
+    let active = vec!(session_id(1), session_id(4)).into_iter().collect();
+    assert_eq!(block_handle_storage.drop_stale_session_checkpoints(&active).unwrap(), 2);
+    block_handle_storage.flush().await.unwrap();
+
+    assert_eq!(block_handle_storage.load_session_checkpoint(&session_id(1)).unwrap(), Some(vec![11; 5]));
+    assert!(block_handle_storage.load_session_checkpoint(&session_id(2)).unwrap().is_none());
+    assert!(block_handle_storage.load_session_checkpoint(&session_id(3)).unwrap().is_none());
+    assert_eq!(block_handle_storage.load_validator_state("s1").unwrap().unwrap().as_ref(), &block_id);
+    assert_eq!(block_handle_storage.drop_stale_session_checkpoints(&active).unwrap(), 0);
+
+}
+
+#[tokio::test]
+async fn test_validator_records() {
+
+    let (block_handle_storage, _) = create_block_handle_storage(None);
+    let block_id = BlockIdExt::with_params(
+        ShardIdent::masterchain(), 1, UInt256::from([9; 32]), UInt256::default()
+    );
+    block_handle_storage.save_validator_state("s1".to_string(), &block_id).unwrap();
+    block_handle_storage.save_validator_record("r1", vec![1, 2, 3]).unwrap();
+    block_handle_storage.flush().await.unwrap();
+
+    assert_eq!(block_handle_storage.load_validator_record("r1").unwrap(), Some(vec![1, 2, 3]));
+    assert!(block_handle_storage.load_validator_record("r2").unwrap().is_none());
+    assert!(block_handle_storage.load_validator_state("r1").unwrap().is_none());
+
+    // Records are not block ids, scans skip them
+    let counters = GcCounters::default();
+    assert_eq!(block_handle_storage.gc_expired_states(u32::MAX, &counters).unwrap(), 0);
+    assert_eq!(counters.scanned.load(Ordering::Relaxed), 1);
+    assert_eq!(block_handle_storage.list_validator_state_keys().unwrap().len(), 1);
+
+    block_handle_storage.save_validator_record("r1", vec![4]).unwrap();
+    block_handle_storage.flush().await.unwrap();
+    assert_eq!(block_handle_storage.load_validator_record("r1").unwrap(), Some(vec![4]));
+
+}
+
+#[tokio::test]
+async fn test_export_import_node_state() {
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::from([seq_no as u8; 32])
+    );
+
+    let (source, _) = create_block_handle_storage(None);
+    source.save_full_node_state("LastMcBlockId".to_string(), &block_id(10)).unwrap();
+    source.save_full_node_state("ShardsClientMcBlockId".to_string(), &block_id(8)).unwrap();
+    source.save_full_node_state("InitMcBlockId".to_string(), &block_id(1)).unwrap();
+    // Validator states are not exported
+    source.save_validator_state("s1".to_string(), &block_id(5)).unwrap();
+    source.flush().await.unwrap();
+
+    let exported = source.export_node_state().unwrap();
+    assert_eq!(
+        exported,
+        vec!(
+            ("InitMcBlockId".to_string(), block_id(1)),
+            ("LastMcBlockId".to_string(), block_id(10)),
+            ("ShardsClientMcBlockId".to_string(), block_id(8)),
+        )
+    );
+    let data = serialize_node_state(&exported).unwrap();
+    assert_eq!(deserialize_node_state(&data).unwrap(), exported);
+    assert!(deserialize_node_state(&data[..data.len() - 1]).is_err());
+    let mut broken = data.clone();
+    broken.push(0);
+    assert!(deserialize_node_state(&broken).is_err());
+
+    let (target, _) = create_block_handle_storage(None);
+    target.save_full_node_state("InitMcBlockId".to_string(), &block_id(1)).unwrap();
+    target.save_full_node_state("LastMcBlockId".to_string(), &block_id(3)).unwrap();
+    target.flush().await.unwrap();
+    let entries = deserialize_node_state(&data).unwrap();
+
+    // Differing state is not overwritten, nothing is imported at all
+    assert!(target.import_node_state(&entries, false).is_err());
+    assert_eq!(target.load_full_node_state("LastMcBlockId").unwrap().unwrap().as_ref(), &block_id(3));
+    assert!(target.load_full_node_state("ShardsClientMcBlockId").unwrap().is_none());
+
+    // Equal state is skipped
+    assert_eq!(target.import_node_state(&entries, true).unwrap(), 2);
+    assert_eq!(target.import_node_state(&entries, false).unwrap(), 0);
+    // Cache is consistent before states are written
+    assert_eq!(target.load_full_node_state("LastMcBlockId").unwrap().unwrap().as_ref(), &block_id(10));
+    target.flush().await.unwrap();
+    assert_eq!(target.export_node_state().unwrap(), exported);
+
+}
+
+#[tokio::test]
+async fn test_list_node_state_keys() {
+
+    const DB_NAME: &str = "test_list_node_state_keys";
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::with_tagged_prefix(0, 0x4000_0000_0000_0000).unwrap(),
+        seq_no,
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()),
+        UInt256::from([seq_no as u8; 32])
+    );
+    let ok = |key: &str, seq_no: u32| NodeStateEntry { key: key.to_string(), id: Ok(block_id(seq_no)) };
+
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+    let (storage, _) = create_block_handle_storage(Some(db.clone()));
+    storage.save_full_node_state("LastMcBlockId".to_string(), &block_id(10)).unwrap();
+    storage.save_full_node_state("ShardsClientMcBlockId".to_string(), &block_id(8)).unwrap();
+    storage.save_validator_state("LastRotationBlockId".to_string(), &block_id(5)).unwrap();
+    storage.save_validator_state_with_ttl("RempMarker".to_string(), &block_id(6), 3600).unwrap();
+    storage.save_session_checkpoint(&UInt256::from([1; 32]), vec![1, 2, 3]).unwrap();
+    storage.flush().await.unwrap();
+
+    // Corrupt records are written directly into db
+    let full_node_db = NodeStateDb::with_db(db.clone(), "full_node_states", false).unwrap();
+    full_node_db.put_raw(b"Corrupt", &[1, 2, 3]).unwrap();
+    let validator_db = NodeStateDb::with_db(db.clone(), "validator_states", false).unwrap();
+    let mut data = Vec::new();
+    block_id(7).serialize(&mut data).unwrap();
+    data.push(0);
+    validator_db.put_raw(b"Trailing", &data).unwrap();
+
+    let listed = storage.list_full_node_state_keys().unwrap();
+    assert_eq!(listed.len(), 3);
+    assert_eq!(listed[0].key, "Corrupt");
+    assert!(listed[0].id.is_err());
+    assert_eq!(listed[1], ok("LastMcBlockId", 10));
+    assert_eq!(listed[2], ok("ShardsClientMcBlockId", 8));
+
+    // Session checkpoints are not states, expiration time is not an error
+    let listed = storage.list_validator_state_keys().unwrap();
+    assert_eq!(listed.len(), 3);
+    assert_eq!(listed[0], ok("LastRotationBlockId", 5));
+    assert_eq!(listed[1], ok("RempMarker", 6));
+    assert_eq!(listed[2].key, "Trailing");
+    assert!(listed[2].id.as_ref().unwrap_err().contains("extra bytes"));
+
+    // Queued save is listed with cached value
+    storage.save_full_node_state("LastMcBlockId".to_string(), &block_id(11)).unwrap();
+    assert_eq!(storage.list_full_node_state_keys().unwrap()[1], ok("LastMcBlockId", 11));
+
+    storage.flush().await.unwrap();
+    drop(full_node_db);
+    drop(validator_db);
+    drop(storage);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+
+}
+
+#[tokio::test]
+async fn test_mesh_handles_filter() {
+
+    let (block_handle_storage, _) = create_block_handle_storage(None);
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+    // (flags, params) for native, mesh nw 1, mesh nw 2 and queue update for wc 1
+    let kinds = [(0, 0), (FLAG_IS_MESH, 1), (FLAG_IS_MESH, 2), (FLAG_IS_QUEUE_UPDATE, 1)];
+    for seq_no in 0..40_u32 {
+        let (flags, params) = kinds[seq_no as usize % kinds.len()];
+        block_handle_storage
+            .create_handle(block_id(seq_no), BlockMeta::with_data(flags, 0, 0, 0, params), None)
+            .unwrap()
+            .unwrap();
+    }
+    block_handle_storage.flush().await.unwrap();
+
+    let collect = |nw_id| {
+        let mut seq_nos = Vec::new();
+        block_handle_storage.for_each_mesh_handle(nw_id, &mut |handle| {
+            assert_eq!(handle.mesh_nw_id(), Some(nw_id));
+            seq_nos.push(handle.id().seq_no());
+            Ok(true)
+        }).unwrap();
+        seq_nos.sort();
+        seq_nos
+    };
+    assert_eq!(collect(1), (0..40).filter(|seq_no| seq_no % 4 == 1).collect::<Vec<_>>());
+    assert_eq!(collect(2), (0..40).filter(|seq_no| seq_no % 4 == 2).collect::<Vec<_>>());
+    assert!(collect(3).is_empty());
+
+    assert_eq!(block_handle_storage.drop_mesh_handles(1, false).unwrap(), 10);
+    block_handle_storage.flush().await.unwrap();
+    assert!(collect(1).is_empty());
+    assert_eq!(collect(2).len(), 10);
+    let load = |seq_no: u32| {
+        let (flags, params) = kinds[seq_no as usize % kinds.len()];
+        let handle = if flags == FLAG_IS_MESH {
+            block_handle_storage.load_mesh_handle(params as i32, block_id(seq_no).root_hash())
+        } else {
+            block_handle_storage.load_handle_by_id(&block_id(seq_no))
+        };
+        handle.unwrap()
+    };
+    for seq_no in 0..40_u32 {
+        assert_eq!(load(seq_no).is_some(), seq_no % 4 != 1);
+    }
+
+    // queue updates are dropped only on demand
+    assert_eq!(block_handle_storage.drop_mesh_handles(1, true).unwrap(), 10);
+    block_handle_storage.flush().await.unwrap();
+    for seq_no in 0..40_u32 {
+        assert_eq!(load(seq_no).is_some(), seq_no % 4 == 0 || seq_no % 4 == 2);
+    }
+
+}
+
+#[tokio::test]
+async fn test_mesh_handles_with_colliding_root_hashes() {
+
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(None);
+
+    let root_hash = UInt256::from_le_bytes(&[7; 32]);
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        root_hash.clone(), 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes())
+    );
+    let mesh_meta = |nw_id: i32| BlockMeta::with_data(FLAG_IS_MESH, 0, 0, 0, nw_id as u32);
+
+    // Native block and blocks of two mesh networks have the same root hash
+    let native = block_handle_storage
+        .create_handle(block_id(1), BlockMeta::default(), None)
+        .unwrap()
+        .unwrap();
+    let mesh1 = block_handle_storage
+        .create_mesh_handle(1, block_id(2), mesh_meta(1), None)
+        .unwrap()
+        .unwrap();
+    let mesh2 = block_handle_storage
+        .create_mesh_handle(2, block_id(3), mesh_meta(2), None)
+        .unwrap()
+        .unwrap();
+    assert!(block_handle_storage.create_mesh_handle(3, block_id(4), mesh_meta(2), None).is_err());
+    assert!(block_handle_storage.create_mesh_handle(3, block_id(4), BlockMeta::default(), None).is_err());
+    mesh1.set_data();
+    block_handle_storage.mark_dirty(&mesh1, None).unwrap();
+    block_handle_storage.flush().await.unwrap();
+
+    // Cached handles are not mixed
+    let loaded = block_handle_storage.load_handle_by_root_hash(&root_hash).unwrap().unwrap();
+    assert!(Arc::ptr_eq(&loaded, &native));
+    let loaded = block_handle_storage.load_mesh_handle(1, &root_hash).unwrap().unwrap();
+    assert!(Arc::ptr_eq(&loaded, &mesh1));
+    let loaded = block_handle_storage.load_mesh_handle(2, &root_hash).unwrap().unwrap();
+    assert!(Arc::ptr_eq(&loaded, &mesh2));
+    drop(loaded);
+    drop(native);
+    drop(mesh1);
+    drop(mesh2);
+
+    // Stored records are not mixed
+    let native = block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().unwrap();
+    assert_eq!(native.id(), &block_id(1));
+    assert_eq!(native.mesh_nw_id(), None);
+    let mesh1 = block_handle_storage.load_mesh_handle(1, &root_hash).unwrap().unwrap();
+    assert_eq!(mesh1.id(), &block_id(2));
+    assert_eq!(mesh1.mesh_nw_id(), Some(1));
+    assert!(mesh1.has_data());
+    let mesh2 = block_handle_storage.load_mesh_handle(2, &root_hash).unwrap().unwrap();
+    assert_eq!(mesh2.id(), &block_id(3));
+    assert!(!mesh2.has_data());
+    assert!(block_handle_storage.load_mesh_handle(3, &root_hash).unwrap().is_none());
+    drop(mesh1);
+    drop(mesh2);
+
+    // Dropping of mesh network doesn't touch others
+    assert_eq!(block_handle_storage.drop_mesh_handles(1, true).unwrap(), 1);
+    block_handle_storage.flush().await.unwrap();
+    assert!(block_handle_storage.load_mesh_handle(1, &root_hash).unwrap().is_none());
+    assert!(block_handle_storage.load_mesh_handle(2, &root_hash).unwrap().is_some());
+    drop(native);
+    assert!(block_handle_storage.load_handle_by_id(&block_id(1)).unwrap().is_some());
+
+    // Record written under root hash only is migrated on load
+    let legacy_hash = UInt256::from_le_bytes(&[8; 32]);
+    block_handle_db.put_raw(legacy_hash.as_slice(), &mesh_meta(5).to_vec().unwrap()).unwrap();
+    let count_keys = |len: usize| {
+        let mut count = 0;
+        block_handle_db.for_each(&mut |key, _| {
+            if key.len() == len {
+                count += 1;
+            }
+            Ok(true)
+        }).unwrap();
+        count
+    };
+    assert_eq!(count_keys(32), 2);
+    assert!(block_handle_storage.load_handle_by_root_hash(&legacy_hash).unwrap().is_none());
+    assert!(block_handle_storage.load_mesh_handle(4, &legacy_hash).unwrap().is_none());
+    let legacy = block_handle_storage.load_mesh_handle(5, &legacy_hash).unwrap().unwrap();
+    assert_eq!(legacy.mesh_nw_id(), Some(5));
+    block_handle_storage.flush().await.unwrap();
+    drop(legacy);
+    assert_eq!(count_keys(32), 1);
+    assert_eq!(count_keys(32 + 5), 2);
+    assert!(block_handle_storage.load_mesh_handle(5, &legacy_hash).unwrap().is_some());
+
+}
+
+#[tokio::test]
+async fn test_coalesced_handle_saves() {
+
+    const COUNT: u32 = 5000;
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(None);
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+    let stored_flags = |seq_no: u32| {
+        let data = block_handle_db
+            .try_get_raw(block_id(seq_no).root_hash().as_slice())
+            .unwrap()
+            .unwrap();
+        BlockMeta::deserialize(&mut std::io::Cursor::new(data)).unwrap().flags()
+    };
+
+    let mut mutations = 0;
+    let mut handles = Vec::new();
+    for seq_no in 0..COUNT {
+        let handle = block_handle_storage
+            .create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+        mutations += 1;
+        handles.push(handle);
+    }
+
+    // Storer doesn't run until the first await, so all changes are coalesced
+    let setters: [fn(&BlockHandle) -> bool; 4] = [
+        BlockHandle::set_data, BlockHandle::set_proof, BlockHandle::set_prev1, BlockHandle::set_state
+    ];
+    for set in setters.iter() {
+        for handle in handles.iter() {
+            assert!(set(handle));
+            block_handle_storage.mark_dirty(handle, None).unwrap();
+            mutations += 1;
+        }
+    }
+    block_handle_storage.flush().await.unwrap();
+    assert_eq!(block_handle_storage.saved_handles.load(Ordering::Relaxed), COUNT as u64);
+    for seq_no in 0..COUNT {
+        let flags = stored_flags(seq_no);
+        assert!(flags & FLAG_DATA != 0 && flags & FLAG_PROOF != 0);
+        assert!(flags & FLAG_PREV_1 != 0 && flags & FLAG_STATE != 0);
+        assert_eq!(flags & FLAG_DIRTY, 0);
+    }
+
+    // Changes after save are written again, explicit flush is not coalesced
+    for handle in handles.iter() {
+        handle.set_next1();
+        block_handle_storage.mark_dirty(handle, None).unwrap();
+        handle.set_block_applied();
+        block_handle_storage.flush_handle(handle, None).unwrap();
+        mutations += 2;
+    }
+    block_handle_storage.flush().await.unwrap();
+    let saved = block_handle_storage.saved_handles.load(Ordering::Relaxed);
+    assert_eq!(saved, 2 * COUNT as u64);
+    assert!(saved * 3 < mutations);
+    for seq_no in 0..COUNT {
+        let flags = stored_flags(seq_no);
+        assert!(flags & FLAG_NEXT_1 != 0 && flags & FLAG_APPLIED != 0);
+    }
+
+}
+
+#[tokio::test]
+async fn test_backfill_full_ids() {
+
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(None);
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::with_tagged_prefix(0, 0x8000_0000_0000_0000).unwrap(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::from_le_bytes(&(seq_no + 1000).to_le_bytes())
+    );
+
+    // Even seqnos are new-format records, odd are legacy ones without full id
+    for seq_no in 0..100_u32 {
+        if seq_no % 2 == 0 {
+            block_handle_storage
+                .create_handle(block_id(seq_no), BlockMeta::default(), None)
+                .unwrap()
+                .unwrap();
+        } else {
+            let mut value = Vec::new();
+            BlockMeta::with_data(FLAG_DATA, seq_no, 0, 0, 0).serialize(&mut value).unwrap();
+            block_handle_db.put_raw(block_id(seq_no).root_hash().as_slice(), &value).unwrap();
+        }
+    }
+    block_handle_storage.flush().await.unwrap();
+    assert_eq!(block_handle_storage.load_full_block_id(block_id(1).root_hash()).unwrap(), None);
+
+    // Legacy handle held in cache is skipped, ids of seqnos above 90 are unknown
+    let held = block_handle_storage.load_handle_by_root_hash(block_id(3).root_hash()).unwrap().unwrap();
+    let known = (0..90_u32)
+        .map(|seq_no| (block_id(seq_no).root_hash().clone(), block_id(seq_no)))
+        .collect::<std::collections::HashMap<_, _>>();
+    let resolver = |root_hash: &UInt256| -> ever_block::Result<Option<BlockIdExt>> {
+        Ok(known.get(root_hash).cloned())
+    };
+    let stats = block_handle_storage.backfill_full_ids(&resolver).unwrap();
+    assert_eq!(stats, BackfillStats { fixed: 44, unresolved: 5, skipped: 1 });
+    for seq_no in (1..90_u32).step_by(2).filter(|seq_no| *seq_no != 3) {
+        let id = block_id(seq_no);
+        assert_eq!(block_handle_storage.load_full_block_id(id.root_hash()).unwrap(), Some(id.clone()));
+        let handle = block_handle_storage.load_handle_by_root_hash(id.root_hash()).unwrap().unwrap();
+        assert_eq!(handle.id(), &id);
+        assert!(handle.has_data());
+        assert_eq!(handle.gen_utime().unwrap(), seq_no);
+    }
+    for seq_no in (0..100_u32).step_by(2) {
+        let id = block_id(seq_no);
+        assert_eq!(block_handle_storage.load_full_block_id(id.root_hash()).unwrap(), Some(id));
+    }
+
+    // Re-run fixes only released handle
+    drop(held);
+    let stats = block_handle_storage.backfill_full_ids(&resolver).unwrap();
+    assert_eq!(stats, BackfillStats { fixed: 1, unresolved: 5, skipped: 0 });
+    assert_eq!(block_handle_storage.load_full_block_id(block_id(3).root_hash()).unwrap(), Some(block_id(3)));
+    let stats = block_handle_storage.backfill_full_ids(&resolver).unwrap();
+    assert_eq!(stats, BackfillStats { fixed: 0, unresolved: 5, skipped: 0 });
+
+}
+
+#[tokio::test]
+async fn test_applied_flag_waits_for_saved_state() {
+
+    let (block_handle_storage, _) = create_block_handle_storage(None);
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(), 
+        seq_no, 
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()), 
+        UInt256::default()
+    );
+    let create = |seq_no: u32| block_handle_storage
+        .create_handle(block_id(seq_no), BlockMeta::default(), None)
+        .unwrap()
+        .unwrap();
+    let reload = |seq_no: u32| block_handle_storage.load_handle_by_id(&block_id(seq_no)).unwrap().unwrap();
+
+    // Crash after the block is applied but before its state is saved:
+    // in-memory handle is lost, stored one is not applied
+    {
+        let handle = create(1);
+        handle.set_state();
+        handle.set_block_applied();
+        block_handle_storage.save_handle_sync(&handle, None).await.unwrap();
+    }
+    let handle = reload(1);
+    assert!(handle.has_state());
+    assert!(!handle.has_saved_state());
+    assert!(!handle.is_applied());
+
+    // State is saved after apply: handle is written again with applied flag
+    {
+        let handle = create(2);
+        handle.set_state();
+        handle.set_block_applied();
+        block_handle_storage.flush_handle(&handle, None).unwrap();
+        handle.set_state_saved();
+        block_handle_storage.mark_dirty(&handle, None).unwrap();
+        block_handle_storage.flush().await.unwrap();
+    }
+    let handle = reload(2);
+    assert!(handle.has_saved_state());
+    assert!(handle.is_applied());
+
+    // Block without own state is applied at once
+    {
+        let handle = create(3);
+        handle.set_block_applied();
+        block_handle_storage.save_handle_sync(&handle, None).await.unwrap();
+    }
+    assert!(reload(3).is_applied());
+
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_set_flags_masked_concurrent() {
+
+    let (block_handle_storage, _) = create_block_handle_storage(None);
+    let handle = block_handle_storage
+        .create_handle(BlockIdExt::default(), BlockMeta::default(), None)
+        .unwrap()
+        .unwrap();
+    block_handle_storage.flush().await.unwrap();
+    let initial = handle.meta().flags();
+
+    // Every flag is claimed by several tasks, only one claim succeeds
+    let flags = [FLAG_DATA, FLAG_PROOF, FLAG_NEXT_1, FLAG_PREV_1];
+    let succeeded = Arc::new(AtomicU32::new(0));
+    let mut tasks = Vec::new();
+    for i in 0..32 {
+        let handle = handle.clone();
+        let succeeded = succeeded.clone();
+        let flag = flags[i % flags.len()];
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..1000 {
+                match handle.set_flags_masked(flag, flag) {
+                    Ok(prev) => {
+                        assert_eq!(prev & flag, 0);
+                        succeeded.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => match e.downcast::<StorageError>() {
+                        Ok(StorageError::FlagsPreconditionFailed(current, expected_clear)) => {
+                            assert_eq!(current & flag, flag);
+                            assert_eq!(expected_clear, flag);
+                        }
+                        e => panic!("unexpected result {:?}", e)
+                    }
+                }
+                tokio::task::yield_now().await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(succeeded.load(Ordering::Relaxed), flags.len() as u32);
+    assert_eq!(handle.meta().flags(), initial | FLAG_DATA | FLAG_PROOF | FLAG_NEXT_1 | FLAG_PREV_1);
+
+    // Precondition is checked against all given flags, nothing is set on failure
+    let err = handle.set_flags_masked(FLAG_STATE, FLAG_PROOF | FLAG_STATE).unwrap_err();
+    assert_eq!(
+        err.downcast::<StorageError>().unwrap(),
+        StorageError::FlagsPreconditionFailed(handle.meta().flags(), FLAG_PROOF | FLAG_STATE)
+    );
+    assert!(!handle.has_state());
+    assert_eq!(handle.set_flags_masked(FLAG_STATE | FLAG_PROOF, FLAG_STATE).unwrap() & FLAG_STATE, 0);
+    assert!(handle.has_state());
+
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_combined_flags_single_save() {
+
+    let (block_handle_storage, block_handle_db) = create_block_handle_storage(None);
+    let callback = Arc::new(OrderCallback { jobs: std::sync::Mutex::new(Vec::new()) });
+    let block_id = || BlockIdExt::with_params(
+        ShardIdent::full(0), 1, UInt256::from([1; 32]), UInt256::from([2; 32])
+    );
+    let handle = block_handle_storage
+        .create_handle(block_id(), BlockMeta::default(), None)
+        .unwrap()
+        .unwrap();
+    block_handle_storage.flush().await.unwrap();
+    // Data flag is already set, proof link one is not
+    handle.set_data();
+
+    let storage = Arc::new(block_handle_storage);
+    let mut tasks = Vec::new();
+    for _ in 0..16 {
+        let (storage, handle, callback) = (storage.clone(), handle.clone(), callback.clone());
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..100 {
+                // Saved state is never seen without state
+                assert!(!handle.has_saved_state() || handle.has_state());
+                if handle.set_data_and_proof_link() {
+                    storage.flush_handle(&handle, Some(callback.clone())).unwrap();
+                }
+                if handle.set_state_both() {
+                    storage.flush_handle(&handle, Some(callback.clone())).unwrap();
+                }
+                tokio::task::yield_now().await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    storage.flush().await.unwrap();
+
+    // One save for every combined transition
+    let jobs = callback.jobs.lock().unwrap().clone();
+    assert_eq!(jobs, vec![("save 1".to_string(), true), ("save 1".to_string(), true)]);
+    let raw = block_handle_db.try_get_raw(block_id().root_hash().as_slice()).unwrap().unwrap();
+    let meta = BlockMeta::deserialize(&mut std::io::Cursor::new(raw)).unwrap();
+    let expected = FLAG_DATA | FLAG_PROOF_LINK | FLAG_STATE | FLAG_STATE_SAVED;
+    assert_eq!(meta.flags() & expected, expected);
+    assert!(handle.has_data() && handle.has_proof_link() && handle.has_state() && handle.has_saved_state());
+
+}
+
+#[tokio::test]
+async fn test_corrupted_file_hash_index() {
+
+    let block_id = |seq_no: u32| BlockIdExt::with_params(
+        ShardIdent::masterchain(),
+        seq_no,
+        UInt256::from_le_bytes(&seq_no.to_le_bytes()),
+        UInt256::from_le_bytes(&(seq_no + 1000).to_le_bytes())
+    );
+    let handles = CorruptedDb::new("test_corrupted_handles");
+    let file_hashes = CorruptedDb::new("test_corrupted_file_hashes");
+    let block_handle_storage = BlockHandleStorage::with_dbs(
+        Arc::new(BlockHandleDb::with_kvc(Box::new(handles.clone()))),
+        Arc::new(NodeStateDb::in_memory()),
+        Arc::new(NodeStateDb::in_memory()),
+        Some(Arc::new(FileHashIndexDb::with_kvc(Box::new(file_hashes.clone())))),
+        #[cfg(feature = "telemetry")]
+        Arc::new(crate::StorageTelemetry::default()),
+        Arc::new(StorageAlloc::default()),
+    );
+    for seq_no in 0..10 {
+        block_handle_storage.create_handle(block_id(seq_no), BlockMeta::default(), None)
+            .unwrap()
+            .unwrap();
+    }
+    block_handle_storage.flush().await.unwrap();
+    let handle = block_handle_storage
+        .load_handle_by_file_hash(block_id(1).file_hash())
+        .unwrap()
+        .unwrap();
+    assert_eq!(handle.id(), &block_id(1));
+    assert!(block_handle_storage.file_hash_index_complete.load(Ordering::Relaxed));
+
+    // Index is rebuildable: handle is found by scan which rebuilds the index
+    file_hashes.corrupt(block_id(5).file_hash().as_slice());
+    let handle = block_handle_storage
+        .load_handle_by_file_hash(block_id(5).file_hash())
+        .unwrap()
+        .unwrap();
+    assert_eq!(handle.id(), &block_id(5));
+    assert!(!file_hashes.is_corrupted(block_id(5).file_hash().as_slice()));
+    assert!(block_handle_storage.file_hash_index_complete.load(Ordering::Relaxed));
+    assert!(!is_degraded("test_corrupted_file_hashes"));
+    drop(handle);
+
+    // Handles are critical: the error is returned as is
+    handles.corrupt(block_id(7).root_hash().as_slice());
+    let error = block_handle_storage.load_handle_by_id(&block_id(7)).unwrap_err();
+    assert!(is_corruption_error(&error));
+    assert!(!is_degraded("test_corrupted_handles"));
+    assert!(handles.is_corrupted(block_id(7).root_hash().as_slice()));
+
+    block_handle_storage.stop().await.unwrap();
+
+}
@@ -0,0 +1,85 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::db::traits::KvcWriteable;
+
+include!("../db/tests/destroy_db.rs");
+
+const DB_PATH: &str = "../target/test";
+
+#[test]
+fn test_fsync_config_check() {
+    FsyncConfig::default().check().unwrap();
+    let config = FsyncConfig {
+        block_handles: FsyncPolicy::Always,
+        node_state: FsyncPolicy::Periodic(5),
+        block_data: FsyncPolicy::Periodic(10),
+        cells: FsyncPolicy::Never,
+    };
+    config.check().unwrap();
+    FsyncControls::with_config(&config).unwrap();
+
+    // Applied flags are weaker than block data
+    let invalid = [
+        FsyncConfig { block_data: FsyncPolicy::Always, ..Default::default() },
+        FsyncConfig { block_data: FsyncPolicy::Always, ..config.clone() },
+        FsyncConfig { node_state: FsyncPolicy::Periodic(20), ..config.clone() },
+        FsyncConfig { node_state: FsyncPolicy::Never, cells: FsyncPolicy::Periodic(60), ..config.clone() },
+        FsyncConfig { block_handles: FsyncPolicy::Periodic(0), ..Default::default() },
+    ];
+    for config in invalid {
+        assert!(config.check().is_err(), "{:?}", config);
+        assert!(FsyncControls::with_config(&config).is_err());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_fsync_periodic_flusher() {
+    const DB_NAME: &str = "test_fsync_periodic_flusher";
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+    let db = RocksDb::with_path(DB_PATH, DB_NAME).unwrap();
+
+    let control = FsyncControl::new("test", FsyncPolicy::Periodic(1));
+    let table = db.clone().table("test_table", true).unwrap().with_fsync(&control);
+    table.put(&"key", &[1, 2, 3]).unwrap();
+    let path = Path::new(DB_PATH).join(DB_NAME).join("file");
+    std::fs::write(&path, [1, 2, 3]).unwrap();
+    control.file_written(&path).await.unwrap();
+    // Removed file is skipped
+    control.file_written(path.with_extension("removed")).await.unwrap();
+    assert_eq!(control.dirty_files.lock().len(), 2);
+
+    control.start_flusher();
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    let flushes = control.flushes();
+    assert!(flushes >= 1);
+    assert!(control.dirty_files.lock().is_empty());
+
+    // Final flush stops the flusher
+    control.stop().unwrap();
+    assert_eq!(control.flushes(), flushes + 1);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(control.flushes(), flushes + 1);
+
+    // Nothing is flushed without policy
+    let control = FsyncControl::new("test", FsyncPolicy::Never);
+    control.start_flusher();
+    control.file_written(&path).await.unwrap();
+    control.stop().unwrap();
+    assert_eq!(control.flushes(), 0);
+
+    drop(table);
+    drop(db);
+    destroy_rocks_db(DB_PATH, DB_NAME).await.unwrap();
+}
@@ -0,0 +1,168 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+
+fn channel(soft_limit: usize, hard_limit: usize, policy: OverflowPolicy) -> (Sender<u32>, Receiver<u32>) {
+    instrumented_channel(
+        "test",
+        ChannelLimits::new(soft_limit, hard_limit, policy),
+        #[cfg(feature = "telemetry")]
+        Arc::new(ChannelTelemetry::default())
+    )
+}
+
+fn drain(receiver: &Receiver<u32>) -> Vec<u32> {
+    std::iter::from_fn(|| receiver.try_recv().ok()).collect()
+}
+
+#[test]
+fn test_channel_unbounded() {
+    let (sender, receiver) = channel(0, 0, OverflowPolicy::DropNew);
+    for i in 0..1000 {
+        sender.send(i).unwrap();
+    }
+    assert_eq!(sender.len(), 1000);
+    assert_eq!(drain(&receiver), (0..1000).collect::<Vec<_>>());
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(receiver.stats(), ChannelStats {
+        max_depth: 1000, enqueued: 1000, dequeued: 1000, ..Default::default()
+    });
+    // Disconnected only when all senders are dropped
+    let another = sender.clone();
+    drop(sender);
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    another.send(1).unwrap();
+    drop(another);
+    assert_eq!(receiver.try_recv(), Ok(1));
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+}
+
+#[test]
+fn test_channel_drop_new() {
+    let (sender, receiver) = channel(0, 2, OverflowPolicy::DropNew);
+    sender.send(1).unwrap();
+    sender.send(2).unwrap();
+    assert_eq!(sender.send(3), Err(SendError::Full(3)));
+    assert_eq!(drain(&receiver), [1, 2]);
+    sender.send(4).unwrap();
+    assert_eq!(drain(&receiver), [4]);
+    let stats = sender.stats();
+    assert_eq!((stats.enqueued, stats.dropped_new, stats.evicted_oldest), (3, 1, 0));
+    assert_eq!(stats.max_depth, 2);
+}
+
+#[test]
+fn test_channel_drop_oldest() {
+    let (sender, receiver) = channel(0, 2, OverflowPolicy::DropOldest);
+    for i in 1..=5 {
+        sender.send(i).unwrap();
+    }
+    assert_eq!(receiver.len(), 2);
+    assert_eq!(drain(&receiver), [4, 5]);
+    let stats = sender.stats();
+    assert_eq!((stats.enqueued, stats.dropped_new, stats.evicted_oldest), (5, 0, 3));
+    assert_eq!((stats.depth, stats.max_depth), (0, 2));
+}
+
+#[test]
+fn test_channel_block() {
+    let (sender, receiver) = channel(0, 1, OverflowPolicy::Block(10000));
+    sender.send(1).unwrap();
+    let blocked = std::thread::spawn(move || {
+        let start = Instant::now();
+        sender.send(2).unwrap();
+        (start.elapsed(), sender.stats())
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(receiver.try_recv(), Ok(1));
+    let (elapsed, stats) = blocked.join().unwrap();
+    assert!(elapsed >= Duration::from_millis(50));
+    assert!(elapsed < Duration::from_secs(5));
+    assert_eq!((stats.blocked, stats.block_timeouts), (1, 0));
+    assert_eq!(drain(&receiver), [2]);
+}
+
+#[test]
+fn test_channel_block_timeout() {
+    let (sender, receiver) = channel(0, 1, OverflowPolicy::Block(50));
+    sender.send(1).unwrap();
+    let start = Instant::now();
+    // Nobody receives, so the item is queued over the limit after the timeout
+    sender.send(2).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    let stats = sender.stats();
+    assert_eq!((stats.depth, stats.blocked, stats.block_timeouts), (2, 1, 1));
+    assert_eq!(drain(&receiver), [1, 2]);
+
+    // Blocked sender is released by closed receiver
+    let (sender, receiver) = channel(0, 1, OverflowPolicy::Block(10000));
+    sender.send(1).unwrap();
+    let blocked = std::thread::spawn(move || sender.send(2));
+    std::thread::sleep(Duration::from_millis(50));
+    receiver.close();
+    assert_eq!(blocked.join().unwrap(), Err(SendError::Closed(2)));
+    assert_eq!(drain(&receiver), [1]);
+}
+
+#[test]
+fn test_channel_soft_limit() {
+    let (sender, receiver) = channel(3, 0, OverflowPolicy::DropNew);
+    for i in 0..5 {
+        sender.send(i).unwrap();
+    }
+    assert_eq!(sender.stats().soft_limit_hits, 1);
+    drain(&receiver);
+    for i in 0..3 {
+        sender.send(i).unwrap();
+    }
+    let stats = sender.stats();
+    assert_eq!(stats.soft_limit_hits, 2);
+    // Soft limit changes nothing but the warning
+    assert_eq!((stats.enqueued, stats.dropped_new), (8, 0));
+}
+
+#[tokio::test]
+async fn test_channel_async_recv() {
+    let (sender, receiver) = channel(0, 0, OverflowPolicy::DropNew);
+    let receiver = Arc::new(receiver);
+    let task = tokio::spawn({
+        let receiver = receiver.clone();
+        async move {
+            let mut received = Vec::new();
+            while let Some(item) = receiver.recv().await {
+                received.push(item);
+            }
+            received
+        }
+    });
+    for i in 0..10 {
+        sender.send(i).unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    drop(sender);
+    assert_eq!(task.await.unwrap(), (0..10).collect::<Vec<_>>());
+
+    // Queued items are received after close, new ones are refused
+    let (sender, receiver) = channel(0, 0, OverflowPolicy::DropNew);
+    sender.send(1).unwrap();
+    receiver.close();
+    assert_eq!(sender.send(2), Err(SendError::Closed(2)));
+    assert_eq!(receiver.recv().await, Some(1));
+    assert_eq!(receiver.recv().await, None);
+
+    // Sending to dropped receiver fails
+    let (sender, receiver) = channel(0, 0, OverflowPolicy::DropNew);
+    drop(receiver);
+    assert_eq!(sender.send(1).map_err(|e| e.into_inner()), Err(1));
+}
@@ -0,0 +1,137 @@
+/*
+* Copyright (C) 2019-2024 EverX. All Rights Reserved.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific EVERX DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use crate::db::traits::DbKey;
+
+// Block n is generated at 1190 + 5 * n, so the blocks cross bucket boundary
+fn index_with_blocks(seq_nos: std::ops::Range<u32>) -> McUtimeIndex {
+    let index = McUtimeIndex::in_memory();
+    for seq_no in seq_nos {
+        index.add(1190 + 5 * seq_no, seq_no).unwrap();
+    }
+    index
+}
+
+#[test]
+fn test_mc_utime_index_find() {
+    let index = McUtimeIndex::in_memory();
+    assert_eq!(index.find(1000).unwrap(), None);
+    assert!(index.range(0, u32::MAX).unwrap().is_empty());
+
+    let index = index_with_blocks(0..10);
+    // Exact hits
+    for seq_no in 0..10 {
+        assert_eq!(index.find(1190 + 5 * seq_no).unwrap(), Some(seq_no));
+    }
+    // Between blocks the previous one is found
+    assert_eq!(index.find(1199).unwrap(), Some(1));
+    assert_eq!(index.find(1201).unwrap(), Some(2));
+    assert_eq!(index.find(1189).unwrap(), None);
+    assert_eq!(index.find(5000).unwrap(), Some(9));
+    // Adding twice changes nothing, blocks without time are not indexed
+    index.add(1200, 2).unwrap();
+    index.add(0, 100).unwrap();
+    assert_eq!(index.range(0, u32::MAX).unwrap(), (0..10).collect::<Vec<_>>());
+
+    // Ranges
+    assert_eq!(index.range(1195, 1210).unwrap(), [1, 2, 3, 4]);
+    assert_eq!(index.range(1196, 1209).unwrap(), [2, 3]);
+    assert_eq!(index.range(1200, 1200).unwrap(), [2]);
+    assert!(index.range(1201, 1204).unwrap().is_empty());
+    assert!(index.range(1210, 1200).unwrap().is_empty());
+    assert!(index.range(2000, 3000).unwrap().is_empty());
+}
+
+#[test]
+fn test_mc_utime_index_clock_skew() {
+    let index = McUtimeIndex::in_memory();
+    // Block 22 is older than block 21 and is in previous bucket
+    for (gen_utime, seq_no) in [(1795, 20), (1805, 21), (1798, 22), (1810, 23)] {
+        index.add(gen_utime, seq_no).unwrap();
+    }
+    assert_eq!(index.find(1797).unwrap(), Some(20));
+    assert_eq!(index.find(1800).unwrap(), Some(22));
+    assert_eq!(index.find(1806).unwrap(), Some(22));
+    assert_eq!(index.find(1810).unwrap(), Some(23));
+
+    assert_eq!(index.range(1800, 1806).unwrap(), [21]);
+    assert_eq!(index.range(1796, 1806).unwrap(), [21, 22]);
+    // Block 21 is out of range, but it is between found ones
+    assert_eq!(index.range(1790, 1800).unwrap(), [20, 21, 22]);
+}
+
+#[test]
+fn test_mc_utime_index_build() {
+    // Old database has only blocks applied after update
+    let index = index_with_blocks(300..320);
+    assert!(!index.is_complete().unwrap());
+    assert_eq!(index.find(1190 + 5 * 299).unwrap(), None);
+
+    // Scan gives all applied blocks in random order
+    let mut scanned = (0..320).collect::<Vec<u32>>();
+    scanned.reverse();
+    scanned.swap(10, 200);
+    let count = index.build_if_incomplete(|add| {
+        for seq_no in scanned {
+            add(1190 + 5 * seq_no, seq_no);
+        }
+        add(0, 1000);
+        Ok(())
+    }).unwrap();
+    assert_eq!(count, Some(320));
+    assert!(index.is_complete().unwrap());
+    assert_eq!(index.build_if_incomplete(|_| panic!("index is complete")).unwrap(), None);
+
+    assert_eq!(index.find(1190 + 5 * 299).unwrap(), Some(299));
+    assert_eq!(index.find(1190 + 5 * 150 + 3).unwrap(), Some(150));
+    // Range goes over built and added blocks
+    let range = index.range(1190 + 5 * 250, 1190 + 5 * 310).unwrap();
+    assert_eq!(range, (250..=310).collect::<Vec<_>>());
+    assert_eq!(index.range(0, u32::MAX).unwrap(), (0..320).collect::<Vec<_>>());
+
+    // Failed scan leaves index incomplete
+    let index = index_with_blocks(0..1);
+    assert!(index.build_if_incomplete(|_| ever_block::fail!("scan failed")).is_err());
+    assert!(!index.is_complete().unwrap());
+}
+
+#[test]
+fn test_mc_utime_index_corruption() {
+    let kvc = crate::db::tests::utils::CorruptedDb::new("test_mc_utime_corruption");
+    let index = McUtimeIndex::with_storage(McUtimeDb::with_kvc(Box::new(kvc.clone())));
+    for seq_no in 0..10 {
+        index.add(1190 + 5 * seq_no, seq_no).unwrap();
+    }
+    index.set_complete().unwrap();
+    assert_eq!(index.find(1210).unwrap(), Some(4));
+
+    // Bucket with blocks 2..10 is lost, so the index is degraded and incomplete
+    kvc.corrupt(U32Key::with_value(2).key());
+    assert_eq!(index.find(1210).unwrap(), Some(1));
+    assert!(!index.is_complete().unwrap());
+    assert!(crate::db::degradation::is_degraded("test_mc_utime_corruption"));
+
+    // Rebuild by scan brings it back
+    let count = index.build_if_incomplete(|add| {
+        for seq_no in 0..10 {
+            add(1190 + 5 * seq_no, seq_no);
+        }
+        Ok(())
+    }).unwrap();
+    assert_eq!(count, Some(10));
+    assert!(index.is_complete().unwrap());
+    assert!(!crate::db::degradation::is_degraded("test_mc_utime_corruption"));
+    assert_eq!(index.find(1210).unwrap(), Some(4));
+    assert_eq!(index.range(0, u32::MAX).unwrap(), (0..10).collect::<Vec<_>>());
+}
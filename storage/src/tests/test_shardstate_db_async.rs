@@ -12,7 +12,7 @@
 */
 
 use crate::{
-    db::rocksdb::RocksDb,
+    db::rocksdb::RocksDb, fsync::{FsyncControl, FsyncPolicy},
     shardstate_db_async::{AllowStateGcResolver, CellsDbConfig, ShardStateDb}, StorageAlloc,
 };
 #[cfg(feature = "telemetry")]
@@ -75,7 +75,9 @@ async fn test_shardstate_db_async() -> Result<()> {
             prefill_cells_counters: false,
             cache_cells_counters: true,
             cache_size_bytes: 10000000,
+            adaptive_cache: None,
         },
+        &FsyncControl::new("cells", FsyncPolicy::Never),
         #[cfg(feature = "telemetry")]
         Arc::new(StorageTelemetry::default()),
         Arc::new(StorageAlloc::default()),
@@ -11,11 +11,11 @@
 * limitations under the License.
 */
 
-use crate::{block_handle_db, traits::Serializable};
+use crate::{block_handle_db, error::StorageError, traits::Serializable};
 use std::{io::{Read, Write}, sync::atomic::{AtomicU64, Ordering}};
 #[cfg(test)]
 use std::sync::atomic::AtomicU32;
-use ever_block::{Block, ByteOrderRead, Result};
+use ever_block::{fail, Block, BlockIdExt, ByteOrderRead, Result};
 
 #[derive(Debug, Default)]
 pub struct BlockMeta {
@@ -24,6 +24,7 @@ pub struct BlockMeta {
     pub gen_lt: u64,
     pub params: u32, // for queue update it is target workchain id
                      // for mesh update/kit it is source network id
+    next_ids: parking_lot::RwLock<[Option<BlockIdExt>; 2]>,
     #[cfg(test)]
     pub test_counter: AtomicU32,
 }
@@ -76,6 +77,7 @@ impl BlockMeta {
             gen_utime,
             gen_lt,
             params,
+            next_ids: parking_lot::RwLock::new([None, None]),
             #[cfg(test)]
             test_counter: AtomicU32::new(0),
         }
@@ -89,6 +91,38 @@ impl BlockMeta {
         (self.flags.fetch_or((flags as u64) << 32, Ordering::Relaxed) >> 32) as u32
     }
 
+    // Sets flags only if none of mask bits are set yet. Returns true if flags were set.
+    pub fn set_flags_if_unset(&self, mask: u32, flags: u32) -> bool {
+        let mask = (mask as u64) << 32;
+        let flags = (flags as u64) << 32;
+        self.flags.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            if current & mask == 0 {
+                Some(current | flags)
+            } else {
+                None
+            }
+        }).is_ok()
+    }
+
+    // Sets all of `set_mask` flags at once if none of `expected_clear` flags are set yet.
+    // Returns flags before the change.
+    pub fn set_flags_masked(&self, set_mask: u32, expected_clear: u32) -> Result<u32> {
+        let set_mask = (set_mask as u64) << 32;
+        let clear_mask = (expected_clear as u64) << 32;
+        match self.flags.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            if current & clear_mask == 0 {
+                Some(current | set_mask)
+            } else {
+                None
+            }
+        }) {
+            Ok(prev) => Ok((prev >> 32) as u32),
+            Err(current) => fail!(
+                StorageError::FlagsPreconditionFailed((current >> 32) as u32, expected_clear)
+            )
+        }
+    }
+
     pub fn reset(&self, flags: u32, reset_mc_ref_seq_no: bool) {
         if reset_mc_ref_seq_no {
             self.flags.fetch_and((!flags as u64) << 32, Ordering::Relaxed);
@@ -106,13 +140,36 @@ impl BlockMeta {
         self.flags.fetch_or(masterchain_ref_seq_no as u64, Ordering::Relaxed) as u32
     }
 
+    // index is 0 for next1 and 1 for next2
+    pub(crate) fn next_id(&self, index: usize) -> Option<BlockIdExt> {
+        self.next_ids.read()[index].clone()
+    }
+
+    // Returns true if id was changed
+    pub(crate) fn set_next_id(&self, index: usize, id: Option<BlockIdExt>) -> bool {
+        let mut next_ids = self.next_ids.write();
+        if next_ids[index] == id {
+            false
+        } else {
+            next_ids[index] = id;
+            true
+        }
+    }
+
 }
 
 impl Serializable for BlockMeta {
 
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
         const FLAG_MASK: u64 = 0x0FFF_FFFF_FFFF_FFFF;
-        let flags = self.flags.load(Ordering::Relaxed) & FLAG_MASK;
+        let mut flags = self.flags.load(Ordering::Relaxed) & FLAG_MASK;
+        // Block may be applied while its state is being saved in background. Applied flag
+        // is not written until the state is saved, so after a crash there is no applied block
+        // without state: it is applied again. Handle is written again when the state is saved.
+        let state_flags = ((block_handle_db::FLAG_STATE | block_handle_db::FLAG_STATE_SAVED) as u64) << 32;
+        if flags & state_flags == (block_handle_db::FLAG_STATE as u64) << 32 {
+            flags &= !((block_handle_db::FLAG_APPLIED as u64) << 32);
+        }
         writer.write_all(&flags.to_le_bytes())?;
         writer.write_all(&self.gen_utime.to_le_bytes())?;
         writer.write_all(&self.gen_lt.to_le_bytes())?;